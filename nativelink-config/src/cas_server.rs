@@ -23,7 +23,9 @@ use crate::serde_utils::{
     convert_optional_string_with_shellexpand, convert_string_with_shellexpand,
     convert_vec_string_with_shellexpand,
 };
-use crate::stores::{ClientTlsConfig, ConfigDigestHashFunction, StoreRefName, StoreSpec};
+use crate::stores::{
+    ClientTlsConfig, ConfigDigestHashFunction, ConfigSha256Backend, StoreRefName, StoreSpec,
+};
 
 /// Name of the scheduler. This type will be used when referencing a
 /// scheduler in the `CasConfig::schedulers`'s map key.
@@ -75,6 +77,41 @@ pub struct HttpCompressionConfig {
     pub accepted_compression_algorithms: Vec<HttpCompressionAlgorithm>,
 }
 
+/// Size limits of the gRPC messages of a service. Clients on fast links
+/// benefit from larger messages, while smaller ones bound the memory used
+/// by each request.
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct GrpcMessageLimits {
+    /// Largest message the service accepts from clients.
+    ///
+    /// Default: 4MiB
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub max_decoding_message_size: usize,
+
+    /// Largest message the service sends to clients.
+    ///
+    /// Default: 0 (no limit)
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub max_encoding_message_size: usize,
+}
+
+/// Message size limits of the services that move the most data.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct GrpcMessageLimitsConfig {
+    #[serde(default)]
+    pub cas: GrpcMessageLimits,
+
+    /// If set, `max_decoding_message_size` overrides the one of the
+    /// `bytestream` service config.
+    #[serde(default)]
+    pub bytestream: GrpcMessageLimits,
+
+    #[serde(default)]
+    pub execution: GrpcMessageLimits,
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct AcStoreConfig {
@@ -87,6 +124,59 @@ pub struct AcStoreConfig {
     /// it is only possible to read from the Action Cache.
     #[serde(default)]
     pub read_only: bool,
+
+    /// The CAS store that `stdout`, `stderr` and output files are read
+    /// from when a `GetActionResult` request asks for them to be inlined.
+    /// If not set, they are never inlined.
+    #[serde(default, deserialize_with = "convert_optional_string_with_shellexpand")]
+    pub cas_store: Option<StoreRefName>,
+
+    /// Whether `UpdateActionResult` checks that every blob the action
+    /// result refers to is in `cas_store` before storing it. Results that
+    /// refer to missing blobs are rejected, so other clients never get an
+    /// action result whose outputs can not be downloaded.
+    /// Requires `cas_store` to be set.
+    #[serde(default)]
+    pub verify_outputs_exist: bool,
+
+    /// Largest serialized `ActionResult` accepted by `UpdateActionResult`.
+    /// Larger results are rejected.
+    ///
+    /// Default: 0 (no limit)
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub max_action_result_size: usize,
+
+    /// Number of seconds action results written through `UpdateActionResult`
+    /// are served for. The expiry time is stored with the action result and
+    /// `GetActionResult` treats expired results as missing.
+    ///
+    /// Default: 0 (never expire)
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub action_result_ttl: usize,
+
+    /// If set, `UpdateActionResult` is only accepted from identities with
+    /// this role, like "ac-writer". Roles come from `ServerConfig::auth`
+    /// when it is set, or else from the header set in
+    /// `IdentityHeaderSpec::roles_header_name`.
+    /// Reads are always allowed.
+    #[serde(default, deserialize_with = "convert_optional_string_with_shellexpand")]
+    pub write_role: Option<String>,
+
+    /// Maximum number of bytes inlined in a single `GetActionResult`
+    /// response. Blobs that do not fit are left for the client to fetch
+    /// from the CAS.
+    ///
+    /// Default: 1MiB
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub max_inline_size: usize,
+
+    /// Digest functions clients may use with this instance. Requests using
+    /// any other digest function are rejected. It must contain the
+    /// `GlobalConfig::default_digest_hash_function`.
+    ///
+    /// Default: ["sha256", "blake3"]
+    #[serde(default)]
+    pub digest_functions: Vec<ConfigDigestHashFunction>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -96,6 +186,68 @@ pub struct CasStoreConfig {
     /// This store name referenced here may be reused multiple times.
     #[serde(deserialize_with = "convert_string_with_shellexpand")]
     pub cas_store: StoreRefName,
+
+    /// Number of digests of a `FindMissingBlobs` request checked against
+    /// the store at once. Larger requests are split into batches of this
+    /// size.
+    ///
+    /// Default: 1000
+    #[serde(default)]
+    pub find_missing_batch_size: usize,
+
+    /// Maximum number of batches of a single `FindMissingBlobs` request
+    /// checked against the store at the same time.
+    ///
+    /// Default: 16
+    #[serde(default)]
+    pub max_concurrent_find_missing_batches: usize,
+
+    /// Maximum number of blobs of a single `BatchUpdateBlobs` request
+    /// written to the store at the same time.
+    ///
+    /// Default: 64
+    #[serde(default)]
+    pub max_concurrent_batch_updates: usize,
+
+    /// Largest combined size of the blobs of a `BatchReadBlobs` request.
+    /// It is advertised to clients by the capabilities service, so they
+    /// use `ByteStream` for larger ones. Keep it below the
+    /// `max_decoding_message_size` of the `cas` service.
+    ///
+    /// Default: 64KiB
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub max_batch_total_size: usize,
+
+    /// Digest functions clients may use with this instance. Requests using
+    /// any other digest function are rejected. It must contain the
+    /// `GlobalConfig::default_digest_hash_function`.
+    ///
+    /// Default: ["sha256", "blake3"]
+    #[serde(default)]
+    pub digest_functions: Vec<ConfigDigestHashFunction>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct DigestSubscriptionConfig {
+    /// The store name referenced in the `stores` map in the main config.
+    /// Clients wait for blobs to become available in this store.
+    #[serde(deserialize_with = "convert_string_with_shellexpand")]
+    pub cas_store: StoreRefName,
+
+    /// How often the store is checked for the blobs a client waits for.
+    /// Value in milliseconds.
+    ///
+    /// Default: 100 (milliseconds)
+    #[serde(default)]
+    pub poll_interval_ms: u64,
+
+    /// The maximum time a client may wait for blobs. Requests without a
+    /// timeout or with a longer one are capped to this. Value in seconds.
+    ///
+    /// Default: 300 (seconds)
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub max_timeout: usize,
 }
 
 #[derive(Deserialize, Debug, Default)]
@@ -113,6 +265,35 @@ pub struct CapabilitiesConfig {
     /// If not set the capabilities service will inform the client that remote
     /// execution is not supported.
     pub remote_execution: Option<CapabilitiesRemoteExecutionConfig>,
+
+    /// Digest functions advertised to the clients of this instance. The
+    /// services of the instance only accept the digest functions of their
+    /// own config, so keep them the same.
+    ///
+    /// Default: {the `digest_functions` of the `cas` service of the
+    /// instance, or else ["sha256", "blake3"]}
+    #[serde(default)]
+    pub digest_functions: Vec<ConfigDigestHashFunction>,
+
+    /// Whether clients may send symlinks with absolute targets, advertised
+    /// as `symlink_absolute_path_strategy` of the cache capabilities.
+    /// Set it to `allowed` if actions create symlinks to paths outside of
+    /// their work directory, as workers upload those as absolute ones.
+    ///
+    /// Default: disallowed
+    #[serde(default)]
+    pub symlink_absolute_path_strategy: SymlinkAbsolutePathStrategy,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SymlinkAbsolutePathStrategy {
+    /// Clients must only send symlinks with relative targets.
+    #[default]
+    disallowed,
+
+    /// Absolute symlinks are allowed in inputs and outputs.
+    allowed,
 }
 
 #[derive(Deserialize, Debug)]
@@ -127,17 +308,43 @@ pub struct ExecutionConfig {
     /// The scheduler name referenced in the `schedulers` map in the main config.
     #[serde(deserialize_with = "convert_string_with_shellexpand")]
     pub scheduler: SchedulerRefName,
+
+    /// Timeout applied to actions that do not request one. Value in seconds.
+    ///
+    /// Default: 0 (Use `max_action_timeout`, or no timeout if that is
+    /// also not set)
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub default_action_timeout: usize,
+
+    /// The maximum timeout an action may request. Actions that request a
+    /// longer timeout are rejected. Value in seconds.
+    ///
+    /// Default: 0 (No limit)
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub max_action_timeout: usize,
+
+    /// How often clients waiting on a queued action are sent an update with
+    /// the number of actions ahead of it and its estimated start time.
+    /// Value in seconds.
+    ///
+    /// Default: 10 (seconds)
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub queue_position_update_interval_s: u64,
 }
 
 #[derive(Deserialize, Debug, Default)]
 #[serde(deny_unknown_fields)]
 pub struct ByteStreamConfig {
     /// Name of the store in the "stores" configuration.
+    /// The key is the `instance_name` used in the protocol, which may end
+    /// with `*` to serve every `instance_name` starting with the rest of
+    /// the key, like `"ci/*"`. `"*"` serves every other `instance_name`.
     pub cas_stores: HashMap<InstanceName, StoreRefName>,
 
     /// Max number of bytes to send on each grpc stream chunk.
     /// According to <https://github.com/grpc/grpc.github.io/issues/371>
-    /// 16KiB - 64KiB is optimal.
+    /// 16KiB - 64KiB is optimal. Chunks read from the store are sent as
+    /// they are, only the ones larger than this are split.
     ///
     ///
     /// Default: 64KiB
@@ -152,11 +359,41 @@ pub struct ByteStreamConfig {
     /// In the event a client disconnects while uploading a blob, we will hold
     /// the internal stream open for this many seconds before closing it.
     /// This allows clients that disconnect to reconnect and continue uploading
-    /// the same blob.
+    /// the same blob. Uploads idle for longer are dropped along with the
+    /// data received so far, and are reported in the `abandoned_sessions`
+    /// and `abandoned_bytes` metrics of the instance.
     ///
-    /// Default: 10 (seconds)
+    /// Default: 60 (seconds)
     #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
     pub persist_stream_on_disconnect_timeout: usize,
+
+    /// If set, resource names are rejected unless the hash matches the
+    /// length of the digest function and the uuid of uploads is a valid
+    /// UUID. The error names the segment of the resource name that is
+    /// invalid. Some clients don't use UUIDs for uploads, so this is off
+    /// by default.
+    ///
+    /// Default: false
+    #[serde(default)]
+    pub strict_resource_name_validation: bool,
+
+    /// Max number of bytes of an upload that may be buffered between the
+    /// client and the store. Once reached, reading from the client pauses
+    /// until the store has consumed half of the buffered bytes, so a slow
+    /// store bounds the memory used by fast clients.
+    ///
+    /// Default: 0 (Only a couple of chunks are buffered, whatever their size)
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub max_upload_buffer_bytes: usize,
+
+    /// Digest functions clients may use, keyed by the instance names of
+    /// `cas_stores`. Resource names using any other digest function are
+    /// rejected. Each list must contain the
+    /// `GlobalConfig::default_digest_hash_function`.
+    ///
+    /// Default: {["sha256", "blake3"] for every instance}
+    #[serde(default)]
+    pub digest_functions: HashMap<InstanceName, Vec<ConfigDigestHashFunction>>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -165,6 +402,29 @@ pub struct WorkerApiConfig {
     /// The scheduler name referenced in the `schedulers` map in the main config.
     #[serde(deserialize_with = "convert_string_with_shellexpand")]
     pub scheduler: SchedulerRefName,
+
+    /// If set, the scheduler validates the results reported by workers and
+    /// writes them to the action cache on their behalf. Workers should then
+    /// be configured without an `ac_store`, so a buggy or compromised worker
+    /// can not insert results that reference missing blobs.
+    ///
+    /// Default: None (Workers write their own results)
+    #[serde(default)]
+    pub action_result_upload: Option<ActionResultUploadConfig>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ActionResultUploadConfig {
+    /// The AC store validated results are written to. This value must be
+    /// an AC store reference.
+    #[serde(deserialize_with = "convert_string_with_shellexpand")]
+    pub ac_store: StoreRefName,
+
+    /// The CAS store every output of a result must exist in before the
+    /// result is written to `ac_store`.
+    #[serde(deserialize_with = "convert_string_with_shellexpand")]
+    pub cas_store: StoreRefName,
 }
 
 #[derive(Deserialize, Debug, Default)]
@@ -179,6 +439,29 @@ pub struct PrometheusConfig {
     pub path: String,
 }
 
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct WorkerAdminConfig {
+    /// How long a `DrainWorker` request without a timeout waits for the
+    /// actions of the worker to finish. Value in seconds.
+    ///
+    /// Default: 600 (seconds)
+    #[serde(default)]
+    pub default_drain_timeout_s: u64,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct OperationAdminConfig {
+    /// Maximum number of operations returned by a `ListOperations`
+    /// request. The response is marked as truncated if more operations
+    /// match.
+    ///
+    /// Default: 1000
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_listed_operations: usize,
+}
+
 #[derive(Deserialize, Debug, Default)]
 #[serde(deny_unknown_fields)]
 pub struct AdminConfig {
@@ -186,9 +469,34 @@ pub struct AdminConfig {
     /// domain is "example.com", you can reach the endpoint with:
     /// <http://example.com/admin>.
     ///
+    /// The log filter can be read with a `GET` to `<path>/log_filter`. If
+    /// `allow_log_filter_changes` is set, it can be changed at runtime with a
+    /// `POST` to the same path. The body uses the same syntax as the
+    /// `RUST_LOG` environment variable, eg:
+    /// `curl -X POST -d 'warn,nativelink_store::redis_store=debug' http://example.com/admin/log_filter`
+    ///
+    /// The most read objects of a `fast_slow` store with `popularity` set
+    /// are listed as JSON with a `GET` to
+    /// `<path>/popularity/<tracker_name>/hottest/<count>`.
+    ///
+    /// A worker is cordoned with a `POST` to
+    /// `<path>/scheduler/<scheduler_name>/set_drain_worker/<worker_id>/1` and
+    /// uncordoned with `0` instead of `1`. A `POST` to
+    /// `<path>/scheduler/<scheduler_name>/drain_worker/<worker_id>/<timeout_s>`
+    /// cordons the worker, waits up to `timeout_s` seconds for its actions to
+    /// finish and removes it from the scheduler.
+    ///
     /// Default: "/admin"
     #[serde(default)]
     pub path: String,
+
+    /// Whether the log filter can be changed with a `POST` to
+    /// `<path>/log_filter`. The admin API is served without authentication,
+    /// so only enable this if the admin API can not be reached by clients.
+    ///
+    /// Default: false
+    #[serde(default)]
+    pub allow_log_filter_changes: bool,
 }
 
 #[derive(Deserialize, Debug, Default)]
@@ -201,14 +509,39 @@ pub struct HealthConfig {
     /// Default: "/status"
     #[serde(default)]
     pub path: String,
+
+    /// The same health checks are also served through the standard
+    /// `grpc.health.v1.Health` service, without authentication, as the
+    /// status of the "" service. This is how often the checks are run for it.
+    ///
+    /// Default: 5 (seconds)
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub grpc_check_interval_s: u64,
 }
 
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ReflectionConfig {}
+
 #[derive(Deserialize, Debug)]
 pub struct BepConfig {
     /// The store to publish build events to.
     /// The store name referenced in the `stores` map in the main config.
+    /// Every invocation is indexed in it under "BepInvocation:<invocation_id>"
+    /// with the identity of the client, the times of its first and last
+    /// events and the number of events received.
     #[serde(deserialize_with = "convert_string_with_shellexpand")]
     pub store: StoreRefName,
+
+    /// CAS store the build events are stored in, keyed by their digest.
+    /// `store` then only holds the digests of the events and the index of
+    /// the invocations lists them, so a small key value store like redis
+    /// is enough for `store`.
+    /// If not set, the build events are stored in `store`.
+    ///
+    /// Default: None
+    #[serde(default, deserialize_with = "convert_optional_string_with_shellexpand")]
+    pub cas_store: Option<StoreRefName>,
 }
 
 #[derive(Deserialize, Clone, Debug, Default)]
@@ -221,6 +554,220 @@ pub struct IdentityHeaderSpec {
     /// If the header is required to be set or fail the request.
     #[serde(default)]
     pub required: bool,
+
+    /// The name of the header listing the roles of the identity, separated
+    /// by commas, like "ac-writer,admin". Like the identity header, it must
+    /// be set by a trusted proxy in front of this server.
+    /// If not set, requests have no roles.
+    #[serde(default, deserialize_with = "convert_optional_string_with_shellexpand")]
+    pub roles_header_name: Option<String>,
+}
+
+/// Authentication of the clients of a server. The identity and roles of
+/// the clients then only come from their credentials, and the headers of
+/// `IdentityHeaderSpec` are ignored.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct AuthConfig {
+    /// The ways clients may authenticate, tried in order. The first one
+    /// the request has credentials for decides the identity. Requests
+    /// with invalid credentials are always rejected with `UNAUTHENTICATED`.
+    pub methods: Vec<AuthMethod>,
+
+    /// If requests without credentials are rejected. Otherwise they are
+    /// served without an identity.
+    ///
+    /// Default: false
+    #[serde(default)]
+    pub required: bool,
+
+    /// Policies deciding what clients may do. Each request is checked
+    /// against the policies in order, and the first one that applies to
+    /// the client, instance and action decides. Requests no policy applies
+    /// to are rejected with `PERMISSION_DENIED`. Requests without
+    /// credentials have no identity and no roles.
+    ///
+    /// Default: {empty, everything is allowed}
+    #[serde(default)]
+    pub policies: Vec<AuthorizationPolicy>,
+}
+
+/// What a client does with an instance.
+#[allow(non_camel_case_types)]
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuthorizationAction {
+    /// Read from the CAS or the action cache, wait for blobs, or follow
+    /// executions.
+    read,
+
+    /// Upload to the CAS, or publish build events. The instance name of
+    /// build events is their `project_id`.
+    cas_write,
+
+    /// Update the action cache.
+    ac_write,
+
+    /// Schedule executions.
+    execute,
+
+    /// Cancel or requeue operations and cordon or drain workers through
+    /// the admin services.
+    admin,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PolicyEffect {
+    /// Allow the requests the policy applies to.
+    #[default]
+    allow,
+
+    /// Reject the requests the policy applies to.
+    deny,
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct AuthorizationPolicy {
+    /// Name of the policy, reported to the clients it rejects.
+    #[serde(deserialize_with = "convert_string_with_shellexpand")]
+    pub name: String,
+
+    /// Identities the policy applies to. "*" matches every client with an
+    /// identity, but not the ones without identity.
+    #[serde(default, deserialize_with = "convert_vec_string_with_shellexpand")]
+    pub identities: Vec<String>,
+
+    /// Roles the policy applies to. The policy applies to clients with any
+    /// of `identities` or any of these roles.
+    #[serde(default, deserialize_with = "convert_vec_string_with_shellexpand")]
+    pub roles: Vec<String>,
+
+    /// Instance names the policy applies to. A name ending with "*", like
+    /// "ci/*", matches every instance name starting with the prefix.
+    ///
+    /// Default: {empty, every instance}
+    #[serde(default, deserialize_with = "convert_vec_string_with_shellexpand")]
+    pub instance_names: Vec<String>,
+
+    /// Actions the policy applies to.
+    pub actions: Vec<AuthorizationAction>,
+
+    /// If the policy allows or rejects the requests it applies to.
+    ///
+    /// Default: `PolicyEffect::allow`
+    #[serde(default)]
+    pub effect: PolicyEffect,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Deserialize, Clone, Debug)]
+pub enum AuthMethod {
+    /// Bearer JWTs in the "authorization" header, like the ID tokens of an
+    /// OIDC provider.
+    jwt(JwtAuthSpec),
+
+    /// Static API keys.
+    api_key(ApiKeyAuthSpec),
+
+    /// Headers set by a trusted proxy in front of this server. The proxy
+    /// must remove the headers from the requests of its clients.
+    proxy_header(ProxyHeaderAuthSpec),
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct JwtAuthSpec {
+    /// Where to fetch the JSON Web Key Set the tokens are signed with.
+    /// Supports "https://", "http://" and "file://" URLs, like
+    /// "https://accounts.example.com/.well-known/jwks.json".
+    #[serde(deserialize_with = "convert_string_with_shellexpand")]
+    pub jwks_url: String,
+
+    /// Path to a PEM file with the certificate authorities trusted when
+    /// fetching `jwks_url` over https.
+    ///
+    /// Default: "/etc/ssl/certs/ca-certificates.crt"
+    #[serde(default, deserialize_with = "convert_optional_string_with_shellexpand")]
+    pub jwks_ca_file: Option<String>,
+
+    /// How often the key set is fetched again, in the background. It is
+    /// also fetched when a token is signed with an unknown key. Fetches
+    /// time out after 10 seconds. Value in seconds.
+    ///
+    /// Default: 3600 (seconds)
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub jwks_refresh_interval: usize,
+
+    /// If set, the "iss" claim of tokens must be this value.
+    #[serde(default, deserialize_with = "convert_optional_string_with_shellexpand")]
+    pub issuer: Option<String>,
+
+    /// If set, the "aud" claim of tokens must contain this value.
+    #[serde(default, deserialize_with = "convert_optional_string_with_shellexpand")]
+    pub audience: Option<String>,
+
+    /// The claim holding the identity of the client.
+    ///
+    /// Default: "sub"
+    #[serde(default, deserialize_with = "convert_string_with_shellexpand")]
+    pub identity_claim: String,
+
+    /// The claim holding the roles of the client, either a list of strings
+    /// or a string of roles separated by spaces or commas. Nested claims
+    /// are separated by ".", like "realm_access.roles".
+    /// If not set, clients have no roles.
+    #[serde(default, deserialize_with = "convert_optional_string_with_shellexpand")]
+    pub roles_claim: Option<String>,
+
+    /// Difference allowed between the clocks of this server and the token
+    /// issuer when checking "exp" and "nbf". Value in seconds.
+    ///
+    /// Default: 60 (seconds)
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub allowed_clock_skew: usize,
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKeyAuthSpec {
+    /// The header holding the API key.
+    ///
+    /// Default: "x-api-key"
+    #[serde(default, deserialize_with = "convert_string_with_shellexpand")]
+    pub header_name: String,
+
+    /// The accepted keys.
+    pub keys: Vec<ApiKeySpec>,
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKeySpec {
+    /// The key, usually read from the environment, like "${CI_API_KEY}".
+    #[serde(deserialize_with = "convert_string_with_shellexpand")]
+    pub key: String,
+
+    /// The identity of the clients using this key.
+    #[serde(deserialize_with = "convert_string_with_shellexpand")]
+    pub identity: String,
+
+    /// The roles of the clients using this key.
+    #[serde(default, deserialize_with = "convert_vec_string_with_shellexpand")]
+    pub roles: Vec<String>,
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ProxyHeaderAuthSpec {
+    /// The header holding the identity of the client.
+    #[serde(deserialize_with = "convert_string_with_shellexpand")]
+    pub identity_header: String,
+
+    /// The header listing the roles of the client, separated by commas.
+    /// If not set, clients have no roles.
+    #[serde(default, deserialize_with = "convert_optional_string_with_shellexpand")]
+    pub roles_header: Option<String>,
 }
 
 #[derive(Deserialize, Clone, Debug)]
@@ -245,17 +792,21 @@ pub struct OriginEventsSpec {
     pub max_event_queue_size: usize,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Default)]
 #[serde(deny_unknown_fields)]
 pub struct ServicesConfig {
     /// The Content Addressable Storage (CAS) backend config.
     /// The key is the `instance_name` used in the protocol and the
-    /// value is the underlying CAS store config.
+    /// value is the underlying CAS store config. A key ending with `*`,
+    /// like `"ci/*"`, serves every `instance_name` starting with the rest
+    /// of the key and `"*"` serves every other `instance_name`. Exact
+    /// names win over these and longer prefixes win over shorter ones.
     pub cas: Option<HashMap<InstanceName, CasStoreConfig>>,
 
     /// The Action Cache (AC) backend config.
     /// The key is the `instance_name` used in the protocol and the
-    /// value is the underlying AC store config.
+    /// value is the underlying AC store config. Keys may end with `*` the
+    /// same way as in `cas`.
     pub ac: Option<HashMap<InstanceName, AcStoreConfig>>,
 
     /// Capabilities service is required in order to use most of the
@@ -268,6 +819,11 @@ pub struct ServicesConfig {
     /// place holder.
     pub execution: Option<HashMap<InstanceName, ExecutionConfig>>,
 
+    /// Lets clients wait for blobs to be uploaded to the CAS by another
+    /// client instead of polling `FindMissingBlobs` themselves.
+    /// The key is the `instance_name` used in the protocol.
+    pub digest_subscription: Option<HashMap<InstanceName, DigestSubscriptionConfig>>,
+
     /// This is the service used to stream data to and from the CAS.
     /// Bazel's protocol strongly encourages users to use this streaming
     /// interface to interact with the CAS when the data is large.
@@ -282,6 +838,23 @@ pub struct ServicesConfig {
     /// that makes the remote execution/cache requests.
     pub worker_api: Option<WorkerApiConfig>,
 
+    /// Lets operators cordon and drain workers of the schedulers, eg. before
+    /// the machines of the workers are taken down for maintenance.
+    /// The `instance_name` of the requests is the scheduler name referenced
+    /// in the `schedulers` map in the main config.
+    /// NOTE: Like `worker_api`, this service should be served on a
+    /// non-public port.
+    pub worker_admin: Option<WorkerAdminConfig>,
+
+    /// Lets operators list the operations of the schedulers, look at the
+    /// stages an operation moved through, and cancel or requeue operations
+    /// that are stuck.
+    /// The `instance_name` of the requests is the scheduler name referenced
+    /// in the `schedulers` map in the main config.
+    /// NOTE: Like `worker_api`, this service should be served on a
+    /// non-public port.
+    pub operation_admin: Option<OperationAdminConfig>,
+
     /// Experimental - Build Event Protocol (BEP) configuration. This is
     /// the service that will consume build events from the client and
     /// publish them to a store for processing by an external service.
@@ -297,6 +870,10 @@ pub struct ServicesConfig {
 
     /// This is the service for health status check.
     pub health: Option<HealthConfig>,
+
+    /// Serves `grpc.reflection.v1.ServerReflection`, so tools like grpcurl
+    /// can list the services of the server and the messages they use.
+    pub reflection: Option<ReflectionConfig>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -319,6 +896,17 @@ pub struct TlsConfig {
     /// authentication is required for this endpoint.
     #[serde(default, deserialize_with = "convert_optional_string_with_shellexpand")]
     pub client_crl_file: Option<String>,
+
+    /// If clients must present a certificate signed by `client_ca_file`.
+    /// When false, clients without a certificate are accepted too, but a
+    /// certificate that is presented must still be valid.
+    ///
+    /// The common name of a client certificate is used as the identity of
+    /// the client, instead of the `identity_header`.
+    ///
+    /// Default: true if `client_ca_file` is set.
+    #[serde(default)]
+    pub require_client_cert: Option<bool>,
 }
 
 /// Advanced Http configurations. These are generally should not be set.
@@ -344,6 +932,9 @@ pub struct HttpServerConfig {
     )]
     pub experimental_http2_max_pending_accept_reset_streams: Option<u32>,
 
+    /// Flow control window of each stream. HTTP2 settings apply to the
+    /// whole connection, so this is shared by every service of the
+    /// listener. Raise it on high latency, high bandwidth links.
     #[serde(
         default,
         deserialize_with = "convert_optional_numeric_with_shellexpand"
@@ -413,6 +1004,10 @@ pub struct HttpListener {
     #[serde(default)]
     pub compression: HttpCompressionConfig,
 
+    /// Message size limits of the services served by this listener.
+    #[serde(default)]
+    pub message_limits: GrpcMessageLimitsConfig,
+
     /// Advanced Http server configuration.
     #[serde(default)]
     pub advanced_http: HttpServerConfig,
@@ -445,6 +1040,13 @@ pub struct ServerConfig {
     /// Default: {see `IdentityHeaderSpec`}
     #[serde(default)]
     pub experimental_identity_header: IdentityHeaderSpec,
+
+    /// Authentication of the clients of all the services of this server.
+    /// If not set, clients are not authenticated.
+    ///
+    /// Default: None
+    #[serde(default)]
+    pub auth: Option<AuthConfig>,
 }
 
 #[allow(non_camel_case_types)]
@@ -613,6 +1215,11 @@ pub struct LocalWorkerConfig {
     /// Endpoint which the worker will connect to the scheduler's `WorkerApiService`.
     pub worker_api_endpoint: EndpointConfig,
 
+    /// How long to wait before reconnecting to the scheduler after the
+    /// connection was lost.
+    #[serde(default)]
+    pub reconnect_backoff: ReconnectBackoffConfig,
+
     /// The maximum time an action is allowed to run. If a task requests for a timeout
     /// longer than this time limit, the task will be rejected. Value in seconds.
     ///
@@ -690,6 +1297,264 @@ pub struct LocalWorkerConfig {
     /// of the environment variable being the value of the property of the
     /// action being executed of that name or the fixed value.
     pub additional_environment: Option<HashMap<String, EnvironmentSource>>,
+
+    /// If set, actions run without network access unless they carry one of
+    /// the platform properties allowed in here. Only supported on Linux,
+    /// where the action is started in a new network namespace that has no
+    /// interfaces besides a loopback interface that is down.
+    /// Default: {Actions share the network of the worker}.
+    pub network_isolation: Option<NetworkIsolationConfig>,
+
+    /// If set, actions run in a bubblewrap sandbox in new user, mount, pid,
+    /// ipc and network namespaces. They only see the `read_only_paths` of
+    /// the worker and their own action directory, `/tmp` is an empty
+    /// tmpfs, and they have no network access unless they carry one of the
+    /// platform properties allowed in here. The inputs of the action are
+    /// read-only, except in the directories its outputs are written to.
+    /// `entrypoint` runs inside the sandbox, so it has to be in one of the
+    /// `read_only_paths`. Only supported on Linux, requires bubblewrap to be
+    /// installed on the worker and can not be combined with
+    /// `network_isolation`.
+    /// Default: {Actions run directly on the worker}.
+    pub sandbox: Option<SandboxConfig>,
+
+    /// If set, actions with the `container-image` platform property run in
+    /// a container of that image, like `docker://ubuntu@sha256:...`, using
+    /// podman. The action directory is mounted in the container at the
+    /// same path. The first action that uses an image pulls it, and later
+    /// actions use the same image even if its tag moved. Actions without
+    /// the property run directly on the worker. To only send actions with
+    /// an image to workers that can run them, make `container-image` a
+    /// `priority` property of the scheduler and give it any value in the
+    /// `platform_properties` of these workers. `entrypoint` runs inside the
+    /// container. Can not be combined with `sandbox`.
+    /// Default: {Actions run directly on the worker}.
+    pub container: Option<ContainerConfig>,
+
+    /// If set, each action runs in its own cgroup, which limits the CPU,
+    /// memory and processes it may use. The resources the action used are
+    /// sent to the client in the `auxiliary_metadata` of its
+    /// `ExecutedActionMetadata`. Only supported on Linux with cgroup v2 and
+    /// can not be combined with `container`.
+    /// Default: {Actions are not limited}.
+    pub cgroup: Option<CgroupConfig>,
+
+    /// How the input root of an action is made available to it.
+    /// Default: download
+    #[serde(default)]
+    pub input_provider: InputProvider,
+
+    /// Maximum number of output files the worker hashes or uploads at the
+    /// same time, over all its actions. Outputs the CAS already has are
+    /// not uploaded again.
+    /// Default: 64
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_concurrent_output_uploads: usize,
+
+    /// If set, actions with the `key_property` platform property run in a
+    /// long-lived worker process, which is reused by later actions with the
+    /// same key, arguments and environment. This is how Bazel runs
+    /// persistent workers remotely with
+    /// `--experimental_remote_mark_tool_inputs`, and saves the startup of
+    /// tools like JVM based compilers for every action. To only send these
+    /// actions to workers that support them, make `key_property` a
+    /// `priority` property of the scheduler and give it any value in the
+    /// `platform_properties` of these workers. Can not be combined with
+    /// `sandbox`, `container`, `cgroup`, `network_isolation` or the `fuse`
+    /// input provider.
+    /// Default: {Every action starts its own process}.
+    pub persistent_workers: Option<PersistentWorkerConfig>,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone, Deserialize, Debug, Default, PartialEq, Eq)]
+pub enum InputProvider {
+    /// Download all the files of the input root into the work directory
+    /// before the action runs.
+    #[default]
+    download,
+
+    /// Serve the input root through FUSE, so only the files the action
+    /// reads are fetched from the CAS, when it first opens them. Anything
+    /// the action writes is kept in an overlay filesystem on the work
+    /// directory. Requires Linux, `/dev/fuse` and a worker that is allowed
+    /// to mount filesystems, usually root.
+    fuse,
+}
+
+/// Actions that run in a persistent worker have to follow Bazel's worker
+/// protocol: the arguments that are `@file` or `--flagfile=file` are sent
+/// to the worker process in a `WorkRequest`, the other arguments and
+/// `--persistent_worker` start it. The `output` of the `WorkResponse` is
+/// the stderr of the action.
+#[derive(Clone, Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct PersistentWorkerConfig {
+    /// The platform property that holds the key of the worker process an
+    /// action runs in.
+    /// Default: "persistentWorkerKey"
+    #[serde(default, deserialize_with = "convert_string_with_shellexpand")]
+    pub key_property: String,
+
+    /// Seconds a worker process may be idle before it is stopped.
+    /// Default: 300 (seconds)
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub idle_timeout: usize,
+
+    /// Number of actions a worker process runs before it is replaced by a
+    /// new one, so tools that leak memory do not grow forever.
+    /// Default: 0 (no limit)
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_requests: u64,
+}
+
+#[derive(Clone, Copy, Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ReconnectBackoffConfig {
+    /// Seconds to wait before the first attempt to reconnect. The delay
+    /// doubles after every failed attempt and is reset once the worker is
+    /// registered with the scheduler again.
+    /// Default: 0.5 (seconds)
+    pub initial_delay_s: Option<f32>,
+
+    /// The longest the worker waits between two attempts, in seconds.
+    /// Default: 30 (seconds)
+    pub max_delay_s: Option<f32>,
+
+    /// Amount of jitter applied to the delay, as a percentage in decimal
+    /// form, so workers that lost the scheduler at the same time do not
+    /// all reconnect at once. A value of 0.5 waits between 75% and 125%
+    /// of the delay.
+    /// Default: 0.5
+    pub jitter: Option<f32>,
+}
+
+#[derive(Clone, Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkIsolationConfig {
+    /// Platform properties of an action that give it network access. An
+    /// action gets network access if it sets one of these properties to one
+    /// of the listed values.
+    ///
+    /// For example, a value of:
+    /// ```json
+    /// { "network": ["enabled"] }
+    /// ```
+    /// Gives network access to actions with `"network" = "enabled"` only.
+    ///
+    /// The scheduler must still be able to match actions that carry these
+    /// properties to this worker, so they should also be declared as
+    /// `priority` properties in `supported_platform_properties` of the
+    /// scheduler and be listed in `platform_properties` of this worker.
+    pub allow_network_properties: HashMap<String, Vec<String>>,
+}
+
+#[derive(Clone, Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct SandboxConfig {
+    /// Path to the bubblewrap binary.
+    /// Default: "/usr/bin/bwrap"
+    #[serde(default, deserialize_with = "convert_string_with_shellexpand")]
+    pub bwrap_path: String,
+
+    /// Paths of the worker the actions may read, like the directories
+    /// their tools and libraries are installed in. Paths that do not exist
+    /// on the worker are skipped.
+    /// Default: ["/bin", "/etc", "/lib", "/lib64", "/sbin", "/usr"]
+    #[serde(default)]
+    pub read_only_paths: Vec<String>,
+
+    /// Platform properties of an action that give it network access, in
+    /// the same format as `allow_network_properties` of
+    /// `NetworkIsolationConfig`.
+    /// Default: {No action has network access}
+    #[serde(default)]
+    pub allow_network_properties: HashMap<String, Vec<String>>,
+}
+
+#[derive(Clone, Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ContainerConfig {
+    /// Path to the podman binary.
+    /// Default: "/usr/bin/podman"
+    #[serde(default, deserialize_with = "convert_string_with_shellexpand")]
+    pub podman_path: String,
+
+    /// If set, only images pinned by digest, like
+    /// `docker://ubuntu@sha256:...`, may be used. Actions that use a tag
+    /// fail with `INVALID_ARGUMENT`.
+    /// Default: false
+    #[serde(default)]
+    pub require_digest: bool,
+}
+
+/// The limits of an action are taken from its `cpu_count`, `memory_kb` and
+/// `max_pids` platform properties. The defaults here apply to actions
+/// without them. An action that uses more memory than its limit is killed
+/// and fails with `RESOURCE_EXHAUSTED`.
+#[derive(Clone, Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct CgroupConfig {
+    /// The cgroup the cgroups of the actions are created in. The worker has
+    /// to be allowed to create cgroups in it, the `cpu`, `memory` and
+    /// `pids` controllers have to be enabled in its `cgroup.subtree_control`
+    /// and the worker itself can not run in it.
+    /// Default: "/sys/fs/cgroup/nativelink"
+    #[serde(default, deserialize_with = "convert_string_with_shellexpand")]
+    pub path: String,
+
+    /// Number of CPU cores an action may use.
+    /// Default: 0 (no limit)
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub default_cpu_count: u64,
+
+    /// Memory an action may use, in kilobytes.
+    /// Default: 0 (no limit)
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub default_memory_kb: u64,
+
+    /// Number of processes and threads an action may have at once.
+    /// Default: 0 (no limit)
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub default_max_pids: u64,
+}
+
+/// Writes a JSON object per line for each `UpdateActionResult`,
+/// `BatchUpdateBlobs` blob, ByteStream `Write` and `Execute` request, with
+/// the identity of the client, the instance name, the digest, the size,
+/// the latency and the status of the request.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct AuditLogConfig {
+    /// File the audit log is appended to.
+    #[serde(deserialize_with = "convert_string_with_shellexpand")]
+    pub path: String,
+
+    /// Size after which the file is rotated. The file is renamed to
+    /// "<path>.1", the previous "<path>.1" to "<path>.2" and so on.
+    ///
+    /// Default: 100MiB
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub max_file_size: u64,
+
+    /// Number of rotated files kept. Older ones are deleted.
+    ///
+    /// Default: 10
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_rotated_files: u32,
+
+    /// Record one request out of every `sample_one_in`.
+    ///
+    /// Default: 1 (every request)
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub sample_one_in: u64,
+
+    /// Number of records waiting to be written before new ones are
+    /// dropped, so a slow disk does not slow down requests.
+    ///
+    /// Default: 10000
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_pending_records: usize,
 }
 
 #[allow(non_camel_case_types)]
@@ -749,6 +1614,12 @@ pub struct GlobalConfig {
     /// Default: `ConfigDigestHashFunction::sha256`
     pub default_digest_hash_function: Option<ConfigDigestHashFunction>,
 
+    /// Implementation used to compute sha256 digests.
+    ///
+    /// Default: `ConfigSha256Backend::sha2` if the CPU has SHA extensions,
+    /// `ConfigSha256Backend::ring` otherwise.
+    pub sha256_backend: Option<ConfigSha256Backend>,
+
     /// Default digest size to use for health check when running
     /// diagnostics checks. Health checks are expected to use this
     /// size for filling a buffer that is used for creation of
@@ -757,6 +1628,24 @@ pub struct GlobalConfig {
     /// Default: 1024*1024 (1MiB)
     #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
     pub default_digest_size_health_check: usize,
+
+    /// Number of threads dedicated to blocking filesystem calls, such as
+    /// opening files, scanning directories and updating file timestamps.
+    /// These threads are separate from the threads that serve requests, so
+    /// slow disks do not add latency to unrelated requests. Threads are
+    /// started on demand, and values above 1024 are capped to 1024.
+    ///
+    /// Default: 64
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub blocking_filesystem_threads: usize,
+
+    /// Number of threads dedicated to CPU heavy blocking work, such as
+    /// hashing files. Threads are started on demand, and values above 1024
+    /// are capped to 1024.
+    ///
+    /// Default: <number of cores on the machine>
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub blocking_cpu_threads: usize,
 }
 
 #[derive(Deserialize, Debug)]
@@ -782,6 +1671,14 @@ pub struct CasConfig {
     /// external service.
     pub experimental_origin_events: Option<OriginEventsSpec>,
 
+    /// Audit log of the requests that change the CAS, the action cache or
+    /// schedule executions.
+    /// If not set, no audit log is written.
+    ///
+    /// Default: None
+    #[serde(default)]
+    pub audit_log: Option<AuditLogConfig>,
+
     /// Any global configurations that apply to all modules live here.
     pub global: Option<GlobalConfig>,
 }
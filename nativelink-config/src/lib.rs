@@ -16,3 +16,4 @@ pub mod cas_server;
 pub mod schedulers;
 pub mod serde_utils;
 pub mod stores;
+pub mod worker_bootstrap;
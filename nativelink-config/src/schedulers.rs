@@ -16,7 +16,10 @@ use std::collections::HashMap;
 
 use serde::Deserialize;
 
-use crate::serde_utils::{convert_duration_with_shellexpand, convert_numeric_with_shellexpand};
+use crate::serde_utils::{
+    convert_duration_with_shellexpand, convert_numeric_with_shellexpand,
+    convert_optional_numeric_with_shellexpand,
+};
 use crate::stores::{GrpcEndpoint, Retry, StoreRefName};
 
 #[allow(non_camel_case_types)]
@@ -44,6 +47,17 @@ pub enum PropertyType {
     /// set to the value with exact string match.
     exact,
 
+    /// The worker may have any number of values for this property, for
+    /// example by listing multiple values in its config. The task will not
+    /// run on a node that does not have the value of the task among them.
+    set,
+
+    /// Like `exact`, but a `*` in the value of the task matches any sequence
+    /// of characters in the value of the worker. For example a task with
+    /// `"os": "ubuntu-*"` runs on workers with `"os": "ubuntu-22.04"` and a
+    /// value of `*` runs on any worker that has the property.
+    wildcard,
+
     /// Does not restrict on this value and instead will be passed to the worker
     /// as an informational piece.
     /// TODO(allada) In the future this will be used by the scheduler and worker
@@ -106,12 +120,34 @@ pub struct SimpleSpec {
     #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
     pub client_action_timeout_s: u64,
 
+    /// Cachable actions that are executed within this many seconds after
+    /// an identical action finished successfully are given its result
+    /// instead of running again. Identical actions that are still running
+    /// are always joined; this also covers a burst of identical actions,
+    /// eg. from several CI shards, that arrives right after the first one
+    /// finished but before its result can be found in the action cache.
+    /// Should not exceed `retain_completed_for_s`, as the result is only
+    /// kept that long.
+    /// The redis backend ignores this.
+    /// Default: 0 (disabled)
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub completed_action_dedup_window_s: u64,
+
     /// Remove workers from pool once the worker has not responded in this
     /// amount of time in seconds.
     /// Default: 5 (seconds)
     #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
     pub worker_timeout_s: u64,
 
+    /// Actions still running this long after their timeout expired are
+    /// killed by the scheduler and completed with a `DEADLINE_EXCEEDED`
+    /// error. Workers kill timed out actions themselves, so this only
+    /// catches workers that fail to, eg. because their `entrypoint` handles
+    /// the timeout.
+    /// Default: 60 (seconds)
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub action_timeout_grace_s: u64,
+
     /// If a job returns an internal error or times out this many times when
     /// attempting to run on a worker the scheduler will return the last error
     /// to the client. Jobs will be retried and this configuration is to help
@@ -122,13 +158,443 @@ pub struct SimpleSpec {
     #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
     pub max_job_retries: usize,
 
+    /// Maximum number of retries shared by all the jobs of a single tool
+    /// invocation (the `tool_invocation_id` of the client's
+    /// `RequestMetadata`). Once an invocation used up its budget, jobs of
+    /// that invocation that fail on a worker are no longer retried and
+    /// return the error to the client right away. This keeps a systemic
+    /// worker problem from multiplying the load of a large build by
+    /// `max_job_retries`. Jobs without a `tool_invocation_id` are not
+    /// affected.
+    /// Default: 0 (no limit)
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_retries_per_invocation: usize,
+
     /// The strategy used to assign workers jobs.
     #[serde(default)]
     pub allocation_strategy: WorkerAllocationStrategy,
 
+    /// Groups the `ExecutionPolicy.priority` of actions into bands. Queued
+    /// actions are handed to workers by band and within a band in the order
+    /// they were queued, so all priorities of a band are treated the same.
+    /// Queue metrics are published for every band. Without bands every
+    /// priority is ordered on its own and all actions share a single
+    /// "default" band in metrics.
+    ///
+    /// For example, with the bands:
+    /// ```json
+    /// [
+    ///   { "name": "interactive", "min_priority": 100 },
+    ///   { "name": "batch", "min_priority": 0 }
+    /// ]
+    /// ```
+    /// An action with priority 150 is handed to a worker before all queued
+    /// actions with priorities below 100.
+    /// Default: no bands
+    #[serde(default)]
+    pub priority_bands: Vec<PriorityBand>,
+
+    /// Keeps actions of low priority from starving behind a steady stream
+    /// of higher priority ones. For every this many seconds an action waited
+    /// in the queue it is ordered as if it were one band higher, or one
+    /// priority higher without `priority_bands`.
+    /// Default: 0 (disabled)
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub priority_aging_interval_s: u64,
+
     /// The storage backend to use for the scheduler.
     /// Default: memory
     pub experimental_backend: Option<ExperimentalSimpleSchedulerBackend>,
+
+    /// Keeps the queued and executing actions of the memory backend in a
+    /// store, so they survive a restart of the scheduler. On startup the
+    /// actions are restored and clients can resume waiting on them with
+    /// `WaitExecution`. Workers abort their actions once they lose the
+    /// connection to the scheduler, so actions that were executing are
+    /// queued again.
+    /// The redis backend keeps all of its state in redis and ignores this.
+    /// Default: None (state is lost on restart)
+    pub experimental_persistence: Option<SchedulerPersistenceSpec>,
+
+    /// Splits the queued actions between multiple schedulers that share the
+    /// redis backend. Every scheduler hands the actions of its own partition
+    /// to its workers right away and only takes over the actions of other
+    /// partitions once they waited `takeover_after_s`, so the actions of a
+    /// scheduler that is down are still run. Clients can look up and wait
+    /// on operations through any of the schedulers.
+    /// Requires the redis backend.
+    /// Default: None (every scheduler matches all actions)
+    pub experimental_partition: Option<SchedulerPartitionSpec>,
+
+    /// Starts a second attempt of actions that run much longer than usual
+    /// on another worker, so a slow or broken worker does not hold up the
+    /// build. Whichever attempt finishes first completes the action and the
+    /// other one is killed.
+    /// Default: None (actions only run on a single worker at a time)
+    pub experimental_hedging: Option<HedgingSpec>,
+
+    /// Shares the workers between the client identities that send actions,
+    /// so a large build of one client does not starve all others. Queued
+    /// actions of the same priority are handed to workers alternating
+    /// between identities, favoring the identities that used the fewest
+    /// workers recently, rather than in the order they were queued.
+    /// The identity of a client is the one the server it sent the action to
+    /// found (see `experimental_identity_header` and `auth`); actions
+    /// without an identity share a single one.
+    /// Default: None (actions of the same priority run in queue order)
+    pub experimental_fair_share: Option<FairShareSpec>,
+
+    /// Prefers handing actions to workers that recently ran actions with
+    /// the same inputs, so the inputs are likely still in the local cache
+    /// of the worker and do not need to be fetched again. The scheduler
+    /// does not see the whole input tree of actions, only the digests of
+    /// their input root and command are compared.
+    /// Default: None (workers are picked by `allocation_strategy` only)
+    pub experimental_worker_affinity: Option<WorkerAffinitySpec>,
+
+    /// Limits how often jobs are retried after specific kinds of failures,
+    /// optionally different for the jobs of some platform pools. These
+    /// limits apply on top of `max_job_retries`, which still limits the
+    /// retries of all kinds together.
+    /// Default: None (all failures count against `max_job_retries` only)
+    pub experimental_retry_policy: Option<RetryPolicySpec>,
+
+    /// Kills actions on their worker once no client waits for their result
+    /// anymore, so the worker can run other actions instead. An action is
+    /// considered abandoned once every `Execute` and `WaitExecution` call
+    /// listening to it went away.
+    /// Default: None (abandoned actions run until they finish)
+    pub experimental_cancel_abandoned_actions: Option<CancelAbandonedActionsSpec>,
+
+    /// Limits how many actions may wait in the queue of platform pools.
+    /// Once the queue of a pool is full, new actions for it are rejected
+    /// with `RESOURCE_EXHAUSTED` and a hint when to retry, instead of
+    /// growing the queue without bounds. The first limit whose platform
+    /// property matches the action is used.
+    ///
+    /// For example:
+    /// ```json
+    /// [
+    ///   {
+    ///     "platform_property": "pool",
+    ///     "value": "gpu",
+    ///     "max_queued_actions": 1000
+    ///   }
+    /// ]
+    /// ```
+    /// Default: no limits
+    #[serde(default)]
+    pub experimental_queue_limits: Vec<QueueLimitSpec>,
+
+    /// Tells workers how often to send keep alives and how many of them
+    /// may be missed in a row before the worker is removed from the pool.
+    /// Workers tolerate failing keep alives up to the same limit before
+    /// they reconnect, so a short network blip no longer drops the worker
+    /// and kills its running actions. When set, workers are removed once
+    /// they were silent for `interval_s * (max_missed_heartbeats + 1)`
+    /// seconds and `worker_timeout_s` is ignored.
+    /// Default: None (workers send keep alives every half of their
+    /// endpoint timeout and are removed after `worker_timeout_s`)
+    pub experimental_worker_heartbeat: Option<WorkerHeartbeatSpec>,
+
+    /// Shuts the scheduler down gracefully when the process receives
+    /// SIGTERM. New actions are rejected with `UNAVAILABLE` so clients
+    /// retry them elsewhere, no more actions are handed to workers and the
+    /// running actions get a grace period to finish. Actions still running
+    /// after that are put back in the queue, then the workers are told to
+    /// disconnect and the unfinished actions are written to
+    /// `experimental_persistence`, if set. The grace period should be
+    /// shorter than the time the process is given to exit.
+    /// Default: None (the process exits without waiting for actions)
+    pub experimental_graceful_shutdown: Option<GracefulShutdownSpec>,
+
+    /// Records how long the actions of every command execute and how large
+    /// their outputs are in a store. Actions expected to run long are
+    /// spread over the workers instead of being stacked on one, and the
+    /// estimates are sent to clients in the metadata of queued operations.
+    /// The scheduler does not see the memory actions use, so actions that
+    /// need a lot of it should still request it with a platform property.
+    /// Default: None (no history is kept)
+    pub experimental_execution_history: Option<ExecutionHistorySpec>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct SchedulerPartitionSpec {
+    /// The partition of this scheduler, starting at 0. Must be below
+    /// `partition_count`.
+    #[serde(deserialize_with = "convert_numeric_with_shellexpand")]
+    pub partition_index: u32,
+
+    /// The number of schedulers sharing the backend. Actions are assigned to
+    /// a partition by the hash of their action digest.
+    #[serde(deserialize_with = "convert_numeric_with_shellexpand")]
+    pub partition_count: u32,
+
+    /// How long an action of another partition has to wait in the queue
+    /// before this scheduler hands it to one of its workers.
+    /// Default: 30 (seconds)
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub takeover_after_s: u64,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct SchedulerPersistenceSpec {
+    /// A reference to the store the scheduler state is written to. This
+    /// should be a store that outlives the scheduler, like a `RedisSpec` or
+    /// a `FilesystemSpec`.
+    pub store: StoreRefName,
+
+    /// The key the scheduler state is written under. Schedulers sharing a
+    /// store need different keys.
+    /// Default: "nativelink_scheduler_state"
+    #[serde(default)]
+    pub key: String,
+
+    /// How often the scheduler state is written to the store if it changed.
+    /// Changes since the last write are lost on restart.
+    /// Default: 1 (seconds)
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub snapshot_interval_s: u64,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct HedgingSpec {
+    /// A second attempt is started once an action runs longer than this
+    /// percentile of the durations of recently finished actions.
+    /// Default: 95
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub percentile: u32,
+
+    /// Number of finished actions needed before any action is hedged.
+    /// Default: 100
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub min_samples: usize,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RetryPolicySpec {
+    /// Maximum number of retries of a job after the worker running it was
+    /// evicted, timed out or stopped sending updates about it.
+    /// Default: `max_job_retries`
+    #[serde(
+        default,
+        deserialize_with = "convert_optional_numeric_with_shellexpand"
+    )]
+    pub max_worker_eviction_retries: Option<usize>,
+
+    /// Maximum number of retries of a job after the worker reported it
+    /// could not reach a service it depends on, like the CAS (errors with
+    /// code `UNAVAILABLE` or `DEADLINE_EXCEEDED`).
+    /// Default: `max_job_retries`
+    #[serde(
+        default,
+        deserialize_with = "convert_optional_numeric_with_shellexpand"
+    )]
+    pub max_infrastructure_retries: Option<usize>,
+
+    /// Maximum number of retries of a job after the worker reported any
+    /// other error while running it.
+    /// Default: `max_job_retries`
+    #[serde(
+        default,
+        deserialize_with = "convert_optional_numeric_with_shellexpand"
+    )]
+    pub max_action_error_retries: Option<usize>,
+
+    /// Different limits for the jobs of platform pools. The first override
+    /// whose platform property matches the job is used.
+    ///
+    /// For example:
+    /// ```json
+    /// [
+    ///   {
+    ///     "platform_property": "pool",
+    ///     "value": "gpu",
+    ///     "max_worker_eviction_retries": 10
+    ///   }
+    /// ]
+    /// ```
+    /// Default: no overrides
+    #[serde(default)]
+    pub platform_overrides: Vec<RetryPolicyOverrideSpec>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RetryPolicyOverrideSpec {
+    /// The platform property the job has to request for this override to
+    /// be used.
+    pub platform_property: String,
+
+    /// The value the job has to request for `platform_property`.
+    pub value: String,
+
+    /// Maximum number of retries of a job after the worker running it was
+    /// evicted, timed out or stopped sending updates about it.
+    /// Default: the limit in `RetryPolicySpec`
+    #[serde(
+        default,
+        deserialize_with = "convert_optional_numeric_with_shellexpand"
+    )]
+    pub max_worker_eviction_retries: Option<usize>,
+
+    /// Maximum number of retries of a job after the worker reported it
+    /// could not reach a service it depends on, like the CAS (errors with
+    /// code `UNAVAILABLE` or `DEADLINE_EXCEEDED`).
+    /// Default: the limit in `RetryPolicySpec`
+    #[serde(
+        default,
+        deserialize_with = "convert_optional_numeric_with_shellexpand"
+    )]
+    pub max_infrastructure_retries: Option<usize>,
+
+    /// Maximum number of retries of a job after the worker reported any
+    /// other error while running it.
+    /// Default: the limit in `RetryPolicySpec`
+    #[serde(
+        default,
+        deserialize_with = "convert_optional_numeric_with_shellexpand"
+    )]
+    pub max_action_error_retries: Option<usize>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct WorkerAffinitySpec {
+    /// The inputs of the actions a worker ran count half as much after
+    /// this many more actions ran on it, as they are likely evicted from
+    /// its cache by then.
+    /// Default: 1000
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub decay_after_actions: usize,
+
+    /// How many more actions a worker that recently ran actions with the
+    /// same inputs may run than the worker picked by `allocation_strategy`
+    /// and still be preferred. Higher values favor cache locality, lower
+    /// values spread the load more evenly.
+    /// Default: 0 (only preferred over workers that are not less busy)
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_load_difference: usize,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct QueueLimitSpec {
+    /// The platform property the action has to request for this limit to
+    /// apply.
+    pub platform_property: String,
+
+    /// The value the action has to request for `platform_property`.
+    pub value: String,
+
+    /// Maximum number of queued actions of the pool. New actions are
+    /// rejected while this many are queued.
+    #[serde(deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_queued_actions: usize,
+
+    /// How long clients are told to wait before sending a rejected action
+    /// again.
+    /// Default: 60 (seconds)
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub retry_after_s: u64,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CancelAbandonedActionsSpec {
+    /// How long no client may listen to an action before it is killed.
+    /// Clients that reconnect within this time keep the action running.
+    /// Clients that are listening refresh the action every 10 seconds, so
+    /// this should be well above that.
+    /// Default: 30 (seconds)
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub grace_period_s: u64,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct GracefulShutdownSpec {
+    /// How long running actions may take to finish once the scheduler
+    /// shuts down.
+    /// Default: 30 (seconds)
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub grace_period_s: u64,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ExecutionHistorySpec {
+    /// A reference to the store the history is written to. Schedulers
+    /// sharing the store share the history.
+    pub store: StoreRefName,
+
+    /// The prefix of the keys the history of every command is written
+    /// under. The digest of the command is appended to it.
+    /// Default: "nativelink_execution_history:"
+    #[serde(default)]
+    pub key_prefix: String,
+
+    /// Actions expected to execute at least this long are only handed to a
+    /// worker that already runs such an action if no other worker can run
+    /// them.
+    /// Default: 60 (seconds)
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub long_action_threshold_s: u64,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct WorkerHeartbeatSpec {
+    /// How often workers send a keep alive to the scheduler.
+    /// Default: 5 (seconds)
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub interval_s: u64,
+
+    /// How many keep alives in a row a worker may miss before it is
+    /// considered dead.
+    /// Default: 3
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_missed_heartbeats: u32,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct FairShareSpec {
+    /// How fast the recorded usage of an identity fades. After this many
+    /// seconds, an action handed to a worker counts half as much.
+    /// Default: 300 (seconds)
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub usage_half_life_s: u64,
+
+    /// The share of the workers of identities, relative to each other. An
+    /// identity with 2 shares may use twice as many workers as one with 1
+    /// share before its actions are handed to workers after those of the
+    /// other identity.
+    ///
+    /// For example:
+    /// ```json
+    /// { "ci": 4, "alice": 1 }
+    /// ```
+    /// Default: 1 share for every identity
+    #[serde(default)]
+    pub identity_shares: HashMap<String, u32>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PriorityBand {
+    /// Name of the band in the queue metrics.
+    pub name: String,
+
+    /// Lowest priority of the band. A band holds all priorities up to the
+    /// `min_priority` of the next higher band. Priorities below every band
+    /// belong to the lowest band.
+    #[serde(deserialize_with = "convert_numeric_with_shellexpand")]
+    pub min_priority: i32,
 }
 
 #[allow(non_camel_case_types)]
@@ -12,6 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
+
 use serde::{Deserialize, Serialize};
 
 use crate::serde_utils::{
@@ -34,6 +36,44 @@ pub enum ConfigDigestHashFunction {
     /// Use the blake3 hash function.
     /// <https://en.wikipedia.org/wiki/BLAKE_(hash_function)>
     blake3,
+
+    /// Use the sha1 hash function. Only meant for clients that can not
+    /// use anything else, sha1 is not collision resistant.
+    /// <https://en.wikipedia.org/wiki/SHA-1>
+    sha1,
+
+    /// Use the sha512 hash function.
+    /// <https://en.wikipedia.org/wiki/SHA-2>
+    sha512,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum ConfigSha256Backend {
+    /// Use the `sha2` crate. It will use the SHA extensions of the CPU
+    /// (SHA-NI on x86, SHA2 on ARM) if they are available at runtime.
+    sha2,
+
+    /// Use the `ring` crate. Its hand written assembly is faster than the
+    /// portable implementation of `sha2` on CPUs without SHA extensions.
+    ring,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigStoreKeyEncoding {
+    /// Digest keys are written as `{hash_in_hex}-{size_in_bytes}`. This is
+    /// the format every version of nativelink has used so far.
+    #[default]
+    hex,
+
+    /// Digest keys are written as `v1_` followed by the unpadded, url safe
+    /// base64 encoding of the hash and the size as a big-endian 64 bit
+    /// integer. The result is always 57 characters long instead of 66 or
+    /// more. Digests whose hash is not 32 bytes long are written as `hex`.
+    ///
+    /// Keys written with `hex` are still readable when this is enabled.
+    compact,
 }
 
 #[allow(non_camel_case_types)]
@@ -78,6 +118,34 @@ pub enum StoreSpec {
     ///
     experimental_s3_store(S3Spec),
 
+    /// GCS store will use Google Cloud Storage as a backend to store the
+    /// files. It talks to the GCS JSON API directly, so it does not need
+    /// the S3 interoperability layer or HMAC keys.
+    ///
+    /// This configuration will never delete files, so you are
+    /// responsible for purging old files in other ways, for example with
+    /// a bucket lifecycle rule.
+    ///
+    /// **Example JSON Config:**
+    /// ```json
+    /// "experimental_gcs_store": {
+    ///   "bucket": "nativelink-cas",
+    ///   "key_prefix": "test-prefix-cas/",
+    ///   "auth": {
+    ///     "service_account": {
+    ///       "key_file": "/etc/nativelink/gcs-key.json"
+    ///     }
+    ///   },
+    ///   "retry": {
+    ///     "max_retries": 6,
+    ///     "delay": 0.3,
+    ///     "jitter": 0.5
+    ///   }
+    /// }
+    /// ```
+    ///
+    experimental_gcs_store(GcsSpec),
+
     /// Verify store is used to apply verifications to an underlying
     /// store implementation. It is strongly encouraged to validate
     /// as much data as you can before accepting data from a client,
@@ -102,9 +170,91 @@ pub enum StoreSpec {
     ///
     verify(Box<VerifySpec>),
 
+    /// Translation store lets clients that use different digest functions
+    /// share one CAS. All content is written to `backend` under its
+    /// `backend_digest_function` digest and `mapping_store` remembers which
+    /// backend digest every other digest function's digest refers to.
+    ///
+    /// Uploads made with a digest function other than
+    /// `backend_digest_function` are buffered in memory, because the
+    /// backend digest is only known once all the data was received. It is
+    /// strongly encouraged to put a `verify` store in front of this store,
+    /// otherwise a client can map any digest to any content.
+    ///
+    /// **Example JSON Config:**
+    /// ```json
+    /// "translation": {
+    ///   "backend": {
+    ///     "ref_store": {
+    ///       "name": "CAS_MAIN_STORE"
+    ///     }
+    ///   },
+    ///   "mapping_store": {
+    ///     "memory": {
+    ///       "eviction_policy": {
+    ///         "max_bytes": 100000000 // 100mb.
+    ///       }
+    ///     }
+    ///   },
+    ///   "backend_digest_function": "sha256"
+    /// }
+    /// ```
+    ///
+    translation(Box<TranslationSpec>),
+
+    /// Limits how many bytes per second are read from and written to the
+    /// backend, so backfills and cache warmers can not saturate a network
+    /// shared with interactive builds. Reads and writes over the limit
+    /// are slowed down, not rejected. Limits can be set for the whole
+    /// store and for each client, where clients are told apart by the
+    /// header set in `experimental_identity_header`.
+    ///
+    /// **Example JSON Config:**
+    /// ```json
+    /// "throttle": {
+    ///   "max_read_bytes_per_second": "200mb",
+    ///   "max_write_bytes_per_second": "100mb",
+    ///   "max_write_bytes_per_second_per_identity": "20mb",
+    ///   "backend": {
+    ///     "ref_store": {
+    ///       "name": "SHARED_CAS_STORE"
+    ///     }
+    ///   }
+    /// }
+    /// ```
+    ///
+    throttle(Box<ThrottleSpec>),
+
+    /// Alias store maps human readable names (eg: `latest-toolchain-manifest`)
+    /// to digests, so tools can refer to immutable content by a stable
+    /// name. Reading an alias returns the digest it points to as
+    /// `{hash}-{size}`, and writing one replaces it. Aliases can also be
+    /// moved atomically with `AliasStore::compare_and_swap`.
+    /// Note: Updates are only atomic between requests made to the same
+    /// store, so only one `alias` store should write to a backend.
+    ///
+    /// **Example JSON Config:**
+    /// ```json
+    /// "alias": {
+    ///   "backend": {
+    ///     "filesystem": {
+    ///       "content_path": "~/.cache/nativelink/content_path-aliases",
+    ///       "temp_path": "~/.cache/nativelink/tmp_path-aliases"
+    ///     }
+    ///   }
+    /// }
+    /// ```
+    ///
+    alias(Box<AliasSpec>),
+
     /// Completeness checking store verifies if the
     /// output files & folders exist in the CAS before forwarding
     /// the request to the underlying store.
+    /// This includes the output files, the trees of the output
+    /// directories and every file in them, and the stdout and stderr
+    /// blobs. If any of them was evicted from the CAS, the action
+    /// result is reported as not found, so the client runs the action
+    /// again instead of failing to download its outputs.
     /// Note: This store should only be used on AC stores.
     ///
     /// **Example JSON Config:**
@@ -158,6 +308,41 @@ pub enum StoreSpec {
     ///
     compression(Box<CompressionSpec>),
 
+    /// Encryption store will encrypt the data before sending it to the
+    /// backend store and decrypt it when reading it back, so the backend
+    /// only ever holds ciphertext. This allows storing data on shared
+    /// infrastructure, like a shared S3 bucket or Redis cluster, while
+    /// meeting at rest encryption requirements.
+    ///
+    /// Every object is encrypted with its own random data key using
+    /// AES-256-GCM in chunks of 64KiB, so partial reads only decrypt the
+    /// chunks they need. The data key is stored next to the object wrapped
+    /// by the master key. The size of an object in the backend is slightly
+    /// larger than its real size, so it is not safe to put a `verify` store
+    /// with `verify_size` between this store and its backend.
+    ///
+    /// **Example JSON Config:**
+    /// ```json
+    /// "encryption": {
+    ///     "master_key": "${NATIVELINK_ENCRYPTION_KEY}",
+    ///     "backend": {
+    ///       "experimental_s3_store": {
+    ///         "region": "eu-north-1",
+    ///         "bucket": "crossplane-bucket-af79aeca9",
+    ///         "key_prefix": "test-prefix-index/",
+    ///         "retry": {
+    ///           "max_retries": 6,
+    ///           "delay": 0.3,
+    ///           "jitter": 0.5
+    ///         },
+    ///         "multipart_max_concurrent_uploads": 10
+    ///       }
+    ///     }
+    ///   }
+    /// ```
+    ///
+    encryption(Box<EncryptionSpec>),
+
     /// A dedup store will take the inputs and run a rolling hash
     /// algorithm on them to slice the input into smaller parts then
     /// run a sha256 algorithm on the slice and if the object doesn't
@@ -179,9 +364,9 @@ pub enum StoreSpec {
     /// the `content_store`, but never put `DedupSpec` as the backend of
     /// `CompressionSpec` as it will negate all the gains.
     ///
-    /// Note: When running `.has()` on this store, it will only check
-    /// to see if the entry exists in the `index_store` and not check
-    /// if the individual chunks exist in the `content_store`.
+    /// Note: When running `.has()` on this store, it will load the
+    /// index from the `index_store` and check that every chunk it
+    /// references exists in the `content_store`.
     ///
     /// **Example JSON Config:**
     /// ```json
@@ -250,6 +435,60 @@ pub enum StoreSpec {
     ///
     existence_cache(Box<ExistenceCacheSpec>),
 
+    /// Retries requests to the backend store that failed with an error
+    /// that is likely temporary, and stops sending requests to a backend
+    /// that keeps failing. Once `circuit_breaker.failure_threshold`
+    /// requests in a row failed, every request fails right away with
+    /// `Unavailable` until `circuit_breaker.open_seconds` passed. After
+    /// that a single request is let through to check if the backend is
+    /// back. This keeps requests from piling up against a backend that
+    /// is down.
+    ///
+    /// **Example JSON Config:**
+    /// ```json
+    /// "retry": {
+    ///     "retry": {
+    ///       "max_retries": 3,
+    ///       "delay": 0.3,
+    ///       "jitter": 0.5
+    ///     },
+    ///     "circuit_breaker": {
+    ///       "failure_threshold": 10,
+    ///       "open_seconds": 30
+    ///     },
+    ///     "backend": {
+    ///       "redis_store": {
+    ///         "addresses": ["redis://127.0.0.1:6379/"]
+    ///       }
+    ///     }
+    ///   }
+    /// ```
+    ///
+    retry(Box<RetryStoreSpec>),
+
+    /// Limits how much data can be stored and how many requests can be
+    /// made through this store. To give every instance name its own
+    /// quota on a shared cache, point each instance name at its own
+    /// `quota` store and let them all wrap the same backend with a
+    /// `ref_store`.
+    ///
+    /// **Example JSON Config:**
+    /// ```json
+    /// "quota": {
+    ///     "max_bytes": "50gb",
+    ///     "max_reads_per_second": 2000,
+    ///     "max_writes_per_second": 500,
+    ///     "on_exceed": "throttle",
+    ///     "backend": {
+    ///       "ref_store": {
+    ///         "name": "SHARED_CAS_STORE"
+    ///       }
+    ///     }
+    ///   }
+    /// ```
+    ///
+    quota(Box<QuotaSpec>),
+
     /// `FastSlow` store will first try to fetch the data from the `fast`
     /// store and then if it does not exist try the `slow` store.
     /// When the object does exist in the `slow` store, it will copy
@@ -298,6 +537,10 @@ pub enum StoreSpec {
     /// The digest hash is used to determine which store to send the
     /// data to.
     ///
+    /// Changing the stores or their weights moves most objects to a
+    /// different store. Set `previous_weight` on the stores to move the
+    /// objects over lazily as they are read instead of losing them.
+    ///
     /// **Example JSON Config:**
     /// ```json
     /// "shard": {
@@ -315,6 +558,73 @@ pub enum StoreSpec {
     ///
     shard(ShardSpec),
 
+    /// Keeps a full copy of the data in each of several stores, usually
+    /// in different regions. Writes go to every replica. Reads go to the
+    /// replica that currently answers the fastest and has not been
+    /// failing, based on moving averages of the latency and error rate of
+    /// its recent reads. A small share of reads is sent to another replica
+    /// so a replica that got faster or recovered is noticed. If a read
+    /// fails before any data was sent, the next replica is tried.
+    ///
+    /// **Example JSON Config:**
+    /// ```json
+    /// "replication": {
+    ///     "replicas": [
+    ///         {
+    ///             "grpc": {
+    ///                 "instance_name": "main",
+    ///                 "endpoints": [{"address": "grpc://cas.us-east1:50051"}],
+    ///                 "store_type": "cas"
+    ///             }
+    ///         },
+    ///         {
+    ///             "grpc": {
+    ///                 "instance_name": "main",
+    ///                 "endpoints": [{"address": "grpc://cas.europe-west1:50051"}],
+    ///                 "store_type": "cas"
+    ///             }
+    ///         }
+    ///     ],
+    ///     "exploration_rate": 0.05
+    /// }
+    /// ```
+    ///
+    replication(ReplicationSpec),
+
+    /// Keeps a full copy of the data in each of several stores, giving
+    /// simple redundancy across for example two disks or two Redis
+    /// clusters. Writes go to every store and succeed once `write_quorum`
+    /// stores accepted the data. Reads try the stores in the order they
+    /// are listed and fail over to the next store if one fails or does not
+    /// have the object. Objects found in a later store are copied into the
+    /// earlier stores that were missing them in the background.
+    ///
+    /// Unlike `replication`, reads always prefer the first store, so the
+    /// stores should be listed from the fastest to the slowest.
+    ///
+    /// **Example JSON Config:**
+    /// ```json
+    /// "mirror": {
+    ///     "stores": [
+    ///         {
+    ///             "filesystem": {
+    ///                 "content_path": "/mnt/disk1/nativelink/content_path-cas",
+    ///                 "temp_path": "/mnt/disk1/nativelink/tmp_path-cas"
+    ///             }
+    ///         },
+    ///         {
+    ///             "filesystem": {
+    ///                 "content_path": "/mnt/disk2/nativelink/content_path-cas",
+    ///                 "temp_path": "/mnt/disk2/nativelink/tmp_path-cas"
+    ///             }
+    ///         }
+    ///     ],
+    ///     "write_quorum": 1
+    /// }
+    /// ```
+    ///
+    mirror(MirrorSpec),
+
     /// Stores the data on the filesystem. This store is designed for
     /// local persistent storage. Restarts of this program should restore
     /// the previous state, meaning anything uploaded will be persistent
@@ -336,6 +646,46 @@ pub enum StoreSpec {
     ///
     filesystem(FilesystemSpec),
 
+    /// Stores the data in a single embedded key-value database file
+    /// (redb). Every write is committed durably before it is acknowledged,
+    /// so the store survives crashes and restarts. It is meant for action
+    /// cache entries and small blobs, where it uses far less space and
+    /// far fewer inodes than one file per entry in the `filesystem` store.
+    /// Larger objects should be routed to another store, for example with
+    /// a `size_partitioning` store.
+    ///
+    /// This configuration will never delete entries, so you are
+    /// responsible for purging old entries in other ways.
+    ///
+    /// **Example JSON Config:**
+    /// ```json
+    /// "experimental_redb_store": {
+    ///     "path": "/tmp/nativelink/data/ac.redb",
+    ///     "max_object_size": "1mb"
+    /// }
+    /// ```
+    ///
+    experimental_redb_store(RedbSpec),
+
+    /// Serves objects straight out of a single uncompressed tar or zip
+    /// archive of a pre-seeded CAS, for example for air-gapped CI runners
+    /// that ship their cache as one artifact. The archive uses the layout
+    /// of the `content_path` of a `filesystem` store, so
+    /// `tar -cf cas.tar -C <content_path> d s` builds one. An index of the
+    /// archive is built when the store is opened.
+    ///
+    /// This store is read only, uploads to it fail.
+    ///
+    /// **Example JSON Config:**
+    /// ```json
+    /// "experimental_archive_store": {
+    ///     "path": "/opt/ci/cas.tar",
+    ///     "format": "tar"
+    /// }
+    /// ```
+    ///
+    experimental_archive_store(ArchiveSpec),
+
     /// Store used to reference a store in the root store manager.
     /// This is useful for cases when you want to share a store in different
     /// nested stores. Example, you may want to share the same memory store
@@ -359,6 +709,11 @@ pub enum StoreSpec {
     /// safely use `VerifySpec.verify_size = true`, this store should be safe
     /// to use (ie: CAS stores).
     ///
+    /// Lookups and downloads only go to the store the digest size picks, so
+    /// objects are never looked up in both stores. A common setup is a fast
+    /// store with little space, like redis, for small objects and a large
+    /// store, like S3, for the rest.
+    ///
     /// **Example JSON Config:**
     /// ```json
     /// "size_partitioning": {
@@ -400,6 +755,47 @@ pub enum StoreSpec {
     ///   }
     /// ```
     ///
+    /// When `local_cache` is set, the store is automatically wrapped with a
+    /// local filesystem fast tier and an existence cache. This is the same
+    /// as building a `fast_slow` store with a `filesystem` store as `fast`
+    /// and an `existence_cache` wrapping this store as `slow`.
+    ///
+    /// **Example JSON Config:**
+    /// ```json
+    /// "grpc": {
+    ///     "instance_name": "main",
+    ///     "endpoints": [
+    ///       {"address": "grpc://${CAS_ENDPOINT:-127.0.0.1}:50051"}
+    ///     ],
+    ///     "store_type": "cas",
+    ///     "local_cache": {
+    ///       "cache_dir": "${HOME}/.cache/nativelink/cas",
+    ///       "max_bytes": "10gb"
+    ///     }
+    ///   }
+    /// ```
+    ///
+    /// When `replication` is set, `update` only writes the blob to a queue
+    /// on local disk and returns. A background task pushes the queued blobs
+    /// to the upstream store, small blobs in batches and large blobs as
+    /// streams, and retries failed pushes until they succeed. Reads of
+    /// blobs that are still queued are served from the queue.
+    ///
+    /// **Example JSON Config:**
+    /// ```json
+    /// "grpc": {
+    ///     "instance_name": "main",
+    ///     "endpoints": [
+    ///       {"address": "grpc://${REPLICA_CAS_ENDPOINT:-127.0.0.1}:50051"}
+    ///     ],
+    ///     "store_type": "cas",
+    ///     "replication": {
+    ///       "queue_dir": "${HOME}/.cache/nativelink/replication",
+    ///       "max_concurrent_pushes": 8
+    ///     }
+    ///   }
+    /// ```
+    ///
     grpc(GrpcSpec),
 
     /// Stores data in any stores compatible with Redis APIs.
@@ -445,6 +841,19 @@ pub struct ShardConfig {
     ///
     /// Default: 1
     pub weight: Option<u32>,
+
+    /// The weight the store had before the set of shards last changed.
+    /// Setting it on any store enables rebalancing: objects that are not
+    /// found in the store the current weights pick are looked up in the
+    /// store the previous weights picked, and are moved into the current
+    /// store when they are read. Stores without a previous weight are
+    /// treated as added since the last change (weight 0). A store that is
+    /// being removed should keep its previous weight and get a `weight`
+    /// of 0 until the objects it still holds are no longer needed.
+    ///
+    /// Default: None (no rebalancing)
+    #[serde(default)]
+    pub previous_weight: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -454,6 +863,55 @@ pub struct ShardSpec {
     pub stores: Vec<ShardConfig>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ReplicationSpec {
+    /// Stores that each hold a full copy of the data.
+    pub replicas: Vec<StoreSpec>,
+
+    /// Weight of the newest read when updating the moving averages of the
+    /// latency and error rate of a replica. Higher values react faster to
+    /// a change, lower values smooth out noisy replicas.
+    ///
+    /// Default: 0.2
+    pub smoothing_factor: Option<f64>,
+
+    /// Share of reads sent to a random replica other than the preferred
+    /// one, so the statistics of every replica stay current.
+    ///
+    /// Default: 0.05
+    pub exploration_rate: Option<f64>,
+
+    /// Replicas whose error rate is above this value are only read from
+    /// when every other replica is failing too, or when exploring.
+    ///
+    /// Default: 0.5
+    pub max_error_rate: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MirrorSpec {
+    /// Stores that each hold a full copy of the data, in the order reads
+    /// try them.
+    pub stores: Vec<StoreSpec>,
+
+    /// Number of stores that must accept an upload for it to succeed.
+    /// Stores that fail are dropped from the upload and are repaired when
+    /// the object is read. Must not be larger than the number of stores.
+    ///
+    /// Default: 0 (every store)
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub write_quorum: usize,
+
+    /// Do not copy objects into the stores that were found to be missing
+    /// them during a read.
+    ///
+    /// Default: false
+    #[serde(default)]
+    pub disable_read_repair: bool,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct SizePartitioningSpec {
@@ -464,7 +922,7 @@ pub struct SizePartitioningSpec {
     /// Store to send data when object is < (less than) size.
     pub lower_store: StoreSpec,
 
-    /// Store to send data when object is >= (less than eq) size.
+    /// Store to send data when object is >= (greater than or equal) size.
     pub upper_store: StoreSpec,
 }
 
@@ -512,6 +970,83 @@ pub struct FilesystemSpec {
     /// Default: 4096
     #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
     pub block_size: u64,
+
+    /// Uploads of at most this many bytes that are already fully in memory
+    /// (eg: `BatchUpdateBlobs` or `update_oneshot()`) are written to the temp
+    /// file with a single write instead of being streamed into it. Larger
+    /// uploads are streamed as usual.
+    ///
+    /// Default: 1MiB
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub max_direct_write_size: u64,
+
+    /// How digest keys are turned into file names. When changed, files
+    /// already in `content_path` are renamed at startup.
+    ///
+    /// Default: hex
+    #[serde(default)]
+    pub key_encoding: ConfigStoreKeyEncoding,
+
+    /// Set when `content_path` and `temp_path` are on a network filesystem,
+    /// such as NFS or SMB. Access times are then kept in an index file in
+    /// `content_path` instead of in the atime of the files, and files are
+    /// moved into place by hard linking and unlinking them instead of with
+    /// `rename()`. Files that are not in the index yet, for example right
+    /// after enabling this, use their modification time.
+    ///
+    /// Default: false
+    #[serde(default)]
+    pub network_filesystem: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RedbSpec {
+    /// Path of the database file. It is created if it does not exist.
+    #[serde(deserialize_with = "convert_string_with_shellexpand")]
+    pub path: String,
+
+    /// Uploads larger than this many bytes are rejected. Objects are held
+    /// in memory while they are written and read, so this should stay
+    /// small.
+    ///
+    /// Default: 4MiB
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub max_object_size: u64,
+
+    /// Number of bytes of memory the database may use to cache pages.
+    ///
+    /// Default: 64MiB
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub cache_size: usize,
+}
+
+/// Format of the archive an `experimental_archive_store` reads from.
+#[allow(non_camel_case_types)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    /// A POSIX (ustar), GNU or pax tar archive. The archive itself must not
+    /// be compressed.
+    #[default]
+    tar,
+
+    /// A zip archive, including zip64. Entries must be stored without
+    /// compression, for example with `zip -0`.
+    zip,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ArchiveSpec {
+    /// Path of the archive file.
+    #[serde(deserialize_with = "convert_string_with_shellexpand")]
+    pub path: String,
+
+    /// Format of the archive.
+    ///
+    /// Default: tar
+    #[serde(default)]
+    pub format: ArchiveFormat,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -524,6 +1059,127 @@ pub struct FastSlowSpec {
     /// If the object does not exist in the `fast` store it will try to
     /// get it from this store.
     pub slow: StoreSpec,
+
+    /// Tracks how often each object is read and only copies objects read
+    /// from the `slow` store into the `fast` store once they are popular.
+    /// If not set, every object read from the `slow` store is copied into
+    /// the `fast` store.
+    ///
+    /// Default: None
+    #[serde(default)]
+    pub popularity: Option<PopularitySpec>,
+
+    /// How and when objects are copied into the `fast` store.
+    ///
+    /// Default: objects are copied inline on reads and writes.
+    #[serde(default)]
+    pub population: FastSlowPopulationSpec,
+
+    /// Use the `fast` store as a write-back cache. Uploads are only
+    /// written to the `fast` store, and objects are copied to the `slow`
+    /// store in the background when the `fast` store evicts them. The
+    /// `fast` store must be a `filesystem` store.
+    /// Note: Objects that were not copied yet are lost if the process
+    /// stops, because the `fast` store clears its `temp_path` on startup.
+    ///
+    /// Default: false
+    #[serde(default)]
+    pub write_back: bool,
+}
+
+/// When a `fast_slow` store copies an object into its `fast` store.
+#[allow(non_camel_case_types)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FastSlowPopulateMode {
+    /// Copy the object while it is streamed to or from the client. The
+    /// client waits until the `fast` store has the object.
+    #[default]
+    inline,
+
+    /// Queue the object to be copied from the `slow` store into the `fast`
+    /// store in the background once the client request is done. Objects
+    /// are dropped from the queue if it is full.
+    background,
+
+    /// Never copy the object into the `fast` store.
+    never,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct FastSlowPopulationSpec {
+    /// When objects read from the `slow` store are copied into the `fast`
+    /// store. Objects not popular enough to be promoted are never copied.
+    ///
+    /// Default: inline
+    #[serde(default)]
+    pub on_read: FastSlowPopulateMode,
+
+    /// When uploaded objects are written into the `fast` store. Uploads
+    /// always go to the `slow` store inline.
+    ///
+    /// Default: inline
+    #[serde(default)]
+    pub on_write: FastSlowPopulateMode,
+
+    /// Maximum number of objects copied into the `fast` store in the
+    /// background at the same time.
+    ///
+    /// Default: 16
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_concurrent_jobs: usize,
+
+    /// Maximum number of objects waiting to be copied into the `fast`
+    /// store in the background. Objects queued while the queue is full
+    /// are not copied.
+    ///
+    /// Default: 1024
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_queued_jobs: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PopularitySpec {
+    /// Name of the tracker that counts the reads. Stores that use the same
+    /// name share the counts. The most read objects of a tracker can be
+    /// listed with the admin API at
+    /// `<admin_path>/popularity/<tracker_name>/hottest/<count>`.
+    ///
+    /// Default: `"fast_slow"`
+    #[serde(default)]
+    pub tracker_name: String,
+
+    /// Number of reads (including the current one) an object needs before
+    /// a read from the `slow` store copies it into the `fast` store.
+    /// Objects with fewer reads are streamed from the `slow` store without
+    /// being copied.
+    ///
+    /// Default: 0 (always copy)
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub min_reads_to_promote: u64,
+
+    /// Number of counters in each of the four rows of the count-min
+    /// sketch. Wider sketches give more accurate counts and use 16 bytes
+    /// of memory per unit of width.
+    ///
+    /// Default: 65536
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub sketch_width: usize,
+
+    /// All counts are halved after this many reads, so objects that are
+    /// no longer read lose their popularity over time.
+    ///
+    /// Default: 10 * `sketch_width`
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub decay_after_reads: u64,
+
+    /// Number of the most read objects that are remembered for the admin
+    /// API.
+    ///
+    /// Default: 100
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_hottest: usize,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
@@ -604,6 +1260,118 @@ pub struct ExistenceCacheSpec {
     /// value will cause items to never be removed from the store causing
     /// infinite memory usage.
     pub eviction_policy: Option<EvictionPolicy>,
+
+    /// Number of seconds to remember that an object is missing from the
+    /// backend. While remembered, lookups of the object are answered
+    /// without asking the backend. Uploads and downloads of the object
+    /// through this store forget it right away. Keep this short, since
+    /// objects uploaded to the backend by other means are reported as
+    /// missing until it expires.
+    /// Default: 0. Zero means missing objects are not cached.
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub negative_cache_seconds: u32,
+
+    /// Maximum number of missing objects to remember.
+    /// Default: 100000.
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub negative_cache_max_count: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RetryStoreSpec {
+    /// The underlying store wrap around. All content will first flow
+    /// through self before forwarding to backend. In the event there
+    /// is an error detected in self, the connection to the backend
+    /// will be terminated, and early termination should always cause
+    /// updates to fail on the backend.
+    pub backend: StoreSpec,
+
+    /// Retry configuration to use for requests to the backend.
+    #[serde(default)]
+    pub retry: Retry,
+
+    /// The maximum number of bytes of an upload to keep so it can be
+    /// retried. Uploads that failed after sending more than this are not
+    /// retried.
+    ///
+    /// Default: 5MB.
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub max_retry_buffer_size: usize,
+
+    /// Configuration of the circuit breaker.
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerSpec,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct CircuitBreakerSpec {
+    /// Number of requests in a row that must fail, after retrying, to open
+    /// the circuit. Only errors that are retried count as failures, so
+    /// for example `NotFound` never opens the circuit.
+    ///
+    /// Default: 0. Zero means the circuit never opens.
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub failure_threshold: u32,
+
+    /// Number of seconds the circuit stays open before a request is let
+    /// through to check if the backend is back.
+    ///
+    /// Default: 30.
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub open_seconds: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct QuotaSpec {
+    /// The underlying store wrap around. All content will first flow
+    /// through self before forwarding to backend. In the event there
+    /// is an error detected in self, the connection to the backend
+    /// will be terminated, and early termination should always cause
+    /// updates to fail on the backend.
+    pub backend: StoreSpec,
+
+    /// Maximum total size of the objects uploaded through this store that
+    /// are still in the backend. Uploads that would go over it fail with
+    /// `ResourceExhausted`. The objects are tracked in memory, so the
+    /// usage starts at zero when the process restarts.
+    ///
+    /// Default: 0. Zero means no limit.
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub max_bytes: u64,
+
+    /// Maximum number of `has` and `get` requests per second.
+    ///
+    /// Default: 0. Zero means no limit.
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_reads_per_second: u32,
+
+    /// Maximum number of uploads per second.
+    ///
+    /// Default: 0. Zero means no limit.
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_writes_per_second: u32,
+
+    /// What to do with requests over `max_reads_per_second` or
+    /// `max_writes_per_second`.
+    ///
+    /// Default: reject
+    #[serde(default)]
+    pub on_exceed: QuotaExceededAction,
+}
+
+/// Action a `quota` store takes with requests over the limit.
+#[allow(non_camel_case_types)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaExceededAction {
+    /// Fail the request with `ResourceExhausted`.
+    #[default]
+    reject,
+
+    /// Delay the request until it fits in the limit.
+    throttle,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -630,6 +1398,99 @@ pub struct VerifySpec {
     /// This should be set to None for AC, but hashing function like `sha256` for CAS stores.
     #[serde(default)]
     pub verify_hash: bool,
+
+    /// If set the store will also hash data read from the backend and
+    /// verify that it matches the requested digest, catching objects that
+    /// were corrupted after they were written. Only reads of the whole
+    /// object can be verified, partial reads are passed through as is.
+    /// If `verify_size` is set the size of whole reads is verified too.
+    ///
+    /// Default: false
+    #[serde(default)]
+    pub verify_hash_on_read: bool,
+
+    /// What to do when a verification fails.
+    ///
+    /// Default: reject
+    #[serde(default)]
+    pub on_failure: VerifyFailureAction,
+}
+
+/// Action a `verify` store takes when data fails verification.
+#[allow(non_camel_case_types)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyFailureAction {
+    /// Fail the upload or read with an error.
+    #[default]
+    reject,
+
+    /// Log a warning and let the data through. Useful to find out how
+    /// often verification would fail before enforcing it.
+    log,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TranslationSpec {
+    /// The store that holds the content, keyed by the digest of
+    /// `backend_digest_function`.
+    pub backend: StoreSpec,
+
+    /// The store that maps digests of other digest functions to the
+    /// digest the content is stored under in `backend`. This store must
+    /// accept string keys (eg: memory, filesystem or redis).
+    pub mapping_store: StoreSpec,
+
+    /// The digest function `backend` is keyed by.
+    ///
+    /// Default: sha256
+    #[serde(default)]
+    pub backend_digest_function: Option<ConfigDigestHashFunction>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ThrottleSpec {
+    /// The underlying store wrap around. All content will first flow
+    /// through self before forwarding to backend.
+    pub backend: StoreSpec,
+
+    /// Maximum number of bytes per second read from the backend.
+    ///
+    /// Default: 0. Zero means no limit.
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub max_read_bytes_per_second: u64,
+
+    /// Maximum number of bytes per second written to the backend.
+    ///
+    /// Default: 0. Zero means no limit.
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub max_write_bytes_per_second: u64,
+
+    /// Maximum number of bytes per second read from the backend by a
+    /// single client identity. Requests without an identity share one
+    /// limit.
+    ///
+    /// Default: 0. Zero means no limit.
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub max_read_bytes_per_second_per_identity: u64,
+
+    /// Maximum number of bytes per second written to the backend by a
+    /// single client identity. Requests without an identity share one
+    /// limit.
+    ///
+    /// Default: 0. Zero means no limit.
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub max_write_bytes_per_second_per_identity: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AliasSpec {
+    /// The store the aliases are kept in. This store must accept string
+    /// keys (eg: memory, filesystem or redis) and should not evict the
+    /// aliases.
+    pub backend: StoreSpec,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -677,6 +1538,58 @@ pub enum CompressionAlgorithm {
     ///
     /// see: <https://lz4.github.io/lz4/>
     lz4(Lz4Config),
+
+    /// Zstandard compression algorithm compresses much better than LZ4 at
+    /// a higher CPU cost. Small objects barely compress on their own, so
+    /// it can train a dictionary on a sample of the small objects and
+    /// compress later small objects with it.
+    ///
+    /// Objects compressed with `lz4` can still be read after switching a
+    /// store to `zstd`.
+    ///
+    /// see: <https://facebook.github.io/zstd/>
+    zstd(ZstdConfig),
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct ZstdConfig {
+    /// Compression level, from 1 (fastest) to 22 (smallest).
+    ///
+    /// Default: 3
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub level: i32,
+
+    /// If set, a dictionary is trained on the first small objects
+    /// uploaded and used to compress every later small object. The
+    /// dictionary is stored in the `backend` and reused after restarts.
+    ///
+    /// Default: None. Objects are compressed without a dictionary.
+    #[serde(default)]
+    pub dictionary: Option<ZstdDictionaryConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct ZstdDictionaryConfig {
+    /// Objects of at most this many bytes are sampled for training and
+    /// compressed with the dictionary.
+    ///
+    /// Default: 4096 (4k).
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub max_object_size: u32,
+
+    /// Number of small objects sampled before the dictionary is trained.
+    ///
+    /// Default: 1000
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub sample_count: u32,
+
+    /// Maximum size of the trained dictionary.
+    ///
+    /// Default: 65536 (64k).
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub max_dictionary_size: u32,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -693,6 +1606,31 @@ pub struct CompressionSpec {
     pub compression_algorithm: CompressionAlgorithm,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct EncryptionSpec {
+    /// The underlying store wrap around. All content will first flow
+    /// through self before forwarding to backend. In the event there
+    /// is an error detected in self, the connection to the backend
+    /// will be terminated, and early termination should always cause
+    /// updates to fail on the backend.
+    pub backend: StoreSpec,
+
+    /// The 256 bit master key used to wrap the data key of every object,
+    /// as 64 hex characters. Use an environment variable, like
+    /// `"${NATIVELINK_ENCRYPTION_KEY}"`, to keep it out of the config file.
+    #[serde(deserialize_with = "convert_string_with_shellexpand")]
+    pub master_key: String,
+
+    /// Master keys that were used before `master_key`, in the same format.
+    /// Objects wrapped by one of these keys can still be read, which
+    /// allows rotating the master key without losing existing objects.
+    ///
+    /// Default: []
+    #[serde(default, deserialize_with = "convert_vec_string_with_shellexpand")]
+    pub previous_master_keys: Vec<String>,
+}
+
 /// Eviction policy always works on LRU (Least Recently Used). Any time an entry
 /// is touched it updates the timestamp. Inserts and updates will execute the
 /// eviction policy removing any expired entries and/or the oldest entries
@@ -766,6 +1704,9 @@ pub struct S3Spec {
     pub max_retry_buffer_per_request: Option<usize>,
 
     /// Maximum number of concurrent `UploadPart` requests per `MultipartUpload`.
+    /// Each part in flight is held in memory. Parts are sized so an upload
+    /// never needs more than the 10,000 parts S3 allows, but are at least
+    /// 5MB.
     ///
     /// Default: 10.
     pub multipart_max_concurrent_uploads: Option<usize>,
@@ -785,6 +1726,106 @@ pub struct S3Spec {
     /// Default: false
     #[serde(default)]
     pub disable_http2: bool,
+
+    /// Tags set on every object this store uploads. Bucket lifecycle rules
+    /// can filter on tags, for example to expire objects or to move them to
+    /// a cheaper storage class. S3 allows at most 10 tags per object.
+    ///
+    /// Default: no tags.
+    #[serde(default)]
+    pub object_tags: HashMap<String, String>,
+
+    /// Storage class of the objects this store uploads, for example
+    /// `STANDARD_IA` or `INTELLIGENT_TIERING`.
+    ///
+    /// Default: None. The default storage class of the bucket is used.
+    #[serde(default, deserialize_with = "convert_optional_string_with_shellexpand")]
+    pub storage_class: Option<String>,
+
+    /// If non-zero, objects are uploaded with a `last-accessed` tag holding
+    /// the number of seconds since the unix epoch, and the tag is refreshed
+    /// when the object is read and this store did not refresh it in the
+    /// last `last_accessed_tag_refresh_s` seconds. This lets bucket
+    /// lifecycle rules or external tools evict objects that were not read
+    /// recently instead of nativelink. Refreshing replaces all tags of the
+    /// object with `object_tags` and the new `last-accessed` tag.
+    ///
+    /// Default: 0. Zero means objects don't get a `last-accessed` tag.
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub last_accessed_tag_refresh_s: u32,
+}
+
+/// How the GCS store gets the `OAuth2` access tokens it sends to GCS.
+#[allow(non_camel_case_types)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub enum GcsAuth {
+    /// Uses the service account key file that the
+    /// `GOOGLE_APPLICATION_CREDENTIALS` environment variable points to if
+    /// it is set, otherwise the metadata server like `workload_identity`.
+    #[default]
+    application_default,
+
+    /// Signs token requests with the key of a service account.
+    service_account {
+        /// Path to the JSON key file of the service account.
+        #[serde(deserialize_with = "convert_string_with_shellexpand")]
+        key_file: String,
+    },
+
+    /// Gets tokens from the metadata server. This is the identity of the
+    /// GCE instance or, on GKE, the Kubernetes service account bound with
+    /// workload identity.
+    workload_identity,
+
+    /// Sends requests without credentials. Only useful for emulators and
+    /// public buckets.
+    none,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct GcsSpec {
+    /// Bucket name to use as the backend.
+    #[serde(default, deserialize_with = "convert_string_with_shellexpand")]
+    pub bucket: String,
+
+    /// If you wish to prefix the object names in the bucket. If None, no
+    /// prefix will be used.
+    #[serde(default)]
+    pub key_prefix: Option<String>,
+
+    /// How to authenticate with GCS.
+    ///
+    /// Default: `application_default`
+    #[serde(default)]
+    pub auth: GcsAuth,
+
+    /// Endpoint of the GCS API. Only change this to use an emulator.
+    ///
+    /// Default: <https://storage.googleapis.com>
+    #[serde(default, deserialize_with = "convert_optional_string_with_shellexpand")]
+    pub endpoint: Option<String>,
+
+    /// Retry configuration to use when a network request fails.
+    #[serde(default)]
+    pub retry: Retry,
+
+    /// If the number of seconds since the `updated` time of the object is
+    /// greater than this value, the object will not be considered
+    /// "existing". See `S3Spec::consider_expired_after_s`.
+    ///
+    /// Default: 0. Zero means never consider an object expired.
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub consider_expired_after_s: u32,
+
+    /// Size of the chunks of resumable uploads. Uploads with a known size
+    /// of at most one chunk are sent in a single request. Each upload
+    /// holds one chunk in memory so it can be retried. Rounded up to a
+    /// multiple of 256KiB as GCS requires.
+    ///
+    /// Default: 8MiB.
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub resumable_chunk_size: usize,
 }
 
 #[allow(non_camel_case_types)]
@@ -850,6 +1891,81 @@ pub struct GrpcSpec {
     /// the load over multiple TCP connections.  Default 1.
     #[serde(default)]
     pub connections_per_endpoint: usize,
+
+    /// If set, objects are cached on local disk in front of the upstream
+    /// store. Only supported when `store_type` is `cas`.
+    ///
+    /// Default: None (No local cache)
+    #[serde(default)]
+    pub local_cache: Option<GrpcLocalCacheSpec>,
+
+    /// If set, writes are queued on local disk and pushed to the upstream
+    /// store in the background instead of being sent before `update`
+    /// returns. Only supported when `store_type` is `cas`.
+    ///
+    /// Default: None (Writes go straight to the upstream store)
+    #[serde(default)]
+    pub replication: Option<GrpcReplicationSpec>,
+
+    /// The maximum number of bytes of an upload to keep so it can be
+    /// resumed from the offset the upstream store committed if the upload
+    /// fails part way. Uploads that fail further than this beyond the
+    /// committed offset are not retried.
+    ///
+    /// Default: 5MB.
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub max_retry_buffer_size: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct GrpcLocalCacheSpec {
+    /// Directory to store the cached objects in. A `content` and a `tmp`
+    /// directory will be created inside of it.
+    #[serde(deserialize_with = "convert_string_with_shellexpand")]
+    pub cache_dir: String,
+
+    /// Maximum number of bytes to keep in the local cache before the least
+    /// recently used objects are evicted.
+    ///
+    /// Default: 0. Zero means never evict based on size.
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub max_bytes: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct GrpcReplicationSpec {
+    /// Directory the queued blobs are written to until the upstream store
+    /// accepted them. Blobs left in it are pushed again after a restart.
+    #[serde(deserialize_with = "convert_string_with_shellexpand")]
+    pub queue_dir: String,
+
+    /// Blobs up to this size are pushed together with `BatchUpdateBlobs`,
+    /// larger ones are streamed one by one with `ByteStream.Write`.
+    ///
+    /// Default: 64KiB
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub max_batch_blob_size: usize,
+
+    /// Maximum total size of the blobs sent in one `BatchUpdateBlobs`
+    /// request. Must stay below the message size limit of the upstream.
+    ///
+    /// Default: 3MiB
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub max_batch_total_size: usize,
+
+    /// Maximum number of batch requests and streams in flight at once.
+    ///
+    /// Default: 16
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_concurrent_pushes: usize,
+
+    /// Time to wait before pushing a blob again after a failed push.
+    ///
+    /// Default: 30 (seconds)
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub retry_delay_s: u64,
 }
 
 /// The possible error codes that might occur on an upstream request.
@@ -914,6 +2030,24 @@ pub struct RedisSpec {
     #[serde(default)]
     pub key_prefix: String,
 
+    /// How digest keys are encoded before `key_prefix` is prepended.
+    /// When set to `compact`, reads that miss fall back to the `hex`
+    /// key so data written before the switch is still found.
+    ///
+    /// Default: hex
+    #[serde(default)]
+    pub key_encoding: ConfigStoreKeyEncoding,
+
+    /// If set, entries are stored as fields of a single Redis hash instead
+    /// of as one string key per entry. This layout is intended for action
+    /// cache stores and allows entries to expire individually and to be
+    /// purged by age. Data written with the other layout is not readable
+    /// when this is enabled and vice versa.
+    ///
+    /// Default: None (one string key per entry)
+    #[serde(default)]
+    pub ac_hash_layout: Option<RedisAcHashLayoutSpec>,
+
     /// Set the mode Redis is operating in.
     ///
     /// Available options are "cluster" for
@@ -993,6 +2127,32 @@ pub struct RedisSpec {
     pub retry: Retry,
 }
 
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RedisAcHashLayoutSpec {
+    /// Name of the partition the entries of this store are written to.
+    /// Every partition is its own Redis hash, so stores serving different
+    /// instances should use different partitions (usually the instance
+    /// name). `key_prefix` is prepended to the name of the hash.
+    #[serde(deserialize_with = "convert_string_with_shellexpand")]
+    pub partition: String,
+
+    /// Number of seconds an entry lives after it was last written. This
+    /// uses hash field expiration, which requires Redis 7.4 or newer.
+    ///
+    /// Default: 0. Zero means entries never expire.
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub entry_ttl_s: u64,
+
+    /// Maintain a sorted set of the time every entry was last written.
+    /// This is required to purge entries older than a given time, at the
+    /// cost of an additional write per update.
+    ///
+    /// Default: false
+    #[serde(default)]
+    pub index_by_update_time: bool,
+}
+
 #[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum RedisMode {
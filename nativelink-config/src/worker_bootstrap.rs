@@ -0,0 +1,290 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cas_server::{
+    CasConfig, EndpointConfig, LocalWorkerConfig, UploadActionResultConfig, WorkerConfig,
+    WorkerProperty,
+};
+use crate::stores::{
+    EvictionPolicy, FastSlowPopulationSpec, FastSlowSpec, FilesystemSpec, GrpcEndpoint, GrpcSpec,
+    Retry, StoreSpec, StoreType,
+};
+
+/// Prefix of the environment variables that override the fields of a
+/// [`WorkerBootstrapConfig`]. Ex: `NATIVELINK_WORKER_SCHEDULER_ENDPOINT`.
+pub const WORKER_ENV_PREFIX: &str = "NATIVELINK_WORKER_";
+
+/// Name of the fast slow store of the generated [`CasConfig`].
+const FAST_SLOW_STORE_NAME: &str = "WORKER_FAST_SLOW_STORE";
+/// Name of the AC store of the generated [`CasConfig`].
+const AC_STORE_NAME: &str = "WORKER_AC_STORE";
+
+/// Default name of the worker if none is set.
+const DEFAULT_WORKER_NAME: &str = "worker";
+/// Default size of the local CAS cache if none is set.
+const DEFAULT_MAX_CACHE_BYTES: usize = 10 * 1024 * 1024 * 1024;
+
+/// Platform property the number of slots is advertised as.
+const SLOTS_PLATFORM_PROPERTY: &str = "cpu_count";
+
+/// Configuration of a standalone worker that connects to a remote
+/// scheduler and CAS. It covers only what a worker needs and is turned
+/// into a full [`CasConfig`] with [`WorkerBootstrapConfig::to_cas_config`].
+///
+/// Every field can be set in a TOML file, overridden by environment
+/// variables prefixed with [`WORKER_ENV_PREFIX`] (ex:
+/// `NATIVELINK_WORKER_CACHE_DIR`) and then by command line flags.
+///
+/// **Example TOML Config:**
+/// ```toml
+/// scheduler_endpoint = "grpc://127.0.0.1:50061"
+/// cas_endpoint = "grpc://127.0.0.1:50051"
+/// cache_dir = "/tmp/nativelink/worker"
+/// slots = 8
+///
+/// [platform_properties]
+/// OSFamily = "linux"
+/// ```
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct WorkerBootstrapConfig {
+    /// Name of the worker used for logging and metrics.
+    ///
+    /// Default: "worker"
+    #[serde(default)]
+    pub name: String,
+
+    /// Endpoint of the scheduler's `WorkerApiService`.
+    /// Ex: `"grpc://127.0.0.1:50061"`
+    #[serde(default)]
+    pub scheduler_endpoint: String,
+
+    /// Endpoint serving the CAS, AC and `ByteStream` services the worker
+    /// downloads inputs from and uploads results to.
+    /// Ex: `"grpc://127.0.0.1:50051"`
+    #[serde(default)]
+    pub cas_endpoint: String,
+
+    /// Instance name used for requests to `cas_endpoint`.
+    ///
+    /// Default: "" (empty instance name)
+    #[serde(default)]
+    pub instance_name: String,
+
+    /// Directory the local CAS cache and the directory actions are
+    /// executed in are created in. It must be on a single filesystem, as
+    /// inputs are hardlinked from the cache into the work directory.
+    #[serde(default)]
+    pub cache_dir: String,
+
+    /// Maximum number of bytes of the local CAS cache.
+    ///
+    /// Default: 10GiB
+    #[serde(default)]
+    pub max_cache_bytes: usize,
+
+    /// Number of actions the worker runs at once. This is advertised to
+    /// the scheduler as the `cpu_count` platform property.
+    ///
+    /// Default: <number of cores on the machine>
+    #[serde(default)]
+    pub slots: usize,
+
+    /// Static platform properties of the worker. `cpu_count` is set from
+    /// `slots` and may not be set here.
+    ///
+    /// Default: {} (no properties besides `cpu_count`)
+    #[serde(default)]
+    pub platform_properties: BTreeMap<String, String>,
+}
+
+impl WorkerBootstrapConfig {
+    /// Sets the field named `key` from `value`. Platform properties are
+    /// set as a comma separated list of `key=value` pairs and are merged
+    /// into the existing ones.
+    pub fn set_field(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let parse_usize = |value: &str| {
+            value
+                .parse::<usize>()
+                .map_err(|e| format!("Invalid value '{value}' for '{key}': {e}"))
+        };
+        match key {
+            "name" => self.name = value.to_string(),
+            "scheduler_endpoint" => self.scheduler_endpoint = value.to_string(),
+            "cas_endpoint" => self.cas_endpoint = value.to_string(),
+            "instance_name" => self.instance_name = value.to_string(),
+            "cache_dir" => self.cache_dir = value.to_string(),
+            "max_cache_bytes" => self.max_cache_bytes = parse_usize(value)?,
+            "slots" => self.slots = parse_usize(value)?,
+            "platform_properties" => {
+                for property in value.split(',').filter(|v| !v.is_empty()) {
+                    let (name, property_value) = property.split_once('=').ok_or_else(|| {
+                        format!("Expected platform property '{property}' to be 'key=value'")
+                    })?;
+                    self.platform_properties
+                        .insert(name.to_string(), property_value.to_string());
+                }
+            }
+            _ => return Err(format!("Unknown worker config field '{key}'")),
+        }
+        Ok(())
+    }
+
+    /// Applies every variable of `vars` that starts with
+    /// [`WORKER_ENV_PREFIX`] with [`WorkerBootstrapConfig::set_field`].
+    pub fn apply_env(
+        &mut self,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<(), String> {
+        for (name, value) in vars {
+            if let Some(key) = name.strip_prefix(WORKER_ENV_PREFIX) {
+                self.set_field(&key.to_lowercase(), &value)
+                    .map_err(|e| format!("{e} (from environment variable {name})"))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fills in the defaults of all unset fields, so the config shows
+    /// the values the worker runs with.
+    pub fn resolve_defaults(&mut self) {
+        if self.name.is_empty() {
+            self.name = DEFAULT_WORKER_NAME.to_string();
+        }
+        if self.max_cache_bytes == 0 {
+            self.max_cache_bytes = DEFAULT_MAX_CACHE_BYTES;
+        }
+        if self.slots == 0 {
+            self.slots = std::thread::available_parallelism().map_or(1, usize::from);
+        }
+    }
+
+    /// Checks that all required fields are set and valid.
+    pub fn validate(&self) -> Result<(), String> {
+        for (key, endpoint) in [
+            ("scheduler_endpoint", &self.scheduler_endpoint),
+            ("cas_endpoint", &self.cas_endpoint),
+        ] {
+            if endpoint.is_empty() {
+                return Err(format!("'{key}' must be set"));
+            }
+            if !endpoint.starts_with("grpc://") && !endpoint.starts_with("grpcs://") {
+                return Err(format!(
+                    "'{key}' must start with grpc:// or grpcs://, got '{endpoint}'"
+                ));
+            }
+        }
+        if self.cache_dir.is_empty() {
+            return Err("'cache_dir' must be set".to_string());
+        }
+        if self
+            .platform_properties
+            .contains_key(SLOTS_PLATFORM_PROPERTY)
+        {
+            return Err(format!(
+                "'{SLOTS_PLATFORM_PROPERTY}' may not be a platform property, set 'slots' instead"
+            ));
+        }
+        if let Some(name) = self.platform_properties.keys().find(|name| name.is_empty()) {
+            return Err(format!(
+                "Platform property names may not be empty, got '{name}'"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the config of a process that only runs this worker. Call
+    /// `resolve_defaults` and `validate` first.
+    pub fn to_cas_config(&self) -> CasConfig {
+        let cache_dir = Path::new(&self.cache_dir);
+        let path = |name: &str| cache_dir.join(name).to_string_lossy().into_owned();
+        let grpc_spec = |store_type| GrpcSpec {
+            instance_name: self.instance_name.clone(),
+            endpoints: vec![GrpcEndpoint {
+                address: self.cas_endpoint.clone(),
+                tls_config: None,
+                concurrency_limit: None,
+            }],
+            store_type,
+            retry: Retry::default(),
+            max_concurrent_requests: 0,
+            connections_per_endpoint: 0,
+            local_cache: None,
+            replication: None,
+            max_retry_buffer_size: 0,
+        };
+
+        let mut platform_properties: HashMap<String, WorkerProperty> = self
+            .platform_properties
+            .iter()
+            .map(|(name, value)| (name.clone(), WorkerProperty::values(vec![value.clone()])))
+            .collect();
+        platform_properties.insert(
+            SLOTS_PLATFORM_PROPERTY.to_string(),
+            WorkerProperty::values(vec![self.slots.to_string()]),
+        );
+
+        CasConfig {
+            stores: HashMap::from([
+                (
+                    AC_STORE_NAME.to_string(),
+                    StoreSpec::grpc(grpc_spec(StoreType::ac)),
+                ),
+                (
+                    FAST_SLOW_STORE_NAME.to_string(),
+                    StoreSpec::fast_slow(Box::new(FastSlowSpec {
+                        fast: StoreSpec::filesystem(FilesystemSpec {
+                            content_path: path("content"),
+                            temp_path: path("tmp"),
+                            eviction_policy: Some(EvictionPolicy {
+                                max_bytes: self.max_cache_bytes,
+                                ..Default::default()
+                            }),
+                            ..Default::default()
+                        }),
+                        slow: StoreSpec::grpc(grpc_spec(StoreType::cas)),
+                        popularity: None,
+                        population: FastSlowPopulationSpec::default(),
+                        write_back: false,
+                    })),
+                ),
+            ]),
+            workers: Some(vec![WorkerConfig::local(LocalWorkerConfig {
+                name: self.name.clone(),
+                worker_api_endpoint: EndpointConfig {
+                    uri: self.scheduler_endpoint.clone(),
+                    ..Default::default()
+                },
+                cas_fast_slow_store: FAST_SLOW_STORE_NAME.to_string(),
+                upload_action_result: UploadActionResultConfig {
+                    ac_store: Some(AC_STORE_NAME.to_string()),
+                    ..Default::default()
+                },
+                work_directory: path("work"),
+                platform_properties,
+                ..Default::default()
+            })]),
+            schedulers: None,
+            servers: Vec::new(),
+            experimental_origin_events: None,
+            audit_log: None,
+            global: None,
+        }
+    }
+}
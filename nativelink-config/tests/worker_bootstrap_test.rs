@@ -0,0 +1,151 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+
+use nativelink_config::cas_server::{WorkerConfig, WorkerProperty};
+use nativelink_config::stores::StoreSpec;
+use nativelink_config::worker_bootstrap::WorkerBootstrapConfig;
+use pretty_assertions::assert_eq;
+
+fn make_valid_config() -> WorkerBootstrapConfig {
+    WorkerBootstrapConfig {
+        scheduler_endpoint: "grpc://127.0.0.1:50061".to_string(),
+        cas_endpoint: "grpc://127.0.0.1:50051".to_string(),
+        cache_dir: "/tmp/nativelink/worker".to_string(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn env_overrides_file_values() {
+    let mut config = make_valid_config();
+    config.platform_properties = BTreeMap::from([("OSFamily".to_string(), "linux".to_string())]);
+    config
+        .apply_env([
+            ("NATIVELINK_WORKER_SLOTS".to_string(), "4".to_string()),
+            (
+                "NATIVELINK_WORKER_CAS_ENDPOINT".to_string(),
+                "grpcs://cas.example.com".to_string(),
+            ),
+            (
+                "NATIVELINK_WORKER_PLATFORM_PROPERTIES".to_string(),
+                "OSFamily=windows,gpu_model=a100".to_string(),
+            ),
+            ("UNRELATED_VARIABLE".to_string(), "ignored".to_string()),
+        ])
+        .unwrap();
+    assert_eq!(config.slots, 4);
+    assert_eq!(config.cas_endpoint, "grpcs://cas.example.com");
+    assert_eq!(
+        config.platform_properties,
+        BTreeMap::from([
+            ("OSFamily".to_string(), "windows".to_string()),
+            ("gpu_model".to_string(), "a100".to_string()),
+        ])
+    );
+}
+
+#[test]
+fn unknown_env_variable_is_rejected() {
+    let mut config = make_valid_config();
+    let err = config
+        .apply_env([("NATIVELINK_WORKER_SLOT".to_string(), "4".to_string())])
+        .unwrap_err();
+    assert!(err.contains("NATIVELINK_WORKER_SLOT"), "Got: {err}");
+}
+
+#[test]
+fn invalid_number_is_rejected() {
+    let mut config = make_valid_config();
+    assert!(config.set_field("slots", "many").is_err());
+}
+
+#[test]
+fn validate_requires_endpoints_and_cache_dir() {
+    make_valid_config().validate().unwrap();
+
+    let config = WorkerBootstrapConfig {
+        scheduler_endpoint: String::new(),
+        ..make_valid_config()
+    };
+    assert!(config
+        .validate()
+        .unwrap_err()
+        .contains("scheduler_endpoint"));
+
+    let config = WorkerBootstrapConfig {
+        cas_endpoint: "127.0.0.1:50051".to_string(),
+        ..make_valid_config()
+    };
+    assert!(config.validate().unwrap_err().contains("cas_endpoint"));
+
+    let config = WorkerBootstrapConfig {
+        cache_dir: String::new(),
+        ..make_valid_config()
+    };
+    assert!(config.validate().unwrap_err().contains("cache_dir"));
+
+    let mut config = make_valid_config();
+    config
+        .set_field("platform_properties", "cpu_count=8")
+        .unwrap();
+    assert!(config.validate().unwrap_err().contains("slots"));
+}
+
+#[test]
+fn resolve_defaults_fills_unset_fields() {
+    let mut config = make_valid_config();
+    config.resolve_defaults();
+    assert_eq!(config.name, "worker");
+    assert_eq!(config.max_cache_bytes, 10 * 1024 * 1024 * 1024);
+    assert!(config.slots > 0);
+}
+
+#[test]
+fn to_cas_config_creates_worker_and_stores() {
+    let mut config = make_valid_config();
+    config.slots = 3;
+    config.resolve_defaults();
+    let cas_config = config.to_cas_config();
+
+    assert!(cas_config.servers.is_empty());
+    let mut store_names: Vec<&String> = cas_config.stores.keys().collect();
+    store_names.sort();
+    assert_eq!(
+        store_names,
+        vec!["WORKER_AC_STORE", "WORKER_FAST_SLOW_STORE"]
+    );
+    let Some(StoreSpec::fast_slow(fast_slow)) = cas_config.stores.get("WORKER_FAST_SLOW_STORE")
+    else {
+        panic!("Expected WORKER_FAST_SLOW_STORE to be a fast_slow store");
+    };
+    let StoreSpec::filesystem(filesystem) = &fast_slow.fast else {
+        panic!("Expected the fast store to be a filesystem store");
+    };
+    assert_eq!(filesystem.content_path, "/tmp/nativelink/worker/content");
+
+    let workers = cas_config.workers.unwrap();
+    let [WorkerConfig::local(worker)] = workers.as_slice() else {
+        panic!("Expected exactly one local worker");
+    };
+    assert_eq!(worker.worker_api_endpoint.uri, "grpc://127.0.0.1:50061");
+    assert_eq!(worker.cas_fast_slow_store, "WORKER_FAST_SLOW_STORE");
+    assert_eq!(worker.work_directory, "/tmp/nativelink/worker/work");
+    let Some(WorkerProperty::values(cpu_count)) = worker.platform_properties.get("cpu_count")
+    else {
+        panic!("Expected cpu_count to be set from slots");
+    };
+    assert_eq!(cpu_count, &vec!["3".to_string()]);
+}
@@ -45,6 +45,10 @@ _HEADER = """\
     clippy::large_enum_variant,
     rustdoc::invalid_html_tags
 )]
+
+/// Encoded `FileDescriptorSet` of all the protos, used to serve gRPC
+/// server reflection.
+pub const FILE_DESCRIPTOR_SET: &[u8] = include_bytes!("file_descriptor_set.bin");
 """
 
 
@@ -71,6 +75,8 @@ def main():
 
     tree_root = { "children": {}, "filename": None }
     for filepath in args.files:
+        if filepath.endswith('.bin'):
+            continue  # The file descriptor set is included by the header.
         filepath = os.path.relpath(os.path.normpath(filepath), args.rootdir)
         assert filepath.endswith('.pb.rs'), "Expected " + filepath + " to end in '.pb.rs'"
         package_parts = filepath.split('.')[:-2]  # Remove `.pb.rs'.
@@ -29,6 +29,7 @@ fn main() -> std::io::Result<()> {
     let mut config = Config::new();
     config.bytes(["."]);
     tonic_build::configure()
+        .file_descriptor_set_path(output_dir.join("file_descriptor_set.bin"))
         .out_dir(output_dir)
         .compile_protos_with_config(config, &paths, &["nativelink-proto"])?;
     Ok(())
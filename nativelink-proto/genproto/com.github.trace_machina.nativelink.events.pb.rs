@@ -303,3 +303,38 @@ pub mod bep_event {
         ),
     }
 }
+/// / Index entry of a build invocation that published its events to the
+/// / build event service.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct BepInvocation {
+    /// / The version of this message.
+    #[prost(uint32, tag = "1")]
+    pub version: u32,
+    /// / The build the invocation is part of.
+    #[prost(string, tag = "2")]
+    pub build_id: ::prost::alloc::string::String,
+    /// / The invocation the events belong to.
+    #[prost(string, tag = "3")]
+    pub invocation_id: ::prost::alloc::string::String,
+    /// / The identity of the client that published the events.
+    #[prost(string, tag = "4")]
+    pub identity: ::prost::alloc::string::String,
+    /// / Milliseconds since the unix epoch when the first and the last build
+    /// / tool event of the invocation were received.
+    #[prost(uint64, tag = "5")]
+    pub first_event_timestamp_ms: u64,
+    #[prost(uint64, tag = "6")]
+    pub last_event_timestamp_ms: u64,
+    /// / Number of build tool events received.
+    #[prost(uint64, tag = "7")]
+    pub event_count: u64,
+    /// / Sequence number of the last build tool event received.
+    #[prost(int64, tag = "8")]
+    pub last_sequence_number: i64,
+    /// / Digests of the `BepEvent`s of the build tool events in the order
+    /// / they were received, if they are stored in a CAS.
+    #[prost(message, repeated, tag = "9")]
+    pub event_digests: ::prost::alloc::vec::Vec<
+        super::super::super::super::super::build::bazel::remote::execution::v2::Digest,
+    >,
+}
@@ -13,6 +13,244 @@
 // limitations under the License.
 
 // This file is @generated by prost-build.
+/// / Request object for `WaitForBlobs`.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct WaitForBlobsRequest {
+    /// / The instance of the execution system to operate against.
+    #[prost(string, tag = "1")]
+    pub instance_name: ::prost::alloc::string::String,
+    /// / The blobs to wait for.
+    #[prost(message, repeated, tag = "2")]
+    pub blob_digests: ::prost::alloc::vec::Vec<
+        super::super::super::super::super::build::bazel::remote::execution::v2::Digest,
+    >,
+    /// / How long to wait for the blobs. If unset or larger than the maximum
+    /// / configured on the server, the server maximum is used.
+    #[prost(message, optional, tag = "3")]
+    pub timeout: ::core::option::Option<::prost_types::Duration>,
+    /// / The digest function of the blobs.
+    #[prost(
+        enumeration = "super::super::super::super::super::build::bazel::remote::execution::v2::digest_function::Value",
+        tag = "4"
+    )]
+    pub digest_function: i32,
+}
+/// / Response object for `WaitForBlobs`.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct WaitForBlobsResponse {
+    /// / The blobs that were still missing when the timeout elapsed. Empty if
+    /// / every requested blob is available.
+    #[prost(message, repeated, tag = "1")]
+    pub missing_blob_digests: ::prost::alloc::vec::Vec<
+        super::super::super::super::super::build::bazel::remote::execution::v2::Digest,
+    >,
+}
+/// / Request object for `ListOperations`.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListOperationsRequest {
+    /// / The instance of the scheduler to list the operations of.
+    #[prost(string, tag = "1")]
+    pub instance_name: ::prost::alloc::string::String,
+    /// / Only list operations in these stages. All unfinished operations are
+    /// / listed if empty.
+    #[prost(
+        enumeration = "super::super::super::super::super::build::bazel::remote::execution::v2::execution_stage::Value",
+        repeated,
+        tag = "2"
+    )]
+    pub stages: ::prost::alloc::vec::Vec<i32>,
+    /// / Only list operations assigned to this worker, if set.
+    #[prost(string, tag = "3")]
+    pub worker_id: ::prost::alloc::string::String,
+    /// / Only list operations that were queued at least this long ago, if set.
+    #[prost(message, optional, tag = "4")]
+    pub min_age: ::core::option::Option<::prost_types::Duration>,
+}
+/// / Response object for `ListOperations`.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListOperationsResponse {
+    /// / The operations that match the filters.
+    #[prost(message, repeated, tag = "1")]
+    pub operations: ::prost::alloc::vec::Vec<OperationInfo>,
+    /// / True if more operations matched than the server lists at once.
+    #[prost(bool, tag = "2")]
+    pub truncated: bool,
+}
+/// / Request object for `GetOperation`.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GetOperationRequest {
+    /// / The instance of the scheduler the operation belongs to.
+    #[prost(string, tag = "1")]
+    pub instance_name: ::prost::alloc::string::String,
+    /// / ID of the operation, as listed by `ListOperations`.
+    #[prost(string, tag = "2")]
+    pub operation_id: ::prost::alloc::string::String,
+}
+/// / A stage an operation moved to.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct StageChange {
+    /// / The stage the operation moved to.
+    #[prost(
+        enumeration = "super::super::super::super::super::build::bazel::remote::execution::v2::execution_stage::Value",
+        tag = "1"
+    )]
+    pub stage: i32,
+    /// / The worker the operation was assigned to, if any.
+    #[prost(string, tag = "2")]
+    pub worker_id: ::prost::alloc::string::String,
+    /// / When the operation moved to the stage.
+    #[prost(message, optional, tag = "3")]
+    pub timestamp: ::core::option::Option<::prost_types::Timestamp>,
+}
+/// / What the scheduler knows about an operation.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct OperationInfo {
+    /// / ID of the operation. This is the ID the workers know the operation
+    /// / by, clients waiting on the same action may use other IDs.
+    #[prost(string, tag = "1")]
+    pub operation_id: ::prost::alloc::string::String,
+    /// / The current stage of the operation.
+    #[prost(
+        enumeration = "super::super::super::super::super::build::bazel::remote::execution::v2::execution_stage::Value",
+        tag = "2"
+    )]
+    pub stage: i32,
+    /// / The digest of the action.
+    #[prost(message, optional, tag = "3")]
+    pub action_digest: ::core::option::Option<
+        super::super::super::super::super::build::bazel::remote::execution::v2::Digest,
+    >,
+    /// / The worker the operation is assigned to, if any.
+    #[prost(string, tag = "4")]
+    pub worker_id: ::prost::alloc::string::String,
+    /// / The priority of the operation.
+    #[prost(int32, tag = "5")]
+    pub priority: i32,
+    /// / When the operation was queued.
+    #[prost(message, optional, tag = "6")]
+    pub queued_timestamp: ::core::option::Option<::prost_types::Timestamp>,
+    /// / The platform properties the action requires.
+    #[prost(map = "string, string", tag = "7")]
+    pub platform_properties: ::std::collections::HashMap<
+        ::prost::alloc::string::String,
+        ::prost::alloc::string::String,
+    >,
+    /// / Number of times the operation failed on a worker.
+    #[prost(uint64, tag = "8")]
+    pub attempts: u64,
+    /// / The last time a worker sent an update about the operation.
+    #[prost(message, optional, tag = "9")]
+    pub last_worker_update_timestamp: ::core::option::Option<::prost_types::Timestamp>,
+    /// / The last time a client listened to the operation.
+    #[prost(message, optional, tag = "10")]
+    pub last_client_keepalive_timestamp: ::core::option::Option<
+        ::prost_types::Timestamp,
+    >,
+    /// / The stages the operation moved through, oldest first. Only the most
+    /// / recent stages are kept.
+    #[prost(message, repeated, tag = "11")]
+    pub stage_history: ::prost::alloc::vec::Vec<StageChange>,
+}
+/// / Request object for `CancelOperation`.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CancelOperationRequest {
+    /// / The instance of the scheduler the operation belongs to.
+    #[prost(string, tag = "1")]
+    pub instance_name: ::prost::alloc::string::String,
+    /// / ID of the operation, as listed by `ListOperations`.
+    #[prost(string, tag = "2")]
+    pub operation_id: ::prost::alloc::string::String,
+}
+/// / Response object for `CancelOperation`.
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct CancelOperationResponse {}
+/// / Request object for `RequeueOperation`.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RequeueOperationRequest {
+    /// / The instance of the scheduler the operation belongs to.
+    #[prost(string, tag = "1")]
+    pub instance_name: ::prost::alloc::string::String,
+    /// / ID of the operation, as listed by `ListOperations`.
+    #[prost(string, tag = "2")]
+    pub operation_id: ::prost::alloc::string::String,
+}
+/// / Response object for `RequeueOperation`.
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct RequeueOperationResponse {}
+/// / Where a queued operation stands in the queue of the scheduler. While an
+/// / operation is queued, this is sent to clients in the `auxiliary_metadata`
+/// / of the `partial_execution_metadata` in the `ExecuteOperationMetadata`.
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct QueuePositionMetadata {
+    /// / Number of queued operations that will be handed to a worker before
+    /// / this one.
+    #[prost(uint64, tag = "1")]
+    pub operations_ahead: u64,
+    /// / When the operation is estimated to start executing, based on how
+    /// / fast the scheduler recently handed queued operations to workers.
+    /// / Unset if there is not enough history for an estimate.
+    #[prost(message, optional, tag = "2")]
+    pub estimated_start_timestamp: ::core::option::Option<::prost_types::Timestamp>,
+    /// / How long the operation is estimated to execute, based on earlier
+    /// / executions of the same command. Unset if the command has no history.
+    #[prost(message, optional, tag = "3")]
+    pub estimated_execution_duration: ::core::option::Option<::prost_types::Duration>,
+    /// / How large the outputs of the operation are estimated to be in bytes,
+    /// / based on earlier executions of the same command. Zero if the command
+    /// / has no history.
+    #[prost(uint64, tag = "4")]
+    pub estimated_output_bytes: u64,
+}
+/// / Resources an action used while it executed, as measured by the worker.
+/// / This is sent to clients in the `auxiliary_metadata` of the
+/// / `ExecutedActionMetadata` if the worker runs actions in cgroups.
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct ResourceUsageMetadata {
+    /// / The most memory the processes of the action used at once, in bytes.
+    /// / Zero if the kernel of the worker does not report it.
+    #[prost(uint64, tag = "1")]
+    pub peak_memory_bytes: u64,
+    /// / The CPU time the processes of the action used.
+    #[prost(message, optional, tag = "2")]
+    pub cpu_time: ::core::option::Option<::prost_types::Duration>,
+    /// / The most processes and threads the action had at once. Zero if the
+    /// / kernel of the worker does not report it.
+    #[prost(uint64, tag = "3")]
+    pub peak_pids: u64,
+}
+/// / Request object for `CordonWorker`.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CordonWorkerRequest {
+    /// / The instance of the scheduler the worker is connected to.
+    #[prost(string, tag = "1")]
+    pub instance_name: ::prost::alloc::string::String,
+    /// / ID of the worker.
+    #[prost(string, tag = "2")]
+    pub worker_id: ::prost::alloc::string::String,
+    /// / True to stop assigning actions to the worker, false to resume.
+    #[prost(bool, tag = "3")]
+    pub cordoned: bool,
+}
+/// / Response object for `CordonWorker`.
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct CordonWorkerResponse {}
+/// / Request object for `DrainWorker`.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DrainWorkerRequest {
+    /// / The instance of the scheduler the worker is connected to.
+    #[prost(string, tag = "1")]
+    pub instance_name: ::prost::alloc::string::String,
+    /// / ID of the worker.
+    #[prost(string, tag = "2")]
+    pub worker_id: ::prost::alloc::string::String,
+    /// / How long to wait for the actions running on the worker to finish.
+    /// / If unset, the server default is used.
+    #[prost(message, optional, tag = "3")]
+    pub timeout: ::core::option::Option<::prost_types::Duration>,
+}
+/// / Response object for `DrainWorker`.
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct DrainWorkerResponse {}
 /// / Request object for keep alive requests.
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct KeepAliveRequest {
@@ -87,6 +325,15 @@ pub struct ConnectionResult {
     /// / The internal ID given to the newly connected node.
     #[prost(string, tag = "1")]
     pub worker_id: ::prost::alloc::string::String,
+    /// / How often the worker should send a `KeepAlive` to the scheduler. If
+    /// / not set, the worker picks the interval itself.
+    #[prost(message, optional, tag = "2")]
+    pub heartbeat_interval: ::core::option::Option<::prost_types::Duration>,
+    /// / How many `KeepAlive` requests in a row may fail before the worker
+    /// / considers the scheduler lost and reconnects. The scheduler removes
+    /// / the worker only after it missed as many of them.
+    #[prost(uint32, tag = "3")]
+    pub max_missed_heartbeats: u32,
 }
 /// / Request to kill a running operation sent from the scheduler to a worker.
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -160,7 +407,7 @@ pub struct HistoricalExecuteResponse {
     >,
 }
 /// Generated client implementations.
-pub mod worker_api_client {
+pub mod digest_subscription_client {
     #![allow(
         unused_variables,
         dead_code,
@@ -170,18 +417,15 @@ pub mod worker_api_client {
     )]
     use tonic::codegen::*;
     use tonic::codegen::http::Uri;
-    /// / This API describes how schedulers communicate with Worker nodes.
-    /// /
-    /// / When a worker node comes online it must be pre-configured with the
-    /// / endpoint of the scheduler it will register with. Once the worker
-    /// / connects to the scheduler it must send a `RegisterSupportedProperties`
-    /// / command to the scheduler. The scheduler will then use this information
-    /// / to determine which jobs the worker can process.
+    /// / This API lets clients wait for blobs to become available in the CAS
+    /// / instead of calling `FindMissingBlobs` in a loop. This is useful for
+    /// / build coordinators where one job uploads an artifact and many
+    /// / downstream jobs wait for it.
     #[derive(Debug, Clone)]
-    pub struct WorkerApiClient<T> {
+    pub struct DigestSubscriptionClient<T> {
         inner: tonic::client::Grpc<T>,
     }
-    impl<T> WorkerApiClient<T>
+    impl<T> DigestSubscriptionClient<T>
     where
         T: tonic::client::GrpcService<tonic::body::BoxBody>,
         T::Error: Into<StdError>,
@@ -199,7 +443,7 @@ pub mod worker_api_client {
         pub fn with_interceptor<F>(
             inner: T,
             interceptor: F,
-        ) -> WorkerApiClient<InterceptedService<T, F>>
+        ) -> DigestSubscriptionClient<InterceptedService<T, F>>
         where
             F: tonic::service::Interceptor,
             T::ResponseBody: Default,
@@ -213,7 +457,7 @@ pub mod worker_api_client {
                 http::Request<tonic::body::BoxBody>,
             >>::Error: Into<StdError> + std::marker::Send + std::marker::Sync,
         {
-            WorkerApiClient::new(InterceptedService::new(inner, interceptor))
+            DigestSubscriptionClient::new(InterceptedService::new(inner, interceptor))
         }
         /// Compress requests with the given encoding.
         ///
@@ -246,15 +490,14 @@ pub mod worker_api_client {
             self.inner = self.inner.max_encoding_message_size(limit);
             self
         }
-        /// / Registers this worker and informs the scheduler what properties
-        /// / this worker supports. The response must be listened on the client
-        /// / side for updates from the server. The first item sent will always be
-        /// / a ConnectionResult, after that it is undefined.
-        pub async fn connect_worker(
+        /// / Returns once every requested blob is in the CAS or once the timeout
+        /// / elapsed, whichever happens first. Blobs that are still missing when
+        /// / the timeout elapsed are returned in the response.
+        pub async fn wait_for_blobs(
             &mut self,
-            request: impl tonic::IntoRequest<super::SupportedProperties>,
+            request: impl tonic::IntoRequest<super::WaitForBlobsRequest>,
         ) -> std::result::Result<
-            tonic::Response<tonic::codec::Streaming<super::UpdateForWorker>>,
+            tonic::Response<super::WaitForBlobsResponse>,
             tonic::Status,
         > {
             self.inner
@@ -267,27 +510,111 @@ pub mod worker_api_client {
                 })?;
             let codec = tonic::codec::ProstCodec::default();
             let path = http::uri::PathAndQuery::from_static(
-                "/com.github.trace_machina.nativelink.remote_execution.WorkerApi/ConnectWorker",
+                "/com.github.trace_machina.nativelink.remote_execution.DigestSubscription/WaitForBlobs",
             );
             let mut req = request.into_request();
             req.extensions_mut()
                 .insert(
                     GrpcMethod::new(
-                        "com.github.trace_machina.nativelink.remote_execution.WorkerApi",
-                        "ConnectWorker",
+                        "com.github.trace_machina.nativelink.remote_execution.DigestSubscription",
+                        "WaitForBlobs",
                     ),
                 );
-            self.inner.server_streaming(req, path, codec).await
+            self.inner.unary(req, path, codec).await
         }
-        /// / Message used to let the scheduler know that it is still alive as
-        /// / well as check to see if the scheduler is still alive. The scheduler
-        /// / may close the connection if the worker has not sent any messages
-        /// / after some amount of time (configured in the scheduler's
-        /// / configuration).
-        pub async fn keep_alive(
+    }
+}
+/// Generated client implementations.
+pub mod operation_admin_client {
+    #![allow(
+        unused_variables,
+        dead_code,
+        missing_docs,
+        clippy::wildcard_imports,
+        clippy::let_unit_value,
+    )]
+    use tonic::codegen::*;
+    use tonic::codegen::http::Uri;
+    /// / This API lets operators look at the operations of a scheduler, to find
+    /// / out why an operation is stuck, and cancel or retry it.
+    #[derive(Debug, Clone)]
+    pub struct OperationAdminClient<T> {
+        inner: tonic::client::Grpc<T>,
+    }
+    impl<T> OperationAdminClient<T>
+    where
+        T: tonic::client::GrpcService<tonic::body::BoxBody>,
+        T::Error: Into<StdError>,
+        T::ResponseBody: Body<Data = Bytes> + std::marker::Send + 'static,
+        <T::ResponseBody as Body>::Error: Into<StdError> + std::marker::Send,
+    {
+        pub fn new(inner: T) -> Self {
+            let inner = tonic::client::Grpc::new(inner);
+            Self { inner }
+        }
+        pub fn with_origin(inner: T, origin: Uri) -> Self {
+            let inner = tonic::client::Grpc::with_origin(inner, origin);
+            Self { inner }
+        }
+        pub fn with_interceptor<F>(
+            inner: T,
+            interceptor: F,
+        ) -> OperationAdminClient<InterceptedService<T, F>>
+        where
+            F: tonic::service::Interceptor,
+            T::ResponseBody: Default,
+            T: tonic::codegen::Service<
+                http::Request<tonic::body::BoxBody>,
+                Response = http::Response<
+                    <T as tonic::client::GrpcService<tonic::body::BoxBody>>::ResponseBody,
+                >,
+            >,
+            <T as tonic::codegen::Service<
+                http::Request<tonic::body::BoxBody>,
+            >>::Error: Into<StdError> + std::marker::Send + std::marker::Sync,
+        {
+            OperationAdminClient::new(InterceptedService::new(inner, interceptor))
+        }
+        /// Compress requests with the given encoding.
+        ///
+        /// This requires the server to support it otherwise it might respond with an
+        /// error.
+        #[must_use]
+        pub fn send_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.inner = self.inner.send_compressed(encoding);
+            self
+        }
+        /// Enable decompressing responses.
+        #[must_use]
+        pub fn accept_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.inner = self.inner.accept_compressed(encoding);
+            self
+        }
+        /// Limits the maximum size of a decoded message.
+        ///
+        /// Default: `4MB`
+        #[must_use]
+        pub fn max_decoding_message_size(mut self, limit: usize) -> Self {
+            self.inner = self.inner.max_decoding_message_size(limit);
+            self
+        }
+        /// Limits the maximum size of an encoded message.
+        ///
+        /// Default: `usize::MAX`
+        #[must_use]
+        pub fn max_encoding_message_size(mut self, limit: usize) -> Self {
+            self.inner = self.inner.max_encoding_message_size(limit);
+            self
+        }
+        /// / Lists the operations of a scheduler that match the filters, oldest
+        /// / first.
+        pub async fn list_operations(
             &mut self,
-            request: impl tonic::IntoRequest<super::KeepAliveRequest>,
-        ) -> std::result::Result<tonic::Response<()>, tonic::Status> {
+            request: impl tonic::IntoRequest<super::ListOperationsRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::ListOperationsResponse>,
+            tonic::Status,
+        > {
             self.inner
                 .ready()
                 .await
@@ -298,33 +625,26 @@ pub mod worker_api_client {
                 })?;
             let codec = tonic::codec::ProstCodec::default();
             let path = http::uri::PathAndQuery::from_static(
-                "/com.github.trace_machina.nativelink.remote_execution.WorkerApi/KeepAlive",
+                "/com.github.trace_machina.nativelink.remote_execution.OperationAdmin/ListOperations",
             );
             let mut req = request.into_request();
             req.extensions_mut()
                 .insert(
                     GrpcMethod::new(
-                        "com.github.trace_machina.nativelink.remote_execution.WorkerApi",
-                        "KeepAlive",
+                        "com.github.trace_machina.nativelink.remote_execution.OperationAdmin",
+                        "ListOperations",
                     ),
                 );
             self.inner.unary(req, path, codec).await
         }
-        /// / Informs the scheduler that the service is going offline and
-        /// / should stop issuing any new actions on this worker.
-        /// /
-        /// / The worker may stay connected even after sending this command
-        /// / and may even send an `ExecuteResult` after sending this command.
-        /// / It is up to the scheduler implementation to decide how to handle
-        /// / this case.
-        /// /
-        /// / Any job that was running on this instance likely needs to be
-        /// / executed again, but up to the scheduler on how or when to handle
-        /// / this case.
-        pub async fn going_away(
+        /// / Returns the state of an operation and the stages it moved through.
+        pub async fn get_operation(
             &mut self,
-            request: impl tonic::IntoRequest<super::GoingAwayRequest>,
-        ) -> std::result::Result<tonic::Response<()>, tonic::Status> {
+            request: impl tonic::IntoRequest<super::GetOperationRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::OperationInfo>,
+            tonic::Status,
+        > {
             self.inner
                 .ready()
                 .await
@@ -335,23 +655,28 @@ pub mod worker_api_client {
                 })?;
             let codec = tonic::codec::ProstCodec::default();
             let path = http::uri::PathAndQuery::from_static(
-                "/com.github.trace_machina.nativelink.remote_execution.WorkerApi/GoingAway",
+                "/com.github.trace_machina.nativelink.remote_execution.OperationAdmin/GetOperation",
             );
             let mut req = request.into_request();
             req.extensions_mut()
                 .insert(
                     GrpcMethod::new(
-                        "com.github.trace_machina.nativelink.remote_execution.WorkerApi",
-                        "GoingAway",
+                        "com.github.trace_machina.nativelink.remote_execution.OperationAdmin",
+                        "GetOperation",
                     ),
                 );
             self.inner.unary(req, path, codec).await
         }
-        /// / Informs the scheduler about the result of an execution request.
-        pub async fn execution_response(
+        /// / Completes the operation with a `CANCELLED` error and kills it on the
+        /// / worker running it, if any. Fails with `FAILED_PRECONDITION` if the
+        /// / operation already finished.
+        pub async fn cancel_operation(
             &mut self,
-            request: impl tonic::IntoRequest<super::ExecuteResult>,
-        ) -> std::result::Result<tonic::Response<()>, tonic::Status> {
+            request: impl tonic::IntoRequest<super::CancelOperationRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::CancelOperationResponse>,
+            tonic::Status,
+        > {
             self.inner
                 .ready()
                 .await
@@ -362,20 +687,1193 @@ pub mod worker_api_client {
                 })?;
             let codec = tonic::codec::ProstCodec::default();
             let path = http::uri::PathAndQuery::from_static(
-                "/com.github.trace_machina.nativelink.remote_execution.WorkerApi/ExecutionResponse",
+                "/com.github.trace_machina.nativelink.remote_execution.OperationAdmin/CancelOperation",
             );
             let mut req = request.into_request();
             req.extensions_mut()
                 .insert(
                     GrpcMethod::new(
-                        "com.github.trace_machina.nativelink.remote_execution.WorkerApi",
-                        "ExecutionResponse",
+                        "com.github.trace_machina.nativelink.remote_execution.OperationAdmin",
+                        "CancelOperation",
+                    ),
+                );
+            self.inner.unary(req, path, codec).await
+        }
+        /// / Kills the operation on the worker running it, if any, and puts it
+        /// / back in the queue. The retry does not count as a failed attempt.
+        /// / Fails with `FAILED_PRECONDITION` if the operation already finished.
+        pub async fn requeue_operation(
+            &mut self,
+            request: impl tonic::IntoRequest<super::RequeueOperationRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::RequeueOperationResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/com.github.trace_machina.nativelink.remote_execution.OperationAdmin/RequeueOperation",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(
+                    GrpcMethod::new(
+                        "com.github.trace_machina.nativelink.remote_execution.OperationAdmin",
+                        "RequeueOperation",
                     ),
                 );
             self.inner.unary(req, path, codec).await
         }
     }
 }
+/// Generated client implementations.
+pub mod worker_admin_client {
+    #![allow(
+        unused_variables,
+        dead_code,
+        missing_docs,
+        clippy::wildcard_imports,
+        clippy::let_unit_value,
+    )]
+    use tonic::codegen::*;
+    use tonic::codegen::http::Uri;
+    /// / This API lets operators take workers out of the pool of a scheduler
+    /// / without failing the actions running on them, for example to do a
+    /// / rolling upgrade of the workers.
+    #[derive(Debug, Clone)]
+    pub struct WorkerAdminClient<T> {
+        inner: tonic::client::Grpc<T>,
+    }
+    impl<T> WorkerAdminClient<T>
+    where
+        T: tonic::client::GrpcService<tonic::body::BoxBody>,
+        T::Error: Into<StdError>,
+        T::ResponseBody: Body<Data = Bytes> + std::marker::Send + 'static,
+        <T::ResponseBody as Body>::Error: Into<StdError> + std::marker::Send,
+    {
+        pub fn new(inner: T) -> Self {
+            let inner = tonic::client::Grpc::new(inner);
+            Self { inner }
+        }
+        pub fn with_origin(inner: T, origin: Uri) -> Self {
+            let inner = tonic::client::Grpc::with_origin(inner, origin);
+            Self { inner }
+        }
+        pub fn with_interceptor<F>(
+            inner: T,
+            interceptor: F,
+        ) -> WorkerAdminClient<InterceptedService<T, F>>
+        where
+            F: tonic::service::Interceptor,
+            T::ResponseBody: Default,
+            T: tonic::codegen::Service<
+                http::Request<tonic::body::BoxBody>,
+                Response = http::Response<
+                    <T as tonic::client::GrpcService<tonic::body::BoxBody>>::ResponseBody,
+                >,
+            >,
+            <T as tonic::codegen::Service<
+                http::Request<tonic::body::BoxBody>,
+            >>::Error: Into<StdError> + std::marker::Send + std::marker::Sync,
+        {
+            WorkerAdminClient::new(InterceptedService::new(inner, interceptor))
+        }
+        /// Compress requests with the given encoding.
+        ///
+        /// This requires the server to support it otherwise it might respond with an
+        /// error.
+        #[must_use]
+        pub fn send_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.inner = self.inner.send_compressed(encoding);
+            self
+        }
+        /// Enable decompressing responses.
+        #[must_use]
+        pub fn accept_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.inner = self.inner.accept_compressed(encoding);
+            self
+        }
+        /// Limits the maximum size of a decoded message.
+        ///
+        /// Default: `4MB`
+        #[must_use]
+        pub fn max_decoding_message_size(mut self, limit: usize) -> Self {
+            self.inner = self.inner.max_decoding_message_size(limit);
+            self
+        }
+        /// Limits the maximum size of an encoded message.
+        ///
+        /// Default: `usize::MAX`
+        #[must_use]
+        pub fn max_encoding_message_size(mut self, limit: usize) -> Self {
+            self.inner = self.inner.max_encoding_message_size(limit);
+            self
+        }
+        /// / Stops or resumes assigning new actions to a worker. Actions that
+        /// / already run on the worker are not affected.
+        pub async fn cordon_worker(
+            &mut self,
+            request: impl tonic::IntoRequest<super::CordonWorkerRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::CordonWorkerResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/com.github.trace_machina.nativelink.remote_execution.WorkerAdmin/CordonWorker",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(
+                    GrpcMethod::new(
+                        "com.github.trace_machina.nativelink.remote_execution.WorkerAdmin",
+                        "CordonWorker",
+                    ),
+                );
+            self.inner.unary(req, path, codec).await
+        }
+        /// / Cordons a worker, waits for the actions running on it to finish and
+        /// / then removes it from the pool. Fails with `DEADLINE_EXCEEDED` if the
+        /// / actions did not finish within the timeout, in which case the worker
+        /// / stays cordoned and keeps running its actions.
+        pub async fn drain_worker(
+            &mut self,
+            request: impl tonic::IntoRequest<super::DrainWorkerRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::DrainWorkerResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/com.github.trace_machina.nativelink.remote_execution.WorkerAdmin/DrainWorker",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(
+                    GrpcMethod::new(
+                        "com.github.trace_machina.nativelink.remote_execution.WorkerAdmin",
+                        "DrainWorker",
+                    ),
+                );
+            self.inner.unary(req, path, codec).await
+        }
+    }
+}
+/// Generated client implementations.
+pub mod worker_api_client {
+    #![allow(
+        unused_variables,
+        dead_code,
+        missing_docs,
+        clippy::wildcard_imports,
+        clippy::let_unit_value,
+    )]
+    use tonic::codegen::*;
+    use tonic::codegen::http::Uri;
+    /// / This API describes how schedulers communicate with Worker nodes.
+    /// /
+    /// / When a worker node comes online it must be pre-configured with the
+    /// / endpoint of the scheduler it will register with. Once the worker
+    /// / connects to the scheduler it must send a `RegisterSupportedProperties`
+    /// / command to the scheduler. The scheduler will then use this information
+    /// / to determine which jobs the worker can process.
+    #[derive(Debug, Clone)]
+    pub struct WorkerApiClient<T> {
+        inner: tonic::client::Grpc<T>,
+    }
+    impl<T> WorkerApiClient<T>
+    where
+        T: tonic::client::GrpcService<tonic::body::BoxBody>,
+        T::Error: Into<StdError>,
+        T::ResponseBody: Body<Data = Bytes> + std::marker::Send + 'static,
+        <T::ResponseBody as Body>::Error: Into<StdError> + std::marker::Send,
+    {
+        pub fn new(inner: T) -> Self {
+            let inner = tonic::client::Grpc::new(inner);
+            Self { inner }
+        }
+        pub fn with_origin(inner: T, origin: Uri) -> Self {
+            let inner = tonic::client::Grpc::with_origin(inner, origin);
+            Self { inner }
+        }
+        pub fn with_interceptor<F>(
+            inner: T,
+            interceptor: F,
+        ) -> WorkerApiClient<InterceptedService<T, F>>
+        where
+            F: tonic::service::Interceptor,
+            T::ResponseBody: Default,
+            T: tonic::codegen::Service<
+                http::Request<tonic::body::BoxBody>,
+                Response = http::Response<
+                    <T as tonic::client::GrpcService<tonic::body::BoxBody>>::ResponseBody,
+                >,
+            >,
+            <T as tonic::codegen::Service<
+                http::Request<tonic::body::BoxBody>,
+            >>::Error: Into<StdError> + std::marker::Send + std::marker::Sync,
+        {
+            WorkerApiClient::new(InterceptedService::new(inner, interceptor))
+        }
+        /// Compress requests with the given encoding.
+        ///
+        /// This requires the server to support it otherwise it might respond with an
+        /// error.
+        #[must_use]
+        pub fn send_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.inner = self.inner.send_compressed(encoding);
+            self
+        }
+        /// Enable decompressing responses.
+        #[must_use]
+        pub fn accept_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.inner = self.inner.accept_compressed(encoding);
+            self
+        }
+        /// Limits the maximum size of a decoded message.
+        ///
+        /// Default: `4MB`
+        #[must_use]
+        pub fn max_decoding_message_size(mut self, limit: usize) -> Self {
+            self.inner = self.inner.max_decoding_message_size(limit);
+            self
+        }
+        /// Limits the maximum size of an encoded message.
+        ///
+        /// Default: `usize::MAX`
+        #[must_use]
+        pub fn max_encoding_message_size(mut self, limit: usize) -> Self {
+            self.inner = self.inner.max_encoding_message_size(limit);
+            self
+        }
+        /// / Registers this worker and informs the scheduler what properties
+        /// / this worker supports. The response must be listened on the client
+        /// / side for updates from the server. The first item sent will always be
+        /// / a ConnectionResult, after that it is undefined.
+        pub async fn connect_worker(
+            &mut self,
+            request: impl tonic::IntoRequest<super::SupportedProperties>,
+        ) -> std::result::Result<
+            tonic::Response<tonic::codec::Streaming<super::UpdateForWorker>>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/com.github.trace_machina.nativelink.remote_execution.WorkerApi/ConnectWorker",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(
+                    GrpcMethod::new(
+                        "com.github.trace_machina.nativelink.remote_execution.WorkerApi",
+                        "ConnectWorker",
+                    ),
+                );
+            self.inner.server_streaming(req, path, codec).await
+        }
+        /// / Message used to let the scheduler know that it is still alive as
+        /// / well as check to see if the scheduler is still alive. The scheduler
+        /// / may close the connection if the worker has not sent any messages
+        /// / after some amount of time (configured in the scheduler's
+        /// / configuration).
+        pub async fn keep_alive(
+            &mut self,
+            request: impl tonic::IntoRequest<super::KeepAliveRequest>,
+        ) -> std::result::Result<tonic::Response<()>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/com.github.trace_machina.nativelink.remote_execution.WorkerApi/KeepAlive",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(
+                    GrpcMethod::new(
+                        "com.github.trace_machina.nativelink.remote_execution.WorkerApi",
+                        "KeepAlive",
+                    ),
+                );
+            self.inner.unary(req, path, codec).await
+        }
+        /// / Informs the scheduler that the service is going offline and
+        /// / should stop issuing any new actions on this worker.
+        /// /
+        /// / The worker may stay connected even after sending this command
+        /// / and may even send an `ExecuteResult` after sending this command.
+        /// / It is up to the scheduler implementation to decide how to handle
+        /// / this case.
+        /// /
+        /// / Any job that was running on this instance likely needs to be
+        /// / executed again, but up to the scheduler on how or when to handle
+        /// / this case.
+        pub async fn going_away(
+            &mut self,
+            request: impl tonic::IntoRequest<super::GoingAwayRequest>,
+        ) -> std::result::Result<tonic::Response<()>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/com.github.trace_machina.nativelink.remote_execution.WorkerApi/GoingAway",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(
+                    GrpcMethod::new(
+                        "com.github.trace_machina.nativelink.remote_execution.WorkerApi",
+                        "GoingAway",
+                    ),
+                );
+            self.inner.unary(req, path, codec).await
+        }
+        /// / Informs the scheduler about the result of an execution request.
+        pub async fn execution_response(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ExecuteResult>,
+        ) -> std::result::Result<tonic::Response<()>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/com.github.trace_machina.nativelink.remote_execution.WorkerApi/ExecutionResponse",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(
+                    GrpcMethod::new(
+                        "com.github.trace_machina.nativelink.remote_execution.WorkerApi",
+                        "ExecutionResponse",
+                    ),
+                );
+            self.inner.unary(req, path, codec).await
+        }
+    }
+}
+/// Generated server implementations.
+pub mod digest_subscription_server {
+    #![allow(
+        unused_variables,
+        dead_code,
+        missing_docs,
+        clippy::wildcard_imports,
+        clippy::let_unit_value,
+    )]
+    use tonic::codegen::*;
+    /// Generated trait containing gRPC methods that should be implemented for use with DigestSubscriptionServer.
+    #[async_trait]
+    pub trait DigestSubscription: std::marker::Send + std::marker::Sync + 'static {
+        /// / Returns once every requested blob is in the CAS or once the timeout
+        /// / elapsed, whichever happens first. Blobs that are still missing when
+        /// / the timeout elapsed are returned in the response.
+        async fn wait_for_blobs(
+            &self,
+            request: tonic::Request<super::WaitForBlobsRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::WaitForBlobsResponse>,
+            tonic::Status,
+        >;
+    }
+    /// / This API lets clients wait for blobs to become available in the CAS
+    /// / instead of calling `FindMissingBlobs` in a loop. This is useful for
+    /// / build coordinators where one job uploads an artifact and many
+    /// / downstream jobs wait for it.
+    #[derive(Debug)]
+    pub struct DigestSubscriptionServer<T> {
+        inner: Arc<T>,
+        accept_compression_encodings: EnabledCompressionEncodings,
+        send_compression_encodings: EnabledCompressionEncodings,
+        max_decoding_message_size: Option<usize>,
+        max_encoding_message_size: Option<usize>,
+    }
+    impl<T> DigestSubscriptionServer<T> {
+        pub fn new(inner: T) -> Self {
+            Self::from_arc(Arc::new(inner))
+        }
+        pub fn from_arc(inner: Arc<T>) -> Self {
+            Self {
+                inner,
+                accept_compression_encodings: Default::default(),
+                send_compression_encodings: Default::default(),
+                max_decoding_message_size: None,
+                max_encoding_message_size: None,
+            }
+        }
+        pub fn with_interceptor<F>(
+            inner: T,
+            interceptor: F,
+        ) -> InterceptedService<Self, F>
+        where
+            F: tonic::service::Interceptor,
+        {
+            InterceptedService::new(Self::new(inner), interceptor)
+        }
+        /// Enable decompressing requests with the given encoding.
+        #[must_use]
+        pub fn accept_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.accept_compression_encodings.enable(encoding);
+            self
+        }
+        /// Compress responses with the given encoding, if the client supports it.
+        #[must_use]
+        pub fn send_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.send_compression_encodings.enable(encoding);
+            self
+        }
+        /// Limits the maximum size of a decoded message.
+        ///
+        /// Default: `4MB`
+        #[must_use]
+        pub fn max_decoding_message_size(mut self, limit: usize) -> Self {
+            self.max_decoding_message_size = Some(limit);
+            self
+        }
+        /// Limits the maximum size of an encoded message.
+        ///
+        /// Default: `usize::MAX`
+        #[must_use]
+        pub fn max_encoding_message_size(mut self, limit: usize) -> Self {
+            self.max_encoding_message_size = Some(limit);
+            self
+        }
+    }
+    impl<T, B> tonic::codegen::Service<http::Request<B>> for DigestSubscriptionServer<T>
+    where
+        T: DigestSubscription,
+        B: Body + std::marker::Send + 'static,
+        B::Error: Into<StdError> + std::marker::Send + 'static,
+    {
+        type Response = http::Response<tonic::body::BoxBody>;
+        type Error = std::convert::Infallible;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+        fn poll_ready(
+            &mut self,
+            _cx: &mut Context<'_>,
+        ) -> Poll<std::result::Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+        fn call(&mut self, req: http::Request<B>) -> Self::Future {
+            match req.uri().path() {
+                "/com.github.trace_machina.nativelink.remote_execution.DigestSubscription/WaitForBlobs" => {
+                    #[allow(non_camel_case_types)]
+                    struct WaitForBlobsSvc<T: DigestSubscription>(pub Arc<T>);
+                    impl<
+                        T: DigestSubscription,
+                    > tonic::server::UnaryService<super::WaitForBlobsRequest>
+                    for WaitForBlobsSvc<T> {
+                        type Response = super::WaitForBlobsResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::WaitForBlobsRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as DigestSubscription>::wait_for_blobs(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = WaitForBlobsSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                _ => {
+                    Box::pin(async move {
+                        let mut response = http::Response::new(empty_body());
+                        let headers = response.headers_mut();
+                        headers
+                            .insert(
+                                tonic::Status::GRPC_STATUS,
+                                (tonic::Code::Unimplemented as i32).into(),
+                            );
+                        headers
+                            .insert(
+                                http::header::CONTENT_TYPE,
+                                tonic::metadata::GRPC_CONTENT_TYPE,
+                            );
+                        Ok(response)
+                    })
+                }
+            }
+        }
+    }
+    impl<T> Clone for DigestSubscriptionServer<T> {
+        fn clone(&self) -> Self {
+            let inner = self.inner.clone();
+            Self {
+                inner,
+                accept_compression_encodings: self.accept_compression_encodings,
+                send_compression_encodings: self.send_compression_encodings,
+                max_decoding_message_size: self.max_decoding_message_size,
+                max_encoding_message_size: self.max_encoding_message_size,
+            }
+        }
+    }
+    /// Generated gRPC service name
+    pub const SERVICE_NAME: &str = "com.github.trace_machina.nativelink.remote_execution.DigestSubscription";
+    impl<T> tonic::server::NamedService for DigestSubscriptionServer<T> {
+        const NAME: &'static str = SERVICE_NAME;
+    }
+}
+/// Generated server implementations.
+pub mod operation_admin_server {
+    #![allow(
+        unused_variables,
+        dead_code,
+        missing_docs,
+        clippy::wildcard_imports,
+        clippy::let_unit_value,
+    )]
+    use tonic::codegen::*;
+    /// Generated trait containing gRPC methods that should be implemented for use with OperationAdminServer.
+    #[async_trait]
+    pub trait OperationAdmin: std::marker::Send + std::marker::Sync + 'static {
+        /// / Lists the operations of a scheduler that match the filters, oldest
+        /// / first.
+        async fn list_operations(
+            &self,
+            request: tonic::Request<super::ListOperationsRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::ListOperationsResponse>,
+            tonic::Status,
+        >;
+        /// / Returns the state of an operation and the stages it moved through.
+        async fn get_operation(
+            &self,
+            request: tonic::Request<super::GetOperationRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::OperationInfo>,
+            tonic::Status,
+        >;
+        /// / Completes the operation with a `CANCELLED` error and kills it on the
+        /// / worker running it, if any. Fails with `FAILED_PRECONDITION` if the
+        /// / operation already finished.
+        async fn cancel_operation(
+            &self,
+            request: tonic::Request<super::CancelOperationRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::CancelOperationResponse>,
+            tonic::Status,
+        >;
+        /// / Kills the operation on the worker running it, if any, and puts it
+        /// / back in the queue. The retry does not count as a failed attempt.
+        /// / Fails with `FAILED_PRECONDITION` if the operation already finished.
+        async fn requeue_operation(
+            &self,
+            request: tonic::Request<super::RequeueOperationRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::RequeueOperationResponse>,
+            tonic::Status,
+        >;
+    }
+    /// / This API lets operators look at the operations of a scheduler, to find
+    /// / out why an operation is stuck, and cancel or retry it.
+    #[derive(Debug)]
+    pub struct OperationAdminServer<T> {
+        inner: Arc<T>,
+        accept_compression_encodings: EnabledCompressionEncodings,
+        send_compression_encodings: EnabledCompressionEncodings,
+        max_decoding_message_size: Option<usize>,
+        max_encoding_message_size: Option<usize>,
+    }
+    impl<T> OperationAdminServer<T> {
+        pub fn new(inner: T) -> Self {
+            Self::from_arc(Arc::new(inner))
+        }
+        pub fn from_arc(inner: Arc<T>) -> Self {
+            Self {
+                inner,
+                accept_compression_encodings: Default::default(),
+                send_compression_encodings: Default::default(),
+                max_decoding_message_size: None,
+                max_encoding_message_size: None,
+            }
+        }
+        pub fn with_interceptor<F>(
+            inner: T,
+            interceptor: F,
+        ) -> InterceptedService<Self, F>
+        where
+            F: tonic::service::Interceptor,
+        {
+            InterceptedService::new(Self::new(inner), interceptor)
+        }
+        /// Enable decompressing requests with the given encoding.
+        #[must_use]
+        pub fn accept_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.accept_compression_encodings.enable(encoding);
+            self
+        }
+        /// Compress responses with the given encoding, if the client supports it.
+        #[must_use]
+        pub fn send_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.send_compression_encodings.enable(encoding);
+            self
+        }
+        /// Limits the maximum size of a decoded message.
+        ///
+        /// Default: `4MB`
+        #[must_use]
+        pub fn max_decoding_message_size(mut self, limit: usize) -> Self {
+            self.max_decoding_message_size = Some(limit);
+            self
+        }
+        /// Limits the maximum size of an encoded message.
+        ///
+        /// Default: `usize::MAX`
+        #[must_use]
+        pub fn max_encoding_message_size(mut self, limit: usize) -> Self {
+            self.max_encoding_message_size = Some(limit);
+            self
+        }
+    }
+    impl<T, B> tonic::codegen::Service<http::Request<B>> for OperationAdminServer<T>
+    where
+        T: OperationAdmin,
+        B: Body + std::marker::Send + 'static,
+        B::Error: Into<StdError> + std::marker::Send + 'static,
+    {
+        type Response = http::Response<tonic::body::BoxBody>;
+        type Error = std::convert::Infallible;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+        fn poll_ready(
+            &mut self,
+            _cx: &mut Context<'_>,
+        ) -> Poll<std::result::Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+        fn call(&mut self, req: http::Request<B>) -> Self::Future {
+            match req.uri().path() {
+                "/com.github.trace_machina.nativelink.remote_execution.OperationAdmin/ListOperations" => {
+                    #[allow(non_camel_case_types)]
+                    struct ListOperationsSvc<T: OperationAdmin>(pub Arc<T>);
+                    impl<
+                        T: OperationAdmin,
+                    > tonic::server::UnaryService<super::ListOperationsRequest>
+                    for ListOperationsSvc<T> {
+                        type Response = super::ListOperationsResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ListOperationsRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as OperationAdmin>::list_operations(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = ListOperationsSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/com.github.trace_machina.nativelink.remote_execution.OperationAdmin/GetOperation" => {
+                    #[allow(non_camel_case_types)]
+                    struct GetOperationSvc<T: OperationAdmin>(pub Arc<T>);
+                    impl<
+                        T: OperationAdmin,
+                    > tonic::server::UnaryService<super::GetOperationRequest>
+                    for GetOperationSvc<T> {
+                        type Response = super::OperationInfo;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::GetOperationRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as OperationAdmin>::get_operation(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = GetOperationSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/com.github.trace_machina.nativelink.remote_execution.OperationAdmin/CancelOperation" => {
+                    #[allow(non_camel_case_types)]
+                    struct CancelOperationSvc<T: OperationAdmin>(pub Arc<T>);
+                    impl<
+                        T: OperationAdmin,
+                    > tonic::server::UnaryService<super::CancelOperationRequest>
+                    for CancelOperationSvc<T> {
+                        type Response = super::CancelOperationResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::CancelOperationRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as OperationAdmin>::cancel_operation(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = CancelOperationSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/com.github.trace_machina.nativelink.remote_execution.OperationAdmin/RequeueOperation" => {
+                    #[allow(non_camel_case_types)]
+                    struct RequeueOperationSvc<T: OperationAdmin>(pub Arc<T>);
+                    impl<
+                        T: OperationAdmin,
+                    > tonic::server::UnaryService<super::RequeueOperationRequest>
+                    for RequeueOperationSvc<T> {
+                        type Response = super::RequeueOperationResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::RequeueOperationRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as OperationAdmin>::requeue_operation(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = RequeueOperationSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                _ => {
+                    Box::pin(async move {
+                        let mut response = http::Response::new(empty_body());
+                        let headers = response.headers_mut();
+                        headers
+                            .insert(
+                                tonic::Status::GRPC_STATUS,
+                                (tonic::Code::Unimplemented as i32).into(),
+                            );
+                        headers
+                            .insert(
+                                http::header::CONTENT_TYPE,
+                                tonic::metadata::GRPC_CONTENT_TYPE,
+                            );
+                        Ok(response)
+                    })
+                }
+            }
+        }
+    }
+    impl<T> Clone for OperationAdminServer<T> {
+        fn clone(&self) -> Self {
+            let inner = self.inner.clone();
+            Self {
+                inner,
+                accept_compression_encodings: self.accept_compression_encodings,
+                send_compression_encodings: self.send_compression_encodings,
+                max_decoding_message_size: self.max_decoding_message_size,
+                max_encoding_message_size: self.max_encoding_message_size,
+            }
+        }
+    }
+    /// Generated gRPC service name
+    pub const SERVICE_NAME: &str = "com.github.trace_machina.nativelink.remote_execution.OperationAdmin";
+    impl<T> tonic::server::NamedService for OperationAdminServer<T> {
+        const NAME: &'static str = SERVICE_NAME;
+    }
+}
+/// Generated server implementations.
+pub mod worker_admin_server {
+    #![allow(
+        unused_variables,
+        dead_code,
+        missing_docs,
+        clippy::wildcard_imports,
+        clippy::let_unit_value,
+    )]
+    use tonic::codegen::*;
+    /// Generated trait containing gRPC methods that should be implemented for use with WorkerAdminServer.
+    #[async_trait]
+    pub trait WorkerAdmin: std::marker::Send + std::marker::Sync + 'static {
+        /// / Stops or resumes assigning new actions to a worker. Actions that
+        /// / already run on the worker are not affected.
+        async fn cordon_worker(
+            &self,
+            request: tonic::Request<super::CordonWorkerRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::CordonWorkerResponse>,
+            tonic::Status,
+        >;
+        /// / Cordons a worker, waits for the actions running on it to finish and
+        /// / then removes it from the pool. Fails with `DEADLINE_EXCEEDED` if the
+        /// / actions did not finish within the timeout, in which case the worker
+        /// / stays cordoned and keeps running its actions.
+        async fn drain_worker(
+            &self,
+            request: tonic::Request<super::DrainWorkerRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::DrainWorkerResponse>,
+            tonic::Status,
+        >;
+    }
+    /// / This API lets operators take workers out of the pool of a scheduler
+    /// / without failing the actions running on them, for example to do a
+    /// / rolling upgrade of the workers.
+    #[derive(Debug)]
+    pub struct WorkerAdminServer<T> {
+        inner: Arc<T>,
+        accept_compression_encodings: EnabledCompressionEncodings,
+        send_compression_encodings: EnabledCompressionEncodings,
+        max_decoding_message_size: Option<usize>,
+        max_encoding_message_size: Option<usize>,
+    }
+    impl<T> WorkerAdminServer<T> {
+        pub fn new(inner: T) -> Self {
+            Self::from_arc(Arc::new(inner))
+        }
+        pub fn from_arc(inner: Arc<T>) -> Self {
+            Self {
+                inner,
+                accept_compression_encodings: Default::default(),
+                send_compression_encodings: Default::default(),
+                max_decoding_message_size: None,
+                max_encoding_message_size: None,
+            }
+        }
+        pub fn with_interceptor<F>(
+            inner: T,
+            interceptor: F,
+        ) -> InterceptedService<Self, F>
+        where
+            F: tonic::service::Interceptor,
+        {
+            InterceptedService::new(Self::new(inner), interceptor)
+        }
+        /// Enable decompressing requests with the given encoding.
+        #[must_use]
+        pub fn accept_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.accept_compression_encodings.enable(encoding);
+            self
+        }
+        /// Compress responses with the given encoding, if the client supports it.
+        #[must_use]
+        pub fn send_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.send_compression_encodings.enable(encoding);
+            self
+        }
+        /// Limits the maximum size of a decoded message.
+        ///
+        /// Default: `4MB`
+        #[must_use]
+        pub fn max_decoding_message_size(mut self, limit: usize) -> Self {
+            self.max_decoding_message_size = Some(limit);
+            self
+        }
+        /// Limits the maximum size of an encoded message.
+        ///
+        /// Default: `usize::MAX`
+        #[must_use]
+        pub fn max_encoding_message_size(mut self, limit: usize) -> Self {
+            self.max_encoding_message_size = Some(limit);
+            self
+        }
+    }
+    impl<T, B> tonic::codegen::Service<http::Request<B>> for WorkerAdminServer<T>
+    where
+        T: WorkerAdmin,
+        B: Body + std::marker::Send + 'static,
+        B::Error: Into<StdError> + std::marker::Send + 'static,
+    {
+        type Response = http::Response<tonic::body::BoxBody>;
+        type Error = std::convert::Infallible;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+        fn poll_ready(
+            &mut self,
+            _cx: &mut Context<'_>,
+        ) -> Poll<std::result::Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+        fn call(&mut self, req: http::Request<B>) -> Self::Future {
+            match req.uri().path() {
+                "/com.github.trace_machina.nativelink.remote_execution.WorkerAdmin/CordonWorker" => {
+                    #[allow(non_camel_case_types)]
+                    struct CordonWorkerSvc<T: WorkerAdmin>(pub Arc<T>);
+                    impl<
+                        T: WorkerAdmin,
+                    > tonic::server::UnaryService<super::CordonWorkerRequest>
+                    for CordonWorkerSvc<T> {
+                        type Response = super::CordonWorkerResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::CordonWorkerRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as WorkerAdmin>::cordon_worker(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = CordonWorkerSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/com.github.trace_machina.nativelink.remote_execution.WorkerAdmin/DrainWorker" => {
+                    #[allow(non_camel_case_types)]
+                    struct DrainWorkerSvc<T: WorkerAdmin>(pub Arc<T>);
+                    impl<
+                        T: WorkerAdmin,
+                    > tonic::server::UnaryService<super::DrainWorkerRequest>
+                    for DrainWorkerSvc<T> {
+                        type Response = super::DrainWorkerResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::DrainWorkerRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as WorkerAdmin>::drain_worker(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = DrainWorkerSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                _ => {
+                    Box::pin(async move {
+                        let mut response = http::Response::new(empty_body());
+                        let headers = response.headers_mut();
+                        headers
+                            .insert(
+                                tonic::Status::GRPC_STATUS,
+                                (tonic::Code::Unimplemented as i32).into(),
+                            );
+                        headers
+                            .insert(
+                                http::header::CONTENT_TYPE,
+                                tonic::metadata::GRPC_CONTENT_TYPE,
+                            );
+                        Ok(response)
+                    })
+                }
+            }
+        }
+    }
+    impl<T> Clone for WorkerAdminServer<T> {
+        fn clone(&self) -> Self {
+            let inner = self.inner.clone();
+            Self {
+                inner,
+                accept_compression_encodings: self.accept_compression_encodings,
+                send_compression_encodings: self.send_compression_encodings,
+                max_decoding_message_size: self.max_decoding_message_size,
+                max_encoding_message_size: self.max_encoding_message_size,
+            }
+        }
+    }
+    /// Generated gRPC service name
+    pub const SERVICE_NAME: &str = "com.github.trace_machina.nativelink.remote_execution.WorkerAdmin";
+    impl<T> tonic::server::NamedService for WorkerAdminServer<T> {
+        const NAME: &'static str = SERVICE_NAME;
+    }
+}
 /// Generated server implementations.
 pub mod worker_api_server {
     #![allow(
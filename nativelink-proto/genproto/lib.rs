@@ -25,6 +25,10 @@
     rustdoc::invalid_html_tags
 )]
 
+/// Encoded `FileDescriptorSet` of all the protos, used to serve gRPC
+/// server reflection.
+pub const FILE_DESCRIPTOR_SET: &[u8] = include_bytes!("file_descriptor_set.bin");
+
 pub mod build {
     pub mod bazel {
         pub mod remote {
@@ -99,6 +103,9 @@ pub mod blaze {
     pub mod strategy_policy {
         include!("blaze.strategy_policy.pb.rs");
     }
+    pub mod worker {
+        include!("blaze.worker.pb.rs");
+    }
 }
 pub mod options {
     include!("options.pb.rs");
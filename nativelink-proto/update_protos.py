@@ -24,6 +24,9 @@ import shutil
 _BAZEL_DIR = os.path.join("nativelink-proto")
 _REPO_DIR = os.path.join(os.path.dirname(os.path.realpath(__file__)), "genproto")
 
+# Encoded descriptors of all protos, written next to the generated code.
+_FILE_DESCRIPTOR_SET = "file_descriptor_set.bin"
+
 _RUST_LICENSE = """\
 // Copyright 2022 The NativeLink Authors. All rights reserved.
 //
@@ -61,6 +64,10 @@ def update(proto_packages):
     for pkg in proto_packages:
         with open(repo_file_path(pkg), "wb") as outfile:
             outfile.write(expected_contents(pkg))
+    shutil.copyfile(
+        os.path.join(_BAZEL_DIR, _FILE_DESCRIPTOR_SET),
+        os.path.join(_REPO_DIR, _FILE_DESCRIPTOR_SET),
+    )
     with open(_REPO_DIR + "/lib.rs", "wb") as outfile:
         with open(_BAZEL_DIR + "/lib.rs", "rb") as infile:
             outfile.write(infile.read())
@@ -85,6 +92,23 @@ def check(proto_packages):
             print("%s out of date" % dst)
             failed = True
 
+    # Now check the file descriptor set.
+    dst = os.path.join(_REPO_DIR, _FILE_DESCRIPTOR_SET)
+    try:
+        with open(os.path.join(_BAZEL_DIR, _FILE_DESCRIPTOR_SET), "rb") as infile:
+            expected = infile.read()
+        with open(dst, "rb") as infile:
+            actual = infile.read()
+    except OSError as e:
+        failed = True
+        print("Could not read %s: %s" % (_FILE_DESCRIPTOR_SET, e))
+    else:
+        if expected == actual:
+            print("%s OK" % dst)
+        else:
+            print("%s out of date" % dst)
+            failed = True
+
     # Now check the lib.rs file.
     dst = _REPO_DIR + "/lib.rs"
     try:
@@ -12,31 +12,46 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
 use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use async_lock::Mutex;
 use lru::LruCache;
-use nativelink_config::schedulers::WorkerAllocationStrategy;
+use nativelink_config::schedulers::{
+    CancelAbandonedActionsSpec, HedgingSpec, WorkerAffinitySpec, WorkerAllocationStrategy,
+};
 use nativelink_error::{error_if, make_err, make_input_err, Code, Error, ResultExt};
 use nativelink_metric::{
     group, MetricFieldData, MetricKind, MetricPublishKnownKindData, MetricsComponent,
     RootMetricsComponent,
 };
-use nativelink_util::action_messages::{OperationId, WorkerId};
+use nativelink_util::action_messages::{
+    ActionInfo, ActionResult, ActionStage, ExecutionMetadata, OperationId, WorkerId,
+};
 use nativelink_util::operation_state_manager::{UpdateOperationType, WorkerStateManager};
-use nativelink_util::platform_properties::PlatformProperties;
 use nativelink_util::spawn;
 use nativelink_util::task::JoinHandleDropGuard;
 use tokio::sync::mpsc::{self, UnboundedSender};
 use tokio::sync::Notify;
+use tokio::time::sleep;
 use tonic::async_trait;
 use tracing::{event, Level};
 
+use crate::hedging_policy::HedgingPolicy;
 use crate::platform_property_manager::PlatformPropertyManager;
-use crate::worker::{ActionInfoWithProps, Worker, WorkerTimestamp, WorkerUpdate};
+use crate::worker::{ActionInfoWithProps, Worker, WorkerHeartbeat, WorkerTimestamp, WorkerUpdate};
+use crate::worker_affinity::WorkerAffinity;
 use crate::worker_scheduler::WorkerScheduler;
 
+/// How often a draining worker is checked for running actions.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Default value for `CancelAbandonedActionsSpec::grace_period_s`.
+/// If this changes, remember to change the documentation in the config.
+const DEFAULT_ABANDONED_ACTION_GRACE_PERIOD_S: u64 = 30;
+
 struct Workers(LruCache<WorkerId, Worker>);
 
 impl Deref for Workers {
@@ -70,6 +85,14 @@ impl MetricsComponent for Workers {
     }
 }
 
+/// An operation that runs on two workers at once.
+struct HedgedOperation {
+    /// The worker the operation is assigned to in the state manager.
+    primary_worker_id: WorkerId,
+    /// The worker running the second attempt.
+    hedge_worker_id: WorkerId,
+}
+
 /// A collection of workers that are available to run tasks.
 #[derive(MetricsComponent)]
 struct ApiWorkerSchedulerImpl {
@@ -86,6 +109,23 @@ struct ApiWorkerSchedulerImpl {
     worker_change_notify: Arc<Notify>,
     /// A channel to notify that an operation is still alive.
     operation_keep_alive_tx: UnboundedSender<(OperationId, WorkerId)>,
+    /// Decides when slow actions get a second attempt. None if hedging is
+    /// disabled.
+    #[metric(group = "hedging_policy")]
+    hedging_policy: Option<HedgingPolicy>,
+    /// Operations that run on two workers at once.
+    hedged_operations: HashMap<OperationId, HedgedOperation>,
+    /// The inputs of the actions the workers ran recently. None if worker
+    /// affinity is disabled.
+    #[metric(group = "worker_affinity")]
+    worker_affinity: Option<WorkerAffinity>,
+    /// The heartbeat workers are told to keep when they connect. None if
+    /// workers pick their own keep alive interval.
+    worker_heartbeat: Option<WorkerHeartbeat>,
+    /// Actions expected to execute at least this long are spread over the
+    /// workers. None if there is no execution history.
+    #[metric(help = "Actions expected to execute at least this long are spread over the workers.")]
+    long_action_threshold: Option<Duration>,
 }
 
 impl ApiWorkerSchedulerImpl {
@@ -109,6 +149,15 @@ impl ApiWorkerSchedulerImpl {
         );
         worker.last_update_timestamp = timestamp;
         for operation_id in worker.running_action_infos.keys() {
+            // The state manager only knows the worker the operation is
+            // assigned to, not the one running its second attempt.
+            if self
+                .hedged_operations
+                .get(operation_id)
+                .is_some_and(|hedged_operation| hedged_operation.hedge_worker_id == *worker_id)
+            {
+                continue;
+            }
             if self
                 .operation_keep_alive_tx
                 .send((operation_id.clone(), *worker_id))
@@ -125,6 +174,32 @@ impl ApiWorkerSchedulerImpl {
         Ok(())
     }
 
+    /// Kills the operation on the worker running it and on the worker
+    /// running its second attempt, if it was hedged.
+    fn kill_operation(&mut self, worker_id: &WorkerId, operation_id: &OperationId) {
+        // Note: `peek_mut()` keeps the order of the workers, which is
+        // needed to find the timed out workers.
+        if let Some(worker) = self.workers.peek_mut(worker_id) {
+            if worker.running_action_infos.contains_key(operation_id) {
+                if let Err(err) = worker.kill_action(operation_id) {
+                    event!(Level::WARN, ?worker_id, ?err, "Failed to kill action");
+                }
+            }
+        }
+        if let Some(hedged_operation) = self.hedged_operations.remove(operation_id) {
+            if let Some(hedge_worker) = self.workers.peek_mut(&hedged_operation.hedge_worker_id) {
+                if let Err(err) = hedge_worker.kill_action(operation_id) {
+                    event!(
+                        Level::WARN,
+                        worker_id = ?hedged_operation.hedge_worker_id,
+                        ?err,
+                        "Failed to kill action"
+                    );
+                }
+            }
+        }
+    }
+
     /// Adds a worker to the pool.
     /// Note: This function will not do any task matching.
     fn add_worker(&mut self, worker: Worker) -> Result<(), Error> {
@@ -136,7 +211,7 @@ impl ApiWorkerSchedulerImpl {
         // the multi-threaded runtime works.
         let worker = self.workers.peek_mut(&worker_id).unwrap();
         let res = worker
-            .send_initial_connection_result()
+            .send_initial_connection_result(self.worker_heartbeat)
             .err_tip(|| "Failed to send initial connection result to worker");
         if let Err(err) = &res {
             event!(
@@ -155,6 +230,9 @@ impl ApiWorkerSchedulerImpl {
     /// running.
     fn remove_worker(&mut self, worker_id: &WorkerId) -> Option<Worker> {
         let result = self.workers.pop(worker_id);
+        if let Some(worker_affinity) = &mut self.worker_affinity {
+            worker_affinity.remove_worker(worker_id);
+        }
         self.worker_change_notify.notify_one();
         result
     }
@@ -174,22 +252,83 @@ impl ApiWorkerSchedulerImpl {
         Ok(())
     }
 
+    /// Workers that still run the operation are skipped, which keeps the
+    /// second attempt of a hedged operation off the worker of the first
+    /// one, and a requeued operation off the worker it is being killed on.
     fn inner_find_worker_for_action(
         &self,
-        platform_properties: &PlatformProperties,
+        operation_id: &OperationId,
+        action_info: &ActionInfoWithProps,
     ) -> Option<WorkerId> {
+        let is_candidate = |w: &Worker| {
+            !w.running_action_infos.contains_key(operation_id)
+                && w.can_accept_work()
+                && action_info
+                    .platform_properties
+                    .is_satisfied_by(&w.platform_properties)
+        };
         let mut workers_iter = self.workers.iter();
         let workers_iter = match self.allocation_strategy {
             // Use rfind to get the least recently used that satisfies the properties.
-            WorkerAllocationStrategy::least_recently_used => workers_iter.rfind(|(_, w)| {
-                w.can_accept_work() && platform_properties.is_satisfied_by(&w.platform_properties)
-            }),
+            WorkerAllocationStrategy::least_recently_used => {
+                workers_iter.rfind(|(_, w)| is_candidate(w))
+            }
             // Use find to get the most recently used that satisfies the properties.
-            WorkerAllocationStrategy::most_recently_used => workers_iter.find(|(_, w)| {
-                w.can_accept_work() && platform_properties.is_satisfied_by(&w.platform_properties)
-            }),
+            WorkerAllocationStrategy::most_recently_used => {
+                workers_iter.find(|(_, w)| is_candidate(w))
+            }
         };
-        workers_iter.map(|(_, w)| &w.id).copied()
+        let (_, mut worker) = workers_iter?;
+
+        // Avoid stacking actions expected to run long on one worker, as
+        // long as another worker can take them.
+        if let Some(long_action_threshold) = self.long_action_threshold {
+            let is_long_action = |action_info: &ActionInfoWithProps| {
+                action_info
+                    .expected_execution_duration
+                    .is_some_and(|duration| duration >= long_action_threshold)
+            };
+            let runs_long_action = |w: &Worker| w.running_action_infos.values().any(is_long_action);
+            if is_long_action(action_info) && runs_long_action(worker) {
+                let mut workers_iter = self.workers.iter();
+                let is_spread_candidate =
+                    |(_, w): &(&WorkerId, &Worker)| is_candidate(w) && !runs_long_action(w);
+                let maybe_spread_worker = match self.allocation_strategy {
+                    WorkerAllocationStrategy::least_recently_used => {
+                        workers_iter.rfind(is_spread_candidate)
+                    }
+                    WorkerAllocationStrategy::most_recently_used => {
+                        workers_iter.find(is_spread_candidate)
+                    }
+                };
+                if let Some((_, spread_worker)) = maybe_spread_worker {
+                    worker = spread_worker;
+                }
+            }
+        }
+
+        let Some(worker_affinity) = &self.worker_affinity else {
+            return Some(worker.id);
+        };
+
+        // Prefer the worker whose recent inputs overlap the most with the
+        // inputs of the action, as long as it is not much busier than the
+        // worker picked above. On a tie the worker picked above is kept.
+        let max_running_actions =
+            worker.running_action_infos.len() + worker_affinity.max_load_difference();
+        let mut best_score = worker_affinity.score(&worker.id, &action_info.inner);
+        let mut best_worker_id = worker.id;
+        for (worker_id, w) in self.workers.iter() {
+            if w.running_action_infos.len() > max_running_actions || !is_candidate(w) {
+                continue;
+            }
+            let score = worker_affinity.score(worker_id, &action_info.inner);
+            if score > best_score {
+                best_score = score;
+                best_worker_id = *worker_id;
+            }
+        }
+        Some(best_worker_id)
     }
 
     async fn update_action(
@@ -217,16 +356,53 @@ impl ApiWorkerSchedulerImpl {
                 (action_stage.is_finished(), false)
             }
             UpdateOperationType::KeepAlive => (false, false),
-            UpdateOperationType::UpdateWithError(err) => {
+            UpdateOperationType::UpdateWithError(err)
+            | UpdateOperationType::UpdateWithWorkerEvicted(err) => {
                 (true, err.code == Code::ResourceExhausted)
             }
         };
+        let is_completed = matches!(
+            &update,
+            UpdateOperationType::UpdateWithActionStage(action_stage) if action_stage.is_finished()
+        );
+
+        // The scheduler already completed the operation when it killed it, so
+        // the worker only needs to give back the resources of the action.
+        if worker.killed_operation_ids.contains(operation_id) {
+            if !is_finished {
+                return Ok(());
+            }
+            let complete_action_res = worker.complete_action(operation_id);
+            self.worker_change_notify.notify_one();
+            return complete_action_res;
+        }
+
+        // Both attempts of a hedged operation report to the state manager as
+        // the worker the operation is assigned to. Only the original attempt
+        // reports progress and whichever attempt finishes first completes the
+        // operation.
+        let mut reported_worker_id = *worker_id;
+        let mut other_attempt_worker_id = None;
+        if let Some(hedged_operation) = self.hedged_operations.get(operation_id) {
+            let is_hedge = hedged_operation.hedge_worker_id == *worker_id;
+            if is_finished {
+                reported_worker_id = hedged_operation.primary_worker_id;
+                other_attempt_worker_id = Some(if is_hedge {
+                    hedged_operation.primary_worker_id
+                } else {
+                    hedged_operation.hedge_worker_id
+                });
+                self.hedged_operations.remove(operation_id);
+            } else if is_hedge {
+                return Ok(());
+            }
+        }
 
         // Update the operation in the worker state manager.
         {
             let update_operation_res = self
                 .worker_state_manager
-                .update_operation(operation_id, worker_id, update)
+                .update_operation(operation_id, &reported_worker_id, update)
                 .await
                 .err_tip(|| "in update_operation on SimpleScheduler::update_action");
             if let Err(err) = update_operation_res {
@@ -245,6 +421,19 @@ impl ApiWorkerSchedulerImpl {
             return Ok(());
         }
 
+        if let (Some(hedging_policy), Some(start_timestamp)) = (
+            self.hedging_policy.as_mut(),
+            worker.running_action_start_timestamps.get(operation_id),
+        ) {
+            if is_completed {
+                let now_timestamp = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs();
+                hedging_policy.record_duration(now_timestamp.saturating_sub(*start_timestamp));
+            }
+        }
+
         // Clear this action from the current worker if finished.
         let complete_action_res = {
             let was_paused = !worker.can_accept_work();
@@ -259,6 +448,20 @@ impl ApiWorkerSchedulerImpl {
             complete_action_res
         };
 
+        if let Some(other_attempt_worker_id) = other_attempt_worker_id {
+            if let Some(other_worker) = self.workers.peek_mut(&other_attempt_worker_id) {
+                if let Err(err) = other_worker.kill_action(operation_id) {
+                    event!(
+                        Level::WARN,
+                        ?operation_id,
+                        worker_id = ?other_attempt_worker_id,
+                        ?err,
+                        "Failed to kill the other attempt of a hedged action"
+                    );
+                }
+            }
+        }
+
         self.worker_change_notify.notify_one();
 
         complete_action_res
@@ -293,6 +496,9 @@ impl ApiWorkerSchedulerImpl {
                 return Result::<(), _>::Err(err.clone())
                     .merge(self.immediate_evict_worker(&worker_id, err).await);
             }
+            if let Some(worker_affinity) = &mut self.worker_affinity {
+                worker_affinity.record_run(&worker_id, &action_info.inner);
+            }
         } else {
             event!(
                 Level::WARN,
@@ -316,12 +522,29 @@ impl ApiWorkerSchedulerImpl {
             // We don't care if we fail to send message to worker, this is only a best attempt.
             let _ = worker.notify_update(WorkerUpdate::Disconnect);
             for (operation_id, _) in worker.running_action_infos.drain() {
+                // Killed operations were already completed or queued again.
+                if worker.killed_operation_ids.contains(&operation_id) {
+                    continue;
+                }
+                if let Some(hedged_operation) = self.hedged_operations.remove(&operation_id) {
+                    if hedged_operation.hedge_worker_id == *worker_id {
+                        // The original attempt keeps running.
+                        continue;
+                    }
+                    // The operation is queued again, so its second attempt
+                    // is no longer needed.
+                    if let Some(hedge_worker) =
+                        self.workers.peek_mut(&hedged_operation.hedge_worker_id)
+                    {
+                        let _ = hedge_worker.kill_action(&operation_id);
+                    }
+                }
                 result = result.merge(
                     self.worker_state_manager
                         .update_operation(
                             &operation_id,
                             worker_id,
-                            UpdateOperationType::UpdateWithError(err.clone()),
+                            UpdateOperationType::UpdateWithWorkerEvicted(err.clone()),
                         )
                         .await,
                 );
@@ -345,17 +568,38 @@ pub struct ApiWorkerScheduler {
         help = "Timeout of how long to evict workers if no response in this given amount of time in seconds."
     )]
     worker_timeout_s: u64,
+    #[metric(
+        help = "How long past their timeout actions may run before the scheduler kills them, in seconds."
+    )]
+    action_timeout_grace_s: u64,
+    /// How long no client may listen to an action before it is killed. None
+    /// if abandoned actions are not cancelled.
+    abandoned_action_grace_period: Option<Duration>,
     _operation_keep_alive_spawn: JoinHandleDropGuard<()>,
 }
 
 impl ApiWorkerScheduler {
+    #[expect(clippy::too_many_arguments)]
     pub fn new(
         worker_state_manager: Arc<dyn WorkerStateManager>,
         platform_property_manager: Arc<PlatformPropertyManager>,
         allocation_strategy: WorkerAllocationStrategy,
         worker_change_notify: Arc<Notify>,
         worker_timeout_s: u64,
+        action_timeout_grace_s: u64,
+        hedging_spec: Option<&HedgingSpec>,
+        worker_affinity_spec: Option<&WorkerAffinitySpec>,
+        cancel_abandoned_actions_spec: Option<&CancelAbandonedActionsSpec>,
+        worker_heartbeat: Option<WorkerHeartbeat>,
+        long_action_threshold: Option<Duration>,
     ) -> Arc<Self> {
+        let abandoned_action_grace_period = cancel_abandoned_actions_spec.map(|spec| {
+            Duration::from_secs(if spec.grace_period_s == 0 {
+                DEFAULT_ABANDONED_ACTION_GRACE_PERIOD_S
+            } else {
+                spec.grace_period_s
+            })
+        });
         let (operation_keep_alive_tx, mut operation_keep_alive_rx) = mpsc::unbounded_channel();
         Arc::new(Self {
             inner: Mutex::new(ApiWorkerSchedulerImpl {
@@ -364,9 +608,16 @@ impl ApiWorkerScheduler {
                 allocation_strategy,
                 worker_change_notify,
                 operation_keep_alive_tx,
+                hedging_policy: hedging_spec.map(HedgingPolicy::new),
+                hedged_operations: HashMap::new(),
+                worker_affinity: worker_affinity_spec.map(WorkerAffinity::new),
+                worker_heartbeat,
+                long_action_threshold,
             }),
             platform_property_manager,
             worker_timeout_s,
+            action_timeout_grace_s,
+            abandoned_action_grace_period,
             _operation_keep_alive_spawn: spawn!(
                 "simple_scheduler_operation_keep_alive",
                 async move {
@@ -410,16 +661,78 @@ impl ApiWorkerScheduler {
             .await
     }
 
+    /// Kills the operation on the worker running it, if the worker still
+    /// runs it. The worker gives back the resources of the operation once
+    /// it stopped, without updating the operation.
+    pub async fn kill_operation(&self, worker_id: &WorkerId, operation_id: &OperationId) {
+        let mut inner = self.inner.lock().await;
+        inner.kill_operation(worker_id, operation_id);
+    }
+
     /// Attempts to find a worker that is capable of running this action.
     // TODO(blaise.bruer) This algorithm is not very efficient. Simple testing using a tree-like
     // structure showed worse performance on a 10_000 worker * 7 properties * 1000 queued tasks
     // simulation of worst cases in a single threaded environment.
     pub async fn find_worker_for_action(
         &self,
-        platform_properties: &PlatformProperties,
+        operation_id: &OperationId,
+        action_info: &ActionInfoWithProps,
     ) -> Option<WorkerId> {
         let inner = self.inner.lock().await;
-        inner.inner_find_worker_for_action(platform_properties)
+        inner.inner_find_worker_for_action(operation_id, action_info)
+    }
+
+    /// Stops giving new actions to all workers and waits up to `timeout`
+    /// for them to finish the actions they run. Returns false if some
+    /// workers still run actions after `timeout`.
+    pub async fn drain_all_workers(&self, timeout: Duration) -> bool {
+        {
+            let mut inner = self.inner.lock().await;
+            for (_, worker) in inner.workers.iter_mut() {
+                worker.is_draining = true;
+            }
+        }
+        let wait_for_idle_workers = async {
+            loop {
+                {
+                    let inner = self.inner.lock().await;
+                    if !inner.workers.iter().any(|(_, worker)| worker.has_actions()) {
+                        return;
+                    }
+                }
+                sleep(DRAIN_POLL_INTERVAL).await;
+            }
+        };
+        tokio::time::timeout(timeout, wait_for_idle_workers)
+            .await
+            .is_ok()
+    }
+
+    /// Disconnects all workers from the scheduler. The operations they still
+    /// run are queued again.
+    pub async fn disconnect_all_workers(&self) {
+        let mut inner = self.inner.lock().await;
+        let worker_ids: Vec<WorkerId> = inner
+            .workers
+            .iter()
+            .map(|(worker_id, _)| *worker_id)
+            .collect();
+        for worker_id in &worker_ids {
+            let evict_result = inner
+                .immediate_evict_worker(
+                    worker_id,
+                    make_err!(Code::Unavailable, "Scheduler is shutting down"),
+                )
+                .await;
+            if let Err(err) = evict_result {
+                event!(
+                    Level::ERROR,
+                    ?err,
+                    ?worker_id,
+                    "Failed to disconnect worker while shutting down"
+                );
+            }
+        }
     }
 
     /// Checks to see if the worker exists in the worker pool. Should only be used in unit tests.
@@ -471,6 +784,24 @@ impl WorkerScheduler for ApiWorkerScheduler {
         inner.update_action(worker_id, operation_id, update).await
     }
 
+    async fn get_running_action_info(
+        &self,
+        worker_id: &WorkerId,
+        operation_id: &OperationId,
+    ) -> Result<Arc<ActionInfo>, Error> {
+        let inner = self.inner.lock().await;
+        let worker = inner.workers.peek(worker_id).err_tip(|| {
+            format!(
+                "Worker {worker_id} does not exist in ApiWorkerScheduler::get_running_action_info"
+            )
+        })?;
+        worker
+            .running_action_infos
+            .get(operation_id)
+            .map(|action_info| action_info.inner.clone())
+            .err_tip(|| format!("Operation {operation_id} is not running on worker {worker_id}"))
+    }
+
     async fn worker_keep_alive_received(
         &self,
         worker_id: &WorkerId,
@@ -536,6 +867,218 @@ impl WorkerScheduler for ApiWorkerScheduler {
         let mut inner = self.inner.lock().await;
         inner.set_drain_worker(worker_id, is_draining).await
     }
+
+    async fn kill_timedout_actions(&self, now_timestamp: WorkerTimestamp) -> Result<(), Error> {
+        let mut inner = self.inner.lock().await;
+        for (_, worker) in inner.workers.iter_mut() {
+            worker.start_action_deadlines(now_timestamp);
+        }
+
+        let timed_out_actions: Vec<(WorkerId, OperationId)> = inner
+            .workers
+            .iter()
+            .flat_map(|(worker_id, worker)| {
+                worker
+                    .running_action_deadlines
+                    .iter()
+                    .filter(|(operation_id, deadline)| {
+                        deadline.saturating_add(self.action_timeout_grace_s) <= now_timestamp
+                            && !worker.killed_operation_ids.contains(*operation_id)
+                            // Second attempts are killed with the original one.
+                            && !inner.hedged_operations.get(*operation_id).is_some_and(
+                                |hedged_operation| hedged_operation.hedge_worker_id == *worker_id,
+                            )
+                    })
+                    .map(|(operation_id, _)| (*worker_id, operation_id.clone()))
+            })
+            .collect();
+        let mut result = Ok(());
+        for (worker_id, operation_id) in timed_out_actions {
+            event!(
+                Level::WARN,
+                ?worker_id,
+                ?operation_id,
+                "Action ran past its timeout, killing it"
+            );
+            inner.kill_operation(&worker_id, &operation_id);
+            let stage = ActionStage::Completed(ActionResult {
+                execution_metadata: ExecutionMetadata {
+                    worker: worker_id.to_string(),
+                    ..ExecutionMetadata::default()
+                },
+                error: Some(make_err!(
+                    Code::DeadlineExceeded,
+                    "Operation {operation_id} was killed after running past its timeout on worker {worker_id}"
+                )),
+                ..ActionResult::default()
+            });
+            result = result.merge(
+                inner
+                    .worker_state_manager
+                    .update_operation(
+                        &operation_id,
+                        &worker_id,
+                        UpdateOperationType::UpdateWithActionStage(stage),
+                    )
+                    .await,
+            );
+        }
+
+        result
+    }
+
+    async fn hedge_slow_actions(&self, now_timestamp: WorkerTimestamp) -> Result<(), Error> {
+        let mut inner = self.inner.lock().await;
+        let Some(threshold_s) = inner
+            .hedging_policy
+            .as_ref()
+            .and_then(HedgingPolicy::threshold_s)
+        else {
+            return Ok(());
+        };
+
+        let slow_actions: Vec<(WorkerId, OperationId, ActionInfoWithProps)> = inner
+            .workers
+            .iter()
+            .flat_map(|(worker_id, worker)| {
+                worker
+                    .running_action_start_timestamps
+                    .iter()
+                    .filter(|(operation_id, start_timestamp)| {
+                        start_timestamp.saturating_add(threshold_s) < now_timestamp
+                            && !worker.killed_operation_ids.contains(*operation_id)
+                            && !inner.hedged_operations.contains_key(*operation_id)
+                    })
+                    .filter_map(|(operation_id, _)| {
+                        let action_info = worker.running_action_infos.get(operation_id)?;
+                        Some((*worker_id, operation_id.clone(), action_info.clone()))
+                    })
+            })
+            .collect();
+        let mut result = Ok(());
+        for (worker_id, operation_id, action_info) in slow_actions {
+            let Some(hedge_worker_id) =
+                inner.inner_find_worker_for_action(&operation_id, &action_info)
+            else {
+                continue;
+            };
+            event!(
+                Level::INFO,
+                ?operation_id,
+                ?worker_id,
+                ?hedge_worker_id,
+                "Action runs longer than usual, starting a second attempt"
+            );
+            inner.hedged_operations.insert(
+                operation_id.clone(),
+                HedgedOperation {
+                    primary_worker_id: worker_id,
+                    hedge_worker_id,
+                },
+            );
+            if let Some(hedging_policy) = &inner.hedging_policy {
+                hedging_policy.record_hedged();
+            }
+            result = result.merge(
+                inner
+                    .worker_notify_run_action(hedge_worker_id, operation_id, action_info)
+                    .await,
+            );
+        }
+
+        result
+    }
+
+    async fn cancel_abandoned_actions(&self) -> Result<(), Error> {
+        let Some(grace_period) = self.abandoned_action_grace_period else {
+            return Ok(());
+        };
+        let mut inner = self.inner.lock().await;
+
+        let running_actions: Vec<(WorkerId, OperationId)> = inner
+            .workers
+            .iter()
+            .flat_map(|(worker_id, worker)| {
+                worker
+                    .running_action_infos
+                    .keys()
+                    .filter(|operation_id| {
+                        !worker.killed_operation_ids.contains(*operation_id)
+                            // Second attempts are killed with the original one.
+                            && !inner.hedged_operations.get(*operation_id).is_some_and(
+                                |hedged_operation| hedged_operation.hedge_worker_id == *worker_id,
+                            )
+                    })
+                    .map(|operation_id| (*worker_id, operation_id.clone()))
+            })
+            .collect();
+        let mut result = Ok(());
+        for (worker_id, operation_id) in running_actions {
+            let is_abandoned = match inner
+                .worker_state_manager
+                .cancel_if_abandoned(&operation_id, &worker_id, grace_period)
+                .await
+            {
+                Ok(is_abandoned) => is_abandoned,
+                Err(err) => {
+                    result = result.merge(Err(err));
+                    continue;
+                }
+            };
+            if !is_abandoned {
+                continue;
+            }
+            event!(
+                Level::INFO,
+                ?worker_id,
+                ?operation_id,
+                "No client listens to the action anymore, killing it"
+            );
+            inner.kill_operation(&worker_id, &operation_id);
+        }
+
+        result
+    }
+
+    async fn drain_worker(&self, worker_id: &WorkerId, timeout: Duration) -> Result<(), Error> {
+        self.set_drain_worker(worker_id, true)
+            .await
+            .err_tip(|| "In ApiWorkerScheduler::drain_worker")?;
+        let wait_for_idle_worker = async {
+            loop {
+                {
+                    let mut inner = self.inner.lock().await;
+                    let Some(worker) = inner.workers.peek(worker_id) else {
+                        // The worker left the pool on its own, so there is
+                        // nothing left to drain.
+                        return Ok(());
+                    };
+                    if !worker.has_actions() {
+                        event!(
+                            Level::INFO,
+                            ?worker_id,
+                            "Worker drained, removing from pool"
+                        );
+                        return inner
+                            .immediate_evict_worker(
+                                worker_id,
+                                make_err!(Code::Internal, "Worker {worker_id} was drained"),
+                            )
+                            .await;
+                    }
+                }
+                sleep(DRAIN_POLL_INTERVAL).await;
+            }
+        };
+        tokio::time::timeout(timeout, wait_for_idle_worker)
+            .await
+            .map_err(|_| {
+                make_err!(
+                    Code::DeadlineExceeded,
+                    "Worker {worker_id} still runs actions after {timeout:?}, it stays cordoned"
+                )
+            })?
+    }
 }
 
 impl RootMetricsComponent for ApiWorkerScheduler {}
@@ -20,11 +20,16 @@ use nativelink_metric::{
     MetricFieldData, MetricKind, MetricPublishKnownKindData, MetricsComponent,
 };
 use nativelink_util::action_messages::{
-    ActionInfo, ActionStage, ActionState, OperationId, WorkerId,
+    ActionInfo, ActionStage, ActionState, OperationDetails, OperationId, StageChange, WorkerId,
 };
 use serde::{Deserialize, Serialize};
 use static_assertions::{assert_eq_size, const_assert, const_assert_eq};
 
+use crate::retry_policy::FailedAttempts;
+
+/// Number of stage changes kept for an action, older ones are dropped.
+const MAX_STAGE_HISTORY: usize = 32;
+
 /// The version of the awaited action.
 /// This number will always increment by one each time
 /// the action is updated.
@@ -81,15 +86,24 @@ pub struct AwaitedAction {
     /// Number of attempts the job has been tried.
     #[metric(help = "The number of attempts the AwaitedAction has been tried")]
     pub attempts: usize,
+
+    /// Number of failed attempts by the kind of failure.
+    #[serde(default)]
+    pub failed_attempts: FailedAttempts,
+
+    /// The stages the action moved through, oldest first.
+    #[serde(default)]
+    stage_history: Vec<StageChange>,
 }
 
 impl AwaitedAction {
     pub fn new(operation_id: OperationId, action_info: Arc<ActionInfo>, now: SystemTime) -> Self {
         let stage = ActionStage::Queued;
         let sort_key = AwaitedActionSortKey::new_with_unique_key(
-            action_info.priority,
+            action_info.sort_priority(),
             &action_info.insert_timestamp,
         );
+        let stage_history = vec![StageChange::new(&stage, None, now)];
         let state = Arc::new(ActionState {
             stage,
             // Note: We don't use the real client_operation_id here because
@@ -106,10 +120,12 @@ impl AwaitedAction {
             operation_id,
             sort_key,
             attempts: 0,
+            failed_attempts: FailedAttempts::default(),
             last_worker_updated_timestamp: now,
             last_client_keepalive_timestamp: now,
             worker_id: None,
             state,
+            stage_history,
         }
     }
 
@@ -160,6 +176,18 @@ impl AwaitedAction {
         self.last_client_keepalive_timestamp = now;
     }
 
+    /// Returns what is known about the action for debugging.
+    pub(crate) fn operation_details(&self) -> OperationDetails {
+        OperationDetails {
+            operation_id: self.operation_id.clone(),
+            worker_id: self.worker_id,
+            attempts: self.attempts,
+            last_worker_update: self.last_worker_updated_timestamp,
+            last_client_keepalive: self.last_client_keepalive_timestamp,
+            stage_history: self.stage_history.clone(),
+        }
+    }
+
     pub(crate) fn set_client_operation_id(&mut self, client_operation_id: OperationId) {
         Arc::make_mut(&mut self.state).client_operation_id = client_operation_id;
     }
@@ -174,6 +202,13 @@ impl AwaitedAction {
 
     /// Sets the current state of the action and updates the last worker updated timestamp.
     pub fn worker_set_state(&mut self, mut state: Arc<ActionState>, now: SystemTime) {
+        if !self.state.stage.is_same_stage(&state.stage) {
+            if self.stage_history.len() == MAX_STAGE_HISTORY {
+                self.stage_history.remove(0);
+            }
+            self.stage_history
+                .push(StageChange::new(&state.stage, self.worker_id, now));
+        }
         std::mem::swap(&mut self.state, &mut state);
         self.worker_keep_alive(now);
     }
@@ -183,4 +183,10 @@ pub trait AwaitedActionDb: Send + Sync + MetricsComponent + Unpin + 'static {
         client_operation_id: OperationId,
         action_info: Arc<ActionInfo>,
     ) -> impl Future<Output = Result<Self::Subscriber, Error>> + Send;
+
+    /// Writes the unfinished actions to the persistence store now, if the
+    /// database has one.
+    fn flush(&self) -> impl Future<Output = Result<(), Error>> + Send {
+        async { Ok(()) }
+    }
 }
@@ -343,6 +343,14 @@ impl ClientStateManager for CacheLookupScheduler {
     fn as_known_platform_property_provider(&self) -> Option<&dyn KnownPlatformPropertyProvider> {
         self.action_scheduler.as_known_platform_property_provider()
     }
+
+    async fn cancel_operation(&self, operation_id: &OperationId) -> Result<(), Error> {
+        self.action_scheduler.cancel_operation(operation_id).await
+    }
+
+    async fn requeue_operation(&self, operation_id: &OperationId) -> Result<(), Error> {
+        self.action_scheduler.requeue_operation(operation_id).await
+    }
 }
 
 impl RootMetricsComponent for CacheLookupScheduler {}
@@ -13,7 +13,7 @@
 // limitations under the License.
 
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 use nativelink_config::schedulers::{
     ExperimentalSimpleSchedulerBackend, SchedulerSpec, SimpleSpec,
@@ -22,11 +22,16 @@ use nativelink_config::stores::EvictionPolicy;
 use nativelink_error::{make_input_err, Error, ResultExt};
 use nativelink_store::redis_store::RedisStore;
 use nativelink_store::store_manager::StoreManager;
+use nativelink_util::background_spawn;
+use nativelink_util::health_utils::HealthRegistryBuilder;
 use nativelink_util::instant_wrapper::InstantWrapper;
 use nativelink_util::operation_state_manager::ClientStateManager;
-use tokio::sync::Notify;
+use nativelink_util::shutdown_guard::ShutdownGuard;
+use tokio::sync::{broadcast, Notify};
+use tracing::{event, Level};
 
 use crate::cache_lookup_scheduler::CacheLookupScheduler;
+use crate::execution_history::ExecutionHistory;
 use crate::grpc_scheduler::GrpcScheduler;
 use crate::memory_awaited_action_db::MemoryAwaitedActionDb;
 use crate::property_modifier_scheduler::PropertyModifierScheduler;
@@ -38,6 +43,19 @@ use crate::worker_scheduler::WorkerScheduler;
 /// If this changes, remember to change the documentation in the config.
 const DEFAULT_RETAIN_COMPLETED_FOR_S: u32 = 60;
 
+/// Default key the scheduler state is persisted under.
+/// If this changes, remember to change the documentation in the config.
+const DEFAULT_PERSISTENCE_KEY: &str = "nativelink_scheduler_state";
+
+/// Default interval to persist the scheduler state in seconds.
+/// If this changes, remember to change the documentation in the config.
+const DEFAULT_PERSISTENCE_SNAPSHOT_INTERVAL_S: u64 = 1;
+
+/// Default time workers get to finish their actions when the scheduler
+/// shuts down, in seconds.
+/// If this changes, remember to change the documentation in the config.
+const DEFAULT_SHUTDOWN_GRACE_PERIOD_S: u64 = 30;
+
 pub type SchedulerFactoryResults = (
     Option<Arc<dyn ClientStateManager>>,
     Option<Arc<dyn WorkerScheduler>>,
@@ -46,26 +64,43 @@ pub type SchedulerFactoryResults = (
 pub fn scheduler_factory(
     spec: &SchedulerSpec,
     store_manager: &StoreManager,
+    maybe_health_registry_builder: Option<&mut HealthRegistryBuilder>,
+    maybe_shutdown_tx: Option<&broadcast::Sender<ShutdownGuard>>,
 ) -> Result<SchedulerFactoryResults, Error> {
-    inner_scheduler_factory(spec, store_manager)
+    inner_scheduler_factory(
+        spec,
+        store_manager,
+        maybe_health_registry_builder,
+        maybe_shutdown_tx,
+    )
 }
 
 fn inner_scheduler_factory(
     spec: &SchedulerSpec,
     store_manager: &StoreManager,
+    maybe_health_registry_builder: Option<&mut HealthRegistryBuilder>,
+    maybe_shutdown_tx: Option<&broadcast::Sender<ShutdownGuard>>,
 ) -> Result<SchedulerFactoryResults, Error> {
     let scheduler: SchedulerFactoryResults = match spec {
-        SchedulerSpec::simple(spec) => {
-            simple_scheduler_factory(spec, store_manager, SystemTime::now)?
-        }
+        SchedulerSpec::simple(spec) => simple_scheduler_factory(
+            spec,
+            store_manager,
+            SystemTime::now,
+            maybe_health_registry_builder,
+            maybe_shutdown_tx,
+        )?,
         SchedulerSpec::grpc(spec) => (Some(Arc::new(GrpcScheduler::new(spec)?)), None),
         SchedulerSpec::cache_lookup(spec) => {
             let ac_store = store_manager
                 .get_store(&spec.ac_store)
                 .err_tip(|| format!("'ac_store': '{}' does not exist", spec.ac_store))?;
-            let (action_scheduler, worker_scheduler) =
-                inner_scheduler_factory(&spec.scheduler, store_manager)
-                    .err_tip(|| "In nested CacheLookupScheduler construction")?;
+            let (action_scheduler, worker_scheduler) = inner_scheduler_factory(
+                &spec.scheduler,
+                store_manager,
+                maybe_health_registry_builder,
+                maybe_shutdown_tx,
+            )
+            .err_tip(|| "In nested CacheLookupScheduler construction")?;
             let cache_lookup_scheduler = Arc::new(CacheLookupScheduler::new(
                 ac_store,
                 action_scheduler.err_tip(|| "Nested scheduler is not an action scheduler")?,
@@ -73,9 +108,13 @@ fn inner_scheduler_factory(
             (Some(cache_lookup_scheduler), worker_scheduler)
         }
         SchedulerSpec::property_modifier(spec) => {
-            let (action_scheduler, worker_scheduler) =
-                inner_scheduler_factory(&spec.scheduler, store_manager)
-                    .err_tip(|| "In nested PropertyModifierScheduler construction")?;
+            let (action_scheduler, worker_scheduler) = inner_scheduler_factory(
+                &spec.scheduler,
+                store_manager,
+                maybe_health_registry_builder,
+                maybe_shutdown_tx,
+            )
+            .err_tip(|| "In nested PropertyModifierScheduler construction")?;
             let property_modifier_scheduler = Arc::new(PropertyModifierScheduler::new(
                 spec,
                 action_scheduler.err_tip(|| "Nested scheduler is not an action scheduler")?,
@@ -91,22 +130,77 @@ fn simple_scheduler_factory(
     spec: &SimpleSpec,
     store_manager: &StoreManager,
     now_fn: fn() -> SystemTime,
+    maybe_health_registry_builder: Option<&mut HealthRegistryBuilder>,
+    maybe_shutdown_tx: Option<&broadcast::Sender<ShutdownGuard>>,
 ) -> Result<SchedulerFactoryResults, Error> {
-    match spec
+    let backend = spec
         .experimental_backend
         .as_ref()
-        .unwrap_or(&ExperimentalSimpleSchedulerBackend::memory)
-    {
+        .unwrap_or(&ExperimentalSimpleSchedulerBackend::memory);
+    if let Some(partition) = &spec.experimental_partition {
+        if !matches!(backend, ExperimentalSimpleSchedulerBackend::redis(_)) {
+            return Err(make_input_err!(
+                "'experimental_partition' requires the redis backend"
+            ));
+        }
+        if partition.partition_index >= partition.partition_count {
+            return Err(make_input_err!(
+                "'partition_index' ({}) must be below 'partition_count' ({})",
+                partition.partition_index,
+                partition.partition_count
+            ));
+        }
+    }
+    let maybe_execution_history = match &spec.experimental_execution_history {
+        Some(execution_history_spec) => {
+            let store = store_manager
+                .get_store(&execution_history_spec.store)
+                .err_tip(|| {
+                    format!("'store': '{}' does not exist", execution_history_spec.store)
+                })?;
+            Some(Arc::new(ExecutionHistory::new(
+                execution_history_spec,
+                store,
+            )))
+        }
+        None => None,
+    };
+    let (action_scheduler, worker_scheduler) = match backend {
         ExperimentalSimpleSchedulerBackend::memory => {
             let task_change_notify = Arc::new(Notify::new());
-            let awaited_action_db = memory_awaited_action_db_factory(
+            let mut awaited_action_db = memory_awaited_action_db_factory(
                 spec.retain_completed_for_s,
                 &task_change_notify.clone(),
                 SystemTime::now,
-            );
-            let (action_scheduler, worker_scheduler) =
-                SimpleScheduler::new(spec, awaited_action_db, task_change_notify);
-            Ok((Some(action_scheduler), Some(worker_scheduler)))
+            )
+            .with_completed_action_dedup_window(Duration::from_secs(
+                spec.completed_action_dedup_window_s,
+            ));
+            if let Some(persistence) = &spec.experimental_persistence {
+                let store = store_manager
+                    .get_store(&persistence.store)
+                    .err_tip(|| format!("'store': '{}' does not exist", persistence.store))?;
+                let key = if persistence.key.is_empty() {
+                    DEFAULT_PERSISTENCE_KEY.to_string()
+                } else {
+                    persistence.key.clone()
+                };
+                let mut snapshot_interval_s = persistence.snapshot_interval_s;
+                if snapshot_interval_s == 0 {
+                    snapshot_interval_s = DEFAULT_PERSISTENCE_SNAPSHOT_INTERVAL_S;
+                }
+                awaited_action_db = awaited_action_db.with_persistence(
+                    store,
+                    key,
+                    Duration::from_secs(snapshot_interval_s),
+                );
+            }
+            SimpleScheduler::new_with_execution_history(
+                spec,
+                awaited_action_db,
+                task_change_notify,
+                maybe_execution_history,
+            )
         }
         ExperimentalSimpleSchedulerBackend::redis(redis_config) => {
             let store = store_manager
@@ -134,11 +228,45 @@ fn simple_scheduler_factory(
                 Default::default,
             )
             .err_tip(|| "In state_manager_factory::redis_state_manager")?;
-            let (action_scheduler, worker_scheduler) =
-                SimpleScheduler::new(spec, awaited_action_db, task_change_notify);
-            Ok((Some(action_scheduler), Some(worker_scheduler)))
+            SimpleScheduler::new_with_execution_history(
+                spec,
+                awaited_action_db,
+                task_change_notify,
+                maybe_execution_history,
+            )
         }
+    };
+    if let Some(health_registry_builder) = maybe_health_registry_builder {
+        health_registry_builder.register_indicator(action_scheduler.clone());
+    }
+    if let (Some(graceful_shutdown), Some(shutdown_tx)) =
+        (&spec.experimental_graceful_shutdown, maybe_shutdown_tx)
+    {
+        let grace_period = Duration::from_secs(if graceful_shutdown.grace_period_s == 0 {
+            DEFAULT_SHUTDOWN_GRACE_PERIOD_S
+        } else {
+            graceful_shutdown.grace_period_s
+        });
+        let mut shutdown_rx = shutdown_tx.subscribe();
+        let weak_scheduler = Arc::downgrade(&action_scheduler);
+        background_spawn!("simple_scheduler_graceful_shutdown", async move {
+            // The process waits for the shutdown until the guard is dropped.
+            let Ok(shutdown_guard) = shutdown_rx.recv().await else {
+                return;
+            };
+            if let Some(scheduler) = weak_scheduler.upgrade() {
+                if let Err(err) = scheduler.shutdown(grace_period).await {
+                    event!(
+                        Level::ERROR,
+                        ?err,
+                        "Failed to shut down scheduler gracefully"
+                    );
+                }
+            }
+            drop(shutdown_guard);
+        });
     }
+    Ok((Some(action_scheduler), Some(worker_scheduler)))
 }
 
 pub fn memory_awaited_action_db_factory<I, NowFn>(
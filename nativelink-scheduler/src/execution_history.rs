@@ -0,0 +1,195 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::num::NonZeroUsize;
+use std::time::{Duration, SystemTime};
+
+use bytes::Bytes;
+use lru::LruCache;
+use nativelink_config::schedulers::ExecutionHistorySpec;
+use nativelink_error::{make_err, Code, Error, ResultExt};
+use nativelink_metric::MetricsComponent;
+use nativelink_util::action_messages::ActionResult;
+use nativelink_util::common::DigestInfo;
+use nativelink_util::metrics_utils::Counter;
+use nativelink_util::store_trait::{Store, StoreLike};
+use serde::{Deserialize, Serialize};
+
+/// Default value for `ExecutionHistorySpec::key_prefix`.
+/// If this changes, remember to change the documentation in the config.
+const DEFAULT_KEY_PREFIX: &str = "nativelink_execution_history:";
+
+/// Default value for `ExecutionHistorySpec::long_action_threshold_s`.
+/// If this changes, remember to change the documentation in the config.
+const DEFAULT_LONG_ACTION_THRESHOLD_S: u64 = 60;
+
+/// Number of commands whose history is kept in memory.
+const MAX_CACHED_COMMANDS: usize = 10_000;
+
+/// Once a command executed this many times, every new execution moves the
+/// averages by the same share, so they follow recent executions.
+const MAX_EXECUTIONS_WEIGHT: u64 = 9;
+
+/// What earlier executions of a command tell about the next one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExecutionStats {
+    /// Number of executions the averages are based on.
+    pub executions: u64,
+    /// Average time the command executed, in milliseconds.
+    pub mean_execution_ms: u64,
+    /// Average size of the outputs of the command, in bytes.
+    pub mean_output_bytes: u64,
+}
+
+impl ExecutionStats {
+    pub const fn mean_execution_duration(&self) -> Duration {
+        Duration::from_millis(self.mean_execution_ms)
+    }
+
+    fn record(&mut self, execution_duration: Duration, output_bytes: u64) {
+        let weight = self.executions.min(MAX_EXECUTIONS_WEIGHT);
+        let execution_ms = u64::try_from(execution_duration.as_millis()).unwrap_or(u64::MAX);
+        self.mean_execution_ms = weighted_mean(self.mean_execution_ms, weight, execution_ms);
+        self.mean_output_bytes = weighted_mean(self.mean_output_bytes, weight, output_bytes);
+        self.executions += 1;
+    }
+}
+
+fn weighted_mean(mean: u64, weight: u64, sample: u64) -> u64 {
+    let total = u128::from(mean) * u128::from(weight) + u128::from(sample);
+    u64::try_from(total / u128::from(weight + 1)).unwrap_or(u64::MAX)
+}
+
+/// Keeps how long the actions of every command executed and how large
+/// their outputs were in a store, so the scheduler can tell long actions
+/// apart before they run. The action mnemonic is not known to the
+/// scheduler, so the history is kept by command digest.
+#[derive(MetricsComponent)]
+pub struct ExecutionHistory {
+    store: Store,
+
+    #[metric(help = "The prefix of the keys the history is written under.")]
+    key_prefix: String,
+
+    #[metric(help = "Actions expected to execute at least this long are spread over the workers.")]
+    long_action_threshold: Duration,
+
+    /// The history of recently used commands. Commands without history
+    /// are not cached, as other schedulers sharing the store may record
+    /// them at any time.
+    cache: parking_lot::Mutex<LruCache<DigestInfo, ExecutionStats>>,
+
+    #[metric(help = "Number of executions recorded in the history.")]
+    recorded_executions: Counter,
+}
+
+impl ExecutionHistory {
+    pub fn new(spec: &ExecutionHistorySpec, store: Store) -> Self {
+        let key_prefix = if spec.key_prefix.is_empty() {
+            DEFAULT_KEY_PREFIX.to_string()
+        } else {
+            spec.key_prefix.clone()
+        };
+        let long_action_threshold = Duration::from_secs(if spec.long_action_threshold_s == 0 {
+            DEFAULT_LONG_ACTION_THRESHOLD_S
+        } else {
+            spec.long_action_threshold_s
+        });
+        Self {
+            store,
+            key_prefix,
+            long_action_threshold,
+            cache: parking_lot::Mutex::new(LruCache::new(
+                NonZeroUsize::new(MAX_CACHED_COMMANDS).unwrap(),
+            )),
+            recorded_executions: Counter::default(),
+        }
+    }
+
+    pub const fn long_action_threshold(&self) -> Duration {
+        self.long_action_threshold
+    }
+
+    /// Returns the history of the command, if it executed before.
+    pub async fn get(&self, command_digest: &DigestInfo) -> Result<Option<ExecutionStats>, Error> {
+        if let Some(stats) = self.cache.lock().get(command_digest) {
+            return Ok(Some(*stats));
+        }
+        let stats: ExecutionStats = match self
+            .store
+            .get_part_unchunked(self.key(command_digest).as_str(), 0, None)
+            .await
+        {
+            Ok(data) => serde_json::from_slice(&data)
+                .map_err(|e| make_err!(Code::InvalidArgument, "{e}"))
+                .err_tip(|| "While decoding history in ExecutionHistory::get")?,
+            Err(err) if err.code == Code::NotFound => return Ok(None),
+            Err(err) => return Err(err).err_tip(|| "In ExecutionHistory::get"),
+        };
+        self.cache.lock().put(*command_digest, stats);
+        Ok(Some(stats))
+    }
+
+    /// Adds a successful execution of the command to its history. Actions
+    /// without execution timestamps are not recorded.
+    pub async fn record(
+        &self,
+        command_digest: &DigestInfo,
+        action_result: &ActionResult,
+    ) -> Result<(), Error> {
+        let metadata = &action_result.execution_metadata;
+        if metadata.execution_start_timestamp == SystemTime::UNIX_EPOCH {
+            return Ok(());
+        }
+        let Ok(execution_duration) = metadata
+            .execution_completed_timestamp
+            .duration_since(metadata.execution_start_timestamp)
+        else {
+            return Ok(());
+        };
+        let output_bytes = action_result
+            .output_files
+            .iter()
+            .map(|file_info| file_info.digest.size_bytes())
+            .chain([
+                action_result.stdout_digest.size_bytes(),
+                action_result.stderr_digest.size_bytes(),
+            ])
+            .sum();
+
+        // Schedulers sharing the store may record the same command at the
+        // same time, which loses one of the executions. That is fine for an
+        // average.
+        let mut stats = self
+            .get(command_digest)
+            .await
+            .err_tip(|| "In ExecutionHistory::record")?
+            .unwrap_or_default();
+        stats.record(execution_duration, output_bytes);
+        let data = serde_json::to_vec(&stats)
+            .map_err(|e| make_err!(Code::Internal, "{e}"))
+            .err_tip(|| "While encoding history in ExecutionHistory::record")?;
+        self.store
+            .update_oneshot(self.key(command_digest).as_str(), Bytes::from(data))
+            .await
+            .err_tip(|| "In ExecutionHistory::record")?;
+        self.cache.lock().put(*command_digest, stats);
+        self.recorded_executions.inc();
+        Ok(())
+    }
+
+    fn key(&self, command_digest: &DigestInfo) -> String {
+        format!("{}{command_digest}", self.key_prefix)
+    }
+}
@@ -0,0 +1,154 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use nativelink_config::schedulers::FairShareSpec;
+use nativelink_metric::MetricsComponent;
+use nativelink_util::action_messages::ActionInfo;
+use parking_lot::Mutex;
+
+/// Default value for `FairShareSpec::usage_half_life_s`.
+/// If this changes, remember to change the documentation in the config.
+const DEFAULT_USAGE_HALF_LIFE_S: u64 = 300;
+
+/// Usage below this is forgotten, so identities that stopped sending
+/// actions do not stay around forever.
+const MIN_TRACKED_USAGE: f64 = 0.01;
+
+/// Usage of an identity as of `timestamp`.
+struct Usage {
+    value: f64,
+    timestamp: SystemTime,
+}
+
+/// Shares the workers between the client identities that send actions.
+///
+/// Every action handed to a worker adds one to the usage of its identity,
+/// which then halves every `usage_half_life_s`. Queued actions of the same
+/// priority are ordered so the identity with the lowest usage relative to
+/// its shares goes next.
+#[derive(MetricsComponent)]
+pub struct FairSharePolicy {
+    #[metric(help = "Seconds after which the usage of an identity counts half as much.")]
+    usage_half_life_s: u64,
+
+    /// The shares of the identities that do not have the default of 1.
+    identity_shares: HashMap<String, u32>,
+
+    /// Recent usage of the identities, keyed by identity.
+    usage: Mutex<HashMap<String, Usage>>,
+}
+
+impl FairSharePolicy {
+    pub fn new(spec: &FairShareSpec) -> Self {
+        let usage_half_life_s = if spec.usage_half_life_s == 0 {
+            DEFAULT_USAGE_HALF_LIFE_S
+        } else {
+            spec.usage_half_life_s
+        };
+        Self {
+            usage_half_life_s,
+            identity_shares: spec.identity_shares.clone(),
+            usage: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records that an action was handed to a worker.
+    pub fn record_assigned(&self, action_info: &ActionInfo, now: SystemTime) {
+        let mut usage = self.usage.lock();
+        usage.retain(|_, identity_usage| self.usage_at(identity_usage, now) >= MIN_TRACKED_USAGE);
+        let identity_usage = usage
+            .entry(identity_of(action_info).to_string())
+            .or_insert(Usage {
+                value: 0.0,
+                timestamp: now,
+            });
+        identity_usage.value = self.usage_at(identity_usage, now) + 1.0;
+        identity_usage.timestamp = now;
+    }
+
+    /// Reorders `queued_actions`, which are sorted by descending priority,
+    /// so actions of the same priority alternate between identities. The
+    /// order of the actions of a single identity is kept.
+    pub fn order_queued_actions<T>(
+        &self,
+        queued_actions: Vec<(Arc<ActionInfo>, T)>,
+        now: SystemTime,
+    ) -> Vec<T> {
+        let usage = self.usage.lock();
+        let mut ordered_actions = Vec::with_capacity(queued_actions.len());
+        let mut queued_actions = queued_actions.into_iter().peekable();
+        while let Some((action_info, item)) = queued_actions.next() {
+            // The identities of the actions with this priority in the order
+            // they first appear, with their actions and weighted usage.
+            let mut identity_queues: Vec<(f64, f64, VecDeque<T>)> = Vec::new();
+            let mut identity_indexes: HashMap<String, usize> = HashMap::new();
+            let priority = action_info.sort_priority();
+            let mut next_action = Some((action_info, item));
+            while let Some((action_info, item)) = next_action.take() {
+                let identity = identity_of(&action_info);
+                let index = if let Some(index) = identity_indexes.get(identity) {
+                    *index
+                } else {
+                    let shares = f64::from(self.shares_of(identity));
+                    let identity_usage = usage
+                        .get(identity)
+                        .map_or(0.0, |identity_usage| self.usage_at(identity_usage, now));
+                    identity_queues.push((identity_usage / shares, shares, VecDeque::new()));
+                    identity_indexes.insert(identity.to_string(), identity_queues.len() - 1);
+                    identity_queues.len() - 1
+                };
+                identity_queues[index].2.push_back(item);
+                next_action = queued_actions
+                    .next_if(|(next_action_info, _)| next_action_info.sort_priority() == priority);
+            }
+            // Hand out the actions one at a time to the identity with the
+            // lowest usage, as if each of them was handed to a worker.
+            while let Some((weighted_usage, shares, actions)) = identity_queues
+                .iter_mut()
+                .filter(|(_, _, actions)| !actions.is_empty())
+                .min_by(|(a, _, _), (b, _, _)| a.total_cmp(b))
+            {
+                if let Some(item) = actions.pop_front() {
+                    ordered_actions.push(item);
+                }
+                *weighted_usage += 1.0 / *shares;
+            }
+        }
+        ordered_actions
+    }
+
+    fn shares_of(&self, identity: &str) -> u32 {
+        self.identity_shares
+            .get(identity)
+            .copied()
+            .unwrap_or(1)
+            .max(1)
+    }
+
+    /// Returns the decayed usage as of `now`.
+    fn usage_at(&self, usage: &Usage, now: SystemTime) -> f64 {
+        let elapsed_s = now
+            .duration_since(usage.timestamp)
+            .map_or(0.0, |elapsed| elapsed.as_secs_f64());
+        usage.value * 0.5f64.powf(elapsed_s / self.usage_half_life_s as f64)
+    }
+}
+
+fn identity_of(action_info: &ActionInfo) -> &str {
+    action_info.client_identity.as_deref().unwrap_or_default()
+}
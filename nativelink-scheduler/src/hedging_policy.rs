@@ -0,0 +1,96 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::VecDeque;
+
+use nativelink_config::schedulers::HedgingSpec;
+use nativelink_metric::MetricsComponent;
+use nativelink_util::metrics_utils::Counter;
+
+/// Default value for `HedgingSpec::percentile`.
+/// If this changes, remember to change the documentation in the config.
+const DEFAULT_PERCENTILE: u32 = 95;
+
+/// Default value for `HedgingSpec::min_samples`.
+/// If this changes, remember to change the documentation in the config.
+const DEFAULT_MIN_SAMPLES: usize = 100;
+
+/// Number of durations of finished actions the threshold is computed from.
+const MAX_TRACKED_DURATIONS: usize = 1000;
+
+/// Decides when a running action gets a second attempt on another worker.
+///
+/// The durations of recently finished actions are tracked, and an action is
+/// hedged once it ran longer than the configured percentile of them. The
+/// action mnemonic is not known to the scheduler, so all actions share the
+/// same history.
+#[derive(MetricsComponent)]
+pub struct HedgingPolicy {
+    #[metric(help = "Percentile of recent action durations after which an action is hedged.")]
+    percentile: u32,
+
+    #[metric(help = "Number of finished actions needed before any action is hedged.")]
+    min_samples: usize,
+
+    /// Durations in seconds of recently finished actions, oldest first.
+    durations_s: VecDeque<u64>,
+
+    #[metric(help = "Number of second attempts started for slow actions.")]
+    hedged_actions: Counter,
+}
+
+impl HedgingPolicy {
+    pub fn new(spec: &HedgingSpec) -> Self {
+        let percentile = if spec.percentile == 0 {
+            DEFAULT_PERCENTILE
+        } else {
+            spec.percentile.min(100)
+        };
+        let min_samples = if spec.min_samples == 0 {
+            DEFAULT_MIN_SAMPLES
+        } else {
+            spec.min_samples.min(MAX_TRACKED_DURATIONS)
+        };
+        Self {
+            percentile,
+            min_samples,
+            durations_s: VecDeque::with_capacity(MAX_TRACKED_DURATIONS),
+            hedged_actions: Counter::default(),
+        }
+    }
+
+    /// Records how long a finished action ran.
+    pub fn record_duration(&mut self, duration_s: u64) {
+        if self.durations_s.len() == MAX_TRACKED_DURATIONS {
+            self.durations_s.pop_front();
+        }
+        self.durations_s.push_back(duration_s);
+    }
+
+    /// Records that a second attempt of an action was started.
+    pub fn record_hedged(&self) {
+        self.hedged_actions.inc();
+    }
+
+    /// Returns how long in seconds an action may run before it is hedged,
+    /// or None if too few actions finished to tell.
+    pub fn threshold_s(&self) -> Option<u64> {
+        if self.durations_s.len() < self.min_samples {
+            return None;
+        }
+        let mut durations_s: Vec<u64> = self.durations_s.iter().copied().collect();
+        let index = (durations_s.len() - 1) * self.percentile as usize / 100;
+        Some(*durations_s.select_nth_unstable(index).1)
+    }
+}
@@ -16,12 +16,21 @@ pub mod api_worker_scheduler;
 pub mod awaited_action_db;
 pub mod cache_lookup_scheduler;
 pub mod default_scheduler_factory;
+pub mod execution_history;
+pub mod fair_share_policy;
 pub mod grpc_scheduler;
+pub mod hedging_policy;
 pub mod memory_awaited_action_db;
 pub mod platform_property_manager;
+pub mod priority_policy;
 pub mod property_modifier_scheduler;
+pub mod queue_limits;
+pub mod retry_policy;
+pub mod scheduler_partition;
+pub mod scheduler_simulation;
 pub mod simple_scheduler;
 mod simple_scheduler_state_manager;
 pub mod store_awaited_action_db;
 pub mod worker;
+pub mod worker_affinity;
 pub mod worker_scheduler;
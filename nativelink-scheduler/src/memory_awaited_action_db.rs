@@ -14,22 +14,26 @@
 
 use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::ops::{Bound, RangeBounds};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
 use async_lock::Mutex;
+use bytes::Bytes;
 use futures::{FutureExt, Stream};
 use nativelink_config::stores::EvictionPolicy;
 use nativelink_error::{error_if, make_err, Code, Error, ResultExt};
 use nativelink_metric::MetricsComponent;
 use nativelink_util::action_messages::{
-    ActionInfo, ActionStage, ActionUniqueKey, ActionUniqueQualifier, OperationId,
+    ActionInfo, ActionStage, ActionState, ActionUniqueKey, ActionUniqueQualifier, OperationId,
 };
 use nativelink_util::chunked_stream::ChunkedStream;
 use nativelink_util::evicting_map::{EvictingMap, LenEntry};
 use nativelink_util::instant_wrapper::InstantWrapper;
 use nativelink_util::spawn;
+use nativelink_util::store_trait::{Store, StoreLike};
 use nativelink_util::task::JoinHandleDropGuard;
+use serde::{Deserialize, Serialize};
 use tokio::sync::{mpsc, watch, Notify};
 use tracing::{event, Level};
 
@@ -44,6 +48,24 @@ const MAX_ACTION_EVENTS_RX_PER_CYCLE: usize = 1024;
 /// Duration to wait before sending client keep alive messages.
 const CLIENT_KEEPALIVE_DURATION: Duration = Duration::from_secs(10);
 
+/// An unfinished action as it is written to the persistence store.
+#[derive(Serialize, Deserialize)]
+struct PersistedAwaitedAction {
+    awaited_action: AwaitedAction,
+    /// The client operation ids that refer to the action.
+    client_operation_ids: Vec<OperationId>,
+}
+
+/// Where the unfinished actions are written to survive a restart.
+struct AwaitedActionPersistence {
+    store: Store,
+    key: String,
+    /// Set once the actions written before the restart were restored.
+    /// Nothing is written until then, so a store that is temporarily
+    /// unavailable does not lose them.
+    restored: AtomicBool,
+}
+
 /// Represents a client that is currently listening to an action.
 /// When the client is dropped, it will send the `AwaitedAction` to the
 /// `event_tx` if there are other cleanups needed.
@@ -310,6 +332,10 @@ pub struct AwaitedActionDbImpl<I: InstantWrapper, NowFn: Fn() -> I> {
     #[metric(group = "action_info_hash_key_to_awaited_action")]
     action_info_hash_key_to_awaited_action: HashMap<ActionUniqueKey, OperationId>,
 
+    /// Cachable actions that finished successfully, with the time they
+    /// finished. Entries are removed once the action is cleared.
+    recently_completed_actions: HashMap<ActionUniqueKey, (OperationId, I)>,
+
     /// A sorted set of [`AwaitedAction`]s. A wrapper is used to perform sorting
     /// based on the [`AwaitedActionSortKey`] of the [`AwaitedAction`].
     ///
@@ -411,6 +437,13 @@ impl<I: InstantWrapper, NowFn: Fn() -> I + Clone + Send + Sync> AwaitedActionDbI
                     // Cleanup action_info_hash_key_to_awaited_action if it was marked cached.
                     match &awaited_action.action_info().unique_qualifier {
                         ActionUniqueQualifier::Cachable(action_key) => {
+                            if self.recently_completed_actions.get(action_key).is_some_and(
+                                |(completed_operation_id, _)| {
+                                    completed_operation_id == &operation_id
+                                },
+                            ) {
+                                self.recently_completed_actions.remove(action_key);
+                            }
                             let maybe_awaited_action = self
                                 .action_info_hash_key_to_awaited_action
                                 .remove(action_key);
@@ -626,6 +659,20 @@ impl<I: InstantWrapper, NowFn: Fn() -> I + Clone + Send + Sync> AwaitedActionDbI
                     &mut self.action_info_hash_key_to_awaited_action,
                     &new_awaited_action,
                 );
+                if let (
+                    ActionStage::Completed(action_result),
+                    ActionUniqueQualifier::Cachable(action_key),
+                ) = (
+                    &new_awaited_action.state().stage,
+                    &new_awaited_action.action_info().unique_qualifier,
+                ) {
+                    if action_result.exit_code == 0 && action_result.error.is_none() {
+                        self.recently_completed_actions.insert(
+                            action_key.clone(),
+                            (new_awaited_action.operation_id().clone(), (self.now_fn)()),
+                        );
+                    }
+                }
             }
         }
 
@@ -664,6 +711,7 @@ impl<I: InstantWrapper, NowFn: Fn() -> I + Clone + Send + Sync> AwaitedActionDbI
         &mut self,
         client_operation_id: OperationId,
         action_info: Arc<ActionInfo>,
+        completed_action_dedup_window: Duration,
     ) -> Result<MemoryAwaitedActionSubscriber<I, NowFn>, Error> {
         // Check to see if the action is already known and subscribe if it is.
         let subscription_result = self
@@ -671,6 +719,7 @@ impl<I: InstantWrapper, NowFn: Fn() -> I + Clone + Send + Sync> AwaitedActionDbI
                 &client_operation_id,
                 &action_info.unique_qualifier,
                 action_info.priority,
+                completed_action_dedup_window,
             )
             .await
             .err_tip(|| "In AwaitedActionDb::subscribe_or_add_action");
@@ -741,6 +790,110 @@ impl<I: InstantWrapper, NowFn: Fn() -> I + Clone + Send + Sync> AwaitedActionDbI
         ))
     }
 
+    /// Returns all unfinished actions together with the client operation
+    /// ids that refer to them.
+    async fn persisted_awaited_actions(&self) -> Vec<PersistedAwaitedAction> {
+        let mut client_operation_ids: HashMap<OperationId, Vec<OperationId>> = HashMap::new();
+        self.client_operation_to_awaited_action
+            .range::<_, OperationId>(.., |client_operation_id, client_awaited_action| {
+                client_operation_ids
+                    .entry(client_awaited_action.operation_id().clone())
+                    .or_default()
+                    .push(client_operation_id.clone());
+                true
+            })
+            .await;
+        self.operation_id_to_awaited_action
+            .iter()
+            .filter_map(|(operation_id, tx)| {
+                let awaited_action = tx.borrow();
+                if awaited_action.state().stage.is_finished() {
+                    return None;
+                }
+                Some(PersistedAwaitedAction {
+                    awaited_action: awaited_action.clone(),
+                    client_operation_ids: client_operation_ids
+                        .remove(operation_id)
+                        .unwrap_or_default(),
+                })
+            })
+            .collect()
+    }
+
+    /// Adds an action that was persisted before a restart back to the queue.
+    /// Returns false if the action was not restored because nothing refers
+    /// to it anymore or it was added again since the restart.
+    async fn restore_action(&mut self, persisted: PersistedAwaitedAction) -> Result<bool, Error> {
+        let PersistedAwaitedAction {
+            mut awaited_action,
+            client_operation_ids,
+        } = persisted;
+        let operation_id = awaited_action.operation_id().clone();
+        if client_operation_ids.is_empty()
+            || self
+                .operation_id_to_awaited_action
+                .contains_key(&operation_id)
+        {
+            return Ok(false);
+        }
+        let maybe_unique_key = match &awaited_action.action_info().unique_qualifier {
+            ActionUniqueQualifier::Cachable(unique_key) => Some(unique_key.clone()),
+            ActionUniqueQualifier::Uncachable(_unique_key) => None,
+        };
+        if let Some(unique_key) = &maybe_unique_key {
+            if self
+                .action_info_hash_key_to_awaited_action
+                .contains_key(unique_key)
+            {
+                return Ok(false);
+            }
+        }
+
+        let now = (self.now_fn)().now();
+        if awaited_action.state().stage != ActionStage::Queued {
+            // Workers abort their actions when they lose the connection to
+            // the scheduler, so the action has to run again.
+            let mut state = ActionState::clone(awaited_action.state().as_ref());
+            state.stage = ActionStage::Queued;
+            awaited_action.set_worker_id(None, now);
+            awaited_action.worker_set_state(Arc::new(state), now);
+        }
+        // Give the clients time to reconnect before the action times out.
+        awaited_action.update_client_keep_alive(now);
+        let sort_key = awaited_action.sort_key();
+
+        let (tx, _rx) = watch::channel(awaited_action);
+        self.operation_id_to_awaited_action
+            .insert(operation_id.clone(), tx);
+        self.connected_clients_for_operation_id
+            .insert(operation_id.clone(), client_operation_ids.len());
+        for client_operation_id in client_operation_ids {
+            self.client_operation_to_awaited_action
+                .insert(
+                    client_operation_id,
+                    Arc::new(ClientAwaitedAction::new(
+                        operation_id.clone(),
+                        self.action_event_tx.clone(),
+                    )),
+                )
+                .await;
+        }
+        if let Some(unique_key) = maybe_unique_key {
+            self.action_info_hash_key_to_awaited_action
+                .insert(unique_key, operation_id.clone());
+        }
+        self.sorted_action_info_hash_keys
+            .insert_sort_map_for_stage(
+                &ActionStage::Queued,
+                &SortedAwaitedAction {
+                    sort_key,
+                    operation_id,
+                },
+            )
+            .err_tip(|| "In AwaitedActionDb::restore_action")?;
+        Ok(true)
+    }
+
     async fn try_subscribe(
         &mut self,
         client_operation_id: &OperationId,
@@ -749,14 +902,31 @@ impl<I: InstantWrapper, NowFn: Fn() -> I + Clone + Send + Sync> AwaitedActionDbI
         // removed the ability to upgrade priorities of actions.
         // we should add priority upgrades back in.
         _priority: i32,
+        completed_action_dedup_window: Duration,
     ) -> Result<Option<MemoryAwaitedActionSubscriber<I, NowFn>>, Error> {
         let unique_key = match unique_qualifier {
             ActionUniqueQualifier::Cachable(unique_key) => unique_key,
             ActionUniqueQualifier::Uncachable(_unique_key) => return Ok(None),
         };
 
-        let Some(operation_id) = self.action_info_hash_key_to_awaited_action.get(unique_key) else {
-            return Ok(None); // Not currently running.
+        let (operation_id, is_recently_completed) = if let Some(operation_id) =
+            self.action_info_hash_key_to_awaited_action.get(unique_key)
+        {
+            (operation_id, false)
+        } else {
+            // Identical actions that finished a moment ago reuse the result
+            // instead of running again.
+            let Some((operation_id, completed_timestamp)) =
+                self.recently_completed_actions.get(unique_key)
+            else {
+                return Ok(None); // Not currently running.
+            };
+            if completed_action_dedup_window.is_zero()
+                || completed_timestamp.elapsed() > completed_action_dedup_window
+            {
+                return Ok(None);
+            }
+            (operation_id, true)
         };
 
         let Some(tx) = self.operation_id_to_awaited_action.get(operation_id) else {
@@ -767,7 +937,7 @@ impl<I: InstantWrapper, NowFn: Fn() -> I + Clone + Send + Sync> AwaitedActionDbI
         };
 
         error_if!(
-            tx.borrow().state().stage.is_finished(),
+            !is_recently_completed && tx.borrow().state().stage.is_finished(),
             "Tried to subscribe to a completed action but it already finished. This should never happen. {:?}",
             tx.borrow()
         );
@@ -809,7 +979,12 @@ pub struct MemoryAwaitedActionDb<I: InstantWrapper, NowFn: Fn() -> I> {
     #[metric]
     inner: Arc<Mutex<AwaitedActionDbImpl<I, NowFn>>>,
     tasks_change_notify: Arc<Notify>,
+    /// How long identical actions reuse the result of an action that
+    /// finished successfully. Zero disables it.
+    completed_action_dedup_window: Duration,
     _handle_awaited_action_events: JoinHandleDropGuard<()>,
+    persistence: Option<Arc<AwaitedActionPersistence>>,
+    _persist_awaited_actions: Option<JoinHandleDropGuard<()>>,
 }
 
 impl<I: InstantWrapper, NowFn: Fn() -> I + Clone + Send + Sync + 'static>
@@ -825,6 +1000,7 @@ impl<I: InstantWrapper, NowFn: Fn() -> I + Clone + Send + Sync + 'static>
             client_operation_to_awaited_action: EvictingMap::new(eviction_config, (now_fn)()),
             operation_id_to_awaited_action: BTreeMap::new(),
             action_info_hash_key_to_awaited_action: HashMap::new(),
+            recently_completed_actions: HashMap::new(),
             sorted_action_info_hash_keys: SortedAwaitedActions::default(),
             connected_clients_for_operation_id: HashMap::new(),
             action_event_tx,
@@ -834,6 +1010,7 @@ impl<I: InstantWrapper, NowFn: Fn() -> I + Clone + Send + Sync + 'static>
         Self {
             inner,
             tasks_change_notify,
+            completed_action_dedup_window: Duration::ZERO,
             _handle_awaited_action_events: spawn!("handle_awaited_action_events", async move {
                 let mut dropped_operation_ids = Vec::with_capacity(MAX_ACTION_EVENTS_RX_PER_CYCLE);
                 loop {
@@ -850,8 +1027,149 @@ impl<I: InstantWrapper, NowFn: Fn() -> I + Clone + Send + Sync + 'static>
                         .await;
                 }
             }),
+            persistence: None,
+            _persist_awaited_actions: None,
+        }
+    }
+
+    /// Lets cachable actions that are added within `window` after an
+    /// identical action finished successfully reuse its result.
+    #[must_use]
+    pub const fn with_completed_action_dedup_window(mut self, window: Duration) -> Self {
+        self.completed_action_dedup_window = window;
+        self
+    }
+
+    /// Restores the actions written to `key` of `store` before a restart
+    /// and from then on writes all unfinished actions to it every
+    /// `snapshot_interval` if they changed.
+    #[must_use]
+    pub fn with_persistence(
+        mut self,
+        store: Store,
+        key: String,
+        snapshot_interval: Duration,
+    ) -> Self {
+        let weak_inner = Arc::downgrade(&self.inner);
+        let tasks_change_notify = self.tasks_change_notify.clone();
+        let persistence = Arc::new(AwaitedActionPersistence {
+            store,
+            key,
+            restored: AtomicBool::new(false),
+        });
+        self.persistence = Some(persistence.clone());
+        self._persist_awaited_actions = Some(spawn!("persist_awaited_actions", async move {
+            let AwaitedActionPersistence { store, key, .. } = persistence.as_ref();
+            let mut interval = tokio::time::interval(snapshot_interval);
+            loop {
+                interval.tick().await;
+                let Some(inner) = weak_inner.upgrade() else {
+                    return;
+                };
+                match restore_awaited_actions(&inner, store, key).await {
+                    Ok(restored_actions) => {
+                        event!(
+                            Level::INFO,
+                            restored_actions,
+                            ?key,
+                            "Restored scheduler state"
+                        );
+                        persistence.restored.store(true, Ordering::Release);
+                        tasks_change_notify.notify_one();
+                        break;
+                    }
+                    Err(err) => {
+                        event!(
+                            Level::ERROR,
+                            ?err,
+                            ?key,
+                            "Failed to restore scheduler state"
+                        );
+                    }
+                }
+            }
+            let mut last_snapshot = None;
+            loop {
+                interval.tick().await;
+                let Some(inner) = weak_inner.upgrade() else {
+                    return;
+                };
+                let snapshot = match snapshot_awaited_actions(&inner).await {
+                    Ok(snapshot) => snapshot,
+                    Err(err) => {
+                        event!(Level::ERROR, ?err, "Failed to serialize scheduler state");
+                        continue;
+                    }
+                };
+                drop(inner);
+                if last_snapshot.as_ref() == Some(&snapshot) {
+                    continue;
+                }
+                if let Err(err) = store.update_oneshot(key.as_str(), snapshot.clone()).await {
+                    event!(Level::ERROR, ?err, ?key, "Failed to write scheduler state");
+                    continue;
+                }
+                last_snapshot = Some(snapshot);
+            }
+        }));
+        self
+    }
+}
+
+/// Serializes all unfinished actions of the database.
+async fn snapshot_awaited_actions<I, NowFn>(
+    inner: &Mutex<AwaitedActionDbImpl<I, NowFn>>,
+) -> Result<Bytes, Error>
+where
+    I: InstantWrapper,
+    NowFn: Fn() -> I + Clone + Send + Sync,
+{
+    let persisted_awaited_actions = inner.lock().await.persisted_awaited_actions().await;
+    serde_json::to_vec(&persisted_awaited_actions)
+        .map(Bytes::from)
+        .map_err(|e| make_err!(Code::Internal, "{e}"))
+        .err_tip(|| "While encoding scheduler state in snapshot_awaited_actions")
+}
+
+/// Reads the actions written to `key` of `store` and adds them to the
+/// database. Returns the number of restored actions. A snapshot that can
+/// not be decoded is logged and skipped, it is overwritten by the next
+/// snapshot.
+async fn restore_awaited_actions<I, NowFn>(
+    inner: &Mutex<AwaitedActionDbImpl<I, NowFn>>,
+    store: &Store,
+    key: &str,
+) -> Result<usize, Error>
+where
+    I: InstantWrapper,
+    NowFn: Fn() -> I + Clone + Send + Sync,
+{
+    let snapshot = match store.get_part_unchunked(key, 0, None).await {
+        Ok(snapshot) => snapshot,
+        Err(err) if err.code == Code::NotFound => return Ok(0),
+        Err(err) => return Err(err).err_tip(|| "In restore_awaited_actions"),
+    };
+    let persisted_awaited_actions: Vec<PersistedAwaitedAction> =
+        match serde_json::from_slice(&snapshot) {
+            Ok(persisted_awaited_actions) => persisted_awaited_actions,
+            Err(err) => {
+                event!(
+                    Level::ERROR,
+                    ?err,
+                    ?key,
+                    "Failed to decode scheduler state, starting without it"
+                );
+                return Ok(0);
+            }
+        };
+    let mut inner = inner.lock().await;
+    let mut restored_actions = 0;
+    for persisted in persisted_awaited_actions {
+        if inner.restore_action(persisted).await? {
+            restored_actions += 1;
         }
     }
+    Ok(restored_actions)
 }
 
 impl<I: InstantWrapper, NowFn: Fn() -> I + Clone + Send + Sync + 'static> AwaitedActionDb
@@ -965,9 +1283,30 @@ impl<I: InstantWrapper, NowFn: Fn() -> I + Clone + Send + Sync + 'static> Awaite
             .inner
             .lock()
             .await
-            .add_action(client_operation_id, action_info)
+            .add_action(
+                client_operation_id,
+                action_info,
+                self.completed_action_dedup_window,
+            )
             .await?;
         self.tasks_change_notify.notify_one();
         Ok(subscriber)
     }
+    async fn flush(&self) -> Result<(), Error> {
+        let Some(persistence) = &self.persistence else {
+            return Ok(());
+        };
+        if !persistence.restored.load(Ordering::Acquire) {
+            return Err(make_err!(
+                Code::FailedPrecondition,
+                "Scheduler state was not restored yet, not overwriting it"
+            ));
+        }
+        let snapshot = snapshot_awaited_actions(&self.inner).await?;
+        persistence
+            .store
+            .update_oneshot(persistence.key.as_str(), snapshot)
+            .await
+            .err_tip(|| "In MemoryAwaitedActionDb::flush")
+    }
 }
@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 
 use nativelink_config::schedulers::PropertyType;
 use nativelink_error::{make_input_err, Code, Error, ResultExt};
@@ -86,6 +86,10 @@ impl PlatformPropertyManager {
                     })?,
                 )),
                 PropertyType::exact => Ok(PlatformPropertyValue::Exact(value.to_string())),
+                PropertyType::set => Ok(PlatformPropertyValue::Set(BTreeSet::from([
+                    value.to_string()
+                ]))),
+                PropertyType::wildcard => Ok(PlatformPropertyValue::Wildcard(value.to_string())),
                 PropertyType::priority => Ok(PlatformPropertyValue::Priority(value.to_string())),
             };
         }
@@ -0,0 +1,156 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use nativelink_config::schedulers::PriorityBand;
+use nativelink_metric::MetricsComponent;
+use nativelink_util::metrics_utils::Counter;
+
+/// Name of the band holding all priorities when no bands are configured.
+const DEFAULT_BAND_NAME: &str = "default";
+
+/// Aging is measured from this point in time (2024-01-01) rather than the
+/// UNIX epoch, so the aged priorities of actions stay well within `i32`.
+const AGING_EPOCH_S: i64 = 1_704_067_200;
+
+/// Queue metrics of the actions with a priority in a single band.
+#[derive(Default, MetricsComponent)]
+pub struct PriorityBandMetrics {
+    #[metric(help = "Number of actions that were added to the queue.")]
+    queued_actions: Counter,
+    #[metric(help = "Number of queued actions that were assigned to a worker.")]
+    assigned_actions: Counter,
+}
+
+/// Decides the order in which queued actions are handed to workers.
+///
+/// The order is expressed as a queue priority that is stored next to the
+/// priority of an action before it is queued, so every `AwaitedActionDb`
+/// sorts by it without knowing about bands or aging. Aging raises the level of an
+/// action by one every `aging_interval_s` it waits. Ordering by
+/// `level * aging_interval_s - queued_time` gives the same order at any
+/// point in time, so the queue priority never needs to be updated.
+#[derive(MetricsComponent)]
+pub struct PriorityPolicy {
+    /// The configured bands, ordered by ascending `min_priority`.
+    bands: Vec<PriorityBand>,
+
+    #[metric(help = "Seconds an action waits before it moves up one band.")]
+    aging_interval_s: u64,
+
+    #[metric(group = "bands")]
+    band_metrics: HashMap<String, PriorityBandMetrics>,
+}
+
+impl PriorityPolicy {
+    pub fn new(bands: &[PriorityBand], aging_interval_s: u64) -> Self {
+        let mut bands = bands.to_vec();
+        bands.sort_by_key(|band| band.min_priority);
+        let band_metrics = if bands.is_empty() {
+            HashMap::from([(
+                DEFAULT_BAND_NAME.to_string(),
+                PriorityBandMetrics::default(),
+            )])
+        } else {
+            bands
+                .iter()
+                .map(|band| (band.name.clone(), PriorityBandMetrics::default()))
+                .collect()
+        };
+        Self {
+            bands,
+            aging_interval_s,
+            band_metrics,
+        }
+    }
+
+    /// Returns the priority the action is queued with.
+    pub fn queue_priority(&self, priority: i32, queued_timestamp: SystemTime) -> i32 {
+        let level = if self.bands.is_empty() {
+            i64::from(priority)
+        } else {
+            self.band_index(priority) as i64
+        };
+        if self.aging_interval_s == 0 {
+            return clamp_to_i32(level);
+        }
+        let aging_interval_s = i64::try_from(self.aging_interval_s).unwrap_or(i64::MAX);
+        clamp_to_i32(
+            level
+                .saturating_mul(aging_interval_s)
+                .saturating_sub(seconds_since_aging_epoch(queued_timestamp)),
+        )
+    }
+
+    /// Records an action with the original `priority` that is queued.
+    pub fn record_queued(&self, priority: i32) {
+        if let Some(metrics) = self.band_metrics_for_priority(priority) {
+            metrics.queued_actions.inc();
+        }
+    }
+
+    /// Records a queued action that was assigned to a worker, given the
+    /// priority returned by `queue_priority()`.
+    pub fn record_assigned(&self, queue_priority: i32, queued_timestamp: SystemTime) {
+        let band_name = if self.bands.is_empty() {
+            DEFAULT_BAND_NAME
+        } else {
+            let level = if self.aging_interval_s == 0 {
+                i64::from(queue_priority)
+            } else {
+                let aging_interval_s = i64::try_from(self.aging_interval_s).unwrap_or(i64::MAX);
+                (i64::from(queue_priority) + seconds_since_aging_epoch(queued_timestamp))
+                    .div_euclid(aging_interval_s)
+            };
+            let index = usize::try_from(level)
+                .unwrap_or(0)
+                .min(self.bands.len() - 1);
+            &self.bands[index].name
+        };
+        if let Some(metrics) = self.band_metrics.get(band_name) {
+            metrics.assigned_actions.inc();
+        }
+    }
+
+    /// Index of the band `priority` belongs to. Requires bands.
+    fn band_index(&self, priority: i32) -> usize {
+        self.bands
+            .partition_point(|band| band.min_priority <= priority)
+            .saturating_sub(1)
+    }
+
+    fn band_metrics_for_priority(&self, priority: i32) -> Option<&PriorityBandMetrics> {
+        let band_name = if self.bands.is_empty() {
+            DEFAULT_BAND_NAME
+        } else {
+            &self.bands[self.band_index(priority)].name
+        };
+        self.band_metrics.get(band_name)
+    }
+}
+
+fn seconds_since_aging_epoch(timestamp: SystemTime) -> i64 {
+    let seconds = timestamp
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    i64::try_from(seconds)
+        .unwrap_or(i64::MAX)
+        .saturating_sub(AGING_EPOCH_S)
+}
+
+fn clamp_to_i32(value: i64) -> i32 {
+    i32::try_from(value).unwrap_or(if value < 0 { i32::MIN } else { i32::MAX })
+}
@@ -133,6 +133,14 @@ impl ClientStateManager for PropertyModifierScheduler {
     fn as_known_platform_property_provider(&self) -> Option<&dyn KnownPlatformPropertyProvider> {
         Some(self)
     }
+
+    async fn cancel_operation(&self, operation_id: &OperationId) -> Result<(), Error> {
+        self.scheduler.cancel_operation(operation_id).await
+    }
+
+    async fn requeue_operation(&self, operation_id: &OperationId) -> Result<(), Error> {
+        self.scheduler.requeue_operation(operation_id).await
+    }
 }
 
 impl RootMetricsComponent for PropertyModifierScheduler {}
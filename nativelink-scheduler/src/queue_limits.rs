@@ -0,0 +1,126 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use nativelink_config::schedulers::QueueLimitSpec;
+use nativelink_error::{make_err, Code, Error};
+use nativelink_metric::MetricsComponent;
+use nativelink_util::action_messages::ActionInfo;
+use nativelink_util::metrics_utils::Counter;
+
+/// Default value for `QueueLimitSpec::retry_after_s`.
+/// If this changes, remember to change the documentation in the config.
+const DEFAULT_RETRY_AFTER_S: u64 = 60;
+
+/// The queue limit of the actions that request a platform property value.
+struct QueueLimit {
+    platform_property: String,
+    value: String,
+    max_queued_actions: usize,
+    retry_after_s: u64,
+    /// Actions of the pool queued when the matching engine last ran, plus
+    /// the ones added since.
+    queued_actions: AtomicUsize,
+}
+
+/// Rejects new actions of the platform pools whose queue is full.
+///
+/// Counting the queued actions of a pool on every new action would be as
+/// slow as the queue is long, so the matching engine, which goes through
+/// the whole queue anyway, refreshes the counts every time it runs.
+#[derive(MetricsComponent)]
+pub struct QueueLimits {
+    /// Limits of the platform pools, first match wins.
+    limits: Vec<QueueLimit>,
+
+    #[metric(help = "Number of actions rejected because the queue of their pool was full.")]
+    rejected_actions: Counter,
+}
+
+impl QueueLimits {
+    pub fn new(specs: &[QueueLimitSpec]) -> Self {
+        Self {
+            limits: specs
+                .iter()
+                .map(|spec| QueueLimit {
+                    platform_property: spec.platform_property.clone(),
+                    value: spec.value.clone(),
+                    max_queued_actions: spec.max_queued_actions,
+                    retry_after_s: if spec.retry_after_s == 0 {
+                        DEFAULT_RETRY_AFTER_S
+                    } else {
+                        spec.retry_after_s
+                    },
+                    queued_actions: AtomicUsize::new(0),
+                })
+                .collect(),
+            rejected_actions: Counter::default(),
+        }
+    }
+
+    /// Counts `action_info` as queued, or returns a `ResourceExhausted`
+    /// error if the queue of its pool is full.
+    pub fn try_enqueue(&self, action_info: &ActionInfo) -> Result<(), Error> {
+        let Some(limit) = self.limit_of(action_info) else {
+            return Ok(());
+        };
+        let reserve_result = limit.queued_actions.fetch_update(
+            Ordering::AcqRel,
+            Ordering::Acquire,
+            |queued_actions| {
+                (queued_actions < limit.max_queued_actions).then_some(queued_actions + 1)
+            },
+        );
+        if let Err(queued_actions) = reserve_result {
+            self.rejected_actions.inc();
+            return Err(make_err!(
+                Code::ResourceExhausted,
+                "The queue of actions with {}={} is full with {queued_actions} actions, retry after {} seconds",
+                limit.platform_property,
+                limit.value,
+                limit.retry_after_s,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Replaces the counts with the actions still queued after the
+    /// matching engine ran.
+    pub fn set_queued_actions<'a>(&self, queued_actions: impl IntoIterator<Item = &'a ActionInfo>) {
+        let mut counts = vec![0; self.limits.len()];
+        for action_info in queued_actions {
+            if let Some(index) = self.limit_index(action_info) {
+                counts[index] += 1;
+            }
+        }
+        for (limit, count) in self.limits.iter().zip(counts) {
+            limit.queued_actions.store(count, Ordering::Release);
+        }
+    }
+
+    fn limit_of(&self, action_info: &ActionInfo) -> Option<&QueueLimit> {
+        self.limit_index(action_info)
+            .map(|index| &self.limits[index])
+    }
+
+    fn limit_index(&self, action_info: &ActionInfo) -> Option<usize> {
+        self.limits.iter().position(|limit| {
+            action_info
+                .platform_properties
+                .get(&limit.platform_property)
+                .is_some_and(|value| *value == limit.value)
+        })
+    }
+}
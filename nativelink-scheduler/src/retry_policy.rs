@@ -0,0 +1,190 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt;
+
+use nativelink_config::schedulers::RetryPolicySpec;
+use nativelink_error::{Code, Error};
+use nativelink_metric::MetricsComponent;
+use nativelink_util::action_messages::ActionInfo;
+use nativelink_util::metrics_utils::Counter;
+use serde::{Deserialize, Serialize};
+
+/// The kinds of failures a job is retried after.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryClass {
+    /// The worker running the job was evicted or stopped sending updates.
+    WorkerEviction,
+    /// The worker could not reach a service it depends on.
+    Infrastructure,
+    /// The worker reported any other error while running the job.
+    ActionError,
+}
+
+impl RetryClass {
+    /// Returns the class of an error a worker reported about a job.
+    pub const fn of_worker_error(err: &Error) -> Self {
+        match err.code {
+            Code::Unavailable | Code::DeadlineExceeded => Self::Infrastructure,
+            _ => Self::ActionError,
+        }
+    }
+}
+
+impl fmt::Display for RetryClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WorkerEviction => write!(f, "worker evictions"),
+            Self::Infrastructure => write!(f, "infrastructure errors"),
+            Self::ActionError => write!(f, "action errors"),
+        }
+    }
+}
+
+/// Number of failed attempts of a job by the kind of failure.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct FailedAttempts {
+    worker_eviction: usize,
+    infrastructure: usize,
+    action_error: usize,
+}
+
+impl FailedAttempts {
+    pub const fn get(&self, retry_class: RetryClass) -> usize {
+        match retry_class {
+            RetryClass::WorkerEviction => self.worker_eviction,
+            RetryClass::Infrastructure => self.infrastructure,
+            RetryClass::ActionError => self.action_error,
+        }
+    }
+
+    pub fn increment(&mut self, retry_class: RetryClass) {
+        match retry_class {
+            RetryClass::WorkerEviction => self.worker_eviction += 1,
+            RetryClass::Infrastructure => self.infrastructure += 1,
+            RetryClass::ActionError => self.action_error += 1,
+        }
+    }
+}
+
+/// Maximum number of retries by the kind of failure. None means only the
+/// overall limit of the job applies.
+#[derive(Debug, Clone, Copy, Default)]
+struct RetryLimits {
+    worker_eviction: Option<usize>,
+    infrastructure: Option<usize>,
+    action_error: Option<usize>,
+}
+
+impl RetryLimits {
+    const fn get(&self, retry_class: RetryClass) -> Option<usize> {
+        match retry_class {
+            RetryClass::WorkerEviction => self.worker_eviction,
+            RetryClass::Infrastructure => self.infrastructure,
+            RetryClass::ActionError => self.action_error,
+        }
+    }
+}
+
+/// A `RetryLimits` used for the jobs that request a platform property value.
+#[derive(Debug)]
+struct RetryLimitsOverride {
+    platform_property: String,
+    value: String,
+    limits: RetryLimits,
+}
+
+/// Decides how often a job is retried after each kind of failure and
+/// counts the retries.
+#[derive(MetricsComponent)]
+pub struct RetryPolicy {
+    limits: RetryLimits,
+
+    /// Limits for the jobs of platform pools, first match wins.
+    overrides: Vec<RetryLimitsOverride>,
+
+    #[metric(help = "Number of jobs retried after the worker running them was evicted.")]
+    worker_eviction_retries: Counter,
+
+    #[metric(help = "Number of jobs retried after the worker could not reach a service.")]
+    infrastructure_retries: Counter,
+
+    #[metric(help = "Number of jobs retried after the worker reported another error.")]
+    action_error_retries: Counter,
+}
+
+impl RetryPolicy {
+    pub fn new(maybe_spec: Option<&RetryPolicySpec>) -> Self {
+        let mut retry_policy = Self {
+            limits: RetryLimits::default(),
+            overrides: Vec::new(),
+            worker_eviction_retries: Counter::default(),
+            infrastructure_retries: Counter::default(),
+            action_error_retries: Counter::default(),
+        };
+        let Some(spec) = maybe_spec else {
+            return retry_policy;
+        };
+        let limits = RetryLimits {
+            worker_eviction: spec.max_worker_eviction_retries,
+            infrastructure: spec.max_infrastructure_retries,
+            action_error: spec.max_action_error_retries,
+        };
+        retry_policy.overrides = spec
+            .platform_overrides
+            .iter()
+            .map(|platform_override| RetryLimitsOverride {
+                platform_property: platform_override.platform_property.clone(),
+                value: platform_override.value.clone(),
+                limits: RetryLimits {
+                    worker_eviction: platform_override
+                        .max_worker_eviction_retries
+                        .or(limits.worker_eviction),
+                    infrastructure: platform_override
+                        .max_infrastructure_retries
+                        .or(limits.infrastructure),
+                    action_error: platform_override
+                        .max_action_error_retries
+                        .or(limits.action_error),
+                },
+            })
+            .collect();
+        retry_policy.limits = limits;
+        retry_policy
+    }
+
+    /// Returns how often the job may be retried after failures of
+    /// `retry_class`, or None if only the overall limit applies.
+    pub fn max_retries(&self, retry_class: RetryClass, action_info: &ActionInfo) -> Option<usize> {
+        self.overrides
+            .iter()
+            .find(|platform_override| {
+                action_info
+                    .platform_properties
+                    .get(&platform_override.platform_property)
+                    .is_some_and(|value| *value == platform_override.value)
+            })
+            .map_or(&self.limits, |platform_override| &platform_override.limits)
+            .get(retry_class)
+    }
+
+    /// Records that a job was queued again after a failure of `retry_class`.
+    pub fn record_retry(&self, retry_class: RetryClass) {
+        match retry_class {
+            RetryClass::WorkerEviction => self.worker_eviction_retries.inc(),
+            RetryClass::Infrastructure => self.infrastructure_retries.inc(),
+            RetryClass::ActionError => self.action_error_retries.inc(),
+        }
+    }
+}
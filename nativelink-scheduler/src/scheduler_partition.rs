@@ -0,0 +1,93 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::{Duration, SystemTime};
+
+use nativelink_config::schedulers::SchedulerPartitionSpec;
+use nativelink_metric::MetricsComponent;
+use nativelink_util::action_messages::ActionInfo;
+use nativelink_util::metrics_utils::Counter;
+
+/// Default time before actions of other partitions are taken over.
+/// If this changes, remember to change the documentation in the config.
+const DEFAULT_TAKEOVER_AFTER_S: u64 = 30;
+
+/// The share of the queued actions a scheduler matches when multiple
+/// schedulers use the same backend. There is no leader: every scheduler
+/// knows its own partition and falls back to the actions of the others
+/// once they waited long enough.
+#[derive(MetricsComponent)]
+pub struct SchedulerPartition {
+    #[metric(help = "The partition of this scheduler.")]
+    partition_index: u64,
+
+    #[metric(help = "The number of schedulers sharing the backend.")]
+    partition_count: u64,
+
+    #[metric(help = "Seconds before actions of other partitions are taken over.")]
+    takeover_after_s: u64,
+
+    #[metric(help = "Number of actions of other partitions that were taken over.")]
+    taken_over_actions: Counter,
+}
+
+impl SchedulerPartition {
+    /// Note: The spec is expected to be validated, a `partition_count` of 0
+    /// is treated as a single partition.
+    pub fn new(spec: &SchedulerPartitionSpec) -> Self {
+        let mut takeover_after_s = spec.takeover_after_s;
+        if takeover_after_s == 0 {
+            takeover_after_s = DEFAULT_TAKEOVER_AFTER_S;
+        }
+        Self {
+            partition_index: u64::from(spec.partition_index),
+            partition_count: u64::from(spec.partition_count.max(1)),
+            takeover_after_s,
+            taken_over_actions: Counter::default(),
+        }
+    }
+
+    /// Time after which queued actions of other partitions are matched.
+    pub const fn takeover_after(&self) -> Duration {
+        Duration::from_secs(self.takeover_after_s)
+    }
+
+    /// Returns true if the action belongs to the partition of this scheduler.
+    pub fn owns(&self, action_info: &ActionInfo) -> bool {
+        let digest = action_info.digest();
+        let mut prefix = [0u8; 8];
+        prefix.copy_from_slice(&digest.packed_hash()[..8]);
+        u64::from_le_bytes(prefix) % self.partition_count == self.partition_index
+    }
+
+    /// Returns true if this scheduler should hand the queued action to one
+    /// of its workers at `now`.
+    pub fn should_match(&self, action_info: &ActionInfo, now: SystemTime) -> bool {
+        if self.owns(action_info) {
+            return true;
+        }
+        let queued_for = now
+            .duration_since(action_info.insert_timestamp)
+            .unwrap_or_default();
+        queued_for >= self.takeover_after()
+    }
+
+    /// Records an action of another partition that was assigned to a worker
+    /// of this scheduler.
+    pub fn record_assigned(&self, action_info: &ActionInfo) {
+        if !self.owns(action_info) {
+            self.taken_over_actions.inc();
+        }
+    }
+}
@@ -0,0 +1,370 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use nativelink_config::schedulers::SimpleSpec;
+use nativelink_error::{make_input_err, Error, ResultExt};
+use nativelink_proto::com::github::trace_machina::nativelink::remote_execution::{
+    update_for_worker, UpdateForWorker,
+};
+use nativelink_util::action_messages::{
+    ActionInfo, ActionResult, ActionStage, ActionUniqueKey, ActionUniqueQualifier, OperationId,
+    WorkerId,
+};
+use nativelink_util::common::DigestInfo;
+use nativelink_util::digest_hasher::DigestHasherFunc;
+use nativelink_util::instant_wrapper::InstantWrapper;
+use nativelink_util::operation_state_manager::{ClientStateManager, UpdateOperationType};
+use serde::Deserialize;
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use crate::default_scheduler_factory::memory_awaited_action_db_factory;
+use crate::platform_property_manager::PlatformPropertyManager;
+use crate::simple_scheduler::SimpleScheduler;
+use crate::worker::Worker;
+use crate::worker_scheduler::WorkerScheduler;
+
+/// Instance name of the simulated actions.
+const SIMULATION_INSTANCE_NAME: &str = "simulation";
+
+/// Nothing listens to the simulated actions and the simulated workers never
+/// send keep alives, so neither must time out. Large enough to never
+/// happen, small enough to not overflow when added to a `SystemTime`.
+const SIMULATION_TIMEOUT_S: u64 = 100 * 365 * 24 * 60 * 60;
+
+/// An action of a recorded trace.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TraceAction {
+    /// When the action arrived at the scheduler, in milliseconds since the
+    /// start of the trace.
+    pub arrival_ms: u64,
+
+    /// How long the action ran on a worker, in milliseconds.
+    pub duration_ms: u64,
+
+    /// The `ExecutionPolicy.priority` of the action.
+    #[serde(default)]
+    pub priority: i32,
+
+    /// The platform properties the action requested.
+    #[serde(default)]
+    pub platform_properties: HashMap<String, String>,
+
+    /// The identity of the client that sent the action.
+    #[serde(default)]
+    pub client_identity: Option<String>,
+}
+
+/// A group of identical workers.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SimulatedWorkerPool {
+    /// Number of workers in the pool.
+    pub count: usize,
+
+    /// The platform properties every worker of the pool provides. Workers
+    /// only run a single action at a time if the actions request a
+    /// `minimum` property the workers provide.
+    #[serde(default)]
+    pub platform_properties: HashMap<String, String>,
+}
+
+/// Queue times and worker utilization of a simulated trace.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SimulationReport {
+    /// Number of actions in the trace.
+    pub actions: usize,
+
+    /// Number of actions no worker was able to run.
+    pub unscheduled_actions: usize,
+
+    /// Time from the start of the trace until the last action finished.
+    pub makespan: Duration,
+
+    /// Mean time the scheduled actions waited for a worker.
+    pub mean_queue_time: Duration,
+
+    /// Median time the scheduled actions waited for a worker.
+    pub p50_queue_time: Duration,
+
+    /// 90th percentile of the time the scheduled actions waited for a worker.
+    pub p90_queue_time: Duration,
+
+    /// 99th percentile of the time the scheduled actions waited for a worker.
+    pub p99_queue_time: Duration,
+
+    /// Longest time a scheduled action waited for a worker.
+    pub max_queue_time: Duration,
+
+    /// Time actions ran divided by the time the workers were available,
+    /// so 1.0 means every worker ran one action at all times.
+    pub worker_utilization: f64,
+}
+
+/// An instant on the clock of a simulation, which only moves when the
+/// simulation moves it.
+struct SimulatedInstant {
+    /// Milliseconds since the unix epoch, None for instants created by
+    /// `from_secs()`, which do not know the clock.
+    clock: Option<Arc<AtomicU64>>,
+    created_ms: u64,
+}
+
+impl SimulatedInstant {
+    fn now_ms(&self) -> u64 {
+        self.clock
+            .as_ref()
+            .map_or(self.created_ms, |clock| clock.load(Ordering::Acquire))
+    }
+}
+
+impl InstantWrapper for SimulatedInstant {
+    fn from_secs(secs: u64) -> Self {
+        Self {
+            clock: None,
+            created_ms: secs.saturating_mul(1000),
+        }
+    }
+
+    fn unix_timestamp(&self) -> u64 {
+        self.now_ms() / 1000
+    }
+
+    fn now(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.now_ms())
+    }
+
+    fn elapsed(&self) -> Duration {
+        Duration::from_millis(self.now_ms().saturating_sub(self.created_ms))
+    }
+
+    async fn sleep(self, duration: Duration) {
+        while self.elapsed() < duration {
+            tokio::task::yield_now().await;
+        }
+    }
+}
+
+/// Replays `trace` against a `SimpleScheduler` configured by `spec` and the
+/// workers of `worker_pools` without executing anything, so the effect of
+/// scheduling policies and pool sizes on queue times can be evaluated
+/// before rolling them out. Time only passes in the simulation, so a trace
+/// of hours is replayed in seconds.
+///
+/// Client and worker timeouts of `spec` are ignored, as nothing listens to
+/// the simulated actions and the simulated workers never fail. Hedging is
+/// not simulated.
+pub async fn simulate(
+    mut spec: SimpleSpec,
+    worker_pools: &[SimulatedWorkerPool],
+    trace: &[TraceAction],
+) -> Result<SimulationReport, Error> {
+    spec.client_action_timeout_s = SIMULATION_TIMEOUT_S;
+    spec.worker_timeout_s = SIMULATION_TIMEOUT_S;
+
+    let start_ms = u64::try_from(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis(),
+    )
+    .unwrap_or_default();
+    let clock = Arc::new(AtomicU64::new(start_ms));
+    let now_fn = {
+        let clock = clock.clone();
+        move || SimulatedInstant {
+            created_ms: clock.load(Ordering::Acquire),
+            clock: Some(clock.clone()),
+        }
+    };
+    let platform_property_manager = PlatformPropertyManager::new(
+        spec.supported_platform_properties
+            .clone()
+            .unwrap_or_default(),
+    );
+    // Matching is driven by the simulation, so the matching engine is not
+    // notified about new actions.
+    let (scheduler, _worker_scheduler) = SimpleScheduler::new_with_callback(
+        &spec,
+        memory_awaited_action_db_factory(0, &Arc::new(Notify::new()), now_fn.clone()),
+        || async move {},
+        Arc::new(Notify::new()),
+        now_fn,
+    );
+
+    let mut workers: Vec<(WorkerId, UnboundedReceiver<UpdateForWorker>)> = Vec::new();
+    for worker_pool in worker_pools {
+        let platform_properties = platform_property_manager
+            .make_platform_properties(worker_pool.platform_properties.clone())
+            .err_tip(|| "In SimulatedWorkerPool::platform_properties")?;
+        for _ in 0..worker_pool.count {
+            let worker_id = WorkerId(Uuid::new_v4());
+            let (tx, rx) = mpsc::unbounded_channel();
+            scheduler
+                .add_worker(Worker::new(
+                    worker_id,
+                    platform_properties.clone(),
+                    tx,
+                    start_ms / 1000,
+                ))
+                .await
+                .err_tip(|| "Failed to add worker in simulate()")?;
+            workers.push((worker_id, rx));
+        }
+    }
+
+    // Every action gets a unique digest, so the actions can be told apart
+    // when they are started.
+    let mut trace_indexes: HashMap<DigestInfo, usize> = HashMap::with_capacity(trace.len());
+    let mut arrivals: Vec<usize> = (0..trace.len()).collect();
+    arrivals.sort_by_key(|index| trace[*index].arrival_ms);
+    let mut arrivals = arrivals.into_iter().peekable();
+    // Running actions by the time they finish, with a sequence number to
+    // keep actions finishing at the same time apart.
+    let mut completions: BTreeMap<(u64, usize), (WorkerId, OperationId)> = BTreeMap::new();
+    let mut queue_times_ms: Vec<u64> = Vec::with_capacity(trace.len());
+    let mut busy_ms: u128 = 0;
+    let mut end_ms: u64 = 0;
+    // Dropping the result of `add_action()` drops the action.
+    let mut action_listeners = Vec::with_capacity(trace.len());
+
+    loop {
+        let next_arrival_ms = arrivals.peek().map(|index| trace[*index].arrival_ms);
+        let next_completion_ms = completions.keys().next().map(|(end_ms, _)| *end_ms);
+        let now_ms = match (next_arrival_ms, next_completion_ms) {
+            (None, None) => break,
+            (Some(arrival_ms), None) => arrival_ms,
+            (None, Some(completion_ms)) => completion_ms,
+            (Some(arrival_ms), Some(completion_ms)) => arrival_ms.min(completion_ms),
+        };
+        clock.store(start_ms + now_ms, Ordering::Release);
+
+        while let Some(entry) = completions.first_entry() {
+            if entry.key().0 > now_ms {
+                break;
+            }
+            let (worker_id, operation_id) = entry.remove();
+            end_ms = end_ms.max(now_ms);
+            scheduler
+                .update_action(
+                    &worker_id,
+                    &operation_id,
+                    UpdateOperationType::UpdateWithActionStage(ActionStage::Completed(
+                        ActionResult::default(),
+                    )),
+                )
+                .await
+                .err_tip(|| "Failed to complete action in simulate()")?;
+        }
+        while let Some(index) = arrivals.next_if(|index| trace[*index].arrival_ms <= now_ms) {
+            let digest = simulated_action_digest(index);
+            trace_indexes.insert(digest, index);
+            let trace_action = &trace[index];
+            let action_info = Arc::new(ActionInfo {
+                command_digest: DigestInfo::zero_digest(),
+                input_root_digest: DigestInfo::zero_digest(),
+                timeout: Duration::MAX,
+                platform_properties: trace_action.platform_properties.clone(),
+                priority: trace_action.priority,
+                load_timestamp: UNIX_EPOCH + Duration::from_millis(start_ms + now_ms),
+                insert_timestamp: UNIX_EPOCH + Duration::from_millis(start_ms + now_ms),
+                unique_qualifier: ActionUniqueQualifier::Uncachable(ActionUniqueKey {
+                    instance_name: SIMULATION_INSTANCE_NAME.to_string(),
+                    digest_function: DigestHasherFunc::Sha256,
+                    digest,
+                }),
+                tool_invocation_id: None,
+                client_identity: trace_action.client_identity.clone(),
+                queue_priority: None,
+            });
+            action_listeners.push(
+                scheduler
+                    .add_action(OperationId::default(), action_info)
+                    .await
+                    .err_tip(|| "Failed to add action in simulate()")?,
+            );
+        }
+
+        scheduler
+            .do_try_match()
+            .await
+            .err_tip(|| "Failed to match actions in simulate()")?;
+
+        for (worker_id, rx) in &mut workers {
+            while let Ok(update) = rx.try_recv() {
+                let Some(update_for_worker::Update::StartAction(start_execute)) = update.update
+                else {
+                    continue;
+                };
+                let digest = start_execute
+                    .execute_request
+                    .and_then(|execute_request| execute_request.action_digest)
+                    .err_tip(|| "Missing action digest in StartExecute in simulate()")?;
+                let digest = DigestInfo::try_from(digest)?;
+                let index = *trace_indexes
+                    .get(&digest)
+                    .ok_or_else(|| make_input_err!("Unknown action {digest} in simulate()"))?;
+                let trace_action = &trace[index];
+                queue_times_ms.push(now_ms.saturating_sub(trace_action.arrival_ms));
+                busy_ms += u128::from(trace_action.duration_ms);
+                completions.insert(
+                    (now_ms + trace_action.duration_ms, index),
+                    (*worker_id, OperationId::from(start_execute.operation_id)),
+                );
+            }
+        }
+    }
+
+    let worker_count: usize = worker_pools.iter().map(|pool| pool.count).sum();
+    let available_ms = u128::from(end_ms) * worker_count as u128;
+    queue_times_ms.sort_unstable();
+    Ok(SimulationReport {
+        actions: trace.len(),
+        unscheduled_actions: trace.len() - queue_times_ms.len(),
+        makespan: Duration::from_millis(end_ms),
+        mean_queue_time: Duration::from_millis(
+            queue_times_ms.iter().sum::<u64>() / (queue_times_ms.len().max(1) as u64),
+        ),
+        p50_queue_time: percentile(&queue_times_ms, 50),
+        p90_queue_time: percentile(&queue_times_ms, 90),
+        p99_queue_time: percentile(&queue_times_ms, 99),
+        max_queue_time: Duration::from_millis(queue_times_ms.last().copied().unwrap_or(0)),
+        worker_utilization: if available_ms == 0 {
+            0.0
+        } else {
+            busy_ms as f64 / available_ms as f64
+        },
+    })
+}
+
+fn simulated_action_digest(index: usize) -> DigestInfo {
+    let mut hash = [0u8; 32];
+    hash[..8].copy_from_slice(&(index as u64).to_le_bytes());
+    DigestInfo::new(hash, 0)
+}
+
+/// Returns the `percentile` of the sorted `values_ms`.
+fn percentile(values_ms: &[u64], percentile: usize) -> Duration {
+    if values_ms.is_empty() {
+        return Duration::ZERO;
+    }
+    Duration::from_millis(values_ms[(values_ms.len() - 1) * percentile / 100])
+}
@@ -12,15 +12,21 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::SystemTime;
 
 use async_trait::async_trait;
-use futures::Future;
+use futures::future::BoxFuture;
+use futures::{Future, FutureExt};
 use nativelink_config::schedulers::SimpleSpec;
-use nativelink_error::{Code, Error, ResultExt};
+use nativelink_error::{make_err, Code, Error, ResultExt};
 use nativelink_metric::{MetricsComponent, RootMetricsComponent};
-use nativelink_util::action_messages::{ActionInfo, ActionState, OperationId, WorkerId};
+use nativelink_util::action_messages::{
+    ActionInfo, ActionStage, ActionState, OperationDetails, OperationId, QueuePosition, WorkerId,
+};
+use nativelink_util::health_utils::{HealthStatus, HealthStatusIndicator};
 use nativelink_util::instant_wrapper::InstantWrapper;
 use nativelink_util::known_platform_property_provider::KnownPlatformPropertyProvider;
 use nativelink_util::operation_state_manager::{
@@ -36,15 +42,26 @@ use tracing::{event, Level};
 
 use crate::api_worker_scheduler::ApiWorkerScheduler;
 use crate::awaited_action_db::AwaitedActionDb;
+use crate::execution_history::ExecutionHistory;
+use crate::fair_share_policy::FairSharePolicy;
 use crate::platform_property_manager::PlatformPropertyManager;
+use crate::priority_policy::PriorityPolicy;
+use crate::queue_limits::QueueLimits;
+use crate::retry_policy::RetryPolicy;
+use crate::scheduler_partition::SchedulerPartition;
 use crate::simple_scheduler_state_manager::SimpleSchedulerStateManager;
-use crate::worker::{ActionInfoWithProps, Worker, WorkerTimestamp};
+use crate::worker::{ActionInfoWithProps, Worker, WorkerHeartbeat, WorkerTimestamp};
 use crate::worker_scheduler::WorkerScheduler;
 
 /// Default timeout for workers in seconds.
 /// If this changes, remember to change the documentation in the config.
 const DEFAULT_WORKER_TIMEOUT_S: u64 = 5;
 
+/// Default time actions may run past their timeout before the scheduler
+/// kills them, in seconds.
+/// If this changes, remember to change the documentation in the config.
+const DEFAULT_ACTION_TIMEOUT_GRACE_S: u64 = 60;
+
 /// Mark operations as completed with error if no client has updated them
 /// within this duration.
 /// If this changes, remember to change the documentation in the config.
@@ -57,16 +74,19 @@ const DEFAULT_MAX_JOB_RETRIES: usize = 3;
 struct SimpleSchedulerActionStateResult {
     client_operation_id: OperationId,
     action_state_result: Box<dyn ActionStateResult>,
+    maybe_execution_history: Option<Arc<ExecutionHistory>>,
 }
 
 impl SimpleSchedulerActionStateResult {
     fn new(
         client_operation_id: OperationId,
         action_state_result: Box<dyn ActionStateResult>,
+        maybe_execution_history: Option<Arc<ExecutionHistory>>,
     ) -> Self {
         Self {
             client_operation_id,
             action_state_result,
+            maybe_execution_history,
         }
     }
 }
@@ -103,6 +123,39 @@ impl ActionStateResult for SimpleSchedulerActionStateResult {
             .await
             .err_tip(|| "In SimpleSchedulerActionStateResult")
     }
+    async fn queue_position(&self) -> Result<Option<QueuePosition>, Error> {
+        let maybe_queue_position = self
+            .action_state_result
+            .queue_position()
+            .await
+            .err_tip(|| "In SimpleSchedulerActionStateResult")?;
+        let (Some(mut queue_position), Some(execution_history)) =
+            (maybe_queue_position, &self.maybe_execution_history)
+        else {
+            return Ok(maybe_queue_position);
+        };
+        let action_info = self
+            .action_state_result
+            .as_action_info()
+            .await
+            .err_tip(|| "In SimpleSchedulerActionStateResult")?;
+        let maybe_stats = execution_history
+            .get(&action_info.command_digest)
+            .await
+            .err_tip(|| "In SimpleSchedulerActionStateResult")?;
+        if let Some(stats) = maybe_stats {
+            queue_position.estimated_execution_duration = Some(stats.mean_execution_duration());
+            queue_position.estimated_output_bytes = Some(stats.mean_output_bytes);
+        }
+        Ok(Some(queue_position))
+    }
+
+    async fn operation_details(&self) -> Result<Option<OperationDetails>, Error> {
+        self.action_state_result
+            .operation_details()
+            .await
+            .err_tip(|| "In SimpleSchedulerActionStateResult")
+    }
 }
 
 /// Engine used to manage the queued/running tasks and relationship with
@@ -127,9 +180,43 @@ pub struct SimpleScheduler {
     #[metric(group = "worker_scheduler")]
     worker_scheduler: Arc<ApiWorkerScheduler>,
 
+    /// Orders the queued actions by priority band and age.
+    #[metric(group = "priority_policy")]
+    priority_policy: Arc<PriorityPolicy>,
+
+    /// Orders the queued actions of the same priority by the recent usage
+    /// of the client identities that sent them.
+    #[metric(group = "fair_share_policy")]
+    maybe_fair_share_policy: Option<Arc<FairSharePolicy>>,
+
+    /// The share of the queued actions this scheduler matches if the
+    /// backend is shared with other schedulers.
+    #[metric(group = "partition")]
+    maybe_partition: Option<Arc<SchedulerPartition>>,
+
+    /// Rejects new actions of the platform pools whose queue is full. None
+    /// if no queue is limited.
+    #[metric(group = "queue_limits")]
+    maybe_queue_limits: Option<Arc<QueueLimits>>,
+
+    /// How long the actions of every command executed before. None if no
+    /// history is kept.
+    #[metric(group = "execution_history")]
+    maybe_execution_history: Option<Arc<ExecutionHistory>>,
+
+    /// The function to get the current time.
+    now_fn: Box<dyn Fn() -> SystemTime + Send + Sync>,
+
+    /// Set once the scheduler started shutting down. No new actions are
+    /// accepted or matched to workers from then on.
+    is_shutting_down: AtomicBool,
+
+    /// Writes the unfinished actions to the persistence store, if any.
+    flush_fn: Box<dyn Fn() -> BoxFuture<'static, Result<(), Error>> + Send + Sync>,
+
     /// Background task that tries to match actions to workers. If this struct
     /// is dropped the spawn will be cancelled as well.
-    _task_worker_matching_spawn: JoinHandleDropGuard<()>,
+    task_worker_matching_spawn: JoinHandleDropGuard<()>,
 }
 
 impl SimpleScheduler {
@@ -143,8 +230,25 @@ impl SimpleScheduler {
     async fn inner_add_action(
         &self,
         client_operation_id: OperationId,
-        action_info: Arc<ActionInfo>,
+        mut action_info: Arc<ActionInfo>,
     ) -> Result<Box<dyn ActionStateResult>, Error> {
+        if self.is_shutting_down.load(Ordering::Acquire) {
+            return Err(make_err!(Code::Unavailable, "Scheduler is shutting down"));
+        }
+        if let Some(queue_limits) = &self.maybe_queue_limits {
+            queue_limits
+                .try_enqueue(&action_info)
+                .err_tip(|| "In SimpleScheduler::add_action")?;
+        }
+        self.priority_policy.record_queued(action_info.priority);
+        // The action is ordered in the queue by its band and age, the
+        // priority of the client is kept as is.
+        let queue_priority = self
+            .priority_policy
+            .queue_priority(action_info.priority, action_info.insert_timestamp);
+        if queue_priority != action_info.priority {
+            Arc::make_mut(&mut action_info).queue_priority = Some(queue_priority);
+        }
         let action_state_result = self
             .client_state_manager
             .add_action(client_operation_id.clone(), action_info)
@@ -153,6 +257,7 @@ impl SimpleScheduler {
         Ok(Box::new(SimpleSchedulerActionStateResult::new(
             client_operation_id.clone(),
             action_state_result,
+            self.maybe_execution_history.clone(),
         )))
     }
 
@@ -166,6 +271,34 @@ impl SimpleScheduler {
             .err_tip(|| "In SimpleScheduler::find_by_client_operation_id getting filter result")
     }
 
+    /// Kills the operation on the worker running it, if any.
+    async fn kill_operation_on_worker(&self, operation_id: &OperationId) -> Result<(), Error> {
+        let filter = OperationFilter {
+            operation_id: Some(operation_id.clone()),
+            stages: OperationStageFlags::Any,
+            ..Default::default()
+        };
+        let mut stream = self
+            .client_state_manager
+            .filter_operations(filter)
+            .await
+            .err_tip(|| "In SimpleScheduler::kill_operation_on_worker")?;
+        let Some(action_state_result) = stream.next().await else {
+            return Ok(());
+        };
+        let maybe_worker_id = action_state_result
+            .operation_details()
+            .await
+            .err_tip(|| "In SimpleScheduler::kill_operation_on_worker")?
+            .and_then(|operation_details| operation_details.worker_id);
+        if let Some(worker_id) = maybe_worker_id {
+            self.worker_scheduler
+                .kill_operation(&worker_id, operation_id)
+                .await;
+        }
+        Ok(())
+    }
+
     async fn get_queued_operations(&self) -> Result<ActionStateResultStream, Error> {
         let filter = OperationFilter {
             stages: OperationStageFlags::Queued,
@@ -182,20 +315,95 @@ impl SimpleScheduler {
         self.do_try_match().await
     }
 
+    /// Shuts the scheduler down. New actions are rejected with
+    /// `UNAVAILABLE`, the workers get up to `grace_period` to finish the
+    /// actions they run, the actions still running after it are queued
+    /// again, and the workers are disconnected. The unfinished actions are
+    /// written to the persistence store, if any, so the next scheduler
+    /// picks them up.
+    pub async fn shutdown(&self, grace_period: Duration) -> Result<(), Error> {
+        self.is_shutting_down.store(true, Ordering::Release);
+        event!(
+            Level::INFO,
+            ?grace_period,
+            "Scheduler is shutting down, waiting for workers to finish their actions"
+        );
+        let mut result = Ok(());
+        if !self.worker_scheduler.drain_all_workers(grace_period).await {
+            result = result.merge(
+                self.requeue_executing_operations()
+                    .await
+                    .err_tip(|| "In SimpleScheduler::shutdown"),
+            );
+        }
+        self.worker_scheduler.disconnect_all_workers().await;
+        result.merge(
+            (self.flush_fn)()
+                .await
+                .err_tip(|| "In SimpleScheduler::shutdown"),
+        )
+    }
+
+    /// Kills all executing operations on their workers and queues them
+    /// again.
+    async fn requeue_executing_operations(&self) -> Result<(), Error> {
+        let filter = OperationFilter {
+            stages: OperationStageFlags::Executing,
+            ..Default::default()
+        };
+        let mut operation_ids = Vec::new();
+        let mut stream = self
+            .matching_engine_state_manager
+            .filter_operations(filter)
+            .await
+            .err_tip(|| "In SimpleScheduler::requeue_executing_operations")?;
+        while let Some(action_state_result) = stream.next().await {
+            let maybe_operation_details = action_state_result
+                .operation_details()
+                .await
+                .err_tip(|| "In SimpleScheduler::requeue_executing_operations")?;
+            if let Some(operation_details) = maybe_operation_details {
+                operation_ids.push(operation_details.operation_id);
+            }
+        }
+        drop(stream);
+        let mut result = Ok(());
+        for operation_id in &operation_ids {
+            event!(
+                Level::WARN,
+                ?operation_id,
+                "Operation still runs after the shutdown grace period, queueing it again"
+            );
+            result = result.merge(ClientStateManager::requeue_operation(self, operation_id).await);
+        }
+        result
+    }
+
     // TODO(blaise.bruer) This is an O(n*m) (aka n^2) algorithm. In theory we
     // can create a map of capabilities of each worker and then try and match
     // the actions to the worker using the map lookup (ie. map reduce).
-    async fn do_try_match(&self) -> Result<(), Error> {
+    pub(crate) async fn do_try_match(&self) -> Result<(), Error> {
+        /// Returns true if the action was assigned to a worker.
+        #[expect(clippy::too_many_arguments)]
         async fn match_action_to_worker(
             action_state_result: &dyn ActionStateResult,
+            action_info: Arc<ActionInfo>,
             workers: &ApiWorkerScheduler,
             matching_engine_state_manager: &dyn MatchingEngineStateManager,
             platform_property_manager: &PlatformPropertyManager,
-        ) -> Result<(), Error> {
-            let action_info = action_state_result
-                .as_action_info()
-                .await
-                .err_tip(|| "Failed to get action_info from as_action_info_result stream")?;
+            priority_policy: &PriorityPolicy,
+            maybe_fair_share_policy: Option<&FairSharePolicy>,
+            maybe_partition: Option<&SchedulerPartition>,
+            maybe_execution_history: Option<&ExecutionHistory>,
+            now: SystemTime,
+        ) -> Result<bool, Error> {
+            // Leave the actions of other partitions to their schedulers
+            // unless they waited too long.
+            if let Some(partition) = maybe_partition {
+                if !partition.should_match(&action_info, now) {
+                    return Ok(false);
+                }
+            }
 
             // TODO(allada) We should not compute this every time and instead store
             // it with the ActionInfo when we receive it.
@@ -205,33 +413,48 @@ impl SimpleScheduler {
                     "Failed to make platform properties in SimpleScheduler::do_try_match"
                 })?;
 
+            let mut expected_execution_duration = None;
+            if let Some(execution_history) = maybe_execution_history {
+                // Without history the action is matched as if it was short.
+                match execution_history.get(&action_info.command_digest).await {
+                    Ok(maybe_stats) => {
+                        expected_execution_duration =
+                            maybe_stats.map(|stats| stats.mean_execution_duration());
+                    }
+                    Err(err) => {
+                        event!(Level::WARN, ?err, "Failed to get execution history");
+                    }
+                }
+            }
+
             let action_info = ActionInfoWithProps {
                 inner: action_info,
                 platform_properties,
+                expected_execution_duration,
+            };
+
+            // Extract the operation_id from the action_state.
+            let operation_id = {
+                let action_state = action_state_result
+                    .as_state()
+                    .await
+                    .err_tip(|| "Failed to get action_info from as_state_result stream")?;
+                action_state.client_operation_id.clone()
             };
 
             // Try to find a worker for the action.
             let worker_id = {
                 match workers
-                    .find_worker_for_action(&action_info.platform_properties)
+                    .find_worker_for_action(&operation_id, &action_info)
                     .await
                 {
                     Some(worker_id) => worker_id,
                     // If we could not find a worker for the action,
                     // we have nothing to do.
-                    None => return Ok(()),
+                    None => return Ok(false),
                 }
             };
 
-            // Extract the operation_id from the action_state.
-            let operation_id = {
-                let action_state = action_state_result
-                    .as_state()
-                    .await
-                    .err_tip(|| "Failed to get action_info from as_state_result stream")?;
-                action_state.client_operation_id.clone()
-            };
-
             // Tell the matching engine that the operation is being assigned to a worker.
             let assign_result = matching_engine_state_manager
                 .assign_operation(&operation_id, Ok(&worker_id))
@@ -241,11 +464,21 @@ impl SimpleScheduler {
                 if err.code == Code::Aborted {
                     // If the operation was aborted, it means that the operation was
                     // cancelled due to another operation being assigned to the worker.
-                    return Ok(());
+                    return Ok(false);
                 }
                 // Any other error is a real error.
                 return Err(err);
             }
+            priority_policy.record_assigned(
+                action_info.inner.sort_priority(),
+                action_info.inner.insert_timestamp,
+            );
+            if let Some(fair_share_policy) = maybe_fair_share_policy {
+                fair_share_policy.record_assigned(&action_info.inner, now);
+            }
+            if let Some(partition) = maybe_partition {
+                partition.record_assigned(&action_info.inner);
+            }
 
             // Notify the worker to run the action.
             {
@@ -254,27 +487,88 @@ impl SimpleScheduler {
                     .await
                     .err_tip(|| {
                         "Failed to run worker_notify_run_action in SimpleScheduler::do_try_match"
-                    })
+                    })?;
             }
+            Ok(true)
+        }
+
+        // The actions stay queued for the next scheduler.
+        if self.is_shutting_down.load(Ordering::Acquire) {
+            return Ok(());
         }
 
         let mut result = Ok(());
+        let now = (self.now_fn)();
 
         let mut stream = self
             .get_queued_operations()
             .await
             .err_tip(|| "Failed to get queued operations in do_try_match")?;
 
+        // Without fair share the actions are matched in queue order as they
+        // come in, otherwise all of them are needed to order them.
+        let mut queued_actions = Vec::new();
+        // The actions left in the queue, to count them against the queue
+        // limits.
+        let mut still_queued_actions = Vec::new();
         while let Some(action_state_result) = stream.next().await {
-            result = result.merge(
-                match_action_to_worker(
+            let action_info = match action_state_result.as_action_info().await {
+                Ok(action_info) => action_info,
+                Err(err) => {
+                    result =
+                        result.merge(Err(err).err_tip(|| {
+                            "Failed to get action_info from as_action_info_result stream"
+                        }));
+                    continue;
+                }
+            };
+            if self.maybe_fair_share_policy.is_some() {
+                queued_actions.push((action_info.clone(), (action_info, action_state_result)));
+                continue;
+            }
+            let match_result = match_action_to_worker(
+                action_state_result.as_ref(),
+                action_info.clone(),
+                self.worker_scheduler.as_ref(),
+                self.matching_engine_state_manager.as_ref(),
+                self.platform_property_manager.as_ref(),
+                self.priority_policy.as_ref(),
+                None,
+                self.maybe_partition.as_deref(),
+                self.maybe_execution_history.as_deref(),
+                now,
+            )
+            .await;
+            if self.maybe_queue_limits.is_some() && !matches!(match_result, Ok(true)) {
+                still_queued_actions.push(action_info);
+            }
+            result = result.merge(match_result.map(|_| ()));
+        }
+        if let Some(fair_share_policy) = &self.maybe_fair_share_policy {
+            for (action_info, action_state_result) in
+                fair_share_policy.order_queued_actions(queued_actions, now)
+            {
+                let match_result = match_action_to_worker(
                     action_state_result.as_ref(),
+                    action_info.clone(),
                     self.worker_scheduler.as_ref(),
                     self.matching_engine_state_manager.as_ref(),
                     self.platform_property_manager.as_ref(),
+                    self.priority_policy.as_ref(),
+                    Some(fair_share_policy),
+                    self.maybe_partition.as_deref(),
+                    self.maybe_execution_history.as_deref(),
+                    now,
                 )
-                .await,
-            );
+                .await;
+                if self.maybe_queue_limits.is_some() && !matches!(match_result, Ok(true)) {
+                    still_queued_actions.push(action_info);
+                }
+                result = result.merge(match_result.map(|_| ()));
+            }
+        }
+        if let Some(queue_limits) = &self.maybe_queue_limits {
+            queue_limits.set_queued_actions(still_queued_actions.iter().map(AsRef::as_ref));
         }
         result
     }
@@ -286,7 +580,19 @@ impl SimpleScheduler {
         awaited_action_db: A,
         task_change_notify: Arc<Notify>,
     ) -> (Arc<Self>, Arc<dyn WorkerScheduler>) {
-        Self::new_with_callback(
+        Self::new_with_execution_history(spec, awaited_action_db, task_change_notify, None)
+    }
+
+    /// Same as `new()`, but records how long actions execute in
+    /// `maybe_execution_history` and uses it to spread long actions over
+    /// the workers.
+    pub fn new_with_execution_history<A: AwaitedActionDb>(
+        spec: &SimpleSpec,
+        awaited_action_db: A,
+        task_change_notify: Arc<Notify>,
+        maybe_execution_history: Option<Arc<ExecutionHistory>>,
+    ) -> (Arc<Self>, Arc<dyn WorkerScheduler>) {
+        Self::inner_new(
             spec,
             awaited_action_db,
             || {
@@ -302,6 +608,7 @@ impl SimpleScheduler {
             },
             task_change_notify,
             SystemTime::now,
+            maybe_execution_history,
         )
     }
 
@@ -317,6 +624,30 @@ impl SimpleScheduler {
         on_matching_engine_run: F,
         task_change_notify: Arc<Notify>,
         now_fn: NowFn,
+    ) -> (Arc<Self>, Arc<dyn WorkerScheduler>) {
+        Self::inner_new(
+            spec,
+            awaited_action_db,
+            on_matching_engine_run,
+            task_change_notify,
+            now_fn,
+            None,
+        )
+    }
+
+    fn inner_new<
+        Fut: Future<Output = ()> + Send,
+        F: Fn() -> Fut + Send + Sync + 'static,
+        A: AwaitedActionDb,
+        I: InstantWrapper,
+        NowFn: Fn() -> I + Clone + Send + Unpin + Sync + 'static,
+    >(
+        spec: &SimpleSpec,
+        awaited_action_db: A,
+        on_matching_engine_run: F,
+        task_change_notify: Arc<Notify>,
+        now_fn: NowFn,
+        maybe_execution_history: Option<Arc<ExecutionHistory>>,
     ) -> (Arc<Self>, Arc<dyn WorkerScheduler>) {
         let platform_property_manager = Arc::new(PlatformPropertyManager::new(
             spec.supported_platform_properties
@@ -329,6 +660,21 @@ impl SimpleScheduler {
             worker_timeout_s = DEFAULT_WORKER_TIMEOUT_S;
         }
 
+        let maybe_worker_heartbeat = spec
+            .experimental_worker_heartbeat
+            .as_ref()
+            .map(WorkerHeartbeat::new);
+        // Workers that keep a heartbeat are only removed once they missed
+        // too many of them.
+        if let Some(worker_heartbeat) = &maybe_worker_heartbeat {
+            worker_timeout_s = worker_heartbeat.eviction_timeout_s();
+        }
+
+        let mut action_timeout_grace_s = spec.action_timeout_grace_s;
+        if action_timeout_grace_s == 0 {
+            action_timeout_grace_s = DEFAULT_ACTION_TIMEOUT_GRACE_S;
+        }
+
         let mut client_action_timeout_s = spec.client_action_timeout_s;
         if client_action_timeout_s == 0 {
             client_action_timeout_s = DEFAULT_CLIENT_ACTION_TIMEOUT_S;
@@ -339,9 +685,41 @@ impl SimpleScheduler {
             max_job_retries = DEFAULT_MAX_JOB_RETRIES;
         }
 
+        let priority_policy = Arc::new(PriorityPolicy::new(
+            &spec.priority_bands,
+            spec.priority_aging_interval_s,
+        ));
+
+        let maybe_fair_share_policy = spec
+            .experimental_fair_share
+            .as_ref()
+            .map(|fair_share_spec| Arc::new(FairSharePolicy::new(fair_share_spec)));
+
+        let maybe_partition = spec
+            .experimental_partition
+            .as_ref()
+            .map(|partition_spec| Arc::new(SchedulerPartition::new(partition_spec)));
+        // Actions of other partitions are taken over without any change to
+        // the actions, so the matching engine needs to run periodically.
+        let maybe_takeover_after = maybe_partition
+            .as_ref()
+            .map(|partition| partition.takeover_after());
+
+        let maybe_queue_limits = if spec.experimental_queue_limits.is_empty() {
+            None
+        } else {
+            Some(Arc::new(QueueLimits::new(&spec.experimental_queue_limits)))
+        };
+
         let worker_change_notify = Arc::new(Notify::new());
+        let scheduler_now_fn = {
+            let now_fn = now_fn.clone();
+            move || now_fn().now()
+        };
         let state_manager = SimpleSchedulerStateManager::new(
             max_job_retries,
+            spec.max_retries_per_invocation,
+            RetryPolicy::new(spec.experimental_retry_policy.as_ref()),
             Duration::from_secs(worker_timeout_s),
             Duration::from_secs(client_action_timeout_s),
             awaited_action_db,
@@ -354,10 +732,26 @@ impl SimpleScheduler {
             spec.allocation_strategy,
             worker_change_notify.clone(),
             worker_timeout_s,
+            action_timeout_grace_s,
+            spec.experimental_hedging.as_ref(),
+            spec.experimental_worker_affinity.as_ref(),
+            spec.experimental_cancel_abandoned_actions.as_ref(),
+            maybe_worker_heartbeat,
+            maybe_execution_history
+                .as_ref()
+                .map(|execution_history| execution_history.long_action_threshold()),
         );
 
         let worker_scheduler_clone = worker_scheduler.clone();
 
+        let flush_fn = {
+            let state_manager = state_manager.clone();
+            move || {
+                let state_manager = state_manager.clone();
+                async move { state_manager.flush().await }.boxed()
+            }
+        };
+
         let action_scheduler = Arc::new_cyclic(move |weak_self| -> Self {
             let weak_inner = weak_self.clone();
             let task_worker_matching_spawn =
@@ -366,10 +760,21 @@ impl SimpleScheduler {
                     loop {
                         let task_change_fut = task_change_notify.notified();
                         let worker_change_fut = worker_change_notify.notified();
+                        let takeover_fut = async {
+                            match maybe_takeover_after {
+                                Some(takeover_after) => tokio::time::sleep(takeover_after).await,
+                                None => futures::future::pending().await,
+                            }
+                        };
                         tokio::pin!(task_change_fut);
                         tokio::pin!(worker_change_fut);
-                        // Wait for either of these futures to be ready.
-                        let _ = futures::future::select(task_change_fut, worker_change_fut).await;
+                        tokio::pin!(takeover_fut);
+                        // Wait for any of these futures to be ready.
+                        let _ = futures::future::select(
+                            futures::future::select(task_change_fut, worker_change_fut),
+                            takeover_fut,
+                        )
+                        .await;
                         let result = match weak_inner.upgrade() {
                             Some(scheduler) => scheduler.do_try_match().await,
                             // If the inner went away it means the scheduler is shutting
@@ -389,7 +794,15 @@ impl SimpleScheduler {
                 client_state_manager: state_manager.clone(),
                 worker_scheduler,
                 platform_property_manager,
-                _task_worker_matching_spawn: task_worker_matching_spawn,
+                priority_policy,
+                maybe_fair_share_policy,
+                maybe_partition,
+                maybe_queue_limits,
+                maybe_execution_history,
+                now_fn: Box::new(scheduler_now_fn),
+                is_shutting_down: AtomicBool::new(false),
+                flush_fn: Box::new(flush_fn),
+                task_worker_matching_spawn,
             }
         });
         (action_scheduler, worker_scheduler_clone)
@@ -417,6 +830,24 @@ impl ClientStateManager for SimpleScheduler {
     fn as_known_platform_property_provider(&self) -> Option<&dyn KnownPlatformPropertyProvider> {
         Some(self)
     }
+
+    async fn cancel_operation(&self, operation_id: &OperationId) -> Result<(), Error> {
+        self.kill_operation_on_worker(operation_id)
+            .await
+            .err_tip(|| "In SimpleScheduler::cancel_operation")?;
+        self.client_state_manager
+            .cancel_operation(operation_id)
+            .await
+    }
+
+    async fn requeue_operation(&self, operation_id: &OperationId) -> Result<(), Error> {
+        self.kill_operation_on_worker(operation_id)
+            .await
+            .err_tip(|| "In SimpleScheduler::requeue_operation")?;
+        self.client_state_manager
+            .requeue_operation(operation_id)
+            .await
+    }
 }
 
 #[async_trait]
@@ -448,8 +879,50 @@ impl WorkerScheduler for SimpleScheduler {
         operation_id: &OperationId,
         update: UpdateOperationType,
     ) -> Result<(), Error> {
+        // Only successful executions tell how long the command runs. The
+        // action info is gone once the update completed the action.
+        let mut maybe_execution_to_record = None;
+        if let (
+            Some(execution_history),
+            UpdateOperationType::UpdateWithActionStage(ActionStage::Completed(action_result)),
+        ) = (&self.maybe_execution_history, &update)
+        {
+            if action_result.exit_code == 0 && action_result.error.is_none() {
+                if let Ok(action_info) = self
+                    .worker_scheduler
+                    .get_running_action_info(worker_id, operation_id)
+                    .await
+                {
+                    maybe_execution_to_record = Some((
+                        execution_history.clone(),
+                        action_info.command_digest,
+                        action_result.clone(),
+                    ));
+                }
+            }
+        }
         self.worker_scheduler
             .update_action(worker_id, operation_id, update)
+            .await?;
+        if let Some((execution_history, command_digest, action_result)) = maybe_execution_to_record
+        {
+            if let Err(err) = execution_history
+                .record(&command_digest, &action_result)
+                .await
+            {
+                event!(Level::WARN, ?err, "Failed to record execution history");
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_running_action_info(
+        &self,
+        worker_id: &WorkerId,
+        operation_id: &OperationId,
+    ) -> Result<Arc<ActionInfo>, Error> {
+        self.worker_scheduler
+            .get_running_action_info(worker_id, operation_id)
             .await
     }
 
@@ -473,11 +946,46 @@ impl WorkerScheduler for SimpleScheduler {
             .await
     }
 
+    async fn kill_timedout_actions(&self, now_timestamp: WorkerTimestamp) -> Result<(), Error> {
+        self.worker_scheduler
+            .kill_timedout_actions(now_timestamp)
+            .await
+    }
+
+    async fn hedge_slow_actions(&self, now_timestamp: WorkerTimestamp) -> Result<(), Error> {
+        self.worker_scheduler
+            .hedge_slow_actions(now_timestamp)
+            .await
+    }
+
+    async fn cancel_abandoned_actions(&self) -> Result<(), Error> {
+        self.worker_scheduler.cancel_abandoned_actions().await
+    }
+
     async fn set_drain_worker(&self, worker_id: &WorkerId, is_draining: bool) -> Result<(), Error> {
         self.worker_scheduler
             .set_drain_worker(worker_id, is_draining)
             .await
     }
+
+    async fn drain_worker(&self, worker_id: &WorkerId, timeout: Duration) -> Result<(), Error> {
+        self.worker_scheduler.drain_worker(worker_id, timeout).await
+    }
 }
 
 impl RootMetricsComponent for SimpleScheduler {}
+
+#[async_trait]
+impl HealthStatusIndicator for SimpleScheduler {
+    fn get_name(&self) -> &'static str {
+        "SimpleScheduler"
+    }
+
+    async fn check_health(&self, _namespace: Cow<'static, str>) -> HealthStatus {
+        if self.task_worker_matching_spawn.is_finished() {
+            HealthStatus::new_failed(self, "Worker matching task is not running".into())
+        } else {
+            HealthStatus::new_ok(self, "Worker matching task is running".into())
+        }
+    }
+}
@@ -12,6 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::VecDeque;
+use std::num::NonZeroUsize;
 use std::ops::Bound;
 use std::string::ToString;
 use std::sync::{Arc, Weak};
@@ -20,11 +22,12 @@ use std::time::{Duration, SystemTime};
 use async_lock::Mutex;
 use async_trait::async_trait;
 use futures::{stream, StreamExt, TryStreamExt};
+use lru::LruCache;
 use nativelink_error::{make_err, Code, Error, ResultExt};
 use nativelink_metric::MetricsComponent;
 use nativelink_util::action_messages::{
     ActionInfo, ActionResult, ActionStage, ActionState, ActionUniqueQualifier, ExecutionMetadata,
-    OperationId, WorkerId,
+    OperationDetails, OperationId, QueuePosition, WorkerId,
 };
 use nativelink_util::instant_wrapper::InstantWrapper;
 use nativelink_util::known_platform_property_provider::KnownPlatformPropertyProvider;
@@ -35,13 +38,23 @@ use nativelink_util::operation_state_manager::{
 use tracing::{event, Level};
 
 use super::awaited_action_db::{
-    AwaitedAction, AwaitedActionDb, AwaitedActionSubscriber, SortedAwaitedActionState,
+    AwaitedAction, AwaitedActionDb, AwaitedActionSubscriber, SortedAwaitedAction,
+    SortedAwaitedActionState,
 };
+use crate::retry_policy::{RetryClass, RetryPolicy};
 
 /// Maximum number of times an update to the database
 /// can fail before giving up.
 const MAX_UPDATE_RETRIES: usize = 5;
 
+/// Maximum number of tool invocations whose retries are remembered. The
+/// least recently retried invocations are forgotten first.
+const MAX_TRACKED_INVOCATIONS: usize = 10_000;
+
+/// Number of recent assignments of queued actions to workers used to
+/// estimate when queued actions start executing.
+const MAX_TRACKED_ASSIGNMENTS: usize = 100;
+
 /// Simple struct that implements the `ActionStateResult` trait and always returns an error.
 struct ErrorActionStateResult(Error);
 
@@ -113,6 +126,14 @@ where
     async fn as_action_info(&self) -> Result<Arc<ActionInfo>, Error> {
         self.inner.as_action_info().await
     }
+
+    async fn queue_position(&self) -> Result<Option<QueuePosition>, Error> {
+        self.inner.queue_position().await
+    }
+
+    async fn operation_details(&self) -> Result<Option<OperationDetails>, Error> {
+        self.inner.operation_details().await
+    }
 }
 
 struct MatchingEngineActionStateResult<U, T, I, NowFn>
@@ -234,6 +255,31 @@ where
             .action_info()
             .clone())
     }
+
+    async fn queue_position(&self) -> Result<Option<QueuePosition>, Error> {
+        let awaited_action = self
+            .awaited_action_sub
+            .borrow()
+            .await
+            .err_tip(|| "In MatchingEngineActionStateResult::queue_position")?;
+        let simple_scheduler_state_manager = self
+            .simple_scheduler_state_manager
+            .upgrade()
+            .err_tip(|| "Failed to upgrade weak reference to SimpleSchedulerStateManager in MatchingEngineActionStateResult::queue_position")?;
+        simple_scheduler_state_manager
+            .queue_position(&awaited_action)
+            .await
+    }
+
+    async fn operation_details(&self) -> Result<Option<OperationDetails>, Error> {
+        Ok(Some(
+            self.awaited_action_sub
+                .borrow()
+                .await
+                .err_tip(|| "In MatchingEngineActionStateResult::operation_details")?
+                .operation_details(),
+        ))
+    }
 }
 
 /// `SimpleSchedulerStateManager` is responsible for maintaining the state of the scheduler.
@@ -257,6 +303,18 @@ where
     #[metric(help = "Maximum number of times a job can be retried")]
     max_job_retries: usize,
 
+    /// Maximum number of retries shared by all the actions of a tool
+    /// invocation. Zero means no limit.
+    #[metric(help = "Maximum number of retries shared by all actions of a tool invocation")]
+    max_retries_per_invocation: usize,
+
+    /// Number of retries used so far by each tool invocation.
+    invocation_retries: parking_lot::Mutex<LruCache<String, usize>>,
+
+    /// Limits the retries by the kind of failure.
+    #[metric(group = "retry_policy")]
+    retry_policy: RetryPolicy,
+
     /// Duration after which an action is considered to be timed out if
     /// no event is received.
     #[metric(
@@ -273,6 +331,9 @@ where
     // on this service.
     timeout_operation_mux: Mutex<()>,
 
+    /// When the most recent queued actions were assigned to a worker.
+    recent_assignments: parking_lot::Mutex<VecDeque<SystemTime>>,
+
     /// Weak reference to self.
     // We use a weak reference to reduce the risk of a memory leak from
     // future changes. If this becomes some kind of perforamnce issue,
@@ -291,6 +352,8 @@ where
 {
     pub fn new(
         max_job_retries: usize,
+        max_retries_per_invocation: usize,
+        retry_policy: RetryPolicy,
         no_event_action_timeout: Duration,
         client_action_timeout: Duration,
         action_db: T,
@@ -299,14 +362,135 @@ where
         Arc::new_cyclic(|weak_self| Self {
             action_db,
             max_job_retries,
+            max_retries_per_invocation,
+            invocation_retries: parking_lot::Mutex::new(LruCache::new(
+                NonZeroUsize::new(MAX_TRACKED_INVOCATIONS).unwrap(),
+            )),
+            retry_policy,
             no_event_action_timeout,
             client_action_timeout,
             timeout_operation_mux: Mutex::new(()),
+            recent_assignments: parking_lot::Mutex::new(VecDeque::with_capacity(
+                MAX_TRACKED_ASSIGNMENTS,
+            )),
             weak_self: weak_self.clone(),
             now_fn,
         })
     }
 
+    /// Writes the unfinished actions to the persistence store of the
+    /// database now, if it has one.
+    pub async fn flush(&self) -> Result<(), Error> {
+        self.action_db
+            .flush()
+            .await
+            .err_tip(|| "In SimpleSchedulerStateManager::flush")
+    }
+
+    /// Returns where `awaited_action` stands in the queue, or None if it is
+    /// not queued.
+    async fn queue_position(
+        &self,
+        awaited_action: &AwaitedAction,
+    ) -> Result<Option<QueuePosition>, Error> {
+        if !matches!(awaited_action.state().stage, ActionStage::Queued) {
+            return Ok(None);
+        }
+        // Queued actions are handed to workers starting with the highest
+        // sort key, so every action with a higher key is ahead of this one.
+        let actions_ahead = self
+            .action_db
+            .get_range_of_actions(
+                SortedAwaitedActionState::Queued,
+                Bound::Excluded(SortedAwaitedAction::from(awaited_action)),
+                Bound::Unbounded,
+                false,
+            )
+            .await
+            .err_tip(|| "In SimpleSchedulerStateManager::queue_position")?
+            .try_fold(0u64, |count, _| async move { Ok(count + 1) })
+            .await
+            .err_tip(|| "In SimpleSchedulerStateManager::queue_position")?;
+        Ok(Some(QueuePosition {
+            actions_ahead,
+            estimated_start: self.estimate_start(actions_ahead),
+            estimated_execution_duration: None,
+            estimated_output_bytes: None,
+        }))
+    }
+
+    /// Estimates when an action with `actions_ahead` queued actions before
+    /// it starts executing, assuming actions keep being assigned to workers
+    /// as fast as they recently were.
+    fn estimate_start(&self, actions_ahead: u64) -> Option<SystemTime> {
+        let recent_assignments = self.recent_assignments.lock();
+        let (Some(first), Some(last)) = (recent_assignments.front(), recent_assignments.back())
+        else {
+            return None;
+        };
+        let intervals = u32::try_from(recent_assignments.len() - 1).ok()?;
+        if intervals == 0 {
+            return None;
+        }
+        let assignment_interval = last.duration_since(*first).ok()? / intervals;
+        let now = (self.now_fn)().now();
+        now.checked_add(assignment_interval.checked_mul(u32::try_from(actions_ahead + 1).ok()?)?)
+    }
+
+    /// Remembers that a queued action was assigned to a worker.
+    fn record_assignment(&self) {
+        let mut recent_assignments = self.recent_assignments.lock();
+        if recent_assignments.len() == MAX_TRACKED_ASSIGNMENTS {
+            recent_assignments.pop_front();
+        }
+        recent_assignments.push_back((self.now_fn)().now());
+    }
+
+    /// Returns true if the tool invocation `invocation_id` used up its
+    /// retry budget.
+    fn invocation_retries_exhausted(&self, invocation_id: &str) -> bool {
+        self.max_retries_per_invocation != 0
+            && self
+                .invocation_retries
+                .lock()
+                .peek(invocation_id)
+                .is_some_and(|retries| *retries >= self.max_retries_per_invocation)
+    }
+
+    /// Charges one retry to the budget of the tool invocation `invocation_id`.
+    fn record_invocation_retry(&self, invocation_id: String) {
+        if self.max_retries_per_invocation == 0 {
+            return;
+        }
+        *self
+            .invocation_retries
+            .lock()
+            .get_or_insert_mut(invocation_id, || 0) += 1;
+    }
+
+    /// Returns the operation if it exists and did not finish yet.
+    async fn get_unfinished_awaited_action(
+        &self,
+        operation_id: &OperationId,
+    ) -> Result<AwaitedAction, Error> {
+        let awaited_action = self
+            .action_db
+            .get_by_operation_id(operation_id)
+            .await
+            .err_tip(|| "In SimpleSchedulerStateManager::get_unfinished_awaited_action")?
+            .ok_or_else(|| make_err!(Code::NotFound, "Operation {operation_id} does not exist"))?
+            .borrow()
+            .await
+            .err_tip(|| "In SimpleSchedulerStateManager::get_unfinished_awaited_action")?;
+        if awaited_action.state().stage.is_finished() {
+            return Err(make_err!(
+                Code::FailedPrecondition,
+                "Operation {operation_id} already finished"
+            ));
+        }
+        Ok(awaited_action)
+    }
+
     async fn apply_filter_predicate(
         &self,
         awaited_action: &AwaitedAction,
@@ -527,6 +711,10 @@ where
                 ));
             }
 
+            // Tool invocation to charge a retry to once the update is stored.
+            let mut retried_invocation_id = None;
+            // Kind of failure to count a retry of once the update is stored.
+            let mut retried_class = None;
             let stage = match &update {
                 UpdateOperationType::KeepAlive => {
                     awaited_action.worker_keep_alive((self.now_fn)().now());
@@ -537,12 +725,25 @@ where
                         .err_tip(|| "Failed to send KeepAlive in SimpleSchedulerStateManager::update_operation");
                 }
                 UpdateOperationType::UpdateWithActionStage(stage) => stage.clone(),
-                UpdateOperationType::UpdateWithError(err) => {
+                UpdateOperationType::UpdateWithError(err)
+                | UpdateOperationType::UpdateWithWorkerEvicted(err) => {
+                    let retry_class =
+                        if matches!(update, UpdateOperationType::UpdateWithWorkerEvicted(_)) {
+                            RetryClass::WorkerEviction
+                        } else {
+                            RetryClass::of_worker_error(err)
+                        };
                     // Don't count a backpressure failure as an attempt for an action.
                     let due_to_backpressure = err.code == Code::ResourceExhausted;
                     if !due_to_backpressure {
                         awaited_action.attempts += 1;
+                        awaited_action.failed_attempts.increment(retry_class);
                     }
+                    let invocation_id = awaited_action.action_info().tool_invocation_id.clone();
+                    let class_attempts = awaited_action.failed_attempts.get(retry_class);
+                    let maybe_max_class_retries = self
+                        .retry_policy
+                        .max_retries(retry_class, awaited_action.action_info());
 
                     if awaited_action.attempts > self.max_job_retries {
                         ActionStage::Completed(ActionResult {
@@ -559,7 +760,43 @@ where
                             ))),
                             ..ActionResult::default()
                         })
+                    } else if let Some(max_class_retries) = maybe_max_class_retries
+                        .filter(|max_class_retries| class_attempts > *max_class_retries)
+                    {
+                        ActionStage::Completed(ActionResult {
+                            execution_metadata: ExecutionMetadata {
+                                worker: maybe_worker_id.map_or_else(String::default, ToString::to_string),
+                                ..ExecutionMetadata::default()
+                            },
+                            error: Some(err.clone().merge(make_err!(
+                                Code::Internal,
+                                "Job cancelled because it failed with {retry_class} too many times {class_attempts} > {max_class_retries} times {}",
+                                format!("for operation_id: {operation_id}, maybe_worker_id: {maybe_worker_id:?}"),
+                            ))),
+                            ..ActionResult::default()
+                        })
+                    } else if let Some(invocation_id) = invocation_id
+                        .as_deref()
+                        .filter(|id| !due_to_backpressure && self.invocation_retries_exhausted(id))
+                    {
+                        ActionStage::Completed(ActionResult {
+                            execution_metadata: ExecutionMetadata {
+                                worker: maybe_worker_id.map_or_else(String::default, ToString::to_string),
+                                ..ExecutionMetadata::default()
+                            },
+                            error: Some(err.clone().merge(make_err!(
+                                Code::Internal,
+                                "Job not retried because tool invocation {invocation_id} already used its budget of {} retries, this usually means the workers are unhealthy {}",
+                                self.max_retries_per_invocation,
+                                format!("for operation_id: {operation_id}, maybe_worker_id: {maybe_worker_id:?}"),
+                            ))),
+                            ..ActionResult::default()
+                        })
                     } else {
+                        if !due_to_backpressure {
+                            retried_invocation_id = invocation_id;
+                            retried_class = Some(retry_class);
+                        }
                         ActionStage::Queued
                     }
                 }
@@ -599,6 +836,12 @@ where
                 }
                 return Err(err);
             }
+            if let Some(invocation_id) = retried_invocation_id {
+                self.record_invocation_retry(invocation_id);
+            }
+            if let Some(retry_class) = retried_class {
+                self.retry_policy.record_retry(retry_class);
+            }
             return Ok(());
         }
         match last_err {
@@ -806,6 +1049,50 @@ where
     fn as_known_platform_property_provider(&self) -> Option<&dyn KnownPlatformPropertyProvider> {
         None
     }
+
+    async fn cancel_operation(&self, operation_id: &OperationId) -> Result<(), Error> {
+        let awaited_action = self
+            .get_unfinished_awaited_action(operation_id)
+            .await
+            .err_tip(|| "In SimpleSchedulerStateManager::cancel_operation")?;
+        let stage = ActionStage::Completed(ActionResult {
+            execution_metadata: ExecutionMetadata {
+                worker: awaited_action
+                    .worker_id()
+                    .map_or_else(String::default, |worker_id| worker_id.to_string()),
+                ..ExecutionMetadata::default()
+            },
+            error: Some(make_err!(
+                Code::Cancelled,
+                "Operation {operation_id} was cancelled by an operator"
+            )),
+            ..ActionResult::default()
+        });
+        self.inner_update_operation(
+            operation_id,
+            None,
+            UpdateOperationType::UpdateWithActionStage(stage),
+        )
+        .await
+        .err_tip(|| "In SimpleSchedulerStateManager::cancel_operation")
+    }
+
+    async fn requeue_operation(&self, operation_id: &OperationId) -> Result<(), Error> {
+        let awaited_action = self
+            .get_unfinished_awaited_action(operation_id)
+            .await
+            .err_tip(|| "In SimpleSchedulerStateManager::requeue_operation")?;
+        if matches!(awaited_action.state().stage, ActionStage::Queued) {
+            return Ok(());
+        }
+        self.inner_update_operation(
+            operation_id,
+            None,
+            UpdateOperationType::UpdateWithActionStage(ActionStage::Queued),
+        )
+        .await
+        .err_tip(|| "In SimpleSchedulerStateManager::requeue_operation")
+    }
 }
 
 #[async_trait]
@@ -824,6 +1111,54 @@ where
         self.inner_update_operation(operation_id, Some(worker_id), update)
             .await
     }
+
+    async fn cancel_if_abandoned(
+        &self,
+        operation_id: &OperationId,
+        worker_id: &WorkerId,
+        grace_period: Duration,
+    ) -> Result<bool, Error> {
+        let Some(awaited_action_subscriber) = self
+            .action_db
+            .get_by_operation_id(operation_id)
+            .await
+            .err_tip(|| "In SimpleSchedulerStateManager::cancel_if_abandoned")?
+        else {
+            // The operation was dropped once all its clients went away.
+            return Ok(true);
+        };
+        let awaited_action = awaited_action_subscriber
+            .borrow()
+            .await
+            .err_tip(|| "In SimpleSchedulerStateManager::cancel_if_abandoned")?;
+        if awaited_action.state().stage.is_finished() {
+            return Ok(true);
+        }
+        if awaited_action.last_client_keepalive_timestamp() + grace_period >= (self.now_fn)().now()
+        {
+            return Ok(false);
+        }
+        let stage = ActionStage::Completed(ActionResult {
+            execution_metadata: ExecutionMetadata {
+                worker: worker_id.to_string(),
+                ..ExecutionMetadata::default()
+            },
+            error: Some(make_err!(
+                Code::Cancelled,
+                "Operation {operation_id} was cancelled on worker {worker_id} after no client listened to it for {} seconds",
+                grace_period.as_secs_f32(),
+            )),
+            ..ActionResult::default()
+        });
+        self.inner_update_operation(
+            operation_id,
+            Some(worker_id),
+            UpdateOperationType::UpdateWithActionStage(stage),
+        )
+        .await
+        .err_tip(|| "In SimpleSchedulerStateManager::cancel_if_abandoned")?;
+        Ok(true)
+    }
 }
 
 #[async_trait]
@@ -858,9 +1193,14 @@ where
                 Some(worker_id),
                 UpdateOperationType::UpdateWithActionStage(ActionStage::Executing),
             ),
-            Err(err) => (None, UpdateOperationType::UpdateWithError(err)),
+            Err(err) => (None, UpdateOperationType::UpdateWithWorkerEvicted(err)),
         };
+        let is_assignment = maybe_worker_id.is_some();
         self.inner_update_operation(operation_id, maybe_worker_id, update)
-            .await
+            .await?;
+        if is_assignment {
+            self.record_assignment();
+        }
+        Ok(())
     }
 }
@@ -12,15 +12,16 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::hash::{Hash, Hasher};
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use nativelink_config::schedulers::WorkerHeartbeatSpec;
 use nativelink_error::{make_err, Code, Error, ResultExt};
 use nativelink_metric::MetricsComponent;
 use nativelink_proto::com::github::trace_machina::nativelink::remote_execution::{
-    update_for_worker, ConnectionResult, StartExecute, UpdateForWorker,
+    update_for_worker, ConnectionResult, KillOperationRequest, StartExecute, UpdateForWorker,
 };
 use nativelink_util::action_messages::{ActionInfo, OperationId, WorkerId};
 use nativelink_util::metrics_utils::{CounterWithTime, FuncCounterWrapper};
@@ -29,6 +30,44 @@ use tokio::sync::mpsc::UnboundedSender;
 
 pub type WorkerTimestamp = u64;
 
+/// Default value for `WorkerHeartbeatSpec::interval_s`.
+/// If this changes, remember to change the documentation in the config.
+const DEFAULT_HEARTBEAT_INTERVAL_S: u64 = 5;
+
+/// Default value for `WorkerHeartbeatSpec::max_missed_heartbeats`.
+/// If this changes, remember to change the documentation in the config.
+const DEFAULT_MAX_MISSED_HEARTBEATS: u32 = 3;
+
+/// How often workers are told to send keep alives, and how many of them they
+/// may miss before they are considered dead.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WorkerHeartbeat {
+    pub interval_s: u64,
+    pub max_missed_heartbeats: u32,
+}
+
+impl WorkerHeartbeat {
+    pub fn new(spec: &WorkerHeartbeatSpec) -> Self {
+        Self {
+            interval_s: if spec.interval_s == 0 {
+                DEFAULT_HEARTBEAT_INTERVAL_S
+            } else {
+                spec.interval_s
+            },
+            max_missed_heartbeats: if spec.max_missed_heartbeats == 0 {
+                DEFAULT_MAX_MISSED_HEARTBEATS
+            } else {
+                spec.max_missed_heartbeats
+            },
+        }
+    }
+
+    /// How long a worker may stay silent before it is removed from the pool.
+    pub fn eviction_timeout_s(&self) -> u64 {
+        self.interval_s * (u64::from(self.max_missed_heartbeats) + 1)
+    }
+}
+
 /// Represents the action info and the platform properties of the action.
 /// These platform properties have the type of the properties as well as
 /// the value of the properties, unlike `ActionInfo`, which only has the
@@ -41,6 +80,10 @@ pub struct ActionInfoWithProps {
     /// The platform properties of the action.
     #[metric(group = "platform_properties")]
     pub platform_properties: PlatformProperties,
+    /// How long the action is expected to execute, if its command has
+    /// history.
+    #[metric(help = "How long the action is expected to execute.")]
+    pub expected_execution_duration: Option<Duration>,
 }
 
 /// Notifications to send worker about a requested state change.
@@ -71,6 +114,22 @@ pub struct Worker {
     #[metric(group = "running_action_infos")]
     pub running_action_infos: HashMap<OperationId, ActionInfoWithProps>,
 
+    /// When the running actions were started on the worker.
+    pub running_action_start_timestamps: HashMap<OperationId, WorkerTimestamp>,
+
+    /// When the running actions with a timeout should have finished.
+    pub running_action_deadlines: HashMap<OperationId, WorkerTimestamp>,
+
+    /// Timeouts in seconds of running actions that have no deadline yet.
+    /// Their deadline is set by `start_action_deadlines()`, so it uses the
+    /// same clock as the checks against it.
+    pub pending_action_timeouts_s: HashMap<OperationId, u64>,
+
+    /// Running actions the scheduler killed because they ran past their
+    /// timeout or another attempt of them finished first. The operation is
+    /// already completed, so updates from the worker about them are dropped.
+    pub killed_operation_ids: HashSet<OperationId>,
+
     /// Timestamp of last time this worker had been communicated with.
     // Warning: Do not update this timestamp without updating the placement of the worker in
     // the LRUCache in the Workers struct.
@@ -130,6 +189,10 @@ impl Worker {
             platform_properties,
             tx,
             running_action_infos: HashMap::new(),
+            running_action_start_timestamps: HashMap::new(),
+            running_action_deadlines: HashMap::new(),
+            pending_action_timeouts_s: HashMap::new(),
+            killed_operation_ids: HashSet::new(),
             last_update_timestamp: timestamp,
             is_paused: false,
             is_draining: false,
@@ -148,11 +211,21 @@ impl Worker {
 
     /// Sends the initial connection information to the worker. This generally is just meta info.
     /// This should only be sent once and should always be the first item in the stream.
-    pub fn send_initial_connection_result(&mut self) -> Result<(), Error> {
+    pub fn send_initial_connection_result(
+        &mut self,
+        maybe_heartbeat: Option<WorkerHeartbeat>,
+    ) -> Result<(), Error> {
         send_msg_to_worker(
             &mut self.tx,
             update_for_worker::Update::ConnectionResult(ConnectionResult {
                 worker_id: self.id.to_string(),
+                heartbeat_interval: maybe_heartbeat.map(|heartbeat| {
+                    Duration::from_secs(heartbeat.interval_s)
+                        .try_into()
+                        .unwrap_or_default()
+                }),
+                max_missed_heartbeats: maybe_heartbeat
+                    .map_or(0, |heartbeat| heartbeat.max_missed_heartbeats),
             }),
         )
         .err_tip(|| format!("Failed to send ConnectionResult to worker : {}", self.id))
@@ -188,9 +261,22 @@ impl Worker {
         let tx = &mut self.tx;
         let worker_platform_properties = &mut self.platform_properties;
         let running_action_infos = &mut self.running_action_infos;
+        let running_action_start_timestamps = &mut self.running_action_start_timestamps;
+        let pending_action_timeouts_s = &mut self.pending_action_timeouts_s;
         self.metrics.run_action.wrap(move || {
             let action_info_clone = action_info.clone();
             let operation_id_string = operation_id.to_string();
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+            running_action_start_timestamps.insert(operation_id.clone(), now);
+            // The worker runs actions with a zero timeout up to its own
+            // maximum timeout, which the scheduler does not know.
+            if !action_info.inner.timeout.is_zero() {
+                pending_action_timeouts_s
+                    .insert(operation_id.clone(), action_info.inner.timeout.as_secs());
+            }
             running_action_infos.insert(operation_id, action_info.clone());
             reduce_platform_properties(
                 worker_platform_properties,
@@ -214,12 +300,47 @@ impl Worker {
                 self.id, operation_id
             )
         })?;
+        self.running_action_start_timestamps.remove(operation_id);
+        self.running_action_deadlines.remove(operation_id);
+        self.pending_action_timeouts_s.remove(operation_id);
+        self.killed_operation_ids.remove(operation_id);
         self.restore_platform_properties(&action_info.platform_properties);
         self.is_paused = false;
         self.metrics.actions_completed.inc();
         Ok(())
     }
 
+    /// Sets the deadline of the actions started since the last call,
+    /// counting their timeout from `now_timestamp`.
+    pub(crate) fn start_action_deadlines(&mut self, now_timestamp: WorkerTimestamp) {
+        for (operation_id, timeout_s) in self.pending_action_timeouts_s.drain() {
+            // Actions without a timeout have a timeout of `Duration::MAX`,
+            // which overflows here.
+            if let Some(deadline) = now_timestamp.checked_add(timeout_s) {
+                self.running_action_deadlines.insert(operation_id, deadline);
+            }
+        }
+    }
+
+    /// Asks the worker to kill an action the scheduler already completed.
+    /// The action keeps its resources on the worker until the worker
+    /// reports that it finished.
+    pub(crate) fn kill_action(&mut self, operation_id: &OperationId) -> Result<(), Error> {
+        self.killed_operation_ids.insert(operation_id.clone());
+        send_msg_to_worker(
+            &mut self.tx,
+            update_for_worker::Update::KillOperationRequest(KillOperationRequest {
+                operation_id: operation_id.to_string(),
+            }),
+        )
+        .err_tip(|| {
+            format!(
+                "Failed to send KillOperationRequest to worker : {}",
+                self.id
+            )
+        })
+    }
+
     pub fn has_actions(&self) -> bool {
         !self.running_action_infos.is_empty()
     }
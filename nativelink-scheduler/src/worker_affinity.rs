@@ -0,0 +1,157 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+
+use nativelink_config::schedulers::WorkerAffinitySpec;
+use nativelink_metric::MetricsComponent;
+use nativelink_util::action_messages::{ActionInfo, WorkerId};
+use nativelink_util::common::DigestInfo;
+
+/// Default value for `WorkerAffinitySpec::decay_after_actions`.
+/// If this changes, remember to change the documentation in the config.
+const DEFAULT_DECAY_AFTER_ACTIONS: usize = 1000;
+
+/// Number of counters in the filter of every worker.
+const FILTER_SIZE: usize = 1 << 14;
+
+/// Number of counters every digest is counted in.
+const COUNTERS_PER_DIGEST: usize = 3;
+
+/// A counting bloom filter of the inputs of the actions a worker ran. The
+/// counters are halved every `decay_after_actions` actions, so inputs of
+/// old actions fade away.
+struct DecayingBloomFilter {
+    counters: Vec<u8>,
+    actions_since_decay: usize,
+}
+
+impl DecayingBloomFilter {
+    fn new() -> Self {
+        Self {
+            counters: vec![0; FILTER_SIZE],
+            actions_since_decay: 0,
+        }
+    }
+
+    fn insert(&mut self, digest: &DigestInfo) {
+        for index in counter_indexes(digest) {
+            self.counters[index] = self.counters[index].saturating_add(1);
+        }
+    }
+
+    /// Returns an upper bound of how often the digest was inserted, taking
+    /// the decay into account.
+    fn count(&self, digest: &DigestInfo) -> u8 {
+        counter_indexes(digest)
+            .map(|index| self.counters[index])
+            .min()
+            .unwrap_or(0)
+    }
+
+    fn decay(&mut self) {
+        for counter in &mut self.counters {
+            *counter /= 2;
+        }
+        self.actions_since_decay = 0;
+    }
+}
+
+/// Tracks the inputs of the actions every worker ran recently, so actions
+/// can be handed to workers that likely still have their inputs cached.
+#[derive(MetricsComponent)]
+pub struct WorkerAffinity {
+    #[metric(
+        help = "Number of actions run on a worker after which the inputs of earlier actions count half as much."
+    )]
+    decay_after_actions: usize,
+
+    #[metric(
+        help = "How many more actions a worker with the inputs of an action may run than the otherwise picked worker."
+    )]
+    max_load_difference: usize,
+
+    /// The inputs of the recent actions of every worker.
+    filters: HashMap<WorkerId, DecayingBloomFilter>,
+}
+
+impl WorkerAffinity {
+    pub fn new(spec: &WorkerAffinitySpec) -> Self {
+        let decay_after_actions = if spec.decay_after_actions == 0 {
+            DEFAULT_DECAY_AFTER_ACTIONS
+        } else {
+            spec.decay_after_actions
+        };
+        Self {
+            decay_after_actions,
+            max_load_difference: spec.max_load_difference,
+            filters: HashMap::new(),
+        }
+    }
+
+    pub const fn max_load_difference(&self) -> usize {
+        self.max_load_difference
+    }
+
+    /// Records that the worker was handed the action.
+    pub fn record_run(&mut self, worker_id: &WorkerId, action_info: &ActionInfo) {
+        let filter = self
+            .filters
+            .entry(*worker_id)
+            .or_insert_with(DecayingBloomFilter::new);
+        if filter.actions_since_decay >= self.decay_after_actions {
+            filter.decay();
+        }
+        for digest in input_digests(action_info) {
+            filter.insert(digest);
+        }
+        filter.actions_since_decay += 1;
+    }
+
+    /// Returns how much the inputs of the action overlap with the inputs
+    /// of the actions the worker ran recently. Zero if they do not overlap.
+    pub fn score(&self, worker_id: &WorkerId, action_info: &ActionInfo) -> u32 {
+        self.filters.get(worker_id).map_or(0, |filter| {
+            input_digests(action_info)
+                .into_iter()
+                .map(|digest| u32::from(filter.count(digest)))
+                .sum()
+        })
+    }
+
+    /// Forgets the inputs of a worker that left the pool.
+    pub fn remove_worker(&mut self, worker_id: &WorkerId) {
+        self.filters.remove(worker_id);
+    }
+}
+
+/// The digests of the inputs of an action the scheduler knows about. The
+/// files of the input tree are not visible to the scheduler.
+const fn input_digests(action_info: &ActionInfo) -> [&DigestInfo; 2] {
+    [&action_info.input_root_digest, &action_info.command_digest]
+}
+
+/// The digests are already hashes, so their bytes are used as is to find
+/// the counters of a digest.
+fn counter_indexes(digest: &DigestInfo) -> impl Iterator<Item = usize> + '_ {
+    digest
+        .packed_hash()
+        .chunks_exact(8)
+        .take(COUNTERS_PER_DIGEST)
+        .map(|chunk| {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(chunk);
+            (u64::from_le_bytes(bytes) % FILTER_SIZE as u64) as usize
+        })
+}
@@ -12,10 +12,13 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::sync::Arc;
+use std::time::Duration;
+
 use async_trait::async_trait;
 use nativelink_error::Error;
 use nativelink_metric::RootMetricsComponent;
-use nativelink_util::action_messages::{OperationId, WorkerId};
+use nativelink_util::action_messages::{ActionInfo, OperationId, WorkerId};
 use nativelink_util::operation_state_manager::UpdateOperationType;
 
 use crate::platform_property_manager::PlatformPropertyManager;
@@ -39,6 +42,13 @@ pub trait WorkerScheduler: Sync + Send + Unpin + RootMetricsComponent + 'static
         update: UpdateOperationType,
     ) -> Result<(), Error>;
 
+    /// Returns the `ActionInfo` of an operation the worker is currently running.
+    async fn get_running_action_info(
+        &self,
+        worker_id: &WorkerId,
+        operation_id: &OperationId,
+    ) -> Result<Arc<ActionInfo>, Error>;
+
     /// Event for when the keep alive message was received from the worker.
     async fn worker_keep_alive_received(
         &self,
@@ -53,6 +63,27 @@ pub trait WorkerScheduler: Sync + Send + Unpin + RootMetricsComponent + 'static
     /// external source.
     async fn remove_timedout_workers(&self, now_timestamp: WorkerTimestamp) -> Result<(), Error>;
 
+    /// Kills the actions that are still running once the grace period after
+    /// their timeout expired and completes them with a `DeadlineExceeded`
+    /// error. This is called periodically by an external source.
+    async fn kill_timedout_actions(&self, now_timestamp: WorkerTimestamp) -> Result<(), Error>;
+
+    /// Starts a second attempt on another worker of the actions that run
+    /// longer than usual, if hedging is enabled. This is called periodically
+    /// by an external source.
+    async fn hedge_slow_actions(&self, now_timestamp: WorkerTimestamp) -> Result<(), Error>;
+
+    /// Kills the actions no client listens to anymore, if cancelling
+    /// abandoned actions is enabled. This is called periodically by an
+    /// external source.
+    async fn cancel_abandoned_actions(&self) -> Result<(), Error>;
+
     /// Sets if the worker is draining or not.
     async fn set_drain_worker(&self, worker_id: &WorkerId, is_draining: bool) -> Result<(), Error>;
+
+    /// Stops scheduling actions on the worker, waits for the actions it is
+    /// running to finish and removes it from the pool. If the actions are
+    /// still running after `timeout`, a `DeadlineExceeded` error is returned
+    /// and the worker stays in the pool without receiving new actions.
+    async fn drain_worker(&self, worker_id: &WorkerId, timeout: Duration) -> Result<(), Error>;
 }
@@ -13,16 +13,18 @@
 // limitations under the License.
 
 use std::collections::HashMap;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 use nativelink_error::Error;
 use nativelink_macro::nativelink_test;
-use nativelink_proto::build::bazel::remote::execution::v2::ExecuteResponse;
+use nativelink_proto::build::bazel::remote::execution::v2::{
+    ExecuteResponse, ExecutedActionMetadata,
+};
 use nativelink_proto::google::longrunning::{operation, Operation};
 use nativelink_proto::google::rpc::Status;
 use nativelink_util::action_messages::{
     ActionResult, ActionStage, ActionState, ActionUniqueKey, ActionUniqueQualifier,
-    ExecutionMetadata, OperationId,
+    ExecutionMetadata, OperationId, ResourceUsage,
 };
 use nativelink_util::common::DigestInfo;
 use nativelink_util::digest_hasher::DigestHasherFunc;
@@ -81,6 +83,7 @@ async fn execute_response_status_message_is_some_on_success_test() -> Result<(),
             execution_completed_timestamp: SystemTime::UNIX_EPOCH,
             output_upload_start_timestamp: SystemTime::UNIX_EPOCH,
             output_upload_completed_timestamp: SystemTime::UNIX_EPOCH,
+            resource_usage: None,
         },
         server_logs: HashMap::default(),
         error: None,
@@ -93,3 +96,24 @@ async fn execute_response_status_message_is_some_on_success_test() -> Result<(),
 
     Ok(())
 }
+
+#[nativelink_test]
+async fn resource_usage_round_trips_through_executed_action_metadata_test() -> Result<(), Error> {
+    let execution_metadata = ExecutionMetadata {
+        resource_usage: Some(ResourceUsage {
+            peak_memory_bytes: 4096,
+            cpu_time: Duration::from_millis(1500),
+            peak_pids: 3,
+        }),
+        ..Default::default()
+    };
+
+    let executed_action_metadata = ExecutedActionMetadata::from(execution_metadata.clone());
+    assert_eq!(executed_action_metadata.auxiliary_metadata.len(), 1);
+    assert_eq!(
+        ExecutionMetadata::try_from(executed_action_metadata)?,
+        execution_metadata
+    );
+
+    Ok(())
+}
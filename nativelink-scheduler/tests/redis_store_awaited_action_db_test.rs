@@ -185,6 +185,9 @@ async fn add_action_smoke_test() -> Result<(), Error> {
                 digest_function: DigestHasherFunc::Sha256,
                 digest: DigestInfo::zero_digest(),
             }),
+            tool_invocation_id: None,
+            client_identity: None,
+            queue_priority: None,
         }),
         MockSystemTime::now().into(),
     );
@@ -0,0 +1,70 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use nativelink_config::schedulers::{PropertyType, SimpleSpec};
+use nativelink_error::Error;
+use nativelink_macro::nativelink_test;
+use nativelink_scheduler::scheduler_simulation::{
+    simulate, SimulatedWorkerPool, SimulationReport, TraceAction,
+};
+use pretty_assertions::assert_eq;
+
+#[nativelink_test]
+async fn simulate_reports_queue_times_and_utilization_test() -> Result<(), Error> {
+    let mut supported_props = HashMap::new();
+    supported_props.insert("cpu_count".to_string(), PropertyType::minimum);
+    supported_props.insert("os".to_string(), PropertyType::exact);
+    let spec = SimpleSpec {
+        supported_platform_properties: Some(supported_props),
+        ..Default::default()
+    };
+    let worker_pools = [SimulatedWorkerPool {
+        count: 2,
+        platform_properties: HashMap::from([
+            ("cpu_count".to_string(), "1".to_string()),
+            ("os".to_string(), "linux".to_string()),
+        ]),
+    }];
+    // Three actions arrive at once on two workers, so one of them waits
+    // for the first two to finish. No worker runs the last action.
+    let trace: Vec<TraceAction> = serde_json::from_str(
+        r#"[
+            { "arrival_ms": 0, "duration_ms": 1000, "platform_properties": { "cpu_count": "1" } },
+            { "arrival_ms": 0, "duration_ms": 1000, "platform_properties": { "cpu_count": "1" } },
+            { "arrival_ms": 0, "duration_ms": 1000, "platform_properties": { "cpu_count": "1" } },
+            { "arrival_ms": 500, "duration_ms": 1000, "platform_properties": { "os": "mac" } }
+        ]"#,
+    )
+    .unwrap();
+
+    let report = simulate(spec, &worker_pools, &trace).await?;
+    assert_eq!(
+        report,
+        SimulationReport {
+            actions: 4,
+            unscheduled_actions: 1,
+            makespan: Duration::from_secs(2),
+            mean_queue_time: Duration::from_millis(333),
+            p50_queue_time: Duration::ZERO,
+            p90_queue_time: Duration::ZERO,
+            p99_queue_time: Duration::ZERO,
+            max_queue_time: Duration::from_secs(1),
+            worker_utilization: 0.75,
+        }
+    );
+    Ok(())
+}
@@ -21,10 +21,16 @@ use std::sync::Arc;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use async_lock::Mutex;
+use bytes::Bytes;
 use futures::task::Poll;
 use futures::{poll, Stream, StreamExt};
 use mock_instant::thread_local::{MockClock, SystemTime as MockSystemTime};
-use nativelink_config::schedulers::{PropertyType, SimpleSpec};
+use nativelink_config::schedulers::{
+    CancelAbandonedActionsSpec, ExecutionHistorySpec, FairShareSpec, HedgingSpec, PriorityBand,
+    PropertyType, QueueLimitSpec, RetryPolicySpec, SchedulerPartitionSpec, SimpleSpec,
+    WorkerAffinitySpec, WorkerAllocationStrategy, WorkerHeartbeatSpec,
+};
+use nativelink_config::stores::MemorySpec;
 use nativelink_error::{make_err, Code, Error, ResultExt};
 use nativelink_macro::nativelink_test;
 use nativelink_metric::MetricsComponent;
@@ -37,9 +43,11 @@ use nativelink_scheduler::awaited_action_db::{
     SortedAwaitedActionState,
 };
 use nativelink_scheduler::default_scheduler_factory::memory_awaited_action_db_factory;
+use nativelink_scheduler::execution_history::ExecutionHistory;
 use nativelink_scheduler::simple_scheduler::SimpleScheduler;
 use nativelink_scheduler::worker::Worker;
 use nativelink_scheduler::worker_scheduler::WorkerScheduler;
+use nativelink_store::memory_store::MemoryStore;
 use nativelink_util::action_messages::{
     ActionInfo, ActionResult, ActionStage, ActionState, DirectoryInfo, ExecutionMetadata, FileInfo,
     NameOrPath, OperationId, SymlinkInfo, WorkerId, INTERNAL_ERROR_EXIT_CODE,
@@ -50,6 +58,7 @@ use nativelink_util::operation_state_manager::{
     ActionStateResult, ClientStateManager, OperationFilter, UpdateOperationType,
 };
 use nativelink_util::platform_properties::{PlatformProperties, PlatformPropertyValue};
+use nativelink_util::store_trait::{Store, StoreLike};
 use pretty_assertions::assert_eq;
 use tokio::sync::{mpsc, Notify};
 use utils::scheduler_utils::{make_base_action_info, INSTANCE_NAME};
@@ -107,6 +116,7 @@ async fn verify_initial_connection_message(
         update: Some(update_for_worker::Update::ConnectionResult(
             ConnectionResult {
                 worker_id: worker_id.to_string(),
+                ..Default::default()
             },
         )),
     };
@@ -620,6 +630,67 @@ async fn set_drain_worker_pauses_and_resumes_worker_test() -> Result<(), Error>
     Ok(())
 }
 
+#[nativelink_test]
+async fn drain_worker_removes_worker_after_running_actions_test() -> Result<(), Error> {
+    let worker_id: WorkerId = WorkerId(Uuid::new_v4());
+
+    let task_change_notify = Arc::new(Notify::new());
+    let (scheduler, _worker_scheduler) = SimpleScheduler::new_with_callback(
+        &SimpleSpec::default(),
+        memory_awaited_action_db_factory(
+            0,
+            &task_change_notify.clone(),
+            MockInstantWrapped::default,
+        ),
+        || async move {},
+        task_change_notify,
+        MockInstantWrapped::default,
+    );
+    let action_digest = DigestInfo::new([99u8; 32], 512);
+
+    let mut rx_from_worker =
+        setup_new_worker(&scheduler, worker_id, PlatformProperties::default()).await?;
+    let insert_timestamp = make_system_time(1);
+    let _action_listener =
+        setup_action(&scheduler, action_digest, HashMap::new(), insert_timestamp).await?;
+
+    let operation_id = match rx_from_worker.recv().await.unwrap().update {
+        Some(update_for_worker::Update::StartAction(start_execute)) => {
+            OperationId::from(start_execute.operation_id)
+        }
+        v => panic!("Expected StartAction, got : {v:?}"),
+    };
+
+    // The worker is still running the action, so the drain times out.
+    let err = scheduler
+        .drain_worker(&worker_id, Duration::from_millis(10))
+        .await
+        .expect_err("Expected drain_worker to time out");
+    assert_eq!(err.code, Code::DeadlineExceeded);
+
+    let drain_fut = scheduler.drain_worker(&worker_id, Duration::from_secs(10));
+    tokio::pin!(drain_fut);
+    assert!(poll!(&mut drain_fut).is_pending());
+
+    // The worker finishes its action, which lets the drain complete.
+    scheduler
+        .update_action(
+            &worker_id,
+            &operation_id,
+            UpdateOperationType::UpdateWithError(make_err!(Code::Unavailable, "Retry")),
+        )
+        .await?;
+    drain_fut.await?;
+
+    assert_eq!(
+        rx_from_worker.recv().await.unwrap().update,
+        Some(update_for_worker::Update::Disconnect(()))
+    );
+    assert!(scheduler.set_drain_worker(&worker_id, false).await.is_err());
+
+    Ok(())
+}
+
 #[nativelink_test]
 async fn worker_should_not_queue_if_properties_dont_match_test() -> Result<(), Error> {
     let worker_id1: WorkerId = WorkerId(Uuid::new_v4());
@@ -1199,6 +1270,258 @@ async fn worker_timesout_reschedules_running_job_test() -> Result<(), Error> {
     Ok(())
 }
 
+#[nativelink_test]
+async fn worker_heartbeat_delays_eviction_test() -> Result<(), Error> {
+    const HEARTBEAT_INTERVAL_S: u64 = 2;
+    const MAX_MISSED_HEARTBEATS: u32 = 3;
+    let worker_id: WorkerId = WorkerId(Uuid::new_v4());
+    let task_change_notify = Arc::new(Notify::new());
+    let (scheduler, _worker_scheduler) = SimpleScheduler::new_with_callback(
+        &SimpleSpec {
+            worker_timeout_s: WORKER_TIMEOUT_S,
+            experimental_worker_heartbeat: Some(WorkerHeartbeatSpec {
+                interval_s: HEARTBEAT_INTERVAL_S,
+                max_missed_heartbeats: MAX_MISSED_HEARTBEATS,
+            }),
+            ..Default::default()
+        },
+        memory_awaited_action_db_factory(
+            0,
+            &task_change_notify.clone(),
+            MockInstantWrapped::default,
+        ),
+        || async move {},
+        task_change_notify,
+        MockInstantWrapped::default,
+    );
+
+    let (tx, mut rx_from_worker) = mpsc::unbounded_channel();
+    scheduler
+        .add_worker(Worker::new(
+            worker_id,
+            PlatformProperties::default(),
+            tx,
+            NOW_TIME,
+        ))
+        .await?;
+    {
+        // The worker is told which heartbeat to keep.
+        let msg_for_worker = rx_from_worker.recv().await.unwrap();
+        let Some(update_for_worker::Update::ConnectionResult(connection_result)) =
+            msg_for_worker.update
+        else {
+            panic!("Expected ConnectionResult, got : {msg_for_worker:?}");
+        };
+        assert_eq!(
+            connection_result
+                .heartbeat_interval
+                .map(|heartbeat_interval| heartbeat_interval.seconds),
+            Some(HEARTBEAT_INTERVAL_S as i64)
+        );
+        assert_eq!(
+            connection_result.max_missed_heartbeats,
+            MAX_MISSED_HEARTBEATS
+        );
+    }
+
+    // Missing up to the allowed number of heartbeats keeps the worker in the
+    // pool, even though this is past `worker_timeout_s`.
+    let eviction_timeout_s = HEARTBEAT_INTERVAL_S * (u64::from(MAX_MISSED_HEARTBEATS) + 1);
+    scheduler
+        .remove_timedout_workers(NOW_TIME + eviction_timeout_s - 1)
+        .await?;
+    assert!(rx_from_worker.try_recv().is_err());
+
+    // Missing one more evicts it.
+    scheduler
+        .remove_timedout_workers(NOW_TIME + eviction_timeout_s)
+        .await?;
+    assert_eq!(
+        rx_from_worker.recv().await.unwrap(),
+        UpdateForWorker {
+            update: Some(update_for_worker::Update::Disconnect(()))
+        }
+    );
+
+    Ok(())
+}
+
+#[nativelink_test]
+async fn graceful_shutdown_requeues_running_actions_test() -> Result<(), Error> {
+    let worker_id: WorkerId = WorkerId(Uuid::new_v4());
+    let task_change_notify = Arc::new(Notify::new());
+    let (scheduler, _worker_scheduler) = SimpleScheduler::new_with_callback(
+        &SimpleSpec::default(),
+        memory_awaited_action_db_factory(
+            0,
+            &task_change_notify.clone(),
+            MockInstantWrapped::default,
+        ),
+        || async move {},
+        task_change_notify,
+        MockInstantWrapped::default,
+    );
+    let action_digest = DigestInfo::new([99u8; 32], 512);
+
+    let mut rx_from_worker =
+        setup_new_worker(&scheduler, worker_id, PlatformProperties::default()).await?;
+    let mut action_listener = setup_action(
+        &scheduler,
+        action_digest,
+        HashMap::new(),
+        make_system_time(1),
+    )
+    .await?;
+    let operation_id = match rx_from_worker.recv().await.unwrap().update {
+        Some(update_for_worker::Update::StartAction(start_execute)) => start_execute.operation_id,
+        v => panic!("Expected StartAction, got : {v:?}"),
+    };
+    assert_eq!(
+        action_listener.changed().await?.stage,
+        ActionStage::Executing
+    );
+
+    // The action is still running once the grace period is over, so it is
+    // killed on the worker and queued again for the next scheduler.
+    scheduler.shutdown(Duration::from_millis(10)).await?;
+    match rx_from_worker.recv().await.unwrap().update {
+        Some(update_for_worker::Update::KillOperationRequest(kill_operation_request)) => {
+            assert_eq!(kill_operation_request.operation_id, operation_id);
+        }
+        v => panic!("Expected KillOperationRequest, got : {v:?}"),
+    }
+    assert_eq!(
+        rx_from_worker.recv().await.unwrap(),
+        UpdateForWorker {
+            update: Some(update_for_worker::Update::Disconnect(()))
+        }
+    );
+    assert_eq!(action_listener.changed().await?.stage, ActionStage::Queued);
+
+    // New actions are rejected.
+    let err = setup_action(
+        &scheduler,
+        DigestInfo::new([98u8; 32], 512),
+        HashMap::new(),
+        make_system_time(2),
+    )
+    .await
+    .err()
+    .unwrap();
+    assert_eq!(err.code, Code::Unavailable);
+
+    Ok(())
+}
+
+#[nativelink_test]
+async fn execution_history_spreads_long_actions_test() -> Result<(), Error> {
+    const EXECUTION_DURATION: Duration = Duration::from_secs(120);
+    let worker_id1: WorkerId = WorkerId(Uuid::new_v4());
+    let worker_id2: WorkerId = WorkerId(Uuid::new_v4());
+    let execution_history = Arc::new(ExecutionHistory::new(
+        &ExecutionHistorySpec::default(),
+        Store::new(MemoryStore::new(&MemorySpec::default())),
+    ));
+    // All actions share the same command, which ran long before.
+    let command_digest =
+        make_base_action_info(UNIX_EPOCH, DigestInfo::zero_digest()).command_digest;
+    let execution_start_timestamp = make_system_time(10);
+    execution_history
+        .record(
+            &command_digest,
+            &ActionResult {
+                stdout_digest: DigestInfo::new([1u8; 32], 100),
+                execution_metadata: ExecutionMetadata {
+                    execution_start_timestamp,
+                    execution_completed_timestamp: execution_start_timestamp + EXECUTION_DURATION,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        )
+        .await?;
+
+    let task_change_notify = Arc::new(Notify::new());
+    let (scheduler, _worker_scheduler) = SimpleScheduler::new_with_execution_history(
+        &SimpleSpec::default(),
+        memory_awaited_action_db_factory(0, &task_change_notify.clone(), SystemTime::now),
+        task_change_notify,
+        Some(execution_history),
+    );
+
+    // Clients learn what to expect while the action is queued.
+    let action_listener1 = setup_action(
+        &scheduler,
+        DigestInfo::new([1u8; 32], 512),
+        HashMap::new(),
+        make_system_time(1),
+    )
+    .await?;
+    let queue_position = action_listener1.queue_position().await?.unwrap();
+    assert_eq!(
+        queue_position.estimated_execution_duration,
+        Some(EXECUTION_DURATION)
+    );
+    assert_eq!(queue_position.estimated_output_bytes, Some(100));
+
+    let mut rx_from_worker1 =
+        setup_new_worker(&scheduler, worker_id1, PlatformProperties::default()).await?;
+    match rx_from_worker1.recv().await.unwrap().update {
+        Some(update_for_worker::Update::StartAction(_)) => {}
+        v => panic!("Expected StartAction, got : {v:?}"),
+    }
+    let mut rx_from_worker2 =
+        setup_new_worker(&scheduler, worker_id2, PlatformProperties::default()).await?;
+
+    // The least recently used worker already runs a long action, so the
+    // second long action goes to the other worker.
+    let _action_listener2 = setup_action(
+        &scheduler,
+        DigestInfo::new([2u8; 32], 512),
+        HashMap::new(),
+        make_system_time(2),
+    )
+    .await?;
+    match rx_from_worker2.recv().await.unwrap().update {
+        Some(update_for_worker::Update::StartAction(_)) => {}
+        v => panic!("Expected StartAction, got : {v:?}"),
+    }
+    assert!(rx_from_worker1.try_recv().is_err());
+
+    Ok(())
+}
+
+#[nativelink_test]
+async fn execution_history_reads_history_recorded_after_miss_test() -> Result<(), Error> {
+    let store = Store::new(MemoryStore::new(&MemorySpec::default()));
+    let execution_history1 = ExecutionHistory::new(&ExecutionHistorySpec::default(), store.clone());
+    let execution_history2 = ExecutionHistory::new(&ExecutionHistorySpec::default(), store);
+    let command_digest = DigestInfo::new([3u8; 32], 512);
+    assert_eq!(execution_history1.get(&command_digest).await?, None);
+
+    // Another scheduler sharing the store records the command.
+    let execution_start_timestamp = make_system_time(10);
+    execution_history2
+        .record(
+            &command_digest,
+            &ActionResult {
+                execution_metadata: ExecutionMetadata {
+                    execution_start_timestamp,
+                    execution_completed_timestamp: execution_start_timestamp
+                        + Duration::from_secs(5),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        )
+        .await?;
+
+    let stats = execution_history1.get(&command_digest).await?.unwrap();
+    assert_eq!(stats.executions, 1);
+    assert_eq!(stats.mean_execution_duration(), Duration::from_secs(5));
+    Ok(())
+}
+
 #[nativelink_test]
 async fn update_action_sends_completed_result_to_client_test() -> Result<(), Error> {
     let worker_id: WorkerId = WorkerId(Uuid::new_v4());
@@ -1270,6 +1593,7 @@ async fn update_action_sends_completed_result_to_client_test() -> Result<(), Err
             execution_completed_timestamp: make_system_time(11),
             output_upload_start_timestamp: make_system_time(12),
             output_upload_completed_timestamp: make_system_time(13),
+            resource_usage: None,
         },
         server_logs: HashMap::default(),
         error: None,
@@ -1376,6 +1700,7 @@ async fn update_action_sends_completed_result_after_disconnect() -> Result<(), E
             execution_completed_timestamp: make_system_time(11),
             output_upload_start_timestamp: make_system_time(12),
             output_upload_completed_timestamp: make_system_time(13),
+            resource_usage: None,
         },
         server_logs: HashMap::default(),
         error: None,
@@ -1475,6 +1800,7 @@ async fn update_action_with_wrong_worker_id_errors_test() -> Result<(), Error> {
             execution_completed_timestamp: make_system_time(11),
             output_upload_start_timestamp: make_system_time(12),
             output_upload_completed_timestamp: make_system_time(13),
+            resource_usage: None,
         },
         server_logs: HashMap::default(),
         error: None,
@@ -1606,6 +1932,7 @@ async fn does_not_crash_if_operation_joined_then_relaunched() -> Result<(), Erro
             execution_completed_timestamp: SystemTime::UNIX_EPOCH,
             output_upload_start_timestamp: SystemTime::UNIX_EPOCH,
             output_upload_completed_timestamp: SystemTime::UNIX_EPOCH,
+            resource_usage: None,
         },
         server_logs: HashMap::default(),
         error: None,
@@ -1652,85 +1979,683 @@ async fn does_not_crash_if_operation_joined_then_relaunched() -> Result<(), Erro
     Ok(())
 }
 
-/// This tests to ensure that platform property restrictions allow jobs to continue to run after
-/// a job finished on a specific worker (eg: restore platform properties).
+/// This tests that identical actions added shortly after an action finished
+/// reuse its result instead of running again.
 #[nativelink_test]
-async fn run_two_jobs_on_same_worker_with_platform_properties_restrictions() -> Result<(), Error> {
+async fn completed_action_dedup_window_reuses_result_test() -> Result<(), Error> {
+    const DEDUP_WINDOW: Duration = Duration::from_secs(10);
     let worker_id: WorkerId = WorkerId(Uuid::new_v4());
 
-    let mut supported_props = HashMap::new();
-    supported_props.insert("prop1".to_string(), PropertyType::minimum);
     let task_change_notify = Arc::new(Notify::new());
     let (scheduler, _worker_scheduler) = SimpleScheduler::new_with_callback(
-        &SimpleSpec {
-            supported_platform_properties: Some(supported_props),
-            ..Default::default()
-        },
+        &SimpleSpec::default(),
         memory_awaited_action_db_factory(
             0,
             &task_change_notify.clone(),
             MockInstantWrapped::default,
-        ),
+        )
+        .with_completed_action_dedup_window(DEDUP_WINDOW),
         || async move {},
         task_change_notify,
         MockInstantWrapped::default,
     );
-    let action_digest1 = DigestInfo::new([11u8; 32], 512);
-    let action_digest2 = DigestInfo::new([99u8; 32], 512);
+    let action_digest = DigestInfo::new([99u8; 32], 512);
 
-    let mut properties = HashMap::new();
-    properties.insert("prop1".to_string(), PlatformPropertyValue::Minimum(1));
-    let platform_properties = PlatformProperties {
-        properties: properties.clone(),
-    };
-    let action_props: HashMap<String, String> = properties
-        .iter()
-        .map(|(k, v)| (k.clone(), v.as_str().into_owned()))
-        .collect();
-    let mut rx_from_worker = setup_new_worker(&scheduler, worker_id, platform_properties.clone())
-        .await
-        .unwrap();
-    let insert_timestamp1 = make_system_time(1);
-    let mut client1_action_listener = setup_action(
+    let mut rx_from_worker =
+        setup_new_worker(&scheduler, worker_id, PlatformProperties::default()).await?;
+    let mut action_listener = setup_action(
         &scheduler,
-        action_digest1,
-        action_props.clone(),
-        insert_timestamp1,
+        action_digest,
+        HashMap::new(),
+        make_system_time(1),
     )
-    .await
-    .unwrap();
-    let insert_timestamp2 = make_system_time(1);
-    let mut client2_action_listener =
-        setup_action(&scheduler, action_digest2, action_props, insert_timestamp2)
-            .await
-            .unwrap();
-
-    let operation_id1 = match rx_from_worker.recv().await.unwrap().update {
+    .await?;
+    let operation_id = match rx_from_worker.recv().await.unwrap().update {
         Some(update_for_worker::Update::StartAction(start_execute)) => {
             OperationId::from(start_execute.operation_id)
         }
         v => panic!("Expected StartAction, got : {v:?}"),
     };
-    {
-        let state_1 = client1_action_listener.changed().await.unwrap();
-        let state_2 = client2_action_listener.changed().await.unwrap();
-        // First client should be in an Executing state.
-        assert_eq!(state_1.stage, ActionStage::Executing);
-        // Second client should be in a queued state.
-        assert_eq!(state_2.stage, ActionStage::Queued);
-    }
+    assert_eq!(
+        action_listener.changed().await?.stage,
+        ActionStage::Executing
+    );
 
     let action_result = ActionResult {
         output_files: Vec::default(),
         output_folders: Vec::default(),
-        output_file_symlinks: Vec::default(),
         output_directory_symlinks: Vec::default(),
+        output_file_symlinks: Vec::default(),
         exit_code: 0,
-        stdout_digest: DigestInfo::new([6u8; 32], 19),
-        stderr_digest: DigestInfo::new([7u8; 32], 20),
+        stdout_digest: DigestInfo::new([1u8; 32], 512),
+        stderr_digest: DigestInfo::new([2u8; 32], 512),
         execution_metadata: ExecutionMetadata {
             worker: worker_id.to_string(),
-            queued_timestamp: make_system_time(5),
+            queued_timestamp: SystemTime::UNIX_EPOCH,
+            worker_start_timestamp: SystemTime::UNIX_EPOCH,
+            worker_completed_timestamp: SystemTime::UNIX_EPOCH,
+            input_fetch_start_timestamp: SystemTime::UNIX_EPOCH,
+            input_fetch_completed_timestamp: SystemTime::UNIX_EPOCH,
+            execution_start_timestamp: SystemTime::UNIX_EPOCH,
+            execution_completed_timestamp: SystemTime::UNIX_EPOCH,
+            output_upload_start_timestamp: SystemTime::UNIX_EPOCH,
+            output_upload_completed_timestamp: SystemTime::UNIX_EPOCH,
+            resource_usage: None,
+        },
+        server_logs: HashMap::default(),
+        error: None,
+        message: String::new(),
+    };
+    scheduler
+        .update_action(
+            &worker_id,
+            &operation_id,
+            UpdateOperationType::UpdateWithActionStage(ActionStage::Completed(
+                action_result.clone(),
+            )),
+        )
+        .await?;
+    assert_eq!(
+        action_listener.changed().await?.stage,
+        ActionStage::Completed(action_result.clone())
+    );
+
+    {
+        // Within the window the result is reused and nothing is sent to the worker.
+        let mut action_listener = setup_action(
+            &scheduler,
+            action_digest,
+            HashMap::new(),
+            make_system_time(2),
+        )
+        .await?;
+        assert_eq!(
+            action_listener.changed().await?.stage,
+            ActionStage::Completed(action_result)
+        );
+        assert!(rx_from_worker.try_recv().is_err());
+    }
+
+    MockClock::advance(DEDUP_WINDOW + Duration::from_secs(1));
+    {
+        // After the window the action runs again.
+        let mut action_listener = setup_action(
+            &scheduler,
+            action_digest,
+            HashMap::new(),
+            make_system_time(3),
+        )
+        .await?;
+        match rx_from_worker.recv().await.unwrap().update {
+            Some(update_for_worker::Update::StartAction(_)) => { /* Success */ }
+            v => panic!("Expected StartAction, got : {v:?}"),
+        }
+        assert_eq!(
+            action_listener.changed().await?.stage,
+            ActionStage::Executing
+        );
+    }
+
+    Ok(())
+}
+
+/// This tests that slow actions get a second attempt on another worker and
+/// that the attempt finishing first completes the action.
+#[nativelink_test]
+async fn hedges_slow_action_on_other_worker_test() -> Result<(), Error> {
+    fn make_action_result(worker_id: WorkerId) -> ActionResult {
+        ActionResult {
+            output_files: Vec::default(),
+            output_folders: Vec::default(),
+            output_directory_symlinks: Vec::default(),
+            output_file_symlinks: Vec::default(),
+            exit_code: 0,
+            stdout_digest: DigestInfo::new([1u8; 32], 512),
+            stderr_digest: DigestInfo::new([2u8; 32], 512),
+            execution_metadata: ExecutionMetadata {
+                worker: worker_id.to_string(),
+                queued_timestamp: SystemTime::UNIX_EPOCH,
+                worker_start_timestamp: SystemTime::UNIX_EPOCH,
+                worker_completed_timestamp: SystemTime::UNIX_EPOCH,
+                input_fetch_start_timestamp: SystemTime::UNIX_EPOCH,
+                input_fetch_completed_timestamp: SystemTime::UNIX_EPOCH,
+                execution_start_timestamp: SystemTime::UNIX_EPOCH,
+                execution_completed_timestamp: SystemTime::UNIX_EPOCH,
+                output_upload_start_timestamp: SystemTime::UNIX_EPOCH,
+                output_upload_completed_timestamp: SystemTime::UNIX_EPOCH,
+                resource_usage: None,
+            },
+            server_logs: HashMap::default(),
+            error: None,
+            message: String::new(),
+        }
+    }
+    fn start_action_operation_id(update: Option<update_for_worker::Update>) -> OperationId {
+        match update {
+            Some(update_for_worker::Update::StartAction(start_execute)) => {
+                OperationId::from(start_execute.operation_id)
+            }
+            v => panic!("Expected StartAction, got : {v:?}"),
+        }
+    }
+
+    let worker_id1: WorkerId = WorkerId(Uuid::new_v4());
+    let worker_id2: WorkerId = WorkerId(Uuid::new_v4());
+
+    let task_change_notify = Arc::new(Notify::new());
+    let (scheduler, _worker_scheduler) = SimpleScheduler::new_with_callback(
+        &SimpleSpec {
+            experimental_hedging: Some(HedgingSpec {
+                percentile: 50,
+                min_samples: 1,
+            }),
+            ..Default::default()
+        },
+        memory_awaited_action_db_factory(
+            0,
+            &task_change_notify.clone(),
+            MockInstantWrapped::default,
+        ),
+        || async move {},
+        task_change_notify,
+        MockInstantWrapped::default,
+    );
+    let now_timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    // Run one action, so there is a duration to compare with.
+    let mut rx_from_worker1 =
+        setup_new_worker(&scheduler, worker_id1, PlatformProperties::default()).await?;
+    let mut action_listener = setup_action(
+        &scheduler,
+        DigestInfo::new([11u8; 32], 512),
+        HashMap::new(),
+        make_system_time(1),
+    )
+    .await?;
+    let operation_id = start_action_operation_id(rx_from_worker1.recv().await.unwrap().update);
+    scheduler
+        .update_action(
+            &worker_id1,
+            &operation_id,
+            UpdateOperationType::UpdateWithActionStage(ActionStage::Completed(make_action_result(
+                worker_id1,
+            ))),
+        )
+        .await?;
+    assert!(action_listener.changed().await?.stage.is_finished());
+
+    let mut rx_from_worker2 =
+        setup_new_worker(&scheduler, worker_id2, PlatformProperties::default()).await?;
+    let mut action_listener = setup_action(
+        &scheduler,
+        DigestInfo::new([99u8; 32], 512),
+        HashMap::new(),
+        make_system_time(2),
+    )
+    .await?;
+    assert_eq!(
+        action_listener.changed().await?.stage,
+        ActionStage::Executing
+    );
+    let (primary_worker_id, mut rx_from_primary, hedge_worker_id, mut rx_from_hedge) =
+        if let Ok(update) = rx_from_worker1.try_recv() {
+            start_action_operation_id(update.update);
+            (worker_id1, rx_from_worker1, worker_id2, rx_from_worker2)
+        } else {
+            start_action_operation_id(rx_from_worker2.recv().await.unwrap().update);
+            (worker_id2, rx_from_worker2, worker_id1, rx_from_worker1)
+        };
+
+    // The action now runs longer than the one before, so it is hedged.
+    scheduler.hedge_slow_actions(now_timestamp + 10).await?;
+    let operation_id = start_action_operation_id(rx_from_hedge.recv().await.unwrap().update);
+    // It is only hedged once.
+    scheduler.hedge_slow_actions(now_timestamp + 20).await?;
+    assert!(rx_from_hedge.try_recv().is_err());
+
+    // The second attempt finishes first, so it completes the action and the
+    // original attempt is killed.
+    let action_result = make_action_result(hedge_worker_id);
+    scheduler
+        .update_action(
+            &hedge_worker_id,
+            &operation_id,
+            UpdateOperationType::UpdateWithActionStage(ActionStage::Completed(
+                action_result.clone(),
+            )),
+        )
+        .await?;
+    assert_eq!(
+        action_listener.changed().await?.stage,
+        ActionStage::Completed(action_result)
+    );
+    match rx_from_primary.recv().await.unwrap().update {
+        Some(update_for_worker::Update::KillOperationRequest(kill_operation_request)) => {
+            assert_eq!(
+                kill_operation_request.operation_id,
+                operation_id.to_string()
+            );
+        }
+        v => panic!("Expected KillOperationRequest, got : {v:?}"),
+    }
+
+    // The killed attempt finishing later does not change the result.
+    scheduler
+        .update_action(
+            &primary_worker_id,
+            &operation_id,
+            UpdateOperationType::UpdateWithError(make_err!(Code::Aborted, "Killed")),
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// This tests that an action nobody listens to anymore is killed on its
+/// worker and completed as cancelled once the grace period passed.
+#[nativelink_test]
+async fn cancels_abandoned_action_on_worker_test() -> Result<(), Error> {
+    const GRACE_PERIOD_S: u64 = 10;
+    let worker_id: WorkerId = WorkerId(Uuid::new_v4());
+
+    let task_change_notify = Arc::new(Notify::new());
+    let (scheduler, _worker_scheduler) = SimpleScheduler::new_with_callback(
+        &SimpleSpec {
+            experimental_cancel_abandoned_actions: Some(CancelAbandonedActionsSpec {
+                grace_period_s: GRACE_PERIOD_S,
+            }),
+            ..Default::default()
+        },
+        memory_awaited_action_db_factory(
+            0,
+            &task_change_notify.clone(),
+            MockInstantWrapped::default,
+        ),
+        || async move {},
+        task_change_notify,
+        MockInstantWrapped::default,
+    );
+    let mut rx_from_worker =
+        setup_new_worker(&scheduler, worker_id, PlatformProperties::default()).await?;
+    let mut action_listener = setup_action(
+        &scheduler,
+        DigestInfo::new([99u8; 32], 512),
+        HashMap::new(),
+        make_system_time(1),
+    )
+    .await?;
+    let operation_id = match rx_from_worker.recv().await.unwrap().update {
+        Some(update_for_worker::Update::StartAction(start_execute)) => {
+            OperationId::from(start_execute.operation_id)
+        }
+        v => panic!("Expected StartAction, got : {v:?}"),
+    };
+    assert_eq!(
+        action_listener.changed().await?.stage,
+        ActionStage::Executing
+    );
+
+    // The client listened recently, so the action keeps running.
+    scheduler.cancel_abandoned_actions().await?;
+    assert!(rx_from_worker.try_recv().is_err());
+
+    MockClock::advance(Duration::from_secs(GRACE_PERIOD_S + 1));
+    scheduler.cancel_abandoned_actions().await?;
+    match rx_from_worker.recv().await.unwrap().update {
+        Some(update_for_worker::Update::KillOperationRequest(kill_operation_request)) => {
+            assert_eq!(
+                kill_operation_request.operation_id,
+                operation_id.to_string()
+            );
+        }
+        v => panic!("Expected KillOperationRequest, got : {v:?}"),
+    }
+    match &action_listener.changed().await?.stage {
+        ActionStage::Completed(action_result) => {
+            assert_eq!(
+                action_result.error.as_ref().map(|err| err.code),
+                Some(Code::Cancelled)
+            );
+        }
+        v => panic!("Expected Completed, got : {v:?}"),
+    }
+
+    // The worker reporting the killed action frees its slot.
+    scheduler
+        .update_action(
+            &worker_id,
+            &operation_id,
+            UpdateOperationType::UpdateWithError(make_err!(Code::Aborted, "Killed")),
+        )
+        .await?;
+    // It is only killed once.
+    scheduler.cancel_abandoned_actions().await?;
+    assert!(rx_from_worker.try_recv().is_err());
+
+    Ok(())
+}
+
+/// This tests that new actions of a platform pool are rejected while its
+/// queue is full and accepted again once its actions left the queue.
+#[nativelink_test]
+async fn queue_limit_rejects_actions_of_full_pool_test() -> Result<(), Error> {
+    let worker_id: WorkerId = WorkerId(Uuid::new_v4());
+
+    let mut prop_defs = HashMap::new();
+    prop_defs.insert("pool".to_string(), PropertyType::exact);
+
+    let task_change_notify = Arc::new(Notify::new());
+    let (scheduler, _worker_scheduler) = SimpleScheduler::new_with_callback(
+        &SimpleSpec {
+            supported_platform_properties: Some(prop_defs),
+            experimental_queue_limits: vec![QueueLimitSpec {
+                platform_property: "pool".to_string(),
+                value: "gpu".to_string(),
+                max_queued_actions: 1,
+                retry_after_s: 0,
+            }],
+            ..Default::default()
+        },
+        memory_awaited_action_db_factory(
+            0,
+            &task_change_notify.clone(),
+            MockInstantWrapped::default,
+        ),
+        || async move {},
+        task_change_notify,
+        MockInstantWrapped::default,
+    );
+    let gpu_properties = HashMap::from([("pool".to_string(), "gpu".to_string())]);
+
+    let _action_listener1 = setup_action(
+        &scheduler,
+        DigestInfo::new([1u8; 32], 512),
+        gpu_properties.clone(),
+        make_system_time(1),
+    )
+    .await?;
+    let Err(err) = setup_action(
+        &scheduler,
+        DigestInfo::new([2u8; 32], 512),
+        gpu_properties.clone(),
+        make_system_time(2),
+    )
+    .await
+    else {
+        panic!("Expected the action to be rejected");
+    };
+    assert_eq!(err.code, Code::ResourceExhausted);
+    assert!(
+        err.to_string().contains("retry after 60 seconds"),
+        "Expected a retry hint, got : {err:?}"
+    );
+    // Actions of other pools are not limited.
+    let _action_listener2 = setup_action(
+        &scheduler,
+        DigestInfo::new([3u8; 32], 512),
+        HashMap::from([("pool".to_string(), "cpu".to_string())]),
+        make_system_time(3),
+    )
+    .await?;
+
+    // Once the queued action runs, the pool has room again.
+    let mut worker_properties = PlatformProperties::default();
+    worker_properties.properties.insert(
+        "pool".to_string(),
+        PlatformPropertyValue::Exact("gpu".to_string()),
+    );
+    let mut rx_from_worker = setup_new_worker(&scheduler, worker_id, worker_properties).await?;
+    match rx_from_worker.recv().await.unwrap().update {
+        Some(update_for_worker::Update::StartAction(_)) => {}
+        v => panic!("Expected StartAction, got : {v:?}"),
+    }
+    scheduler.do_try_match_for_test().await?;
+    let _action_listener3 = setup_action(
+        &scheduler,
+        DigestInfo::new([2u8; 32], 512),
+        gpu_properties,
+        make_system_time(4),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// This tests that with fair share enabled an identity that queued a single
+/// action does not wait for all the actions another identity queued before.
+#[nativelink_test]
+async fn fair_share_alternates_between_identities_test() -> Result<(), Error> {
+    let worker_id: WorkerId = WorkerId(Uuid::new_v4());
+
+    let mut supported_props = HashMap::new();
+    supported_props.insert("prop1".to_string(), PropertyType::minimum);
+    let task_change_notify = Arc::new(Notify::new());
+    let (scheduler, _worker_scheduler) = SimpleScheduler::new_with_callback(
+        &SimpleSpec {
+            supported_platform_properties: Some(supported_props),
+            experimental_fair_share: Some(FairShareSpec::default()),
+            ..Default::default()
+        },
+        memory_awaited_action_db_factory(
+            0,
+            &task_change_notify.clone(),
+            MockInstantWrapped::default,
+        ),
+        || async move {},
+        task_change_notify,
+        MockInstantWrapped::default,
+    );
+
+    let mut properties = HashMap::new();
+    properties.insert("prop1".to_string(), PlatformPropertyValue::Minimum(1));
+    let action_props: HashMap<String, String> = properties
+        .iter()
+        .map(|(k, v)| (k.clone(), v.as_str().into_owned()))
+        .collect();
+
+    // Identity "a" queues three actions before identity "b" queues one.
+    let queued_actions = [(1u8, "a"), (2u8, "a"), (3u8, "a"), (4u8, "b")];
+    let mut action_listeners = Vec::new();
+    for (i, identity) in queued_actions {
+        let mut action_info = make_base_action_info(
+            make_system_time(u64::from(i)),
+            DigestInfo::new([i; 32], 512),
+        );
+        let action_info_mut = Arc::make_mut(&mut action_info);
+        action_info_mut.platform_properties = action_props.clone();
+        action_info_mut.client_identity = Some(identity.to_string());
+        action_listeners.push(
+            scheduler
+                .add_action(OperationId::default(), action_info)
+                .await?,
+        );
+    }
+
+    // The worker only fits a single action at a time.
+    let mut rx_from_worker =
+        setup_new_worker(&scheduler, worker_id, PlatformProperties { properties }).await?;
+
+    let mut started_actions = Vec::new();
+    for _ in 0..queued_actions.len() {
+        let (operation_id, action_digest) = match rx_from_worker.recv().await.unwrap().update {
+            Some(update_for_worker::Update::StartAction(start_execute)) => (
+                OperationId::from(start_execute.operation_id),
+                DigestInfo::try_from(
+                    start_execute
+                        .execute_request
+                        .unwrap()
+                        .action_digest
+                        .unwrap(),
+                )?,
+            ),
+            v => panic!("Expected StartAction, got : {v:?}"),
+        };
+        started_actions.push(action_digest.packed_hash()[0]);
+        scheduler
+            .update_action(
+                &worker_id,
+                &operation_id,
+                UpdateOperationType::UpdateWithActionStage(ActionStage::Completed(
+                    ActionResult::default(),
+                )),
+            )
+            .await?;
+    }
+    assert_eq!(started_actions, vec![1, 4, 2, 3]);
+    Ok(())
+}
+
+/// This tests that with worker affinity an action is handed to the worker
+/// that recently ran an action with the same inputs.
+#[nativelink_test]
+async fn worker_affinity_prefers_worker_with_same_inputs_test() -> Result<(), Error> {
+    let worker_id1: WorkerId = WorkerId(Uuid::new_v4());
+    let worker_id2: WorkerId = WorkerId(Uuid::new_v4());
+
+    let task_change_notify = Arc::new(Notify::new());
+    let (scheduler, _worker_scheduler) = SimpleScheduler::new_with_callback(
+        &SimpleSpec {
+            // Without affinity the most recently added worker is picked.
+            allocation_strategy: WorkerAllocationStrategy::most_recently_used,
+            experimental_worker_affinity: Some(WorkerAffinitySpec::default()),
+            ..Default::default()
+        },
+        memory_awaited_action_db_factory(
+            0,
+            &task_change_notify.clone(),
+            MockInstantWrapped::default,
+        ),
+        || async move {},
+        task_change_notify,
+        MockInstantWrapped::default,
+    );
+    let make_action_info = |i: u8| {
+        let mut action_info = make_base_action_info(
+            make_system_time(u64::from(i)),
+            DigestInfo::new([i; 32], 512),
+        );
+        Arc::make_mut(&mut action_info).input_root_digest = DigestInfo::new([5u8; 32], 100);
+        action_info
+    };
+
+    let mut rx_from_worker1 =
+        setup_new_worker(&scheduler, worker_id1, PlatformProperties::default()).await?;
+    let _action_listener1 = scheduler
+        .add_action(OperationId::default(), make_action_info(1))
+        .await?;
+    let operation_id1 = match rx_from_worker1.recv().await.unwrap().update {
+        Some(update_for_worker::Update::StartAction(start_execute)) => {
+            OperationId::from(start_execute.operation_id)
+        }
+        v => panic!("Expected StartAction, got : {v:?}"),
+    };
+    scheduler
+        .update_action(
+            &worker_id1,
+            &operation_id1,
+            UpdateOperationType::UpdateWithActionStage(ActionStage::Completed(
+                ActionResult::default(),
+            )),
+        )
+        .await?;
+
+    let mut rx_from_worker2 =
+        setup_new_worker(&scheduler, worker_id2, PlatformProperties::default()).await?;
+    let _action_listener2 = scheduler
+        .add_action(OperationId::default(), make_action_info(2))
+        .await?;
+    // The second action has the same inputs as the first one, so it goes to
+    // the first worker.
+    match rx_from_worker1.recv().await.unwrap().update {
+        Some(update_for_worker::Update::StartAction(_)) => {}
+        v => panic!("Expected StartAction, got : {v:?}"),
+    }
+    assert!(rx_from_worker2.try_recv().is_err());
+    Ok(())
+}
+
+/// This tests to ensure that platform property restrictions allow jobs to continue to run after
+/// a job finished on a specific worker (eg: restore platform properties).
+#[nativelink_test]
+async fn run_two_jobs_on_same_worker_with_platform_properties_restrictions() -> Result<(), Error> {
+    let worker_id: WorkerId = WorkerId(Uuid::new_v4());
+
+    let mut supported_props = HashMap::new();
+    supported_props.insert("prop1".to_string(), PropertyType::minimum);
+    let task_change_notify = Arc::new(Notify::new());
+    let (scheduler, _worker_scheduler) = SimpleScheduler::new_with_callback(
+        &SimpleSpec {
+            supported_platform_properties: Some(supported_props),
+            ..Default::default()
+        },
+        memory_awaited_action_db_factory(
+            0,
+            &task_change_notify.clone(),
+            MockInstantWrapped::default,
+        ),
+        || async move {},
+        task_change_notify,
+        MockInstantWrapped::default,
+    );
+    let action_digest1 = DigestInfo::new([11u8; 32], 512);
+    let action_digest2 = DigestInfo::new([99u8; 32], 512);
+
+    let mut properties = HashMap::new();
+    properties.insert("prop1".to_string(), PlatformPropertyValue::Minimum(1));
+    let platform_properties = PlatformProperties {
+        properties: properties.clone(),
+    };
+    let action_props: HashMap<String, String> = properties
+        .iter()
+        .map(|(k, v)| (k.clone(), v.as_str().into_owned()))
+        .collect();
+    let mut rx_from_worker = setup_new_worker(&scheduler, worker_id, platform_properties.clone())
+        .await
+        .unwrap();
+    let insert_timestamp1 = make_system_time(1);
+    let mut client1_action_listener = setup_action(
+        &scheduler,
+        action_digest1,
+        action_props.clone(),
+        insert_timestamp1,
+    )
+    .await
+    .unwrap();
+    let insert_timestamp2 = make_system_time(1);
+    let mut client2_action_listener =
+        setup_action(&scheduler, action_digest2, action_props, insert_timestamp2)
+            .await
+            .unwrap();
+
+    let operation_id1 = match rx_from_worker.recv().await.unwrap().update {
+        Some(update_for_worker::Update::StartAction(start_execute)) => {
+            OperationId::from(start_execute.operation_id)
+        }
+        v => panic!("Expected StartAction, got : {v:?}"),
+    };
+    {
+        let state_1 = client1_action_listener.changed().await.unwrap();
+        let state_2 = client2_action_listener.changed().await.unwrap();
+        // First client should be in an Executing state.
+        assert_eq!(state_1.stage, ActionStage::Executing);
+        // Second client should be in a queued state.
+        assert_eq!(state_2.stage, ActionStage::Queued);
+    }
+
+    let action_result = ActionResult {
+        output_files: Vec::default(),
+        output_folders: Vec::default(),
+        output_file_symlinks: Vec::default(),
+        output_directory_symlinks: Vec::default(),
+        exit_code: 0,
+        stdout_digest: DigestInfo::new([6u8; 32], 19),
+        stderr_digest: DigestInfo::new([7u8; 32], 20),
+        execution_metadata: ExecutionMetadata {
+            worker: worker_id.to_string(),
+            queued_timestamp: make_system_time(5),
             worker_start_timestamp: make_system_time(6),
             worker_completed_timestamp: make_system_time(7),
             input_fetch_start_timestamp: make_system_time(8),
@@ -1739,6 +2664,7 @@ async fn run_two_jobs_on_same_worker_with_platform_properties_restrictions() ->
             execution_completed_timestamp: make_system_time(11),
             output_upload_start_timestamp: make_system_time(12),
             output_upload_completed_timestamp: make_system_time(13),
+            resource_usage: None,
         },
         server_logs: HashMap::default(),
         error: None,
@@ -1885,6 +2811,216 @@ async fn run_jobs_in_the_order_they_were_queued() -> Result<(), Error> {
     Ok(())
 }
 
+/// This tests that queued actions report how many actions are ahead of them.
+#[nativelink_test]
+async fn queued_actions_report_queue_position_test() -> Result<(), Error> {
+    let worker_id: WorkerId = WorkerId(Uuid::new_v4());
+
+    let task_change_notify = Arc::new(Notify::new());
+    let (scheduler, _worker_scheduler) = SimpleScheduler::new_with_callback(
+        &SimpleSpec::default(),
+        memory_awaited_action_db_factory(
+            0,
+            &task_change_notify.clone(),
+            MockInstantWrapped::default,
+        ),
+        || async move {},
+        task_change_notify,
+        MockInstantWrapped::default,
+    );
+    let client1_action_listener = setup_action(
+        &scheduler,
+        DigestInfo::new([11u8; 32], 512),
+        HashMap::new(),
+        make_system_time(1),
+    )
+    .await?;
+    let client2_action_listener = setup_action(
+        &scheduler,
+        DigestInfo::new([99u8; 32], 512),
+        HashMap::new(),
+        make_system_time(2),
+    )
+    .await?;
+
+    let queue_position1 = client1_action_listener.queue_position().await?.unwrap();
+    let queue_position2 = client2_action_listener.queue_position().await?.unwrap();
+    assert_eq!(queue_position1.actions_ahead, 0);
+    assert_eq!(queue_position2.actions_ahead, 1);
+    // No actions were assigned yet, so there is nothing to estimate from.
+    assert_eq!(queue_position2.estimated_start, None);
+
+    let mut rx_from_worker =
+        setup_new_worker(&scheduler, worker_id, PlatformProperties::default()).await?;
+    match rx_from_worker.recv().await.unwrap().update {
+        Some(update_for_worker::Update::StartAction(_)) => { /* Success */ }
+        v => panic!("Expected StartAction, got : {v:?}"),
+    }
+    assert_eq!(
+        client1_action_listener.as_state().await?.stage,
+        ActionStage::Executing
+    );
+    assert_eq!(client1_action_listener.queue_position().await?, None);
+
+    Ok(())
+}
+
+/// This tests that priority bands and aging decide the order in which queued
+/// actions run.
+#[nativelink_test]
+async fn run_jobs_by_priority_band_and_age() -> Result<(), Error> {
+    let worker_id: WorkerId = WorkerId(Uuid::new_v4());
+
+    let task_change_notify = Arc::new(Notify::new());
+    let (scheduler, _worker_scheduler) = SimpleScheduler::new_with_callback(
+        &SimpleSpec {
+            priority_bands: vec![
+                PriorityBand {
+                    name: "batch".to_string(),
+                    min_priority: 0,
+                },
+                PriorityBand {
+                    name: "interactive".to_string(),
+                    min_priority: 100,
+                },
+            ],
+            priority_aging_interval_s: 60,
+            ..Default::default()
+        },
+        memory_awaited_action_db_factory(
+            0,
+            &task_change_notify.clone(),
+            MockInstantWrapped::default,
+        ),
+        || async move {},
+        task_change_notify,
+        MockInstantWrapped::default,
+    );
+    let add_action = |action_digest, priority, insert_timestamp| {
+        let mut action_info = make_base_action_info(insert_timestamp, action_digest);
+        Arc::make_mut(&mut action_info).priority = priority;
+        scheduler.add_action(OperationId::default(), action_info)
+    };
+
+    // Waited for ten aging intervals, so it overtakes the interactive band.
+    let old_batch_digest = DigestInfo::new([1u8; 32], 512);
+    let old_batch_listener = add_action(old_batch_digest, 10, make_system_time(0)).await?;
+    // The band and age only order the queue, the priority of the client is
+    // kept.
+    assert_eq!(old_batch_listener.as_action_info().await?.priority, 10);
+    // Both are interactive, so they run in the order they were queued.
+    let late_interactive_digest = DigestInfo::new([2u8; 32], 512);
+    let _late_interactive_listener =
+        add_action(late_interactive_digest, 150, make_system_time(600)).await?;
+    let early_interactive_digest = DigestInfo::new([3u8; 32], 512);
+    let _early_interactive_listener =
+        add_action(early_interactive_digest, 120, make_system_time(590)).await?;
+    // Queued before the interactive actions, but not long enough to age.
+    let batch_digest = DigestInfo::new([4u8; 32], 512);
+    let _batch_listener = add_action(batch_digest, 50, make_system_time(580)).await?;
+
+    let mut rx_from_worker =
+        setup_new_worker(&scheduler, worker_id, PlatformProperties::default()).await?;
+    let mut started_digests = Vec::new();
+    for _ in 0..4 {
+        match rx_from_worker.recv().await.unwrap().update {
+            Some(update_for_worker::Update::StartAction(exec)) => {
+                started_digests.push(exec.execute_request.unwrap().action_digest.unwrap());
+            }
+            v => panic!("Expected StartAction, got : {v:?}"),
+        }
+    }
+    assert_eq!(
+        started_digests,
+        vec![
+            old_batch_digest.into(),
+            early_interactive_digest.into(),
+            late_interactive_digest.into(),
+            batch_digest.into(),
+        ]
+    );
+
+    Ok(())
+}
+
+/// This tests that actions of other partitions are only run once they
+/// waited long enough for their own scheduler.
+#[nativelink_test]
+async fn run_jobs_of_other_partitions_after_takeover_test() -> Result<(), Error> {
+    const TAKEOVER_AFTER_S: u64 = 30;
+    let worker_id: WorkerId = WorkerId(Uuid::new_v4());
+    MockClock::set_time(Duration::from_secs(NOW_TIME));
+
+    let task_change_notify = Arc::new(Notify::new());
+    let (scheduler, _worker_scheduler) = SimpleScheduler::new_with_callback(
+        &SimpleSpec {
+            experimental_partition: Some(SchedulerPartitionSpec {
+                partition_index: 0,
+                partition_count: 2,
+                takeover_after_s: TAKEOVER_AFTER_S,
+            }),
+            ..Default::default()
+        },
+        memory_awaited_action_db_factory(
+            0,
+            &task_change_notify.clone(),
+            MockInstantWrapped::default,
+        ),
+        || async move {},
+        task_change_notify,
+        MockInstantWrapped::default,
+    );
+    let recv_digest = |update: Option<UpdateForWorker>| match update {
+        Some(UpdateForWorker {
+            update: Some(update_for_worker::Update::StartAction(start_execute)),
+        }) => start_execute
+            .execute_request
+            .unwrap()
+            .action_digest
+            .unwrap(),
+        update => panic!("Expected StartAction, got {update:?}"),
+    };
+
+    // The partition is picked by the hash of the action digest.
+    let other_partition_digest = DigestInfo::new([1u8; 32], 512);
+    let _other_partition_listener = setup_action(
+        &scheduler,
+        other_partition_digest,
+        HashMap::new(),
+        make_system_time(0),
+    )
+    .await?;
+    let own_partition_digest = DigestInfo::new([2u8; 32], 512);
+    let _own_partition_listener = setup_action(
+        &scheduler,
+        own_partition_digest,
+        HashMap::new(),
+        make_system_time(0),
+    )
+    .await?;
+
+    let mut rx_from_worker =
+        setup_new_worker(&scheduler, worker_id, PlatformProperties::default()).await?;
+    assert_eq!(
+        recv_digest(rx_from_worker.recv().await),
+        own_partition_digest.into()
+    );
+    scheduler.do_try_match_for_test().await?;
+    assert_eq!(
+        rx_from_worker.try_recv(),
+        Err(mpsc::error::TryRecvError::Empty)
+    );
+
+    MockClock::advance(Duration::from_secs(TAKEOVER_AFTER_S));
+    scheduler.do_try_match_for_test().await?;
+    assert_eq!(
+        recv_digest(rx_from_worker.recv().await),
+        other_partition_digest.into()
+    );
+
+    Ok(())
+}
+
 #[nativelink_test]
 async fn worker_retries_on_internal_error_and_fails_test() -> Result<(), Error> {
     let worker_id: WorkerId = WorkerId(Uuid::new_v4());
@@ -1997,6 +3133,7 @@ async fn worker_retries_on_internal_error_and_fails_test() -> Result<(), Error>
                     execution_completed_timestamp: SystemTime::UNIX_EPOCH,
                     output_upload_start_timestamp: SystemTime::UNIX_EPOCH,
                     output_upload_completed_timestamp: SystemTime::UNIX_EPOCH,
+                    resource_usage: None,
                 },
                 server_logs: HashMap::default(),
                 error: Some(err.clone()),
@@ -2022,6 +3159,190 @@ async fn worker_retries_on_internal_error_and_fails_test() -> Result<(), Error>
     Ok(())
 }
 
+/// This tests that the retry policy limits the retries of a job by the
+/// kind of failure.
+#[nativelink_test]
+async fn retry_policy_limits_retries_by_error_class_test() -> Result<(), Error> {
+    let worker_id: WorkerId = WorkerId(Uuid::new_v4());
+
+    let task_change_notify = Arc::new(Notify::new());
+    let (scheduler, _worker_scheduler) = SimpleScheduler::new_with_callback(
+        &SimpleSpec {
+            experimental_retry_policy: Some(RetryPolicySpec {
+                max_action_error_retries: Some(0),
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+        memory_awaited_action_db_factory(
+            0,
+            &task_change_notify.clone(),
+            MockInstantWrapped::default,
+        ),
+        || async move {},
+        task_change_notify,
+        MockInstantWrapped::default,
+    );
+    let action_digest = DigestInfo::new([99u8; 32], 512);
+
+    let mut rx_from_worker =
+        setup_new_worker(&scheduler, worker_id, PlatformProperties::default()).await?;
+    let mut action_listener = setup_action(
+        &scheduler,
+        action_digest,
+        HashMap::new(),
+        make_system_time(1),
+    )
+    .await?;
+
+    let operation_id = match rx_from_worker.recv().await.unwrap().update {
+        Some(update_for_worker::Update::StartAction(exec)) => OperationId::from(exec.operation_id),
+        v => panic!("Expected StartAction, got : {v:?}"),
+    };
+    assert_eq!(
+        action_listener.changed().await?.stage,
+        ActionStage::Executing
+    );
+
+    // Infrastructure errors are not limited by the policy, so the job is
+    // queued again.
+    scheduler
+        .update_action(
+            &worker_id,
+            &operation_id,
+            UpdateOperationType::UpdateWithError(make_err!(Code::Unavailable, "CAS is down")),
+        )
+        .await?;
+    assert_eq!(action_listener.changed().await?.stage, ActionStage::Queued);
+    match rx_from_worker.recv().await.unwrap().update {
+        Some(update_for_worker::Update::StartAction(_)) => {}
+        v => panic!("Expected StartAction, got : {v:?}"),
+    }
+    assert_eq!(
+        action_listener.changed().await?.stage,
+        ActionStage::Executing
+    );
+
+    // Action errors may not be retried at all.
+    scheduler
+        .update_action(
+            &worker_id,
+            &operation_id,
+            UpdateOperationType::UpdateWithError(make_err!(Code::Internal, "Some error")),
+        )
+        .await?;
+    match &action_listener.changed().await?.stage {
+        ActionStage::Completed(action_result) => {
+            let err = action_result.error.as_ref().unwrap();
+            assert!(
+                err.message_string()
+                    .contains("failed with action errors too many times 1 > 0 times"),
+                "{err:?}"
+            );
+        }
+        v => panic!("Expected Completed, got : {v:?}"),
+    }
+    Ok(())
+}
+
+#[nativelink_test]
+async fn invocation_retry_budget_is_shared_by_actions_test() -> Result<(), Error> {
+    const TOOL_INVOCATION_ID: &str = "some-invocation";
+
+    async fn recv_operation_id(
+        rx_from_worker: &mut mpsc::UnboundedReceiver<UpdateForWorker>,
+    ) -> OperationId {
+        match rx_from_worker.recv().await.unwrap().update {
+            Some(update_for_worker::Update::StartAction(exec)) => {
+                OperationId::from(exec.operation_id.as_str())
+            }
+            v => panic!("Expected StartAction, got : {v:?}"),
+        }
+    }
+
+    let worker_id: WorkerId = WorkerId(Uuid::new_v4());
+
+    let task_change_notify = Arc::new(Notify::new());
+    let (scheduler, _worker_scheduler) = SimpleScheduler::new_with_callback(
+        &SimpleSpec {
+            max_job_retries: 5,
+            max_retries_per_invocation: 1,
+            ..Default::default()
+        },
+        memory_awaited_action_db_factory(
+            0,
+            &task_change_notify.clone(),
+            MockInstantWrapped::default,
+        ),
+        || async move {},
+        task_change_notify,
+        MockInstantWrapped::default,
+    );
+    let add_action = |action_digest| {
+        let mut action_info = make_base_action_info(make_system_time(1), action_digest);
+        Arc::make_mut(&mut action_info).tool_invocation_id = Some(TOOL_INVOCATION_ID.to_string());
+        scheduler.add_action(OperationId::default(), action_info)
+    };
+
+    let mut rx_from_worker =
+        setup_new_worker(&scheduler, worker_id, PlatformProperties::default()).await?;
+    let mut action_listener1 = add_action(DigestInfo::new([98u8; 32], 512)).await?;
+    let operation_id1 = recv_operation_id(&mut rx_from_worker).await;
+    assert_eq!(
+        action_listener1.changed().await.unwrap().stage,
+        ActionStage::Executing
+    );
+
+    // The first failure uses the retry budget of the invocation.
+    scheduler
+        .update_action(
+            &worker_id,
+            &operation_id1,
+            UpdateOperationType::UpdateWithError(make_err!(Code::Internal, "Some error")),
+        )
+        .await?;
+    assert_eq!(
+        action_listener1.changed().await.unwrap().stage,
+        ActionStage::Queued
+    );
+
+    let mut rx_from_worker =
+        setup_new_worker(&scheduler, worker_id, PlatformProperties::default()).await?;
+    recv_operation_id(&mut rx_from_worker).await;
+    assert_eq!(
+        action_listener1.changed().await.unwrap().stage,
+        ActionStage::Executing
+    );
+    let mut action_listener2 = add_action(DigestInfo::new([99u8; 32], 512)).await?;
+    let operation_id2 = recv_operation_id(&mut rx_from_worker).await;
+    assert_eq!(
+        action_listener2.changed().await.unwrap().stage,
+        ActionStage::Executing
+    );
+
+    // Another action of the same invocation is not retried anymore even
+    // though it did not reach `max_job_retries`.
+    scheduler
+        .update_action(
+            &worker_id,
+            &operation_id2,
+            UpdateOperationType::UpdateWithError(make_err!(Code::Internal, "Some error")),
+        )
+        .await?;
+    let action_state = action_listener2.changed().await.unwrap();
+    let ActionStage::Completed(action_result) = &action_state.stage else {
+        panic!("Expected Completed, got : {:?}", action_state.stage);
+    };
+    let err = action_result.error.as_ref().expect("Expected an error");
+    assert!(
+        err.to_string()
+            .contains("tool invocation some-invocation already used its budget of 1 retries"),
+        "{err} did not mention the invocation retry budget",
+    );
+
+    Ok(())
+}
+
 #[nativelink_test]
 async fn ensure_scheduler_drops_inner_spawn() -> Result<(), Error> {
     struct DropChecker {
@@ -2221,3 +3542,247 @@ async fn client_reconnect_keeps_action_alive() -> Result<(), Error> {
 
     Ok(())
 }
+
+#[nativelink_test]
+async fn restores_actions_after_restart_test() -> Result<(), Error> {
+    const STATE_KEY: &str = "scheduler_state";
+    const SNAPSHOT_INTERVAL: Duration = Duration::from_millis(10);
+
+    let store = Store::new(MemoryStore::new(&MemorySpec::default()));
+    let make_scheduler = || {
+        let task_change_notify = Arc::new(Notify::new());
+        SimpleScheduler::new_with_callback(
+            &SimpleSpec::default(),
+            memory_awaited_action_db_factory(
+                0,
+                &task_change_notify.clone(),
+                MockInstantWrapped::default,
+            )
+            .with_persistence(store.clone(), STATE_KEY.to_string(), SNAPSHOT_INTERVAL),
+            || async move {},
+            task_change_notify,
+            MockInstantWrapped::default,
+        )
+    };
+    let recv_operation_id = |update: Option<UpdateForWorker>| match update {
+        Some(UpdateForWorker {
+            update: Some(update_for_worker::Update::StartAction(start_execute)),
+        }) => start_execute.operation_id,
+        update => panic!("Expected StartAction, got {update:?}"),
+    };
+    let client_operation_id = OperationId::from("client_operation_id");
+    let action_digest = DigestInfo::new([99u8; 32], 512);
+
+    let (scheduler, worker_scheduler) = make_scheduler();
+    let mut rx_from_worker = setup_new_worker(
+        &scheduler,
+        WorkerId(Uuid::new_v4()),
+        PlatformProperties::default(),
+    )
+    .await?;
+    let action_listener = scheduler
+        .add_action(
+            client_operation_id.clone(),
+            make_base_action_info(make_system_time(1), action_digest),
+        )
+        .await?;
+    let operation_id = recv_operation_id(rx_from_worker.recv().await);
+    // Wait for the executing action to be written to the store.
+    loop {
+        tokio::time::sleep(SNAPSHOT_INTERVAL).await;
+        let snapshot = store.get_part_unchunked(STATE_KEY, 0, None).await;
+        if snapshot.is_ok_and(|snapshot| String::from_utf8_lossy(&snapshot).contains("Executing")) {
+            break;
+        }
+    }
+    drop(action_listener);
+    drop(rx_from_worker);
+    drop(worker_scheduler);
+    drop(scheduler);
+
+    let (scheduler, _worker_scheduler) = make_scheduler();
+    let mut action_listener = loop {
+        tokio::time::sleep(SNAPSHOT_INTERVAL).await;
+        let maybe_action_listener = scheduler
+            .filter_operations(OperationFilter {
+                client_operation_id: Some(client_operation_id.clone()),
+                ..Default::default()
+            })
+            .await?
+            .next()
+            .await;
+        if let Some(action_listener) = maybe_action_listener {
+            break action_listener;
+        }
+    };
+    // The worker of the action is gone, so it is queued again.
+    assert_eq!(action_listener.changed().await?.stage, ActionStage::Queued);
+
+    let mut rx_from_worker = setup_new_worker(
+        &scheduler,
+        WorkerId(Uuid::new_v4()),
+        PlatformProperties::default(),
+    )
+    .await?;
+    assert_eq!(recv_operation_id(rx_from_worker.recv().await), operation_id);
+    assert_eq!(
+        action_listener.changed().await?.stage,
+        ActionStage::Executing
+    );
+
+    Ok(())
+}
+
+#[nativelink_test]
+async fn corrupt_persisted_state_is_overwritten_test() -> Result<(), Error> {
+    const STATE_KEY: &str = "scheduler_state";
+    const SNAPSHOT_INTERVAL: Duration = Duration::from_millis(10);
+
+    let store = Store::new(MemoryStore::new(&MemorySpec::default()));
+    store
+        .update_oneshot(STATE_KEY, Bytes::from_static(b"not json"))
+        .await?;
+    let task_change_notify = Arc::new(Notify::new());
+    let (_scheduler, _worker_scheduler) = SimpleScheduler::new_with_callback(
+        &SimpleSpec::default(),
+        memory_awaited_action_db_factory(
+            0,
+            &task_change_notify.clone(),
+            MockInstantWrapped::default,
+        )
+        .with_persistence(store.clone(), STATE_KEY.to_string(), SNAPSHOT_INTERVAL),
+        || async move {},
+        task_change_notify,
+        MockInstantWrapped::default,
+    );
+
+    // Snapshots are only written once the state was restored, so the corrupt
+    // snapshot must not block the restore.
+    loop {
+        tokio::time::sleep(SNAPSHOT_INTERVAL).await;
+        if store.get_part_unchunked(STATE_KEY, 0, None).await? == "[]" {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// This tests that an operator can requeue an operation, which kills it on
+/// its worker and runs it again once the worker stopped it, and cancel it.
+#[nativelink_test]
+async fn requeue_and_cancel_operation_test() -> Result<(), Error> {
+    let worker_id: WorkerId = WorkerId(Uuid::new_v4());
+
+    let task_change_notify = Arc::new(Notify::new());
+    let (scheduler, _worker_scheduler) = SimpleScheduler::new_with_callback(
+        &SimpleSpec::default(),
+        memory_awaited_action_db_factory(
+            0,
+            &task_change_notify.clone(),
+            MockInstantWrapped::default,
+        ),
+        || async move {},
+        task_change_notify,
+        MockInstantWrapped::default,
+    );
+    let mut rx_from_worker =
+        setup_new_worker(&scheduler, worker_id, PlatformProperties::default()).await?;
+    let mut action_listener = setup_action(
+        &scheduler,
+        DigestInfo::new([99u8; 32], 512),
+        HashMap::new(),
+        make_system_time(1),
+    )
+    .await?;
+    let operation_id = match rx_from_worker.recv().await.unwrap().update {
+        Some(update_for_worker::Update::StartAction(start_execute)) => {
+            OperationId::from(start_execute.operation_id)
+        }
+        v => panic!("Expected StartAction, got : {v:?}"),
+    };
+    assert_eq!(
+        action_listener.changed().await?.stage,
+        ActionStage::Executing
+    );
+    let operation_details = action_listener.operation_details().await?.unwrap();
+    assert_eq!(operation_details.operation_id, operation_id);
+    assert_eq!(operation_details.worker_id, Some(worker_id));
+    assert_eq!(
+        operation_details
+            .stage_history
+            .iter()
+            .map(|stage_change| stage_change.stage.as_str())
+            .collect::<Vec<_>>(),
+        vec!["QUEUED", "EXECUTING"]
+    );
+
+    scheduler.requeue_operation(&operation_id).await?;
+    match rx_from_worker.recv().await.unwrap().update {
+        Some(update_for_worker::Update::KillOperationRequest(kill_operation_request)) => {
+            assert_eq!(
+                kill_operation_request.operation_id,
+                operation_id.to_string()
+            );
+        }
+        v => panic!("Expected KillOperationRequest, got : {v:?}"),
+    }
+    assert_eq!(action_listener.changed().await?.stage, ActionStage::Queued);
+    // The worker did not stop the operation yet, so it is not handed to it
+    // again.
+    scheduler.do_try_match_for_test().await?;
+    assert!(rx_from_worker.try_recv().is_err());
+
+    scheduler
+        .update_action(
+            &worker_id,
+            &operation_id,
+            UpdateOperationType::UpdateWithError(make_err!(Code::Aborted, "Killed")),
+        )
+        .await?;
+    scheduler.do_try_match_for_test().await?;
+    match rx_from_worker.recv().await.unwrap().update {
+        Some(update_for_worker::Update::StartAction(start_execute)) => {
+            assert_eq!(start_execute.operation_id, operation_id.to_string());
+        }
+        v => panic!("Expected StartAction, got : {v:?}"),
+    }
+    assert_eq!(
+        action_listener.changed().await?.stage,
+        ActionStage::Executing
+    );
+    // Requeueing does not count as a failed attempt.
+    assert_eq!(
+        action_listener.operation_details().await?.unwrap().attempts,
+        0
+    );
+
+    scheduler.cancel_operation(&operation_id).await?;
+    match rx_from_worker.recv().await.unwrap().update {
+        Some(update_for_worker::Update::KillOperationRequest(kill_operation_request)) => {
+            assert_eq!(
+                kill_operation_request.operation_id,
+                operation_id.to_string()
+            );
+        }
+        v => panic!("Expected KillOperationRequest, got : {v:?}"),
+    }
+    match &action_listener.changed().await?.stage {
+        ActionStage::Completed(action_result) => {
+            assert_eq!(
+                action_result.error.as_ref().map(|err| err.code),
+                Some(Code::Cancelled)
+            );
+        }
+        v => panic!("Expected Completed, got : {v:?}"),
+    }
+    assert_eq!(
+        scheduler
+            .cancel_operation(&operation_id)
+            .await
+            .map_err(|err| err.code),
+        Err(Code::FailedPrecondition)
+    );
+
+    Ok(())
+}
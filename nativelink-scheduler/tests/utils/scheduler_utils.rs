@@ -45,6 +45,9 @@ pub fn make_base_action_info(
             digest_function: DigestHasherFunc::Sha256,
             digest: action_digest,
         }),
+        tool_invocation_id: None,
+        client_identity: None,
+        queue_priority: None,
     })
 }
 
@@ -12,38 +12,170 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::convert::Into;
 use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use bytes::BytesMut;
-use nativelink_config::cas_server::{AcStoreConfig, InstanceName};
-use nativelink_error::{make_err, make_input_err, Code, Error, ResultExt};
+use bytes::{Bytes, BytesMut};
+use nativelink_config::cas_server::{AcStoreConfig, AuthorizationAction, InstanceName};
+use nativelink_error::{error_if, make_err, make_input_err, Code, Error, ResultExt};
 use nativelink_proto::build::bazel::remote::execution::v2::action_cache_server::{
     ActionCache, ActionCacheServer as Server,
 };
 use nativelink_proto::build::bazel::remote::execution::v2::{
-    ActionResult, GetActionResultRequest, UpdateActionResultRequest,
+    ActionResult, Digest, ExecutedActionMetadata, GetActionResultRequest, UpdateActionResultRequest,
 };
 use nativelink_store::ac_utils::{get_and_decode_digest, ESTIMATED_DIGEST_SIZE};
+use nativelink_store::completeness_checking_store::find_missing_outputs;
 use nativelink_store::grpc_store::GrpcStore;
 use nativelink_store::store_manager::StoreManager;
+use nativelink_util::audit_log::{record_audit, AuditRecord};
+use nativelink_util::authorization::authorize;
 use nativelink_util::common::DigestInfo;
-use nativelink_util::digest_hasher::make_ctx_for_hash_func;
+use nativelink_util::digest_hasher::{
+    allowed_digest_hasher_funcs, make_ctx_for_hash_func, verify_digest_hasher_func_allowed,
+    DigestHasherFunc,
+};
+use nativelink_util::origin_context::{ActiveOriginContext, ORIGIN_ROLES};
 use nativelink_util::origin_event::OriginEventContext;
 use nativelink_util::store_trait::{Store, StoreLike};
 use prost::Message;
+use prost_types::{Any, Timestamp};
 use tonic::{Request, Response, Status};
 use tracing::{error_span, event, instrument, Level};
 
+use crate::instance_metrics::{instance_metrics, InstanceMetrics};
+use crate::instance_router::InstanceRouter;
+
+/// Default value of [`AcStoreConfig::max_inline_size`].
+const DEFAULT_MAX_INLINE_SIZE: usize = 1024 * 1024;
+
+/// Type of the `auxiliary_metadata` entry holding the time a stored
+/// `ActionResult` expires. It is never sent to clients.
+const EXPIRY_TYPE_URL: &str = "type.googleapis.com/nativelink.ActionResultExpiry";
+
 #[derive(Clone)]
 pub struct AcStoreInfo {
     store: Store,
     read_only: bool,
+    metrics: Arc<InstanceMetrics>,
+    cas_store: Option<Store>,
+    max_inline_size: usize,
+    verify_outputs_exist: bool,
+    max_action_result_size: usize,
+    action_result_ttl: Option<Duration>,
+    write_role: Option<String>,
+    digest_functions: Vec<DigestHasherFunc>,
+}
+
+/// Returns `action_result` with `expiry` stored in its metadata.
+fn with_expiry(mut action_result: ActionResult, expiry: SystemTime) -> ActionResult {
+    let since_epoch = expiry.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let timestamp = Timestamp {
+        seconds: i64::try_from(since_epoch.as_secs()).unwrap_or(i64::MAX),
+        nanos: i32::try_from(since_epoch.subsec_nanos()).unwrap_or_default(),
+    };
+    action_result
+        .execution_metadata
+        .get_or_insert_with(ExecutedActionMetadata::default)
+        .auxiliary_metadata
+        .push(Any {
+            type_url: EXPIRY_TYPE_URL.to_string(),
+            value: timestamp.encode_to_vec(),
+        });
+    action_result
+}
+
+/// Removes the expiry time stored by `with_expiry` from `action_result`
+/// and returns it.
+fn take_expiry(action_result: &mut ActionResult) -> Option<SystemTime> {
+    let metadata = action_result.execution_metadata.as_mut()?;
+    let index = metadata
+        .auxiliary_metadata
+        .iter()
+        .position(|any| any.type_url == EXPIRY_TYPE_URL)?;
+    let expiry = metadata.auxiliary_metadata.remove(index);
+    if *metadata == ExecutedActionMetadata::default() {
+        action_result.execution_metadata = None;
+    }
+    let timestamp = Timestamp::decode(expiry.value.as_slice()).ok()?;
+    Some(
+        UNIX_EPOCH
+            + Duration::new(
+                u64::try_from(timestamp.seconds).ok()?,
+                u32::try_from(timestamp.nanos).ok()?,
+            ),
+    )
+}
+
+/// Reads the blob of `digest` into `raw` if it fits in `remaining` bytes.
+async fn inline_blob(
+    cas_store: &Store,
+    digest: Option<&Digest>,
+    raw: &mut Bytes,
+    remaining: &mut usize,
+) -> Result<(), Error> {
+    let Some(digest) = digest else {
+        return Ok(());
+    };
+    let digest = DigestInfo::try_from(digest.clone())?;
+    let size = usize::try_from(digest.size_bytes())
+        .err_tip(|| "Digest size_bytes was not convertible to usize")?;
+    if size == 0 || size > *remaining || !raw.is_empty() {
+        return Ok(());
+    }
+    *raw = cas_store
+        .get_part_unchunked(digest, 0, None)
+        .await
+        .err_tip(|| format!("Failed to inline {digest}"))?;
+    *remaining -= size;
+    Ok(())
+}
+
+/// Inlines the outputs of `action_result` that `request` asks for until
+/// `max_inline_size` bytes are used. Outputs that can not be read are left
+/// for the client to fetch from the CAS.
+async fn inline_outputs(
+    cas_store: &Store,
+    request: &GetActionResultRequest,
+    action_result: &mut ActionResult,
+    max_inline_size: usize,
+) {
+    let mut remaining = max_inline_size;
+    let mut inlines = Vec::new();
+    if request.inline_stdout {
+        inlines.push((
+            action_result.stdout_digest.as_ref(),
+            &mut action_result.stdout_raw,
+        ));
+    }
+    if request.inline_stderr {
+        inlines.push((
+            action_result.stderr_digest.as_ref(),
+            &mut action_result.stderr_raw,
+        ));
+    }
+    for output_file in &mut action_result.output_files {
+        if request.inline_output_files.contains(&output_file.path) {
+            inlines.push((output_file.digest.as_ref(), &mut output_file.contents));
+        }
+    }
+    for (digest, raw) in inlines {
+        if let Err(err) = inline_blob(cas_store, digest, raw, &mut remaining).await {
+            event!(
+                Level::WARN,
+                ?err,
+                "Failed to inline output in GetActionResult"
+            );
+        }
+    }
 }
 
 pub struct AcServer {
-    stores: HashMap<String, AcStoreInfo>,
+    stores: InstanceRouter<AcStoreInfo>,
 }
 
 impl Debug for AcServer {
@@ -57,22 +189,49 @@ impl AcServer {
         config: &HashMap<InstanceName, AcStoreConfig>,
         store_manager: &StoreManager,
     ) -> Result<Self, Error> {
-        let mut stores = HashMap::with_capacity(config.len());
+        let mut stores = InstanceRouter::new();
         for (instance_name, ac_cfg) in config {
             let store = store_manager.get_store(&ac_cfg.ac_store).ok_or_else(|| {
                 make_input_err!("'ac_store': '{}' does not exist", ac_cfg.ac_store)
             })?;
+            let cas_store = ac_cfg
+                .cas_store
+                .as_ref()
+                .map(|cas_store| {
+                    store_manager
+                        .get_store(cas_store)
+                        .ok_or_else(|| make_input_err!("'cas_store': '{cas_store}' does not exist"))
+                })
+                .transpose()?;
+            error_if!(
+                ac_cfg.verify_outputs_exist && cas_store.is_none(),
+                "'verify_outputs_exist' requires 'cas_store' to be set for instance '{instance_name}'"
+            );
             stores.insert(
-                instance_name.to_string(),
+                instance_name,
                 AcStoreInfo {
                     store,
                     read_only: ac_cfg.read_only,
+                    metrics: instance_metrics(instance_name),
+                    cas_store,
+                    max_inline_size: if ac_cfg.max_inline_size == 0 {
+                        DEFAULT_MAX_INLINE_SIZE
+                    } else {
+                        ac_cfg.max_inline_size
+                    },
+                    verify_outputs_exist: ac_cfg.verify_outputs_exist,
+                    max_action_result_size: ac_cfg.max_action_result_size,
+                    action_result_ttl: (ac_cfg.action_result_ttl != 0)
+                        .then(|| Duration::from_secs(ac_cfg.action_result_ttl as u64)),
+                    write_role: ac_cfg.write_role.clone(),
+                    digest_functions: allowed_digest_hasher_funcs(
+                        instance_name,
+                        &ac_cfg.digest_functions,
+                    )?,
                 },
-            );
+            )?;
         }
-        Ok(AcServer {
-            stores: stores.clone(),
-        })
+        Ok(AcServer { stores })
     }
 
     pub fn into_service(self) -> Server<AcServer> {
@@ -84,10 +243,16 @@ impl AcServer {
         request: GetActionResultRequest,
     ) -> Result<Response<ActionResult>, Error> {
         let instance_name = &request.instance_name;
+        authorize(AuthorizationAction::read, instance_name)?;
         let store_info = self
             .stores
             .get(instance_name)
             .err_tip(|| format!("'instance_name' not configured for '{instance_name}'"))?;
+        verify_digest_hasher_func_allowed(
+            instance_name,
+            &store_info.digest_functions,
+            DigestHasherFunc::try_from(request.digest_function)?,
+        )?;
 
         // TODO(blaise.bruer) We should write a test for these errors.
         let digest: DigestInfo = request
@@ -101,12 +266,36 @@ impl AcServer {
             .store
             .downcast_ref::<GrpcStore>(Some(digest.into()))
         {
-            return grpc_store.get_action_result(Request::new(request)).await;
+            let response = grpc_store.get_action_result(Request::new(request)).await?;
+            store_info
+                .metrics
+                .ac
+                .record_download(response.get_ref().encoded_len() as u64);
+            return Ok(response);
         }
 
         let res = get_and_decode_digest::<ActionResult>(&store_info.store, digest.into()).await;
         match res {
-            Ok(action_result) => Ok(Response::new(action_result)),
+            Ok(mut action_result) => {
+                if take_expiry(&mut action_result).is_some_and(|expiry| expiry <= SystemTime::now())
+                {
+                    return Err(make_err!(Code::NotFound, "Action result {digest} expired"));
+                }
+                if let Some(cas_store) = &store_info.cas_store {
+                    inline_outputs(
+                        cas_store,
+                        &request,
+                        &mut action_result,
+                        store_info.max_inline_size,
+                    )
+                    .await;
+                }
+                store_info
+                    .metrics
+                    .ac
+                    .record_download(action_result.encoded_len() as u64);
+                Ok(Response::new(action_result))
+            }
             Err(mut e) => {
                 if e.code == Code::NotFound {
                     // `get_action_result` is frequent to get NotFound errors, so remove all
@@ -123,10 +312,16 @@ impl AcServer {
         request: UpdateActionResultRequest,
     ) -> Result<Response<ActionResult>, Error> {
         let instance_name = &request.instance_name;
+        authorize(AuthorizationAction::ac_write, instance_name)?;
         let store_info = self
             .stores
             .get(instance_name)
             .err_tip(|| format!("'instance_name' not configured for '{instance_name}'"))?;
+        verify_digest_hasher_func_allowed(
+            instance_name,
+            &store_info.digest_functions,
+            DigestHasherFunc::try_from(request.digest_function)?,
+        )?;
 
         if store_info.read_only {
             return Err(make_err!(
@@ -134,6 +329,15 @@ impl AcServer {
                 "The store '{instance_name}' is read only on this endpoint",
             ));
         }
+        if let Some(write_role) = &store_info.write_role {
+            let roles = ActiveOriginContext::get_value(&ORIGIN_ROLES).ok().flatten();
+            if !roles.is_some_and(|roles| roles.contains(write_role)) {
+                return Err(make_err!(
+                    Code::PermissionDenied,
+                    "Updating the action cache of '{instance_name}' requires the '{write_role}' role",
+                ));
+            }
+        }
 
         let digest: DigestInfo = request
             .action_digest
@@ -141,20 +345,60 @@ impl AcServer {
             .err_tip(|| "Action digest was not set in message")?
             .try_into()?;
 
+        let action_result_size = request
+            .action_result
+            .as_ref()
+            .map_or(0, Message::encoded_len);
+        error_if!(
+            store_info.max_action_result_size != 0
+                && action_result_size > store_info.max_action_result_size,
+            "ActionResult is {action_result_size} bytes, more than the {} bytes allowed for instance '{instance_name}'",
+            store_info.max_action_result_size
+        );
+
         // If we are a GrpcStore we shortcut here, as this is a special store.
         if let Some(grpc_store) = store_info
             .store
             .downcast_ref::<GrpcStore>(Some(digest.into()))
         {
-            return grpc_store.update_action_result(Request::new(request)).await;
+            let response = grpc_store
+                .update_action_result(Request::new(request))
+                .await?;
+            store_info
+                .metrics
+                .ac
+                .record_upload(action_result_size as u64);
+            return Ok(response);
         }
 
         let action_result = request
             .action_result
             .err_tip(|| "Action result was not set in message")?;
 
+        if let Some(cas_store) = store_info
+            .cas_store
+            .as_ref()
+            .filter(|_| store_info.verify_outputs_exist)
+        {
+            let missing = find_missing_outputs(cas_store, action_result.clone())
+                .await
+                .err_tip(|| "Failed to verify outputs of ActionResult")?;
+            if let Some(key) = missing.first() {
+                return Err(make_err!(
+                    Code::FailedPrecondition,
+                    "ActionResult refers to {} blobs missing from the CAS, like {}",
+                    missing.len(),
+                    key.as_str()
+                ));
+            }
+        }
+
+        let stored_action_result = match store_info.action_result_ttl {
+            Some(ttl) => Cow::Owned(with_expiry(action_result.clone(), SystemTime::now() + ttl)),
+            None => Cow::Borrowed(&action_result),
+        };
         let mut store_data = BytesMut::with_capacity(ESTIMATED_DIGEST_SIZE);
-        action_result
+        stored_action_result
             .encode(&mut store_data)
             .err_tip(|| "Provided ActionResult could not be serialized")?;
 
@@ -163,6 +407,10 @@ impl AcServer {
             .update_oneshot(digest, store_data.freeze())
             .await
             .err_tip(|| "Failed to update in action cache")?;
+        store_info
+            .metrics
+            .ac
+            .record_upload(action_result_size as u64);
         Ok(Response::new(action_result))
     }
 }
@@ -213,12 +461,30 @@ impl ActionCache for AcServer {
     ) -> Result<Response<ActionResult>, Status> {
         let request = grpc_request.into_inner();
         let ctx = OriginEventContext::new(|| &request).await;
+        let started_at = Instant::now();
+        let instance_name = request.instance_name.clone();
+        let digest = request
+            .action_digest
+            .clone()
+            .and_then(|digest| digest.try_into().ok());
+        let size_bytes = request
+            .action_result
+            .as_ref()
+            .map_or(0, Message::encoded_len) as u64;
         let resp = make_ctx_for_hash_func(request.digest_function)
             .err_tip(|| "In AcServer::update_action_result")?
-            .wrap_async(
-                error_span!("ac_server_update_action_result"),
-                self.inner_update_action_result(request),
-            )
+            .wrap_async(error_span!("ac_server_update_action_result"), async {
+                let result = self.inner_update_action_result(request).await;
+                record_audit(&AuditRecord {
+                    operation: "UpdateActionResult",
+                    instance_name: &instance_name,
+                    digest,
+                    size_bytes,
+                    latency: started_at.elapsed(),
+                    status: result.as_ref().map_or_else(|err| err.code, |_| Code::Ok),
+                });
+                result
+            })
             .await
             .map_err(Into::into);
         ctx.emit(|| &resp).await;
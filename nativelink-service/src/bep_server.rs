@@ -14,12 +14,18 @@
 
 use std::borrow::Cow;
 use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use bytes::BytesMut;
+use bytes::Bytes;
 use futures::stream::unfold;
 use futures::Stream;
+use nativelink_config::cas_server::AuthorizationAction;
 use nativelink_error::{Error, ResultExt};
-use nativelink_proto::com::github::trace_machina::nativelink::events::{bep_event, BepEvent};
+use nativelink_proto::build::bazel::remote::execution::v2::Digest;
+use nativelink_proto::com::github::trace_machina::nativelink::events::{
+    bep_event, BepEvent, BepInvocation,
+};
 use nativelink_proto::google::devtools::build::v1::publish_build_event_server::{
     PublishBuildEvent, PublishBuildEventServer,
 };
@@ -27,17 +33,24 @@ use nativelink_proto::google::devtools::build::v1::{
     PublishBuildToolEventStreamRequest, PublishBuildToolEventStreamResponse,
     PublishLifecycleEventRequest,
 };
+use nativelink_store::ac_utils::compute_buf_digest;
 use nativelink_store::store_manager::StoreManager;
-use nativelink_util::origin_context::{ActiveOriginContext, ORIGIN_IDENTITY};
+use nativelink_util::authorization::authorize;
+use nativelink_util::common::DigestInfo;
+use nativelink_util::digest_hasher::default_digest_hasher_func;
+use nativelink_util::origin_context::{ActiveOriginContext, OriginContext, ORIGIN_IDENTITY};
 use nativelink_util::store_trait::{Store, StoreDriver, StoreKey, StoreLike};
 use prost::Message;
 use tonic::{Request, Response, Result, Status, Streaming};
-use tracing::{instrument, Level};
+use tracing::{instrument, trace_span, Level};
 
 /// Current version of the BEP event. This might be used in the future if
 /// there is a breaking change in the BEP event format.
 const BEP_EVENT_VERSION: u32 = 0;
 
+/// Current version of the index entry of an invocation.
+const BEP_INVOCATION_VERSION: u32 = 0;
+
 fn get_identity() -> Result<Option<String>, Status> {
     ActiveOriginContext::get()
         .map_or(Ok(None), |ctx| ctx.get_value(&ORIGIN_IDENTITY))
@@ -45,8 +58,62 @@ fn get_identity() -> Result<Option<String>, Status> {
         .map_or_else(|e| Err(e.into()), |v| Ok(v.map(|v| v.as_ref().clone())))
 }
 
+fn now_timestamp_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| {
+            u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+        })
+}
+
+/// Stores the encoded `bep_event` under `store_key`. With a CAS, the event
+/// is stored in the CAS instead and `store_key` only gets its digest.
+async fn store_bep_event(
+    store: Pin<&dyn StoreDriver>,
+    cas_store: Option<Pin<&dyn StoreDriver>>,
+    store_key: StoreKey<'_>,
+    bep_event: &BepEvent,
+) -> Result<Option<DigestInfo>, Error> {
+    let data = Bytes::from(bep_event.encode_to_vec());
+    let Some(cas_store) = cas_store else {
+        store
+            .update_oneshot(store_key, data)
+            .await
+            .err_tip(|| "Failed to store BepEvent")?;
+        return Ok(None);
+    };
+    let digest = compute_buf_digest(&data, &mut default_digest_hasher_func().hasher());
+    cas_store
+        .update_oneshot(digest.into(), data)
+        .await
+        .err_tip(|| "Failed to store BepEvent in the CAS")?;
+    store
+        .update_oneshot(store_key, Bytes::from(Digest::from(digest).encode_to_vec()))
+        .await
+        .err_tip(|| "Failed to store digest of BepEvent")?;
+    Ok(Some(digest))
+}
+
+/// Stores the index entry of `invocation`.
+async fn store_bep_invocation(
+    store: Pin<&dyn StoreDriver>,
+    invocation: &BepInvocation,
+) -> Result<(), Error> {
+    store
+        .update_oneshot(
+            StoreKey::Str(Cow::Owned(format!(
+                "BepInvocation:{}",
+                invocation.invocation_id
+            ))),
+            Bytes::from(invocation.encode_to_vec()),
+        )
+        .await
+        .err_tip(|| "Failed to store BepInvocation")
+}
+
 pub struct BepServer {
     store: Store,
+    cas_store: Option<Store>,
 }
 
 impl BepServer {
@@ -57,8 +124,17 @@ impl BepServer {
         let store = store_manager
             .get_store(&config.store)
             .err_tip(|| format!("Expected store {} to exist in store manager", &config.store))?;
+        let cas_store = config
+            .cas_store
+            .as_ref()
+            .map(|cas_store| {
+                store_manager
+                    .get_store(cas_store)
+                    .err_tip(|| format!("Expected store {cas_store} to exist in store manager"))
+            })
+            .transpose()?;
 
-        Ok(Self { store })
+        Ok(Self { store, cas_store })
     }
 
     pub fn into_service(self) -> PublishBuildEventServer<BepServer> {
@@ -70,6 +146,7 @@ impl BepServer {
         request: PublishLifecycleEventRequest,
         identity: Option<String>,
     ) -> Result<Response<()>, Error> {
+        authorize(AuthorizationAction::cas_write, &request.project_id)?;
         let build_event = request
             .build_event
             .as_ref()
@@ -91,15 +168,14 @@ impl BepServer {
             identity: identity.unwrap_or_default(),
             event: Some(bep_event::Event::LifecycleEvent(request)),
         };
-        let mut buf = BytesMut::new();
-        bep_event
-            .encode(&mut buf)
-            .err_tip(|| "Could not encode PublishLifecycleEventRequest proto")?;
-
-        self.store
-            .update_oneshot(store_key, buf.freeze())
-            .await
-            .err_tip(|| "Failed to store PublishLifecycleEventRequest")?;
+        store_bep_event(
+            self.store.as_store_driver_pin(),
+            self.cas_store.as_ref().map(StoreLike::as_store_driver_pin),
+            store_key,
+            &bep_event,
+        )
+        .await
+        .err_tip(|| "Failed to store PublishLifecycleEventRequest")?;
 
         Ok(Response::new(()))
     }
@@ -110,10 +186,10 @@ impl BepServer {
         identity: Option<String>,
     ) -> Result<Response<PublishBuildToolEventStreamStream>, Error> {
         async fn process_request(
-            store: Pin<&dyn StoreDriver>,
+            state: &mut State,
             request: PublishBuildToolEventStreamRequest,
-            identity: String,
         ) -> Result<PublishBuildToolEventStreamResponse, Status> {
+            authorize(AuthorizationAction::cas_write, &request.project_id)?;
             let ordered_build_event = request
                 .ordered_build_event
                 .as_ref()
@@ -128,25 +204,44 @@ impl BepServer {
 
             let bep_event = BepEvent {
                 version: BEP_EVENT_VERSION,
-                identity,
+                identity: state.identity.clone(),
                 event: Some(bep_event::Event::BuildToolEvent(request)),
             };
-            let mut buf = BytesMut::new();
-
-            bep_event
-                .encode(&mut buf)
-                .err_tip(|| "Could not encode PublishBuildToolEventStreamRequest proto")?;
-
-            store
-                .update_oneshot(
-                    StoreKey::Str(Cow::Owned(format!(
-                        "BepEvent:be:{}:{}:{}",
-                        &stream_id.build_id, &stream_id.invocation_id, sequence_number,
-                    ))),
-                    buf.freeze(),
-                )
-                .await
-                .err_tip(|| "Failed to store PublishBuildToolEventStreamRequest")?;
+
+            let store = state.store.as_store_driver_pin();
+            let maybe_digest = store_bep_event(
+                store,
+                state.cas_store.as_ref().map(StoreLike::as_store_driver_pin),
+                StoreKey::Str(Cow::Owned(format!(
+                    "BepEvent:be:{}:{}:{}",
+                    &stream_id.build_id, &stream_id.invocation_id, sequence_number,
+                ))),
+                &bep_event,
+            )
+            .await
+            .err_tip(|| "Failed to store PublishBuildToolEventStreamRequest")?;
+
+            // The index is stored with the first event, so running
+            // invocations show up, and again when the stream ends.
+            let now = now_timestamp_ms();
+            let is_first_event = state.invocation.is_none();
+            let invocation = state.invocation.get_or_insert_with(|| BepInvocation {
+                version: BEP_INVOCATION_VERSION,
+                build_id: stream_id.build_id.clone(),
+                invocation_id: stream_id.invocation_id.clone(),
+                identity: state.identity.clone(),
+                first_event_timestamp_ms: now,
+                ..Default::default()
+            });
+            invocation.last_event_timestamp_ms = now;
+            invocation.event_count += 1;
+            invocation.last_sequence_number = sequence_number;
+            invocation
+                .event_digests
+                .extend(maybe_digest.map(Digest::from));
+            if is_first_event {
+                store_bep_invocation(store, invocation).await?;
+            }
 
             Ok(PublishBuildToolEventStreamResponse {
                 stream_id: Some(stream_id.clone()),
@@ -156,39 +251,56 @@ impl BepServer {
 
         struct State {
             store: Store,
+            cas_store: Option<Store>,
             stream: Streaming<PublishBuildToolEventStreamRequest>,
             identity: String,
+            origin_context: Arc<OriginContext>,
+            invocation: Option<BepInvocation>,
         }
 
-        let response_stream =
-            unfold(
-                Some(State {
-                    store: self.store.clone(),
-                    stream,
-                    identity: identity.unwrap_or_default(),
-                }),
-                move |maybe_state| async move {
-                    let mut state = maybe_state?;
-                    let request =
-                        match state.stream.message().await.err_tip(|| {
-                            "While receiving message in publish_build_tool_event_stream"
-                        }) {
-                            Ok(Some(request)) => request,
-                            Ok(None) => return None,
-                            Err(e) => return Some((Err(e.into()), None)),
-                        };
-                    process_request(
-                        state.store.as_store_driver_pin(),
-                        request,
-                        state.identity.clone(),
+        let response_stream = unfold(
+            Some(State {
+                store: self.store.clone(),
+                cas_store: self.cas_store.clone(),
+                stream,
+                identity: identity.unwrap_or_default(),
+                // The response stream is polled outside of the request, so
+                // the requests are processed in its context to authorize
+                // them.
+                origin_context: ActiveOriginContext::get().unwrap_or_default(),
+                invocation: None,
+            }),
+            move |maybe_state| async move {
+                let mut state = maybe_state?;
+                let request = match state
+                    .stream
+                    .message()
+                    .await
+                    .err_tip(|| "While receiving message in publish_build_tool_event_stream")
+                {
+                    Ok(Some(request)) => request,
+                    Ok(None) => {
+                        let invocation = state.invocation.as_ref()?;
+                        return store_bep_invocation(state.store.as_store_driver_pin(), invocation)
+                            .await
+                            .err()
+                            .map(|e| (Err(e.into()), None));
+                    }
+                    Err(e) => return Some((Err(e.into()), None)),
+                };
+                let origin_context = state.origin_context.clone();
+                origin_context
+                    .wrap_async(
+                        trace_span!("publish_build_tool_event_stream"),
+                        process_request(&mut state, request),
                     )
                     .await
                     .map_or_else(
                         |e| Some((Err(e), None)),
                         |response| Some((Ok(response), Some(state))),
                     )
-                },
-            );
+            },
+        );
 
         Ok(Response::new(Box::pin(response_stream)))
     }
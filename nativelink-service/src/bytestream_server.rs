@@ -16,16 +16,18 @@ use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::convert::Into;
 use std::fmt::{Debug, Formatter};
+use std::net::SocketAddr;
 use std::pin::Pin;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use bytes::Bytes;
 use futures::future::{pending, BoxFuture};
 use futures::stream::unfold;
-use futures::{try_join, Future, Stream, TryFutureExt};
-use nativelink_config::cas_server::ByteStreamConfig;
-use nativelink_error::{make_err, make_input_err, Code, Error, ResultExt};
+use futures::{try_join, Future, Stream, TryFutureExt, TryStreamExt};
+use nativelink_config::cas_server::{AuthorizationAction, ByteStreamConfig};
+use nativelink_error::{error_if, make_err, make_input_err, Code, Error, ResultExt};
 use nativelink_proto::google::bytestream::byte_stream_server::{
     ByteStream, ByteStreamServer as Server,
 };
@@ -35,12 +37,16 @@ use nativelink_proto::google::bytestream::{
 };
 use nativelink_store::grpc_store::GrpcStore;
 use nativelink_store::store_manager::StoreManager;
+use nativelink_util::audit_log::{record_audit, AuditRecord};
+use nativelink_util::authorization::authorize;
 use nativelink_util::buf_channel::{
-    make_buf_channel_pair, DropCloserReadHalf, DropCloserWriteHalf,
+    make_buf_channel_pair, make_buf_channel_pair_with_watermarks, BufChannelWatermarks,
+    DropCloserReadHalf, DropCloserWriteHalf,
 };
 use nativelink_util::common::DigestInfo;
 use nativelink_util::digest_hasher::{
-    default_digest_hasher_func, make_ctx_for_hash_func, DigestHasherFunc,
+    allowed_digest_hasher_funcs, make_ctx_for_hash_func, verify_digest_hasher_func_allowed,
+    DigestHasherFunc,
 };
 use nativelink_util::origin_event::OriginEventContext;
 use nativelink_util::proto_stream_utils::WriteRequestStreamWrapper;
@@ -48,11 +54,16 @@ use nativelink_util::resource_info::ResourceInfo;
 use nativelink_util::spawn;
 use nativelink_util::store_trait::{Store, StoreLike, UploadSizeInfo};
 use nativelink_util::task::JoinHandleDropGuard;
+use nativelink_util::zstd_stream;
 use parking_lot::Mutex;
 use tokio::time::sleep;
 use tonic::{Request, Response, Status, Streaming};
 use tracing::{enabled, error_span, event, instrument, Instrument, Level};
 
+use crate::inflight_uploads::inflight_uploads;
+use crate::instance_metrics::{instance_metrics, InstanceMetrics};
+use crate::instance_router::InstanceRouter;
+
 /// If this value changes update the documentation in the config definition.
 const DEFAULT_PERSIST_STREAM_ON_DISCONNECT_TIMEOUT: Duration = Duration::from_secs(60);
 
@@ -65,8 +76,17 @@ const DEFAULT_MAX_DECODING_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
 type ReadStream = Pin<Box<dyn Stream<Item = Result<ReadResponse, Status>> + Send + 'static>>;
 type StoreUpdateFuture = Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'static>>;
 
-struct StreamState {
+/// Identifies an upload. Clients may reuse a UUID for uploads of other
+/// blobs or instances, so those are part of the key as well.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct UploadKey {
+    instance_name: String,
     uuid: String,
+    digest: DigestInfo,
+}
+
+struct StreamState {
+    key: UploadKey,
     tx: DropCloserWriteHalf,
     store_update_fut: StoreUpdateFuture,
 }
@@ -74,7 +94,7 @@ struct StreamState {
 impl Debug for StreamState {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("StreamState")
-            .field("uuid", &self.uuid)
+            .field("key", &self.key)
             .finish()
     }
 }
@@ -96,7 +116,7 @@ impl ActiveStreamGuard<'_> {
         self.bytestream_server
             .active_uploads
             .lock()
-            .remove(&stream_state.uuid);
+            .remove(&stream_state.key);
     }
 }
 
@@ -107,24 +127,27 @@ impl Drop for ActiveStreamGuard<'_> {
         };
         let weak_active_uploads = Arc::downgrade(&self.bytestream_server.active_uploads);
         let mut active_uploads = self.bytestream_server.active_uploads.lock();
-        let uuid = stream_state.uuid.clone();
-        let Some(active_uploads_slot) = active_uploads.get_mut(&uuid) else {
+        let key = stream_state.key.clone();
+        let Some(active_uploads_slot) = active_uploads.get_mut(&key) else {
             event!(
                 Level::ERROR,
                 err = "Failed to find active upload. This should never happen.",
-                uuid = ?uuid,
+                key = ?key,
             );
             return;
         };
         let sleep_fn = self.bytestream_server.sleep_fn.clone();
-        active_uploads_slot.1 = Some(IdleStream {
+        active_uploads_slot.idle_stream = Some(IdleStream {
             stream_state,
             _timeout_streaam_drop_guard: spawn!("bytestream_idle_stream_timeout", async move {
                 (*sleep_fn)().await;
                 if let Some(active_uploads) = weak_active_uploads.upgrade() {
                     let mut active_uploads = active_uploads.lock();
-                    event!(Level::INFO, msg = "Removing idle stream", uuid = ?uuid);
-                    active_uploads.remove(&uuid);
+                    // Dropping the session drops the store update, which
+                    // cleans up any partially written data.
+                    if let Some(session) = active_uploads.remove(&key) {
+                        session.expire(&key);
+                    }
                 }
             }),
         });
@@ -154,16 +177,157 @@ impl IdleStream {
     }
 }
 
-type BytesWrittenAndIdleStream = (Arc<AtomicU64>, Option<IdleStream>);
+/// An upload that is receiving data or waiting to be resumed.
+struct UploadSession {
+    bytes_received: Arc<AtomicU64>,
+    peer: Option<SocketAddr>,
+    started_at: Instant,
+    metrics: Option<Arc<InstanceMetrics>>,
+    /// None while a client is sending data.
+    idle_stream: Option<IdleStream>,
+}
+
+impl UploadSession {
+    fn new(peer: Option<SocketAddr>, metrics: Option<Arc<InstanceMetrics>>) -> Self {
+        if let Some(metrics) = &metrics {
+            metrics
+                .bytestream_sessions
+                .active_sessions
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        Self {
+            bytes_received: Arc::new(AtomicU64::new(0)),
+            peer,
+            started_at: Instant::now(),
+            metrics,
+            idle_stream: None,
+        }
+    }
+
+    /// Records that the session stayed idle for too long.
+    fn expire(&self, key: &UploadKey) {
+        let bytes_received = self.bytes_received.load(Ordering::Acquire);
+        event!(
+            Level::INFO,
+            msg = "Removing idle stream",
+            ?key,
+            peer = ?self.peer,
+            bytes_received,
+            age = ?self.started_at.elapsed(),
+        );
+        if let Some(metrics) = &self.metrics {
+            metrics.bytestream_sessions.abandoned_sessions.inc();
+            metrics
+                .bytestream_sessions
+                .abandoned_bytes
+                .add(bytes_received);
+        }
+    }
+}
+
+impl Drop for UploadSession {
+    fn drop(&mut self) {
+        if let Some(metrics) = &self.metrics {
+            metrics
+                .bytestream_sessions
+                .active_sessions
+                .fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// An upload session as returned by `ByteStreamServer::upload_sessions`.
+#[derive(Clone, Debug)]
+pub struct UploadSessionInfo {
+    pub instance_name: String,
+    pub uuid: String,
+    pub digest: DigestInfo,
+    /// Address of the client that started the upload, if known.
+    pub peer: Option<SocketAddr>,
+    pub bytes_received: u64,
+    /// Time since the upload started.
+    pub age: Duration,
+    /// True if no client is sending data for this upload.
+    pub idle: bool,
+}
+
 type SleepFn = Arc<dyn Fn() -> BoxFuture<'static, ()> + Send + Sync>;
 
+/// Returns true if `resource_info` is a zstd `compressed-blobs` resource.
+fn is_zstd_compressed(resource_info: &ResourceInfo) -> Result<bool, Error> {
+    match resource_info.compressor.as_deref() {
+        None | Some("identity") => Ok(false),
+        Some("zstd") => Ok(true),
+        Some(compressor) => Err(make_err!(
+            Code::Unimplemented,
+            "Compressor '{compressor}' is not supported, only 'zstd' is"
+        )),
+    }
+}
+
+/// Streams the zstd compressed data of `digest` to `writer`. For
+/// `compressed-blobs` the `offset` and `length` refer to the compressed
+/// data, so the whole blob is compressed and the range is cut out of it.
+async fn get_part_zstd_compressed(
+    store: Store,
+    digest: DigestInfo,
+    mut writer: DropCloserWriteHalf,
+    offset: u64,
+    length: Option<u64>,
+) -> Result<(), Error> {
+    let (mut store_tx, mut store_rx) = make_buf_channel_pair();
+    let (mut compressed_tx, mut compressed_rx) = make_buf_channel_pair();
+    let forward_fut = async move {
+        let mut bytes_to_skip = offset;
+        let mut bytes_remaining = length.unwrap_or(u64::MAX);
+        loop {
+            let mut chunk = compressed_rx
+                .recv()
+                .await
+                .err_tip(|| "In get_part_zstd_compressed")?;
+            if chunk.is_empty() {
+                break; // EOF.
+            }
+            let skip = bytes_to_skip.min(chunk.len() as u64);
+            bytes_to_skip -= skip;
+            chunk = chunk.slice(skip as usize..);
+            let take = bytes_remaining.min(chunk.len() as u64);
+            bytes_remaining -= take;
+            if take > 0 {
+                writer
+                    .send(chunk.slice(..take as usize))
+                    .await
+                    .err_tip(|| "In get_part_zstd_compressed")?;
+            }
+        }
+        writer
+            .send_eof()
+            .err_tip(|| "Failed to send EOF in get_part_zstd_compressed")
+    };
+    try_join!(
+        store.get_part(digest, &mut store_tx, 0, None),
+        zstd_stream::compress(
+            &mut store_rx,
+            &mut compressed_tx,
+            zstd_stream::DEFAULT_COMPRESSION_LEVEL
+        ),
+        forward_fut,
+    )?;
+    Ok(())
+}
+
 pub struct ByteStreamServer {
-    stores: HashMap<String, Store>,
+    stores: InstanceRouter<Store>,
+    digest_functions: InstanceRouter<Vec<DigestHasherFunc>>,
+    instance_metrics: InstanceRouter<Arc<InstanceMetrics>>,
     // Max number of bytes to send on each grpc stream chunk.
     max_bytes_per_stream: usize,
     max_decoding_message_size: usize,
-    active_uploads: Arc<Mutex<HashMap<String, BytesWrittenAndIdleStream>>>,
+    active_uploads: Arc<Mutex<HashMap<UploadKey, UploadSession>>>,
     sleep_fn: SleepFn,
+    strict_resource_name_validation: bool,
+    // Bytes of an upload buffered before reading from the client pauses.
+    upload_watermarks: Option<BufChannelWatermarks>,
 }
 
 impl ByteStreamServer {
@@ -185,12 +349,31 @@ impl ByteStreamServer {
         store_manager: &StoreManager,
         sleep_fn: SleepFn,
     ) -> Result<Self, Error> {
-        let mut stores = HashMap::with_capacity(config.cas_stores.len());
+        let mut stores = InstanceRouter::new();
+        let mut digest_functions = InstanceRouter::new();
+        let mut metrics = InstanceRouter::new();
+        for instance_name in config.digest_functions.keys() {
+            error_if!(
+                !config.cas_stores.contains_key(instance_name),
+                "'digest_functions' has instance '{instance_name}' that is not in 'cas_stores'"
+            );
+        }
         for (instance_name, store_name) in &config.cas_stores {
             let store = store_manager
                 .get_store(store_name)
                 .ok_or_else(|| make_input_err!("'cas_store': '{}' does not exist", store_name))?;
-            stores.insert(instance_name.to_string(), store);
+            stores.insert(instance_name, store)?;
+            digest_functions.insert(
+                instance_name,
+                allowed_digest_hasher_funcs(
+                    instance_name,
+                    config
+                        .digest_functions
+                        .get(instance_name)
+                        .map_or(&[], Vec::as_slice),
+                )?,
+            )?;
+            metrics.insert(instance_name, instance_metrics(instance_name))?;
         }
         let max_bytes_per_stream = if config.max_bytes_per_stream == 0 {
             DEFAULT_MAX_BYTES_PER_STREAM
@@ -202,42 +385,100 @@ impl ByteStreamServer {
         } else {
             config.max_decoding_message_size
         };
+        let upload_watermarks = if config.max_upload_buffer_bytes == 0 {
+            None
+        } else {
+            let high = config.max_upload_buffer_bytes as u64;
+            Some(BufChannelWatermarks::new(high, high / 2)?)
+        };
         Ok(ByteStreamServer {
             stores,
+            digest_functions,
+            instance_metrics: metrics,
             max_bytes_per_stream,
             max_decoding_message_size,
             active_uploads: Arc::new(Mutex::new(HashMap::new())),
             sleep_fn,
+            strict_resource_name_validation: config.strict_resource_name_validation,
+            upload_watermarks,
         })
     }
 
+    /// Returns the uploads that are receiving data or waiting to be
+    /// resumed.
+    pub fn upload_sessions(&self) -> Vec<UploadSessionInfo> {
+        self.active_uploads
+            .lock()
+            .iter()
+            .map(|(key, session)| UploadSessionInfo {
+                instance_name: key.instance_name.clone(),
+                uuid: key.uuid.clone(),
+                digest: key.digest,
+                peer: session.peer,
+                bytes_received: session.bytes_received.load(Ordering::Acquire),
+                age: session.started_at.elapsed(),
+                idle: session.idle_stream.is_some(),
+            })
+            .collect()
+    }
+
+    fn verify_digest_function(
+        &self,
+        instance_name: &str,
+        digest_function: DigestHasherFunc,
+    ) -> Result<(), Error> {
+        verify_digest_hasher_func_allowed(
+            instance_name,
+            self.digest_functions
+                .get(instance_name)
+                .map_or(&[], Vec::as_slice),
+            digest_function,
+        )
+    }
+
+    fn validate_resource_info(&self, resource_info: &ResourceInfo) -> Result<(), Error> {
+        if self.strict_resource_name_validation {
+            resource_info.validate()?;
+        }
+        Ok(())
+    }
+
     pub fn into_service(self) -> Server<Self> {
         let max_decoding_message_size = self.max_decoding_message_size;
         Server::new(self).max_decoding_message_size(max_decoding_message_size)
     }
 
+    /// If `zstd_hasher_func` is set the uploaded data is zstd compressed and
+    /// is verified with this digest function after decompressing it.
     fn create_or_join_upload_stream(
         &self,
-        uuid: String,
+        key: UploadKey,
+        peer: Option<SocketAddr>,
         store: Store,
-        digest: DigestInfo,
+        zstd_hasher_func: Option<DigestHasherFunc>,
     ) -> Result<ActiveStreamGuard<'_>, Error> {
-        let (uuid, bytes_received) = match self.active_uploads.lock().entry(uuid) {
+        let digest = key.digest;
+        let (key, bytes_received) = match self.active_uploads.lock().entry(key) {
             Entry::Occupied(mut entry) => {
-                let maybe_idle_stream = entry.get_mut();
-                let Some(idle_stream) = maybe_idle_stream.1.take() else {
+                let session = entry.get_mut();
+                let Some(idle_stream) = session.idle_stream.take() else {
                     return Err(make_input_err!("Cannot upload same UUID simultaneously"));
                 };
-                let bytes_received = maybe_idle_stream.0.clone();
+                let bytes_received = session.bytes_received.clone();
                 event!(Level::INFO, msg = "Joining existing stream", entry = ?entry.key());
                 return Ok(idle_stream.into_active_stream(bytes_received, self));
             }
             Entry::Vacant(entry) => {
-                let bytes_received = Arc::new(AtomicU64::new(0));
-                let uuid = entry.key().clone();
-                // Our stream is "in use" if the key is in the map, but the value is None.
-                entry.insert((bytes_received.clone(), None));
-                (uuid, bytes_received)
+                let key = entry.key().clone();
+                let session = UploadSession::new(
+                    peer,
+                    self.instance_metrics.get(&key.instance_name).cloned(),
+                );
+                let bytes_received = session.bytes_received.clone();
+                // Our stream is "in use" if the key is in the map, but the
+                // `idle_stream` is None.
+                entry.insert(session);
+                (key, bytes_received)
             }
         };
 
@@ -245,18 +486,38 @@ impl ByteStreamServer {
         // removing the entry from the map, otherwise that UUID becomes
         // unusable.
 
-        let (tx, rx) = make_buf_channel_pair();
+        let (tx, mut rx) = match self.upload_watermarks {
+            Some(watermarks) => make_buf_channel_pair_with_watermarks(watermarks),
+            None => make_buf_channel_pair(),
+        };
         let store_update_fut = Box::pin(async move {
             // We need to wrap `Store::update()` in a another future because we need to capture
             // `store` to ensure its lifetime follows the future and not the caller.
-            store
-                // Bytestream always uses digest size as the actual byte size.
-                .update(digest, rx, UploadSizeInfo::ExactSize(digest.size_bytes()))
-                .await
+            let Some(hasher_func) = zstd_hasher_func else {
+                return store
+                    // Bytestream always uses digest size as the actual byte size.
+                    .update(digest, rx, UploadSizeInfo::ExactSize(digest.size_bytes()))
+                    .await;
+            };
+            let (mut decompressed_tx, decompressed_rx) = make_buf_channel_pair();
+            try_join!(
+                zstd_stream::decompress_and_verify(
+                    &mut rx,
+                    &mut decompressed_tx,
+                    hasher_func,
+                    digest
+                ),
+                store.update(
+                    digest,
+                    decompressed_rx,
+                    UploadSizeInfo::ExactSize(digest.size_bytes())
+                ),
+            )?;
+            Ok(())
         });
         Ok(ActiveStreamGuard {
             stream_state: Some(StreamState {
-                uuid,
+                key,
                 tx,
                 store_update_fut,
             }),
@@ -270,16 +531,39 @@ impl ByteStreamServer {
         store: Store,
         digest: DigestInfo,
         read_request: ReadRequest,
+        is_zstd: bool,
     ) -> Result<impl Stream<Item = Result<ReadResponse, Status>> + Send + 'static, Error> {
         struct ReaderState {
             max_bytes_per_stream: usize,
             rx: DropCloserReadHalf,
+            /// Data received from the store that was not sent yet.
+            unsent: Bytes,
             maybe_get_part_result: Option<Result<(), Error>>,
             get_part_fut: Pin<Box<dyn Future<Output = Result<(), Error>> + Send>>,
         }
 
-        let read_limit = u64::try_from(read_request.read_limit)
-            .err_tip(|| "Could not convert read_limit to u64")?;
+        let read_limit = u64::try_from(read_request.read_limit).map_err(|_| {
+            make_err!(
+                Code::OutOfRange,
+                "read_limit must not be negative, got {}",
+                read_request.read_limit
+            )
+        })?;
+        let read_offset = u64::try_from(read_request.read_offset).map_err(|_| {
+            make_err!(
+                Code::OutOfRange,
+                "read_offset must not be negative, got {}",
+                read_request.read_offset
+            )
+        })?;
+        // The offset of a compressed read applies to the compressed data,
+        // whose size is not known until it is compressed.
+        if !is_zstd && read_offset > digest.size_bytes() {
+            return Err(make_err!(
+                Code::OutOfRange,
+                "read_offset {read_offset} is past the end of {digest}"
+            ));
+        }
 
         let (tx, rx) = make_buf_channel_pair();
 
@@ -292,19 +576,20 @@ impl ByteStreamServer {
         // This allows us to call a destructor when the the object is dropped.
         let state = Some(ReaderState {
             rx,
+            unsent: Bytes::new(),
             max_bytes_per_stream: self.max_bytes_per_stream,
             maybe_get_part_result: None,
-            get_part_fut: Box::pin(async move {
-                store
-                    .get_part(
-                        digest,
-                        tx,
-                        u64::try_from(read_request.read_offset)
-                            .err_tip(|| "Could not convert read_offset to u64")?,
-                        read_limit,
-                    )
-                    .await
-            }),
+            get_part_fut: if is_zstd {
+                Box::pin(get_part_zstd_compressed(
+                    store,
+                    digest,
+                    tx,
+                    read_offset,
+                    read_limit,
+                ))
+            } else {
+                Box::pin(async move { store.get_part(digest, tx, read_offset, read_limit).await })
+            },
         });
 
         let read_stream_span = error_span!("read_stream");
@@ -312,29 +597,19 @@ impl ByteStreamServer {
         Ok(Box::pin(unfold(state, move |state| {
             async {
             let mut state = state?; // If None our stream is done.
-            let mut response = ReadResponse::default();
-            {
-                let consume_fut = state.rx.consume(Some(state.max_bytes_per_stream));
-                tokio::pin!(consume_fut);
+            if state.unsent.is_empty() {
+                let recv_fut = state.rx.recv();
+                tokio::pin!(recv_fut);
                 loop {
                     tokio::select! {
-                        read_result = &mut consume_fut => {
+                        read_result = &mut recv_fut => {
                             match read_result {
                                 Ok(bytes) => {
                                     if bytes.is_empty() {
                                         // EOF.
                                         return None;
                                     }
-                                    if bytes.len() > state.max_bytes_per_stream {
-                                        let err = make_err!(Code::Internal, "Returned store size was larger than read size");
-                                        return Some((Err(err.into()), None));
-                                    }
-                                    response.data = bytes;
-                                    if enabled!(Level::DEBUG) {
-                                        event!(Level::INFO, response = ?response);
-                                    } else {
-                                        event!(Level::INFO, response.data = format!("<redacted len({})>", response.data.len()));
-                                    }
+                                    state.unsent = bytes;
                                     break;
                                 }
                                 Err(mut e) => {
@@ -366,20 +641,31 @@ impl ByteStreamServer {
                         result = &mut state.get_part_fut => {
                             state.maybe_get_part_result = Some(result);
                             // It is non-deterministic on which future will finish in what order.
-                            // It is also possible that the `state.rx.consume()` call above may not be able to
+                            // It is also possible that the `state.rx.recv()` call above may not be able to
                             // respond even though the publishing future is done.
                             // Because of this we set the writing future to pending so it never finishes.
-                            // The `state.rx.consume()` future will eventually finish and return either the
+                            // The `state.rx.recv()` future will eventually finish and return either the
                             // data or an error.
-                            // An EOF will terminate the `state.rx.consume()` future, but we are also protected
+                            // An EOF will terminate the `state.rx.recv()` future, but we are also protected
                             // because we are dropping the writing future, it will drop the `tx` channel
-                            // which will eventually propagate an error to the `state.rx.consume()` future if
+                            // which will eventually propagate an error to the `state.rx.recv()` future if
                             // the EOF was not sent due to some other error.
                             state.get_part_fut = Box::pin(pending());
                         },
                     }
                 }
             }
+            // Chunks from the store are forwarded as they are, only the ones
+            // larger than a message are split. Neither copies the data.
+            let len = state.unsent.len().min(state.max_bytes_per_stream);
+            let response = ReadResponse {
+                data: state.unsent.split_to(len),
+            };
+            if enabled!(Level::DEBUG) {
+                event!(Level::INFO, response = ?response);
+            } else {
+                event!(Level::INFO, response.data = format!("<redacted len({})>", response.data.len()));
+            }
             Some((Ok(response), Some(state)))
         }.instrument(read_stream_span.clone())
         })))
@@ -398,6 +684,8 @@ impl ByteStreamServer {
         &self,
         store: Store,
         digest: DigestInfo,
+        digest_function: DigestHasherFunc,
+        peer: Option<SocketAddr>,
         stream: WriteRequestStreamWrapper<impl Stream<Item = Result<WriteRequest, Status>> + Unpin>,
     ) -> Result<Response<WriteResponse>, Error> {
         async fn process_client_stream(
@@ -406,7 +694,7 @@ impl ByteStreamServer {
             >,
             tx: &mut DropCloserWriteHalf,
             outer_bytes_received: &Arc<AtomicU64>,
-            expected_size: u64,
+            max_size: u64,
         ) -> Result<(), Error> {
             loop {
                 let write_request = match stream.next().await {
@@ -472,7 +760,7 @@ impl ByteStreamServer {
                     outer_bytes_received.store(tx.get_bytes_written(), Ordering::Release);
                 }
 
-                if expected_size < tx.get_bytes_written() {
+                if max_size < tx.get_bytes_written() {
                     return Err(make_input_err!("Received more bytes than expected"));
                 }
                 if write_request.finish_write {
@@ -486,32 +774,75 @@ impl ByteStreamServer {
             // Unreachable.
         }
 
-        let uuid = stream
-            .resource_info
-            .uuid
-            .as_ref()
-            .ok_or_else(|| make_input_err!("UUID must be set if writing data"))?
-            .to_string();
-        let mut active_stream_guard = self.create_or_join_upload_stream(uuid, store, digest)?;
-        let expected_size = stream.resource_info.expected_size as u64;
+        let key = UploadKey {
+            instance_name: stream.resource_info.instance_name.to_string(),
+            uuid: stream
+                .resource_info
+                .uuid
+                .as_ref()
+                .ok_or_else(|| make_input_err!("UUID must be set if writing data"))?
+                .to_string(),
+            digest,
+        };
+        let is_zstd = is_zstd_compressed(&stream.resource_info)?;
+        // A resumed upload already owns the blob, otherwise another client
+        // may be uploading the same blob right now.
+        let is_resumed = self.active_uploads.lock().contains_key(&key);
+        let inflight_upload = if is_resumed {
+            None
+        } else {
+            let Some(inflight_upload) = inflight_uploads().join(&store, digest).await? else {
+                // Compressed uploads do not know how many compressed bytes
+                // the blob takes, so they report -1 as the spec requires.
+                return Ok(Response::new(WriteResponse {
+                    committed_size: if is_zstd {
+                        -1
+                    } else {
+                        digest.size_bytes() as i64
+                    },
+                }));
+            };
+            Some(inflight_upload)
+        };
+        let mut active_stream_guard = self.create_or_join_upload_stream(
+            key,
+            peer,
+            store,
+            is_zstd.then_some(digest_function),
+        )?;
+        // The size of compressed data is not known upfront, the decompressed
+        // data is checked against the digest instead.
+        let max_size = if is_zstd {
+            u64::MAX
+        } else {
+            stream.resource_info.expected_size as u64
+        };
 
         let active_stream = active_stream_guard.stream_state.as_mut().unwrap();
-        try_join!(
+        let result = try_join!(
             process_client_stream(
                 stream,
                 &mut active_stream.tx,
                 &active_stream_guard.bytes_received,
-                expected_size
+                max_size
             ),
             (&mut active_stream.store_update_fut)
                 .map_err(|err| { err.append("Error updating inner store") })
-        )?;
+        )
+        .map(|_| ());
+        if let Some(inflight_upload) = inflight_upload {
+            inflight_upload.finish(&result);
+        }
+        result?;
+        // For `compressed-blobs` the committed size is the number of
+        // compressed bytes received.
+        let committed_size = active_stream_guard.bytes_received.load(Ordering::Acquire);
 
         // Close our guard and consider the stream no longer active.
         active_stream_guard.graceful_finish();
 
         Ok(Response::new(WriteResponse {
-            committed_size: expected_size as i64,
+            committed_size: committed_size as i64,
         }))
     }
 
@@ -520,6 +851,12 @@ impl ByteStreamServer {
         query_request: &QueryWriteStatusRequest,
     ) -> Result<Response<QueryWriteStatusResponse>, Error> {
         let mut resource_info = ResourceInfo::new(&query_request.resource_name, true)?;
+        self.validate_resource_info(&resource_info)
+            .err_tip(|| "In ByteStreamServer::query_write_status")?;
+        authorize(
+            AuthorizationAction::read,
+            resource_info.instance_name.as_ref(),
+        )?;
 
         let store_clone = self
             .stores
@@ -541,16 +878,21 @@ impl ByteStreamServer {
                 .await;
         }
 
-        let uuid = resource_info
-            .uuid
-            .take()
-            .ok_or_else(|| make_input_err!("UUID must be set if querying write status"))?;
+        let key = UploadKey {
+            instance_name: resource_info.instance_name.to_string(),
+            uuid: resource_info
+                .uuid
+                .take()
+                .ok_or_else(|| make_input_err!("UUID must be set if querying write status"))?
+                .to_string(),
+            digest,
+        };
 
         {
             let active_uploads = self.active_uploads.lock();
-            if let Some((received_bytes, _maybe_idle_stream)) = active_uploads.get(uuid.as_ref()) {
+            if let Some(session) = active_uploads.get(&key) {
                 return Ok(Response::new(QueryWriteStatusResponse {
-                    committed_size: received_bytes.load(Ordering::Acquire) as i64,
+                    committed_size: session.bytes_received.load(Ordering::Acquire) as i64,
                     // If we are in the active_uploads map, but the value is None,
                     // it means the stream is not complete.
                     complete: false,
@@ -595,7 +937,10 @@ impl ByteStream for ByteStreamServer {
         let ctx = OriginEventContext::new(|| &read_request).await;
 
         let resource_info = ResourceInfo::new(&read_request.resource_name, false)?;
+        self.validate_resource_info(&resource_info)
+            .err_tip(|| "In ByteStreamServer::read")?;
         let instance_name = resource_info.instance_name.as_ref();
+        authorize(AuthorizationAction::read, instance_name)?;
         let store = self
             .stores
             .get(instance_name)
@@ -604,29 +949,54 @@ impl ByteStream for ByteStreamServer {
 
         let digest = DigestInfo::try_new(resource_info.hash.as_ref(), resource_info.expected_size)?;
 
+        let digest_function = resource_info.digest_function.as_deref().map_or_else(
+            || {
+                Ok(DigestHasherFunc::for_omitted_resource_name_component(
+                    &resource_info.hash,
+                ))
+            },
+            DigestHasherFunc::try_from,
+        )?;
+        self.verify_digest_function(instance_name, digest_function)
+            .err_tip(|| "In ByteStreamServer::read")?;
+        let is_zstd = is_zstd_compressed(&resource_info).err_tip(|| "In ByteStreamServer::read")?;
+
+        let maybe_metrics = self.instance_metrics.get(instance_name).cloned();
+        if let Some(metrics) = &maybe_metrics {
+            metrics.bytestream.download_requests.inc();
+        }
+        // Bytes are counted as each chunk is handed to the client.
+        let count_bytes_sent = move |response: &ReadResponse| {
+            if let Some(metrics) = &maybe_metrics {
+                metrics
+                    .bytestream
+                    .bytes_sent
+                    .add(response.data.len() as u64);
+            }
+        };
+
         // If we are a GrpcStore we shortcut here, as this is a special store.
         if let Some(grpc_store) = store.downcast_ref::<GrpcStore>(Some(digest.into())) {
             let stream = grpc_store.read(Request::new(read_request)).await?;
-            let resp = Ok(Response::new(ctx.wrap_stream(stream)));
+            let resp = Ok(Response::new(
+                ctx.wrap_stream(stream.inspect_ok(count_bytes_sent)),
+            ));
             ctx.emit(|| &resp).await;
             return resp;
         }
 
-        let digest_function = resource_info.digest_function.as_deref().map_or_else(
-            || Ok(default_digest_hasher_func()),
-            DigestHasherFunc::try_from,
-        )?;
-
         let resp = make_ctx_for_hash_func(digest_function)
             .err_tip(|| "In BytestreamServer::read")?
             .wrap_async(
                 error_span!("bytestream_read"),
-                self.inner_read(store, digest, read_request),
+                self.inner_read(store, digest, read_request, is_zstd),
             )
             .await
             .err_tip(|| "In ByteStreamServer::read")
             .map(|stream| -> Response<Self::ReadStream> {
-                Response::new(Box::pin(ctx.wrap_stream(stream)))
+                Response::new(Box::pin(
+                    ctx.wrap_stream(stream.inspect_ok(count_bytes_sent)),
+                ))
             })
             .map_err(Into::into);
 
@@ -648,14 +1018,19 @@ impl ByteStream for ByteStreamServer {
         &self,
         grpc_request: Request<Streaming<WriteRequest>>,
     ) -> Result<Response<WriteResponse>, Status> {
+        let started_at = Instant::now();
+        let peer = grpc_request.remote_addr();
         let request = grpc_request.into_inner();
         let ctx = OriginEventContext::new(|| &request).await;
         let stream = WriteRequestStreamWrapper::from(ctx.wrap_stream(request))
             .await
             .err_tip(|| "Could not unwrap first stream message")
             .map_err(Into::<Status>::into)?;
+        self.validate_resource_info(&stream.resource_info)
+            .err_tip(|| "In ByteStreamServer::write")?;
 
         let instance_name = stream.resource_info.instance_name.as_ref();
+        authorize(AuthorizationAction::cas_write, instance_name)?;
         let store = self
             .stores
             .get(instance_name)
@@ -668,31 +1043,59 @@ impl ByteStream for ByteStreamServer {
         )
         .err_tip(|| "Invalid digest input in ByteStream::write")?;
 
-        // If we are a GrpcStore we shortcut here, as this is a special store.
-        if let Some(grpc_store) = store.downcast_ref::<GrpcStore>(Some(digest.into())) {
-            let resp = grpc_store.write(stream).await.map_err(Into::into);
-            ctx.emit(|| &resp).await;
-            return resp;
-        }
-
         let digest_function = stream
             .resource_info
             .digest_function
             .as_deref()
             .map_or_else(
-                || Ok(default_digest_hasher_func()),
+                || {
+                    Ok(DigestHasherFunc::for_omitted_resource_name_component(
+                        &stream.resource_info.hash,
+                    ))
+                },
                 DigestHasherFunc::try_from,
             )?;
+        self.verify_digest_function(instance_name, digest_function)
+            .err_tip(|| "In ByteStreamServer::write")?;
+
+        let maybe_metrics = self.instance_metrics.get(instance_name).cloned();
+        let instance_name = instance_name.to_string();
+        let record_upload = |resp: &Result<Response<WriteResponse>, Status>| {
+            if let (Some(metrics), Ok(resp)) = (&maybe_metrics, resp) {
+                metrics
+                    .bytestream
+                    .record_upload(u64::try_from(resp.get_ref().committed_size).unwrap_or(0));
+            }
+            record_audit(&AuditRecord {
+                operation: "Write",
+                instance_name: &instance_name,
+                digest: Some(digest),
+                size_bytes: digest.size_bytes(),
+                latency: started_at.elapsed(),
+                status: resp
+                    .as_ref()
+                    .map_or_else(|status| status.code().into(), |_| Code::Ok),
+            });
+        };
+
+        // If we are a GrpcStore we shortcut here, as this is a special store.
+        if let Some(grpc_store) = store.downcast_ref::<GrpcStore>(Some(digest.into())) {
+            let resp = grpc_store.write(stream).await.map_err(Into::into);
+            record_upload(&resp);
+            ctx.emit(|| &resp).await;
+            return resp;
+        }
 
         let resp = make_ctx_for_hash_func(digest_function)
             .err_tip(|| "In BytestreamServer::write")?
             .wrap_async(
                 error_span!("bytestream_write"),
-                self.inner_write(store, digest, stream),
+                self.inner_write(store, digest, digest_function, peer, stream),
             )
             .await
             .err_tip(|| "In ByteStreamServer::write")
             .map_err(Into::into);
+        record_upload(&resp);
         ctx.emit(|| &resp).await;
         resp
     }
@@ -15,39 +15,80 @@
 use std::collections::HashMap;
 use std::sync::Arc;
 
-use nativelink_config::cas_server::{CapabilitiesConfig, InstanceName};
+use nativelink_config::cas_server::{
+    InstanceName, ServicesConfig, SymlinkAbsolutePathStrategy as ConfigSymlinkAbsolutePathStrategy,
+};
 use nativelink_error::{Error, ResultExt};
 use nativelink_proto::build::bazel::remote::execution::v2::capabilities_server::{
     Capabilities, CapabilitiesServer as Server,
 };
-use nativelink_proto::build::bazel::remote::execution::v2::digest_function::Value as DigestFunction;
 use nativelink_proto::build::bazel::remote::execution::v2::priority_capabilities::PriorityRange;
 use nativelink_proto::build::bazel::remote::execution::v2::symlink_absolute_path_strategy::Value as SymlinkAbsolutePathStrategy;
 use nativelink_proto::build::bazel::remote::execution::v2::{
-    ActionCacheUpdateCapabilities, CacheCapabilities, ExecutionCapabilities,
+    compressor, ActionCacheUpdateCapabilities, CacheCapabilities, ExecutionCapabilities,
     GetCapabilitiesRequest, PriorityCapabilities, ServerCapabilities,
 };
 use nativelink_proto::build::bazel::semver::SemVer;
-use nativelink_util::digest_hasher::default_digest_hasher_func;
+use nativelink_util::digest_hasher::{
+    allowed_digest_hasher_funcs, default_digest_hasher_func, DEFAULT_ALLOWED_DIGEST_HASHER_FUNCS,
+};
 use nativelink_util::operation_state_manager::ClientStateManager;
 use nativelink_util::origin_event::OriginEventContext;
 use tonic::{Request, Response, Status};
 use tracing::{event, instrument, Level};
 
-const MAX_BATCH_TOTAL_SIZE: i64 = 64 * 1024;
+use crate::cas_server::DEFAULT_MAX_BATCH_TOTAL_SIZE;
+use crate::instance_router::InstanceRouter;
+
+/// Capabilities of an instance that follow from the configuration of the
+/// services of the server.
+#[derive(Debug)]
+struct InstanceInfo {
+    supported_node_properties: Vec<String>,
+    digest_functions: Vec<i32>,
+    exec_enabled: bool,
+    update_enabled: bool,
+    max_batch_total_size_bytes: i64,
+    supported_compressors: Vec<i32>,
+    symlink_absolute_path_strategy: SymlinkAbsolutePathStrategy,
+}
 
 #[derive(Debug, Default)]
 pub struct CapabilitiesServer {
-    supported_node_properties_for_instance: HashMap<InstanceName, Vec<String>>,
+    instance_infos: HashMap<InstanceName, InstanceInfo>,
+}
+
+fn make_router<T>(config: Option<&HashMap<InstanceName, T>>) -> Result<InstanceRouter<&T>, Error> {
+    let mut router = InstanceRouter::new();
+    for (instance_name, value) in config.into_iter().flatten() {
+        router.insert(instance_name, value)?;
+    }
+    Ok(router)
 }
 
 impl CapabilitiesServer {
     pub async fn new(
-        config: &HashMap<InstanceName, CapabilitiesConfig>,
+        services: &ServicesConfig,
         scheduler_map: &HashMap<String, Arc<dyn ClientStateManager>>,
     ) -> Result<Self, Error> {
-        let mut supported_node_properties_for_instance = HashMap::new();
+        let config = services
+            .capabilities
+            .as_ref()
+            .err_tip(|| "'capabilities' must be configured")?;
+        let ac_configs = make_router(services.ac.as_ref())?;
+        let cas_configs = make_router(services.cas.as_ref())?;
+        let bytestream_stores =
+            make_router(services.bytestream.as_ref().map(|cfg| &cfg.cas_stores))?;
+        let mut instance_infos = HashMap::new();
         for (instance_name, cfg) in config {
+            let digest_functions = if cfg.digest_functions.is_empty() {
+                cas_configs
+                    .get(instance_name)
+                    .map_or(&[][..], |cas_cfg| cas_cfg.digest_functions.as_slice())
+            } else {
+                cfg.digest_functions.as_slice()
+            };
+            let digest_functions = allowed_digest_hasher_funcs(instance_name, digest_functions)?;
             let mut properties = Vec::new();
             if let Some(remote_execution_cfg) = &cfg.remote_execution {
                 let scheduler =
@@ -77,11 +118,46 @@ impl CapabilitiesServer {
                     );
                 }
             }
-            supported_node_properties_for_instance.insert(instance_name.clone(), properties);
+            let max_batch_total_size = cas_configs
+                .get(instance_name)
+                .map_or(0, |cas_cfg| cas_cfg.max_batch_total_size);
+            instance_infos.insert(
+                instance_name.clone(),
+                InstanceInfo {
+                    supported_node_properties: properties,
+                    digest_functions: digest_functions
+                        .iter()
+                        .map(|func| func.proto_digest_func().into())
+                        .collect(),
+                    exec_enabled: cfg.remote_execution.is_some(),
+                    // The action cache may be served by another server.
+                    update_enabled: ac_configs
+                        .get(instance_name)
+                        .is_none_or(|ac_cfg| !ac_cfg.read_only),
+                    max_batch_total_size_bytes: i64::try_from(if max_batch_total_size == 0 {
+                        DEFAULT_MAX_BATCH_TOTAL_SIZE
+                    } else {
+                        max_batch_total_size
+                    })
+                    .err_tip(|| "'max_batch_total_size' is too large")?,
+                    // Only ByteStream supports compressed blobs.
+                    supported_compressors: if bytestream_stores.get(instance_name).is_some() {
+                        vec![compressor::Value::Zstd.into()]
+                    } else {
+                        Vec::new()
+                    },
+                    symlink_absolute_path_strategy: match cfg.symlink_absolute_path_strategy {
+                        ConfigSymlinkAbsolutePathStrategy::disallowed => {
+                            SymlinkAbsolutePathStrategy::Disallowed
+                        }
+                        ConfigSymlinkAbsolutePathStrategy::allowed => {
+                            SymlinkAbsolutePathStrategy::Allowed
+                        }
+                    },
+                },
+            );
         }
-        Ok(CapabilitiesServer {
-            supported_node_properties_for_instance,
-        })
+        Ok(CapabilitiesServer { instance_infos })
     }
 
     pub fn into_service(self) -> Server<CapabilitiesServer> {
@@ -107,39 +183,51 @@ impl Capabilities for CapabilitiesServer {
         let ctx = OriginEventContext::new(|| &request).await;
 
         let instance_name = request.instance_name;
-        let maybe_supported_node_properties = self
-            .supported_node_properties_for_instance
-            .get(&instance_name);
+        let maybe_instance_info = self.instance_infos.get(&instance_name);
+        let digest_functions: Vec<i32> = maybe_instance_info.map_or_else(
+            || {
+                DEFAULT_ALLOWED_DIGEST_HASHER_FUNCS
+                    .iter()
+                    .map(|func| func.proto_digest_func().into())
+                    .collect()
+            },
+            |instance_info| instance_info.digest_functions.clone(),
+        );
         let execution_capabilities =
-            maybe_supported_node_properties.map(|props_for_instance| ExecutionCapabilities {
+            maybe_instance_info.map(|instance_info| ExecutionCapabilities {
                 digest_function: default_digest_hasher_func().proto_digest_func().into(),
-                exec_enabled: true, // TODO(blaise.bruer) Make this configurable.
+                exec_enabled: instance_info.exec_enabled,
                 execution_priority_capabilities: Some(PriorityCapabilities {
                     priorities: vec![PriorityRange {
                         min_priority: 0,
                         max_priority: i32::MAX,
                     }],
                 }),
-                supported_node_properties: props_for_instance.clone(),
-                digest_functions: vec![
-                    DigestFunction::Sha256.into(),
-                    DigestFunction::Blake3.into(),
-                ],
+                supported_node_properties: instance_info.supported_node_properties.clone(),
+                digest_functions: digest_functions.clone(),
             });
 
         let resp = ServerCapabilities {
             cache_capabilities: Some(CacheCapabilities {
-                digest_functions: vec![
-                    DigestFunction::Sha256.into(),
-                    DigestFunction::Blake3.into(),
-                ],
+                digest_functions,
                 action_cache_update_capabilities: Some(ActionCacheUpdateCapabilities {
-                    update_enabled: true,
+                    update_enabled: maybe_instance_info
+                        .is_none_or(|instance_info| instance_info.update_enabled),
                 }),
                 cache_priority_capabilities: None,
-                max_batch_total_size_bytes: MAX_BATCH_TOTAL_SIZE,
-                symlink_absolute_path_strategy: SymlinkAbsolutePathStrategy::Disallowed.into(),
-                supported_compressors: vec![],
+                max_batch_total_size_bytes: maybe_instance_info.map_or(
+                    i64::try_from(DEFAULT_MAX_BATCH_TOTAL_SIZE).unwrap_or(i64::MAX),
+                    |instance_info| instance_info.max_batch_total_size_bytes,
+                ),
+                symlink_absolute_path_strategy: maybe_instance_info
+                    .map_or(SymlinkAbsolutePathStrategy::Disallowed, |instance_info| {
+                        instance_info.symlink_absolute_path_strategy
+                    })
+                    .into(),
+                supported_compressors: maybe_instance_info
+                    .map(|instance_info| instance_info.supported_compressors.clone())
+                    .unwrap_or_default(),
+                // BatchUpdateBlobs only accepts uncompressed blobs.
                 supported_batch_update_compressors: vec![],
             }),
             execution_capabilities,
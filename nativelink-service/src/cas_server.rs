@@ -14,51 +14,331 @@
 
 use std::collections::{HashMap, VecDeque};
 use std::convert::Into;
+use std::io::Write;
 use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Instant;
 
 use bytes::Bytes;
-use futures::stream::{FuturesUnordered, Stream};
-use futures::{StreamExt, TryStreamExt};
-use nativelink_config::cas_server::{CasStoreConfig, InstanceName};
-use nativelink_error::{error_if, make_input_err, Code, Error, ResultExt};
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use futures::stream::{FuturesOrdered, FuturesUnordered, Stream};
+use futures::{try_join, FutureExt, StreamExt, TryStreamExt};
+use nativelink_config::cas_server::{AuthorizationAction, CasStoreConfig, InstanceName};
+use nativelink_error::{error_if, make_err, make_input_err, Code, Error, ResultExt};
 use nativelink_proto::build::bazel::remote::execution::v2::content_addressable_storage_server::{
     ContentAddressableStorage, ContentAddressableStorageServer as Server,
 };
 use nativelink_proto::build::bazel::remote::execution::v2::{
-    batch_read_blobs_response, batch_update_blobs_response, compressor, BatchReadBlobsRequest,
-    BatchReadBlobsResponse, BatchUpdateBlobsRequest, BatchUpdateBlobsResponse, Directory,
-    FindMissingBlobsRequest, FindMissingBlobsResponse, GetTreeRequest, GetTreeResponse,
+    batch_read_blobs_response, batch_update_blobs_request, batch_update_blobs_response, compressor,
+    BatchReadBlobsRequest, BatchReadBlobsResponse, BatchUpdateBlobsRequest,
+    BatchUpdateBlobsResponse, Directory, FindMissingBlobsRequest, FindMissingBlobsResponse,
+    GetTreeRequest, GetTreeResponse,
 };
 use nativelink_proto::google::rpc::Status as GrpcStatus;
 use nativelink_store::ac_utils::get_and_decode_digest;
 use nativelink_store::grpc_store::GrpcStore;
 use nativelink_store::store_manager::StoreManager;
+use nativelink_util::audit_log::{record_audit, AuditRecord};
+use nativelink_util::authorization::authorize;
+use nativelink_util::buf_channel::{make_buf_channel_pair, DropCloserReadHalf};
 use nativelink_util::common::DigestInfo;
-use nativelink_util::digest_hasher::make_ctx_for_hash_func;
+use nativelink_util::digest_hasher::{
+    allowed_digest_hasher_funcs, make_ctx_for_hash_func, verify_digest_hasher_func_allowed,
+    DigestHasherFunc,
+};
 use nativelink_util::origin_event::OriginEventContext;
 use nativelink_util::store_trait::{Store, StoreLike};
+use nativelink_util::zstd_stream;
 use tonic::{Request, Response, Status};
 use tracing::{error_span, event, instrument, Level};
 
+use crate::inflight_uploads::inflight_uploads;
+use crate::instance_metrics::{instance_metrics, InstanceMetrics};
+use crate::instance_router::InstanceRouter;
+
+/// Default value of [`CasStoreConfig::find_missing_batch_size`].
+const DEFAULT_FIND_MISSING_BATCH_SIZE: usize = 1000;
+
+/// Default value of [`CasStoreConfig::max_concurrent_find_missing_batches`].
+const DEFAULT_MAX_CONCURRENT_FIND_MISSING_BATCHES: usize = 16;
+
+/// Default value of [`CasStoreConfig::max_concurrent_batch_updates`].
+const DEFAULT_MAX_CONCURRENT_BATCH_UPDATES: usize = 64;
+
+/// Default value of [`CasStoreConfig::max_batch_total_size`].
+pub const DEFAULT_MAX_BATCH_TOTAL_SIZE: usize = 64 * 1024;
+
+/// Number of directories in each response of a `GetTree` stream when the
+/// client does not set a `page_size`.
+const DEFAULT_GET_TREE_PAGE_SIZE: usize = 1000;
+
+/// Deepest directory tree `GetTree` walks.
+const MAX_GET_TREE_DEPTH: usize = 1024;
+
+/// Most directories `GetTree` walks for a single request.
+const MAX_GET_TREE_DIRECTORIES: usize = 1_000_000;
+
+struct InstanceInfo {
+    store: Store,
+    find_missing_batch_size: usize,
+    max_concurrent_find_missing_batches: usize,
+    max_concurrent_batch_updates: usize,
+    max_batch_total_size: i64,
+    digest_functions: Vec<DigestHasherFunc>,
+}
+
+impl InstanceInfo {
+    /// Returns an error if the instance does not accept the
+    /// `digest_function` of a request.
+    fn verify_digest_function(
+        &self,
+        instance_name: &str,
+        digest_function: i32,
+    ) -> Result<(), Error> {
+        verify_digest_hasher_func_allowed(
+            instance_name,
+            &self.digest_functions,
+            DigestHasherFunc::try_from(digest_function)?,
+        )
+    }
+}
+
 pub struct CasServer {
-    stores: HashMap<String, Store>,
+    instance_infos: InstanceRouter<InstanceInfo>,
+    instance_metrics: InstanceRouter<Arc<InstanceMetrics>>,
 }
 
 type GetTreeStream = Pin<Box<dyn Stream<Item = Result<GetTreeResponse, Status>> + Send + 'static>>;
 
+/// Walks a directory tree in breadth first order. Subtrees that are
+/// referenced more than once are only walked the first time.
+struct TreeWalk {
+    store: Store,
+    root_digest: DigestInfo,
+    /// Directories left to read with their depth in the tree.
+    pending: VecDeque<(DigestInfo, usize)>,
+    /// Every directory found so far with the directory it was first found in.
+    parents: HashMap<DigestInfo, Option<DigestInfo>>,
+}
+
+impl TreeWalk {
+    fn new(store: Store, root_digest: DigestInfo) -> Self {
+        Self {
+            store,
+            root_digest,
+            pending: VecDeque::from([(root_digest, 0)]),
+            parents: HashMap::from([(root_digest, None)]),
+        }
+    }
+
+    /// Digest of the directory `next` returns, if any.
+    fn next_digest(&self) -> Option<DigestInfo> {
+        self.pending.front().map(|(digest, _)| *digest)
+    }
+
+    /// Returns true if `ancestor` is `digest` or one of its parents.
+    fn is_ancestor(&self, ancestor: DigestInfo, mut digest: DigestInfo) -> bool {
+        loop {
+            if digest == ancestor {
+                return true;
+            }
+            match self.parents.get(&digest) {
+                Some(Some(parent)) => digest = *parent,
+                _ => return false,
+            }
+        }
+    }
+
+    async fn next(&mut self) -> Result<Option<Directory>, Error> {
+        let Some((digest, depth)) = self.pending.pop_front() else {
+            return Ok(None);
+        };
+        let directory = get_and_decode_digest::<Directory>(&self.store, digest.into())
+            .await
+            .err_tip(|| "Converting digest to Directory")?;
+        for directory in &directory.directories {
+            let child_digest: DigestInfo = directory
+                .digest
+                .clone()
+                .err_tip(|| "Expected Digest to exist in Directory::directories::digest")?
+                .try_into()
+                .err_tip(|| "In Directory::file::digest")?;
+            if self.parents.contains_key(&child_digest) {
+                error_if!(
+                    self.is_ancestor(child_digest, digest),
+                    "Directory {digest} in the tree of {} refers back to {child_digest}",
+                    self.root_digest
+                );
+                continue;
+            }
+            error_if!(
+                depth >= MAX_GET_TREE_DEPTH,
+                "Tree of {} is deeper than {MAX_GET_TREE_DEPTH} directories",
+                self.root_digest
+            );
+            error_if!(
+                self.parents.len() >= MAX_GET_TREE_DIRECTORIES,
+                "Tree of {} has more than {MAX_GET_TREE_DIRECTORIES} directories",
+                self.root_digest
+            );
+            self.parents.insert(child_digest, Some(digest));
+            self.pending.push_back((child_digest, depth + 1));
+        }
+        Ok(Some(directory))
+    }
+
+    /// Reads up to `page_size` directories.
+    async fn next_page(&mut self, page_size: usize) -> Result<Vec<Directory>, Error> {
+        let mut directories = Vec::new();
+        while directories.len() < page_size {
+            let Some(directory) = self.next().await? else {
+                break;
+            };
+            directories.push(directory);
+        }
+        Ok(directories)
+    }
+}
+
+/// Picks the compressor of the blobs returned by `BatchReadBlobs` out of
+/// the ones the client accepts. Identity is always accepted.
+fn batch_read_compressor(acceptable_compressors: &[i32]) -> compressor::Value {
+    [compressor::Value::Zstd, compressor::Value::Deflate]
+        .into_iter()
+        .find(|value| acceptable_compressors.contains(&i32::from(*value)))
+        .unwrap_or(compressor::Value::Identity)
+}
+
+/// Writes a single blob of a `BatchUpdateBlobs` request to `store`.
+async fn update_blob(
+    store: &Store,
+    request: batch_update_blobs_request::Request,
+) -> Result<(), Error> {
+    let digest = request
+        .digest
+        .ok_or_else(|| make_input_err!("Digest not found in request"))?;
+    let digest_info = DigestInfo::try_from(digest)?;
+    let size_bytes = usize::try_from(digest_info.size_bytes())
+        .err_tip(|| "Digest size_bytes was not convertible to usize")?;
+    error_if!(
+        size_bytes != request.data.len(),
+        "Digest for upload had mismatching sizes, digest said {} data  said {}",
+        size_bytes,
+        request.data.len()
+    );
+    // Another client may be uploading the same blob right now.
+    let Some(inflight_upload) = inflight_uploads().join(store, digest_info).await? else {
+        return Ok(());
+    };
+    let result = store
+        .update_oneshot(digest_info, request.data)
+        .await
+        .err_tip(|| "Error writing to store");
+    inflight_upload.finish(&result);
+    result
+}
+
+/// Collects all the data of `reader` compressed as a raw deflate stream.
+async fn deflate_compress(reader: &mut DropCloserReadHalf) -> Result<Bytes, Error> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+    loop {
+        let chunk = reader
+            .recv()
+            .await
+            .err_tip(|| "Failed to read chunk in deflate_compress")?;
+        if chunk.is_empty() {
+            break; // EOF.
+        }
+        encoder
+            .write_all(&chunk)
+            .map_err(|e| make_err!(Code::Internal, "Failed to deflate compress: {e:?}"))?;
+    }
+    encoder
+        .finish()
+        .map(Bytes::from)
+        .map_err(|e| make_err!(Code::Internal, "Failed to finish deflate stream: {e:?}"))
+}
+
+/// Reads the blob of `digest` compressed with `compressor`. The data is
+/// compressed while it is read from the store.
+async fn read_compressed_blob(
+    store: &Store,
+    digest: DigestInfo,
+    compressor: compressor::Value,
+) -> Result<Bytes, Error> {
+    let (mut store_tx, mut store_rx) = make_buf_channel_pair();
+    let get_part_fut = async move { store.get_part(digest, &mut store_tx, 0, None).await };
+    match compressor {
+        compressor::Value::Zstd => {
+            let (mut compressed_tx, mut compressed_rx) = make_buf_channel_pair();
+            let ((), (), data) = try_join!(
+                get_part_fut,
+                async move {
+                    zstd_stream::compress(
+                        &mut store_rx,
+                        &mut compressed_tx,
+                        zstd_stream::DEFAULT_COMPRESSION_LEVEL,
+                    )
+                    .await
+                },
+                compressed_rx.consume(None),
+            )?;
+            Ok(data)
+        }
+        compressor::Value::Deflate => {
+            let ((), data) = try_join!(get_part_fut, deflate_compress(&mut store_rx))?;
+            Ok(data)
+        }
+        _ => store.get_part_unchunked(digest, 0, None).await,
+    }
+}
+
 impl CasServer {
     pub fn new(
         config: &HashMap<InstanceName, CasStoreConfig>,
         store_manager: &StoreManager,
     ) -> Result<Self, Error> {
-        let mut stores = HashMap::with_capacity(config.len());
+        let mut instance_infos = InstanceRouter::new();
+        let mut metrics = InstanceRouter::new();
         for (instance_name, cas_cfg) in config {
             let store = store_manager.get_store(&cas_cfg.cas_store).ok_or_else(|| {
                 make_input_err!("'cas_store': '{}' does not exist", cas_cfg.cas_store)
             })?;
-            stores.insert(instance_name.to_string(), store);
+            let or_default = |value, default| if value == 0 { default } else { value };
+            instance_infos.insert(
+                instance_name,
+                InstanceInfo {
+                    store,
+                    find_missing_batch_size: or_default(
+                        cas_cfg.find_missing_batch_size,
+                        DEFAULT_FIND_MISSING_BATCH_SIZE,
+                    ),
+                    max_concurrent_find_missing_batches: or_default(
+                        cas_cfg.max_concurrent_find_missing_batches,
+                        DEFAULT_MAX_CONCURRENT_FIND_MISSING_BATCHES,
+                    ),
+                    max_concurrent_batch_updates: or_default(
+                        cas_cfg.max_concurrent_batch_updates,
+                        DEFAULT_MAX_CONCURRENT_BATCH_UPDATES,
+                    ),
+                    max_batch_total_size: i64::try_from(or_default(
+                        cas_cfg.max_batch_total_size,
+                        DEFAULT_MAX_BATCH_TOTAL_SIZE,
+                    ))
+                    .err_tip(|| "'max_batch_total_size' is too large")?,
+                    digest_functions: allowed_digest_hasher_funcs(
+                        instance_name,
+                        &cas_cfg.digest_functions,
+                    )?,
+                },
+            )?;
+            metrics.insert(instance_name, instance_metrics(instance_name))?;
         }
-        Ok(CasServer { stores })
+        Ok(CasServer {
+            instance_infos,
+            instance_metrics: metrics,
+        })
     }
 
     pub fn into_service(self) -> Server<CasServer> {
@@ -70,20 +350,34 @@ impl CasServer {
         request: FindMissingBlobsRequest,
     ) -> Result<Response<FindMissingBlobsResponse>, Error> {
         let instance_name = &request.instance_name;
-        let store = self
-            .stores
+        authorize(AuthorizationAction::read, instance_name)?;
+        let instance_info = self
+            .instance_infos
             .get(instance_name)
-            .err_tip(|| format!("'instance_name' not configured for '{instance_name}'"))?
-            .clone();
+            .err_tip(|| format!("'instance_name' not configured for '{instance_name}'"))?;
+        instance_info.verify_digest_function(instance_name, request.digest_function)?;
 
         let mut requested_blobs = Vec::with_capacity(request.blob_digests.len());
         for digest in &request.blob_digests {
             requested_blobs.push(DigestInfo::try_from(digest.clone())?.into());
         }
-        let sizes = store
-            .has_many(&requested_blobs)
-            .await
-            .err_tip(|| "In find_missing_blobs")?;
+        // Large requests are checked in batches, so a single request does
+        // not turn into one huge call to the store.
+        let mut batches = requested_blobs.chunks(instance_info.find_missing_batch_size);
+        let mut pending_batches = FuturesOrdered::new();
+        let mut sizes = Vec::with_capacity(requested_blobs.len());
+        loop {
+            while pending_batches.len() < instance_info.max_concurrent_find_missing_batches {
+                let Some(keys) = batches.next() else {
+                    break;
+                };
+                pending_batches.push_back(instance_info.store.has_many(keys));
+            }
+            let Some(batch_sizes) = pending_batches.next().await else {
+                break;
+            };
+            sizes.extend(batch_sizes.err_tip(|| "In find_missing_blobs")?);
+        }
         let missing_blob_digests = sizes
             .into_iter()
             .zip(request.blob_digests)
@@ -100,52 +394,76 @@ impl CasServer {
         request: BatchUpdateBlobsRequest,
     ) -> Result<Response<BatchUpdateBlobsResponse>, Error> {
         let instance_name = &request.instance_name;
+        authorize(AuthorizationAction::cas_write, instance_name)?;
 
-        let store = self
-            .stores
+        let instance_info = self
+            .instance_infos
             .get(instance_name)
-            .err_tip(|| format!("'instance_name' not configured for '{instance_name}'"))?
-            .clone();
+            .err_tip(|| format!("'instance_name' not configured for '{instance_name}'"))?;
+        instance_info.verify_digest_function(instance_name, request.digest_function)?;
+
+        let maybe_metrics = self.instance_metrics.get(instance_name);
 
         // If we are a GrpcStore we shortcut here, as this is a special store.
         // Note: We don't know the digests here, so we try perform a very shallow
         // check to see if it's a grpc store.
-        if let Some(grpc_store) = store.downcast_ref::<GrpcStore>(None) {
-            return grpc_store.batch_update_blobs(Request::new(request)).await;
+        if let Some(grpc_store) = instance_info.store.downcast_ref::<GrpcStore>(None) {
+            let total_size = request
+                .requests
+                .iter()
+                .map(|request| request.data.len() as u64)
+                .sum();
+            let response = grpc_store.batch_update_blobs(Request::new(request)).await?;
+            if let Some(metrics) = maybe_metrics {
+                metrics.cas.record_upload(total_size);
+            }
+            return Ok(response);
         }
 
-        let store_ref = &store;
-        let update_futures: FuturesUnordered<_> = request
-            .requests
-            .into_iter()
-            .map(|request| async move {
-                let digest = request
-                    .digest
-                    .clone()
-                    .err_tip(|| "Digest not found in request")?;
-                let request_data = request.data;
-                let digest_info = DigestInfo::try_from(digest.clone())?;
-                let size_bytes = usize::try_from(digest_info.size_bytes())
-                    .err_tip(|| "Digest size_bytes was not convertible to usize")?;
-                error_if!(
-                    size_bytes != request_data.len(),
-                    "Digest for upload had mismatching sizes, digest said {} data  said {}",
-                    size_bytes,
-                    request_data.len()
-                );
-                let result = store_ref
-                    .update_oneshot(digest_info, request_data)
-                    .await
-                    .err_tip(|| "Error writing to store");
-                Ok::<_, Error>(batch_update_blobs_response::Response {
-                    digest: Some(digest),
-                    status: Some(result.map_or_else(Into::into, |()| GrpcStatus::default())),
-                })
-            })
-            .collect();
-        let responses = update_futures
-            .try_collect::<Vec<batch_update_blobs_response::Response>>()
-            .await?;
+        // A bad blob only fails its own entry, the others are still written.
+        let started_at = Instant::now();
+        let mut requests = request.requests.into_iter();
+        let mut pending_updates = FuturesUnordered::new();
+        let mut responses = Vec::with_capacity(requests.len());
+        let mut uploaded_size = 0;
+        loop {
+            while pending_updates.len() < instance_info.max_concurrent_batch_updates {
+                let Some(request) = requests.next() else {
+                    break;
+                };
+                let digest = request.digest.clone();
+                let size_bytes = request.data.len() as u64;
+                pending_updates.push(update_blob(&instance_info.store, request).map(
+                    move |result| {
+                        let uploaded_size = if result.is_ok() { size_bytes } else { 0 };
+                        record_audit(&AuditRecord {
+                            operation: "BatchUpdateBlobs",
+                            instance_name,
+                            digest: digest.clone().and_then(|digest| digest.try_into().ok()),
+                            size_bytes,
+                            latency: started_at.elapsed(),
+                            status: result.as_ref().map_or_else(|err| err.code, |()| Code::Ok),
+                        });
+                        let response = batch_update_blobs_response::Response {
+                            digest,
+                            status: Some(
+                                result.map_or_else(Into::into, |()| GrpcStatus::default()),
+                            ),
+                        };
+                        (response, uploaded_size)
+                    },
+                ));
+            }
+            let Some((response, size)) = pending_updates.next().await else {
+                break;
+            };
+            uploaded_size += size;
+            responses.push(response);
+        }
+        // Only bytes that made it into the store count as received.
+        if let Some(metrics) = maybe_metrics {
+            metrics.cas.record_upload(uploaded_size);
+        }
 
         Ok(Response::new(BatchUpdateBlobsResponse { responses }))
     }
@@ -155,29 +473,55 @@ impl CasServer {
         request: BatchReadBlobsRequest,
     ) -> Result<Response<BatchReadBlobsResponse>, Error> {
         let instance_name = &request.instance_name;
+        authorize(AuthorizationAction::read, instance_name)?;
 
-        let store = self
-            .stores
+        let instance_info = self
+            .instance_infos
             .get(instance_name)
-            .err_tip(|| format!("'instance_name' not configured for '{instance_name}'"))?
-            .clone();
+            .err_tip(|| format!("'instance_name' not configured for '{instance_name}'"))?;
+        instance_info.verify_digest_function(instance_name, request.digest_function)?;
+        let store = instance_info.store.clone();
+
+        let mut total_size: i64 = 0;
+        for digest in &request.digests {
+            total_size = total_size.saturating_add(digest.size_bytes);
+        }
+        error_if!(
+            total_size > instance_info.max_batch_total_size,
+            "BatchReadBlobs requested {total_size} bytes, more than the max_batch_total_size_bytes of {}, use ByteStream to read large blobs",
+            instance_info.max_batch_total_size
+        );
+
+        let maybe_metrics = self.instance_metrics.get(instance_name).cloned();
+        let record_download = |response: &BatchReadBlobsResponse| {
+            if let Some(metrics) = &maybe_metrics {
+                metrics.cas.record_download(
+                    response
+                        .responses
+                        .iter()
+                        .map(|response| response.data.len() as u64)
+                        .sum(),
+                );
+            }
+        };
 
         // If we are a GrpcStore we shortcut here, as this is a special store.
         // Note: We don't know the digests here, so we try perform a very shallow
         // check to see if it's a grpc store.
         if let Some(grpc_store) = store.downcast_ref::<GrpcStore>(None) {
-            return grpc_store.batch_read_blobs(Request::new(request)).await;
+            let response = grpc_store.batch_read_blobs(Request::new(request)).await?;
+            record_download(response.get_ref());
+            return Ok(response);
         }
 
+        let compressor = batch_read_compressor(&request.acceptable_compressors);
         let store_ref = &store;
         let read_futures: FuturesUnordered<_> = request
             .digests
             .into_iter()
             .map(|digest| async move {
                 let digest_copy = DigestInfo::try_from(digest.clone())?;
-                // TODO(allada) There is a security risk here of someone taking all the memory on the instance.
-                let result = store_ref
-                    .get_part_unchunked(digest_copy, 0, None)
+                let result = read_compressed_blob(store_ref, digest_copy, compressor)
                     .await
                     .err_tip(|| "Error reading from store");
                 let (status, data) = result.map_or_else(
@@ -192,10 +536,16 @@ impl CasServer {
                     },
                     |v| (GrpcStatus::default(), v),
                 );
+                // Failed reads have no data, so nothing is compressed.
+                let compressor = if status.code == 0 {
+                    compressor
+                } else {
+                    compressor::Value::Identity
+                };
                 Ok::<_, Error>(batch_read_blobs_response::Response {
                     status: Some(status),
                     digest: Some(digest),
-                    compressor: compressor::Value::Identity.into(),
+                    compressor: compressor.into(),
                     data,
                 })
             })
@@ -204,7 +554,9 @@ impl CasServer {
             .try_collect::<Vec<batch_read_blobs_response::Response>>()
             .await?;
 
-        Ok(Response::new(BatchReadBlobsResponse { responses }))
+        let response = BatchReadBlobsResponse { responses };
+        record_download(&response);
+        Ok(Response::new(response))
     }
 
     async fn inner_get_tree(
@@ -212,12 +564,14 @@ impl CasServer {
         request: GetTreeRequest,
     ) -> Result<impl Stream<Item = Result<GetTreeResponse, Status>> + Send + 'static, Error> {
         let instance_name = &request.instance_name;
+        authorize(AuthorizationAction::read, instance_name)?;
 
-        let store = self
-            .stores
+        let instance_info = self
+            .instance_infos
             .get(instance_name)
-            .err_tip(|| format!("'instance_name' not configured for '{instance_name}'"))?
-            .clone();
+            .err_tip(|| format!("'instance_name' not configured for '{instance_name}'"))?;
+        instance_info.verify_digest_function(instance_name, request.digest_function)?;
+        let store = instance_info.store.clone();
 
         // If we are a GrpcStore we shortcut here, as this is a special store.
         // Note: We don't know the digests here, so we try perform a very shallow
@@ -235,8 +589,6 @@ impl CasServer {
             .try_into()
             .err_tip(|| "In GetTreeRequest::root_digest")?;
 
-        let mut deque: VecDeque<DigestInfo> = VecDeque::new();
-        let mut directories: Vec<Directory> = Vec::new();
         // `page_token` will return the `{hash_str}-{size_bytes}` of the current request's first directory digest.
         let page_token_digest = if request.page_token.is_empty() {
             root_digest
@@ -254,50 +606,49 @@ impl CasServer {
             )
             .err_tip(|| "Failed to parse `page_token` as `Digest` in `GetTreeRequest`")?
         };
-        let page_size = request.page_size;
-        // If `page_size` is 0, paging is not necessary.
-        let mut page_token_matched = page_size == 0;
-        deque.push_back(root_digest);
-
-        while !deque.is_empty() {
-            let digest: DigestInfo = deque.pop_front().err_tip(|| "In VecDeque::pop_front")?;
-            let directory = get_and_decode_digest::<Directory>(&store, digest.into())
-                .await
-                .err_tip(|| "Converting digest to Directory")?;
-            if digest == page_token_digest {
-                page_token_matched = true;
-            }
-            for directory in &directory.directories {
-                let digest: DigestInfo = directory
-                    .digest
-                    .clone()
-                    .err_tip(|| "Expected Digest to exist in Directory::directories::digest")?
-                    .try_into()
-                    .err_tip(|| "In Directory::file::digest")?;
-                deque.push_back(digest);
-            }
-            if page_token_matched {
-                directories.push(directory);
-                if directories.len() as i32 == page_size {
-                    break;
-                }
-            }
+        let mut walk = TreeWalk::new(store, root_digest);
+        // Skip the directories returned by the previous pages.
+        while walk.next_digest() != Some(page_token_digest) {
+            error_if!(
+                walk.next().await?.is_none(),
+                "page_token {} is not part of the tree of {root_digest}",
+                request.page_token
+            );
         }
-        // `next_page_token` will return the `{hash_str}:{size_bytes}` of the next request's first directory digest.
-        // It will be an empty string when it reached the end of the directory tree.
-        let next_page_token: String = if let Some(value) = deque.front() {
-            format!("{value}")
+        // If `page_size` is 0, paging is not necessary and the whole tree is
+        // streamed in responses of `DEFAULT_GET_TREE_PAGE_SIZE` directories.
+        let page_size = usize::try_from(request.page_size).unwrap_or(0);
+        let single_page = page_size != 0;
+        let page_size = if single_page {
+            page_size
         } else {
-            String::new()
+            DEFAULT_GET_TREE_PAGE_SIZE
         };
+        let first_page = Some(walk.next_page(page_size).await?);
 
-        Ok(futures::stream::once(async {
-            Ok(GetTreeResponse {
-                directories,
-                next_page_token,
+        Ok(
+            futures::stream::unfold(Some((first_page, walk)), move |state| async move {
+                let (directories, walk) = match state? {
+                    (Some(directories), walk) => (directories, walk),
+                    (None, mut walk) => match walk.next_page(page_size).await {
+                        Ok(directories) => (directories, walk),
+                        Err(err) => return Some((Err(err.into()), None)),
+                    },
+                };
+                // `next_page_token` will return the `{hash_str}-{size_bytes}` of the next request's first directory digest.
+                // It will be an empty string when it reached the end of the directory tree.
+                let next_page_token = walk
+                    .next_digest()
+                    .map_or_else(String::new, |digest| format!("{digest}"));
+                let done = single_page || next_page_token.is_empty();
+                let response = GetTreeResponse {
+                    directories,
+                    next_page_token,
+                };
+                Some((Ok(response), (!done).then_some((None, walk))))
             })
-        })
-        .right_stream())
+            .right_stream(),
+        )
     }
 }
 
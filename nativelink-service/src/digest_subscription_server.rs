@@ -0,0 +1,161 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use nativelink_config::cas_server::{AuthorizationAction, DigestSubscriptionConfig, InstanceName};
+use nativelink_error::{make_input_err, Error, ResultExt};
+use nativelink_proto::build::bazel::remote::execution::v2::Digest;
+use nativelink_proto::com::github::trace_machina::nativelink::remote_execution::digest_subscription_server::{
+    DigestSubscription, DigestSubscriptionServer as Server,
+};
+use nativelink_proto::com::github::trace_machina::nativelink::remote_execution::{
+    WaitForBlobsRequest, WaitForBlobsResponse,
+};
+use nativelink_store::store_manager::StoreManager;
+use nativelink_util::authorization::authorize;
+use nativelink_util::common::DigestInfo;
+use nativelink_util::digest_hasher::make_ctx_for_hash_func;
+use nativelink_util::store_trait::{Store, StoreKey, StoreLike};
+use tokio::time::{sleep, Instant};
+use tonic::{Request, Response, Status};
+use tracing::{error_span, instrument, Level};
+
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const DEFAULT_MAX_TIMEOUT: Duration = Duration::from_secs(300);
+
+struct InstanceInfo {
+    cas_store: Store,
+    poll_interval: Duration,
+    max_timeout: Duration,
+}
+
+/// Lets clients wait until blobs uploaded by other clients are available
+/// in the CAS. The store is polled until every blob is present or the
+/// timeout elapses.
+pub struct DigestSubscriptionServer {
+    instance_infos: HashMap<InstanceName, InstanceInfo>,
+}
+
+impl DigestSubscriptionServer {
+    pub fn new(
+        config: &HashMap<InstanceName, DigestSubscriptionConfig>,
+        store_manager: &StoreManager,
+    ) -> Result<Self, Error> {
+        let mut instance_infos = HashMap::with_capacity(config.len());
+        for (instance_name, subscription_cfg) in config {
+            let cas_store = store_manager
+                .get_store(&subscription_cfg.cas_store)
+                .ok_or_else(|| {
+                    make_input_err!(
+                        "'cas_store': '{}' does not exist",
+                        subscription_cfg.cas_store
+                    )
+                })?;
+            let poll_interval = if subscription_cfg.poll_interval_ms == 0 {
+                DEFAULT_POLL_INTERVAL
+            } else {
+                Duration::from_millis(subscription_cfg.poll_interval_ms)
+            };
+            let max_timeout = if subscription_cfg.max_timeout == 0 {
+                DEFAULT_MAX_TIMEOUT
+            } else {
+                Duration::from_secs(subscription_cfg.max_timeout as u64)
+            };
+            instance_infos.insert(
+                instance_name.to_string(),
+                InstanceInfo {
+                    cas_store,
+                    poll_interval,
+                    max_timeout,
+                },
+            );
+        }
+        Ok(Self { instance_infos })
+    }
+
+    pub fn into_service(self) -> Server<DigestSubscriptionServer> {
+        Server::new(self)
+    }
+
+    async fn inner_wait_for_blobs(
+        &self,
+        request: WaitForBlobsRequest,
+    ) -> Result<Response<WaitForBlobsResponse>, Error> {
+        let instance_name = &request.instance_name;
+        authorize(AuthorizationAction::read, instance_name)?;
+        let instance_info = self
+            .instance_infos
+            .get(instance_name)
+            .err_tip(|| format!("'instance_name' not configured for '{instance_name}'"))?;
+
+        let timeout = request.timeout.map_or(instance_info.max_timeout, |v| {
+            Duration::new(v.seconds.max(0) as u64, v.nanos.max(0) as u32)
+                .min(instance_info.max_timeout)
+        });
+        let deadline = Instant::now() + timeout;
+
+        let mut missing: Vec<(StoreKey<'static>, Digest)> =
+            Vec::with_capacity(request.blob_digests.len());
+        for digest in request.blob_digests {
+            missing.push((DigestInfo::try_from(digest.clone())?.into(), digest));
+        }
+        loop {
+            let keys: Vec<StoreKey<'_>> = missing.iter().map(|(key, _)| key.borrow()).collect();
+            let sizes = instance_info
+                .cas_store
+                .has_many(&keys)
+                .await
+                .err_tip(|| "In DigestSubscriptionServer::wait_for_blobs")?;
+            let mut sizes = sizes.into_iter();
+            missing.retain(|_| sizes.next().flatten().is_none());
+            if missing.is_empty() || Instant::now() >= deadline {
+                break;
+            }
+            sleep(instance_info.poll_interval.min(deadline - Instant::now())).await;
+        }
+
+        Ok(Response::new(WaitForBlobsResponse {
+            missing_blob_digests: missing.into_iter().map(|(_, digest)| digest).collect(),
+        }))
+    }
+}
+
+#[tonic::async_trait]
+impl DigestSubscription for DigestSubscriptionServer {
+    #[allow(clippy::blocks_in_conditions)]
+    #[instrument(
+        err,
+        ret(level = Level::INFO),
+        level = Level::ERROR,
+        skip_all,
+        fields(request = ?grpc_request.get_ref())
+    )]
+    async fn wait_for_blobs(
+        &self,
+        grpc_request: Request<WaitForBlobsRequest>,
+    ) -> Result<Response<WaitForBlobsResponse>, Status> {
+        let request = grpc_request.into_inner();
+        make_ctx_for_hash_func(request.digest_function)
+            .err_tip(|| "In DigestSubscriptionServer::wait_for_blobs")?
+            .wrap_async(
+                error_span!("digest_subscription_server_wait_for_blobs"),
+                self.inner_wait_for_blobs(request),
+            )
+            .await
+            .err_tip(|| "Failed on wait_for_blobs() command")
+            .map_err(Into::into)
+    }
+}
@@ -17,36 +17,49 @@ use std::convert::Into;
 use std::fmt;
 use std::pin::Pin;
 use std::sync::Arc;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use futures::stream::unfold;
 use futures::{Stream, StreamExt};
-use nativelink_config::cas_server::{ExecutionConfig, InstanceName};
-use nativelink_error::{make_input_err, Error, ResultExt};
+use nativelink_config::cas_server::{AuthorizationAction, ExecutionConfig, InstanceName};
+use nativelink_error::{make_input_err, Code, Error, ResultExt};
 use nativelink_proto::build::bazel::remote::execution::v2::execution_server::{
     Execution, ExecutionServer as Server,
 };
 use nativelink_proto::build::bazel::remote::execution::v2::{
-    Action, Command, ExecuteRequest, WaitExecutionRequest,
+    Action, Command, ExecuteRequest, RequestMetadata, WaitExecutionRequest,
 };
 use nativelink_proto::google::longrunning::Operation;
 use nativelink_store::ac_utils::get_and_decode_digest;
 use nativelink_store::store_manager::StoreManager;
 use nativelink_util::action_messages::{
-    ActionInfo, ActionUniqueKey, ActionUniqueQualifier, OperationId, DEFAULT_EXECUTION_PRIORITY,
+    ActionInfo, ActionStage, ActionUniqueKey, ActionUniqueQualifier, OperationId,
+    DEFAULT_EXECUTION_PRIORITY,
 };
+use nativelink_util::audit_log::{record_audit, AuditRecord};
+use nativelink_util::authorization::authorize;
 use nativelink_util::common::DigestInfo;
 use nativelink_util::digest_hasher::{make_ctx_for_hash_func, DigestHasherFunc};
 use nativelink_util::operation_state_manager::{
     ActionStateResult, ClientStateManager, OperationFilter,
 };
+use nativelink_util::origin_context::{ActiveOriginContext, ORIGIN_IDENTITY};
 use nativelink_util::origin_event::OriginEventContext;
 use nativelink_util::store_trait::Store;
+use prost::Message;
+use tokio::time::sleep;
 use tonic::{Request, Response, Status};
 use tracing::{error_span, event, instrument, Level};
 
 type InstanceInfoName = String;
 
+/// Header the client sends its `RequestMetadata` in.
+const REQUEST_METADATA_HEADER: &str = "build.bazel.remote.execution.v2.requestmetadata-bin";
+
+/// Default value for `ExecutionConfig::queue_position_update_interval_s`.
+/// If this changes, remember to change the documentation in the config.
+const DEFAULT_QUEUE_POSITION_UPDATE_INTERVAL: Duration = Duration::from_secs(10);
+
 struct NativelinkOperationId {
     instance_name: InstanceInfoName,
     client_operation_id: OperationId,
@@ -80,18 +93,33 @@ impl fmt::Display for NativelinkOperationId {
 struct InstanceInfo {
     scheduler: Arc<dyn ClientStateManager>,
     cas_store: Store,
+    default_action_timeout: Option<Duration>,
+    max_action_timeout: Option<Duration>,
+    queue_position_update_interval: Duration,
+}
+
+/// The action a client asked to execute and how it asked for it.
+struct ActionRequest {
+    instance_name: String,
+    action_digest: DigestInfo,
+    action: Action,
+    priority: i32,
+    skip_cache_lookup: bool,
+    digest_function: DigestHasherFunc,
+    tool_invocation_id: Option<String>,
 }
 
 impl InstanceInfo {
-    async fn build_action_info(
-        &self,
-        instance_name: String,
-        action_digest: DigestInfo,
-        action: Action,
-        priority: i32,
-        skip_cache_lookup: bool,
-        digest_function: DigestHasherFunc,
-    ) -> Result<ActionInfo, Error> {
+    async fn build_action_info(&self, request: ActionRequest) -> Result<ActionInfo, Error> {
+        let ActionRequest {
+            instance_name,
+            action_digest,
+            action,
+            priority,
+            skip_cache_lookup,
+            digest_function,
+            tool_invocation_id,
+        } = request;
         let command_digest = DigestInfo::try_from(
             action
                 .command_digest
@@ -106,9 +134,21 @@ impl InstanceInfo {
                 .input_root_digest
                 .err_tip(|| "Expected input_digest_root")?,
         )?;
-        let timeout = action.timeout.map_or(Duration::MAX, |v| {
-            Duration::new(v.seconds as u64, v.nanos as u32)
-        });
+        let timeout = action.timeout.map_or_else(
+            || {
+                self.default_action_timeout
+                    .or(self.max_action_timeout)
+                    .unwrap_or(Duration::MAX)
+            },
+            |v| Duration::new(v.seconds as u64, v.nanos as u32),
+        );
+        if let Some(max_action_timeout) = self.max_action_timeout {
+            if timeout > max_action_timeout {
+                return Err(make_input_err!(
+                    "Action timeout of {timeout:?} exceeds the max_action_timeout of {max_action_timeout:?}"
+                ));
+            }
+        }
 
         let mut platform_properties = HashMap::new();
         if let Some(platform) = action.platform {
@@ -148,6 +188,12 @@ impl InstanceInfo {
             load_timestamp: UNIX_EPOCH,
             insert_timestamp: SystemTime::now(),
             unique_qualifier,
+            tool_invocation_id,
+            client_identity: ActiveOriginContext::get_value(&ORIGIN_IDENTITY)
+                .ok()
+                .flatten()
+                .map(|identity| identity.as_ref().clone()),
+            queue_priority: None,
         })
     }
 }
@@ -181,11 +227,37 @@ impl ExecutionServer {
                 })?
                 .clone();
 
+            let default_action_timeout = (exec_cfg.default_action_timeout != 0)
+                .then(|| Duration::from_secs(exec_cfg.default_action_timeout as u64));
+            let max_action_timeout = (exec_cfg.max_action_timeout != 0)
+                .then(|| Duration::from_secs(exec_cfg.max_action_timeout as u64));
+            if let (Some(default_timeout), Some(max_timeout)) =
+                (default_action_timeout, max_action_timeout)
+            {
+                if default_timeout > max_timeout {
+                    return Err(make_input_err!(
+                        "'default_action_timeout' ({}) must not be greater than 'max_action_timeout' ({}) for instance '{}'",
+                        exec_cfg.default_action_timeout,
+                        exec_cfg.max_action_timeout,
+                        instance_name
+                    ));
+                }
+            }
+
+            let queue_position_update_interval = if exec_cfg.queue_position_update_interval_s == 0 {
+                DEFAULT_QUEUE_POSITION_UPDATE_INTERVAL
+            } else {
+                Duration::from_secs(exec_cfg.queue_position_update_interval_s)
+            };
+
             instance_infos.insert(
                 instance_name.to_string(),
                 InstanceInfo {
                     scheduler,
                     cas_store,
+                    default_action_timeout,
+                    max_action_timeout,
+                    queue_position_update_interval,
                 },
             );
         }
@@ -196,43 +268,79 @@ impl ExecutionServer {
         Server::new(self)
     }
 
+    /// Streams the updates of an action. While the action is queued, the
+    /// current state is also resent every `queue_position_update_interval`
+    /// so clients see its position in the queue move.
     fn to_execute_stream(
         nl_client_operation_id: &NativelinkOperationId,
         action_listener: Box<dyn ActionStateResult>,
+        queue_position_update_interval: Duration,
     ) -> impl Stream<Item = Result<Operation, Status>> + Send + 'static {
         let client_operation_id = OperationId::from(nl_client_operation_id.to_string());
-        unfold(Some(action_listener), move |maybe_action_listener| {
-            let client_operation_id = client_operation_id.clone();
-            async move {
-                let mut action_listener = maybe_action_listener?;
-                match action_listener.changed().await {
-                    Ok(action_update) => {
-                        event!(Level::INFO, ?action_update, "Execute Resp Stream");
-                        // If the action is finished we won't be sending any more updates.
-                        let maybe_action_listener = if action_update.stage.is_finished() {
-                            None
-                        } else {
-                            Some(action_listener)
-                        };
-                        Some((
-                            Ok(action_update.as_operation(client_operation_id)),
-                            maybe_action_listener,
-                        ))
-                    }
-                    Err(err) => {
-                        event!(Level::ERROR, ?err, "Error in action_listener stream");
-                        Some((Err(err.into()), None))
+        unfold(
+            Some((action_listener, false)),
+            move |maybe_action_listener| {
+                let client_operation_id = client_operation_id.clone();
+                async move {
+                    let (mut action_listener, is_queued) = maybe_action_listener?;
+                    let maybe_changed = if is_queued {
+                        tokio::select! {
+                            result = action_listener.changed() => Some(result),
+                            () = sleep(queue_position_update_interval) => None,
+                        }
+                    } else {
+                        Some(action_listener.changed().await)
+                    };
+                    let action_update = match maybe_changed {
+                        Some(result) => result,
+                        None => action_listener.as_state().await,
+                    };
+                    match action_update {
+                        Ok(action_update) => {
+                            event!(Level::INFO, ?action_update, "Execute Resp Stream");
+                            let is_queued = action_update.stage == ActionStage::Queued;
+                            let maybe_queue_position = if is_queued {
+                                action_listener
+                                    .queue_position()
+                                    .await
+                                    .unwrap_or_else(|err| {
+                                        event!(Level::WARN, ?err, "Failed to get queue position");
+                                        None
+                                    })
+                            } else {
+                                None
+                            };
+                            // If the action is finished we won't be sending any more updates.
+                            let maybe_action_listener = if action_update.stage.is_finished() {
+                                None
+                            } else {
+                                Some((action_listener, is_queued))
+                            };
+                            Some((
+                                Ok(action_update.as_operation_with_queue_position(
+                                    client_operation_id,
+                                    maybe_queue_position,
+                                )),
+                                maybe_action_listener,
+                            ))
+                        }
+                        Err(err) => {
+                            event!(Level::ERROR, ?err, "Error in action_listener stream");
+                            Some((Err(err.into()), None))
+                        }
                     }
                 }
-            }
-        })
+            },
+        )
     }
 
     async fn inner_execute(
         &self,
         request: ExecuteRequest,
+        tool_invocation_id: Option<String>,
     ) -> Result<impl Stream<Item = Result<Operation, Status>> + Send + 'static, Error> {
         let instance_name = request.instance_name;
+        authorize(AuthorizationAction::execute, &instance_name)?;
 
         let instance_info = self
             .instance_infos
@@ -253,17 +361,18 @@ impl ExecutionServer {
         let action =
             get_and_decode_digest::<Action>(&instance_info.cas_store, digest.into()).await?;
         let action_info = instance_info
-            .build_action_info(
-                instance_name.clone(),
-                digest,
+            .build_action_info(ActionRequest {
+                instance_name: instance_name.clone(),
+                action_digest: digest,
                 action,
                 priority,
-                request.skip_cache_lookup,
-                request
+                skip_cache_lookup: request.skip_cache_lookup,
+                digest_function: request
                     .digest_function
                     .try_into()
                     .err_tip(|| "Could not convert digest function in inner_execute()")?,
-            )
+                tool_invocation_id,
+            })
             .await?;
 
         let action_listener = instance_info
@@ -283,6 +392,7 @@ impl ExecutionServer {
                     .clone(),
             ),
             action_listener,
+            instance_info.queue_position_update_interval,
         )))
     }
 
@@ -292,6 +402,7 @@ impl ExecutionServer {
     ) -> Result<impl Stream<Item = Result<Operation, Status>> + Send + 'static, Status> {
         let nl_operation_id = NativelinkOperationId::from_name(&request.name)
             .err_tip(|| "Failed to parse operation_id in ExecutionServer::wait_execution")?;
+        authorize(AuthorizationAction::read, &nl_operation_id.instance_name)?;
         let Some(instance_info) = self.instance_infos.get(&nl_operation_id.instance_name) else {
             return Err(Status::not_found(format!(
                 "No scheduler with the instance name {}",
@@ -311,7 +422,11 @@ impl ExecutionServer {
         else {
             return Err(Status::not_found("Failed to find existing task"));
         };
-        Ok(Self::to_execute_stream(&nl_operation_id, rx))
+        Ok(Self::to_execute_stream(
+            &nl_operation_id,
+            rx,
+            instance_info.queue_position_update_interval,
+        ))
     }
 }
 
@@ -331,14 +446,35 @@ impl Execution for ExecutionServer {
         &self,
         grpc_request: Request<ExecuteRequest>,
     ) -> Result<Response<ExecuteStream>, Status> {
+        let tool_invocation_id = grpc_request
+            .metadata()
+            .get_bin(REQUEST_METADATA_HEADER)
+            .and_then(|value| value.to_bytes().ok())
+            .and_then(|data| RequestMetadata::decode(data).ok())
+            .map(|metadata| metadata.tool_invocation_id)
+            .filter(|tool_invocation_id| !tool_invocation_id.is_empty());
         let request = grpc_request.into_inner();
         let ctx = OriginEventContext::new(|| &request).await;
+        let started_at = Instant::now();
+        let instance_name = request.instance_name.clone();
+        let digest: Option<DigestInfo> = request
+            .action_digest
+            .clone()
+            .and_then(|digest| digest.try_into().ok());
         let resp = make_ctx_for_hash_func(request.digest_function)
             .err_tip(|| "In ExecutionServer::execute")?
-            .wrap_async(
-                error_span!("execution_server_execute"),
-                self.inner_execute(request),
-            )
+            .wrap_async(error_span!("execution_server_execute"), async {
+                let result = self.inner_execute(request, tool_invocation_id).await;
+                record_audit(&AuditRecord {
+                    operation: "Execute",
+                    instance_name: &instance_name,
+                    digest,
+                    size_bytes: digest.map_or(0, |digest| digest.size_bytes()),
+                    latency: started_at.elapsed(),
+                    status: result.as_ref().map_or_else(|err| err.code, |_| Code::Ok),
+                });
+                result
+            })
             .await
             .map(|stream| ctx.wrap_stream(stream))
             .map(Response::new)
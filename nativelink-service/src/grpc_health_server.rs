@@ -0,0 +1,101 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Duration;
+
+use futures::StreamExt;
+use nativelink_util::health_utils::{
+    HealthRegistry, HealthStatus, HealthStatusDescription, HealthStatusReporter,
+};
+use tokio::time::interval;
+use tonic_health::pb::health_server::{Health, HealthServer};
+use tonic_health::server::{health_reporter, HealthReporter};
+use tonic_health::ServingStatus;
+use tracing::{event, Level};
+
+/// Default value for `HealthConfig::grpc_check_interval_s`.
+pub const DEFAULT_GRPC_CHECK_INTERVAL_S: u64 = 5;
+
+/// Name of the service that stands for the whole server in the gRPC health
+/// checking protocol.
+const OVERALL_SERVICE_NAME: &str = "";
+
+/// Serves `grpc.health.v1.Health` from the health indicators of the stores
+/// and schedulers. The server is reported as serving once all of them were
+/// checked and none of them failed or is still initializing.
+pub struct GrpcHealthServer {
+    health_registry: HealthRegistry,
+    reporter: HealthReporter,
+    serving_status: ServingStatus,
+}
+
+impl GrpcHealthServer {
+    pub async fn new(health_registry: HealthRegistry) -> (Self, HealthServer<impl Health>) {
+        let (mut reporter, service) = health_reporter();
+        // The reporter starts out serving, but nothing was checked yet.
+        reporter
+            .set_service_status(OVERALL_SERVICE_NAME, ServingStatus::NotServing)
+            .await;
+        (
+            Self {
+                health_registry,
+                reporter,
+                serving_status: ServingStatus::NotServing,
+            },
+            service,
+        )
+    }
+
+    /// Checks all health indicators once and reports the result.
+    pub async fn update_serving_status(&mut self) -> ServingStatus {
+        let failures: Vec<HealthStatusDescription> = self
+            .health_registry
+            .health_status_report()
+            .filter(|description| {
+                futures::future::ready(matches!(
+                    description.status,
+                    HealthStatus::Initializing { .. } | HealthStatus::Failed { .. }
+                ))
+            })
+            .collect()
+            .await;
+        let serving_status = if failures.is_empty() {
+            ServingStatus::Serving
+        } else {
+            ServingStatus::NotServing
+        };
+        if serving_status != self.serving_status {
+            event!(
+                Level::WARN,
+                ?serving_status,
+                ?failures,
+                "gRPC health status changed"
+            );
+            self.serving_status = serving_status;
+        }
+        self.reporter
+            .set_service_status(OVERALL_SERVICE_NAME, serving_status)
+            .await;
+        serving_status
+    }
+
+    /// Keeps the reported status up to date, checking every `check_interval`.
+    pub async fn run(mut self, check_interval: Duration) {
+        let mut interval = interval(check_interval);
+        loop {
+            interval.tick().await;
+            self.update_serving_status().await;
+        }
+    }
+}
@@ -0,0 +1,118 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+
+use futures::channel::oneshot;
+use futures::future::Shared;
+use futures::FutureExt;
+use nativelink_error::{Error, ResultExt};
+use nativelink_util::common::DigestInfo;
+use nativelink_util::store_trait::{Store, StoreLike};
+use parking_lot::Mutex;
+
+static INFLIGHT_UPLOADS: OnceLock<InflightUploads> = OnceLock::new();
+
+/// Returns the process wide registry of the uploads in flight, shared by
+/// every service that writes to the CAS.
+pub fn inflight_uploads() -> &'static InflightUploads {
+    INFLIGHT_UPLOADS.get_or_init(InflightUploads::default)
+}
+
+/// Resolves with the result of an upload once it is done. Resolves with
+/// `Canceled` if the upload was abandoned.
+type UploadResult = Shared<oneshot::Receiver<Result<(), Error>>>;
+
+/// Identifies an upload of `DigestInfo` to the store at the address.
+type UploadKey = (usize, DigestInfo);
+
+/// Tracks the blobs being uploaded, so when several clients upload the
+/// same blob at the same time only one of them writes it to the store.
+#[derive(Default)]
+pub struct InflightUploads {
+    uploads: Mutex<HashMap<UploadKey, UploadResult>>,
+}
+
+impl InflightUploads {
+    /// Waits until no other upload of `digest` to `store` is in flight.
+    /// Returns None if the blob was uploaded in the meantime, otherwise
+    /// the caller must upload it and report the result to the guard.
+    pub async fn join(
+        &'static self,
+        store: &Store,
+        digest: DigestInfo,
+    ) -> Result<Option<InflightUploadGuard>, Error> {
+        let key = (
+            Arc::as_ptr(&store.clone().into_inner()).cast::<()>() as usize,
+            digest,
+        );
+        loop {
+            let upload = match self.uploads.lock().entry(key) {
+                Entry::Occupied(entry) => entry.get().clone(),
+                Entry::Vacant(entry) => {
+                    let (tx, rx) = oneshot::channel();
+                    entry.insert(rx.shared());
+                    return Ok(Some(InflightUploadGuard {
+                        registry: self,
+                        key,
+                        tx: Some(tx),
+                    }));
+                }
+            };
+            if let Ok(Ok(())) = upload.await {
+                return Ok(None);
+            }
+            // The other upload failed, but the blob may have been uploaded
+            // some other way.
+            if store
+                .has(digest)
+                .await
+                .err_tip(|| "In InflightUploads::join")?
+                .is_some()
+            {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+/// Held while uploading a blob. Other uploads of the blob wait until it
+/// is dropped, and upload the blob themselves unless `finish` was called
+/// with a success.
+pub struct InflightUploadGuard {
+    registry: &'static InflightUploads,
+    key: UploadKey,
+    tx: Option<oneshot::Sender<Result<(), Error>>>,
+}
+
+impl InflightUploadGuard {
+    /// Reports the result of the upload to the ones waiting for it.
+    pub fn finish(mut self, result: &Result<(), Error>) {
+        self.registry.uploads.lock().remove(&self.key);
+        if let Some(tx) = self.tx.take() {
+            // Nobody waiting is fine.
+            let _ = tx.send(result.clone());
+        }
+    }
+}
+
+impl Drop for InflightUploadGuard {
+    fn drop(&mut self) {
+        if self.tx.is_some() {
+            self.registry.uploads.lock().remove(&self.key);
+        }
+    }
+}
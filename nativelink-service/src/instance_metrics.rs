@@ -0,0 +1,115 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, OnceLock};
+
+use nativelink_config::cas_server::InstanceName;
+use nativelink_metric::{MetricsComponent, RootMetricsComponent};
+use nativelink_util::metrics_utils::Counter;
+use parking_lot::RwLock;
+
+static INSTANCE_METRICS_REGISTRY: OnceLock<Arc<InstanceMetricsRegistry>> = OnceLock::new();
+
+/// Returns the process wide registry holding the transfer metrics of
+/// every `instance_name` that has been configured on a service.
+pub fn instance_metrics_registry() -> Arc<InstanceMetricsRegistry> {
+    INSTANCE_METRICS_REGISTRY
+        .get_or_init(|| Arc::new(InstanceMetricsRegistry::default()))
+        .clone()
+}
+
+/// Returns the metrics for the given `instance_name`, creating them if
+/// this is the first service to use this `instance_name`.
+pub fn instance_metrics(instance_name: &str) -> Arc<InstanceMetrics> {
+    instance_metrics_registry().get_or_create(instance_name)
+}
+
+/// Bytes and requests that flowed through a single service.
+#[derive(Default, MetricsComponent)]
+pub struct TransferMetrics {
+    #[metric(help = "Number of bytes received from clients.")]
+    pub bytes_received: Counter,
+    #[metric(help = "Number of bytes sent to clients.")]
+    pub bytes_sent: Counter,
+    #[metric(help = "Number of requests that uploaded data.")]
+    pub upload_requests: Counter,
+    #[metric(help = "Number of requests that downloaded data.")]
+    pub download_requests: Counter,
+}
+
+impl TransferMetrics {
+    /// Records an upload request that received `bytes` from the client.
+    #[inline]
+    pub fn record_upload(&self, bytes: u64) {
+        self.upload_requests.inc();
+        self.bytes_received.add(bytes);
+    }
+
+    /// Records a download request that sent `bytes` to the client.
+    #[inline]
+    pub fn record_download(&self, bytes: u64) {
+        self.download_requests.inc();
+        self.bytes_sent.add(bytes);
+    }
+}
+
+/// Upload sessions of the `ByteStream` service. A session lasts from the
+/// first write of an upload until it finishes or expires.
+#[derive(Default, MetricsComponent)]
+pub struct UploadSessionMetrics {
+    #[metric(help = "Number of upload sessions receiving data or waiting to be resumed.")]
+    pub active_sessions: AtomicU64,
+    #[metric(help = "Number of upload sessions that expired before they finished.")]
+    pub abandoned_sessions: Counter,
+    #[metric(help = "Number of bytes received by upload sessions that expired.")]
+    pub abandoned_bytes: Counter,
+}
+
+/// Transfer metrics of every service that serves a given `instance_name`.
+#[derive(Default, MetricsComponent)]
+pub struct InstanceMetrics {
+    #[metric(group = "cas")]
+    pub cas: TransferMetrics,
+    #[metric(group = "bytestream")]
+    pub bytestream: TransferMetrics,
+    #[metric(group = "bytestream_sessions")]
+    pub bytestream_sessions: UploadSessionMetrics,
+    #[metric(group = "ac")]
+    pub ac: TransferMetrics,
+}
+
+/// Map of `instance_name` to the metrics of that instance.
+#[derive(Default, MetricsComponent)]
+pub struct InstanceMetricsRegistry {
+    #[metric]
+    instances: RwLock<HashMap<InstanceName, Arc<InstanceMetrics>>>,
+}
+
+impl InstanceMetricsRegistry {
+    /// Returns the metrics for `instance_name`, creating them if needed.
+    pub fn get_or_create(&self, instance_name: &str) -> Arc<InstanceMetrics> {
+        if let Some(metrics) = self.instances.read().get(instance_name) {
+            return metrics.clone();
+        }
+        self.instances
+            .write()
+            .entry(instance_name.to_string())
+            .or_default()
+            .clone()
+    }
+}
+
+impl RootMetricsComponent for InstanceMetricsRegistry {}
@@ -0,0 +1,82 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+
+use nativelink_error::{error_if, Error};
+
+/// Maps the `instance_name` of a request to the configured instance that
+/// serves it. Configured names are either:
+/// * An exact instance name, like `"main"`.
+/// * A prefix followed by `*`, like `"ci/*"`, that serves every instance
+///   name starting with `"ci/"`. When several prefixes match, the longest
+///   one wins. A lone `"*"` serves every instance nothing else matches.
+///
+/// Exact names always win over prefixes.
+#[derive(Clone, Debug)]
+pub struct InstanceRouter<T> {
+    exact: HashMap<String, T>,
+    /// Sorted from the longest to the shortest prefix.
+    prefixes: Vec<(String, T)>,
+}
+
+impl<T> Default for InstanceRouter<T> {
+    fn default() -> Self {
+        Self {
+            exact: HashMap::new(),
+            prefixes: Vec::new(),
+        }
+    }
+}
+
+impl<T> InstanceRouter<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the instance configured under `name`.
+    pub fn insert(&mut self, name: &str, value: T) -> Result<(), Error> {
+        let Some(prefix) = name.strip_suffix('*') else {
+            error_if!(
+                name.contains('*'),
+                "'*' must be the last character of instance name '{name}'"
+            );
+            self.exact.insert(name.to_string(), value);
+            return Ok(());
+        };
+        error_if!(
+            prefix.contains('*'),
+            "'*' must be the last character of instance name '{name}'"
+        );
+        if let Some((_, old_value)) = self.prefixes.iter_mut().find(|(other, _)| other == prefix) {
+            *old_value = value;
+            return Ok(());
+        }
+        let index = self
+            .prefixes
+            .partition_point(|(other, _)| other.len() > prefix.len());
+        self.prefixes.insert(index, (prefix.to_string(), value));
+        Ok(())
+    }
+
+    /// Returns the instance serving `instance_name`, if any.
+    pub fn get(&self, instance_name: &str) -> Option<&T> {
+        self.exact.get(instance_name).or_else(|| {
+            self.prefixes
+                .iter()
+                .find(|(prefix, _)| instance_name.starts_with(prefix.as_str()))
+                .map(|(_, value)| value)
+        })
+    }
+}
@@ -17,6 +17,14 @@ pub mod bep_server;
 pub mod bytestream_server;
 pub mod capabilities_server;
 pub mod cas_server;
+pub mod digest_subscription_server;
 pub mod execution_server;
+pub mod grpc_health_server;
 pub mod health_server;
+pub mod inflight_uploads;
+pub mod instance_metrics;
+pub mod instance_router;
+pub mod operation_admin_server;
+pub mod reflection_server;
+pub mod worker_admin_server;
 pub mod worker_api_server;
@@ -0,0 +1,320 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use futures::StreamExt;
+use nativelink_config::cas_server::{AuthorizationAction, OperationAdminConfig};
+use nativelink_error::{make_err, make_input_err, Code, Error, ResultExt};
+use nativelink_proto::build::bazel::remote::execution::v2::execution_stage;
+use nativelink_proto::com::github::trace_machina::nativelink::remote_execution::operation_admin_server::{
+    OperationAdmin, OperationAdminServer as Server,
+};
+use nativelink_proto::com::github::trace_machina::nativelink::remote_execution::{
+    CancelOperationRequest, CancelOperationResponse, GetOperationRequest, ListOperationsRequest,
+    ListOperationsResponse, OperationInfo, RequeueOperationRequest, RequeueOperationResponse,
+    StageChange,
+};
+use nativelink_util::action_messages::{OperationId, WorkerId};
+use nativelink_util::authorization::authorize;
+use nativelink_util::operation_state_manager::{
+    ActionStateResult, ClientStateManager, OperationFilter, OperationStageFlags,
+};
+use tonic::{Request, Response, Status};
+use tracing::{instrument, Level};
+
+/// Default value for `OperationAdminConfig::max_listed_operations`.
+/// If this changes, remember to change the documentation in the config.
+const DEFAULT_MAX_LISTED_OPERATIONS: usize = 1000;
+
+/// Lets operators look at the operations of the schedulers, and cancel or
+/// requeue the ones that are stuck.
+pub struct OperationAdminServer {
+    schedulers: HashMap<String, Arc<dyn ClientStateManager>>,
+    max_listed_operations: usize,
+}
+
+impl OperationAdminServer {
+    pub fn new(
+        config: &OperationAdminConfig,
+        schedulers: &HashMap<String, Arc<dyn ClientStateManager>>,
+    ) -> Self {
+        let max_listed_operations = if config.max_listed_operations == 0 {
+            DEFAULT_MAX_LISTED_OPERATIONS
+        } else {
+            config.max_listed_operations
+        };
+        Self {
+            schedulers: schedulers.clone(),
+            max_listed_operations,
+        }
+    }
+
+    pub fn into_service(self) -> Server<OperationAdminServer> {
+        Server::new(self)
+    }
+
+    fn get_scheduler(&self, instance_name: &str) -> Result<&Arc<dyn ClientStateManager>, Error> {
+        self.schedulers
+            .get(instance_name)
+            .err_tip(|| format!("'instance_name' not configured for '{instance_name}'"))
+    }
+
+    async fn inner_list_operations(
+        &self,
+        request: ListOperationsRequest,
+    ) -> Result<Response<ListOperationsResponse>, Error> {
+        authorize(AuthorizationAction::read, &request.instance_name)?;
+        let scheduler = self.get_scheduler(&request.instance_name)?;
+        let worker_id = if request.worker_id.is_empty() {
+            None
+        } else {
+            Some(WorkerId::try_from(request.worker_id)?)
+        };
+        let queued_before = request.min_age.map(|min_age| {
+            SystemTime::now()
+                - Duration::new(min_age.seconds.max(0) as u64, min_age.nanos.max(0) as u32)
+        });
+        let filter = OperationFilter {
+            stages: to_stage_flags(&request.stages)?,
+            worker_id,
+            ..Default::default()
+        };
+        let mut stream = scheduler
+            .filter_operations(filter)
+            .await
+            .err_tip(|| "In OperationAdminServer::list_operations")?;
+        let mut operations = Vec::new();
+        while let Some(action_state_result) = stream.next().await {
+            let operation_info = to_operation_info(action_state_result.as_ref()).await?;
+            if let Some(queued_before) = queued_before {
+                let is_old_enough = operation_info
+                    .queued_timestamp
+                    .and_then(|timestamp| SystemTime::try_from(timestamp).ok())
+                    .is_some_and(|queued_timestamp| queued_timestamp <= queued_before);
+                if !is_old_enough {
+                    continue;
+                }
+            }
+            operations.push(operation_info);
+        }
+        operations.sort_by_key(|operation_info| {
+            operation_info
+                .queued_timestamp
+                .map(|timestamp| (timestamp.seconds, timestamp.nanos))
+        });
+        let truncated = operations.len() > self.max_listed_operations;
+        operations.truncate(self.max_listed_operations);
+        Ok(Response::new(ListOperationsResponse {
+            operations,
+            truncated,
+        }))
+    }
+
+    async fn inner_get_operation(
+        &self,
+        request: GetOperationRequest,
+    ) -> Result<Response<OperationInfo>, Error> {
+        authorize(AuthorizationAction::read, &request.instance_name)?;
+        let operation_id = OperationId::from(request.operation_id);
+        let filter = OperationFilter {
+            stages: OperationStageFlags::Any,
+            operation_id: Some(operation_id.clone()),
+            ..Default::default()
+        };
+        let action_state_result = self
+            .get_scheduler(&request.instance_name)?
+            .filter_operations(filter)
+            .await
+            .err_tip(|| "In OperationAdminServer::get_operation")?
+            .next()
+            .await
+            .ok_or_else(|| make_err!(Code::NotFound, "Operation {operation_id} does not exist"))?;
+        Ok(Response::new(
+            to_operation_info(action_state_result.as_ref()).await?,
+        ))
+    }
+
+    async fn inner_cancel_operation(
+        &self,
+        request: CancelOperationRequest,
+    ) -> Result<Response<CancelOperationResponse>, Error> {
+        authorize(AuthorizationAction::admin, &request.instance_name)?;
+        self.get_scheduler(&request.instance_name)?
+            .cancel_operation(&OperationId::from(request.operation_id))
+            .await?;
+        Ok(Response::new(CancelOperationResponse {}))
+    }
+
+    async fn inner_requeue_operation(
+        &self,
+        request: RequeueOperationRequest,
+    ) -> Result<Response<RequeueOperationResponse>, Error> {
+        authorize(AuthorizationAction::admin, &request.instance_name)?;
+        self.get_scheduler(&request.instance_name)?
+            .requeue_operation(&OperationId::from(request.operation_id))
+            .await?;
+        Ok(Response::new(RequeueOperationResponse {}))
+    }
+}
+
+/// Converts the stages of a `ListOperationsRequest` to a filter. No stages
+/// means all the unfinished ones.
+fn to_stage_flags(stages: &[i32]) -> Result<OperationStageFlags, Error> {
+    if stages.is_empty() {
+        return Ok(OperationStageFlags::CacheCheck
+            | OperationStageFlags::Queued
+            | OperationStageFlags::Executing);
+    }
+    stages
+        .iter()
+        .try_fold(OperationStageFlags::empty(), |stage_flags, stage| {
+            let stage_flag = match execution_stage::Value::try_from(*stage) {
+                Ok(execution_stage::Value::CacheCheck) => OperationStageFlags::CacheCheck,
+                Ok(execution_stage::Value::Queued) => OperationStageFlags::Queued,
+                Ok(execution_stage::Value::Executing) => OperationStageFlags::Executing,
+                Ok(execution_stage::Value::Completed) => OperationStageFlags::Completed,
+                Ok(execution_stage::Value::Unknown) | Err(_) => {
+                    return Err(make_input_err!("Invalid stage {stage} to list operations"));
+                }
+            };
+            Ok(stage_flags | stage_flag)
+        })
+}
+
+async fn to_operation_info(
+    action_state_result: &dyn ActionStateResult,
+) -> Result<OperationInfo, Error> {
+    let action_state = action_state_result
+        .as_state()
+        .await
+        .err_tip(|| "In OperationAdminServer::to_operation_info")?;
+    let action_info = action_state_result
+        .as_action_info()
+        .await
+        .err_tip(|| "In OperationAdminServer::to_operation_info")?;
+    let mut operation_info = OperationInfo {
+        operation_id: action_state.client_operation_id.to_string(),
+        stage: execution_stage::Value::from(&action_state.stage).into(),
+        action_digest: Some(action_state.action_digest.into()),
+        priority: action_info.priority,
+        queued_timestamp: Some(action_info.insert_timestamp.into()),
+        platform_properties: action_info.platform_properties.clone(),
+        ..Default::default()
+    };
+    let maybe_operation_details = action_state_result
+        .operation_details()
+        .await
+        .err_tip(|| "In OperationAdminServer::to_operation_info")?;
+    if let Some(operation_details) = maybe_operation_details {
+        operation_info.operation_id = operation_details.operation_id.to_string();
+        operation_info.worker_id = operation_details
+            .worker_id
+            .map_or_else(String::default, |worker_id| worker_id.to_string());
+        operation_info.attempts = operation_details.attempts as u64;
+        operation_info.last_worker_update_timestamp =
+            Some(operation_details.last_worker_update.into());
+        operation_info.last_client_keepalive_timestamp =
+            Some(operation_details.last_client_keepalive.into());
+        operation_info.stage_history = operation_details
+            .stage_history
+            .into_iter()
+            .map(|stage_change| StageChange {
+                stage: execution_stage::Value::from_str_name(&stage_change.stage)
+                    .unwrap_or(execution_stage::Value::Unknown)
+                    .into(),
+                worker_id: stage_change
+                    .worker_id
+                    .map_or_else(String::default, |worker_id| worker_id.to_string()),
+                timestamp: Some(stage_change.timestamp.into()),
+            })
+            .collect();
+    }
+    Ok(operation_info)
+}
+
+#[tonic::async_trait]
+impl OperationAdmin for OperationAdminServer {
+    #[allow(clippy::blocks_in_conditions)]
+    #[instrument(
+        err,
+        level = Level::ERROR,
+        skip_all,
+        fields(request = ?grpc_request.get_ref())
+    )]
+    async fn list_operations(
+        &self,
+        grpc_request: Request<ListOperationsRequest>,
+    ) -> Result<Response<ListOperationsResponse>, Status> {
+        self.inner_list_operations(grpc_request.into_inner())
+            .await
+            .err_tip(|| "Failed on list_operations() command")
+            .map_err(Into::into)
+    }
+
+    #[allow(clippy::blocks_in_conditions)]
+    #[instrument(
+        err,
+        level = Level::ERROR,
+        skip_all,
+        fields(request = ?grpc_request.get_ref())
+    )]
+    async fn get_operation(
+        &self,
+        grpc_request: Request<GetOperationRequest>,
+    ) -> Result<Response<OperationInfo>, Status> {
+        self.inner_get_operation(grpc_request.into_inner())
+            .await
+            .err_tip(|| "Failed on get_operation() command")
+            .map_err(Into::into)
+    }
+
+    #[allow(clippy::blocks_in_conditions)]
+    #[instrument(
+        err,
+        ret(level = Level::INFO),
+        level = Level::ERROR,
+        skip_all,
+        fields(request = ?grpc_request.get_ref())
+    )]
+    async fn cancel_operation(
+        &self,
+        grpc_request: Request<CancelOperationRequest>,
+    ) -> Result<Response<CancelOperationResponse>, Status> {
+        self.inner_cancel_operation(grpc_request.into_inner())
+            .await
+            .err_tip(|| "Failed on cancel_operation() command")
+            .map_err(Into::into)
+    }
+
+    #[allow(clippy::blocks_in_conditions)]
+    #[instrument(
+        err,
+        ret(level = Level::INFO),
+        level = Level::ERROR,
+        skip_all,
+        fields(request = ?grpc_request.get_ref())
+    )]
+    async fn requeue_operation(
+        &self,
+        grpc_request: Request<RequeueOperationRequest>,
+    ) -> Result<Response<RequeueOperationResponse>, Status> {
+        self.inner_requeue_operation(grpc_request.into_inner())
+            .await
+            .err_tip(|| "Failed on requeue_operation() command")
+            .map_err(Into::into)
+    }
+}
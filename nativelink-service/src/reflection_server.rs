@@ -0,0 +1,37 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use nativelink_config::cas_server::ReflectionConfig;
+use nativelink_error::{make_err, Code, Error};
+use tonic_reflection::server::v1::{ServerReflection, ServerReflectionServer};
+use tonic_reflection::server::Builder;
+
+/// Serves `grpc.reflection.v1.ServerReflection` with the descriptors of all
+/// the protos of nativelink and of `grpc.health.v1.Health`, so tools like
+/// grpcurl can discover the API.
+pub struct ReflectionServer {}
+
+impl ReflectionServer {
+    pub const fn new(_config: &ReflectionConfig) -> Self {
+        Self {}
+    }
+
+    pub fn into_service(self) -> Result<ServerReflectionServer<impl ServerReflection>, Error> {
+        Builder::configure()
+            .register_encoded_file_descriptor_set(nativelink_proto::FILE_DESCRIPTOR_SET)
+            .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+            .build_v1()
+            .map_err(|e| make_err!(Code::Internal, "Could not build reflection service: {e}"))
+    }
+}
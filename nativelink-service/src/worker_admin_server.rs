@@ -0,0 +1,136 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use nativelink_config::cas_server::{AuthorizationAction, WorkerAdminConfig};
+use nativelink_error::{Error, ResultExt};
+use nativelink_proto::com::github::trace_machina::nativelink::remote_execution::worker_admin_server::{
+    WorkerAdmin, WorkerAdminServer as Server,
+};
+use nativelink_proto::com::github::trace_machina::nativelink::remote_execution::{
+    CordonWorkerRequest, CordonWorkerResponse, DrainWorkerRequest, DrainWorkerResponse,
+};
+use nativelink_scheduler::worker_scheduler::WorkerScheduler;
+use nativelink_util::action_messages::WorkerId;
+use nativelink_util::authorization::authorize;
+use tonic::{Request, Response, Status};
+use tracing::{instrument, Level};
+
+/// Default value for `WorkerAdminConfig::default_drain_timeout_s`.
+/// If this changes, remember to change the documentation in the config.
+const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Lets operators cordon workers, so no new actions are scheduled on them,
+/// and drain workers, which also removes them from the scheduler once
+/// their running actions finished.
+pub struct WorkerAdminServer {
+    schedulers: HashMap<String, Arc<dyn WorkerScheduler>>,
+    default_drain_timeout: Duration,
+}
+
+impl WorkerAdminServer {
+    pub fn new(
+        config: &WorkerAdminConfig,
+        schedulers: &HashMap<String, Arc<dyn WorkerScheduler>>,
+    ) -> Self {
+        let default_drain_timeout = if config.default_drain_timeout_s == 0 {
+            DEFAULT_DRAIN_TIMEOUT
+        } else {
+            Duration::from_secs(config.default_drain_timeout_s)
+        };
+        Self {
+            schedulers: schedulers.clone(),
+            default_drain_timeout,
+        }
+    }
+
+    pub fn into_service(self) -> Server<WorkerAdminServer> {
+        Server::new(self)
+    }
+
+    fn get_scheduler(&self, instance_name: &str) -> Result<&Arc<dyn WorkerScheduler>, Error> {
+        self.schedulers
+            .get(instance_name)
+            .err_tip(|| format!("'instance_name' not configured for '{instance_name}'"))
+    }
+
+    async fn inner_cordon_worker(
+        &self,
+        request: CordonWorkerRequest,
+    ) -> Result<Response<CordonWorkerResponse>, Error> {
+        authorize(AuthorizationAction::admin, &request.instance_name)?;
+        let worker_id = WorkerId::try_from(request.worker_id)?;
+        self.get_scheduler(&request.instance_name)?
+            .set_drain_worker(&worker_id, request.cordoned)
+            .await?;
+        Ok(Response::new(CordonWorkerResponse {}))
+    }
+
+    async fn inner_drain_worker(
+        &self,
+        request: DrainWorkerRequest,
+    ) -> Result<Response<DrainWorkerResponse>, Error> {
+        authorize(AuthorizationAction::admin, &request.instance_name)?;
+        let worker_id = WorkerId::try_from(request.worker_id)?;
+        let timeout = request.timeout.map_or(self.default_drain_timeout, |v| {
+            Duration::new(v.seconds.max(0) as u64, v.nanos.max(0) as u32)
+        });
+        self.get_scheduler(&request.instance_name)?
+            .drain_worker(&worker_id, timeout)
+            .await?;
+        Ok(Response::new(DrainWorkerResponse {}))
+    }
+}
+
+#[tonic::async_trait]
+impl WorkerAdmin for WorkerAdminServer {
+    #[allow(clippy::blocks_in_conditions)]
+    #[instrument(
+        err,
+        ret(level = Level::INFO),
+        level = Level::ERROR,
+        skip_all,
+        fields(request = ?grpc_request.get_ref())
+    )]
+    async fn cordon_worker(
+        &self,
+        grpc_request: Request<CordonWorkerRequest>,
+    ) -> Result<Response<CordonWorkerResponse>, Status> {
+        self.inner_cordon_worker(grpc_request.into_inner())
+            .await
+            .err_tip(|| "Failed on cordon_worker() command")
+            .map_err(Into::into)
+    }
+
+    #[allow(clippy::blocks_in_conditions)]
+    #[instrument(
+        err,
+        ret(level = Level::INFO),
+        level = Level::ERROR,
+        skip_all,
+        fields(request = ?grpc_request.get_ref())
+    )]
+    async fn drain_worker(
+        &self,
+        grpc_request: Request<DrainWorkerRequest>,
+    ) -> Result<Response<DrainWorkerResponse>, Status> {
+        self.inner_drain_worker(grpc_request.into_inner())
+            .await
+            .err_tip(|| "Failed on drain_worker() command")
+            .map_err(Into::into)
+    }
+}
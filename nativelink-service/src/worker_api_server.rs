@@ -18,10 +18,12 @@ use std::pin::Pin;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use bytes::Bytes;
 use futures::stream::unfold;
 use futures::Stream;
 use nativelink_config::cas_server::WorkerApiConfig;
-use nativelink_error::{make_err, Code, Error, ResultExt};
+use nativelink_error::{make_err, make_input_err, Code, Error, ResultExt};
+use nativelink_proto::build::bazel::remote::execution::v2::ExecuteResponse;
 use nativelink_proto::com::github::trace_machina::nativelink::remote_execution::worker_api_server::{
     WorkerApi, WorkerApiServer as Server,
 };
@@ -30,14 +32,20 @@ use nativelink_proto::com::github::trace_machina::nativelink::remote_execution::
 };
 use nativelink_scheduler::worker::Worker;
 use nativelink_scheduler::worker_scheduler::WorkerScheduler;
+use nativelink_store::cas_utils::is_zero_digest;
+use nativelink_store::store_manager::StoreManager;
 use nativelink_util::background_spawn;
-use nativelink_util::action_messages::{OperationId, WorkerId};
+use nativelink_util::action_messages::{ActionUniqueQualifier, OperationId, WorkerId};
+use nativelink_util::common::DigestInfo;
+use nativelink_util::digest_hasher::make_ctx_for_hash_func;
 use nativelink_util::operation_state_manager::UpdateOperationType;
 use nativelink_util::platform_properties::PlatformProperties;
+use nativelink_util::store_trait::{Store, StoreKey, StoreLike};
+use prost::Message;
 use tokio::sync::mpsc;
 use tokio::time::interval;
 use tonic::{Request, Response, Status};
-use tracing::{event, instrument, Level};
+use tracing::{error_span, event, instrument, Level};
 use uuid::Uuid;
 
 pub type ConnectWorkerStream =
@@ -45,8 +53,15 @@ pub type ConnectWorkerStream =
 
 pub type NowFn = Box<dyn Fn() -> Result<Duration, Error> + Send + Sync>;
 
+/// Stores used to validate and cache the results reported by workers.
+struct ActionResultUploadStores {
+    ac_store: Store,
+    cas_store: Store,
+}
+
 pub struct WorkerApiServer {
     scheduler: Arc<dyn WorkerScheduler>,
+    action_result_upload_stores: Option<ActionResultUploadStores>,
     now_fn: NowFn,
 }
 
@@ -54,6 +69,7 @@ impl WorkerApiServer {
     pub fn new(
         config: &WorkerApiConfig,
         schedulers: &HashMap<String, Arc<dyn WorkerScheduler>>,
+        store_manager: &StoreManager,
     ) -> Result<Self, Error> {
         for scheduler in schedulers.values() {
             // This will protect us from holding a reference to the scheduler forever in the
@@ -74,6 +90,19 @@ impl WorkerApiServer {
                             {
                                 event!(Level::ERROR, ?err, "Failed to remove_timedout_workers",);
                             }
+                            if let Err(err) =
+                                scheduler.kill_timedout_actions(timestamp.as_secs()).await
+                            {
+                                event!(Level::ERROR, ?err, "Failed to kill_timedout_actions",);
+                            }
+                            if let Err(err) =
+                                scheduler.hedge_slow_actions(timestamp.as_secs()).await
+                            {
+                                event!(Level::ERROR, ?err, "Failed to hedge_slow_actions",);
+                            }
+                            if let Err(err) = scheduler.cancel_abandoned_actions().await {
+                                event!(Level::ERROR, ?err, "Failed to cancel_abandoned_actions",);
+                            }
                         }
                         // If we fail to upgrade, our service is probably destroyed, so return.
                         None => return,
@@ -85,6 +114,7 @@ impl WorkerApiServer {
         Self::new_with_now_fn(
             config,
             schedulers,
+            store_manager,
             Box::new(move || {
                 SystemTime::now()
                     .duration_since(UNIX_EPOCH)
@@ -98,6 +128,7 @@ impl WorkerApiServer {
     pub fn new_with_now_fn(
         config: &WorkerApiConfig,
         schedulers: &HashMap<String, Arc<dyn WorkerScheduler>>,
+        store_manager: &StoreManager,
         now_fn: NowFn,
     ) -> Result<Self, Error> {
         let scheduler = schedulers
@@ -109,7 +140,28 @@ impl WorkerApiServer {
                 )
             })?
             .clone();
-        Ok(Self { scheduler, now_fn })
+        let action_result_upload_stores = config
+            .action_result_upload
+            .as_ref()
+            .map(|upload_config| {
+                let get_store = |store_name: &str| {
+                    store_manager.get_store(store_name).ok_or_else(|| {
+                        make_input_err!(
+                            "'{store_name}' does not exist in worker_api action_result_upload"
+                        )
+                    })
+                };
+                Ok::<_, Error>(ActionResultUploadStores {
+                    ac_store: get_store(&upload_config.ac_store)?,
+                    cas_store: get_store(&upload_config.cas_store)?,
+                })
+            })
+            .transpose()?;
+        Ok(Self {
+            scheduler,
+            action_result_upload_stores,
+            now_fn,
+        })
     }
 
     pub fn into_service(self) -> Server<WorkerApiServer> {
@@ -131,9 +183,15 @@ impl WorkerApiServer {
                     .get_platform_property_manager()
                     .make_prop_value(&property.name, &property.value)
                     .err_tip(|| "Bad Property during connect_worker()")?;
-                platform_properties
-                    .properties
-                    .insert(property.name.clone(), platform_property_value);
+                // A worker may have multiple values for the same property.
+                match platform_properties.properties.get_mut(&property.name) {
+                    Some(value) => value.merge(platform_property_value),
+                    None => {
+                        platform_properties
+                            .properties
+                            .insert(property.name.clone(), platform_property_value);
+                    }
+                }
             }
             platform_properties
         };
@@ -208,6 +266,27 @@ impl WorkerApiServer {
             .err_tip(|| "Expected result to exist in ExecuteResult")?
         {
             execute_result::Result::ExecuteResponse(finished_result) => {
+                // This must happen before the scheduler is told the operation
+                // finished, as the worker stops tracking the operation then.
+                if let Some(stores) = &self.action_result_upload_stores {
+                    if let Err(err) = self
+                        .upload_validated_action_result(
+                            stores,
+                            &worker_id,
+                            &operation_id,
+                            &finished_result,
+                        )
+                        .await
+                    {
+                        event!(
+                            Level::WARN,
+                            ?err,
+                            ?operation_id,
+                            ?worker_id,
+                            "Not caching ActionResult reported by worker"
+                        );
+                    }
+                }
                 let action_stage = finished_result
                     .try_into()
                     .err_tip(|| "Failed to convert ExecuteResponse into an ActionStage")?;
@@ -233,6 +312,85 @@ impl WorkerApiServer {
         }
         Ok(Response::new(()))
     }
+
+    /// Writes the `ActionResult` of a finished operation to the AC if the
+    /// action may be cached, it succeeded and all of its outputs exist in
+    /// the CAS.
+    async fn upload_validated_action_result(
+        &self,
+        stores: &ActionResultUploadStores,
+        worker_id: &WorkerId,
+        operation_id: &OperationId,
+        execute_response: &ExecuteResponse,
+    ) -> Result<(), Error> {
+        let action_info = self
+            .scheduler
+            .get_running_action_info(worker_id, operation_id)
+            .await
+            .err_tip(|| "In WorkerApiServer::upload_validated_action_result")?;
+        let ActionUniqueQualifier::Cachable(unique_key) = &action_info.unique_qualifier else {
+            return Ok(()); // The client asked for the result to not be cached.
+        };
+        let action_result = execute_response
+            .result
+            .as_ref()
+            .err_tip(|| "Expected result to exist in ExecuteResponse")?;
+        let failed = execute_response
+            .status
+            .as_ref()
+            .is_some_and(|status| status.code != Code::Ok as i32);
+        if failed || action_result.exit_code != 0 {
+            return Ok(()); // Only successful results are cached.
+        }
+
+        let output_digests = action_result
+            .output_files
+            .iter()
+            .filter_map(|output_file| output_file.digest.as_ref())
+            .chain(
+                action_result
+                    .output_directories
+                    .iter()
+                    .filter_map(|output_directory| output_directory.tree_digest.as_ref()),
+            )
+            .chain(action_result.stdout_digest.as_ref())
+            .chain(action_result.stderr_digest.as_ref())
+            .map(DigestInfo::try_from)
+            .filter(|digest| {
+                digest
+                    .as_ref()
+                    .map_or(true, |digest| !is_zero_digest(*digest))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .err_tip(|| "Invalid output digest in ActionResult")?;
+        let keys: Vec<StoreKey> = output_digests.iter().map(StoreKey::from).collect();
+        let results = stores
+            .cas_store
+            .has_many(&keys)
+            .await
+            .err_tip(|| "Checking outputs of ActionResult exist in CAS")?;
+        if let Some((missing_digest, _)) = output_digests
+            .iter()
+            .zip(results)
+            .find(|(_, result)| result.is_none())
+        {
+            return Err(make_err!(
+                Code::FailedPrecondition,
+                "ActionResult of operation {operation_id} references {missing_digest} which does not exist in the CAS"
+            ));
+        }
+
+        let action_digest = unique_key.digest;
+        let store_data = Bytes::from(action_result.encode_to_vec());
+        make_ctx_for_hash_func(unique_key.digest_function)
+            .err_tip(|| "In WorkerApiServer::upload_validated_action_result")?
+            .wrap_async(
+                error_span!("worker_api_upload_action_result"),
+                stores.ac_store.update_oneshot(action_digest, store_data),
+            )
+            .await
+            .err_tip(|| "Caching ActionResult")
+    }
 }
 
 #[tonic::async_trait]
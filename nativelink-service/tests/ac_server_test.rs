@@ -17,25 +17,34 @@ use std::sync::Arc;
 
 use bytes::BytesMut;
 use maplit::hashmap;
-use nativelink_config::stores::{MemorySpec, StoreSpec};
+use nativelink_config::cas_server::{AuthorizationAction, AuthorizationPolicy};
+use nativelink_config::stores::{ConfigDigestHashFunction, MemorySpec, StoreSpec};
 use nativelink_error::Error;
 use nativelink_macro::nativelink_test;
 use nativelink_proto::build::bazel::remote::execution::v2::action_cache_server::ActionCache;
 use nativelink_proto::build::bazel::remote::execution::v2::{
-    digest_function, ActionResult, Digest, GetActionResultRequest, UpdateActionResultRequest,
+    digest_function, ActionResult, Digest, GetActionResultRequest, OutputFile,
+    UpdateActionResultRequest,
 };
 use nativelink_service::ac_server::AcServer;
 use nativelink_store::default_store_factory::store_factory;
 use nativelink_store::store_manager::StoreManager;
+use nativelink_util::authorization::{Authorizer, ORIGIN_AUTHORIZER};
 use nativelink_util::common::DigestInfo;
+use nativelink_util::origin_context::{ActiveOriginContext, ORIGIN_IDENTITY, ORIGIN_ROLES};
 use nativelink_util::store_trait::StoreLike;
 use pretty_assertions::assert_eq;
 use prost::Message;
+use prost_types::Timestamp;
 use tonic::{Code, Request, Response, Status};
+use tracing::info_span;
 
 const INSTANCE_NAME: &str = "foo_instance_name";
 const HASH1: &str = "0123456789abcdef000000000000000000000000000000000123456789abcdef";
 const HASH1_SIZE: i64 = 147;
+const HASH2: &str = "9993456789abcdef000000000000000000000000000000000123456789abc999";
+const HASH3: &str = "7773456789abcdef000000000000000000000000000000000123456789abc777";
+const HASH4: &str = "5553456789abcdef000000000000000000000000000000000123456789abc555";
 
 async fn insert_into_store<T: Message>(
     store: Pin<&impl StoreLike>,
@@ -80,6 +89,13 @@ fn make_ac_server(store_manager: &StoreManager) -> Result<AcServer, Error> {
             "foo_instance_name".to_string() => nativelink_config::cas_server::AcStoreConfig{
                 ac_store: "main_ac".to_string(),
                 read_only: false,
+                cas_store: None,
+                max_inline_size: 0,
+                verify_outputs_exist: false,
+                max_action_result_size: 0,
+                action_result_ttl: 0,
+                write_role: None,
+                digest_functions: Vec::new(),
             }
         },
         store_manager,
@@ -219,3 +235,378 @@ async fn one_item_update_test() -> Result<(), Box<dyn std::error::Error>> {
     assert_eq!(decoded_action_result, action_result);
     Ok(())
 }
+
+#[nativelink_test]
+async fn get_action_result_inlines_requested_outputs() -> Result<(), Box<dyn std::error::Error>> {
+    const STDOUT: &str = "out";
+    const STDERR: &str = "error";
+    const CONTENTS: &str = "x";
+
+    let store_manager = make_store_manager().await?;
+    let ac_server = AcServer::new(
+        &hashmap! {
+            INSTANCE_NAME.to_string() => nativelink_config::cas_server::AcStoreConfig{
+                ac_store: "main_ac".to_string(),
+                read_only: false,
+                cas_store: Some("main_cas".to_string()),
+                // Fits stdout and the output file, but not stderr as well.
+                max_inline_size: 5,
+                verify_outputs_exist: false,
+                max_action_result_size: 0,
+                action_result_ttl: 0,
+                write_role: None,
+                digest_functions: Vec::new(),
+            }
+        },
+        &store_manager,
+    )?;
+    let ac_store = store_manager.get_store("main_ac").unwrap();
+    let cas_store = store_manager.get_store("main_cas").unwrap();
+
+    let mut digests = Vec::new();
+    for (hash, data) in [(HASH2, STDOUT), (HASH3, STDERR), (HASH4, CONTENTS)] {
+        let digest = DigestInfo::try_new(hash, data.len())?;
+        cas_store.update_oneshot(digest, data.into()).await?;
+        digests.push(Digest::from(digest));
+    }
+    let output_file = |path: &str| OutputFile {
+        path: path.to_string(),
+        digest: Some(digests[2].clone()),
+        ..Default::default()
+    };
+    let action_result = ActionResult {
+        stdout_digest: Some(digests[0].clone()),
+        stderr_digest: Some(digests[1].clone()),
+        output_files: vec![output_file("a.txt"), output_file("b.txt")],
+        ..Default::default()
+    };
+    insert_into_store(ac_store.as_pin(), HASH1, HASH1_SIZE, &action_result).await?;
+
+    let response = ac_server
+        .get_action_result(Request::new(GetActionResultRequest {
+            instance_name: INSTANCE_NAME.to_string(),
+            action_digest: Some(Digest {
+                hash: HASH1.to_string(),
+                size_bytes: HASH1_SIZE,
+            }),
+            inline_stdout: true,
+            inline_stderr: true,
+            inline_output_files: vec!["b.txt".to_string()],
+            digest_function: digest_function::Value::Sha256.into(),
+        }))
+        .await?
+        .into_inner();
+
+    let mut expected_action_result = action_result;
+    expected_action_result.stdout_raw = STDOUT.into();
+    expected_action_result.output_files[1].contents = CONTENTS.into();
+    assert_eq!(response, expected_action_result);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn update_action_result_rejects_missing_outputs() -> Result<(), Box<dyn std::error::Error>> {
+    const STDOUT: &str = "out";
+
+    let store_manager = make_store_manager().await?;
+    let ac_server = AcServer::new(
+        &hashmap! {
+            INSTANCE_NAME.to_string() => nativelink_config::cas_server::AcStoreConfig{
+                ac_store: "main_ac".to_string(),
+                read_only: false,
+                cas_store: Some("main_cas".to_string()),
+                max_inline_size: 0,
+                verify_outputs_exist: true,
+                max_action_result_size: 0,
+                action_result_ttl: 0,
+                write_role: None,
+                digest_functions: Vec::new(),
+            }
+        },
+        &store_manager,
+    )?;
+    let ac_store = store_manager.get_store("main_ac").unwrap();
+    let cas_store = store_manager.get_store("main_cas").unwrap();
+
+    let stdout_digest = DigestInfo::try_new(HASH2, STDOUT.len())?;
+    let action_result = ActionResult {
+        stdout_digest: Some(stdout_digest.into()),
+        ..Default::default()
+    };
+    let action_digest = Digest {
+        hash: HASH1.to_string(),
+        size_bytes: get_encoded_proto_size(&action_result)? as i64,
+    };
+
+    let err = update_action_result(&ac_server, action_digest.clone(), action_result.clone())
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::FailedPrecondition);
+    assert_eq!(
+        ac_store
+            .has(DigestInfo::try_from(action_digest.clone())?)
+            .await?,
+        None
+    );
+
+    cas_store
+        .update_oneshot(stdout_digest, STDOUT.into())
+        .await?;
+    update_action_result(&ac_server, action_digest, action_result).await?;
+    Ok(())
+}
+
+fn make_limited_ac_server(
+    store_manager: &StoreManager,
+    max_action_result_size: usize,
+    action_result_ttl: usize,
+) -> Result<AcServer, Error> {
+    AcServer::new(
+        &hashmap! {
+            INSTANCE_NAME.to_string() => nativelink_config::cas_server::AcStoreConfig{
+                ac_store: "main_ac".to_string(),
+                read_only: false,
+                cas_store: None,
+                max_inline_size: 0,
+                verify_outputs_exist: false,
+                max_action_result_size,
+                action_result_ttl,
+                write_role: None,
+                digest_functions: Vec::new(),
+            }
+        },
+        store_manager,
+    )
+}
+
+#[nativelink_test]
+async fn update_action_result_rejects_results_over_max_size(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let store_manager = make_store_manager().await?;
+    let action_result = ActionResult {
+        exit_code: 45,
+        ..Default::default()
+    };
+    let size_bytes = get_encoded_proto_size(&action_result)?;
+    let digest = Digest {
+        hash: HASH1.to_string(),
+        size_bytes: size_bytes as i64,
+    };
+
+    let ac_server = make_limited_ac_server(&store_manager, size_bytes - 1, 0)?;
+    let err = update_action_result(&ac_server, digest.clone(), action_result.clone())
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+
+    let ac_server = make_limited_ac_server(&store_manager, size_bytes, 0)?;
+    update_action_result(&ac_server, digest, action_result).await?;
+    Ok(())
+}
+
+#[nativelink_test]
+async fn get_action_result_hides_expired_results() -> Result<(), Box<dyn std::error::Error>> {
+    let store_manager = make_store_manager().await?;
+    let ac_server = make_limited_ac_server(&store_manager, 0, 3600)?;
+    let ac_store = store_manager.get_store("main_ac").unwrap();
+
+    let action_result = ActionResult {
+        exit_code: 45,
+        ..Default::default()
+    };
+    let digest = Digest {
+        hash: HASH1.to_string(),
+        size_bytes: HASH1_SIZE,
+    };
+    update_action_result(&ac_server, digest, action_result.clone()).await?;
+    let response = get_action_result(&ac_server, HASH1, HASH1_SIZE).await?;
+    assert_eq!(response.into_inner(), action_result);
+
+    // Move the stored expiry time to the past.
+    let digest_info = DigestInfo::try_new(HASH1, HASH1_SIZE)?;
+    let mut stored_action_result =
+        ActionResult::decode(ac_store.get_part_unchunked(digest_info, 0, None).await?)?;
+    let expiry = &mut stored_action_result
+        .execution_metadata
+        .as_mut()
+        .unwrap()
+        .auxiliary_metadata[0];
+    expiry.value = Timestamp {
+        seconds: 1,
+        nanos: 0,
+    }
+    .encode_to_vec();
+    insert_into_store(ac_store.as_pin(), HASH1, HASH1_SIZE, &stored_action_result).await?;
+
+    let err = get_action_result(&ac_server, HASH1, HASH1_SIZE)
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn update_action_result_requires_write_role() -> Result<(), Box<dyn std::error::Error>> {
+    let store_manager = make_store_manager().await?;
+    let ac_server = AcServer::new(
+        &hashmap! {
+            INSTANCE_NAME.to_string() => nativelink_config::cas_server::AcStoreConfig{
+                ac_store: "main_ac".to_string(),
+                read_only: false,
+                cas_store: None,
+                max_inline_size: 0,
+                verify_outputs_exist: false,
+                max_action_result_size: 0,
+                action_result_ttl: 0,
+                write_role: Some("ac-writer".to_string()),
+                digest_functions: Vec::new(),
+            }
+        },
+        &store_manager,
+    )?;
+    let action_result = ActionResult {
+        exit_code: 45,
+        ..Default::default()
+    };
+    let digest = Digest {
+        hash: HASH1.to_string(),
+        size_bytes: HASH1_SIZE,
+    };
+    let with_roles = |roles: &[&str]| -> Result<_, Error> {
+        let mut ctx = ActiveOriginContext::fork()?;
+        ctx.set_value(
+            &ORIGIN_ROLES,
+            Arc::new(roles.iter().map(ToString::to_string).collect()),
+        );
+        Ok(Arc::new(ctx))
+    };
+
+    let err = update_action_result(&ac_server, digest.clone(), action_result.clone())
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::PermissionDenied);
+    let err = with_roles(&["reader"])?
+        .wrap_async(
+            info_span!("update"),
+            update_action_result(&ac_server, digest.clone(), action_result.clone()),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::PermissionDenied);
+
+    with_roles(&["reader", "ac-writer"])?
+        .wrap_async(
+            info_span!("update"),
+            update_action_result(&ac_server, digest, action_result.clone()),
+        )
+        .await?;
+    // Reads do not need any role.
+    let response = get_action_result(&ac_server, HASH1, HASH1_SIZE).await?;
+    assert_eq!(response.into_inner(), action_result);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn action_cache_follows_authorization_policies() -> Result<(), Box<dyn std::error::Error>> {
+    let store_manager = make_store_manager().await?;
+    let ac_server = make_ac_server(&store_manager)?;
+    let authorizer = Arc::new(Authorizer::new(&[
+        AuthorizationPolicy {
+            name: "ci-writes".to_string(),
+            identities: vec!["ci".to_string()],
+            actions: vec![AuthorizationAction::ac_write],
+            ..Default::default()
+        },
+        AuthorizationPolicy {
+            name: "everyone-reads".to_string(),
+            identities: vec!["*".to_string()],
+            actions: vec![AuthorizationAction::read],
+            ..Default::default()
+        },
+    ])?);
+    let as_identity = |identity: &str| -> Result<_, Error> {
+        let mut ctx = ActiveOriginContext::fork()?;
+        ctx.set_value(&ORIGIN_AUTHORIZER, authorizer.clone());
+        ctx.set_value(&ORIGIN_IDENTITY, Arc::new(identity.to_string()));
+        Ok(Arc::new(ctx))
+    };
+    let action_result = ActionResult {
+        exit_code: 45,
+        ..Default::default()
+    };
+    let digest = Digest {
+        hash: HASH1.to_string(),
+        size_bytes: HASH1_SIZE,
+    };
+
+    let err = as_identity("alice")?
+        .wrap_async(
+            info_span!("update"),
+            update_action_result(&ac_server, digest.clone(), action_result.clone()),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::PermissionDenied);
+    assert!(
+        err.message().contains("No policy allows 'alice'"),
+        "{err:?}"
+    );
+
+    as_identity("ci")?
+        .wrap_async(
+            info_span!("update"),
+            update_action_result(&ac_server, digest, action_result.clone()),
+        )
+        .await?;
+    let response = as_identity("alice")?
+        .wrap_async(
+            info_span!("get"),
+            get_action_result(&ac_server, HASH1, HASH1_SIZE),
+        )
+        .await?;
+    assert_eq!(response.into_inner(), action_result);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn rejects_digest_functions_not_allowed() -> Result<(), Box<dyn std::error::Error>> {
+    let store_manager = make_store_manager().await?;
+    let ac_server = AcServer::new(
+        &hashmap! {
+            INSTANCE_NAME.to_string() => nativelink_config::cas_server::AcStoreConfig{
+                ac_store: "main_ac".to_string(),
+                read_only: false,
+                cas_store: None,
+                max_inline_size: 0,
+                verify_outputs_exist: false,
+                max_action_result_size: 0,
+                action_result_ttl: 0,
+                write_role: None,
+                digest_functions: vec![ConfigDigestHashFunction::sha256],
+            }
+        },
+        &store_manager,
+    )?;
+
+    let err = ac_server
+        .get_action_result(Request::new(GetActionResultRequest {
+            instance_name: INSTANCE_NAME.to_string(),
+            action_digest: Some(Digest {
+                hash: HASH1.to_string(),
+                size_bytes: HASH1_SIZE,
+            }),
+            inline_stdout: false,
+            inline_stderr: false,
+            inline_output_files: vec![],
+            digest_function: digest_function::Value::Blake3.into(),
+        }))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+    assert!(
+        err.message()
+            .contains("Digest function BLAKE3 is not allowed"),
+        "{err:?}"
+    );
+    Ok(())
+}
@@ -17,11 +17,13 @@ use std::sync::Arc;
 
 use futures::StreamExt;
 use hyper::body::Frame;
-use nativelink_config::cas_server::BepConfig;
+use nativelink_config::cas_server::{AuthorizationAction, AuthorizationPolicy, BepConfig};
 use nativelink_config::stores::{MemorySpec, StoreSpec};
 use nativelink_error::{Error, ResultExt};
 use nativelink_macro::nativelink_test;
-use nativelink_proto::com::github::trace_machina::nativelink::events::{bep_event, BepEvent};
+use nativelink_proto::com::github::trace_machina::nativelink::events::{
+    bep_event, BepEvent, BepInvocation,
+};
 use nativelink_proto::google::devtools::build::v1::build_event::console_output::Output;
 use nativelink_proto::google::devtools::build::v1::build_event::{
     BuildEnqueued, BuildFinished, ConsoleOutput, Event, InvocationAttemptFinished,
@@ -37,30 +39,36 @@ use nativelink_proto::google::devtools::build::v1::{
 use nativelink_service::bep_server::BepServer;
 use nativelink_store::default_store_factory::store_factory;
 use nativelink_store::store_manager::StoreManager;
+use nativelink_util::authorization::{Authorizer, ORIGIN_AUTHORIZER};
 use nativelink_util::buf_channel::make_buf_channel_pair;
 use nativelink_util::channel_body_for_tests::ChannelBody;
-use nativelink_util::common::encode_stream_proto;
+use nativelink_util::common::{encode_stream_proto, DigestInfo};
+use nativelink_util::origin_context::{ActiveOriginContext, ORIGIN_IDENTITY};
 use nativelink_util::store_trait::{Store, StoreKey, StoreLike};
 use pretty_assertions::assert_eq;
 use prost::Message;
 use prost_types::Timestamp;
 use tonic::codec::{Codec, ProstCodec};
-use tonic::{Request, Streaming};
+use tonic::{Code, Request, Streaming};
+use tracing::info_span;
 
 const BEP_STORE_NAME: &str = "main_bep";
+const CAS_STORE_NAME: &str = "main_cas";
 
 /// Utility function to construct a [`StoreManager`]
 async fn make_store_manager() -> Result<Arc<StoreManager>, Error> {
     let store_manager = Arc::new(StoreManager::new());
-    store_manager.add_store(
-        BEP_STORE_NAME,
-        store_factory(
-            &StoreSpec::memory(MemorySpec::default()),
-            &store_manager,
-            None,
-        )
-        .await?,
-    );
+    for store_name in [BEP_STORE_NAME, CAS_STORE_NAME] {
+        store_manager.add_store(
+            store_name,
+            store_factory(
+                &StoreSpec::memory(MemorySpec::default()),
+                &store_manager,
+                None,
+            )
+            .await?,
+        );
+    }
     Ok(store_manager)
 }
 
@@ -69,6 +77,7 @@ fn make_bep_server(store_manager: &StoreManager) -> Result<BepServer, Error> {
     BepServer::new(
         &BepConfig {
             store: BEP_STORE_NAME.to_string(),
+            cas_store: None,
         },
         store_manager,
     )
@@ -354,3 +363,166 @@ async fn publish_build_tool_event_stream_test() -> Result<(), Box<dyn std::error
         Ok(())
     }
 }
+
+/// Asserts that build tool events are stored in the CAS and that the
+/// invocation is indexed once its stream ends.
+#[nativelink_test]
+async fn publish_build_tool_event_stream_to_cas_test() -> Result<(), Box<dyn std::error::Error>> {
+    let store_manager = make_store_manager().await?;
+    let bep_server = BepServer::new(
+        &BepConfig {
+            store: BEP_STORE_NAME.to_string(),
+            cas_store: Some(CAS_STORE_NAME.to_string()),
+        },
+        &store_manager,
+    )?;
+    let bep_store = get_bep_store(&store_manager)?;
+    let cas_store = store_manager
+        .get_store(CAS_STORE_NAME)
+        .err_tip(|| format!("While retrieving cas_store {CAS_STORE_NAME}"))?;
+
+    let (tx, body) = ChannelBody::new();
+    let mut codec = ProstCodec::<PublishBuildToolEventStreamRequest, _>::default();
+    let stream = Streaming::new_request(codec.decoder(), body, None, None);
+    let mut response_stream = bep_server
+        .publish_build_tool_event_stream(Request::new(stream))
+        .await
+        .err_tip(|| "While invoking publish_build_tool_event_stream")?
+        .into_inner();
+
+    let stream_id = StreamId {
+        build_id: "some-build-id".to_string(),
+        invocation_id: "some-invocation-id".to_string(),
+        component: BuildComponent::Controller as i32,
+    };
+    let requests: Vec<_> = (1..=2)
+        .map(|sequence_number| PublishBuildToolEventStreamRequest {
+            ordered_build_event: Some(OrderedBuildEvent {
+                stream_id: Some(stream_id.clone()),
+                sequence_number,
+                event: Some(BuildEvent {
+                    event_time: None,
+                    event: Some(Event::BuildEnqueued(BuildEnqueued { details: None })),
+                }),
+            }),
+            notification_keywords: vec![],
+            project_id: "some-project-id".to_string(),
+            check_preceding_lifecycle_events_present: false,
+        })
+        .collect();
+    for request in &requests {
+        tx.send(Frame::data(encode_stream_proto(request)?)).await?;
+        response_stream
+            .next()
+            .await
+            .err_tip(|| "Response stream closed unexpectedly")??;
+    }
+    drop(tx);
+    assert!(response_stream.next().await.is_none());
+
+    let invocation = BepInvocation::decode(
+        bep_store
+            .get_part_unchunked(
+                StoreKey::Str(Cow::Borrowed("BepInvocation:some-invocation-id")),
+                0,
+                None,
+            )
+            .await?,
+    )?;
+    assert_eq!(invocation.build_id, "some-build-id");
+    assert_eq!(invocation.event_count, 2);
+    assert_eq!(invocation.last_sequence_number, 2);
+    assert_eq!(invocation.event_digests.len(), 2);
+
+    for (request, digest) in requests.into_iter().zip(invocation.event_digests) {
+        let digest = DigestInfo::try_from(digest)?;
+        let bep_event = BepEvent::decode(cas_store.get_part_unchunked(digest, 0, None).await?)?;
+        assert_eq!(
+            bep_event.event,
+            Some(bep_event::Event::BuildToolEvent(request))
+        );
+    }
+    Ok(())
+}
+
+#[nativelink_test]
+async fn bep_follows_authorization_policies() -> Result<(), Box<dyn std::error::Error>> {
+    let store_manager = make_store_manager().await?;
+    let bep_server = make_bep_server(&store_manager)?;
+    let authorizer = Arc::new(Authorizer::new(&[AuthorizationPolicy {
+        name: "publish-events".to_string(),
+        identities: vec!["*".to_string()],
+        instance_names: vec!["allowed-project".to_string()],
+        actions: vec![AuthorizationAction::cas_write],
+        ..Default::default()
+    }])?);
+    let mut ctx = ActiveOriginContext::fork()?;
+    ctx.set_value(&ORIGIN_AUTHORIZER, authorizer);
+    ctx.set_value(&ORIGIN_IDENTITY, Arc::new("alice".to_string()));
+    let ctx = Arc::new(ctx);
+
+    let stream_id = StreamId {
+        build_id: "some-build-id".to_string(),
+        invocation_id: "some-invocation-id".to_string(),
+        component: BuildComponent::Controller as i32,
+    };
+    let build_event = OrderedBuildEvent {
+        stream_id: Some(stream_id),
+        sequence_number: 1,
+        event: Some(BuildEvent {
+            event_time: None,
+            event: Some(Event::BuildEnqueued(BuildEnqueued { details: None })),
+        }),
+    };
+
+    let err = ctx
+        .clone()
+        .wrap_async(
+            info_span!("publish_lifecycle_event"),
+            bep_server.publish_lifecycle_event(Request::new(PublishLifecycleEventRequest {
+                build_event: Some(build_event.clone()),
+                project_id: "other-project".to_string(),
+                ..Default::default()
+            })),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::PermissionDenied);
+
+    let (tx, body) = ChannelBody::new();
+    let mut codec = ProstCodec::<PublishBuildToolEventStreamRequest, _>::default();
+    let stream = Streaming::new_request(codec.decoder(), body, None, None);
+    let mut response_stream = ctx
+        .wrap_async(
+            info_span!("publish_build_tool_event_stream"),
+            bep_server.publish_build_tool_event_stream(Request::new(stream)),
+        )
+        .await?
+        .into_inner();
+
+    // The response stream is polled outside of the request context, but
+    // the policies of the request still apply.
+    let request = PublishBuildToolEventStreamRequest {
+        ordered_build_event: Some(build_event),
+        project_id: "allowed-project".to_string(),
+        ..Default::default()
+    };
+    tx.send(Frame::data(encode_stream_proto(&request)?)).await?;
+    response_stream
+        .next()
+        .await
+        .err_tip(|| "Response stream closed unexpectedly")??;
+
+    let request = PublishBuildToolEventStreamRequest {
+        project_id: "other-project".to_string(),
+        ..request
+    };
+    tx.send(Frame::data(encode_stream_proto(&request)?)).await?;
+    let err = response_stream
+        .next()
+        .await
+        .err_tip(|| "Response stream closed unexpectedly")?
+        .unwrap_err();
+    assert_eq!(err.code(), Code::PermissionDenied);
+    Ok(())
+}
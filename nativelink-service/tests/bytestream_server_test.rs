@@ -12,6 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
 use bytes::Bytes;
@@ -25,7 +27,7 @@ use hyper_util::server::conn::auto;
 use hyper_util::service::TowerToHyperService;
 use maplit::hashmap;
 use nativelink_config::cas_server::ByteStreamConfig;
-use nativelink_config::stores::{MemorySpec, StoreSpec};
+use nativelink_config::stores::{ConfigDigestHashFunction, MemorySpec, StoreSpec};
 use nativelink_error::{make_err, Code, Error, ResultExt};
 use nativelink_macro::nativelink_test;
 use nativelink_proto::google::bytestream::byte_stream_client::ByteStreamClient;
@@ -34,17 +36,22 @@ use nativelink_proto::google::bytestream::{
     QueryWriteStatusRequest, QueryWriteStatusResponse, ReadRequest, WriteRequest, WriteResponse,
 };
 use nativelink_service::bytestream_server::ByteStreamServer;
+use nativelink_service::instance_metrics::instance_metrics;
 use nativelink_store::default_store_factory::store_factory;
 use nativelink_store::store_manager::StoreManager;
 use nativelink_util::channel_body_for_tests::ChannelBody;
 use nativelink_util::common::{encode_stream_proto, DigestInfo};
-use nativelink_util::store_trait::StoreLike;
+use nativelink_util::digest_hasher::{DigestHasher, DigestHasherFunc};
+use nativelink_util::fake_store_for_tests::FakeStore;
+use nativelink_util::resource_info::ResourceInfo;
+use nativelink_util::store_trait::{Store, StoreLike};
 use nativelink_util::task::JoinHandleDropGuard;
 use nativelink_util::{background_spawn, spawn};
 use pretty_assertions::assert_eq;
 use tokio::io::DuplexStream;
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::unbounded_channel;
+use tokio::sync::Notify;
 use tokio::task::yield_now;
 use tokio_stream::wrappers::UnboundedReceiverStream;
 use tokio_stream::StreamExt;
@@ -81,6 +88,9 @@ fn make_bytestream_server(
         persist_stream_on_disconnect_timeout: 0,
         max_bytes_per_stream: 1024,
         max_decoding_message_size: 0,
+        strict_resource_name_validation: false,
+        max_upload_buffer_bytes: 0,
+        digest_functions: HashMap::new(),
     });
     ByteStreamServer::new(&config, store_manager)
 }
@@ -268,6 +278,91 @@ pub async fn chunked_stream_receives_all_data() -> Result<(), Box<dyn std::error
     Ok(())
 }
 
+#[nativelink_test]
+pub async fn write_with_upload_buffer_limit_receives_all_data(
+) -> Result<(), Box<dyn std::error::Error>> {
+    const CHUNK_SIZE: usize = 4;
+    // More than the two chunks the channel to the store holds without
+    // watermarks, so only the limit can stop the upload at this point.
+    const MAX_UPLOAD_BUFFER_BYTES: usize = 3 * CHUNK_SIZE;
+
+    let store_manager = Arc::new(StoreManager::new());
+    let fake_store = FakeStore::new(
+        store_factory(
+            &StoreSpec::memory(MemorySpec::default()),
+            &store_manager,
+            None,
+        )
+        .await?,
+    );
+    store_manager.add_store("main_cas", Store::new(fake_store.clone()));
+    let bs_server = Arc::new(make_bytestream_server(
+        store_manager.as_ref(),
+        Some(ByteStreamConfig {
+            cas_stores: hashmap! {
+                INSTANCE_NAME.to_string() => "main_cas".to_string(),
+            },
+            max_upload_buffer_bytes: MAX_UPLOAD_BUFFER_BYTES,
+            ..Default::default()
+        }),
+    )?);
+
+    // The store does not read anything until it is resumed.
+    fake_store.set_updates_paused(true);
+    let (tx, join_handle) = make_stream_and_writer_spawn(bs_server.clone(), None);
+    let raw_data = "12456789abcdefghijk".as_bytes();
+    let mut write_request = WriteRequest {
+        resource_name: format!(
+            "{}/uploads/{}/blobs/{}/{}",
+            INSTANCE_NAME,
+            "4dcec57e-1389-4ab5-b188-4a59f22ceb4b", // Randomly generated.
+            HASH1,
+            raw_data.len()
+        ),
+        write_offset: 0,
+        finish_write: false,
+        data: vec![].into(),
+    };
+    for (i, chunk) in raw_data.chunks(CHUNK_SIZE).enumerate() {
+        write_request.write_offset = (i * CHUNK_SIZE) as i64;
+        write_request.data = chunk.to_vec().into();
+        write_request.finish_write = (i + 1) * CHUNK_SIZE >= raw_data.len();
+        tx.send(Frame::data(encode_stream_proto(&write_request)?))
+            .await?;
+    }
+
+    let bytes_received = || {
+        bs_server
+            .upload_sessions()
+            .first()
+            .map_or(0, |session| session.bytes_received)
+    };
+    while bytes_received() < MAX_UPLOAD_BUFFER_BYTES as u64 {
+        yield_now().await;
+    }
+    for _ in 0..100 {
+        yield_now().await;
+    }
+    // The writer is blocked at the limit, not after the rest of the data.
+    assert_eq!(bytes_received(), MAX_UPLOAD_BUFFER_BYTES as u64);
+
+    fake_store.set_updates_paused(false);
+    let server_result = join_handle
+        .await
+        .expect("Failed to join")
+        .expect("Failed write");
+    assert_eq!(
+        server_result.into_inner().committed_size,
+        raw_data.len() as i64
+    );
+    let store_data = fake_store
+        .inner()
+        .get_part_unchunked(DigestInfo::try_new(HASH1, raw_data.len())?, 0, None)
+        .await?;
+    assert_eq!(store_data.as_ref(), raw_data);
+    Ok(())
+}
+
 #[nativelink_test]
 pub async fn resume_write_success() -> Result<(), Box<dyn std::error::Error>> {
     const WRITE_DATA: &str = "12456789abcdefghijk";
@@ -342,6 +437,95 @@ pub async fn resume_write_success() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[nativelink_test]
+pub async fn idle_upload_sessions_expire() -> Result<(), Box<dyn std::error::Error>> {
+    const WRITE_DATA: &str = "12456789abcdefghijk";
+    const BYTE_SPLIT_OFFSET: usize = 8;
+    const SESSION_INSTANCE_NAME: &str = "idle_upload_sessions_expire";
+
+    let store_manager = make_store_manager().await?;
+    let expire_idle_streams = Arc::new(Notify::new());
+    let bs_server = Arc::new(ByteStreamServer::new_with_sleep_fn(
+        &ByteStreamConfig {
+            cas_stores: hashmap! {
+                SESSION_INSTANCE_NAME.to_string() => "main_cas".to_string(),
+            },
+            persist_stream_on_disconnect_timeout: 0,
+            max_bytes_per_stream: 1024,
+            max_decoding_message_size: 0,
+            strict_resource_name_validation: false,
+            max_upload_buffer_bytes: 0,
+            digest_functions: HashMap::new(),
+        },
+        store_manager.as_ref(),
+        {
+            let expire_idle_streams = expire_idle_streams.clone();
+            Arc::new(move || {
+                let expire_idle_streams = expire_idle_streams.clone();
+                Box::pin(async move { expire_idle_streams.notified().await })
+            })
+        },
+    )?);
+    let metrics = instance_metrics(SESSION_INSTANCE_NAME);
+
+    let (tx, join_handle) = make_stream_and_writer_spawn(bs_server.clone(), None);
+    let write_request = WriteRequest {
+        resource_name: format!(
+            "{SESSION_INSTANCE_NAME}/uploads/4dcec57e-1389-4ab5-b188-4a59f22ceb4b/blobs/{HASH1}/{}",
+            WRITE_DATA.len()
+        ),
+        write_offset: 0,
+        finish_write: false,
+        data: WRITE_DATA[..BYTE_SPLIT_OFFSET].into(),
+    };
+    tx.send(Frame::data(encode_stream_proto(&write_request)?))
+        .await?;
+    yield_now().await;
+    {
+        let sessions = bs_server.upload_sessions();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].uuid, "4dcec57e-1389-4ab5-b188-4a59f22ceb4b");
+        assert_eq!(sessions[0].bytes_received, BYTE_SPLIT_OFFSET as u64);
+        assert!(!sessions[0].idle, "Expected session to be receiving data");
+        assert_eq!(
+            metrics
+                .bytestream_sessions
+                .active_sessions
+                .load(Ordering::Acquire),
+            1
+        );
+    }
+
+    // Client disconnects, the session waits to be resumed.
+    drop(tx);
+    assert!(join_handle.await.expect("Failed to join").is_err());
+    {
+        let sessions = bs_server.upload_sessions();
+        assert_eq!(sessions.len(), 1);
+        assert!(sessions[0].idle, "Expected session to be idle");
+    }
+
+    // Nobody resumed the session in time.
+    expire_idle_streams.notify_one();
+    for _ in 0..10 {
+        yield_now().await;
+    }
+    assert!(bs_server.upload_sessions().is_empty());
+    assert_eq!(
+        metrics
+            .bytestream_sessions
+            .active_sessions
+            .load(Ordering::Acquire),
+        0
+    );
+    assert_eq!(metrics.bytestream_sessions.abandoned_sessions.get(), 1);
+    assert_eq!(
+        metrics.bytestream_sessions.abandoned_bytes.get(),
+        BYTE_SPLIT_OFFSET as u64
+    );
+    Ok(())
+}
+
 #[nativelink_test]
 pub async fn restart_write_success() -> Result<(), Box<dyn std::error::Error>> {
     const WRITE_DATA: &str = "12456789abcdefghijk";
@@ -764,6 +948,101 @@ pub async fn chunked_stream_reads_small_set_of_data() -> Result<(), Box<dyn std:
     Ok(())
 }
 
+#[nativelink_test]
+pub async fn read_honors_offset_and_limit() -> Result<(), Box<dyn std::error::Error>> {
+    const VALUE1: &str = "12456789abcdefghijk";
+
+    let store_manager = make_store_manager().await?;
+    let bs_server = make_bytestream_server(
+        store_manager.as_ref(),
+        Some(ByteStreamConfig {
+            cas_stores: hashmap! {
+                INSTANCE_NAME.to_string() => "main_cas".to_string(),
+            },
+            persist_stream_on_disconnect_timeout: 0,
+            max_bytes_per_stream: 4,
+            max_decoding_message_size: 0,
+            strict_resource_name_validation: false,
+            max_upload_buffer_bytes: 0,
+            digest_functions: HashMap::new(),
+        }),
+    )?;
+    let store = store_manager.get_store("main_cas").unwrap();
+    let digest = DigestInfo::try_new(HASH1, VALUE1.len())?;
+    store.update_oneshot(digest, VALUE1.into()).await?;
+
+    let read = |read_offset: i64, read_limit: i64| {
+        bs_server.read(Request::new(ReadRequest {
+            resource_name: format!("{INSTANCE_NAME}/blobs/{HASH1}/{}", VALUE1.len()),
+            read_offset,
+            read_limit,
+        }))
+    };
+
+    {
+        // Reads are split into messages of at most `max_bytes_per_stream`.
+        let mut read_stream = read(2, 10).await?.into_inner();
+        let mut chunks = Vec::new();
+        while let Some(response) = read_stream.next().await {
+            chunks.push(response?.data);
+        }
+        assert_eq!(chunks, vec!["4567", "89ab", "cd"]);
+    }
+    {
+        // Reading from the end of the blob returns no data.
+        let mut read_stream = read(VALUE1.len() as i64, 0).await?.into_inner();
+        assert!(read_stream.next().await.is_none());
+    }
+    for (read_offset, read_limit) in [(-1, 0), (0, -1), (VALUE1.len() as i64 + 1, 0)] {
+        let Err(status) = read(read_offset, read_limit).await else {
+            panic!("Expected read at {read_offset} of {read_limit} bytes to fail");
+        };
+        assert_eq!(status.code(), tonic::Code::OutOfRange);
+    }
+    Ok(())
+}
+
+#[nativelink_test]
+pub async fn read_rejects_disallowed_digest_function() -> Result<(), Box<dyn std::error::Error>> {
+    const VALUE1: &str = "12456789abcdefghijk";
+
+    let store_manager = make_store_manager().await?;
+    let bs_server = make_bytestream_server(
+        store_manager.as_ref(),
+        Some(ByteStreamConfig {
+            cas_stores: hashmap! {
+                INSTANCE_NAME.to_string() => "main_cas".to_string(),
+            },
+            digest_functions: hashmap! {
+                INSTANCE_NAME.to_string() => vec![ConfigDigestHashFunction::sha256],
+            },
+            ..Default::default()
+        }),
+    )?;
+    let store = store_manager.get_store("main_cas").unwrap();
+    let digest = DigestInfo::try_new(HASH1, VALUE1.len())?;
+    store.update_oneshot(digest, VALUE1.into()).await?;
+
+    let read = |resource_name: String| {
+        bs_server.read(Request::new(ReadRequest {
+            resource_name,
+            read_offset: 0,
+            read_limit: 0,
+        }))
+    };
+    read(format!("{INSTANCE_NAME}/blobs/{HASH1}/{}", VALUE1.len())).await?;
+    let Err(status) = read(format!(
+        "{INSTANCE_NAME}/blobs/blake3/{HASH1}/{}",
+        VALUE1.len()
+    ))
+    .await
+    else {
+        panic!("Expected read with a digest function that is not allowed to fail");
+    };
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    Ok(())
+}
+
 #[nativelink_test]
 pub async fn chunked_stream_reads_10mb_of_data() -> Result<(), Box<dyn std::error::Error>> {
     const DATA_SIZE: usize = 10_000_000;
@@ -953,6 +1232,142 @@ pub async fn test_query_write_status_smoke_test() -> Result<(), Box<dyn std::err
     Ok(())
 }
 
+#[nativelink_test]
+pub async fn query_write_status_is_keyed_by_digest() -> Result<(), Box<dyn std::error::Error>> {
+    const BYTE_SPLIT_OFFSET: usize = 8;
+
+    let store_manager = make_store_manager()
+        .await
+        .expect("Failed to make store manager");
+    let bs_server = Arc::new(
+        make_bytestream_server(store_manager.as_ref(), None).expect("Failed to make server"),
+    );
+
+    let raw_data = "12456789abcdefghijk".as_bytes();
+    let resource_name = make_resource_name(raw_data.len());
+
+    let (tx, join_handle) =
+        make_stream_and_writer_spawn(bs_server.clone(), Some(CompressionEncoding::Gzip));
+    let mut write_request = WriteRequest {
+        resource_name: resource_name.clone(),
+        write_offset: 0,
+        finish_write: false,
+        data: raw_data[..BYTE_SPLIT_OFFSET].into(),
+    };
+    tx.send(Frame::data(encode_stream_proto(&write_request)?))
+        .await?;
+    tokio::task::yield_now().await;
+
+    {
+        // The same UUID used for another blob is a different upload.
+        let data = bs_server
+            .query_write_status(Request::new(QueryWriteStatusRequest {
+                resource_name: make_resource_name(raw_data.len() + 1),
+            }))
+            .await?;
+        assert_eq!(
+            data.into_inner(),
+            QueryWriteStatusResponse {
+                committed_size: 0,
+                complete: false,
+            }
+        );
+    }
+    {
+        let data = bs_server
+            .query_write_status(Request::new(QueryWriteStatusRequest {
+                resource_name: resource_name.clone(),
+            }))
+            .await?;
+        assert_eq!(
+            data.into_inner(),
+            QueryWriteStatusResponse {
+                committed_size: BYTE_SPLIT_OFFSET as i64,
+                complete: false,
+            }
+        );
+    }
+
+    write_request.write_offset = BYTE_SPLIT_OFFSET as i64;
+    write_request.data = raw_data[BYTE_SPLIT_OFFSET..].into();
+    write_request.finish_write = true;
+    tx.send(Frame::data(encode_stream_proto(&write_request)?))
+        .await?;
+    join_handle
+        .await
+        .expect("Failed to join")
+        .expect("Failed write");
+    Ok(())
+}
+
+#[nativelink_test]
+pub async fn concurrent_writes_of_same_blob_upload_once() -> Result<(), Box<dyn std::error::Error>>
+{
+    const BYTE_SPLIT_OFFSET: usize = 8;
+
+    let store_manager = make_store_manager()
+        .await
+        .expect("Failed to make store manager");
+    let bs_server = Arc::new(
+        make_bytestream_server(store_manager.as_ref(), None).expect("Failed to make server"),
+    );
+
+    let raw_data = "12456789abcdefghijk".as_bytes();
+    let resource_name = make_resource_name(raw_data.len());
+
+    // First client starts uploading.
+    let (tx1, join_handle1) = make_stream_and_writer_spawn(bs_server.clone(), None);
+    let mut write_request = WriteRequest {
+        resource_name: resource_name.clone(),
+        write_offset: 0,
+        finish_write: false,
+        data: raw_data[..BYTE_SPLIT_OFFSET].into(),
+    };
+    tx1.send(Frame::data(encode_stream_proto(&write_request)?))
+        .await?;
+    yield_now().await;
+
+    // Second client uploads the same blob under another UUID.
+    let (tx2, mut join_handle2) = make_stream_and_writer_spawn(bs_server.clone(), None);
+    tx2.send(Frame::data(encode_stream_proto(&WriteRequest {
+        resource_name: resource_name.replace(
+            "4dcec57e-1389-4ab5-b188-4a59f22ceb4b",
+            "c49b4e39-ed1f-4bd3-9de9-a3c4d2d1f3e7",
+        ),
+        ..write_request.clone()
+    })?))
+    .await?;
+    yield_now().await;
+    assert!(
+        poll!(&mut join_handle2).is_pending(),
+        "Expected second upload to wait for the first one"
+    );
+
+    // First client finishes uploading.
+    write_request.write_offset = BYTE_SPLIT_OFFSET as i64;
+    write_request.data = raw_data[BYTE_SPLIT_OFFSET..].into();
+    write_request.finish_write = true;
+    tx1.send(Frame::data(encode_stream_proto(&write_request)?))
+        .await?;
+    join_handle1
+        .await
+        .expect("Failed to join")
+        .expect("Failed write");
+
+    // Second client is done without sending the rest of the data.
+    let response = join_handle2
+        .await
+        .expect("Failed to join")
+        .expect("Failed write");
+    assert_eq!(
+        response.into_inner(),
+        WriteResponse {
+            committed_size: raw_data.len() as i64
+        }
+    );
+    Ok(())
+}
+
 #[nativelink_test]
 pub async fn max_decoding_message_size_test() -> Result<(), Box<dyn std::error::Error>> {
     const MAX_MESSAGE_SIZE: usize = 1024 * 1024; // 1MB.
@@ -1025,3 +1440,151 @@ pub async fn max_decoding_message_size_test() -> Result<(), Box<dyn std::error::
 
     Ok(())
 }
+
+fn sha256_digest(data: &[u8]) -> DigestInfo {
+    let mut hasher = DigestHasherFunc::Sha256.hasher();
+    hasher.update(data);
+    hasher.finalize_digest()
+}
+
+#[nativelink_test]
+pub async fn zstd_compressed_write_is_stored_decompressed() -> Result<(), Box<dyn std::error::Error>>
+{
+    const WRITE_DATA: &str = "12456789abcdefghijk12456789abcdefghijk";
+
+    let store_manager = make_store_manager().await?;
+    let bs_server = Arc::new(
+        make_bytestream_server(store_manager.as_ref(), None).expect("Failed to make server"),
+    );
+    let store = store_manager.get_store("main_cas").unwrap();
+    let digest = sha256_digest(WRITE_DATA.as_bytes());
+    let compressed_data = zstd::bulk::compress(WRITE_DATA.as_bytes(), 1)?;
+
+    let (tx, join_handle) = make_stream_and_writer_spawn(bs_server, None);
+    let write_request = WriteRequest {
+        resource_name: ResourceInfo::from_digest(INSTANCE_NAME, digest)
+            .with_uuid("4dcec57e-1389-4ab5-b188-4a59f22ceb4b")
+            .with_compressor("zstd")
+            .to_string(true),
+        write_offset: 0,
+        finish_write: true,
+        data: compressed_data.clone().into(),
+    };
+    tx.send(Frame::data(encode_stream_proto(&write_request)?))
+        .await?;
+
+    let server_result = join_handle
+        .await
+        .expect("Failed to join")
+        .expect("Failed write");
+    assert_eq!(
+        server_result.into_inner().committed_size,
+        compressed_data.len() as i64,
+        "Expected committed_size to be the compressed size"
+    );
+    let store_data = store.get_part_unchunked(digest, 0, None).await?;
+    assert_eq!(
+        store_data,
+        WRITE_DATA.as_bytes(),
+        "Expected store to hold the decompressed data"
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+pub async fn zstd_compressed_write_with_wrong_digest_fails(
+) -> Result<(), Box<dyn std::error::Error>> {
+    const WRITE_DATA: &str = "12456789abcdefghijk";
+    const OTHER_DATA: &str = "abcdefghijk12456789";
+
+    let store_manager = make_store_manager().await?;
+    let bs_server = Arc::new(
+        make_bytestream_server(store_manager.as_ref(), None).expect("Failed to make server"),
+    );
+    let store = store_manager.get_store("main_cas").unwrap();
+    let digest = sha256_digest(WRITE_DATA.as_bytes());
+
+    let (tx, join_handle) = make_stream_and_writer_spawn(bs_server, None);
+    let write_request = WriteRequest {
+        resource_name: ResourceInfo::from_digest(INSTANCE_NAME, digest)
+            .with_uuid("4dcec57e-1389-4ab5-b188-4a59f22ceb4b")
+            .with_compressor("zstd")
+            .to_string(true),
+        write_offset: 0,
+        finish_write: true,
+        data: zstd::bulk::compress(OTHER_DATA.as_bytes(), 1)?.into(),
+    };
+    tx.send(Frame::data(encode_stream_proto(&write_request)?))
+        .await?;
+
+    let result = join_handle.await.expect("Failed to join");
+    assert_eq!(
+        result.unwrap_err().code(),
+        tonic::Code::InvalidArgument,
+        "Expected mismatching data to be rejected"
+    );
+    assert_eq!(
+        store.has(digest).await?,
+        None,
+        "Expected data to not be stored"
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+pub async fn zstd_compressed_read_returns_compressed_data() -> Result<(), Box<dyn std::error::Error>>
+{
+    const READ_OFFSET: usize = 5;
+
+    let store_manager = make_store_manager().await?;
+    let bs_server = Arc::new(
+        make_bytestream_server(store_manager.as_ref(), None).expect("Failed to make server"),
+    );
+    let store = store_manager.get_store("main_cas").unwrap();
+
+    let raw_data = vec![41u8; 100_000];
+    let digest = sha256_digest(&raw_data);
+    store
+        .update_oneshot(digest, raw_data.clone().into())
+        .await?;
+
+    let resource_name = ResourceInfo::from_digest(INSTANCE_NAME, digest)
+        .with_compressor("zstd")
+        .to_string(false);
+    let read_all = |read_offset: usize| {
+        let bs_server = bs_server.clone();
+        let read_request = ReadRequest {
+            resource_name: resource_name.clone(),
+            read_offset: read_offset as i64,
+            read_limit: 0,
+        };
+        async move {
+            let mut read_stream = bs_server
+                .read(Request::new(read_request))
+                .await?
+                .into_inner();
+            let mut data = Vec::new();
+            while let Some(read_response) = read_stream.next().await {
+                data.extend_from_slice(&read_response?.data);
+            }
+            Ok::<_, tonic::Status>(data)
+        }
+    };
+
+    let compressed_data = read_all(0).await?;
+    assert!(
+        compressed_data.len() < raw_data.len(),
+        "Expected data to be compressed"
+    );
+    assert_eq!(
+        zstd::bulk::decompress(&compressed_data, raw_data.len())?,
+        raw_data,
+        "Expected compressed data to decompress to the data in the store"
+    );
+    assert_eq!(
+        read_all(READ_OFFSET).await?,
+        compressed_data[READ_OFFSET..],
+        "Expected read_offset to apply to the compressed data"
+    );
+    Ok(())
+}
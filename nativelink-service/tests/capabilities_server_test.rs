@@ -0,0 +1,141 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+
+use maplit::hashmap;
+use nativelink_config::cas_server::{
+    AcStoreConfig, ByteStreamConfig, CapabilitiesConfig, CasStoreConfig, ServicesConfig,
+    SymlinkAbsolutePathStrategy,
+};
+use nativelink_config::stores::ConfigDigestHashFunction;
+use nativelink_macro::nativelink_test;
+use nativelink_proto::build::bazel::remote::execution::v2::capabilities_server::Capabilities;
+use nativelink_proto::build::bazel::remote::execution::v2::symlink_absolute_path_strategy::Value as ProtoSymlinkAbsolutePathStrategy;
+use nativelink_proto::build::bazel::remote::execution::v2::{
+    compressor, digest_function, CacheCapabilities, GetCapabilitiesRequest,
+};
+use nativelink_service::capabilities_server::CapabilitiesServer;
+use nativelink_service::cas_server::DEFAULT_MAX_BATCH_TOTAL_SIZE;
+use pretty_assertions::assert_eq;
+use tonic::Request;
+
+async fn get_cache_capabilities(
+    server: &CapabilitiesServer,
+    instance_name: &str,
+) -> Result<CacheCapabilities, Box<dyn std::error::Error>> {
+    Ok(server
+        .get_capabilities(Request::new(GetCapabilitiesRequest {
+            instance_name: instance_name.to_string(),
+        }))
+        .await?
+        .into_inner()
+        .cache_capabilities
+        .ok_or("Expected cache capabilities")?)
+}
+
+#[nativelink_test]
+async fn capabilities_follow_service_config() -> Result<(), Box<dyn std::error::Error>> {
+    let server = CapabilitiesServer::new(
+        &ServicesConfig {
+            cas: Some(hashmap! {
+                "main".to_string() => CasStoreConfig {
+                    cas_store: "cas".to_string(),
+                    find_missing_batch_size: 0,
+                    max_concurrent_find_missing_batches: 0,
+                    max_concurrent_batch_updates: 0,
+                    max_batch_total_size: 1024 * 1024,
+                    digest_functions: vec![ConfigDigestHashFunction::sha256],
+                },
+            }),
+            ac: Some(hashmap! {
+                "main".to_string() => AcStoreConfig {
+                    ac_store: "ac".to_string(),
+                    read_only: true,
+                    cas_store: None,
+                    verify_outputs_exist: false,
+                    max_action_result_size: 0,
+                    action_result_ttl: 0,
+                    write_role: None,
+                    max_inline_size: 0,
+                    digest_functions: Vec::new(),
+                },
+            }),
+            capabilities: Some(hashmap! {
+                "main".to_string() => CapabilitiesConfig {
+                    symlink_absolute_path_strategy: SymlinkAbsolutePathStrategy::allowed,
+                    ..Default::default()
+                },
+                "other".to_string() => CapabilitiesConfig::default(),
+            }),
+            bytestream: Some(ByteStreamConfig {
+                cas_stores: hashmap! {
+                    "main".to_string() => "cas".to_string(),
+                },
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+        &HashMap::new(),
+    )
+    .await?;
+
+    let main = get_cache_capabilities(&server, "main").await?;
+    assert_eq!(main.max_batch_total_size_bytes, 1024 * 1024);
+    assert!(
+        !main
+            .action_cache_update_capabilities
+            .unwrap()
+            .update_enabled
+    );
+    assert_eq!(
+        main.supported_compressors,
+        vec![i32::from(compressor::Value::Zstd)]
+    );
+    assert_eq!(
+        main.symlink_absolute_path_strategy,
+        i32::from(ProtoSymlinkAbsolutePathStrategy::Allowed)
+    );
+    // Only the digest functions the CAS accepts are advertised.
+    assert_eq!(
+        main.digest_functions,
+        vec![i32::from(digest_function::Value::Sha256)]
+    );
+
+    // Nothing but the capabilities is configured for this instance here.
+    let other = get_cache_capabilities(&server, "other").await?;
+    assert_eq!(
+        other.max_batch_total_size_bytes,
+        i64::try_from(DEFAULT_MAX_BATCH_TOTAL_SIZE)?
+    );
+    assert!(
+        other
+            .action_cache_update_capabilities
+            .unwrap()
+            .update_enabled
+    );
+    assert_eq!(other.supported_compressors, Vec::<i32>::new());
+    assert_eq!(
+        other.symlink_absolute_path_strategy,
+        i32::from(ProtoSymlinkAbsolutePathStrategy::Disallowed)
+    );
+    assert_eq!(
+        other.digest_functions,
+        vec![
+            i32::from(digest_function::Value::Sha256),
+            i32::from(digest_function::Value::Blake3),
+        ]
+    );
+    Ok(())
+}
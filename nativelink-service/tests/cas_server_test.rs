@@ -12,12 +12,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::io::Read;
 use std::pin::Pin;
 use std::sync::Arc;
 
+use flate2::read::DeflateDecoder;
 use futures::StreamExt;
 use maplit::hashmap;
-use nativelink_config::stores::{MemorySpec, StoreSpec};
+use nativelink_config::stores::{ConfigDigestHashFunction, MemorySpec, StoreSpec};
 use nativelink_error::Error;
 use nativelink_macro::nativelink_test;
 use nativelink_proto::build::bazel::remote::execution::v2::content_addressable_storage_server::ContentAddressableStorage;
@@ -28,14 +30,17 @@ use nativelink_proto::build::bazel::remote::execution::v2::{
     GetTreeRequest, GetTreeResponse, NodeProperties,
 };
 use nativelink_proto::google::rpc::Status as GrpcStatus;
-use nativelink_service::cas_server::CasServer;
+use nativelink_service::cas_server::{CasServer, DEFAULT_MAX_BATCH_TOTAL_SIZE};
+use nativelink_service::instance_metrics::instance_metrics;
 use nativelink_store::ac_utils::serialize_and_upload_message;
 use nativelink_store::default_store_factory::store_factory;
 use nativelink_store::store_manager::StoreManager;
 use nativelink_util::common::DigestInfo;
 use nativelink_util::digest_hasher::DigestHasherFunc;
+use nativelink_util::metrics_utils::set_metrics_enabled_for_this_thread;
 use nativelink_util::store_trait::{StoreKey, StoreLike};
 use pretty_assertions::assert_eq;
+use prost::Message;
 use prost_types::Timestamp;
 use tonic::{Code, Request};
 
@@ -64,6 +69,11 @@ fn make_cas_server(store_manager: &StoreManager) -> Result<CasServer, Error> {
         &hashmap! {
             "foo_instance_name".to_string() => nativelink_config::cas_server::CasStoreConfig{
                 cas_store: "main_cas".to_string(),
+                find_missing_batch_size: 0,
+                max_concurrent_find_missing_batches: 0,
+                max_concurrent_batch_updates: 0,
+                max_batch_total_size: 0,
+                digest_functions: Vec::new(),
             }
         },
         store_manager,
@@ -118,6 +128,133 @@ async fn store_one_item_existence() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[nativelink_test]
+async fn routes_instance_names_matching_a_prefix() -> Result<(), Box<dyn std::error::Error>> {
+    const VALUE: &str = "1";
+
+    let store_manager = make_store_manager().await?;
+    let cas_server = CasServer::new(
+        &hashmap! {
+            "ci/*".to_string() => nativelink_config::cas_server::CasStoreConfig{
+                cas_store: "main_cas".to_string(),
+                find_missing_batch_size: 0,
+                max_concurrent_find_missing_batches: 0,
+                max_concurrent_batch_updates: 0,
+                max_batch_total_size: 0,
+                digest_functions: Vec::new(),
+            }
+        },
+        &store_manager,
+    )?;
+    let store = store_manager.get_store("main_cas").unwrap();
+    store
+        .update_oneshot(DigestInfo::try_new(HASH1, VALUE.len())?, VALUE.into())
+        .await?;
+
+    let find_missing_blobs = |instance_name: &str| {
+        cas_server.find_missing_blobs(Request::new(FindMissingBlobsRequest {
+            instance_name: instance_name.to_string(),
+            blob_digests: vec![Digest {
+                hash: HASH1.to_string(),
+                size_bytes: VALUE.len() as i64,
+            }],
+            digest_function: digest_function::Value::Sha256.into(),
+        }))
+    };
+    let response = find_missing_blobs("ci/linux").await?.into_inner();
+    assert_eq!(response.missing_blob_digests, vec![]);
+    assert!(find_missing_blobs("release/linux").await.is_err());
+    Ok(())
+}
+
+#[nativelink_test]
+async fn rejects_digest_functions_not_allowed() -> Result<(), Box<dyn std::error::Error>> {
+    let store_manager = make_store_manager().await?;
+    let cas_server = CasServer::new(
+        &hashmap! {
+            INSTANCE_NAME.to_string() => nativelink_config::cas_server::CasStoreConfig{
+                cas_store: "main_cas".to_string(),
+                find_missing_batch_size: 0,
+                max_concurrent_find_missing_batches: 0,
+                max_concurrent_batch_updates: 0,
+                max_batch_total_size: 0,
+                digest_functions: vec![ConfigDigestHashFunction::sha256],
+            }
+        },
+        &store_manager,
+    )?;
+
+    let find_missing_blobs = |digest_function: digest_function::Value| {
+        cas_server.find_missing_blobs(Request::new(FindMissingBlobsRequest {
+            instance_name: INSTANCE_NAME.to_string(),
+            blob_digests: Vec::new(),
+            digest_function: digest_function.into(),
+        }))
+    };
+    find_missing_blobs(digest_function::Value::Sha256).await?;
+    let status = find_missing_blobs(digest_function::Value::Blake3)
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+
+    let status = cas_server
+        .batch_update_blobs(Request::new(BatchUpdateBlobsRequest {
+            instance_name: INSTANCE_NAME.to_string(),
+            requests: Vec::new(),
+            digest_function: digest_function::Value::Blake3.into(),
+        }))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn find_missing_blobs_splits_large_requests() -> Result<(), Box<dyn std::error::Error>> {
+    const VALUE: &str = "1";
+
+    let store_manager = make_store_manager().await?;
+    let cas_server = CasServer::new(
+        &hashmap! {
+            INSTANCE_NAME.to_string() => nativelink_config::cas_server::CasStoreConfig{
+                cas_store: "main_cas".to_string(),
+                find_missing_batch_size: 2,
+                max_concurrent_find_missing_batches: 2,
+                max_concurrent_batch_updates: 0,
+                max_batch_total_size: 0,
+                digest_functions: Vec::new(),
+            }
+        },
+        &store_manager,
+    )?;
+    let store = store_manager.get_store("main_cas").unwrap();
+
+    let digests: Vec<Digest> = (0..5)
+        .map(|i| Digest {
+            hash: format!("{i:064x}"),
+            size_bytes: VALUE.len() as i64,
+        })
+        .collect();
+    for digest in [&digests[1], &digests[3]] {
+        store
+            .update_oneshot(DigestInfo::try_from(digest.clone())?, VALUE.into())
+            .await?;
+    }
+    let response = cas_server
+        .find_missing_blobs(Request::new(FindMissingBlobsRequest {
+            instance_name: INSTANCE_NAME.to_string(),
+            blob_digests: digests.clone(),
+            digest_function: digest_function::Value::Sha256.into(),
+        }))
+        .await?
+        .into_inner();
+    assert_eq!(
+        response.missing_blob_digests,
+        vec![digests[0].clone(), digests[2].clone(), digests[4].clone()]
+    );
+    Ok(())
+}
+
 #[nativelink_test]
 async fn has_three_requests_one_bad_hash() -> Result<(), Box<dyn std::error::Error>> {
     const VALUE: &str = "1";
@@ -151,8 +288,8 @@ async fn has_three_requests_one_bad_hash() -> Result<(), Box<dyn std::error::Err
         .await;
     let error = raw_response.unwrap_err();
     assert!(
-        error.to_string().contains("Invalid sha256 hash: BAD_HASH"),
-        "'Invalid sha256 hash: BAD_HASH' not found in: {error:?}"
+        error.to_string().contains("Invalid hash: BAD_HASH"),
+        "'Invalid hash: BAD_HASH' not found in: {error:?}"
     );
     Ok(())
 }
@@ -301,6 +438,79 @@ async fn batch_read_blobs_read_two_blobs_success_one_fail() -> Result<(), Box<dy
     Ok(())
 }
 
+#[nativelink_test]
+async fn batch_read_blobs_compresses_for_accepting_clients(
+) -> Result<(), Box<dyn std::error::Error>> {
+    const VALUE1: &str = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+
+    let store_manager = make_store_manager().await?;
+    let cas_server = make_cas_server(&store_manager)?;
+    let store = store_manager.get_store("main_cas").unwrap();
+    store
+        .update_oneshot(DigestInfo::try_new(HASH1, VALUE1.len())?, VALUE1.into())
+        .await?;
+    let digest1 = Digest {
+        hash: HASH1.to_string(),
+        size_bytes: VALUE1.len() as i64,
+    };
+    let read_blob = |acceptable_compressors: Vec<compressor::Value>| {
+        cas_server.batch_read_blobs(Request::new(BatchReadBlobsRequest {
+            instance_name: INSTANCE_NAME.to_string(),
+            digests: vec![digest1.clone()],
+            acceptable_compressors: acceptable_compressors.into_iter().map(Into::into).collect(),
+            digest_function: digest_function::Value::Sha256.into(),
+        }))
+    };
+
+    let response = read_blob(vec![compressor::Value::Deflate])
+        .await?
+        .into_inner()
+        .responses
+        .remove(0);
+    assert_eq!(response.compressor, i32::from(compressor::Value::Deflate));
+    let mut data = String::new();
+    DeflateDecoder::new(response.data.as_ref()).read_to_string(&mut data)?;
+    assert_eq!(data, VALUE1);
+
+    let response = read_blob(vec![compressor::Value::Deflate, compressor::Value::Zstd])
+        .await?
+        .into_inner()
+        .responses
+        .remove(0);
+    assert_eq!(response.compressor, i32::from(compressor::Value::Zstd));
+    assert!(response.data.len() < VALUE1.len());
+    assert_eq!(zstd::decode_all(response.data.as_ref())?, VALUE1.as_bytes());
+    Ok(())
+}
+
+#[nativelink_test]
+async fn batch_read_blobs_rejects_requests_over_max_batch_total_size(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let store_manager = make_store_manager().await?;
+    let cas_server = make_cas_server(&store_manager)?;
+
+    let status = cas_server
+        .batch_read_blobs(Request::new(BatchReadBlobsRequest {
+            instance_name: INSTANCE_NAME.to_string(),
+            digests: vec![
+                Digest {
+                    hash: HASH1.to_string(),
+                    size_bytes: i64::try_from(DEFAULT_MAX_BATCH_TOTAL_SIZE)?,
+                },
+                Digest {
+                    hash: HASH2.to_string(),
+                    size_bytes: 1,
+                },
+            ],
+            acceptable_compressors: vec![],
+            digest_function: digest_function::Value::Sha256.into(),
+        }))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+    Ok(())
+}
+
 struct SetupDirectoryResult {
     root_directory: Directory,
     root_directory_digest_info: DigestInfo,
@@ -572,6 +782,97 @@ async fn get_tree_read_directories_with_paging() -> Result<(), Box<dyn std::erro
     Ok(())
 }
 
+#[nativelink_test]
+async fn get_tree_returns_shared_directories_once() -> Result<(), Box<dyn std::error::Error>> {
+    let store_manager = make_store_manager().await?;
+    let cas_server = make_cas_server(&store_manager)?;
+    let store = store_manager.get_store("main_cas").unwrap();
+
+    let sub_directory = Directory::default();
+    let sub_directory_digest_info = serialize_and_upload_message(
+        &sub_directory,
+        store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let root_directory = Directory {
+        directories: vec![
+            DirectoryNode {
+                name: "a".to_string(),
+                digest: Some(sub_directory_digest_info.into()),
+            },
+            DirectoryNode {
+                name: "b".to_string(),
+                digest: Some(sub_directory_digest_info.into()),
+            },
+        ],
+        ..Default::default()
+    };
+    let root_directory_digest_info = serialize_and_upload_message(
+        &root_directory,
+        store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+
+    let responses = cas_server
+        .get_tree(Request::new(GetTreeRequest {
+            instance_name: INSTANCE_NAME.to_string(),
+            page_size: 0,
+            page_token: String::new(),
+            root_digest: Some(root_directory_digest_info.into()),
+            digest_function: digest_function::Value::Sha256.into(),
+        }))
+        .await?
+        .into_inner()
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(
+        responses.into_iter().collect::<Result<Vec<_>, _>>()?,
+        vec![GetTreeResponse {
+            directories: vec![root_directory, sub_directory],
+            next_page_token: String::new(),
+        }]
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn get_tree_rejects_directories_referring_to_a_parent(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let store_manager = make_store_manager().await?;
+    let cas_server = make_cas_server(&store_manager)?;
+    let store = store_manager.get_store("main_cas").unwrap();
+
+    // Content addressed directories can not refer to themselves, but a store
+    // with bad data can still make them look like they do.
+    let root_digest_info = DigestInfo::try_new(HASH1, 100)?;
+    let root_directory = Directory {
+        directories: vec![DirectoryNode {
+            name: "loop".to_string(),
+            digest: Some(root_digest_info.into()),
+        }],
+        ..Default::default()
+    };
+    store
+        .update_oneshot(root_digest_info, root_directory.encode_to_vec().into())
+        .await?;
+
+    let status = cas_server
+        .get_tree(Request::new(GetTreeRequest {
+            instance_name: INSTANCE_NAME.to_string(),
+            page_size: 0,
+            page_token: String::new(),
+            root_digest: Some(root_digest_info.into()),
+            digest_function: digest_function::Value::Sha256.into(),
+        }))
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(status.code(), Code::InvalidArgument);
+    Ok(())
+}
+
 #[nativelink_test]
 async fn batch_update_blobs_two_items_existence_with_third_missing(
 ) -> Result<(), Box<dyn std::error::Error>> {
@@ -665,3 +966,193 @@ async fn batch_update_blobs_two_items_existence_with_third_missing(
     }
     Ok(())
 }
+
+#[nativelink_test]
+async fn batch_update_blobs_reports_failures_per_blob() -> Result<(), Box<dyn std::error::Error>> {
+    const VALUE1: &str = "1";
+    const VALUE2: &str = "23";
+
+    let store_manager = make_store_manager().await?;
+    let cas_server = make_cas_server(&store_manager)?;
+    let store = store_manager.get_store("main_cas").unwrap();
+
+    let digest1 = Digest {
+        hash: HASH1.to_string(),
+        size_bytes: VALUE1.len() as i64,
+    };
+    let digest2 = Digest {
+        hash: HASH2.to_string(),
+        size_bytes: VALUE2.len() as i64,
+    };
+    let responses = cas_server
+        .batch_update_blobs(Request::new(BatchUpdateBlobsRequest {
+            instance_name: INSTANCE_NAME.to_string(),
+            requests: vec![
+                batch_update_blobs_request::Request {
+                    digest: Some(digest1.clone()),
+                    // Does not match the size of the digest.
+                    data: VALUE2.into(),
+                    compressor: compressor::Value::Identity.into(),
+                },
+                batch_update_blobs_request::Request {
+                    digest: None,
+                    data: VALUE1.into(),
+                    compressor: compressor::Value::Identity.into(),
+                },
+                batch_update_blobs_request::Request {
+                    digest: Some(digest2.clone()),
+                    data: VALUE2.into(),
+                    compressor: compressor::Value::Identity.into(),
+                },
+            ],
+            digest_function: digest_function::Value::Sha256.into(),
+        }))
+        .await?
+        .into_inner()
+        .responses;
+
+    let code_of = |digest: Option<Digest>| {
+        responses
+            .iter()
+            .find(|response| response.digest == digest)
+            .and_then(|response| response.status.as_ref())
+            .map(|status| Code::from(status.code))
+    };
+    assert_eq!(responses.len(), 3);
+    assert_eq!(code_of(Some(digest1)), Some(Code::InvalidArgument));
+    assert_eq!(code_of(None), Some(Code::InvalidArgument));
+    assert_eq!(code_of(Some(digest2)), Some(Code::Ok));
+    assert_eq!(
+        store.has(DigestInfo::try_new(HASH2, VALUE2.len())?).await?,
+        Some(VALUE2.len() as u64)
+    );
+    assert_eq!(
+        store.has(DigestInfo::try_new(HASH1, VALUE1.len())?).await?,
+        None
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn batch_blobs_records_instance_metrics() -> Result<(), Box<dyn std::error::Error>> {
+    // Use a dedicated instance name, the metrics registry is process wide.
+    const METRICS_INSTANCE_NAME: &str = "metrics_instance_name";
+    const VALUE1: &str = "1";
+    const VALUE2: &str = "23";
+
+    set_metrics_enabled_for_this_thread(true);
+    let store_manager = make_store_manager().await?;
+    let cas_server = CasServer::new(
+        &hashmap! {
+            METRICS_INSTANCE_NAME.to_string() => nativelink_config::cas_server::CasStoreConfig{
+                cas_store: "main_cas".to_string(),
+                find_missing_batch_size: 0,
+                max_concurrent_find_missing_batches: 0,
+                max_concurrent_batch_updates: 0,
+                max_batch_total_size: 0,
+                digest_functions: Vec::new(),
+            }
+        },
+        &store_manager,
+    )?;
+
+    let digest1 = Digest {
+        hash: HASH1.to_string(),
+        size_bytes: VALUE1.len() as i64,
+    };
+    let digest2 = Digest {
+        hash: HASH2.to_string(),
+        size_bytes: VALUE2.len() as i64,
+    };
+    cas_server
+        .batch_update_blobs(Request::new(BatchUpdateBlobsRequest {
+            instance_name: METRICS_INSTANCE_NAME.to_string(),
+            requests: vec![
+                batch_update_blobs_request::Request {
+                    digest: Some(digest1),
+                    data: VALUE1.into(),
+                    compressor: compressor::Value::Identity.into(),
+                },
+                batch_update_blobs_request::Request {
+                    digest: Some(digest2.clone()),
+                    data: VALUE2.into(),
+                    compressor: compressor::Value::Identity.into(),
+                },
+            ],
+            digest_function: digest_function::Value::Sha256.into(),
+        }))
+        .await?;
+    cas_server
+        .batch_read_blobs(Request::new(BatchReadBlobsRequest {
+            instance_name: METRICS_INSTANCE_NAME.to_string(),
+            digests: vec![digest2],
+            acceptable_compressors: vec![compressor::Value::Identity.into()],
+            digest_function: digest_function::Value::Sha256.into(),
+        }))
+        .await?;
+
+    let metrics = instance_metrics(METRICS_INSTANCE_NAME);
+    assert_eq!(metrics.cas.upload_requests.get(), 1);
+    assert_eq!(metrics.cas.bytes_received.get(), 3);
+    assert_eq!(metrics.cas.download_requests.get(), 1);
+    assert_eq!(metrics.cas.bytes_sent.get(), 2);
+    assert_eq!(metrics.bytestream.bytes_sent.get(), 0);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn batch_update_blobs_metrics_skip_failed_blobs() -> Result<(), Box<dyn std::error::Error>> {
+    // Use a dedicated instance name, the metrics registry is process wide.
+    const METRICS_INSTANCE_NAME: &str = "failed_upload_metrics_instance_name";
+    const VALUE1: &str = "1";
+    const VALUE2: &str = "23";
+
+    set_metrics_enabled_for_this_thread(true);
+    let store_manager = make_store_manager().await?;
+    let cas_server = CasServer::new(
+        &hashmap! {
+            METRICS_INSTANCE_NAME.to_string() => nativelink_config::cas_server::CasStoreConfig{
+                cas_store: "main_cas".to_string(),
+                find_missing_batch_size: 0,
+                max_concurrent_find_missing_batches: 0,
+                max_concurrent_batch_updates: 0,
+                max_batch_total_size: 0,
+                digest_functions: Vec::new(),
+            }
+        },
+        &store_manager,
+    )?;
+
+    let response = cas_server
+        .batch_update_blobs(Request::new(BatchUpdateBlobsRequest {
+            instance_name: METRICS_INSTANCE_NAME.to_string(),
+            requests: vec![
+                batch_update_blobs_request::Request {
+                    digest: Some(Digest {
+                        hash: HASH1.to_string(),
+                        size_bytes: VALUE1.len() as i64,
+                    }),
+                    data: VALUE1.into(),
+                    compressor: compressor::Value::Identity.into(),
+                },
+                // The digest size does not match the data, so this blob fails.
+                batch_update_blobs_request::Request {
+                    digest: Some(Digest {
+                        hash: HASH2.to_string(),
+                        size_bytes: 100,
+                    }),
+                    data: VALUE2.into(),
+                    compressor: compressor::Value::Identity.into(),
+                },
+            ],
+            digest_function: digest_function::Value::Sha256.into(),
+        }))
+        .await?
+        .into_inner();
+    assert_eq!(response.responses.len(), 2);
+
+    let metrics = instance_metrics(METRICS_INSTANCE_NAME);
+    assert_eq!(metrics.cas.upload_requests.get(), 1);
+    assert_eq!(metrics.cas.bytes_received.get(), VALUE1.len() as u64);
+    Ok(())
+}
@@ -0,0 +1,178 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use maplit::hashmap;
+use nativelink_config::cas_server::{
+    AuthorizationAction, AuthorizationPolicy, DigestSubscriptionConfig,
+};
+use nativelink_config::stores::{MemorySpec, StoreSpec};
+use nativelink_error::Error;
+use nativelink_macro::nativelink_test;
+use nativelink_proto::build::bazel::remote::execution::v2::{digest_function, Digest};
+use nativelink_proto::com::github::trace_machina::nativelink::remote_execution::digest_subscription_server::DigestSubscription;
+use nativelink_proto::com::github::trace_machina::nativelink::remote_execution::WaitForBlobsRequest;
+use nativelink_service::digest_subscription_server::DigestSubscriptionServer;
+use nativelink_store::default_store_factory::store_factory;
+use nativelink_store::store_manager::StoreManager;
+use nativelink_util::authorization::{Authorizer, ORIGIN_AUTHORIZER};
+use nativelink_util::common::DigestInfo;
+use nativelink_util::origin_context::{ActiveOriginContext, ORIGIN_IDENTITY};
+use nativelink_util::store_trait::StoreLike;
+use pretty_assertions::assert_eq;
+use tonic::{Code, Request};
+use tracing::info_span;
+
+const INSTANCE_NAME: &str = "foo_instance_name";
+const HASH1: &str = "0123456789abcdef000000000000000000000000000000000123456789abcdef";
+const HASH2: &str = "9993456789abcdef000000000000000000000000000000000123456789abc999";
+const VALUE1: &str = "1";
+const VALUE2: &str = "23";
+
+async fn make_store_manager() -> Result<Arc<StoreManager>, Error> {
+    let store_manager = Arc::new(StoreManager::new());
+    store_manager.add_store(
+        "main_cas",
+        store_factory(
+            &StoreSpec::memory(MemorySpec::default()),
+            &store_manager,
+            None,
+        )
+        .await?,
+    );
+    Ok(store_manager)
+}
+
+fn make_digest_subscription_server(
+    store_manager: &StoreManager,
+) -> Result<DigestSubscriptionServer, Error> {
+    DigestSubscriptionServer::new(
+        &hashmap! {
+            INSTANCE_NAME.to_string() => DigestSubscriptionConfig {
+                cas_store: "main_cas".to_string(),
+                poll_interval_ms: 10,
+                max_timeout: 0,
+            }
+        },
+        store_manager,
+    )
+}
+
+fn make_request(digests: Vec<Digest>, timeout: Duration) -> Request<WaitForBlobsRequest> {
+    Request::new(WaitForBlobsRequest {
+        instance_name: INSTANCE_NAME.to_string(),
+        blob_digests: digests,
+        timeout: Some(timeout.try_into().unwrap()),
+        digest_function: digest_function::Value::Sha256.into(),
+    })
+}
+
+#[nativelink_test]
+async fn blobs_already_present_return_immediately() -> Result<(), Box<dyn std::error::Error>> {
+    let store_manager = make_store_manager().await?;
+    let server = make_digest_subscription_server(&store_manager)?;
+    let store = store_manager.get_store("main_cas").unwrap();
+    let digest1 = DigestInfo::try_new(HASH1, VALUE1.len())?;
+    store.update_oneshot(digest1, VALUE1.into()).await?;
+
+    let response = server
+        .wait_for_blobs(make_request(vec![digest1.into()], Duration::from_secs(60)))
+        .await?
+        .into_inner();
+    assert_eq!(response.missing_blob_digests, Vec::<Digest>::new());
+    Ok(())
+}
+
+#[nativelink_test]
+async fn waits_for_blob_uploaded_during_request() -> Result<(), Box<dyn std::error::Error>> {
+    let store_manager = make_store_manager().await?;
+    let server = make_digest_subscription_server(&store_manager)?;
+    let store = store_manager.get_store("main_cas").unwrap();
+    let digest1 = DigestInfo::try_new(HASH1, VALUE1.len())?;
+
+    let (response, upload_result) = tokio::join!(
+        server.wait_for_blobs(make_request(vec![digest1.into()], Duration::from_secs(60))),
+        async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            store.update_oneshot(digest1, VALUE1.into()).await
+        },
+    );
+    upload_result?;
+    assert_eq!(
+        response?.into_inner().missing_blob_digests,
+        Vec::<Digest>::new()
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn timeout_returns_missing_blobs() -> Result<(), Box<dyn std::error::Error>> {
+    let store_manager = make_store_manager().await?;
+    let server = make_digest_subscription_server(&store_manager)?;
+    let store = store_manager.get_store("main_cas").unwrap();
+    let digest1 = DigestInfo::try_new(HASH1, VALUE1.len())?;
+    let digest2 = DigestInfo::try_new(HASH2, VALUE2.len())?;
+    store.update_oneshot(digest1, VALUE1.into()).await?;
+
+    let response = server
+        .wait_for_blobs(make_request(
+            vec![digest1.into(), digest2.into()],
+            Duration::from_millis(50),
+        ))
+        .await?
+        .into_inner();
+    assert_eq!(response.missing_blob_digests, vec![digest2.into()]);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn follows_authorization_policies() -> Result<(), Box<dyn std::error::Error>> {
+    let store_manager = make_store_manager().await?;
+    let server = make_digest_subscription_server(&store_manager)?;
+    let authorizer = Arc::new(Authorizer::new(&[AuthorizationPolicy {
+        name: "everyone-reads".to_string(),
+        identities: vec!["*".to_string()],
+        actions: vec![AuthorizationAction::read],
+        ..Default::default()
+    }])?);
+    let as_identity = |identity: &str| -> Result<_, Error> {
+        let mut ctx = ActiveOriginContext::fork()?;
+        ctx.set_value(&ORIGIN_AUTHORIZER, authorizer.clone());
+        ctx.set_value(&ORIGIN_IDENTITY, Arc::new(identity.to_string()));
+        Ok(Arc::new(ctx))
+    };
+    let digest1 = DigestInfo::try_new(HASH1, VALUE1.len())?;
+
+    // Clients without identity are not matched by "*".
+    let err = as_identity("")?
+        .wrap_async(
+            info_span!("wait_for_blobs"),
+            server.wait_for_blobs(make_request(vec![digest1.into()], Duration::ZERO)),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::PermissionDenied);
+
+    let response = as_identity("alice")?
+        .wrap_async(
+            info_span!("wait_for_blobs"),
+            server.wait_for_blobs(make_request(vec![digest1.into()], Duration::ZERO)),
+        )
+        .await?
+        .into_inner();
+    assert_eq!(response.missing_blob_digests, vec![digest1.into()]);
+    Ok(())
+}
@@ -0,0 +1,202 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use futures::StreamExt;
+use nativelink_config::cas_server::ExecutionConfig;
+use nativelink_config::schedulers::SimpleSpec;
+use nativelink_config::stores::MemorySpec;
+use nativelink_error::Error;
+use nativelink_macro::nativelink_test;
+use nativelink_proto::build::bazel::remote::execution::v2::execution_server::Execution;
+use nativelink_proto::build::bazel::remote::execution::v2::{
+    digest_function, Action, Command, ExecuteRequest,
+};
+use nativelink_scheduler::default_scheduler_factory::memory_awaited_action_db_factory;
+use nativelink_scheduler::simple_scheduler::SimpleScheduler;
+use nativelink_service::execution_server::ExecutionServer;
+use nativelink_store::ac_utils::serialize_and_upload_message;
+use nativelink_store::memory_store::MemoryStore;
+use nativelink_store::store_manager::StoreManager;
+use nativelink_util::digest_hasher::DigestHasherFunc;
+use nativelink_util::operation_state_manager::{ClientStateManager, OperationFilter};
+use nativelink_util::store_trait::Store;
+use tokio::sync::Notify;
+use tonic::{Code, Request};
+
+const INSTANCE_NAME: &str = "main";
+const DEFAULT_ACTION_TIMEOUT: Duration = Duration::from_secs(60);
+const MAX_ACTION_TIMEOUT: Duration = Duration::from_secs(600);
+
+struct TestContext {
+    server: ExecutionServer,
+    scheduler: Arc<dyn ClientStateManager>,
+    cas_store: Store,
+}
+
+fn make_execution_config(
+    default_action_timeout: Duration,
+    max_action_timeout: Duration,
+) -> ExecutionConfig {
+    ExecutionConfig {
+        cas_store: "main_cas".to_string(),
+        scheduler: INSTANCE_NAME.to_string(),
+        default_action_timeout: default_action_timeout.as_secs() as usize,
+        max_action_timeout: max_action_timeout.as_secs() as usize,
+        queue_position_update_interval_s: 0,
+    }
+}
+
+fn make_test_context(config: ExecutionConfig) -> Result<TestContext, Error> {
+    let cas_store = Store::new(MemoryStore::new(&MemorySpec::default()));
+    let store_manager = StoreManager::new();
+    store_manager.add_store("main_cas", cas_store.clone());
+
+    let task_change_notify = Arc::new(Notify::new());
+    let (scheduler, _worker_scheduler) = SimpleScheduler::new(
+        &SimpleSpec::default(),
+        memory_awaited_action_db_factory(0, &task_change_notify, SystemTime::now),
+        task_change_notify.clone(),
+    );
+    let scheduler: Arc<dyn ClientStateManager> = scheduler;
+    let server = ExecutionServer::new(
+        &HashMap::from([(INSTANCE_NAME.to_string(), config)]),
+        &HashMap::from([(INSTANCE_NAME.to_string(), scheduler.clone())]),
+        &store_manager,
+    )?;
+    Ok(TestContext {
+        server,
+        scheduler,
+        cas_store,
+    })
+}
+
+/// Uploads an action with the given timeout and asks the server to execute it.
+async fn execute_action(
+    context: &TestContext,
+    timeout: Option<Duration>,
+) -> Result<(), tonic::Status> {
+    let mut hasher = DigestHasherFunc::Sha256.hasher();
+    let command_digest = serialize_and_upload_message(
+        &Command::default(),
+        Pin::new(&context.cas_store),
+        &mut hasher,
+    )
+    .await?;
+    let action = Action {
+        command_digest: Some(command_digest.into()),
+        input_root_digest: Some(command_digest.into()),
+        timeout: timeout.map(|timeout| prost_types::Duration {
+            seconds: timeout.as_secs() as i64,
+            nanos: 0,
+        }),
+        ..Default::default()
+    };
+    let mut hasher = DigestHasherFunc::Sha256.hasher();
+    let action_digest =
+        serialize_and_upload_message(&action, Pin::new(&context.cas_store), &mut hasher).await?;
+    context
+        .server
+        .execute(Request::new(ExecuteRequest {
+            instance_name: INSTANCE_NAME.to_string(),
+            skip_cache_lookup: true,
+            action_digest: Some(action_digest.into()),
+            digest_function: digest_function::Value::Sha256.into(),
+            ..Default::default()
+        }))
+        .await?;
+    Ok(())
+}
+
+/// Returns the timeout of the only action queued on the scheduler.
+async fn queued_action_timeout(context: &TestContext) -> Result<Duration, Error> {
+    let mut operations = context
+        .scheduler
+        .filter_operations(OperationFilter::default())
+        .await?;
+    let operation = operations.next().await.expect("No queued operation");
+    Ok(operation.as_action_info().await?.timeout)
+}
+
+#[nativelink_test]
+async fn default_action_timeout_applies_when_unset() -> Result<(), Box<dyn std::error::Error>> {
+    let context = make_test_context(make_execution_config(
+        DEFAULT_ACTION_TIMEOUT,
+        MAX_ACTION_TIMEOUT,
+    ))?;
+    execute_action(&context, None).await?;
+    assert_eq!(
+        queued_action_timeout(&context).await?,
+        DEFAULT_ACTION_TIMEOUT
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn max_action_timeout_applies_without_default() -> Result<(), Box<dyn std::error::Error>> {
+    let context = make_test_context(make_execution_config(Duration::ZERO, MAX_ACTION_TIMEOUT))?;
+    execute_action(&context, None).await?;
+    assert_eq!(queued_action_timeout(&context).await?, MAX_ACTION_TIMEOUT);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn requested_action_timeout_up_to_max_is_kept() -> Result<(), Box<dyn std::error::Error>> {
+    let context = make_test_context(make_execution_config(
+        DEFAULT_ACTION_TIMEOUT,
+        MAX_ACTION_TIMEOUT,
+    ))?;
+    execute_action(&context, Some(MAX_ACTION_TIMEOUT)).await?;
+    assert_eq!(queued_action_timeout(&context).await?, MAX_ACTION_TIMEOUT);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn requested_action_timeout_above_max_is_rejected() -> Result<(), Box<dyn std::error::Error>>
+{
+    let context = make_test_context(make_execution_config(
+        DEFAULT_ACTION_TIMEOUT,
+        MAX_ACTION_TIMEOUT,
+    ))?;
+    let status = execute_action(&context, Some(MAX_ACTION_TIMEOUT + Duration::from_secs(1)))
+        .await
+        .expect_err("Action timeout above the max should be rejected");
+    assert_eq!(status.code(), Code::InvalidArgument);
+    assert!(context
+        .scheduler
+        .filter_operations(OperationFilter::default())
+        .await?
+        .next()
+        .await
+        .is_none());
+    Ok(())
+}
+
+#[nativelink_test]
+async fn default_action_timeout_above_max_is_a_config_error(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let result = make_test_context(make_execution_config(
+        MAX_ACTION_TIMEOUT + Duration::from_secs(1),
+        MAX_ACTION_TIMEOUT,
+    ));
+    assert_eq!(
+        result.err().map(|err| err.code),
+        Some(nativelink_error::Code::InvalidArgument)
+    );
+    Ok(())
+}
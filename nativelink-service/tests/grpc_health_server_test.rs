@@ -0,0 +1,116 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use nativelink_config::stores::MemorySpec;
+use nativelink_macro::nativelink_test;
+use nativelink_service::grpc_health_server::GrpcHealthServer;
+use nativelink_store::memory_store::MemoryStore;
+use nativelink_util::fake_store_for_tests::FakeStore;
+use nativelink_util::health_utils::{HealthRegistryBuilder, HealthStatus, HealthStatusIndicator};
+use nativelink_util::store_trait::Store;
+use pretty_assertions::assert_eq;
+use tonic_health::pb::health_check_response::ServingStatus as ProtoServingStatus;
+use tonic_health::pb::health_client::HealthClient;
+use tonic_health::pb::HealthCheckRequest;
+use tonic_health::ServingStatus;
+
+#[derive(Default)]
+struct ToggleIndicator {
+    failed: AtomicBool,
+}
+
+#[async_trait]
+impl HealthStatusIndicator for ToggleIndicator {
+    fn get_name(&self) -> &'static str {
+        "ToggleIndicator"
+    }
+
+    async fn check_health(&self, _namespace: Cow<'static, str>) -> HealthStatus {
+        if self.failed.load(Ordering::Relaxed) {
+            HealthStatus::new_failed(self, "failed".into())
+        } else {
+            HealthStatus::new_ok(self, "ok".into())
+        }
+    }
+}
+
+#[nativelink_test]
+async fn serving_status_follows_health_indicators() -> Result<(), Box<dyn std::error::Error>> {
+    let indicator = Arc::new(ToggleIndicator::default());
+    let mut health_registry_builder = HealthRegistryBuilder::new("nativelink");
+    health_registry_builder
+        .sub_builder("stores/main")
+        .register_indicator(indicator.clone());
+    let (mut grpc_health_server, grpc_health_service) =
+        GrpcHealthServer::new(health_registry_builder.build()).await;
+    let mut client = HealthClient::new(grpc_health_service);
+    let request = || {
+        tonic::Request::new(HealthCheckRequest {
+            service: String::new(),
+        })
+    };
+
+    // Nothing was checked yet.
+    assert_eq!(
+        client.check(request()).await?.into_inner().status(),
+        ProtoServingStatus::NotServing
+    );
+
+    assert_eq!(
+        grpc_health_server.update_serving_status().await,
+        ServingStatus::Serving
+    );
+    assert_eq!(
+        client.check(request()).await?.into_inner().status(),
+        ProtoServingStatus::Serving
+    );
+
+    indicator.failed.store(true, Ordering::Relaxed);
+    assert_eq!(
+        grpc_health_server.update_serving_status().await,
+        ServingStatus::NotServing
+    );
+    assert_eq!(
+        client.check(request()).await?.into_inner().status(),
+        ProtoServingStatus::NotServing
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn stores_without_own_indicator_are_checked() -> Result<(), Box<dyn std::error::Error>> {
+    let fake_store = FakeStore::new(Store::new(MemoryStore::new(&MemorySpec::default())));
+    let mut health_registry_builder = HealthRegistryBuilder::new("nativelink");
+    Store::new(fake_store.clone())
+        .register_health(&mut health_registry_builder.sub_builder("stores/main"));
+    let (mut grpc_health_server, _grpc_health_service) =
+        GrpcHealthServer::new(health_registry_builder.build()).await;
+
+    assert_eq!(
+        grpc_health_server.update_serving_status().await,
+        ServingStatus::Serving
+    );
+
+    fake_store.set_failing(true);
+    assert_eq!(
+        grpc_health_server.update_serving_status().await,
+        ServingStatus::NotServing
+    );
+    Ok(())
+}
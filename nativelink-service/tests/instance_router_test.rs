@@ -0,0 +1,50 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use nativelink_error::{Code, Error};
+use nativelink_macro::nativelink_test;
+use nativelink_service::instance_router::InstanceRouter;
+use pretty_assertions::assert_eq;
+
+#[nativelink_test]
+async fn exact_names_win_over_longer_prefixes() -> Result<(), Error> {
+    let mut router = InstanceRouter::new();
+    router.insert("*", "default")?;
+    router.insert("ci/*", "ci")?;
+    router.insert("ci/linux/*", "ci_linux")?;
+    router.insert("ci/linux/main", "ci_linux_main")?;
+
+    assert_eq!(router.get("ci/linux/main"), Some(&"ci_linux_main"));
+    assert_eq!(router.get("ci/linux/pr"), Some(&"ci_linux"));
+    assert_eq!(router.get("ci/mac"), Some(&"ci"));
+    assert_eq!(router.get("release"), Some(&"default"));
+    assert_eq!(router.get(""), Some(&"default"));
+    Ok(())
+}
+
+#[nativelink_test]
+async fn unmatched_names_are_not_routed() -> Result<(), Error> {
+    let mut router = InstanceRouter::new();
+    router.insert("", "empty")?;
+    router.insert("ci/*", "ci")?;
+
+    assert_eq!(router.get(""), Some(&"empty"));
+    assert_eq!(router.get("ci"), None);
+    assert_eq!(router.get("release"), None);
+    assert_eq!(
+        router.insert("ci/*/linux", "ci_linux").unwrap_err().code,
+        Code::InvalidArgument
+    );
+    Ok(())
+}
@@ -0,0 +1,294 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use nativelink_config::cas_server::{
+    AuthorizationAction, AuthorizationPolicy, OperationAdminConfig,
+};
+use nativelink_config::schedulers::SimpleSpec;
+use nativelink_error::Error;
+use nativelink_macro::nativelink_test;
+use nativelink_proto::build::bazel::remote::execution::v2::execution_stage;
+use nativelink_proto::com::github::trace_machina::nativelink::remote_execution::operation_admin_server::OperationAdmin;
+use nativelink_proto::com::github::trace_machina::nativelink::remote_execution::{
+    CancelOperationRequest, GetOperationRequest, ListOperationsRequest, RequeueOperationRequest,
+};
+use nativelink_scheduler::default_scheduler_factory::memory_awaited_action_db_factory;
+use nativelink_scheduler::simple_scheduler::SimpleScheduler;
+use nativelink_service::operation_admin_server::OperationAdminServer;
+use nativelink_util::action_messages::{
+    ActionInfo, ActionUniqueKey, ActionUniqueQualifier, OperationId,
+};
+use nativelink_util::authorization::{Authorizer, ORIGIN_AUTHORIZER};
+use nativelink_util::common::DigestInfo;
+use nativelink_util::digest_hasher::DigestHasherFunc;
+use nativelink_util::operation_state_manager::ClientStateManager;
+use nativelink_util::origin_context::{ActiveOriginContext, ORIGIN_IDENTITY};
+use tokio::sync::Notify;
+use tonic::{Code, Request};
+use tracing::info_span;
+
+const SCHEDULER_NAME: &str = "main";
+
+fn make_action_info(hash: u8, insert_timestamp: SystemTime) -> Arc<ActionInfo> {
+    Arc::new(ActionInfo {
+        command_digest: DigestInfo::new([0u8; 32], 0),
+        input_root_digest: DigestInfo::new([0u8; 32], 0),
+        timeout: Duration::MAX,
+        platform_properties: HashMap::new(),
+        priority: 0,
+        load_timestamp: insert_timestamp,
+        insert_timestamp,
+        unique_qualifier: ActionUniqueQualifier::Cachable(ActionUniqueKey {
+            instance_name: SCHEDULER_NAME.to_string(),
+            digest_function: DigestHasherFunc::Sha256,
+            digest: DigestInfo::new([hash; 32], 512),
+        }),
+        tool_invocation_id: None,
+        client_identity: None,
+        queue_priority: None,
+    })
+}
+
+#[nativelink_test]
+async fn list_get_and_cancel_operations_test() -> Result<(), Box<dyn std::error::Error>> {
+    let task_change_notify = Arc::new(Notify::new());
+    let (scheduler, _worker_scheduler) = SimpleScheduler::new(
+        &SimpleSpec::default(),
+        memory_awaited_action_db_factory(0, &task_change_notify, SystemTime::now),
+        task_change_notify.clone(),
+    );
+    let mut schedulers: HashMap<String, Arc<dyn ClientStateManager>> = HashMap::new();
+    schedulers.insert(SCHEDULER_NAME.to_string(), scheduler.clone());
+    let server = OperationAdminServer::new(
+        &OperationAdminConfig {
+            max_listed_operations: 1,
+        },
+        &schedulers,
+    );
+
+    // No worker is connected, so both actions stay queued.
+    let now = SystemTime::now();
+    let _old_action_listener = scheduler
+        .add_action(
+            OperationId::default(),
+            make_action_info(1, now - Duration::from_secs(3600)),
+        )
+        .await?;
+    let _new_action_listener = scheduler
+        .add_action(OperationId::default(), make_action_info(2, now))
+        .await?;
+
+    assert!(server
+        .list_operations(Request::new(ListOperationsRequest {
+            instance_name: "unknown".to_string(),
+            ..Default::default()
+        }))
+        .await
+        .is_err());
+
+    // The oldest operation is listed first.
+    let response = server
+        .list_operations(Request::new(ListOperationsRequest {
+            instance_name: SCHEDULER_NAME.to_string(),
+            ..Default::default()
+        }))
+        .await?
+        .into_inner();
+    assert!(response.truncated);
+    assert_eq!(response.operations.len(), 1);
+    let old_operation = &response.operations[0];
+    assert_eq!(
+        old_operation.action_digest,
+        Some(DigestInfo::new([1u8; 32], 512).into())
+    );
+    assert_eq!(old_operation.stage(), execution_stage::Value::Queued);
+
+    let response = server
+        .list_operations(Request::new(ListOperationsRequest {
+            instance_name: SCHEDULER_NAME.to_string(),
+            min_age: Some(prost_types::Duration {
+                seconds: 60,
+                nanos: 0,
+            }),
+            ..Default::default()
+        }))
+        .await?
+        .into_inner();
+    assert!(!response.truncated);
+    assert_eq!(response.operations.len(), 1);
+    assert_eq!(
+        response.operations[0].operation_id,
+        old_operation.operation_id
+    );
+
+    let response = server
+        .list_operations(Request::new(ListOperationsRequest {
+            instance_name: SCHEDULER_NAME.to_string(),
+            stages: vec![execution_stage::Value::Executing.into()],
+            ..Default::default()
+        }))
+        .await?
+        .into_inner();
+    assert!(response.operations.is_empty());
+
+    let operation_info = server
+        .get_operation(Request::new(GetOperationRequest {
+            instance_name: SCHEDULER_NAME.to_string(),
+            operation_id: old_operation.operation_id.clone(),
+        }))
+        .await?
+        .into_inner();
+    assert_eq!(operation_info.stage_history.len(), 1);
+    assert_eq!(
+        operation_info.stage_history[0].stage(),
+        execution_stage::Value::Queued
+    );
+
+    server
+        .cancel_operation(Request::new(CancelOperationRequest {
+            instance_name: SCHEDULER_NAME.to_string(),
+            operation_id: old_operation.operation_id.clone(),
+        }))
+        .await?;
+    let operation_info = server
+        .get_operation(Request::new(GetOperationRequest {
+            instance_name: SCHEDULER_NAME.to_string(),
+            operation_id: old_operation.operation_id.clone(),
+        }))
+        .await?
+        .into_inner();
+    assert_eq!(operation_info.stage(), execution_stage::Value::Completed);
+    let err = server
+        .cancel_operation(Request::new(CancelOperationRequest {
+            instance_name: SCHEDULER_NAME.to_string(),
+            operation_id: old_operation.operation_id.clone(),
+        }))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::FailedPrecondition);
+
+    // Only the unfinished operation is left.
+    let response = server
+        .list_operations(Request::new(ListOperationsRequest {
+            instance_name: SCHEDULER_NAME.to_string(),
+            ..Default::default()
+        }))
+        .await?
+        .into_inner();
+    assert!(!response.truncated);
+    assert_eq!(response.operations.len(), 1);
+    assert_eq!(
+        response.operations[0].action_digest,
+        Some(DigestInfo::new([2u8; 32], 512).into())
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn cancel_operation_requires_admin_test() -> Result<(), Box<dyn std::error::Error>> {
+    let task_change_notify = Arc::new(Notify::new());
+    let (scheduler, _worker_scheduler) = SimpleScheduler::new(
+        &SimpleSpec::default(),
+        memory_awaited_action_db_factory(0, &task_change_notify, SystemTime::now),
+        task_change_notify.clone(),
+    );
+    let mut schedulers: HashMap<String, Arc<dyn ClientStateManager>> = HashMap::new();
+    schedulers.insert(SCHEDULER_NAME.to_string(), scheduler.clone());
+    let server = OperationAdminServer::new(&OperationAdminConfig::default(), &schedulers);
+    let authorizer = Arc::new(Authorizer::new(&[
+        AuthorizationPolicy {
+            name: "operators".to_string(),
+            identities: vec!["ops".to_string()],
+            actions: vec![AuthorizationAction::read, AuthorizationAction::admin],
+            ..Default::default()
+        },
+        AuthorizationPolicy {
+            name: "everyone-reads".to_string(),
+            identities: vec!["*".to_string()],
+            actions: vec![AuthorizationAction::read],
+            ..Default::default()
+        },
+    ])?);
+    let as_identity = |identity: &str| -> Result<_, Error> {
+        let mut ctx = ActiveOriginContext::fork()?;
+        ctx.set_value(&ORIGIN_AUTHORIZER, authorizer.clone());
+        ctx.set_value(&ORIGIN_IDENTITY, Arc::new(identity.to_string()));
+        Ok(Arc::new(ctx))
+    };
+
+    let _action_listener = scheduler
+        .add_action(
+            OperationId::default(),
+            make_action_info(1, SystemTime::now()),
+        )
+        .await?;
+    let response = as_identity("alice")?
+        .wrap_async(
+            info_span!("list"),
+            server.list_operations(Request::new(ListOperationsRequest {
+                instance_name: SCHEDULER_NAME.to_string(),
+                ..Default::default()
+            })),
+        )
+        .await?
+        .into_inner();
+    assert_eq!(response.operations.len(), 1);
+    let operation_id = response.operations[0].operation_id.clone();
+
+    let err = as_identity("alice")?
+        .wrap_async(
+            info_span!("cancel"),
+            server.cancel_operation(Request::new(CancelOperationRequest {
+                instance_name: SCHEDULER_NAME.to_string(),
+                operation_id: operation_id.clone(),
+            })),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::PermissionDenied);
+    let err = as_identity("alice")?
+        .wrap_async(
+            info_span!("requeue"),
+            server.requeue_operation(Request::new(RequeueOperationRequest {
+                instance_name: SCHEDULER_NAME.to_string(),
+                operation_id: operation_id.clone(),
+            })),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::PermissionDenied);
+
+    as_identity("ops")?
+        .wrap_async(
+            info_span!("cancel"),
+            server.cancel_operation(Request::new(CancelOperationRequest {
+                instance_name: SCHEDULER_NAME.to_string(),
+                operation_id: operation_id.clone(),
+            })),
+        )
+        .await?;
+    let operation_info = server
+        .get_operation(Request::new(GetOperationRequest {
+            instance_name: SCHEDULER_NAME.to_string(),
+            operation_id,
+        }))
+        .await?
+        .into_inner();
+    assert_eq!(operation_info.stage(), execution_stage::Value::Completed);
+    Ok(())
+}
@@ -0,0 +1,54 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use nativelink_config::cas_server::ReflectionConfig;
+use nativelink_macro::nativelink_test;
+use nativelink_service::reflection_server::ReflectionServer;
+use tonic_reflection::pb::v1::server_reflection_client::ServerReflectionClient;
+use tonic_reflection::pb::v1::server_reflection_request::MessageRequest;
+use tonic_reflection::pb::v1::server_reflection_response::MessageResponse;
+use tonic_reflection::pb::v1::ServerReflectionRequest;
+
+#[nativelink_test]
+async fn lists_nativelink_and_health_services() -> Result<(), Box<dyn std::error::Error>> {
+    let service = ReflectionServer::new(&ReflectionConfig::default()).into_service()?;
+    let mut client = ServerReflectionClient::new(service);
+
+    let request = ServerReflectionRequest {
+        host: String::new(),
+        message_request: Some(MessageRequest::ListServices(String::new())),
+    };
+    let mut responses = client
+        .server_reflection_info(tokio_stream::iter(vec![request]))
+        .await?
+        .into_inner();
+    let response = responses.message().await?.ok_or("No response")?;
+
+    let Some(MessageResponse::ListServicesResponse(list)) = response.message_response else {
+        return Err(format!("Unexpected response: {response:?}").into());
+    };
+    let names: Vec<String> = list.service.into_iter().map(|s| s.name).collect();
+    for expected in [
+        "build.bazel.remote.execution.v2.ContentAddressableStorage",
+        "google.bytestream.ByteStream",
+        "grpc.health.v1.Health",
+        "grpc.reflection.v1.ServerReflection",
+    ] {
+        assert!(
+            names.iter().any(|name| name == expected),
+            "{expected} missing in {names:?}"
+        );
+    }
+    Ok(())
+}
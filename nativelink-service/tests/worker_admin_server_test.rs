@@ -0,0 +1,207 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use nativelink_config::cas_server::{
+    AuthorizationAction, AuthorizationPolicy, WorkerAdminConfig,
+};
+use nativelink_config::schedulers::WorkerAllocationStrategy;
+use nativelink_error::Error;
+use nativelink_macro::nativelink_test;
+use nativelink_metric::MetricsComponent;
+use nativelink_proto::com::github::trace_machina::nativelink::remote_execution::worker_admin_server::WorkerAdmin;
+use nativelink_proto::com::github::trace_machina::nativelink::remote_execution::{
+    update_for_worker, CordonWorkerRequest, DrainWorkerRequest,
+};
+use nativelink_scheduler::api_worker_scheduler::ApiWorkerScheduler;
+use nativelink_scheduler::platform_property_manager::PlatformPropertyManager;
+use nativelink_scheduler::worker::Worker;
+use nativelink_scheduler::worker_scheduler::WorkerScheduler;
+use nativelink_service::worker_admin_server::WorkerAdminServer;
+use nativelink_util::action_messages::{OperationId, WorkerId};
+use nativelink_util::authorization::{Authorizer, ORIGIN_AUTHORIZER};
+use nativelink_util::operation_state_manager::{UpdateOperationType, WorkerStateManager};
+use nativelink_util::origin_context::{ActiveOriginContext, ORIGIN_IDENTITY};
+use nativelink_util::platform_properties::PlatformProperties;
+use tokio::sync::{mpsc, Notify};
+use tonic::{Code, Request};
+use tracing::info_span;
+use uuid::Uuid;
+
+const SCHEDULER_NAME: &str = "main";
+
+#[derive(MetricsComponent)]
+struct NoopWorkerStateManager {}
+
+#[async_trait]
+impl WorkerStateManager for NoopWorkerStateManager {
+    async fn update_operation(
+        &self,
+        _operation_id: &OperationId,
+        _worker_id: &WorkerId,
+        _update: UpdateOperationType,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn cancel_if_abandoned(
+        &self,
+        _operation_id: &OperationId,
+        _worker_id: &WorkerId,
+        _grace_period: Duration,
+    ) -> Result<bool, Error> {
+        Ok(false)
+    }
+}
+
+#[nativelink_test]
+async fn cordon_and_drain_worker_test() -> Result<(), Box<dyn std::error::Error>> {
+    let scheduler = ApiWorkerScheduler::new(
+        Arc::new(NoopWorkerStateManager {}),
+        Arc::new(PlatformPropertyManager::new(HashMap::new())),
+        WorkerAllocationStrategy::default(),
+        Arc::new(Notify::new()),
+        100,
+        60,
+        None,
+        None,
+        None,
+        None,
+        None,
+    );
+    let mut schedulers: HashMap<String, Arc<dyn WorkerScheduler>> = HashMap::new();
+    schedulers.insert(SCHEDULER_NAME.to_string(), scheduler.clone());
+    let server = WorkerAdminServer::new(&WorkerAdminConfig::default(), &schedulers);
+
+    let worker_id = WorkerId(Uuid::new_v4());
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    scheduler
+        .add_worker(Worker::new(worker_id, PlatformProperties::default(), tx, 0))
+        .await?;
+
+    assert!(server
+        .cordon_worker(Request::new(CordonWorkerRequest {
+            instance_name: "unknown".to_string(),
+            worker_id: worker_id.to_string(),
+            cordoned: true,
+        }))
+        .await
+        .is_err());
+
+    server
+        .cordon_worker(Request::new(CordonWorkerRequest {
+            instance_name: SCHEDULER_NAME.to_string(),
+            worker_id: worker_id.to_string(),
+            cordoned: true,
+        }))
+        .await?;
+    assert!(scheduler.contains_worker_for_test(&worker_id).await);
+
+    // The worker runs no actions, so it is removed right away.
+    server
+        .drain_worker(Request::new(DrainWorkerRequest {
+            instance_name: SCHEDULER_NAME.to_string(),
+            worker_id: worker_id.to_string(),
+            timeout: None,
+        }))
+        .await?;
+    assert!(!scheduler.contains_worker_for_test(&worker_id).await);
+    let mut updates = Vec::new();
+    while let Ok(update) = rx.try_recv() {
+        updates.push(update.update);
+    }
+    assert!(updates.contains(&Some(update_for_worker::Update::Disconnect(()))));
+    Ok(())
+}
+
+#[nativelink_test]
+async fn cordon_and_drain_worker_require_admin_test() -> Result<(), Box<dyn std::error::Error>> {
+    let scheduler = ApiWorkerScheduler::new(
+        Arc::new(NoopWorkerStateManager {}),
+        Arc::new(PlatformPropertyManager::new(HashMap::new())),
+        WorkerAllocationStrategy::default(),
+        Arc::new(Notify::new()),
+        100,
+        60,
+        None,
+        None,
+        None,
+        None,
+        None,
+    );
+    let mut schedulers: HashMap<String, Arc<dyn WorkerScheduler>> = HashMap::new();
+    schedulers.insert(SCHEDULER_NAME.to_string(), scheduler.clone());
+    let server = WorkerAdminServer::new(&WorkerAdminConfig::default(), &schedulers);
+    let authorizer = Arc::new(Authorizer::new(&[AuthorizationPolicy {
+        name: "operators".to_string(),
+        identities: vec!["ops".to_string()],
+        actions: vec![AuthorizationAction::admin],
+        ..Default::default()
+    }])?);
+    let as_identity = |identity: &str| -> Result<_, Error> {
+        let mut ctx = ActiveOriginContext::fork()?;
+        ctx.set_value(&ORIGIN_AUTHORIZER, authorizer.clone());
+        ctx.set_value(&ORIGIN_IDENTITY, Arc::new(identity.to_string()));
+        Ok(Arc::new(ctx))
+    };
+
+    let worker_id = WorkerId(Uuid::new_v4());
+    let (tx, _rx) = mpsc::unbounded_channel();
+    scheduler
+        .add_worker(Worker::new(worker_id, PlatformProperties::default(), tx, 0))
+        .await?;
+
+    let err = as_identity("alice")?
+        .wrap_async(
+            info_span!("cordon"),
+            server.cordon_worker(Request::new(CordonWorkerRequest {
+                instance_name: SCHEDULER_NAME.to_string(),
+                worker_id: worker_id.to_string(),
+                cordoned: true,
+            })),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::PermissionDenied);
+    let err = as_identity("alice")?
+        .wrap_async(
+            info_span!("drain"),
+            server.drain_worker(Request::new(DrainWorkerRequest {
+                instance_name: SCHEDULER_NAME.to_string(),
+                worker_id: worker_id.to_string(),
+                timeout: None,
+            })),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::PermissionDenied);
+    assert!(scheduler.contains_worker_for_test(&worker_id).await);
+
+    as_identity("ops")?
+        .wrap_async(
+            info_span!("drain"),
+            server.drain_worker(Request::new(DrainWorkerRequest {
+                instance_name: SCHEDULER_NAME.to_string(),
+                worker_id: worker_id.to_string(),
+                timeout: None,
+            })),
+        )
+        .await?;
+    assert!(!scheduler.contains_worker_for_test(&worker_id).await);
+    Ok(())
+}
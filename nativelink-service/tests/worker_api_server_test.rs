@@ -19,9 +19,11 @@ use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use async_lock::Mutex as AsyncMutex;
 use async_trait::async_trait;
 use bytes::Bytes;
-use nativelink_config::cas_server::WorkerApiConfig;
+use futures::FutureExt;
+use nativelink_config::cas_server::{ActionResultUploadConfig, WorkerApiConfig};
 use nativelink_config::schedulers::WorkerAllocationStrategy;
-use nativelink_error::{Error, ResultExt};
+use nativelink_config::stores::{MemorySpec, StoreSpec};
+use nativelink_error::{Code, Error, ResultExt};
 use nativelink_macro::nativelink_test;
 use nativelink_metric::MetricsComponent;
 use nativelink_proto::build::bazel::remote::execution::v2::{
@@ -30,7 +32,8 @@ use nativelink_proto::build::bazel::remote::execution::v2::{
 };
 use nativelink_proto::com::github::trace_machina::nativelink::remote_execution::worker_api_server::WorkerApi;
 use nativelink_proto::com::github::trace_machina::nativelink::remote_execution::{
-    execute_result, update_for_worker, ExecuteResult, KeepAliveRequest, SupportedProperties,
+    execute_result, update_for_worker, ExecuteResult, KeepAliveRequest, KillOperationRequest,
+    SupportedProperties,
 };
 use nativelink_proto::google::rpc::Status as ProtoStatus;
 use nativelink_scheduler::api_worker_scheduler::ApiWorkerScheduler;
@@ -38,13 +41,18 @@ use nativelink_scheduler::platform_property_manager::PlatformPropertyManager;
 use nativelink_scheduler::worker::ActionInfoWithProps;
 use nativelink_scheduler::worker_scheduler::WorkerScheduler;
 use nativelink_service::worker_api_server::{ConnectWorkerStream, NowFn, WorkerApiServer};
+use nativelink_store::default_store_factory::store_factory;
+use nativelink_store::store_manager::StoreManager;
 use nativelink_util::action_messages::{
-    ActionInfo, ActionUniqueKey, ActionUniqueQualifier, OperationId, WorkerId,
+    ActionInfo, ActionStage, ActionUniqueKey, ActionUniqueQualifier, ExecutionMetadata,
+    OperationId, WorkerId,
 };
 use nativelink_util::common::DigestInfo;
 use nativelink_util::digest_hasher::DigestHasherFunc;
 use nativelink_util::operation_state_manager::{UpdateOperationType, WorkerStateManager};
+use nativelink_util::store_trait::StoreLike;
 use pretty_assertions::assert_eq;
+use prost::Message;
 use tokio::join;
 use tokio::sync::{mpsc, Notify};
 use tokio_stream::StreamExt;
@@ -52,6 +60,7 @@ use tonic::Request;
 
 const BASE_NOW_S: u64 = 10;
 const BASE_WORKER_TIMEOUT_S: u64 = 100;
+const BASE_ACTION_TIMEOUT_GRACE_S: u64 = 60;
 
 #[derive(Debug)]
 enum WorkerStateManagerCalls {
@@ -122,6 +131,15 @@ impl WorkerStateManager for MockWorkerStateManager {
             WorkerStateManagerReturns::UpdateOperation(result) => result,
         }
     }
+
+    async fn cancel_if_abandoned(
+        &self,
+        _operation_id: &OperationId,
+        _worker_id: &WorkerId,
+        _grace_period: Duration,
+    ) -> Result<bool, Error> {
+        Ok(false)
+    }
 }
 
 struct TestContext {
@@ -138,6 +156,15 @@ fn static_now_fn() -> Result<Duration, Error> {
 }
 
 async fn setup_api_server(worker_timeout: u64, now_fn: NowFn) -> Result<TestContext, Error> {
+    setup_api_server_with_stores(worker_timeout, now_fn, None, &StoreManager::new()).await
+}
+
+async fn setup_api_server_with_stores(
+    worker_timeout: u64,
+    now_fn: NowFn,
+    action_result_upload: Option<ActionResultUploadConfig>,
+    store_manager: &StoreManager,
+) -> Result<TestContext, Error> {
     const SCHEDULER_NAME: &str = "DUMMY_SCHEDULE_NAME";
 
     const UUID_SIZE: usize = 36;
@@ -151,6 +178,12 @@ async fn setup_api_server(worker_timeout: u64, now_fn: NowFn) -> Result<TestCont
         WorkerAllocationStrategy::default(),
         tasks_or_worker_change_notify,
         worker_timeout,
+        BASE_ACTION_TIMEOUT_GRACE_S,
+        None,
+        None,
+        None,
+        None,
+        None,
     );
 
     let mut schedulers: HashMap<String, Arc<dyn WorkerScheduler>> = HashMap::new();
@@ -158,8 +191,10 @@ async fn setup_api_server(worker_timeout: u64, now_fn: NowFn) -> Result<TestCont
     let worker_api_server = WorkerApiServer::new_with_now_fn(
         &WorkerApiConfig {
             scheduler: SCHEDULER_NAME.to_string(),
+            action_result_upload,
         },
         &schedulers,
+        store_manager,
         now_fn,
     )
     .err_tip(|| "Error creating WorkerApiServer")?;
@@ -393,6 +428,9 @@ pub async fn execution_response_success_test() -> Result<(), Box<dyn std::error:
         load_timestamp: make_system_time(0),
         insert_timestamp: make_system_time(0),
         unique_qualifier,
+        tool_invocation_id: None,
+        client_identity: None,
+        queue_priority: None,
     });
     let expected_operation_id = OperationId::default();
 
@@ -410,6 +448,7 @@ pub async fn execution_response_success_test() -> Result<(), Box<dyn std::error:
             ActionInfoWithProps {
                 inner: action_info,
                 platform_properties,
+                expected_execution_duration: None,
             },
         )
         .await
@@ -526,3 +565,294 @@ pub async fn execution_response_success_test() -> Result<(), Box<dyn std::error:
     }
     Ok(())
 }
+
+/// Runs an action with `timeout` on the worker of `test_context` and
+/// returns its operation id.
+async fn run_action_with_timeout(
+    test_context: &mut TestContext,
+    timeout: Duration,
+) -> Result<OperationId, Box<dyn std::error::Error>> {
+    let action_info = Arc::new(ActionInfo {
+        command_digest: DigestInfo::new([0u8; 32], 0),
+        input_root_digest: DigestInfo::new([0u8; 32], 0),
+        timeout,
+        platform_properties: HashMap::new(),
+        priority: 0,
+        load_timestamp: make_system_time(0),
+        insert_timestamp: make_system_time(0),
+        unique_qualifier: ActionUniqueQualifier::Uncachable(ActionUniqueKey {
+            instance_name: "instance_name".to_string(),
+            digest_function: DigestHasherFunc::Sha256,
+            digest: DigestInfo::new([7u8; 32], 123),
+        }),
+        tool_invocation_id: None,
+        client_identity: None,
+        queue_priority: None,
+    });
+    let operation_id = OperationId::default();
+    let platform_properties = test_context
+        .scheduler
+        .get_platform_property_manager()
+        .make_platform_properties(action_info.platform_properties.clone())?;
+    test_context
+        .scheduler
+        .worker_notify_run_action(
+            test_context.worker_id,
+            operation_id.clone(),
+            ActionInfoWithProps {
+                inner: action_info,
+                platform_properties,
+                expected_execution_duration: None,
+            },
+        )
+        .await?;
+    let update_for_worker = test_context
+        .connection_worker_stream
+        .next()
+        .await
+        .expect("Worker stream ended early")?
+        .update;
+    assert!(matches!(
+        update_for_worker,
+        Some(update_for_worker::Update::StartAction(_))
+    ));
+    Ok(operation_id)
+}
+
+#[nativelink_test]
+pub async fn kills_actions_running_past_timeout_test() -> Result<(), Box<dyn std::error::Error>> {
+    const ACTION_TIMEOUT_S: u64 = 10;
+    let mut test_context = setup_api_server(BASE_WORKER_TIMEOUT_S, Box::new(static_now_fn)).await?;
+    let operation_id =
+        run_action_with_timeout(&mut test_context, Duration::from_secs(ACTION_TIMEOUT_S)).await?;
+
+    // The first check after the action started starts its deadline.
+    test_context
+        .scheduler
+        .kill_timedout_actions(BASE_NOW_S)
+        .await?;
+    // Within the grace period the worker is expected to kill the action.
+    test_context
+        .scheduler
+        .kill_timedout_actions(BASE_NOW_S + ACTION_TIMEOUT_S + BASE_ACTION_TIMEOUT_GRACE_S)
+        .await?;
+
+    let (kill_result, (updated_operation_id, worker_id, update)) = join!(
+        test_context
+            .scheduler
+            .kill_timedout_actions(BASE_NOW_S + ACTION_TIMEOUT_S + BASE_ACTION_TIMEOUT_GRACE_S + 1),
+        test_context.state_manager.expect_update_operation(Ok(())),
+    );
+    kill_result?;
+    assert_eq!(updated_operation_id, operation_id);
+    assert_eq!(worker_id, test_context.worker_id);
+    let UpdateOperationType::UpdateWithActionStage(ActionStage::Completed(action_result)) = update
+    else {
+        panic!("Expected the operation to be completed, got {update:?}");
+    };
+    assert_eq!(
+        action_result.error.map(|err| err.code),
+        Some(Code::DeadlineExceeded)
+    );
+
+    let update_for_worker = test_context
+        .connection_worker_stream
+        .next()
+        .await
+        .expect("Worker stream ended early")?
+        .update;
+    assert_eq!(
+        update_for_worker,
+        Some(update_for_worker::Update::KillOperationRequest(
+            KillOperationRequest {
+                operation_id: operation_id.to_string(),
+            }
+        ))
+    );
+
+    // The late result of the worker only frees the action on the worker,
+    // the operation was already completed.
+    test_context
+        .worker_api_server
+        .execution_response(Request::new(ExecuteResult {
+            instance_name: "instance_name".to_string(),
+            worker_id: test_context.worker_id.to_string(),
+            operation_id: operation_id.to_string(),
+            result: Some(execute_result::Result::InternalError(ProtoStatus {
+                code: 4,
+                message: "Killed".to_string(),
+                details: Vec::default(),
+            })),
+        }))
+        .await?;
+    assert!(test_context
+        .scheduler
+        .get_running_action_info(&test_context.worker_id, &operation_id)
+        .await
+        .is_err());
+    assert!(
+        test_context
+            .scheduler
+            .contains_worker_for_test(&test_context.worker_id)
+            .await
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+pub async fn does_not_kill_actions_with_zero_timeout_test() -> Result<(), Box<dyn std::error::Error>>
+{
+    let mut test_context = setup_api_server(BASE_WORKER_TIMEOUT_S, Box::new(static_now_fn)).await?;
+    // The worker runs actions without a timeout up to its own maximum
+    // timeout, so the scheduler must not count it as "now".
+    let operation_id = run_action_with_timeout(&mut test_context, Duration::ZERO).await?;
+
+    test_context
+        .scheduler
+        .kill_timedout_actions(BASE_NOW_S)
+        .await?;
+    test_context
+        .scheduler
+        .kill_timedout_actions(BASE_NOW_S + BASE_ACTION_TIMEOUT_GRACE_S + 1)
+        .await?;
+
+    assert!(test_context
+        .scheduler
+        .get_running_action_info(&test_context.worker_id, &operation_id)
+        .await
+        .is_ok());
+    // No KillOperationRequest was sent to the worker.
+    assert!(test_context
+        .connection_worker_stream
+        .next()
+        .now_or_never()
+        .is_none());
+    Ok(())
+}
+
+#[nativelink_test]
+pub async fn execution_response_caches_only_validated_results_test(
+) -> Result<(), Box<dyn std::error::Error>> {
+    const STDOUT_DATA: &str = "stdout data";
+
+    let store_manager = StoreManager::new();
+    for store_name in ["main_cas", "main_ac"] {
+        let store = store_factory(
+            &StoreSpec::memory(MemorySpec::default()),
+            &Arc::new(StoreManager::new()),
+            None,
+        )
+        .await?;
+        store_manager.add_store(store_name, store);
+    }
+    let cas_store = store_manager.get_store("main_cas").unwrap();
+    let ac_store = store_manager.get_store("main_ac").unwrap();
+    let mut test_context = setup_api_server_with_stores(
+        BASE_WORKER_TIMEOUT_S,
+        Box::new(static_now_fn),
+        Some(ActionResultUploadConfig {
+            ac_store: "main_ac".to_string(),
+            cas_store: "main_cas".to_string(),
+        }),
+        &store_manager,
+    )
+    .await?;
+
+    let stdout_digest = DigestInfo::new([3u8; 32], STDOUT_DATA.len() as u64);
+    cas_store
+        .update_oneshot(stdout_digest, STDOUT_DATA.into())
+        .await?;
+    let missing_digest = DigestInfo::new([8u8; 32], 124);
+
+    for (action_digest, output_digests, expect_cached) in [
+        // References a blob that is not in the CAS, so it must not be cached.
+        (DigestInfo::new([1u8; 32], 10), vec![missing_digest], false),
+        // Every output exists in the CAS, so it is cached.
+        (DigestInfo::new([2u8; 32], 10), vec![], true),
+    ] {
+        let action_info = Arc::new(ActionInfo {
+            command_digest: DigestInfo::new([0u8; 32], 0),
+            input_root_digest: DigestInfo::new([0u8; 32], 0),
+            timeout: Duration::MAX,
+            platform_properties: HashMap::new(),
+            priority: 0,
+            load_timestamp: make_system_time(0),
+            insert_timestamp: make_system_time(0),
+            unique_qualifier: ActionUniqueQualifier::Cachable(ActionUniqueKey {
+                instance_name: "instance_name".to_string(),
+                digest_function: DigestHasherFunc::Sha256,
+                digest: action_digest,
+            }),
+            tool_invocation_id: None,
+            client_identity: None,
+            queue_priority: None,
+        });
+        let operation_id = OperationId::default();
+        let platform_properties = test_context
+            .scheduler
+            .get_platform_property_manager()
+            .make_platform_properties(action_info.platform_properties.clone())?;
+        test_context
+            .scheduler
+            .worker_notify_run_action(
+                test_context.worker_id,
+                operation_id.clone(),
+                ActionInfoWithProps {
+                    inner: action_info,
+                    platform_properties,
+                    expected_execution_duration: None,
+                },
+            )
+            .await?;
+        let update_for_worker = test_context
+            .connection_worker_stream
+            .next()
+            .await
+            .expect("Worker stream ended early")?
+            .update
+            .expect("Expected update field to be populated");
+        let update_for_worker::Update::StartAction(_) = update_for_worker else {
+            panic!("Expected StartAction message");
+        };
+
+        let action_result = ProtoActionResult {
+            output_files: output_digests
+                .into_iter()
+                .map(|digest| OutputFile {
+                    path: "some path".to_string(),
+                    digest: Some(digest.into()),
+                    ..Default::default()
+                })
+                .collect(),
+            stdout_digest: Some(stdout_digest.into()),
+            stderr_digest: Some(stdout_digest.into()),
+            exit_code: 0,
+            execution_metadata: Some(ExecutionMetadata::default().into()),
+            ..Default::default()
+        };
+        let result = ExecuteResult {
+            instance_name: "instance_name".to_string(),
+            worker_id: test_context.worker_id.to_string(),
+            operation_id: operation_id.to_string(),
+            result: Some(execute_result::Result::ExecuteResponse(ExecuteResponse {
+                result: Some(action_result.clone()),
+                ..Default::default()
+            })),
+        };
+        let (execution_response_result, _) = join!(
+            test_context
+                .worker_api_server
+                .execution_response(Request::new(result)),
+            test_context.state_manager.expect_update_operation(Ok(())),
+        );
+        execution_response_result?;
+
+        let cached_result = ac_store.has(action_digest).await?;
+        assert_eq!(cached_result.is_some(), expect_cached);
+        if expect_cached {
+            let data = ac_store.get_part_unchunked(action_digest, 0, None).await?;
+            assert_eq!(ProtoActionResult::decode(data)?, action_result);
+        }
+    }
+    Ok(())
+}
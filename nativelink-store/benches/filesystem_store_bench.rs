@@ -0,0 +1,82 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use std::env;
+use std::sync::Arc;
+
+#[path = "../../nativelink-util/benches/common/mod.rs"]
+mod common;
+
+use bytes::Bytes;
+use common::{block_on, make_runtime};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use nativelink_config::stores::{EvictionPolicy, FilesystemSpec};
+use nativelink_store::filesystem_store::{FileEntryImpl, FilesystemStore};
+use nativelink_util::common::DigestInfo;
+use nativelink_util::store_trait::StoreLike;
+
+/// Blob sizes below `max_direct_write_size`, which take the single write
+/// path.
+const BLOB_SIZES: [usize; 3] = [128, 4 * 1024, 64 * 1024];
+
+/// Keeps the number of files the benchmark leaves on disk bounded.
+const MAX_COUNT: u64 = 10_000;
+
+fn digest(index: u64, size: usize) -> DigestInfo {
+    let mut hash = [0u8; 32];
+    hash[..8].copy_from_slice(&index.to_le_bytes());
+    DigestInfo::new(hash, size as u64)
+}
+
+fn filesystem_store_benchmark(c: &mut Criterion) {
+    let runtime = make_runtime(None);
+    let root = env::temp_dir().join(format!("filesystem_store_bench_{}", std::process::id()));
+    let mut group = c.benchmark_group("filesystem_store");
+    for size in BLOB_SIZES {
+        let path = root.join(size.to_string());
+        let spec = FilesystemSpec {
+            content_path: path.join("content").to_string_lossy().into_owned(),
+            temp_path: path.join("temp").to_string_lossy().into_owned(),
+            eviction_policy: Some(EvictionPolicy {
+                max_count: MAX_COUNT,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let store = block_on(
+            &runtime,
+            "filesystem_store_bench",
+            FilesystemStore::<FileEntryImpl>::new(&spec),
+        )
+        .unwrap();
+        let data = Bytes::from(vec![0x5a_u8; size]);
+        let mut index = 0;
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("update", size), &data, |b, data| {
+            b.iter(|| {
+                index += 1;
+                block_on(
+                    &runtime,
+                    "filesystem_store_bench",
+                    store.update_oneshot(digest(index, size), data.clone()),
+                )
+                .unwrap();
+            });
+        });
+    }
+    group.finish();
+    let _ = std::fs::remove_dir_all(root);
+}
+
+criterion_group!(benches, filesystem_store_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,107 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use std::sync::Arc;
+
+#[path = "../../nativelink-util/benches/common/mod.rs"]
+mod common;
+
+use bytes::Bytes;
+use common::{block_on, make_runtime};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use fred::error::{Error as RedisError, ErrorKind as RedisErrorKind};
+use fred::mocks::{MockCommand, Mocks};
+use fred::prelude::Builder;
+use fred::types::config::Config as RedisConfig;
+use fred::types::Value as RedisValue;
+use nativelink_store::redis_store::RedisStore;
+use nativelink_util::common::DigestInfo;
+use nativelink_util::store_trait::{StoreKeyEncoding, StoreLike};
+
+const HASH: &str = "0123456789abcdef000000000000000000010000000000000123456789abcdef";
+
+/// Size of the blob every iteration reads.
+const BLOB_SIZE: usize = 4 * 1024 * 1024;
+
+/// Values of `read_chunk_size` to compare.
+const READ_CHUNK_SIZES: [usize; 3] = [16 * 1024, 64 * 1024, 1024 * 1024];
+
+/// Redis backend that answers every `GETRANGE` from a single blob.
+#[derive(Debug)]
+struct FakeRedis {
+    blob: Bytes,
+}
+
+impl Mocks for FakeRedis {
+    fn process_command(&self, command: MockCommand) -> Result<RedisValue, RedisError> {
+        match (&*command.cmd, command.args.as_slice()) {
+            ("GETRANGE", [_, RedisValue::Integer(start), RedisValue::Integer(end)]) => {
+                let start = (*start as usize).min(self.blob.len());
+                let end = (*end as usize + 1).min(self.blob.len());
+                Ok(RedisValue::Bytes(self.blob.slice(start..end)))
+            }
+            _ => Err(RedisError::new(
+                RedisErrorKind::Unknown,
+                format!("Unexpected command {command:?}"),
+            )),
+        }
+    }
+}
+
+fn make_store(read_chunk_size: usize) -> RedisStore {
+    let mut builder = Builder::default_centralized();
+    builder.set_config(RedisConfig {
+        mocks: Some(Arc::new(FakeRedis {
+            blob: Bytes::from(vec![0x5a_u8; BLOB_SIZE]),
+        })),
+        ..Default::default()
+    });
+    RedisStore::new_from_builder_and_parts(
+        builder.build_pool(1).unwrap(),
+        builder.build_subscriber_client().unwrap(),
+        None,
+        String::new,
+        String::new(),
+        StoreKeyEncoding::Hex,
+        read_chunk_size,
+        1,
+    )
+    .unwrap()
+}
+
+fn redis_store_benchmark(c: &mut Criterion) {
+    let runtime = make_runtime(None);
+    let digest = DigestInfo::try_new(HASH, BLOB_SIZE).unwrap();
+    let mut group = c.benchmark_group("redis_store");
+    group.throughput(Throughput::Bytes(BLOB_SIZE as u64));
+    for read_chunk_size in READ_CHUNK_SIZES {
+        // The fred clients connect in the background, so they need a runtime.
+        let _runtime_guard = runtime.enter();
+        let store = make_store(read_chunk_size);
+        group.bench_function(BenchmarkId::new("get_part", read_chunk_size), |b| {
+            b.iter(|| {
+                let data = block_on(
+                    &runtime,
+                    "redis_store_bench",
+                    store.get_part_unchunked(digest, 0, None),
+                )
+                .unwrap();
+                assert_eq!(data.len(), BLOB_SIZE);
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, redis_store_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,174 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use nativelink_config::stores::AliasSpec;
+use nativelink_error::{make_input_err, Code, Error, ResultExt};
+use nativelink_metric::MetricsComponent;
+use nativelink_util::buf_channel::{DropCloserReadHalf, DropCloserWriteHalf};
+use nativelink_util::common::DigestInfo;
+use nativelink_util::health_utils::{default_health_status_indicator, HealthStatusIndicator};
+use nativelink_util::store_trait::{
+    Store, StoreDriver, StoreKey, StoreKeyEncoding, StoreLike, UploadSizeInfo,
+};
+use tokio::sync::Mutex;
+
+/// Longest value that can be written to an alias. A digest written as
+/// `{hash}-{size}` is always shorter.
+const MAX_ALIAS_VALUE_SIZE: usize = 256;
+
+#[derive(MetricsComponent)]
+pub struct AliasStore {
+    #[metric(group = "backend")]
+    backend: Store,
+    /// Held while an alias is written, so a `compare_and_swap` never
+    /// races with another write.
+    write_lock: Mutex<()>,
+    #[metric(help = "Number of compare_and_swap calls that did not match the current digest")]
+    swap_conflicts: AtomicU64,
+}
+
+impl AliasStore {
+    pub fn new(_spec: &AliasSpec, backend: Store) -> Arc<Self> {
+        Arc::new(AliasStore {
+            backend,
+            write_lock: Mutex::new(()),
+            swap_conflicts: AtomicU64::new(0),
+        })
+    }
+
+    /// Aliases are always stored under string keys, even when they are
+    /// requested with a digest key.
+    fn alias_key(name: &str) -> StoreKey<'static> {
+        StoreKey::Str(name.to_string().into())
+    }
+
+    fn decode(name: &str, data: &[u8]) -> Result<DigestInfo, Error> {
+        let encoded = std::str::from_utf8(data)
+            .map_err(|e| make_input_err!("Invalid digest for alias {name}: {e}"))?;
+        let (digest, _) = StoreKeyEncoding::decode_digest(encoded.trim())
+            .map_err(|e| make_input_err!("Invalid digest for alias {name}: {e:?}"))?;
+        Ok(digest)
+    }
+
+    async fn write(&self, name: &str, digest: DigestInfo) -> Result<(), Error> {
+        self.backend
+            .update_oneshot(Self::alias_key(name), Bytes::from(format!("{digest}")))
+            .await
+            .err_tip(|| format!("Failed to write alias {name} in AliasStore"))
+    }
+
+    /// Returns the digest `name` points to or `None` if it was never set.
+    pub async fn resolve(&self, name: &str) -> Result<Option<DigestInfo>, Error> {
+        let data = match self
+            .backend
+            .get_part_unchunked(Self::alias_key(name), 0, None)
+            .await
+        {
+            Ok(data) => data,
+            Err(err) if err.code == Code::NotFound => return Ok(None),
+            Err(err) => return Err(err).err_tip(|| "In AliasStore::resolve"),
+        };
+        Self::decode(name, &data).map(Some)
+    }
+
+    /// Points `name` to `new` if it currently points to `expected`, where
+    /// `None` means the alias must not exist yet. Returns false and leaves
+    /// the alias untouched if it points somewhere else.
+    pub async fn compare_and_swap(
+        &self,
+        name: &str,
+        expected: Option<DigestInfo>,
+        new: DigestInfo,
+    ) -> Result<bool, Error> {
+        let _write_lock = self.write_lock.lock().await;
+        let current = self
+            .resolve(name)
+            .await
+            .err_tip(|| "In AliasStore::compare_and_swap")?;
+        if current != expected {
+            self.swap_conflicts.fetch_add(1, Ordering::Relaxed);
+            return Ok(false);
+        }
+        self.write(name, new).await?;
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl StoreDriver for AliasStore {
+    async fn has_with_results(
+        self: Pin<&Self>,
+        keys: &[StoreKey<'_>],
+        results: &mut [Option<u64>],
+    ) -> Result<(), Error> {
+        let keys: Vec<_> = keys
+            .iter()
+            .map(|key| Self::alias_key(&key.as_str()))
+            .collect();
+        self.backend.has_with_results(&keys, results).await
+    }
+
+    async fn update(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        mut reader: DropCloserReadHalf,
+        _size_info: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        let data = reader
+            .consume(Some(MAX_ALIAS_VALUE_SIZE + 1))
+            .await
+            .err_tip(|| "Failed to read alias in AliasStore::update")?;
+        let name = key.as_str();
+        if data.len() > MAX_ALIAS_VALUE_SIZE {
+            return Err(make_input_err!(
+                "Value of alias {name} is larger than {MAX_ALIAS_VALUE_SIZE} bytes"
+            ));
+        }
+        let digest = Self::decode(&name, &data)?;
+        let _write_lock = self.write_lock.lock().await;
+        self.write(&name, digest).await
+    }
+
+    async fn get_part(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        writer: &mut DropCloserWriteHalf,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<(), Error> {
+        self.backend
+            .get_part(Self::alias_key(&key.as_str()), writer, offset, length)
+            .await
+    }
+
+    fn inner_store(&self, _key: Option<StoreKey>) -> &'_ dyn StoreDriver {
+        self
+    }
+
+    fn as_any<'a>(&'a self) -> &'a (dyn std::any::Any + Sync + Send + 'static) {
+        self
+    }
+
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn std::any::Any + Sync + Send + 'static> {
+        self
+    }
+}
+
+default_health_status_indicator!(AliasStore);
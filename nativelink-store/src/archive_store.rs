@@ -0,0 +1,502 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::pin::Pin;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::BytesMut;
+use nativelink_config::stores::{ArchiveFormat, ArchiveSpec};
+use nativelink_error::{error_if, make_err, Code, Error, ResultExt};
+use nativelink_metric::MetricsComponent;
+use nativelink_util::buf_channel::{DropCloserReadHalf, DropCloserWriteHalf};
+use nativelink_util::health_utils::{default_health_status_indicator, HealthStatusIndicator};
+use nativelink_util::store_trait::{StoreDriver, StoreKey, UploadSizeInfo};
+use nativelink_util::task::BlockingPoolKind;
+use nativelink_util::{fs, spawn_blocking};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::cas_utils::is_zero_digest;
+use crate::filesystem_store::{DIGEST_FOLDER, STR_FOLDER};
+
+/// Number of bytes read from the archive at once in `get_part`.
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+const TAR_BLOCK_SIZE: u64 = 512;
+
+const ZIP_LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+const ZIP_CENTRAL_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+const ZIP_END_OF_CENTRAL_DIR_SIGNATURE: u32 = 0x0605_4b50;
+const ZIP64_END_OF_CENTRAL_DIR_SIGNATURE: u32 = 0x0606_4b50;
+const ZIP64_END_OF_CENTRAL_DIR_LOCATOR_SIGNATURE: u32 = 0x0706_4b50;
+/// Id of the extra field that holds the 64 bit sizes and offsets of an
+/// entry of a zip64 archive.
+const ZIP64_EXTRA_FIELD_ID: u16 = 0x0001;
+/// The end of central directory record is 22 bytes and may be followed by
+/// a comment of at most this many bytes.
+const ZIP_MAX_COMMENT_SIZE: u64 = 0xFFFF;
+const ZIP_END_OF_CENTRAL_DIR_SIZE: u64 = 22;
+
+/// Location of an object in the archive.
+#[derive(Debug, Clone, Copy)]
+struct ArchiveEntry {
+    offset: u64,
+    size: u64,
+}
+
+type ArchiveIndex = HashMap<String, ArchiveEntry>;
+
+/// Returns the path `key` has in the `content_path` of a filesystem store,
+/// which is also its path in the archive.
+fn archive_path(key: &StoreKey<'_>) -> String {
+    match key {
+        StoreKey::Str(str) => format!("{STR_FOLDER}/{str}"),
+        StoreKey::Digest(digest) => format!("{DIGEST_FOLDER}/{digest}"),
+    }
+}
+
+/// Adds a file of the archive to `index`, unless it is not an object.
+fn add_to_index(index: &mut ArchiveIndex, path: &str, entry: ArchiveEntry) {
+    let path = path.trim_start_matches("./").trim_start_matches('/');
+    let is_object = [DIGEST_FOLDER, STR_FOLDER].iter().any(|folder| {
+        path.strip_prefix(folder)
+            .and_then(|rest| rest.strip_prefix('/'))
+            .is_some_and(|name| !name.is_empty() && !name.contains('/'))
+    });
+    if is_object {
+        index.insert(path.to_string(), entry);
+    }
+}
+
+fn le_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn le_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn le_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+/// Returns the bytes of a tar header field up to the first NUL.
+fn tar_str(field: &[u8]) -> Result<&str, Error> {
+    let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    std::str::from_utf8(&field[..end])
+        .map_err(|e| make_err!(Code::InvalidArgument, "Tar header is not utf8: {e:?}"))
+}
+
+/// Returns the path in a tar header. POSIX tar archives split long paths
+/// into a prefix and a name.
+fn tar_header_path(header: &[u8]) -> Result<String, Error> {
+    let name = tar_str(&header[0..100])?;
+    if &header[257..263] == b"ustar\0" {
+        let prefix = tar_str(&header[345..500])?;
+        if !prefix.is_empty() {
+            return Ok(format!("{prefix}/{name}"));
+        }
+    }
+    Ok(name.to_string())
+}
+
+/// Parses the size field of a tar header, which is either octal or, for
+/// large files, a big-endian binary number flagged by the highest bit.
+fn tar_size(field: &[u8]) -> Result<u64, Error> {
+    if field[0] & 0x80 != 0 {
+        return Ok(field[1..]
+            .iter()
+            .fold(u64::from(field[0] & 0x7F), |size, b| {
+                (size << 8) | u64::from(*b)
+            }));
+    }
+    let octal = tar_str(field)?.trim_matches(|c: char| c == ' ' || c == '\0');
+    if octal.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(octal, 8)
+        .map_err(|e| make_err!(Code::InvalidArgument, "Invalid tar size {octal:?}: {e:?}"))
+}
+
+/// Returns the `path` and `size` records of a pax extended header.
+fn pax_records(data: &[u8]) -> Result<(Option<String>, Option<u64>), Error> {
+    let mut path = None;
+    let mut size = None;
+    let mut rest = data;
+    while !rest.is_empty() {
+        // Each record is "<length> <key>=<value>\n", the length includes
+        // the whole record.
+        let space = rest
+            .iter()
+            .position(|b| *b == b' ')
+            .err_tip(|| "Pax record without length")?;
+        let len: usize = std::str::from_utf8(&rest[..space])
+            .ok()
+            .and_then(|len| len.parse().ok())
+            .err_tip(|| "Invalid pax record length")?;
+        error_if!(
+            len <= space + 1 || len > rest.len(),
+            "Pax record length {len} is out of bounds"
+        );
+        let record = std::str::from_utf8(&rest[space + 1..len - 1])
+            .map_err(|e| make_err!(Code::InvalidArgument, "Pax record is not utf8: {e:?}"))?;
+        match record.split_once('=') {
+            Some(("path", value)) => path = Some(value.to_string()),
+            Some(("size", value)) => {
+                size = Some(value.parse().map_err(|e| {
+                    make_err!(Code::InvalidArgument, "Invalid pax size {value:?}: {e:?}")
+                })?);
+            }
+            _ => {}
+        }
+        rest = &rest[len..];
+    }
+    Ok((path, size))
+}
+
+fn read_data(reader: &mut impl Read, size: u64) -> Result<Vec<u8>, Error> {
+    let mut data = vec![0; usize::try_from(size).err_tip(|| "Could not convert size to usize")?];
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
+
+fn index_tar(reader: &mut (impl Read + Seek)) -> Result<ArchiveIndex, Error> {
+    let mut index = ArchiveIndex::new();
+    // Set by GNU long name and pax headers for the entry that follows.
+    let mut next_path: Option<String> = None;
+    let mut next_size: Option<u64> = None;
+    let mut offset = 0;
+    let mut header = [0; TAR_BLOCK_SIZE as usize];
+    loop {
+        reader
+            .read_exact(&mut header)
+            .err_tip(|| format!("Failed to read tar header at offset {offset}"))?;
+        // The archive ends with empty blocks.
+        if header.iter().all(|b| *b == 0) {
+            return Ok(index);
+        }
+        let data_offset = offset + TAR_BLOCK_SIZE;
+        let size = match next_size.take() {
+            Some(size) => size,
+            None => tar_size(&header[124..136])?,
+        };
+        match header[156] {
+            // GNU long name of the next entry.
+            b'L' => {
+                let data = read_data(reader, size)?;
+                next_path = Some(tar_str(&data)?.to_string());
+            }
+            // Pax extended header of the next entry.
+            b'x' => {
+                let (path, size) = pax_records(&read_data(reader, size)?)?;
+                next_path = path;
+                next_size = size;
+            }
+            // Regular file.
+            b'0' | 0 => {
+                let path = if let Some(path) = next_path.take() {
+                    path
+                } else {
+                    tar_header_path(&header)?
+                };
+                add_to_index(
+                    &mut index,
+                    &path,
+                    ArchiveEntry {
+                        offset: data_offset,
+                        size,
+                    },
+                );
+            }
+            _ => next_path = None,
+        }
+        offset = data_offset + size.div_ceil(TAR_BLOCK_SIZE) * TAR_BLOCK_SIZE;
+        reader.seek(SeekFrom::Start(offset))?;
+    }
+}
+
+/// Returns the number of entries, the size and the offset of the central
+/// directory of a zip archive.
+fn zip_central_dir(reader: &mut (impl Read + Seek)) -> Result<(u64, u64, u64), Error> {
+    let file_len = reader.seek(SeekFrom::End(0))?;
+    let tail_len = file_len.min(ZIP_END_OF_CENTRAL_DIR_SIZE + ZIP_MAX_COMMENT_SIZE);
+    reader.seek(SeekFrom::Start(file_len - tail_len))?;
+    let tail = read_data(reader, tail_len)?;
+    let eocd_at = (0..=tail
+        .len()
+        .saturating_sub(ZIP_END_OF_CENTRAL_DIR_SIZE as usize))
+        .rev()
+        .find(|at| {
+            tail.len() >= at + ZIP_END_OF_CENTRAL_DIR_SIZE as usize
+                && le_u32(&tail, *at) == ZIP_END_OF_CENTRAL_DIR_SIGNATURE
+        })
+        .err_tip(|| "Could not find the end of the zip central directory")?;
+    let eocd = &tail[eocd_at..];
+    let entries = le_u16(eocd, 10);
+    let size = le_u32(eocd, 12);
+    let offset = le_u32(eocd, 16);
+    if entries != u16::MAX && size != u32::MAX && offset != u32::MAX {
+        return Ok((u64::from(entries), u64::from(size), u64::from(offset)));
+    }
+
+    // Zip64 archives store the real values in another record, which a
+    // locator right before the end of central directory points to.
+    let eocd_offset = file_len - tail_len + eocd_at as u64;
+    error_if!(
+        eocd_offset < 20,
+        "Zip64 end of central directory locator is missing"
+    );
+    reader.seek(SeekFrom::Start(eocd_offset - 20))?;
+    let locator = read_data(reader, 20)?;
+    error_if!(
+        le_u32(&locator, 0) != ZIP64_END_OF_CENTRAL_DIR_LOCATOR_SIGNATURE,
+        "Zip64 end of central directory locator is missing"
+    );
+    reader.seek(SeekFrom::Start(le_u64(&locator, 8)))?;
+    let eocd64 = read_data(reader, 56)?;
+    error_if!(
+        le_u32(&eocd64, 0) != ZIP64_END_OF_CENTRAL_DIR_SIGNATURE,
+        "Invalid zip64 end of central directory"
+    );
+    Ok((
+        le_u64(&eocd64, 32),
+        le_u64(&eocd64, 40),
+        le_u64(&eocd64, 48),
+    ))
+}
+
+fn index_zip(reader: &mut (impl Read + Seek)) -> Result<ArchiveIndex, Error> {
+    let (entries, _size, central_dir_offset) = zip_central_dir(reader)?;
+    reader.seek(SeekFrom::Start(central_dir_offset))?;
+
+    // (path, local header offset, size) of every file in the archive.
+    let mut files = Vec::new();
+    for _ in 0..entries {
+        let mut header = [0; 46];
+        reader
+            .read_exact(&mut header)
+            .err_tip(|| "Failed to read zip central directory")?;
+        error_if!(
+            le_u32(&header, 0) != ZIP_CENTRAL_HEADER_SIGNATURE,
+            "Invalid zip central directory entry"
+        );
+        let method = le_u16(&header, 10);
+        let mut compressed_size = u64::from(le_u32(&header, 20));
+        let mut size = u64::from(le_u32(&header, 24));
+        let mut local_offset = u64::from(le_u32(&header, 42));
+        let name = read_data(reader, u64::from(le_u16(&header, 28)))?;
+        let extra = read_data(reader, u64::from(le_u16(&header, 30)))?;
+        reader.seek(SeekFrom::Current(i64::from(le_u16(&header, 32))))?;
+
+        // The zip64 extra field holds, in order, only the values that did
+        // not fit into the header.
+        let mut at = 0;
+        while at + 4 <= extra.len() {
+            let id = le_u16(&extra, at);
+            let len = usize::from(le_u16(&extra, at + 2));
+            let mut field = &extra[at + 4..(at + 4 + len).min(extra.len())];
+            if id == ZIP64_EXTRA_FIELD_ID {
+                for value in [&mut size, &mut compressed_size, &mut local_offset] {
+                    if *value == u64::from(u32::MAX) && field.len() >= 8 {
+                        *value = le_u64(field, 0);
+                        field = &field[8..];
+                    }
+                }
+            }
+            at += 4 + len;
+        }
+
+        let name = String::from_utf8(name)
+            .map_err(|e| make_err!(Code::InvalidArgument, "Zip entry name is not utf8: {e:?}"))?;
+        if name.ends_with('/') {
+            continue;
+        }
+        error_if!(
+            method != 0 || compressed_size != size,
+            "Zip entry {name} is compressed, entries must be stored without compression"
+        );
+        files.push((name, local_offset, size));
+    }
+
+    // The data of an entry follows its local header, whose extra field may
+    // differ from the one in the central directory.
+    let mut index = ArchiveIndex::new();
+    for (name, local_offset, size) in files {
+        reader.seek(SeekFrom::Start(local_offset))?;
+        let mut header = [0; 30];
+        reader
+            .read_exact(&mut header)
+            .err_tip(|| format!("Failed to read zip local header of {name}"))?;
+        error_if!(
+            le_u32(&header, 0) != ZIP_LOCAL_HEADER_SIGNATURE,
+            "Invalid zip local header of {name}"
+        );
+        let offset = local_offset
+            + header.len() as u64
+            + u64::from(le_u16(&header, 26))
+            + u64::from(le_u16(&header, 28));
+        add_to_index(&mut index, &name, ArchiveEntry { offset, size });
+    }
+    Ok(index)
+}
+
+#[derive(MetricsComponent)]
+pub struct ArchiveStore {
+    #[metric(help = "Path of the archive file")]
+    path: String,
+    index: ArchiveIndex,
+    #[metric(help = "Number of objects in the archive")]
+    object_count: u64,
+}
+
+impl ArchiveStore {
+    pub async fn new(spec: &ArchiveSpec) -> Result<Arc<Self>, Error> {
+        let path = spec.path.clone();
+        let format = spec.format;
+        let index =
+            spawn_blocking!(pool: BlockingPoolKind::Filesystem, "archive_store_open", move || {
+                let file = std::fs::File::open(&path)
+                    .err_tip(|| format!("Failed to open archive {path}"))?;
+                let mut reader = BufReader::new(file);
+                match format {
+                    ArchiveFormat::tar => index_tar(&mut reader),
+                    ArchiveFormat::zip => index_zip(&mut reader),
+                }
+                .err_tip(|| format!("Failed to index archive {path}"))
+            })
+            .await
+            .map_err(|e| {
+                make_err!(
+                    Code::Internal,
+                    "Failed to open archive store due to spawn failing {e:?}"
+                )
+            })??;
+
+        Ok(Arc::new(Self {
+            path: spec.path.clone(),
+            object_count: index.len() as u64,
+            index,
+        }))
+    }
+}
+
+#[async_trait]
+impl StoreDriver for ArchiveStore {
+    async fn has_with_results(
+        self: Pin<&Self>,
+        keys: &[StoreKey<'_>],
+        results: &mut [Option<u64>],
+    ) -> Result<(), Error> {
+        for (key, result) in keys.iter().zip(results.iter_mut()) {
+            // We need to do a special pass to ensure our zero digest exist.
+            *result = if is_zero_digest(key.borrow()) {
+                Some(0)
+            } else {
+                self.index.get(&archive_path(key)).map(|entry| entry.size)
+            };
+        }
+        Ok(())
+    }
+
+    async fn update(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        _reader: DropCloserReadHalf,
+        _size_info: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        Err(make_err!(
+            Code::PermissionDenied,
+            "ArchiveStore is read only, can not update {}",
+            key.as_str()
+        ))
+    }
+
+    async fn get_part(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        writer: &mut DropCloserWriteHalf,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<(), Error> {
+        if is_zero_digest(key.borrow()) {
+            writer
+                .send_eof()
+                .err_tip(|| "Failed to send zero EOF in archive store get_part")?;
+            return Ok(());
+        }
+
+        let entry = self.index.get(&archive_path(&key)).ok_or_else(|| {
+            make_err!(
+                Code::NotFound,
+                "{} not found in archive store",
+                key.as_str()
+            )
+        })?;
+        let start = offset.min(entry.size);
+        let read_limit = length.map_or(entry.size - start, |length| length.min(entry.size - start));
+        if read_limit > 0 {
+            let mut file = fs::open_file(&self.path, read_limit)
+                .await
+                .err_tip(|| "In ArchiveStore::get_part")?;
+            let reader = file
+                .as_reader()
+                .await
+                .err_tip(|| "Could not seek archive in ArchiveStore::get_part")?;
+            reader
+                .get_mut()
+                .seek(SeekFrom::Start(entry.offset + start))
+                .await
+                .err_tip(|| format!("Failed to seek archive {}", self.path))?;
+            let mut remaining = read_limit;
+            while remaining > 0 {
+                let mut buf = BytesMut::with_capacity(READ_BUFFER_SIZE);
+                reader
+                    .read_buf(&mut buf)
+                    .await
+                    .err_tip(|| "Failed to read data in archive store")?;
+                error_if!(
+                    buf.is_empty(),
+                    "Archive {} ended {remaining} bytes before the end of {}",
+                    self.path,
+                    key.as_str()
+                );
+                remaining -= buf.len() as u64;
+                writer
+                    .send(buf.freeze())
+                    .await
+                    .err_tip(|| "Failed to send chunk in archive store get_part")?;
+            }
+        }
+        writer
+            .send_eof()
+            .err_tip(|| "Failed to write EOF in archive store get_part")?;
+        Ok(())
+    }
+
+    fn inner_store(&self, _digest: Option<StoreKey>) -> &dyn StoreDriver {
+        self
+    }
+
+    fn as_any<'a>(&'a self) -> &'a (dyn std::any::Any + Sync + Send + 'static) {
+        self
+    }
+
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn std::any::Any + Sync + Send + 'static> {
+        self
+    }
+}
+
+default_health_status_indicator!(ArchiveStore);
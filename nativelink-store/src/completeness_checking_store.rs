@@ -104,6 +104,48 @@ async fn check_output_directories<'a>(
     Ok(())
 }
 
+/// Returns the keys of the blobs `action_result` refers to that are not in
+/// `cas_store`, including the files of its output directories.
+pub async fn find_missing_outputs(
+    cas_store: &Store,
+    action_result: ProtoActionResult,
+) -> Result<Vec<StoreKey<'static>>, Error> {
+    let (mut keys, output_directories) = get_digests_and_output_dirs(action_result)?;
+    for output_directory in &output_directories {
+        if let Some(tree_digest) = &output_directory.tree_digest {
+            keys.push(
+                DigestInfo::try_from(tree_digest.clone())
+                    .err_tip(|| "Could not decode tree digest in find_missing_outputs")?
+                    .into(),
+            );
+        }
+    }
+    let missing_keys = |keys: Vec<StoreKey<'static>>| async move {
+        let results = cas_store
+            .has_many(&keys)
+            .await
+            .err_tip(|| "In find_missing_outputs")?;
+        Result::<_, Error>::Ok(
+            keys.into_iter()
+                .zip(results)
+                .filter_map(|(key, result)| result.is_none().then_some(key))
+                .collect::<Vec<_>>(),
+        )
+    };
+    let missing = missing_keys(keys).await?;
+    // The trees of the output directories can only be read once they are
+    // known to exist.
+    if !missing.is_empty() {
+        return Ok(missing);
+    }
+    let directory_keys = Mutex::new(Vec::new());
+    check_output_directories(cas_store, output_directories, &|keys| {
+        directory_keys.lock().extend(keys);
+    })
+    .await?;
+    missing_keys(directory_keys.into_inner()).await
+}
+
 #[derive(MetricsComponent)]
 pub struct CompletenessCheckingStore {
     cas_store: Store,
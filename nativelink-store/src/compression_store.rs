@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use std::cmp;
+use std::collections::HashMap;
 use std::pin::Pin;
 use std::sync::Arc;
 
@@ -20,19 +21,25 @@ use async_trait::async_trait;
 use bincode::config::{FixintEncoding, WithOtherIntEncoding};
 use bincode::{DefaultOptions, Options};
 use byteorder::{ByteOrder, LittleEndian};
-use bytes::{Buf, BufMut, BytesMut};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use futures::future::FutureExt;
 use lz4_flex::block::{compress_into, decompress_into, get_maximum_output_size};
-use nativelink_config::stores::CompressionSpec;
+use nativelink_config::stores::{CompressionAlgorithm, CompressionSpec, ZstdDictionaryConfig};
 use nativelink_error::{error_if, make_err, Code, Error, ResultExt};
 use nativelink_metric::MetricsComponent;
 use nativelink_util::buf_channel::{
     make_buf_channel_pair, DropCloserReadHalf, DropCloserWriteHalf,
 };
+use nativelink_util::common::DigestInfo;
 use nativelink_util::health_utils::{default_health_status_indicator, HealthStatusIndicator};
-use nativelink_util::spawn;
 use nativelink_util::store_trait::{Store, StoreDriver, StoreKey, StoreLike, UploadSizeInfo};
+use nativelink_util::task::BlockingPoolKind;
+use nativelink_util::{background_spawn, spawn, spawn_blocking};
+use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
+use tracing::{event, Level};
+use zstd::dict::{DecoderDictionary, EncoderDictionary};
+use zstd::stream::raw::{Decoder, Encoder, Operation, OutBuffer};
 
 use crate::cas_utils::is_zero_digest;
 
@@ -108,6 +115,43 @@ type BincodeOptions = WithOtherIntEncoding<DefaultOptions, FixintEncoding>;
 //
 // Note: All fields fields little-endian.
 
+// Objects compressed with zstd use a simpler format, because zstd frames
+// already carry everything needed to decompress them:
+// |----------------------------------HEADER-----------------------------------------|
+// |  format (u8) 0x28 |  dictionary_hash (32 bytes) |  dictionary_size (u64)        |
+// |----------------------------------FRAME------------------------------------------|
+// |                                ...ZSTD FRAME...                                 |
+// |---------------------------------------------------------------------------------|
+//
+// format               - Always `ZSTD_STREAM_FORMAT`. LZ4 streams start with their
+//                        version instead, so both can be told apart.
+// dictionary_hash      - Blake3 hash of the dictionary the frame was compressed with,
+//                        all zeros if no dictionary was used.
+// dictionary_size      - Size of the dictionary, zero if no dictionary was used.
+
+/// First byte of objects compressed with zstd. It is the first byte of the
+/// zstd magic number, which never collides with `CURRENT_STREAM_FORMAT_VERSION`.
+pub const ZSTD_STREAM_FORMAT: u8 = 0x28;
+
+/// Size of the header of objects compressed with zstd.
+pub const ZSTD_HEADER_SIZE: usize = 1 + 32 + 8;
+
+/// Key the digest of the current zstd dictionary is stored under in the
+/// inner store. Dictionaries themselves are stored under the blake3 digest
+/// of their content.
+pub const ZSTD_DICTIONARY_POINTER_DIGEST: DigestInfo =
+    DigestInfo::new(*b"nativelink-zstd-dictionary\0\0\0\0\0\0", 40);
+
+// NOTE: If these change update the comments in `stores.rs` to reflect
+// the new defaults.
+const DEFAULT_ZSTD_LEVEL: i32 = 3;
+const DEFAULT_DICTIONARY_MAX_OBJECT_SIZE: u32 = 4 * 1024;
+const DEFAULT_DICTIONARY_SAMPLE_COUNT: u32 = 1000;
+const DEFAULT_MAX_DICTIONARY_SIZE: u32 = 64 * 1024;
+
+/// Size of the buffer zstd writes (de)compressed data into.
+const ZSTD_OUTPUT_BUFFER_SIZE: usize = 64 * 1024;
+
 /// Number representing a chunk.
 pub const CHUNK_FRAME_TYPE: u8 = 0;
 
@@ -206,6 +250,226 @@ impl UploadState {
     }
 }
 
+enum DictionaryState {
+    /// The dictionary of an earlier run was not looked up yet.
+    Unloaded,
+    /// The dictionary is being loaded or trained.
+    Pending,
+    /// No dictionary exists yet, small objects are collected to train one.
+    Sampling(Vec<Bytes>),
+    /// Small objects are compressed with this dictionary.
+    Ready(DigestInfo, Arc<EncoderDictionary<'static>>),
+}
+
+/// Trains the zstd dictionary small objects are compressed with.
+struct DictionaryTrainer {
+    max_object_size: u64,
+    sample_count: usize,
+    max_dictionary_size: usize,
+    level: i32,
+    state: Mutex<DictionaryState>,
+}
+
+impl DictionaryTrainer {
+    fn new(config: &ZstdDictionaryConfig, level: i32) -> Self {
+        let or_default = |value, default| if value == 0 { default } else { value };
+        Self {
+            max_object_size: u64::from(or_default(
+                config.max_object_size,
+                DEFAULT_DICTIONARY_MAX_OBJECT_SIZE,
+            )),
+            sample_count: or_default(config.sample_count, DEFAULT_DICTIONARY_SAMPLE_COUNT) as usize,
+            max_dictionary_size: or_default(config.max_dictionary_size, DEFAULT_MAX_DICTIONARY_SIZE)
+                as usize,
+            level,
+            state: Mutex::new(DictionaryState::Unloaded),
+        }
+    }
+
+    /// Returns the dictionary `data` should be compressed with, if one is
+    /// ready. Otherwise `data` is kept as a sample and the dictionary is
+    /// loaded or trained in the background once that is possible.
+    fn dictionary_for(
+        self: &Arc<Self>,
+        data: &Bytes,
+        inner_store: &Store,
+    ) -> Option<(DigestInfo, Arc<EncoderDictionary<'static>>)> {
+        let mut state = self.state.lock();
+        match &mut *state {
+            DictionaryState::Ready(digest, dictionary) => {
+                return Some((*digest, dictionary.clone()));
+            }
+            DictionaryState::Pending => {}
+            DictionaryState::Unloaded => {
+                *state = DictionaryState::Pending;
+                let trainer = self.clone();
+                let inner_store = inner_store.clone();
+                background_spawn!("compression_store_load_dictionary", async move {
+                    let new_state = match trainer.load(&inner_store).await {
+                        Ok(Some((digest, dictionary))) => {
+                            DictionaryState::Ready(digest, dictionary)
+                        }
+                        Ok(None) => DictionaryState::Sampling(Vec::new()),
+                        Err(err) => {
+                            event!(
+                                Level::WARN,
+                                ?err,
+                                "Failed to load zstd dictionary in compression store, training a new one"
+                            );
+                            DictionaryState::Sampling(Vec::new())
+                        }
+                    };
+                    *trainer.state.lock() = new_state;
+                });
+            }
+            DictionaryState::Sampling(samples) => {
+                samples.push(data.clone());
+                if samples.len() >= self.sample_count {
+                    let samples = std::mem::take(samples);
+                    *state = DictionaryState::Pending;
+                    let trainer = self.clone();
+                    let inner_store = inner_store.clone();
+                    background_spawn!("compression_store_train_dictionary", async move {
+                        let new_state = match trainer.train(samples, &inner_store).await {
+                            Ok((digest, dictionary)) => DictionaryState::Ready(digest, dictionary),
+                            Err(err) => {
+                                event!(
+                                    Level::WARN,
+                                    ?err,
+                                    "Failed to train zstd dictionary in compression store, collecting new samples"
+                                );
+                                DictionaryState::Sampling(Vec::new())
+                            }
+                        };
+                        *trainer.state.lock() = new_state;
+                    });
+                }
+            }
+        }
+        None
+    }
+
+    /// Loads the dictionary a previous run stored in `inner_store`.
+    async fn load(
+        &self,
+        inner_store: &Store,
+    ) -> Result<Option<(DigestInfo, Arc<EncoderDictionary<'static>>)>, Error> {
+        let pointer = match inner_store
+            .get_part_unchunked(ZSTD_DICTIONARY_POINTER_DIGEST, 0, None)
+            .await
+        {
+            Ok(pointer) => pointer,
+            Err(err) if err.code == Code::NotFound => return Ok(None),
+            Err(err) => return Err(err).err_tip(|| "Failed to read zstd dictionary pointer"),
+        };
+        error_if!(
+            pointer.len() != 40,
+            "Expected zstd dictionary pointer to be 40 bytes, got {}",
+            pointer.len()
+        );
+        let digest = DigestInfo::new(
+            pointer[..32].try_into().unwrap(),
+            LittleEndian::read_u64(&pointer[32..]),
+        );
+        let dictionary = fetch_dictionary(inner_store, digest).await?;
+        Ok(Some((
+            digest,
+            Arc::new(EncoderDictionary::copy(&dictionary, self.level)),
+        )))
+    }
+
+    /// Trains a dictionary on `samples` and stores it in `inner_store`.
+    async fn train(
+        &self,
+        samples: Vec<Bytes>,
+        inner_store: &Store,
+    ) -> Result<(DigestInfo, Arc<EncoderDictionary<'static>>), Error> {
+        let max_dictionary_size = self.max_dictionary_size;
+        let dictionary = spawn_blocking!(
+            pool: BlockingPoolKind::Cpu,
+            "compression_store_train_dictionary",
+            move || zstd::dict::from_samples(&samples, max_dictionary_size)
+        )
+        .await
+        .map_err(|e| {
+            make_err!(
+                Code::Internal,
+                "Failed to train zstd dictionary due to spawn failing {e:?}"
+            )
+        })?
+        .map_err(|e| make_err!(Code::Internal, "Failed to train zstd dictionary: {e:?}"))?;
+
+        let digest = DigestInfo::new(blake3::hash(&dictionary).into(), dictionary.len() as u64);
+        let encoder_dictionary = Arc::new(EncoderDictionary::copy(&dictionary, self.level));
+        inner_store
+            .update_oneshot(digest, dictionary.into())
+            .await
+            .err_tip(|| "Failed to store zstd dictionary")?;
+        let mut pointer = BytesMut::with_capacity(40);
+        pointer.extend_from_slice(&digest.packed_hash()[..]);
+        pointer.put_u64_le(digest.size_bytes());
+        inner_store
+            .update_oneshot(ZSTD_DICTIONARY_POINTER_DIGEST, pointer.freeze())
+            .await
+            .err_tip(|| "Failed to store zstd dictionary pointer")?;
+        Ok((digest, encoder_dictionary))
+    }
+}
+
+/// Reads the dictionary `digest` from `inner_store` and checks its hash.
+async fn fetch_dictionary(inner_store: &Store, digest: DigestInfo) -> Result<Bytes, Error> {
+    let dictionary = inner_store
+        .get_part_unchunked(digest, 0, None)
+        .await
+        .err_tip(|| format!("Failed to read zstd dictionary {digest}"))?;
+    let actual_digest = DigestInfo::new(blake3::hash(&dictionary).into(), dictionary.len() as u64);
+    error_if!(
+        actual_digest != digest,
+        "Zstd dictionary {digest} has digest {actual_digest}"
+    );
+    Ok(dictionary)
+}
+
+struct ZstdState {
+    level: i32,
+    trainer: Option<Arc<DictionaryTrainer>>,
+    /// Dictionaries objects were compressed with, by their digest.
+    decoder_dictionaries: Mutex<HashMap<DigestInfo, Arc<DecoderDictionary<'static>>>>,
+}
+
+/// Runs `input` through `encoder` and appends the output to `output`.
+fn zstd_compress_into(
+    encoder: &mut Encoder<'_>,
+    mut input: &[u8],
+    output: &mut BytesMut,
+) -> Result<(), Error> {
+    let mut buffer = vec![0u8; ZSTD_OUTPUT_BUFFER_SIZE];
+    while !input.is_empty() {
+        let status = encoder
+            .run_on_buffers(input, &mut buffer)
+            .map_err(|e| make_err!(Code::Internal, "Failed to zstd compress: {e:?}"))?;
+        input = &input[status.bytes_read..];
+        output.extend_from_slice(&buffer[..status.bytes_written]);
+    }
+    Ok(())
+}
+
+/// Finishes the frame of `encoder` and appends the output to `output`.
+fn zstd_finish_into(encoder: &mut Encoder<'_>, output: &mut BytesMut) -> Result<(), Error> {
+    let mut buffer = vec![0u8; ZSTD_OUTPUT_BUFFER_SIZE];
+    loop {
+        let mut out_buffer = OutBuffer::around(buffer.as_mut_slice());
+        let remaining = encoder
+            .finish(&mut out_buffer, true)
+            .map_err(|e| make_err!(Code::Internal, "Failed to finish zstd frame: {e:?}"))?;
+        let bytes_written = out_buffer.pos();
+        output.extend_from_slice(&buffer[..bytes_written]);
+        if remaining == 0 {
+            return Ok(());
+        }
+    }
+}
+
 /// This store will compress data before sending it on to the inner store.
 /// Note: Currently using `get_part()` and trying to read part of the data will
 /// result in the entire contents being read from the inner store but will
@@ -216,27 +480,292 @@ pub struct CompressionStore {
     inner_store: Store,
     config: nativelink_config::stores::Lz4Config,
     bincode_options: BincodeOptions,
+    /// Set if new objects are compressed with zstd instead of LZ4.
+    zstd: Option<ZstdState>,
 }
 
 impl CompressionStore {
     pub fn new(spec: &CompressionSpec, inner_store: Store) -> Result<Arc<Self>, Error> {
-        let lz4_config = match spec.compression_algorithm {
-            nativelink_config::stores::CompressionAlgorithm::lz4(mut lz4_config) => {
+        let (lz4_config, zstd) = match spec.compression_algorithm {
+            CompressionAlgorithm::lz4(mut lz4_config) => {
                 if lz4_config.block_size == 0 {
                     lz4_config.block_size = DEFAULT_BLOCK_SIZE;
                 }
                 if lz4_config.max_decode_block_size == 0 {
                     lz4_config.max_decode_block_size = lz4_config.block_size;
                 }
-                lz4_config
+                (lz4_config, None)
+            }
+            CompressionAlgorithm::zstd(zstd_config) => {
+                let level = if zstd_config.level == 0 {
+                    DEFAULT_ZSTD_LEVEL
+                } else {
+                    zstd_config.level
+                };
+                let zstd = ZstdState {
+                    level,
+                    trainer: zstd_config
+                        .dictionary
+                        .map(|config| Arc::new(DictionaryTrainer::new(&config, level))),
+                    decoder_dictionaries: Mutex::new(HashMap::new()),
+                };
+                // Only used to read objects compressed before switching to zstd.
+                let lz4_config = nativelink_config::stores::Lz4Config {
+                    block_size: DEFAULT_BLOCK_SIZE,
+                    max_decode_block_size: DEFAULT_BLOCK_SIZE,
+                };
+                (lz4_config, Some(zstd))
             }
         };
         Ok(Arc::new(CompressionStore {
             inner_store,
             config: lz4_config,
             bincode_options: DefaultOptions::new().with_fixint_encoding(),
+            zstd,
         }))
     }
+
+    async fn update_zstd(
+        &self,
+        zstd: &ZstdState,
+        key: StoreKey<'_>,
+        mut reader: DropCloserReadHalf,
+        upload_size: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        let input_max_size = match upload_size {
+            UploadSizeInfo::MaxSize(sz) | UploadSizeInfo::ExactSize(sz) => sz,
+        };
+        let max_output_size = ZSTD_HEADER_SIZE as u64
+            + zstd::zstd_safe::compress_bound(
+                usize::try_from(input_max_size)
+                    .err_tip(|| "Could not convert input_max_size to usize")?,
+            ) as u64;
+
+        let (mut tx, rx) = make_buf_channel_pair();
+        let inner_store = self.inner_store.clone();
+        let key = key.into_owned();
+        let update_fut = spawn!("compression_store_update_zstd_spawn", async move {
+            inner_store
+                .update(key, rx, UploadSizeInfo::MaxSize(max_output_size))
+                .await
+                .err_tip(|| "Inner store update in compression store failed")
+        })
+        .map(
+            |result| match result.err_tip(|| "Failed to run compression update spawn") {
+                Ok(inner_result) => {
+                    inner_result.err_tip(|| "Compression underlying store update failed")
+                }
+                Err(e) => Err(e),
+            },
+        );
+
+        let write_fut = async move {
+            // Small objects are read whole, so they can be sampled for and
+            // compressed with the dictionary.
+            let small_object = match (&zstd.trainer, upload_size) {
+                (Some(trainer), UploadSizeInfo::ExactSize(size))
+                    if size <= trainer.max_object_size =>
+                {
+                    let data = reader
+                        .consume(Some(size as usize + 1))
+                        .await
+                        .err_tip(|| "Failed to read small object in compression store")?;
+                    error_if!(
+                        data.len() as u64 != size,
+                        "Expected {size} bytes in compression store upload, got {}",
+                        data.len()
+                    );
+                    Some((trainer.dictionary_for(&data, &self.inner_store), data))
+                }
+                _ => None,
+            };
+
+            let mut header = BytesMut::with_capacity(ZSTD_HEADER_SIZE);
+            header.put_u8(ZSTD_STREAM_FORMAT);
+            let dictionary_digest = small_object
+                .as_ref()
+                .and_then(|(dictionary, _)| dictionary.as_ref())
+                .map_or_else(DigestInfo::zero_digest, |(digest, _)| *digest);
+            header.extend_from_slice(&dictionary_digest.packed_hash()[..]);
+            header.put_u64_le(dictionary_digest.size_bytes());
+            tx.send(header.freeze())
+                .await
+                .err_tip(|| "Failed to write compression header on upload")?;
+
+            let mut output = BytesMut::new();
+            if let Some((dictionary, data)) = small_object {
+                let level = zstd.level;
+                output = spawn_blocking!(
+                    pool: BlockingPoolKind::Cpu,
+                    "compression_store_compress_small_object",
+                    move || {
+                        let mut encoder = match &dictionary {
+                            Some((_, dictionary)) => Encoder::with_prepared_dictionary(dictionary),
+                            None => Encoder::new(level),
+                        }
+                        .map_err(|e| {
+                            make_err!(Code::Internal, "Failed to create zstd encoder: {e:?}")
+                        })?;
+                        zstd_compress_into(&mut encoder, &data, &mut output)?;
+                        zstd_finish_into(&mut encoder, &mut output)?;
+                        Ok::<_, Error>(output)
+                    }
+                )
+                .await
+                .map_err(|e| {
+                    make_err!(
+                        Code::Internal,
+                        "Failed to compress small object due to spawn failing {e:?}"
+                    )
+                })??;
+            } else {
+                let mut encoder = Encoder::new(zstd.level).map_err(|e| {
+                    make_err!(Code::Internal, "Failed to create zstd encoder: {e:?}")
+                })?;
+                let mut received_amt = 0;
+                loop {
+                    let chunk = reader
+                        .recv()
+                        .await
+                        .err_tip(|| "Failed to read chunk in compression store update")?;
+                    if chunk.is_empty() {
+                        break; // EOF.
+                    }
+                    received_amt += chunk.len() as u64;
+                    error_if!(
+                        received_amt > input_max_size,
+                        "Got more data than stated in compression store upload request"
+                    );
+                    zstd_compress_into(&mut encoder, &chunk, &mut output)?;
+                    if !output.is_empty() {
+                        tx.send(output.split().freeze()).await.err_tip(|| {
+                            "Failed to write chunk to inner store in compression store"
+                        })?;
+                    }
+                }
+                zstd_finish_into(&mut encoder, &mut output)?;
+            }
+            tx.send(output.freeze())
+                .await
+                .err_tip(|| "Failed to write chunk to inner store in compression store")?;
+            tx.send_eof()
+                .err_tip(|| "Failed writing EOF in compression store update")
+        };
+        let (write_result, update_result) = tokio::join!(write_fut, update_fut);
+        write_result.merge(update_result)
+    }
+
+    /// Returns the dictionary `digest` to decompress objects with.
+    async fn decoder_dictionary(
+        &self,
+        zstd: Option<&ZstdState>,
+        digest: DigestInfo,
+    ) -> Result<Arc<DecoderDictionary<'static>>, Error> {
+        if let Some(dictionary) =
+            zstd.and_then(|zstd| zstd.decoder_dictionaries.lock().get(&digest).cloned())
+        {
+            return Ok(dictionary);
+        }
+        let dictionary = Arc::new(DecoderDictionary::copy(
+            &fetch_dictionary(&self.inner_store, digest).await?,
+        ));
+        if let Some(zstd) = zstd {
+            zstd.decoder_dictionaries
+                .lock()
+                .insert(digest, dictionary.clone());
+        }
+        Ok(dictionary)
+    }
+
+    /// Decompresses an object compressed with zstd from `rx` and sends the
+    /// requested part of it to `writer`.
+    async fn read_zstd(
+        &self,
+        rx: &mut DropCloserReadHalf,
+        writer: &mut DropCloserWriteHalf,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<(), Error> {
+        let header = rx
+            .consume(Some(ZSTD_HEADER_SIZE))
+            .await
+            .err_tip(|| "Failed to read header in get_part compression store")?;
+        error_if!(
+            header.len() != ZSTD_HEADER_SIZE,
+            "Expected inner store to return the proper amount of data in compression store {} != {}",
+            header.len(),
+            ZSTD_HEADER_SIZE,
+        );
+        let dictionary_digest = DigestInfo::new(
+            header[1..33].try_into().unwrap(),
+            LittleEndian::read_u64(&header[33..]),
+        );
+        let dictionary = if dictionary_digest == DigestInfo::zero_digest() {
+            None
+        } else {
+            Some(
+                self.decoder_dictionary(self.zstd.as_ref(), dictionary_digest)
+                    .await?,
+            )
+        };
+        let mut decoder = match &dictionary {
+            Some(dictionary) => Decoder::with_prepared_dictionary(dictionary),
+            None => Decoder::new(),
+        }
+        .map_err(|e| make_err!(Code::Internal, "Failed to create zstd decoder: {e:?}"))?;
+
+        let end = length.map_or(u64::MAX, |length| offset.saturating_add(length));
+        let mut output = vec![0u8; ZSTD_OUTPUT_BUFFER_SIZE];
+        let mut uncompressed_data_sz: u64 = 0;
+        // Zero once the decoder finished the frame and flushed all of its data.
+        let mut frame_remaining = 1;
+        loop {
+            let chunk = rx
+                .recv()
+                .await
+                .err_tip(|| "Failed to read chunk in get_part compression store")?;
+            if chunk.is_empty() {
+                break; // EOF.
+            }
+            let mut input = chunk.as_ref();
+            loop {
+                let status = decoder
+                    .run_on_buffers(input, &mut output)
+                    .map_err(|e| make_err!(Code::Internal, "Decompression error {e:?}"))?;
+                input = &input[status.bytes_read..];
+                // Once a frame is done zstd expects the next one, calls that
+                // made no progress must not overwrite the finished state.
+                if status.bytes_read > 0 || status.bytes_written > 0 {
+                    frame_remaining = status.remaining;
+                }
+                let chunk_start = uncompressed_data_sz;
+                uncompressed_data_sz += status.bytes_written as u64;
+                let start_pos = offset.clamp(chunk_start, uncompressed_data_sz);
+                let end_pos = end.clamp(chunk_start, uncompressed_data_sz);
+                if end_pos > start_pos {
+                    writer
+                        .send(Bytes::copy_from_slice(
+                            &output[(start_pos - chunk_start) as usize
+                                ..(end_pos - chunk_start) as usize],
+                        ))
+                        .await
+                        .err_tip(|| "Failed sending chunk in compression store")?;
+                }
+                // A full output buffer means the decoder may still hold data
+                // even if all the input was consumed.
+                if input.is_empty() && status.bytes_written < output.len() {
+                    break;
+                }
+            }
+        }
+        error_if!(
+            frame_remaining != 0,
+            "Compressed data ended in the middle of a zstd frame in compression store"
+        );
+        writer
+            .send_eof()
+            .err_tip(|| "Failed to send eof in compression store write")
+    }
 }
 
 #[async_trait]
@@ -255,6 +784,9 @@ impl StoreDriver for CompressionStore {
         mut reader: DropCloserReadHalf,
         upload_size: UploadSizeInfo,
     ) -> Result<(), Error> {
+        if let Some(zstd) = &self.zstd {
+            return self.update_zstd(zstd, key, reader, upload_size).await;
+        }
         let mut output_state = UploadState::new(&self, upload_size)?;
 
         let (mut tx, rx) = make_buf_channel_pair();
@@ -420,6 +952,13 @@ impl StoreDriver for CompressionStore {
             },
         );
         let read_fut = async move {
+            let first_chunk = rx
+                .peek()
+                .await
+                .err_tip(|| "Failed to read header in get_part compression store")?;
+            if first_chunk.first() == Some(&ZSTD_STREAM_FORMAT) {
+                return self.read_zstd(&mut rx, writer, offset, length).await;
+            }
             let header = {
                 // Read header.
                 static EMPTY_HEADER: Header = Header {
@@ -18,26 +18,40 @@ use std::time::SystemTime;
 
 use futures::stream::FuturesOrdered;
 use futures::{Future, TryStreamExt};
-use nativelink_config::stores::StoreSpec;
-use nativelink_error::Error;
+use nativelink_config::stores::{
+    EvictionPolicy, ExistenceCacheSpec, FastSlowPopulationSpec, FastSlowSpec, FilesystemSpec,
+    GrpcLocalCacheSpec, GrpcSpec, StoreSpec, StoreType,
+};
+use nativelink_error::{error_if, Error};
 use nativelink_util::health_utils::HealthRegistryBuilder;
 use nativelink_util::store_trait::{Store, StoreDriver};
 
+use crate::alias_store::AliasStore;
+use crate::archive_store::ArchiveStore;
 use crate::completeness_checking_store::CompletenessCheckingStore;
 use crate::compression_store::CompressionStore;
 use crate::dedup_store::DedupStore;
+use crate::encryption_store::EncryptionStore;
 use crate::existence_cache_store::ExistenceCacheStore;
 use crate::fast_slow_store::FastSlowStore;
 use crate::filesystem_store::FilesystemStore;
+use crate::gcs_store::GcsStore;
 use crate::grpc_store::GrpcStore;
 use crate::memory_store::MemoryStore;
+use crate::mirror_store::MirrorStore;
 use crate::noop_store::NoopStore;
+use crate::quota_store::QuotaStore;
+use crate::redb_store::RedbStore;
 use crate::redis_store::RedisStore;
 use crate::ref_store::RefStore;
+use crate::replication_store::ReplicationStore;
+use crate::retry_store::RetryStore;
 use crate::s3_store::S3Store;
 use crate::shard_store::ShardStore;
 use crate::size_partitioning_store::SizePartitioningStore;
 use crate::store_manager::StoreManager;
+use crate::throttle_store::ThrottleStore;
+use crate::translation_store::TranslationStore;
 use crate::verify_store::VerifyStore;
 
 type FutureMaybeStore<'a> = Box<dyn Future<Output = Result<Store, Error>> + 'a>;
@@ -51,15 +65,25 @@ pub fn store_factory<'a>(
         let store: Arc<dyn StoreDriver> = match backend {
             StoreSpec::memory(spec) => MemoryStore::new(spec),
             StoreSpec::experimental_s3_store(spec) => S3Store::new(spec, SystemTime::now).await?,
+            StoreSpec::experimental_gcs_store(spec) => GcsStore::new(spec, SystemTime::now)?,
             StoreSpec::redis_store(spec) => RedisStore::new(spec.clone())?,
             StoreSpec::verify(spec) => VerifyStore::new(
                 spec,
                 store_factory(&spec.backend, store_manager, None).await?,
             ),
+            StoreSpec::translation(spec) => TranslationStore::new(
+                spec,
+                store_factory(&spec.backend, store_manager, None).await?,
+                store_factory(&spec.mapping_store, store_manager, None).await?,
+            ),
             StoreSpec::compression(spec) => CompressionStore::new(
                 &spec.clone(),
                 store_factory(&spec.backend, store_manager, None).await?,
             )?,
+            StoreSpec::encryption(spec) => EncryptionStore::new(
+                spec,
+                store_factory(&spec.backend, store_manager, None).await?,
+            )?,
             StoreSpec::dedup(spec) => DedupStore::new(
                 spec,
                 store_factory(&spec.index_store, store_manager, None).await?,
@@ -69,6 +93,22 @@ pub fn store_factory<'a>(
                 spec,
                 store_factory(&spec.backend, store_manager, None).await?,
             ),
+            StoreSpec::retry(spec) => RetryStore::new(
+                spec,
+                store_factory(&spec.backend, store_manager, None).await?,
+            ),
+            StoreSpec::quota(spec) => QuotaStore::new(
+                spec,
+                store_factory(&spec.backend, store_manager, None).await?,
+            ),
+            StoreSpec::throttle(spec) => ThrottleStore::new(
+                spec,
+                store_factory(&spec.backend, store_manager, None).await?,
+            ),
+            StoreSpec::alias(spec) => AliasStore::new(
+                spec,
+                store_factory(&spec.backend, store_manager, None).await?,
+            ),
             StoreSpec::completeness_checking(spec) => CompletenessCheckingStore::new(
                 store_factory(&spec.backend, store_manager, None).await?,
                 store_factory(&spec.cas_store, store_manager, None).await?,
@@ -77,15 +117,26 @@ pub fn store_factory<'a>(
                 spec,
                 store_factory(&spec.fast, store_manager, None).await?,
                 store_factory(&spec.slow, store_manager, None).await?,
-            ),
+            )?,
             StoreSpec::filesystem(spec) => <FilesystemStore>::new(spec).await?,
+            StoreSpec::experimental_redb_store(spec) => RedbStore::new(spec).await?,
+            StoreSpec::experimental_archive_store(spec) => ArchiveStore::new(spec).await?,
             StoreSpec::ref_store(spec) => RefStore::new(spec, Arc::downgrade(store_manager)),
             StoreSpec::size_partitioning(spec) => SizePartitioningStore::new(
                 spec,
                 store_factory(&spec.lower_store, store_manager, None).await?,
                 store_factory(&spec.upper_store, store_manager, None).await?,
             ),
-            StoreSpec::grpc(spec) => GrpcStore::new(spec).await?,
+            StoreSpec::grpc(spec) => match &spec.local_cache {
+                Some(local_cache) => store_factory(
+                    &grpc_local_cache_spec(spec, local_cache)?,
+                    store_manager,
+                    None,
+                )
+                .await?
+                .into_inner(),
+                None => GrpcStore::new(spec).await?,
+            },
             StoreSpec::noop(_) => NoopStore::new(),
             StoreSpec::shard(spec) => {
                 let stores = spec
@@ -97,6 +148,26 @@ pub fn store_factory<'a>(
                     .await?;
                 ShardStore::new(spec, stores)?
             }
+            StoreSpec::replication(spec) => {
+                let stores = spec
+                    .replicas
+                    .iter()
+                    .map(|store_spec| store_factory(store_spec, store_manager, None))
+                    .collect::<FuturesOrdered<_>>()
+                    .try_collect::<Vec<_>>()
+                    .await?;
+                ReplicationStore::new(spec, stores, SystemTime::now)?
+            }
+            StoreSpec::mirror(spec) => {
+                let stores = spec
+                    .stores
+                    .iter()
+                    .map(|store_spec| store_factory(store_spec, store_manager, None))
+                    .collect::<FuturesOrdered<_>>()
+                    .try_collect::<Vec<_>>()
+                    .await?;
+                MirrorStore::new(spec, stores)?
+            }
         };
 
         if let Some(health_registry_builder) = maybe_health_registry_builder {
@@ -106,3 +177,46 @@ pub fn store_factory<'a>(
         Ok(Store::new(store))
     })
 }
+
+/// Expands a `GrpcSpec` with a `local_cache` into the equivalent
+/// `fast_slow` store with a filesystem fast tier and an existence cache
+/// in front of the upstream store.
+fn grpc_local_cache_spec(
+    spec: &GrpcSpec,
+    local_cache: &GrpcLocalCacheSpec,
+) -> Result<StoreSpec, Error> {
+    error_if!(
+        !matches!(spec.store_type, StoreType::cas),
+        "GrpcStore local_cache is only supported when store_type is cas"
+    );
+    error_if!(
+        local_cache.cache_dir.is_empty(),
+        "GrpcStore local_cache.cache_dir must be set"
+    );
+    let cache_dir = std::path::Path::new(&local_cache.cache_dir);
+    let eviction_policy = (local_cache.max_bytes != 0).then(|| EvictionPolicy {
+        max_bytes: local_cache.max_bytes,
+        ..Default::default()
+    });
+    Ok(StoreSpec::fast_slow(Box::new(FastSlowSpec {
+        fast: StoreSpec::filesystem(FilesystemSpec {
+            content_path: cache_dir.join("content").to_string_lossy().into_owned(),
+            temp_path: cache_dir.join("tmp").to_string_lossy().into_owned(),
+            read_buffer_size: 0,
+            eviction_policy,
+            ..Default::default()
+        }),
+        slow: StoreSpec::existence_cache(Box::new(ExistenceCacheSpec {
+            backend: StoreSpec::grpc(GrpcSpec {
+                local_cache: None,
+                ..spec.clone()
+            }),
+            eviction_policy: None,
+            negative_cache_seconds: 0,
+            negative_cache_max_count: 0,
+        })),
+        popularity: None,
+        population: FastSlowPopulationSpec::default(),
+        write_back: false,
+    })))
+}
@@ -0,0 +1,509 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use futures::join;
+use nativelink_config::stores::EncryptionSpec;
+use nativelink_error::{error_if, make_err, make_input_err, Code, Error, ResultExt};
+use nativelink_metric::MetricsComponent;
+use nativelink_util::buf_channel::{
+    make_buf_channel_pair, DropCloserReadHalf, DropCloserWriteHalf,
+};
+use nativelink_util::health_utils::{default_health_status_indicator, HealthStatusIndicator};
+use nativelink_util::store_trait::{Store, StoreDriver, StoreKey, StoreLike, UploadSizeInfo};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::digest::{digest, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// Size of the plaintext of every chunk, except the last one which may be
+/// smaller.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Size of the authentication tag appended to every sealed message.
+const TAG_SIZE: usize = 16;
+
+/// Size of a chunk as stored in the inner store.
+const ENCRYPTED_CHUNK_SIZE: usize = CHUNK_SIZE + TAG_SIZE;
+
+/// Size of the keys used for both the master key and the data keys.
+const KEY_SIZE: usize = 32;
+
+/// Size of the fingerprint of the master key stored in the header.
+const KEY_ID_SIZE: usize = 8;
+
+/// Identifies objects written by this store.
+const MAGIC: [u8; 4] = *b"NLEN";
+
+/// Bumped whenever the layout of the stored objects changes.
+const FORMAT_VERSION: u8 = 1;
+
+/// Size of the part of the header that is authenticated but not encrypted.
+const HEADER_PREFIX_SIZE: usize = MAGIC.len() + 1 + KEY_ID_SIZE;
+
+/// Every object starts with a header made of `MAGIC`, `FORMAT_VERSION`,
+/// the id of the master key, the nonce used to wrap the data key and the
+/// wrapped data key. It is followed by at least one chunk, the last one
+/// being sealed with a different nonce so truncation is detected.
+const HEADER_SIZE: usize = HEADER_PREFIX_SIZE + NONCE_LEN + KEY_SIZE + TAG_SIZE;
+
+struct MasterKey {
+    id: [u8; KEY_ID_SIZE],
+    key: LessSafeKey,
+}
+
+impl MasterKey {
+    fn from_hex(hex_key: &str) -> Result<Self, Error> {
+        let key_bytes = hex::decode(hex_key.trim())
+            .ok()
+            .filter(|key_bytes| key_bytes.len() == KEY_SIZE)
+            .err_tip(|| "Master keys of EncryptionStore must be 64 hex characters")?;
+        let mut id = [0; KEY_ID_SIZE];
+        id.copy_from_slice(&digest(&SHA256, &key_bytes).as_ref()[..KEY_ID_SIZE]);
+        Ok(Self {
+            id,
+            key: aead_key(&key_bytes)?,
+        })
+    }
+}
+
+fn aead_key(key_bytes: &[u8]) -> Result<LessSafeKey, Error> {
+    let key = UnboundKey::new(&AES_256_GCM, key_bytes)
+        .map_err(|_| make_input_err!("Invalid key length for AES-256-GCM"))?;
+    Ok(LessSafeKey::new(key))
+}
+
+/// Data keys are only used for one object, so the nonces only need to be
+/// unique within it.
+fn chunk_nonce(index: u64, is_final: bool) -> Nonce {
+    let mut nonce = [0; NONCE_LEN];
+    nonce[0] = u8::from(is_final);
+    nonce[NONCE_LEN - 8..].copy_from_slice(&index.to_be_bytes());
+    Nonce::assume_unique_for_key(nonce)
+}
+
+/// Offset in the inner store of the chunk at `index`.
+const fn chunk_offset(index: u64) -> u64 {
+    HEADER_SIZE as u64 + index * ENCRYPTED_CHUNK_SIZE as u64
+}
+
+/// Size of an object of `size` bytes once encrypted.
+const fn encrypted_size(size: u64) -> u64 {
+    let chunks = if size == 0 {
+        1
+    } else {
+        size.div_ceil(CHUNK_SIZE as u64)
+    };
+    HEADER_SIZE as u64 + size + chunks * TAG_SIZE as u64
+}
+
+/// Real size of an object that takes `size` bytes in the inner store.
+fn decrypted_size(size: u64) -> Option<u64> {
+    let payload_size = size.checked_sub(HEADER_SIZE as u64)?;
+    let chunks = payload_size.div_ceil(ENCRYPTED_CHUNK_SIZE as u64).max(1);
+    payload_size.checked_sub(chunks * TAG_SIZE as u64)
+}
+
+#[derive(MetricsComponent)]
+pub struct EncryptionStore {
+    #[metric(group = "inner_store")]
+    inner_store: Store,
+    master_key: MasterKey,
+    previous_master_keys: Vec<MasterKey>,
+    rng: SystemRandom,
+    #[metric(help = "Number of objects that failed to decrypt")]
+    decryption_failures: AtomicU64,
+}
+
+impl EncryptionStore {
+    pub fn new(spec: &EncryptionSpec, inner_store: Store) -> Result<Arc<Self>, Error> {
+        let master_key =
+            MasterKey::from_hex(&spec.master_key).err_tip(|| "In EncryptionStore::new")?;
+        let previous_master_keys = spec
+            .previous_master_keys
+            .iter()
+            .map(|hex_key| MasterKey::from_hex(hex_key))
+            .collect::<Result<Vec<_>, Error>>()
+            .err_tip(|| "In EncryptionStore::new for previous_master_keys")?;
+        Ok(Arc::new(Self {
+            inner_store,
+            master_key,
+            previous_master_keys,
+            rng: SystemRandom::new(),
+            decryption_failures: AtomicU64::new(0),
+        }))
+    }
+
+    /// Builds the header of a new object, which holds `data_key` wrapped by
+    /// the master key. The key of the object is authenticated, so objects
+    /// can not be swapped in the inner store.
+    fn make_header(&self, key: &StoreKey<'_>, data_key: &[u8]) -> Result<BytesMut, Error> {
+        let mut header = BytesMut::with_capacity(HEADER_SIZE);
+        header.extend_from_slice(&MAGIC);
+        header.extend_from_slice(&[FORMAT_VERSION]);
+        header.extend_from_slice(&self.master_key.id);
+        let mut nonce = [0; NONCE_LEN];
+        self.rng
+            .fill(&mut nonce)
+            .map_err(|_| make_err!(Code::Internal, "Failed to generate nonce"))?;
+        header.extend_from_slice(&nonce);
+
+        let mut aad = header[..HEADER_PREFIX_SIZE].to_vec();
+        aad.extend_from_slice(key.as_str().as_bytes());
+        let mut wrapped_key = BytesMut::from(data_key);
+        self.master_key
+            .key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(aad),
+                &mut wrapped_key,
+            )
+            .map_err(|_| make_err!(Code::Internal, "Failed to wrap data key"))?;
+        header.extend_from_slice(&wrapped_key);
+        Ok(header)
+    }
+
+    /// Returns the data key of an object from its header.
+    fn open_header(&self, key: &StoreKey<'_>, header: &[u8]) -> Result<LessSafeKey, Error> {
+        error_if!(
+            header.len() != HEADER_SIZE || header[..MAGIC.len()] != MAGIC,
+            "Object {} was not written by EncryptionStore",
+            key.as_str()
+        );
+        error_if!(
+            header[MAGIC.len()] != FORMAT_VERSION,
+            "Object {} has unsupported EncryptionStore version {}",
+            key.as_str(),
+            header[MAGIC.len()]
+        );
+        let key_id = &header[MAGIC.len() + 1..HEADER_PREFIX_SIZE];
+        let master_key = std::iter::once(&self.master_key)
+            .chain(&self.previous_master_keys)
+            .find(|master_key| master_key.id == key_id)
+            .err_tip(|| {
+                format!(
+                    "Object {} was wrapped by an unknown master key {}",
+                    key.as_str(),
+                    hex::encode(key_id)
+                )
+            })?;
+
+        let mut nonce = [0; NONCE_LEN];
+        nonce.copy_from_slice(&header[HEADER_PREFIX_SIZE..HEADER_PREFIX_SIZE + NONCE_LEN]);
+        let mut aad = header[..HEADER_PREFIX_SIZE].to_vec();
+        aad.extend_from_slice(key.as_str().as_bytes());
+        let mut wrapped_key = header[HEADER_PREFIX_SIZE + NONCE_LEN..].to_vec();
+        let data_key = master_key
+            .key
+            .open_in_place(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(aad),
+                &mut wrapped_key,
+            )
+            .map_err(|_| {
+                self.decryption_failures.fetch_add(1, Ordering::Relaxed);
+                make_err!(
+                    Code::DataLoss,
+                    "Failed to unwrap the data key of {}",
+                    key.as_str()
+                )
+            })?;
+        aead_key(data_key)
+    }
+
+    async fn encrypt(
+        &self,
+        header: BytesMut,
+        data_key: LessSafeKey,
+        mut reader: DropCloserReadHalf,
+        mut tx: DropCloserWriteHalf,
+    ) -> Result<(), Error> {
+        tx.send(header.freeze())
+            .await
+            .err_tip(|| "Failed to write header in EncryptionStore::update")?;
+        let mut index = 0;
+        loop {
+            let chunk = reader
+                .consume(Some(CHUNK_SIZE))
+                .await
+                .err_tip(|| "Failed to read upload in EncryptionStore::update")?;
+            let is_final = chunk.len() < CHUNK_SIZE
+                || reader
+                    .peek()
+                    .await
+                    .err_tip(|| "Failed to read upload in EncryptionStore::update")?
+                    .is_empty();
+            let mut sealed = BytesMut::with_capacity(chunk.len() + TAG_SIZE);
+            sealed.extend_from_slice(&chunk);
+            data_key
+                .seal_in_place_append_tag(chunk_nonce(index, is_final), Aad::empty(), &mut sealed)
+                .map_err(|_| make_err!(Code::Internal, "Failed to encrypt chunk {index}"))?;
+            tx.send(sealed.freeze())
+                .await
+                .err_tip(|| "Failed to write chunk in EncryptionStore::update")?;
+            if is_final {
+                return tx
+                    .send_eof()
+                    .err_tip(|| "Failed to write EOF in EncryptionStore::update");
+            }
+            index += 1;
+        }
+    }
+
+    /// Decrypts the chunks from `first_chunk` up to the final chunk or
+    /// `last_chunk` from `rx` and writes the bytes between `offset` and
+    /// `end` to `writer`.
+    #[allow(clippy::too_many_arguments)]
+    async fn decrypt(
+        &self,
+        key: &StoreKey<'_>,
+        header: Option<Bytes>,
+        mut rx: DropCloserReadHalf,
+        writer: &mut DropCloserWriteHalf,
+        first_chunk: u64,
+        last_chunk: Option<u64>,
+        offset: u64,
+        end: Option<u64>,
+    ) -> Result<(), Error> {
+        let header = match header {
+            Some(header) => header,
+            None => rx
+                .consume(Some(HEADER_SIZE))
+                .await
+                .err_tip(|| "Failed to read header in EncryptionStore::get_part")?,
+        };
+        let data_key = self.open_header(key, &header)?;
+
+        let mut index = first_chunk;
+        loop {
+            let chunk = rx
+                .consume(Some(ENCRYPTED_CHUNK_SIZE))
+                .await
+                .err_tip(|| "Failed to read chunk in EncryptionStore::get_part")?;
+            if chunk.is_empty() && index == first_chunk && index > 0 {
+                // The read starts at the end of an object whose size is a
+                // multiple of `CHUNK_SIZE`, so the chunk before must be the
+                // final one.
+                self.check_final_chunk(key, &data_key, index - 1).await?;
+                break;
+            }
+            error_if!(
+                chunk.is_empty(),
+                "Object {} ended before its last chunk in EncryptionStore",
+                key.as_str()
+            );
+            // When the read was cut short after `last_chunk`, one more byte
+            // was requested to tell whether this chunk is the final one.
+            let is_final = chunk.len() < ENCRYPTED_CHUNK_SIZE
+                || rx
+                    .peek()
+                    .await
+                    .err_tip(|| "Failed to read chunk in EncryptionStore::get_part")?
+                    .is_empty();
+            let mut plaintext = BytesMut::from(&chunk[..]);
+            let plaintext_len = data_key
+                .open_in_place(chunk_nonce(index, is_final), Aad::empty(), &mut plaintext)
+                .map_err(|_| {
+                    self.decryption_failures.fetch_add(1, Ordering::Relaxed);
+                    make_err!(
+                        Code::DataLoss,
+                        "Failed to decrypt chunk {index} of {}",
+                        key.as_str()
+                    )
+                })?
+                .len();
+            plaintext.truncate(plaintext_len);
+
+            let chunk_start = index * CHUNK_SIZE as u64;
+            let start = usize::try_from(offset.saturating_sub(chunk_start))
+                .unwrap_or(usize::MAX)
+                .min(plaintext_len);
+            let stop = end.map_or(plaintext_len, |end| {
+                usize::try_from(end.saturating_sub(chunk_start))
+                    .unwrap_or(usize::MAX)
+                    .min(plaintext_len)
+            });
+            if start < stop {
+                writer
+                    .send(plaintext.freeze().slice(start..stop))
+                    .await
+                    .err_tip(|| "Failed to write data in EncryptionStore::get_part")?;
+            }
+            if is_final || Some(index) == last_chunk {
+                break;
+            }
+            index += 1;
+        }
+        rx.drain()
+            .await
+            .err_tip(|| "Failed to drain reader in EncryptionStore::get_part")?;
+        writer
+            .send_eof()
+            .err_tip(|| "Failed to write EOF in EncryptionStore::get_part")
+    }
+
+    /// Returns an error unless the chunk at `index` is a full chunk that
+    /// was sealed as the final one.
+    async fn check_final_chunk(
+        &self,
+        key: &StoreKey<'_>,
+        data_key: &LessSafeKey,
+        index: u64,
+    ) -> Result<(), Error> {
+        // One more byte tells whether another chunk follows.
+        let chunk = self
+            .inner_store
+            .get_part_unchunked(
+                key.borrow(),
+                chunk_offset(index),
+                Some(ENCRYPTED_CHUNK_SIZE as u64 + 1),
+            )
+            .await
+            .err_tip(|| "Failed to read chunk in EncryptionStore::get_part")?;
+        error_if!(
+            chunk.len() != ENCRYPTED_CHUNK_SIZE,
+            "Object {} ended before its last chunk in EncryptionStore",
+            key.as_str()
+        );
+        let mut sealed = BytesMut::from(&chunk[..]);
+        data_key
+            .open_in_place(chunk_nonce(index, true), Aad::empty(), &mut sealed)
+            .map_err(|_| {
+                self.decryption_failures.fetch_add(1, Ordering::Relaxed);
+                make_err!(
+                    Code::DataLoss,
+                    "Failed to decrypt chunk {index} of {}",
+                    key.as_str()
+                )
+            })?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StoreDriver for EncryptionStore {
+    async fn has_with_results(
+        self: Pin<&Self>,
+        keys: &[StoreKey<'_>],
+        results: &mut [Option<u64>],
+    ) -> Result<(), Error> {
+        self.inner_store
+            .has_with_results(keys, results)
+            .await
+            .err_tip(|| "In EncryptionStore::has_with_results")?;
+        for result in results.iter_mut() {
+            *result = result.and_then(decrypted_size);
+        }
+        Ok(())
+    }
+
+    async fn update(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        reader: DropCloserReadHalf,
+        size_info: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        let mut data_key = [0; KEY_SIZE];
+        self.rng
+            .fill(&mut data_key)
+            .map_err(|_| make_err!(Code::Internal, "Failed to generate data key"))?;
+        let header = self
+            .make_header(&key, &data_key)
+            .err_tip(|| "In EncryptionStore::update")?;
+        let data_key = aead_key(&data_key)?;
+        let inner_size_info = match size_info {
+            UploadSizeInfo::ExactSize(size) => UploadSizeInfo::ExactSize(encrypted_size(size)),
+            UploadSizeInfo::MaxSize(size) => UploadSizeInfo::MaxSize(encrypted_size(size)),
+        };
+
+        let (tx, rx) = make_buf_channel_pair();
+        let (encrypt_res, update_res) = join!(
+            self.encrypt(header, data_key, reader, tx),
+            self.inner_store.update(key.borrow(), rx, inner_size_info)
+        );
+        update_res
+            .merge(encrypt_res)
+            .err_tip(|| "In EncryptionStore::update")
+    }
+
+    async fn get_part(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        writer: &mut DropCloserWriteHalf,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<(), Error> {
+        // Special case for if a client tries to read zero bytes.
+        if length == Some(0) {
+            return writer
+                .send_eof()
+                .err_tip(|| "Failed to write EOF in EncryptionStore::get_part");
+        }
+        let first_chunk = offset / CHUNK_SIZE as u64;
+        let end = length.map(|length| offset.saturating_add(length));
+        let last_chunk = end.map(|end| end.saturating_sub(1).max(offset) / CHUNK_SIZE as u64);
+
+        // Reads starting in the first chunk get the header in the same
+        // request, others fetch it on its own first.
+        let (header, inner_offset) = if first_chunk == 0 {
+            (None, 0)
+        } else {
+            let header = self
+                .inner_store
+                .get_part_unchunked(key.borrow(), 0, Some(HEADER_SIZE as u64))
+                .await
+                .err_tip(|| "Failed to read header in EncryptionStore::get_part")?;
+            (Some(header), chunk_offset(first_chunk))
+        };
+        let inner_length =
+            last_chunk.map(|last_chunk| chunk_offset(last_chunk + 1) - inner_offset + 1);
+
+        let (tx, rx) = make_buf_channel_pair();
+        let (get_res, decrypt_res) = join!(
+            self.inner_store
+                .get_part(key.borrow(), tx, inner_offset, inner_length),
+            self.decrypt(
+                &key,
+                header,
+                rx,
+                writer,
+                first_chunk,
+                last_chunk,
+                offset,
+                end
+            )
+        );
+        decrypt_res
+            .merge(get_res)
+            .err_tip(|| "In EncryptionStore::get_part")
+    }
+
+    fn inner_store(&self, _key: Option<StoreKey>) -> &dyn StoreDriver {
+        self
+    }
+
+    fn as_any<'a>(&'a self) -> &'a (dyn std::any::Any + Sync + Send + 'static) {
+        self
+    }
+
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn std::any::Any + Sync + Send + 'static> {
+        self
+    }
+}
+
+default_health_status_indicator!(EncryptionStore);
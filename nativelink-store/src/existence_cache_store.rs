@@ -14,6 +14,7 @@
 
 use std::borrow::Cow;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::SystemTime;
 
@@ -28,6 +29,9 @@ use nativelink_util::health_utils::{HealthStatus, HealthStatusIndicator};
 use nativelink_util::instant_wrapper::InstantWrapper;
 use nativelink_util::store_trait::{Store, StoreDriver, StoreKey, StoreLike, UploadSizeInfo};
 
+/// Default for `negative_cache_max_count` when it is 0.
+const DEFAULT_NEGATIVE_CACHE_MAX_COUNT: u64 = 100_000;
+
 #[derive(Clone, Debug)]
 struct ExistanceItem(u64);
 
@@ -48,6 +52,10 @@ pub struct ExistenceCacheStore<I: InstantWrapper> {
     #[metric(group = "inner_store")]
     inner_store: Store,
     existence_cache: EvictingMap<DigestInfo, ExistanceItem, I>,
+    /// Digests the backend recently reported as missing, if enabled.
+    negative_cache: Option<EvictingMap<DigestInfo, ExistanceItem, I>>,
+    #[metric(help = "Number of lookups answered as missing from the negative cache")]
+    negative_cache_hits: AtomicU64,
 }
 
 impl ExistenceCacheStore<SystemTime> {
@@ -64,9 +72,26 @@ impl<I: InstantWrapper> ExistenceCacheStore<I> {
     ) -> Arc<Self> {
         let empty_policy = EvictionPolicy::default();
         let eviction_policy = spec.eviction_policy.as_ref().unwrap_or(&empty_policy);
+        let negative_cache = (spec.negative_cache_seconds != 0).then(|| {
+            let max_count = if spec.negative_cache_max_count == 0 {
+                DEFAULT_NEGATIVE_CACHE_MAX_COUNT
+            } else {
+                spec.negative_cache_max_count
+            };
+            EvictingMap::new(
+                &EvictionPolicy {
+                    max_seconds: spec.negative_cache_seconds,
+                    max_count,
+                    ..Default::default()
+                },
+                I::from_secs(anchor_time.unix_timestamp()),
+            )
+        });
         Arc::new(Self {
             inner_store,
             existence_cache: EvictingMap::new(eviction_policy, anchor_time),
+            negative_cache,
+            negative_cache_hits: AtomicU64::new(0),
         })
     }
 
@@ -80,6 +105,25 @@ impl<I: InstantWrapper> ExistenceCacheStore<I> {
 
     pub async fn remove_from_cache(&self, digest: &DigestInfo) {
         self.existence_cache.remove(digest).await;
+        self.forget_missing(digest).await;
+    }
+
+    /// Returns true if `digest` was recently reported missing by the backend.
+    pub async fn missing_in_cache(&self, digest: &DigestInfo) -> bool {
+        let Some(negative_cache) = &self.negative_cache else {
+            return false;
+        };
+        let mut results = [None];
+        negative_cache
+            .sizes_for_keys([digest], &mut results[..], true /* peek */)
+            .await;
+        results[0].is_some()
+    }
+
+    async fn forget_missing(&self, digest: &DigestInfo) {
+        if let Some(negative_cache) = &self.negative_cache {
+            negative_cache.remove(digest).await;
+        }
     }
 
     async fn inner_has_with_results(
@@ -91,10 +135,32 @@ impl<I: InstantWrapper> ExistenceCacheStore<I> {
             .sizes_for_keys(keys, results, true /* peek */)
             .await;
 
+        // Keys the backend recently reported as missing are not queried
+        // again until their entry expires.
+        let mut known_missing = vec![false; keys.len()];
+        if let Some(negative_cache) = &self.negative_cache {
+            let mut missing_results = vec![None; keys.len()];
+            negative_cache
+                .sizes_for_keys(keys, &mut missing_results, true /* peek */)
+                .await;
+            for ((known_missing, missing_result), result) in known_missing
+                .iter_mut()
+                .zip(missing_results)
+                .zip(results.iter())
+            {
+                *known_missing = result.is_none() && missing_result.is_some();
+            }
+            let hits = known_missing.iter().filter(|missing| **missing).count();
+            self.negative_cache_hits
+                .fetch_add(hits as u64, Ordering::Relaxed);
+        }
+
         let not_cached_keys: Vec<_> = keys
             .iter()
             .zip(results.iter())
-            .filter_map(|(digest, result)| result.map_or_else(|| Some(digest.into()), |_| None))
+            .zip(known_missing.iter())
+            .filter(|((_, result), known_missing)| result.is_none() && !**known_missing)
+            .map(|((digest, _), _)| digest.into())
             .collect();
 
         // Hot path optimization when all keys are cached.
@@ -123,13 +189,27 @@ impl<I: InstantWrapper> ExistenceCacheStore<I> {
             let _ = self.existence_cache.insert_many(inserts).await;
         }
 
+        // Remember the keys the backend does not have.
+        if let Some(negative_cache) = &self.negative_cache {
+            let inserts = not_cached_keys
+                .iter()
+                .zip(inner_results.iter())
+                .filter(|(_, result)| result.is_none())
+                .map(|(key, _)| (key.borrow().into_digest(), ExistanceItem(0)))
+                .collect::<Vec<_>>();
+            if !inserts.is_empty() {
+                let _ = negative_cache.insert_many(inserts).await;
+            }
+        }
+
         // Merge the results from the cache and the query.
         {
             let mut inner_results_iter = inner_results.into_iter();
-            // We know at this point that any None in results was queried and will have
-            // a result in inner_results_iter, so use this knowledge to fill in the results.
-            for result in results.iter_mut() {
-                if result.is_none() {
+            // We know at this point that any None in results that is not known to be
+            // missing was queried and will have a result in inner_results_iter, so use
+            // this knowledge to fill in the results.
+            for (result, known_missing) in results.iter_mut().zip(known_missing) {
+                if result.is_none() && !known_missing {
                     *result = inner_results_iter
                         .next()
                         .expect("has_with_results returned less results than expected");
@@ -186,6 +266,7 @@ impl<I: InstantWrapper> StoreDriver for ExistenceCacheStore<I> {
         }
         let result = self.inner_store.update(digest, reader, size_info).await;
         if result.is_ok() {
+            self.forget_missing(&digest).await;
             if let UploadSizeInfo::ExactSize(size) = size_info {
                 let _ = self
                     .existence_cache
@@ -209,6 +290,7 @@ impl<I: InstantWrapper> StoreDriver for ExistenceCacheStore<I> {
             .get_part(digest, writer, offset, length)
             .await;
         if result.is_ok() {
+            self.forget_missing(&digest).await;
             let _ = self
                 .existence_cache
                 .insert(digest, ExistanceItem(digest.size_bytes()))
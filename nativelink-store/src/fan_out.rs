@@ -0,0 +1,111 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use futures::future::join_all;
+use futures::join;
+use nativelink_error::{error_if, Error, ResultExt};
+use nativelink_util::buf_channel::{
+    make_buf_channel_pair, DropCloserReadHalf, DropCloserWriteHalf,
+};
+use nativelink_util::store_trait::{Store, StoreKey, StoreLike, UploadSizeInfo};
+
+/// Uploads the data of `reader` to all of `stores` at once. The upload is
+/// aborted once fewer than `write_quorum` stores are receiving data.
+///
+/// Returns the result of reading `reader` and sending its data, and the
+/// result of the upload to each store in the order of `stores`.
+pub(crate) async fn update_all(
+    stores: &[&Store],
+    key: StoreKey<'_>,
+    reader: DropCloserReadHalf,
+    size_info: UploadSizeInfo,
+    write_quorum: usize,
+) -> (Result<(), Error>, Vec<Result<(), Error>>) {
+    let (txs, rxs): (Vec<_>, Vec<_>) = stores
+        .iter()
+        .map(|_| {
+            let (tx, rx) = make_buf_channel_pair();
+            (Some(tx), rx)
+        })
+        .unzip();
+
+    let data_stream_fut = tee_to_quorum(reader, txs, write_quorum);
+    let store_futs = join_all(
+        stores
+            .iter()
+            .zip(rxs)
+            .enumerate()
+            .map(|(index, (store, rx))| {
+                let key = key.borrow();
+                async move {
+                    store
+                        .update(key, rx, size_info)
+                        .await
+                        .err_tip(|| format!("In update of store {index}"))
+                }
+            }),
+    );
+    join!(data_stream_fut, store_futs)
+}
+
+/// Sends the data of `reader` to every writer. Writers that fail are
+/// dropped, which fails the upload to their store. Fails once fewer than
+/// `write_quorum` writers are left.
+async fn tee_to_quorum(
+    mut reader: DropCloserReadHalf,
+    mut writers: Vec<Option<DropCloserWriteHalf>>,
+    write_quorum: usize,
+) -> Result<(), Error> {
+    loop {
+        let chunk = reader
+            .recv()
+            .await
+            .err_tip(|| "In fan_out::tee_to_quorum reading upload")?;
+        if chunk.is_empty() {
+            for writer in &mut writers {
+                if writer.as_mut().is_some_and(|w| w.send_eof().is_err()) {
+                    *writer = None;
+                }
+            }
+        } else {
+            // Read one message ahead so an error on the EOF message is
+            // forwarded before the last payload message.
+            reader
+                .peek()
+                .await
+                .err_tip(|| "In fan_out::tee_to_quorum reading upload")?;
+            let send_results = join_all(
+                writers
+                    .iter_mut()
+                    .flatten()
+                    .map(|writer| writer.send(chunk.clone())),
+            )
+            .await;
+            let mut send_results = send_results.into_iter();
+            for writer in &mut writers {
+                if writer.is_some() && send_results.next().is_some_and(|r| r.is_err()) {
+                    *writer = None;
+                }
+            }
+        }
+        let remaining = writers.iter().flatten().count();
+        error_if!(
+            remaining < write_quorum,
+            "Only {remaining} stores are left in fan_out::tee_to_quorum, but write_quorum is {write_quorum}"
+        );
+        if chunk.is_empty() {
+            return Ok(());
+        }
+    }
+}
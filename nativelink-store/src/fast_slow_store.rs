@@ -14,6 +14,7 @@
 
 use std::borrow::BorrowMut;
 use std::cmp::{max, min};
+use std::collections::{HashMap, HashSet};
 use std::ops::Range;
 use std::pin::Pin;
 use std::sync::atomic::{AtomicU64, Ordering};
@@ -21,18 +22,88 @@ use std::sync::{Arc, Weak};
 
 use async_trait::async_trait;
 use futures::{join, FutureExt};
-use nativelink_config::stores::FastSlowSpec;
-use nativelink_error::{make_err, Code, Error, ResultExt};
+use nativelink_config::stores::{
+    FastSlowPopulateMode, FastSlowPopulationSpec, FastSlowSpec, PopularitySpec,
+};
+use nativelink_error::{make_err, make_input_err, Code, Error, ResultExt};
 use nativelink_metric::MetricsComponent;
+use nativelink_util::background_spawn;
 use nativelink_util::buf_channel::{
     make_buf_channel_pair, DropCloserReadHalf, DropCloserWriteHalf,
 };
 use nativelink_util::fs;
 use nativelink_util::health_utils::{default_health_status_indicator, HealthStatusIndicator};
+use nativelink_util::popularity_tracker::{popularity_tracker, PopularityTracker};
 use nativelink_util::store_trait::{
     slow_update_store_with_file, Store, StoreDriver, StoreKey, StoreLike, StoreOptimizations,
     UploadSizeInfo,
 };
+use parking_lot::Mutex;
+use tokio::sync::{mpsc, watch, Semaphore};
+use tracing::{event, Level};
+
+use crate::filesystem_store::{EvictedFile, FilesystemStore};
+
+/// Name of the popularity tracker if none is configured.
+const DEFAULT_TRACKER_NAME: &str = "fast_slow";
+/// Default number of counters per row of the popularity sketch.
+const DEFAULT_SKETCH_WIDTH: usize = 64 * 1024;
+/// Default number of hottest objects remembered for the admin API.
+const DEFAULT_MAX_HOTTEST: usize = 100;
+/// Default number of objects copied into the fast store in the background
+/// at the same time.
+const DEFAULT_MAX_CONCURRENT_POPULATE_JOBS: usize = 16;
+/// Default number of objects waiting to be copied into the fast store.
+const DEFAULT_MAX_QUEUED_POPULATE_JOBS: usize = 1024;
+/// Number of objects evicted from the fast store copied into the slow
+/// store at the same time when `write_back` is set.
+const MAX_CONCURRENT_WRITE_BACKS: usize = 16;
+
+/// Read counts used to decide which objects are copied into the fast
+/// store.
+struct Popularity {
+    tracker: Arc<PopularityTracker>,
+    min_reads_to_promote: u64,
+}
+
+impl Popularity {
+    fn new(spec: &PopularitySpec) -> Self {
+        let tracker_name = if spec.tracker_name.is_empty() {
+            DEFAULT_TRACKER_NAME
+        } else {
+            &spec.tracker_name
+        };
+        let sketch_width = if spec.sketch_width == 0 {
+            DEFAULT_SKETCH_WIDTH
+        } else {
+            spec.sketch_width
+        };
+        let decay_after_reads = if spec.decay_after_reads == 0 {
+            sketch_width as u64 * 10
+        } else {
+            spec.decay_after_reads
+        };
+        let max_hottest = if spec.max_hottest == 0 {
+            DEFAULT_MAX_HOTTEST
+        } else {
+            spec.max_hottest
+        };
+        Self {
+            tracker: popularity_tracker(tracker_name, || {
+                PopularityTracker::new(sketch_width, decay_after_reads, max_hottest)
+            }),
+            min_reads_to_promote: spec.min_reads_to_promote,
+        }
+    }
+}
+
+/// Objects waiting to be copied into the fast store in the background.
+struct PopulateQueue {
+    tx: mpsc::Sender<StoreKey<'static>>,
+    /// Keys that are queued or being copied, so every object is only
+    /// queued once.
+    pending: Mutex<HashSet<StoreKey<'static>>>,
+}
 
 // TODO(blaise.bruer) This store needs to be evaluated for more efficient memory usage,
 // there are many copies happening internally.
@@ -47,21 +118,260 @@ pub struct FastSlowStore {
     fast_store: Store,
     #[metric(group = "slow_store")]
     slow_store: Store,
+    popularity: Option<Popularity>,
+    populate_on_read: FastSlowPopulateMode,
+    populate_on_write: FastSlowPopulateMode,
+    /// Set if objects are copied into the fast store in the background.
+    populate_queue: Option<PopulateQueue>,
+    #[metric(help = "If uploads are only written to the fast store")]
+    write_back: bool,
+    /// Objects evicted from the fast store that are being copied into the
+    /// slow store. The sender is dropped once the copy is done.
+    pending_write_backs: Mutex<HashMap<StoreKey<'static>, watch::Sender<()>>>,
     weak_self: Weak<Self>,
     #[metric]
     metrics: FastSlowStoreMetrics,
 }
 
 impl FastSlowStore {
-    pub fn new(_spec: &FastSlowSpec, fast_store: Store, slow_store: Store) -> Arc<Self> {
-        Arc::new_cyclic(|weak_self| Self {
+    pub fn new(
+        spec: &FastSlowSpec,
+        fast_store: Store,
+        slow_store: Store,
+    ) -> Result<Arc<Self>, Error> {
+        let evictions_rx = if spec.write_back {
+            let filesystem_store = fast_store
+                .downcast_ref::<FilesystemStore>(None)
+                .ok_or_else(|| {
+                    make_input_err!(
+                        "The fast store of a FastSlowStore with write_back must be a filesystem store"
+                    )
+                })?;
+            Some(filesystem_store.subscribe_evictions()?)
+        } else {
+            None
+        };
+        let population = &spec.population;
+        let store = Arc::new_cyclic(|weak_self| Self {
             fast_store,
             slow_store,
+            popularity: spec.popularity.as_ref().map(Popularity::new),
+            populate_on_read: population.on_read,
+            populate_on_write: population.on_write,
+            populate_queue: Self::start_populate_queue(population, weak_self.clone()),
+            write_back: spec.write_back,
+            pending_write_backs: Mutex::new(HashMap::new()),
             weak_self: weak_self.clone(),
             metrics: FastSlowStoreMetrics::default(),
+        });
+        if let Some(evictions_rx) = evictions_rx {
+            Self::start_write_back(evictions_rx, Arc::downgrade(&store));
+        }
+        Ok(store)
+    }
+
+    /// Copies the objects evicted from the fast store into the slow store.
+    fn start_write_back(
+        mut evictions_rx: mpsc::UnboundedReceiver<EvictedFile>,
+        weak_self: Weak<Self>,
+    ) {
+        background_spawn!("fast_slow_store_write_back_queue", async move {
+            let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_WRITE_BACKS));
+            while let Some(evicted_file) = evictions_rx.recv().await {
+                let Some(store) = weak_self.upgrade() else {
+                    return;
+                };
+                let key = evicted_file.key().into_owned();
+                let (done_tx, _) = watch::channel(());
+                store
+                    .pending_write_backs
+                    .lock()
+                    .insert(key.clone(), done_tx);
+                drop(store);
+                let Ok(permit) = semaphore.clone().acquire_owned().await else {
+                    return;
+                };
+                let Some(store) = weak_self.upgrade() else {
+                    return;
+                };
+                background_spawn!("fast_slow_store_write_back", async move {
+                    if let Err(err) = store.write_back(&evicted_file).await {
+                        store
+                            .metrics
+                            .write_back_failures
+                            .fetch_add(1, Ordering::Acquire);
+                        event!(
+                            Level::ERROR,
+                            ?err,
+                            key = %key.as_str(),
+                            "Failed to copy object evicted from fast store into slow store"
+                        );
+                    }
+                    store.pending_write_backs.lock().remove(&key);
+                    drop(permit);
+                });
+            }
+        });
+    }
+
+    /// Copies a file evicted from the fast store into the slow store unless
+    /// the slow store already has it.
+    async fn write_back(&self, evicted_file: &EvictedFile) -> Result<(), Error> {
+        let key = evicted_file.key();
+        // The object was uploaded again since it was evicted, it will be
+        // copied once the new version is evicted.
+        if self.fast_store.has(key.borrow()).await?.is_some() {
+            return Ok(());
+        }
+        // Objects with string keys can change, so they are always copied.
+        if matches!(key, StoreKey::Digest(_)) && self.slow_store.has(key.borrow()).await?.is_some()
+        {
+            return Ok(());
+        }
+        let mut file = evicted_file.open().await?;
+        slow_update_store_with_file(
+            self.slow_store.as_store_driver_pin(),
+            key,
+            &mut file,
+            UploadSizeInfo::ExactSize(evicted_file.data_size()),
+        )
+        .await
+        .err_tip(|| "In FastSlowStore::write_back")?;
+        self.metrics
+            .write_back_bytes
+            .fetch_add(evicted_file.data_size(), Ordering::Acquire);
+        Ok(())
+    }
+
+    /// Waits until `key` is copied into the slow store if it was evicted
+    /// from the fast store and is not there yet.
+    async fn wait_for_write_back(&self, key: &StoreKey<'_>) {
+        if !self.write_back {
+            return;
+        }
+        let Some(mut done_rx) = self
+            .pending_write_backs
+            .lock()
+            .get(&key.borrow().into_owned())
+            .map(watch::Sender::subscribe)
+        else {
+            return;
+        };
+        // Returns an error once the sender is dropped.
+        while done_rx.changed().await.is_ok() {}
+    }
+
+    /// Starts copying queued objects into the fast store if any objects
+    /// are configured to be copied in the background.
+    fn start_populate_queue(
+        population: &FastSlowPopulationSpec,
+        weak_self: Weak<Self>,
+    ) -> Option<PopulateQueue> {
+        if population.on_read != FastSlowPopulateMode::background
+            && population.on_write != FastSlowPopulateMode::background
+        {
+            return None;
+        }
+        let max_concurrent_jobs = if population.max_concurrent_jobs == 0 {
+            DEFAULT_MAX_CONCURRENT_POPULATE_JOBS
+        } else {
+            population.max_concurrent_jobs
+        };
+        let max_queued_jobs = if population.max_queued_jobs == 0 {
+            DEFAULT_MAX_QUEUED_POPULATE_JOBS
+        } else {
+            population.max_queued_jobs
+        };
+        let (tx, mut rx) = mpsc::channel::<StoreKey<'static>>(max_queued_jobs);
+        background_spawn!("fast_slow_store_populate_queue", async move {
+            let semaphore = Arc::new(Semaphore::new(max_concurrent_jobs));
+            // Ends once the store, which owns the sender, is dropped.
+            while let Some(key) = rx.recv().await {
+                let Ok(permit) = semaphore.clone().acquire_owned().await else {
+                    return;
+                };
+                let Some(store) = weak_self.upgrade() else {
+                    return;
+                };
+                background_spawn!("fast_slow_store_populate", async move {
+                    if let Err(err) = store.copy_to_fast_store(key.borrow()).await {
+                        store
+                            .metrics
+                            .background_populate_failures
+                            .fetch_add(1, Ordering::Acquire);
+                        event!(
+                            Level::WARN,
+                            ?err,
+                            key = %key.as_str(),
+                            "Failed to copy object into fast store in the background"
+                        );
+                    }
+                    if let Some(queue) = &store.populate_queue {
+                        queue.pending.lock().remove(&key);
+                    }
+                    drop(permit);
+                });
+            }
+        });
+        Some(PopulateQueue {
+            tx,
+            pending: Mutex::new(HashSet::new()),
         })
     }
 
+    /// Queues `key` to be copied from the slow store into the fast store
+    /// in the background. The object is not copied if the queue is full.
+    fn queue_population(&self, key: StoreKey<'_>) {
+        let Some(queue) = &self.populate_queue else {
+            return;
+        };
+        let key = key.into_owned();
+        if !queue.pending.lock().insert(key.clone()) {
+            return; // Already queued.
+        }
+        if queue.tx.try_send(key.clone()).is_ok() {
+            self.metrics
+                .background_populate_queued
+                .fetch_add(1, Ordering::Acquire);
+        } else {
+            queue.pending.lock().remove(&key);
+            self.metrics
+                .background_populate_dropped
+                .fetch_add(1, Ordering::Acquire);
+        }
+    }
+
+    /// Copies `key` from the slow store into the fast store unless the
+    /// fast store already has it. Unlike reads this does not count towards
+    /// the popularity of the object.
+    async fn copy_to_fast_store(&self, key: StoreKey<'_>) -> Result<(), Error> {
+        if self.fast_store.has(key.borrow()).await?.is_some() {
+            return Ok(());
+        }
+        let sz = self
+            .slow_store
+            .has(key.borrow())
+            .await
+            .err_tip(|| "Failed to run has() on slow store")?
+            .ok_or_else(|| {
+                make_err!(
+                    Code::NotFound,
+                    "Object {} not found in slow store",
+                    key.as_str()
+                )
+            })?;
+        let (tx, rx) = make_buf_channel_pair();
+        let (slow_res, fast_res) = join!(
+            self.slow_store.get(key.borrow(), tx),
+            self.fast_store
+                .update(key.borrow(), rx, UploadSizeInfo::ExactSize(sz))
+        );
+        self.metrics
+            .slow_store_downloaded_bytes
+            .fetch_add(sz, Ordering::Acquire);
+        slow_res.merge(fast_res)
+    }
+
     pub fn fast_store(&self) -> &Store {
         &self.fast_store
     }
@@ -70,6 +380,15 @@ impl FastSlowStore {
         &self.slow_store
     }
 
+    /// Returns the tracker counting the reads of this store, if
+    /// popularity tracking is enabled. Wrappers can use it to keep
+    /// popular objects around.
+    pub fn popularity_tracker(&self) -> Option<&Arc<PopularityTracker>> {
+        self.popularity
+            .as_ref()
+            .map(|popularity| &popularity.tracker)
+    }
+
     pub fn get_arc(&self) -> Option<Arc<Self>> {
         self.weak_self.upgrade()
     }
@@ -90,13 +409,147 @@ impl FastSlowStore {
         // TODO(blaise.bruer) This is extremely inefficient, since we are just trying
         // to send the stream to /dev/null. Maybe we could instead make a version of
         // the stream that can send to the drain more efficiently?
-        let (tx, mut rx) = make_buf_channel_pair();
+        let (mut tx, mut rx) = make_buf_channel_pair();
         let drain_fut = async move {
             while !rx.recv().await?.is_empty() {}
             Ok(())
         };
-        let (drain_res, get_res) = join!(drain_fut, StoreDriver::get(Pin::new(self), key, tx));
-        get_res.err_tip(|| "Failed to populate()").merge(drain_res)
+        let get_fut = async move {
+            self.get_part_with_promotion(key, &mut tx, 0, None, true)
+                .await
+                .err_tip(|| "Failed to populate()")
+        };
+        let (drain_res, get_res) = join!(drain_fut, get_fut);
+        get_res.merge(drain_res)
+    }
+
+    /// Sends the requested data to `writer`. Data read from the slow store
+    /// is copied into the fast store if `always_promote` is set or the
+    /// object is popular enough.
+    async fn get_part_with_promotion(
+        &self,
+        key: StoreKey<'_>,
+        writer: &mut DropCloserWriteHalf,
+        offset: u64,
+        length: Option<u64>,
+        always_promote: bool,
+    ) -> Result<(), Error> {
+        let reads = self
+            .popularity
+            .as_ref()
+            .map(|popularity| (popularity.tracker.record_read(&key), popularity));
+        // TODO(blaise.bruer) Investigate if we should maybe ignore errors here instead of
+        // forwarding the up.
+        if self.fast_store.has(key.borrow()).await?.is_some() {
+            self.metrics
+                .fast_store_hit_count
+                .fetch_add(1, Ordering::Acquire);
+            self.fast_store
+                .get_part(key, writer.borrow_mut(), offset, length)
+                .await?;
+            self.metrics
+                .fast_store_downloaded_bytes
+                .fetch_add(writer.get_bytes_written(), Ordering::Acquire);
+            return Ok(());
+        }
+
+        self.wait_for_write_back(&key).await;
+        let sz = self
+            .slow_store
+            .has(key.borrow())
+            .await
+            .err_tip(|| "Failed to run has() on slow store")?
+            .ok_or_else(|| {
+                make_err!(
+                    Code::NotFound,
+                    "Object {} not found in either fast or slow store",
+                    key.as_str()
+                )
+            })?;
+        self.metrics
+            .slow_store_hit_count
+            .fetch_add(1, Ordering::Acquire);
+
+        let popular =
+            reads.is_none_or(|(reads, popularity)| reads >= popularity.min_reads_to_promote);
+        if !always_promote && (!popular || self.populate_on_read != FastSlowPopulateMode::inline) {
+            if !popular {
+                self.metrics
+                    .slow_store_unpromoted_count
+                    .fetch_add(1, Ordering::Acquire);
+            }
+            self.slow_store
+                .get_part(key.borrow(), writer.borrow_mut(), offset, length)
+                .await?;
+            self.metrics
+                .slow_store_downloaded_bytes
+                .fetch_add(writer.get_bytes_written(), Ordering::Acquire);
+            if popular && self.populate_on_read == FastSlowPopulateMode::background {
+                self.queue_population(key);
+            }
+            return Ok(());
+        }
+
+        let send_range = offset..length.map_or(u64::MAX, |length| length + offset);
+        let mut bytes_received: u64 = 0;
+
+        let (mut fast_tx, fast_rx) = make_buf_channel_pair();
+        let (slow_tx, mut slow_rx) = make_buf_channel_pair();
+        let data_stream_fut = async move {
+            let mut writer_pin = Pin::new(writer);
+            loop {
+                let output_buf = slow_rx
+                    .recv()
+                    .await
+                    .err_tip(|| "Failed to read data data buffer from slow store")?;
+                if output_buf.is_empty() {
+                    // Write out our EOF.
+                    // We are dropped as soon as we send_eof to writer_pin, so
+                    // we wait until we've finished all of our joins to do that.
+                    let fast_res = fast_tx.send_eof();
+                    return Ok::<_, Error>((fast_res, writer_pin));
+                }
+                let output_buf_len = u64::try_from(output_buf.len())
+                    .err_tip(|| "Could not output_buf.len() to u64")?;
+                self.metrics
+                    .slow_store_downloaded_bytes
+                    .fetch_add(output_buf_len, Ordering::Acquire);
+
+                let writer_fut = if let Some(range) = Self::calculate_range(
+                    &(bytes_received..bytes_received + output_buf_len),
+                    &send_range,
+                )? {
+                    writer_pin.send(output_buf.slice(range)).right_future()
+                } else {
+                    futures::future::ready(Ok(())).left_future()
+                };
+                bytes_received += output_buf_len;
+
+                let (fast_tx_res, writer_res) = join!(fast_tx.send(output_buf), writer_fut);
+                fast_tx_res.err_tip(|| "Failed to write to fast store in fast_slow store")?;
+                writer_res.err_tip(|| "Failed to write result to writer in fast_slow store")?;
+            }
+        };
+
+        let slow_store_fut = self.slow_store.get(key.borrow(), slow_tx);
+        let fast_store_fut =
+            self.fast_store
+                .update(key.borrow(), fast_rx, UploadSizeInfo::ExactSize(sz));
+
+        let (data_stream_res, slow_res, fast_res) =
+            join!(data_stream_fut, slow_store_fut, fast_store_fut);
+        match data_stream_res {
+            Ok((fast_eof_res, mut writer_pin)) =>
+            // Sending the EOF will drop us almost immediately in bytestream_server
+            // so we perform it as the very last action in this method.
+            {
+                fast_eof_res
+                    .merge(fast_res)
+                    .merge(slow_res)
+                    .merge(writer_pin.send_eof())
+            }
+            Err(err) => fast_res.merge(slow_res).merge(Err(err)),
+        }
     }
 
     /// Returns the range of bytes that should be sent given a slice bounds
@@ -140,6 +593,31 @@ impl StoreDriver for FastSlowStore {
         if slow_store.optimized_for(StoreOptimizations::NoopDownloads) {
             return self.fast_store.has_with_results(key, results).await;
         }
+        if self.write_back {
+            // Objects are only in the fast store until they are evicted.
+            self.fast_store.has_with_results(key, results).await?;
+            let (missing_keys, missing_indexes): (Vec<_>, Vec<_>) = key
+                .iter()
+                .zip(results.iter())
+                .enumerate()
+                .filter(|(_, (_, result))| result.is_none())
+                .map(|(i, (key, _))| (key.borrow(), i))
+                .unzip();
+            if missing_keys.is_empty() {
+                return Ok(());
+            }
+            for key in &missing_keys {
+                self.wait_for_write_back(key).await;
+            }
+            let mut slow_results = vec![None; missing_keys.len()];
+            self.slow_store
+                .has_with_results(&missing_keys, &mut slow_results)
+                .await?;
+            for (i, result) in missing_indexes.into_iter().zip(slow_results) {
+                results[i] = result;
+            }
+            return Ok(());
+        }
         // Only check the slow store because if it's not there, then something
         // down stream might be unable to get it.  This should not affect
         // workers as they only use get() and a CAS can use an
@@ -163,45 +641,27 @@ impl StoreDriver for FastSlowStore {
         if fast_store.optimized_for(StoreOptimizations::NoopUpdates) {
             return self.slow_store.update(key, reader, size_info).await;
         }
+        if self.write_back {
+            return self.fast_store.update(key, reader, size_info).await;
+        }
+        if self.populate_on_write != FastSlowPopulateMode::inline {
+            self.slow_store
+                .update(key.borrow(), reader, size_info)
+                .await?;
+            if self.populate_on_write == FastSlowPopulateMode::background {
+                self.queue_population(key);
+            }
+            return Ok(());
+        }
 
-        let (mut fast_tx, fast_rx) = make_buf_channel_pair();
-        let (mut slow_tx, slow_rx) = make_buf_channel_pair();
+        let (fast_tx, fast_rx) = make_buf_channel_pair();
+        let (slow_tx, slow_rx) = make_buf_channel_pair();
 
         let data_stream_fut = async move {
-            loop {
-                let buffer = reader
-                    .recv()
-                    .await
-                    .err_tip(|| "Failed to read buffer in fastslow store")?;
-                if buffer.is_empty() {
-                    // EOF received.
-                    fast_tx.send_eof().err_tip(|| {
-                        "Failed to write eof to fast store in fast_slow store update"
-                    })?;
-                    slow_tx
-                        .send_eof()
-                        .err_tip(|| "Failed to write eof to writer in fast_slow store update")?;
-                    return Result::<(), Error>::Ok(());
-                }
-
-                let (fast_result, slow_result) =
-                    join!(fast_tx.send(buffer.clone()), slow_tx.send(buffer));
-                fast_result
-                    .map_err(|e| {
-                        make_err!(
-                            Code::Internal,
-                            "Failed to send message to fast_store in fast_slow_store {:?}",
-                            e
-                        )
-                    })
-                    .merge(slow_result.map_err(|e| {
-                        make_err!(
-                            Code::Internal,
-                            "Failed to send message to slow_store in fast_slow store {:?}",
-                            e
-                        )
-                    }))?;
-            }
+            reader
+                .tee(&mut [fast_tx, slow_tx])
+                .await
+                .err_tip(|| "In FastSlowStore::update sending to fast and slow stores")
         };
 
         let fast_store_fut = self.fast_store.update(key.borrow(), fast_rx, size_info);
@@ -227,6 +687,28 @@ impl StoreDriver for FastSlowStore {
         mut file: fs::ResumeableFileSlot,
         upload_size: UploadSizeInfo,
     ) -> Result<Option<fs::ResumeableFileSlot>, Error> {
+        if self.write_back {
+            return self
+                .fast_store
+                .update_with_whole_file(key, file, upload_size)
+                .await;
+        }
+        if self.populate_on_write != FastSlowPopulateMode::inline
+            && !self
+                .slow_store
+                .optimized_for(StoreOptimizations::NoopUpdates)
+        {
+            let maybe_file = self
+                .slow_store
+                .update_with_whole_file(key.borrow(), file, upload_size)
+                .await
+                .err_tip(|| "In FastSlowStore::update_with_whole_file slow_store")?;
+            if self.populate_on_write == FastSlowPopulateMode::background {
+                self.queue_population(key);
+            }
+            return Ok(maybe_file);
+        }
+
         if self
             .fast_store
             .optimized_for(StoreOptimizations::FileUpdates)
@@ -286,97 +768,8 @@ impl StoreDriver for FastSlowStore {
         offset: u64,
         length: Option<u64>,
     ) -> Result<(), Error> {
-        // TODO(blaise.bruer) Investigate if we should maybe ignore errors here instead of
-        // forwarding the up.
-        if self.fast_store.has(key.borrow()).await?.is_some() {
-            self.metrics
-                .fast_store_hit_count
-                .fetch_add(1, Ordering::Acquire);
-            self.fast_store
-                .get_part(key, writer.borrow_mut(), offset, length)
-                .await?;
-            self.metrics
-                .fast_store_downloaded_bytes
-                .fetch_add(writer.get_bytes_written(), Ordering::Acquire);
-            return Ok(());
-        }
-
-        let sz = self
-            .slow_store
-            .has(key.borrow())
+        self.get_part_with_promotion(key, writer, offset, length, false)
             .await
-            .err_tip(|| "Failed to run has() on slow store")?
-            .ok_or_else(|| {
-                make_err!(
-                    Code::NotFound,
-                    "Object {} not found in either fast or slow store",
-                    key.as_str()
-                )
-            })?;
-        self.metrics
-            .slow_store_hit_count
-            .fetch_add(1, Ordering::Acquire);
-
-        let send_range = offset..length.map_or(u64::MAX, |length| length + offset);
-        let mut bytes_received: u64 = 0;
-
-        let (mut fast_tx, fast_rx) = make_buf_channel_pair();
-        let (slow_tx, mut slow_rx) = make_buf_channel_pair();
-        let data_stream_fut = async move {
-            let mut writer_pin = Pin::new(writer);
-            loop {
-                let output_buf = slow_rx
-                    .recv()
-                    .await
-                    .err_tip(|| "Failed to read data data buffer from slow store")?;
-                if output_buf.is_empty() {
-                    // Write out our EOF.
-                    // We are dropped as soon as we send_eof to writer_pin, so
-                    // we wait until we've finished all of our joins to do that.
-                    let fast_res = fast_tx.send_eof();
-                    return Ok::<_, Error>((fast_res, writer_pin));
-                }
-                let output_buf_len = u64::try_from(output_buf.len())
-                    .err_tip(|| "Could not output_buf.len() to u64")?;
-                self.metrics
-                    .slow_store_downloaded_bytes
-                    .fetch_add(output_buf_len, Ordering::Acquire);
-
-                let writer_fut = if let Some(range) = Self::calculate_range(
-                    &(bytes_received..bytes_received + output_buf_len),
-                    &send_range,
-                )? {
-                    writer_pin.send(output_buf.slice(range)).right_future()
-                } else {
-                    futures::future::ready(Ok(())).left_future()
-                };
-                bytes_received += output_buf_len;
-
-                let (fast_tx_res, writer_res) = join!(fast_tx.send(output_buf), writer_fut);
-                fast_tx_res.err_tip(|| "Failed to write to fast store in fast_slow store")?;
-                writer_res.err_tip(|| "Failed to write result to writer in fast_slow store")?;
-            }
-        };
-
-        let slow_store_fut = self.slow_store.get(key.borrow(), slow_tx);
-        let fast_store_fut =
-            self.fast_store
-                .update(key.borrow(), fast_rx, UploadSizeInfo::ExactSize(sz));
-
-        let (data_stream_res, slow_res, fast_res) =
-            join!(data_stream_fut, slow_store_fut, fast_store_fut);
-        match data_stream_res {
-            Ok((fast_eof_res, mut writer_pin)) =>
-            // Sending the EOF will drop us almost immediately in bytestream_server
-            // so we perform it as the very last action in this method.
-            {
-                fast_eof_res
-                    .merge(fast_res)
-                    .merge(slow_res)
-                    .merge(writer_pin.send_eof())
-            }
-            Err(err) => fast_res.merge(slow_res).merge(Err(err)),
-        }
     }
 
     fn inner_store(&self, _key: Option<StoreKey>) -> &dyn StoreDriver {
@@ -402,6 +795,20 @@ struct FastSlowStoreMetrics {
     slow_store_hit_count: AtomicU64,
     #[metric(help = "Downloaded bytes from the slow store")]
     slow_store_downloaded_bytes: AtomicU64,
+    #[metric(help = "Slow store hits not copied to the fast store as they were not popular")]
+    slow_store_unpromoted_count: AtomicU64,
+    #[metric(help = "Objects queued to be copied to the fast store in the background")]
+    background_populate_queued: AtomicU64,
+    #[metric(help = "Objects not copied to the fast store because the queue was full")]
+    background_populate_dropped: AtomicU64,
+    #[metric(help = "Objects that failed to be copied to the fast store in the background")]
+    background_populate_failures: AtomicU64,
+    #[metric(help = "Bytes copied to the slow store when evicted from the fast store")]
+    write_back_bytes: AtomicU64,
+    #[metric(
+        help = "Objects evicted from the fast store that failed to be copied to the slow store"
+    )]
+    write_back_failures: AtomicU64,
 }
 
 default_health_status_indicator!(FastSlowStore);
@@ -13,33 +13,38 @@
 // limitations under the License.
 
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::ffi::{OsStr, OsString};
 use std::fmt::{Debug, Formatter};
+use std::io::Write;
 use std::pin::Pin;
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::{Arc, Weak};
-use std::time::{Duration, SystemTime};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock, Weak};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use async_lock::RwLock;
 use async_trait::async_trait;
-use bytes::BytesMut;
+use bytes::{Bytes, BytesMut};
 use filetime::{set_file_atime, FileTime};
 use futures::stream::{StreamExt, TryStreamExt};
-use futures::{Future, TryFutureExt};
+use futures::{try_join, Future, TryFutureExt};
 use nativelink_config::stores::FilesystemSpec;
 use nativelink_error::{make_err, make_input_err, Code, Error, ResultExt};
 use nativelink_metric::MetricsComponent;
 use nativelink_util::buf_channel::{
     make_buf_channel_pair, DropCloserReadHalf, DropCloserWriteHalf,
 };
-use nativelink_util::common::{fs, DigestInfo};
+use nativelink_util::common::{fs, DigestInfo, PackedHash};
 use nativelink_util::evicting_map::{EvictingMap, LenEntry};
 use nativelink_util::health_utils::{HealthRegistryBuilder, HealthStatus, HealthStatusIndicator};
 use nativelink_util::store_trait::{
-    StoreDriver, StoreKey, StoreKeyBorrow, StoreOptimizations, UploadSizeInfo,
+    StoreDriver, StoreKey, StoreKeyBorrow, StoreKeyEncoding, StoreOptimizations, UploadSizeInfo,
 };
+use nativelink_util::task::BlockingPoolKind;
 use nativelink_util::{background_spawn, spawn_blocking};
+use parking_lot::Mutex;
 use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, SeekFrom};
+use tokio::sync::mpsc;
 use tokio::time::{sleep, timeout, Sleep};
 use tokio_stream::wrappers::ReadDirStream;
 use tracing::{event, Level};
@@ -50,10 +55,20 @@ use crate::cas_utils::is_zero_digest;
 const DEFAULT_BUFF_SIZE: usize = 32 * 1024;
 // Default block size of all major filesystems is 4KB
 const DEFAULT_BLOCK_SIZE: u64 = 4 * 1024;
+// Default size up to which in memory uploads are written with a single write.
+const DEFAULT_MAX_DIRECT_WRITE_SIZE: u64 = 1024 * 1024;
 
 pub const STR_FOLDER: &str = "s";
 pub const DIGEST_FOLDER: &str = "d";
 
+/// Name of the access time index in `content_path`, used when
+/// `network_filesystem` is set.
+pub const ACCESS_INDEX_FILE: &str = "access_index";
+/// Name the access time index is written to before it replaces the old one.
+const ACCESS_INDEX_TEMP_FILE: &str = "access_index.tmp";
+/// How often the access time index is written to disk if it changed.
+const ACCESS_INDEX_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
 #[derive(Clone, Copy, Debug)]
 pub enum FileType {
     Digest,
@@ -72,6 +87,193 @@ pub struct SharedContext {
     temp_path: String,
     #[metric(help = "Path to the configured content path")]
     content_path: String,
+    /// How digest keys are turned into file names.
+    key_encoding: StoreKeyEncoding,
+    /// Set when access times are kept in an index instead of in the atime
+    /// of the files.
+    access_index: Option<AccessIndex>,
+    /// Set once something subscribed to the files evicted from the store.
+    evictions_tx: OnceLock<mpsc::UnboundedSender<EvictedFile>>,
+}
+
+/// Last access times of the files in `content_path`, for filesystems where
+/// the atime of a file can not be relied on. Kept in memory and written to
+/// [`ACCESS_INDEX_FILE`] every [`ACCESS_INDEX_FLUSH_INTERVAL`]. Each line of
+/// the file holds the access time in seconds since the unix epoch and the
+/// path of the file relative to `content_path`.
+#[derive(Debug)]
+struct AccessIndex {
+    content_path: String,
+    /// Access times in the index are never later than this, so a skewed
+    /// clock can not make a file look like it was accessed in the future.
+    anchor_time: SystemTime,
+    times: Mutex<HashMap<String, u64>>,
+    dirty: AtomicBool,
+}
+
+impl AccessIndex {
+    async fn load(content_path: &str, anchor_time: SystemTime) -> Result<Self, Error> {
+        let index_path = format!("{content_path}/{ACCESS_INDEX_FILE}");
+        let times = match fs::read(&index_path).await {
+            Ok(data) => String::from_utf8_lossy(&data)
+                .lines()
+                .filter_map(|line| {
+                    let (secs, relative_path) = line.split_once(' ')?;
+                    Some((relative_path.to_string(), secs.parse().ok()?))
+                })
+                .collect(),
+            Err(err) if err.code == Code::NotFound => HashMap::new(),
+            Err(err) => {
+                return Err(err).err_tip(|| format!("Failed to read access index {index_path}"))
+            }
+        };
+        Ok(Self {
+            content_path: content_path.to_string(),
+            anchor_time,
+            times: Mutex::new(times),
+            dirty: AtomicBool::new(false),
+        })
+    }
+
+    fn relative_path<'a>(&self, full_path: &'a OsStr) -> Option<&'a str> {
+        full_path
+            .to_str()?
+            .strip_prefix(self.content_path.as_str())?
+            .strip_prefix('/')
+    }
+
+    /// Returns the access time of the file at `relative_path`, or
+    /// `fallback` if it is not in the index.
+    fn last_access(&self, relative_path: &str, fallback: SystemTime) -> SystemTime {
+        self.times
+            .lock()
+            .get(relative_path)
+            .map_or(fallback, |secs| UNIX_EPOCH + Duration::from_secs(*secs))
+            .min(self.anchor_time)
+    }
+
+    fn touch(&self, full_path: &OsStr) {
+        let Some(relative_path) = self.relative_path(full_path) else {
+            return;
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.times.lock().insert(relative_path.to_string(), now);
+        self.dirty.store(true, Ordering::Release);
+    }
+
+    fn remove(&self, full_path: &OsStr) {
+        let Some(relative_path) = self.relative_path(full_path) else {
+            return;
+        };
+        if self.times.lock().remove(relative_path).is_some() {
+            self.dirty.store(true, Ordering::Release);
+        }
+    }
+
+    fn rename(&self, from_path: &OsStr, to_path: &OsStr) {
+        let (Some(from), Some(to)) = (self.relative_path(from_path), self.relative_path(to_path))
+        else {
+            return;
+        };
+        let mut times = self.times.lock();
+        if let Some(secs) = times.remove(from) {
+            times.insert(to.to_string(), secs);
+            self.dirty.store(true, Ordering::Release);
+        }
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        for (relative_path, secs) in self.times.lock().iter() {
+            // Writing to a `Vec` can not fail.
+            let _ = writeln!(data, "{secs} {relative_path}");
+        }
+        data
+    }
+
+    /// Replaces the index on disk with `data`. The index has a single
+    /// writer, so replacing it with `rename()` is safe even on network
+    /// filesystems.
+    fn write_to_disk(content_path: &str, data: &[u8]) -> Result<(), std::io::Error> {
+        let temp_path = format!("{content_path}/{ACCESS_INDEX_TEMP_FILE}");
+        let mut file = std::fs::File::create(&temp_path)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        std::fs::rename(&temp_path, format!("{content_path}/{ACCESS_INDEX_FILE}"))
+    }
+
+    /// Writes the index to disk if it changed since the last write.
+    async fn flush(&self) -> Result<(), Error> {
+        if !self.dirty.swap(false, Ordering::AcqRel) {
+            return Ok(());
+        }
+        let data = self.serialize();
+        let content_path = self.content_path.clone();
+        let result = spawn_blocking!(pool: BlockingPoolKind::Filesystem, "filesystem_flush_access_index", move || {
+            Self::write_to_disk(&content_path, &data)
+        })
+        .await
+        .map_err(|e| {
+            make_err!(
+                Code::Internal,
+                "Failed to flush access index due to spawn failing {e:?}"
+            )
+        })?
+        .err_tip(|| "Failed to write access index");
+        if result.is_err() {
+            // Try again on the next flush.
+            self.dirty.store(true, Ordering::Release);
+        }
+        result
+    }
+
+    /// Flushes the index of `shared_context` until the store is dropped.
+    async fn flush_periodically(shared_context: Weak<SharedContext>) {
+        loop {
+            sleep(ACCESS_INDEX_FLUSH_INTERVAL).await;
+            let Some(shared_context) = shared_context.upgrade() else {
+                return;
+            };
+            let Some(access_index) = &shared_context.access_index else {
+                return;
+            };
+            if let Err(err) = access_index.flush().await {
+                event!(Level::WARN, ?err, "Failed to flush access index");
+            }
+        }
+    }
+}
+
+impl Drop for AccessIndex {
+    fn drop(&mut self) {
+        if !self.dirty.load(Ordering::Acquire) {
+            return;
+        }
+        if let Err(err) = Self::write_to_disk(&self.content_path, &self.serialize()) {
+            event!(
+                Level::WARN,
+                ?err,
+                "Failed to flush access index on shutdown"
+            );
+        }
+    }
+}
+
+/// Moves `from` to `to` by hard linking `to` and then unlinking `from`.
+/// Unlike `rename()` this behaves the same on network filesystems. An
+/// existing file at `to` is replaced.
+fn link_and_unlink(from: &OsStr, to: &OsStr) -> Result<(), std::io::Error> {
+    if let Err(err) = std::fs::hard_link(from, to) {
+        if err.kind() != std::io::ErrorKind::AlreadyExists {
+            return Err(err);
+        }
+        std::fs::remove_file(to)?;
+        std::fs::hard_link(from, to)?;
+    }
+    std::fs::remove_file(from)
 }
 
 #[derive(Eq, PartialEq, Debug)]
@@ -110,7 +312,11 @@ fn get_file_path_raw<'a>(
         PathType::Temp => &shared_context.temp_path,
         PathType::Custom(path) => return Cow::Borrowed(path),
     };
-    Cow::Owned(to_full_path_from_key(folder, key))
+    Cow::Owned(to_full_path_from_key(
+        folder,
+        key,
+        shared_context.key_encoding,
+    ))
 }
 
 impl Drop for EncodedFilePath {
@@ -141,21 +347,53 @@ impl Drop for EncodedFilePath {
     }
 }
 
+/// A file evicted from a [`FilesystemStore`], handed to the subscriber of
+/// [`FilesystemStore::subscribe_evictions`]. The file is kept in the temp
+/// path until this is dropped.
+pub struct EvictedFile {
+    key: StoreKey<'static>,
+    data_size: u64,
+    encoded_file_path: EncodedFilePath,
+}
+
+impl EvictedFile {
+    /// The key the file was stored under.
+    pub fn key(&self) -> StoreKey<'_> {
+        self.key.borrow()
+    }
+
+    /// Size of the data in the file.
+    pub const fn data_size(&self) -> u64 {
+        self.data_size
+    }
+
+    /// Opens the file for reading.
+    pub async fn open(&self) -> Result<fs::ResumeableFileSlot, Error> {
+        fs::open_file(self.encoded_file_path.get_file_path(), self.data_size)
+            .await
+            .err_tip(|| format!("Failed to open evicted file of {}", self.key.as_str()))
+    }
+}
+
 /// This creates the file path from the [`StoreKey`]. If
 /// it is a string, the string, prefixed with [`STR_PREFIX`]
 /// for backwards compatibility, is stored.
 ///
 /// If it is a [`DigestInfo`], it is prefixed by [`DIGEST_PREFIX`]
-/// followed by the string representation of a digest - the hash in hex,
-/// a hyphen then the size in bytes
+/// followed by the digest encoded with `key_encoding` - by default the
+/// hash in hex, a hyphen then the size in bytes
 ///
 /// Previously, only the string representation of the [`DigestInfo`] was
 /// used with no prefix
 #[inline]
-fn to_full_path_from_key(folder: &str, key: &StoreKey<'_>) -> OsString {
+fn to_full_path_from_key(
+    folder: &str,
+    key: &StoreKey<'_>,
+    key_encoding: StoreKeyEncoding,
+) -> OsString {
     match key {
         StoreKey::Str(str) => format!("{folder}/{STR_FOLDER}/{str}"),
-        StoreKey::Digest(digest_info) => format!("{folder}/{DIGEST_FOLDER}/{digest_info}"),
+        StoreKey::Digest(_) => format!("{folder}/{DIGEST_FOLDER}/{}", key.encode(key_encoding)),
     }
     .into()
 }
@@ -214,6 +452,46 @@ impl FileEntryImpl {
     pub fn get_shared_context_for_test(&mut self) -> Arc<SharedContext> {
         self.encoded_file_path.get_mut().shared_context.clone()
     }
+
+    /// Hands a link to the file that was just moved to `temp_path` to the
+    /// subscriber of the evictions, if any.
+    async fn send_eviction(
+        &self,
+        key: StoreKey<'static>,
+        temp_path: &OsStr,
+        shared_context: &Arc<SharedContext>,
+    ) {
+        let Some(evictions_tx) = shared_context.evictions_tx.get() else {
+            return;
+        };
+        let evicted_key = make_temp_key(&key);
+        let evicted_path = to_full_path_from_key(
+            &shared_context.temp_path,
+            &evicted_key,
+            shared_context.key_encoding,
+        );
+        if let Err(err) = fs::hard_link(temp_path, &evicted_path).await {
+            event!(
+                Level::WARN,
+                ?key,
+                ?temp_path,
+                ?evicted_path,
+                ?err,
+                "Failed to link evicted file",
+            );
+            return;
+        }
+        // If the subscriber is gone the file is deleted when dropped.
+        let _ = evictions_tx.send(EvictedFile {
+            key,
+            data_size: self.data_size,
+            encoded_file_path: EncodedFilePath {
+                shared_context: shared_context.clone(),
+                path_type: PathType::Temp,
+                key: evicted_key,
+            },
+        });
+    }
 }
 
 impl FileEntry for FileEntryImpl {
@@ -326,13 +604,17 @@ impl Debug for FileEntryImpl {
 
 fn make_temp_digest(mut digest: DigestInfo) -> DigestInfo {
     static DELETE_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
-    let mut hash = *digest.packed_hash();
-    hash[24..].clone_from_slice(
+    // Temp digests only name temp files, so SHA-512 hashes are cut to 32
+    // bytes instead of interning a new hash for every temp file.
+    let mut hash = [0u8; 32];
+    let len = digest.packed_hash().len().min(hash.len());
+    hash[..len].copy_from_slice(&digest.packed_hash()[..len]);
+    hash[len - 8..len].clone_from_slice(
         &DELETE_FILE_COUNTER
             .fetch_add(1, Ordering::Relaxed)
             .to_le_bytes(),
     );
-    digest.set_packed_hash(*hash);
+    digest.set_packed_hash(PackedHash::from_slice(&hash[..len]).unwrap_or_default());
     digest
 }
 
@@ -352,10 +634,19 @@ impl LenEntry for FileEntryImpl {
 
     #[inline]
     async fn touch(&self) -> bool {
+        {
+            let encoded_file_path = self.get_encoded_file_path().read().await;
+            if let Some(access_index) = &encoded_file_path.shared_context.access_index {
+                if encoded_file_path.path_type == PathType::Content {
+                    access_index.touch(&encoded_file_path.get_file_path());
+                }
+                return true;
+            }
+        }
         let result = self
             .get_file_path_locked(move |full_content_path| async move {
                 let full_content_path = full_content_path.clone();
-                spawn_blocking!("filesystem_touch_set_mtime", move || {
+                spawn_blocking!(pool: BlockingPoolKind::Filesystem, "filesystem_touch_set_mtime", move || {
                     set_file_atime(&full_content_path, FileTime::now()).err_tip(|| {
                         format!("Failed to touch file in filesystem store {full_content_path:?}")
                     })
@@ -393,9 +684,15 @@ impl LenEntry for FileEntryImpl {
             let from_path = encoded_file_path.get_file_path();
             let new_key = make_temp_key(&encoded_file_path.key);
 
-            let to_path =
-                to_full_path_from_key(&encoded_file_path.shared_context.temp_path, &new_key);
+            let to_path = to_full_path_from_key(
+                &encoded_file_path.shared_context.temp_path,
+                &new_key,
+                encoded_file_path.shared_context.key_encoding,
+            );
 
+            if let Some(access_index) = &encoded_file_path.shared_context.access_index {
+                access_index.remove(&from_path);
+            }
             if let Err(err) = fs::rename(&from_path, &to_path).await {
                 event!(
                     Level::WARN,
@@ -414,17 +711,18 @@ impl LenEntry for FileEntryImpl {
                     "Renamed file",
                 );
                 encoded_file_path.path_type = PathType::Temp;
-                encoded_file_path.key = new_key;
+                let key = std::mem::replace(&mut encoded_file_path.key, new_key);
+                self.send_eviction(key, &to_path, &encoded_file_path.shared_context)
+                    .await;
             }
         }
     }
 }
 
+/// Parses a digest file name written with any [`StoreKeyEncoding`].
 #[inline]
 fn digest_from_filename(file_name: &str) -> Result<DigestInfo, Error> {
-    let (hash, size) = file_name.split_once('-').err_tip(|| "")?;
-    let size = size.parse::<i64>()?;
-    DigestInfo::try_new(hash, size)
+    StoreKeyEncoding::decode_digest(file_name).map(|(digest, _)| digest)
 }
 
 pub fn key_from_file(file_name: &str, file_type: FileType) -> Result<StoreKey<'_>, Error> {
@@ -455,9 +753,27 @@ async fn add_files_to_cache<Fe: FileEntry>(
         block_size: u64,
         anchor_time: &SystemTime,
         shared_context: &Arc<SharedContext>,
+        rename_fn: fn(&OsStr, &OsStr) -> Result<(), std::io::Error>,
     ) -> Result<(), Error> {
         let key = key_from_file(file_name, file_type)?;
 
+        // Files written before `key_encoding` was changed are renamed so
+        // they can be found under the name the key now encodes to.
+        if let StoreKey::Digest(_) = key {
+            let encoded_file_name = key.encode(shared_context.key_encoding);
+            if encoded_file_name != file_name {
+                let path_root = format!("{}/{DIGEST_FOLDER}", shared_context.content_path);
+                let from_file: OsString = format!("{path_root}/{file_name}").into();
+                let to_file: OsString = format!("{path_root}/{encoded_file_name}").into();
+                rename_fn(&from_file, &to_file).err_tip(|| {
+                    format!("Failed to rename {from_file:?} to {to_file:?} in filesystem store")
+                })?;
+                if let Some(access_index) = &shared_context.access_index {
+                    access_index.rename(&from_file, &to_file);
+                }
+            }
+        }
+
         let file_entry = Fe::create(
             data_size,
             block_size,
@@ -511,18 +827,20 @@ async fn add_files_to_cache<Fe: FileEntry>(
                 // We need to filter out folders - we do not want to try to cache the s and d folders.
                 let is_file =
                     metadata.is_file() || !(file_name == STR_FOLDER || file_name == DIGEST_FOLDER);
-                let atime = match metadata.accessed() {
-                    Ok(atime) => atime,
-                    Err(err) => {
-                        panic!(
-                            "{}{}{} : {} {:?}",
-                            "It appears this filesystem does not support access time. ",
-                            "Please configure this program to run on a drive that supports ",
-                            "atime",
-                            file_name,
-                            err
-                        );
-                    }
+                let atime = if let Some(access_index) = &shared_context.access_index {
+                    let relative_path = match folder {
+                        Some(folder) => format!("{folder}/{file_name}"),
+                        None => file_name.clone(),
+                    };
+                    access_index
+                        .last_access(&relative_path, metadata.modified().unwrap_or(UNIX_EPOCH))
+                } else {
+                    metadata.accessed().map_err(|err| {
+                        make_err!(
+                            Code::FailedPrecondition,
+                            "It appears this filesystem does not support access time. Please configure this program to run on a drive that supports atime or set network_filesystem : {file_name} {err:?}"
+                        )
+                    })?
                 };
                 Result::<(String, SystemTime, u64, bool), Error>::Ok((
                     file_name,
@@ -550,7 +868,10 @@ async fn add_files_to_cache<Fe: FileEntry>(
 
         let to_path = format!("{}/{DIGEST_FOLDER}", shared_context.content_path);
 
-        for (file_name, _, _, _) in file_infos.into_iter().filter(|x| x.3) {
+        for (file_name, _, _, _) in file_infos
+            .into_iter()
+            .filter(|x| x.3 && x.0 != ACCESS_INDEX_FILE && x.0 != ACCESS_INDEX_TEMP_FILE)
+        {
             let from_file: OsString = format!("{from_path}/{file_name}").into();
             let to_file: OsString = format!("{to_path}/{file_name}").into();
 
@@ -575,6 +896,7 @@ async fn add_files_to_cache<Fe: FileEntry>(
         shared_context: &Arc<SharedContext>,
         block_size: u64,
         folder: &str,
+        rename_fn: fn(&OsStr, &OsStr) -> Result<(), std::io::Error>,
     ) -> Result<(), Error> {
         let file_infos = read_files(Some(folder), shared_context).await?;
         let file_type = match folder {
@@ -595,6 +917,7 @@ async fn add_files_to_cache<Fe: FileEntry>(
                 block_size,
                 anchor_time,
                 shared_context,
+                rename_fn,
             )
             .await;
             if let Err(err) = result {
@@ -619,6 +942,7 @@ async fn add_files_to_cache<Fe: FileEntry>(
         shared_context,
         block_size,
         DIGEST_FOLDER,
+        rename_fn,
     )
     .await?;
 
@@ -628,6 +952,7 @@ async fn add_files_to_cache<Fe: FileEntry>(
         shared_context,
         block_size,
         STR_FOLDER,
+        rename_fn,
     )
     .await?;
     Ok(())
@@ -667,6 +992,8 @@ pub struct FilesystemStore<Fe: FileEntry = FileEntryImpl> {
     block_size: u64,
     #[metric(help = "Size of the configured read buffer size")]
     read_buffer_size: usize,
+    #[metric(help = "Max size of in memory uploads that are written with a single write")]
+    max_direct_write_size: u64,
     weak_self: Weak<Self>,
     sleep_fn: fn(Duration) -> Sleep,
     rename_fn: fn(&OsStr, &OsStr) -> Result<(), std::io::Error>,
@@ -674,6 +1001,9 @@ pub struct FilesystemStore<Fe: FileEntry = FileEntryImpl> {
 
 impl<Fe: FileEntry> FilesystemStore<Fe> {
     pub async fn new(spec: &FilesystemSpec) -> Result<Arc<Self>, Error> {
+        if spec.network_filesystem {
+            return Self::new_with_timeout_and_rename_fn(spec, sleep, link_and_unlink).await;
+        }
         Self::new_with_timeout_and_rename_fn(spec, sleep, |from, to| std::fs::rename(from, to))
             .await
     }
@@ -703,10 +1033,18 @@ impl<Fe: FileEntry> FilesystemStore<Fe> {
         create_subdirs(&spec.temp_path).await?;
         create_subdirs(&spec.content_path).await?;
 
+        let access_index = if spec.network_filesystem {
+            Some(AccessIndex::load(&spec.content_path, now).await?)
+        } else {
+            None
+        };
         let shared_context = Arc::new(SharedContext {
             active_drop_spawns: AtomicU64::new(0),
             temp_path: spec.temp_path.clone(),
             content_path: spec.content_path.clone(),
+            key_encoding: spec.key_encoding.into(),
+            access_index,
+            evictions_tx: OnceLock::new(),
         });
 
         let block_size = if spec.block_size == 0 {
@@ -723,23 +1061,49 @@ impl<Fe: FileEntry> FilesystemStore<Fe> {
         )
         .await?;
         prune_temp_path(&shared_context.temp_path).await?;
+        if shared_context.access_index.is_some() {
+            background_spawn!(
+                "filesystem_store_flush_access_index",
+                AccessIndex::flush_periodically(Arc::downgrade(&shared_context))
+            );
+        }
 
         let read_buffer_size = if spec.read_buffer_size == 0 {
             DEFAULT_BUFF_SIZE
         } else {
             spec.read_buffer_size as usize
         };
+        let max_direct_write_size = if spec.max_direct_write_size == 0 {
+            DEFAULT_MAX_DIRECT_WRITE_SIZE
+        } else {
+            spec.max_direct_write_size
+        };
         Ok(Arc::new_cyclic(|weak_self| Self {
             shared_context,
             evicting_map,
             block_size,
             read_buffer_size,
+            max_direct_write_size,
             weak_self: weak_self.clone(),
             sleep_fn,
             rename_fn,
         }))
     }
 
+    /// Returns a receiver of every file evicted from the store from now
+    /// on, for example to copy it somewhere else before it is deleted.
+    /// Only one subscriber is supported.
+    pub fn subscribe_evictions(&self) -> Result<mpsc::UnboundedReceiver<EvictedFile>, Error> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.shared_context.evictions_tx.set(tx).map_err(|_| {
+            make_err!(
+                Code::Internal,
+                "Evictions of FilesystemStore already have a subscriber"
+            )
+        })?;
+        Ok(rx)
+    }
+
     pub fn get_arc(&self) -> Option<Arc<Self>> {
         self.weak_self.upgrade()
     }
@@ -751,6 +1115,50 @@ impl<Fe: FileEntry> FilesystemStore<Fe> {
             .ok_or_else(|| make_err!(Code::NotFound, "{digest} not found in filesystem store"))
     }
 
+    async fn make_temp_file(
+        &self,
+        key: &StoreKey<'_>,
+    ) -> Result<(Fe, fs::ResumeableFileSlot, OsString), Error> {
+        Fe::make_and_open_file(
+            self.block_size,
+            EncodedFilePath {
+                shared_context: self.shared_context.clone(),
+                path_type: PathType::Temp,
+                key: make_temp_key(key),
+            },
+        )
+        .await
+    }
+
+    /// Writes `data` into the temp file with a single write and moves it
+    /// into place. Unlike `update_file()` there is no receive loop and only
+    /// the data (not the timestamps) of the file is synced.
+    async fn update_file_with_bytes(
+        self: Pin<&Self>,
+        mut entry: Fe,
+        mut temp_file: fs::ResumeableFileSlot,
+        final_key: StoreKey<'static>,
+        data: Bytes,
+    ) -> Result<(), Error> {
+        let writer = temp_file
+            .as_writer()
+            .await
+            .err_tip(|| "in filesystem_store::update_file_with_bytes")?;
+        writer
+            .write_all(&data)
+            .await
+            .err_tip(|| "Failed to write data into filesystem store")?;
+        writer
+            .as_ref()
+            .sync_data()
+            .await
+            .err_tip(|| "Failed to sync_data in filesystem store")?;
+        drop(temp_file);
+
+        *entry.data_size_mut() = data.len() as u64;
+        self.emplace_file(final_key, Arc::new(entry)).await
+    }
+
     async fn update_file<'a>(
         self: Pin<&'a Self>,
         mut entry: Fe,
@@ -919,22 +1327,37 @@ impl<Fe: FileEntry> StoreDriver for FilesystemStore<Fe> {
         reader: DropCloserReadHalf,
         _upload_size: UploadSizeInfo,
     ) -> Result<(), Error> {
-        let temp_key = make_temp_key(&key);
-        let (entry, temp_file, temp_full_path) = Fe::make_and_open_file(
-            self.block_size,
-            EncodedFilePath {
-                shared_context: self.shared_context.clone(),
-                path_type: PathType::Temp,
-                key: temp_key,
-            },
-        )
-        .await?;
-
+        let (entry, temp_file, temp_full_path) = self.make_temp_file(&key).await?;
         self.update_file(entry, temp_file, key.into_owned(), reader)
             .await
             .err_tip(|| format!("While processing with temp file {temp_full_path:?}"))
     }
 
+    async fn update_oneshot(self: Pin<&Self>, key: StoreKey<'_>, data: Bytes) -> Result<(), Error> {
+        if data.len() as u64 > self.max_direct_write_size {
+            let (mut tx, rx) = make_buf_channel_pair();
+            let data_len = data.len() as u64;
+            let send_fut = async move {
+                if !data.is_empty() {
+                    tx.send(data)
+                        .await
+                        .err_tip(|| "Failed to write data in update_oneshot")?;
+                }
+                tx.send_eof()
+                    .err_tip(|| "Failed to write EOF in update_oneshot")
+            };
+            try_join!(
+                send_fut,
+                self.update(key, rx, UploadSizeInfo::ExactSize(data_len))
+            )?;
+            return Ok(());
+        }
+        let (entry, temp_file, temp_full_path) = self.make_temp_file(&key).await?;
+        self.update_file_with_bytes(entry, temp_file, key.into_owned(), data)
+            .await
+            .err_tip(|| format!("While processing with temp file {temp_full_path:?}"))
+    }
+
     fn optimized_for(&self, optimization: StoreOptimizations) -> bool {
         optimization == StoreOptimizations::FileUpdates
     }
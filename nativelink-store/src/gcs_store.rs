@@ -0,0 +1,989 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::borrow::Cow;
+use std::env;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use async_lock::Mutex;
+use async_trait::async_trait;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use bytes::Bytes;
+use futures::stream::{unfold, FuturesUnordered};
+use futures::TryStreamExt;
+use hyper::body::HttpBody;
+use hyper::client::HttpConnector;
+use hyper::{header, Body, Method, Request, Response, StatusCode};
+use hyper_rustls::HttpsConnector;
+use nativelink_config::stores::{GcsAuth, GcsSpec};
+// Note: GCS store should be very careful about the error codes it returns
+// when in a retryable wrapper. Always prefer Code::Aborted or another
+// retryable code over Code::InvalidArgument or make_input_err!().
+// ie: Don't import make_input_err!() to help prevent this.
+use nativelink_error::{make_err, Code, Error, ResultExt};
+use nativelink_metric::MetricsComponent;
+use nativelink_util::buf_channel::{DropCloserReadHalf, DropCloserWriteHalf};
+use nativelink_util::health_utils::{HealthStatus, HealthStatusIndicator};
+use nativelink_util::instant_wrapper::InstantWrapper;
+use nativelink_util::retry::{Retrier, RetryResult};
+use nativelink_util::store_trait::{StoreDriver, StoreKey, UploadSizeInfo};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use rand::rngs::OsRng;
+use rand::Rng;
+use ring::rand::SystemRandom;
+use ring::signature::{RsaKeyPair, RSA_PKCS1_SHA256};
+use serde::Deserialize;
+use tokio::time::sleep;
+use tracing::{event, Level};
+
+use crate::cas_utils::is_zero_digest;
+
+const DEFAULT_ENDPOINT: &str = "https://storage.googleapis.com";
+
+// Chunks of resumable uploads must be a multiple of this size. See:
+// https://cloud.google.com/storage/docs/performing-resumable-uploads#chunked-upload
+const RESUMABLE_CHUNK_ALIGNMENT: usize = 256 * 1024;
+
+// Default size of the chunks of resumable uploads.
+// Note: If you change this, adjust the docs in the config.
+const DEFAULT_RESUMABLE_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Scope requested for the access tokens of service accounts.
+const STORAGE_SCOPE: &str = "https://www.googleapis.com/auth/devstorage.read_write";
+
+/// Lifetime requested for the access tokens of service accounts.
+const SERVICE_ACCOUNT_TOKEN_LIFETIME: Duration = Duration::from_secs(3600);
+
+/// Access tokens are refreshed this long before they expire.
+const TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+/// Host of the metadata server if `GCE_METADATA_HOST` is not set.
+const DEFAULT_METADATA_HOST: &str = "metadata.google.internal";
+
+/// Characters that are kept as-is in object names in URLs. Everything else,
+/// including `/`, is percent encoded.
+const OBJECT_NAME_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Sends the HTTP requests of the GCS store. Tests replace it to replay
+/// canned responses.
+#[async_trait]
+pub trait GcsHttpClient: Send + Sync + 'static {
+    async fn send(&self, request: Request<Body>) -> Result<Response<Body>, Error>;
+}
+
+/// [`GcsHttpClient`] that sends requests over the network.
+pub struct HyperGcsHttpClient {
+    client: hyper::Client<HttpsConnector<HttpConnector>, Body>,
+}
+
+impl HyperGcsHttpClient {
+    #[must_use]
+    pub fn new() -> Self {
+        // The metadata server and emulators are only reachable over http.
+        let connector = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_webpki_roots()
+            .https_or_http()
+            .enable_http1()
+            .enable_http2()
+            .build();
+        Self {
+            client: hyper::Client::builder().build(connector),
+        }
+    }
+}
+
+impl Default for HyperGcsHttpClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl GcsHttpClient for HyperGcsHttpClient {
+    async fn send(&self, request: Request<Body>) -> Result<Response<Body>, Error> {
+        self.client
+            .request(request)
+            .await
+            .map_err(|e| make_err!(Code::Unavailable, "Failed to send request to GCS: {e:?}"))
+    }
+}
+
+/// Fields of a service account key file that are needed to get tokens.
+#[derive(Deserialize)]
+struct ServiceAccountKey {
+    #[serde(rename = "type")]
+    key_type: String,
+    client_email: String,
+    private_key: String,
+    token_uri: String,
+}
+
+/// Response of the token endpoints of both the OAuth2 server and the
+/// metadata server.
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+/// Fields of the object metadata requested by `has()`.
+#[derive(Deserialize)]
+struct ObjectMetadata {
+    /// GCS encodes 64 bit integers as strings.
+    size: String,
+    updated: Option<String>,
+}
+
+enum Credentials {
+    None,
+    ServiceAccount {
+        client_email: String,
+        token_uri: String,
+        key_pair: RsaKeyPair,
+    },
+    MetadataServer {
+        token_uri: String,
+    },
+}
+
+impl Credentials {
+    fn from_spec(auth: &GcsAuth) -> Result<Self, Error> {
+        match auth {
+            GcsAuth::application_default => match env::var("GOOGLE_APPLICATION_CREDENTIALS") {
+                Ok(key_file) => Self::from_key_file(&key_file),
+                Err(_) => Ok(Self::metadata_server()),
+            },
+            GcsAuth::service_account { key_file } => Self::from_key_file(key_file),
+            GcsAuth::workload_identity => Ok(Self::metadata_server()),
+            GcsAuth::none => Ok(Self::None),
+        }
+    }
+
+    fn metadata_server() -> Self {
+        let host = env::var("GCE_METADATA_HOST").unwrap_or_else(|_| DEFAULT_METADATA_HOST.into());
+        Self::MetadataServer {
+            token_uri: format!(
+                "http://{host}/computeMetadata/v1/instance/service-accounts/default/token"
+            ),
+        }
+    }
+
+    fn from_key_file(key_file: &str) -> Result<Self, Error> {
+        let contents = std::fs::read(key_file)
+            .map_err(|e| make_err!(Code::InvalidArgument, "Could not read {key_file}: {e}"))?;
+        let key: ServiceAccountKey = serde_json::from_slice(&contents).map_err(|e| {
+            make_err!(
+                Code::InvalidArgument,
+                "Could not parse service account key {key_file}: {e}"
+            )
+        })?;
+        if key.key_type != "service_account" {
+            return Err(make_err!(
+                Code::InvalidArgument,
+                "Expected a key of type 'service_account' in {key_file}, got '{}'",
+                key.key_type
+            ));
+        }
+        let der = pem_to_der(&key.private_key)
+            .err_tip(|| format!("While reading the private key of {key_file}"))?;
+        let key_pair = RsaKeyPair::from_pkcs8(&der).map_err(|e| {
+            make_err!(
+                Code::InvalidArgument,
+                "Invalid private key in {key_file}: {e}"
+            )
+        })?;
+        Ok(Self::ServiceAccount {
+            client_email: key.client_email,
+            token_uri: key.token_uri,
+            key_pair,
+        })
+    }
+}
+
+/// Decodes the base64 body of a PEM encoded key.
+fn pem_to_der(pem: &str) -> Result<Vec<u8>, Error> {
+    let body: String = pem
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with("-----"))
+        .collect();
+    STANDARD
+        .decode(body)
+        .map_err(|e| make_err!(Code::InvalidArgument, "Invalid PEM key: {e}"))
+}
+
+struct AccessToken {
+    token: String,
+    /// Seconds since the unix epoch after which the token must not be used.
+    refresh_after: u64,
+}
+
+/// Returns the object name of `key` encoded for use in a URL.
+fn encode_object_name(name: &str) -> String {
+    utf8_percent_encode(name, OBJECT_NAME_ENCODE_SET).to_string()
+}
+
+/// Returns true if a request that failed with `status` may succeed if it
+/// is sent again.
+fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::REQUEST_TIMEOUT
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || status.is_server_error()
+}
+
+/// Turns an unexpected response into the result of a retry attempt.
+async fn error_response<T>(response: Response<Body>, context: &str) -> RetryResult<T> {
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body())
+        .await
+        .unwrap_or_default();
+    let message = format!(
+        "{context} failed in GCS with status {status}: {}",
+        String::from_utf8_lossy(&body)
+    );
+    if is_retryable_status(status) {
+        return RetryResult::Retry(make_err!(Code::Unavailable, "{message}"));
+    }
+    let code = match status {
+        StatusCode::NOT_FOUND => Code::NotFound,
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Code::PermissionDenied,
+        _ => Code::Internal,
+    };
+    RetryResult::Err(make_err!(code, "{message}"))
+}
+
+/// Returns the offset after the last byte GCS persisted, as reported by
+/// the `Range` header of a `308 Resume Incomplete` response.
+fn persisted_offset(response: &Response<Body>) -> Result<u64, Error> {
+    let Some(range) = response.headers().get(header::RANGE) else {
+        // No range means no bytes were persisted yet.
+        return Ok(0);
+    };
+    range
+        .to_str()
+        .ok()
+        .and_then(|range| range.strip_prefix("bytes=0-"))
+        .and_then(|last| last.parse::<u64>().ok())
+        .map(|last| last + 1)
+        .ok_or_else(|| make_err!(Code::Internal, "Invalid range in GCS response: {range:?}"))
+}
+
+/// State of a resumable upload session after a chunk was sent.
+enum ChunkResult {
+    /// GCS expects the next chunk.
+    Incomplete,
+    /// GCS finalized the object.
+    Complete,
+}
+
+#[derive(MetricsComponent)]
+pub struct GcsStore<NowFn> {
+    http_client: Arc<dyn GcsHttpClient>,
+    credentials: Credentials,
+    access_token: Mutex<Option<AccessToken>>,
+    now_fn: NowFn,
+    #[metric(help = "The endpoint of the GCS API")]
+    endpoint: String,
+    #[metric(help = "The bucket name for the GCS store")]
+    bucket: String,
+    #[metric(help = "The key prefix for the GCS store")]
+    key_prefix: String,
+    retrier: Retrier,
+    #[metric(help = "The number of seconds to consider an object expired")]
+    consider_expired_after_s: u64,
+    #[metric(help = "The size of the chunks of resumable uploads")]
+    resumable_chunk_size: usize,
+}
+
+impl<I, NowFn> GcsStore<NowFn>
+where
+    I: InstantWrapper,
+    NowFn: Fn() -> I + Send + Sync + Unpin + 'static,
+{
+    pub fn new(spec: &GcsSpec, now_fn: NowFn) -> Result<Arc<Self>, Error> {
+        let jitter_amt = spec.retry.jitter;
+        let jitter_fn = Arc::new(move |delay: Duration| {
+            if jitter_amt == 0. {
+                return delay;
+            }
+            let min = 1. - (jitter_amt / 2.);
+            let max = 1. + (jitter_amt / 2.);
+            delay.mul_f32(OsRng.gen_range(min..max))
+        });
+        Self::new_with_client_and_jitter(
+            spec,
+            Arc::new(HyperGcsHttpClient::new()),
+            jitter_fn,
+            now_fn,
+        )
+    }
+
+    pub fn new_with_client_and_jitter(
+        spec: &GcsSpec,
+        http_client: Arc<dyn GcsHttpClient>,
+        jitter_fn: Arc<dyn Fn(Duration) -> Duration + Send + Sync>,
+        now_fn: NowFn,
+    ) -> Result<Arc<Self>, Error> {
+        let credentials = Credentials::from_spec(&spec.auth).err_tip(|| {
+            format!(
+                "While loading the credentials of GCS bucket {}",
+                spec.bucket
+            )
+        })?;
+        let resumable_chunk_size = if spec.resumable_chunk_size == 0 {
+            DEFAULT_RESUMABLE_CHUNK_SIZE
+        } else {
+            spec.resumable_chunk_size
+                .next_multiple_of(RESUMABLE_CHUNK_ALIGNMENT)
+        };
+        Ok(Arc::new(Self {
+            http_client,
+            credentials,
+            access_token: Mutex::new(None),
+            now_fn,
+            endpoint: spec
+                .endpoint
+                .as_deref()
+                .unwrap_or(DEFAULT_ENDPOINT)
+                .trim_end_matches('/')
+                .to_string(),
+            bucket: spec.bucket.clone(),
+            key_prefix: spec.key_prefix.clone().unwrap_or_default(),
+            retrier: Retrier::new(
+                Arc::new(|duration| Box::pin(sleep(duration))),
+                jitter_fn,
+                spec.retry.clone(),
+            ),
+            consider_expired_after_s: u64::from(spec.consider_expired_after_s),
+            resumable_chunk_size,
+        }))
+    }
+
+    fn make_object_name(&self, key: &StoreKey<'_>) -> String {
+        encode_object_name(&format!("{}{}", self.key_prefix, key.as_str()))
+    }
+
+    fn object_uri(&self, object_name: &str, query: &str) -> String {
+        format!(
+            "{}/storage/v1/b/{}/o/{object_name}?{query}",
+            self.endpoint,
+            encode_object_name(&self.bucket)
+        )
+    }
+
+    fn upload_uri(&self, object_name: &str, upload_type: &str) -> String {
+        format!(
+            "{}/upload/storage/v1/b/{}/o?uploadType={upload_type}&name={object_name}",
+            self.endpoint,
+            encode_object_name(&self.bucket)
+        )
+    }
+
+    /// Returns a valid access token, fetching a new one if the cached one
+    /// is about to expire.
+    async fn access_token(&self) -> Result<Option<String>, Error> {
+        if matches!(self.credentials, Credentials::None) {
+            return Ok(None);
+        }
+        let now_s = (self.now_fn)().unix_timestamp();
+        let mut access_token = self.access_token.lock().await;
+        if let Some(token) = access_token.as_ref() {
+            if now_s < token.refresh_after {
+                return Ok(Some(token.token.clone()));
+            }
+        }
+        let request = match &self.credentials {
+            Credentials::None => return Ok(None),
+            Credentials::ServiceAccount {
+                client_email,
+                token_uri,
+                key_pair,
+            } => {
+                let assertion = make_jwt(client_email, token_uri, key_pair, now_s)?;
+                Request::post(token_uri)
+                    .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                    .body(Body::from(format!(
+                        "grant_type=urn%3Aietf%3Aparams%3Aoauth%3Agrant-type%3Ajwt-bearer&assertion={assertion}"
+                    )))
+            }
+            Credentials::MetadataServer { token_uri } => Request::get(token_uri)
+                .header("Metadata-Flavor", "Google")
+                .body(Body::empty()),
+        }
+        .map_err(|e| make_err!(Code::Internal, "Could not build GCS token request: {e}"))?;
+
+        let response = self
+            .http_client
+            .send(request)
+            .await
+            .err_tip(|| "While fetching a GCS access token")?;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(|e| make_err!(Code::Unavailable, "Could not read GCS token: {e}"))?;
+        if !status.is_success() {
+            return Err(make_err!(
+                Code::Unavailable,
+                "Fetching a GCS access token failed with status {status}: {}",
+                String::from_utf8_lossy(&body)
+            ));
+        }
+        let token: TokenResponse = serde_json::from_slice(&body)
+            .map_err(|e| make_err!(Code::Unavailable, "Could not parse GCS token: {e}"))?;
+        let refresh_after =
+            (now_s + token.expires_in).saturating_sub(TOKEN_REFRESH_MARGIN.as_secs());
+        *access_token = Some(AccessToken {
+            token: token.access_token.clone(),
+            refresh_after,
+        });
+        Ok(Some(token.access_token))
+    }
+
+    /// Sends a request to GCS with the access token of the store.
+    async fn send(
+        &self,
+        method: Method,
+        uri: &str,
+        headers: &[(header::HeaderName, String)],
+        body: Bytes,
+    ) -> Result<Response<Body>, Error> {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::CONTENT_LENGTH, body.len());
+        if let Some(token) = self.access_token().await? {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+        }
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        let request = request
+            .body(Body::from(body))
+            .map_err(|e| make_err!(Code::Internal, "Could not build GCS request: {e}"))?;
+        self.http_client.send(request).await
+    }
+
+    async fn has(self: Pin<&Self>, key: &StoreKey<'_>) -> Result<Option<u64>, Error> {
+        let uri = &self.object_uri(&self.make_object_name(key), "fields=size%2Cupdated");
+        self.retrier
+            .retry(unfold((), move |()| async move {
+                let response = match self.send(Method::GET, uri, &[], Bytes::new()).await {
+                    Ok(response) => response,
+                    Err(e) => return Some((RetryResult::Retry(e), ())),
+                };
+                if response.status() == StatusCode::NOT_FOUND {
+                    return Some((RetryResult::Ok(None), ()));
+                }
+                if !response.status().is_success() {
+                    return Some((error_response(response, "Get object metadata").await, ()));
+                }
+                let metadata = match hyper::body::to_bytes(response.into_body()).await {
+                    Ok(body) => serde_json::from_slice::<ObjectMetadata>(&body).map_err(|e| {
+                        make_err!(Code::Internal, "Invalid object metadata in GCS: {e}")
+                    }),
+                    Err(e) => {
+                        return Some((
+                            RetryResult::Retry(make_err!(
+                                Code::Unavailable,
+                                "Could not read object metadata from GCS: {e}"
+                            )),
+                            (),
+                        ))
+                    }
+                };
+                Some((
+                    metadata
+                        .and_then(|metadata| self.object_size_if_not_expired(&metadata))
+                        .map_or_else(RetryResult::Err, RetryResult::Ok),
+                    (),
+                ))
+            }))
+            .await
+    }
+
+    fn object_size_if_not_expired(&self, metadata: &ObjectMetadata) -> Result<Option<u64>, Error> {
+        if self.consider_expired_after_s != 0 {
+            if let Some(updated) = &metadata.updated {
+                let updated_s = humantime::parse_rfc3339_weak(updated)
+                    .ok()
+                    .and_then(|updated| updated.duration_since(UNIX_EPOCH).ok())
+                    .err_tip(|| format!("Invalid updated time in GCS: {updated}"))?
+                    .as_secs();
+                let now_s = (self.now_fn)().unix_timestamp();
+                if updated_s + self.consider_expired_after_s <= now_s {
+                    return Ok(None);
+                }
+            }
+        }
+        metadata
+            .size
+            .parse::<u64>()
+            .map(Some)
+            .map_err(|e| make_err!(Code::Internal, "Invalid object size in GCS: {e}"))
+    }
+
+    /// Uploads `data` in a single request.
+    async fn upload_simple(&self, object_name: &str, data: Bytes) -> Result<(), Error> {
+        let uri = &self.upload_uri(object_name, "media");
+        self.retrier
+            .retry(unfold(data, move |data| async move {
+                let result = match self
+                    .send(
+                        Method::POST,
+                        uri,
+                        &[(header::CONTENT_TYPE, "application/octet-stream".to_string())],
+                        data.clone(),
+                    )
+                    .await
+                {
+                    Ok(response) if response.status().is_success() => RetryResult::Ok(()),
+                    Ok(response) => error_response(response, "Upload").await,
+                    Err(e) => RetryResult::Retry(e),
+                };
+                Some((result, data))
+            }))
+            .await
+    }
+
+    /// Starts a resumable upload and returns the URI of its session.
+    async fn start_resumable_upload(
+        &self,
+        object_name: &str,
+        upload_size: UploadSizeInfo,
+    ) -> Result<String, Error> {
+        let uri = &self.upload_uri(object_name, "resumable");
+        let headers = &match upload_size {
+            UploadSizeInfo::ExactSize(sz) => {
+                vec![(
+                    header::HeaderName::from_static("x-upload-content-length"),
+                    sz.to_string(),
+                )]
+            }
+            UploadSizeInfo::MaxSize(_) => Vec::new(),
+        };
+        self.retrier
+            .retry(unfold((), move |()| async move {
+                let result = match self.send(Method::POST, uri, headers, Bytes::new()).await {
+                    Ok(response) if response.status().is_success() => response
+                        .headers()
+                        .get(header::LOCATION)
+                        .and_then(|location| location.to_str().ok())
+                        .map_or_else(
+                            || {
+                                RetryResult::Err(make_err!(
+                                    Code::Internal,
+                                    "Expected a Location header in the GCS resumable upload response"
+                                ))
+                            },
+                            |location| RetryResult::Ok(location.to_string()),
+                        ),
+                    Ok(response) => error_response(response, "Start resumable upload").await,
+                    Err(e) => RetryResult::Retry(e),
+                };
+                Some((result, ()))
+            }))
+            .await
+    }
+
+    /// Returns how many bytes of the upload GCS persisted, or None if the
+    /// upload is already complete.
+    async fn query_resumable_upload(
+        &self,
+        session_uri: &str,
+        total_size: Option<u64>,
+    ) -> Result<Option<u64>, Error> {
+        let total_size = total_size.map_or_else(|| "*".to_string(), |sz| sz.to_string());
+        let response = self
+            .send(
+                Method::PUT,
+                session_uri,
+                &[(header::CONTENT_RANGE, format!("bytes */{total_size}"))],
+                Bytes::new(),
+            )
+            .await?;
+        match response.status() {
+            StatusCode::OK | StatusCode::CREATED => Ok(None),
+            StatusCode::PERMANENT_REDIRECT => persisted_offset(&response).map(Some),
+            status => Err(make_err!(
+                Code::Unavailable,
+                "Querying a resumable upload failed in GCS with status {status}"
+            )),
+        }
+    }
+
+    /// Sends the chunk of a resumable upload that starts at `chunk_start`.
+    /// `total_size` must be set for the last chunk.
+    async fn upload_chunk(
+        &self,
+        session_uri: &str,
+        chunk: Bytes,
+        chunk_start: u64,
+        total_size: Option<u64>,
+    ) -> Result<ChunkResult, Error> {
+        let chunk_end = chunk_start + chunk.len() as u64;
+        let total = total_size.map_or_else(|| "*".to_string(), |sz| sz.to_string());
+        self.retrier
+            .retry(unfold(false, move |resuming| {
+                let chunk = chunk.clone();
+                let total = total.clone();
+                async move {
+                    // After a failure GCS may have persisted part of the
+                    // chunk, so only send what it is missing.
+                    let mut start = chunk_start;
+                    if resuming {
+                        match self.query_resumable_upload(session_uri, total_size).await {
+                            Ok(None) => {
+                                return Some((RetryResult::Ok(ChunkResult::Complete), true))
+                            }
+                            Ok(Some(persisted)) if persisted <= chunk_end => {
+                                start = persisted.max(chunk_start);
+                            }
+                            Ok(Some(persisted)) => {
+                                return Some((
+                                    RetryResult::Err(make_err!(
+                                    Code::Internal,
+                                    "GCS persisted {persisted} bytes, expected at most {chunk_end}"
+                                )),
+                                    true,
+                                ))
+                            }
+                            Err(e) => return Some((RetryResult::Retry(e), true)),
+                        }
+                    }
+                    let data = chunk.slice((start - chunk_start) as usize..);
+                    let content_range = if data.is_empty() {
+                        format!("bytes */{total}")
+                    } else {
+                        format!("bytes {start}-{}/{total}", chunk_end - 1)
+                    };
+                    let response = match self
+                        .send(
+                            Method::PUT,
+                            session_uri,
+                            &[(header::CONTENT_RANGE, content_range)],
+                            data,
+                        )
+                        .await
+                    {
+                        Ok(response) => response,
+                        Err(e) => return Some((RetryResult::Retry(e), true)),
+                    };
+                    let result = match response.status() {
+                        StatusCode::OK | StatusCode::CREATED => {
+                            RetryResult::Ok(ChunkResult::Complete)
+                        }
+                        StatusCode::PERMANENT_REDIRECT => match persisted_offset(&response) {
+                            Ok(persisted) if persisted == chunk_end => {
+                                RetryResult::Ok(ChunkResult::Incomplete)
+                            }
+                            Ok(persisted) => RetryResult::Retry(make_err!(
+                                Code::Unavailable,
+                                "GCS persisted {persisted} bytes of a chunk ending at {chunk_end}"
+                            )),
+                            Err(e) => RetryResult::Err(e),
+                        },
+                        _ => error_response(response, "Upload chunk").await,
+                    };
+                    Some((result, true))
+                }
+            }))
+            .await
+    }
+
+    async fn upload_resumable(
+        &self,
+        object_name: &str,
+        session_uri: &str,
+        reader: &mut DropCloserReadHalf,
+    ) -> Result<(), Error> {
+        let mut chunk_start = 0;
+        let mut chunk = reader
+            .consume(Some(self.resumable_chunk_size))
+            .await
+            .err_tip(|| "Failed to read chunk in gcs_store")?;
+        loop {
+            // Read ahead one chunk, as the last chunk has to carry the total
+            // size of the object.
+            let next_chunk = if chunk.len() < self.resumable_chunk_size {
+                Bytes::new()
+            } else {
+                reader
+                    .consume(Some(self.resumable_chunk_size))
+                    .await
+                    .err_tip(|| "Failed to read chunk in gcs_store")?
+            };
+            let chunk_len = chunk.len() as u64;
+            let total_size = next_chunk.is_empty().then_some(chunk_start + chunk_len);
+            let result = self
+                .upload_chunk(session_uri, chunk, chunk_start, total_size)
+                .await
+                .err_tip(|| format!("While uploading {object_name} to GCS"))?;
+            match (result, total_size) {
+                (ChunkResult::Complete, Some(_)) => return Ok(()),
+                (ChunkResult::Incomplete, None) => {}
+                (ChunkResult::Complete, None) => {
+                    return Err(make_err!(
+                        Code::Internal,
+                        "GCS completed the upload of {object_name} before the last chunk"
+                    ));
+                }
+                (ChunkResult::Incomplete, Some(_)) => {
+                    return Err(make_err!(
+                        Code::Internal,
+                        "GCS did not complete the upload of {object_name} after the last chunk"
+                    ));
+                }
+            }
+            chunk_start += chunk_len;
+            chunk = next_chunk;
+        }
+    }
+}
+
+/// Returns a JWT that asks `token_uri` for an access token of the service
+/// account `client_email`.
+fn make_jwt(
+    client_email: &str,
+    token_uri: &str,
+    key_pair: &RsaKeyPair,
+    now_s: u64,
+) -> Result<String, Error> {
+    let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"RS256","typ":"JWT"}"#);
+    let claims = URL_SAFE_NO_PAD.encode(
+        serde_json::json!({
+            "iss": client_email,
+            "scope": STORAGE_SCOPE,
+            "aud": token_uri,
+            "iat": now_s,
+            "exp": now_s + SERVICE_ACCOUNT_TOKEN_LIFETIME.as_secs(),
+        })
+        .to_string(),
+    );
+    let message = format!("{header}.{claims}");
+    let mut signature = vec![0; key_pair.public().modulus_len()];
+    key_pair
+        .sign(
+            &RSA_PKCS1_SHA256,
+            &SystemRandom::new(),
+            message.as_bytes(),
+            &mut signature,
+        )
+        .map_err(|e| make_err!(Code::Internal, "Could not sign GCS token request: {e}"))?;
+    Ok(format!("{message}.{}", URL_SAFE_NO_PAD.encode(signature)))
+}
+
+#[async_trait]
+impl<I, NowFn> StoreDriver for GcsStore<NowFn>
+where
+    I: InstantWrapper,
+    NowFn: Fn() -> I + Send + Sync + Unpin + 'static,
+{
+    async fn has_with_results(
+        self: Pin<&Self>,
+        keys: &[StoreKey<'_>],
+        results: &mut [Option<u64>],
+    ) -> Result<(), Error> {
+        keys.iter()
+            .zip(results.iter_mut())
+            .map(|(key, result)| async move {
+                // We need to do a special pass to ensure our zero key exist.
+                if is_zero_digest(key.borrow()) {
+                    *result = Some(0);
+                    return Ok::<_, Error>(());
+                }
+                *result = self.has(key).await?;
+                Ok::<_, Error>(())
+            })
+            .collect::<FuturesUnordered<_>>()
+            .try_collect()
+            .await
+    }
+
+    async fn update(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        mut reader: DropCloserReadHalf,
+        upload_size: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        let object_name = &self.make_object_name(&key);
+
+        // Objects that fit in a single chunk are uploaded with a single
+        // request instead of the minimum of two a resumable upload needs.
+        if let UploadSizeInfo::ExactSize(sz) = upload_size {
+            if sz <= self.resumable_chunk_size as u64 {
+                let data = reader
+                    .consume(None)
+                    .await
+                    .err_tip(|| "Failed to read data in gcs_store")?;
+                return self
+                    .upload_simple(object_name, data)
+                    .await
+                    .err_tip(|| "Failed to upload file to GCS in a single request");
+            }
+        }
+
+        let session_uri = &self
+            .start_resumable_upload(object_name, upload_size)
+            .await
+            .err_tip(|| "Failed to start resumable upload in GCS")?;
+        match self
+            .upload_resumable(object_name, session_uri, &mut reader)
+            .await
+        {
+            Ok(()) => Ok(()),
+            Err(err) => {
+                // Note: We don't retry here because this is just a best attempt.
+                let cancel_res = self
+                    .send(Method::DELETE, session_uri, &[], Bytes::new())
+                    .await
+                    .map(|_| ())
+                    .err_tip(|| "Failed to cancel resumable upload in GCS");
+                if let Err(cancel_err) = &cancel_res {
+                    event!(Level::INFO, ?cancel_err, "Resumable upload error");
+                }
+                Err(err)
+            }
+        }
+    }
+
+    async fn get_part(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        writer: &mut DropCloserWriteHalf,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<(), Error> {
+        if is_zero_digest(key.borrow()) || length == Some(0) {
+            writer
+                .send_eof()
+                .err_tip(|| "Failed to send zero EOF in gcs store get_part")?;
+            return Ok(());
+        }
+
+        let uri = &self.object_uri(&self.make_object_name(&key), "alt=media");
+        let end_read_byte = length
+            .map_or(Some(None), |length| Some(offset.checked_add(length)))
+            .err_tip(|| "Integer overflow protection triggered")?;
+
+        self.retrier
+            .retry(unfold(writer, move |writer| async move {
+                // Retries continue where the previous attempt stopped.
+                let start = offset + writer.get_bytes_written();
+                let range = format!(
+                    "bytes={start}-{}",
+                    // Unlike `length`, the end of HTTP ranges is inclusive.
+                    end_read_byte.map_or_else(String::new, |end| (end - 1).to_string())
+                );
+                let response = match self
+                    .send(Method::GET, uri, &[(header::RANGE, range)], Bytes::new())
+                    .await
+                {
+                    Ok(response) => response,
+                    Err(e) => return Some((RetryResult::Retry(e), writer)),
+                };
+                match response.status() {
+                    StatusCode::OK | StatusCode::PARTIAL_CONTENT => {}
+                    // The offset is at or past the end of the object.
+                    StatusCode::RANGE_NOT_SATISFIABLE => {
+                        let result = writer.send_eof().map_or_else(
+                            |e| {
+                                RetryResult::Err(make_err!(
+                                    Code::Aborted,
+                                    "Failed to send EOF to consumer in GCS: {e}"
+                                ))
+                            },
+                            RetryResult::Ok,
+                        );
+                        return Some((result, writer));
+                    }
+                    _ => return Some((error_response(response, "Get object").await, writer)),
+                }
+
+                // Copy data from the response body to the writer stream.
+                let mut body = response.into_body();
+                while let Some(maybe_bytes) = body.data().await {
+                    match maybe_bytes {
+                        Ok(bytes) => {
+                            if bytes.is_empty() {
+                                continue;
+                            }
+                            if let Err(e) = writer.send(bytes).await {
+                                return Some((
+                                    RetryResult::Err(make_err!(
+                                        Code::Aborted,
+                                        "Error sending bytes to consumer in GCS: {e}"
+                                    )),
+                                    writer,
+                                ));
+                            }
+                        }
+                        Err(e) => {
+                            return Some((
+                                RetryResult::Retry(make_err!(
+                                    Code::Aborted,
+                                    "Bad response body in GCS: {e}"
+                                )),
+                                writer,
+                            ));
+                        }
+                    }
+                }
+                if let Err(e) = writer.send_eof() {
+                    return Some((
+                        RetryResult::Err(make_err!(
+                            Code::Aborted,
+                            "Failed to send EOF to consumer in GCS: {e}"
+                        )),
+                        writer,
+                    ));
+                }
+                Some((RetryResult::Ok(()), writer))
+            }))
+            .await
+    }
+
+    fn inner_store(&self, _key: Option<StoreKey>) -> &'_ dyn StoreDriver {
+        self
+    }
+
+    fn as_any<'a>(&'a self) -> &'a (dyn std::any::Any + Sync + Send + 'static) {
+        self
+    }
+
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn std::any::Any + Sync + Send + 'static> {
+        self
+    }
+}
+
+#[async_trait]
+impl<I, NowFn> HealthStatusIndicator for GcsStore<NowFn>
+where
+    I: InstantWrapper,
+    NowFn: Fn() -> I + Send + Sync + Unpin + 'static,
+{
+    fn get_name(&self) -> &'static str {
+        "GcsStore"
+    }
+
+    async fn check_health(&self, namespace: Cow<'static, str>) -> HealthStatus {
+        StoreDriver::check_health(Pin::new(self), namespace).await
+    }
+}
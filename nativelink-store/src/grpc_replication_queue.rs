@@ -0,0 +1,435 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use bytes::BytesMut;
+use nativelink_config::stores::GrpcReplicationSpec;
+use nativelink_error::{error_if, make_input_err, Code, Error, ResultExt};
+use nativelink_metric::MetricsComponent;
+use nativelink_util::buf_channel::{DropCloserReadHalf, DropCloserWriteHalf};
+use nativelink_util::common::DigestInfo;
+use nativelink_util::digest_hasher::DigestHasherFunc;
+use nativelink_util::fs;
+use parking_lot::Mutex;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::Notify;
+use tracing::{event, Level};
+use uuid::Uuid;
+
+/// Default value of [`GrpcReplicationSpec::max_batch_blob_size`].
+const DEFAULT_MAX_BATCH_BLOB_SIZE: u64 = 64 * 1024;
+
+/// Default value of [`GrpcReplicationSpec::max_batch_total_size`].
+const DEFAULT_MAX_BATCH_TOTAL_SIZE: u64 = 3 * 1024 * 1024;
+
+/// Default value of [`GrpcReplicationSpec::max_concurrent_pushes`].
+const DEFAULT_MAX_CONCURRENT_PUSHES: usize = 16;
+
+/// Default value of [`GrpcReplicationSpec::retry_delay_s`].
+const DEFAULT_RETRY_DELAY_S: u64 = 30;
+
+/// Folder inside the queue directory holding the queued blobs.
+const BLOBS_FOLDER: &str = "blobs";
+
+/// Folder inside the queue directory holding blobs still being written.
+const TMP_FOLDER: &str = "tmp";
+
+/// A blob waiting to be pushed to the upstream store.
+struct QueuedBlob {
+    digest_function: DigestHasherFunc,
+    /// Set after a failed push, the blob is not pushed again before then.
+    retry_at: Option<Instant>,
+}
+
+/// Blobs written to a `GrpcStore` in replication mode that the upstream
+/// store did not accept yet. Every queued blob is a file named after its
+/// digest, so the queue survives restarts.
+#[derive(MetricsComponent)]
+pub(crate) struct ReplicationQueue {
+    #[metric(help = "Directory the queued blobs are written to")]
+    queue_dir: String,
+    #[metric(help = "Blobs up to this size are pushed with BatchUpdateBlobs")]
+    pub(crate) max_batch_blob_size: u64,
+    #[metric(help = "Maximum total size of the blobs in one BatchUpdateBlobs request")]
+    pub(crate) max_batch_total_size: u64,
+    #[metric(help = "Maximum number of pushes in flight at once")]
+    pub(crate) max_concurrent_pushes: usize,
+    #[metric(help = "Time to wait before pushing a blob again after a failed push")]
+    pub(crate) retry_delay: Duration,
+    blobs: Mutex<HashMap<DigestInfo, QueuedBlob>>,
+    /// Notified every time a blob is added to the queue.
+    pub(crate) notify: Notify,
+    #[metric(help = "Number of blobs waiting to be pushed")]
+    queued_blobs: AtomicU64,
+    #[metric(help = "Number of blobs pushed to the upstream store")]
+    pushed_blobs: AtomicU64,
+    #[metric(help = "Number of pushes of a blob that failed")]
+    failed_pushes: AtomicU64,
+}
+
+impl ReplicationQueue {
+    /// Opens the queue in `spec.queue_dir` and loads the blobs left in it
+    /// by a previous run.
+    pub(crate) async fn open(spec: &GrpcReplicationSpec) -> Result<Self, Error> {
+        error_if!(
+            spec.queue_dir.is_empty(),
+            "GrpcStore replication.queue_dir must be set"
+        );
+        let or_default = |value: usize, default: u64| {
+            if value == 0 {
+                default
+            } else {
+                value as u64
+            }
+        };
+        let max_batch_blob_size = or_default(spec.max_batch_blob_size, DEFAULT_MAX_BATCH_BLOB_SIZE);
+        let max_batch_total_size =
+            or_default(spec.max_batch_total_size, DEFAULT_MAX_BATCH_TOTAL_SIZE);
+        error_if!(
+            max_batch_blob_size > max_batch_total_size,
+            "GrpcStore replication.max_batch_blob_size ({max_batch_blob_size}) must not be larger than max_batch_total_size ({max_batch_total_size})"
+        );
+
+        let queue_dir = Path::new(&spec.queue_dir);
+        let tmp_dir = queue_dir.join(TMP_FOLDER);
+        // Anything in the temp folder was being written when the process
+        // stopped and was never acknowledged to the client.
+        if fs::metadata(&tmp_dir).await.is_ok() {
+            fs::remove_dir_all(&tmp_dir)
+                .await
+                .err_tip(|| "Failed to clear temp folder of GrpcStore replication queue")?;
+        }
+        fs::create_dir_all(&tmp_dir)
+            .await
+            .err_tip(|| "Failed to create temp folder of GrpcStore replication queue")?;
+        let blobs_dir = queue_dir.join(BLOBS_FOLDER);
+        fs::create_dir_all(&blobs_dir)
+            .await
+            .err_tip(|| "Failed to create blobs folder of GrpcStore replication queue")?;
+
+        let mut blobs = HashMap::new();
+        let (_permit, mut dir_handle) = fs::read_dir(&blobs_dir)
+            .await
+            .err_tip(|| "Failed to open blobs folder of GrpcStore replication queue")?
+            .into_inner();
+        while let Some(entry) = dir_handle
+            .next_entry()
+            .await
+            .err_tip(|| "Failed to read blobs folder of GrpcStore replication queue")?
+        {
+            let file_name = entry.file_name();
+            let parsed = file_name
+                .to_str()
+                .ok_or_else(|| make_input_err!("File name is not valid utf8"))
+                .and_then(parse_blob_file_name);
+            match parsed {
+                Ok((digest_function, digest)) => {
+                    blobs.insert(
+                        digest,
+                        QueuedBlob {
+                            digest_function,
+                            retry_at: None,
+                        },
+                    );
+                }
+                Err(err) => {
+                    event!(
+                        Level::WARN,
+                        ?file_name,
+                        ?err,
+                        "Ignoring unknown file in GrpcStore replication queue"
+                    );
+                }
+            }
+        }
+        if !blobs.is_empty() {
+            event!(
+                Level::INFO,
+                count = blobs.len(),
+                "Found blobs left in GrpcStore replication queue"
+            );
+        }
+
+        Ok(Self {
+            queue_dir: spec.queue_dir.clone(),
+            max_batch_blob_size,
+            max_batch_total_size,
+            max_concurrent_pushes: if spec.max_concurrent_pushes == 0 {
+                DEFAULT_MAX_CONCURRENT_PUSHES
+            } else {
+                spec.max_concurrent_pushes
+            },
+            retry_delay: Duration::from_secs(if spec.retry_delay_s == 0 {
+                DEFAULT_RETRY_DELAY_S
+            } else {
+                spec.retry_delay_s
+            }),
+            queued_blobs: AtomicU64::new(blobs.len() as u64),
+            blobs: Mutex::new(blobs),
+            notify: Notify::new(),
+            pushed_blobs: AtomicU64::new(0),
+            failed_pushes: AtomicU64::new(0),
+        })
+    }
+
+    fn blob_path(&self, digest_function: DigestHasherFunc, digest: DigestInfo) -> PathBuf {
+        Path::new(&self.queue_dir)
+            .join(BLOBS_FOLDER)
+            .join(format!("{digest_function}-{digest}"))
+    }
+
+    /// Returns the digest function of `digest` if it is in the queue.
+    pub(crate) fn queued(&self, digest: DigestInfo) -> Option<DigestHasherFunc> {
+        self.blobs
+            .lock()
+            .get(&digest)
+            .map(|blob| blob.digest_function)
+    }
+
+    /// Writes the data of `reader` to the queue. Once this returns the
+    /// blob is on disk and will be pushed, even after a restart.
+    pub(crate) async fn enqueue(
+        &self,
+        digest_function: DigestHasherFunc,
+        digest: DigestInfo,
+        mut reader: DropCloserReadHalf,
+    ) -> Result<(), Error> {
+        if self.queued(digest).is_some() {
+            // The content of a digest never changes, so the queued copy is
+            // just as good.
+            return reader
+                .drain()
+                .await
+                .err_tip(|| "Failed to drain reader in ReplicationQueue::enqueue");
+        }
+
+        let temp_path = Path::new(&self.queue_dir)
+            .join(TMP_FOLDER)
+            .join(Uuid::new_v4().to_string());
+        let write_result = write_file(&temp_path, &mut reader).await.and_then(|size| {
+            error_if!(
+                size != digest.size_bytes(),
+                "Received {size} bytes for digest {digest} in ReplicationQueue::enqueue"
+            );
+            Ok(())
+        });
+        if let Err(err) = write_result {
+            if let Err(remove_err) = fs::remove_file(&temp_path).await {
+                event!(
+                    Level::WARN,
+                    ?temp_path,
+                    ?remove_err,
+                    "Failed to delete temp file of GrpcStore replication queue"
+                );
+            }
+            return Err(err);
+        }
+        fs::rename(&temp_path, self.blob_path(digest_function, digest))
+            .await
+            .err_tip(|| "Failed to move blob into GrpcStore replication queue")?;
+
+        let previous = self.blobs.lock().insert(
+            digest,
+            QueuedBlob {
+                digest_function,
+                retry_at: None,
+            },
+        );
+        if previous.is_none() {
+            self.queued_blobs.fetch_add(1, Ordering::Relaxed);
+        }
+        self.notify.notify_one();
+        Ok(())
+    }
+
+    /// Returns the blobs that are due to be pushed, sorted by digest
+    /// function and size.
+    pub(crate) fn due_blobs(&self) -> Vec<(DigestHasherFunc, DigestInfo)> {
+        let now = Instant::now();
+        let mut due: Vec<_> = self
+            .blobs
+            .lock()
+            .iter()
+            .filter(|(_, blob)| blob.retry_at.is_none_or(|retry_at| retry_at <= now))
+            .map(|(digest, blob)| (blob.digest_function, *digest))
+            .collect();
+        due.sort_unstable_by_key(|(digest_function, digest)| {
+            (*digest_function, digest.size_bytes())
+        });
+        due
+    }
+
+    /// Reads a queued blob into memory. Returns `None` if its file is gone.
+    pub(crate) async fn read_blob(
+        &self,
+        digest_function: DigestHasherFunc,
+        digest: DigestInfo,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        match fs::read(self.blob_path(digest_function, digest)).await {
+            Ok(data) => Ok(Some(data)),
+            Err(err) if err.code == Code::NotFound => Ok(None),
+            Err(err) => Err(err).err_tip(|| "Failed to read blob of GrpcStore replication queue"),
+        }
+    }
+
+    /// Opens a queued blob at `offset`, reading at most `length` bytes.
+    /// Returns `None` if its file is gone.
+    pub(crate) async fn open_blob(
+        &self,
+        digest_function: DigestHasherFunc,
+        digest: DigestInfo,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<Option<fs::ResumeableFileSlot>, Error> {
+        let mut file = match fs::open_file(
+            self.blob_path(digest_function, digest),
+            length.unwrap_or(u64::MAX),
+        )
+        .await
+        {
+            Ok(file) => file,
+            Err(err) if err.code == Code::NotFound => return Ok(None),
+            Err(err) => {
+                return Err(err).err_tip(|| "Failed to open blob of GrpcStore replication queue")
+            }
+        };
+        file.as_reader()
+            .await
+            .err_tip(|| "Could not get reader in ReplicationQueue::open_blob")?
+            .get_mut()
+            .seek(SeekFrom::Start(offset))
+            .await
+            .err_tip(|| "Failed to seek in ReplicationQueue::open_blob")?;
+        Ok(Some(file))
+    }
+
+    /// Records the outcome of a push of `digest`. A pushed blob is removed
+    /// from the queue, a failed one is pushed again after `retry_delay`.
+    pub(crate) async fn finish_push(
+        &self,
+        digest_function: DigestHasherFunc,
+        digest: DigestInfo,
+        result: Result<(), Error>,
+    ) {
+        if let Err(err) = result {
+            self.failed_pushes.fetch_add(1, Ordering::Relaxed);
+            event!(
+                Level::WARN,
+                %digest,
+                ?err,
+                "Failed to push blob of GrpcStore replication queue, will retry"
+            );
+            if let Some(blob) = self.blobs.lock().get_mut(&digest) {
+                blob.retry_at = Some(Instant::now() + self.retry_delay);
+            }
+            return;
+        }
+        self.pushed_blobs.fetch_add(1, Ordering::Relaxed);
+        self.forget(digest_function, digest).await;
+    }
+
+    /// Removes `digest` from the queue and deletes its file.
+    pub(crate) async fn forget(&self, digest_function: DigestHasherFunc, digest: DigestInfo) {
+        if self.blobs.lock().remove(&digest).is_some() {
+            self.queued_blobs.fetch_sub(1, Ordering::Relaxed);
+        }
+        let path = self.blob_path(digest_function, digest);
+        match fs::remove_file(&path).await {
+            Err(err) if err.code != Code::NotFound => {
+                event!(
+                    Level::WARN,
+                    ?path,
+                    ?err,
+                    "Failed to delete blob of GrpcStore replication queue"
+                );
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Parses a file name of the form `{digest_function}-{hash}-{size}`.
+fn parse_blob_file_name(file_name: &str) -> Result<(DigestHasherFunc, DigestInfo), Error> {
+    let (digest_function, digest) = file_name
+        .split_once('-')
+        .err_tip(|| format!("Missing digest function in {file_name}"))?;
+    let (hash, size) = digest
+        .rsplit_once('-')
+        .err_tip(|| format!("Missing size in {file_name}"))?;
+    Ok((
+        DigestHasherFunc::try_from(digest_function)?,
+        DigestInfo::try_new(hash, size.parse::<u64>()?)?,
+    ))
+}
+
+/// Writes the data of `reader` to a new file at `path` and syncs it to
+/// disk. Returns the number of bytes written.
+async fn write_file(path: &Path, reader: &mut DropCloserReadHalf) -> Result<u64, Error> {
+    let mut file = fs::create_file(path)
+        .await
+        .err_tip(|| "Failed to create file in GrpcStore replication queue")?;
+    let mut size = 0;
+    loop {
+        let mut data = reader
+            .recv()
+            .await
+            .err_tip(|| "Failed to receive data in GrpcStore replication queue")?;
+        if data.is_empty() {
+            break; // EOF.
+        }
+        size += data.len() as u64;
+        file.as_writer()
+            .await
+            .err_tip(|| "in GrpcStore replication queue write_file")?
+            .write_all_buf(&mut data)
+            .await
+            .err_tip(|| "Failed to write data into GrpcStore replication queue")?;
+    }
+    file.as_writer()
+        .await
+        .err_tip(|| "in GrpcStore replication queue write_file")?
+        .as_ref()
+        .sync_all()
+        .await
+        .err_tip(|| "Failed to sync file in GrpcStore replication queue")?;
+    Ok(size)
+}
+
+/// Sends the data of `file` to `writer`, followed by EOF.
+pub(crate) async fn send_file(
+    file: &mut fs::ResumeableFileSlot,
+    writer: &mut DropCloserWriteHalf,
+) -> Result<(), Error> {
+    let (_, writer) = file
+        .read_buf_cb(
+            (BytesMut::with_capacity(fs::DEFAULT_READ_BUFF_SIZE), writer),
+            move |(chunk, writer)| async move {
+                writer
+                    .send(chunk.freeze())
+                    .await
+                    .err_tip(|| "Failed to send data from GrpcStore replication queue")?;
+                Ok((BytesMut::with_capacity(fs::DEFAULT_READ_BUFF_SIZE), writer))
+            },
+        )
+        .await
+        .err_tip(|| "Failed to read blob of GrpcStore replication queue")?;
+    writer
+        .send_eof()
+        .err_tip(|| "Failed to send EOF from GrpcStore replication queue")
+}
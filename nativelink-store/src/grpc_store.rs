@@ -13,33 +13,39 @@
 // limitations under the License.
 
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
 use std::time::Duration;
 
 use async_trait::async_trait;
 use bytes::BytesMut;
-use futures::stream::{unfold, FuturesUnordered};
-use futures::{future, Future, Stream, StreamExt, TryFutureExt, TryStreamExt};
+use futures::stream::{self, unfold, FuturesUnordered};
+use futures::{future, join, Future, Stream, StreamExt, TryFutureExt, TryStreamExt};
 use nativelink_config::stores::GrpcSpec;
 use nativelink_error::{error_if, make_input_err, Error, ResultExt};
 use nativelink_metric::MetricsComponent;
 use nativelink_proto::build::bazel::remote::execution::v2::action_cache_client::ActionCacheClient;
 use nativelink_proto::build::bazel::remote::execution::v2::content_addressable_storage_client::ContentAddressableStorageClient;
 use nativelink_proto::build::bazel::remote::execution::v2::{
-    ActionResult, BatchReadBlobsRequest, BatchReadBlobsResponse, BatchUpdateBlobsRequest,
-    BatchUpdateBlobsResponse, FindMissingBlobsRequest, FindMissingBlobsResponse,
-    GetActionResultRequest, GetTreeRequest, GetTreeResponse, UpdateActionResultRequest,
+    batch_update_blobs_request, compressor, ActionResult, BatchReadBlobsRequest,
+    BatchReadBlobsResponse, BatchUpdateBlobsRequest, BatchUpdateBlobsResponse,
+    FindMissingBlobsRequest, FindMissingBlobsResponse, GetActionResultRequest, GetTreeRequest,
+    GetTreeResponse, UpdateActionResultRequest,
 };
 use nativelink_proto::google::bytestream::byte_stream_client::ByteStreamClient;
 use nativelink_proto::google::bytestream::{
     QueryWriteStatusRequest, QueryWriteStatusResponse, ReadRequest, ReadResponse, WriteRequest,
     WriteResponse,
 };
-use nativelink_util::buf_channel::{DropCloserReadHalf, DropCloserWriteHalf};
+use nativelink_util::buf_channel::{
+    make_buf_channel_pair, DropCloserReadHalf, DropCloserWriteHalf,
+};
 use nativelink_util::common::DigestInfo;
 use nativelink_util::connection_manager::ConnectionManager;
-use nativelink_util::digest_hasher::{default_digest_hasher_func, ACTIVE_HASHER_FUNC};
+use nativelink_util::digest_hasher::{
+    default_digest_hasher_func, DigestHasherFunc, ACTIVE_HASHER_FUNC,
+};
 use nativelink_util::health_utils::HealthStatusIndicator;
 use nativelink_util::origin_context::ActiveOriginContext;
 use nativelink_util::proto_stream_utils::{
@@ -48,16 +54,23 @@ use nativelink_util::proto_stream_utils::{
 use nativelink_util::resource_info::ResourceInfo;
 use nativelink_util::retry::{Retrier, RetryResult};
 use nativelink_util::store_trait::{StoreDriver, StoreKey, UploadSizeInfo};
-use nativelink_util::{default_health_status_indicator, tls_utils};
+use nativelink_util::{background_spawn, default_health_status_indicator, tls_utils};
 use parking_lot::Mutex;
 use prost::Message;
 use rand::rngs::OsRng;
 use rand::Rng;
+use tokio::sync::mpsc;
 use tokio::time::sleep;
 use tonic::{IntoRequest, Request, Response, Status, Streaming};
 use tracing::{event, Level};
 use uuid::Uuid;
 
+use crate::grpc_replication_queue::{send_file, ReplicationQueue};
+
+// Default max buffer size for resuming failed uploads.
+// Note: If you change this, adjust the docs in the config.
+const DEFAULT_MAX_RETRY_BUFFER_SIZE: usize = 5 * 1024 * 1024; // 5MB.
+
 // This store is usually a pass-through store, but can also be used as a CAS store. Using it as an
 // AC store has one major side-effect... The has() function may not give the proper size of the
 // underlying data. This might cause issues if embedded in certain stores.
@@ -68,6 +81,24 @@ pub struct GrpcStore {
     store_type: nativelink_config::stores::StoreType,
     retrier: Retrier,
     connection_manager: ConnectionManager,
+    #[metric(group = "replication")]
+    replication: Option<Arc<ReplicationQueue>>,
+    max_retry_buffer_size: usize,
+}
+
+/// Outcome of a single `ByteStream.Write` call of `GrpcStore::write_blob()`.
+enum WriteAttempt {
+    Done,
+    /// The upstream store failed, the upload may be resumed.
+    Failed(Error),
+}
+
+/// A push of queued blobs to the upstream store.
+enum Push {
+    /// Small blobs sent together with `BatchUpdateBlobs`.
+    Batch(DigestHasherFunc, Vec<DigestInfo>),
+    /// A large blob sent with `ByteStream.Write`.
+    Stream(DigestHasherFunc, DigestInfo),
 }
 
 impl GrpcStore {
@@ -102,8 +133,19 @@ impl GrpcStore {
             endpoints.push(endpoint);
         }
 
+        let replication = match &spec.replication {
+            Some(replication_spec) => {
+                error_if!(
+                    !matches!(spec.store_type, nativelink_config::stores::StoreType::cas),
+                    "GrpcStore replication is only supported when store_type is cas"
+                );
+                Some(Arc::new(ReplicationQueue::open(replication_spec).await?))
+            }
+            None => None,
+        };
+
         let jitter_fn = Arc::new(jitter_fn);
-        Ok(Arc::new(GrpcStore {
+        let store = Arc::new(GrpcStore {
             instance_name: spec.instance_name.clone(),
             store_type: spec.store_type,
             retrier: Retrier::new(
@@ -118,7 +160,178 @@ impl GrpcStore {
                 spec.retry.clone(),
                 jitter_fn,
             ),
-        }))
+            replication: replication.clone(),
+            max_retry_buffer_size: if spec.max_retry_buffer_size == 0 {
+                DEFAULT_MAX_RETRY_BUFFER_SIZE
+            } else {
+                spec.max_retry_buffer_size
+            },
+        });
+        if let Some(replication) = replication {
+            background_spawn!(
+                "grpc_store_replication",
+                Self::replicate(Arc::downgrade(&store), replication)
+            );
+        }
+        Ok(store)
+    }
+
+    /// Pushes the blobs of the replication queue to the upstream store
+    /// until the store is dropped. Runs whenever a blob is queued and at
+    /// least every `retry_delay`, so failed pushes are retried.
+    async fn replicate(weak_self: Weak<Self>, queue: Arc<ReplicationQueue>) {
+        loop {
+            {
+                let Some(store) = weak_self.upgrade() else {
+                    return;
+                };
+                store.push_queued_blobs(&queue).await;
+            }
+            tokio::select! {
+                () = queue.notify.notified() => {}
+                () = sleep(queue.retry_delay) => {}
+            }
+        }
+    }
+
+    async fn push_queued_blobs(&self, queue: &ReplicationQueue) {
+        let mut pushes = Vec::new();
+        let mut batch: Option<(DigestHasherFunc, Vec<DigestInfo>, u64)> = None;
+        for (digest_function, digest) in queue.due_blobs() {
+            let size = digest.size_bytes();
+            if size > queue.max_batch_blob_size {
+                pushes.push(Push::Stream(digest_function, digest));
+                continue;
+            }
+            match &mut batch {
+                Some((batch_function, digests, batch_size))
+                    if *batch_function == digest_function
+                        && *batch_size + size <= queue.max_batch_total_size =>
+                {
+                    digests.push(digest);
+                    *batch_size += size;
+                }
+                _ => {
+                    if let Some((batch_function, digests, _)) =
+                        batch.replace((digest_function, vec![digest], size))
+                    {
+                        pushes.push(Push::Batch(batch_function, digests));
+                    }
+                }
+            }
+        }
+        if let Some((batch_function, digests, _)) = batch {
+            pushes.push(Push::Batch(batch_function, digests));
+        }
+
+        stream::iter(pushes)
+            .for_each_concurrent(queue.max_concurrent_pushes, |push| async move {
+                match push {
+                    Push::Batch(digest_function, digests) => {
+                        for (digest, result) in
+                            self.push_batch(queue, digest_function, digests).await
+                        {
+                            queue.finish_push(digest_function, digest, result).await;
+                        }
+                    }
+                    Push::Stream(digest_function, digest) => {
+                        let result = self.push_stream(queue, digest_function, digest).await;
+                        queue.finish_push(digest_function, digest, result).await;
+                    }
+                }
+            })
+            .await;
+    }
+
+    /// Sends queued blobs to the upstream store in one `BatchUpdateBlobs`
+    /// request and returns the result of each blob.
+    async fn push_batch(
+        &self,
+        queue: &ReplicationQueue,
+        digest_function: DigestHasherFunc,
+        digests: Vec<DigestInfo>,
+    ) -> Vec<(DigestInfo, Result<(), Error>)> {
+        let mut results = Vec::new();
+        let mut requests = Vec::with_capacity(digests.len());
+        for digest in digests {
+            match queue.read_blob(digest_function, digest).await {
+                Ok(Some(data)) => requests.push(batch_update_blobs_request::Request {
+                    digest: Some(digest.into()),
+                    data: data.into(),
+                    compressor: compressor::Value::Identity.into(),
+                }),
+                // The blob was pushed and removed while it was queued again.
+                Ok(None) => queue.forget(digest_function, digest).await,
+                Err(err) => results.push((digest, Err(err))),
+            }
+        }
+        if requests.is_empty() {
+            return results;
+        }
+
+        let pushed: Vec<DigestInfo> = requests
+            .iter()
+            .filter_map(|request| DigestInfo::try_from(request.digest.clone()?).ok())
+            .collect();
+        let response = self
+            .batch_update_blobs(Request::new(BatchUpdateBlobsRequest {
+                instance_name: self.instance_name.clone(),
+                requests,
+                digest_function: digest_function.proto_digest_func().into(),
+            }))
+            .await
+            .err_tip(|| "In GrpcStore::push_batch");
+        let mut statuses = match response {
+            Ok(response) => response
+                .into_inner()
+                .responses
+                .into_iter()
+                .filter_map(|response| {
+                    Some((
+                        DigestInfo::try_from(response.digest?).ok()?,
+                        response.status,
+                    ))
+                })
+                .collect::<HashMap<_, _>>(),
+            Err(err) => {
+                results.extend(pushed.into_iter().map(|digest| (digest, Err(err.clone()))));
+                return results;
+            }
+        };
+        results.extend(pushed.into_iter().map(|digest| {
+            let result = match statuses.remove(&digest) {
+                Some(None) => Ok(()),
+                Some(Some(status)) if status.code == 0 => Ok(()),
+                Some(Some(status)) => Err(status.into()),
+                None => Err(make_input_err!(
+                    "Upstream did not return a status for {digest} in BatchUpdateBlobs"
+                )),
+            };
+            (digest, result)
+        }));
+        results
+    }
+
+    /// Streams a queued blob to the upstream store with `ByteStream.Write`.
+    async fn push_stream(
+        &self,
+        queue: &ReplicationQueue,
+        digest_function: DigestHasherFunc,
+        digest: DigestInfo,
+    ) -> Result<(), Error> {
+        let Some(mut file) = queue.open_blob(digest_function, digest, 0, None).await? else {
+            // The blob was pushed and removed while it was queued again.
+            queue.forget(digest_function, digest).await;
+            return Ok(());
+        };
+        let (mut tx, rx) = make_buf_channel_pair();
+        let (write_result, read_result) = join!(
+            self.write_blob(digest_function, digest, rx),
+            send_file(&mut file, &mut tx)
+        );
+        write_result
+            .merge(read_result)
+            .err_tip(|| "In GrpcStore::push_stream")
     }
 
     async fn perform_request<F, Fut, R, I>(&self, input: I, mut request: F) -> Result<R, Error>
@@ -427,6 +640,18 @@ impl GrpcStore {
         .await
     }
 
+    /// Returns the `ResourceInfo` of `digest` using the digest function of
+    /// the active request.
+    fn resource_info_for_digest(&self, digest: DigestInfo) -> Result<ResourceInfo<'_>, Error> {
+        let digest_function = ActiveOriginContext::get_value(&ACTIVE_HASHER_FUNC)
+            .err_tip(|| "In GrpcStore::resource_info_for_digest")?
+            .map_or_else(default_digest_hasher_func, |v| *v);
+        Ok(
+            ResourceInfo::from_digest(self.instance_name.as_str(), digest)
+                .with_digest_function(digest_function),
+        )
+    }
+
     async fn get_action_result_from_digest(
         &self,
         digest: DigestInfo,
@@ -503,36 +728,14 @@ impl GrpcStore {
             .await
             .map(|_| ())
     }
-}
 
-#[async_trait]
-impl StoreDriver for GrpcStore {
-    // NOTE: This function can only be safely used on CAS stores. AC stores may return a size that
-    // is incorrect.
-    async fn has_with_results(
-        self: Pin<&Self>,
+    /// Sets the result of each key to its size if the upstream store has
+    /// it and to `None` if it is missing.
+    async fn find_missing_upstream(
+        &self,
         keys: &[StoreKey<'_>],
         results: &mut [Option<u64>],
     ) -> Result<(), Error> {
-        if matches!(self.store_type, nativelink_config::stores::StoreType::ac) {
-            keys.iter()
-                .zip(results.iter_mut())
-                .map(|(key, result)| async move {
-                    // The length of an AC is incorrect, so we don't figure out the
-                    // length, instead the biggest possible result is returned in the
-                    // hope that we detect incorrect usage.
-                    self.get_action_result_from_digest(key.borrow().into_digest())
-                        .await?;
-                    *result = Some(u64::MAX);
-                    Ok::<_, Error>(())
-                })
-                .collect::<FuturesUnordered<_>>()
-                .try_for_each(|()| future::ready(Ok(())))
-                .await
-                .err_tip(|| "Getting upstream action cache entry")?;
-            return Ok(());
-        }
-
         let missing_blobs_response = self
             .find_missing_blobs(Request::new(FindMissingBlobsRequest {
                 instance_name: self.instance_name.clone(),
@@ -541,7 +744,7 @@ impl StoreDriver for GrpcStore {
                     .map(|k| k.borrow().into_digest().into())
                     .collect(),
                 digest_function: ActiveOriginContext::get_value(&ACTIVE_HASHER_FUNC)
-                    .err_tip(|| "In GrpcStore::has_with_results")?
+                    .err_tip(|| "In GrpcStore::find_missing_upstream")?
                     .map_or_else(default_digest_hasher_func, |v| *v)
                     .proto_digest_func()
                     .into(),
@@ -574,83 +777,207 @@ impl StoreDriver for GrpcStore {
         Ok(())
     }
 
-    async fn update(
-        self: Pin<&Self>,
-        key: StoreKey<'_>,
-        reader: DropCloserReadHalf,
-        _size_info: UploadSizeInfo,
+    /// Uploads the data of `reader` to the upstream CAS with
+    /// `ByteStream.Write`.
+    async fn write_blob(
+        &self,
+        digest_function: DigestHasherFunc,
+        digest: DigestInfo,
+        mut reader: DropCloserReadHalf,
     ) -> Result<(), Error> {
-        let digest = key.into_digest();
-        if matches!(self.store_type, nativelink_config::stores::StoreType::ac) {
-            return self.update_action_result_from_bytes(digest, reader).await;
-        }
+        const IS_UPLOAD_TRUE: bool = true;
 
         let mut buf = Uuid::encode_buffer();
-        let resource_name = format!(
-            "{}/uploads/{}/blobs/{}/{}",
-            &self.instance_name,
-            Uuid::new_v4().hyphenated().encode_lower(&mut buf),
-            digest.packed_hash(),
-            digest.size_bytes(),
+        let resource_name = ResourceInfo::from_digest(self.instance_name.as_str(), digest)
+            .with_digest_function(digest_function)
+            .with_uuid(&*Uuid::new_v4().hyphenated().encode_lower(&mut buf))
+            .to_string(IS_UPLOAD_TRUE);
+        let resource_name = &resource_name;
+
+        // Keep the most recent data, so a failed upload can be resumed from
+        // the offset the upstream store committed.
+        reader.set_max_recent_data_size(
+            u64::try_from(self.max_retry_buffer_size)
+                .err_tip(|| "Could not convert max_retry_buffer_size to u64")?,
         );
+        self.retrier
+            .retry(unfold(
+                (reader, false),
+                move |(mut reader, is_resumed)| async move {
+                    if is_resumed {
+                        match self.resume_blob_upload(resource_name, &mut reader).await {
+                            Ok(true) => {}
+                            Ok(false) => return Some((RetryResult::Ok(()), (reader, true))),
+                            Err(retry_result) => return Some((retry_result, (reader, true))),
+                        }
+                    }
+                    let retry_result =
+                        match self.write_blob_attempt(resource_name, &mut reader).await {
+                            Ok(WriteAttempt::Done) => RetryResult::Ok(()),
+                            Ok(WriteAttempt::Failed(err)) => RetryResult::Retry(err),
+                            Err(err) => RetryResult::Err(err),
+                        };
+                    Some((retry_result, (reader, true)))
+                },
+            ))
+            .await
+            .err_tip(|| "in GrpcStore::write_blob()")
+    }
 
-        struct LocalState {
-            resource_name: String,
-            reader: DropCloserReadHalf,
-            did_error: bool,
-            bytes_received: i64,
-        }
-        let local_state = LocalState {
-            resource_name,
-            reader,
-            did_error: false,
-            bytes_received: 0,
+    /// Sends the data of `reader` to the upstream store in a single
+    /// `ByteStream.Write` call, starting at the current offset of `reader`.
+    /// Errors reading from `reader` are returned as `Err`, errors of the
+    /// upstream store as `WriteAttempt::Failed`.
+    async fn write_blob_attempt(
+        &self,
+        resource_name: &str,
+        reader: &mut DropCloserReadHalf,
+    ) -> Result<WriteAttempt, Error> {
+        let (tx, rx) = mpsc::channel(1);
+        let send_fut = async move {
+            loop {
+                let write_offset = i64::try_from(reader.get_bytes_received())
+                    .err_tip(|| "Could not convert write offset to i64")?;
+                let data = tokio::select! {
+                    data = reader.recv() => data.err_tip(|| "In GrpcStore::write_blob_attempt()")?,
+                    // The upstream store stopped reading, the write call reports why.
+                    () = tx.closed() => return Ok(()),
+                };
+                let finish_write = data.is_empty(); // EOF is when no data was polled.
+                let request = WriteRequest {
+                    resource_name: resource_name.to_string(),
+                    write_offset,
+                    finish_write,
+                    data,
+                };
+                if tx.send(request).await.is_err() || finish_write {
+                    return Ok::<_, Error>(());
+                }
+            }
         };
+        let write_fut = self
+            .connection_manager
+            .connection()
+            .and_then(|channel| async {
+                ByteStreamClient::new(channel)
+                    .write(unfold(rx, |mut rx| async move {
+                        rx.recv().await.map(|request| (request, rx))
+                    }))
+                    .await
+                    .err_tip(|| "in GrpcStore::write_blob_attempt()")
+            });
+        let (send_res, write_res) = join!(send_fut, write_fut);
+        send_res?;
+        Ok(write_res.map_or_else(WriteAttempt::Failed, |_| WriteAttempt::Done))
+    }
 
-        let stream = Box::pin(unfold(local_state, |mut local_state| async move {
-            if local_state.did_error {
-                event!(
-                    Level::ERROR,
-                    "GrpcStore::update() polled stream after error was returned"
-                );
-                return None;
-            }
-            let data = match local_state
-                .reader
-                .recv()
+    /// Rewinds `reader` to the number of bytes of the upload at
+    /// `resource_name` the upstream store committed. Returns false if the
+    /// upstream store already has the whole blob.
+    async fn resume_blob_upload(
+        &self,
+        resource_name: &str,
+        reader: &mut DropCloserReadHalf,
+    ) -> Result<bool, RetryResult<()>> {
+        let status = self
+            .query_write_status(Request::new(QueryWriteStatusRequest {
+                resource_name: resource_name.to_string(),
+            }))
+            .await
+            .map_err(|err| RetryResult::Retry(err.append("In GrpcStore::resume_blob_upload()")))?
+            .into_inner();
+        if status.complete {
+            return Ok(false);
+        }
+        let committed_size = u64::try_from(status.committed_size)
+            .err_tip(|| "Upstream store returned a negative committed_size")
+            .map_err(RetryResult::Err)?;
+        reader
+            .try_rewind_to(committed_size)
+            .err_tip(|| format!("Could not resume upload at byte {committed_size}"))
+            .map_err(RetryResult::Err)?;
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl StoreDriver for GrpcStore {
+    // NOTE: This function can only be safely used on CAS stores. AC stores may return a size that
+    // is incorrect.
+    async fn has_with_results(
+        self: Pin<&Self>,
+        keys: &[StoreKey<'_>],
+        results: &mut [Option<u64>],
+    ) -> Result<(), Error> {
+        if matches!(self.store_type, nativelink_config::stores::StoreType::ac) {
+            keys.iter()
+                .zip(results.iter_mut())
+                .map(|(key, result)| async move {
+                    // The length of an AC is incorrect, so we don't figure out the
+                    // length, instead the biggest possible result is returned in the
+                    // hope that we detect incorrect usage.
+                    self.get_action_result_from_digest(key.borrow().into_digest())
+                        .await?;
+                    *result = Some(u64::MAX);
+                    Ok::<_, Error>(())
+                })
+                .collect::<FuturesUnordered<_>>()
+                .try_for_each(|()| future::ready(Ok(())))
                 .await
-                .err_tip(|| "In GrpcStore::update()")
-            {
-                Ok(data) => data,
-                Err(err) => {
-                    local_state.did_error = true;
-                    return Some((Err(err), local_state));
-                }
-            };
+                .err_tip(|| "Getting upstream action cache entry")?;
+            return Ok(());
+        }
 
-            let write_offset = local_state.bytes_received;
-            local_state.bytes_received += data.len() as i64;
+        let Some(queue) = &self.replication else {
+            return self.find_missing_upstream(keys, results).await;
+        };
+        // Queued blobs are not upstream yet, but will be soon.
+        let mut upstream_keys = Vec::new();
+        let mut upstream_indexes = Vec::new();
+        for (index, (key, result)) in keys.iter().zip(results.iter_mut()).enumerate() {
+            let digest = key.borrow().into_digest();
+            if queue.queued(digest).is_some() {
+                *result = Some(digest.size_bytes());
+            } else {
+                upstream_keys.push(key.borrow());
+                upstream_indexes.push(index);
+            }
+        }
+        if upstream_keys.is_empty() {
+            return Ok(());
+        }
+        let mut upstream_results = vec![None; upstream_keys.len()];
+        self.find_missing_upstream(&upstream_keys, &mut upstream_results)
+            .await?;
+        for (index, result) in upstream_indexes.into_iter().zip(upstream_results) {
+            results[index] = result;
+        }
+        Ok(())
+    }
 
-            Some((
-                Ok(WriteRequest {
-                    resource_name: local_state.resource_name.clone(),
-                    write_offset,
-                    finish_write: data.is_empty(), // EOF is when no data was polled.
-                    data,
-                }),
-                local_state,
-            ))
-        }));
+    async fn update(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        reader: DropCloserReadHalf,
+        _size_info: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        let digest = key.into_digest();
+        if matches!(self.store_type, nativelink_config::stores::StoreType::ac) {
+            return self.update_action_result_from_bytes(digest, reader).await;
+        }
 
-        self.write(
-            WriteRequestStreamWrapper::from(stream)
+        let digest_function = ActiveOriginContext::get_value(&ACTIVE_HASHER_FUNC)
+            .err_tip(|| "In GrpcStore::update")?
+            .map_or_else(default_digest_hasher_func, |v| *v);
+        if let Some(queue) = &self.replication {
+            return queue
+                .enqueue(digest_function, digest, reader)
                 .await
-                .err_tip(|| "in GrpcStore::update()")?,
-        )
-        .await
-        .err_tip(|| "in GrpcStore::update()")?;
-
-        Ok(())
+                .err_tip(|| "In GrpcStore::update");
+        }
+        self.write_blob(digest_function, digest, reader)
+            .await
+            .err_tip(|| "in GrpcStore::update()")
     }
 
     async fn get_part(
@@ -660,6 +987,7 @@ impl StoreDriver for GrpcStore {
         offset: u64,
         length: Option<u64>,
     ) -> Result<(), Error> {
+        const IS_UPLOAD_FALSE: bool = false;
         let digest = key.into_digest();
         if matches!(self.store_type, nativelink_config::stores::StoreType::ac) {
             let offset = usize::try_from(offset).err_tip(|| "Could not convert offset to usize")?;
@@ -677,12 +1005,24 @@ impl StoreDriver for GrpcStore {
             return writer.send_eof();
         }
 
-        let resource_name = format!(
-            "{}/blobs/{}/{}",
-            &self.instance_name,
-            digest.packed_hash(),
-            digest.size_bytes(),
-        );
+        // Blobs still in the replication queue may not be upstream yet.
+        if let Some(queue) = &self.replication {
+            if let Some(digest_function) = queue.queued(digest) {
+                if let Some(mut file) = queue
+                    .open_blob(digest_function, digest, offset, length)
+                    .await
+                    .err_tip(|| "In GrpcStore::get_part")?
+                {
+                    return send_file(&mut file, writer)
+                        .await
+                        .err_tip(|| "In GrpcStore::get_part");
+                }
+            }
+        }
+
+        let resource_name = self
+            .resource_info_for_digest(digest)?
+            .to_string(IS_UPLOAD_FALSE);
 
         struct LocalState<'a> {
             resource_name: String,
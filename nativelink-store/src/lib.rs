@@ -13,22 +13,35 @@
 // limitations under the License.
 
 pub mod ac_utils;
+pub mod alias_store;
+pub mod archive_store;
 pub mod cas_utils;
 pub mod completeness_checking_store;
 pub mod compression_store;
 pub mod dedup_store;
 pub mod default_store_factory;
+pub mod encryption_store;
 pub mod existence_cache_store;
+mod fan_out;
 pub mod fast_slow_store;
 pub mod filesystem_store;
+pub mod gcs_store;
+mod grpc_replication_queue;
 pub mod grpc_store;
 pub mod memory_store;
+pub mod mirror_store;
 pub mod noop_store;
+pub mod quota_store;
+pub mod redb_store;
 pub mod redis_store;
 mod redis_utils;
 pub mod ref_store;
+pub mod replication_store;
+pub mod retry_store;
 pub mod s3_store;
 pub mod shard_store;
 pub mod size_partitioning_store;
 pub mod store_manager;
+pub mod throttle_store;
+pub mod translation_store;
 pub mod verify_store;
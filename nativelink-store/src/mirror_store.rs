@@ -0,0 +1,248 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures::join;
+use nativelink_config::stores::MirrorSpec;
+use nativelink_error::{error_if, make_err, Code, Error, ResultExt};
+use nativelink_metric::MetricsComponent;
+use nativelink_util::background_spawn;
+use nativelink_util::buf_channel::{
+    make_buf_channel_pair, DropCloserReadHalf, DropCloserWriteHalf,
+};
+use nativelink_util::health_utils::{default_health_status_indicator, HealthStatusIndicator};
+use nativelink_util::store_trait::{Store, StoreDriver, StoreKey, StoreLike, UploadSizeInfo};
+use tracing::{event, Level};
+
+use crate::fan_out::update_all;
+
+#[derive(MetricsComponent)]
+pub struct MirrorStore {
+    #[metric(group = "stores")]
+    stores: Vec<Store>,
+    #[metric(help = "Number of stores that must accept an upload")]
+    write_quorum: usize,
+    #[metric(help = "If objects missing from a store are copied into it on read")]
+    read_repair: bool,
+    #[metric(help = "Number of reads retried on the next store")]
+    failover_count: AtomicU64,
+    #[metric(help = "Number of uploads that succeeded without reaching every store")]
+    partial_write_count: AtomicU64,
+    #[metric(help = "Number of objects copied into a store that was missing them")]
+    read_repair_count: AtomicU64,
+    #[metric(help = "Number of objects that failed to be copied into a store missing them")]
+    read_repair_failures: Arc<AtomicU64>,
+}
+
+impl MirrorStore {
+    pub fn new(spec: &MirrorSpec, stores: Vec<Store>) -> Result<Arc<Self>, Error> {
+        error_if!(
+            spec.stores.len() != stores.len(),
+            "Config stores do not match stores length"
+        );
+        error_if!(
+            stores.is_empty(),
+            "MirrorStore must have at least one store"
+        );
+        error_if!(
+            spec.write_quorum > stores.len(),
+            "write_quorum of MirrorStore is {} but there are only {} stores",
+            spec.write_quorum,
+            stores.len()
+        );
+        let write_quorum = if spec.write_quorum == 0 {
+            stores.len()
+        } else {
+            spec.write_quorum
+        };
+        Ok(Arc::new(Self {
+            stores,
+            write_quorum,
+            read_repair: !spec.disable_read_repair,
+            failover_count: AtomicU64::new(0),
+            partial_write_count: AtomicU64::new(0),
+            read_repair_count: AtomicU64::new(0),
+            read_repair_failures: Arc::new(AtomicU64::new(0)),
+        }))
+    }
+
+    /// Copies `key` from `source` into each of `targets` in the background.
+    fn spawn_read_repair(&self, key: StoreKey<'_>, source: usize, targets: Vec<usize>) {
+        self.read_repair_count
+            .fetch_add(targets.len() as u64, Ordering::Relaxed);
+        let key = key.into_owned();
+        let source = self.stores[source].clone();
+        let targets: Vec<Store> = targets
+            .into_iter()
+            .map(|index| self.stores[index].clone())
+            .collect();
+        let read_repair_failures = self.read_repair_failures.clone();
+        background_spawn!("mirror_store_read_repair", async move {
+            for target in targets {
+                let result = async {
+                    let size = source
+                        .has(key.borrow())
+                        .await?
+                        .err_tip(|| "Object disappeared from the source store")?;
+                    let (tx, rx) = make_buf_channel_pair();
+                    let (get_res, update_res) = join!(
+                        source.get(key.borrow(), tx),
+                        target.update(key.borrow(), rx, UploadSizeInfo::ExactSize(size))
+                    );
+                    get_res.merge(update_res)
+                }
+                .await;
+                if let Err(err) = result {
+                    read_repair_failures.fetch_add(1, Ordering::Relaxed);
+                    event!(
+                        Level::WARN,
+                        ?err,
+                        key = %key.as_str(),
+                        "Failed to repair object in MirrorStore"
+                    );
+                }
+            }
+        });
+    }
+}
+
+#[async_trait]
+impl StoreDriver for MirrorStore {
+    async fn has_with_results(
+        self: Pin<&Self>,
+        keys: &[StoreKey<'_>],
+        results: &mut [Option<u64>],
+    ) -> Result<(), Error> {
+        results.fill(None);
+        // Indexes of the keys no store answered for yet.
+        let mut pending: Vec<usize> = (0..keys.len()).collect();
+        let mut answered = false;
+        let mut last_err = None;
+        for (index, store) in self.stores.iter().enumerate() {
+            if pending.is_empty() {
+                break;
+            }
+            if index > 0 {
+                self.failover_count.fetch_add(1, Ordering::Relaxed);
+            }
+            let pending_keys: Vec<StoreKey<'_>> =
+                pending.iter().map(|&i| keys[i].borrow()).collect();
+            let mut pending_results = vec![None; pending_keys.len()];
+            match store
+                .has_with_results(&pending_keys, &mut pending_results)
+                .await
+            {
+                Ok(()) => {
+                    answered = true;
+                    for (&i, result) in pending.iter().zip(pending_results) {
+                        results[i] = result;
+                    }
+                    pending.retain(|&i| results[i].is_none());
+                }
+                Err(err) => last_err = Some(err),
+            }
+        }
+        if answered {
+            return Ok(());
+        }
+        Err(last_err.unwrap_or_else(|| make_err!(Code::Internal, "No store to read from")))
+            .err_tip(|| "In MirrorStore::has_with_results")
+    }
+
+    async fn update(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        reader: DropCloserReadHalf,
+        size_info: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        let stores: Vec<&Store> = self.stores.iter().collect();
+        let (data_stream_res, store_results) =
+            update_all(&stores, key, reader, size_info, self.write_quorum).await;
+        let data_stream_res = data_stream_res.err_tip(|| "In MirrorStore::update");
+        let succeeded = store_results.iter().filter(|result| result.is_ok()).count();
+        if data_stream_res.is_err() || succeeded < self.write_quorum {
+            return store_results
+                .into_iter()
+                .fold(data_stream_res, ResultExt::merge);
+        }
+        if succeeded < self.stores.len() {
+            self.partial_write_count.fetch_add(1, Ordering::Relaxed);
+            for err in store_results.into_iter().filter_map(Result::err) {
+                event!(
+                    Level::WARN,
+                    ?err,
+                    "Upload reached write_quorum in MirrorStore, but failed on a store"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_part(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        writer: &mut DropCloserWriteHalf,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<(), Error> {
+        // Stores that answered they do not have the object.
+        let mut missing = Vec::new();
+        let mut last_err = None;
+        for (index, store) in self.stores.iter().enumerate() {
+            if index > 0 {
+                self.failover_count.fetch_add(1, Ordering::Relaxed);
+            }
+            let bytes_written = writer.get_bytes_written();
+            let result = store
+                .get_part(key.borrow(), &mut *writer, offset, length)
+                .await;
+            let Err(err) = result else {
+                if self.read_repair && !missing.is_empty() {
+                    self.spawn_read_repair(key, index, missing);
+                }
+                return Ok(());
+            };
+            // Once data was sent the stream can not be handed over to
+            // another store.
+            if writer.get_bytes_written() != bytes_written {
+                return Err(err)
+                    .err_tip(|| format!("In MirrorStore::get_part after store {index} sent data"));
+            }
+            if err.code == Code::NotFound {
+                missing.push(index);
+            }
+            last_err = Some(err);
+        }
+        Err(last_err.unwrap_or_else(|| make_err!(Code::Internal, "No store to read from")))
+            .err_tip(|| "In MirrorStore::get_part")
+    }
+
+    fn inner_store(&self, _key: Option<StoreKey>) -> &'_ dyn StoreDriver {
+        self
+    }
+
+    fn as_any<'a>(&'a self) -> &'a (dyn std::any::Any + Sync + Send + 'static) {
+        self
+    }
+
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn std::any::Any + Sync + Send + 'static> {
+        self
+    }
+}
+
+default_health_status_indicator!(MirrorStore);
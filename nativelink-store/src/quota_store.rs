@@ -0,0 +1,324 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use nativelink_config::stores::{QuotaExceededAction, QuotaSpec};
+use nativelink_error::{make_err, Code, Error, ResultExt};
+use nativelink_metric::MetricsComponent;
+use nativelink_util::buf_channel::{DropCloserReadHalf, DropCloserWriteHalf};
+use nativelink_util::health_utils::{HealthStatus, HealthStatusIndicator};
+use nativelink_util::instant_wrapper::InstantWrapper;
+use nativelink_util::store_trait::{Store, StoreDriver, StoreKey, StoreLike, UploadSizeInfo};
+use parking_lot::Mutex;
+
+/// Minimum time between two checks of which tracked objects are still in
+/// the backend.
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Number of keys checked per request when reconciling.
+const RECONCILE_BATCH_SIZE: usize = 1000;
+
+struct TokenBucketState {
+    /// Tokens that can be taken right away. Negative when throttled
+    /// requests are waiting.
+    tokens: f64,
+    last_refill: Duration,
+}
+
+/// Limits usage to `rate` tokens per second, allowing bursts of up to one
+/// second worth of tokens.
+pub(crate) struct TokenBucket {
+    rate: f64,
+    state: Mutex<TokenBucketState>,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(rate: u64) -> Option<Self> {
+        let rate = rate as f64;
+        (rate != 0.).then(|| Self {
+            rate,
+            state: Mutex::new(TokenBucketState {
+                tokens: rate,
+                last_refill: Duration::ZERO,
+            }),
+        })
+    }
+
+    /// Takes `cost` tokens. Returns how long to wait before the usage fits
+    /// in the limit, or None if it fits right away. When `reserve` is
+    /// false nothing is taken if the caller would have to wait.
+    pub(crate) fn take(&self, now: Duration, cost: f64, reserve: bool) -> Option<Duration> {
+        let mut state = self.state.lock();
+        let elapsed = now.saturating_sub(state.last_refill).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * self.rate).min(self.rate);
+        state.last_refill = now;
+        if state.tokens >= cost {
+            state.tokens -= cost;
+            return None;
+        }
+        let wait = Duration::from_secs_f64((cost - state.tokens) / self.rate);
+        if reserve {
+            state.tokens -= cost;
+        }
+        Some(wait)
+    }
+
+    /// Returns true if the bucket refilled completely, so it behaves the
+    /// same as a new one.
+    pub(crate) fn is_full(&self, now: Duration) -> bool {
+        let state = self.state.lock();
+        let elapsed = now.saturating_sub(state.last_refill).as_secs_f64();
+        state.tokens + elapsed * self.rate >= self.rate
+    }
+}
+
+struct Usage {
+    /// Size of every object uploaded through this store that is believed
+    /// to still be in the backend.
+    objects: HashMap<StoreKey<'static>, u64>,
+    last_reconcile: Option<Duration>,
+}
+
+#[derive(MetricsComponent)]
+pub struct QuotaStore<I: InstantWrapper> {
+    #[metric(group = "inner_store")]
+    inner_store: Store,
+    #[metric(help = "Maximum number of bytes stored through this store")]
+    max_bytes: u64,
+    #[metric(help = "Number of bytes stored through this store")]
+    used_bytes: AtomicU64,
+    read_limit: Option<TokenBucket>,
+    write_limit: Option<TokenBucket>,
+    #[metric(help = "If requests over the limit are delayed instead of rejected")]
+    throttle: bool,
+    anchor_time: I,
+    usage: Mutex<Usage>,
+    #[metric(help = "Number of requests rejected for going over a request limit")]
+    rejected_requests: AtomicU64,
+    #[metric(help = "Number of requests delayed for going over a request limit")]
+    throttled_requests: AtomicU64,
+    #[metric(help = "Number of uploads rejected for going over max_bytes")]
+    rejected_uploads: AtomicU64,
+}
+
+impl QuotaStore<SystemTime> {
+    pub fn new(spec: &QuotaSpec, inner_store: Store) -> Arc<Self> {
+        Self::new_with_time(spec, inner_store, SystemTime::now())
+    }
+}
+
+impl<I: InstantWrapper> QuotaStore<I> {
+    pub fn new_with_time(spec: &QuotaSpec, inner_store: Store, anchor_time: I) -> Arc<Self> {
+        Arc::new(Self {
+            inner_store,
+            max_bytes: spec.max_bytes,
+            used_bytes: AtomicU64::new(0),
+            read_limit: TokenBucket::new(u64::from(spec.max_reads_per_second)),
+            write_limit: TokenBucket::new(u64::from(spec.max_writes_per_second)),
+            throttle: spec.on_exceed == QuotaExceededAction::throttle,
+            anchor_time,
+            usage: Mutex::new(Usage {
+                objects: HashMap::new(),
+                last_reconcile: None,
+            }),
+            rejected_requests: AtomicU64::new(0),
+            throttled_requests: AtomicU64::new(0),
+            rejected_uploads: AtomicU64::new(0),
+        })
+    }
+
+    /// Waits for or rejects a request that goes over `limit`.
+    async fn acquire(&self, limit: Option<&TokenBucket>, kind: &str) -> Result<(), Error> {
+        let Some(limit) = limit else {
+            return Ok(());
+        };
+        let Some(wait) = limit.take(self.anchor_time.elapsed(), 1., self.throttle) else {
+            return Ok(());
+        };
+        if !self.throttle {
+            self.rejected_requests.fetch_add(1, Ordering::Relaxed);
+            return Err(make_err!(
+                Code::ResourceExhausted,
+                "Too many {kind} requests in QuotaStore"
+            ));
+        }
+        self.throttled_requests.fetch_add(1, Ordering::Relaxed);
+        I::from_secs(0).sleep(wait).await;
+        Ok(())
+    }
+
+    fn track(&self, key: StoreKey<'static>, size: u64) {
+        let mut usage = self.usage.lock();
+        if let Some(old_size) = usage.objects.insert(key, size) {
+            self.used_bytes.fetch_sub(old_size, Ordering::Relaxed);
+        }
+        self.used_bytes.fetch_add(size, Ordering::Relaxed);
+    }
+
+    fn untrack(&self, key: &StoreKey<'_>) {
+        if self.max_bytes == 0 {
+            return;
+        }
+        if let Some(size) = self.usage.lock().objects.remove(&key.borrow().into_owned()) {
+            self.used_bytes.fetch_sub(size, Ordering::Relaxed);
+        }
+    }
+
+    /// Stops tracking the objects the backend no longer has, at most once
+    /// every `RECONCILE_INTERVAL`.
+    async fn reconcile(&self) -> Result<(), Error> {
+        let keys: Vec<StoreKey<'static>> = {
+            let mut usage = self.usage.lock();
+            let now = self.anchor_time.elapsed();
+            if usage
+                .last_reconcile
+                .is_some_and(|last_reconcile| now < last_reconcile + RECONCILE_INTERVAL)
+            {
+                return Ok(());
+            }
+            usage.last_reconcile = Some(now);
+            usage.objects.keys().cloned().collect()
+        };
+        for keys in keys.chunks(RECONCILE_BATCH_SIZE) {
+            let mut results = vec![None; keys.len()];
+            self.inner_store
+                .has_with_results(keys, &mut results)
+                .await
+                .err_tip(|| "In QuotaStore::reconcile")?;
+            for (key, result) in keys.iter().zip(results) {
+                if result.is_none() {
+                    self.untrack(key);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails if storing `size` more bytes under `key` goes over `max_bytes`.
+    async fn check_bytes(&self, key: &StoreKey<'_>, size: u64) -> Result<(), Error> {
+        let fits = || {
+            let usage = self.usage.lock();
+            let replaced = usage
+                .objects
+                .get(&key.borrow().into_owned())
+                .copied()
+                .unwrap_or(0);
+            self.used_bytes.load(Ordering::Relaxed) - replaced + size <= self.max_bytes
+        };
+        if fits() {
+            return Ok(());
+        }
+        self.reconcile().await?;
+        if fits() {
+            return Ok(());
+        }
+        self.rejected_uploads.fetch_add(1, Ordering::Relaxed);
+        Err(make_err!(
+            Code::ResourceExhausted,
+            "Uploading {} of {size} bytes would go over the {} bytes allowed by QuotaStore, {} bytes are used",
+            key.as_str(),
+            self.max_bytes,
+            self.used_bytes.load(Ordering::Relaxed)
+        ))
+    }
+}
+
+#[async_trait]
+impl<I: InstantWrapper> StoreDriver for QuotaStore<I> {
+    async fn has_with_results(
+        self: Pin<&Self>,
+        keys: &[StoreKey<'_>],
+        results: &mut [Option<u64>],
+    ) -> Result<(), Error> {
+        self.acquire(self.read_limit.as_ref(), "read").await?;
+        self.inner_store
+            .has_with_results(keys, results)
+            .await
+            .err_tip(|| "In QuotaStore::has_with_results")?;
+        for (key, result) in keys.iter().zip(results.iter()) {
+            if result.is_none() {
+                self.untrack(key);
+            }
+        }
+        Ok(())
+    }
+
+    async fn update(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        reader: DropCloserReadHalf,
+        size_info: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        self.acquire(self.write_limit.as_ref(), "write").await?;
+        let (UploadSizeInfo::ExactSize(size) | UploadSizeInfo::MaxSize(size)) = size_info;
+        if self.max_bytes == 0 {
+            return self.inner_store.update(key, reader, size_info).await;
+        }
+        self.check_bytes(&key, size).await?;
+        self.inner_store
+            .update(key.borrow(), reader, size_info)
+            .await
+            .err_tip(|| "In QuotaStore::update")?;
+        self.track(key.into_owned(), size);
+        Ok(())
+    }
+
+    async fn get_part(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        writer: &mut DropCloserWriteHalf,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<(), Error> {
+        self.acquire(self.read_limit.as_ref(), "read").await?;
+        let result = self
+            .inner_store
+            .get_part(key.borrow(), writer, offset, length)
+            .await;
+        if result.as_ref().is_err_and(|err| err.code == Code::NotFound) {
+            self.untrack(&key);
+        }
+        result
+    }
+
+    fn inner_store(&self, _key: Option<StoreKey>) -> &'_ dyn StoreDriver {
+        self
+    }
+
+    fn as_any<'a>(&'a self) -> &'a (dyn std::any::Any + Sync + Send + 'static) {
+        self
+    }
+
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn std::any::Any + Sync + Send + 'static> {
+        self
+    }
+}
+
+#[async_trait]
+impl<I: InstantWrapper> HealthStatusIndicator for QuotaStore<I> {
+    fn get_name(&self) -> &'static str {
+        "QuotaStore"
+    }
+
+    async fn check_health(&self, namespace: Cow<'static, str>) -> HealthStatus {
+        StoreDriver::check_health(Pin::new(self), namespace).await
+    }
+}
@@ -0,0 +1,316 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::borrow::Cow;
+use std::ops::Bound;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use nativelink_config::stores::RedbSpec;
+use nativelink_error::{error_if, make_err, Code, Error, ResultExt};
+use nativelink_metric::MetricsComponent;
+use nativelink_util::buf_channel::{DropCloserReadHalf, DropCloserWriteHalf};
+use nativelink_util::health_utils::{default_health_status_indicator, HealthStatusIndicator};
+use nativelink_util::spawn_blocking;
+use nativelink_util::store_trait::{StoreDriver, StoreKey, UploadSizeInfo};
+use nativelink_util::task::BlockingPoolKind;
+use redb::{Database, TableDefinition};
+
+use crate::cas_utils::is_zero_digest;
+
+/// Table all the objects are stored in, by the string form of their key.
+const OBJECTS_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("objects");
+
+/// Default value of [`RedbSpec::max_object_size`].
+const DEFAULT_MAX_OBJECT_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Default value of [`RedbSpec::cache_size`].
+const DEFAULT_CACHE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Number of keys read per blocking task while listing.
+const LIST_BATCH_SIZE: usize = 1024;
+
+fn redb_err(err: impl Into<redb::Error>) -> Error {
+    make_err!(Code::Internal, "Redb error: {}", err.into())
+}
+
+/// Runs `f` with the database on the filesystem blocking pool.
+async fn run_blocking<T, F>(db: &Arc<Database>, name: &'static str, f: F) -> Result<T, Error>
+where
+    T: Send + 'static,
+    F: FnOnce(&Database) -> Result<T, Error> + Send + 'static,
+{
+    let db = db.clone();
+    spawn_blocking!(pool: BlockingPoolKind::Filesystem, "redb_store", move || f(&db), op = name)
+        .await
+        .map_err(|e| {
+            make_err!(
+                Code::Internal,
+                "Failed to run {name} due to spawn failing {e:?}"
+            )
+        })?
+}
+
+#[derive(MetricsComponent)]
+pub struct RedbStore {
+    db: Arc<Database>,
+    #[metric(help = "Path of the database file")]
+    path: String,
+    #[metric(help = "Uploads larger than this many bytes are rejected")]
+    max_object_size: u64,
+}
+
+impl RedbStore {
+    pub async fn new(spec: &RedbSpec) -> Result<Arc<Self>, Error> {
+        let path = spec.path.clone();
+        let cache_size = if spec.cache_size == 0 {
+            DEFAULT_CACHE_SIZE
+        } else {
+            spec.cache_size
+        };
+        let db = spawn_blocking!(pool: BlockingPoolKind::Filesystem, "redb_store_open", move || {
+            if let Some(parent) = Path::new(&path).parent() {
+                std::fs::create_dir_all(parent)
+                    .err_tip(|| format!("Failed to create directory of redb store {path}"))?;
+            }
+            let db = Database::builder()
+                .set_cache_size(cache_size)
+                .create(&path)
+                .map_err(redb_err)
+                .err_tip(|| format!("Failed to open redb store {path}"))?;
+            // Create the table up front, so readers never find it missing.
+            let txn = db.begin_write().map_err(redb_err)?;
+            txn.open_table(OBJECTS_TABLE).map_err(redb_err)?;
+            txn.commit().map_err(redb_err)?;
+            Ok::<_, Error>(db)
+        })
+        .await
+        .map_err(|e| {
+            make_err!(
+                Code::Internal,
+                "Failed to open redb store due to spawn failing {e:?}"
+            )
+        })??;
+
+        Ok(Arc::new(Self {
+            db: Arc::new(db),
+            path: spec.path.clone(),
+            max_object_size: if spec.max_object_size == 0 {
+                DEFAULT_MAX_OBJECT_SIZE
+            } else {
+                spec.max_object_size
+            },
+        }))
+    }
+
+    /// Removes `key` from the store. Returns true if it existed.
+    pub async fn remove_entry(&self, key: StoreKey<'_>) -> Result<bool, Error> {
+        let key = key.as_str().into_owned();
+        run_blocking(&self.db, "redb_store_remove", move |db| {
+            let txn = db.begin_write().map_err(redb_err)?;
+            let existed = txn
+                .open_table(OBJECTS_TABLE)
+                .map_err(redb_err)?
+                .remove(key.as_str())
+                .map_err(redb_err)?
+                .is_some();
+            txn.commit().map_err(redb_err)?;
+            Ok(existed)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl StoreDriver for RedbStore {
+    async fn has_with_results(
+        self: Pin<&Self>,
+        keys: &[StoreKey<'_>],
+        results: &mut [Option<u64>],
+    ) -> Result<(), Error> {
+        let owned_keys: Vec<String> = keys.iter().map(|key| key.as_str().into_owned()).collect();
+        let sizes = run_blocking(&self.db, "redb_store_has", move |db| {
+            let txn = db.begin_read().map_err(redb_err)?;
+            let table = txn.open_table(OBJECTS_TABLE).map_err(redb_err)?;
+            owned_keys
+                .iter()
+                .map(|key| {
+                    Ok(table
+                        .get(key.as_str())
+                        .map_err(redb_err)?
+                        .map(|value| value.value().len() as u64))
+                })
+                .collect::<Result<Vec<_>, Error>>()
+        })
+        .await
+        .err_tip(|| "In RedbStore::has_with_results")?;
+
+        for ((key, result), size) in keys.iter().zip(results.iter_mut()).zip(sizes) {
+            // We need to do a special pass to ensure our zero digest exist.
+            *result = if is_zero_digest(key.borrow()) {
+                Some(0)
+            } else {
+                size
+            };
+        }
+        Ok(())
+    }
+
+    async fn list(
+        self: Pin<&Self>,
+        range: (Bound<StoreKey<'_>>, Bound<StoreKey<'_>>),
+        handler: &mut (dyn for<'a> FnMut(&'a StoreKey) -> bool + Send + Sync + '_),
+    ) -> Result<u64, Error> {
+        let to_string_bound =
+            |bound: Bound<StoreKey<'_>>| bound.map(|key| key.as_str().into_owned());
+        let mut start = to_string_bound(range.0);
+        let end = to_string_bound(range.1);
+        let mut iterations = 0;
+        loop {
+            let batch_start = start.clone();
+            let batch_end = end.clone();
+            let keys = run_blocking(&self.db, "redb_store_list", move |db| {
+                let txn = db.begin_read().map_err(redb_err)?;
+                let table = txn.open_table(OBJECTS_TABLE).map_err(redb_err)?;
+                table
+                    .range::<&str>((
+                        batch_start.as_ref().map(String::as_str),
+                        batch_end.as_ref().map(String::as_str),
+                    ))
+                    .map_err(redb_err)?
+                    .take(LIST_BATCH_SIZE)
+                    .map(|entry| Ok(entry.map_err(redb_err)?.0.value().to_string()))
+                    .collect::<Result<Vec<_>, Error>>()
+            })
+            .await
+            .err_tip(|| "In RedbStore::list")?;
+
+            let batch_len = keys.len();
+            for key in keys {
+                iterations += 1;
+                if !handler(&StoreKey::Str(Cow::Borrowed(&key))) {
+                    return Ok(iterations);
+                }
+                start = Bound::Excluded(key);
+            }
+            if batch_len < LIST_BATCH_SIZE {
+                return Ok(iterations);
+            }
+        }
+    }
+
+    async fn update(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        mut reader: DropCloserReadHalf,
+        size_info: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        let max_object_size = self.max_object_size;
+        if let UploadSizeInfo::ExactSize(size) = size_info {
+            error_if!(
+                size > max_object_size,
+                "Object of {size} bytes is larger than the max_object_size of {max_object_size} bytes in RedbStore::update"
+            );
+        }
+        let max_read = usize::try_from(max_object_size.saturating_add(1))
+            .err_tip(|| "Could not convert max_object_size to usize")?;
+        let data = reader
+            .consume(Some(max_read))
+            .await
+            .err_tip(|| "Failed to collect all bytes from reader in RedbStore::update")?;
+        error_if!(
+            data.len() as u64 > max_object_size,
+            "Object is larger than the max_object_size of {max_object_size} bytes in RedbStore::update"
+        );
+
+        let key = key.as_str().into_owned();
+        run_blocking(&self.db, "redb_store_update", move |db| {
+            let txn = db.begin_write().map_err(redb_err)?;
+            txn.open_table(OBJECTS_TABLE)
+                .map_err(redb_err)?
+                .insert(key.as_str(), &data[..])
+                .map_err(redb_err)?;
+            txn.commit().map_err(redb_err)
+        })
+        .await
+        .err_tip(|| "In RedbStore::update")
+    }
+
+    async fn get_part(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        writer: &mut DropCloserWriteHalf,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<(), Error> {
+        let offset = usize::try_from(offset).err_tip(|| "Could not convert offset to usize")?;
+        let length = length
+            .map(|v| usize::try_from(v).err_tip(|| "Could not convert length to usize"))
+            .transpose()?;
+
+        if is_zero_digest(key.borrow()) {
+            writer
+                .send_eof()
+                .err_tip(|| "Failed to send zero EOF in redb store get_part")?;
+            return Ok(());
+        }
+
+        let owned_key = key.as_str().into_owned();
+        let maybe_data = run_blocking(&self.db, "redb_store_get_part", move |db| {
+            let txn = db.begin_read().map_err(redb_err)?;
+            let table = txn.open_table(OBJECTS_TABLE).map_err(redb_err)?;
+            let Some(value) = table.get(owned_key.as_str()).map_err(redb_err)? else {
+                return Ok(None);
+            };
+            let value = value.value();
+            let start = offset.min(value.len());
+            let end = length.map_or(value.len(), |length| {
+                start.saturating_add(length).min(value.len())
+            });
+            Ok(Some(Bytes::copy_from_slice(&value[start..end])))
+        })
+        .await
+        .err_tip(|| "In RedbStore::get_part")?;
+        let data =
+            maybe_data.err_tip_with_code(|_| (Code::NotFound, format!("Key {key:?} not found")))?;
+
+        if !data.is_empty() {
+            writer
+                .send(data)
+                .await
+                .err_tip(|| "Failed to write data in redb store")?;
+        }
+        writer
+            .send_eof()
+            .err_tip(|| "Failed to write EOF in redb store get_part")?;
+        Ok(())
+    }
+
+    fn inner_store(&self, _digest: Option<StoreKey>) -> &dyn StoreDriver {
+        self
+    }
+
+    fn as_any<'a>(&'a self) -> &'a (dyn std::any::Any + Sync + Send + 'static) {
+        self
+    }
+
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn std::any::Any + Sync + Send + 'static> {
+        self
+    }
+}
+
+default_health_status_indicator!(RedbStore);
@@ -16,12 +16,12 @@ use std::borrow::Cow;
 use std::cmp;
 use std::pin::Pin;
 use std::sync::{Arc, Weak};
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use async_trait::async_trait;
 use bytes::Bytes;
 use const_format::formatcp;
-use fred::clients::{Pool as RedisPool, SubscriberClient};
+use fred::clients::{Client, Pool as RedisPool, SubscriberClient};
 use fred::interfaces::{ClientLike, KeysInterface, PubsubInterface};
 use fred::prelude::{EventInterface, HashesInterface, RediSearchInterface};
 use fred::types::config::{
@@ -35,7 +35,7 @@ use fred::types::scripts::Script;
 use fred::types::{Builder, Key as RedisKey, Map as RedisMap, SortOrder, Value as RedisValue};
 use futures::stream::FuturesUnordered;
 use futures::{future, FutureExt, Stream, StreamExt, TryFutureExt, TryStreamExt};
-use nativelink_config::stores::{RedisMode, RedisSpec};
+use nativelink_config::stores::{RedisAcHashLayoutSpec, RedisMode, RedisSpec};
 use nativelink_error::{make_err, make_input_err, Code, Error, ResultExt};
 use nativelink_metric::MetricsComponent;
 use nativelink_util::buf_channel::{DropCloserReadHalf, DropCloserWriteHalf};
@@ -44,7 +44,8 @@ use nativelink_util::spawn;
 use nativelink_util::store_trait::{
     BoolValue, SchedulerCurrentVersionProvider, SchedulerIndexProvider, SchedulerStore,
     SchedulerStoreDataProvider, SchedulerStoreDecodeTo, SchedulerStoreKeyProvider,
-    SchedulerSubscription, SchedulerSubscriptionManager, StoreDriver, StoreKey, UploadSizeInfo,
+    SchedulerSubscription, SchedulerSubscriptionManager, StoreDriver, StoreKey, StoreKeyEncoding,
+    UploadSizeInfo,
 };
 use nativelink_util::task::JoinHandleDropGuard;
 use parking_lot::{Mutex, RwLock};
@@ -88,6 +89,77 @@ const DEFAULT_COMMAND_TIMEOUT_MS: u64 = 10_000;
 /// Note: If this changes it should be updated in the config documentation.
 const DEFAULT_MAX_CHUNK_UPLOADS_PER_UPDATE: usize = 10;
 
+/// The maximum number of entries deleted by a single purge script call.
+const AC_HASH_PURGE_BATCH_SIZE: u64 = 1000;
+
+/// Lua script to write an entry when the AC hash layout is used.
+/// Args:
+///   KEYS[1]: The hash the entries are stored in.
+///   KEYS[2]: The sorted set of update times.
+///   ARGV[1]: The field of the entry.
+///   ARGV[2]: The data of the entry.
+///   ARGV[3]: The number of seconds until the entry expires (0 is never).
+///   ARGV[4]: The current unix time in seconds or an empty string if the
+///            update times are not indexed.
+const LUA_AC_HASH_SET_SCRIPT: &str = r"
+local ttl = tonumber(ARGV[3])
+redis.call('HSET', KEYS[1], ARGV[1], ARGV[2])
+if ttl > 0 then
+    redis.call('HEXPIRE', KEYS[1], ttl, 'FIELDS', 1, ARGV[1])
+end
+if ARGV[4] ~= '' then
+    local now = tonumber(ARGV[4])
+    redis.call('ZADD', KEYS[2], now, ARGV[1])
+    -- Fields written before this time already expired, so stop tracking them.
+    if ttl > 0 then
+        redis.call('ZREMRANGEBYSCORE', KEYS[2], '-inf', '(' .. (now - ttl))
+    end
+end
+return 1
+";
+
+/// Lua script to delete entries last written before a given time when the
+/// AC hash layout is used.
+/// Args:
+///   KEYS[1]: The hash the entries are stored in.
+///   KEYS[2]: The sorted set of update times.
+///   ARGV[1]: The unix time in seconds entries must be older than.
+///   ARGV[2]: The maximum number of entries to delete.
+/// Returns:
+///   The number of entries deleted.
+const LUA_AC_HASH_PURGE_SCRIPT: &str = r"
+local fields = redis.call('ZRANGEBYSCORE', KEYS[2], '-inf', '(' .. ARGV[1], 'LIMIT', 0, ARGV[2])
+if #fields == 0 then
+    return 0
+end
+redis.call('HDEL', KEYS[1], unpack(fields))
+redis.call('ZREM', KEYS[2], unpack(fields))
+return #fields
+";
+
+/// Where entries are stored when the AC hash layout is enabled.
+///
+/// See [`RedisSpec::ac_hash_layout`](`nativelink_config::stores::RedisSpec::ac_hash_layout`).
+#[derive(MetricsComponent)]
+struct AcHashLayout {
+    /// The hash all entries are stored in.
+    #[metric(help = "The Redis hash the action cache entries are stored in")]
+    hash_key: String,
+
+    /// The sorted set of the time every entry was last written. Both keys
+    /// share a hash tag so they are on the same node in cluster mode.
+    #[metric(help = "The Redis sorted set of the time every entry was last written")]
+    update_time_index_key: String,
+
+    /// Whether `update_time_index_key` is maintained.
+    #[metric(help = "Whether the time every entry was last written is indexed")]
+    index_by_update_time: bool,
+
+    /// The number of seconds until an entry expires or zero for never.
+    #[metric(help = "The number of seconds until an entry expires")]
+    entry_ttl_s: u64,
+}
+
 #[allow(clippy::trivially_copy_pass_by_ref)]
 fn to_hex(value: &u32) -> String {
     format!("{value:08x}")
@@ -125,6 +197,11 @@ pub struct RedisStore {
     #[metric(help = "Prefix to append to all keys before sending to Redis")]
     key_prefix: String,
 
+    /// How digest keys are encoded before `key_prefix` is prepended.
+    ///
+    /// See [`RedisSpec::key_encoding`](`nativelink_config::stores::RedisSpec::key_encoding`).
+    key_encoding: StoreKeyEncoding,
+
     /// The amount of data to read from Redis at a time.
     #[metric(help = "The amount of data to read from Redis at a time")]
     read_chunk_size: usize,
@@ -141,6 +218,16 @@ pub struct RedisStore {
 
     /// A manager for subscriptions to keys in Redis.
     subscription_manager: Mutex<Option<Arc<RedisSubscriptionManager>>>,
+
+    /// Set if entries are stored as fields of a single hash.
+    #[metric(group = "ac_hash_layout")]
+    ac_hash_layout: Option<AcHashLayout>,
+
+    /// Redis script used to write an entry when `ac_hash_layout` is set.
+    ac_hash_set_script: Script,
+
+    /// Redis script used to purge old entries when `ac_hash_layout` is set.
+    ac_hash_purge_script: Script,
 }
 
 impl RedisStore {
@@ -249,9 +336,14 @@ impl RedisStore {
             spec.experimental_pub_sub_channel.clone(),
             || Uuid::new_v4().to_string(),
             spec.key_prefix.clone(),
+            spec.key_encoding.into(),
             spec.read_chunk_size,
             spec.max_chunk_uploads_per_update,
         )
+        .map(|store| match &spec.ac_hash_layout {
+            Some(ac_hash_layout) => store.with_ac_hash_layout(ac_hash_layout),
+            None => store,
+        })
         .map(Arc::new)
     }
 
@@ -262,6 +354,7 @@ impl RedisStore {
         pub_sub_channel: Option<String>,
         temp_name_generator_fn: fn() -> String,
         key_prefix: String,
+        key_encoding: StoreKeyEncoding,
         read_chunk_size: usize,
         max_chunk_uploads_per_update: usize,
     ) -> Result<Self, Error> {
@@ -276,16 +369,90 @@ impl RedisStore {
             fingerprint_create_index: fingerprint_create_index_template(),
             temp_name_generator_fn,
             key_prefix,
+            key_encoding,
             read_chunk_size,
             max_chunk_uploads_per_update,
             update_if_version_matches_script: Script::from_lua(LUA_VERSION_SET_SCRIPT),
             subscription_manager: Mutex::new(None),
+            ac_hash_layout: None,
+            ac_hash_set_script: Script::from_lua(LUA_AC_HASH_SET_SCRIPT),
+            ac_hash_purge_script: Script::from_lua(LUA_AC_HASH_PURGE_SCRIPT),
         })
     }
 
+    /// Store entries as fields of a single hash as configured by `spec`.
+    #[must_use]
+    pub fn with_ac_hash_layout(mut self, spec: &RedisAcHashLayoutSpec) -> Self {
+        self.ac_hash_layout = Some(AcHashLayout {
+            hash_key: format!("{}ac:{{{}}}", self.key_prefix, spec.partition),
+            update_time_index_key: format!("{}ac_updated:{{{}}}", self.key_prefix, spec.partition),
+            index_by_update_time: spec.index_by_update_time,
+            entry_ttl_s: spec.entry_ttl_s,
+        });
+        self
+    }
+
+    /// Deletes all entries last written before `cutoff` and returns the
+    /// number of entries deleted. Only available if the AC hash layout is
+    /// used with `index_by_update_time` enabled.
+    pub async fn purge_ac_entries_older_than(&self, cutoff: SystemTime) -> Result<u64, Error> {
+        let layout = self
+            .ac_hash_layout
+            .as_ref()
+            .filter(|layout| layout.index_by_update_time)
+            .ok_or_else(|| {
+                make_input_err!(
+                    "Purging entries requires ac_hash_layout.index_by_update_time in RedisStore"
+                )
+            })?;
+        let cutoff_s = cutoff.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let client = self.client_pool.next();
+        let mut total_purged = 0;
+        loop {
+            let purged = self
+                .ac_hash_purge_script
+                .evalsha_with_reload::<u64, _, Vec<Bytes>>(
+                    client,
+                    vec![
+                        layout.hash_key.as_str(),
+                        layout.update_time_index_key.as_str(),
+                    ],
+                    vec![
+                        Bytes::from(cutoff_s.to_string()),
+                        Bytes::from(AC_HASH_PURGE_BATCH_SIZE.to_string()),
+                    ],
+                )
+                .await
+                .err_tip(|| "In RedisStore::purge_ac_entries_older_than")?;
+            total_purged += purged;
+            if purged < AC_HASH_PURGE_BATCH_SIZE {
+                return Ok(total_purged);
+            }
+        }
+    }
+
     /// Encode a [`StoreKey`] so it can be sent to Redis.
     fn encode_key<'a>(&self, key: &'a StoreKey<'a>) -> Cow<'a, str> {
-        let key_body = key.as_str();
+        self.encode_key_with(key, self.key_encoding)
+    }
+
+    /// Returns the key `key` was stored under before `key_encoding` was
+    /// changed from `hex`, if it could have been stored under another key.
+    fn legacy_key<'a>(&self, key: &'a StoreKey<'a>) -> Option<Cow<'a, str>> {
+        match key {
+            StoreKey::Digest(_) if self.key_encoding != StoreKeyEncoding::Hex => {
+                Some(self.encode_key_with(key, StoreKeyEncoding::Hex))
+            }
+            _ => None,
+        }
+    }
+
+    fn encode_key_with<'a>(
+        &self,
+        key: &'a StoreKey<'a>,
+        key_encoding: StoreKeyEncoding,
+    ) -> Cow<'a, str> {
+        let key_body = key.encode(key_encoding);
         if self.key_prefix.is_empty() {
             key_body
         } else {
@@ -305,6 +472,196 @@ impl RedisStore {
             }
         }
     }
+
+    /// Returns the length of the value stored at `encoded_key` or `None`
+    /// if there is no such key.
+    async fn blob_len(client: &Client, encoded_key: &str) -> Result<Option<u64>, Error> {
+        let pipeline = client.pipeline();
+        pipeline
+            .strlen::<(), _>(encoded_key)
+            .await
+            .err_tip(|| format!("In RedisStore::has_with_results::strlen for {encoded_key}"))?;
+        // Redis returns 0 when the key doesn't exist
+        // AND when the key exists with value of length 0.
+        // Therefore, we need to check both length and existence
+        // and do it in a pipeline for efficiency.
+        pipeline
+            .exists::<(), _>(encoded_key)
+            .await
+            .err_tip(|| format!("In RedisStore::has_with_results::exists for {encoded_key}"))?;
+        let (blob_len, exists) = pipeline
+            .all::<(u64, bool)>()
+            .await
+            .err_tip(|| "In RedisStore::has_with_results::query")?;
+        Ok(exists.then_some(blob_len))
+    }
+
+    /// Sends the data stored at `encoded_key` from `offset` to `offset + length`
+    /// to `writer`, reading at most `read_chunk_size` bytes at a time.
+    async fn read_range(
+        &self,
+        client: &Client,
+        encoded_key: &str,
+        writer: &mut DropCloserWriteHalf,
+        offset: usize,
+        length: Option<usize>,
+    ) -> Result<(), Error> {
+        // N.B. the `-1`'s you see here are because redis GETRANGE is inclusive at both the start and end, so when we
+        // do math with indices we change them to be exclusive at the end.
+
+        // We want to read the data at the key from `offset` to `offset + length`.
+        let data_start = offset;
+        let data_end = data_start
+            .saturating_add(length.unwrap_or(isize::MAX as usize))
+            .saturating_sub(1);
+
+        // And we don't ever want to read more than `read_chunk_size` bytes at a time, so we'll need to iterate.
+        let mut chunk_start = data_start;
+        let mut chunk_end = cmp::min(
+            data_start.saturating_add(self.read_chunk_size) - 1,
+            data_end,
+        );
+
+        loop {
+            let chunk: Bytes = client
+                .getrange(encoded_key, chunk_start, chunk_end)
+                .await
+                .err_tip(|| "In RedisStore::get_part::getrange")?;
+
+            let didnt_receive_full_chunk = chunk.len() < self.read_chunk_size;
+            let reached_end_of_data = chunk_end == data_end;
+
+            if didnt_receive_full_chunk || reached_end_of_data {
+                if !chunk.is_empty() {
+                    writer
+                        .send(chunk)
+                        .await
+                        .err_tip(|| "Failed to write data in RedisStore::get_part")?;
+                }
+
+                break; // No more data to read.
+            }
+
+            // We received a full chunk's worth of data, so write it...
+            writer
+                .send(chunk)
+                .await
+                .err_tip(|| "Failed to write data in RedisStore::get_part")?;
+
+            // ...and go grab the next chunk.
+            chunk_start = chunk_end + 1;
+            chunk_end = cmp::min(
+                chunk_start.saturating_add(self.read_chunk_size) - 1,
+                data_end,
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns the length of the entry stored in the `field` of the AC hash
+    /// or `None` if there is no such entry.
+    async fn ac_hash_entry_len(
+        client: &Client,
+        layout: &AcHashLayout,
+        field: &str,
+    ) -> Result<Option<u64>, Error> {
+        let pipeline = client.pipeline();
+        pipeline
+            .hstrlen::<(), _, _>(&layout.hash_key, field)
+            .await
+            .err_tip(|| format!("In RedisStore::ac_hash_entry_len::hstrlen for {field}"))?;
+        // Like STRLEN, HSTRLEN returns 0 for fields that don't exist.
+        pipeline
+            .hexists::<(), _, _>(&layout.hash_key, field)
+            .await
+            .err_tip(|| format!("In RedisStore::ac_hash_entry_len::hexists for {field}"))?;
+        let (entry_len, exists) = pipeline
+            .all::<(u64, bool)>()
+            .await
+            .err_tip(|| "In RedisStore::ac_hash_entry_len::query")?;
+        Ok(exists.then_some(entry_len))
+    }
+
+    async fn update_ac_hash(
+        &self,
+        layout: &AcHashLayout,
+        key: StoreKey<'_>,
+        mut reader: DropCloserReadHalf,
+    ) -> Result<(), Error> {
+        let data = reader
+            .consume(None)
+            .await
+            .err_tip(|| "Failed to read data in RedisStore::update_ac_hash")?;
+        let field = key.encode(self.key_encoding);
+        let update_time = if layout.index_by_update_time {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_err(|e| make_err!(Code::Internal, "System time is before unix epoch: {e}"))?
+                .as_secs()
+                .to_string()
+        } else {
+            String::new()
+        };
+        let client = self.client_pool.next();
+        self.ac_hash_set_script
+            .evalsha_with_reload::<(), _, Vec<Bytes>>(
+                client,
+                vec![
+                    layout.hash_key.as_str(),
+                    layout.update_time_index_key.as_str(),
+                ],
+                vec![
+                    Bytes::from(field.to_string()),
+                    data,
+                    Bytes::from(layout.entry_ttl_s.to_string()),
+                    Bytes::from(update_time),
+                ],
+            )
+            .await
+            .err_tip(|| format!("In RedisStore::update_ac_hash for {field}"))?;
+
+        // If we have a publish channel configured, send a notice that the key has been set.
+        if let Some(pub_sub_channel) = &self.pub_sub_channel {
+            return Ok(client.publish(pub_sub_channel, field.as_ref()).await?);
+        };
+        Ok(())
+    }
+
+    async fn get_part_ac_hash(
+        &self,
+        layout: &AcHashLayout,
+        key: StoreKey<'_>,
+        writer: &mut DropCloserWriteHalf,
+        offset: usize,
+        length: Option<usize>,
+    ) -> Result<(), Error> {
+        let field = key.encode(self.key_encoding);
+        let data = self
+            .client_pool
+            .next()
+            .hget::<Option<Bytes>, _, _>(&layout.hash_key, field.as_ref())
+            .await
+            .err_tip(|| "In RedisStore::get_part_ac_hash::hget")?
+            .ok_or_else(|| {
+                make_err!(
+                    Code::NotFound,
+                    "Data not found in Redis store for digest: {key:?}"
+                )
+            })?;
+        let start = cmp::min(offset, data.len());
+        let end = length.map_or(data.len(), |length| {
+            cmp::min(start.saturating_add(length), data.len())
+        });
+        if start < end {
+            writer
+                .send(data.slice(start..end))
+                .await
+                .err_tip(|| "Failed to write data in RedisStore::get_part_ac_hash")?;
+        }
+        writer
+            .send_eof()
+            .err_tip(|| "Failed to write EOF in RedisStore::get_part_ac_hash")
+    }
 }
 
 #[async_trait]
@@ -327,30 +684,17 @@ impl StoreDriver for RedisStore {
                     *result = Some(0);
                     return Ok::<_, Error>(());
                 }
-                let encoded_key = self.encode_key(key);
-                let pipeline = client.pipeline();
-                pipeline
-                    .strlen::<(), _>(encoded_key.as_ref())
-                    .await
-                    .err_tip(|| {
-                        format!("In RedisStore::has_with_results::strlen for {encoded_key}")
-                    })?;
-                // Redis returns 0 when the key doesn't exist
-                // AND when the key exists with value of length 0.
-                // Therefore, we need to check both length and existence
-                // and do it in a pipeline for efficiency.
-                pipeline
-                    .exists::<(), _>(encoded_key.as_ref())
-                    .await
-                    .err_tip(|| {
-                        format!("In RedisStore::has_with_results::exists for {encoded_key}")
-                    })?;
-                let (blob_len, exists) = pipeline
-                    .all::<(u64, bool)>()
-                    .await
-                    .err_tip(|| "In RedisStore::has_with_results::query")?;
-
-                *result = if exists { Some(blob_len) } else { None };
+                if let Some(layout) = &self.ac_hash_layout {
+                    let field = key.encode(self.key_encoding);
+                    *result = Self::ac_hash_entry_len(client, layout, &field).await?;
+                    return Ok::<_, Error>(());
+                }
+                *result = Self::blob_len(client, &self.encode_key(key)).await?;
+                if result.is_none() {
+                    if let Some(legacy_key) = self.legacy_key(key) {
+                        *result = Self::blob_len(client, &legacy_key).await?;
+                    }
+                }
 
                 Ok::<_, Error>(())
             })
@@ -396,6 +740,10 @@ impl StoreDriver for RedisStore {
             }
         }
 
+        if let Some(layout) = &self.ac_hash_layout {
+            return self.update_ac_hash(layout, key, reader).await;
+        }
+
         let client = self.client_pool.next();
 
         let mut read_stream = reader
@@ -488,74 +836,44 @@ impl StoreDriver for RedisStore {
                 .err_tip(|| "Failed to send zero EOF in redis store get_part");
         }
 
+        if let Some(layout) = &self.ac_hash_layout {
+            return self
+                .get_part_ac_hash(layout, key, writer, offset, length)
+                .await;
+        }
+
         let client = self.client_pool.next();
         let encoded_key = self.encode_key(&key);
-        let encoded_key = encoded_key.as_ref();
-
-        // N.B. the `-1`'s you see here are because redis GETRANGE is inclusive at both the start and end, so when we
-        // do math with indices we change them to be exclusive at the end.
-
-        // We want to read the data at the key from `offset` to `offset + length`.
-        let data_start = offset;
-        let data_end = data_start
-            .saturating_add(length.unwrap_or(isize::MAX as usize))
-            .saturating_sub(1);
-
-        // And we don't ever want to read more than `read_chunk_size` bytes at a time, so we'll need to iterate.
-        let mut chunk_start = data_start;
-        let mut chunk_end = cmp::min(
-            data_start.saturating_add(self.read_chunk_size) - 1,
-            data_end,
-        );
-
-        loop {
-            let chunk: Bytes = client
-                .getrange(encoded_key, chunk_start, chunk_end)
-                .await
-                .err_tip(|| "In RedisStore::get_part::getrange")?;
-
-            let didnt_receive_full_chunk = chunk.len() < self.read_chunk_size;
-            let reached_end_of_data = chunk_end == data_end;
-
-            if didnt_receive_full_chunk || reached_end_of_data {
-                if !chunk.is_empty() {
-                    writer
-                        .send(chunk)
-                        .await
-                        .err_tip(|| "Failed to write data in RedisStore::get_part")?;
-                }
-
-                break; // No more data to read.
-            }
-
-            // We received a full chunk's worth of data, so write it...
-            writer
-                .send(chunk)
-                .await
-                .err_tip(|| "Failed to write data in RedisStore::get_part")?;
-
-            // ...and go grab the next chunk.
-            chunk_start = chunk_end + 1;
-            chunk_end = cmp::min(
-                chunk_start.saturating_add(self.read_chunk_size) - 1,
-                data_end,
-            );
-        }
+        self.read_range(client, &encoded_key, writer, offset, length)
+            .await?;
 
         // If we didn't write any data, check if the key exists, if not return a NotFound error.
         // This is required by spec.
         if writer.get_bytes_written() == 0 {
+            let not_found = || {
+                make_err!(
+                    Code::NotFound,
+                    "Data not found in Redis store for digest: {key:?}"
+                )
+            };
             // We're supposed to read 0 bytes, so just check if the key exists.
             let exists = client
-                .exists::<bool, _>(encoded_key)
+                .exists::<bool, _>(encoded_key.as_ref())
                 .await
                 .err_tip(|| "In RedisStore::get_part::zero_exists")?;
 
             if !exists {
-                return Err(make_err!(
-                    Code::NotFound,
-                    "Data not found in Redis store for digest: {key:?}"
-                ));
+                // The data may have been written before `key_encoding` was changed.
+                let legacy_key = self.legacy_key(&key).ok_or_else(not_found)?;
+                let legacy_exists = client
+                    .exists::<bool, _>(legacy_key.as_ref())
+                    .await
+                    .err_tip(|| "In RedisStore::get_part::legacy_exists")?;
+                if !legacy_exists {
+                    return Err(not_found());
+                }
+                self.read_range(client, &legacy_key, writer, offset, length)
+                    .await?;
             }
         }
 
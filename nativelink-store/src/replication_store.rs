@@ -0,0 +1,299 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::borrow::Cow;
+use std::cmp::Ordering as CmpOrdering;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use nativelink_config::stores::ReplicationSpec;
+use nativelink_error::{error_if, make_err, Code, Error, ResultExt};
+use nativelink_metric::MetricsComponent;
+use nativelink_util::buf_channel::{DropCloserReadHalf, DropCloserWriteHalf};
+use nativelink_util::health_utils::{HealthStatus, HealthStatusIndicator};
+use nativelink_util::instant_wrapper::InstantWrapper;
+use nativelink_util::store_trait::{Store, StoreDriver, StoreKey, StoreLike, UploadSizeInfo};
+use parking_lot::Mutex;
+use rand::rngs::OsRng;
+use rand::Rng;
+
+use crate::fan_out::update_all;
+
+/// Default value of [`ReplicationSpec::smoothing_factor`].
+const DEFAULT_SMOOTHING_FACTOR: f64 = 0.2;
+
+/// Default value of [`ReplicationSpec::exploration_rate`].
+const DEFAULT_EXPLORATION_RATE: f64 = 0.05;
+
+/// Default value of [`ReplicationSpec::max_error_rate`].
+const DEFAULT_MAX_ERROR_RATE: f64 = 0.5;
+
+/// Moving averages of the recent reads of a replica.
+#[derive(MetricsComponent, Default)]
+struct ReplicaHealth {
+    #[metric(help = "Moving average of the read latency in seconds")]
+    latency_s: Option<f64>,
+    #[metric(help = "Moving average of the share of reads that failed")]
+    error_rate: f64,
+}
+
+#[derive(MetricsComponent)]
+struct Replica {
+    #[metric(group = "store")]
+    store: Store,
+    #[metric(group = "health")]
+    health: Mutex<ReplicaHealth>,
+    #[metric(help = "Number of reads sent to this replica")]
+    reads: AtomicU64,
+    #[metric(help = "Number of reads of this replica that failed")]
+    read_errors: AtomicU64,
+}
+
+#[derive(MetricsComponent)]
+pub struct ReplicationStore<NowFn> {
+    #[metric(group = "replicas")]
+    replicas: Vec<Replica>,
+    now_fn: NowFn,
+    #[metric(help = "Weight of the newest read in the moving averages")]
+    smoothing_factor: f64,
+    #[metric(help = "Share of reads sent to a replica other than the preferred one")]
+    exploration_rate: f64,
+    #[metric(help = "Error rate above which a replica is considered unhealthy")]
+    max_error_rate: f64,
+    #[metric(help = "Number of reads sent to a replica other than the preferred one")]
+    exploration_count: AtomicU64,
+    #[metric(help = "Number of reads retried on another replica after a replica failed")]
+    failover_count: AtomicU64,
+}
+
+impl<I, NowFn> ReplicationStore<NowFn>
+where
+    I: InstantWrapper,
+    NowFn: Fn() -> I + Send + Sync + Unpin + 'static,
+{
+    pub fn new(
+        spec: &ReplicationSpec,
+        stores: Vec<Store>,
+        now_fn: NowFn,
+    ) -> Result<Arc<Self>, Error> {
+        error_if!(
+            spec.replicas.len() != stores.len(),
+            "Config replicas do not match stores length"
+        );
+        error_if!(
+            stores.is_empty(),
+            "ReplicationStore must have at least one replica"
+        );
+        let smoothing_factor = spec.smoothing_factor.unwrap_or(DEFAULT_SMOOTHING_FACTOR);
+        error_if!(
+            !(smoothing_factor > 0. && smoothing_factor <= 1.),
+            "smoothing_factor of ReplicationStore must be in (0, 1], got {smoothing_factor}"
+        );
+        let exploration_rate = spec.exploration_rate.unwrap_or(DEFAULT_EXPLORATION_RATE);
+        error_if!(
+            !(0. ..=1.).contains(&exploration_rate),
+            "exploration_rate of ReplicationStore must be in [0, 1], got {exploration_rate}"
+        );
+        let max_error_rate = spec.max_error_rate.unwrap_or(DEFAULT_MAX_ERROR_RATE);
+        error_if!(
+            !(0. ..=1.).contains(&max_error_rate),
+            "max_error_rate of ReplicationStore must be in [0, 1], got {max_error_rate}"
+        );
+        Ok(Arc::new(Self {
+            replicas: stores
+                .into_iter()
+                .map(|store| Replica {
+                    store,
+                    health: Mutex::new(ReplicaHealth::default()),
+                    reads: AtomicU64::new(0),
+                    read_errors: AtomicU64::new(0),
+                })
+                .collect(),
+            now_fn,
+            smoothing_factor,
+            exploration_rate,
+            max_error_rate,
+            exploration_count: AtomicU64::new(0),
+            failover_count: AtomicU64::new(0),
+        }))
+    }
+
+    /// Returns the indexes of the replicas in the order reads should try
+    /// them. Healthy replicas come first, fastest first, followed by the
+    /// unhealthy ones with the fewest errors first. Replicas that were
+    /// never read from are treated as the fastest, so each of them gets
+    /// measured. Now and then a random replica is moved to the front.
+    fn read_order(&self) -> Vec<usize> {
+        let mut ranked: Vec<(usize, bool, f64)> = self
+            .replicas
+            .iter()
+            .enumerate()
+            .map(|(index, replica)| {
+                let health = replica.health.lock();
+                if health.error_rate > self.max_error_rate {
+                    (index, false, health.error_rate)
+                } else {
+                    (index, true, health.latency_s.unwrap_or(0.))
+                }
+            })
+            .collect();
+        ranked.sort_by(|(_, a_healthy, a_score), (_, b_healthy, b_score)| {
+            b_healthy
+                .cmp(a_healthy)
+                .then_with(|| a_score.partial_cmp(b_score).unwrap_or(CmpOrdering::Equal))
+        });
+        let mut order: Vec<usize> = ranked.into_iter().map(|(index, _, _)| index).collect();
+        if order.len() > 1 && OsRng.gen_bool(self.exploration_rate) {
+            let explored = order.remove(OsRng.gen_range(1..order.len()));
+            order.insert(0, explored);
+            self.exploration_count.fetch_add(1, Ordering::Relaxed);
+        }
+        order
+    }
+
+    /// Folds the outcome of a read of a replica into its moving averages.
+    /// A `NotFound` answer still means the replica is up, so it counts as
+    /// a success.
+    fn record_read(&self, index: usize, latency: Duration, result: &Result<(), Error>) {
+        let replica = &self.replicas[index];
+        replica.reads.fetch_add(1, Ordering::Relaxed);
+        let failed = matches!(result, Err(err) if err.code != Code::NotFound);
+        let mut health = replica.health.lock();
+        if failed {
+            replica.read_errors.fetch_add(1, Ordering::Relaxed);
+            health.error_rate += self.smoothing_factor * (1. - health.error_rate);
+            return;
+        }
+        health.error_rate -= self.smoothing_factor * health.error_rate;
+        let latency_s = latency.as_secs_f64();
+        health.latency_s = Some(health.latency_s.map_or(latency_s, |average| {
+            average + self.smoothing_factor * (latency_s - average)
+        }));
+    }
+}
+
+#[async_trait]
+impl<I, NowFn> StoreDriver for ReplicationStore<NowFn>
+where
+    I: InstantWrapper,
+    NowFn: Fn() -> I + Send + Sync + Unpin + 'static,
+{
+    async fn has_with_results(
+        self: Pin<&Self>,
+        keys: &[StoreKey<'_>],
+        results: &mut [Option<u64>],
+    ) -> Result<(), Error> {
+        // Replicas hold the same data, so the first replica that answers
+        // is trusted, even for the keys it does not have.
+        let mut last_err = None;
+        for (attempt, index) in self.read_order().into_iter().enumerate() {
+            if attempt > 0 {
+                self.failover_count.fetch_add(1, Ordering::Relaxed);
+            }
+            results.fill(None);
+            let start = (self.now_fn)();
+            let result = self.replicas[index]
+                .store
+                .has_with_results(keys, results)
+                .await;
+            self.record_read(index, start.elapsed(), &result);
+            match result {
+                Ok(()) => return Ok(()),
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| make_err!(Code::Internal, "No replica to read from")))
+            .err_tip(|| "In ReplicationStore::has_with_results")
+    }
+
+    async fn update(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        reader: DropCloserReadHalf,
+        size_info: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        let stores: Vec<&Store> = self.replicas.iter().map(|replica| &replica.store).collect();
+        let (data_stream_res, replica_results) =
+            update_all(&stores, key, reader, size_info, stores.len()).await;
+        replica_results
+            .into_iter()
+            .fold(data_stream_res, ResultExt::merge)
+            .err_tip(|| "In ReplicationStore::update")
+    }
+
+    async fn get_part(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        writer: &mut DropCloserWriteHalf,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<(), Error> {
+        let mut last_err = None;
+        for (attempt, index) in self.read_order().into_iter().enumerate() {
+            if attempt > 0 {
+                self.failover_count.fetch_add(1, Ordering::Relaxed);
+            }
+            let bytes_written = writer.get_bytes_written();
+            let start = (self.now_fn)();
+            let result = self.replicas[index]
+                .store
+                .get_part(key.borrow(), &mut *writer, offset, length)
+                .await;
+            self.record_read(index, start.elapsed(), &result);
+            let Err(err) = result else {
+                return Ok(());
+            };
+            // Once data was sent the stream can not be handed over to
+            // another replica.
+            if writer.get_bytes_written() != bytes_written {
+                return Err(err).err_tip(|| {
+                    format!("In ReplicationStore::get_part after replica {index} sent data")
+                });
+            }
+            last_err = Some(err);
+        }
+        Err(last_err.unwrap_or_else(|| make_err!(Code::Internal, "No replica to read from")))
+            .err_tip(|| "In ReplicationStore::get_part")
+    }
+
+    fn inner_store(&self, _key: Option<StoreKey>) -> &'_ dyn StoreDriver {
+        self
+    }
+
+    fn as_any<'a>(&'a self) -> &'a (dyn std::any::Any + Sync + Send + 'static) {
+        self
+    }
+
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn std::any::Any + Sync + Send + 'static> {
+        self
+    }
+}
+
+#[async_trait]
+impl<I, NowFn> HealthStatusIndicator for ReplicationStore<NowFn>
+where
+    I: InstantWrapper,
+    NowFn: Fn() -> I + Send + Sync + Unpin + 'static,
+{
+    fn get_name(&self) -> &'static str {
+        "ReplicationStore"
+    }
+
+    async fn check_health(&self, namespace: Cow<'static, str>) -> HealthStatus {
+        StoreDriver::check_health(Pin::new(self), namespace).await
+    }
+}
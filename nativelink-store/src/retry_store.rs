@@ -0,0 +1,318 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::borrow::Cow;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use futures::join;
+use futures::stream::unfold;
+use nativelink_config::stores::RetryStoreSpec;
+use nativelink_error::{make_err, Code, Error, ResultExt};
+use nativelink_metric::MetricsComponent;
+use nativelink_util::buf_channel::{
+    make_buf_channel_pair, DropCloserReadHalf, DropCloserWriteHalf,
+};
+use nativelink_util::health_utils::{HealthStatus, HealthStatusIndicator};
+use nativelink_util::instant_wrapper::InstantWrapper;
+use nativelink_util::retry::{Retrier, RetryResult};
+use nativelink_util::store_trait::{Store, StoreDriver, StoreKey, StoreLike, UploadSizeInfo};
+use parking_lot::Mutex;
+use rand::rngs::OsRng;
+use rand::Rng;
+use tokio::time::sleep;
+use tracing::{event, Level};
+
+const DEFAULT_MAX_RETRY_BUFFER_SIZE: usize = 5 * 1024 * 1024; // 5MB.
+
+const DEFAULT_OPEN_SECONDS: u32 = 30;
+
+struct CircuitState {
+    /// Number of requests in a row that failed.
+    consecutive_failures: u32,
+    /// Time since the anchor until which requests fail right away.
+    open_until: Option<Duration>,
+}
+
+/// Fails requests right away while the backend keeps failing.
+struct CircuitBreaker<I: InstantWrapper> {
+    failure_threshold: u32,
+    open_duration: Duration,
+    anchor_time: I,
+    state: Mutex<CircuitState>,
+}
+
+impl<I: InstantWrapper> CircuitBreaker<I> {
+    /// Returns an error if the request must fail without reaching the
+    /// backend. Once the circuit was open for `open_duration`, one request
+    /// is let through and the circuit stays open for the others until it
+    /// finishes or `open_duration` passed again.
+    fn check(&self) -> Result<(), Error> {
+        let mut state = self.state.lock();
+        let Some(open_until) = state.open_until else {
+            return Ok(());
+        };
+        let now = self.anchor_time.elapsed();
+        if now < open_until {
+            return Err(make_err!(
+                Code::Unavailable,
+                "Circuit breaker of RetryStore is open after {} failed requests in a row",
+                state.consecutive_failures
+            ));
+        }
+        state.open_until = Some(now + self.open_duration);
+        Ok(())
+    }
+
+    /// Returns true if this request opened the circuit.
+    fn record(&self, failed: bool) -> bool {
+        let mut state = self.state.lock();
+        if !failed {
+            state.consecutive_failures = 0;
+            state.open_until = None;
+            return false;
+        }
+        state.consecutive_failures = state.consecutive_failures.saturating_add(1);
+        if self.failure_threshold == 0 || state.consecutive_failures < self.failure_threshold {
+            return false;
+        }
+        let was_closed = state.open_until.is_none();
+        state.open_until = Some(self.anchor_time.elapsed() + self.open_duration);
+        was_closed
+    }
+}
+
+#[derive(MetricsComponent)]
+pub struct RetryStore<I: InstantWrapper> {
+    #[metric(group = "inner_store")]
+    inner_store: Store,
+    retrier: Retrier,
+    #[metric(help = "The maximum number of bytes of an upload kept to retry it")]
+    max_retry_buffer_size: usize,
+    circuit_breaker: CircuitBreaker<I>,
+    #[metric(help = "Number of attempts that failed")]
+    failed_attempts: AtomicU64,
+    #[metric(help = "Number of requests that failed right away because the circuit was open")]
+    rejected_requests: AtomicU64,
+    #[metric(help = "Number of times the circuit opened")]
+    circuit_opened_count: AtomicU64,
+}
+
+impl RetryStore<SystemTime> {
+    pub fn new(spec: &RetryStoreSpec, inner_store: Store) -> Arc<Self> {
+        let jitter_amt = spec.retry.jitter;
+        Self::new_with_time(
+            spec,
+            inner_store,
+            Arc::new(move |delay: Duration| {
+                if jitter_amt == 0. {
+                    return delay;
+                }
+                let min = 1. - (jitter_amt / 2.);
+                let max = 1. + (jitter_amt / 2.);
+                delay.mul_f32(OsRng.gen_range(min..max))
+            }),
+            SystemTime::now(),
+        )
+    }
+}
+
+impl<I: InstantWrapper> RetryStore<I> {
+    pub fn new_with_time(
+        spec: &RetryStoreSpec,
+        inner_store: Store,
+        jitter_fn: Arc<dyn Fn(Duration) -> Duration + Send + Sync>,
+        anchor_time: I,
+    ) -> Arc<Self> {
+        let max_retry_buffer_size = if spec.max_retry_buffer_size == 0 {
+            DEFAULT_MAX_RETRY_BUFFER_SIZE
+        } else {
+            spec.max_retry_buffer_size
+        };
+        let open_seconds = if spec.circuit_breaker.open_seconds == 0 {
+            DEFAULT_OPEN_SECONDS
+        } else {
+            spec.circuit_breaker.open_seconds
+        };
+        Arc::new(Self {
+            inner_store,
+            retrier: Retrier::new(
+                Arc::new(|duration| Box::pin(sleep(duration))),
+                jitter_fn,
+                spec.retry.clone(),
+            ),
+            max_retry_buffer_size,
+            circuit_breaker: CircuitBreaker {
+                failure_threshold: spec.circuit_breaker.failure_threshold,
+                open_duration: Duration::from_secs(u64::from(open_seconds)),
+                anchor_time,
+                state: Mutex::new(CircuitState {
+                    consecutive_failures: 0,
+                    open_until: None,
+                }),
+            },
+            failed_attempts: AtomicU64::new(0),
+            rejected_requests: AtomicU64::new(0),
+            circuit_opened_count: AtomicU64::new(0),
+        })
+    }
+
+    fn check_circuit(&self) -> Result<(), Error> {
+        self.circuit_breaker.check().inspect_err(|_| {
+            self.rejected_requests.fetch_add(1, Ordering::Relaxed);
+        })
+    }
+
+    /// Records the outcome of a request in the circuit breaker. Only
+    /// errors that would be retried count as failures of the backend.
+    fn record_result<T>(&self, result: Result<T, Error>) -> Result<T, Error> {
+        let failed = result
+            .as_ref()
+            .is_err_and(|err| self.retrier.should_retry(err.code));
+        if self.circuit_breaker.record(failed) {
+            self.circuit_opened_count.fetch_add(1, Ordering::Relaxed);
+            event!(
+                Level::WARN,
+                open_duration = ?self.circuit_breaker.open_duration,
+                "Circuit breaker of RetryStore opened"
+            );
+        }
+        result
+    }
+
+    fn to_retry_result<T>(&self, result: Result<T, Error>) -> RetryResult<T> {
+        match result {
+            Ok(value) => RetryResult::Ok(value),
+            Err(err) => {
+                self.failed_attempts.fetch_add(1, Ordering::Relaxed);
+                RetryResult::Retry(err)
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<I: InstantWrapper> StoreDriver for RetryStore<I> {
+    async fn has_with_results(
+        self: Pin<&Self>,
+        keys: &[StoreKey<'_>],
+        results: &mut [Option<u64>],
+    ) -> Result<(), Error> {
+        self.check_circuit()?;
+        let result = self
+            .retrier
+            .retry(unfold(results, move |results| async move {
+                let result = self.inner_store.has_with_results(keys, results).await;
+                Some((self.to_retry_result(result), results))
+            }))
+            .await
+            .err_tip(|| "In RetryStore::has_with_results");
+        self.record_result(result)
+    }
+
+    async fn update(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        mut reader: DropCloserReadHalf,
+        size_info: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        self.check_circuit()?;
+        reader.set_max_recent_data_size(
+            u64::try_from(self.max_retry_buffer_size)
+                .err_tip(|| "Could not convert max_retry_buffer_size to u64")?,
+        );
+        let key = &key;
+        let result = self
+            .retrier
+            .retry(unfold(reader, move |mut reader| async move {
+                let (mut tx, rx) = make_buf_channel_pair();
+                let (update_res, bind_res) = join!(
+                    self.inner_store.update(key.borrow(), rx, size_info),
+                    tx.bind_buffered(&mut reader)
+                );
+                let Err(err) = update_res.merge(bind_res) else {
+                    return Some((RetryResult::Ok(()), reader));
+                };
+                let bytes_received = reader.get_bytes_received();
+                if let Err(reset_err) = reader.try_reset_stream() {
+                    return Some((
+                        RetryResult::Err(err.merge(reset_err).append(format!(
+                            "Could not retry upload with {bytes_received} bytes received in RetryStore::update"
+                        ))),
+                        reader,
+                    ));
+                }
+                Some((self.to_retry_result(Err(err)), reader))
+            }))
+            .await
+            .err_tip(|| "In RetryStore::update");
+        self.record_result(result)
+    }
+
+    async fn get_part(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        writer: &mut DropCloserWriteHalf,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<(), Error> {
+        self.check_circuit()?;
+        let key = &key;
+        let result = self
+            .retrier
+            .retry(unfold(writer, move |writer| async move {
+                // Data sent by a failed attempt is not sent again.
+                let bytes_written = writer.get_bytes_written();
+                let result = self
+                    .inner_store
+                    .get_part(
+                        key.borrow(),
+                        &mut *writer,
+                        offset + bytes_written,
+                        length.map(|length| length.saturating_sub(bytes_written)),
+                    )
+                    .await;
+                Some((self.to_retry_result(result), writer))
+            }))
+            .await
+            .err_tip(|| "In RetryStore::get_part");
+        self.record_result(result)
+    }
+
+    fn inner_store(&self, _key: Option<StoreKey>) -> &'_ dyn StoreDriver {
+        self
+    }
+
+    fn as_any<'a>(&'a self) -> &'a (dyn std::any::Any + Sync + Send + 'static) {
+        self
+    }
+
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn std::any::Any + Sync + Send + 'static> {
+        self
+    }
+}
+
+#[async_trait]
+impl<I: InstantWrapper> HealthStatusIndicator for RetryStore<I> {
+    fn get_name(&self) -> &'static str {
+        "RetryStore"
+    }
+
+    async fn check_health(&self, namespace: Cow<'static, str>) -> HealthStatus {
+        StoreDriver::check_health(Pin::new(self), namespace).await
+    }
+}
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::future::Future;
 use std::pin::Pin;
 use std::sync::Arc;
@@ -29,6 +30,7 @@ use aws_sdk_s3::operation::get_object::GetObjectError;
 use aws_sdk_s3::operation::head_object::HeadObjectError;
 use aws_sdk_s3::primitives::{ByteStream, SdkBody};
 use aws_sdk_s3::types::builders::{CompletedMultipartUploadBuilder, CompletedPartBuilder};
+use aws_sdk_s3::types::{StorageClass, Tag, Tagging};
 use aws_sdk_s3::Client;
 use aws_smithy_runtime::client::http::hyper_014::HyperClientBuilder;
 use bytes::Bytes;
@@ -47,6 +49,7 @@ use nativelink_config::stores::S3Spec;
 // ie: Don't import make_input_err!() to help prevent this.
 use nativelink_error::{make_err, Code, Error, ResultExt};
 use nativelink_metric::MetricsComponent;
+use nativelink_util::background_spawn;
 use nativelink_util::buf_channel::{
     make_buf_channel_pair, DropCloserReadHalf, DropCloserWriteHalf,
 };
@@ -55,6 +58,8 @@ use nativelink_util::health_utils::{HealthStatus, HealthStatusIndicator};
 use nativelink_util::instant_wrapper::InstantWrapper;
 use nativelink_util::retry::{Retrier, RetryResult};
 use nativelink_util::store_trait::{StoreDriver, StoreKey, UploadSizeInfo};
+use parking_lot::Mutex;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
 use rand::rngs::OsRng;
 use rand::Rng;
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
@@ -85,6 +90,34 @@ const DEFAULT_MAX_RETRY_BUFFER_PER_REQUEST: usize = 5 * 1024 * 1024; // 5MB.
 // Note: If you change this, adjust the docs in the config.
 const DEFAULT_MULTIPART_MAX_CONCURRENT_UPLOADS: usize = 10;
 
+// S3 objects cannot have more tags than this number. See:
+// https://docs.aws.amazon.com/AmazonS3/latest/userguide/object-tagging.html
+const MAX_OBJECT_TAGS: usize = 10;
+
+/// Name of the tag that holds the last time an object was read.
+const LAST_ACCESSED_TAG: &str = "last-accessed";
+
+/// Number of keys the store remembers the last `last-accessed` tag refresh
+/// of before it forgets the ones that are due for a refresh anyway.
+const MAX_TRACKED_TAG_REFRESHES: usize = 100_000;
+
+/// Characters that are escaped in the `x-amz-tagging` header, which is
+/// encoded like a URL query.
+const TAGGING_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Returns the size of the parts an upload of at most `max_size` bytes is
+/// split into. Parts are as small as S3 allows unless that would need more
+/// than `MAX_UPLOAD_PARTS` parts.
+fn bytes_per_upload_part(max_size: u64) -> u64 {
+    max_size
+        .div_ceil(MAX_UPLOAD_PARTS as u64)
+        .clamp(MIN_MULTIPART_SIZE, MAX_MULTIPART_SIZE)
+}
+
 pub struct ConnectionWithPermit<T: Connection + AsyncRead + AsyncWrite + Unpin> {
     connection: T,
     _permit: SemaphorePermit<'static>,
@@ -251,6 +284,14 @@ pub struct S3Store<NowFn> {
     max_retry_buffer_per_request: usize,
     #[metric(help = "The number of concurrent uploads allowed for multipart uploads")]
     multipart_max_concurrent_uploads: usize,
+    /// Tags set on uploaded objects, sorted by key.
+    object_tags: Vec<(String, String)>,
+    storage_class: Option<StorageClass>,
+    #[metric(help = "The number of seconds between refreshes of the last-accessed tag")]
+    last_accessed_tag_refresh_s: i64,
+    /// Unix time at which this store last set the `last-accessed` tag of
+    /// each recently read object.
+    last_accessed_tag_times: Mutex<HashMap<String, i64>>,
 }
 
 impl<I, NowFn> S3Store<NowFn>
@@ -299,6 +340,25 @@ where
         jitter_fn: Arc<dyn Fn(Duration) -> Duration + Send + Sync>,
         now_fn: NowFn,
     ) -> Result<Arc<Self>, Error> {
+        let mut object_tags: Vec<(String, String)> = spec
+            .object_tags
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        object_tags.sort();
+        let tag_count = object_tags.len() + usize::from(spec.last_accessed_tag_refresh_s != 0);
+        if tag_count > MAX_OBJECT_TAGS {
+            return Err(make_err!(
+                Code::InvalidArgument,
+                "S3 objects can have at most {MAX_OBJECT_TAGS} tags, but the S3 store config sets {tag_count}"
+            ));
+        }
+        if object_tags.iter().any(|(key, _)| key == LAST_ACCESSED_TAG) {
+            return Err(make_err!(
+                Code::InvalidArgument,
+                "The {LAST_ACCESSED_TAG} tag is set by the S3 store and can not be in object_tags"
+            ));
+        }
         Ok(Arc::new(Self {
             s3_client: Arc::new(s3_client),
             now_fn,
@@ -316,6 +376,10 @@ where
             multipart_max_concurrent_uploads: spec
                 .multipart_max_concurrent_uploads
                 .map_or(DEFAULT_MULTIPART_MAX_CONCURRENT_UPLOADS, |v| v),
+            object_tags,
+            storage_class: spec.storage_class.as_deref().map(StorageClass::from),
+            last_accessed_tag_refresh_s: i64::from(spec.last_accessed_tag_refresh_s),
+            last_accessed_tag_times: Mutex::new(HashMap::new()),
         }))
     }
 
@@ -323,6 +387,105 @@ where
         format!("{}{}", self.key_prefix, key.as_str(),)
     }
 
+    /// Returns the tags of an object, including the `last-accessed` tag
+    /// set to `now_s` if the store keeps it.
+    fn object_tags(&self, now_s: i64) -> Vec<(String, String)> {
+        let mut tags = self.object_tags.clone();
+        if self.last_accessed_tag_refresh_s != 0 {
+            tags.push((LAST_ACCESSED_TAG.to_string(), now_s.to_string()));
+        }
+        tags
+    }
+
+    /// Returns the `x-amz-tagging` header of uploads, if objects are tagged.
+    fn upload_tagging(&self, s3_path: &str) -> Option<String> {
+        let now_s = (self.now_fn)().unix_timestamp() as i64;
+        if self.last_accessed_tag_refresh_s != 0 {
+            self.last_accessed_tag_times
+                .lock()
+                .insert(s3_path.to_string(), now_s);
+        }
+        let tags = self.object_tags(now_s);
+        if tags.is_empty() {
+            return None;
+        }
+        Some(
+            tags.iter()
+                .map(|(key, value)| {
+                    format!(
+                        "{}={}",
+                        utf8_percent_encode(key, TAGGING_ENCODE_SET),
+                        utf8_percent_encode(value, TAGGING_ENCODE_SET)
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("&"),
+        )
+    }
+
+    /// Sets the `last-accessed` tag of the object at `s3_path` in the
+    /// background, unless this store already did so in the last
+    /// `last_accessed_tag_refresh_s` seconds.
+    fn refresh_last_accessed_tag(&self, s3_path: &str) {
+        if self.last_accessed_tag_refresh_s == 0 {
+            return;
+        }
+        let now_s = (self.now_fn)().unix_timestamp() as i64;
+        {
+            let mut tag_times = self.last_accessed_tag_times.lock();
+            if tag_times
+                .get(s3_path)
+                .is_some_and(|tag_time| now_s - tag_time < self.last_accessed_tag_refresh_s)
+            {
+                return;
+            }
+            if tag_times.len() >= MAX_TRACKED_TAG_REFRESHES {
+                tag_times
+                    .retain(|_, tag_time| now_s - *tag_time < self.last_accessed_tag_refresh_s);
+            }
+            tag_times.insert(s3_path.to_string(), now_s);
+        }
+        let tags = self.object_tags(now_s).into_iter().map(|(key, value)| {
+            Tag::builder()
+                .key(key)
+                .value(value)
+                .build()
+                .map_err(|e| make_err!(Code::Internal, "Failed to build S3 tag: {e:?}"))
+        });
+        let tagging = tags.collect::<Result<Vec<_>, Error>>().and_then(|tags| {
+            Tagging::builder()
+                .set_tag_set(Some(tags))
+                .build()
+                .map_err(|e| make_err!(Code::Internal, "Failed to build S3 tagging: {e:?}"))
+        });
+        let request = tagging.map(|tagging| {
+            self.s3_client
+                .put_object_tagging()
+                .bucket(&self.bucket)
+                .key(s3_path)
+                .tagging(tagging)
+        });
+        let s3_path = s3_path.to_string();
+        background_spawn!("s3_store_refresh_last_accessed_tag", async move {
+            let result = match request {
+                Ok(request) => request
+                    .send()
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| make_err!(Code::Unavailable, "{e:?}")),
+                Err(err) => Err(err),
+            };
+            if let Err(err) = result {
+                event!(
+                    Level::WARN,
+                    ?err,
+                    ?s3_path,
+                    "Failed to refresh last-accessed tag in S3Store"
+                );
+            }
+        });
+    }
+
     async fn has(self: Pin<&Self>, digest: &StoreKey<'_>) -> Result<Option<u64>, Error> {
         self.retrier
             .retry(unfold((), move |state| async move {
@@ -444,6 +607,8 @@ where
                                 .put_object()
                                 .bucket(&self.bucket)
                                 .key(s3_path.clone())
+                                .set_tagging(self.upload_tagging(s3_path))
+                                .set_storage_class(self.storage_class.clone())
                                 .content_length(sz as i64)
                                 .body(ByteStream::from_body_1_x(BodyWrapper {
                                     reader: rx,
@@ -497,6 +662,8 @@ where
                     .create_multipart_upload()
                     .bucket(&self.bucket)
                     .key(s3_path)
+                    .set_tagging(self.upload_tagging(s3_path))
+                    .set_storage_class(self.storage_class.clone())
                     .send()
                     .await
                     .map_or_else(
@@ -524,12 +691,12 @@ where
 
         // S3 requires us to upload in parts if the size is greater than 5GB. The part size must be at least
         // 5mb (except last part) and can have up to 10,000 parts.
-        let bytes_per_upload_part =
-            (max_size / (MIN_MULTIPART_SIZE - 1)).clamp(MIN_MULTIPART_SIZE, MAX_MULTIPART_SIZE);
+        let bytes_per_upload_part = bytes_per_upload_part(max_size);
 
         let upload_parts = move || async move {
             // This will ensure we only have `multipart_max_concurrent_uploads` * `bytes_per_upload_part`
-            // bytes in memory at any given time waiting to be uploaded.
+            // bytes in memory at any given time waiting to be uploaded. The same number of parts may be
+            // in flight.
             let (tx, mut rx) = mpsc::channel(self.multipart_max_concurrent_uploads);
 
             let read_stream_fut = async move {
@@ -602,7 +769,8 @@ where
                 tokio::select! {
                     result = &mut read_stream_fut => result?, // Return error or wait for other futures.
                     Some(upload_result) = upload_futures.next() => completed_parts.push(upload_result?),
-                    Some(fut) = rx.recv() => upload_futures.push(fut),
+                    // Each part is retried on its own, so a failed part never restarts the whole upload.
+                    Some(fut) = rx.recv(), if upload_futures.len() < self.multipart_max_concurrent_uploads => upload_futures.push(fut),
                 }
             }
 
@@ -767,7 +935,9 @@ where
                 }
                 Some((RetryResult::Ok(()), writer))
             }))
-            .await
+            .await?;
+        self.refresh_last_accessed_tag(s3_path);
+        Ok(())
     }
 
     fn inner_store(&self, _digest: Option<StoreKey>) -> &'_ dyn StoreDriver {
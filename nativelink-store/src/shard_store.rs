@@ -15,14 +15,18 @@
 use std::hash::{DefaultHasher, Hasher};
 use std::ops::BitXor;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
 use async_trait::async_trait;
+use futures::join;
 use futures::stream::{FuturesUnordered, TryStreamExt};
 use nativelink_config::stores::ShardSpec;
-use nativelink_error::{error_if, Error, ResultExt};
+use nativelink_error::{error_if, make_err, Code, Error, ResultExt};
 use nativelink_metric::MetricsComponent;
-use nativelink_util::buf_channel::{DropCloserReadHalf, DropCloserWriteHalf};
+use nativelink_util::buf_channel::{
+    make_buf_channel_pair, DropCloserReadHalf, DropCloserWriteHalf,
+};
 use nativelink_util::health_utils::{default_health_status_indicator, HealthStatusIndicator};
 use nativelink_util::store_trait::{Store, StoreDriver, StoreKey, StoreLike, UploadSizeInfo};
 
@@ -34,15 +38,57 @@ struct StoreAndWeight {
     store: Store,
 }
 
+/// Maps the hash of a key to the store it belongs to.
+struct ShardLayout {
+    // The upper bounds are always in ascending order, a specific store is
+    // choosen based on the hash of the key that is nearest-binary searched
+    // using the u32 as the index. Stores with a weight of 0 are left out.
+    upper_bounds_and_indexes: Vec<(u32, usize)>,
+}
+
+impl ShardLayout {
+    fn new(weights: &[u32]) -> Result<Self, Error> {
+        let total_weight: u64 = weights.iter().copied().map(u64::from).sum();
+        error_if!(
+            total_weight == 0,
+            "ShardStore must have at least one store with a weight above 0"
+        );
+        let mut upper_bounds_and_indexes: Vec<(u32, usize)> = weights
+            .iter()
+            .enumerate()
+            .filter(|(_, weight)| **weight != 0)
+            .scan(0, |state, (index, weight)| {
+                *state += (u64::from(u32::MAX) * u64::from(*weight) / total_weight) as u32;
+                Some((*state, index))
+            })
+            .collect();
+        // Our last item should always be the max.
+        upper_bounds_and_indexes.last_mut().unwrap().0 = u32::MAX;
+        Ok(Self {
+            upper_bounds_and_indexes,
+        })
+    }
+
+    fn store_index(&self, key_hash: u32) -> usize {
+        let position = self
+            .upper_bounds_and_indexes
+            .partition_point(|(upper_bound, _)| *upper_bound < key_hash);
+        self.upper_bounds_and_indexes[position].1
+    }
+}
+
 #[derive(MetricsComponent)]
 pub struct ShardStore {
-    // The weights will always be in ascending order a specific store is choosen based on the
-    // the hash of the key hash that is nearest-binary searched using the u32 as the index.
     #[metric(
         group = "stores",
         help = "The weights and stores that are used to determine which store to use"
     )]
     weights_and_stores: Vec<StoreAndWeight>,
+    layout: ShardLayout,
+    /// Layout before the set of shards last changed, if rebalancing.
+    previous_layout: Option<ShardLayout>,
+    #[metric(help = "Number of objects moved from their previous store on read")]
+    rebalanced_count: AtomicU64,
 }
 
 impl ShardStore {
@@ -55,87 +101,133 @@ impl ShardStore {
             spec.stores.is_empty(),
             "ShardStore must have at least one store"
         );
-        let total_weight: u64 = spec
+        let weights: Vec<u32> = spec
             .stores
             .iter()
-            .map(|shard_config| u64::from(shard_config.weight.unwrap_or(1)))
-            .sum();
-        let mut weights: Vec<u32> = spec
+            .map(|shard_config| shard_config.weight.unwrap_or(1))
+            .collect();
+        let layout = ShardLayout::new(&weights)?;
+        let previous_layout = if spec
             .stores
             .iter()
-            .map(|shard_config| {
-                (u64::from(u32::MAX) * u64::from(shard_config.weight.unwrap_or(1)) / total_weight)
-                    as u32
-            })
-            .scan(0, |state, weight| {
-                *state += weight;
-                Some(*state)
-            })
-            .collect();
-        // Our last item should always be the max.
-        *weights.last_mut().unwrap() = u32::MAX;
+            .any(|shard_config| shard_config.previous_weight.is_some())
+        {
+            let previous_weights: Vec<u32> = spec
+                .stores
+                .iter()
+                .map(|shard_config| shard_config.previous_weight.unwrap_or(0))
+                .collect();
+            Some(ShardLayout::new(&previous_weights).err_tip(|| "In previous_weight")?)
+        } else {
+            None
+        };
         Ok(Arc::new(Self {
             weights_and_stores: weights
                 .into_iter()
                 .zip(stores)
                 .map(|(weight, store)| StoreAndWeight { weight, store })
                 .collect(),
+            layout,
+            previous_layout,
+            rebalanced_count: AtomicU64::new(0),
         }))
     }
 
     fn get_store_index(&self, store_key: &StoreKey) -> usize {
-        let key = match store_key {
-            StoreKey::Digest(digest) => {
-                // Quote from std primitive array documentation:
-                //     Array’s try_from(slice) implementations (and the corresponding slice.try_into()
-                //     array implementations) succeed if the input slice length is the same as the result
-                //     array length. They optimize especially well when the optimizer can easily determine
-                //     the slice length, e.g. <[u8; 4]>::try_from(&slice[4..8]).unwrap(). Array implements
-                //     TryFrom returning.
-                let size_bytes = digest.size_bytes().to_le_bytes();
-                0.bitxor(u32::from_le_bytes(
-                    digest.packed_hash()[0..4].try_into().unwrap(),
-                ))
-                .bitxor(u32::from_le_bytes(
-                    digest.packed_hash()[4..8].try_into().unwrap(),
-                ))
-                .bitxor(u32::from_le_bytes(
-                    digest.packed_hash()[8..12].try_into().unwrap(),
-                ))
-                .bitxor(u32::from_le_bytes(
-                    digest.packed_hash()[12..16].try_into().unwrap(),
-                ))
-                .bitxor(u32::from_le_bytes(
-                    digest.packed_hash()[16..20].try_into().unwrap(),
-                ))
-                .bitxor(u32::from_le_bytes(
-                    digest.packed_hash()[20..24].try_into().unwrap(),
-                ))
-                .bitxor(u32::from_le_bytes(
-                    digest.packed_hash()[24..28].try_into().unwrap(),
-                ))
-                .bitxor(u32::from_le_bytes(
-                    digest.packed_hash()[28..32].try_into().unwrap(),
-                ))
-                .bitxor(u32::from_le_bytes(size_bytes[0..4].try_into().unwrap()))
-                .bitxor(u32::from_le_bytes(size_bytes[4..8].try_into().unwrap()))
-            }
-            StoreKey::Str(s) => {
-                let mut hasher = DefaultHasher::new();
-                hasher.write(s.as_bytes());
-                let key_u64 = hasher.finish();
-                (key_u64 >> 32) as u32 // We only need the top 32 bits.
-            }
-        };
-        self.weights_and_stores
-            .binary_search_by_key(&key, |item| item.weight)
-            .unwrap_or_else(|index| index)
+        self.layout.store_index(key_hash(store_key))
+    }
+
+    /// Returns the index of the store `store_key` belonged to before the
+    /// set of shards last changed, if that is a different store.
+    fn get_previous_store_index(&self, store_key: &StoreKey) -> Option<usize> {
+        let previous_index = self
+            .previous_layout
+            .as_ref()?
+            .store_index(key_hash(store_key));
+        (previous_index != self.get_store_index(store_key)).then_some(previous_index)
     }
 
     fn get_store(&self, key: &StoreKey) -> &Store {
         let index = self.get_store_index(key);
         &self.weights_and_stores[index].store
     }
+
+    /// Moves `key` from the store it belonged to before the set of shards
+    /// last changed into its current store. Returns false if the previous
+    /// store does not have it either.
+    async fn move_from_previous_store(
+        &self,
+        key: StoreKey<'_>,
+        previous_index: usize,
+    ) -> Result<bool, Error> {
+        let previous_store = &self.weights_and_stores[previous_index].store;
+        let Some(size) = previous_store.has(key.borrow()).await.err_tip(|| {
+            format!("In ShardStore::move_from_previous_store for store {previous_index}")
+        })?
+        else {
+            return Ok(false);
+        };
+        let (tx, rx) = make_buf_channel_pair();
+        let (get_res, update_res) = join!(
+            previous_store.get(key.borrow(), tx),
+            self.get_store(&key)
+                .update(key.borrow(), rx, UploadSizeInfo::ExactSize(size))
+        );
+        get_res
+            .merge(update_res)
+            .err_tip(|| "In ShardStore::move_from_previous_store")?;
+        self.rebalanced_count.fetch_add(1, Ordering::Relaxed);
+        Ok(true)
+    }
+
+    /// Looks up the keys missing from their current store in the store
+    /// they belonged to before the set of shards last changed.
+    async fn has_in_previous_stores(
+        &self,
+        keys: &[StoreKey<'_>],
+        results: &mut [Option<u64>],
+    ) -> Result<(), Error> {
+        for (key, result) in keys.iter().zip(results.iter_mut()) {
+            if result.is_some() {
+                continue;
+            }
+            if let Some(previous_index) = self.get_previous_store_index(key) {
+                *result = self.weights_and_stores[previous_index]
+                    .store
+                    .has(key.borrow())
+                    .await
+                    .err_tip(|| {
+                        format!(
+                            "In ShardStore::has_with_results() for previous store {previous_index}"
+                        )
+                    })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn key_hash(store_key: &StoreKey) -> u32 {
+    match store_key {
+        StoreKey::Digest(digest) => {
+            // Every supported hash length is a multiple of 4 bytes.
+            let size_bytes = digest.size_bytes().to_le_bytes();
+            digest
+                .packed_hash()
+                .chunks_exact(4)
+                .fold(0, |key, chunk| {
+                    key.bitxor(u32::from_le_bytes(chunk.try_into().unwrap()))
+                })
+                .bitxor(u32::from_le_bytes(size_bytes[0..4].try_into().unwrap()))
+                .bitxor(u32::from_le_bytes(size_bytes[4..8].try_into().unwrap()))
+        }
+        StoreKey::Str(s) => {
+            let mut hasher = DefaultHasher::new();
+            hasher.write(s.as_bytes());
+            let key_u64 = hasher.finish();
+            (key_u64 >> 32) as u32 // We only need the top 32 bits.
+        }
+    }
 }
 
 #[async_trait]
@@ -145,17 +237,18 @@ impl StoreDriver for ShardStore {
         keys: &[StoreKey<'_>],
         results: &mut [Option<u64>],
     ) -> Result<(), Error> {
+        type KeyIdxVec = Vec<usize>;
+        type KeyVec<'a> = Vec<StoreKey<'a>>;
         if keys.len() == 1 {
             // Hot path: It is very common to lookup only one key.
             let store_idx = self.get_store_index(&keys[0]);
             let store = &self.weights_and_stores[store_idx].store;
-            return store
+            store
                 .has_with_results(keys, results)
                 .await
-                .err_tip(|| "In ShardStore::has_with_results() for store {store_idx}}");
+                .err_tip(|| "In ShardStore::has_with_results() for store {store_idx}}")?;
+            return self.has_in_previous_stores(keys, results).await;
         }
-        type KeyIdxVec = Vec<usize>;
-        type KeyVec<'a> = Vec<StoreKey<'a>>;
         let mut keys_for_store: Vec<(KeyIdxVec, KeyVec)> = self
             .weights_and_stores
             .iter()
@@ -191,7 +284,7 @@ impl StoreDriver for ShardStore {
                 results[key_idx] = inner_result;
             }
         }
-        Ok(())
+        self.has_in_previous_stores(keys, results).await
     }
 
     async fn update(
@@ -215,6 +308,23 @@ impl StoreDriver for ShardStore {
         length: Option<u64>,
     ) -> Result<(), Error> {
         let store = self.get_store(&key);
+        if let Some(previous_index) = self.get_previous_store_index(&key) {
+            if store
+                .has(key.borrow())
+                .await
+                .err_tip(|| "In ShardStore::get_part()")?
+                .is_none()
+                && !self
+                    .move_from_previous_store(key.borrow(), previous_index)
+                    .await?
+            {
+                return Err(make_err!(
+                    Code::NotFound,
+                    "Key {} not found in ShardStore",
+                    key.as_str()
+                ));
+            }
+        }
         store
             .get_part(key, writer, offset, length)
             .await
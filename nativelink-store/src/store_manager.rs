@@ -12,8 +12,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
 
+use nativelink_config::stores::{StoreRefName, StoreSpec};
+use nativelink_error::{error_if, make_input_err, Error};
 use nativelink_metric::{MetricsComponent, RootMetricsComponent};
 use nativelink_util::store_trait::Store;
 use parking_lot::RwLock;
@@ -43,6 +46,197 @@ impl StoreManager {
         }
         None
     }
+
+    /// Checks the stores of a config before any of them is created, so
+    /// mistakes are reported at startup instead of by the first request
+    /// using the store. Fails if a `ref_store` points to a store that is
+    /// not configured, if stores refer to each other in a cycle or if
+    /// stores are combined in a way that can not work. Returns the tree
+    /// of every store, meant to be logged.
+    pub fn validate_specs(stores: &HashMap<StoreRefName, StoreSpec>) -> Result<String, Error> {
+        let mut names: Vec<&str> = stores.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        let mut refs = HashMap::new();
+        for name in &names {
+            let mut store_refs = Vec::new();
+            check_spec(name, &stores[*name], stores, &mut store_refs)?;
+            refs.insert(*name, store_refs);
+        }
+        let mut done = HashSet::new();
+        for name in &names {
+            if let Some(cycle) = find_cycle(name, &refs, &mut Vec::new(), &mut done) {
+                return Err(make_input_err!(
+                    "Stores refer to each other in a cycle: {}",
+                    cycle.join(" -> ")
+                ));
+            }
+        }
+        let mut tree = String::new();
+        for name in names {
+            write_tree(&mut tree, name, &stores[name], 0);
+        }
+        Ok(tree)
+    }
+}
+
+/// Returns the kind of `spec` as written in the config and the stores it
+/// wraps, named by the field they are configured in.
+fn spec_children(spec: &StoreSpec) -> (&'static str, Vec<(String, &StoreSpec)>) {
+    fn field<'a>(name: &str, spec: &'a StoreSpec) -> (String, &'a StoreSpec) {
+        (name.to_string(), spec)
+    }
+    fn list<'a>(
+        name: &str,
+        specs: impl Iterator<Item = &'a StoreSpec>,
+    ) -> Vec<(String, &'a StoreSpec)> {
+        specs
+            .enumerate()
+            .map(|(i, spec)| (format!("{name}[{i}]"), spec))
+            .collect()
+    }
+    match spec {
+        StoreSpec::memory(_) => ("memory", vec![]),
+        StoreSpec::experimental_s3_store(_) => ("experimental_s3_store", vec![]),
+        StoreSpec::experimental_gcs_store(_) => ("experimental_gcs_store", vec![]),
+        StoreSpec::verify(spec) => ("verify", vec![field("backend", &spec.backend)]),
+        StoreSpec::translation(spec) => (
+            "translation",
+            vec![
+                field("backend", &spec.backend),
+                field("mapping_store", &spec.mapping_store),
+            ],
+        ),
+        StoreSpec::throttle(spec) => ("throttle", vec![field("backend", &spec.backend)]),
+        StoreSpec::alias(spec) => ("alias", vec![field("backend", &spec.backend)]),
+        StoreSpec::completeness_checking(spec) => (
+            "completeness_checking",
+            vec![
+                field("backend", &spec.backend),
+                field("cas_store", &spec.cas_store),
+            ],
+        ),
+        StoreSpec::compression(spec) => ("compression", vec![field("backend", &spec.backend)]),
+        StoreSpec::encryption(spec) => ("encryption", vec![field("backend", &spec.backend)]),
+        StoreSpec::dedup(spec) => (
+            "dedup",
+            vec![
+                field("index_store", &spec.index_store),
+                field("content_store", &spec.content_store),
+            ],
+        ),
+        StoreSpec::existence_cache(spec) => {
+            ("existence_cache", vec![field("backend", &spec.backend)])
+        }
+        StoreSpec::retry(spec) => ("retry", vec![field("backend", &spec.backend)]),
+        StoreSpec::quota(spec) => ("quota", vec![field("backend", &spec.backend)]),
+        StoreSpec::fast_slow(spec) => (
+            "fast_slow",
+            vec![field("fast", &spec.fast), field("slow", &spec.slow)],
+        ),
+        StoreSpec::shard(spec) => (
+            "shard",
+            list("stores", spec.stores.iter().map(|shard| &shard.store)),
+        ),
+        StoreSpec::replication(spec) => ("replication", list("replicas", spec.replicas.iter())),
+        StoreSpec::mirror(spec) => ("mirror", list("stores", spec.stores.iter())),
+        StoreSpec::filesystem(_) => ("filesystem", vec![]),
+        StoreSpec::experimental_redb_store(_) => ("experimental_redb_store", vec![]),
+        StoreSpec::experimental_archive_store(_) => ("experimental_archive_store", vec![]),
+        StoreSpec::ref_store(_) => ("ref_store", vec![]),
+        StoreSpec::size_partitioning(spec) => (
+            "size_partitioning",
+            vec![
+                field("lower_store", &spec.lower_store),
+                field("upper_store", &spec.upper_store),
+            ],
+        ),
+        StoreSpec::grpc(_) => ("grpc", vec![]),
+        StoreSpec::redis_store(_) => ("redis_store", vec![]),
+        StoreSpec::noop(_) => ("noop", vec![]),
+    }
+}
+
+/// Returns true if both specs are a `ref_store` to the same store.
+fn is_same_ref(a: &StoreSpec, b: &StoreSpec) -> bool {
+    matches!((a, b), (StoreSpec::ref_store(a), StoreSpec::ref_store(b)) if a.name == b.name)
+}
+
+/// Checks `spec` and the stores it wraps, adding the names of the stores
+/// they refer to to `refs`.
+fn check_spec<'a>(
+    path: &str,
+    spec: &'a StoreSpec,
+    stores: &HashMap<StoreRefName, StoreSpec>,
+    refs: &mut Vec<&'a str>,
+) -> Result<(), Error> {
+    match spec {
+        StoreSpec::ref_store(spec) => {
+            error_if!(
+                !stores.contains_key(&spec.name),
+                "Store {path} refers to store '{}' which is not configured",
+                spec.name
+            );
+            refs.push(&spec.name);
+        }
+        StoreSpec::fast_slow(spec) => {
+            error_if!(
+                is_same_ref(&spec.fast, &spec.slow),
+                "The fast and slow stores of {path} are the same store"
+            );
+            error_if!(
+                spec.write_back && !matches!(spec.fast, StoreSpec::filesystem(_)),
+                "Store {path} sets write_back, which requires the fast store to be a filesystem store"
+            );
+        }
+        StoreSpec::dedup(spec) if is_same_ref(&spec.index_store, &spec.content_store) => {
+            return Err(make_input_err!(
+                "The index and content stores of {path} are the same store"
+            ));
+        }
+        _ => {}
+    }
+    for (field, child) in spec_children(spec).1 {
+        check_spec(&format!("{path}.{field}"), child, stores, refs)?;
+    }
+    Ok(())
+}
+
+/// Returns the stores on a cycle of `ref_store`s reachable from `name`.
+fn find_cycle<'a>(
+    name: &'a str,
+    refs: &HashMap<&'a str, Vec<&'a str>>,
+    path: &mut Vec<&'a str>,
+    done: &mut HashSet<&'a str>,
+) -> Option<Vec<&'a str>> {
+    if let Some(start) = path.iter().position(|visited| *visited == name) {
+        let mut cycle = path[start..].to_vec();
+        cycle.push(name);
+        return Some(cycle);
+    }
+    if done.contains(name) {
+        return None;
+    }
+    path.push(name);
+    for next in refs.get(name).into_iter().flatten() {
+        if let Some(cycle) = find_cycle(next, refs, path, done) {
+            return Some(cycle);
+        }
+    }
+    path.pop();
+    done.insert(name);
+    None
+}
+
+fn write_tree(tree: &mut String, label: &str, spec: &StoreSpec, depth: usize) {
+    let (kind, children) = spec_children(spec);
+    let _ = write!(tree, "{:indent$}{label}: {kind}", "", indent = depth * 2);
+    if let StoreSpec::ref_store(spec) = spec {
+        let _ = write!(tree, " -> {}", spec.name);
+    }
+    tree.push('\n');
+    for (field, child) in children {
+        write_tree(tree, &field, child, depth + 1);
+    }
 }
 
 impl RootMetricsComponent for StoreManager {}
@@ -0,0 +1,255 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use futures::join;
+use nativelink_config::stores::ThrottleSpec;
+use nativelink_error::{Error, ResultExt};
+use nativelink_metric::MetricsComponent;
+use nativelink_util::buf_channel::{
+    make_buf_channel_pair, DropCloserReadHalf, DropCloserWriteHalf,
+};
+use nativelink_util::health_utils::{HealthStatus, HealthStatusIndicator};
+use nativelink_util::instant_wrapper::InstantWrapper;
+use nativelink_util::origin_context::{ActiveOriginContext, ORIGIN_IDENTITY};
+use nativelink_util::store_trait::{Store, StoreDriver, StoreKey, StoreLike, UploadSizeInfo};
+use parking_lot::Mutex;
+
+use crate::quota_store::TokenBucket;
+
+/// Number of client identities tracked before the ones that are not
+/// being limited are forgotten.
+const MAX_TRACKED_IDENTITIES: usize = 10_000;
+
+/// Token buckets of a limit applied to each client identity.
+struct IdentityLimits {
+    rate: u64,
+    buckets: Mutex<HashMap<String, Arc<TokenBucket>>>,
+}
+
+impl IdentityLimits {
+    fn new(rate: u64) -> Option<Self> {
+        (rate != 0).then(|| Self {
+            rate,
+            buckets: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn get(&self, identity: &str, now: Duration) -> Arc<TokenBucket> {
+        let mut buckets = self.buckets.lock();
+        if let Some(bucket) = buckets.get(identity) {
+            return bucket.clone();
+        }
+        if buckets.len() >= MAX_TRACKED_IDENTITIES {
+            // A full bucket behaves the same as a new one.
+            buckets.retain(|_, bucket| !bucket.is_full(now));
+        }
+        let bucket = Arc::new(TokenBucket::new(self.rate).expect("Rate must not be zero"));
+        buckets.insert(identity.to_string(), bucket.clone());
+        bucket
+    }
+}
+
+#[derive(MetricsComponent)]
+pub struct ThrottleStore<I: InstantWrapper> {
+    #[metric(group = "inner_store")]
+    inner_store: Store,
+    read_limit: Option<TokenBucket>,
+    write_limit: Option<TokenBucket>,
+    identity_read_limits: Option<IdentityLimits>,
+    identity_write_limits: Option<IdentityLimits>,
+    anchor_time: I,
+    #[metric(help = "Number of bytes read from the backend")]
+    read_bytes: AtomicU64,
+    #[metric(help = "Number of bytes written to the backend")]
+    written_bytes: AtomicU64,
+    #[metric(help = "Number of times a read or write was slowed down")]
+    throttled_count: AtomicU64,
+}
+
+impl ThrottleStore<SystemTime> {
+    pub fn new(spec: &ThrottleSpec, inner_store: Store) -> Arc<Self> {
+        Self::new_with_time(spec, inner_store, SystemTime::now())
+    }
+}
+
+impl<I: InstantWrapper> ThrottleStore<I> {
+    pub fn new_with_time(spec: &ThrottleSpec, inner_store: Store, anchor_time: I) -> Arc<Self> {
+        Arc::new(Self {
+            inner_store,
+            read_limit: TokenBucket::new(spec.max_read_bytes_per_second),
+            write_limit: TokenBucket::new(spec.max_write_bytes_per_second),
+            identity_read_limits: IdentityLimits::new(spec.max_read_bytes_per_second_per_identity),
+            identity_write_limits: IdentityLimits::new(
+                spec.max_write_bytes_per_second_per_identity,
+            ),
+            anchor_time,
+            read_bytes: AtomicU64::new(0),
+            written_bytes: AtomicU64::new(0),
+            throttled_count: AtomicU64::new(0),
+        })
+    }
+
+    /// Returns the buckets a request of the active client takes tokens
+    /// from.
+    fn limits<'a>(
+        &'a self,
+        limit: Option<&'a TokenBucket>,
+        identity_limits: Option<&IdentityLimits>,
+    ) -> (Option<&'a TokenBucket>, Option<Arc<TokenBucket>>) {
+        let identity_bucket = identity_limits.map(|identity_limits| {
+            let identity = ActiveOriginContext::get_value(&ORIGIN_IDENTITY)
+                .ok()
+                .flatten()
+                .map_or(String::new(), |identity| identity.as_ref().clone());
+            identity_limits.get(&identity, self.anchor_time.elapsed())
+        });
+        (limit, identity_bucket)
+    }
+
+    /// Copies `reader` into `writer`, waiting after each chunk until it
+    /// fits in the limits.
+    async fn forward(
+        &self,
+        reader: &mut DropCloserReadHalf,
+        writer: &mut DropCloserWriteHalf,
+        (limit, identity_limit): (Option<&TokenBucket>, Option<Arc<TokenBucket>>),
+        counter: &AtomicU64,
+    ) -> Result<(), Error> {
+        loop {
+            let chunk = reader
+                .recv()
+                .await
+                .err_tip(|| "Failed to read chunk in ThrottleStore")?;
+            if chunk.is_empty() {
+                return writer
+                    .send_eof()
+                    .err_tip(|| "Failed to send EOF in ThrottleStore");
+            }
+            let cost = chunk.len() as f64;
+            let now = self.anchor_time.elapsed();
+            let wait = [limit, identity_limit.as_deref()]
+                .into_iter()
+                .flatten()
+                .filter_map(|bucket| bucket.take(now, cost, true))
+                .max();
+            if let Some(wait) = wait {
+                self.throttled_count.fetch_add(1, Ordering::Relaxed);
+                I::from_secs(0).sleep(wait).await;
+            }
+            counter.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+            writer
+                .send(chunk)
+                .await
+                .err_tip(|| "Failed to send chunk in ThrottleStore")?;
+        }
+    }
+}
+
+#[async_trait]
+impl<I: InstantWrapper> StoreDriver for ThrottleStore<I> {
+    async fn has_with_results(
+        self: Pin<&Self>,
+        keys: &[StoreKey<'_>],
+        results: &mut [Option<u64>],
+    ) -> Result<(), Error> {
+        self.inner_store.has_with_results(keys, results).await
+    }
+
+    async fn update(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        mut reader: DropCloserReadHalf,
+        size_info: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        let limits = self.limits(
+            self.write_limit.as_ref(),
+            self.identity_write_limits.as_ref(),
+        );
+        if limits.0.is_none() && limits.1.is_none() {
+            return self.inner_store.update(key, reader, size_info).await;
+        }
+        let (mut tx, rx) = make_buf_channel_pair();
+        let (update_res, forward_res) = join!(
+            self.inner_store.update(key, rx, size_info),
+            // Note: `tx` is dropped as soon as forwarding fails, so the
+            // backend does not wait for more data.
+            async move {
+                self.forward(&mut reader, &mut tx, limits, &self.written_bytes)
+                    .await
+            },
+        );
+        update_res
+            .merge(forward_res)
+            .err_tip(|| "In ThrottleStore::update")
+    }
+
+    async fn get_part(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        writer: &mut DropCloserWriteHalf,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<(), Error> {
+        let limits = self.limits(self.read_limit.as_ref(), self.identity_read_limits.as_ref());
+        if limits.0.is_none() && limits.1.is_none() {
+            return self.inner_store.get_part(key, writer, offset, length).await;
+        }
+        let (mut tx, mut rx) = make_buf_channel_pair();
+        let (get_res, forward_res) = join!(
+            async move {
+                self.inner_store
+                    .get_part(key, &mut tx, offset, length)
+                    .await
+            },
+            async move {
+                self.forward(&mut rx, writer, limits, &self.read_bytes)
+                    .await
+            },
+        );
+        get_res
+            .merge(forward_res)
+            .err_tip(|| "In ThrottleStore::get_part")
+    }
+
+    fn inner_store(&self, _key: Option<StoreKey>) -> &'_ dyn StoreDriver {
+        self
+    }
+
+    fn as_any<'a>(&'a self) -> &'a (dyn std::any::Any + Sync + Send + 'static) {
+        self
+    }
+
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn std::any::Any + Sync + Send + 'static> {
+        self
+    }
+}
+
+#[async_trait]
+impl<I: InstantWrapper> HealthStatusIndicator for ThrottleStore<I> {
+    fn get_name(&self) -> &'static str {
+        "ThrottleStore"
+    }
+
+    async fn check_health(&self, namespace: Cow<'static, str>) -> HealthStatus {
+        StoreDriver::check_health(Pin::new(self), namespace).await
+    }
+}
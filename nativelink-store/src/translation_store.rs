@@ -0,0 +1,271 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::pin::Pin;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::future::try_join_all;
+use nativelink_config::stores::TranslationSpec;
+use nativelink_error::{make_err, make_input_err, Code, Error, ResultExt};
+use nativelink_metric::MetricsComponent;
+use nativelink_util::buf_channel::{
+    make_buf_channel_pair, DropCloserReadHalf, DropCloserWriteHalf, HashingWriteHalf,
+};
+use nativelink_util::common::DigestInfo;
+use nativelink_util::digest_hasher::{
+    default_digest_hasher_func, DigestHasher, DigestHasherFunc, ACTIVE_HASHER_FUNC,
+};
+use nativelink_util::health_utils::{default_health_status_indicator, HealthStatusIndicator};
+use nativelink_util::origin_context::ActiveOriginContext;
+use nativelink_util::store_trait::{
+    Store, StoreDriver, StoreKey, StoreKeyEncoding, StoreLike, UploadSizeInfo,
+};
+
+/// Returns the digest function whose digests are translated to and from
+/// the digests of `backend_func`.
+const fn translated_digest_hasher_func(backend_func: DigestHasherFunc) -> DigestHasherFunc {
+    match backend_func {
+        DigestHasherFunc::Sha256 => DigestHasherFunc::Blake3,
+        DigestHasherFunc::Blake3 | DigestHasherFunc::Sha1 | DigestHasherFunc::Sha512 => {
+            DigestHasherFunc::Sha256
+        }
+    }
+}
+
+#[derive(MetricsComponent)]
+pub struct TranslationStore {
+    #[metric(group = "backend")]
+    backend: Store,
+    #[metric(group = "mapping_store")]
+    mapping_store: Store,
+    #[metric(help = "The digest function the backend is keyed by")]
+    backend_func: DigestHasherFunc,
+}
+
+impl TranslationStore {
+    pub fn new(spec: &TranslationSpec, backend: Store, mapping_store: Store) -> Arc<Self> {
+        Arc::new(TranslationStore {
+            backend,
+            mapping_store,
+            backend_func: spec
+                .backend_digest_function
+                .map_or(DigestHasherFunc::Sha256, Into::into),
+        })
+    }
+
+    fn active_hasher_func() -> Result<DigestHasherFunc, Error> {
+        Ok(ActiveOriginContext::get_value(&ACTIVE_HASHER_FUNC)
+            .err_tip(|| "In TranslationStore::active_hasher_func")?
+            .map_or_else(default_digest_hasher_func, |v| *v))
+    }
+
+    fn mapping_key(func: DigestHasherFunc, digest: DigestInfo) -> StoreKey<'static> {
+        StoreKey::Str(format!("{func}-{digest}").into())
+    }
+
+    /// Returns the key the content of `key` is stored under in the backend
+    /// or `None` if no content of `key` was ever stored.
+    async fn translate_key<'a>(
+        &self,
+        key: StoreKey<'a>,
+        func: DigestHasherFunc,
+    ) -> Result<Option<StoreKey<'a>>, Error> {
+        let StoreKey::Digest(digest) = key else {
+            return Ok(Some(key));
+        };
+        if func == self.backend_func {
+            return Ok(Some(key));
+        }
+        if digest.size_bytes() == 0 {
+            return Ok(Some(self.backend_func.hasher().finalize_digest().into()));
+        }
+        let data = match self
+            .mapping_store
+            .get_part_unchunked(Self::mapping_key(func, digest), 0, None)
+            .await
+        {
+            Ok(data) => data,
+            Err(err) if err.code == Code::NotFound => return Ok(None),
+            Err(err) => return Err(err).err_tip(|| "In TranslationStore::translate_key"),
+        };
+        let encoded = std::str::from_utf8(&data).map_err(|e| {
+            make_input_err!("Invalid mapping for {digest} in TranslationStore: {e}")
+        })?;
+        let (backend_digest, _) = StoreKeyEncoding::decode_digest(encoded)
+            .err_tip(|| format!("Invalid mapping for {digest} in TranslationStore"))?;
+        Ok(Some(backend_digest.into()))
+    }
+
+    async fn write_mapping(
+        &self,
+        func: DigestHasherFunc,
+        digest: DigestInfo,
+        backend_digest: DigestInfo,
+    ) -> Result<(), Error> {
+        self.mapping_store
+            .update_oneshot(
+                Self::mapping_key(func, digest),
+                Bytes::from(format!("{backend_digest}")),
+            )
+            .await
+            .err_tip(|| "Failed to write digest mapping in TranslationStore")
+    }
+
+    /// Streams the data to the backend under `digest` while hashing it with
+    /// the translated digest function, then maps that digest to `digest`.
+    async fn update_backend_func(
+        &self,
+        digest: DigestInfo,
+        mut reader: DropCloserReadHalf,
+        size_info: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        let translated_func = translated_digest_hasher_func(self.backend_func);
+        let (mut tx, rx) = make_buf_channel_pair();
+        let update_fut = self.backend.update(digest, rx, size_info);
+        let hash_fut = async {
+            let mut tx = HashingWriteHalf::new(&mut tx, Some(translated_func.hasher()));
+            loop {
+                let chunk = reader
+                    .recv()
+                    .await
+                    .err_tip(|| "Failed to read chunk in TranslationStore::update")?;
+                if chunk.is_empty() {
+                    let translated_digest = tx
+                        .finalize_digest()
+                        .err_tip(|| "Hasher missing in TranslationStore::update")?;
+                    tx.send_eof().err_tip(|| "In TranslationStore::update")?;
+                    return Ok::<_, Error>(translated_digest);
+                }
+                tx.send(chunk)
+                    .await
+                    .err_tip(|| "Failed to write chunk to backend in TranslationStore")?;
+            }
+        };
+        let (update_res, hash_res) = tokio::join!(update_fut, hash_fut);
+        let translated_digest = match (update_res, hash_res) {
+            (Ok(()), Ok(translated_digest)) => translated_digest,
+            (update_res, hash_res) => return update_res.merge(hash_res.map(|_| ())),
+        };
+        self.write_mapping(translated_func, translated_digest, digest)
+            .await
+    }
+
+    /// The backend digest is only known once all the data was received, so
+    /// the data is buffered before it is written to the backend.
+    async fn update_translated_func(
+        &self,
+        func: DigestHasherFunc,
+        digest: DigestInfo,
+        mut reader: DropCloserReadHalf,
+    ) -> Result<(), Error> {
+        let data = reader
+            .consume(None)
+            .await
+            .err_tip(|| "Failed to read data in TranslationStore::update")?;
+        let mut hasher = self.backend_func.hasher();
+        hasher.update(&data);
+        let backend_digest = hasher.finalize_digest();
+        self.backend
+            .update_oneshot(backend_digest, data)
+            .await
+            .err_tip(|| "Failed to write to backend in TranslationStore")?;
+        self.write_mapping(func, digest, backend_digest).await
+    }
+}
+
+#[async_trait]
+impl StoreDriver for TranslationStore {
+    async fn has_with_results(
+        self: Pin<&Self>,
+        keys: &[StoreKey<'_>],
+        results: &mut [Option<u64>],
+    ) -> Result<(), Error> {
+        let func = Self::active_hasher_func()?;
+        if func == self.backend_func {
+            return self.backend.has_with_results(keys, results).await;
+        }
+        let translated_keys = try_join_all(
+            keys.iter()
+                .map(|key| self.translate_key(key.borrow(), func)),
+        )
+        .await?;
+        let backend_keys: Vec<StoreKey<'_>> = translated_keys
+            .iter()
+            .flatten()
+            .map(StoreKey::borrow)
+            .collect();
+        let mut backend_results = self.backend.has_many(&backend_keys).await?.into_iter();
+        for (translated_key, result) in translated_keys.iter().zip(results.iter_mut()) {
+            *result = match translated_key {
+                Some(_) => backend_results
+                    .next()
+                    .err_tip(|| "backend_results out of sync in TranslationStore")?,
+                None => None,
+            };
+        }
+        Ok(())
+    }
+
+    async fn update(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        reader: DropCloserReadHalf,
+        size_info: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        let StoreKey::Digest(digest) = key else {
+            return self.backend.update(key, reader, size_info).await;
+        };
+        let func = Self::active_hasher_func()?;
+        if func == self.backend_func {
+            return self.update_backend_func(digest, reader, size_info).await;
+        }
+        self.update_translated_func(func, digest, reader).await
+    }
+
+    async fn get_part(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        writer: &mut DropCloserWriteHalf,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<(), Error> {
+        let func = Self::active_hasher_func()?;
+        let Some(backend_key) = self.translate_key(key.borrow(), func).await? else {
+            return Err(make_err!(
+                Code::NotFound,
+                "{key:?} has no {} digest in TranslationStore",
+                self.backend_func
+            ));
+        };
+        self.backend
+            .get_part(backend_key, writer, offset, length)
+            .await
+    }
+
+    fn inner_store(&self, _digest: Option<StoreKey>) -> &'_ dyn StoreDriver {
+        self
+    }
+
+    fn as_any<'a>(&'a self) -> &'a (dyn std::any::Any + Sync + Send + 'static) {
+        self
+    }
+
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn std::any::Any + Sync + Send + 'static> {
+        self
+    }
+}
+
+default_health_status_indicator!(TranslationStore);
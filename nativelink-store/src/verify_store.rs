@@ -16,20 +16,21 @@ use std::pin::Pin;
 use std::sync::Arc;
 
 use async_trait::async_trait;
-use nativelink_config::stores::VerifySpec;
+use nativelink_config::stores::{VerifyFailureAction, VerifySpec};
 use nativelink_error::{make_input_err, Error, ResultExt};
 use nativelink_metric::MetricsComponent;
 use nativelink_util::buf_channel::{
-    make_buf_channel_pair, DropCloserReadHalf, DropCloserWriteHalf,
+    make_buf_channel_pair, DropCloserReadHalf, DropCloserWriteHalf, HashingWriteHalf,
 };
-use nativelink_util::common::PackedHash;
+use nativelink_util::common::{DigestInfo, PackedHash};
 use nativelink_util::digest_hasher::{
-    default_digest_hasher_func, DigestHasher, ACTIVE_HASHER_FUNC,
+    default_digest_hasher_func, DigestHasher, DigestHasherImpl, ACTIVE_HASHER_FUNC,
 };
 use nativelink_util::health_utils::{default_health_status_indicator, HealthStatusIndicator};
 use nativelink_util::metrics_utils::CounterWithTime;
 use nativelink_util::origin_context::ActiveOriginContext;
 use nativelink_util::store_trait::{Store, StoreDriver, StoreKey, StoreLike, UploadSizeInfo};
+use tracing::{event, Level};
 
 #[derive(MetricsComponent)]
 pub struct VerifyStore {
@@ -39,12 +40,18 @@ pub struct VerifyStore {
     verify_size: bool,
     #[metric(help = "If the verification store is verifying the hash of the data")]
     verify_hash: bool,
+    #[metric(help = "If the verification store is verifying the hash of data read from it")]
+    verify_hash_on_read: bool,
+    #[metric(help = "If the verification store only logs failures instead of rejecting data")]
+    log_failures: bool,
 
     // Metrics.
     #[metric(help = "Number of failures the verification store had due to size mismatches")]
     size_verification_failures: CounterWithTime,
     #[metric(help = "Number of failures the verification store had due to hash mismatches")]
     hash_verification_failures: CounterWithTime,
+    #[metric(help = "Number of reads from the inner store that failed verification")]
+    read_verification_failures: CounterWithTime,
 }
 
 impl VerifyStore {
@@ -53,19 +60,45 @@ impl VerifyStore {
             inner_store,
             verify_size: spec.verify_size,
             verify_hash: spec.verify_hash,
+            verify_hash_on_read: spec.verify_hash_on_read,
+            log_failures: spec.on_failure == VerifyFailureAction::log,
             size_verification_failures: CounterWithTime::default(),
             hash_verification_failures: CounterWithTime::default(),
+            read_verification_failures: CounterWithTime::default(),
         })
     }
 
+    /// Records a verification failure. Returns the error if data failing
+    /// verification should be rejected, otherwise logs it.
+    fn on_failure(&self, counter: &CounterWithTime, err: Error) -> Result<(), Error> {
+        counter.inc();
+        if !self.log_failures {
+            return Err(err);
+        }
+        event!(
+            Level::WARN,
+            ?err,
+            "Verification failed in verify store, letting data through"
+        );
+        Ok(())
+    }
+
+    fn hasher(&self) -> Result<DigestHasherImpl, Error> {
+        Ok(ActiveOriginContext::get_value(&ACTIVE_HASHER_FUNC)
+            .err_tip(|| "In verify_store::hasher")?
+            .map_or_else(default_digest_hasher_func, |v| *v)
+            .hasher())
+    }
+
     async fn inner_check_update<D: DigestHasher>(
         &self,
         mut tx: DropCloserWriteHalf,
         mut rx: DropCloserReadHalf,
-        maybe_expected_digest_size: Option<u64>,
+        mut maybe_expected_digest_size: Option<u64>,
         original_hash: &PackedHash,
-        mut maybe_hasher: Option<&mut D>,
+        maybe_hasher: Option<D>,
     ) -> Result<(), Error> {
+        let mut tx = HashingWriteHalf::new(&mut tx, maybe_hasher);
         let mut sum_size: u64 = 0;
         loop {
             let chunk = rx
@@ -78,12 +111,16 @@ impl VerifyStore {
             if let Some(expected_size) = maybe_expected_digest_size {
                 match sum_size.cmp(&expected_size) {
                     std::cmp::Ordering::Greater => {
-                        self.size_verification_failures.inc();
-                        return Err(make_input_err!(
-                            "Expected size {} but already received {} on insert",
-                            expected_size,
-                            sum_size
-                        ));
+                        self.on_failure(
+                            &self.size_verification_failures,
+                            make_input_err!(
+                                "Expected size {} but already received {} on insert",
+                                expected_size,
+                                sum_size
+                            ),
+                        )?;
+                        // Only report the size mismatch once.
+                        maybe_expected_digest_size = None;
                     }
                     std::cmp::Ordering::Equal => {
                         // Ensure our next chunk is the EOF chunk.
@@ -91,11 +128,14 @@ impl VerifyStore {
                         // on next cycle.
                         if let Ok(eof_chunk) = rx.peek().await {
                             if !eof_chunk.is_empty() {
-                                self.size_verification_failures.inc();
-                                return Err(make_input_err!(
-                                    "Expected EOF chunk when exact size was hit on insert in verify store - {}",
-                                    expected_size,
-                                ));
+                                self.on_failure(
+                                    &self.size_verification_failures,
+                                    make_input_err!(
+                                        "Expected EOF chunk when exact size was hit on insert in verify store - {}",
+                                        expected_size,
+                                    ),
+                                )?;
+                                maybe_expected_digest_size = None;
                             }
                         }
                     }
@@ -107,41 +147,86 @@ impl VerifyStore {
             if chunk.is_empty() {
                 if let Some(expected_size) = maybe_expected_digest_size {
                     if sum_size != expected_size {
-                        self.size_verification_failures.inc();
-                        return Err(make_input_err!(
-                            "Expected size {} but got size {} on insert",
-                            expected_size,
-                            sum_size
-                        ));
+                        self.on_failure(
+                            &self.size_verification_failures,
+                            make_input_err!(
+                                "Expected size {} but got size {} on insert",
+                                expected_size,
+                                sum_size
+                            ),
+                        )?;
                     }
                 }
-                if let Some(hasher) = maybe_hasher.as_mut() {
-                    let digest = hasher.finalize_digest();
+                if let Some(digest) = tx.finalize_digest() {
                     let hash_result = digest.packed_hash();
                     if original_hash != hash_result {
-                        self.hash_verification_failures.inc();
-                        return Err(make_input_err!(
-                            "Hashes do not match, got: {original_hash} but digest hash was {hash_result}",
-                        ));
+                        self.on_failure(
+                            &self.hash_verification_failures,
+                            make_input_err!(
+                                "Hashes do not match, got: {original_hash} but digest hash was {hash_result}",
+                            ),
+                        )?;
                     }
                 }
                 tx.send_eof().err_tip(|| "In verify_store::check_update")?;
                 break;
             }
 
-            // This will allows us to hash while sending data to another thread.
-            let write_future = tx.send(chunk.clone());
-
-            if let Some(hasher) = maybe_hasher.as_mut() {
-                hasher.update(chunk.as_ref());
-            }
-
-            write_future
+            tx.send(chunk)
                 .await
                 .err_tip(|| "Failed to write chunk to inner store in verify store")?;
         }
         Ok(())
     }
+
+    /// Forwards the whole object `digest` read from `rx` to `writer` and
+    /// verifies it before sending EOF.
+    async fn inner_check_read(
+        &self,
+        digest: DigestInfo,
+        mut rx: DropCloserReadHalf,
+        writer: &mut DropCloserWriteHalf,
+        hasher: DigestHasherImpl,
+    ) -> Result<(), Error> {
+        let mut writer = HashingWriteHalf::new(writer, Some(hasher));
+        let mut sum_size: u64 = 0;
+        loop {
+            let chunk = rx
+                .recv()
+                .await
+                .err_tip(|| "Failed to read chunk in check_read in verify store")?;
+            if chunk.is_empty() {
+                break; // EOF.
+            }
+            sum_size += chunk.len() as u64;
+            writer
+                .send(chunk)
+                .await
+                .err_tip(|| "Failed to write chunk to reader in verify store")?;
+        }
+        if self.verify_size && sum_size != digest.size_bytes() {
+            self.on_failure(
+                &self.read_verification_failures,
+                make_input_err!(
+                    "Expected size {} but got size {sum_size} on read of {digest} in verify store",
+                    digest.size_bytes(),
+                ),
+            )?;
+        }
+        if let Some(actual_digest) = writer.finalize_digest() {
+            if actual_digest.packed_hash() != digest.packed_hash() {
+                self.on_failure(
+                    &self.read_verification_failures,
+                    make_input_err!(
+                        "Hashes do not match on read, expected: {} but data hash was {} in verify store",
+                        digest.packed_hash(),
+                        actual_digest.packed_hash(),
+                    ),
+                )?;
+            }
+        }
+        writer.send_eof().err_tip(|| "In verify_store::check_read")
+    }
 }
 
 #[async_trait]
@@ -168,22 +253,19 @@ impl StoreDriver for VerifyStore {
         let digest_size = digest.size_bytes();
         if let UploadSizeInfo::ExactSize(expected_size) = size_info {
             if self.verify_size && expected_size != digest_size {
-                self.size_verification_failures.inc();
-                return Err(make_input_err!(
-                    "Expected size to match. Got {} but digest says {} on update",
-                    expected_size,
-                    digest_size
-                ));
+                self.on_failure(
+                    &self.size_verification_failures,
+                    make_input_err!(
+                        "Expected size to match. Got {} but digest says {} on update",
+                        expected_size,
+                        digest_size
+                    ),
+                )?;
             }
         }
 
-        let mut hasher = if self.verify_hash {
-            Some(
-                ActiveOriginContext::get_value(&ACTIVE_HASHER_FUNC)
-                    .err_tip(|| "In verify_store::update")?
-                    .map_or_else(default_digest_hasher_func, |v| *v)
-                    .hasher(),
-            )
+        let hasher = if self.verify_hash {
+            Some(self.hasher().err_tip(|| "In verify_store::update")?)
         } else {
             None
         };
@@ -196,13 +278,8 @@ impl StoreDriver for VerifyStore {
         let (tx, rx) = make_buf_channel_pair();
 
         let update_fut = self.inner_store.update(digest, rx, size_info);
-        let check_fut = self.inner_check_update(
-            tx,
-            reader,
-            maybe_digest_size,
-            digest.packed_hash(),
-            hasher.as_mut(),
-        );
+        let check_fut =
+            self.inner_check_update(tx, reader, maybe_digest_size, digest.packed_hash(), hasher);
 
         let (update_res, check_res) = tokio::join!(update_fut, check_fut);
 
@@ -216,7 +293,26 @@ impl StoreDriver for VerifyStore {
         offset: u64,
         length: Option<u64>,
     ) -> Result<(), Error> {
-        self.inner_store.get_part(key, writer, offset, length).await
+        // Only reads of the whole object can be verified.
+        let digest = match key {
+            StoreKey::Digest(digest)
+                if self.verify_hash_on_read
+                    && offset == 0
+                    && length.is_none_or(|length| length >= digest.size_bytes()) =>
+            {
+                digest
+            }
+            _ => return self.inner_store.get_part(key, writer, offset, length).await,
+        };
+        let hasher = self.hasher().err_tip(|| "In verify_store::get_part")?;
+
+        let (tx, rx) = make_buf_channel_pair();
+        let get_fut = self.inner_store.get_part(digest, tx, 0, length);
+        let check_fut = self.inner_check_read(digest, rx, writer, hasher);
+
+        let (get_res, check_res) = tokio::join!(get_fut, check_fut);
+
+        get_res.merge(check_res)
     }
 
     fn inner_store(&self, _digest: Option<StoreKey>) -> &'_ dyn StoreDriver {
@@ -0,0 +1,99 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use futures::future::join_all;
+use nativelink_config::stores::{AliasSpec, MemorySpec, StoreSpec};
+use nativelink_error::{Code, Error};
+use nativelink_macro::nativelink_test;
+use nativelink_store::alias_store::AliasStore;
+use nativelink_store::memory_store::MemoryStore;
+use nativelink_util::common::DigestInfo;
+use nativelink_util::store_trait::{Store, StoreLike};
+use pretty_assertions::assert_eq;
+
+const ALIAS: &str = "latest-toolchain-manifest";
+const VALID_HASH1: &str = "0123456789abcdef000000000000000000010000000000000123456789abcdef";
+const VALID_HASH2: &str = "0123456789abcdef000000000000000000020000000000000123456789abcdef";
+const VALID_HASH3: &str = "0123456789abcdef000000000000000000030000000000000123456789abcdef";
+
+fn make_store() -> Arc<AliasStore> {
+    AliasStore::new(
+        &AliasSpec {
+            backend: StoreSpec::memory(MemorySpec::default()), // Note: Not used.
+        },
+        Store::new(MemoryStore::new(&MemorySpec::default())),
+    )
+}
+
+#[nativelink_test]
+async fn resolves_aliases_written_through_store_api() -> Result<(), Error> {
+    let store = make_store();
+    let digest = DigestInfo::try_new(VALID_HASH1, 100)?;
+    assert_eq!(store.resolve(ALIAS).await, Ok(None));
+
+    store
+        .update_oneshot(ALIAS, Bytes::from(format!("{digest}")))
+        .await?;
+    assert_eq!(store.resolve(ALIAS).await, Ok(Some(digest)));
+    assert_eq!(
+        store.get_part_unchunked(ALIAS, 0, None).await,
+        Ok(Bytes::from(format!("{digest}")))
+    );
+
+    let err = store
+        .update_oneshot(ALIAS, Bytes::from_static(b"not a digest"))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code, Code::InvalidArgument);
+    assert_eq!(store.resolve(ALIAS).await, Ok(Some(digest)));
+    Ok(())
+}
+
+#[nativelink_test]
+async fn compare_and_swap_only_moves_expected_alias() -> Result<(), Error> {
+    let store = make_store();
+    let digest1 = DigestInfo::try_new(VALID_HASH1, 100)?;
+    let digest2 = DigestInfo::try_new(VALID_HASH2, 200)?;
+    let digest3 = DigestInfo::try_new(VALID_HASH3, 300)?;
+
+    assert_eq!(store.compare_and_swap(ALIAS, None, digest1).await, Ok(true));
+    assert_eq!(
+        store.compare_and_swap(ALIAS, None, digest2).await,
+        Ok(false)
+    );
+    assert_eq!(
+        store.compare_and_swap(ALIAS, Some(digest2), digest3).await,
+        Ok(false)
+    );
+    assert_eq!(store.resolve(ALIAS).await, Ok(Some(digest1)));
+    assert_eq!(
+        store.compare_and_swap(ALIAS, Some(digest1), digest2).await,
+        Ok(true)
+    );
+    assert_eq!(store.resolve(ALIAS).await, Ok(Some(digest2)));
+
+    // Only one of several writers moving the alias from the same digest
+    // succeeds.
+    let results =
+        join_all([digest1, digest3].map(|new| store.compare_and_swap(ALIAS, Some(digest2), new)))
+            .await;
+    assert_eq!(
+        results.iter().filter(|result| result == &&Ok(true)).count(),
+        1
+    );
+    Ok(())
+}
@@ -0,0 +1,218 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::env;
+
+use bytes::Bytes;
+use nativelink_config::stores::{ArchiveFormat, ArchiveSpec};
+use nativelink_error::{Code, Error};
+use nativelink_macro::nativelink_test;
+use nativelink_store::archive_store::ArchiveStore;
+use nativelink_util::common::DigestInfo;
+use nativelink_util::store_trait::StoreLike;
+use pretty_assertions::assert_eq;
+use rand::{thread_rng, Rng};
+
+const VALID_HASH1: &str = "0123456789abcdef000000000000000000010000000000000123456789abcdef";
+const VALID_HASH2: &str = "0123456789abcdef000000000000000000020000000000000123456789abcdef";
+const VALUE1: &str = "hello world";
+const VALUE2: &str = "string keyed value";
+
+fn write_temp_file(name: &str, data: &[u8]) -> Result<String, Error> {
+    let dir = format!(
+        "{}/{}",
+        env::var("TEST_TMPDIR").unwrap_or(env::temp_dir().to_str().unwrap().to_string()),
+        thread_rng().gen::<u64>(),
+    );
+    std::fs::create_dir_all(&dir)?;
+    let path = format!("{dir}/{name}");
+    std::fs::write(&path, data)?;
+    Ok(path)
+}
+
+fn tar_header(name: &str, size: usize, typeflag: u8) -> Vec<u8> {
+    let mut header = vec![0; 512];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[124..136].copy_from_slice(format!("{size:011o}\0").as_bytes());
+    header[156] = typeflag;
+    header[257..265].copy_from_slice(b"ustar\x0000");
+    header[148..156].copy_from_slice(b"        ");
+    let checksum: u32 = header.iter().map(|b| u32::from(*b)).sum();
+    header[148..156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+    header
+}
+
+fn tar_entry(archive: &mut Vec<u8>, name: &str, data: &[u8], typeflag: u8) {
+    archive.extend(tar_header(name, data.len(), typeflag));
+    archive.extend(data);
+    archive.resize(archive.len().div_ceil(512) * 512, 0);
+}
+
+/// Builds a zip archive whose entries are stored without compression.
+fn make_zip(entries: &[(&str, &[u8])], method: u16) -> Vec<u8> {
+    let mut archive = Vec::new();
+    let mut central_dir = Vec::new();
+    for (name, data) in entries {
+        let local_offset = archive.len() as u32;
+        let mut fields = Vec::new();
+        fields.extend(20u16.to_le_bytes()); // Version needed.
+        fields.extend(0u16.to_le_bytes()); // Flags.
+        fields.extend(method.to_le_bytes());
+        fields.extend([0; 8]); // Time, date and crc32, not checked.
+        fields.extend((data.len() as u32).to_le_bytes());
+        fields.extend((data.len() as u32).to_le_bytes());
+        fields.extend((name.len() as u16).to_le_bytes());
+        fields.extend(0u16.to_le_bytes()); // Extra field length.
+
+        archive.extend(0x0403_4b50u32.to_le_bytes());
+        archive.extend(&fields);
+        archive.extend(name.as_bytes());
+        archive.extend(*data);
+
+        central_dir.extend(0x0201_4b50u32.to_le_bytes());
+        central_dir.extend(20u16.to_le_bytes()); // Version made by.
+        central_dir.extend(&fields);
+        central_dir.extend([0; 10]); // Comment length, disk and attributes.
+        central_dir.extend(local_offset.to_le_bytes());
+        central_dir.extend(name.as_bytes());
+    }
+    let central_dir_offset = archive.len() as u32;
+    archive.extend(&central_dir);
+    archive.extend(0x0605_4b50u32.to_le_bytes());
+    archive.extend([0; 4]); // Disk numbers.
+    archive.extend((entries.len() as u16).to_le_bytes());
+    archive.extend((entries.len() as u16).to_le_bytes());
+    archive.extend((central_dir.len() as u32).to_le_bytes());
+    archive.extend(central_dir_offset.to_le_bytes());
+    archive.extend(0u16.to_le_bytes()); // Comment length.
+    archive
+}
+
+async fn assert_serves_objects(store: &ArchiveStore) -> Result<(), Error> {
+    let digest1 = DigestInfo::try_new(VALID_HASH1, VALUE1.len())?;
+    let digest2 = DigestInfo::try_new(VALID_HASH2, 3)?;
+
+    assert_eq!(store.has(digest1).await, Ok(Some(VALUE1.len() as u64)));
+    assert_eq!(store.has(digest2).await, Ok(None));
+    assert_eq!(
+        store.get_part_unchunked(digest1, 0, None).await,
+        Ok(Bytes::from_static(VALUE1.as_bytes()))
+    );
+    assert_eq!(
+        store.get_part_unchunked(digest1, 6, Some(3)).await,
+        Ok(Bytes::from_static(b"wor"))
+    );
+    assert_eq!(
+        store.get_part_unchunked("some_key", 0, None).await,
+        Ok(Bytes::from_static(VALUE2.as_bytes()))
+    );
+    let err = store
+        .get_part_unchunked(digest2, 0, None)
+        .await
+        .unwrap_err();
+    assert_eq!(err.code, Code::NotFound);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn serves_objects_from_tar() -> Result<(), Error> {
+    let long_key = "k".repeat(200);
+    let mut archive = Vec::new();
+    tar_entry(&mut archive, "./d/", b"", b'5');
+    tar_entry(
+        &mut archive,
+        &format!("./d/{VALID_HASH1}-{}", VALUE1.len()),
+        VALUE1.as_bytes(),
+        b'0',
+    );
+    tar_entry(&mut archive, "./s/some_key", VALUE2.as_bytes(), b'0');
+    // Names over 100 bytes are stored in a GNU long name entry.
+    tar_entry(
+        &mut archive,
+        "././@LongLink",
+        format!("./s/{long_key}\0").as_bytes(),
+        b'L',
+    );
+    tar_entry(&mut archive, "./s/truncated_name", b"long", b'0');
+    tar_entry(&mut archive, "./unrelated", b"ignored", b'0');
+    archive.extend([0; 1024]);
+
+    let store = ArchiveStore::new(&ArchiveSpec {
+        path: write_temp_file("cas.tar", &archive)?,
+        format: ArchiveFormat::tar,
+    })
+    .await?;
+
+    assert_serves_objects(&store).await?;
+    assert_eq!(
+        store.get_part_unchunked(long_key.as_str(), 0, None).await,
+        Ok(Bytes::from_static(b"long"))
+    );
+    assert_eq!(store.has("truncated_name").await, Ok(None));
+    Ok(())
+}
+
+#[nativelink_test]
+async fn serves_objects_from_zip() -> Result<(), Error> {
+    let digest_path = format!("d/{VALID_HASH1}-{}", VALUE1.len());
+    let archive = make_zip(
+        &[
+            ("d/", b""),
+            (&digest_path, VALUE1.as_bytes()),
+            ("s/some_key", VALUE2.as_bytes()),
+        ],
+        0,
+    );
+
+    let store = ArchiveStore::new(&ArchiveSpec {
+        path: write_temp_file("cas.zip", &archive)?,
+        format: ArchiveFormat::zip,
+    })
+    .await?;
+
+    assert_serves_objects(&store).await
+}
+
+#[nativelink_test]
+async fn rejects_compressed_zip_entries() -> Result<(), Error> {
+    // Method 8 is deflate.
+    let archive = make_zip(&[("s/some_key", VALUE2.as_bytes())], 8);
+
+    let result = ArchiveStore::new(&ArchiveSpec {
+        path: write_temp_file("cas.zip", &archive)?,
+        format: ArchiveFormat::zip,
+    })
+    .await;
+    assert!(result.is_err());
+    Ok(())
+}
+
+#[nativelink_test]
+async fn updates_are_rejected() -> Result<(), Error> {
+    let mut archive = Vec::new();
+    archive.extend([0; 1024]);
+    let store = ArchiveStore::new(&ArchiveSpec {
+        path: write_temp_file("cas.tar", &archive)?,
+        format: ArchiveFormat::tar,
+    })
+    .await?;
+
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE1.len())?;
+    let err = store
+        .update_oneshot(digest, VALUE1.into())
+        .await
+        .unwrap_err();
+    assert_eq!(err.code, Code::PermissionDenied);
+    Ok(())
+}
@@ -21,7 +21,7 @@ use nativelink_proto::build::bazel::remote::execution::v2::{
     ActionResult as ProtoActionResult, Directory, DirectoryNode, FileNode, OutputDirectory,
     OutputFile, Tree,
 };
-use nativelink_store::ac_utils::serialize_and_upload_message;
+use nativelink_store::ac_utils::{get_and_decode_digest, serialize_and_upload_message};
 use nativelink_store::completeness_checking_store::CompletenessCheckingStore;
 use nativelink_store::memory_store::MemoryStore;
 use nativelink_util::common::DigestInfo;
@@ -176,6 +176,34 @@ async fn verify_has_function_call_checks_cas() -> Result<(), Error> {
         );
     }
 
+    {
+        // Completeness check should fail when the tree of an output
+        // directory is missing.
+
+        let (ac_store, cas_store, action_result_digest) = setup().await?;
+
+        let action_result = get_and_decode_digest::<ProtoActionResult>(
+            &Store::new(ac_store.clone()),
+            action_result_digest.into(),
+        )
+        .await?;
+        let tree_digest = DigestInfo::try_from(
+            action_result.output_directories[0]
+                .tree_digest
+                .clone()
+                .unwrap(),
+        )?;
+        cas_store.remove_entry(tree_digest.into()).await;
+        let res = ac_store
+            .has_many(&[action_result_digest.into()])
+            .await
+            .unwrap();
+        assert!(
+            res[0].is_none(),
+            "Results should be none with missing output directory tree."
+        );
+    }
+
     {
         // Completeness check should fail when stdout digest is missing.
 
@@ -20,12 +20,15 @@ use std::sync::Arc;
 
 use bincode::{DefaultOptions, Options};
 use bytes::Bytes;
-use nativelink_config::stores::{CompressionSpec, MemorySpec, StoreSpec};
+use nativelink_config::stores::{
+    CompressionAlgorithm, CompressionSpec, MemorySpec, StoreSpec, ZstdConfig, ZstdDictionaryConfig,
+};
 use nativelink_error::{make_err, Code, Error, ResultExt};
 use nativelink_macro::nativelink_test;
 use nativelink_store::compression_store::{
     CompressionStore, Footer, Lz4Config, SliceIndex, CURRENT_STREAM_FORMAT_VERSION,
-    DEFAULT_BLOCK_SIZE, FOOTER_FRAME_TYPE,
+    DEFAULT_BLOCK_SIZE, FOOTER_FRAME_TYPE, ZSTD_DICTIONARY_POINTER_DIGEST, ZSTD_HEADER_SIZE,
+    ZSTD_STREAM_FORMAT,
 };
 use nativelink_store::memory_store::MemoryStore;
 use nativelink_util::buf_channel::make_buf_channel_pair;
@@ -514,3 +517,171 @@ async fn get_part_is_zero_digest() -> Result<(), Error> {
 
     Ok(())
 }
+
+#[nativelink_test]
+async fn zstd_partial_reads_test() -> Result<(), Error> {
+    let mut rng = SmallRng::seed_from_u64(1);
+    // Compressible data spanning several zstd output buffers.
+    let raw_data: Vec<u8> = (0..3 * MEGABYTE_SZ)
+        .map(|i| {
+            if i % 7 == 0 {
+                rng.gen()
+            } else {
+                (i % 251) as u8
+            }
+        })
+        .collect();
+
+    let inner_store = MemoryStore::new(&MemorySpec::default());
+    let store = CompressionStore::new(
+        &CompressionSpec {
+            backend: StoreSpec::memory(MemorySpec::default()),
+            compression_algorithm: CompressionAlgorithm::zstd(ZstdConfig::default()),
+        },
+        Store::new(inner_store.clone()),
+    )
+    .err_tip(|| "Failed to create compression store")?;
+
+    let digest = DigestInfo::try_new(VALID_HASH, DUMMY_DATA_SIZE).unwrap();
+    store
+        .update_oneshot(digest, raw_data.clone().into())
+        .await?;
+
+    let compressed_data = inner_store.get_part_unchunked(digest, 0, None).await?;
+    assert_eq!(compressed_data[0], ZSTD_STREAM_FORMAT);
+    assert!(compressed_data.len() < raw_data.len());
+
+    assert_eq!(
+        store.get_part_unchunked(digest, 0, None).await?,
+        Bytes::from(raw_data.clone()),
+        "Expected full read to match"
+    );
+    for (offset, length) in [(0, Some(10)), (70_000, Some(200_000)), (MEGABYTE_SZ, None)] {
+        let end = length.map_or(raw_data.len(), |length| offset + length);
+        assert_eq!(
+            store
+                .get_part_unchunked(digest, offset as u64, length.map(|l| l as u64))
+                .await?,
+            Bytes::copy_from_slice(&raw_data[offset..end]),
+            "Expected partial read at {offset} to match"
+        );
+    }
+    Ok(())
+}
+
+#[nativelink_test]
+async fn zstd_store_reads_lz4_objects_test() -> Result<(), Error> {
+    const RAW_INPUT: &str = "written before switching to zstd";
+
+    let inner_store = Store::new(MemoryStore::new(&MemorySpec::default()));
+    let lz4_store = CompressionStore::new(
+        &CompressionSpec {
+            backend: StoreSpec::memory(MemorySpec::default()),
+            compression_algorithm: CompressionAlgorithm::lz4(
+                nativelink_config::stores::Lz4Config::default(),
+            ),
+        },
+        inner_store.clone(),
+    )
+    .err_tip(|| "Failed to create lz4 compression store")?;
+    let zstd_store = CompressionStore::new(
+        &CompressionSpec {
+            backend: StoreSpec::memory(MemorySpec::default()),
+            compression_algorithm: CompressionAlgorithm::zstd(ZstdConfig::default()),
+        },
+        inner_store,
+    )
+    .err_tip(|| "Failed to create zstd compression store")?;
+
+    let digest = DigestInfo::try_new(VALID_HASH, DUMMY_DATA_SIZE).unwrap();
+    lz4_store.update_oneshot(digest, RAW_INPUT.into()).await?;
+
+    assert_eq!(
+        zstd_store.get_part_unchunked(digest, 0, None).await?,
+        Bytes::from_static(RAW_INPUT.as_bytes()),
+        "Expected zstd store to read lz4 data"
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn zstd_dictionary_training_test() -> Result<(), Error> {
+    const SAMPLE_COUNT: u32 = 100;
+
+    let mut rng = SmallRng::seed_from_u64(1);
+    // Small objects that share most of their content, like protos of the
+    // same message type.
+    let mut make_blob = || {
+        let mut blob = String::new();
+        for i in 0..20 {
+            blob.push_str(&format!(
+                "{{\"name\": \"field_{i}\", \"kind\": \"ACTION_RESULT\", \"value\": {}}}\n",
+                rng.gen::<u32>()
+            ));
+        }
+        Bytes::from(blob)
+    };
+
+    let inner_store = MemoryStore::new(&MemorySpec::default());
+    let store = CompressionStore::new(
+        &CompressionSpec {
+            backend: StoreSpec::memory(MemorySpec::default()),
+            compression_algorithm: CompressionAlgorithm::zstd(ZstdConfig {
+                level: 3,
+                dictionary: Some(ZstdDictionaryConfig {
+                    sample_count: SAMPLE_COUNT,
+                    max_dictionary_size: 8 * 1024,
+                    ..Default::default()
+                }),
+            }),
+        },
+        Store::new(inner_store.clone()),
+    )
+    .err_tip(|| "Failed to create compression store")?;
+
+    // Keep uploading small objects until they are compressed with a
+    // dictionary trained on the earlier ones.
+    let mut uploads = 0;
+    let (digest, blob) = loop {
+        uploads += 1;
+        assert!(uploads < 10_000, "Dictionary was never trained");
+        let blob = make_blob();
+        let digest = DigestInfo::new(Sha256::digest(&blob).into(), blob.len() as u64);
+        store.update_oneshot(digest, blob.clone()).await?;
+        let compressed_data = inner_store.get_part_unchunked(digest, 0, None).await?;
+        if compressed_data[1..ZSTD_HEADER_SIZE].iter().any(|b| *b != 0) {
+            break (digest, blob);
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+    };
+    assert!(
+        uploads > SAMPLE_COUNT,
+        "Expected dictionary to be trained on {SAMPLE_COUNT} samples"
+    );
+    assert!(inner_store
+        .has(ZSTD_DICTIONARY_POINTER_DIGEST)
+        .await?
+        .is_some());
+
+    let with_dictionary = inner_store.get_part_unchunked(digest, 0, None).await?;
+    let without_dictionary = zstd::bulk::compress(&blob, 3)?;
+    assert!(
+        with_dictionary.len() - ZSTD_HEADER_SIZE < without_dictionary.len(),
+        "Expected dictionary to improve compression, {} >= {}",
+        with_dictionary.len() - ZSTD_HEADER_SIZE,
+        without_dictionary.len()
+    );
+    assert_eq!(store.get_part_unchunked(digest, 0, None).await?, blob);
+
+    // A new store loads the dictionary from the inner store to read objects.
+    let new_store = CompressionStore::new(
+        &CompressionSpec {
+            backend: StoreSpec::memory(MemorySpec::default()),
+            compression_algorithm: CompressionAlgorithm::zstd(ZstdConfig::default()),
+        },
+        Store::new(inner_store),
+    )
+    .err_tip(|| "Failed to create compression store")?;
+    assert_eq!(new_store.get_part_unchunked(digest, 0, None).await?, blob);
+    Ok(())
+}
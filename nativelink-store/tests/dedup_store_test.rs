@@ -71,6 +71,49 @@ async fn simple_round_trip_test() -> Result<(), Error> {
     Ok(())
 }
 
+#[nativelink_test]
+async fn similar_blobs_share_chunks_test() -> Result<(), Error> {
+    let content_store = MemoryStore::new(&MemorySpec::default());
+    let store = DedupStore::new(
+        &make_default_config(),
+        Store::new(MemoryStore::new(&MemorySpec::default())), // Index store.
+        Store::new(content_store.clone()),
+    )?;
+
+    let original_data = make_random_data(MEGABYTE_SZ);
+    store
+        .update_oneshot(
+            DigestInfo::try_new(VALID_HASH1, MEGABYTE_SZ)?,
+            original_data.clone().into(),
+        )
+        .await
+        .err_tip(|| "Failed to write original data to dedup store")?;
+    let original_chunk_count = content_store.len_for_test().await;
+
+    // Insert a few bytes in the middle, like a patched binary would.
+    let mut similar_data = original_data;
+    similar_data.splice(MEGABYTE_SZ / 2..MEGABYTE_SZ / 2, *b"patched");
+    let similar_digest = DigestInfo::try_new(VALID_HASH2, similar_data.len())?;
+    store
+        .update_oneshot(similar_digest, similar_data.clone().into())
+        .await
+        .err_tip(|| "Failed to write similar data to dedup store")?;
+
+    // Only the chunk around the inserted bytes is new, chunk boundaries
+    // resynchronize right after it.
+    assert_eq!(
+        content_store.len_for_test().await,
+        original_chunk_count + 1,
+        "Expected a single new chunk"
+    );
+    assert_eq!(
+        store.get_part_unchunked(similar_digest, 0, None).await?,
+        similar_data,
+        "Expected round trip data to match"
+    );
+    Ok(())
+}
+
 #[nativelink_test]
 async fn check_missing_last_chunk_test() -> Result<(), Error> {
     // This is the hash & size of the last chunk item in the content_store.
@@ -0,0 +1,201 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use bytes::{Bytes, BytesMut};
+use nativelink_config::stores::{EncryptionSpec, MemorySpec, StoreSpec};
+use nativelink_error::{Code, Error};
+use nativelink_macro::nativelink_test;
+use nativelink_store::encryption_store::EncryptionStore;
+use nativelink_store::memory_store::MemoryStore;
+use nativelink_util::common::DigestInfo;
+use nativelink_util::store_trait::{Store, StoreLike};
+use pretty_assertions::assert_eq;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+
+const VALID_HASH1: &str = "0123456789abcdef000000000000000000010000000000000123456789abcdef";
+const VALID_HASH2: &str = "0123456789abcdef000000000000000000020000000000000123456789abcdef";
+const MASTER_KEY1: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+const MASTER_KEY2: &str = "f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff000102030405060708090a0b0c0d0e0f";
+
+fn make_store(
+    inner_store: &Store,
+    master_key: &str,
+    previous_master_keys: &[&str],
+) -> Result<Arc<EncryptionStore>, Error> {
+    EncryptionStore::new(
+        &EncryptionSpec {
+            backend: StoreSpec::memory(MemorySpec::default()), // Note: Not used.
+            master_key: master_key.to_string(),
+            previous_master_keys: previous_master_keys
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+        },
+        inner_store.clone(),
+    )
+}
+
+fn make_random_data(size: usize) -> Vec<u8> {
+    let mut value = vec![0u8; size];
+    let mut rng = SmallRng::seed_from_u64(1);
+    rng.fill(&mut value[..]);
+    value
+}
+
+#[nativelink_test]
+async fn round_trip_and_partial_reads() -> Result<(), Error> {
+    const DATA_SIZE: usize = 200 * 1024;
+    let inner_store = Store::new(MemoryStore::new(&MemorySpec::default()));
+    let store = make_store(&inner_store, MASTER_KEY1, &[])?;
+    let data = make_random_data(DATA_SIZE);
+    let digest = DigestInfo::try_new(VALID_HASH1, DATA_SIZE)?;
+
+    store.update_oneshot(digest, data.clone().into()).await?;
+    assert_eq!(store.has(digest).await, Ok(Some(DATA_SIZE as u64)));
+
+    let stored = inner_store.get_part_unchunked(digest, 0, None).await?;
+    assert!(stored.len() > DATA_SIZE, "Expected encryption overhead");
+    assert!(
+        !stored.windows(64).any(|window| window == &data[..64]),
+        "Expected plaintext to not be stored"
+    );
+
+    assert_eq!(
+        store.get_part_unchunked(digest, 0, None).await,
+        Ok(Bytes::from(data.clone()))
+    );
+    // Starts in a later chunk and spans two chunk boundaries.
+    assert_eq!(
+        store
+            .get_part_unchunked(digest, 70_000, Some(100_000))
+            .await,
+        Ok(Bytes::copy_from_slice(&data[70_000..170_000]))
+    );
+    // Ends exactly at the end of a chunk.
+    assert_eq!(
+        store.get_part_unchunked(digest, 10, Some(65_526)).await,
+        Ok(Bytes::copy_from_slice(&data[10..65_536]))
+    );
+    assert_eq!(
+        store.get_part_unchunked(digest, 199_000, None).await,
+        Ok(Bytes::copy_from_slice(&data[199_000..]))
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn round_trip_empty_and_chunk_sized_objects() -> Result<(), Error> {
+    let inner_store = Store::new(MemoryStore::new(&MemorySpec::default()));
+    let store = make_store(&inner_store, MASTER_KEY1, &[])?;
+
+    for size in [0, 64 * 1024, 128 * 1024] {
+        let data = make_random_data(size);
+        let digest = DigestInfo::try_new(VALID_HASH1, size)?;
+        store.update_oneshot(digest, data.clone().into()).await?;
+        assert_eq!(store.has(digest).await, Ok(Some(size as u64)));
+        assert_eq!(
+            store.get_part_unchunked(digest, 0, None).await,
+            Ok(Bytes::from(data))
+        );
+        // Empty reads at the start and at the end of the object.
+        assert_eq!(
+            store.get_part_unchunked(digest, 0, Some(0)).await,
+            Ok(Bytes::new())
+        );
+        assert_eq!(
+            store.get_part_unchunked(digest, size as u64, None).await,
+            Ok(Bytes::new())
+        );
+        assert_eq!(
+            store.get_part_unchunked(digest, size as u64, Some(0)).await,
+            Ok(Bytes::new())
+        );
+    }
+    Ok(())
+}
+
+#[nativelink_test]
+async fn tampered_objects_fail_to_decrypt() -> Result<(), Error> {
+    const DATA_SIZE: usize = 100 * 1024;
+    let inner_store = Store::new(MemoryStore::new(&MemorySpec::default()));
+    let store = make_store(&inner_store, MASTER_KEY1, &[])?;
+    let digest1 = DigestInfo::try_new(VALID_HASH1, DATA_SIZE)?;
+    let digest2 = DigestInfo::try_new(VALID_HASH2, DATA_SIZE)?;
+    store
+        .update_oneshot(digest1, make_random_data(DATA_SIZE).into())
+        .await?;
+    let stored = inner_store.get_part_unchunked(digest1, 0, None).await?;
+
+    // Flipped bit in the last chunk.
+    let mut flipped = BytesMut::from(&stored[..]);
+    let last = flipped.len() - 1;
+    flipped[last] ^= 1;
+    inner_store
+        .update_oneshot(digest1, flipped.freeze())
+        .await?;
+    let err = store
+        .get_part_unchunked(digest1, 0, None)
+        .await
+        .unwrap_err();
+    assert_eq!(err.code, Code::DataLoss);
+
+    // Object cut at the end of its first chunk.
+    let truncated = stored.slice(..stored.len() - (DATA_SIZE - 64 * 1024) - 16);
+    inner_store.update_oneshot(digest1, truncated).await?;
+    let err = store
+        .get_part_unchunked(digest1, 0, None)
+        .await
+        .unwrap_err();
+    assert_eq!(err.code, Code::DataLoss);
+    // A read starting where the object was cut.
+    let err = store
+        .get_part_unchunked(digest1, 64 * 1024, None)
+        .await
+        .unwrap_err();
+    assert_eq!(err.code, Code::DataLoss);
+
+    // Object copied to another key in the inner store.
+    inner_store.update_oneshot(digest2, stored).await?;
+    let err = store
+        .get_part_unchunked(digest2, 0, None)
+        .await
+        .unwrap_err();
+    assert_eq!(err.code, Code::DataLoss);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn previous_master_keys_can_read() -> Result<(), Error> {
+    const VALUE: &str = "encrypted";
+    let inner_store = Store::new(MemoryStore::new(&MemorySpec::default()));
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE.len())?;
+    make_store(&inner_store, MASTER_KEY1, &[])?
+        .update_oneshot(digest, VALUE.into())
+        .await?;
+
+    let rotated_store = make_store(&inner_store, MASTER_KEY2, &[MASTER_KEY1])?;
+    assert_eq!(
+        rotated_store.get_part_unchunked(digest, 0, None).await,
+        Ok(Bytes::from_static(VALUE.as_bytes()))
+    );
+
+    let new_store = make_store(&inner_store, MASTER_KEY2, &[])?;
+    assert!(new_store.get_part_unchunked(digest, 0, None).await.is_err());
+
+    assert!(make_store(&inner_store, "not a key", &[]).is_err());
+    Ok(())
+}
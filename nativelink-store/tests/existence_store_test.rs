@@ -35,6 +35,8 @@ async fn simple_exist_cache_test() -> Result<(), Error> {
     let spec = ExistenceCacheSpec {
         backend: StoreSpec::noop(NoopSpec::default()), // Note: Not used.
         eviction_policy: Option::default(),
+        negative_cache_seconds: 0,
+        negative_cache_max_count: 0,
     };
     let inner_store = Store::new(MemoryStore::new(&MemorySpec::default()));
     let store = ExistenceCacheStore::new(&spec, inner_store.clone());
@@ -73,6 +75,8 @@ async fn update_flags_existance_cache_test() -> Result<(), Error> {
     let spec = ExistenceCacheSpec {
         backend: StoreSpec::noop(NoopSpec::default()),
         eviction_policy: Option::default(),
+        negative_cache_seconds: 0,
+        negative_cache_max_count: 0,
     };
     let inner_store = Store::new(MemoryStore::new(&MemorySpec::default()));
     let store = ExistenceCacheStore::new(&spec, inner_store.clone());
@@ -96,6 +100,8 @@ async fn get_part_caches_if_exact_size_set() -> Result<(), Error> {
     let spec = ExistenceCacheSpec {
         backend: StoreSpec::noop(NoopSpec::default()),
         eviction_policy: Option::default(),
+        negative_cache_seconds: 0,
+        negative_cache_max_count: 0,
     };
     let inner_store = Store::new(MemoryStore::new(&MemorySpec::default()));
     let digest = DigestInfo::try_new(VALID_HASH1, 3).unwrap();
@@ -134,6 +140,8 @@ async fn ensure_has_requests_eventually_do_let_evictions_happen() -> Result<(),
                 max_seconds: 10,
                 ..Default::default()
             }),
+            negative_cache_seconds: 0,
+            negative_cache_max_count: 0,
         },
         Store::new(inner_store.clone()),
         MockInstantWrapped::default(),
@@ -160,3 +168,63 @@ async fn ensure_has_requests_eventually_do_let_evictions_happen() -> Result<(),
 
     Ok(())
 }
+
+#[nativelink_test]
+async fn missing_objects_are_cached_until_expired() -> Result<(), Error> {
+    const VALUE: &str = "123";
+    let inner_store = MemoryStore::new(&MemorySpec::default());
+    let store = ExistenceCacheStore::new_with_time(
+        &ExistenceCacheSpec {
+            backend: StoreSpec::noop(NoopSpec::default()),
+            eviction_policy: Option::default(),
+            negative_cache_seconds: 5,
+            negative_cache_max_count: 0,
+        },
+        Store::new(inner_store.clone()),
+        MockInstantWrapped::default(),
+    );
+    let digest = DigestInfo::try_new(VALID_HASH1, 3).unwrap();
+
+    assert_eq!(store.has(digest).await, Ok(None));
+    assert!(
+        store.missing_in_cache(&digest).await,
+        "Expected digest to be cached as missing"
+    );
+
+    // Uploads that bypass this store are not seen until the entry expires.
+    inner_store
+        .update_oneshot(digest, VALUE.into())
+        .await
+        .err_tip(|| "Failed to update store")?;
+    assert_eq!(store.has(digest).await, Ok(None));
+
+    MockClock::advance(Duration::from_secs(6));
+    assert_eq!(store.has(digest).await, Ok(Some(VALUE.len() as u64)));
+    Ok(())
+}
+
+#[nativelink_test]
+async fn update_forgets_missing_object() -> Result<(), Error> {
+    const VALUE: &str = "123";
+    let spec = ExistenceCacheSpec {
+        backend: StoreSpec::noop(NoopSpec::default()),
+        eviction_policy: Option::default(),
+        negative_cache_seconds: 60,
+        negative_cache_max_count: 0,
+    };
+    let inner_store = Store::new(MemoryStore::new(&MemorySpec::default()));
+    let store = ExistenceCacheStore::new(&spec, inner_store);
+    let digest = DigestInfo::try_new(VALID_HASH1, 3).unwrap();
+
+    assert_eq!(store.has(digest).await, Ok(None));
+    store
+        .update_oneshot(digest, VALUE.into())
+        .await
+        .err_tip(|| "Failed to update store")?;
+    assert!(
+        !store.missing_in_cache(&digest).await,
+        "Expected digest to not be cached as missing"
+    );
+    assert_eq!(store.has(digest).await, Ok(Some(VALUE.len() as u64)));
+    Ok(())
+}
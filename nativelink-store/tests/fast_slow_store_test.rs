@@ -12,17 +12,22 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::env;
 use std::pin::Pin;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 
 use async_trait::async_trait;
 use bytes::Bytes;
-use nativelink_config::stores::{FastSlowSpec, MemorySpec, NoopSpec, StoreSpec};
+use nativelink_config::stores::{
+    EvictionPolicy, FastSlowPopulateMode, FastSlowPopulationSpec, FastSlowSpec, FilesystemSpec,
+    MemorySpec, NoopSpec, PopularitySpec, StoreSpec,
+};
 use nativelink_error::{make_err, Code, Error, ResultExt};
 use nativelink_macro::nativelink_test;
 use nativelink_metric::MetricsComponent;
 use nativelink_store::fast_slow_store::FastSlowStore;
+use nativelink_store::filesystem_store::FilesystemStore;
 use nativelink_store::memory_store::MemoryStore;
 use nativelink_store::noop_store::NoopStore;
 use nativelink_util::buf_channel::make_buf_channel_pair;
@@ -31,21 +36,27 @@ use nativelink_util::health_utils::{default_health_status_indicator, HealthStatu
 use nativelink_util::store_trait::{Store, StoreDriver, StoreKey, StoreLike};
 use pretty_assertions::assert_eq;
 use rand::rngs::SmallRng;
-use rand::{Rng, SeedableRng};
+use rand::{thread_rng, Rng, SeedableRng};
 
 const MEGABYTE_SZ: usize = 1024 * 1024;
 
 fn make_stores() -> (Store, Store, Store) {
     let fast_store = Store::new(MemoryStore::new(&MemorySpec::default()));
     let slow_store = Store::new(MemoryStore::new(&MemorySpec::default()));
-    let fast_slow_store = Store::new(FastSlowStore::new(
-        &FastSlowSpec {
-            fast: StoreSpec::memory(MemorySpec::default()),
-            slow: StoreSpec::memory(MemorySpec::default()),
-        },
-        fast_store.clone(),
-        slow_store.clone(),
-    ));
+    let fast_slow_store = Store::new(
+        FastSlowStore::new(
+            &FastSlowSpec {
+                fast: StoreSpec::memory(MemorySpec::default()),
+                slow: StoreSpec::memory(MemorySpec::default()),
+                popularity: None,
+                population: FastSlowPopulationSpec::default(),
+                write_back: false,
+            },
+            fast_store.clone(),
+            slow_store.clone(),
+        )
+        .unwrap(),
+    );
     (fast_slow_store, fast_store, slow_store)
 }
 
@@ -332,10 +343,13 @@ async fn drop_on_eof_completes_store_futures() -> Result<(), Error> {
         &FastSlowSpec {
             fast: StoreSpec::memory(MemorySpec::default()),
             slow: StoreSpec::memory(MemorySpec::default()),
+            popularity: None,
+            population: FastSlowPopulationSpec::default(),
+            write_back: false,
         },
         fast_store,
         slow_store,
-    );
+    )?;
 
     let (tx, mut rx) = make_buf_channel_pair();
     let (get_res, read_res) = tokio::join!(
@@ -373,10 +387,13 @@ async fn ignore_value_in_fast_store() -> Result<(), Error> {
         &FastSlowSpec {
             fast: StoreSpec::memory(MemorySpec::default()),
             slow: StoreSpec::memory(MemorySpec::default()),
+            popularity: None,
+            population: FastSlowPopulationSpec::default(),
+            write_back: false,
         },
         fast_store.clone(),
         slow_store,
-    ));
+    )?);
     let digest = DigestInfo::try_new(VALID_HASH, 100).unwrap();
     fast_store
         .update_oneshot(digest, make_random_data(100).into())
@@ -396,12 +413,15 @@ async fn has_checks_fast_store_when_noop() -> Result<(), Error> {
     let fast_slow_store_config = FastSlowSpec {
         fast: StoreSpec::memory(MemorySpec::default()),
         slow: StoreSpec::noop(NoopSpec::default()),
+        popularity: None,
+        population: FastSlowPopulationSpec::default(),
+        write_back: false,
     };
     let fast_slow_store = Arc::new(FastSlowStore::new(
         &fast_slow_store_config,
         fast_store.clone(),
         slow_store.clone(),
-    ));
+    )?);
 
     let data = make_random_data(100);
     let digest = DigestInfo::try_new(VALID_HASH, data.len()).unwrap();
@@ -430,3 +450,216 @@ async fn has_checks_fast_store_when_noop() -> Result<(), Error> {
     );
     Ok(())
 }
+
+#[nativelink_test]
+async fn slow_store_reads_promote_only_popular_objects() -> Result<(), Error> {
+    let fast_store = Store::new(MemoryStore::new(&MemorySpec::default()));
+    let slow_store = Store::new(MemoryStore::new(&MemorySpec::default()));
+    let fast_slow_store = FastSlowStore::new(
+        &FastSlowSpec {
+            fast: StoreSpec::memory(MemorySpec::default()),
+            slow: StoreSpec::memory(MemorySpec::default()),
+            popularity: Some(PopularitySpec {
+                tracker_name: "slow_store_reads_promote_only_popular_objects".to_string(),
+                min_reads_to_promote: 3,
+                ..Default::default()
+            }),
+            population: FastSlowPopulationSpec::default(),
+            write_back: false,
+        },
+        fast_store.clone(),
+        slow_store.clone(),
+    )?;
+
+    let original_data = make_random_data(100);
+    let digest = DigestInfo::try_new(VALID_HASH, original_data.len()).unwrap();
+    slow_store
+        .update_oneshot(digest, original_data.clone().into())
+        .await?;
+
+    for _ in 0..2 {
+        assert_eq!(
+            fast_slow_store.get_part_unchunked(digest, 0, None).await,
+            Ok(original_data.clone().into())
+        );
+        assert_eq!(
+            fast_store.has(digest).await,
+            Ok(None),
+            "Expected unpopular data to not be copied to the fast store"
+        );
+    }
+
+    // The third read makes the data popular enough to be promoted.
+    fast_slow_store.get_part_unchunked(digest, 0, None).await?;
+    check_data(&fast_store, digest, &original_data, "fast_store").await?;
+
+    let tracker = fast_slow_store.popularity_tracker().unwrap();
+    assert_eq!(tracker.hottest(10), vec![(StoreKey::Digest(digest), 3)]);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn background_population_copies_after_read() -> Result<(), Error> {
+    let fast_store = Store::new(MemoryStore::new(&MemorySpec::default()));
+    let slow_store = Store::new(MemoryStore::new(&MemorySpec::default()));
+    let fast_slow_store = FastSlowStore::new(
+        &FastSlowSpec {
+            fast: StoreSpec::memory(MemorySpec::default()),
+            slow: StoreSpec::memory(MemorySpec::default()),
+            popularity: None,
+            population: FastSlowPopulationSpec {
+                on_read: FastSlowPopulateMode::background,
+                on_write: FastSlowPopulateMode::background,
+                ..Default::default()
+            },
+            write_back: false,
+        },
+        fast_store.clone(),
+        slow_store.clone(),
+    )?;
+
+    let original_data = make_random_data(MEGABYTE_SZ);
+    let digest = DigestInfo::try_new(VALID_HASH, 100).unwrap();
+    slow_store
+        .update_oneshot(digest, original_data.clone().into())
+        .await?;
+
+    assert_eq!(
+        fast_slow_store
+            .get_part_unchunked(digest, 10, Some(20))
+            .await,
+        Ok(original_data[10..30].to_vec().into())
+    );
+    // The whole object is copied into the fast store in the background.
+    while fast_store.has(digest).await?.is_none() {
+        tokio::task::yield_now().await;
+    }
+    check_data(&fast_store, digest, &original_data, "fast_store").await?;
+
+    // Uploads are copied the same way once they are in the slow store.
+    let digest = DigestInfo::try_new(VALID_HASH, 200).unwrap();
+    fast_slow_store
+        .update_oneshot(digest, original_data.clone().into())
+        .await?;
+    check_data(&slow_store, digest, &original_data, "slow_store").await?;
+    while fast_store.has(digest).await?.is_none() {
+        tokio::task::yield_now().await;
+    }
+    check_data(&fast_store, digest, &original_data, "fast_store").await?;
+    Ok(())
+}
+
+#[nativelink_test]
+async fn never_populate_only_uses_slow_store() -> Result<(), Error> {
+    let fast_store = Store::new(MemoryStore::new(&MemorySpec::default()));
+    let slow_store = Store::new(MemoryStore::new(&MemorySpec::default()));
+    let fast_slow_store = FastSlowStore::new(
+        &FastSlowSpec {
+            fast: StoreSpec::memory(MemorySpec::default()),
+            slow: StoreSpec::memory(MemorySpec::default()),
+            popularity: None,
+            population: FastSlowPopulationSpec {
+                on_read: FastSlowPopulateMode::never,
+                on_write: FastSlowPopulateMode::never,
+                ..Default::default()
+            },
+            write_back: false,
+        },
+        fast_store.clone(),
+        slow_store.clone(),
+    )?;
+
+    let original_data = make_random_data(100);
+    let digest = DigestInfo::try_new(VALID_HASH, 100).unwrap();
+    fast_slow_store
+        .update_oneshot(digest, original_data.clone().into())
+        .await?;
+    assert_eq!(
+        fast_slow_store.get_part_unchunked(digest, 0, None).await,
+        Ok(original_data.clone().into())
+    );
+
+    check_data(&slow_store, digest, &original_data, "slow_store").await?;
+    assert_eq!(fast_store.has(digest).await, Ok(None));
+    Ok(())
+}
+
+#[nativelink_test]
+async fn write_back_copies_evicted_objects_to_slow_store() -> Result<(), Error> {
+    let temp_dir = format!(
+        "{}/{}",
+        env::var("TEST_TMPDIR").unwrap_or(env::temp_dir().to_str().unwrap().to_string()),
+        thread_rng().gen::<u64>(),
+    );
+    let fast_spec = FilesystemSpec {
+        content_path: format!("{temp_dir}/content_path"),
+        temp_path: format!("{temp_dir}/temp_path"),
+        eviction_policy: Some(EvictionPolicy {
+            max_count: 1,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let fast_store = Store::new(<FilesystemStore>::new(&fast_spec).await?);
+    let slow_store = Store::new(MemoryStore::new(&MemorySpec::default()));
+    let fast_slow_store = FastSlowStore::new(
+        &FastSlowSpec {
+            fast: StoreSpec::filesystem(fast_spec),
+            slow: StoreSpec::memory(MemorySpec::default()),
+            popularity: None,
+            population: FastSlowPopulationSpec::default(),
+            write_back: true,
+        },
+        fast_store.clone(),
+        slow_store.clone(),
+    )?;
+
+    let data1 = make_random_data(100);
+    let digest1 = DigestInfo::try_new(VALID_HASH, data1.len())?;
+    fast_slow_store
+        .update_oneshot(digest1, data1.clone().into())
+        .await?;
+    assert_eq!(slow_store.has(digest1).await, Ok(None));
+    assert!(
+        fast_slow_store.has(digest1).await?.is_some(),
+        "Expected data to exist in fast store"
+    );
+
+    // Evicts the first object from the fast store.
+    let data2 = make_random_data(200);
+    let digest2 = DigestInfo::try_new(VALID_HASH, data2.len())?;
+    fast_slow_store
+        .update_oneshot(digest2, data2.clone().into())
+        .await?;
+    while slow_store.has(digest1).await?.is_none() {
+        tokio::task::yield_now().await;
+    }
+    assert_eq!(fast_store.has(digest1).await, Ok(None));
+    assert_eq!(slow_store.has(digest2).await, Ok(None));
+    check_data(&slow_store, digest1, &data1, "slow_store").await?;
+    assert_eq!(
+        fast_slow_store.get_part_unchunked(digest1, 0, None).await,
+        Ok(data1.into())
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn write_back_requires_filesystem_fast_store() -> Result<(), Error> {
+    let result = FastSlowStore::new(
+        &FastSlowSpec {
+            fast: StoreSpec::memory(MemorySpec::default()),
+            slow: StoreSpec::memory(MemorySpec::default()),
+            popularity: None,
+            population: FastSlowPopulationSpec::default(),
+            write_back: true,
+        },
+        Store::new(MemoryStore::new(&MemorySpec::default())),
+        Store::new(MemoryStore::new(&MemorySpec::default())),
+    );
+    assert_eq!(
+        result.err().map(|err| err.code),
+        Some(Code::InvalidArgument)
+    );
+    Ok(())
+}
@@ -27,19 +27,22 @@ use filetime::{set_file_atime, FileTime};
 use futures::executor::block_on;
 use futures::task::Poll;
 use futures::{poll, Future, FutureExt};
-use nativelink_config::stores::{FastSlowSpec, FilesystemSpec, MemorySpec, StoreSpec};
+use nativelink_config::stores::{
+    ConfigStoreKeyEncoding, FastSlowPopulationSpec, FastSlowSpec, FilesystemSpec, MemorySpec,
+    StoreSpec,
+};
 use nativelink_error::{make_err, Code, Error, ResultExt};
 use nativelink_macro::nativelink_test;
 use nativelink_store::fast_slow_store::FastSlowStore;
 use nativelink_store::filesystem_store::{
     key_from_file, EncodedFilePath, FileEntry, FileEntryImpl, FileType, FilesystemStore,
-    DIGEST_FOLDER, STR_FOLDER,
+    ACCESS_INDEX_FILE, DIGEST_FOLDER, STR_FOLDER,
 };
 use nativelink_util::buf_channel::make_buf_channel_pair;
 use nativelink_util::common::{fs, DigestInfo};
 use nativelink_util::evicting_map::LenEntry;
 use nativelink_util::origin_context::ContextAwareFuture;
-use nativelink_util::store_trait::{Store, StoreKey, StoreLike, UploadSizeInfo};
+use nativelink_util::store_trait::{Store, StoreKey, StoreKeyEncoding, StoreLike, UploadSizeInfo};
 use nativelink_util::{background_spawn, spawn};
 use parking_lot::Mutex;
 use pretty_assertions::assert_eq;
@@ -314,6 +317,77 @@ async fn valid_results_after_shutdown_test() -> Result<(), Error> {
     Ok(())
 }
 
+#[serial]
+#[nativelink_test]
+async fn files_are_renamed_when_key_encoding_changes_test() -> Result<(), Error> {
+    let digest = DigestInfo::try_new(HASH1, VALUE1.len())?;
+    let key = StoreKey::Digest(digest);
+    let content_path = make_temp_path("content_path");
+    let temp_path = make_temp_path("temp_path");
+    {
+        let store = Store::new(
+            FilesystemStore::<FileEntryImpl>::new(&FilesystemSpec {
+                content_path: content_path.clone(),
+                temp_path: temp_path.clone(),
+                ..Default::default()
+            })
+            .await?,
+        );
+        store.update_oneshot(digest, VALUE1.into()).await?;
+    }
+    let hex_path = format!("{content_path}/{DIGEST_FOLDER}/{digest}");
+    let compact_path = format!(
+        "{content_path}/{DIGEST_FOLDER}/{}",
+        key.encode(StoreKeyEncoding::Compact)
+    );
+    assert!(
+        Path::new(&hex_path).exists(),
+        "Expected {hex_path} to exist"
+    );
+    {
+        let store = Box::pin(
+            FilesystemStore::<FileEntryImpl>::new(&FilesystemSpec {
+                content_path: content_path.clone(),
+                temp_path: temp_path.clone(),
+                key_encoding: ConfigStoreKeyEncoding::compact,
+                ..Default::default()
+            })
+            .await?,
+        );
+        assert!(
+            !Path::new(&hex_path).exists(),
+            "Expected {hex_path} to be renamed"
+        );
+        assert!(
+            Path::new(&compact_path).exists(),
+            "Expected {compact_path} to exist"
+        );
+
+        let content = store.get_part_unchunked(key.borrow(), 0, None).await?;
+        assert_eq!(content, VALUE1.as_bytes());
+    }
+    {
+        // Switching back to hex must rename the file again.
+        let store = Box::pin(
+            FilesystemStore::<FileEntryImpl>::new(&FilesystemSpec {
+                content_path,
+                temp_path,
+                ..Default::default()
+            })
+            .await?,
+        );
+        assert!(
+            Path::new(&hex_path).exists(),
+            "Expected {hex_path} to exist"
+        );
+
+        let content = store.get_part_unchunked(key, 0, None).await?;
+        assert_eq!(content, VALUE1.as_bytes());
+    }
+
+    Ok(())
+}
+
 #[serial]
 #[nativelink_test]
 async fn temp_files_get_deleted_on_replace_test() -> Result<(), Error> {
@@ -406,6 +480,7 @@ async fn file_continues_to_stream_on_content_replace_test() -> Result<(), Error>
             }),
             block_size: 1,
             read_buffer_size: 1,
+            ..Default::default()
         })
         .await?,
     );
@@ -515,6 +590,7 @@ async fn file_gets_cleans_up_on_cache_eviction() -> Result<(), Error> {
             }),
             block_size: 1,
             read_buffer_size: 1,
+            ..Default::default()
         })
         .await?,
     );
@@ -1334,6 +1410,9 @@ async fn update_with_whole_file_slow_path_when_low_file_descriptors() -> Result<
         &FastSlowSpec {
             fast: StoreSpec::memory(MemorySpec::default()),
             slow: StoreSpec::memory(MemorySpec::default()),
+            popularity: None,
+            population: FastSlowPopulationSpec::default(),
+            write_back: false,
         },
         Store::new(
             FilesystemStore::<FileEntryImpl>::new(&FilesystemSpec {
@@ -1353,7 +1432,7 @@ async fn update_with_whole_file_slow_path_when_low_file_descriptors() -> Result<
             })
             .await?,
         ),
-    );
+    )?;
     store.update_oneshot(digest, value.clone().into()).await?;
 
     let temp_path = make_temp_path("temp_path2");
@@ -1434,3 +1513,127 @@ async fn update_with_whole_file_uses_same_inode() -> Result<(), Error> {
 
     Ok(())
 }
+
+#[serial]
+#[nativelink_test]
+async fn update_oneshot_writes_small_and_large_values() -> Result<(), Error> {
+    let content_path = make_temp_path("content_path");
+    let temp_path = make_temp_path("temp_path");
+
+    let small_value = "x".repeat(4);
+    let large_value = "y".repeat(1024);
+    let small_digest = DigestInfo::try_new(HASH1, small_value.len())?;
+    let large_digest = DigestInfo::try_new(HASH2, large_value.len())?;
+
+    let store = Box::pin(
+        FilesystemStore::<FileEntryImpl>::new(&FilesystemSpec {
+            content_path: content_path.clone(),
+            temp_path: temp_path.clone(),
+            max_direct_write_size: small_value.len() as u64,
+            ..Default::default()
+        })
+        .await?,
+    );
+
+    // The small value takes the single write path, the large one is streamed.
+    store
+        .update_oneshot(small_digest, small_value.clone().into())
+        .await?;
+    store
+        .update_oneshot(large_digest, large_value.clone().into())
+        .await?;
+
+    assert_eq!(
+        store.get_part_unchunked(small_digest, 0, None).await?,
+        small_value.as_bytes()
+    );
+    assert_eq!(
+        store.get_part_unchunked(large_digest, 0, None).await?,
+        large_value.as_bytes()
+    );
+    assert_eq!(
+        read_file_contents(&OsString::from(format!(
+            "{content_path}/{DIGEST_FOLDER}/{small_digest}"
+        )))
+        .await?,
+        small_value.as_bytes()
+    );
+
+    check_temp_empty(&temp_path).await
+}
+
+#[serial]
+#[nativelink_test]
+async fn network_filesystem_writes_access_index_test() -> Result<(), Error> {
+    let digest = DigestInfo::try_new(HASH1, VALUE1.len())?;
+    let content_path = make_temp_path("content_path");
+    let temp_path = make_temp_path("temp_path");
+
+    let store = FilesystemStore::<FileEntryImpl>::new(&FilesystemSpec {
+        content_path: content_path.clone(),
+        temp_path: temp_path.clone(),
+        network_filesystem: true,
+        ..Default::default()
+    })
+    .await?;
+    store.update_oneshot(digest, VALUE1.into()).await?;
+    assert_eq!(
+        store.get_part_unchunked(digest, 0, None).await?,
+        VALUE1.as_bytes()
+    );
+    drop(store);
+
+    // The access time is kept in the index instead of in the atime of the file.
+    let index = read_file_contents(&OsString::from(format!(
+        "{content_path}/{ACCESS_INDEX_FILE}"
+    )))
+    .await?;
+    let index = String::from_utf8(index).unwrap();
+    assert!(
+        index.lines().any(|line| line
+            .split_once(' ')
+            .is_some_and(|(_, path)| path == format!("{DIGEST_FOLDER}/{digest}"))),
+        "Expected {digest} in access index, got {index:?}"
+    );
+
+    check_temp_empty(&temp_path).await
+}
+
+#[serial]
+#[nativelink_test]
+async fn network_filesystem_keeps_files_across_restart_test() -> Result<(), Error> {
+    let digest1 = DigestInfo::try_new(HASH1, VALUE1.len())?;
+    let digest2 = DigestInfo::try_new(HASH2, VALUE2.len())?;
+    let content_path = make_temp_path("content_path");
+    let spec = FilesystemSpec {
+        content_path: content_path.clone(),
+        temp_path: make_temp_path("temp_path"),
+        network_filesystem: true,
+        ..Default::default()
+    };
+
+    {
+        let store = FilesystemStore::<FileEntryImpl>::new(&spec).await?;
+        store.update_oneshot(digest1, VALUE1.into()).await?;
+        store.update_oneshot(digest2, VALUE2.into()).await?;
+        // Replacing a file links the new file over the old one.
+        store.update_oneshot(digest2, VALUE2.into()).await?;
+        // Reads are recorded in the index.
+        store.get_part_unchunked(digest1, 0, None).await?;
+    }
+
+    let store = FilesystemStore::<FileEntryImpl>::new(&spec).await?;
+    assert_eq!(
+        store.get_part_unchunked(digest1, 0, None).await?,
+        VALUE1.as_bytes()
+    );
+    assert_eq!(
+        store.get_part_unchunked(digest2, 0, None).await?,
+        VALUE2.as_bytes()
+    );
+    // The index is not mistaken for a file of an old cache layout.
+    assert!(fs::metadata(format!("{content_path}/{ACCESS_INDEX_FILE}"))
+        .await
+        .is_ok());
+    Ok(())
+}
@@ -0,0 +1,350 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use hyper::{header, Body, Method, Request, Response, StatusCode};
+use mock_instant::thread_local::MockClock;
+use nativelink_config::stores::{GcsAuth, GcsSpec, Retry};
+use nativelink_error::{make_err, Code, Error, ResultExt};
+use nativelink_macro::nativelink_test;
+use nativelink_store::gcs_store::{GcsHttpClient, GcsStore};
+use nativelink_util::common::DigestInfo;
+use nativelink_util::instant_wrapper::MockInstantWrapped;
+use nativelink_util::store_trait::{StoreLike, UploadSizeInfo};
+use pretty_assertions::assert_eq;
+
+const BUCKET_NAME: &str = "dummy-bucket-name";
+const ENDPOINT: &str = "http://gcs.test";
+const VALID_HASH1: &str = "0123456789abcdef000000000000000000010000000000000123456789abcdef";
+const CHUNK_SIZE: usize = 256 * 1024;
+
+/// A request the store sent, with its body read into memory.
+#[derive(Debug, PartialEq, Eq)]
+struct SentRequest {
+    method: Method,
+    uri: String,
+    content_range: Option<String>,
+    range: Option<String>,
+    body: Bytes,
+}
+
+/// Replays `responses` in order and records the requests it got.
+#[derive(Default)]
+struct MockGcsHttpClient {
+    responses: Mutex<VecDeque<(StatusCode, Vec<(header::HeaderName, &'static str)>, Bytes)>>,
+    requests: Mutex<Vec<SentRequest>>,
+}
+
+impl MockGcsHttpClient {
+    fn new(
+        responses: Vec<(StatusCode, Vec<(header::HeaderName, &'static str)>, Bytes)>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            responses: Mutex::new(responses.into()),
+            requests: Mutex::default(),
+        })
+    }
+
+    fn take_requests(&self) -> Vec<SentRequest> {
+        std::mem::take(&mut *self.requests.lock().unwrap())
+    }
+}
+
+#[async_trait]
+impl GcsHttpClient for MockGcsHttpClient {
+    async fn send(&self, request: Request<Body>) -> Result<Response<Body>, Error> {
+        let header_value = |name: &header::HeaderName| {
+            request
+                .headers()
+                .get(name)
+                .map(|value| value.to_str().unwrap().to_string())
+        };
+        let content_range = header_value(&header::CONTENT_RANGE);
+        let range = header_value(&header::RANGE);
+        let (parts, body) = request.into_parts();
+        let body = hyper::body::to_bytes(body).await.unwrap();
+        self.requests.lock().unwrap().push(SentRequest {
+            method: parts.method,
+            uri: parts.uri.to_string(),
+            content_range,
+            range,
+            body,
+        });
+        let (status, headers, body) = self
+            .responses
+            .lock()
+            .unwrap()
+            .pop_front()
+            .ok_or_else(|| make_err!(Code::Internal, "Unexpected request"))?;
+        let mut response = Response::builder().status(status);
+        for (name, value) in headers {
+            response = response.header(name, value);
+        }
+        Ok(response.body(Body::from(body)).unwrap())
+    }
+}
+
+fn make_store(
+    http_client: Arc<MockGcsHttpClient>,
+    spec: GcsSpec,
+) -> Result<Arc<GcsStore<fn() -> MockInstantWrapped>>, Error> {
+    GcsStore::new_with_client_and_jitter(
+        &GcsSpec {
+            bucket: BUCKET_NAME.to_string(),
+            endpoint: Some(ENDPOINT.to_string()),
+            auth: GcsAuth::none,
+            retry: Retry {
+                max_retries: 2,
+                ..Default::default()
+            },
+            ..spec
+        },
+        http_client,
+        Arc::new(|_delay| Duration::from_secs(0)),
+        MockInstantWrapped::default,
+    )
+}
+
+fn object_uri(digest: DigestInfo, query: &str) -> String {
+    format!("{ENDPOINT}/storage/v1/b/{BUCKET_NAME}/o/{digest}?{query}")
+}
+
+#[nativelink_test]
+async fn has_object_found_and_not_found() -> Result<(), Error> {
+    let http_client = MockGcsHttpClient::new(vec![
+        (
+            StatusCode::OK,
+            vec![],
+            Bytes::from_static(br#"{"size":"512","updated":"2024-01-01T00:00:00.000Z"}"#),
+        ),
+        (StatusCode::NOT_FOUND, vec![], Bytes::new()),
+    ]);
+    let store = make_store(http_client.clone(), GcsSpec::default())?;
+    let digest = DigestInfo::try_new(VALID_HASH1, 100)?;
+
+    assert_eq!(store.has(digest).await, Ok(Some(512)));
+    assert_eq!(store.has(digest).await, Ok(None));
+    let requests = http_client.take_requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0].uri, object_uri(digest, "fields=size%2Cupdated"));
+    Ok(())
+}
+
+#[nativelink_test]
+async fn has_retries_server_errors() -> Result<(), Error> {
+    let http_client = MockGcsHttpClient::new(vec![
+        (StatusCode::SERVICE_UNAVAILABLE, vec![], Bytes::new()),
+        (
+            StatusCode::OK,
+            vec![],
+            Bytes::from_static(br#"{"size":"10"}"#),
+        ),
+    ]);
+    let store = make_store(http_client, GcsSpec::default())?;
+    let digest = DigestInfo::try_new(VALID_HASH1, 10)?;
+
+    assert_eq!(store.has(digest).await, Ok(Some(10)));
+    Ok(())
+}
+
+#[nativelink_test]
+async fn has_ignores_expired_objects() -> Result<(), Error> {
+    // 2024-01-01T00:00:00Z.
+    const UPDATED_S: u64 = 1_704_067_200;
+    let http_client = MockGcsHttpClient::new(vec![
+        (
+            StatusCode::OK,
+            vec![],
+            Bytes::from_static(br#"{"size":"10","updated":"2024-01-01T00:00:00Z"}"#),
+        ),
+        (
+            StatusCode::OK,
+            vec![],
+            Bytes::from_static(br#"{"size":"10","updated":"2024-01-01T00:00:00Z"}"#),
+        ),
+    ]);
+    let store = make_store(
+        http_client,
+        GcsSpec {
+            consider_expired_after_s: 60,
+            ..Default::default()
+        },
+    )?;
+    let digest = DigestInfo::try_new(VALID_HASH1, 10)?;
+
+    MockClock::set_time(Duration::from_secs(UPDATED_S + 59));
+    assert_eq!(store.has(digest).await, Ok(Some(10)));
+    MockClock::set_time(Duration::from_secs(UPDATED_S + 60));
+    assert_eq!(store.has(digest).await, Ok(None));
+    Ok(())
+}
+
+#[nativelink_test]
+async fn small_upload_uses_single_request() -> Result<(), Error> {
+    let http_client = MockGcsHttpClient::new(vec![(StatusCode::OK, vec![], Bytes::new())]);
+    let store = make_store(http_client.clone(), GcsSpec::default())?;
+    let digest = DigestInfo::try_new(VALID_HASH1, 5)?;
+
+    store.update_oneshot(digest, "hello".into()).await?;
+
+    assert_eq!(
+        http_client.take_requests(),
+        vec![SentRequest {
+            method: Method::POST,
+            uri: format!(
+                "{ENDPOINT}/upload/storage/v1/b/{BUCKET_NAME}/o?uploadType=media&name={digest}"
+            ),
+            content_range: None,
+            range: None,
+            body: Bytes::from_static(b"hello"),
+        }]
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn large_upload_resumes_after_partial_chunk() -> Result<(), Error> {
+    const SESSION_URI: &str = "http://gcs.test/upload/session/1";
+    let http_client = MockGcsHttpClient::new(vec![
+        // Start the session.
+        (
+            StatusCode::OK,
+            vec![(header::LOCATION, SESSION_URI)],
+            Bytes::new(),
+        ),
+        // First chunk fails.
+        (StatusCode::SERVICE_UNAVAILABLE, vec![], Bytes::new()),
+        // The status query shows only the first 100 bytes were persisted.
+        (
+            StatusCode::PERMANENT_REDIRECT,
+            vec![(header::RANGE, "bytes=0-99")],
+            Bytes::new(),
+        ),
+        // The rest of the first chunk.
+        (
+            StatusCode::PERMANENT_REDIRECT,
+            vec![(header::RANGE, "bytes=0-262143")],
+            Bytes::new(),
+        ),
+        // The last chunk completes the object.
+        (StatusCode::OK, vec![], Bytes::new()),
+    ]);
+    let store = make_store(
+        http_client.clone(),
+        GcsSpec {
+            resumable_chunk_size: CHUNK_SIZE,
+            ..Default::default()
+        },
+    )?;
+    let data: Bytes = (0..CHUNK_SIZE + 10).map(|i| (i % 251) as u8).collect();
+    let digest = DigestInfo::try_new(VALID_HASH1, data.len())?;
+
+    let (mut tx, rx) = nativelink_util::buf_channel::make_buf_channel_pair();
+    let send_data = data.clone();
+    let (update_res, send_res) = futures::join!(
+        store.update(digest, rx, UploadSizeInfo::MaxSize(data.len() as u64)),
+        async move {
+            tx.send(send_data).await?;
+            tx.send_eof()
+        },
+    );
+    update_res.merge(send_res)?;
+
+    let requests = http_client.take_requests();
+    let summary: Vec<_> = requests
+        .iter()
+        .map(|request| {
+            (
+                request.method.clone(),
+                request.content_range.clone(),
+                request.body.len(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            (Method::POST, None, 0),
+            (
+                Method::PUT,
+                Some("bytes 0-262143/*".to_string()),
+                CHUNK_SIZE
+            ),
+            (Method::PUT, Some("bytes */*".to_string()), 0),
+            (
+                Method::PUT,
+                Some("bytes 100-262143/*".to_string()),
+                CHUNK_SIZE - 100
+            ),
+            (
+                Method::PUT,
+                Some("bytes 262144-262153/262154".to_string()),
+                10
+            ),
+        ]
+    );
+    assert_eq!(
+        requests[0].uri,
+        format!(
+            "{ENDPOINT}/upload/storage/v1/b/{BUCKET_NAME}/o?uploadType=resumable&name={digest}"
+        )
+    );
+    assert_eq!(requests[3].body, data.slice(100..CHUNK_SIZE));
+    assert_eq!(requests[4].body, data.slice(CHUNK_SIZE..));
+    Ok(())
+}
+
+#[nativelink_test]
+async fn get_part_requests_inclusive_range() -> Result<(), Error> {
+    let http_client = MockGcsHttpClient::new(vec![(
+        StatusCode::PARTIAL_CONTENT,
+        vec![],
+        Bytes::from_static(b"llo w"),
+    )]);
+    let store = make_store(http_client.clone(), GcsSpec::default())?;
+    let digest = DigestInfo::try_new(VALID_HASH1, 11)?;
+
+    assert_eq!(
+        store.get_part_unchunked(digest, 2, Some(5)).await,
+        Ok(Bytes::from_static(b"llo w"))
+    );
+    assert_eq!(
+        http_client.take_requests(),
+        vec![SentRequest {
+            method: Method::GET,
+            uri: object_uri(digest, "alt=media"),
+            content_range: None,
+            range: Some("bytes=2-6".to_string()),
+            body: Bytes::new(),
+        }]
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn get_part_not_found_is_not_retried() -> Result<(), Error> {
+    let http_client = MockGcsHttpClient::new(vec![(StatusCode::NOT_FOUND, vec![], Bytes::new())]);
+    let store = make_store(http_client.clone(), GcsSpec::default())?;
+    let digest = DigestInfo::try_new(VALID_HASH1, 11)?;
+
+    let err = store.get_part_unchunked(digest, 0, None).await.unwrap_err();
+    assert_eq!(err.code, Code::NotFound);
+    assert_eq!(http_client.take_requests().len(), 1);
+    Ok(())
+}
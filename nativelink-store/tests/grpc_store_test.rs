@@ -0,0 +1,191 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::pin::Pin;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use futures::Stream;
+use nativelink_config::stores::{GrpcEndpoint, GrpcSpec, Retry, StoreType};
+use nativelink_error::{make_err, Code, Error};
+use nativelink_macro::nativelink_test;
+use nativelink_proto::google::bytestream::byte_stream_server::{ByteStream, ByteStreamServer};
+use nativelink_proto::google::bytestream::{
+    QueryWriteStatusRequest, QueryWriteStatusResponse, ReadRequest, ReadResponse, WriteRequest,
+    WriteResponse,
+};
+use nativelink_store::grpc_store::GrpcStore;
+use nativelink_util::background_spawn;
+use nativelink_util::common::DigestInfo;
+use nativelink_util::store_trait::StoreLike;
+use parking_lot::Mutex;
+use pretty_assertions::assert_eq;
+use tokio::net::TcpListener;
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status, Streaming};
+
+const VALID_HASH1: &str = "0123456789abcdef000000000000000000010000000000000123456789abcdef";
+const VALUE: &[u8] = b"0123456789";
+
+#[derive(Default)]
+struct UploadState {
+    committed: Vec<u8>,
+    complete: bool,
+    write_calls: usize,
+    /// The next write fails once this many bytes are committed.
+    fail_at: Option<usize>,
+}
+
+/// ByteStream server that keeps a single upload and can fail a write part
+/// way through it.
+#[derive(Clone, Default)]
+struct FakeByteStream {
+    state: Arc<Mutex<UploadState>>,
+}
+
+#[tonic::async_trait]
+impl ByteStream for FakeByteStream {
+    type ReadStream = Pin<Box<dyn Stream<Item = Result<ReadResponse, Status>> + Send + 'static>>;
+
+    async fn read(
+        &self,
+        _request: Request<ReadRequest>,
+    ) -> Result<Response<Self::ReadStream>, Status> {
+        Err(Status::unimplemented("read"))
+    }
+
+    async fn write(
+        &self,
+        request: Request<Streaming<WriteRequest>>,
+    ) -> Result<Response<WriteResponse>, Status> {
+        let mut stream = request.into_inner();
+        self.state.lock().write_calls += 1;
+        while let Some(request) = stream.message().await? {
+            let mut state = self.state.lock();
+            if request.write_offset != state.committed.len() as i64 {
+                return Err(Status::invalid_argument(format!(
+                    "Expected write_offset {}, got {}",
+                    state.committed.len(),
+                    request.write_offset
+                )));
+            }
+            state.committed.extend_from_slice(&request.data);
+            if let Some(fail_at) = state.fail_at {
+                if state.committed.len() >= fail_at {
+                    state.committed.truncate(fail_at);
+                    state.fail_at = None;
+                    return Err(Status::unavailable("Injected failure"));
+                }
+            }
+            if request.finish_write {
+                state.complete = true;
+                return Ok(Response::new(WriteResponse {
+                    committed_size: state.committed.len() as i64,
+                }));
+            }
+        }
+        Err(Status::invalid_argument(
+            "Upload ended without finish_write",
+        ))
+    }
+
+    async fn query_write_status(
+        &self,
+        _request: Request<QueryWriteStatusRequest>,
+    ) -> Result<Response<QueryWriteStatusResponse>, Status> {
+        let state = self.state.lock();
+        Ok(Response::new(QueryWriteStatusResponse {
+            committed_size: state.committed.len() as i64,
+            complete: state.complete,
+        }))
+    }
+}
+
+async fn make_grpc_store(
+    service: FakeByteStream,
+    max_retry_buffer_size: usize,
+) -> Result<Arc<GrpcStore>, Error> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let address = format!("grpc://{}", listener.local_addr()?);
+    let incoming = TcpIncoming::from_listener(listener, true, None)
+        .map_err(|e| make_err!(Code::Internal, "Could not listen for test server: {e:?}"))?;
+    background_spawn!(
+        "grpc_store_test_server",
+        Server::builder()
+            .add_service(ByteStreamServer::new(service))
+            .serve_with_incoming(incoming)
+    );
+    GrpcStore::new(&GrpcSpec {
+        instance_name: String::new(),
+        endpoints: vec![GrpcEndpoint {
+            address,
+            tls_config: None,
+            concurrency_limit: None,
+        }],
+        store_type: StoreType::cas,
+        retry: Retry {
+            max_retries: 2,
+            delay: 0.,
+            jitter: 0.,
+            retry_on_errors: None,
+        },
+        max_concurrent_requests: 0,
+        connections_per_endpoint: 0,
+        local_cache: None,
+        replication: None,
+        max_retry_buffer_size,
+    })
+    .await
+}
+
+#[nativelink_test]
+async fn write_resumes_from_committed_offset_test() -> Result<(), Error> {
+    let service = FakeByteStream::default();
+    service.state.lock().fail_at = Some(4);
+    let store = make_grpc_store(service.clone(), 0).await?;
+
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE.len())?;
+    store
+        .update_oneshot(digest, Bytes::from_static(VALUE))
+        .await?;
+
+    let state = service.state.lock();
+    // The second call only sent the bytes after the committed offset,
+    // otherwise the server would have rejected its write_offset.
+    assert_eq!(state.write_calls, 2);
+    assert_eq!(state.committed, VALUE);
+    assert!(state.complete);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn write_fails_when_retry_buffer_is_exceeded_test() -> Result<(), Error> {
+    let service = FakeByteStream::default();
+    service.state.lock().fail_at = Some(2);
+    // Only the last byte sent is kept, so the upload can not be resumed
+    // from byte 2.
+    let store = make_grpc_store(service.clone(), 1).await?;
+
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE.len())?;
+    let result = store
+        .update_oneshot(digest, Bytes::from_static(VALUE))
+        .await;
+
+    assert!(result.is_err(), "Expected upload to fail, got {result:?}");
+    let state = service.state.lock();
+    assert_eq!(state.write_calls, 1);
+    assert!(!state.complete);
+    Ok(())
+}
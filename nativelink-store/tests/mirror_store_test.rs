@@ -0,0 +1,147 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use nativelink_config::stores::{MemorySpec, MirrorSpec, StoreSpec};
+use nativelink_error::{Code, Error};
+use nativelink_macro::nativelink_test;
+use nativelink_store::memory_store::MemoryStore;
+use nativelink_store::mirror_store::MirrorStore;
+use nativelink_util::common::DigestInfo;
+use nativelink_util::fake_store_for_tests::FakeStore;
+use nativelink_util::store_trait::{Store, StoreLike};
+use pretty_assertions::assert_eq;
+
+const VALID_HASH1: &str = "0123456789abcdef000000000000000000010000000000000123456789abcdef";
+const VALUE: &str = "mirrored";
+
+fn make_fake_store() -> Arc<FakeStore> {
+    FakeStore::new(Store::new(MemoryStore::new(&MemorySpec::default())))
+}
+
+fn make_store(stores: &[Arc<FakeStore>], write_quorum: usize) -> Result<Arc<MirrorStore>, Error> {
+    MirrorStore::new(
+        &MirrorSpec {
+            stores: stores
+                .iter()
+                .map(|_| StoreSpec::memory(MemorySpec::default()))
+                .collect(),
+            write_quorum,
+            disable_read_repair: false,
+        },
+        stores
+            .iter()
+            .map(|store| Store::new(store.clone()))
+            .collect(),
+    )
+}
+
+#[nativelink_test]
+async fn update_writes_to_every_store() -> Result<(), Error> {
+    let stores = [make_fake_store(), make_fake_store()];
+    let store = make_store(&stores, 0)?;
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE.len())?;
+
+    store.update_oneshot(digest, VALUE.into()).await?;
+    for fake in &stores {
+        assert_eq!(
+            fake.inner().get_part_unchunked(digest, 0, None).await,
+            Ok(Bytes::from_static(VALUE.as_bytes()))
+        );
+    }
+
+    // Without a quorum every store must accept the upload.
+    stores[1].set_failing(true);
+    assert!(store.update_oneshot(digest, VALUE.into()).await.is_err());
+    Ok(())
+}
+
+#[nativelink_test]
+async fn update_succeeds_with_quorum() -> Result<(), Error> {
+    let stores = [make_fake_store(), make_fake_store(), make_fake_store()];
+    let store = make_store(&stores, 2)?;
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE.len())?;
+
+    stores[0].set_failing(true);
+    store.update_oneshot(digest, VALUE.into()).await?;
+    assert_eq!(stores[0].inner().has(digest).await, Ok(None));
+    assert_eq!(
+        stores[1].inner().has(digest).await,
+        Ok(Some(VALUE.len() as u64))
+    );
+    assert_eq!(
+        stores[2].inner().has(digest).await,
+        Ok(Some(VALUE.len() as u64))
+    );
+
+    stores[1].set_failing(true);
+    assert!(store.update_oneshot(digest, VALUE.into()).await.is_err());
+    Ok(())
+}
+
+#[nativelink_test]
+async fn reads_fail_over_in_order() -> Result<(), Error> {
+    let stores = [make_fake_store(), make_fake_store()];
+    let store = make_store(&stores, 0)?;
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE.len())?;
+    store.update_oneshot(digest, VALUE.into()).await?;
+
+    stores[0].set_failing(true);
+    assert_eq!(store.has(digest).await, Ok(Some(VALUE.len() as u64)));
+    assert_eq!(
+        store.get_part_unchunked(digest, 0, None).await,
+        Ok(Bytes::from_static(VALUE.as_bytes()))
+    );
+
+    stores[1].set_failing(true);
+    let err = store.get_part_unchunked(digest, 0, None).await.unwrap_err();
+    assert_eq!(err.code, Code::Unavailable);
+    assert!(store.has(digest).await.is_err());
+    Ok(())
+}
+
+#[nativelink_test]
+async fn read_repairs_missing_objects() -> Result<(), Error> {
+    let stores = [make_fake_store(), make_fake_store()];
+    let store = make_store(&stores, 0)?;
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE.len())?;
+    stores[1]
+        .inner()
+        .update_oneshot(digest, VALUE.into())
+        .await?;
+
+    assert_eq!(store.has(digest).await, Ok(Some(VALUE.len() as u64)));
+    assert_eq!(
+        store.get_part_unchunked(digest, 0, None).await,
+        Ok(Bytes::from_static(VALUE.as_bytes()))
+    );
+    // The first store gets a copy of the object in the background.
+    while stores[0].inner().has(digest).await?.is_none() {
+        tokio::task::yield_now().await;
+    }
+    assert_eq!(
+        stores[0].inner().get_part_unchunked(digest, 0, None).await,
+        Ok(Bytes::from_static(VALUE.as_bytes()))
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn rejects_write_quorum_above_store_count() -> Result<(), Error> {
+    let stores = [make_fake_store(), make_fake_store()];
+    assert!(make_store(&stores, 3).is_err());
+    Ok(())
+}
@@ -0,0 +1,155 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::pin::pin;
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::Duration;
+
+use futures::poll;
+use mock_instant::thread_local::MockClock;
+use nativelink_config::stores::{MemorySpec, NoopSpec, QuotaExceededAction, QuotaSpec, StoreSpec};
+use nativelink_error::{Code, Error};
+use nativelink_macro::nativelink_test;
+use nativelink_store::memory_store::MemoryStore;
+use nativelink_store::quota_store::QuotaStore;
+use nativelink_util::common::DigestInfo;
+use nativelink_util::instant_wrapper::MockInstantWrapped;
+use nativelink_util::store_trait::{Store, StoreLike};
+use pretty_assertions::assert_eq;
+
+const VALID_HASH1: &str = "0123456789abcdef000000000000000000010000000000000123456789abcdef";
+const VALID_HASH2: &str = "0123456789abcdef000000000000000000020000000000000123456789abcdef";
+const VALID_HASH3: &str = "0123456789abcdef000000000000000000030000000000000123456789abcdef";
+const VALUE: &str = "0123456789";
+
+fn make_store(
+    inner_store: &Arc<MemoryStore>,
+    spec: &QuotaSpec,
+) -> Arc<QuotaStore<MockInstantWrapped>> {
+    QuotaStore::new_with_time(
+        spec,
+        Store::new(inner_store.clone()),
+        MockInstantWrapped::default(),
+    )
+}
+
+fn make_spec() -> QuotaSpec {
+    QuotaSpec {
+        backend: StoreSpec::noop(NoopSpec::default()), // Note: Not used.
+        max_bytes: 0,
+        max_reads_per_second: 0,
+        max_writes_per_second: 0,
+        on_exceed: QuotaExceededAction::reject,
+    }
+}
+
+#[nativelink_test]
+async fn rejects_requests_over_rate_limit() -> Result<(), Error> {
+    let inner_store = MemoryStore::new(&MemorySpec::default());
+    let store = make_store(
+        &inner_store,
+        &QuotaSpec {
+            max_reads_per_second: 2,
+            max_writes_per_second: 1,
+            ..make_spec()
+        },
+    );
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE.len())?;
+
+    store.update_oneshot(digest, VALUE.into()).await?;
+    let err = store
+        .update_oneshot(digest, VALUE.into())
+        .await
+        .unwrap_err();
+    assert_eq!(err.code, Code::ResourceExhausted);
+
+    assert_eq!(store.has(digest).await, Ok(Some(VALUE.len() as u64)));
+    assert_eq!(store.has(digest).await, Ok(Some(VALUE.len() as u64)));
+    let err = store.has(digest).await.unwrap_err();
+    assert_eq!(err.code, Code::ResourceExhausted);
+
+    MockClock::advance(Duration::from_millis(500));
+    assert_eq!(store.has(digest).await, Ok(Some(VALUE.len() as u64)));
+    let err = store.has(digest).await.unwrap_err();
+    assert_eq!(err.code, Code::ResourceExhausted);
+
+    MockClock::advance(Duration::from_secs(1));
+    store.update_oneshot(digest, VALUE.into()).await?;
+    Ok(())
+}
+
+#[nativelink_test]
+async fn throttles_requests_over_rate_limit() -> Result<(), Error> {
+    let inner_store = MemoryStore::new(&MemorySpec::default());
+    let store = make_store(
+        &inner_store,
+        &QuotaSpec {
+            max_reads_per_second: 1,
+            on_exceed: QuotaExceededAction::throttle,
+            ..make_spec()
+        },
+    );
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE.len())?;
+
+    assert_eq!(store.has(digest).await, Ok(None));
+    let mut has_fut = pin!(store.has(digest));
+    assert_eq!(poll!(&mut has_fut), Poll::Pending);
+    MockClock::advance(Duration::from_millis(500));
+    assert_eq!(poll!(&mut has_fut), Poll::Pending);
+    MockClock::advance(Duration::from_millis(500));
+    assert_eq!(has_fut.await, Ok(None));
+    Ok(())
+}
+
+#[nativelink_test]
+async fn rejects_uploads_over_max_bytes() -> Result<(), Error> {
+    let inner_store = MemoryStore::new(&MemorySpec::default());
+    let store = make_store(
+        &inner_store,
+        &QuotaSpec {
+            max_bytes: 2 * VALUE.len() as u64,
+            ..make_spec()
+        },
+    );
+    let digest1 = DigestInfo::try_new(VALID_HASH1, VALUE.len())?;
+    let digest2 = DigestInfo::try_new(VALID_HASH2, VALUE.len())?;
+    let digest3 = DigestInfo::try_new(VALID_HASH3, VALUE.len())?;
+
+    store.update_oneshot(digest1, VALUE.into()).await?;
+    store.update_oneshot(digest2, VALUE.into()).await?;
+    let err = store
+        .update_oneshot(digest3, VALUE.into())
+        .await
+        .unwrap_err();
+    assert_eq!(err.code, Code::ResourceExhausted);
+    // Uploading an object again does not use more bytes.
+    store.update_oneshot(digest1, VALUE.into()).await?;
+
+    // Objects the backend no longer has are not counted once it was
+    // checked again.
+    inner_store.remove_entry(digest1.into()).await;
+    assert_eq!(
+        store
+            .update_oneshot(digest3, VALUE.into())
+            .await
+            .unwrap_err()
+            .code,
+        Code::ResourceExhausted
+    );
+    MockClock::advance(Duration::from_secs(61));
+    store.update_oneshot(digest3, VALUE.into()).await?;
+    assert_eq!(inner_store.has(digest3).await, Ok(Some(VALUE.len() as u64)));
+    Ok(())
+}
@@ -0,0 +1,140 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::env;
+
+use bytes::Bytes;
+use nativelink_config::stores::RedbSpec;
+use nativelink_error::{Code, Error};
+use nativelink_macro::nativelink_test;
+use nativelink_store::redb_store::RedbStore;
+use nativelink_util::common::DigestInfo;
+use nativelink_util::store_trait::{StoreKey, StoreLike};
+use pretty_assertions::assert_eq;
+use rand::{thread_rng, Rng};
+
+const VALID_HASH1: &str = "0123456789abcdef000000000000000000010000000000000123456789abcdef";
+const VALID_HASH2: &str = "0123456789abcdef000000000000000000020000000000000123456789abcdef";
+
+fn make_temp_path(data: &str) -> String {
+    format!(
+        "{}/{}/{}",
+        env::var("TEST_TMPDIR").unwrap_or(env::temp_dir().to_str().unwrap().to_string()),
+        thread_rng().gen::<u64>(),
+        data
+    )
+}
+
+#[nativelink_test]
+async fn update_then_read_back() -> Result<(), Error> {
+    const VALUE: &str = "hello world";
+    let store = RedbStore::new(&RedbSpec {
+        path: make_temp_path("store.redb"),
+        ..Default::default()
+    })
+    .await?;
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE.len())?;
+
+    assert_eq!(store.has(digest).await, Ok(None));
+    store.update_oneshot(digest, VALUE.into()).await?;
+    assert_eq!(store.has(digest).await, Ok(Some(VALUE.len() as u64)));
+    assert_eq!(
+        store.get_part_unchunked(digest, 0, None).await,
+        Ok(Bytes::from_static(VALUE.as_bytes()))
+    );
+    assert_eq!(
+        store.get_part_unchunked(digest, 6, Some(3)).await,
+        Ok(Bytes::from_static(b"wor"))
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn data_survives_reopen() -> Result<(), Error> {
+    const VALUE: &str = "persistent";
+    let spec = RedbSpec {
+        path: make_temp_path("store.redb"),
+        ..Default::default()
+    };
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE.len())?;
+    {
+        let store = RedbStore::new(&spec).await?;
+        store.update_oneshot(digest, VALUE.into()).await?;
+    }
+
+    let store = RedbStore::new(&spec).await?;
+    assert_eq!(
+        store.get_part_unchunked(digest, 0, None).await,
+        Ok(Bytes::from_static(VALUE.as_bytes()))
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn missing_key_is_not_found() -> Result<(), Error> {
+    let store = RedbStore::new(&RedbSpec {
+        path: make_temp_path("store.redb"),
+        ..Default::default()
+    })
+    .await?;
+    let digest = DigestInfo::try_new(VALID_HASH1, 10)?;
+
+    let err = store.get_part_unchunked(digest, 0, None).await.unwrap_err();
+    assert_eq!(err.code, Code::NotFound);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn rejects_objects_over_max_object_size() -> Result<(), Error> {
+    let store = RedbStore::new(&RedbSpec {
+        path: make_temp_path("store.redb"),
+        max_object_size: 4,
+        ..Default::default()
+    })
+    .await?;
+    let digest = DigestInfo::try_new(VALID_HASH1, 5)?;
+
+    assert!(store.update_oneshot(digest, "12345".into()).await.is_err());
+    assert_eq!(store.has(digest).await, Ok(None));
+    store
+        .update_oneshot(DigestInfo::try_new(VALID_HASH2, 4)?, "1234".into())
+        .await?;
+    Ok(())
+}
+
+#[nativelink_test]
+async fn list_and_remove_entries() -> Result<(), Error> {
+    let store = RedbStore::new(&RedbSpec {
+        path: make_temp_path("store.redb"),
+        ..Default::default()
+    })
+    .await?;
+    store.update_oneshot("key1", "a".into()).await?;
+    store.update_oneshot("key2", "b".into()).await?;
+    store.update_oneshot("key3", "c".into()).await?;
+
+    let mut keys = Vec::new();
+    store
+        .list(StoreKey::from("key2").., |key| {
+            keys.push(key.borrow().into_owned());
+            true
+        })
+        .await?;
+    assert_eq!(keys, vec![StoreKey::from("key2"), StoreKey::from("key3")]);
+
+    assert_eq!(store.remove_entry("key2".into()).await, Ok(true));
+    assert_eq!(store.remove_entry("key2".into()).await, Ok(false));
+    assert_eq!(store.has("key2").await, Ok(None));
+    Ok(())
+}
@@ -16,6 +16,7 @@ use std::collections::VecDeque;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread::panicking;
+use std::time::{Duration, UNIX_EPOCH};
 
 use bytes::{Bytes, BytesMut};
 use fred::bytes_utils::string::Str;
@@ -25,6 +26,7 @@ use fred::mocks::{MockCommand, Mocks};
 use fred::prelude::{Builder, Pool as RedisPool};
 use fred::types::config::{Config as RedisConfig, PerformanceConfig};
 use fred::types::Value as RedisValue;
+use nativelink_config::stores::RedisAcHashLayoutSpec;
 use nativelink_error::{Code, Error};
 use nativelink_macro::nativelink_test;
 use nativelink_metric::{MetricFieldData, MetricKind, MetricsComponent, RootMetricsComponent};
@@ -34,7 +36,7 @@ use nativelink_store::redis_store::RedisStore;
 use nativelink_store::store_manager::StoreManager;
 use nativelink_util::buf_channel::make_buf_channel_pair;
 use nativelink_util::common::DigestInfo;
-use nativelink_util::store_trait::{Store, StoreLike, UploadSizeInfo};
+use nativelink_util::store_trait::{Store, StoreKey, StoreKeyEncoding, StoreLike, UploadSizeInfo};
 use parking_lot::RwLock;
 use pretty_assertions::assert_eq;
 use serde_json::{from_str, to_string, Value};
@@ -43,6 +45,8 @@ use tracing_subscriber::layer::SubscriberExt;
 
 const VALID_HASH1: &str = "3031323334353637383961626364656630303030303030303030303030303030";
 const TEMP_UUID: &str = "550e8400-e29b-41d4-a716-446655440000";
+const AC_HASH_SET_SCRIPT_HASH: &str = "33b6a47f36934035e38efc7ef5f463fa6eea5e10";
+const AC_HASH_PURGE_SCRIPT_HASH: &str = "3adfbd0fa1dc4e44fb5b1a914794d2de0844075c";
 
 const DEFAULT_READ_CHUNK_SIZE: usize = 1024;
 const DEFAULT_MAX_CHUNK_UPLOADS_PER_UPDATE: usize = 10;
@@ -274,6 +278,7 @@ async fn upload_and_get_data() -> Result<(), Error> {
             None,
             mock_uuid_generator,
             String::new(),
+            StoreKeyEncoding::Hex,
             DEFAULT_READ_CHUNK_SIZE,
             DEFAULT_MAX_CHUNK_UPLOADS_PER_UPDATE,
         )
@@ -378,6 +383,7 @@ async fn upload_and_get_data_with_prefix() -> Result<(), Error> {
             None,
             mock_uuid_generator,
             prefix.to_string(),
+            StoreKeyEncoding::Hex,
             DEFAULT_READ_CHUNK_SIZE,
             DEFAULT_MAX_CHUNK_UPLOADS_PER_UPDATE,
         )
@@ -402,6 +408,121 @@ async fn upload_and_get_data_with_prefix() -> Result<(), Error> {
     Ok(())
 }
 
+#[nativelink_test]
+async fn compact_key_encoding_falls_back_to_hex_key() -> Result<(), Error> {
+    let data = Bytes::from_static(b"14");
+
+    // The data was written before the store switched to compact keys.
+    let digest = DigestInfo::try_new(VALID_HASH1, 2)?;
+    let compact_key = RedisValue::Bytes(
+        StoreKey::Digest(digest)
+            .encode(StoreKeyEncoding::Compact)
+            .into_owned()
+            .into(),
+    );
+    let hex_key = RedisValue::Bytes(format!("{digest}").into());
+
+    let mocks = Arc::new(MockRedisBackend::new());
+    mocks
+        .expect(
+            MockCommand {
+                cmd: Str::from_static("STRLEN"),
+                subcommand: None,
+                args: vec![compact_key.clone()],
+            },
+            Ok(RedisValue::Integer(0)),
+        )
+        .expect(
+            MockCommand {
+                cmd: Str::from_static("EXISTS"),
+                subcommand: None,
+                args: vec![compact_key.clone()],
+            },
+            Ok(RedisValue::Integer(0)),
+        )
+        .expect(
+            MockCommand {
+                cmd: Str::from_static("STRLEN"),
+                subcommand: None,
+                args: vec![hex_key.clone()],
+            },
+            Ok(RedisValue::Integer(2)),
+        )
+        .expect(
+            MockCommand {
+                cmd: Str::from_static("EXISTS"),
+                subcommand: None,
+                args: vec![hex_key.clone()],
+            },
+            Ok(RedisValue::Integer(1)),
+        )
+        .expect(
+            MockCommand {
+                cmd: Str::from_static("GETRANGE"),
+                subcommand: None,
+                args: vec![
+                    compact_key.clone(),
+                    RedisValue::Integer(0),
+                    RedisValue::Integer(1),
+                ],
+            },
+            Ok(RedisValue::String(Str::from_static(""))),
+        )
+        .expect(
+            MockCommand {
+                cmd: Str::from_static("EXISTS"),
+                subcommand: None,
+                args: vec![compact_key],
+            },
+            Ok(RedisValue::Integer(0)),
+        )
+        .expect(
+            MockCommand {
+                cmd: Str::from_static("EXISTS"),
+                subcommand: None,
+                args: vec![hex_key.clone()],
+            },
+            Ok(RedisValue::Integer(1)),
+        )
+        .expect(
+            MockCommand {
+                cmd: Str::from_static("GETRANGE"),
+                subcommand: None,
+                args: vec![hex_key, RedisValue::Integer(0), RedisValue::Integer(1)],
+            },
+            Ok(RedisValue::String(Str::from_static("14"))),
+        );
+
+    let store = {
+        let mut builder = Builder::default_centralized();
+        builder.set_config(RedisConfig {
+            mocks: Some(Arc::clone(&mocks) as Arc<dyn Mocks>),
+            ..Default::default()
+        });
+        let (client_pool, subscriber_client) = make_clients(builder);
+        RedisStore::new_from_builder_and_parts(
+            client_pool,
+            subscriber_client,
+            None,
+            mock_uuid_generator,
+            String::new(),
+            StoreKeyEncoding::Compact,
+            DEFAULT_READ_CHUNK_SIZE,
+            DEFAULT_MAX_CHUNK_UPLOADS_PER_UPDATE,
+        )
+        .unwrap()
+    };
+
+    assert_eq!(store.has(digest).await?, Some(2));
+
+    let result = store
+        .get_part_unchunked(digest, 0, Some(data.len() as u64))
+        .await?;
+    assert_eq!(result, data, "Expected data stored under the hex key");
+
+    Ok(())
+}
+
 #[nativelink_test]
 async fn upload_empty_data() -> Result<(), Error> {
     let data = Bytes::from_static(b"");
@@ -415,6 +536,7 @@ async fn upload_empty_data() -> Result<(), Error> {
         None,
         mock_uuid_generator,
         String::new(),
+        StoreKeyEncoding::Hex,
         DEFAULT_READ_CHUNK_SIZE,
         DEFAULT_MAX_CHUNK_UPLOADS_PER_UPDATE,
     )
@@ -444,6 +566,7 @@ async fn upload_empty_data_with_prefix() -> Result<(), Error> {
         None,
         mock_uuid_generator,
         prefix.to_string(),
+        StoreKeyEncoding::Hex,
         DEFAULT_READ_CHUNK_SIZE,
         DEFAULT_MAX_CHUNK_UPLOADS_PER_UPDATE,
     )
@@ -558,6 +681,7 @@ async fn test_large_downloads_are_chunked() -> Result<(), Error> {
             None,
             mock_uuid_generator,
             String::new(),
+            StoreKeyEncoding::Hex,
             READ_CHUNK_SIZE,
             DEFAULT_MAX_CHUNK_UPLOADS_PER_UPDATE,
         )
@@ -711,6 +835,7 @@ async fn yield_between_sending_packets_in_update() -> Result<(), Error> {
             None,
             mock_uuid_generator,
             String::new(),
+            StoreKeyEncoding::Hex,
             DEFAULT_READ_CHUNK_SIZE,
             DEFAULT_MAX_CHUNK_UPLOADS_PER_UPDATE,
         )
@@ -801,6 +926,7 @@ async fn zero_len_items_exist_check() -> Result<(), Error> {
             None,
             mock_uuid_generator,
             String::new(),
+            StoreKeyEncoding::Hex,
             DEFAULT_READ_CHUNK_SIZE,
             DEFAULT_MAX_CHUNK_UPLOADS_PER_UPDATE,
         )
@@ -830,6 +956,7 @@ async fn dont_loop_forever_on_empty() -> Result<(), Error> {
             None,
             mock_uuid_generator,
             String::new(),
+            StoreKeyEncoding::Hex,
             DEFAULT_READ_CHUNK_SIZE,
             DEFAULT_MAX_CHUNK_UPLOADS_PER_UPDATE,
         )
@@ -876,6 +1003,7 @@ async fn test_redis_fingerprint_metric() -> Result<(), Error> {
                     None,
                     mock_uuid_generator,
                     String::new(),
+                    StoreKeyEncoding::Hex,
                     DEFAULT_READ_CHUNK_SIZE,
                     DEFAULT_MAX_CHUNK_UPLOADS_PER_UPDATE,
                 )
@@ -923,3 +1051,154 @@ struct RootMetricsTest {
 }
 
 impl RootMetricsComponent for RootMetricsTest {}
+
+fn make_ac_hash_store(mocks: &Arc<MockRedisBackend>, spec: &RedisAcHashLayoutSpec) -> RedisStore {
+    let mut builder = Builder::default_centralized();
+    builder.set_config(RedisConfig {
+        mocks: Some(Arc::clone(mocks) as Arc<dyn Mocks>),
+        ..Default::default()
+    });
+    let (client_pool, subscriber_client) = make_clients(builder);
+    RedisStore::new_from_builder_and_parts(
+        client_pool,
+        subscriber_client,
+        None,
+        mock_uuid_generator,
+        "TEST_PREFIX-".to_string(),
+        StoreKeyEncoding::Hex,
+        DEFAULT_READ_CHUNK_SIZE,
+        DEFAULT_MAX_CHUNK_UPLOADS_PER_UPDATE,
+    )
+    .unwrap()
+    .with_ac_hash_layout(spec)
+}
+
+#[nativelink_test]
+async fn ac_hash_layout_upload_and_get_data() -> Result<(), Error> {
+    let data = Bytes::from_static(b"14");
+    let digest = DigestInfo::try_new(VALID_HASH1, 2)?;
+    let field = RedisValue::Bytes(format!("{digest}").into());
+    let hash_key = RedisValue::Bytes("TEST_PREFIX-ac:{main}".into());
+    let index_key = RedisValue::Bytes("TEST_PREFIX-ac_updated:{main}".into());
+
+    let mocks = Arc::new(MockRedisBackend::new());
+    mocks
+        .expect(
+            MockCommand {
+                cmd: Str::from_static("EVALSHA"),
+                subcommand: None,
+                args: vec![
+                    AC_HASH_SET_SCRIPT_HASH.into(),
+                    2.into(),
+                    hash_key.clone(),
+                    index_key,
+                    field.clone(),
+                    RedisValue::Bytes(data.clone()),
+                    "60".as_bytes().into(),
+                    "".as_bytes().into(),
+                ],
+            },
+            Ok(RedisValue::Integer(1)),
+        )
+        .expect(
+            MockCommand {
+                cmd: Str::from_static("HSTRLEN"),
+                subcommand: None,
+                args: vec![hash_key.clone(), field.clone()],
+            },
+            Ok(RedisValue::Integer(2)),
+        )
+        .expect(
+            MockCommand {
+                cmd: Str::from_static("HEXISTS"),
+                subcommand: None,
+                args: vec![hash_key.clone(), field.clone()],
+            },
+            Ok(RedisValue::Integer(1)),
+        )
+        .expect(
+            MockCommand {
+                cmd: Str::from_static("HGET"),
+                subcommand: None,
+                args: vec![hash_key, field],
+            },
+            Ok(RedisValue::Bytes(data.clone())),
+        );
+
+    let store = make_ac_hash_store(
+        &mocks,
+        &RedisAcHashLayoutSpec {
+            partition: "main".to_string(),
+            entry_ttl_s: 60,
+            index_by_update_time: false,
+        },
+    );
+
+    store.update_oneshot(digest, data.clone()).await?;
+    assert_eq!(store.has(digest).await?, Some(2));
+    assert_eq!(
+        store.get_part_unchunked(digest, 1, None).await?,
+        data.slice(1..)
+    );
+
+    Ok(())
+}
+
+#[nativelink_test]
+async fn ac_hash_layout_purge_old_entries() -> Result<(), Error> {
+    let hash_key = RedisValue::Bytes("TEST_PREFIX-ac:{main}".into());
+    let index_key = RedisValue::Bytes("TEST_PREFIX-ac_updated:{main}".into());
+
+    let mocks = Arc::new(MockRedisBackend::new());
+    mocks.expect(
+        MockCommand {
+            cmd: Str::from_static("EVALSHA"),
+            subcommand: None,
+            args: vec![
+                AC_HASH_PURGE_SCRIPT_HASH.into(),
+                2.into(),
+                hash_key,
+                index_key,
+                "100".as_bytes().into(),
+                "1000".as_bytes().into(),
+            ],
+        },
+        Ok(RedisValue::Integer(3)),
+    );
+
+    let store = make_ac_hash_store(
+        &mocks,
+        &RedisAcHashLayoutSpec {
+            partition: "main".to_string(),
+            entry_ttl_s: 0,
+            index_by_update_time: true,
+        },
+    );
+
+    let purged = store
+        .purge_ac_entries_older_than(UNIX_EPOCH + Duration::from_secs(100))
+        .await?;
+    assert_eq!(purged, 3);
+
+    Ok(())
+}
+
+#[nativelink_test]
+async fn ac_hash_layout_purge_requires_update_time_index() -> Result<(), Error> {
+    let mocks = Arc::new(MockRedisBackend::new());
+    let store = make_ac_hash_store(
+        &mocks,
+        &RedisAcHashLayoutSpec {
+            partition: "main".to_string(),
+            entry_ttl_s: 0,
+            index_by_update_time: false,
+        },
+    );
+
+    let result = store
+        .purge_ac_entries_older_than(UNIX_EPOCH + Duration::from_secs(100))
+        .await;
+    assert_eq!(result.unwrap_err().code, Code::InvalidArgument);
+
+    Ok(())
+}
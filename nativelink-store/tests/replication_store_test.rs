@@ -0,0 +1,189 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use nativelink_config::stores::{MemorySpec, ReplicationSpec, StoreSpec};
+use nativelink_error::{Code, Error};
+use nativelink_macro::nativelink_test;
+use nativelink_store::memory_store::MemoryStore;
+use nativelink_store::replication_store::ReplicationStore;
+use nativelink_util::common::DigestInfo;
+use nativelink_util::fake_store_for_tests::FakeStore;
+use nativelink_util::instant_wrapper::MockInstantWrapped;
+use nativelink_util::store_trait::{Store, StoreLike};
+use pretty_assertions::assert_eq;
+
+const VALID_HASH1: &str = "0123456789abcdef000000000000000000010000000000000123456789abcdef";
+const VALUE: &str = "replicated";
+
+type TestReplicationStore = ReplicationStore<fn() -> MockInstantWrapped>;
+
+fn make_fake_store(read_delay: Duration) -> Arc<FakeStore> {
+    FakeStore::new_with_read_delay(
+        Store::new(MemoryStore::new(&MemorySpec::default())),
+        read_delay,
+    )
+}
+
+fn make_store(
+    replicas: &[Arc<FakeStore>],
+    exploration_rate: f64,
+) -> Result<Arc<TestReplicationStore>, Error> {
+    ReplicationStore::new(
+        &ReplicationSpec {
+            replicas: replicas
+                .iter()
+                .map(|_| StoreSpec::memory(MemorySpec::default()))
+                .collect(),
+            smoothing_factor: Some(0.5),
+            exploration_rate: Some(exploration_rate),
+            max_error_rate: None,
+        },
+        replicas
+            .iter()
+            .map(|replica| Store::new(replica.clone()))
+            .collect(),
+        MockInstantWrapped::default,
+    )
+}
+
+#[nativelink_test]
+async fn update_writes_to_every_replica() -> Result<(), Error> {
+    let replicas = [
+        make_fake_store(Duration::ZERO),
+        make_fake_store(Duration::ZERO),
+    ];
+    let store = make_store(&replicas, 0.)?;
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE.len())?;
+
+    store.update_oneshot(digest, VALUE.into()).await?;
+
+    for replica in &replicas {
+        assert_eq!(
+            replica.inner().get_part_unchunked(digest, 0, None).await,
+            Ok(Bytes::from_static(VALUE.as_bytes()))
+        );
+    }
+    Ok(())
+}
+
+#[nativelink_test]
+async fn reads_go_to_fastest_replica() -> Result<(), Error> {
+    let replicas = [
+        make_fake_store(Duration::from_millis(100)),
+        make_fake_store(Duration::from_millis(10)),
+    ];
+    let store = make_store(&replicas, 0.)?;
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE.len())?;
+    store.update_oneshot(digest, VALUE.into()).await?;
+
+    for _ in 0..10 {
+        assert_eq!(
+            store.get_part_unchunked(digest, 0, None).await,
+            Ok(Bytes::from_static(VALUE.as_bytes()))
+        );
+    }
+
+    // Each replica is measured once, after that the fast one wins.
+    assert_eq!(replicas[0].reads(), 1);
+    assert_eq!(replicas[1].reads(), 9);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn failing_replica_is_avoided_and_reads_fail_over() -> Result<(), Error> {
+    let replicas = [
+        make_fake_store(Duration::from_millis(10)),
+        make_fake_store(Duration::from_millis(100)),
+    ];
+    let store = make_store(&replicas, 0.)?;
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE.len())?;
+    store.update_oneshot(digest, VALUE.into()).await?;
+    // Measure both replicas, the first one is the fastest.
+    assert_eq!(store.has(digest).await, Ok(Some(VALUE.len() as u64)));
+    assert_eq!(store.has(digest).await, Ok(Some(VALUE.len() as u64)));
+    assert_eq!((replicas[0].reads(), replicas[1].reads()), (1, 1));
+
+    replicas[0].set_failing(true);
+    for _ in 0..5 {
+        assert_eq!(
+            store.get_part_unchunked(digest, 0, None).await,
+            Ok(Bytes::from_static(VALUE.as_bytes()))
+        );
+    }
+
+    // Two failures push the error rate of the first replica over the
+    // limit, from then on it is skipped.
+    assert_eq!((replicas[0].reads(), replicas[1].reads()), (3, 6));
+    Ok(())
+}
+
+#[nativelink_test]
+async fn read_fails_when_every_replica_fails() -> Result<(), Error> {
+    let replicas = [
+        make_fake_store(Duration::ZERO),
+        make_fake_store(Duration::ZERO),
+    ];
+    let store = make_store(&replicas, 0.)?;
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE.len())?;
+    store.update_oneshot(digest, VALUE.into()).await?;
+    for replica in &replicas {
+        replica.set_failing(true);
+    }
+
+    let err = store.get_part_unchunked(digest, 0, None).await.unwrap_err();
+    assert_eq!(err.code, Code::Unavailable);
+    assert_eq!((replicas[0].reads(), replicas[1].reads()), (1, 1));
+    Ok(())
+}
+
+#[nativelink_test]
+async fn exploration_reads_from_other_replica() -> Result<(), Error> {
+    let replicas = [
+        make_fake_store(Duration::from_millis(100)),
+        make_fake_store(Duration::from_millis(10)),
+    ];
+    let store = make_store(&replicas, 1.)?;
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE.len())?;
+    store.update_oneshot(digest, VALUE.into()).await?;
+
+    for _ in 0..4 {
+        store.get_part_unchunked(digest, 0, None).await?;
+    }
+
+    // The first replica is never measured, so it stays the preferred one
+    // and every read explores the other replica instead.
+    assert_eq!((replicas[0].reads(), replicas[1].reads()), (0, 4));
+    Ok(())
+}
+
+#[nativelink_test]
+async fn rejects_invalid_rates() -> Result<(), Error> {
+    let replica = make_fake_store(Duration::ZERO);
+    let result = ReplicationStore::new(
+        &ReplicationSpec {
+            replicas: vec![StoreSpec::memory(MemorySpec::default())],
+            smoothing_factor: None,
+            exploration_rate: Some(1.5),
+            max_error_rate: None,
+        },
+        vec![Store::new(replica)],
+        MockInstantWrapped::default,
+    );
+    assert!(result.is_err());
+    Ok(())
+}
@@ -0,0 +1,137 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use mock_instant::thread_local::MockClock;
+use nativelink_config::stores::{
+    CircuitBreakerSpec, MemorySpec, NoopSpec, Retry, RetryStoreSpec, StoreSpec,
+};
+use nativelink_error::{Code, Error};
+use nativelink_macro::nativelink_test;
+use nativelink_store::memory_store::MemoryStore;
+use nativelink_store::retry_store::RetryStore;
+use nativelink_util::common::DigestInfo;
+use nativelink_util::fake_store_for_tests::FakeStore;
+use nativelink_util::instant_wrapper::MockInstantWrapped;
+use nativelink_util::store_trait::{Store, StoreLike};
+use pretty_assertions::assert_eq;
+
+const VALID_HASH1: &str = "0123456789abcdef000000000000000000010000000000000123456789abcdef";
+const VALID_HASH2: &str = "0123456789abcdef000000000000000000020000000000000123456789abcdef";
+const VALUE: &str = "retried";
+
+fn make_fake_store() -> Arc<FakeStore> {
+    FakeStore::new(Store::new(MemoryStore::new(&MemorySpec::default())))
+}
+
+fn make_store(
+    fake: &Arc<FakeStore>,
+    max_retries: usize,
+    failure_threshold: u32,
+) -> Arc<RetryStore<MockInstantWrapped>> {
+    RetryStore::new_with_time(
+        &RetryStoreSpec {
+            backend: StoreSpec::noop(NoopSpec::default()), // Note: Not used.
+            retry: Retry {
+                max_retries,
+                ..Default::default()
+            },
+            max_retry_buffer_size: 0,
+            circuit_breaker: CircuitBreakerSpec {
+                failure_threshold,
+                open_seconds: 10,
+            },
+        },
+        Store::new(fake.clone()),
+        Arc::new(|delay| delay),
+        MockInstantWrapped::default(),
+    )
+}
+
+#[nativelink_test]
+async fn retries_failed_requests() -> Result<(), Error> {
+    let fake = make_fake_store();
+    let store = make_store(&fake, 3, 0);
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE.len())?;
+
+    fake.fail_next(2);
+    store.update_oneshot(digest, VALUE.into()).await?;
+    assert_eq!(fake.requests(), 3);
+
+    fake.fail_next(3);
+    assert_eq!(store.has(digest).await, Ok(Some(VALUE.len() as u64)));
+
+    fake.fail_next(1);
+    assert_eq!(
+        store.get_part_unchunked(digest, 0, None).await,
+        Ok(Bytes::from_static(VALUE.as_bytes()))
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn stops_after_max_retries_and_on_permanent_errors() -> Result<(), Error> {
+    let fake = make_fake_store();
+    let store = make_store(&fake, 2, 0);
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE.len())?;
+
+    fake.fail_next(10);
+    let err = store.has(digest).await.unwrap_err();
+    assert_eq!(err.code, Code::Unavailable);
+    assert_eq!(fake.requests(), 3);
+
+    fake.fail_next(0);
+    let missing_digest = DigestInfo::try_new(VALID_HASH2, VALUE.len())?;
+    let err = store
+        .get_part_unchunked(missing_digest, 0, None)
+        .await
+        .unwrap_err();
+    assert_eq!(err.code, Code::NotFound);
+    assert_eq!(fake.requests(), 4);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn circuit_breaker_fails_fast_until_backend_recovers() -> Result<(), Error> {
+    let fake = make_fake_store();
+    let store = make_store(&fake, 0, 2);
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE.len())?;
+
+    fake.fail_next(3);
+    assert!(store.has(digest).await.is_err());
+    assert!(store.has(digest).await.is_err());
+    assert_eq!(fake.requests(), 2);
+
+    // The circuit is open, so the backend is not called.
+    let err = store.has(digest).await.unwrap_err();
+    assert_eq!(err.code, Code::Unavailable);
+    assert_eq!(fake.requests(), 2);
+
+    // The first request after the circuit was open for a while is let
+    // through, and opens it again when it fails.
+    MockClock::advance(Duration::from_secs(11));
+    assert!(store.has(digest).await.is_err());
+    assert_eq!(fake.requests(), 3);
+    assert!(store.has(digest).await.is_err());
+    assert_eq!(fake.requests(), 3);
+
+    MockClock::advance(Duration::from_secs(11));
+    assert_eq!(store.has(digest).await, Ok(None));
+    assert_eq!(store.has(digest).await, Ok(None));
+    assert_eq!(fake.requests(), 5);
+    Ok(())
+}
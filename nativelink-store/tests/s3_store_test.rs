@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -547,6 +548,126 @@ async fn multipart_update_large_cas() -> Result<(), Error> {
     Ok(())
 }
 
+#[nativelink_test]
+async fn multipart_update_retries_failed_part_only() -> Result<(), Error> {
+    // Same as in s3_store.
+    const MIN_MULTIPART_SIZE: usize = 5 * 1024 * 1024; // 5mb.
+    const CAS_ENTRY_SIZE: usize = MIN_MULTIPART_SIZE + 50;
+
+    let mut send_data = Vec::with_capacity(CAS_ENTRY_SIZE);
+    for i in 0..send_data.capacity() {
+        send_data.push(((i * 3) % 256) as u8);
+    }
+    let digest = DigestInfo::try_new(VALID_HASH1, send_data.len())?;
+
+    let upload_part_request = |part_number: usize, data: &[u8]| {
+        http::Request::builder()
+            .uri(format!(
+                "https://{BUCKET_NAME}.s3.{REGION}.amazonaws.com/{VALID_HASH1}-{CAS_ENTRY_SIZE}?x-id=UploadPart&partNumber={part_number}&uploadId=Dummy-uploadid",
+            ))
+            .method("PUT")
+            .header("content-type", "application/octet-stream")
+            .header("content-length", format!("{}", data.len()))
+            .body(SdkBody::from(data.to_vec()))
+            .unwrap()
+    };
+    let ok_response = || {
+        http::Response::builder()
+            .status(StatusCode::OK)
+            .body(SdkBody::empty())
+            .unwrap()
+    };
+
+    let mock_client = StaticReplayClient::new(vec![
+        ReplayEvent::new(
+            http::Request::builder()
+                .uri(format!(
+                    "https://{BUCKET_NAME}.s3.{REGION}.amazonaws.com/{VALID_HASH1}-{CAS_ENTRY_SIZE}?uploads",
+                ))
+                .method("POST")
+                .body(SdkBody::empty())
+                .unwrap(),
+            http::Response::builder()
+                .status(StatusCode::OK)
+                .body(SdkBody::from(
+                    r#"
+                    <InitiateMultipartUploadResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+                      <UploadId>Dummy-uploadid</UploadId>
+                    </InitiateMultipartUploadResult>"#
+                        .as_bytes(),
+                ))
+                .unwrap(),
+        ),
+        ReplayEvent::new(
+            upload_part_request(1, &send_data[0..MIN_MULTIPART_SIZE]),
+            ok_response(),
+        ),
+        // The second part fails once and only that part is sent again.
+        ReplayEvent::new(
+            upload_part_request(2, &send_data[MIN_MULTIPART_SIZE..]),
+            http::Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(SdkBody::empty())
+                .unwrap(),
+        ),
+        ReplayEvent::new(
+            upload_part_request(2, &send_data[MIN_MULTIPART_SIZE..]),
+            ok_response(),
+        ),
+        ReplayEvent::new(
+            http::Request::builder()
+                .uri(format!(
+                    "https://{BUCKET_NAME}.s3.{REGION}.amazonaws.com/{VALID_HASH1}-{CAS_ENTRY_SIZE}?uploadId=Dummy-uploadid",
+                ))
+                .method("POST")
+                .header("content-length", "177")
+                .body(SdkBody::from(concat!(
+                    r#"<CompleteMultipartUpload xmlns="http://s3.amazonaws.com/doc/2006-03-01/">"#,
+                    "<Part><PartNumber>1</PartNumber></Part>",
+                    "<Part><PartNumber>2</PartNumber></Part>",
+                    "</CompleteMultipartUpload>",
+                )))
+                .unwrap(),
+            http::Response::builder()
+                .status(StatusCode::OK)
+                .body(SdkBody::from(concat!(
+                    "<CompleteMultipartUploadResult>",
+                    "</CompleteMultipartUploadResult>",
+                )))
+                .unwrap(),
+        ),
+    ]);
+    let test_config = Builder::new()
+        .behavior_version(BehaviorVersion::v2024_03_28())
+        .region(Region::from_static(REGION))
+        .http_client(mock_client.clone())
+        .build();
+    let s3_client = aws_sdk_s3::Client::from_conf(test_config);
+    let store = S3Store::new_with_client_and_jitter(
+        &S3Spec {
+            bucket: BUCKET_NAME.to_string(),
+            retry: nativelink_config::stores::Retry {
+                max_retries: 1024,
+                delay: 0.,
+                jitter: 0.,
+                ..Default::default()
+            },
+            // Upload one part at a time so the requests are made in order.
+            multipart_max_concurrent_uploads: Some(1),
+            ..Default::default()
+        },
+        s3_client,
+        Arc::new(move |_delay| Duration::from_secs(0)),
+        MockInstantWrapped::default,
+    )?;
+    store
+        .update_oneshot(digest, send_data.clone().into())
+        .await
+        .unwrap();
+    mock_client.assert_requests_match(&[]);
+    Ok(())
+}
+
 #[nativelink_test]
 async fn ensure_empty_string_in_stream_works_test() -> Result<(), Error> {
     const CAS_ENTRY_SIZE: usize = 10; // Length of "helloworld".
@@ -735,3 +856,144 @@ async fn has_with_expired_result() -> Result<(), Error> {
 
     Ok(())
 }
+
+#[nativelink_test]
+async fn update_sets_object_tags_and_storage_class() -> Result<(), Error> {
+    const CONTENT_LENGTH: u64 = 50;
+    let (mock_client, request_receiver) =
+        aws_smithy_runtime::client::http::test_util::capture_request(None);
+    let test_config = Builder::new()
+        .behavior_version(BehaviorVersion::v2024_03_28())
+        .region(Region::from_static(REGION))
+        .http_client(mock_client)
+        .build();
+    let s3_client = aws_sdk_s3::Client::from_conf(test_config);
+    let store = S3Store::new_with_client_and_jitter(
+        &S3Spec {
+            bucket: BUCKET_NAME.to_string(),
+            object_tags: HashMap::from([("team".to_string(), "remote build".to_string())]),
+            storage_class: Some("STANDARD_IA".to_string()),
+            last_accessed_tag_refresh_s: 60 * 60,
+            ..Default::default()
+        },
+        s3_client,
+        Arc::new(move |_delay| Duration::from_secs(0)),
+        MockInstantWrapped::default,
+    )?;
+
+    // Time starts at 1970-01-01 00:00:00.
+    MockClock::advance(Duration::from_secs(42));
+    let (_tx, rx) = make_buf_channel_pair();
+    let mut update_fut = Box::pin(store.update(
+        DigestInfo::try_new(VALID_HASH1, CONTENT_LENGTH)?,
+        rx,
+        UploadSizeInfo::ExactSize(CONTENT_LENGTH),
+    ));
+    // Polling once sends the request, the body is never sent.
+    let _ = futures::poll!(&mut update_fut);
+
+    let sent_request = request_receiver.expect_request();
+    assert_eq!(sent_request.method(), "PUT");
+    assert_eq!(
+        sent_request.headers().get("x-amz-tagging"),
+        Some("team=remote%20build&last-accessed=42")
+    );
+    assert_eq!(
+        sent_request.headers().get("x-amz-storage-class"),
+        Some("STANDARD_IA")
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn get_part_refreshes_last_accessed_tag() -> Result<(), Error> {
+    const VALUE: &str = "23";
+    const REFRESH_S: u64 = 60 * 60;
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE.len())?;
+    let get_event = || {
+        ReplayEvent::new(
+            http::Request::builder().body(SdkBody::empty()).unwrap(),
+            http::Response::builder()
+                .status(StatusCode::OK)
+                .body(SdkBody::from(VALUE))
+                .unwrap(),
+        )
+    };
+    let tagging_event = || {
+        ReplayEvent::new(
+            http::Request::builder().body(SdkBody::empty()).unwrap(),
+            http::Response::builder()
+                .status(StatusCode::OK)
+                .body(SdkBody::empty())
+                .unwrap(),
+        )
+    };
+    let mock_client = StaticReplayClient::new(vec![
+        get_event(),
+        tagging_event(),
+        get_event(),
+        get_event(),
+        tagging_event(),
+    ]);
+    let test_config = Builder::new()
+        .behavior_version(BehaviorVersion::v2024_03_28())
+        .region(Region::from_static(REGION))
+        .http_client(mock_client.clone())
+        .build();
+    let s3_client = aws_sdk_s3::Client::from_conf(test_config);
+    let store = S3Store::new_with_client_and_jitter(
+        &S3Spec {
+            bucket: BUCKET_NAME.to_string(),
+            last_accessed_tag_refresh_s: REFRESH_S as u32,
+            ..Default::default()
+        },
+        s3_client,
+        Arc::new(move |_delay| Duration::from_secs(0)),
+        MockInstantWrapped::default,
+    )?;
+    // The tag is refreshed in the background.
+    let wait_for_requests = |count: usize| {
+        let mock_client = mock_client.clone();
+        async move {
+            while mock_client.actual_requests().count() < count {
+                tokio::task::yield_now().await;
+            }
+        }
+    };
+
+    // The first read sets the tag.
+    assert_eq!(store.get_part_unchunked(digest, 0, None).await?, VALUE);
+    wait_for_requests(2).await;
+    // A read right after it doesn't.
+    assert_eq!(store.get_part_unchunked(digest, 0, None).await?, VALUE);
+    wait_for_requests(3).await;
+    // Once the refresh interval passed, the next read sets it again.
+    MockClock::advance(Duration::from_secs(REFRESH_S));
+    assert_eq!(store.get_part_unchunked(digest, 0, None).await?, VALUE);
+    wait_for_requests(5).await;
+
+    let requests: Vec<_> = mock_client
+        .actual_requests()
+        .map(|request| {
+            (
+                request.method().to_string(),
+                request.uri().to_string(),
+                String::from_utf8_lossy(request.body().bytes().unwrap_or_default()).to_string(),
+            )
+        })
+        .collect();
+    let tagging_uri = format!(
+        "https://{BUCKET_NAME}.s3.{REGION}.amazonaws.com/{VALID_HASH1}-{}?tagging",
+        VALUE.len()
+    );
+    assert_eq!(requests[1].0, "PUT");
+    assert_eq!(requests[1].1, tagging_uri);
+    assert!(requests[1]
+        .2
+        .contains("<Tag><Key>last-accessed</Key><Value>0</Value></Tag>"));
+    assert_eq!(requests[4].1, tagging_uri);
+    assert!(requests[4]
+        .2
+        .contains(&format!("<Value>{REFRESH_S}</Value>")));
+    Ok(())
+}
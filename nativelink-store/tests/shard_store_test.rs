@@ -43,6 +43,7 @@ fn make_stores(weights: &[u32]) -> (Arc<ShardStore>, Vec<Arc<MemoryStore>>) {
                 .map(|weight| nativelink_config::stores::ShardConfig {
                     store: store_config.clone(),
                     weight: Some(*weight),
+                    previous_weight: None,
                 })
                 .collect(),
         },
@@ -307,3 +308,87 @@ async fn verify_weights_left_bias() -> Result<(), Error> {
 async fn verify_weights_right_bias() -> Result<(), Error> {
     verify_weights(&[1, 1, 1, 1, 1, 100], &[5, 13, 12, 5, 11, 954], 1000, false).await
 }
+
+fn make_rebalancing_stores(
+    weights_and_previous_weights: &[(u32, Option<u32>)],
+) -> (Arc<ShardStore>, Vec<Arc<MemoryStore>>) {
+    let memory_store_config = MemorySpec::default();
+    let stores: Vec<_> = weights_and_previous_weights
+        .iter()
+        .map(|_| MemoryStore::new(&memory_store_config))
+        .collect();
+    let shard_store = ShardStore::new(
+        &ShardSpec {
+            stores: weights_and_previous_weights
+                .iter()
+                .map(
+                    |(weight, previous_weight)| nativelink_config::stores::ShardConfig {
+                        store: StoreSpec::memory(memory_store_config.clone()),
+                        weight: Some(*weight),
+                        previous_weight: *previous_weight,
+                    },
+                )
+                .collect(),
+        },
+        stores
+            .iter()
+            .map(|store| Store::new(store.clone()))
+            .collect(),
+    )
+    .unwrap();
+    (shard_store, stores)
+}
+
+#[nativelink_test]
+async fn rebalance_moves_objects_to_added_store_on_read() -> Result<(), Error> {
+    // Store 1 was added, so everything used to be in store 0.
+    let (shard_store, stores) = make_rebalancing_stores(&[(1, Some(1)), (1, None)]);
+
+    let original_data = make_random_data(MEGABYTE_SZ);
+    let digest1 = DigestInfo::try_new(STORE1_HASH, 100).unwrap();
+    stores[0]
+        .update_oneshot(digest1, original_data.clone().into())
+        .await?;
+
+    assert_eq!(shard_store.has(digest1).await, Ok(Some(MEGABYTE_SZ as u64)));
+    assert_eq!(stores[1].has(digest1).await, Ok(None));
+    assert_eq!(
+        shard_store.get_part_unchunked(digest1, 10, Some(20)).await,
+        Ok(original_data[10..30].to_vec().into())
+    );
+    assert_eq!(stores[1].has(digest1).await, Ok(Some(MEGABYTE_SZ as u64)));
+
+    let missing_digest = DigestInfo::try_new(STORE1_HASH, 200).unwrap();
+    assert_eq!(shard_store.has(missing_digest).await, Ok(None));
+    assert!(shard_store
+        .get_part_unchunked(missing_digest, 0, None)
+        .await
+        .is_err());
+    Ok(())
+}
+
+#[nativelink_test]
+async fn rebalance_drains_store_with_zero_weight() -> Result<(), Error> {
+    // Store 1 is being removed.
+    let (shard_store, stores) = make_rebalancing_stores(&[(1, Some(1)), (0, Some(1))]);
+
+    let original_data = make_random_data(MEGABYTE_SZ);
+    let digest1 = DigestInfo::try_new(STORE1_HASH, 100).unwrap();
+    stores[1]
+        .update_oneshot(digest1, original_data.clone().into())
+        .await?;
+
+    assert_eq!(
+        shard_store.get_part_unchunked(digest1, 0, None).await,
+        Ok(original_data.clone().into())
+    );
+    assert_eq!(stores[0].has(digest1).await, Ok(Some(MEGABYTE_SZ as u64)));
+
+    // New uploads never go to the store that is being removed.
+    let digest2 = DigestInfo::try_new(STORE1_HASH, 200).unwrap();
+    shard_store
+        .update_oneshot(digest2, original_data.into())
+        .await?;
+    assert_eq!(stores[1].has(digest2).await, Ok(None));
+    Ok(())
+}
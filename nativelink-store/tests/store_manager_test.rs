@@ -0,0 +1,101 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+
+use nativelink_config::stores::{
+    FastSlowPopulationSpec, FastSlowSpec, MemorySpec, RefSpec, StoreSpec,
+};
+use nativelink_error::{Code, Error};
+use nativelink_macro::nativelink_test;
+use nativelink_store::store_manager::StoreManager;
+use pretty_assertions::assert_eq;
+
+fn ref_spec(name: &str) -> StoreSpec {
+    StoreSpec::ref_store(RefSpec {
+        name: name.to_string(),
+    })
+}
+
+fn fast_slow_spec(fast: StoreSpec, slow: StoreSpec) -> StoreSpec {
+    StoreSpec::fast_slow(Box::new(FastSlowSpec {
+        fast,
+        slow,
+        popularity: None,
+        population: FastSlowPopulationSpec::default(),
+        write_back: false,
+    }))
+}
+
+#[nativelink_test]
+async fn validate_specs_returns_store_tree() -> Result<(), Error> {
+    let stores = HashMap::from([
+        (
+            "CAS".to_string(),
+            fast_slow_spec(StoreSpec::memory(MemorySpec::default()), ref_spec("SLOW")),
+        ),
+        ("SLOW".to_string(), StoreSpec::memory(MemorySpec::default())),
+    ]);
+    assert_eq!(
+        StoreManager::validate_specs(&stores)?,
+        concat!(
+            "CAS: fast_slow\n",
+            "  fast: memory\n",
+            "  slow: ref_store -> SLOW\n",
+            "SLOW: memory\n",
+        )
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn validate_specs_rejects_broken_graphs() -> Result<(), Error> {
+    let missing_ref = HashMap::from([(
+        "CAS".to_string(),
+        fast_slow_spec(StoreSpec::memory(MemorySpec::default()), ref_spec("SLOW")),
+    )]);
+    let err = StoreManager::validate_specs(&missing_ref).unwrap_err();
+    assert_eq!(err.code, Code::InvalidArgument);
+    assert_eq!(
+        err.messages,
+        vec!["Store CAS.slow refers to store 'SLOW' which is not configured".to_string()]
+    );
+
+    let cycle = HashMap::from([
+        (
+            "A".to_string(),
+            fast_slow_spec(StoreSpec::memory(MemorySpec::default()), ref_spec("B")),
+        ),
+        ("B".to_string(), ref_spec("A")),
+    ]);
+    let err = StoreManager::validate_specs(&cycle).unwrap_err();
+    assert_eq!(
+        err.messages,
+        vec!["Stores refer to each other in a cycle: A -> B -> A".to_string()]
+    );
+
+    let same_store = HashMap::from([
+        (
+            "CAS".to_string(),
+            fast_slow_spec(ref_spec("MEM"), ref_spec("MEM")),
+        ),
+        ("MEM".to_string(), StoreSpec::memory(MemorySpec::default())),
+    ]);
+    let err = StoreManager::validate_specs(&same_store).unwrap_err();
+    assert_eq!(
+        err.messages,
+        vec!["The fast and slow stores of CAS are the same store".to_string()]
+    );
+    Ok(())
+}
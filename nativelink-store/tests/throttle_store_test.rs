@@ -0,0 +1,122 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::pin::pin;
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::poll;
+use mock_instant::thread_local::MockClock;
+use nativelink_config::stores::{MemorySpec, NoopSpec, StoreSpec, ThrottleSpec};
+use nativelink_error::{Error, ResultExt};
+use nativelink_macro::nativelink_test;
+use nativelink_store::memory_store::MemoryStore;
+use nativelink_store::throttle_store::ThrottleStore;
+use nativelink_util::common::DigestInfo;
+use nativelink_util::instant_wrapper::MockInstantWrapped;
+use nativelink_util::origin_context::{ActiveOriginContext, OriginContext, ORIGIN_IDENTITY};
+use nativelink_util::store_trait::{Store, StoreLike};
+use pretty_assertions::assert_eq;
+use tracing::info_span;
+
+const VALID_HASH1: &str = "0123456789abcdef000000000000000000010000000000000123456789abcdef";
+
+fn make_spec() -> ThrottleSpec {
+    ThrottleSpec {
+        backend: StoreSpec::noop(NoopSpec::default()), // Note: Not used.
+        max_read_bytes_per_second: 0,
+        max_write_bytes_per_second: 0,
+        max_read_bytes_per_second_per_identity: 0,
+        max_write_bytes_per_second_per_identity: 0,
+    }
+}
+
+fn make_ctx_for_identity(identity: &str) -> Result<Arc<OriginContext>, Error> {
+    let mut ctx = ActiveOriginContext::fork().err_tip(|| "In make_ctx_for_identity")?;
+    ctx.set_value(&ORIGIN_IDENTITY, Arc::new(identity.to_string()));
+    Ok(Arc::new(ctx))
+}
+
+#[nativelink_test]
+async fn slows_down_writes_over_limit() -> Result<(), Error> {
+    let inner_store = MemoryStore::new(&MemorySpec::default());
+    let store = ThrottleStore::new_with_time(
+        &ThrottleSpec {
+            max_write_bytes_per_second: 100,
+            ..make_spec()
+        },
+        Store::new(inner_store.clone()),
+        MockInstantWrapped::default(),
+    );
+    let data = Bytes::from(vec![1u8; 300]);
+    let digest = DigestInfo::try_new(VALID_HASH1, data.len())?;
+
+    // The first 100 bytes fit in the limit, the other 200 take two seconds.
+    let mut update_fut = pin!(store.update_oneshot(digest, data.clone()));
+    assert_eq!(poll!(&mut update_fut), Poll::Pending);
+    MockClock::advance(Duration::from_secs(1));
+    assert_eq!(poll!(&mut update_fut), Poll::Pending);
+    MockClock::advance(Duration::from_secs(1));
+    update_fut.await?;
+
+    assert_eq!(
+        inner_store.get_part_unchunked(digest, 0, None).await,
+        Ok(data)
+    );
+    // Reads are not limited.
+    store.get_part_unchunked(digest, 0, None).await?;
+    Ok(())
+}
+
+#[nativelink_test]
+async fn limits_reads_of_each_identity() -> Result<(), Error> {
+    let inner_store = MemoryStore::new(&MemorySpec::default());
+    let store = ThrottleStore::new_with_time(
+        &ThrottleSpec {
+            max_read_bytes_per_second_per_identity: 100,
+            ..make_spec()
+        },
+        Store::new(inner_store.clone()),
+        MockInstantWrapped::default(),
+    );
+    let data = Bytes::from(vec![1u8; 100]);
+    let digest = DigestInfo::try_new(VALID_HASH1, data.len())?;
+    inner_store.update_oneshot(digest, data.clone()).await?;
+
+    let backfill_ctx = make_ctx_for_identity("backfill")?;
+    let build_ctx = make_ctx_for_identity("build")?;
+    assert_eq!(
+        backfill_ctx
+            .clone()
+            .wrap_async(info_span!("get"), store.get_part_unchunked(digest, 0, None))
+            .await,
+        Ok(data.clone())
+    );
+    // Another client is not slowed down by the first one.
+    assert_eq!(
+        build_ctx
+            .wrap_async(info_span!("get"), store.get_part_unchunked(digest, 0, None))
+            .await,
+        Ok(data.clone())
+    );
+
+    let mut get_fut =
+        pin!(backfill_ctx.wrap_async(info_span!("get"), store.get_part_unchunked(digest, 0, None)));
+    assert_eq!(poll!(&mut get_fut), Poll::Pending);
+    MockClock::advance(Duration::from_secs(1));
+    assert_eq!(get_fut.await, Ok(data));
+    Ok(())
+}
@@ -0,0 +1,136 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use nativelink_config::stores::{MemorySpec, StoreSpec, TranslationSpec};
+use nativelink_error::{Code, Error};
+use nativelink_macro::nativelink_test;
+use nativelink_store::memory_store::MemoryStore;
+use nativelink_store::translation_store::TranslationStore;
+use nativelink_util::common::DigestInfo;
+use nativelink_util::digest_hasher::{make_ctx_for_hash_func, DigestHasher, DigestHasherFunc};
+use nativelink_util::store_trait::{Store, StoreLike};
+use pretty_assertions::assert_eq;
+use tracing::info_span;
+
+const VALUE: &str = "123456789";
+
+fn digest_of(func: DigestHasherFunc, data: &str) -> DigestInfo {
+    let mut hasher = func.hasher();
+    hasher.update(data.as_bytes());
+    hasher.finalize_digest()
+}
+
+fn setup_stores() -> (Arc<TranslationStore>, Arc<MemoryStore>) {
+    let backend_store = MemoryStore::new(&MemorySpec::default());
+    let translation_store = TranslationStore::new(
+        &TranslationSpec {
+            backend: StoreSpec::memory(MemorySpec::default()),
+            mapping_store: StoreSpec::memory(MemorySpec::default()),
+            backend_digest_function: None,
+        },
+        Store::new(backend_store.clone()),
+        Store::new(MemoryStore::new(&MemorySpec::default())),
+    );
+    (translation_store, backend_store)
+}
+
+#[nativelink_test]
+async fn blake3_upload_is_stored_under_sha256_test() -> Result<(), Error> {
+    let (translation_store, backend_store) = setup_stores();
+    let blake3_digest = digest_of(DigestHasherFunc::Blake3, VALUE);
+    let sha256_digest = digest_of(DigestHasherFunc::Sha256, VALUE);
+
+    let blake3_ctx = make_ctx_for_hash_func(DigestHasherFunc::Blake3)?;
+    blake3_ctx
+        .clone()
+        .wrap_async(
+            info_span!("update_oneshot"),
+            translation_store.update_oneshot(blake3_digest, VALUE.into()),
+        )
+        .await?;
+
+    assert_eq!(
+        backend_store.has(sha256_digest).await?,
+        Some(VALUE.len() as u64),
+        "Expected backend to hold the data under its sha256 digest"
+    );
+    assert_eq!(
+        backend_store.has(blake3_digest).await?,
+        None,
+        "Expected backend to not hold the data under its blake3 digest"
+    );
+
+    let has_result = blake3_ctx
+        .clone()
+        .wrap_async(info_span!("has"), translation_store.has(blake3_digest))
+        .await?;
+    assert_eq!(has_result, Some(VALUE.len() as u64));
+
+    let data = blake3_ctx
+        .wrap_async(
+            info_span!("get_part_unchunked"),
+            translation_store.get_part_unchunked(blake3_digest, 0, None),
+        )
+        .await?;
+    assert_eq!(data, VALUE.as_bytes());
+    Ok(())
+}
+
+#[nativelink_test]
+async fn sha256_upload_is_readable_with_blake3_test() -> Result<(), Error> {
+    let (translation_store, _backend_store) = setup_stores();
+    let blake3_digest = digest_of(DigestHasherFunc::Blake3, VALUE);
+    let sha256_digest = digest_of(DigestHasherFunc::Sha256, VALUE);
+
+    make_ctx_for_hash_func(DigestHasherFunc::Sha256)?
+        .wrap_async(
+            info_span!("update_oneshot"),
+            translation_store.update_oneshot(sha256_digest, VALUE.into()),
+        )
+        .await?;
+
+    let data = make_ctx_for_hash_func(DigestHasherFunc::Blake3)?
+        .wrap_async(
+            info_span!("get_part_unchunked"),
+            translation_store.get_part_unchunked(blake3_digest, 0, None),
+        )
+        .await?;
+    assert_eq!(data, VALUE.as_bytes());
+    Ok(())
+}
+
+#[nativelink_test]
+async fn unknown_translated_digest_is_missing_test() -> Result<(), Error> {
+    let (translation_store, _backend_store) = setup_stores();
+    let blake3_digest = digest_of(DigestHasherFunc::Blake3, VALUE);
+    let blake3_ctx = make_ctx_for_hash_func(DigestHasherFunc::Blake3)?;
+
+    let has_result = blake3_ctx
+        .clone()
+        .wrap_async(info_span!("has"), translation_store.has(blake3_digest))
+        .await?;
+    assert_eq!(has_result, None);
+
+    let err = blake3_ctx
+        .wrap_async(
+            info_span!("get_part_unchunked"),
+            translation_store.get_part_unchunked(blake3_digest, 0, None),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(err.code, Code::NotFound);
+    Ok(())
+}
@@ -16,7 +16,7 @@ use std::pin::Pin;
 
 use futures::future::pending;
 use futures::try_join;
-use nativelink_config::stores::{MemorySpec, StoreSpec, VerifySpec};
+use nativelink_config::stores::{MemorySpec, StoreSpec, VerifyFailureAction, VerifySpec};
 use nativelink_error::{Error, ResultExt};
 use nativelink_macro::nativelink_test;
 use nativelink_store::memory_store::MemoryStore;
@@ -41,6 +41,8 @@ async fn verify_size_false_passes_on_update() -> Result<(), Error> {
             backend: StoreSpec::memory(MemorySpec::default()),
             verify_size: false,
             verify_hash: false,
+            verify_hash_on_read: false,
+            on_failure: VerifyFailureAction::reject,
         },
         Store::new(inner_store.clone()),
     );
@@ -72,6 +74,8 @@ async fn verify_size_true_fails_on_update() -> Result<(), Error> {
             backend: StoreSpec::memory(MemorySpec::default()),
             verify_size: true,
             verify_hash: false,
+            verify_hash_on_read: false,
+            on_failure: VerifyFailureAction::reject,
         },
         Store::new(inner_store.clone()),
     );
@@ -110,6 +114,8 @@ async fn verify_size_true_suceeds_on_update() -> Result<(), Error> {
             backend: StoreSpec::memory(MemorySpec::default()),
             verify_size: true,
             verify_hash: false,
+            verify_hash_on_read: false,
+            on_failure: VerifyFailureAction::reject,
         },
         Store::new(inner_store.clone()),
     );
@@ -133,6 +139,8 @@ async fn verify_size_true_suceeds_on_multi_chunk_stream_update() -> Result<(), E
             backend: StoreSpec::memory(MemorySpec::default()),
             verify_size: true,
             verify_hash: false,
+            verify_hash_on_read: false,
+            on_failure: VerifyFailureAction::reject,
         },
         Store::new(inner_store.clone()),
     );
@@ -173,6 +181,8 @@ async fn verify_sha256_hash_true_suceeds_on_update() -> Result<(), Error> {
             backend: StoreSpec::memory(MemorySpec::default()),
             verify_size: false,
             verify_hash: true,
+            verify_hash_on_read: false,
+            on_failure: VerifyFailureAction::reject,
         },
         Store::new(inner_store.clone()),
     );
@@ -201,6 +211,8 @@ async fn verify_sha256_hash_true_fails_on_update() -> Result<(), Error> {
             backend: StoreSpec::memory(MemorySpec::default()),
             verify_size: false,
             verify_hash: true,
+            verify_hash_on_read: false,
+            on_failure: VerifyFailureAction::reject,
         },
         Store::new(inner_store.clone()),
     );
@@ -234,6 +246,8 @@ async fn verify_blake3_hash_true_suceeds_on_update() -> Result<(), Error> {
             backend: StoreSpec::memory(MemorySpec::default()),
             verify_size: false,
             verify_hash: true,
+            verify_hash_on_read: false,
+            on_failure: VerifyFailureAction::reject,
         },
         Store::new(inner_store.clone()),
     );
@@ -268,6 +282,8 @@ async fn verify_blake3_hash_true_fails_on_update() -> Result<(), Error> {
             backend: StoreSpec::memory(MemorySpec::default()),
             verify_size: false,
             verify_hash: true,
+            verify_hash_on_read: false,
+            on_failure: VerifyFailureAction::reject,
         },
         Store::new(inner_store.clone()),
     );
@@ -311,6 +327,8 @@ async fn verify_fails_immediately_on_too_much_data_sent_update() -> Result<(), E
             backend: StoreSpec::memory(MemorySpec::default()),
             verify_size: true,
             verify_hash: false,
+            verify_hash_on_read: false,
+            on_failure: VerifyFailureAction::reject,
         },
         Store::new(inner_store.clone()),
     );
@@ -355,6 +373,8 @@ async fn verify_size_and_hash_suceeds_on_small_data() -> Result<(), Error> {
             backend: StoreSpec::memory(MemorySpec::default()),
             verify_size: true,
             verify_hash: true,
+            verify_hash_on_read: false,
+            on_failure: VerifyFailureAction::reject,
         },
         Store::new(inner_store.clone()),
     );
@@ -369,3 +389,91 @@ async fn verify_size_and_hash_suceeds_on_small_data() -> Result<(), Error> {
     );
     Ok(())
 }
+
+#[nativelink_test]
+async fn verify_hash_on_read_fails_on_corrupted_data() -> Result<(), Error> {
+    /// This value is sha256("123").
+    const HASH: &str = "a665a45920422f9d417e4867efdc4fb8a04a1f3fff1fa07e998e86f7f7a27ae3";
+    const VALUE: &str = "123";
+    const CORRUPTED_VALUE: &str = "124";
+
+    let inner_store = MemoryStore::new(&MemorySpec::default());
+    let store = VerifyStore::new(
+        &VerifySpec {
+            backend: StoreSpec::memory(MemorySpec::default()),
+            verify_size: true,
+            verify_hash: true,
+            verify_hash_on_read: true,
+            on_failure: VerifyFailureAction::reject,
+        },
+        Store::new(inner_store.clone()),
+    );
+
+    let digest = DigestInfo::try_new(HASH, 3).unwrap();
+    store.update_oneshot(digest, VALUE.into()).await?;
+    assert_eq!(
+        store.get_part_unchunked(digest, 0, None).await,
+        Ok(VALUE.into()),
+        "Expected intact data to be readable"
+    );
+
+    // Corrupt the data behind the verify store's back.
+    inner_store
+        .update_oneshot(digest, CORRUPTED_VALUE.into())
+        .await?;
+    let err = store
+        .get_part_unchunked(digest, 0, None)
+        .await
+        .unwrap_err()
+        .to_string();
+    assert!(
+        err.contains("Hashes do not match on read"),
+        "Expected hash mismatch error, got: {err:?}"
+    );
+
+    // Partial reads can not be verified and are passed through.
+    assert_eq!(
+        store.get_part_unchunked(digest, 1, None).await,
+        Ok("24".into()),
+        "Expected partial read to pass through"
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn verify_on_failure_log_lets_data_through() -> Result<(), Error> {
+    /// This value is sha256("123").
+    const HASH: &str = "a665a45920422f9d417e4867efdc4fb8a04a1f3fff1fa07e998e86f7f7a27ae3";
+    const VALUE: &str = "1234";
+
+    let inner_store = MemoryStore::new(&MemorySpec::default());
+    let store = VerifyStore::new(
+        &VerifySpec {
+            backend: StoreSpec::memory(MemorySpec::default()),
+            verify_size: true,
+            verify_hash: true,
+            verify_hash_on_read: true,
+            on_failure: VerifyFailureAction::log,
+        },
+        Store::new(inner_store.clone()),
+    );
+
+    // Both the size and the hash are wrong.
+    let digest = DigestInfo::try_new(HASH, 3).unwrap();
+    let (mut tx, rx) = make_buf_channel_pair();
+    let send_fut = async move {
+        tx.send(VALUE.into()).await?;
+        tx.send_eof()
+    };
+    let result = try_join!(
+        send_fut,
+        store.update(digest, rx, UploadSizeInfo::MaxSize(4))
+    );
+    assert_eq!(result, Ok(((), ())), "Expected update to succeed");
+    assert_eq!(
+        store.get_part_unchunked(digest, 0, None).await,
+        Ok(VALUE.into()),
+        "Expected read to succeed"
+    );
+    Ok(())
+}
@@ -0,0 +1,74 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use bytes::Bytes;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures::join;
+use nativelink_util::buf_channel::make_buf_channel_pair;
+use tokio::runtime::Runtime;
+
+/// Bytes sent through the channel in each iteration.
+const TOTAL_SIZE: usize = 16 * 1024 * 1024;
+
+/// Chunk sizes that match small reads, gRPC messages and large reads
+/// respectively.
+const CHUNK_SIZES: [usize; 3] = [4 * 1024, 64 * 1024, 1024 * 1024];
+
+#[allow(clippy::disallowed_methods)]
+fn make_runtime() -> Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+async fn send_through_channel(chunk: Bytes) {
+    let (mut tx, mut rx) = make_buf_channel_pair();
+    let send_fut = async move {
+        for _ in 0..TOTAL_SIZE / chunk.len() {
+            tx.send(chunk.clone()).await.unwrap();
+        }
+        tx.send_eof().unwrap();
+    };
+    let recv_fut = async move {
+        loop {
+            let data = rx.recv().await.unwrap();
+            if data.is_empty() {
+                break;
+            }
+            black_box(data);
+        }
+    };
+    join!(send_fut, recv_fut);
+}
+
+fn buf_channel_benchmark(c: &mut Criterion) {
+    let runtime = make_runtime();
+    let mut group = c.benchmark_group("buf_channel");
+    group.throughput(Throughput::Bytes(TOTAL_SIZE as u64));
+    for chunk_size in CHUNK_SIZES {
+        let chunk = Bytes::from(vec![0x5a_u8; chunk_size]);
+        group.bench_with_input(
+            BenchmarkId::new("send_recv", chunk_size),
+            &chunk,
+            |b, chunk| {
+                #[allow(clippy::disallowed_methods)]
+                b.iter(|| runtime.block_on(send_through_channel(chunk.clone())));
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, buf_channel_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,41 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers shared by the benchmarks. The `nativelink-store` benchmarks
+//! include this file with `#[path]` so both crates use the same runtime
+//! setup.
+
+use std::future::Future;
+use std::sync::Arc;
+
+use nativelink_util::origin_context::OriginContext;
+use tokio::runtime::Runtime;
+use tracing::info_span;
+
+/// Builds a multi threaded runtime. `None` uses one worker per core.
+#[allow(clippy::disallowed_methods)]
+pub(crate) fn make_runtime(worker_threads: Option<usize>) -> Runtime {
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    if let Some(worker_threads) = worker_threads {
+        builder.worker_threads(worker_threads);
+    }
+    builder.enable_all().build().unwrap()
+}
+
+/// Runs `fut` inside an `OriginContext` the way `nativelink_test` runs
+/// tests.
+#[allow(clippy::disallowed_methods)]
+pub(crate) fn block_on<T>(runtime: &Runtime, bench: &str, fut: impl Future<Output = T>) -> T {
+    runtime.block_on(Arc::new(OriginContext::new()).wrap_async(info_span!("bench", bench), fut))
+}
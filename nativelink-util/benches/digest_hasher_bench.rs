@@ -0,0 +1,54 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use nativelink_util::digest_hasher::{
+    DigestHasher, DigestHasherFunc, DigestHasherImpl, Sha256Backend,
+};
+
+/// Sizes that roughly match small action outputs, typical objects and
+/// large objects respectively.
+const INPUT_SIZES: [usize; 3] = [4 * 1024, 1024 * 1024, 16 * 1024 * 1024];
+
+/// Feed the hasher in the same sized chunks we read from disk.
+const CHUNK_SIZE: usize = 16 * 1024;
+
+fn hash(mut hasher: DigestHasherImpl, data: &[u8]) {
+    for chunk in data.chunks(CHUNK_SIZE) {
+        hasher.update(chunk);
+    }
+    criterion::black_box(hasher.finalize_digest());
+}
+
+fn digest_hasher_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("digest_hasher");
+    for size in INPUT_SIZES {
+        let data = vec![0x5a_u8; size];
+        group.throughput(Throughput::Bytes(size as u64));
+        for backend in [Sha256Backend::Sha2, Sha256Backend::Ring] {
+            group.bench_with_input(
+                BenchmarkId::new(format!("sha256_{backend:?}"), size),
+                &data,
+                |b, data| b.iter(|| hash(backend.hasher(), data)),
+            );
+        }
+        group.bench_with_input(BenchmarkId::new("blake3", size), &data, |b, data| {
+            b.iter(|| hash(DigestHasherFunc::Blake3.hasher(), data));
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, digest_hasher_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,47 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use nativelink_util::common::DigestInfo;
+use nativelink_util::store_trait::{StoreKey, StoreKeyEncoding};
+
+const HASH: &str = "0123456789abcdef000000000000000000010000000000000123456789abcdef";
+const SIZE: u64 = 142_857;
+
+fn digest_info_benchmark(c: &mut Criterion) {
+    let digest = DigestInfo::try_new(HASH, SIZE).unwrap();
+    let key = StoreKey::Digest(digest);
+    let mut group = c.benchmark_group("digest_info");
+    group.bench_function("display", |b| {
+        b.iter(|| black_box(black_box(&digest).to_string()));
+    });
+    group.bench_function("parse", |b| {
+        b.iter(|| black_box(DigestInfo::try_new(black_box(HASH), SIZE).unwrap()));
+    });
+    for (name, encoding) in [
+        ("hex", StoreKeyEncoding::Hex),
+        ("compact", StoreKeyEncoding::Compact),
+    ] {
+        let encoded = key.encode(encoding).into_owned();
+        group.bench_function(format!("encode_{name}"), |b| {
+            b.iter(|| black_box(black_box(&key).encode(encoding).into_owned()));
+        });
+        group.bench_function(format!("decode_{name}"), |b| {
+            b.iter(|| black_box(StoreKeyEncoding::decode_digest(black_box(&encoded)).unwrap()));
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, digest_info_benchmark);
+criterion_main!(benches);
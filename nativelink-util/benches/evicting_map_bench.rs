@@ -0,0 +1,108 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use std::sync::Arc;
+use std::time::SystemTime;
+
+mod common;
+
+use common::{block_on, make_runtime};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures::future::join_all;
+use nativelink_config::stores::EvictionPolicy;
+use nativelink_util::common::DigestInfo;
+use nativelink_util::evicting_map::{EvictingMap, LenEntry};
+use nativelink_util::spawn;
+
+/// Operations each task runs per iteration, half inserts and half gets.
+const OPS_PER_TASK: usize = 1000;
+
+/// Number of distinct keys the tasks work on. More than `MAX_COUNT` so
+/// inserts keep evicting items.
+const KEY_SPACE: u64 = 4096;
+const MAX_COUNT: u64 = 1024;
+
+/// Number of tasks using the map at the same time.
+const TASK_COUNTS: [usize; 3] = [1, 4, 16];
+
+#[derive(Clone, Debug)]
+struct Entry(u64);
+
+impl LenEntry for Entry {
+    fn len(&self) -> u64 {
+        self.0
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+type Map = EvictingMap<DigestInfo, Entry, SystemTime>;
+
+fn key(index: u64) -> DigestInfo {
+    let mut hash = [0u8; 32];
+    hash[..8].copy_from_slice(&index.to_le_bytes());
+    DigestInfo::new(hash, index)
+}
+
+async fn run_tasks(map: Arc<Map>, task_count: usize) {
+    let tasks = (0..task_count).map(|task| {
+        let map = map.clone();
+        spawn!("evicting_map_bench_task", async move {
+            for op in 0..OPS_PER_TASK {
+                let index = (task * OPS_PER_TASK + op) as u64 * 7919 % KEY_SPACE;
+                if op % 2 == 0 {
+                    map.insert(key(index), Entry(index)).await;
+                } else {
+                    criterion::black_box(map.get(&key(index)).await);
+                }
+            }
+        })
+    });
+    for result in join_all(tasks).await {
+        result.unwrap();
+    }
+}
+
+fn evicting_map_benchmark(c: &mut Criterion) {
+    let runtime = make_runtime(Some(4));
+    let mut group = c.benchmark_group("evicting_map");
+    for task_count in TASK_COUNTS {
+        let map = Arc::new(Map::new(
+            &EvictionPolicy {
+                max_count: MAX_COUNT,
+                ..Default::default()
+            },
+            SystemTime::now(),
+        ));
+        group.throughput(Throughput::Elements((task_count * OPS_PER_TASK) as u64));
+        group.bench_with_input(
+            BenchmarkId::new("insert_get", task_count),
+            &task_count,
+            |b, task_count| {
+                b.iter(|| {
+                    block_on(
+                        &runtime,
+                        "evicting_map_bench",
+                        run_tasks(map.clone(), *task_count),
+                    )
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, evicting_map_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,37 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nativelink_proto::build::bazel::remote::execution::v2::{
+    ActionResult as ProtoActionResult, ExecuteResponse,
+};
+use nativelink_util::action_messages::{ActionResult, ActionStage};
+use prost::Message;
+
+// Action results are uploaded by clients with `UpdateActionResult` and
+// sent by workers when they finish an action.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(proto_action_result) = ProtoActionResult::decode(data) {
+        if let Ok(action_result) = ActionResult::try_from(proto_action_result) {
+            let _ = ProtoActionResult::from(action_result);
+        }
+    }
+    if let Ok(execute_response) = ExecuteResponse::decode(data) {
+        if let Ok(action_stage) = ActionStage::try_from(execute_response) {
+            let _ = ExecuteResponse::from(action_stage);
+        }
+    }
+});
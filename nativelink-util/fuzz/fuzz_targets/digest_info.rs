@@ -0,0 +1,46 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nativelink_proto::build::bazel::remote::execution::v2::Digest;
+use nativelink_util::common::DigestInfo;
+use nativelink_util::store_trait::StoreKeyEncoding;
+use prost::Message;
+
+// Digests are part of nearly every request and are also parsed back from
+// keys persisted by the stores.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(digest) = Digest::decode(data) {
+        if let Ok(digest_info) = DigestInfo::try_from(&digest) {
+            assert_eq!(Digest::from(digest_info).size_bytes, digest.size_bytes);
+        }
+    }
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+    if let Some((hash, size)) = text.rsplit_once('-') {
+        if let Ok(size) = size.parse::<u64>() {
+            let _ = DigestInfo::try_new(hash, size);
+        }
+    }
+    if let Ok((digest_info, _)) = StoreKeyEncoding::decode_digest(text) {
+        // Every digest that was decoded must round trip through its
+        // string form.
+        let (decoded, _) = StoreKeyEncoding::decode_digest(&digest_info.to_string())
+            .expect("Failed to decode a digest that was just encoded");
+        assert_eq!(decoded, digest_info);
+    }
+});
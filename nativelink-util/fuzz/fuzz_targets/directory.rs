@@ -0,0 +1,42 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nativelink_proto::build::bazel::remote::execution::v2::Directory;
+use nativelink_util::action_messages::SymlinkInfo;
+use nativelink_util::common::DigestInfo;
+use prost::Message;
+
+// Directories are uploaded by clients and walked by `GetTree` and by the
+// workers when they download the input root of an action.
+fuzz_target!(|data: &[u8]| {
+    let Ok(directory) = Directory::decode(data) else {
+        return;
+    };
+    for file in &directory.files {
+        if let Some(digest) = &file.digest {
+            let _ = DigestInfo::try_from(digest);
+        }
+    }
+    for subdirectory in &directory.directories {
+        if let Some(digest) = &subdirectory.digest {
+            let _ = DigestInfo::try_from(digest);
+        }
+    }
+    for symlink in directory.symlinks {
+        let _ = SymlinkInfo::try_from(symlink);
+    }
+});
@@ -0,0 +1,36 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nativelink_util::resource_info::ResourceInfo;
+
+// Resource names come straight from `ByteStream` read and write requests.
+fuzz_target!(|resource_name: &str| {
+    for is_upload in [false, true] {
+        let Ok(resource_info) = ResourceInfo::new(resource_name, is_upload) else {
+            continue;
+        };
+        let _ = resource_info.validate();
+        // A parsed resource name must parse again once turned back into a
+        // string, with the same parts.
+        let rebuilt = resource_info.to_string(is_upload);
+        let reparsed = ResourceInfo::new(&rebuilt, is_upload).unwrap_or_else(|e| {
+            panic!("Failed to reparse {rebuilt:?} from {resource_name:?}: {e:?}")
+        });
+        assert_eq!(reparsed.hash, resource_info.hash);
+        assert_eq!(reparsed.expected_size, resource_info.expected_size);
+    }
+});
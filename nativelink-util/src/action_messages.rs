@@ -27,6 +27,9 @@ use nativelink_proto::build::bazel::remote::execution::v2::{
     ExecuteRequest, ExecuteResponse, ExecutedActionMetadata, FileNode, LogFile, OutputDirectory,
     OutputFile, OutputSymlink, SymlinkNode,
 };
+use nativelink_proto::com::github::trace_machina::nativelink::remote_execution::{
+    QueuePositionMetadata, ResourceUsageMetadata,
+};
 use nativelink_proto::google::longrunning::operation::Result as LongRunningResult;
 use nativelink_proto::google::longrunning::Operation;
 use nativelink_proto::google::rpc::Status;
@@ -309,6 +312,18 @@ pub struct ActionInfo {
     /// This is primarily used to join actions/operations together using this key.
     #[metric(help = "Info used to uniquely identify this ActionInfo and if it is cachable.")]
     pub unique_qualifier: ActionUniqueQualifier,
+    /// The `tool_invocation_id` of the `RequestMetadata` the client sent
+    /// with the `ExecuteRequest`, if any.
+    #[serde(default)]
+    pub tool_invocation_id: Option<String>,
+    /// The identity of the client that sent the `ExecuteRequest`, if the
+    /// server identifies its clients.
+    #[serde(default)]
+    pub client_identity: Option<String>,
+    /// The priority the action is ordered by in the queue, if the scheduler
+    /// orders it by something else than `priority`.
+    #[serde(default)]
+    pub queue_priority: Option<i32>,
 }
 
 impl ActionInfo {
@@ -323,6 +338,12 @@ impl ActionInfo {
         self.unique_qualifier.digest()
     }
 
+    /// Returns the priority the action is ordered by in the queue.
+    #[inline]
+    pub fn sort_priority(&self) -> i32 {
+        self.queue_priority.unwrap_or(self.priority)
+    }
+
     pub fn try_from_action_and_execute_request(
         execute_request: ExecuteRequest,
         action: Action,
@@ -372,6 +393,9 @@ impl ActionInfo {
             load_timestamp,
             insert_timestamp: queued_timestamp,
             unique_qualifier,
+            tool_invocation_id: None,
+            client_identity: None,
+            queue_priority: None,
         })
     }
 }
@@ -585,6 +609,44 @@ pub struct ExecutionMetadata {
     pub execution_completed_timestamp: SystemTime,
     pub output_upload_start_timestamp: SystemTime,
     pub output_upload_completed_timestamp: SystemTime,
+    /// Resources the action used while it executed, if the worker measured
+    /// them.
+    #[serde(default)]
+    pub resource_usage: Option<ResourceUsage>,
+}
+
+/// Resources an action used while it executed, as measured by the worker.
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    /// The most memory the action used at once, in bytes.
+    pub peak_memory_bytes: u64,
+    /// The CPU time the action used.
+    pub cpu_time: Duration,
+    /// The most processes and threads the action had at once.
+    pub peak_pids: u64,
+}
+
+impl From<ResourceUsage> for ResourceUsageMetadata {
+    fn from(val: ResourceUsage) -> Self {
+        Self {
+            peak_memory_bytes: val.peak_memory_bytes,
+            cpu_time: prost_types::Duration::try_from(val.cpu_time).ok(),
+            peak_pids: val.peak_pids,
+        }
+    }
+}
+
+impl From<ResourceUsageMetadata> for ResourceUsage {
+    fn from(val: ResourceUsageMetadata) -> Self {
+        Self {
+            peak_memory_bytes: val.peak_memory_bytes,
+            cpu_time: val
+                .cpu_time
+                .and_then(|cpu_time| Duration::try_from(cpu_time).ok())
+                .unwrap_or_default(),
+            peak_pids: val.peak_pids,
+        }
+    }
 }
 
 impl Default for ExecutionMetadata {
@@ -600,6 +662,7 @@ impl Default for ExecutionMetadata {
             execution_completed_timestamp: SystemTime::UNIX_EPOCH,
             output_upload_start_timestamp: SystemTime::UNIX_EPOCH,
             output_upload_completed_timestamp: SystemTime::UNIX_EPOCH,
+            resource_usage: None,
         }
     }
 }
@@ -622,7 +685,11 @@ impl From<ExecutionMetadata> for ExecutedActionMetadata {
                 .duration_since(val.execution_start_timestamp)
                 .ok()
                 .and_then(|duration| prost_types::Duration::try_from(duration).ok()),
-            auxiliary_metadata: Vec::default(),
+            auxiliary_metadata: val
+                .resource_usage
+                .map(|resource_usage| to_any(&ResourceUsageMetadata::from(resource_usage)))
+                .into_iter()
+                .collect(),
         }
     }
 }
@@ -683,6 +750,14 @@ impl TryFrom<ExecutedActionMetadata> for ExecutionMetadata {
                     "Expected output_upload_completed_timestamp to exist in ExecutedActionMetadata"
                 })?
                 .try_into()?,
+            resource_usage: eam
+                .auxiliary_metadata
+                .iter()
+                .find(|any| any.type_url == ResourceUsageMetadata::TYPE_URL)
+                .map(from_any::<ResourceUsageMetadata>)
+                .transpose()
+                .err_tip(|| "Decoding resource usage in ExecutedActionMetadata")?
+                .map(Into::into),
         })
     }
 }
@@ -725,6 +800,7 @@ impl Default for ActionResult {
                 execution_completed_timestamp: SystemTime::UNIX_EPOCH,
                 output_upload_start_timestamp: SystemTime::UNIX_EPOCH,
                 output_upload_completed_timestamp: SystemTime::UNIX_EPOCH,
+                resource_usage: None,
             },
             server_logs: HashMap::default(),
             error: None,
@@ -1052,6 +1128,16 @@ impl TypeUrl for ExecuteOperationMetadata {
         "type.googleapis.com/build.bazel.remote.execution.v2.ExecuteOperationMetadata";
 }
 
+impl TypeUrl for QueuePositionMetadata {
+    const TYPE_URL: &'static str =
+        "type.googleapis.com/com.github.trace_machina.nativelink.remote_execution.QueuePositionMetadata";
+}
+
+impl TypeUrl for ResourceUsageMetadata {
+    const TYPE_URL: &'static str =
+        "type.googleapis.com/com.github.trace_machina.nativelink.remote_execution.ResourceUsageMetadata";
+}
+
 fn from_any<T>(message: &Any) -> Result<T, Error>
 where
     T: TypeUrl + Default,
@@ -1075,6 +1161,77 @@ where
     }
 }
 
+/// A stage an operation moved to, kept so operators can tell how an
+/// operation got to its current state.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StageChange {
+    /// Name of the `ExecutionStage` the operation moved to.
+    pub stage: String,
+    /// The worker the operation was assigned to, if any.
+    pub worker_id: Option<WorkerId>,
+    /// When the operation moved to the stage.
+    pub timestamp: SystemTime,
+}
+
+impl StageChange {
+    pub fn new(stage: &ActionStage, worker_id: Option<WorkerId>, timestamp: SystemTime) -> Self {
+        Self {
+            stage: execution_stage::Value::from(stage)
+                .as_str_name()
+                .to_string(),
+            worker_id,
+            timestamp,
+        }
+    }
+}
+
+/// What the scheduler knows about an operation beyond its `ActionState`,
+/// to debug operations that are stuck.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OperationDetails {
+    /// The id the scheduler and the workers know the operation by.
+    pub operation_id: OperationId,
+    /// The worker the operation is assigned to, if any.
+    pub worker_id: Option<WorkerId>,
+    /// Number of times the operation was handed to a worker and failed.
+    pub attempts: usize,
+    /// The last time a worker sent an update about the operation.
+    pub last_worker_update: SystemTime,
+    /// The last time a client listened to the operation.
+    pub last_client_keepalive: SystemTime,
+    /// The stages the operation moved through, oldest first.
+    pub stage_history: Vec<StageChange>,
+}
+
+/// Where a queued action stands in the queue of the scheduler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueuePosition {
+    /// Number of queued actions that will be handed to a worker first.
+    pub actions_ahead: u64,
+    /// When the action is expected to start executing, if there is enough
+    /// history to estimate it.
+    pub estimated_start: Option<SystemTime>,
+    /// How long the action is expected to execute, if its command has
+    /// history.
+    pub estimated_execution_duration: Option<Duration>,
+    /// How large the outputs of the action are expected to be in bytes, if
+    /// its command has history.
+    pub estimated_output_bytes: Option<u64>,
+}
+
+impl From<QueuePosition> for QueuePositionMetadata {
+    fn from(val: QueuePosition) -> Self {
+        Self {
+            operations_ahead: val.actions_ahead,
+            estimated_start_timestamp: val.estimated_start.map(Into::into),
+            estimated_execution_duration: val
+                .estimated_execution_duration
+                .and_then(|duration| prost_types::Duration::try_from(duration).ok()),
+            estimated_output_bytes: val.estimated_output_bytes.unwrap_or_default(),
+        }
+    }
+}
+
 /// Current state of the action.
 /// This must be 100% compatible with `Operation` in `google/longrunning/operations.proto`.
 #[derive(PartialEq, Debug, Clone, Serialize, Deserialize, MetricsComponent)]
@@ -1144,6 +1301,16 @@ impl ActionState {
     }
 
     pub fn as_operation(&self, client_operation_id: OperationId) -> Operation {
+        self.as_operation_with_queue_position(client_operation_id, None)
+    }
+
+    /// Same as `as_operation()`, but also tells the client where the action
+    /// stands in the queue.
+    pub fn as_operation_with_queue_position(
+        &self,
+        client_operation_id: OperationId,
+        maybe_queue_position: Option<QueuePosition>,
+    ) -> Operation {
         let stage = Into::<execution_stage::Value>::into(&self.stage) as i32;
         let name = client_operation_id.into_string();
 
@@ -1161,7 +1328,12 @@ impl ActionState {
             // TODO(blaise.bruer) We should support stderr/stdout streaming.
             stdout_stream_name: String::default(),
             stderr_stream_name: String::default(),
-            partial_execution_metadata: None,
+            partial_execution_metadata: maybe_queue_position.map(|queue_position| {
+                ExecutedActionMetadata {
+                    auxiliary_metadata: vec![to_any(&QueuePositionMetadata::from(queue_position))],
+                    ..ExecutedActionMetadata::default()
+                }
+            }),
         };
 
         Operation {
@@ -0,0 +1,260 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::ErrorKind;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use nativelink_config::cas_server::AuditLogConfig;
+use nativelink_error::{error_if, make_err, Code, Error, ResultExt};
+use serde_json::json;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{event, Level};
+
+use crate::background_spawn;
+use crate::common::DigestInfo;
+use crate::origin_context::{ActiveOriginContext, ORIGIN_IDENTITY};
+
+/// Default value for `AuditLogConfig::max_file_size`.
+const DEFAULT_MAX_FILE_SIZE: u64 = 100 * 1024 * 1024;
+
+/// Default value for `AuditLogConfig::max_rotated_files`.
+const DEFAULT_MAX_ROTATED_FILES: u32 = 10;
+
+/// Default value for `AuditLogConfig::max_pending_records`.
+const DEFAULT_MAX_PENDING_RECORDS: usize = 10_000;
+
+/// A warning is logged every time this many records were dropped.
+const DROPPED_RECORDS_WARNING_INTERVAL: u64 = 1000;
+
+static AUDIT_LOG: OnceLock<AuditLog> = OnceLock::new();
+
+/// Starts writing the audit log of this process as configured.
+pub fn init_audit_log(config: &AuditLogConfig) -> Result<(), Error> {
+    AUDIT_LOG
+        .set(AuditLog::new(config)?)
+        .map_err(|_| make_err!(Code::Internal, "Audit log was already initialized"))
+}
+
+/// Records `record` in the audit log of this process, if there is one.
+pub fn record_audit(record: &AuditRecord<'_>) {
+    if let Some(audit_log) = AUDIT_LOG.get() {
+        audit_log.record(record);
+    }
+}
+
+/// A request that changed the CAS, the action cache or scheduled an
+/// execution. The identity of the client is taken from the active origin
+/// context.
+#[derive(Debug)]
+pub struct AuditRecord<'a> {
+    /// Name of the request, like "UpdateActionResult".
+    pub operation: &'static str,
+    pub instance_name: &'a str,
+    pub digest: Option<DigestInfo>,
+    pub size_bytes: u64,
+    pub latency: Duration,
+    pub status: Code,
+}
+
+enum Message {
+    Line(String),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Writes audit records as JSON lines to a file in the background.
+pub struct AuditLog {
+    tx: mpsc::Sender<Message>,
+    sample_one_in: u64,
+    requests: AtomicU64,
+    dropped_records: AtomicU64,
+}
+
+impl AuditLog {
+    pub fn new(config: &AuditLogConfig) -> Result<Self, Error> {
+        error_if!(config.path.is_empty(), "Audit log path must be set");
+        let max_pending_records = match config.max_pending_records {
+            0 => DEFAULT_MAX_PENDING_RECORDS,
+            max_pending_records => max_pending_records,
+        };
+        let (tx, rx) = mpsc::channel(max_pending_records);
+        let writer = AuditLogWriter {
+            path: config.path.clone(),
+            max_file_size: match config.max_file_size {
+                0 => DEFAULT_MAX_FILE_SIZE,
+                max_file_size => max_file_size,
+            },
+            max_rotated_files: match config.max_rotated_files {
+                0 => DEFAULT_MAX_ROTATED_FILES,
+                max_rotated_files => max_rotated_files,
+            },
+            file: None,
+            file_size: 0,
+        };
+        background_spawn!("audit_log_writer", writer.run(rx));
+        Ok(Self {
+            tx,
+            sample_one_in: config.sample_one_in.max(1),
+            requests: AtomicU64::new(0),
+            dropped_records: AtomicU64::new(0),
+        })
+    }
+
+    pub fn record(&self, record: &AuditRecord<'_>) {
+        if self.requests.fetch_add(1, Ordering::Relaxed) % self.sample_one_in != 0 {
+            return;
+        }
+        let identity = ActiveOriginContext::get_value(&ORIGIN_IDENTITY)
+            .ok()
+            .flatten()
+            .map_or(String::new(), |identity| identity.as_ref().clone());
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let line = json!({
+            "timestamp_ms": timestamp_ms,
+            "operation": record.operation,
+            "identity": identity,
+            "instance_name": record.instance_name,
+            "digest": record.digest.map(|digest| digest.to_string()),
+            "size_bytes": record.size_bytes,
+            "latency_ms": record.latency.as_secs_f64() * 1000.,
+            "status": format!("{:?}", record.status),
+        })
+        .to_string();
+        if self.tx.try_send(Message::Line(line)).is_err() {
+            let dropped_records = self.dropped_records.fetch_add(1, Ordering::Relaxed);
+            if dropped_records % DROPPED_RECORDS_WARNING_INTERVAL == 0 {
+                event!(
+                    Level::WARN,
+                    dropped_records = dropped_records + 1,
+                    "Audit log can not keep up, dropping records"
+                );
+            }
+        }
+    }
+
+    /// Waits until the records recorded so far are written to the file.
+    pub async fn flush(&self) -> Result<(), Error> {
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .send(Message::Flush(tx))
+            .await
+            .map_err(|_| make_err!(Code::Internal, "Audit log writer stopped"))?;
+        rx.await
+            .map_err(|_| make_err!(Code::Internal, "Audit log writer stopped"))
+    }
+}
+
+struct AuditLogWriter {
+    path: String,
+    max_file_size: u64,
+    max_rotated_files: u32,
+    file: Option<BufWriter<File>>,
+    file_size: u64,
+}
+
+impl AuditLogWriter {
+    async fn run(mut self, mut rx: mpsc::Receiver<Message>) {
+        while let Some(message) = rx.recv().await {
+            let result = match message {
+                Message::Line(line) => self.write(&line).await,
+                Message::Flush(tx) => {
+                    let result = self.flush().await;
+                    // Nobody waiting is fine.
+                    let _ = tx.send(());
+                    result
+                }
+            };
+            // Flush once no more records are waiting.
+            let result = match result {
+                Ok(()) if rx.is_empty() => self.flush().await,
+                result => result,
+            };
+            if let Err(err) = result {
+                event!(
+                    Level::ERROR,
+                    ?err,
+                    path = self.path,
+                    "Failed to write audit log"
+                );
+                // Open the file again for the next record.
+                self.file = None;
+            }
+        }
+    }
+
+    async fn write(&mut self, line: &str) -> Result<(), Error> {
+        let line_size = line.len() as u64 + 1;
+        if self.file.is_some() && self.file_size + line_size > self.max_file_size {
+            self.rotate().await?;
+        }
+        let mut file = if let Some(file) = self.file.take() {
+            file
+        } else {
+            self.open().await?
+        };
+        file.write_all(line.as_bytes()).await?;
+        file.write_all(b"\n").await?;
+        self.file = Some(file);
+        self.file_size += line_size;
+        Ok(())
+    }
+
+    async fn open(&mut self) -> Result<BufWriter<File>, Error> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .err_tip(|| format!("Opening audit log {}", self.path))?;
+        self.file_size = file
+            .metadata()
+            .await
+            .err_tip(|| format!("Reading size of audit log {}", self.path))?
+            .len();
+        Ok(BufWriter::new(file))
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        if let Some(file) = &mut self.file {
+            file.flush()
+                .await
+                .err_tip(|| format!("Flushing audit log {}", self.path))?;
+        }
+        Ok(())
+    }
+
+    async fn rotate(&mut self) -> Result<(), Error> {
+        self.flush().await?;
+        self.file = None;
+        let rotated_path = |index: u32| format!("{}.{index}", self.path);
+        for index in (1..self.max_rotated_files).rev() {
+            match tokio::fs::rename(rotated_path(index), rotated_path(index + 1)).await {
+                Err(err) if err.kind() != ErrorKind::NotFound => {
+                    return Err(Error::from(err))
+                        .err_tip(|| format!("Rotating audit log {}", rotated_path(index)));
+                }
+                _ => {}
+            }
+        }
+        tokio::fs::rename(&self.path, rotated_path(1))
+            .await
+            .err_tip(|| format!("Rotating audit log {}", self.path))
+    }
+}
@@ -0,0 +1,732 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use futures::task::{Context, Poll};
+use http_body_util::{BodyExt, Empty};
+use hyper::header::{AUTHORIZATION, HOST};
+use hyper::http::{self, HeaderMap};
+use hyper_util::rt::TokioIo;
+use nativelink_config::cas_server::{
+    ApiKeyAuthSpec, AuthConfig, AuthMethod, JwtAuthSpec, ProxyHeaderAuthSpec,
+};
+use nativelink_error::{make_err, make_input_err, Code, Error, ResultExt};
+use parking_lot::Mutex;
+use ring::signature::{self, RsaParameters, RsaPublicKeyComponents, UnparsedPublicKey};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+use tower::layer::Layer;
+use tower::Service;
+use tracing::{event, trace_span, Level};
+
+use crate::authorization::{require_authorizer, Authorizer, ORIGIN_AUTHORIZER};
+use crate::origin_context::ActiveOriginContext;
+use crate::{background_spawn, spawn};
+
+/// Default value for `JwtAuthSpec::jwks_refresh_interval`.
+const DEFAULT_JWKS_REFRESH_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Default value for `JwtAuthSpec::jwks_ca_file`.
+const DEFAULT_JWKS_CA_FILE: &str = "/etc/ssl/certs/ca-certificates.crt";
+
+/// Default value for `JwtAuthSpec::identity_claim`.
+const DEFAULT_IDENTITY_CLAIM: &str = "sub";
+
+/// Default value for `JwtAuthSpec::allowed_clock_skew`.
+const DEFAULT_ALLOWED_CLOCK_SKEW: u64 = 60;
+
+/// Default value for `ApiKeyAuthSpec::header_name`.
+const DEFAULT_API_KEY_HEADER: &str = "x-api-key";
+
+/// Minimum time between two fetches of a key set caused by tokens signed
+/// with a key it does not have.
+const MIN_JWKS_FETCH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Maximum time to fetch a key set.
+const JWKS_FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Identity of an authenticated client. The auth middleware attaches it
+/// to the requests as an extension, and the origin event middleware makes
+/// it the `ORIGIN_IDENTITY` and `ORIGIN_ROLES` of the request.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AuthIdentity {
+    pub identity: String,
+    pub roles: Vec<String>,
+}
+
+/// Marks the requests that went through an `AuthMiddleware`. The identity
+/// and roles of these requests only come from their `AuthIdentity`, so an
+/// anonymous client can not claim roles with the plain identity headers.
+#[derive(Clone, Copy, Debug)]
+pub struct Authenticated;
+
+/// Authenticates clients with the methods of an `AuthConfig`.
+pub struct Authenticator {
+    methods: Vec<Method>,
+    required: bool,
+}
+
+enum Method {
+    Jwt(Arc<JwtVerifier>),
+    ApiKey(ApiKeys),
+    ProxyHeader(ProxyHeaderAuthSpec),
+}
+
+impl Authenticator {
+    pub fn new(config: &AuthConfig) -> Result<Self, Error> {
+        let methods = config
+            .methods
+            .iter()
+            .map(|method| match method {
+                AuthMethod::jwt(spec) => JwtVerifier::new(spec).map(|v| Method::Jwt(Arc::new(v))),
+                AuthMethod::api_key(spec) => Ok(Method::ApiKey(ApiKeys::new(spec))),
+                AuthMethod::proxy_header(spec) => {
+                    if spec.identity_header.is_empty() {
+                        return Err(make_input_err!(
+                            "identity_header must be set in the proxy_header auth method"
+                        ));
+                    }
+                    Ok(Method::ProxyHeader(spec.clone()))
+                }
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(Self {
+            methods,
+            required: config.required,
+        })
+    }
+
+    /// Returns the identity of the client sending `headers`, or None if
+    /// they have no credentials.
+    pub async fn authenticate(&self, headers: &HeaderMap) -> Result<Option<AuthIdentity>, Error> {
+        for method in &self.methods {
+            let identity = match method {
+                Method::Jwt(verifier) => match bearer_token(headers) {
+                    Some(token) => Some(verifier.verify(token).await?),
+                    None => None,
+                },
+                Method::ApiKey(keys) => keys.authenticate(headers)?,
+                Method::ProxyHeader(spec) => proxy_identity(spec, headers),
+            };
+            if identity.is_some() {
+                return Ok(identity);
+            }
+        }
+        if self.required {
+            return Err(make_err!(
+                Code::Unauthenticated,
+                "Request has no credentials"
+            ));
+        }
+        Ok(None)
+    }
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+fn split_roles(roles: &str) -> Vec<String> {
+    roles
+        .split([',', ' '])
+        .filter(|role| !role.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let value = header_str(headers, AUTHORIZATION.as_str())?;
+    let (scheme, token) = value.split_once(' ')?;
+    scheme
+        .eq_ignore_ascii_case("bearer")
+        .then(|| token.trim_start())
+}
+
+fn proxy_identity(spec: &ProxyHeaderAuthSpec, headers: &HeaderMap) -> Option<AuthIdentity> {
+    let identity = header_str(headers, &spec.identity_header)?;
+    let roles = spec
+        .roles_header
+        .as_ref()
+        .and_then(|roles_header| header_str(headers, roles_header))
+        .map_or_else(Vec::new, split_roles);
+    Some(AuthIdentity {
+        identity: identity.to_string(),
+        roles,
+    })
+}
+
+struct ApiKeys {
+    header_name: String,
+    /// Identities by the sha256 of their key.
+    identities: HashMap<Vec<u8>, AuthIdentity>,
+}
+
+impl ApiKeys {
+    fn new(spec: &ApiKeyAuthSpec) -> Self {
+        let header_name = if spec.header_name.is_empty() {
+            DEFAULT_API_KEY_HEADER.to_string()
+        } else {
+            spec.header_name.clone()
+        };
+        let identities = spec
+            .keys
+            .iter()
+            .map(|key| {
+                (
+                    Sha256::digest(key.key.as_bytes()).to_vec(),
+                    AuthIdentity {
+                        identity: key.identity.clone(),
+                        roles: key.roles.clone(),
+                    },
+                )
+            })
+            .collect();
+        Self {
+            header_name,
+            identities,
+        }
+    }
+
+    fn authenticate(&self, headers: &HeaderMap) -> Result<Option<AuthIdentity>, Error> {
+        let Some(key) = header_str(headers, &self.header_name) else {
+            return Ok(None);
+        };
+        // Comparing hashes does not reveal how much of a key is right.
+        self.identities
+            .get(Sha256::digest(key.as_bytes()).as_slice())
+            .cloned()
+            .map(Some)
+            .ok_or_else(|| make_err!(Code::Unauthenticated, "Invalid API key"))
+    }
+}
+
+/// A public key of a JSON Web Key Set.
+struct Jwk {
+    kid: Option<String>,
+    key: JwkKey,
+}
+
+enum JwkKey {
+    Rsa {
+        n: Vec<u8>,
+        e: Vec<u8>,
+    },
+    /// Uncompressed point of a P-256 or P-384 key.
+    Ec {
+        crv: String,
+        point: Vec<u8>,
+    },
+    Ed25519(Vec<u8>),
+}
+
+impl Jwk {
+    /// Returns true if `signature` of `message` is valid for this key
+    /// and the `alg` of a JWT header.
+    fn verify(&self, alg: &str, message: &[u8], sig: &[u8]) -> bool {
+        let rsa_params: &RsaParameters = match (alg, &self.key) {
+            ("RS256", JwkKey::Rsa { .. }) => &signature::RSA_PKCS1_2048_8192_SHA256,
+            ("RS384", JwkKey::Rsa { .. }) => &signature::RSA_PKCS1_2048_8192_SHA384,
+            ("RS512", JwkKey::Rsa { .. }) => &signature::RSA_PKCS1_2048_8192_SHA512,
+            ("PS256", JwkKey::Rsa { .. }) => &signature::RSA_PSS_2048_8192_SHA256,
+            ("PS384", JwkKey::Rsa { .. }) => &signature::RSA_PSS_2048_8192_SHA384,
+            ("PS512", JwkKey::Rsa { .. }) => &signature::RSA_PSS_2048_8192_SHA512,
+            ("ES256", JwkKey::Ec { crv, point }) if crv == "P-256" => {
+                return UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, point)
+                    .verify(message, sig)
+                    .is_ok();
+            }
+            ("ES384", JwkKey::Ec { crv, point }) if crv == "P-384" => {
+                return UnparsedPublicKey::new(&signature::ECDSA_P384_SHA384_FIXED, point)
+                    .verify(message, sig)
+                    .is_ok();
+            }
+            ("EdDSA", JwkKey::Ed25519(x)) => {
+                return UnparsedPublicKey::new(&signature::ED25519, x)
+                    .verify(message, sig)
+                    .is_ok();
+            }
+            _ => return false,
+        };
+        let JwkKey::Rsa { n, e } = &self.key else {
+            return false;
+        };
+        RsaPublicKeyComponents { n, e }
+            .verify(rsa_params, message, sig)
+            .is_ok()
+    }
+}
+
+/// Parses the keys of a JSON Web Key Set, skipping the unsupported ones.
+fn parse_jwks(data: &[u8]) -> Result<Vec<Jwk>, Error> {
+    let jwks: Value = serde_json::from_slice(data)
+        .map_err(|e| make_input_err!("Could not parse JSON Web Key Set: {e}"))?;
+    let keys = jwks
+        .get("keys")
+        .and_then(Value::as_array)
+        .err_tip(|| "JSON Web Key Set has no keys")?;
+    let field = |key: &Value, name: &str| {
+        key.get(name)
+            .and_then(Value::as_str)
+            .and_then(|value| URL_SAFE_NO_PAD.decode(value).ok())
+    };
+    Ok(keys
+        .iter()
+        .filter(|key| key.get("use").and_then(Value::as_str).unwrap_or("sig") == "sig")
+        .filter_map(|key| {
+            let crv = key.get("crv").and_then(Value::as_str).unwrap_or_default();
+            let jwk_key = match key.get("kty").and_then(Value::as_str)? {
+                "RSA" => JwkKey::Rsa {
+                    n: field(key, "n")?,
+                    e: field(key, "e")?,
+                },
+                "EC" => {
+                    let mut point = vec![4];
+                    point.extend(field(key, "x")?);
+                    point.extend(field(key, "y")?);
+                    JwkKey::Ec {
+                        crv: crv.to_string(),
+                        point,
+                    }
+                }
+                "OKP" if crv == "Ed25519" => JwkKey::Ed25519(field(key, "x")?),
+                _ => return None,
+            };
+            Some(Jwk {
+                kid: key.get("kid").and_then(Value::as_str).map(str::to_string),
+                key: jwk_key,
+            })
+        })
+        .collect())
+}
+
+#[derive(Default)]
+struct JwkSet {
+    keys: Arc<Vec<Jwk>>,
+    fetched_at: Option<Instant>,
+}
+
+struct JwtVerifier {
+    spec: JwtAuthSpec,
+    refresh_interval: Duration,
+    allowed_clock_skew: u64,
+    jwks: Mutex<JwkSet>,
+    fetch_lock: tokio::sync::Mutex<()>,
+    /// Whether a background task is refreshing the stale key set.
+    refreshing: AtomicBool,
+}
+
+impl JwtVerifier {
+    fn new(spec: &JwtAuthSpec) -> Result<Self, Error> {
+        let mut spec = spec.clone();
+        if spec.identity_claim.is_empty() {
+            spec.identity_claim = DEFAULT_IDENTITY_CLAIM.to_string();
+        }
+        error_if_unsupported_url(&spec.jwks_url)?;
+        Ok(Self {
+            refresh_interval: match spec.jwks_refresh_interval {
+                0 => DEFAULT_JWKS_REFRESH_INTERVAL,
+                secs => Duration::from_secs(secs as u64),
+            },
+            allowed_clock_skew: match spec.allowed_clock_skew {
+                0 => DEFAULT_ALLOWED_CLOCK_SKEW,
+                secs => secs as u64,
+            },
+            spec,
+            jwks: Mutex::new(JwkSet::default()),
+            fetch_lock: tokio::sync::Mutex::new(()),
+            refreshing: AtomicBool::new(false),
+        })
+    }
+
+    /// Returns the keys to check a token signed with `kid`. A stale key set
+    /// that has the key is refreshed in the background, while a missing key
+    /// set, or one that does not have the key, is fetched before returning.
+    async fn keys(self: &Arc<Self>, kid: Option<&str>) -> Result<Arc<Vec<Jwk>>, Error> {
+        let (keys, fetched_at) = {
+            let jwks = self.jwks.lock();
+            (jwks.keys.clone(), jwks.fetched_at)
+        };
+        let age = fetched_at.map(|fetched_at| fetched_at.elapsed());
+        let has_key = match kid {
+            Some(kid) => keys.iter().any(|jwk| jwk.kid.as_deref() == Some(kid)),
+            None => !keys.is_empty(),
+        };
+        if has_key {
+            if age.is_none_or(|age| age >= self.refresh_interval) {
+                self.refresh_in_background(fetched_at);
+            }
+            return Ok(keys);
+        }
+        if age.is_some_and(|age| age < MIN_JWKS_FETCH_INTERVAL) {
+            return Ok(keys);
+        }
+        self.fetch(fetched_at).await
+    }
+
+    fn refresh_in_background(self: &Arc<Self>, fetched_at: Option<Instant>) {
+        if self.refreshing.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let verifier = self.clone();
+        background_spawn!("jwks_refresh", async move {
+            // Errors are logged by `fetch`, the previous keys stay in use.
+            let _ = verifier.fetch(fetched_at).await;
+            verifier.refreshing.store(false, Ordering::Release);
+        });
+    }
+
+    /// Fetches the key set, unless it changed since `fetched_at`.
+    async fn fetch(&self, fetched_at: Option<Instant>) -> Result<Arc<Vec<Jwk>>, Error> {
+        let _fetch_guard = self.fetch_lock.lock().await;
+        {
+            // Another request may have fetched the keys while we waited.
+            let jwks = self.jwks.lock();
+            if jwks.fetched_at != fetched_at {
+                return Ok(jwks.keys.clone());
+            }
+        }
+        let fetch = fetch_url(&self.spec.jwks_url, self.spec.jwks_ca_file.as_deref());
+        let result = tokio::time::timeout(JWKS_FETCH_TIMEOUT, fetch)
+            .await
+            .unwrap_or_else(|_| {
+                Err(make_err!(
+                    Code::Unavailable,
+                    "Timed out after {JWKS_FETCH_TIMEOUT:?}"
+                ))
+            })
+            .and_then(|data| parse_jwks(&data))
+            .err_tip(|| format!("Fetching JSON Web Key Set from {}", self.spec.jwks_url));
+        let mut jwks = self.jwks.lock();
+        match result {
+            Ok(new_keys) => {
+                jwks.keys = Arc::new(new_keys);
+                jwks.fetched_at = Some(Instant::now());
+                Ok(jwks.keys.clone())
+            }
+            Err(err) if fetched_at.is_some() => {
+                event!(
+                    Level::WARN,
+                    ?err,
+                    "Could not refresh JSON Web Key Set, using the previous keys"
+                );
+                jwks.fetched_at = Some(Instant::now());
+                Ok(jwks.keys.clone())
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn verify(self: &Arc<Self>, token: &str) -> Result<AuthIdentity, Error> {
+        let invalid = |msg: &str| make_err!(Code::Unauthenticated, "Invalid JWT: {msg}");
+        let decode_json = |part: &str| {
+            URL_SAFE_NO_PAD
+                .decode(part)
+                .ok()
+                .and_then(|data| serde_json::from_slice::<Value>(&data).ok())
+                .filter(Value::is_object)
+        };
+
+        let (signed, sig) = token.rsplit_once('.').ok_or_else(|| invalid("malformed"))?;
+        let (header, claims) = signed.split_once('.').ok_or_else(|| invalid("malformed"))?;
+        let header = decode_json(header).ok_or_else(|| invalid("malformed header"))?;
+        let claims = decode_json(claims).ok_or_else(|| invalid("malformed claims"))?;
+        let sig = URL_SAFE_NO_PAD
+            .decode(sig)
+            .map_err(|_| invalid("malformed signature"))?;
+        let alg = header
+            .get("alg")
+            .and_then(Value::as_str)
+            .ok_or_else(|| invalid("no alg"))?;
+        let kid = header.get("kid").and_then(Value::as_str);
+
+        let keys = self.keys(kid).await?;
+        let verified = keys
+            .iter()
+            .filter(|jwk| kid.is_none() || jwk.kid.as_deref() == kid)
+            .any(|jwk| jwk.verify(alg, signed.as_bytes(), &sig));
+        if !verified {
+            return Err(invalid("bad signature"));
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let exp = claims
+            .get("exp")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| invalid("no exp"))?;
+        if exp.saturating_add(self.allowed_clock_skew) < now {
+            return Err(invalid("expired"));
+        }
+        if let Some(nbf) = claims.get("nbf").and_then(Value::as_u64) {
+            if nbf > now.saturating_add(self.allowed_clock_skew) {
+                return Err(invalid("not valid yet"));
+            }
+        }
+        if let Some(issuer) = &self.spec.issuer {
+            if claims.get("iss").and_then(Value::as_str) != Some(issuer.as_str()) {
+                return Err(invalid("wrong issuer"));
+            }
+        }
+        if let Some(audience) = &self.spec.audience {
+            let matches = match claims.get("aud") {
+                Some(Value::String(aud)) => aud == audience,
+                Some(Value::Array(auds)) => auds.iter().any(|aud| aud == audience.as_str()),
+                _ => false,
+            };
+            if !matches {
+                return Err(invalid("wrong audience"));
+            }
+        }
+
+        let identity = claim(&claims, &self.spec.identity_claim)
+            .and_then(Value::as_str)
+            .filter(|identity| !identity.is_empty())
+            .ok_or_else(|| invalid(&format!("no {} claim", self.spec.identity_claim)))?;
+        let roles = match self
+            .spec
+            .roles_claim
+            .as_ref()
+            .and_then(|roles_claim| claim(&claims, roles_claim))
+        {
+            Some(Value::String(roles)) => split_roles(roles),
+            Some(Value::Array(roles)) => roles
+                .iter()
+                .filter_map(Value::as_str)
+                .map(str::to_string)
+                .collect(),
+            _ => Vec::new(),
+        };
+        Ok(AuthIdentity {
+            identity: identity.to_string(),
+            roles,
+        })
+    }
+}
+
+/// Returns the claim at `path`, where nested claims are separated by ".".
+fn claim<'a>(claims: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .try_fold(claims, |value, name| value.get(name))
+}
+
+fn error_if_unsupported_url(url: &str) -> Result<(), Error> {
+    if ["file://", "http://", "https://"]
+        .iter()
+        .any(|scheme| url.starts_with(scheme))
+    {
+        return Ok(());
+    }
+    Err(make_input_err!(
+        "jwks_url '{url}' must start with file://, http:// or https://"
+    ))
+}
+
+/// Returns the contents at a "file://", "http://" or "https://" `url`.
+async fn fetch_url(url: &str, ca_file: Option<&str>) -> Result<Bytes, Error> {
+    if let Some(path) = url.strip_prefix("file://") {
+        return Ok(tokio::fs::read(path)
+            .await
+            .map_err(Error::from)
+            .err_tip(|| format!("Reading {path}"))?
+            .into());
+    }
+    let uri: hyper::Uri = url
+        .parse()
+        .map_err(|e| make_input_err!("Invalid URL '{url}': {e}"))?;
+    let host = uri
+        .host()
+        .err_tip(|| format!("URL '{url}' has no host"))?
+        .to_string();
+    let https = uri.scheme_str() == Some("https");
+    let port = uri.port_u16().unwrap_or(if https { 443 } else { 80 });
+    let request = http::Request::get(uri.path_and_query().map_or("/", |path| path.as_str()))
+        .header(HOST, uri.authority().map_or(host.as_str(), |a| a.as_str()))
+        .body(Empty::<Bytes>::new())
+        .map_err(|e| make_input_err!("Could not build request for '{url}': {e}"))?;
+    let stream = TcpStream::connect((host.as_str(), port))
+        .await
+        .map_err(Error::from)
+        .err_tip(|| format!("Connecting to {host}:{port}"))?;
+    if !https {
+        return send_request(stream, request).await;
+    }
+    let server_name = ServerName::try_from(host.clone())
+        .map_err(|e| make_input_err!("Invalid host name '{host}': {e}"))?;
+    let stream = TlsConnector::from(Arc::new(tls_client_config(ca_file)?))
+        .connect(server_name, stream)
+        .await
+        .map_err(Error::from)
+        .err_tip(|| format!("TLS handshake with {host}:{port}"))?;
+    send_request(stream, request).await
+}
+
+fn tls_client_config(ca_file: Option<&str>) -> Result<ClientConfig, Error> {
+    let ca_file = ca_file.unwrap_or(DEFAULT_JWKS_CA_FILE);
+    let pem = std::fs::read(ca_file)
+        .map_err(Error::from)
+        .err_tip(|| format!("Reading CA file {ca_file}"))?;
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut pem.as_slice()) {
+        roots
+            .add(cert.map_err(Error::from)?)
+            .map_err(|e| make_input_err!("Invalid certificate in {ca_file}: {e}"))?;
+    }
+    Ok(ClientConfig::builder_with_provider(Arc::new(
+        tokio_rustls::rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .map_err(|e| make_err!(Code::Internal, "Could not configure TLS: {e}"))?
+    .with_root_certificates(roots)
+    .with_no_client_auth())
+}
+
+async fn send_request<T>(stream: T, request: http::Request<Empty<Bytes>>) -> Result<Bytes, Error>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+        .await
+        .map_err(|e| make_err!(Code::Unavailable, "HTTP handshake failed: {e}"))?;
+    let _connection_guard = spawn!("auth_http_connection", connection);
+    let response = sender
+        .send_request(request)
+        .await
+        .map_err(|e| make_err!(Code::Unavailable, "HTTP request failed: {e}"))?;
+    if !response.status().is_success() {
+        return Err(make_err!(
+            Code::Unavailable,
+            "HTTP request failed with status {}",
+            response.status()
+        ));
+    }
+    Ok(response
+        .into_body()
+        .collect()
+        .await
+        .map_err(|e| make_err!(Code::Unavailable, "Reading HTTP response failed: {e}"))?
+        .to_bytes())
+}
+
+/// Authenticates the requests, attaching the `AuthIdentity` of the client
+/// to them. Requests with invalid credentials, or without credentials if
+/// they are required, are rejected with `UNAUTHENTICATED`. The policies of
+/// the config are made available to the services through the origin
+/// context, see `authorization::authorize`.
+#[derive(Clone)]
+pub struct AuthMiddlewareLayer {
+    authenticator: Arc<Authenticator>,
+    authorizer: Arc<Authorizer>,
+}
+
+impl AuthMiddlewareLayer {
+    pub fn new(config: &AuthConfig) -> Result<Self, Error> {
+        let authorizer = if config.policies.is_empty() {
+            Authorizer::allow_all()
+        } else {
+            Authorizer::new(&config.policies)?
+        };
+        let authenticator = Authenticator::new(config)?;
+        require_authorizer();
+        Ok(Self {
+            authenticator: Arc::new(authenticator),
+            authorizer: Arc::new(authorizer),
+        })
+    }
+}
+
+impl<S> Layer<S> for AuthMiddlewareLayer {
+    type Service = AuthMiddleware<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        AuthMiddleware {
+            inner: service,
+            authenticator: self.authenticator.clone(),
+            authorizer: self.authorizer.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AuthMiddleware<S> {
+    inner: S,
+    authenticator: Arc<Authenticator>,
+    authorizer: Arc<Authorizer>,
+}
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for AuthMiddleware<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Send + 'static,
+    ResBody: Default + Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: http::Request<ReqBody>) -> Self::Future {
+        // We must take the current `inner` and not the clone.
+        // See: https://docs.rs/tower/latest/tower/trait.Service.html#be-careful-when-cloning-inner-services
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let authenticator = self.authenticator.clone();
+        let authorizer = self.authorizer.clone();
+
+        Box::pin(async move {
+            req.extensions_mut().insert(Authenticated);
+            match authenticator.authenticate(req.headers()).await {
+                Ok(Some(identity)) => {
+                    req.extensions_mut().insert(identity);
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    event!(Level::DEBUG, ?err, "Rejected request in AuthMiddleware");
+                    // A gRPC response with only the status.
+                    let (parts, _) = tonic::Status::from(err).into_http().into_parts();
+                    return Ok(http::Response::from_parts(parts, ResBody::default()));
+                }
+            }
+            // The origin event middleware forks the context active when
+            // it is called, so the services see the policies.
+            let mut context = ActiveOriginContext::fork().unwrap_or_default();
+            context.set_value(&ORIGIN_AUTHORIZER, authorizer);
+            context
+                .run(trace_span!("AuthMiddleware"), || inner.call(req))
+                .await
+        })
+    }
+}
@@ -0,0 +1,231 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use futures::task::{Context, Poll};
+use hyper::http;
+use nativelink_config::cas_server::{AuthorizationAction, AuthorizationPolicy, PolicyEffect};
+use nativelink_error::{error_if, make_err, Code, Error};
+use tower::layer::Layer;
+use tower::Service;
+use tracing::trace_span;
+
+use crate::make_symbol;
+use crate::origin_context::{ActiveOriginContext, ORIGIN_IDENTITY, ORIGIN_ROLES};
+
+// Symbol that holds the policies of the server a request came to.
+// See: AuthConfig::policies for details.
+make_symbol!(ORIGIN_AUTHORIZER, Authorizer);
+
+/// Set once a server authenticates its requests. From then on requests
+/// without an `Authorizer` in their origin context are denied, as the
+/// policies of the server they came to are unknown.
+static AUTHORIZER_REQUIRED: AtomicBool = AtomicBool::new(false);
+
+/// Makes `authorize` deny requests without an `Authorizer` in their origin
+/// context. Called when an auth middleware is created.
+pub fn require_authorizer() {
+    AUTHORIZER_REQUIRED.store(true, Ordering::Relaxed);
+}
+
+/// Decides what clients may do according to a list of policies.
+#[derive(Debug)]
+pub struct Authorizer {
+    policies: Vec<AuthorizationPolicy>,
+    allow_all: bool,
+}
+
+fn matches_instance(pattern: &str, instance_name: &str) -> bool {
+    pattern
+        .strip_suffix('*')
+        .map_or(pattern == instance_name, |prefix| {
+            instance_name.starts_with(prefix)
+        })
+}
+
+impl Authorizer {
+    pub fn new(policies: &[AuthorizationPolicy]) -> Result<Self, Error> {
+        for policy in policies {
+            error_if!(
+                policy.name.is_empty(),
+                "Authorization policies must have a name"
+            );
+            error_if!(
+                policy.identities.is_empty() && policy.roles.is_empty(),
+                "Authorization policy '{}' must list identities or roles",
+                policy.name
+            );
+            error_if!(
+                policy.actions.is_empty(),
+                "Authorization policy '{}' must list actions",
+                policy.name
+            );
+            if let Some(pattern) = policy
+                .instance_names
+                .iter()
+                .find(|pattern| pattern.trim_end_matches('*').contains('*'))
+            {
+                return Err(make_err!(
+                    Code::InvalidArgument,
+                    "'*' must be the last character of instance name '{pattern}' in authorization policy '{}'",
+                    policy.name
+                ));
+            }
+        }
+        Ok(Self {
+            policies: policies.to_vec(),
+            allow_all: false,
+        })
+    }
+
+    /// Authorizer of servers without policies, which allows everything.
+    pub const fn allow_all() -> Self {
+        Self {
+            policies: Vec::new(),
+            allow_all: true,
+        }
+    }
+
+    /// Fails with `PermissionDenied` if the client with `identity` and
+    /// `roles` may not do `action` on `instance_name`.
+    pub fn check(
+        &self,
+        identity: &str,
+        roles: &[String],
+        action: AuthorizationAction,
+        instance_name: &str,
+    ) -> Result<(), Error> {
+        if self.allow_all {
+            return Ok(());
+        }
+        let policy = self.policies.iter().find(|policy| {
+            policy.actions.contains(&action)
+                && (policy.instance_names.is_empty()
+                    || policy
+                        .instance_names
+                        .iter()
+                        .any(|pattern| matches_instance(pattern, instance_name)))
+                && (policy
+                    .identities
+                    .iter()
+                    .any(|other| (other == "*" && !identity.is_empty()) || other == identity)
+                    || policy.roles.iter().any(|role| roles.contains(role)))
+        });
+        match policy {
+            Some(policy) if policy.effect == PolicyEffect::allow => Ok(()),
+            Some(policy) => Err(make_err!(
+                Code::PermissionDenied,
+                "Policy '{}' does not allow '{identity}' to {action:?} on instance '{instance_name}'",
+                policy.name
+            )),
+            None => Err(make_err!(
+                Code::PermissionDenied,
+                "No policy allows '{identity}' to {action:?} on instance '{instance_name}'"
+            )),
+        }
+    }
+}
+
+/// Fails with `PermissionDenied` if the policies of the server the active
+/// request came to do not allow it to do `action` on `instance_name`.
+/// Requests to servers without policies are always allowed. Once a server
+/// authenticates its requests, requests without an authorizer are denied.
+pub fn authorize(action: AuthorizationAction, instance_name: &str) -> Result<(), Error> {
+    let Some(authorizer) = ActiveOriginContext::get_value(&ORIGIN_AUTHORIZER)
+        .ok()
+        .flatten()
+    else {
+        if AUTHORIZER_REQUIRED.load(Ordering::Relaxed) {
+            return Err(make_err!(
+                Code::PermissionDenied,
+                "Request to {action:?} on instance '{instance_name}' has no authorizer"
+            ));
+        }
+        return Ok(());
+    };
+    let identity = ActiveOriginContext::get_value(&ORIGIN_IDENTITY)
+        .ok()
+        .flatten()
+        .map_or(String::new(), |identity| identity.as_ref().clone());
+    let roles = ActiveOriginContext::get_value(&ORIGIN_ROLES)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    authorizer.check(&identity, &roles, action, instance_name)
+}
+
+/// Runs the requests with `authorizer` in their origin context, so
+/// `authorize` uses its policies.
+#[derive(Clone)]
+pub struct AuthorizerLayer {
+    authorizer: Arc<Authorizer>,
+}
+
+impl AuthorizerLayer {
+    pub const fn new(authorizer: Arc<Authorizer>) -> Self {
+        Self { authorizer }
+    }
+}
+
+impl<S> Layer<S> for AuthorizerLayer {
+    type Service = AuthorizerMiddleware<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        AuthorizerMiddleware {
+            inner: service,
+            authorizer: self.authorizer.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AuthorizerMiddleware<S> {
+    inner: S,
+    authorizer: Arc<Authorizer>,
+}
+
+impl<S, ReqBody> Service<http::Request<ReqBody>> for AuthorizerMiddleware<S>
+where
+    S: Service<http::Request<ReqBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: http::Request<ReqBody>) -> Self::Future {
+        // We must take the current `inner` and not the clone.
+        // See: https://docs.rs/tower/latest/tower/trait.Service.html#be-careful-when-cloning-inner-services
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let authorizer = self.authorizer.clone();
+        Box::pin(async move {
+            // The origin event middleware forks the context active when it
+            // is called, so the services see the policies.
+            let mut context = ActiveOriginContext::fork().unwrap_or_default();
+            context.set_value(&ORIGIN_AUTHORIZER, authorizer);
+            context
+                .run(trace_span!("AuthorizerMiddleware"), || inner.call(req))
+                .await
+        })
+    }
+}
@@ -14,17 +14,22 @@
 
 use std::collections::VecDeque;
 use std::pin::Pin;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::task::Poll;
 
 use bytes::{Bytes, BytesMut};
+use futures::future::join_all;
 use futures::task::Context;
 use futures::{Future, Stream, TryFutureExt};
 use nativelink_error::{error_if, make_err, make_input_err, Code, Error, ResultExt};
-use tokio::sync::mpsc;
+use nativelink_metric::MetricsComponent;
+use tokio::sync::{mpsc, Notify, Semaphore};
 use tracing::{event, Level};
 
+use crate::common::DigestInfo;
+use crate::digest_hasher::DigestHasher;
+
 const ZERO_DATA: Bytes = Bytes::new();
 
 /// Create a channel pair that can be used to transport buffer objects around to
@@ -34,35 +39,167 @@ const ZERO_DATA: Bytes = Bytes::new();
 /// the number of bytes sent.
 #[must_use]
 pub fn make_buf_channel_pair() -> (DropCloserWriteHalf, DropCloserReadHalf) {
+    make_buf_channel_pair_inner(None)
+}
+
+/// Same as `make_buf_channel_pair()`, but the writer will stop sending once
+/// `watermarks.high` bytes are buffered in the channel and will not resume
+/// until the reader has drained it down to `watermarks.low` bytes.
+#[must_use]
+pub fn make_buf_channel_pair_with_watermarks(
+    watermarks: BufChannelWatermarks,
+) -> (DropCloserWriteHalf, DropCloserReadHalf) {
+    make_buf_channel_pair_inner(Some(watermarks))
+}
+
+fn make_buf_channel_pair_inner(
+    watermarks: Option<BufChannelWatermarks>,
+) -> (DropCloserWriteHalf, DropCloserReadHalf) {
     // We allow up to 2 items in the buffer at any given time. There is no major
     // reason behind this magic number other than thinking it will be nice to give
     // a little time for another thread to wake up and consume data if another
     // thread is pumping large amounts of data into the channel.
-    let (tx, rx) = mpsc::channel(2);
+    // With watermarks the writer is paused by the number of bytes buffered
+    // instead, so the number of items must never be what pauses it.
+    let max_items = if watermarks.is_some() {
+        Semaphore::MAX_PERMITS
+    } else {
+        2
+    };
+    let (tx, rx) = mpsc::channel(max_items);
     let eof_sent = Arc::new(AtomicBool::new(false));
+    let shared = Arc::new(SharedState {
+        watermarks,
+        below_low_watermark: Notify::new(),
+        metrics: Arc::new(BufChannelMetrics::default()),
+    });
     (
         DropCloserWriteHalf {
             tx: Some(tx),
             bytes_written: 0,
             eof_sent: eof_sent.clone(),
+            shared: shared.clone(),
         },
         DropCloserReadHalf {
             rx,
             queued_data: VecDeque::new(),
             last_err: None,
             eof_sent,
+            shared,
             bytes_received: 0,
-            recent_data: Vec::new(),
+            recent_data: VecDeque::new(),
+            recent_data_size: 0,
             max_recent_data_size: 0,
         },
     )
 }
 
+/// Number of bytes that may be in flight between the writer and the reader
+/// of a channel before the writer is paused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BufChannelWatermarks {
+    high: u64,
+    low: u64,
+}
+
+impl BufChannelWatermarks {
+    /// Once `high` bytes are buffered the writer will wait until the reader
+    /// has drained the channel down to `low` bytes before sending more.
+    pub fn new(high: u64, low: u64) -> Result<Self, Error> {
+        error_if!(high == 0, "High watermark of buf_channel must be non-zero");
+        error_if!(
+            low > high,
+            "Low watermark ({low}) of buf_channel must not be above high watermark ({high})"
+        );
+        Ok(Self { high, low })
+    }
+
+    #[must_use]
+    pub const fn high(&self) -> u64 {
+        self.high
+    }
+
+    #[must_use]
+    pub const fn low(&self) -> u64 {
+        self.low
+    }
+}
+
+/// Metrics of a single channel pair.
+#[derive(Default, MetricsComponent)]
+pub struct BufChannelMetrics {
+    #[metric(help = "Bytes sent by the writer that the reader has not yet received.")]
+    pub bytes_buffered: AtomicU64,
+    #[metric(help = "Largest number of bytes that were buffered at any one time.")]
+    pub max_bytes_buffered: AtomicU64,
+    #[metric(help = "Number of times the writer waited for the high watermark to clear.")]
+    pub backpressure_waits: AtomicU64,
+}
+
+/// State shared between the writer and the reader of a channel.
+struct SharedState {
+    watermarks: Option<BufChannelWatermarks>,
+    /// Notified by the reader when the buffered bytes drop to the low watermark.
+    below_low_watermark: Notify,
+    metrics: Arc<BufChannelMetrics>,
+}
+
+impl SharedState {
+    fn bytes_buffered(&self) -> u64 {
+        self.metrics.bytes_buffered.load(Ordering::Acquire)
+    }
+
+    fn add_buffered(&self, bytes: u64) {
+        let bytes_buffered = self
+            .metrics
+            .bytes_buffered
+            .fetch_add(bytes, Ordering::AcqRel)
+            + bytes;
+        self.metrics
+            .max_bytes_buffered
+            .fetch_max(bytes_buffered, Ordering::AcqRel);
+    }
+
+    fn sub_buffered(&self, bytes: u64) {
+        let bytes_buffered = self
+            .metrics
+            .bytes_buffered
+            .fetch_sub(bytes, Ordering::AcqRel)
+            - bytes;
+        if let Some(watermarks) = &self.watermarks {
+            if bytes_buffered <= watermarks.low {
+                self.below_low_watermark.notify_one();
+            }
+        }
+    }
+
+    /// Waits until the writer is allowed to send more data. Returns early if
+    /// the reader was dropped, so the caller gets the send error instead.
+    async fn wait_for_capacity(&self, tx: &mpsc::Sender<Bytes>) {
+        let Some(watermarks) = &self.watermarks else {
+            return; // Fast path.
+        };
+        if self.bytes_buffered() < watermarks.high {
+            return;
+        }
+        self.metrics
+            .backpressure_waits
+            .fetch_add(1, Ordering::Relaxed);
+        while self.bytes_buffered() > watermarks.low {
+            tokio::select! {
+                () = self.below_low_watermark.notified() => {}
+                () = tx.closed() => return,
+            }
+        }
+    }
+}
+
 /// Writer half of the pair.
 pub struct DropCloserWriteHalf {
     tx: Option<mpsc::Sender<Bytes>>,
     bytes_written: u64,
     eof_sent: Arc<AtomicBool>,
+    shared: Arc<SharedState>,
 }
 
 impl DropCloserWriteHalf {
@@ -94,7 +231,12 @@ impl DropCloserWriteHalf {
                 buf,
             ));
         }
+        self.shared.wait_for_capacity(tx).await;
+        // Bytes are counted before sending, otherwise the reader could
+        // receive them before they were ever added.
+        self.shared.add_buffered(buf_len);
         if let Err(err) = tx.send(buf).await {
+            self.shared.sub_buffered(buf_len);
             // Close our channel.
             self.tx = None;
             return Err((
@@ -174,6 +316,18 @@ impl DropCloserWriteHalf {
         self.bytes_written
     }
 
+    /// Returns the number of bytes sent that the receiver has not yet received.
+    #[must_use]
+    pub fn bytes_buffered(&self) -> u64 {
+        self.shared.bytes_buffered()
+    }
+
+    /// Returns the metrics of this channel.
+    #[must_use]
+    pub fn metrics(&self) -> &Arc<BufChannelMetrics> {
+        &self.shared.metrics
+    }
+
     /// Returns if the pipe was broken. This is good for determining if the reader broke the
     /// pipe or the writer broke the pipe, since this will only return true if the pipe was
     /// broken by the writer.
@@ -183,26 +337,74 @@ impl DropCloserWriteHalf {
     }
 }
 
+/// Wrapper around a `DropCloserWriteHalf` that hashes the data as it is
+/// sent. This allows the digest of the data to be validated once the last
+/// chunk was sent, but before the EOF is sent, without a second pass over
+/// the data.
+pub struct HashingWriteHalf<'a, H: DigestHasher> {
+    writer: &'a mut DropCloserWriteHalf,
+    maybe_hasher: Option<H>,
+}
+
+impl<'a, H: DigestHasher> HashingWriteHalf<'a, H> {
+    /// If `maybe_hasher` is `None` this acts as a plain passthrough.
+    pub fn new(writer: &'a mut DropCloserWriteHalf, maybe_hasher: Option<H>) -> Self {
+        Self {
+            writer,
+            maybe_hasher,
+        }
+    }
+
+    /// Hashes the data, then sends it over the channel to the receiver.
+    pub async fn send(&mut self, buf: Bytes) -> Result<(), Error> {
+        if let Some(hasher) = self.maybe_hasher.as_mut() {
+            hasher.update(buf.as_ref());
+        }
+        self.writer.send(buf).await
+    }
+
+    /// Returns the digest of all the data sent so far, or `None` if no
+    /// hasher was given.
+    pub fn finalize_digest(&mut self) -> Option<DigestInfo> {
+        self.maybe_hasher
+            .as_mut()
+            .map(DigestHasher::finalize_digest)
+    }
+
+    /// Sends an EOF to the receiver. Callers should validate the result of
+    /// `finalize_digest()` before calling this.
+    pub fn send_eof(&mut self) -> Result<(), Error> {
+        self.writer.send_eof()
+    }
+
+    /// Returns the number of bytes written so far.
+    #[must_use]
+    pub const fn get_bytes_written(&self) -> u64 {
+        self.writer.get_bytes_written()
+    }
+}
+
 /// Reader half of the pair.
 pub struct DropCloserReadHalf {
     rx: mpsc::Receiver<Bytes>,
     /// Number of bytes received over the stream.
     bytes_received: u64,
     eof_sent: Arc<AtomicBool>,
+    shared: Arc<SharedState>,
     /// If there was an error in the stream, this will be set to the last error.
     last_err: Option<Error>,
     /// If not empty, this is the data that needs to be sent out before
     /// data from the underlying channel can should be sent.
     queued_data: VecDeque<Bytes>,
-    /// As data is being read from the stream, this buffer will be filled
-    /// with the most recent data. Once `max_recent_data_size` is reached
-    /// this buffer will be cleared and no longer be populated.
-    /// This is useful if the caller wants to reset the the reader to before
-    /// any of the data was received if possible (eg: something failed and
-    /// we want to retry).
-    recent_data: Vec<Bytes>,
-    /// Amount of data to keep in the `recent_data` buffer before clearing it
-    /// and no longer populating it.
+    /// As data is being read from the stream, this ring buffer will be
+    /// filled with the most recent data. Once more than `max_recent_data_size`
+    /// bytes are held the oldest bytes are dropped.
+    /// This is useful if the caller wants to rewind the reader to an earlier
+    /// offset if possible (eg: something failed and we want to retry).
+    recent_data: VecDeque<Bytes>,
+    /// Number of bytes currently held in `recent_data`.
+    recent_data_size: u64,
+    /// Maximum number of bytes to keep in the `recent_data` buffer.
     max_recent_data_size: u64,
 }
 
@@ -219,18 +421,57 @@ impl DropCloserReadHalf {
                 let err = make_err!(Code::Internal, "Sender dropped before sending EOF");
                 self.queued_data.clear();
                 self.recent_data.clear();
+                self.recent_data_size = 0;
                 self.bytes_received = 0;
                 self.last_err = Some(err.clone());
                 return Err(err);
             };
 
-            self.maybe_populate_recent_data(&ZERO_DATA);
             return Ok(ZERO_DATA);
         };
+        Ok(chunk)
+    }
+
+    /// Receives the next chunk without marking it as received by the caller.
+    /// Chunks that are peeked or put back into `queued_data` must not count
+    /// towards `bytes_received` until they are actually handed out.
+    async fn recv_raw(&mut self) -> Result<Bytes, Error> {
+        if let Some(err) = &self.last_err {
+            return Err(err.clone());
+        }
+        if let Some(chunk) = self.queued_data.pop_front() {
+            return Ok(chunk);
+        }
+        // `None` here indicates EOF, which we represent as Zero data
+        let data = self.rx.recv().await.unwrap_or(ZERO_DATA);
+        self.shared.sub_buffered(data.len() as u64);
+        self.recv_inner(data)
+    }
 
+    /// Records that `chunk` was handed out to the caller.
+    fn mark_received(&mut self, chunk: &Bytes) {
         self.bytes_received += chunk.len() as u64;
-        self.maybe_populate_recent_data(&chunk);
-        Ok(chunk)
+        if self.max_recent_data_size == 0 {
+            return; // Fast path.
+        }
+        self.recent_data.push_back(chunk.clone());
+        self.recent_data_size += chunk.len() as u64;
+        // Drop the oldest bytes until we are back within our limit.
+        while self.recent_data_size > self.max_recent_data_size {
+            let excess = self.recent_data_size - self.max_recent_data_size;
+            let oldest = self
+                .recent_data
+                .front_mut()
+                .expect("recent_data_size is non-zero, so recent_data must not be empty");
+            if oldest.len() as u64 <= excess {
+                self.recent_data_size -= oldest.len() as u64;
+                self.recent_data.pop_front();
+            } else {
+                // `excess` is smaller than a chunk length, so it fits in usize.
+                *oldest = oldest.slice(excess as usize..);
+                self.recent_data_size -= excess;
+            }
+        }
     }
 
     /// Try to receive a chunk of data, returning `None` if none is available.
@@ -238,45 +479,76 @@ impl DropCloserReadHalf {
         if let Some(err) = &self.last_err {
             return Some(Err(err.clone()));
         }
-        self.queued_data.pop_front().map(Ok)
+        let chunk = self.queued_data.pop_front()?;
+        self.mark_received(&chunk);
+        Some(Ok(chunk))
     }
 
     /// Receive a chunk of data, waiting asynchronously until some is available.
     pub async fn recv(&mut self) -> Result<Bytes, Error> {
-        if let Some(result) = self.try_recv() {
-            result
-        } else {
-            // `None` here indicates EOF, which we represent as Zero data
-            let data = self.rx.recv().await.unwrap_or(ZERO_DATA);
-            self.recv_inner(data)
-        }
+        let chunk = self.recv_raw().await?;
+        self.mark_received(&chunk);
+        Ok(chunk)
     }
 
-    fn maybe_populate_recent_data(&mut self, chunk: &Bytes) {
-        if self.max_recent_data_size == 0 {
-            return; // Fast path.
+    /// Sets the maximum size of the `recent_data` ring buffer. The reader keeps
+    /// the last `size` bytes it handed out so it can be rewound to any offset
+    /// within that window with `try_rewind_to()`.
+    pub fn set_max_recent_data_size(&mut self, size: u64) {
+        self.max_recent_data_size = size;
+        self.recent_data.clear();
+        self.recent_data_size = 0;
+    }
+
+    /// Attempts to rewind the stream so the next data received starts at
+    /// `offset`. This will only work if the bytes after `offset` are still
+    /// held in the `recent_data` ring buffer.
+    ///
+    /// On error the state of the stream is left untouched.
+    pub fn try_rewind_to(&mut self, offset: u64) -> Result<(), Error> {
+        error_if!(
+            offset > self.bytes_received,
+            "Cannot rewind stream to {offset}, only {} bytes were received",
+            self.bytes_received
+        );
+        let mut remaining = self.bytes_received - offset;
+        if remaining > self.recent_data_size {
+            return Err(make_err!(
+                Code::Internal,
+                "Cannot rewind stream to {offset}, max_recent_data_size of {} exceeded",
+                self.max_recent_data_size
+            ));
         }
-        if self.bytes_received > self.max_recent_data_size {
-            if !self.recent_data.is_empty() {
-                self.recent_data.clear();
+        // Move the data after `offset` back onto the front of the queue. Any
+        // trailing EOF (empty chunk) is moved too so it is replayed.
+        while let Some(chunk) = self.recent_data.pop_back() {
+            let chunk_len = chunk.len() as u64;
+            if chunk_len <= remaining {
+                remaining -= chunk_len;
+                self.recent_data_size -= chunk_len;
+                self.queued_data.push_front(chunk);
+                continue;
             }
-            return;
+            if remaining > 0 {
+                let mut head = chunk;
+                // `remaining` is smaller than `chunk_len`, so it fits in usize.
+                let tail = head.split_off(head.len() - remaining as usize);
+                self.recent_data_size -= remaining;
+                self.queued_data.push_front(tail);
+                self.recent_data.push_back(head);
+            } else {
+                self.recent_data.push_back(chunk);
+            }
+            break;
         }
-        self.recent_data.push(chunk.clone());
-    }
-
-    /// Sets the maximum size of the `recent_data` buffer. If the number of bytes
-    /// received exceeds this size, the `recent_data` buffer will be cleared and
-    /// no longer populated.
-    pub fn set_max_recent_data_size(&mut self, size: u64) {
-        self.max_recent_data_size = size;
+        self.bytes_received = offset;
+        Ok(())
     }
 
     /// Attempts to reset the stream to before any data was received. This will
     /// only work if the number of bytes received is less than `max_recent_data_size`.
     ///
-    /// On error the state of the stream is undefined and the caller should not
-    /// attempt to use the stream again.
+    /// On error the state of the stream is left untouched.
     pub fn try_reset_stream(&mut self) -> Result<(), Error> {
         if self.bytes_received > self.max_recent_data_size {
             return Err(make_err!(
@@ -284,19 +556,7 @@ impl DropCloserReadHalf {
                 "Cannot reset stream, max_recent_data_size exceeded"
             ));
         }
-        let mut data_sum = 0;
-        for chunk in self.recent_data.drain(..).rev() {
-            data_sum += chunk.len() as u64;
-            self.queued_data.push_front(chunk);
-        }
-        assert!(self.recent_data.is_empty(), "Recent_data should be empty");
-        // Ensure the sum of the bytes in recent_data is equal to the bytes_received.
-        error_if!(
-            data_sum != self.bytes_received,
-            "Sum of recent_data bytes does not equal bytes_received"
-        );
-        self.bytes_received = 0;
-        Ok(())
+        self.try_rewind_to(0)
     }
 
     /// Drains the reader until an EOF is received, but sends data to the void.
@@ -314,10 +574,48 @@ impl DropCloserReadHalf {
         Ok(())
     }
 
+    /// Sends all the data from this reader to every writer in `writers` until
+    /// an EOF is received, then sends an EOF to every writer. Each chunk is
+    /// sent to all writers concurrently, so every writer applies its own
+    /// backpressure and the reader only advances as fast as the slowest one.
+    /// Like `bind_buffered()` this reads one message ahead, so an error on
+    /// the EOF message is not hidden behind the last payload message.
+    pub async fn tee(&mut self, writers: &mut [DropCloserWriteHalf]) -> Result<(), Error> {
+        loop {
+            let chunk = self
+                .recv()
+                .await
+                .err_tip(|| "In DropCloserReadHalf::tee::recv")?;
+            if chunk.is_empty() {
+                for writer in writers.iter_mut() {
+                    writer
+                        .send_eof()
+                        .err_tip(|| "In DropCloserReadHalf::tee::send_eof")?;
+                }
+                return Ok(()); // EOF.
+            }
+            // Always read one message ahead so if we get an error on our EOF
+            // we forward it on to the writers.
+            self.peek()
+                .await
+                .err_tip(|| "In DropCloserReadHalf::tee::peek")?;
+            let send_results =
+                join_all(writers.iter_mut().map(|writer| writer.send(chunk.clone()))).await;
+            let mut result = Ok(());
+            for (i, send_result) in send_results.into_iter().enumerate() {
+                result = result.merge(
+                    send_result
+                        .err_tip(|| format!("In DropCloserReadHalf::tee::send to writer {i}")),
+                );
+            }
+            result?;
+        }
+    }
+
     /// Peek the next set of bytes in the stream without consuming them.
     pub async fn peek(&mut self) -> Result<&Bytes, Error> {
         if self.queued_data.is_empty() {
-            let chunk = self.recv().await.err_tip(|| "In buf_channel::peek")?;
+            let chunk = self.recv_raw().await.err_tip(|| "In buf_channel::peek")?;
             self.queued_data.push_front(chunk);
         }
         Ok(self
@@ -331,6 +629,16 @@ impl DropCloserReadHalf {
         self.bytes_received
     }
 
+    /// Returns the number of bytes sent that this receiver has not yet received.
+    pub fn bytes_buffered(&self) -> u64 {
+        self.shared.bytes_buffered()
+    }
+
+    /// Returns the metrics of this channel.
+    pub fn metrics(&self) -> &Arc<BufChannelMetrics> {
+        &self.shared.metrics
+    }
+
     /// Takes exactly `size` number of bytes from the stream and returns them.
     /// This means the stream will keep polling until either an EOF is received or
     /// `size` bytes are received and concat them all together then return them.
@@ -340,18 +648,20 @@ impl DropCloserReadHalf {
         let size = size.unwrap_or(usize::MAX);
         let first_chunk = {
             let mut chunk = self
-                .recv()
+                .recv_raw()
                 .await
                 .err_tip(|| "During first read of buf_channel::take()")?;
-            if chunk.is_empty() {
-                return Ok(chunk); // EOF.
-            }
             if chunk.len() > size {
                 let remaining = chunk.split_off(size);
                 self.queued_data.push_front(remaining);
+                self.mark_received(&chunk);
                 // No need to read EOF if we are a partial chunk.
                 return Ok(chunk);
             }
+            self.mark_received(&chunk);
+            if chunk.is_empty() {
+                return Ok(chunk); // EOF.
+            }
             // Try to read our EOF to ensure our sender did not error out.
             match self.peek().await {
                 Ok(peeked_chunk) => {
@@ -370,17 +680,18 @@ impl DropCloserReadHalf {
 
         loop {
             let mut chunk = self
-                .recv()
+                .recv_raw()
                 .await
                 .err_tip(|| "During first read of buf_channel::take()")?;
-            if chunk.is_empty() {
-                break; // EOF.
-            }
             if output.len() + chunk.len() > size {
                 // Slice off the extra data and put it back into the queue. We are done.
                 let remaining = chunk.split_off(size - output.len());
                 self.queued_data.push_front(remaining);
             }
+            self.mark_received(&chunk);
+            if chunk.is_empty() {
+                break; // EOF.
+            }
             output.extend_from_slice(&chunk);
             if output.len() == size {
                 break; // We are done.
@@ -13,11 +13,12 @@
 // limitations under the License.
 
 use std::cmp::{Eq, Ordering};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
-use std::hash::{BuildHasher, Hash};
+use std::hash::{BuildHasher, Hash, Hasher};
 use std::io::{Cursor, Write};
-use std::ops::{Deref, DerefMut};
+use std::ops::Deref;
+use std::sync::LazyLock;
 
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use nativelink_error::{make_input_err, Error, ResultExt};
@@ -25,6 +26,7 @@ use nativelink_metric::{
     MetricFieldData, MetricKind, MetricPublishKnownKindData, MetricsComponent,
 };
 use nativelink_proto::build::bazel::remote::execution::v2::Digest;
+use parking_lot::Mutex;
 use prost::Message;
 use serde::de::Visitor;
 use serde::ser::Error as _;
@@ -57,7 +59,16 @@ impl DigestInfo {
     pub const fn new(packed_hash: [u8; 32], size_bytes: u64) -> Self {
         DigestInfo {
             size_bytes,
-            packed_hash: PackedHash(packed_hash),
+            packed_hash: PackedHash::from_array(packed_hash),
+        }
+    }
+
+    /// Creates a digest from a hash of any supported length, like the 20
+    /// bytes of SHA-1 or the 64 bytes of SHA-512.
+    pub const fn with_packed_hash(packed_hash: PackedHash, size_bytes: u64) -> Self {
+        DigestInfo {
+            size_bytes,
+            packed_hash,
         }
     }
 
@@ -65,8 +76,7 @@ impl DigestInfo {
     where
         T: TryInto<u64> + std::fmt::Display + Copy,
     {
-        let packed_hash =
-            PackedHash::from_hex(hash).err_tip(|| format!("Invalid sha256 hash: {hash}"))?;
+        let packed_hash = PackedHash::from_hex(hash).err_tip(|| format!("Invalid hash: {hash}"))?;
         let size_bytes = size_bytes
             .try_into()
             .map_err(|_| make_input_err!("Could not convert {} into u64", size_bytes))?;
@@ -96,8 +106,8 @@ impl DigestInfo {
         &self.packed_hash
     }
 
-    pub fn set_packed_hash(&mut self, packed_hash: [u8; 32]) {
-        self.packed_hash = PackedHash(packed_hash);
+    pub fn set_packed_hash(&mut self, packed_hash: PackedHash) {
+        self.packed_hash = packed_hash;
     }
 
     pub const fn size_bytes(&self) -> u64 {
@@ -126,17 +136,17 @@ const fn count_digits(mut num: u64) -> usize {
 struct DigestStackStringifier<'a> {
     digest: &'a DigestInfo,
     /// Buffer that can hold the string representation of the `DigestInfo`.
-    /// - Hex is '2 * sizeof(PackedHash)'.
+    /// - Hex is at most '2 * MAX_PACKED_HASH_SIZE'.
     /// - Digits can be at most `count_digits(u64::MAX)`.
     /// - We also have a hyphen separator.
-    buf: [u8; std::mem::size_of::<PackedHash>() * 2 + count_digits(u64::MAX) + 1],
+    buf: [u8; MAX_PACKED_HASH_SIZE * 2 + count_digits(u64::MAX) + 1],
 }
 
 impl<'a> DigestStackStringifier<'a> {
     const fn new(digest: &'a DigestInfo) -> Self {
         DigestStackStringifier {
             digest,
-            buf: [b'-'; std::mem::size_of::<PackedHash>() * 2 + count_digits(u64::MAX) + 1],
+            buf: [b'-'; MAX_PACKED_HASH_SIZE * 2 + count_digits(u64::MAX) + 1],
         }
     }
 
@@ -145,14 +155,15 @@ impl<'a> DigestStackStringifier<'a> {
         // to the buffer.
         let len = {
             let mut cursor = Cursor::new(&mut self.buf[..]);
-            let hex = self.digest.packed_hash.to_hex().map_err(|e| {
+            let mut hex_buf = [0u8; MAX_PACKED_HASH_SIZE * 2];
+            let hex = self.digest.packed_hash.to_hex(&mut hex_buf).map_err(|e| {
                 make_input_err!(
                     "Could not convert PackedHash to hex - {e:?} - {:?}",
                     self.digest
                 )
             })?;
             cursor
-                .write_all(&hex)
+                .write_all(hex)
                 .err_tip(|| format!("Could not write hex to buffer - {hex:?} - {hex:?}",))?;
             // Note: We already have a hyphen at this point because we
             // initialized the buffer with hyphens.
@@ -279,7 +290,7 @@ impl TryFrom<Digest> for DigestInfo {
 
     fn try_from(digest: Digest) -> Result<Self, Self::Error> {
         let packed_hash = PackedHash::from_hex(&digest.hash)
-            .err_tip(|| format!("Invalid sha256 hash: {}", digest.hash))?;
+            .err_tip(|| format!("Invalid hash: {}", digest.hash))?;
         let size_bytes = digest
             .size_bytes
             .try_into()
@@ -296,7 +307,7 @@ impl TryFrom<&Digest> for DigestInfo {
 
     fn try_from(digest: &Digest) -> Result<Self, Self::Error> {
         let packed_hash = PackedHash::from_hex(&digest.hash)
-            .err_tip(|| format!("Invalid sha256 hash: {}", digest.hash))?;
+            .err_tip(|| format!("Invalid hash: {}", digest.hash))?;
         let size_bytes = digest
             .size_bytes
             .try_into()
@@ -344,60 +355,195 @@ impl From<&DigestInfo> for Digest {
     }
 }
 
-#[derive(Serialize, Deserialize, Default, Clone, Copy, Eq, PartialEq, Hash, PartialOrd, Ord)]
-pub struct PackedHash([u8; 32]);
+/// Length in bytes of the longest hash a `PackedHash` holds, the one of
+/// SHA-512.
+pub const MAX_PACKED_HASH_SIZE: usize = 64;
+
+/// Length in bytes of the longest hash a `PackedHash` stores inline, the
+/// one of SHA-256 and BLAKE3.
+const INLINE_PACKED_HASH_SIZE: usize = 32;
+
+/// Lengths in bytes of the hashes of the supported digest functions: SHA-1,
+/// SHA-256 and BLAKE3, and SHA-512.
+const PACKED_HASH_SIZES: [usize; 3] = [20, 32, 64];
+
+/// Raw hash of a digest. Most digest functions have 32 byte hashes, which
+/// are stored inline like the 20 byte ones of SHA-1. The 64 byte hashes of
+/// SHA-512 are interned, so `DigestInfo` stays small and `Copy`.
+#[derive(Clone, Copy)]
+pub struct PackedHash(PackedHashRepr);
+
+#[derive(Clone, Copy)]
+enum PackedHashRepr {
+    /// Bytes past `len` are zero.
+    Inline {
+        hash: [u8; INLINE_PACKED_HASH_SIZE],
+        len: u8,
+    },
+    Interned(&'static [u8; MAX_PACKED_HASH_SIZE]),
+}
+
+/// Hashes too long to be stored inline. They are never freed, so this
+/// grows with the number of distinct SHA-512 hashes the process sees.
+static INTERNED_HASHES: LazyLock<Mutex<HashSet<&'static [u8; MAX_PACKED_HASH_SIZE]>>> =
+    LazyLock::new(|| Mutex::new(HashSet::new()));
+
+fn intern_hash(hash: &[u8; MAX_PACKED_HASH_SIZE]) -> &'static [u8; MAX_PACKED_HASH_SIZE] {
+    let mut interned_hashes = INTERNED_HASHES.lock();
+    if let Some(interned) = interned_hashes.get(hash) {
+        return *interned;
+    }
+    let interned: &'static [u8; MAX_PACKED_HASH_SIZE] = Box::leak(Box::new(*hash));
+    interned_hashes.insert(interned);
+    interned
+}
 
-const SIZE_OF_PACKED_HASH: usize = 32;
 impl PackedHash {
     const fn new() -> Self {
-        PackedHash([0; SIZE_OF_PACKED_HASH])
+        Self::from_array([0; INLINE_PACKED_HASH_SIZE])
+    }
+
+    /// Creates a hash from the `N` bytes of `hash`. Only hashes of up to 32
+    /// bytes can be created at compile time, use `from_slice` for longer
+    /// ones.
+    pub const fn from_array<const N: usize>(hash: [u8; N]) -> Self {
+        assert!(N <= INLINE_PACKED_HASH_SIZE, "Hash is too long");
+        let mut packed_hash = [0u8; INLINE_PACKED_HASH_SIZE];
+        let mut i = 0;
+        while i < N {
+            packed_hash[i] = hash[i];
+            i += 1;
+        }
+        PackedHash(PackedHashRepr::Inline {
+            hash: packed_hash,
+            len: N as u8,
+        })
+    }
+
+    /// Creates a hash from the bytes of `hash`, which must have the length
+    /// of the hashes of a supported digest function.
+    pub fn from_slice(hash: &[u8]) -> Result<Self, Error> {
+        if !PACKED_HASH_SIZES.contains(&hash.len()) {
+            return Err(make_input_err!(
+                "Invalid hash length {} - expected one of {PACKED_HASH_SIZES:?}",
+                hash.len()
+            ));
+        }
+        if let Ok(hash) = <&[u8; MAX_PACKED_HASH_SIZE]>::try_from(hash) {
+            return Ok(PackedHash(PackedHashRepr::Interned(intern_hash(hash))));
+        }
+        let mut packed_hash = [0u8; INLINE_PACKED_HASH_SIZE];
+        packed_hash[..hash.len()].copy_from_slice(hash);
+        Ok(PackedHash(PackedHashRepr::Inline {
+            hash: packed_hash,
+            len: hash.len() as u8,
+        }))
     }
 
     fn from_hex(hash: &str) -> Result<Self, Error> {
-        let mut packed_hash = [0u8; 32];
-        hex::decode_to_slice(hash, &mut packed_hash)
-            .map_err(|e| make_input_err!("Invalid sha256 hash: {hash} - {e:?}"))?;
-        Ok(PackedHash(packed_hash))
+        if hash.len() % 2 != 0 || !PACKED_HASH_SIZES.contains(&(hash.len() / 2)) {
+            return Err(make_input_err!(
+                "Invalid hash: {hash} - expected one of {PACKED_HASH_SIZES:?} bytes"
+            ));
+        }
+        let mut packed_hash = [0u8; MAX_PACKED_HASH_SIZE];
+        let packed_hash = &mut packed_hash[..hash.len() / 2];
+        hex::decode_to_slice(hash, packed_hash)
+            .map_err(|e| make_input_err!("Invalid hash: {hash} - {e:?}"))?;
+        Self::from_slice(packed_hash)
     }
 
-    /// Converts the packed hash into a hex string.
+    /// Converts the packed hash into a hex string written to `buf`.
     #[inline]
-    fn to_hex(self) -> Result<[u8; SIZE_OF_PACKED_HASH * 2], fmt::Error> {
-        let mut hash = [0u8; SIZE_OF_PACKED_HASH * 2];
-        hex::encode_to_slice(self.0, &mut hash).map_err(|e| {
+    fn to_hex<'a>(
+        &self,
+        buf: &'a mut [u8; MAX_PACKED_HASH_SIZE * 2],
+    ) -> Result<&'a [u8], fmt::Error> {
+        let hex = &mut buf[..self.len() * 2];
+        hex::encode_to_slice(&**self, hex).map_err(|e| {
             event!(
                 Level::ERROR,
                 "Could not convert PackedHash to hex - {e:?} - {:?}",
-                self.0
+                &**self
             );
             fmt::Error
         })?;
-        Ok(hash)
+        Ok(hex)
+    }
+}
+
+impl Default for PackedHash {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PartialEq for PackedHash {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for PackedHash {}
+
+impl Hash for PackedHash {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state);
+    }
+}
+
+impl Ord for PackedHash {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (**self).cmp(&**other)
+    }
+}
+
+impl PartialOrd for PackedHash {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
 }
 
 impl fmt::Display for PackedHash {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let hash = self.to_hex()?;
-        match std::str::from_utf8(&hash) {
+        let mut buf = [0u8; MAX_PACKED_HASH_SIZE * 2];
+        let hash = self.to_hex(&mut buf)?;
+        match std::str::from_utf8(hash) {
             Ok(hash) => f.write_str(hash)?,
-            Err(_) => f.write_str(&format!("Could not convert hash to utf8 {:?}", self.0))?,
+            Err(_) => f.write_str(&format!("Could not convert hash to utf8 {:?}", &**self))?,
         }
         Ok(())
     }
 }
 
-impl Deref for PackedHash {
-    type Target = [u8; 32];
+/// Serialized as the hex string of the hash.
+impl Serialize for PackedHash {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
+impl<'de> Deserialize<'de> for PackedHash {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let hash = String::deserialize(deserializer)?;
+        PackedHash::from_hex(&hash).map_err(|e| serde::de::Error::custom(format!("{e:?}")))
     }
 }
 
-impl DerefMut for PackedHash {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+impl Deref for PackedHash {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        match &self.0 {
+            PackedHashRepr::Inline { hash, len } => &hash[..usize::from(*len)],
+            PackedHashRepr::Interned(hash) => &hash[..],
+        }
     }
 }
 
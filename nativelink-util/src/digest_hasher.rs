@@ -17,18 +17,23 @@ use std::sync::{Arc, OnceLock};
 use blake3::Hasher as Blake3Hasher;
 use bytes::BytesMut;
 use futures::Future;
-use nativelink_config::stores::ConfigDigestHashFunction;
-use nativelink_error::{make_err, make_input_err, Code, Error, ResultExt};
+use nativelink_config::stores::{ConfigDigestHashFunction, ConfigSha256Backend};
+use nativelink_error::{error_if, make_err, make_input_err, Code, Error, ResultExt};
 use nativelink_metric::{
     MetricFieldData, MetricKind, MetricPublishKnownKindData, MetricsComponent,
 };
 use nativelink_proto::build::bazel::remote::execution::v2::digest_function::Value as ProtoDigestFunction;
+use ring::digest::{
+    Context as RingContext, SHA1_FOR_LEGACY_USE_ONLY as RING_SHA1, SHA256 as RING_SHA256,
+    SHA512 as RING_SHA512,
+};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use tokio::io::{AsyncRead, AsyncReadExt};
 
-use crate::common::DigestInfo;
+use crate::common::{DigestInfo, PackedHash};
 use crate::origin_context::{ActiveOriginContext, OriginContext};
+use crate::task::BlockingPoolKind;
 use crate::{fs, make_symbol, spawn_blocking};
 
 // The symbol can be used to retrieve the active hasher function.
@@ -37,6 +42,12 @@ make_symbol!(ACTIVE_HASHER_FUNC, DigestHasherFunc);
 
 static DEFAULT_DIGEST_HASHER_FUNC: OnceLock<DigestHasherFunc> = OnceLock::new();
 
+static SHA256_BACKEND: OnceLock<Sha256Backend> = OnceLock::new();
+
+/// Digest functions an instance accepts if none were configured for it.
+pub const DEFAULT_ALLOWED_DIGEST_HASHER_FUNCS: [DigestHasherFunc; 2] =
+    [DigestHasherFunc::Sha256, DigestHasherFunc::Blake3];
+
 /// Utility function to make a context with a specific hasher function set.
 pub fn make_ctx_for_hash_func<H>(hasher: H) -> Result<Arc<OriginContext>, Error>
 where
@@ -64,11 +75,119 @@ pub fn set_default_digest_hasher_func(hasher: DigestHasherFunc) -> Result<(), Er
         .map_err(|_| make_err!(Code::Internal, "default_digest_hasher_func already set"))
 }
 
+/// Returns the digest functions clients may use with `instance_name` when
+/// its service is configured with `configured`. Nothing configured means
+/// `DEFAULT_ALLOWED_DIGEST_HASHER_FUNCS`. Clients that do not name a digest
+/// function use the default one, so it must be allowed.
+pub fn allowed_digest_hasher_funcs(
+    instance_name: &str,
+    configured: &[ConfigDigestHashFunction],
+) -> Result<Vec<DigestHasherFunc>, Error> {
+    if configured.is_empty() {
+        return Ok(DEFAULT_ALLOWED_DIGEST_HASHER_FUNCS.to_vec());
+    }
+    let funcs: Vec<DigestHasherFunc> = configured
+        .iter()
+        .map(|func| DigestHasherFunc::from(*func))
+        .collect();
+    error_if!(
+        !funcs.contains(&default_digest_hasher_func()),
+        "'digest_functions' for instance '{instance_name}' must contain the default digest function {}",
+        default_digest_hasher_func()
+    );
+    Ok(funcs)
+}
+
+/// Returns an error if `func` is not one of the `allowed` digest functions
+/// of `instance_name`.
+pub fn verify_digest_hasher_func_allowed(
+    instance_name: &str,
+    allowed: &[DigestHasherFunc],
+    func: DigestHasherFunc,
+) -> Result<(), Error> {
+    error_if!(
+        !allowed.contains(&func),
+        "Digest function {func} is not allowed for instance '{instance_name}', allowed: {allowed:?}"
+    );
+    Ok(())
+}
+
+/// Get the implementation used to compute sha256 digests.
+pub fn sha256_backend() -> Sha256Backend {
+    *SHA256_BACKEND.get_or_init(Sha256Backend::detect)
+}
+
+/// Sets the implementation used to compute sha256 digests, instead of
+/// detecting the fastest one for this CPU.
+pub fn set_sha256_backend(backend: Sha256Backend) -> Result<(), Error> {
+    SHA256_BACKEND
+        .set(backend)
+        .map_err(|_| make_err!(Code::Internal, "sha256_backend already set"))
+}
+
+/// Implementations that can compute sha256 digests.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Sha256Backend {
+    Sha2,
+    Ring,
+}
+
+impl Sha256Backend {
+    /// Picks the fastest backend for the CPU we are running on. `sha2`
+    /// uses the SHA extensions of the CPU when they exist, otherwise the
+    /// assembly implementation of `ring` is faster.
+    pub fn detect() -> Self {
+        if cpu_has_sha_extensions() {
+            Self::Sha2
+        } else {
+            Self::Ring
+        }
+    }
+
+    /// Creates a sha256 hasher that uses this backend.
+    pub fn hasher(self) -> DigestHasherImpl {
+        let hash_func_impl = match self {
+            Self::Sha2 => DigestHasherFuncImpl::Sha256(Sha256::new()),
+            Self::Ring => DigestHasherFuncImpl::Sha256Ring(RingContext::new(&RING_SHA256)),
+        };
+        DigestHasherImpl {
+            hashed_size: 0,
+            hash_func_impl,
+        }
+    }
+}
+
+impl From<ConfigSha256Backend> for Sha256Backend {
+    fn from(value: ConfigSha256Backend) -> Self {
+        match value {
+            ConfigSha256Backend::sha2 => Self::Sha2,
+            ConfigSha256Backend::ring => Self::Ring,
+        }
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn cpu_has_sha_extensions() -> bool {
+    std::arch::is_x86_feature_detected!("sha")
+}
+
+#[cfg(target_arch = "aarch64")]
+fn cpu_has_sha_extensions() -> bool {
+    std::arch::is_aarch64_feature_detected!("sha2")
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+fn cpu_has_sha_extensions() -> bool {
+    false
+}
+
 /// Supported digest hash functions.
 #[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub enum DigestHasherFunc {
     Sha256,
     Blake3,
+    Sha1,
+    Sha512,
 }
 
 impl MetricsComponent for DigestHasherFunc {
@@ -91,6 +210,31 @@ impl DigestHasherFunc {
         match self {
             Self::Sha256 => ProtoDigestFunction::Sha256,
             Self::Blake3 => ProtoDigestFunction::Blake3,
+            Self::Sha1 => ProtoDigestFunction::Sha1,
+            Self::Sha512 => ProtoDigestFunction::Sha512,
+        }
+    }
+
+    /// The `digest_function` component of a ByteStream resource name. The
+    /// REAPI requires it to be omitted for legacy functions like SHA256, so
+    /// servers that do not understand it keep working.
+    #[must_use]
+    pub const fn resource_name_component(&self) -> Option<&'static str> {
+        match self {
+            Self::Sha256 | Self::Sha1 | Self::Sha512 => None,
+            Self::Blake3 => Some("blake3"),
+        }
+    }
+
+    /// The digest function of a ByteStream resource name without a
+    /// `digest_function` component. The REAPI infers it from the length
+    /// of the hash.
+    #[must_use]
+    pub fn for_omitted_resource_name_component(hash: &str) -> Self {
+        match hash.len() {
+            40 => Self::Sha1,
+            128 => Self::Sha512,
+            _ => default_digest_hasher_func(),
         }
     }
 }
@@ -100,6 +244,8 @@ impl From<ConfigDigestHashFunction> for DigestHasherFunc {
         match value {
             ConfigDigestHashFunction::sha256 => Self::Sha256,
             ConfigDigestHashFunction::blake3 => Self::Blake3,
+            ConfigDigestHashFunction::sha1 => Self::Sha1,
+            ConfigDigestHashFunction::sha512 => Self::Sha512,
         }
     }
 }
@@ -111,6 +257,8 @@ impl TryFrom<ProtoDigestFunction> for DigestHasherFunc {
         match value {
             ProtoDigestFunction::Sha256 => Ok(Self::Sha256),
             ProtoDigestFunction::Blake3 => Ok(Self::Blake3),
+            ProtoDigestFunction::Sha1 => Ok(Self::Sha1),
+            ProtoDigestFunction::Sha512 => Ok(Self::Sha512),
             v => Err(make_input_err!(
                 "Unknown or unsupported digest function for proto conversion {v:?}"
             )),
@@ -125,6 +273,8 @@ impl TryFrom<&str> for DigestHasherFunc {
         match value.to_uppercase().as_str() {
             "SHA256" => Ok(Self::Sha256),
             "BLAKE3" => Ok(Self::Blake3),
+            "SHA1" => Ok(Self::Sha1),
+            "SHA512" => Ok(Self::Sha512),
             v => Err(make_input_err!(
                 "Unknown or unsupported digest function for string conversion: {v:?}"
             )),
@@ -137,6 +287,8 @@ impl std::fmt::Display for DigestHasherFunc {
         match self {
             DigestHasherFunc::Sha256 => write!(f, "SHA256"),
             DigestHasherFunc::Blake3 => write!(f, "BLAKE3"),
+            DigestHasherFunc::Sha1 => write!(f, "SHA1"),
+            DigestHasherFunc::Sha512 => write!(f, "SHA512"),
         }
     }
 }
@@ -152,6 +304,8 @@ impl TryFrom<i32> for DigestHasherFunc {
         match ProtoDigestFunction::try_from(value) {
             Ok(ProtoDigestFunction::Sha256) => Ok(Self::Sha256),
             Ok(ProtoDigestFunction::Blake3) => Ok(Self::Blake3),
+            Ok(ProtoDigestFunction::Sha1) => Ok(Self::Sha1),
+            Ok(ProtoDigestFunction::Sha512) => Ok(Self::Sha512),
             value => Err(make_input_err!(
                 "Unknown or unsupported digest function for int conversion: {:?}",
                 value.map(|v| v.as_str_name())
@@ -163,8 +317,10 @@ impl TryFrom<i32> for DigestHasherFunc {
 impl From<&DigestHasherFunc> for DigestHasherImpl {
     fn from(value: &DigestHasherFunc) -> Self {
         let hash_func_impl = match value {
-            DigestHasherFunc::Sha256 => DigestHasherFuncImpl::Sha256(Sha256::new()),
+            DigestHasherFunc::Sha256 => return sha256_backend().hasher(),
             DigestHasherFunc::Blake3 => DigestHasherFuncImpl::Blake3(Box::new(Blake3Hasher::new())),
+            DigestHasherFunc::Sha1 => DigestHasherFuncImpl::Ring(RingContext::new(&RING_SHA1)),
+            DigestHasherFunc::Sha512 => DigestHasherFuncImpl::Ring(RingContext::new(&RING_SHA512)),
         };
         Self {
             hashed_size: 0,
@@ -216,7 +372,10 @@ pub trait DigestHasher {
 
 pub enum DigestHasherFuncImpl {
     Sha256(Sha256),
+    Sha256Ring(RingContext),
     Blake3(Box<Blake3Hasher>), // Box because Blake3Hasher is 1.3kb in size.
+    /// SHA-1 and SHA-512, which only `ring` implements here.
+    Ring(RingContext),
 }
 
 /// The individual implementation of the hash function.
@@ -246,6 +405,7 @@ impl DigestHasher for DigestHasherImpl {
         self.hashed_size += input.len() as u64;
         match &mut self.hash_func_impl {
             DigestHasherFuncImpl::Sha256(h) => sha2::digest::Update::update(h, input),
+            DigestHasherFuncImpl::Sha256Ring(h) | DigestHasherFuncImpl::Ring(h) => h.update(input),
             DigestHasherFuncImpl::Blake3(h) => {
                 Blake3Hasher::update(h, input);
             }
@@ -256,7 +416,23 @@ impl DigestHasher for DigestHasherImpl {
     fn finalize_digest(&mut self) -> DigestInfo {
         let hash = match &mut self.hash_func_impl {
             DigestHasherFuncImpl::Sha256(h) => h.finalize_reset().into(),
+            DigestHasherFuncImpl::Sha256Ring(h) => {
+                std::mem::replace(h, RingContext::new(&RING_SHA256))
+                    .finish()
+                    .as_ref()
+                    .try_into()
+                    .expect("sha256 digests are always 32 bytes")
+            }
             DigestHasherFuncImpl::Blake3(h) => h.finalize().into(),
+            DigestHasherFuncImpl::Ring(h) => {
+                let algorithm = h.algorithm();
+                let hash = std::mem::replace(h, RingContext::new(algorithm)).finish();
+                return DigestInfo::with_packed_hash(
+                    PackedHash::from_slice(hash.as_ref())
+                        .expect("sha1 and sha512 digests are always 20 and 64 bytes"),
+                    self.hashed_size,
+                );
+            }
         };
         DigestInfo::new(hash, self.hashed_size)
     }
@@ -281,9 +457,11 @@ impl DigestHasher for DigestHasherImpl {
             }
         }
         match self.hash_func_impl {
-            DigestHasherFuncImpl::Sha256(_) => self.hash_file(file).await,
+            DigestHasherFuncImpl::Sha256(_)
+            | DigestHasherFuncImpl::Sha256Ring(_)
+            | DigestHasherFuncImpl::Ring(_) => self.hash_file(file).await,
             DigestHasherFuncImpl::Blake3(mut hasher) => {
-                spawn_blocking!("digest_for_file", move || {
+                spawn_blocking!(pool: BlockingPoolKind::Cpu, "digest_for_file", move || {
                     hasher.update_mmap(file.get_path()).map_err(|e| {
                         make_err!(Code::Internal, "Error in blake3's update_mmap: {e:?}")
                     })?;
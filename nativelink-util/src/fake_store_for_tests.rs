@@ -0,0 +1,182 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::borrow::Cow;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use mock_instant::thread_local::MockClock;
+use nativelink_error::{make_err, Code, Error};
+use nativelink_metric::MetricsComponent;
+use tokio::sync::watch;
+
+use crate::buf_channel::{DropCloserReadHalf, DropCloserWriteHalf};
+use crate::health_utils::{HealthStatus, HealthStatusIndicator};
+use crate::store_trait::{Store, StoreDriver, StoreKey, StoreLike, UploadSizeInfo};
+
+/// Store for tests of stores and services that use other stores. Forwards
+/// every request to `inner`, but can be told to fail requests or to hold
+/// back uploads and makes every read take `read_delay` on the mock clock.
+#[derive(MetricsComponent)]
+pub struct FakeStore {
+    inner: Store,
+    read_delay: Duration,
+    /// Every request fails while this is set.
+    failing: AtomicBool,
+    /// Number of upcoming requests that fail.
+    failures_left: AtomicU64,
+    /// Uploads do not read any data while this is true.
+    updates_paused: watch::Sender<bool>,
+    reads: AtomicU64,
+    updates: AtomicU64,
+}
+
+impl FakeStore {
+    pub fn new(inner: Store) -> Arc<Self> {
+        Self::new_with_read_delay(inner, Duration::ZERO)
+    }
+
+    pub fn new_with_read_delay(inner: Store, read_delay: Duration) -> Arc<Self> {
+        Arc::new(Self {
+            inner,
+            read_delay,
+            failing: AtomicBool::new(false),
+            failures_left: AtomicU64::new(0),
+            updates_paused: watch::Sender::new(false),
+            reads: AtomicU64::new(0),
+            updates: AtomicU64::new(0),
+        })
+    }
+
+    /// The store requests are forwarded to. Requests made directly to it
+    /// never fail.
+    pub const fn inner(&self) -> &Store {
+        &self.inner
+    }
+
+    /// Makes every request fail until it is called with `false`.
+    pub fn set_failing(&self, failing: bool) {
+        self.failing.store(failing, Ordering::Relaxed);
+    }
+
+    /// Makes the next `count` requests fail.
+    pub fn fail_next(&self, count: u64) {
+        self.failures_left.store(count, Ordering::Relaxed);
+    }
+
+    /// Makes uploads wait before reading any data until it is called with
+    /// `false`.
+    pub fn set_updates_paused(&self, paused: bool) {
+        self.updates_paused.send_replace(paused);
+    }
+
+    /// Number of `has` and `get_part` requests received so far.
+    pub fn reads(&self) -> u64 {
+        self.reads.load(Ordering::Relaxed)
+    }
+
+    /// Number of requests of any kind received so far.
+    pub fn requests(&self) -> u64 {
+        self.reads() + self.updates.load(Ordering::Relaxed)
+    }
+
+    fn check(&self) -> Result<(), Error> {
+        let failed_once = self
+            .failures_left
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+                left.checked_sub(1)
+            })
+            .is_ok();
+        if failed_once || self.failing.load(Ordering::Relaxed) {
+            return Err(make_err!(Code::Unavailable, "Store is down"));
+        }
+        Ok(())
+    }
+
+    fn start_read(&self) -> Result<(), Error> {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        MockClock::advance(self.read_delay);
+        self.check()
+    }
+}
+
+#[async_trait]
+impl StoreDriver for FakeStore {
+    async fn has_with_results(
+        self: Pin<&Self>,
+        keys: &[StoreKey<'_>],
+        results: &mut [Option<u64>],
+    ) -> Result<(), Error> {
+        self.start_read()?;
+        self.inner.has_with_results(keys, results).await
+    }
+
+    async fn update(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        mut reader: DropCloserReadHalf,
+        size_info: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        self.updates.fetch_add(1, Ordering::Relaxed);
+        // The sender lives as long as `self`, so this can not fail.
+        let _ = self
+            .updates_paused
+            .subscribe()
+            .wait_for(|paused| !paused)
+            .await;
+        if let Err(err) = self.check() {
+            // Fail after reading part of the upload.
+            reader.consume(Some(1)).await?;
+            return Err(err);
+        }
+        self.inner.update(key, reader, size_info).await
+    }
+
+    async fn get_part(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        writer: &mut DropCloserWriteHalf,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<(), Error> {
+        self.start_read()?;
+        self.inner.get_part(key, writer, offset, length).await
+    }
+
+    fn inner_store(&self, _key: Option<StoreKey>) -> &'_ dyn StoreDriver {
+        self
+    }
+
+    fn as_any(&self) -> &(dyn std::any::Any + Sync + Send + 'static) {
+        self
+    }
+
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn std::any::Any + Sync + Send + 'static> {
+        self
+    }
+}
+
+#[async_trait]
+impl HealthStatusIndicator for FakeStore {
+    fn get_name(&self) -> &'static str {
+        "FakeStore"
+    }
+
+    async fn check_health(&self, namespace: Cow<'static, str>) -> HealthStatus {
+        StoreDriver::check_health(Pin::new(self), namespace).await
+    }
+}
@@ -36,6 +36,7 @@ use tokio::time::timeout;
 use tracing::{event, Level};
 
 use crate::spawn_blocking;
+use crate::task::BlockingPoolKind;
 
 /// Default read buffer size when reading to/from disk.
 pub const DEFAULT_READ_BUFF_SIZE: usize = 16384;
@@ -303,7 +304,7 @@ where
     T: Send + 'static,
 {
     let permit = get_permit().await?;
-    spawn_blocking!("fs_call_with_permit", move || f(permit))
+    spawn_blocking!(pool: BlockingPoolKind::Filesystem, "fs_call_with_permit", move || f(permit))
         .await
         .unwrap_or_else(|e| Err(make_err!(Code::Internal, "background task failed: {e:?}")))
 }
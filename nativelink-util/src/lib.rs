@@ -13,6 +13,9 @@
 // limitations under the License.
 
 pub mod action_messages;
+pub mod audit_log;
+pub mod auth_middleware;
+pub mod authorization;
 pub mod buf_channel;
 pub mod channel_body_for_tests;
 pub mod chunked_stream;
@@ -20,6 +23,7 @@ pub mod common;
 pub mod connection_manager;
 pub mod digest_hasher;
 pub mod evicting_map;
+pub mod fake_store_for_tests;
 pub mod fastcdc;
 pub mod fs;
 pub mod health_utils;
@@ -32,6 +36,7 @@ pub mod origin_event;
 pub mod origin_event_middleware;
 pub mod origin_event_publisher;
 pub mod platform_properties;
+pub mod popularity_tracker;
 pub mod proto_stream_utils;
 pub mod resource_info;
 pub mod retry;
@@ -40,10 +45,60 @@ pub mod store_trait;
 pub mod task;
 pub mod tls_utils;
 pub mod write_counter;
+pub mod zstd_stream;
 
 // Re-export tracing mostly for use in macros.
 pub use tracing as __tracing;
 
+type LogFilterHandle =
+    tracing_subscriber::reload::Handle<tracing_subscriber::EnvFilter, tracing_subscriber::Registry>;
+
+/// Handle used to swap out the log filter after `init_tracing()` was called.
+static LOG_FILTER_HANDLE: std::sync::OnceLock<LogFilterHandle> = std::sync::OnceLock::new();
+
+fn make_log_filter(
+    directives: &str,
+) -> Result<tracing_subscriber::EnvFilter, tracing_subscriber::filter::ParseError> {
+    tracing_subscriber::EnvFilter::builder()
+        .with_default_directive(tracing::metadata::LevelFilter::WARN.into())
+        .parse(directives)
+}
+
+fn log_filter_handle() -> Result<&'static LogFilterHandle, nativelink_error::Error> {
+    LOG_FILTER_HANDLE.get().ok_or_else(|| {
+        nativelink_error::make_err!(
+            nativelink_error::Code::FailedPrecondition,
+            "Logging has not been initialized"
+        )
+    })
+}
+
+/// Returns the directives of the currently active log filter.
+pub fn log_filter() -> Result<String, nativelink_error::Error> {
+    log_filter_handle()?
+        .with_current(ToString::to_string)
+        .map_err(|e| {
+            nativelink_error::make_err!(
+                nativelink_error::Code::Internal,
+                "Could not read log filter: {e}"
+            )
+        })
+}
+
+/// Replaces the active log filter with `directives` without restarting the
+/// process. Uses the same syntax as the `RUST_LOG` environment variable,
+/// eg: `warn,nativelink_store::redis_store=debug`.
+pub fn set_log_filter(directives: &str) -> Result<(), nativelink_error::Error> {
+    let env_filter = make_log_filter(directives)
+        .map_err(|e| nativelink_error::make_input_err!("Invalid log filter '{directives}': {e}"))?;
+    log_filter_handle()?.reload(env_filter).map_err(|e| {
+        nativelink_error::make_err!(
+            nativelink_error::Code::Internal,
+            "Could not set log filter: {e}"
+        )
+    })
+}
+
 /// Initialize tracing.
 pub fn init_tracing() -> Result<(), nativelink_error::Error> {
     use tracing_subscriber::prelude::*;
@@ -60,6 +115,14 @@ pub fn init_tracing() -> Result<(), nativelink_error::Error> {
     let env_filter = tracing_subscriber::EnvFilter::builder()
         .with_default_directive(tracing::metadata::LevelFilter::WARN.into())
         .from_env_lossy();
+    // Wrap the filter so it can be changed at runtime with `set_log_filter()`.
+    let (env_filter, log_filter_handle) = tracing_subscriber::reload::Layer::new(env_filter);
+    LOG_FILTER_HANDLE.set(log_filter_handle).map_err(|_| {
+        nativelink_error::make_err!(
+            nativelink_error::Code::Internal,
+            "Log filter handle already set"
+        )
+    })?;
 
     // Setup tracing logger for multiple format types, compact, json, and pretty as a single layer.
     // Configuration for log format comes from environment variable NL_LOG_FMT due to subscribers
@@ -303,6 +303,11 @@ impl Counter {
         }
         self.0.fetch_sub(value, Ordering::Acquire);
     }
+
+    #[inline]
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Acquire)
+    }
 }
 
 impl MetricsComponent for Counter {
@@ -14,16 +14,17 @@
 
 use std::pin::Pin;
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 use async_trait::async_trait;
 use bitflags::bitflags;
 use futures::Stream;
-use nativelink_error::Error;
+use nativelink_error::{make_err, Code, Error};
 use nativelink_metric::MetricsComponent;
 
 use crate::action_messages::{
-    ActionInfo, ActionStage, ActionState, ActionUniqueKey, OperationId, WorkerId,
+    ActionInfo, ActionStage, ActionState, ActionUniqueKey, OperationDetails, OperationId,
+    QueuePosition, WorkerId,
 };
 use crate::common::DigestInfo;
 use crate::known_platform_property_provider::KnownPlatformPropertyProvider;
@@ -53,6 +54,16 @@ pub trait ActionStateResult: Send + Sync + 'static {
     async fn changed(&mut self) -> Result<Arc<ActionState>, Error>;
     // Provide result as action info. This behavior will not be supported by all implementations.
     async fn as_action_info(&self) -> Result<Arc<ActionInfo>, Error>;
+    // Provides where the action stands in the queue while it is queued.
+    // This behavior will not be supported by all implementations.
+    async fn queue_position(&self) -> Result<Option<QueuePosition>, Error> {
+        Ok(None)
+    }
+    // Provides the details the scheduler keeps about the operation.
+    // This behavior will not be supported by all implementations.
+    async fn operation_details(&self) -> Result<Option<OperationDetails>, Error> {
+        Ok(None)
+    }
 }
 
 /// The direction in which the results are ordered.
@@ -119,6 +130,24 @@ pub trait ClientStateManager: Sync + Send + Unpin + MetricsComponent + 'static {
     // into a KnownPlatformPropertyProvider instead. Rust currently does not support
     // casting traits to other traits.
     fn as_known_platform_property_provider(&self) -> Option<&dyn KnownPlatformPropertyProvider>;
+
+    /// Completes the operation with a `Cancelled` error and stops the
+    /// worker running it, if any.
+    async fn cancel_operation(&self, operation_id: &OperationId) -> Result<(), Error> {
+        Err(make_err!(
+            Code::Unimplemented,
+            "Cancelling operation {operation_id} is not supported by this scheduler"
+        ))
+    }
+
+    /// Stops the worker running the operation, if any, and puts the
+    /// operation back in the queue.
+    async fn requeue_operation(&self, operation_id: &OperationId) -> Result<(), Error> {
+        Err(make_err!(
+            Code::Unimplemented,
+            "Requeueing operation {operation_id} is not supported by this scheduler"
+        ))
+    }
 }
 
 /// The type of update to perform on an operation.
@@ -132,6 +161,10 @@ pub enum UpdateOperationType {
 
     /// Notification that the operation has been completed.
     UpdateWithError(Error),
+
+    /// Notification that the worker running the operation was evicted or
+    /// stopped sending updates, so the operation did not finish.
+    UpdateWithWorkerEvicted(Error),
 }
 
 #[async_trait]
@@ -146,6 +179,17 @@ pub trait WorkerStateManager: Sync + Send + MetricsComponent {
         worker_id: &WorkerId,
         update: UpdateOperationType,
     ) -> Result<(), Error>;
+
+    /// Completes the operation with a `Cancelled` error if no client
+    /// listened to it for `grace_period`. Returns true if the worker should
+    /// stop running the operation, which is also the case if the operation
+    /// already finished or no longer exists.
+    async fn cancel_if_abandoned(
+        &self,
+        operation_id: &OperationId,
+        worker_id: &WorkerId,
+        grace_period: Duration,
+    ) -> Result<bool, Error>;
 }
 
 #[async_trait]
@@ -53,9 +53,16 @@ macro_rules! make_symbol {
 }
 
 // Symbol that represents the identity of the origin of a request.
-// See: IdentityHeaderSpec for details.
+// See: IdentityHeaderSpec and AuthConfig for details.
 make_symbol!(ORIGIN_IDENTITY, String);
 
+/// Roles of the identity a request originates from.
+pub type OriginRoles = Vec<String>;
+
+// Symbol that represents the roles of the origin of a request.
+// See: IdentityHeaderSpec::roles_header_name and AuthConfig for details.
+make_symbol!(ORIGIN_ROLES, OriginRoles);
+
 pub struct NLSymbol<T: Send + Sync + 'static> {
     pub name: &'static str,
     pub _phantom: std::marker::PhantomData<T>,
@@ -28,7 +28,8 @@ use tower::layer::Layer;
 use tower::Service;
 use tracing::trace_span;
 
-use crate::origin_context::{ActiveOriginContext, ORIGIN_IDENTITY};
+use crate::auth_middleware::{AuthIdentity, Authenticated};
+use crate::origin_context::{ActiveOriginContext, ORIGIN_IDENTITY, ORIGIN_ROLES};
 use crate::origin_event::{OriginEventCollector, ORIGIN_EVENT_COLLECTOR};
 
 /// Default identity header name.
@@ -37,6 +38,12 @@ use crate::origin_event::{OriginEventCollector, ORIGIN_EVENT_COLLECTOR};
 // We should consolidate these.
 const DEFAULT_IDENTITY_HEADER: &str = "x-identity";
 
+/// Identity of a client taken from its verified TLS certificate. When a
+/// request carries it as an extension it is used instead of the identity
+/// header, which the client could set to anything.
+#[derive(Clone, Debug)]
+pub struct TlsClientIdentity(pub String);
+
 #[derive(Default, Clone)]
 pub struct OriginRequestMetadata {
     pub identity: String,
@@ -102,13 +109,21 @@ where
         let mut inner = std::mem::replace(&mut self.inner, clone);
 
         let mut context = ActiveOriginContext::fork().unwrap_or_default();
+        let auth_identity = req.extensions().get::<AuthIdentity>().cloned();
+        // Behind an auth middleware the plain headers are set by the
+        // client, so they are only trusted without one.
+        let trust_headers = req.extensions().get::<Authenticated>().is_none();
         let identity = {
             let identity_header = self
                 .idenity_header_config
                 .header_name
                 .as_deref()
                 .unwrap_or(DEFAULT_IDENTITY_HEADER);
-            let identity = if !identity_header.is_empty() {
+            let identity = if let Some(auth_identity) = &auth_identity {
+                auth_identity.identity.clone()
+            } else if let Some(TlsClientIdentity(identity)) = req.extensions().get() {
+                identity.clone()
+            } else if trust_headers && !identity_header.is_empty() {
                 req.headers()
                     .get(identity_header)
                     .and_then(|header| header.to_str().ok().map(str::to_string))
@@ -128,6 +143,26 @@ where
             context.set_value(&ORIGIN_IDENTITY, Arc::new(identity.clone()));
             identity
         };
+        if let Some(auth_identity) = auth_identity {
+            context.set_value(&ORIGIN_ROLES, Arc::new(auth_identity.roles));
+        } else if !trust_headers {
+            context.set_value(&ORIGIN_ROLES, Arc::new(Vec::new()));
+        } else if let Some(roles_header) = &self.idenity_header_config.roles_header_name {
+            let roles = req
+                .headers()
+                .get(roles_header)
+                .and_then(|header| header.to_str().ok())
+                .map(|roles| {
+                    roles
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|role| !role.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default();
+            context.set_value(&ORIGIN_ROLES, Arc::new(roles));
+        }
         if let Some(origin_event_tx) = &self.maybe_origin_event_tx {
             let bazel_metadata = req
                 .headers()
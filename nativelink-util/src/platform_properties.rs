@@ -13,7 +13,7 @@
 // limitations under the License.
 
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 
 use nativelink_metric::{
     publish, MetricFieldData, MetricKind, MetricPublishKnownKindData, MetricsComponent,
@@ -75,6 +75,9 @@ impl From<ProtoPlatform> for PlatformProperties {
 /// Minimum  - Means that workers must have at least this number available. When
 ///            a worker executes a task that has this value, the worker will have
 ///            this value subtracted from the available resources of the worker.
+/// Set      - Means the worker must have all of these values among its values.
+/// Wildcard - Means the worker value must match this value, where a `*` matches
+///            any sequence of characters.
 /// Priority - Means the worker is given this information, but does not restrict
 ///            what workers can take this value. However, the worker must have the
 ///            associated key present to be matched.
@@ -85,6 +88,8 @@ impl From<ProtoPlatform> for PlatformProperties {
 pub enum PlatformPropertyValue {
     Exact(String),
     Minimum(u64),
+    Set(BTreeSet<String>),
+    Wildcard(String),
     Priority(String),
     Unknown(String),
 }
@@ -103,6 +108,18 @@ impl PlatformPropertyValue {
                 }
                 false
             }
+            Self::Set(values) => {
+                if let Self::Set(worker_values) = worker_value {
+                    return values.is_subset(worker_values);
+                }
+                false
+            }
+            Self::Wildcard(pattern) => {
+                if let Self::Wildcard(worker_v) = worker_value {
+                    return wildcard_matches(pattern, worker_v);
+                }
+                false
+            }
             // Priority is used to pass info to the worker and not restrict which
             // workers can be selected, but might be used to prefer certain workers
             // over others.
@@ -114,12 +131,47 @@ impl PlatformPropertyValue {
 
     pub fn as_str(&self) -> Cow<str> {
         match self {
-            Self::Exact(value) | Self::Priority(value) | Self::Unknown(value) => {
-                Cow::Borrowed(value)
-            }
+            Self::Exact(value)
+            | Self::Wildcard(value)
+            | Self::Priority(value)
+            | Self::Unknown(value) => Cow::Borrowed(value),
             Self::Minimum(value) => Cow::Owned(value.to_string()),
+            Self::Set(values) => Cow::Owned(values.iter().cloned().collect::<Vec<_>>().join(",")),
+        }
+    }
+
+    /// Combines multiple values a worker has for the same property. Only
+    /// `Set` keeps all values, for any other type the last value wins.
+    pub fn merge(&mut self, other: Self) {
+        match (self, other) {
+            (Self::Set(values), Self::Set(other_values)) => values.extend(other_values),
+            (this, other) => *this = other,
+        }
+    }
+}
+
+/// Returns true if `value` matches `pattern`, where a `*` in `pattern`
+/// matches any sequence of characters.
+fn wildcard_matches(pattern: &str, value: &str) -> bool {
+    let mut parts = pattern.split('*');
+    // There is always a first part, even if the pattern is empty.
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = value.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts = parts.peekable();
+    while let Some(part) = parts.next() {
+        if parts.peek().is_none() {
+            // The last part has to match the end of the value.
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
         }
     }
+    // The pattern has no `*`, so it must be equal to the value.
+    rest.is_empty()
 }
 
 impl MetricsComponent for PlatformPropertyValue {
@@ -133,6 +185,8 @@ impl MetricsComponent for PlatformPropertyValue {
         match self {
             Self::Exact(v) => publish!(name, v, kind, help, "exact"),
             Self::Minimum(v) => publish!(name, v, kind, help, "minimum"),
+            Self::Set(_) => publish!(name, &self.as_str().into_owned(), kind, help, "set"),
+            Self::Wildcard(v) => publish!(name, v, kind, help, "wildcard"),
             Self::Priority(v) => publish!(name, v, kind, help, "priority"),
             Self::Unknown(v) => publish!(name, v, kind, help, "unknown"),
         }
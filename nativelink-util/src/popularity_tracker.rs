@@ -0,0 +1,211 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, OnceLock};
+
+use parking_lot::Mutex;
+
+use crate::store_trait::StoreKey;
+
+/// Number of rows of the count-min sketch. Each row uses a different hash
+/// of the key, the estimate is the smallest counter of all rows.
+const SKETCH_DEPTH: usize = 4;
+
+/// Trackers registered with [`popularity_tracker`], by name.
+static POPULARITY_TRACKERS: OnceLock<Mutex<HashMap<String, Arc<PopularityTracker>>>> =
+    OnceLock::new();
+
+/// Returns the tracker registered under `name`, creating it with `create`
+/// if no store registered it yet. Stores that use the same name share
+/// their read counts.
+pub fn popularity_tracker(
+    name: &str,
+    create: impl FnOnce() -> PopularityTracker,
+) -> Arc<PopularityTracker> {
+    POPULARITY_TRACKERS
+        .get_or_init(Mutex::default)
+        .lock()
+        .entry(name.to_string())
+        .or_insert_with(|| Arc::new(create()))
+        .clone()
+}
+
+/// Returns the tracker registered under `name`, if any.
+pub fn get_popularity_tracker(name: &str) -> Option<Arc<PopularityTracker>> {
+    POPULARITY_TRACKERS.get()?.lock().get(name).cloned()
+}
+
+struct TrackerState {
+    /// `SKETCH_DEPTH` rows of `width` counters.
+    counters: Vec<u32>,
+    /// Reads recorded since the counters were last halved.
+    reads_since_decay: u64,
+    /// Estimated reads of the most read keys seen so far.
+    hottest: HashMap<StoreKey<'static>, u64>,
+    /// Lower bound of the smallest value in `hottest`, used to skip
+    /// scanning `hottest` for keys that can not make it in.
+    hottest_min: u64,
+}
+
+/// Approximate per key read counter backed by a count-min sketch. Memory
+/// use is fixed regardless of the number of keys; estimates may be too
+/// high when keys collide but are never too low.
+///
+/// Counts are halved every `decay_after_reads` reads, so the estimates
+/// favor keys that were read often recently.
+pub struct PopularityTracker {
+    width: usize,
+    decay_after_reads: u64,
+    max_hottest: usize,
+    state: Mutex<TrackerState>,
+}
+
+impl PopularityTracker {
+    /// Creates a tracker with `width` counters per sketch row that
+    /// remembers the `max_hottest` most read keys.
+    pub fn new(width: usize, decay_after_reads: u64, max_hottest: usize) -> Self {
+        let width = width.max(1);
+        Self {
+            width,
+            decay_after_reads: decay_after_reads.max(1),
+            max_hottest,
+            state: Mutex::new(TrackerState {
+                counters: vec![0; width * SKETCH_DEPTH],
+                reads_since_decay: 0,
+                hottest: HashMap::with_capacity(max_hottest),
+                hottest_min: u64::MAX,
+            }),
+        }
+    }
+
+    /// Returns the index of the counter of `key` in each row.
+    fn counter_indexes(&self, key: &StoreKey<'_>) -> [usize; SKETCH_DEPTH] {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let hash = hasher.finish();
+        // Derive the row hashes from a single hash (Kirsch-Mitzenmacher).
+        let (h1, h2) = (hash & 0xFFFF_FFFF, (hash >> 32) | 1);
+        let mut indexes = [0; SKETCH_DEPTH];
+        for (row, index) in indexes.iter_mut().enumerate() {
+            let row_hash = h1.wrapping_add((row as u64).wrapping_mul(h2));
+            *index = row * self.width + (row_hash % self.width as u64) as usize;
+        }
+        indexes
+    }
+
+    /// Records a read of `key` and returns its estimated number of reads,
+    /// including this one.
+    pub fn record_read(&self, key: &StoreKey<'_>) -> u64 {
+        let indexes = self.counter_indexes(key);
+        let mut state = self.state.lock();
+        let current = indexes
+            .iter()
+            .map(|&i| state.counters[i])
+            .min()
+            .unwrap_or(0);
+        let estimate = current.saturating_add(1);
+        // Conservative update: only raise the counters that are below the
+        // new estimate, which keeps collisions from inflating other keys.
+        for &i in &indexes {
+            state.counters[i] = state.counters[i].max(estimate);
+        }
+        let estimate = u64::from(estimate);
+        self.update_hottest(&mut state, key, estimate);
+
+        state.reads_since_decay += 1;
+        if state.reads_since_decay >= self.decay_after_reads {
+            Self::decay(&mut state);
+        }
+        estimate
+    }
+
+    /// Returns the estimated number of reads of `key`.
+    pub fn estimate(&self, key: &StoreKey<'_>) -> u64 {
+        let indexes = self.counter_indexes(key);
+        let state = self.state.lock();
+        indexes
+            .iter()
+            .map(|&i| u64::from(state.counters[i]))
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Returns up to `count` of the most read keys with their estimated
+    /// number of reads, most read first.
+    pub fn hottest(&self, count: usize) -> Vec<(StoreKey<'static>, u64)> {
+        let mut hottest: Vec<_> = self
+            .state
+            .lock()
+            .hottest
+            .iter()
+            .map(|(key, reads)| (key.clone(), *reads))
+            .collect();
+        hottest.sort_unstable_by(|(a_key, a_reads), (b_key, b_reads)| {
+            b_reads
+                .cmp(a_reads)
+                .then_with(|| a_key.as_str().cmp(&b_key.as_str()))
+        });
+        hottest.truncate(count);
+        hottest
+    }
+
+    fn update_hottest(&self, state: &mut TrackerState, key: &StoreKey<'_>, estimate: u64) {
+        if self.max_hottest == 0 {
+            return;
+        }
+        let key = key.borrow().into_owned();
+        if let Some(reads) = state.hottest.get_mut(&key) {
+            *reads = estimate;
+            return;
+        }
+        if state.hottest.len() < self.max_hottest {
+            state.hottest.insert(key, estimate);
+            state.hottest_min = state.hottest_min.min(estimate);
+            return;
+        }
+        if estimate <= state.hottest_min {
+            return;
+        }
+        let Some((coldest_key, coldest_reads)) = state
+            .hottest
+            .iter()
+            .min_by_key(|(_, reads)| **reads)
+            .map(|(key, reads)| (key.clone(), *reads))
+        else {
+            return;
+        };
+        if estimate <= coldest_reads {
+            state.hottest_min = coldest_reads;
+            return;
+        }
+        state.hottest.remove(&coldest_key);
+        state.hottest.insert(key, estimate);
+        state.hottest_min = state.hottest.values().copied().min().unwrap_or(u64::MAX);
+    }
+
+    fn decay(state: &mut TrackerState) {
+        for counter in &mut state.counters {
+            *counter /= 2;
+        }
+        state.hottest.retain(|_, reads| {
+            *reads /= 2;
+            *reads > 0
+        });
+        state.hottest_min = state.hottest.values().copied().min().unwrap_or(u64::MAX);
+        state.reads_since_decay = 0;
+    }
+}
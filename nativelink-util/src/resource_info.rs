@@ -17,6 +17,9 @@ use std::convert::AsRef;
 
 use nativelink_error::{error_if, make_input_err, Error, ResultExt};
 
+use crate::common::DigestInfo;
+use crate::digest_hasher::DigestHasherFunc;
+
 const ERROR_MSG: &str = concat!(
     "Expected resource_name to be of pattern ",
     "'{?instance_name/}(?uploads/{uuid}/)blobs/{?/digest_function}{/hash}/{size}{?/optional_metadata}' or ",
@@ -35,6 +38,11 @@ const DIGEST_FUNCTIONS: [&str; 9] = [
     "blake3",
 ];
 
+// Hash lengths (in hex characters) a resource name without a
+// `digest_function` segment may have. The digest function of these is
+// inferred from the hash length.
+const INFERABLE_HASH_LENGTHS: [usize; 6] = [32, 40, 64, 66, 96, 128];
+
 // Named struct to make the code easier to read when adding the slash size.
 const SLASH_SIZE: usize = 1;
 
@@ -126,11 +134,14 @@ impl<'a> ResourceInfo<'a> {
         // Remember, `instance_name` can contain slashes and/or special names
         // like "blobs" or "uploads".
         let mut parts = beginning_part.rsplitn(3, '/');
-        output.uuid = Some(Cow::Borrowed(
-            parts
-                .next()
-                .err_tip(|| format!("{ERROR_MSG} in {resource_name}"))?,
-        ));
+        let uuid = parts
+            .next()
+            .err_tip(|| format!("{ERROR_MSG} in {resource_name}"))?;
+        error_if!(
+            uuid.is_empty(),
+            "Invalid 'uuid' segment in resource name: expected a uuid, got an empty segment in {resource_name}"
+        );
+        output.uuid = Some(Cow::Borrowed(uuid));
         {
             // Sanity check that our next item is "uploads".
             let uploads = parts
@@ -149,6 +160,91 @@ impl<'a> ResourceInfo<'a> {
         Ok(output)
     }
 
+    /// Returns a `ResourceInfo` for the blob of `digest`. Use the `with_*`
+    /// functions to set the optional parts and `to_string` to turn it into
+    /// a resource name.
+    pub fn from_digest(instance_name: impl Into<Cow<'a, str>>, digest: DigestInfo) -> Self {
+        let size_bytes = digest.size_bytes();
+        ResourceInfo {
+            instance_name: instance_name.into(),
+            hash: Cow::Owned(digest.packed_hash().to_string()),
+            size: Cow::Owned(size_bytes.to_string()),
+            expected_size: size_bytes as usize,
+            ..Default::default()
+        }
+    }
+
+    /// Sets the `uuid` of an upload. Only used if `to_string` is called
+    /// with `is_upload` set.
+    #[must_use]
+    pub fn with_uuid(mut self, uuid: impl Into<Cow<'a, str>>) -> Self {
+        self.uuid = Some(uuid.into());
+        self
+    }
+
+    /// Sets the compressor, which makes this a `compressed-blobs` resource.
+    #[must_use]
+    pub fn with_compressor(mut self, compressor: impl Into<Cow<'a, str>>) -> Self {
+        self.compressor = Some(compressor.into());
+        self
+    }
+
+    /// Sets the `digest_function` segment. Sha256 has no segment, as it is
+    /// the default digest function of resource names.
+    #[must_use]
+    pub fn with_digest_function(mut self, digest_function: DigestHasherFunc) -> Self {
+        self.digest_function = digest_function.resource_name_component().map(Cow::Borrowed);
+        self
+    }
+
+    /// Sets the metadata appended after the size of the blob.
+    #[must_use]
+    pub fn with_optional_metadata(mut self, optional_metadata: impl Into<Cow<'a, str>>) -> Self {
+        self.optional_metadata = Some(optional_metadata.into());
+        self
+    }
+
+    /// Strictly validates the segments `new` does not interpret: the hash
+    /// must be hex of the length of the digest function and the uuid of an
+    /// upload must be a valid UUID. The returned `InvalidArgument` error
+    /// names the segment that failed.
+    pub fn validate(&self) -> Result<(), Error> {
+        error_if!(
+            !self.hash.bytes().all(|b| b.is_ascii_hexdigit()),
+            "Invalid 'hash' segment in resource name: expected hex characters, got '{}'",
+            self.hash
+        );
+        match self.digest_function.as_deref() {
+            Some(digest_function) => {
+                let expected_len = hash_hex_len(digest_function).ok_or_else(|| {
+                    make_input_err!(
+                        "Invalid 'digest_function' segment in resource name: unknown digest function '{digest_function}'"
+                    )
+                })?;
+                error_if!(
+                    self.hash.len() != expected_len,
+                    "Invalid 'hash' segment in resource name: expected {expected_len} hex characters for {digest_function}, got {} in '{}'",
+                    self.hash.len(),
+                    self.hash
+                );
+            }
+            None => error_if!(
+                !INFERABLE_HASH_LENGTHS.contains(&self.hash.len()),
+                "Invalid 'hash' segment in resource name: {} hex characters do not match any digest function that may be omitted, got '{}'",
+                self.hash.len(),
+                self.hash
+            ),
+        }
+        if let Some(uuid) = &self.uuid {
+            uuid::Uuid::try_parse(uuid).map_err(|e| {
+                make_input_err!(
+                    "Invalid 'uuid' segment in resource name: '{uuid}' is not a UUID: {e}"
+                )
+            })?;
+        }
+        Ok(())
+    }
+
     /// Returns a new `ResourceInfo` with all fields owned.
     pub fn to_owned(&self) -> ResourceInfo<'static> {
         ResourceInfo {
@@ -176,7 +272,7 @@ impl<'a> ResourceInfo<'a> {
         [
             Some(self.instance_name.as_ref()),
             is_upload.then_some("uploads"),
-            self.uuid.as_ref().map(AsRef::as_ref),
+            self.uuid.as_ref().filter(|_| is_upload).map(AsRef::as_ref),
             Some(
                 self.compressor
                     .as_ref()
@@ -196,6 +292,19 @@ impl<'a> ResourceInfo<'a> {
     }
 }
 
+/// Returns the length in hex characters of the hashes of `digest_function`.
+fn hash_hex_len(digest_function: &str) -> Option<usize> {
+    match digest_function {
+        "md5" | "murmur3" => Some(32),
+        "sha1" => Some(40),
+        "sha256" | "sha256tree" | "blake3" => Some(64),
+        "vso" => Some(66),
+        "sha384" => Some(96),
+        "sha512" => Some(128),
+        _ => None,
+    }
+}
+
 #[derive(Debug, PartialEq)]
 enum State {
     Unknown,
@@ -254,6 +363,10 @@ fn recursive_parse<'a>(
                 }
             }
             State::Hash => {
+                error_if!(
+                    part.is_empty(),
+                    "Invalid 'hash' segment in resource name: expected a hash, got an empty segment"
+                );
                 output.hash = Cow::Borrowed(part);
                 *bytes_processed += part.len() + SLASH_SIZE;
                 // TODO(allada) Set the digest_function if it is not set based on the hash size.
@@ -263,7 +376,7 @@ fn recursive_parse<'a>(
                 output.size = Cow::Borrowed(part);
                 output.expected_size = part.parse::<usize>().map_err(|_| {
                     make_input_err!(
-                        "Digest size_bytes was not convertible to usize. Got: {}",
+                        "Invalid 'size' segment in resource name: expected a non-negative integer, got '{}'",
                         part
                     )
                 })?;
@@ -91,7 +91,7 @@ impl Retrier {
 
     /// This should only return true if the error code should be interpreted as
     /// temporary.
-    fn should_retry(&self, code: Code) -> bool {
+    pub fn should_retry(&self, code: Code) -> bool {
         if code == Code::Ok {
             false
         } else if let Some(retry_codes) = &self.config.retry_on_errors {
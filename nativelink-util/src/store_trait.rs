@@ -22,10 +22,13 @@ use std::ptr::addr_eq;
 use std::sync::{Arc, OnceLock};
 
 use async_trait::async_trait;
+use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use base64::Engine;
 use bytes::{Bytes, BytesMut};
 use futures::future::{select, Either};
 use futures::{join, try_join, Future, FutureExt, Stream};
-use nativelink_error::{error_if, make_err, Code, Error, ResultExt};
+use nativelink_config::stores::ConfigStoreKeyEncoding;
+use nativelink_error::{error_if, make_err, make_input_err, Code, Error, ResultExt};
 use nativelink_metric::MetricsComponent;
 use rand::rngs::StdRng;
 use rand::{RngCore, SeedableRng};
@@ -245,6 +248,98 @@ impl<'a> StoreKey<'a> {
             StoreKey::Digest(d) => Cow::Owned(format!("{d}")),
         }
     }
+
+    /// Returns the key as a string using the given encoding for digests.
+    /// String keys are always returned as-is.
+    pub fn encode(&'a self, encoding: StoreKeyEncoding) -> Cow<'a, str> {
+        match (self, encoding) {
+            (StoreKey::Digest(d), StoreKeyEncoding::Compact)
+                if d.packed_hash().len() == COMPACT_DIGEST_HASH_LEN =>
+            {
+                Cow::Owned(StoreKeyEncoding::encode_compact_digest(d))
+            }
+            _ => self.as_str(),
+        }
+    }
+}
+
+/// Number of characters of a digest encoded with [`StoreKeyEncoding::Compact`].
+pub const COMPACT_DIGEST_KEY_LEN: usize = 57;
+
+/// Version tag every key encoded with [`StoreKeyEncoding::Compact`] starts
+/// with. Hex keys never contain "_", so the encodings can't be confused.
+pub const COMPACT_DIGEST_KEY_PREFIX: &str = "v1_";
+
+/// Length of the hashes [`StoreKeyEncoding::Compact`] encodes. Digests
+/// with other hash lengths, like SHA-1 or SHA-512 ones, are always
+/// written in hex.
+const COMPACT_DIGEST_HASH_LEN: usize = 32;
+
+/// How stores that persist keys (file names, redis keys, ...) write
+/// [`StoreKey::Digest`] keys. String keys are never re-encoded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StoreKeyEncoding {
+    /// `{hash_in_hex}-{size_in_bytes}`, the same as the `Display` of
+    /// [`DigestInfo`].
+    #[default]
+    Hex,
+    /// [`COMPACT_DIGEST_KEY_PREFIX`], then the unpadded url safe base64 of
+    /// the 32 byte hash followed by the size as a big-endian u64. Always
+    /// [`COMPACT_DIGEST_KEY_LEN`] characters.
+    /// Digests with hashes of other lengths are written in hex.
+    Compact,
+}
+
+impl StoreKeyEncoding {
+    fn encode_compact_digest(digest: &DigestInfo) -> String {
+        let mut raw = [0u8; 40];
+        raw[..COMPACT_DIGEST_HASH_LEN].copy_from_slice(&digest.packed_hash()[..]);
+        raw[COMPACT_DIGEST_HASH_LEN..].copy_from_slice(&digest.size_bytes().to_be_bytes());
+        let mut encoded = String::with_capacity(COMPACT_DIGEST_KEY_LEN);
+        encoded.push_str(COMPACT_DIGEST_KEY_PREFIX);
+        BASE64_URL_SAFE_NO_PAD.encode_string(raw, &mut encoded);
+        encoded
+    }
+
+    /// Parses a digest written with any [`StoreKeyEncoding`] and returns
+    /// it along with the encoding it was written in. Compact keys are the
+    /// ones starting with [`COMPACT_DIGEST_KEY_PREFIX`].
+    pub fn decode_digest(encoded: &str) -> Result<(DigestInfo, Self), Error> {
+        if let Some(compact) = encoded.strip_prefix(COMPACT_DIGEST_KEY_PREFIX) {
+            let mut raw = [0u8; 40];
+            let decoded_len = BASE64_URL_SAFE_NO_PAD
+                .decode_slice(compact, &mut raw)
+                .map_err(|e| make_input_err!("Invalid compact digest key {encoded}: {e}"))?;
+            error_if!(
+                decoded_len != raw.len(),
+                "Invalid compact digest key length {decoded_len} for {encoded}"
+            );
+            let mut packed_hash = [0u8; COMPACT_DIGEST_HASH_LEN];
+            packed_hash.copy_from_slice(&raw[..COMPACT_DIGEST_HASH_LEN]);
+            let size_bytes = u64::from_be_bytes(raw[32..].try_into().unwrap());
+            error_if!(
+                size_bytes > i64::MAX as u64,
+                "Size bytes is too large in compact digest key {encoded}"
+            );
+            return Ok((DigestInfo::new(packed_hash, size_bytes), Self::Compact));
+        }
+        let (hash, size) = encoded
+            .split_once('-')
+            .err_tip(|| format!("Invalid digest key {encoded}"))?;
+        let size = size
+            .parse::<i64>()
+            .map_err(|e| make_input_err!("Invalid size in digest key {encoded}: {e}"))?;
+        Ok((DigestInfo::try_new(hash, size)?, Self::Hex))
+    }
+}
+
+impl From<ConfigStoreKeyEncoding> for StoreKeyEncoding {
+    fn from(value: ConfigStoreKeyEncoding) -> Self {
+        match value {
+            ConfigStoreKeyEncoding::hex => Self::Hex,
+            ConfigStoreKeyEncoding::compact => Self::Compact,
+        }
+    }
 }
 
 impl Clone for StoreKey<'static> {
@@ -813,8 +908,44 @@ pub trait StoreDriver:
     fn as_any(&self) -> &(dyn std::any::Any + Sync + Send + 'static);
     fn as_any_arc(self: Arc<Self>) -> Arc<dyn std::any::Any + Sync + Send + 'static>;
 
-    // Register health checks used to monitor the store.
-    fn register_health(self: Arc<Self>, _registry: &mut HealthRegistryBuilder) {}
+    // Register health checks used to monitor the store. By default only
+    // checks that the store can be reached, as not every store accepts
+    // arbitrary data for the round trip of `StoreDriver::check_health`.
+    fn register_health(self: Arc<Self>, registry: &mut HealthRegistryBuilder) {
+        registry.register_indicator(Arc::new(StoreReachableIndicator { store: self }));
+    }
+}
+
+/// Health indicator that reports a store as healthy while it answers
+/// `has` requests.
+struct StoreReachableIndicator<S: StoreDriver + ?Sized> {
+    store: Arc<S>,
+}
+
+#[async_trait]
+impl<S: StoreDriver + ?Sized> HealthStatusIndicator for StoreReachableIndicator<S> {
+    fn get_name(&self) -> &'static str {
+        self.store.get_name()
+    }
+
+    fn struct_name(&self) -> &'static str {
+        self.store.struct_name()
+    }
+
+    async fn check_health(&self, namespace: Cow<'static, str>) -> HealthStatus {
+        // A digest the store is unlikely to have, so stores with a fast
+        // path for known digests still reach their backend.
+        let mut digest_hasher = default_digest_hasher_func().hasher();
+        digest_hasher.update(namespace.as_bytes());
+        let digest_info = StoreKey::from(digest_hasher.finalize_digest());
+        match Pin::new(self.store.as_ref()).has(digest_info).await {
+            Ok(_) => HealthStatus::new_ok(self.store.as_ref(), "Store is reachable".into()),
+            Err(e) => HealthStatus::new_failed(
+                self.store.as_ref(),
+                format!("Store.has() failed: {e}").into(),
+            ),
+        }
+    }
 }
 
 /// The instructions on how to decode a value from a Bytes & version into
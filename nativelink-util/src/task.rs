@@ -12,19 +12,35 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::panic::{catch_unwind, AssertUnwindSafe};
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, OnceLock};
 use std::task::{Context, Poll};
+use std::thread::available_parallelism;
 
 use futures::Future;
 use hyper::rt::Executor;
 use hyper_util::rt::tokio::TokioExecutor;
+use nativelink_error::{make_err, Code, Error};
+use nativelink_metric::{MetricsComponent, RootMetricsComponent};
+use parking_lot::Mutex;
+use tokio::runtime::Handle;
+use tokio::sync::oneshot;
 use tokio::task::{spawn_blocking, JoinError, JoinHandle};
 pub use tracing::error_span as __error_span;
-use tracing::{Instrument, Span};
+use tracing::{event, Instrument, Level, Span};
 
 use crate::origin_context::{ActiveOriginContext, ContextAwareFuture, OriginContext};
 
+/// Number of threads in the filesystem pool if `set_blocking_pool_threads()`
+/// was never called.
+pub const DEFAULT_FILESYSTEM_POOL_THREADS: usize = 64;
+
+/// Upper bound of the number of threads of a blocking pool. Larger values
+/// passed to `set_blocking_pool_threads()` are capped to this.
+pub const MAX_BLOCKING_POOL_THREADS: usize = 1024;
+
 pub fn __spawn_with_span_and_context<F, T>(
     f: F,
     span: Span,
@@ -55,6 +71,291 @@ where
     spawn_blocking(move || span.in_scope(f))
 }
 
+pub fn __spawn_blocking_on<F, T>(kind: BlockingPoolKind, f: F, span: Span) -> BlockingJobHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    blocking_pools().get(kind).spawn(f, span)
+}
+
+/// Dedicated pools that blocking work can be routed to, so it does not
+/// compete with the tokio worker threads or with other kinds of blocking
+/// work.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockingPoolKind {
+    /// Filesystem calls, such as opening files, reading directories and
+    /// updating timestamps.
+    Filesystem,
+    /// CPU heavy work, such as hashing and compression.
+    Cpu,
+}
+
+static BLOCKING_POOL_THREADS: OnceLock<(usize, usize)> = OnceLock::new();
+static BLOCKING_POOLS: OnceLock<Arc<BlockingPools>> = OnceLock::new();
+
+/// Sets the maximum number of threads of the filesystem and cpu pools,
+/// capped to `MAX_BLOCKING_POOL_THREADS`. Must be called before any work is
+/// sent to a pool.
+pub fn set_blocking_pool_threads(
+    filesystem_threads: usize,
+    cpu_threads: usize,
+) -> Result<(), Error> {
+    if filesystem_threads == 0 || cpu_threads == 0 {
+        return Err(make_err!(
+            Code::InvalidArgument,
+            "Blocking pools must have at least one thread"
+        ));
+    }
+    if filesystem_threads > MAX_BLOCKING_POOL_THREADS || cpu_threads > MAX_BLOCKING_POOL_THREADS {
+        event!(
+            Level::WARN,
+            filesystem_threads,
+            cpu_threads,
+            max_threads = MAX_BLOCKING_POOL_THREADS,
+            "Blocking pool threads capped to the maximum"
+        );
+    }
+    // Starting the pools also sets this value, so this will fail if any
+    // work was already sent to a pool.
+    BLOCKING_POOL_THREADS
+        .set((
+            filesystem_threads.min(MAX_BLOCKING_POOL_THREADS),
+            cpu_threads.min(MAX_BLOCKING_POOL_THREADS),
+        ))
+        .map_err(|_| make_err!(Code::Internal, "blocking_pool_threads already set"))
+}
+
+/// Returns the dedicated blocking pools, starting them if needed.
+pub fn blocking_pools() -> Arc<BlockingPools> {
+    BLOCKING_POOLS
+        .get_or_init(|| {
+            let (filesystem_threads, cpu_threads) = *BLOCKING_POOL_THREADS.get_or_init(|| {
+                (
+                    DEFAULT_FILESYSTEM_POOL_THREADS,
+                    available_parallelism().map_or(1, usize::from),
+                )
+            });
+            Arc::new(BlockingPools {
+                filesystem: BlockingPool::new("filesystem", filesystem_threads),
+                cpu: BlockingPool::new("cpu", cpu_threads),
+            })
+        })
+        .clone()
+}
+
+#[derive(MetricsComponent)]
+pub struct BlockingPools {
+    #[metric(group = "filesystem")]
+    filesystem: BlockingPool,
+    #[metric(group = "cpu")]
+    cpu: BlockingPool,
+}
+
+impl BlockingPools {
+    pub fn get(&self, kind: BlockingPoolKind) -> &BlockingPool {
+        match kind {
+            BlockingPoolKind::Filesystem => &self.filesystem,
+            BlockingPoolKind::Cpu => &self.cpu,
+        }
+    }
+}
+
+impl RootMetricsComponent for BlockingPools {}
+
+#[derive(Default, MetricsComponent)]
+pub struct BlockingPoolMetrics {
+    #[metric(help = "Number of threads in the pool.")]
+    pub threads: AtomicU64,
+    #[metric(help = "Number of threads waiting for a task.")]
+    pub idle_threads: AtomicU64,
+    #[metric(help = "Number of tasks waiting for a free thread.")]
+    pub queued_tasks: AtomicU64,
+    #[metric(help = "Number of tasks currently running.")]
+    pub active_tasks: AtomicU64,
+    #[metric(help = "Number of tasks that have finished running.")]
+    pub completed_tasks: AtomicU64,
+}
+
+type BlockingJob = Box<dyn FnOnce() + Send + 'static>;
+
+/// A pool of OS threads that runs blocking closures. Threads are started
+/// on demand, up to `max_threads`, and are kept for the life of the pool.
+#[derive(MetricsComponent)]
+pub struct BlockingPool {
+    name: &'static str,
+    #[metric(help = "Maximum number of threads in the pool.")]
+    max_threads: usize,
+    tx: Mutex<mpsc::Sender<BlockingJob>>,
+    rx: Arc<Mutex<mpsc::Receiver<BlockingJob>>>,
+    #[metric]
+    metrics: Arc<BlockingPoolMetrics>,
+}
+
+impl BlockingPool {
+    fn new(name: &'static str, max_threads: usize) -> Self {
+        let (tx, rx) = mpsc::channel::<BlockingJob>();
+        Self {
+            name,
+            max_threads,
+            tx: Mutex::new(tx),
+            rx: Arc::new(Mutex::new(rx)),
+            metrics: Arc::new(BlockingPoolMetrics::default()),
+        }
+    }
+
+    /// Returns the metrics of this pool.
+    pub fn metrics(&self) -> &Arc<BlockingPoolMetrics> {
+        &self.metrics
+    }
+
+    /// Starts one more thread that takes jobs from the queue.
+    fn start_thread(&self) -> Result<(), Error> {
+        let rx = self.rx.clone();
+        let metrics = self.metrics.clone();
+        let index = metrics.threads.load(Ordering::Acquire);
+        // A new thread is idle until it receives its first job.
+        metrics.idle_threads.fetch_add(1, Ordering::AcqRel);
+        #[allow(clippy::disallowed_methods)]
+        let spawn_result = std::thread::Builder::new()
+            .name(format!("nl-{}-{index}", self.name))
+            .spawn(move || loop {
+                // The lock is released as soon as a job is received.
+                let job = rx.lock().recv();
+                metrics.idle_threads.fetch_sub(1, Ordering::AcqRel);
+                let Ok(job) = job else {
+                    return; // Pool was dropped.
+                };
+                // Jobs mark the thread as idle again before waking their
+                // caller, see `spawn()`.
+                job();
+            });
+        if let Err(e) = spawn_result {
+            self.metrics.idle_threads.fetch_sub(1, Ordering::AcqRel);
+            return Err(make_err!(
+                Code::ResourceExhausted,
+                "Failed to spawn thread of the {} blocking pool: {e:?}",
+                self.name
+            ));
+        }
+        self.metrics.threads.fetch_add(1, Ordering::AcqRel);
+        Ok(())
+    }
+
+    /// Runs `f` on one of the threads of this pool, starting a new thread
+    /// if all threads are busy and the pool is not full. Panics in `f` are
+    /// returned as an `Error` by the handle.
+    pub fn spawn<F, T>(&self, f: F, span: Span) -> BlockingJobHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (result_tx, result_rx) = oneshot::channel();
+        let metrics = self.metrics.clone();
+        // Like tokio's own blocking threads, jobs run inside the runtime of
+        // the caller, so they can use `Handle::current()`.
+        let runtime = Handle::try_current().ok();
+        let job: BlockingJob = Box::new(move || {
+            let _runtime_guard = runtime.as_ref().map(Handle::enter);
+            metrics.queued_tasks.fetch_sub(1, Ordering::Relaxed);
+            metrics.active_tasks.fetch_add(1, Ordering::Relaxed);
+            let result = catch_unwind(AssertUnwindSafe(|| span.in_scope(f)));
+            metrics.active_tasks.fetch_sub(1, Ordering::Relaxed);
+            metrics.completed_tasks.fetch_add(1, Ordering::Relaxed);
+            // Count the thread as idle before the caller can send the next
+            // job, so sequential jobs reuse this thread.
+            metrics.idle_threads.fetch_add(1, Ordering::AcqRel);
+            // The receiver may have been dropped if the caller lost interest.
+            let _ = result_tx.send(result);
+        });
+        // Holding the sender lock makes the thread count check and the
+        // thread start atomic with respect to other callers.
+        let tx = self.tx.lock();
+        let threads = self.metrics.threads.load(Ordering::Acquire);
+        let queued = self.metrics.queued_tasks.load(Ordering::Acquire);
+        if queued >= self.metrics.idle_threads.load(Ordering::Acquire)
+            && threads < self.max_threads as u64
+        {
+            if let Err(err) = self.start_thread() {
+                // Jobs still run on the existing threads, only fail if
+                // there are none.
+                if threads == 0 {
+                    return BlockingJobHandle {
+                        result_rx: None,
+                        start_error: Some(err),
+                    };
+                }
+                event!(
+                    Level::WARN,
+                    ?err,
+                    "Running job on existing blocking pool threads"
+                );
+            }
+        }
+        self.metrics.queued_tasks.fetch_add(1, Ordering::Relaxed);
+        if tx.send(job).is_err() {
+            // Unreachable while the pool holds the receiver, the dropped
+            // job is reported by the handle.
+            self.metrics.queued_tasks.fetch_sub(1, Ordering::Relaxed);
+        }
+        BlockingJobHandle {
+            result_rx: Some(result_rx),
+            start_error: None,
+        }
+    }
+}
+
+/// Future returned by `BlockingPool::spawn()`, resolving to the value
+/// returned by the job.
+#[must_use]
+pub struct BlockingJobHandle<T> {
+    result_rx: Option<oneshot::Receiver<std::thread::Result<T>>>,
+    start_error: Option<Error>,
+}
+
+impl<T> Future for BlockingJobHandle<T> {
+    type Output = Result<T, Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some(err) = self.start_error.take() {
+            return Poll::Ready(Err(err));
+        }
+        let Some(result_rx) = self.result_rx.as_mut() else {
+            return Poll::Ready(Err(make_err!(
+                Code::Internal,
+                "BlockingJobHandle polled after completion"
+            )));
+        };
+        let result = match Pin::new(result_rx).poll(cx) {
+            Poll::Ready(result) => result,
+            Poll::Pending => return Poll::Pending,
+        };
+        self.result_rx = None;
+        Poll::Ready(match result {
+            Ok(Ok(value)) => Ok(value),
+            Ok(Err(panic_payload)) => Err(make_err!(
+                Code::Internal,
+                "Blocking pool job panicked: {}",
+                panic_message(&*panic_payload)
+            )),
+            Err(_) => Err(make_err!(
+                Code::Internal,
+                "Blocking pool dropped job before it ran"
+            )),
+        })
+    }
+}
+
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "<non-string panic payload>"
+    }
+}
+
 #[macro_export]
 macro_rules! background_spawn {
     ($name:expr, $fut:expr) => {{
@@ -86,6 +387,12 @@ macro_rules! spawn {
 
 #[macro_export]
 macro_rules! spawn_blocking {
+    (pool: $pool:expr, $name:expr, $fut:expr) => {{
+        $crate::task::__spawn_blocking_on($pool, $fut, $crate::task::__error_span!($name))
+    }};
+    (pool: $pool:expr, $name:expr, $fut:expr, $($fields:tt)*) => {{
+        $crate::task::__spawn_blocking_on($pool, $fut, $crate::task::__error_span!($name, $($fields)*))
+    }};
     ($name:expr, $fut:expr) => {{
         $crate::task::JoinHandleDropGuard::new($crate::task::__spawn_blocking($fut, $crate::task::__error_span!($name)))
     }};
@@ -112,6 +419,11 @@ impl<T> JoinHandleDropGuard<T> {
     pub fn new(inner: JoinHandle<T>) -> Self {
         Self { inner }
     }
+
+    /// Returns true if the task has stopped running.
+    pub fn is_finished(&self) -> bool {
+        self.inner.is_finished()
+    }
 }
 
 impl<T> Future for JoinHandleDropGuard<T> {
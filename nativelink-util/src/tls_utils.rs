@@ -110,3 +110,78 @@ pub fn endpoint(endpoint_config: &GrpcEndpoint) -> Result<tonic::transport::Endp
         Ok(endpoint)
     }
 }
+
+const DER_SEQUENCE: u8 = 0x30;
+const DER_SET: u8 = 0x31;
+const DER_OBJECT_IDENTIFIER: u8 = 0x06;
+/// Tag of the explicit `version` field of a certificate.
+const DER_CONTEXT_SPECIFIC_0: u8 = 0xa0;
+/// Object identifier of the common name attribute, 2.5.4.3.
+const COMMON_NAME_OID: &[u8] = &[0x55, 0x04, 0x03];
+
+/// Splits the DER element at the start of `data` into its tag, its
+/// contents and the data following it.
+fn der_element(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, data) = data.split_first()?;
+    let (&len, data) = data.split_first()?;
+    let (len, data) = if len & 0x80 == 0 {
+        (usize::from(len), data)
+    } else {
+        let len_size = usize::from(len & 0x7f);
+        if len_size == 0 || len_size > std::mem::size_of::<usize>() || data.len() < len_size {
+            return None;
+        }
+        let (len_bytes, data) = data.split_at(len_size);
+        let len = len_bytes
+            .iter()
+            .fold(0, |len, byte| len << 8 | usize::from(*byte));
+        (len, data)
+    };
+    if data.len() < len {
+        return None;
+    }
+    let (contents, data) = data.split_at(len);
+    Some((tag, contents, data))
+}
+
+/// Returns the common name of the subject of a DER encoded X.509
+/// certificate, or None if it has none.
+pub fn certificate_common_name(cert: &[u8]) -> Option<String> {
+    let (DER_SEQUENCE, cert, _) = der_element(cert)? else {
+        return None;
+    };
+    let (DER_SEQUENCE, mut fields, _) = der_element(cert)? else {
+        return None;
+    };
+    let (tag, _, rest) = der_element(fields)?;
+    if tag == DER_CONTEXT_SPECIFIC_0 {
+        fields = rest;
+    }
+    // Skip the serial number, signature algorithm, issuer and validity.
+    for _ in 0..4 {
+        fields = der_element(fields)?.2;
+    }
+    let (DER_SEQUENCE, mut subject, _) = der_element(fields)? else {
+        return None;
+    };
+    while !subject.is_empty() {
+        let (DER_SET, mut attributes, rest) = der_element(subject)? else {
+            return None;
+        };
+        subject = rest;
+        while !attributes.is_empty() {
+            let (DER_SEQUENCE, attribute, rest) = der_element(attributes)? else {
+                return None;
+            };
+            attributes = rest;
+            let (DER_OBJECT_IDENTIFIER, oid, value) = der_element(attribute)? else {
+                return None;
+            };
+            if oid == COMMON_NAME_OID {
+                let (_, value, _) = der_element(value)?;
+                return String::from_utf8(value.to_vec()).ok();
+            }
+        }
+    }
+    None
+}
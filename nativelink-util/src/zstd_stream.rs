@@ -0,0 +1,150 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use bytes::Bytes;
+use nativelink_error::{make_err, make_input_err, Code, Error, ResultExt};
+use zstd::stream::raw::{Decoder, Encoder, Operation, OutBuffer};
+
+use crate::buf_channel::{DropCloserReadHalf, DropCloserWriteHalf, HashingWriteHalf};
+use crate::common::DigestInfo;
+use crate::digest_hasher::DigestHasherFunc;
+
+/// Size of the buffer the (de)compressed data is written into before it
+/// is sent to the writer.
+const OUTPUT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Compression level used for `compressed-blobs` reads. Level 1 keeps the
+/// CPU cost of serving compressed reads low.
+pub const DEFAULT_COMPRESSION_LEVEL: i32 = 1;
+
+/// Compresses all the data of `reader` into a single zstd frame and writes
+/// it to `writer`, followed by an EOF.
+pub async fn compress(
+    reader: &mut DropCloserReadHalf,
+    writer: &mut DropCloserWriteHalf,
+    level: i32,
+) -> Result<(), Error> {
+    let mut encoder = Encoder::new(level)
+        .map_err(|e| make_err!(Code::Internal, "Failed to create zstd encoder: {e:?}"))?;
+    let mut output = vec![0u8; OUTPUT_BUFFER_SIZE];
+    loop {
+        let chunk = reader
+            .recv()
+            .await
+            .err_tip(|| "Failed to read chunk in zstd_stream::compress")?;
+        if chunk.is_empty() {
+            break; // EOF.
+        }
+        let mut input = chunk.as_ref();
+        while !input.is_empty() {
+            let status = encoder
+                .run_on_buffers(input, &mut output)
+                .map_err(|e| make_err!(Code::Internal, "Failed to zstd compress: {e:?}"))?;
+            input = &input[status.bytes_read..];
+            if status.bytes_written > 0 {
+                writer
+                    .send(Bytes::copy_from_slice(&output[..status.bytes_written]))
+                    .await
+                    .err_tip(|| "Failed to send chunk in zstd_stream::compress")?;
+            }
+        }
+    }
+    loop {
+        let mut out_buffer = OutBuffer::around(output.as_mut_slice());
+        let remaining = encoder
+            .finish(&mut out_buffer, true)
+            .map_err(|e| make_err!(Code::Internal, "Failed to finish zstd frame: {e:?}"))?;
+        let bytes_written = out_buffer.pos();
+        if bytes_written > 0 {
+            writer
+                .send(Bytes::copy_from_slice(&output[..bytes_written]))
+                .await
+                .err_tip(|| "Failed to send chunk in zstd_stream::compress")?;
+        }
+        if remaining == 0 {
+            break;
+        }
+    }
+    writer
+        .send_eof()
+        .err_tip(|| "Failed to send EOF in zstd_stream::compress")
+}
+
+/// Decompresses the zstd data of `reader` and writes it to `writer`. The
+/// EOF is only sent once the decompressed data is verified to match
+/// `expected_digest`, so stores never commit data that does not belong to
+/// the digest it was uploaded under.
+pub async fn decompress_and_verify(
+    reader: &mut DropCloserReadHalf,
+    writer: &mut DropCloserWriteHalf,
+    hasher_func: DigestHasherFunc,
+    expected_digest: DigestInfo,
+) -> Result<(), Error> {
+    let mut decoder = Decoder::new()
+        .map_err(|e| make_err!(Code::Internal, "Failed to create zstd decoder: {e:?}"))?;
+    let mut writer = HashingWriteHalf::new(writer, Some(hasher_func.hasher()));
+    let mut output = vec![0u8; OUTPUT_BUFFER_SIZE];
+    // Zero once the decoder finished a frame and flushed all of its data.
+    let mut frame_remaining = 0;
+    loop {
+        let chunk = reader
+            .recv()
+            .await
+            .err_tip(|| "Failed to read chunk in zstd_stream::decompress_and_verify")?;
+        if chunk.is_empty() {
+            break; // EOF.
+        }
+        let mut input = chunk.as_ref();
+        loop {
+            let status = decoder
+                .run_on_buffers(input, &mut output)
+                .map_err(|e| make_input_err!("Failed to zstd decompress: {e:?}"))?;
+            input = &input[status.bytes_read..];
+            frame_remaining = status.remaining;
+            if status.bytes_written > 0 {
+                writer
+                    .send(Bytes::copy_from_slice(&output[..status.bytes_written]))
+                    .await
+                    .err_tip(|| "Failed to send chunk in zstd_stream::decompress_and_verify")?;
+            }
+            if writer.get_bytes_written() > expected_digest.size_bytes() {
+                return Err(make_input_err!(
+                    "Decompressed data is larger than the {} bytes of {expected_digest}",
+                    expected_digest.size_bytes()
+                ));
+            }
+            // A full output buffer means the decoder may still hold data
+            // even if all the input was consumed.
+            if input.is_empty() && status.bytes_written < output.len() {
+                break;
+            }
+        }
+    }
+    if frame_remaining != 0 {
+        return Err(make_input_err!(
+            "Compressed data of {expected_digest} ended in the middle of a zstd frame"
+        ));
+    }
+    let actual_digest = writer
+        .finalize_digest()
+        .err_tip(|| "Hasher missing in zstd_stream::decompress_and_verify")?;
+    if actual_digest != expected_digest {
+        return Err(make_input_err!(
+            "Decompressed data has digest {actual_digest}, expected {expected_digest}"
+        ));
+    }
+    writer
+        .send_eof()
+        .err_tip(|| "Failed to send EOF in zstd_stream::decompress_and_verify")
+}
@@ -0,0 +1,114 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+
+use nativelink_config::cas_server::AuditLogConfig;
+use nativelink_error::{Code, Error};
+use nativelink_macro::nativelink_test;
+use nativelink_util::audit_log::{AuditLog, AuditRecord};
+use nativelink_util::common::DigestInfo;
+use nativelink_util::origin_context::{ActiveOriginContext, ORIGIN_IDENTITY};
+use pretty_assertions::assert_eq;
+use rand::{thread_rng, Rng};
+use tracing::info_span;
+
+const HASH: &str = "0123456789abcdef000000000000000000000000000000000123456789abcdef";
+
+fn make_path() -> String {
+    format!(
+        "{}/audit-{}.log",
+        env::var("TEST_TMPDIR").unwrap_or(env::temp_dir().to_str().unwrap().to_string()),
+        thread_rng().gen::<u64>(),
+    )
+}
+
+fn make_record(size_bytes: u64) -> Result<AuditRecord<'static>, Error> {
+    Ok(AuditRecord {
+        operation: "UpdateActionResult",
+        instance_name: "main",
+        digest: Some(DigestInfo::try_new(HASH, size_bytes)?),
+        size_bytes,
+        latency: Duration::from_millis(3),
+        status: Code::Ok,
+    })
+}
+
+fn read_lines(path: &str) -> Result<Vec<serde_json::Value>, Error> {
+    std::fs::read_to_string(path)?
+        .lines()
+        .map(|line| {
+            serde_json::from_str(line).map_err(|e| Error::new(Code::Internal, e.to_string()))
+        })
+        .collect()
+}
+
+#[nativelink_test]
+async fn records_identity_and_request_test() -> Result<(), Error> {
+    let path = make_path();
+    let audit_log = AuditLog::new(&AuditLogConfig {
+        path: path.clone(),
+        ..Default::default()
+    })?;
+
+    let mut context = ActiveOriginContext::fork()?;
+    context.set_value(&ORIGIN_IDENTITY, Arc::new("alice".to_string()));
+    let record = make_record(5)?;
+    context.run(info_span!("audit"), || audit_log.record(&record));
+    audit_log.flush().await?;
+
+    let lines = read_lines(&path)?;
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0]["operation"], "UpdateActionResult");
+    assert_eq!(lines[0]["identity"], "alice");
+    assert_eq!(lines[0]["instance_name"], "main");
+    assert_eq!(lines[0]["digest"], format!("{HASH}-5"));
+    assert_eq!(lines[0]["size_bytes"], 5);
+    assert_eq!(lines[0]["latency_ms"], 3.);
+    assert_eq!(lines[0]["status"], "Ok");
+    Ok(())
+}
+
+#[nativelink_test]
+async fn samples_and_rotates_test() -> Result<(), Error> {
+    let path = make_path();
+    let audit_log = AuditLog::new(&AuditLogConfig {
+        path: path.clone(),
+        max_file_size: 1,
+        max_rotated_files: 2,
+        sample_one_in: 2,
+        ..Default::default()
+    })?;
+
+    // Only the records with even sizes are sampled, and every one of them
+    // ends up in its own file, of which the last three are kept.
+    for size_bytes in 0..10 {
+        audit_log.record(&make_record(size_bytes)?);
+    }
+    audit_log.flush().await?;
+
+    let sizes = |path: &str| -> Result<Vec<serde_json::Value>, Error> {
+        Ok(read_lines(path)?
+            .into_iter()
+            .map(|line| line["size_bytes"].clone())
+            .collect())
+    };
+    assert_eq!(sizes(&path)?, vec![8]);
+    assert_eq!(sizes(&format!("{path}.1"))?, vec![6]);
+    assert_eq!(sizes(&format!("{path}.2"))?, vec![4]);
+    assert!(!std::path::Path::new(&format!("{path}.3")).exists());
+    Ok(())
+}
@@ -0,0 +1,291 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::convert::Infallible;
+use std::env;
+use std::task::{Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use futures::future::BoxFuture;
+use hyper::header::HeaderValue;
+use hyper::http::{self, StatusCode};
+use hyper::HeaderMap;
+use nativelink_config::cas_server::{
+    ApiKeyAuthSpec, ApiKeySpec, AuthConfig, AuthMethod, AuthorizationAction, AuthorizationPolicy,
+    IdentityHeaderSpec, JwtAuthSpec, PolicyEffect, ProxyHeaderAuthSpec,
+};
+use nativelink_error::{Code, Error};
+use nativelink_macro::nativelink_test;
+use nativelink_util::auth_middleware::{AuthIdentity, AuthMiddlewareLayer, Authenticator};
+use nativelink_util::authorization::authorize;
+use nativelink_util::origin_event_middleware::OriginEventMiddlewareLayer;
+use pretty_assertions::assert_eq;
+use rand::{thread_rng, Rng};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use serde_json::json;
+use tower::{Layer, Service};
+
+fn headers(entries: &[(&'static str, &str)]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for (name, value) in entries {
+        headers.insert(*name, HeaderValue::from_str(value).unwrap());
+    }
+    headers
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn sign_jwt(key: &Ed25519KeyPair, kid: &str, claims: &serde_json::Value) -> String {
+    let header = URL_SAFE_NO_PAD.encode(json!({"alg": "EdDSA", "kid": kid}).to_string());
+    let claims = URL_SAFE_NO_PAD.encode(claims.to_string());
+    let signature = URL_SAFE_NO_PAD.encode(key.sign(format!("{header}.{claims}").as_bytes()));
+    format!("{header}.{claims}.{signature}")
+}
+
+/// Answers whether the request may update the action cache of "main".
+#[derive(Clone)]
+struct AcWriteService;
+
+impl Service<http::Request<String>> for AcWriteService {
+    type Response = http::Response<String>;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _req: http::Request<String>) -> Self::Future {
+        Box::pin(async move {
+            let status = match authorize(AuthorizationAction::ac_write, "main") {
+                Ok(()) => StatusCode::OK,
+                Err(_) => StatusCode::FORBIDDEN,
+            };
+            Ok(http::Response::builder()
+                .status(status)
+                .body(String::new())
+                .unwrap())
+        })
+    }
+}
+
+#[nativelink_test]
+async fn api_key_and_proxy_header_test() -> Result<(), Error> {
+    let authenticator = Authenticator::new(&AuthConfig {
+        methods: vec![
+            AuthMethod::api_key(ApiKeyAuthSpec {
+                header_name: String::new(),
+                keys: vec![ApiKeySpec {
+                    key: "secret".to_string(),
+                    identity: "ci".to_string(),
+                    roles: vec!["ac-writer".to_string()],
+                }],
+            }),
+            AuthMethod::proxy_header(ProxyHeaderAuthSpec {
+                identity_header: "x-forwarded-user".to_string(),
+                roles_header: Some("x-forwarded-groups".to_string()),
+            }),
+        ],
+        required: false,
+        policies: Vec::new(),
+    })?;
+
+    assert_eq!(
+        authenticator
+            .authenticate(&headers(&[("x-api-key", "secret")]))
+            .await?,
+        Some(AuthIdentity {
+            identity: "ci".to_string(),
+            roles: vec!["ac-writer".to_string()],
+        })
+    );
+    assert_eq!(
+        authenticator
+            .authenticate(&headers(&[("x-api-key", "wrong")]))
+            .await
+            .unwrap_err()
+            .code,
+        Code::Unauthenticated
+    );
+    assert_eq!(
+        authenticator
+            .authenticate(&headers(&[
+                ("x-forwarded-user", "alice"),
+                ("x-forwarded-groups", "dev, admin"),
+            ]))
+            .await?,
+        Some(AuthIdentity {
+            identity: "alice".to_string(),
+            roles: vec!["dev".to_string(), "admin".to_string()],
+        })
+    );
+    assert_eq!(authenticator.authenticate(&HeaderMap::new()).await?, None);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn required_credentials_test() -> Result<(), Error> {
+    let authenticator = Authenticator::new(&AuthConfig {
+        methods: vec![AuthMethod::proxy_header(ProxyHeaderAuthSpec {
+            identity_header: "x-forwarded-user".to_string(),
+            roles_header: None,
+        })],
+        required: true,
+        policies: Vec::new(),
+    })?;
+    assert_eq!(
+        authenticator
+            .authenticate(&HeaderMap::new())
+            .await
+            .unwrap_err()
+            .code,
+        Code::Unauthenticated
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn jwt_test() -> Result<(), Error> {
+    let key = Ed25519KeyPair::from_seed_unchecked(&[7; 32]).unwrap();
+    let other_key = Ed25519KeyPair::from_seed_unchecked(&[8; 32]).unwrap();
+    let jwks_path = format!(
+        "{}/jwks-{}.json",
+        env::var("TEST_TMPDIR").unwrap_or(env::temp_dir().to_str().unwrap().to_string()),
+        thread_rng().gen::<u64>(),
+    );
+    let jwks = json!({"keys": [{
+        "kty": "OKP",
+        "crv": "Ed25519",
+        "kid": "key-1",
+        "x": URL_SAFE_NO_PAD.encode(key.public_key().as_ref()),
+    }]});
+    std::fs::write(&jwks_path, jwks.to_string())?;
+
+    let authenticator = Authenticator::new(&AuthConfig {
+        methods: vec![AuthMethod::jwt(JwtAuthSpec {
+            jwks_url: format!("file://{jwks_path}"),
+            issuer: Some("https://issuer.example.com".to_string()),
+            audience: Some("nativelink".to_string()),
+            roles_claim: Some("realm_access.roles".to_string()),
+            ..Default::default()
+        })],
+        required: false,
+        policies: Vec::new(),
+    })?;
+    let claims = json!({
+        "iss": "https://issuer.example.com",
+        "aud": ["nativelink", "other"],
+        "sub": "bob",
+        "exp": now() + 600,
+        "realm_access": {"roles": ["ac-writer"]},
+    });
+    let authenticate = |token: String| {
+        let authenticator = &authenticator;
+        async move {
+            authenticator
+                .authenticate(&headers(&[("authorization", &format!("Bearer {token}"))]))
+                .await
+        }
+    };
+
+    assert_eq!(
+        authenticate(sign_jwt(&key, "key-1", &claims)).await?,
+        Some(AuthIdentity {
+            identity: "bob".to_string(),
+            roles: vec!["ac-writer".to_string()],
+        })
+    );
+
+    let mut far_future_claims = claims.clone();
+    far_future_claims["exp"] = json!(u64::MAX);
+    assert_eq!(
+        authenticate(sign_jwt(&key, "key-1", &far_future_claims)).await?,
+        Some(AuthIdentity {
+            identity: "bob".to_string(),
+            roles: vec!["ac-writer".to_string()],
+        })
+    );
+
+    let mut expired_claims = claims.clone();
+    expired_claims["exp"] = json!(now() - 600);
+    let mut wrong_audience_claims = claims.clone();
+    wrong_audience_claims["aud"] = json!("other");
+    for token in [
+        sign_jwt(&other_key, "key-1", &claims),
+        sign_jwt(&key, "key-1", &expired_claims),
+        sign_jwt(&key, "key-1", &wrong_audience_claims),
+        "not-a-jwt".to_string(),
+    ] {
+        assert_eq!(
+            authenticate(token).await.unwrap_err().code,
+            Code::Unauthenticated
+        );
+    }
+    Ok(())
+}
+
+#[nativelink_test]
+async fn anonymous_request_roles_header_ignored_test() -> Result<(), Error> {
+    let auth_layer = AuthMiddlewareLayer::new(&AuthConfig {
+        methods: vec![AuthMethod::api_key(ApiKeyAuthSpec {
+            header_name: String::new(),
+            keys: vec![ApiKeySpec {
+                key: "secret".to_string(),
+                identity: "ci".to_string(),
+                roles: vec!["ac-writer".to_string()],
+            }],
+        })],
+        required: false,
+        policies: vec![AuthorizationPolicy {
+            name: "ac-writers".to_string(),
+            identities: Vec::new(),
+            roles: vec!["ac-writer".to_string()],
+            instance_names: Vec::new(),
+            actions: vec![AuthorizationAction::ac_write],
+            effect: PolicyEffect::allow,
+        }],
+    })?;
+    let origin_event_layer = OriginEventMiddlewareLayer::new(
+        None,
+        IdentityHeaderSpec {
+            header_name: None,
+            required: false,
+            roles_header_name: Some("x-roles".to_string()),
+        },
+    );
+    let mut service = auth_layer.layer(origin_event_layer.layer(AcWriteService));
+
+    let mut call = |entries: &[(&'static str, &str)]| {
+        let mut req = http::Request::new(String::new());
+        *req.headers_mut() = headers(entries);
+        service.call(req)
+    };
+
+    // The roles header is set by the client, so it is not trusted.
+    let response = call(&[("x-identity", "ci"), ("x-roles", "ac-writer")])
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = call(&[("x-api-key", "secret")]).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    Ok(())
+}
@@ -0,0 +1,128 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use nativelink_config::cas_server::{AuthorizationAction, AuthorizationPolicy, PolicyEffect};
+use nativelink_error::{Code, Error};
+use nativelink_util::authorization::{authorize, require_authorizer, Authorizer};
+use pretty_assertions::assert_eq;
+
+fn make_authorizer() -> Result<Authorizer, Error> {
+    Authorizer::new(&[
+        AuthorizationPolicy {
+            name: "no-release-writes-from-ci".to_string(),
+            identities: vec!["ci".to_string()],
+            instance_names: vec!["release".to_string()],
+            actions: vec![
+                AuthorizationAction::cas_write,
+                AuthorizationAction::ac_write,
+            ],
+            effect: PolicyEffect::deny,
+            ..Default::default()
+        },
+        AuthorizationPolicy {
+            name: "writers".to_string(),
+            identities: vec!["ci".to_string()],
+            roles: vec!["ac-writer".to_string()],
+            actions: vec![
+                AuthorizationAction::cas_write,
+                AuthorizationAction::ac_write,
+            ],
+            ..Default::default()
+        },
+        AuthorizationPolicy {
+            name: "team-execution".to_string(),
+            roles: vec!["dev".to_string()],
+            instance_names: vec!["team/*".to_string()],
+            actions: vec![AuthorizationAction::execute],
+            ..Default::default()
+        },
+        AuthorizationPolicy {
+            name: "readers".to_string(),
+            identities: vec!["*".to_string()],
+            actions: vec![AuthorizationAction::read],
+            ..Default::default()
+        },
+    ])
+}
+
+#[test]
+fn first_matching_policy_decides() -> Result<(), Error> {
+    let authorizer = make_authorizer()?;
+    let no_roles: &[String] = &[];
+    let dev: &[String] = &["dev".to_string()];
+
+    // Every client with an identity may read.
+    authorizer.check("bob", no_roles, AuthorizationAction::read, "main")?;
+    authorizer.check("ci", no_roles, AuthorizationAction::cas_write, "main")?;
+    authorizer.check(
+        "alice",
+        &["ac-writer".to_string()],
+        AuthorizationAction::ac_write,
+        "main",
+    )?;
+    authorizer.check("alice", dev, AuthorizationAction::execute, "team/a")?;
+
+    let err = authorizer
+        .check("ci", no_roles, AuthorizationAction::ac_write, "release")
+        .unwrap_err();
+    assert_eq!(err.code, Code::PermissionDenied);
+    assert!(
+        err.message_string()
+            .contains("Policy 'no-release-writes-from-ci'"),
+        "{err:?}"
+    );
+
+    for (identity, roles, action, instance_name) in [
+        ("alice", dev, AuthorizationAction::cas_write, "main"),
+        ("alice", dev, AuthorizationAction::execute, "main"),
+        ("", no_roles, AuthorizationAction::execute, "team/a"),
+        // "*" does not match clients without identity.
+        ("", no_roles, AuthorizationAction::read, "main"),
+    ] {
+        let err = authorizer
+            .check(identity, roles, action, instance_name)
+            .unwrap_err();
+        assert_eq!(err.code, Code::PermissionDenied);
+        assert!(err.message_string().contains("No policy allows"), "{err:?}");
+    }
+    Ok(())
+}
+
+#[test]
+fn invalid_policies_are_rejected() {
+    let policy = AuthorizationPolicy {
+        name: "bad".to_string(),
+        identities: vec!["*".to_string()],
+        instance_names: vec!["a*b".to_string()],
+        actions: vec![AuthorizationAction::read],
+        ..Default::default()
+    };
+    assert!(Authorizer::new(std::slice::from_ref(&policy)).is_err());
+    assert!(Authorizer::new(&[AuthorizationPolicy {
+        instance_names: Vec::new(),
+        actions: Vec::new(),
+        ..policy
+    }])
+    .is_err());
+}
+
+#[test]
+fn requests_without_authorizer_are_denied_once_required() -> Result<(), Error> {
+    authorize(AuthorizationAction::read, "main")?;
+
+    require_authorizer();
+    let err = authorize(AuthorizationAction::read, "main").unwrap_err();
+    assert_eq!(err.code, Code::PermissionDenied);
+    Ok(())
+}
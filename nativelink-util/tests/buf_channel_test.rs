@@ -12,13 +12,19 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::sync::atomic::Ordering;
 use std::task::Poll;
 
 use bytes::{Bytes, BytesMut};
 use futures::poll;
 use nativelink_error::{make_err, Code, Error, ResultExt};
 use nativelink_macro::nativelink_test;
-use nativelink_util::buf_channel::make_buf_channel_pair;
+use nativelink_util::buf_channel::{
+    make_buf_channel_pair, make_buf_channel_pair_with_watermarks, BufChannelWatermarks,
+    HashingWriteHalf,
+};
+use nativelink_util::common::DigestInfo;
+use nativelink_util::digest_hasher::{DigestHasherFunc, DigestHasherImpl};
 use pretty_assertions::assert_eq;
 use tokio::try_join;
 
@@ -340,3 +346,254 @@ async fn set_max_recent_data_size_no_eof_then_retry_test() -> Result<(), Error>
     }
     Ok(())
 }
+
+#[nativelink_test]
+async fn rewind_to_offset_mid_chunk_test() -> Result<(), Error> {
+    let (mut tx, mut rx) = make_buf_channel_pair();
+    rx.set_max_recent_data_size(1024);
+    tx.send(DATA1.into()).await?;
+    tx.send(DATA3.into()).await?;
+    tx.send_eof()?;
+    assert_eq!(rx.recv().await?, DATA1);
+    assert_eq!(rx.recv().await?, DATA3);
+    assert_eq!(rx.recv().await?, Bytes::new());
+    assert_eq!(rx.get_bytes_received(), (DATA1.len() + DATA3.len()) as u64);
+
+    // Rewind into the middle of the second chunk.
+    rx.try_rewind_to(5)?;
+    assert_eq!(rx.get_bytes_received(), 5);
+    assert_eq!(rx.consume(None).await?, Bytes::from(&DATA3[2..]));
+    assert_eq!(rx.get_bytes_received(), (DATA1.len() + DATA3.len()) as u64);
+
+    // A second rewind must still see all the data again.
+    rx.try_reset_stream()?;
+    assert_eq!(
+        rx.consume(None).await?,
+        Bytes::from(format!("{DATA1}{DATA3}"))
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn rewind_only_keeps_most_recent_data_test() -> Result<(), Error> {
+    let (mut tx, mut rx) = make_buf_channel_pair();
+    rx.set_max_recent_data_size(DATA3.len() as u64);
+    tx.send(DATA1.into()).await?;
+    tx.send(DATA3.into()).await?;
+    tx.send_eof()?;
+    assert_eq!(rx.recv().await?, DATA1);
+    assert_eq!(rx.recv().await?, DATA3);
+
+    // The first chunk fell out of the ring buffer.
+    assert!(rx.try_reset_stream().is_err());
+    assert!(rx.try_rewind_to(DATA1.len() as u64 - 1).is_err());
+    // A failed rewind must not change the stream.
+    assert_eq!(rx.get_bytes_received(), (DATA1.len() + DATA3.len()) as u64);
+
+    rx.try_rewind_to(DATA1.len() as u64)?;
+    assert_eq!(rx.consume(None).await?, DATA3);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn rewind_after_peek_does_not_duplicate_data_test() -> Result<(), Error> {
+    let (mut tx, mut rx) = make_buf_channel_pair();
+    rx.set_max_recent_data_size(1024);
+    tx.send(DATA1.into()).await?;
+    tx.send(DATA2.into()).await?;
+    tx.send_eof()?;
+    assert_eq!(rx.recv().await?, DATA1);
+    // Peeked data was not handed out, so it is not part of the rewind.
+    assert_eq!(rx.peek().await?, DATA2);
+    assert_eq!(rx.get_bytes_received(), DATA1.len() as u64);
+
+    rx.try_reset_stream()?;
+    assert_eq!(
+        rx.consume(None).await?,
+        Bytes::from(format!("{DATA1}{DATA2}"))
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn bytes_buffered_test() -> Result<(), Error> {
+    let (mut tx, mut rx) = make_buf_channel_pair();
+    tx.send(DATA1.into()).await?;
+    tx.send(DATA3.into()).await?;
+    assert_eq!(tx.bytes_buffered(), (DATA1.len() + DATA3.len()) as u64);
+    assert_eq!(rx.bytes_buffered(), (DATA1.len() + DATA3.len()) as u64);
+    assert_eq!(rx.recv().await?, DATA1);
+    assert_eq!(rx.bytes_buffered(), DATA3.len() as u64);
+    assert_eq!(rx.recv().await?, DATA3);
+    assert_eq!(tx.bytes_buffered(), 0);
+    assert_eq!(
+        rx.metrics().max_bytes_buffered.load(Ordering::Acquire),
+        (DATA1.len() + DATA3.len()) as u64
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn high_watermark_pauses_writer_until_low_watermark_test() -> Result<(), Error> {
+    let (mut tx, mut rx) = make_buf_channel_pair_with_watermarks(BufChannelWatermarks::new(
+        DATA3.len() as u64,
+        DATA1.len() as u64,
+    )?);
+    tx.send(DATA3.into()).await?;
+    {
+        let mut send_fut = Box::pin(tx.send(DATA2.into()));
+        // Buffer is at the high watermark, so the writer must wait.
+        assert!(poll!(&mut send_fut).is_pending());
+        assert_eq!(rx.recv().await?, DATA3);
+        send_fut.await?;
+    }
+    assert_eq!(rx.recv().await?, DATA2);
+    assert_eq!(rx.metrics().backpressure_waits.load(Ordering::Acquire), 1);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn high_watermark_is_not_limited_by_chunk_count_test() -> Result<(), Error> {
+    const CHUNKS: u64 = 5;
+    let (mut tx, mut rx) =
+        make_buf_channel_pair_with_watermarks(BufChannelWatermarks::new(CHUNKS, 0)?);
+    for _ in 0..CHUNKS {
+        tx.send(Bytes::from_static(b"a")).await?;
+    }
+    assert_eq!(tx.bytes_buffered(), CHUNKS);
+    {
+        let mut send_fut = Box::pin(tx.send(Bytes::from_static(b"b")));
+        // Only the high watermark pauses the writer.
+        assert!(poll!(&mut send_fut).is_pending());
+        for _ in 0..CHUNKS {
+            assert_eq!(rx.recv().await?, Bytes::from_static(b"a"));
+        }
+        send_fut.await?;
+    }
+    assert_eq!(rx.recv().await?, Bytes::from_static(b"b"));
+    Ok(())
+}
+
+#[nativelink_test]
+async fn paused_writer_errors_when_reader_dropped_test() -> Result<(), Error> {
+    let (mut tx, rx) =
+        make_buf_channel_pair_with_watermarks(BufChannelWatermarks::new(DATA1.len() as u64, 0)?);
+    tx.send(DATA1.into()).await?;
+    let mut send_fut = Box::pin(tx.send(DATA2.into()));
+    assert!(poll!(&mut send_fut).is_pending());
+    drop(rx);
+    assert_eq!(
+        send_fut.await,
+        Err(make_err!(
+            Code::Internal,
+            "Failed to write to data, receiver disconnected"
+        ))
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn invalid_watermarks_test() -> Result<(), Error> {
+    assert!(BufChannelWatermarks::new(0, 0).is_err());
+    assert!(BufChannelWatermarks::new(1, 2).is_err());
+    assert!(BufChannelWatermarks::new(2, 2).is_ok());
+    Ok(())
+}
+
+#[nativelink_test]
+async fn tee_sends_to_all_writers_test() -> Result<(), Error> {
+    let (mut tx, mut rx) = make_buf_channel_pair();
+    let (tee_tx1, mut tee_rx1) = make_buf_channel_pair();
+    let (tee_tx2, mut tee_rx2) = make_buf_channel_pair();
+    let mut writers = [tee_tx1, tee_tx2];
+    let tee_fut = rx.tee(&mut writers);
+    let send_fut = async move {
+        tx.send(DATA1.into()).await?;
+        tx.send(DATA2.into()).await?;
+        tx.send_eof()
+    };
+    let (tee_rx1_data, tee_rx2_data, (), ()) = try_join!(
+        tee_rx1.consume(None),
+        tee_rx2.consume(None),
+        tee_fut,
+        send_fut
+    )?;
+    assert_eq!(tee_rx1_data, Bytes::from(format!("{DATA1}{DATA2}")));
+    assert_eq!(tee_rx2_data, Bytes::from(format!("{DATA1}{DATA2}")));
+    Ok(())
+}
+
+#[nativelink_test]
+async fn tee_errors_if_any_writer_is_dropped_test() -> Result<(), Error> {
+    let (mut tx, mut rx) = make_buf_channel_pair();
+    let (tee_tx1, mut tee_rx1) = make_buf_channel_pair();
+    let (tee_tx2, tee_rx2) = make_buf_channel_pair();
+    drop(tee_rx2);
+    tx.send(DATA1.into()).await?;
+    tx.send_eof()?;
+    let mut writers = [tee_tx1, tee_tx2];
+    assert!(rx.tee(&mut writers).await.is_err());
+    drop(writers);
+    // The healthy writer still got the data, but never an EOF.
+    assert_eq!(tee_rx1.recv().await?, Bytes::from(DATA1));
+    assert_eq!(
+        tee_rx1.recv().await,
+        Err(make_err!(
+            Code::Internal,
+            "Sender dropped before sending EOF"
+        ))
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn tee_forwards_reader_error_test() -> Result<(), Error> {
+    let (mut tx, mut rx) = make_buf_channel_pair();
+    let (tee_tx, mut tee_rx) = make_buf_channel_pair();
+    tx.send(DATA1.into()).await?;
+    drop(tx);
+    let mut writers = [tee_tx];
+    assert!(rx.tee(&mut writers).await.is_err());
+    drop(writers);
+    // The last chunk is never forwarded because the EOF errored.
+    assert_eq!(
+        tee_rx.recv().await,
+        Err(make_err!(
+            Code::Internal,
+            "Sender dropped before sending EOF"
+        ))
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn hashing_write_half_computes_digest_test() -> Result<(), Error> {
+    const DATA1_DATA2_SHA256: &str =
+        "c3ab8ff13720e8ad9047dd39466b3c8974e592c2fa383d4a3960714caef0c4f2";
+    let (mut tx, mut rx) = make_buf_channel_pair();
+    let mut hashing_tx = HashingWriteHalf::new(&mut tx, Some(DigestHasherFunc::Sha256.hasher()));
+    hashing_tx.send(DATA1.into()).await?;
+    hashing_tx.send(DATA2.into()).await?;
+    assert_eq!(
+        hashing_tx.finalize_digest(),
+        Some(DigestInfo::try_new(DATA1_DATA2_SHA256, 6)?)
+    );
+    hashing_tx.send_eof()?;
+    assert_eq!(
+        rx.consume(None).await?,
+        Bytes::from(format!("{DATA1}{DATA2}"))
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn hashing_write_half_without_hasher_test() -> Result<(), Error> {
+    let (mut tx, mut rx) = make_buf_channel_pair();
+    let mut hashing_tx = HashingWriteHalf::<DigestHasherImpl>::new(&mut tx, None);
+    hashing_tx.send(DATA1.into()).await?;
+    assert_eq!(hashing_tx.get_bytes_written(), DATA1.len() as u64);
+    assert_eq!(hashing_tx.finalize_digest(), None);
+    hashing_tx.send_eof()?;
+    assert_eq!(rx.consume(None).await?, Bytes::from(DATA1));
+    Ok(())
+}
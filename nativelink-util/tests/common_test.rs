@@ -15,6 +15,9 @@
 use nativelink_error::{make_input_err, Error};
 use nativelink_macro::nativelink_test;
 use nativelink_util::common::DigestInfo;
+use nativelink_util::store_trait::{
+    StoreKey, StoreKeyEncoding, COMPACT_DIGEST_KEY_LEN, COMPACT_DIGEST_KEY_PREFIX,
+};
 use pretty_assertions::assert_eq;
 
 const MIN_DIGEST: &str = "0000000000000000000000000000000000000000000000000000000000000000-0";
@@ -130,3 +133,71 @@ async fn digest_info_deserialize_test() -> Result<(), Error> {
     }
     Ok(())
 }
+
+#[nativelink_test]
+async fn store_key_encoding_round_trip_test() -> Result<(), Error> {
+    for digest in [
+        DigestInfo::new([0u8; 32], u64::MIN),
+        DigestInfo::new([255u8; 32], i64::MAX as u64),
+        DigestInfo::try_new(MAX_SAFE_DIGEST.split_once('-').unwrap().0, 1234)?,
+    ] {
+        let key = StoreKey::Digest(digest);
+        let hex = key.encode(StoreKeyEncoding::Hex);
+        assert_eq!(hex, format!("{digest}"));
+        assert_eq!(
+            StoreKeyEncoding::decode_digest(&hex)?,
+            (digest, StoreKeyEncoding::Hex)
+        );
+
+        let compact = key.encode(StoreKeyEncoding::Compact);
+        assert_eq!(compact.len(), COMPACT_DIGEST_KEY_LEN);
+        assert!(compact.starts_with(COMPACT_DIGEST_KEY_PREFIX));
+        assert_eq!(
+            StoreKeyEncoding::decode_digest(&compact)?,
+            (digest, StoreKeyEncoding::Compact)
+        );
+        // Without its version tag, a compact key is not a digest key.
+        assert!(
+            StoreKeyEncoding::decode_digest(&compact[COMPACT_DIGEST_KEY_PREFIX.len()..]).is_err()
+        );
+    }
+    {
+        // String keys are never re-encoded.
+        let key = StoreKey::new_str("some-string-key");
+        assert_eq!(key.encode(StoreKeyEncoding::Compact), "some-string-key");
+    }
+    {
+        let key = StoreKey::Digest(DigestInfo::new([255u8; 32], u64::MAX));
+        let compact = key.encode(StoreKeyEncoding::Compact);
+        assert!(StoreKeyEncoding::decode_digest(&compact).is_err());
+    }
+    Ok(())
+}
+
+#[nativelink_test]
+async fn digest_info_sha1_and_sha512_round_trip_test() -> Result<(), Error> {
+    for hash in [
+        "aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d".to_string(),
+        "ab".repeat(64),
+    ] {
+        // 1 TiB, so the hex key of the sha1 digest is as long as the base64
+        // part of a compact key.
+        let digest = DigestInfo::try_new(&hash, 1 << 40)?;
+        assert_eq!(digest.packed_hash().len(), hash.len() / 2);
+        assert_eq!(digest.packed_hash().to_string(), hash);
+        assert_eq!(DigestInfo::try_new(&hash, 1 << 40)?, digest);
+
+        let key = StoreKey::Digest(digest);
+        // Only 32 byte hashes have a compact encoding.
+        let encoded = key.encode(StoreKeyEncoding::Compact);
+        assert_eq!(encoded, format!("{digest}"));
+        assert_eq!(
+            StoreKeyEncoding::decode_digest(&encoded)?,
+            (digest, StoreKeyEncoding::Hex)
+        );
+    }
+    assert!(DigestInfo::try_new(&"ab".repeat(24), 0).is_err());
+    // Only 32 bytes of a hash are stored inline.
+    assert!(size_of::<DigestInfo>() <= 48);
+    Ok(())
+}
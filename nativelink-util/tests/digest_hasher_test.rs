@@ -0,0 +1,123 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use nativelink_config::stores::ConfigDigestHashFunction;
+use nativelink_error::{Code, Error};
+use nativelink_macro::nativelink_test;
+use nativelink_util::common::DigestInfo;
+use nativelink_util::digest_hasher::{
+    allowed_digest_hasher_funcs, verify_digest_hasher_func_allowed, DigestHasher, DigestHasherFunc,
+    Sha256Backend, DEFAULT_ALLOWED_DIGEST_HASHER_FUNCS,
+};
+use nativelink_util::resource_info::ResourceInfo;
+use pretty_assertions::assert_eq;
+
+const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+#[nativelink_test]
+async fn sha256_backends_agree_test() -> Result<(), Error> {
+    for backend in [Sha256Backend::Sha2, Sha256Backend::Ring] {
+        let mut hasher = backend.hasher();
+        hasher.update(b"hel");
+        hasher.update(b"lo");
+        assert_eq!(
+            hasher.finalize_digest(),
+            DigestInfo::try_new(HELLO_SHA256, 5)?,
+            "Backend {backend:?} returned the wrong digest"
+        );
+    }
+    Ok(())
+}
+
+#[nativelink_test]
+async fn sha256_backend_can_be_reused_after_finalize_test() -> Result<(), Error> {
+    for backend in [Sha256Backend::Sha2, Sha256Backend::Ring] {
+        let mut hasher = backend.hasher();
+        hasher.update(b"foo");
+        let _ = hasher.finalize_digest();
+        hasher.update(b"hello");
+        assert_eq!(
+            hasher.finalize_digest().packed_hash().to_string(),
+            HELLO_SHA256,
+            "Backend {backend:?} did not reset after finalize"
+        );
+    }
+    Ok(())
+}
+
+#[nativelink_test]
+async fn resource_name_component_round_trips_test() -> Result<(), Error> {
+    const HASH: &str = "0123456789abcdef000000000000000000000000000000000123456789abcdef";
+    assert_eq!(DigestHasherFunc::Sha256.resource_name_component(), None);
+    let component = DigestHasherFunc::Blake3
+        .resource_name_component()
+        .expect("Blake3 must have a resource name component");
+    let resource_name = format!("main/blobs/{component}/{HASH}/3");
+    let resource_info = ResourceInfo::new(&resource_name, false)?;
+    assert_eq!(resource_info.digest_function.as_deref(), Some(component));
+    assert_eq!(
+        DigestHasherFunc::try_from(component)?,
+        DigestHasherFunc::Blake3
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn allowed_digest_hasher_funcs_test() -> Result<(), Error> {
+    let defaults = allowed_digest_hasher_funcs("main", &[])?;
+    assert_eq!(defaults, DEFAULT_ALLOWED_DIGEST_HASHER_FUNCS.to_vec());
+    verify_digest_hasher_func_allowed("main", &defaults, DigestHasherFunc::Blake3)?;
+
+    let sha256_only = allowed_digest_hasher_funcs("main", &[ConfigDigestHashFunction::sha256])?;
+    verify_digest_hasher_func_allowed("main", &sha256_only, DigestHasherFunc::Sha256)?;
+    let err = verify_digest_hasher_func_allowed("main", &sha256_only, DigestHasherFunc::Blake3)
+        .unwrap_err();
+    assert_eq!(err.code, Code::InvalidArgument);
+
+    // Clients that do not name a digest function use the default one.
+    let err = allowed_digest_hasher_funcs("main", &[ConfigDigestHashFunction::blake3]).unwrap_err();
+    assert_eq!(err.code, Code::InvalidArgument);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn sha1_and_sha512_hashers_test() -> Result<(), Error> {
+    const HELLO_SHA1: &str = "aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d";
+    const HELLO_SHA512: &str = concat!(
+        "9b71d224bd62f3785d96d46ad3ea3d73319bfbc2890caadae2dff72519673ca7",
+        "2323c3d99ba5c11d7c7acc6e14b8c5da0c4663475c2e5c3adef46f73bcdec043"
+    );
+    for (func, expected) in [
+        (DigestHasherFunc::Sha1, HELLO_SHA1),
+        (DigestHasherFunc::Sha512, HELLO_SHA512),
+    ] {
+        let mut hasher = func.hasher();
+        hasher.update(b"foo");
+        let _ = hasher.finalize_digest();
+        hasher.update(b"hel");
+        hasher.update(b"lo");
+        assert_eq!(
+            hasher.finalize_digest(),
+            DigestInfo::try_new(expected, 5)?,
+            "{func} returned the wrong digest"
+        );
+        assert_eq!(func.resource_name_component(), None);
+        assert_eq!(
+            DigestHasherFunc::for_omitted_resource_name_component(expected),
+            func
+        );
+        assert_eq!(DigestHasherFunc::try_from(func.to_string().as_str())?, func);
+    }
+    Ok(())
+}
@@ -0,0 +1,36 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use nativelink_error::{Code, Error};
+use nativelink_macro::nativelink_test;
+use nativelink_util::{log_filter, set_log_filter};
+use pretty_assertions::assert_eq;
+
+#[nativelink_test]
+async fn set_log_filter_updates_active_filter_test() -> Result<(), Error> {
+    set_log_filter("warn,nativelink_store::redis_store=debug")?;
+    let active_filter = log_filter()?;
+    assert!(
+        active_filter.contains("nativelink_store::redis_store=debug"),
+        "Expected redis_store directive in '{active_filter}'"
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn set_log_filter_rejects_invalid_directives_test() -> Result<(), Error> {
+    let err = set_log_filter("nativelink_store=not_a_level").unwrap_err();
+    assert_eq!(err.code, Code::InvalidArgument);
+    Ok(())
+}
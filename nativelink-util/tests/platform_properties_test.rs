@@ -0,0 +1,63 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeSet;
+
+use nativelink_macro::nativelink_test;
+use nativelink_util::platform_properties::PlatformPropertyValue;
+use pretty_assertions::assert_eq;
+
+fn set(values: &[&str]) -> PlatformPropertyValue {
+    PlatformPropertyValue::Set(
+        values
+            .iter()
+            .map(ToString::to_string)
+            .collect::<BTreeSet<_>>(),
+    )
+}
+
+fn wildcard(value: &str) -> PlatformPropertyValue {
+    PlatformPropertyValue::Wildcard(value.to_string())
+}
+
+#[nativelink_test]
+async fn set_is_satisfied_by_worker_with_all_values() -> Result<(), Box<dyn std::error::Error>> {
+    let mut worker_value = set(&["gpu-a100"]);
+    worker_value.merge(set(&["gpu-h100"]));
+    assert_eq!(worker_value, set(&["gpu-a100", "gpu-h100"]));
+
+    assert!(set(&["gpu-h100"]).is_satisfied_by(&worker_value));
+    assert!(set(&["gpu-a100", "gpu-h100"]).is_satisfied_by(&worker_value));
+    assert!(!set(&["gpu-t4"]).is_satisfied_by(&worker_value));
+    assert!(!set(&["gpu-a100", "gpu-t4"]).is_satisfied_by(&worker_value));
+    Ok(())
+}
+
+#[nativelink_test]
+async fn wildcard_is_satisfied_by_matching_worker() -> Result<(), Box<dyn std::error::Error>> {
+    let worker_value = wildcard("ubuntu-22.04-x86");
+
+    assert!(wildcard("ubuntu-22.04-x86").is_satisfied_by(&worker_value));
+    assert!(wildcard("*").is_satisfied_by(&worker_value));
+    assert!(wildcard("ubuntu-*").is_satisfied_by(&worker_value));
+    assert!(wildcard("*-x86").is_satisfied_by(&worker_value));
+    assert!(wildcard("ubuntu-*-x86").is_satisfied_by(&worker_value));
+    assert!(wildcard("u*2*x*").is_satisfied_by(&worker_value));
+
+    assert!(!wildcard("ubuntu").is_satisfied_by(&worker_value));
+    assert!(!wildcard("debian-*").is_satisfied_by(&worker_value));
+    assert!(!wildcard("*-arm").is_satisfied_by(&worker_value));
+    assert!(!wildcard("ubuntu-*-22.04-x86").is_satisfied_by(&worker_value));
+    Ok(())
+}
@@ -0,0 +1,106 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use nativelink_error::Error;
+use nativelink_macro::nativelink_test;
+use nativelink_util::common::DigestInfo;
+use nativelink_util::popularity_tracker::{
+    get_popularity_tracker, popularity_tracker, PopularityTracker,
+};
+use nativelink_util::store_trait::StoreKey;
+use pretty_assertions::assert_eq;
+
+const HASH1: &str = "0123456789abcdef000000000000000000000000000000000123456789abcdef";
+const HASH2: &str = "9993456789abcdef000000000000000000000000000000000123456789abc999";
+const HASH3: &str = "1113456789abcdef000000000000000000000000000000000123456789abc111";
+
+fn digest_key(hash: &str) -> Result<StoreKey<'static>, Error> {
+    Ok(DigestInfo::try_new(hash, 100)?.into())
+}
+
+#[nativelink_test]
+async fn record_read_counts_reads() -> Result<(), Error> {
+    let tracker = PopularityTracker::new(1024, u64::MAX, 10);
+    let key1 = digest_key(HASH1)?;
+    let key2 = digest_key(HASH2)?;
+
+    assert_eq!(tracker.record_read(&key1), 1);
+    assert_eq!(tracker.record_read(&key1), 2);
+    assert_eq!(tracker.record_read(&key2), 1);
+    assert_eq!(tracker.estimate(&key1), 2);
+    assert_eq!(tracker.estimate(&key2), 1);
+    assert_eq!(tracker.estimate(&digest_key(HASH3)?), 0);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn hottest_returns_most_read_keys_first() -> Result<(), Error> {
+    let tracker = PopularityTracker::new(1024, u64::MAX, 2);
+    let key1 = digest_key(HASH1)?;
+    let key2 = digest_key(HASH2)?;
+    let key3 = digest_key(HASH3)?;
+
+    tracker.record_read(&key1);
+    for _ in 0..3 {
+        tracker.record_read(&key2);
+    }
+    // Only two keys are remembered, so key3 has to push out key1.
+    for _ in 0..2 {
+        tracker.record_read(&key3);
+    }
+
+    assert_eq!(tracker.hottest(10), vec![(key2.clone(), 3), (key3, 2)]);
+    assert_eq!(tracker.hottest(1), vec![(key2, 3)]);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn counts_are_halved_after_decay_reads() -> Result<(), Error> {
+    let tracker = PopularityTracker::new(1024, 4, 10);
+    let key1 = digest_key(HASH1)?;
+    let key2 = digest_key(HASH2)?;
+
+    for _ in 0..3 {
+        tracker.record_read(&key1);
+    }
+    // The fourth read triggers the decay.
+    tracker.record_read(&key2);
+
+    assert_eq!(tracker.estimate(&key1), 1);
+    assert_eq!(tracker.estimate(&key2), 0);
+    assert_eq!(tracker.hottest(10), vec![(key1, 1)]);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn trackers_with_same_name_are_shared() -> Result<(), Error> {
+    let key1 = digest_key(HASH1)?;
+    let tracker = popularity_tracker("shared_tracker_test", || {
+        PopularityTracker::new(1024, u64::MAX, 10)
+    });
+    let same_tracker = popularity_tracker("shared_tracker_test", || {
+        panic!("Tracker should already exist")
+    });
+    assert!(Arc::ptr_eq(&tracker, &same_tracker));
+
+    tracker.record_read(&key1);
+    assert_eq!(
+        get_popularity_tracker("shared_tracker_test").map(|t| t.estimate(&key1)),
+        Some(1)
+    );
+    assert!(get_popularity_tracker("missing_tracker_test").is_none());
+    Ok(())
+}
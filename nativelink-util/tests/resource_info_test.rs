@@ -14,7 +14,10 @@
 
 use std::borrow::Cow;
 
+use nativelink_error::Code;
 use nativelink_macro::nativelink_test;
+use nativelink_util::common::DigestInfo;
+use nativelink_util::digest_hasher::DigestHasherFunc;
 use nativelink_util::resource_info::ResourceInfo;
 use pretty_assertions::assert_eq;
 
@@ -707,3 +710,148 @@ async fn write_invalid_size_test() -> Result<(), Box<dyn std::error::Error>> {
     assert!(ResourceInfo::new(RESOURCE_NAME, true).is_err());
     Ok(())
 }
+
+#[nativelink_test]
+async fn empty_hash_and_uuid_segments_invalid_test() -> Result<(), Box<dyn std::error::Error>> {
+    // Found by the `resource_info` fuzz target.
+    assert!(ResourceInfo::new("main/compressed-blobs/zstd//12", false).is_err());
+    assert!(ResourceInfo::new("main/blobs//12", false).is_err());
+    assert!(ResourceInfo::new("main/uploads//blobs/hash/12", true).is_err());
+    Ok(())
+}
+
+#[nativelink_test]
+async fn from_digest_round_trip_test() -> Result<(), Box<dyn std::error::Error>> {
+    const HASH: &str = "0123456789abcdef000000000000000000000000000000000123456789abcdef";
+    let digest = DigestInfo::try_new(HASH, 12345)?;
+    let resource_name = ResourceInfo::from_digest("instance_name", digest).to_string(false);
+    assert_eq!(resource_name, format!("instance_name/blobs/{HASH}/12345"));
+
+    let resource_info = ResourceInfo::new(&resource_name, false)?;
+    assert_eq!(resource_info.instance_name, "instance_name");
+    assert_eq!(resource_info.digest_function, None);
+    assert_eq!(resource_info.hash, HASH);
+    assert_eq!(resource_info.expected_size, 12345);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn from_digest_compressed_blobs_digest_function_optional_metadata_round_trip_test(
+) -> Result<(), Box<dyn std::error::Error>> {
+    const HASH: &str = "0123456789abcdef000000000000000000000000000000000123456789abcdef";
+    let digest = DigestInfo::try_new(HASH, 12345)?;
+    let resource_name = ResourceInfo::from_digest("instance/name", digest)
+        .with_compressor("zstd")
+        .with_digest_function(DigestHasherFunc::Blake3)
+        .with_optional_metadata("optional_metadata")
+        .to_string(false);
+    assert_eq!(
+        resource_name,
+        format!("instance/name/compressed-blobs/zstd/blake3/{HASH}/12345/optional_metadata")
+    );
+
+    let resource_info = ResourceInfo::new(&resource_name, false)?;
+    assert_eq!(resource_info.instance_name, "instance/name");
+    assert_eq!(resource_info.uuid, None);
+    assert_eq!(resource_info.compressor, Some(Cow::Borrowed("zstd")));
+    assert_eq!(resource_info.digest_function, Some(Cow::Borrowed("blake3")));
+    assert_eq!(resource_info.hash, HASH);
+    assert_eq!(resource_info.expected_size, 12345);
+    assert_eq!(
+        resource_info.optional_metadata,
+        Some(Cow::Borrowed("optional_metadata"))
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn from_digest_uploads_round_trip_test() -> Result<(), Box<dyn std::error::Error>> {
+    const HASH: &str = "0123456789abcdef000000000000000000000000000000000123456789abcdef";
+    let digest = DigestInfo::try_new(HASH, 12345)?;
+    let resource_info = ResourceInfo::from_digest("", digest)
+        .with_uuid("uuid")
+        .with_digest_function(DigestHasherFunc::Sha256);
+    let resource_name = resource_info.to_string(true);
+    assert_eq!(resource_name, format!("uploads/uuid/blobs/{HASH}/12345"));
+    // The uuid is only part of upload resource names.
+    assert_eq!(
+        resource_info.to_string(false),
+        format!("blobs/{HASH}/12345")
+    );
+
+    let resource_info = ResourceInfo::new(&resource_name, true)?;
+    assert_eq!(resource_info.instance_name, "");
+    assert_eq!(resource_info.uuid, Some(Cow::Borrowed("uuid")));
+    assert_eq!(resource_info.digest_function, None);
+    assert_eq!(resource_info.hash, HASH);
+    assert_eq!(resource_info.expected_size, 12345);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn validate_accepts_valid_resource_names_test() -> Result<(), Box<dyn std::error::Error>> {
+    const HASH: &str = "0123456789abcdef000000000000000000000000000000000123456789abcdef";
+    ResourceInfo::new(&format!("instance_name/blobs/{HASH}/12345"), false)?.validate()?;
+    ResourceInfo::new(
+        &format!("uploads/d3bd47c5-8fe0-4c4c-9bd1-0a5d1a0b9e49/compressed-blobs/zstd/blake3/{HASH}/12345"),
+        true,
+    )?
+    .validate()?;
+    Ok(())
+}
+
+#[nativelink_test]
+async fn validate_hash_length_mismatch_test() -> Result<(), Box<dyn std::error::Error>> {
+    const SHA1_HASH: &str = "0123456789abcdef0123456789abcdef01234567";
+    // A sha1 hash is valid without a digest function, but not with blake3.
+    ResourceInfo::new(&format!("blobs/{SHA1_HASH}/12345"), false)?.validate()?;
+    let err = ResourceInfo::new(&format!("blobs/blake3/{SHA1_HASH}/12345"), false)?
+        .validate()
+        .unwrap_err();
+    assert_eq!(err.code, Code::InvalidArgument);
+    assert!(
+        err.message_string().contains("'hash' segment"),
+        "Expected error to name the hash segment, got: {err:?}"
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn validate_hash_not_hex_test() -> Result<(), Box<dyn std::error::Error>> {
+    const HASH: &str = "0123456789abcdef000000000000000000000000000000000123456789abcdeX";
+    let err = ResourceInfo::new(&format!("blobs/{HASH}/12345"), false)?
+        .validate()
+        .unwrap_err();
+    assert_eq!(err.code, Code::InvalidArgument);
+    assert!(
+        err.message_string().contains("'hash' segment"),
+        "Expected error to name the hash segment, got: {err:?}"
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn validate_invalid_uuid_test() -> Result<(), Box<dyn std::error::Error>> {
+    const HASH: &str = "0123456789abcdef000000000000000000000000000000000123456789abcdef";
+    let err = ResourceInfo::new(&format!("uploads/uuid/blobs/{HASH}/12345"), true)?
+        .validate()
+        .unwrap_err();
+    assert_eq!(err.code, Code::InvalidArgument);
+    assert!(
+        err.message_string().contains("'uuid' segment"),
+        "Expected error to name the uuid segment, got: {err:?}"
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn negative_size_names_size_segment_test() -> Result<(), Box<dyn std::error::Error>> {
+    const HASH: &str = "0123456789abcdef000000000000000000000000000000000123456789abcdef";
+    let err = ResourceInfo::new(&format!("blobs/{HASH}/-1"), false).unwrap_err();
+    assert_eq!(err.code, Code::InvalidArgument);
+    assert!(
+        err.message_string().contains("'size' segment"),
+        "Expected error to name the size segment, got: {err:?}"
+    );
+    Ok(())
+}
@@ -0,0 +1,103 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::atomic::Ordering;
+
+use nativelink_error::{Code, Error};
+use nativelink_macro::nativelink_test;
+use nativelink_util::spawn_blocking;
+use nativelink_util::task::{blocking_pools, BlockingPoolKind, MAX_BLOCKING_POOL_THREADS};
+use pretty_assertions::assert_eq;
+
+#[nativelink_test]
+async fn spawn_blocking_on_pool_runs_on_pool_thread_test() -> Result<(), Error> {
+    let thread_name = spawn_blocking!(pool: BlockingPoolKind::Cpu, "test_cpu_pool", || {
+        std::thread::current().name().map(ToString::to_string)
+    })
+    .await
+    .unwrap();
+    assert!(thread_name.unwrap().starts_with("nl-cpu-"));
+
+    let thread_name = spawn_blocking!(pool: BlockingPoolKind::Filesystem, "test_fs_pool", || {
+        std::thread::current().name().map(ToString::to_string)
+    })
+    .await
+    .unwrap();
+    assert!(thread_name.unwrap().starts_with("nl-filesystem-"));
+    Ok(())
+}
+
+#[nativelink_test]
+async fn spawn_blocking_on_pool_updates_metrics_test() -> Result<(), Error> {
+    let pools = blocking_pools();
+    let metrics = pools.get(BlockingPoolKind::Cpu).metrics();
+    let completed_before = metrics.completed_tasks.load(Ordering::Acquire);
+    let value = spawn_blocking!(pool: BlockingPoolKind::Cpu, "test_cpu_pool", || 42)
+        .await
+        .unwrap();
+    assert_eq!(value, 42);
+    assert!(metrics.completed_tasks.load(Ordering::Acquire) > completed_before);
+    assert!(metrics.threads.load(Ordering::Acquire) > 0);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn spawn_blocking_on_pool_propagates_panic_test() -> Result<(), Error> {
+    let result = spawn_blocking!(pool: BlockingPoolKind::Cpu, "test_cpu_pool_panic", || {
+        panic!("expected panic");
+    })
+    .await;
+    let err = result.unwrap_err();
+    assert_eq!(err.code, Code::Internal);
+    assert!(err.to_string().contains("expected panic"), "{err:?}");
+
+    // The pool must still be usable after a task panicked.
+    let value = spawn_blocking!(pool: BlockingPoolKind::Cpu, "test_cpu_pool", || 7)
+        .await
+        .unwrap();
+    assert_eq!(value, 7);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn spawn_blocking_on_pool_runs_inside_runtime_test() -> Result<(), Error> {
+    let has_runtime = spawn_blocking!(pool: BlockingPoolKind::Filesystem, "test_fs_pool", || {
+        tokio::runtime::Handle::try_current().is_ok()
+    })
+    .await
+    .unwrap();
+    assert!(has_runtime);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn spawn_blocking_on_pool_starts_threads_on_demand_test() -> Result<(), Error> {
+    let pools = blocking_pools();
+    let metrics = pools.get(BlockingPoolKind::Filesystem).metrics();
+    for _ in 0..8 {
+        spawn_blocking!(pool: BlockingPoolKind::Filesystem, "test_fs_pool", || {})
+            .await
+            .unwrap();
+    }
+    // Jobs that run one after another reuse the idle thread instead of
+    // starting the whole pool.
+    let threads = metrics.threads.load(Ordering::Acquire);
+    assert!(threads > 0);
+    assert!(
+        threads < 8,
+        "Expected idle threads to be reused, got {threads}"
+    );
+    assert!(threads <= MAX_BLOCKING_POOL_THREADS as u64);
+    Ok(())
+}
@@ -0,0 +1,36 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use nativelink_util::tls_utils::certificate_common_name;
+use pretty_assertions::assert_eq;
+
+/// Self signed certificate with the subject `/O=NativeLink/CN=ci-worker`.
+const CLIENT_CERT: &[u8] = include_bytes!("data/client_cert.der");
+
+#[test]
+fn certificate_common_name_test() {
+    assert_eq!(
+        certificate_common_name(CLIENT_CERT),
+        Some("ci-worker".to_string())
+    );
+}
+
+#[test]
+fn certificate_common_name_rejects_truncated_certificate() {
+    assert_eq!(
+        certificate_common_name(&CLIENT_CERT[..CLIENT_CERT.len() / 2]),
+        None
+    );
+    assert_eq!(certificate_common_name(&[]), None);
+}
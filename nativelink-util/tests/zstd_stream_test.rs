@@ -0,0 +1,147 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use bytes::Bytes;
+use nativelink_error::{Code, Error};
+use nativelink_macro::nativelink_test;
+use nativelink_util::buf_channel::make_buf_channel_pair;
+use nativelink_util::common::DigestInfo;
+use nativelink_util::digest_hasher::{DigestHasher, DigestHasherFunc};
+use nativelink_util::zstd_stream::{compress, decompress_and_verify, DEFAULT_COMPRESSION_LEVEL};
+use pretty_assertions::assert_eq;
+use tokio::try_join;
+
+fn make_data() -> Bytes {
+    // Large enough to need multiple output buffers, but repetitive enough
+    // to compress well.
+    (0..200_000u32)
+        .flat_map(|i| (i % 97).to_le_bytes())
+        .collect::<Vec<u8>>()
+        .into()
+}
+
+fn sha256_digest(data: &[u8]) -> DigestInfo {
+    let mut hasher = DigestHasherFunc::Sha256.hasher();
+    hasher.update(data);
+    hasher.finalize_digest()
+}
+
+async fn compress_data(data: Bytes) -> Result<Bytes, Error> {
+    let (mut tx, mut rx) = make_buf_channel_pair();
+    let (mut compressed_tx, mut compressed_rx) = make_buf_channel_pair();
+    let ((), (), compressed_data) = try_join!(
+        async {
+            // Split the data to ensure chunk boundaries are handled.
+            let (first, second) = data.split_at(data.len() / 3);
+            tx.send(Bytes::copy_from_slice(first)).await?;
+            tx.send(Bytes::copy_from_slice(second)).await?;
+            tx.send_eof()
+        },
+        compress(&mut rx, &mut compressed_tx, DEFAULT_COMPRESSION_LEVEL),
+        compressed_rx.consume(None),
+    )?;
+    Ok(compressed_data)
+}
+
+#[nativelink_test]
+async fn round_trip_test() -> Result<(), Error> {
+    let data = make_data();
+    let digest = sha256_digest(&data);
+    let compressed_data = compress_data(data.clone()).await?;
+    assert!(
+        compressed_data.len() < data.len(),
+        "Expected data to be compressed"
+    );
+
+    let (mut tx, mut rx) = make_buf_channel_pair();
+    let (mut decompressed_tx, mut decompressed_rx) = make_buf_channel_pair();
+    let ((), (), decompressed_data) = try_join!(
+        async {
+            // Send the compressed data one byte at a time to ensure partial
+            // frames are handled.
+            for i in 0..compressed_data.len() {
+                tx.send(compressed_data.slice(i..=i)).await?;
+            }
+            tx.send_eof()
+        },
+        decompress_and_verify(
+            &mut rx,
+            &mut decompressed_tx,
+            DigestHasherFunc::Sha256,
+            digest
+        ),
+        decompressed_rx.consume(None),
+    )?;
+    assert_eq!(decompressed_data, data);
+    Ok(())
+}
+
+/// Decompresses `compressed_data` and returns the result of the
+/// decompression and of reading the decompressed data.
+async fn decompress_data(
+    compressed_data: Bytes,
+    expected_digest: DigestInfo,
+) -> (Result<(), Error>, Result<Bytes, Error>) {
+    let (mut tx, mut rx) = make_buf_channel_pair();
+    let (mut decompressed_tx, mut decompressed_rx) = make_buf_channel_pair();
+    tx.send(compressed_data).await.unwrap();
+    tx.send_eof().unwrap();
+    tokio::join!(
+        // Move the writer into the future, so it is dropped once the
+        // decompression finished.
+        async move {
+            decompress_and_verify(
+                &mut rx,
+                &mut decompressed_tx,
+                DigestHasherFunc::Sha256,
+                expected_digest,
+            )
+            .await
+        },
+        decompressed_rx.consume(None),
+    )
+}
+
+#[nativelink_test]
+async fn decompress_digest_mismatch_test() -> Result<(), Error> {
+    let data = make_data();
+    let mut wrong_data = data.to_vec();
+    wrong_data[0] ^= 1;
+    let compressed_data = compress_data(data).await?;
+
+    let (decompress_result, read_result) =
+        decompress_data(compressed_data, sha256_digest(&wrong_data)).await;
+    assert_eq!(decompress_result.unwrap_err().code, Code::InvalidArgument);
+    assert!(
+        read_result.is_err(),
+        "Expected the receiver to not get an EOF"
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn decompress_truncated_data_test() -> Result<(), Error> {
+    let data = make_data();
+    let digest = sha256_digest(&data);
+    let compressed_data = compress_data(data).await?;
+
+    let (decompress_result, read_result) =
+        decompress_data(compressed_data.slice(..compressed_data.len() - 1), digest).await;
+    assert_eq!(decompress_result.unwrap_err().code, Code::InvalidArgument);
+    assert!(
+        read_result.is_err(),
+        "Expected the receiver to not get an EOF"
+    );
+    Ok(())
+}
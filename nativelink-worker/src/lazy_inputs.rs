@@ -0,0 +1,855 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Serves the input root of an action through FUSE, so the files of the
+//! input root are only fetched from the CAS once the action opens them.
+//!
+//! The FUSE filesystem is read only. An overlay filesystem is mounted on top
+//! of it at the work directory of the action, which keeps everything the
+//! action writes in a separate directory. Once the action is done, that
+//! directory replaces the work directory, so the outputs live on the same
+//! filesystem as before and can be moved into the `FilesystemStore`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{CStr, CString};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use nativelink_error::{make_err, Code, Error, ResultExt};
+use nativelink_proto::build::bazel::remote::execution::v2::Directory as ProtoDirectory;
+use nativelink_store::ac_utils::get_and_decode_digest;
+use nativelink_store::fast_slow_store::FastSlowStore;
+use nativelink_store::filesystem_store::{FileEntry, FilesystemStore};
+use nativelink_util::common::{fs, DigestInfo};
+use nativelink_util::origin_context::{ActiveOriginContext, OriginContext};
+use nativelink_util::task::{BlockingPoolKind, JoinHandleDropGuard};
+use nativelink_util::{background_spawn, spawn_blocking};
+use parking_lot::Mutex;
+use tracing::{error_span, event, Level};
+
+use crate::running_actions_manager::get_file_entry_for_staging;
+
+const FUSE_KERNEL_VERSION: u32 = 7;
+const FUSE_KERNEL_MINOR_VERSION: u32 = 31;
+const FUSE_ROOT_ID: u64 = 1;
+
+const FUSE_LOOKUP: u32 = 1;
+const FUSE_FORGET: u32 = 2;
+const FUSE_GETATTR: u32 = 3;
+const FUSE_READLINK: u32 = 5;
+const FUSE_OPEN: u32 = 14;
+const FUSE_READ: u32 = 15;
+const FUSE_STATFS: u32 = 17;
+const FUSE_RELEASE: u32 = 18;
+const FUSE_INIT: u32 = 26;
+const FUSE_OPENDIR: u32 = 27;
+const FUSE_READDIR: u32 = 28;
+const FUSE_RELEASEDIR: u32 = 29;
+const FUSE_INTERRUPT: u32 = 36;
+const FUSE_BATCH_FORGET: u32 = 42;
+
+const FUSE_ASYNC_READ: u32 = 1 << 0;
+const FUSE_PARALLEL_DIROPS: u32 = 1 << 18;
+const FOPEN_KEEP_CACHE: u32 = 1 << 1;
+const FOPEN_CACHE_DIR: u32 = 1 << 3;
+
+const IN_HEADER_SIZE: usize = 40;
+const GETATTR_IN_SIZE: usize = 16;
+const OPEN_IN_SIZE: usize = 8;
+const READ_IN_SIZE: usize = 40;
+const RELEASE_IN_SIZE: usize = 24;
+const INIT_IN_SIZE: usize = 16;
+const OUT_HEADER_SIZE: usize = 16;
+const DIRENT_HEADER_SIZE: usize = 24;
+
+/// Largest write the kernel may send. Nothing is ever written to the
+/// filesystem, but the request buffer must still be able to hold one.
+const MAX_WRITE: u32 = 128 * 1024;
+
+/// How long the kernel may cache names and attributes. Inputs never change
+/// while they are mounted.
+const ENTRY_VALID: Duration = Duration::from_secs(3600);
+
+/// Mounts the input root of an action lazily at its work directory.
+pub struct LazyInputs {
+    fuse_mount_path: String,
+    upper_directory: String,
+    work_directory: String,
+    fuse_mounted: bool,
+    overlay_mounted: bool,
+    _serve_task: Option<JoinHandleDropGuard<()>>,
+}
+
+impl LazyInputs {
+    /// Mounts the input root `input_root_digest` at `work_directory`. The
+    /// mounts and the files the action writes are kept in
+    /// `action_directory`.
+    pub async fn mount(
+        cas_store: Arc<FastSlowStore>,
+        filesystem_store: Arc<FilesystemStore>,
+        input_root_digest: DigestInfo,
+        action_directory: &str,
+        work_directory: &str,
+    ) -> Result<Self, Error> {
+        let fuse_mount_path = format!("{action_directory}/lazy_inputs");
+        let upper_directory = format!("{action_directory}/upper");
+        let overlay_work_directory = format!("{action_directory}/overlay_work");
+        for path in [
+            &fuse_mount_path,
+            &upper_directory,
+            &overlay_work_directory,
+            &work_directory.to_string(),
+        ] {
+            fs::create_dir(path)
+                .await
+                .err_tip(|| format!("Could not create directory {path} for lazy inputs"))?;
+        }
+        let device = Arc::new(
+            File::options()
+                .read(true)
+                .write(true)
+                .open("/dev/fuse")
+                .err_tip(|| "Could not open /dev/fuse for lazy inputs")?,
+        );
+        // SAFETY: getuid and getgid always succeed.
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        let mut lazy_inputs = Self {
+            fuse_mount_path: fuse_mount_path.clone(),
+            upper_directory: upper_directory.clone(),
+            work_directory: work_directory.to_string(),
+            fuse_mounted: false,
+            overlay_mounted: false,
+            _serve_task: None,
+        };
+
+        // Mounts wait for requests that read files on the filesystem pool,
+        // so they must not block a thread of that pool themselves.
+        let fuse_options = format!(
+            "fd={},rootmode=40000,user_id={uid},group_id={gid},allow_other,default_permissions",
+            device.as_raw_fd()
+        );
+        let path = fuse_mount_path.clone();
+        spawn_blocking!("lazy_inputs_mount_fuse", move || {
+            mount(
+                "nativelink",
+                &path,
+                "fuse.nativelink",
+                libc::MS_RDONLY | libc::MS_NOSUID | libc::MS_NODEV,
+                &fuse_options,
+            )
+        })
+        .await
+        .err_tip(|| "Failed to launch spawn_blocking in LazyInputs::mount")??;
+        lazy_inputs.fuse_mounted = true;
+
+        let filesystem = Arc::new(LazyInputsFs {
+            cas_store,
+            filesystem_store,
+            nodes: Mutex::new(vec![Node {
+                parent: FUSE_ROOT_ID,
+                kind: NodeKind::Directory {
+                    digest: input_root_digest,
+                    children: None,
+                },
+            }]),
+            open_files: Mutex::new(HashMap::new()),
+            next_file_handle: AtomicU64::new(1),
+            mount_time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default(),
+            uid,
+            gid,
+        });
+        // Requests are served from a blocking thread, which does not have
+        // the context of the action.
+        let origin_context = ActiveOriginContext::get();
+        lazy_inputs._serve_task = Some(spawn_blocking!("lazy_inputs_serve", move || {
+            serve(&filesystem, &device, &origin_context);
+        }));
+
+        // Directories renamed in the overlay would otherwise point into the
+        // input root, which is gone once the upper directory is taken out.
+        let overlay_options = format!(
+            "lowerdir={fuse_mount_path},upperdir={upper_directory},workdir={overlay_work_directory},redirect_dir=off,metacopy=off"
+        );
+        let path = work_directory.to_string();
+        spawn_blocking!("lazy_inputs_mount_overlay", move || {
+            mount("overlay", &path, "overlay", 0, &overlay_options)
+        })
+        .await
+        .err_tip(|| "Failed to launch spawn_blocking in LazyInputs::mount")??;
+        lazy_inputs.overlay_mounted = true;
+        Ok(lazy_inputs)
+    }
+
+    /// Unmounts the input root and replaces the work directory with the
+    /// files the action wrote. `output_paths` are the full paths of the
+    /// outputs. Outputs the action did not write, but took from the input
+    /// root, are kept too.
+    pub async fn finish(mut self, output_paths: Vec<String>) -> Result<(), Error> {
+        let work_directory = self.work_directory.clone();
+        let fuse_mount_path = self.fuse_mount_path.clone();
+        let upper_directory = self.upper_directory.clone();
+        spawn_blocking!("lazy_inputs_finish", move || {
+            for output_path in &output_paths {
+                copy_up(Path::new(output_path))
+                    .err_tip(|| format!("Could not keep output {output_path} of lazy inputs"))?;
+            }
+            unmount(&work_directory)?;
+            unmount(&fuse_mount_path)?;
+            std::fs::remove_dir(&work_directory)
+                .err_tip(|| format!("Could not remove {work_directory}"))?;
+            std::fs::rename(&upper_directory, &work_directory)
+                .err_tip(|| format!("Could not move {upper_directory} to {work_directory}"))?;
+            Result::<(), Error>::Ok(())
+        })
+        .await
+        .err_tip(|| "Failed to launch spawn_blocking in LazyInputs::finish")??;
+        self.overlay_mounted = false;
+        self.fuse_mounted = false;
+        Ok(())
+    }
+}
+
+impl Drop for LazyInputs {
+    fn drop(&mut self) {
+        // Detaching does not wait for the filesystem, so it can not hang even
+        // if the filesystem no longer answers.
+        for (path, mounted) in [
+            (&self.work_directory, self.overlay_mounted),
+            (&self.fuse_mount_path, self.fuse_mounted),
+        ] {
+            if !mounted {
+                continue;
+            }
+            if let Err(err) = unmount(path) {
+                event!(Level::ERROR, ?path, ?err, "Could not unmount lazy inputs");
+            }
+        }
+    }
+}
+
+fn to_c_string(value: &str) -> Result<CString, Error> {
+    CString::new(value).map_err(|e| make_err!(Code::InvalidArgument, "{e:?} : {value}"))
+}
+
+fn mount(
+    source: &str,
+    target: &str,
+    filesystem_type: &str,
+    flags: libc::c_ulong,
+    options: &str,
+) -> Result<(), Error> {
+    let source_c = to_c_string(source)?;
+    let target_c = to_c_string(target)?;
+    let filesystem_type_c = to_c_string(filesystem_type)?;
+    let options_c = to_c_string(options)?;
+    // SAFETY: All the pointers are valid C strings that outlive the call.
+    let result = unsafe {
+        libc::mount(
+            source_c.as_ptr(),
+            target_c.as_ptr(),
+            filesystem_type_c.as_ptr(),
+            flags,
+            options_c.as_ptr().cast(),
+        )
+    };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error())
+            .err_tip(|| format!("Could not mount {filesystem_type} at {target}"));
+    }
+    Ok(())
+}
+
+fn unmount(target: &str) -> Result<(), Error> {
+    let target_c = to_c_string(target)?;
+    // SAFETY: `target_c` is a valid C string that outlives the call.
+    let result = unsafe { libc::umount2(target_c.as_ptr(), libc::MNT_DETACH) };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error())
+            .err_tip(|| format!("Could not unmount {target}"));
+    }
+    Ok(())
+}
+
+/// Makes the overlay copy `path` and everything below it from the input
+/// root into the upper directory, if it is not there yet. Changing the
+/// owner of an entry always copies it up, even if the owner stays the same.
+fn copy_up(path: &Path) -> std::io::Result<()> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        // The action did not create the output.
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    std::os::unix::fs::lchown(path, Some(metadata.uid()), Some(metadata.gid()))?;
+    if metadata.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            copy_up(&entry?.path())?;
+        }
+    }
+    Ok(())
+}
+
+enum NodeKind {
+    Directory {
+        digest: DigestInfo,
+        children: Option<BTreeMap<String, u64>>,
+    },
+    File {
+        digest: DigestInfo,
+        mode: u32,
+        mtime: Option<Duration>,
+    },
+    Symlink {
+        target: String,
+    },
+}
+
+struct Node {
+    parent: u64,
+    kind: NodeKind,
+}
+
+impl Node {
+    fn dirent_type(&self) -> u32 {
+        match self.kind {
+            NodeKind::Directory { .. } => u32::from(libc::DT_DIR),
+            NodeKind::File { .. } => u32::from(libc::DT_REG),
+            NodeKind::Symlink { .. } => u32::from(libc::DT_LNK),
+        }
+    }
+}
+
+/// Builds the nodes of the entries of `directory`.
+fn nodes_of_directory(
+    directory: ProtoDirectory,
+    parent: u64,
+) -> Result<Vec<(String, Node)>, Error> {
+    let mut nodes = Vec::with_capacity(
+        directory.files.len() + directory.directories.len() + directory.symlinks.len(),
+    );
+    for file in directory.files {
+        let digest: DigestInfo = file
+            .digest
+            .err_tip(|| "Expected Digest to exist in Directory::file::digest")?
+            .try_into()
+            .err_tip(|| "In Directory::file::digest")?;
+        let (mtime, unix_mode) = match file.node_properties {
+            Some(properties) => (properties.mtime, properties.unix_mode),
+            None => (None, None),
+        };
+        let mut mode = unix_mode.unwrap_or(0o444) & 0o7777;
+        if file.is_executable {
+            mode |= 0o111;
+        }
+        let mtime = mtime.map(|mtime| {
+            Duration::new(
+                u64::try_from(mtime.seconds).unwrap_or(0),
+                u32::try_from(mtime.nanos).unwrap_or(0),
+            )
+        });
+        nodes.push((
+            file.name,
+            Node {
+                parent,
+                kind: NodeKind::File {
+                    digest,
+                    mode,
+                    mtime,
+                },
+            },
+        ));
+    }
+    for directory in directory.directories {
+        let digest: DigestInfo = directory
+            .digest
+            .err_tip(|| "Expected Digest to exist in Directory::directories::digest")?
+            .try_into()
+            .err_tip(|| "In Directory::directories::digest")?;
+        nodes.push((
+            directory.name,
+            Node {
+                parent,
+                kind: NodeKind::Directory {
+                    digest,
+                    children: None,
+                },
+            },
+        ));
+    }
+    for symlink in directory.symlinks {
+        nodes.push((
+            symlink.name,
+            Node {
+                parent,
+                kind: NodeKind::Symlink {
+                    target: symlink.target,
+                },
+            },
+        ));
+    }
+    Ok(nodes)
+}
+
+/// The FUSE filesystem of the input root. Nodes are never forgotten, the
+/// inode of a node is its index in `nodes` plus one.
+struct LazyInputsFs {
+    cas_store: Arc<FastSlowStore>,
+    filesystem_store: Arc<FilesystemStore>,
+    nodes: Mutex<Vec<Node>>,
+    open_files: Mutex<HashMap<u64, Arc<File>>>,
+    next_file_handle: AtomicU64,
+    mount_time: Duration,
+    uid: u32,
+    gid: u32,
+}
+
+fn node_index(inode: u64) -> Result<usize, i32> {
+    inode
+        .checked_sub(1)
+        .and_then(|index| usize::try_from(index).ok())
+        .ok_or(libc::ENOENT)
+}
+
+/// Returns the size of the fixed part of the body of `opcode`. Shorter
+/// requests are rejected before their body is parsed.
+const fn min_body_size(opcode: u32) -> usize {
+    match opcode {
+        // The name, terminated by a NUL.
+        FUSE_LOOKUP => 1,
+        FUSE_GETATTR => GETATTR_IN_SIZE,
+        FUSE_OPEN | FUSE_OPENDIR => OPEN_IN_SIZE,
+        FUSE_READ | FUSE_READDIR => READ_IN_SIZE,
+        FUSE_RELEASE | FUSE_RELEASEDIR => RELEASE_IN_SIZE,
+        FUSE_INIT => INIT_IN_SIZE,
+        _ => 0,
+    }
+}
+
+fn internal_error(err: &Error) -> i32 {
+    event!(Level::ERROR, ?err, "Error serving lazy inputs");
+    libc::EIO
+}
+
+impl LazyInputsFs {
+    /// Serves a request. Returns the payload of the reply, or the error
+    /// number to reply with.
+    async fn handle(&self, opcode: u32, inode: u64, body: &[u8]) -> Result<Vec<u8>, i32> {
+        match opcode {
+            FUSE_LOOKUP => {
+                let name = CStr::from_bytes_until_nul(body)
+                    .map_err(|_| libc::EINVAL)?
+                    .to_str()
+                    .map_err(|_| libc::ENOENT)?;
+                self.load_directory(inode).await?;
+                let nodes = self.nodes.lock();
+                let NodeKind::Directory {
+                    children: Some(children),
+                    ..
+                } = &nodes.get(node_index(inode)?).ok_or(libc::ENOENT)?.kind
+                else {
+                    return Err(libc::ENOTDIR);
+                };
+                let child = *children.get(name).ok_or(libc::ENOENT)?;
+                let mut reply = Vec::with_capacity(128);
+                put_u64(&mut reply, child);
+                put_u64(&mut reply, 0);
+                put_u64(&mut reply, ENTRY_VALID.as_secs());
+                put_u64(&mut reply, ENTRY_VALID.as_secs());
+                put_u32(&mut reply, 0);
+                put_u32(&mut reply, 0);
+                let child_node = nodes.get(node_index(child)?).ok_or(libc::ENOENT)?;
+                self.put_attr(&mut reply, child, child_node);
+                Ok(reply)
+            }
+            FUSE_GETATTR => {
+                let nodes = self.nodes.lock();
+                let node = nodes.get(node_index(inode)?).ok_or(libc::ENOENT)?;
+                let mut reply = Vec::with_capacity(104);
+                put_u64(&mut reply, ENTRY_VALID.as_secs());
+                put_u32(&mut reply, 0);
+                put_u32(&mut reply, 0);
+                self.put_attr(&mut reply, inode, node);
+                Ok(reply)
+            }
+            FUSE_READLINK => {
+                let nodes = self.nodes.lock();
+                match &nodes.get(node_index(inode)?).ok_or(libc::ENOENT)?.kind {
+                    NodeKind::Symlink { target } => Ok(target.as_bytes().to_vec()),
+                    _ => Err(libc::EINVAL),
+                }
+            }
+            FUSE_OPEN => {
+                let digest = match self
+                    .nodes
+                    .lock()
+                    .get(node_index(inode)?)
+                    .map(|node| &node.kind)
+                {
+                    Some(NodeKind::File { digest, .. }) => *digest,
+                    Some(_) => return Err(libc::EISDIR),
+                    None => return Err(libc::ENOENT),
+                };
+                let file = self
+                    .open_file(&digest)
+                    .await
+                    .map_err(|err| internal_error(&err))?;
+                let file_handle = self.next_file_handle.fetch_add(1, Ordering::Relaxed);
+                self.open_files.lock().insert(file_handle, Arc::new(file));
+                let mut reply = Vec::with_capacity(16);
+                put_u64(&mut reply, file_handle);
+                put_u32(&mut reply, FOPEN_KEEP_CACHE);
+                put_u32(&mut reply, 0);
+                Ok(reply)
+            }
+            FUSE_READ => {
+                let file_handle = get_u64(body, 0)?;
+                let offset = get_u64(body, 8)?;
+                let size = get_u32(body, 16)?.min(MAX_WRITE);
+                let file = self
+                    .open_files
+                    .lock()
+                    .get(&file_handle)
+                    .cloned()
+                    .ok_or(libc::EBADF)?;
+                spawn_blocking!(pool: BlockingPoolKind::Filesystem, "lazy_inputs_read", move || {
+                    let mut data = vec![0; size as usize];
+                    let mut filled = 0;
+                    while filled < data.len() {
+                        let position = offset.checked_add(filled as u64).ok_or(libc::EINVAL)?;
+                        match file.read_at(&mut data[filled..], position) {
+                            Ok(0) => break,
+                            Ok(read) => filled += read,
+                            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+                            Err(err) => return Err(err.raw_os_error().unwrap_or(libc::EIO)),
+                        }
+                    }
+                    data.truncate(filled);
+                    Ok(data)
+                })
+                .await
+                .map_err(|_| libc::EIO)?
+            }
+            FUSE_RELEASE => {
+                self.open_files.lock().remove(&get_u64(body, 0)?);
+                Ok(Vec::new())
+            }
+            FUSE_OPENDIR => {
+                let mut reply = Vec::with_capacity(16);
+                put_u64(&mut reply, 0);
+                put_u32(&mut reply, FOPEN_KEEP_CACHE | FOPEN_CACHE_DIR);
+                put_u32(&mut reply, 0);
+                Ok(reply)
+            }
+            FUSE_READDIR => {
+                let offset = usize::try_from(get_u64(body, 8)?).map_err(|_| libc::EINVAL)?;
+                let size = get_u32(body, 16)? as usize;
+                self.load_directory(inode).await?;
+                let nodes = self.nodes.lock();
+                let node = nodes.get(node_index(inode)?).ok_or(libc::ENOENT)?;
+                let NodeKind::Directory {
+                    children: Some(children),
+                    ..
+                } = &node.kind
+                else {
+                    return Err(libc::ENOTDIR);
+                };
+                let dir_type = u32::from(libc::DT_DIR);
+                let entries = [(".", inode, dir_type), ("..", node.parent, dir_type)]
+                    .into_iter()
+                    .chain(children.iter().map(|(name, child)| {
+                        let child_type = node_index(*child)
+                            .ok()
+                            .and_then(|index| nodes.get(index))
+                            .map_or(u32::from(libc::DT_UNKNOWN), Node::dirent_type);
+                        (name.as_str(), *child, child_type)
+                    }));
+                let mut reply = Vec::with_capacity(size);
+                for (index, (name, child, child_type)) in entries.enumerate().skip(offset) {
+                    let entry_size = (DIRENT_HEADER_SIZE + name.len()).next_multiple_of(8);
+                    if reply.len() + entry_size > size {
+                        break;
+                    }
+                    put_u64(&mut reply, child);
+                    put_u64(&mut reply, index as u64 + 1);
+                    put_u32(&mut reply, name.len() as u32);
+                    put_u32(&mut reply, child_type);
+                    reply.extend_from_slice(name.as_bytes());
+                    reply.resize(reply.len().next_multiple_of(8), 0);
+                }
+                Ok(reply)
+            }
+            FUSE_RELEASEDIR => Ok(Vec::new()),
+            FUSE_STATFS => {
+                let mut reply = Vec::with_capacity(80);
+                for _ in 0..5 {
+                    put_u64(&mut reply, 0);
+                }
+                put_u32(&mut reply, 4096);
+                put_u32(&mut reply, 255);
+                put_u32(&mut reply, 4096);
+                reply.resize(80, 0);
+                Ok(reply)
+            }
+            _ => Err(libc::ENOSYS),
+        }
+    }
+
+    /// Fetches the entries of the directory `inode` if they were not yet.
+    async fn load_directory(&self, inode: u64) -> Result<(), i32> {
+        let index = node_index(inode)?;
+        let digest = match self.nodes.lock().get(index).map(|node| &node.kind) {
+            Some(NodeKind::Directory {
+                children: Some(_), ..
+            }) => return Ok(()),
+            Some(NodeKind::Directory { digest, .. }) => *digest,
+            Some(_) => return Err(libc::ENOTDIR),
+            None => return Err(libc::ENOENT),
+        };
+        let directory =
+            get_and_decode_digest::<ProtoDirectory>(self.cas_store.as_ref(), digest.into())
+                .await
+                .err_tip(|| format!("Converting digest {digest} to Directory in lazy inputs"))
+                .map_err(|err| internal_error(&err))?;
+        let new_nodes = nodes_of_directory(directory, inode).map_err(|err| internal_error(&err))?;
+        let mut nodes = self.nodes.lock();
+        // Another request may have loaded the directory meanwhile.
+        if let NodeKind::Directory {
+            children: Some(_), ..
+        } = nodes.get(index).ok_or(libc::ENOENT)?.kind
+        {
+            return Ok(());
+        }
+        let mut children = BTreeMap::new();
+        for (name, node) in new_nodes {
+            nodes.push(node);
+            children.insert(name, nodes.len() as u64);
+        }
+        if let Some(Node {
+            kind:
+                NodeKind::Directory {
+                    children: node_children,
+                    ..
+                },
+            ..
+        }) = nodes.get_mut(index)
+        {
+            *node_children = Some(children);
+        }
+        Ok(())
+    }
+
+    /// Opens the file of `digest` in the `FilesystemStore`. The open file
+    /// stays readable even if the store evicts it.
+    async fn open_file(&self, digest: &DigestInfo) -> Result<File, Error> {
+        let file_entry = get_file_entry_for_staging(
+            &self.cas_store,
+            Pin::new(self.filesystem_store.as_ref()),
+            digest,
+        )
+        .await
+        .err_tip(|| format!("for digest {digest} in lazy inputs"))?;
+        file_entry
+            .get_file_path_locked(|path| async move {
+                spawn_blocking!(pool: BlockingPoolKind::Filesystem, "lazy_inputs_open", move || {
+                    File::open(&path).err_tip(|| format!("Could not open {path:?}"))
+                })
+                .await
+                .err_tip(|| "Failed to launch spawn_blocking in lazy inputs")?
+            })
+            .await
+    }
+
+    /// Writes the `fuse_attr` of a node.
+    fn put_attr(&self, reply: &mut Vec<u8>, inode: u64, node: &Node) {
+        let (size, mode, nlink, mtime) = match &node.kind {
+            NodeKind::Directory { .. } => (4096, libc::S_IFDIR | 0o555, 2, self.mount_time),
+            NodeKind::File {
+                digest,
+                mode,
+                mtime,
+            } => (
+                digest.size_bytes(),
+                libc::S_IFREG | mode,
+                1,
+                mtime.unwrap_or(self.mount_time),
+            ),
+            NodeKind::Symlink { target } => (
+                target.len() as u64,
+                libc::S_IFLNK | 0o777,
+                1,
+                self.mount_time,
+            ),
+        };
+        put_u64(reply, inode);
+        put_u64(reply, size);
+        put_u64(reply, size.div_ceil(512));
+        for _ in 0..3 {
+            put_u64(reply, mtime.as_secs());
+        }
+        for _ in 0..3 {
+            put_u32(reply, mtime.subsec_nanos());
+        }
+        put_u32(reply, mode);
+        put_u32(reply, nlink);
+        put_u32(reply, self.uid);
+        put_u32(reply, self.gid);
+        // rdev, blksize and flags.
+        put_u32(reply, 0);
+        put_u32(reply, 4096);
+        put_u32(reply, 0);
+    }
+}
+
+fn put_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_ne_bytes());
+}
+
+fn put_u64(buffer: &mut Vec<u8>, value: u64) {
+    buffer.extend_from_slice(&value.to_ne_bytes());
+}
+
+fn get_u32(buffer: &[u8], offset: usize) -> Result<u32, i32> {
+    buffer
+        .get(offset..offset + 4)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u32::from_ne_bytes)
+        .ok_or(libc::EINVAL)
+}
+
+fn get_u64(buffer: &[u8], offset: usize) -> Result<u64, i32> {
+    buffer
+        .get(offset..offset + 8)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u64::from_ne_bytes)
+        .ok_or(libc::EINVAL)
+}
+
+/// Builds the `fuse_init_out` for the `fuse_init_in` of the kernel.
+fn init_reply(body: &[u8]) -> Vec<u8> {
+    let max_readahead = get_u32(body, 8).unwrap_or(0);
+    let flags = get_u32(body, 12).unwrap_or(0) & (FUSE_ASYNC_READ | FUSE_PARALLEL_DIROPS);
+    let mut reply = Vec::with_capacity(64);
+    put_u32(&mut reply, FUSE_KERNEL_VERSION);
+    put_u32(&mut reply, FUSE_KERNEL_MINOR_VERSION);
+    put_u32(&mut reply, max_readahead);
+    put_u32(&mut reply, flags);
+    // max_background and congestion_threshold.
+    put_u32(&mut reply, 0);
+    put_u32(&mut reply, MAX_WRITE);
+    // time_gran.
+    put_u32(&mut reply, 1);
+    reply.resize(64, 0);
+    reply
+}
+
+fn write_reply(device: &File, unique: u64, result: Result<Vec<u8>, i32>) {
+    let (error, payload) = match result {
+        Ok(payload) => (0, payload),
+        Err(errno) => (-errno, Vec::new()),
+    };
+    let mut reply = Vec::with_capacity(OUT_HEADER_SIZE + payload.len());
+    put_u32(&mut reply, (OUT_HEADER_SIZE + payload.len()) as u32);
+    reply.extend_from_slice(&error.to_ne_bytes());
+    put_u64(&mut reply, unique);
+    reply.extend_from_slice(&payload);
+    // Each reply must be written at once. ENOENT means the request was
+    // interrupted and the kernel no longer waits for it.
+    if let Err(err) = (&*device).write(&reply) {
+        if err.raw_os_error() != Some(libc::ENOENT) {
+            event!(
+                Level::WARN,
+                ?err,
+                "Could not reply to request of lazy inputs"
+            );
+        }
+    }
+}
+
+/// Reads the requests of the kernel until the filesystem is unmounted.
+fn serve(
+    filesystem: &Arc<LazyInputsFs>,
+    device: &Arc<File>,
+    origin_context: &Option<Arc<OriginContext>>,
+) {
+    let mut buffer = vec![0; IN_HEADER_SIZE + MAX_WRITE as usize + 4096];
+    loop {
+        let len = match (&**device).read(&mut buffer) {
+            Ok(len) => len,
+            Err(err) => match err.raw_os_error() {
+                Some(libc::EINTR | libc::EAGAIN | libc::ENOENT) => continue,
+                // The filesystem was unmounted.
+                Some(libc::ENODEV) => return,
+                _ => {
+                    event!(Level::ERROR, ?err, "Could not read request of lazy inputs");
+                    return;
+                }
+            },
+        };
+        let request = &buffer[..len];
+        let (Ok(request_len), Ok(opcode), Ok(unique), Ok(inode)) = (
+            get_u32(request, 0),
+            get_u32(request, 4),
+            get_u64(request, 8),
+            get_u64(request, 16),
+        ) else {
+            event!(Level::ERROR, len, "Invalid request of lazy inputs");
+            continue;
+        };
+        if len < IN_HEADER_SIZE || request_len as usize != len {
+            event!(
+                Level::ERROR,
+                len,
+                request_len,
+                "Invalid length of request of lazy inputs"
+            );
+            write_reply(device, unique, Err(libc::EINVAL));
+            continue;
+        }
+        let body = request[IN_HEADER_SIZE..].to_vec();
+        match opcode {
+            FUSE_FORGET | FUSE_BATCH_FORGET | FUSE_INTERRUPT => {}
+            _ if body.len() < min_body_size(opcode) => {
+                event!(
+                    Level::ERROR,
+                    opcode,
+                    len,
+                    "Request of lazy inputs is too short"
+                );
+                write_reply(device, unique, Err(libc::EINVAL));
+            }
+            FUSE_INIT => write_reply(device, unique, Ok(init_reply(&body))),
+            _ => {
+                let filesystem = filesystem.clone();
+                let device = device.clone();
+                background_spawn!(
+                    span: error_span!("lazy_inputs_request", opcode, inode),
+                    ctx: origin_context.clone(),
+                    fut: async move {
+                        let result = filesystem.handle(opcode, inode, &body).await;
+                        write_reply(&device, unique, result);
+                    }
+                );
+            }
+        }
+    }
+}
@@ -22,13 +22,14 @@ use std::time::Duration;
 use futures::future::BoxFuture;
 use futures::stream::FuturesUnordered;
 use futures::{select, Future, FutureExt, StreamExt, TryFutureExt};
-use nativelink_config::cas_server::LocalWorkerConfig;
+use nativelink_config::cas_server::{LocalWorkerConfig, ReconnectBackoffConfig};
 use nativelink_error::{make_err, make_input_err, Code, Error, ResultExt};
 use nativelink_metric::{MetricsComponent, RootMetricsComponent};
 use nativelink_proto::com::github::trace_machina::nativelink::remote_execution::update_for_worker::Update;
 use nativelink_proto::com::github::trace_machina::nativelink::remote_execution::worker_api_client::WorkerApiClient;
 use nativelink_proto::com::github::trace_machina::nativelink::remote_execution::{
-    execute_result, ExecuteResult, GoingAwayRequest, KeepAliveRequest, UpdateForWorker,
+    execute_result, ConnectionResult, ExecuteResult, GoingAwayRequest, KeepAliveRequest,
+    UpdateForWorker,
 };
 use nativelink_store::fast_slow_store::FastSlowStore;
 use nativelink_util::action_messages::{ActionResult, ActionStage, OperationId};
@@ -39,6 +40,8 @@ use nativelink_util::origin_context::ActiveOriginContext;
 use nativelink_util::shutdown_guard::ShutdownGuard;
 use nativelink_util::store_trait::Store;
 use nativelink_util::{spawn, tls_utils};
+use rand::rngs::OsRng;
+use rand::Rng;
 use tokio::process;
 use tokio::sync::{broadcast, mpsc};
 use tokio::time::sleep;
@@ -58,8 +61,17 @@ use crate::worker_utils::make_supported_properties;
 const ACTIONS_IN_TRANSIT_TIMEOUT_S: f32 = 10.;
 
 /// If we lose connection to the worker api server we will wait this many seconds
-/// before trying to connect.
-const CONNECTION_RETRY_DELAY_S: f32 = 0.5;
+/// before trying to connect for the first time. If this value gets modified the
+/// documentation in `cas_server.rs` must also be updated.
+const DEFAULT_RECONNECT_INITIAL_DELAY_S: f32 = 0.5;
+
+/// Longest time to wait between two attempts to connect to the worker api server.
+/// If this value gets modified the documentation in `cas_server.rs` must also be updated.
+const DEFAULT_RECONNECT_MAX_DELAY_S: f32 = 30.;
+
+/// Default jitter applied to the delay between two attempts to connect.
+/// If this value gets modified the documentation in `cas_server.rs` must also be updated.
+const DEFAULT_RECONNECT_JITTER: f32 = 0.5;
 
 /// Default endpoint timeout. If this value gets modified the documentation in
 /// `cas_server.rs` must also be updated.
@@ -74,6 +86,11 @@ struct LocalWorkerImpl<'a, T: WorkerApiClientTrait, U: RunningActionsManager> {
     // According to the tonic documentation it is a cheap operation to clone this.
     grpc_client: T,
     worker_id: String,
+    // How often to send a keep alive to the scheduler.
+    heartbeat_interval: Duration,
+    // Number of keep alives in a row that may fail before we consider the
+    // scheduler lost.
+    max_missed_heartbeats: u32,
     running_actions_manager: Arc<U>,
     // Number of actions that have been received in `Update::StartAction`, but
     // not yet processed by running_actions_manager's spawn. This number should
@@ -119,14 +136,30 @@ impl<'a, T: WorkerApiClientTrait, U: RunningActionsManager> LocalWorkerImpl<'a,
     fn new(
         config: &'a LocalWorkerConfig,
         grpc_client: T,
-        worker_id: String,
+        connection_result: ConnectionResult,
         running_actions_manager: Arc<U>,
         metrics: Arc<Metrics>,
     ) -> Self {
+        // If the scheduler did not ask for a heartbeat, we always send 2 keep alive
+        // requests per timeout. Http2 should manage most of our timeout issues, this
+        // is a secondary check to ensure we can still send data.
+        let heartbeat_interval = connection_result
+            .heartbeat_interval
+            .and_then(|heartbeat_interval| Duration::try_from(heartbeat_interval).ok())
+            .filter(|heartbeat_interval| !heartbeat_interval.is_zero())
+            .unwrap_or_else(|| {
+                let timeout = config
+                    .worker_api_endpoint
+                    .timeout
+                    .unwrap_or(DEFAULT_ENDPOINT_TIMEOUT_S);
+                Duration::from_secs_f32(timeout / 2.)
+            });
         Self {
             config,
             grpc_client,
-            worker_id,
+            worker_id: connection_result.worker_id,
+            heartbeat_interval,
+            max_missed_heartbeats: connection_result.max_missed_heartbeats,
             running_actions_manager,
             // Number of actions that have been received in `Update::StartAction`, but
             // not yet processed by running_actions_manager's spawn. This number should
@@ -137,32 +170,40 @@ impl<'a, T: WorkerApiClientTrait, U: RunningActionsManager> LocalWorkerImpl<'a,
         }
     }
 
-    /// Starts a background spawn/thread that will send a message to the server every `timeout / 2`.
+    /// Starts a background spawn/thread that will send a message to the server every
+    /// `heartbeat_interval`. Fails once more than `max_missed_heartbeats` keep alives
+    /// in a row could not be sent.
     async fn start_keep_alive(&self) -> Result<(), Error> {
         // According to tonic's documentation this call should be cheap and is the same stream.
         let mut grpc_client = self.grpc_client.clone();
 
+        let mut missed_heartbeats = 0;
         loop {
-            let timeout = self
-                .config
-                .worker_api_endpoint
-                .timeout
-                .unwrap_or(DEFAULT_ENDPOINT_TIMEOUT_S);
-            // We always send 2 keep alive requests per timeout. Http2 should manage most of our
-            // timeout issues, this is a secondary check to ensure we can still send data.
-            sleep(Duration::from_secs_f32(timeout / 2.)).await;
-            if let Err(e) = grpc_client
+            sleep(self.heartbeat_interval).await;
+            let keep_alive_result = grpc_client
                 .keep_alive(KeepAliveRequest {
                     worker_id: self.worker_id.clone(),
                 })
-                .await
-            {
+                .await;
+            let Err(e) = keep_alive_result else {
+                missed_heartbeats = 0;
+                continue;
+            };
+            missed_heartbeats += 1;
+            if missed_heartbeats > self.max_missed_heartbeats {
                 return Err(make_err!(
                     Code::Internal,
                     "Failed to send KeepAlive in LocalWorker : {:?}",
                     e
                 ));
             }
+            event!(
+                Level::WARN,
+                ?e,
+                missed_heartbeats,
+                max_missed_heartbeats = self.max_missed_heartbeats,
+                "Failed to send KeepAlive, will retry"
+            );
         }
     }
 
@@ -428,6 +469,13 @@ pub async fn new_local_worker(
             execution_configuration: ExecutionConfiguration {
                 entrypoint,
                 additional_environment: config.additional_environment.clone(),
+                network_isolation: config.network_isolation.clone(),
+                sandbox: config.sandbox.clone(),
+                container: config.container.clone(),
+                cgroup: config.cgroup.clone(),
+                input_provider: config.input_provider,
+                max_concurrent_output_uploads: config.max_concurrent_output_uploads,
+                persistent_workers: config.persistent_workers.clone(),
             },
             cas_store: fast_slow_store,
             ac_store,
@@ -498,7 +546,7 @@ impl<T: WorkerApiClientTrait, U: RunningActionsManager> LocalWorker<T, U> {
     async fn register_worker(
         &self,
         client: &mut T,
-    ) -> Result<(String, Streaming<UpdateForWorker>), Error> {
+    ) -> Result<(ConnectionResult, Streaming<UpdateForWorker>), Error> {
         let supported_properties =
             make_supported_properties(&self.config.platform_properties).await?;
         let mut update_for_worker_stream = client
@@ -514,8 +562,8 @@ impl<T: WorkerApiClientTrait, U: RunningActionsManager> LocalWorker<T, U> {
             .err_tip(|| "Got error when receiving UpdateForWorker")?
             .update;
 
-        let worker_id = match first_msg_update {
-            Some(Update::ConnectionResult(connection_result)) => connection_result.worker_id,
+        let connection_result = match first_msg_update {
+            Some(Update::ConnectionResult(connection_result)) => connection_result,
             other => {
                 return Err(make_input_err!(
                     "Expected first response from scheduler to be a ConnectResult got : {:?}",
@@ -523,7 +571,7 @@ impl<T: WorkerApiClientTrait, U: RunningActionsManager> LocalWorker<T, U> {
                 ))
             }
         };
-        Ok((worker_id, update_for_worker_stream))
+        Ok((connection_result, update_for_worker_stream))
     }
 
     #[instrument(skip(self), level = Level::INFO)]
@@ -536,17 +584,23 @@ impl<T: WorkerApiClientTrait, U: RunningActionsManager> LocalWorker<T, U> {
             .take()
             .err_tip(|| "Could not unwrap sleep_fn in LocalWorker::run")?;
         let sleep_fn_pin = Pin::new(&sleep_fn);
-        let error_handler = Box::pin(move |err| async move {
-            event!(Level::ERROR, ?err, "Error");
-            (sleep_fn_pin)(Duration::from_secs_f32(CONNECTION_RETRY_DELAY_S)).await;
+        let error_handler = Box::pin(move |err, delay| async move {
+            event!(
+                Level::ERROR,
+                ?err,
+                ?delay,
+                "Error, reconnecting to scheduler"
+            );
+            (sleep_fn_pin)(delay).await;
         });
+        let mut reconnect_backoff = ReconnectBackoff::new(&self.config.reconnect_backoff);
 
         loop {
             // First connect to our endpoint.
             let mut client = match (self.connection_factory)().await {
                 Ok(client) => client,
                 Err(e) => {
-                    (error_handler)(e).await;
+                    (error_handler)(e, reconnect_backoff.next_delay()).await;
                     continue; // Try to connect again.
                 }
             };
@@ -555,14 +609,14 @@ impl<T: WorkerApiClientTrait, U: RunningActionsManager> LocalWorker<T, U> {
             let (mut inner, update_for_worker_stream) =
                 match self.register_worker(&mut client).await {
                     Err(e) => {
-                        (error_handler)(e).await;
+                        (error_handler)(e, reconnect_backoff.next_delay()).await;
                         continue; // Try to connect again.
                     }
-                    Ok((worker_id, update_for_worker_stream)) => (
+                    Ok((connection_result, update_for_worker_stream)) => (
                         LocalWorkerImpl::new(
                             &self.config,
                             client,
-                            worker_id,
+                            connection_result,
                             self.running_actions_manager.clone(),
                             self.metrics.clone(),
                         ),
@@ -574,6 +628,7 @@ impl<T: WorkerApiClientTrait, U: RunningActionsManager> LocalWorker<T, U> {
                 worker_id = %inner.worker_id,
                 "Worker registered with scheduler"
             );
+            reconnect_backoff.reset();
 
             // Now listen for connections and run all other services.
             if let Err(err) = inner.run(update_for_worker_stream, &mut shutdown_rx).await {
@@ -599,13 +654,58 @@ impl<T: WorkerApiClientTrait, U: RunningActionsManager> LocalWorker<T, U> {
                 // get some more and it might resource lock us.
                 self.running_actions_manager.kill_all().await;
 
-                (error_handler)(err).await; // Try to connect again.
+                (error_handler)(err, reconnect_backoff.next_delay()).await; // Try to connect again.
             }
         }
         // Unreachable.
     }
 }
 
+/// Delay before reconnecting to the scheduler. The delay doubles after every
+/// failed attempt, up to a maximum, and is jittered so workers that lost the
+/// scheduler at the same time do not all reconnect at once.
+struct ReconnectBackoff {
+    initial_delay: Duration,
+    max_delay: Duration,
+    jitter: f32,
+    delay: Duration,
+}
+
+impl ReconnectBackoff {
+    fn new(config: &ReconnectBackoffConfig) -> Self {
+        let initial_delay = Duration::from_secs_f32(
+            config
+                .initial_delay_s
+                .unwrap_or(DEFAULT_RECONNECT_INITIAL_DELAY_S),
+        );
+        Self {
+            initial_delay,
+            max_delay: Duration::from_secs_f32(
+                config.max_delay_s.unwrap_or(DEFAULT_RECONNECT_MAX_DELAY_S),
+            ),
+            jitter: config.jitter.unwrap_or(DEFAULT_RECONNECT_JITTER),
+            delay: initial_delay,
+        }
+    }
+
+    /// Returns how long to wait before the next attempt and backs off further.
+    fn next_delay(&mut self) -> Duration {
+        let delay = self.delay;
+        self.delay = (delay * 2).min(self.max_delay);
+        if self.jitter <= 0. {
+            return delay;
+        }
+        let min = 1. - (self.jitter / 2.);
+        let max = 1. + (self.jitter / 2.);
+        delay.mul_f32(OsRng.gen_range(min..max))
+    }
+
+    /// Starts over with the initial delay once the worker is connected again.
+    fn reset(&mut self) {
+        self.delay = self.initial_delay;
+    }
+}
+
 #[derive(MetricsComponent)]
 pub struct Metrics {
     #[metric(
@@ -0,0 +1,484 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::future::Future;
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+use futures::StreamExt;
+use nativelink_config::cas_server::PersistentWorkerConfig;
+use nativelink_error::{error_if, make_err, Code, Error, ResultExt};
+use nativelink_proto::blaze::worker::{Input, WorkRequest, WorkResponse};
+use nativelink_proto::build::bazel::remote::execution::v2::Directory as ProtoDirectory;
+use nativelink_store::ac_utils::get_and_decode_digest;
+use nativelink_store::fast_slow_store::FastSlowStore;
+use nativelink_util::background_spawn;
+use nativelink_util::common::{fs, DigestInfo};
+use parking_lot::Mutex;
+use prost::Message;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{self, Child, ChildStdin, ChildStdout};
+use tokio_stream::wrappers::ReadDirStream;
+use tracing::{event, Level};
+
+/// Default value for `PersistentWorkerConfig::key_property`.
+/// If this changes, remember to change the documentation in the config.
+const DEFAULT_KEY_PROPERTY: &str = "persistentWorkerKey";
+
+/// Default value for `PersistentWorkerConfig::idle_timeout`.
+/// If this changes, remember to change the documentation in the config.
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Largest `WorkResponse` accepted from a worker process. A bigger one is
+/// most likely a tool writing to stdout, which the protocol reserves for
+/// responses.
+const MAX_WORK_RESPONSE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Identifies the worker processes an action may run in. Actions only
+/// share a process if they start it the same way.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct WorkerKey {
+    key: String,
+    working_directory: String,
+    arguments: Vec<OsString>,
+    environment: Vec<(String, String)>,
+}
+
+impl WorkerKey {
+    /// The arguments the worker process is started with.
+    pub fn arguments(&self) -> &[OsString] {
+        &self.arguments
+    }
+}
+
+/// Keeps the worker processes of actions with a persistent worker key
+/// between actions. Each process runs one action at a time in its own
+/// directory, the inputs of the action are moved into it for the request
+/// and the outputs moved back afterwards.
+pub struct PersistentWorkers {
+    key_property: String,
+    idle_timeout: Duration,
+    max_requests: u64,
+    directory: String,
+    next_worker_id: AtomicU64,
+    idle_workers: Arc<Mutex<IdleWorkers>>,
+}
+
+#[derive(Default)]
+struct IdleWorkers {
+    workers: HashMap<WorkerKey, Vec<WorkerProcess>>,
+    reaper_running: bool,
+}
+
+impl PersistentWorkers {
+    pub fn new(config: &PersistentWorkerConfig, root_action_directory: &str) -> Self {
+        Self {
+            key_property: if config.key_property.is_empty() {
+                DEFAULT_KEY_PROPERTY.to_string()
+            } else {
+                config.key_property.clone()
+            },
+            idle_timeout: if config.idle_timeout == 0 {
+                DEFAULT_IDLE_TIMEOUT
+            } else {
+                Duration::from_secs(config.idle_timeout as u64)
+            },
+            max_requests: config.max_requests,
+            directory: format!("{root_action_directory}/persistent_workers"),
+            next_worker_id: AtomicU64::new(0),
+            idle_workers: Arc::new(Mutex::new(IdleWorkers::default())),
+        }
+    }
+
+    /// Returns the key of the worker process the action runs in and the
+    /// files the arguments of its request are read from, or `None` if the
+    /// action does not run in a persistent worker. Like Bazel, only
+    /// actions with an `@file` or `--flagfile=file` argument do.
+    pub fn worker_key(
+        &self,
+        platform_properties: &HashMap<String, String>,
+        arguments: &[&OsStr],
+        working_directory: &str,
+        environment: &[(&str, Cow<'_, str>)],
+    ) -> Option<(WorkerKey, Vec<OsString>)> {
+        let key = platform_properties.get(&self.key_property)?;
+        let (flagfiles, startup_arguments): (Vec<&OsStr>, Vec<&OsStr>) = arguments
+            .iter()
+            .partition(|argument| flagfile_path(argument).is_some());
+        if flagfiles.is_empty() || startup_arguments.is_empty() {
+            return None;
+        }
+        Some((
+            WorkerKey {
+                key: key.clone(),
+                working_directory: working_directory.to_string(),
+                arguments: startup_arguments.iter().map(OsString::from).collect(),
+                environment: environment
+                    .iter()
+                    .map(|(name, value)| ((*name).to_string(), value.to_string()))
+                    .collect(),
+            },
+            flagfiles
+                .iter()
+                .filter_map(|flagfile| flagfile_path(flagfile))
+                .map(OsString::from)
+                .collect(),
+        ))
+    }
+
+    /// Sends `work_request` to an idle worker process for `key`, or to a
+    /// new one. The entries of `work_directory` are moved into the
+    /// directory of the process while it runs. Returns `None` if
+    /// `cancelled` completes first, the process is stopped then.
+    pub async fn execute(
+        &self,
+        key: WorkerKey,
+        work_directory: &str,
+        work_request: &WorkRequest,
+        cancelled: impl Future<Output = ()>,
+    ) -> Result<Option<WorkResponse>, Error> {
+        let mut worker = match self.take_idle_worker(&key) {
+            Some(worker) => worker,
+            None => self.new_worker().await?,
+        };
+        let execroot = worker.execroot();
+        let result = async {
+            move_entries(work_directory, &execroot)
+                .await
+                .err_tip(|| "Could not move inputs into persistent worker")?;
+            tokio::select! {
+                result = worker.run(&key, work_request) => result.map(Some),
+                () = cancelled => Ok(None),
+            }
+        }
+        .await;
+        // The outputs are uploaded, and the inputs removed, from the work
+        // directory of the action.
+        let result = move_entries(&execroot, work_directory)
+            .await
+            .err_tip(|| "Could not move outputs out of persistent worker")
+            .merge(result);
+        let reusable = matches!(result, Ok(Some(_)))
+            && (self.max_requests == 0 || worker.requests < self.max_requests);
+        if reusable {
+            self.release_worker(key, worker);
+        } else {
+            worker.stop().await;
+        }
+        result
+    }
+
+    fn take_idle_worker(&self, key: &WorkerKey) -> Option<WorkerProcess> {
+        let mut idle_workers = self.idle_workers.lock();
+        let workers = idle_workers.workers.get_mut(key)?;
+        // The most recently used process is the most likely to be warm.
+        let worker = workers.pop();
+        if workers.is_empty() {
+            idle_workers.workers.remove(key);
+        }
+        worker
+    }
+
+    async fn new_worker(&self) -> Result<WorkerProcess, Error> {
+        let worker_id = self.next_worker_id.fetch_add(1, Ordering::Relaxed);
+        let worker = WorkerProcess {
+            directory: format!("{}/{worker_id}", self.directory),
+            process: None,
+            requests: 0,
+            idle_since: Instant::now(),
+        };
+        fs::create_dir_all(worker.execroot())
+            .await
+            .err_tip(|| format!("Could not create directory of {}", worker.directory))?;
+        Ok(worker)
+    }
+
+    fn release_worker(&self, key: WorkerKey, mut worker: WorkerProcess) {
+        worker.idle_since = Instant::now();
+        let mut idle_workers = self.idle_workers.lock();
+        idle_workers.workers.entry(key).or_default().push(worker);
+        if !idle_workers.reaper_running {
+            idle_workers.reaper_running = true;
+            start_reaper(Arc::downgrade(&self.idle_workers), self.idle_timeout);
+        }
+    }
+}
+
+/// Stops the worker processes that have been idle for `idle_timeout`,
+/// until no idle processes are left.
+fn start_reaper(idle_workers: Weak<Mutex<IdleWorkers>>, idle_timeout: Duration) {
+    background_spawn!("persistent_workers_reaper", async move {
+        let mut sleep_duration = idle_timeout;
+        loop {
+            tokio::time::sleep(sleep_duration).await;
+            let Some(idle_workers) = idle_workers.upgrade() else {
+                return;
+            };
+            let mut expired_workers = Vec::new();
+            let done = {
+                let mut idle_workers = idle_workers.lock();
+                let now = Instant::now();
+                sleep_duration = idle_timeout;
+                idle_workers.workers.retain(|_, workers| {
+                    let (expired, idle): (Vec<_>, Vec<_>) = std::mem::take(workers)
+                        .into_iter()
+                        .partition(|worker| now.duration_since(worker.idle_since) >= idle_timeout);
+                    for worker in &idle {
+                        sleep_duration = sleep_duration
+                            .min(idle_timeout - now.duration_since(worker.idle_since));
+                    }
+                    expired_workers.extend(expired);
+                    *workers = idle;
+                    !workers.is_empty()
+                });
+                idle_workers.reaper_running = !idle_workers.workers.is_empty();
+                !idle_workers.reaper_running
+            };
+            drop(idle_workers);
+            for worker in expired_workers {
+                event!(
+                    Level::INFO,
+                    directory = ?worker.directory,
+                    "Stopping idle persistent worker"
+                );
+                worker.stop().await;
+            }
+            if done {
+                return;
+            }
+        }
+    });
+}
+
+struct WorkerProcess {
+    directory: String,
+    // Started by the first request, once its inputs are in place.
+    process: Option<Process>,
+    requests: u64,
+    idle_since: Instant,
+}
+
+struct Process {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl WorkerProcess {
+    fn execroot(&self) -> String {
+        format!("{}/execroot", self.directory)
+    }
+
+    async fn run(
+        &mut self,
+        key: &WorkerKey,
+        work_request: &WorkRequest,
+    ) -> Result<WorkResponse, Error> {
+        if self.process.is_none() {
+            self.process = Some(self.start(key)?);
+        }
+        let process = self
+            .process
+            .as_mut()
+            .err_tip(|| "Expected persistent worker to be started")?;
+        self.requests += 1;
+        process
+            .stdin
+            .write_all(&work_request.encode_length_delimited_to_vec())
+            .await
+            .err_tip(|| "Could not send WorkRequest to persistent worker")?;
+        process
+            .stdin
+            .flush()
+            .await
+            .err_tip(|| "Could not send WorkRequest to persistent worker")?;
+        read_work_response(&mut process.stdout)
+            .await
+            .err_tip(|| format!("Reading WorkResponse of persistent worker {key:?}"))
+    }
+
+    fn start(&self, key: &WorkerKey) -> Result<Process, Error> {
+        let (program, arguments) = key
+            .arguments
+            .split_first()
+            .err_tip(|| "Expected persistent worker to have arguments")?;
+        // The log of the process is kept next to, not in, the directory the
+        // inputs of the actions are moved into.
+        let stderr = std::fs::File::create(format!("{}/stderr.log", self.directory))
+            .err_tip(|| format!("Could not create log of {}", self.directory))?;
+        event!(Level::INFO, ?key, "Starting persistent worker");
+        let mut child = process::Command::new(program)
+            .args(arguments)
+            .arg("--persistent_worker")
+            .kill_on_drop(true)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(stderr)
+            .current_dir(format!("{}/{}", self.execroot(), key.working_directory))
+            .env_clear()
+            .envs(key.environment.iter().map(|(name, value)| (name, value)))
+            .spawn()
+            .err_tip(|| format!("Could not start persistent worker {:?}", key.arguments))?;
+        Ok(Process {
+            stdin: child
+                .stdin
+                .take()
+                .err_tip(|| "Expected stdin to exist on persistent worker")?,
+            stdout: BufReader::new(
+                child
+                    .stdout
+                    .take()
+                    .err_tip(|| "Expected stdout to exist on persistent worker")?,
+            ),
+            child,
+        })
+    }
+
+    async fn stop(self) {
+        if let Some(mut process) = self.process {
+            if let Err(err) = process.child.kill().await {
+                event!(Level::ERROR, ?err, "Could not kill persistent worker");
+            }
+        }
+        if let Err(err) = fs::remove_dir_all(&self.directory).await {
+            event!(
+                Level::ERROR,
+                ?err,
+                directory = ?self.directory,
+                "Could not remove directory of persistent worker"
+            );
+        }
+    }
+}
+
+/// Returns the file an argument reads more arguments from, if it is an
+/// `@file`, `-flagfile=file` or `--flagfile=file` argument.
+fn flagfile_path(argument: &OsStr) -> Option<&OsStr> {
+    let argument = argument.to_str()?;
+    ["@", "--flagfile=", "-flagfile="]
+        .iter()
+        .find_map(|prefix| argument.strip_prefix(prefix))
+        .filter(|path| !path.is_empty())
+        .map(OsStr::new)
+}
+
+/// Makes the request for an action, with the lines of its flagfiles as
+/// arguments and the files of its input root as inputs.
+pub async fn make_work_request(
+    cas_store: &FastSlowStore,
+    input_root_digest: DigestInfo,
+    current_directory: &str,
+    flagfiles: &[OsString],
+) -> Result<WorkRequest, Error> {
+    let mut arguments = Vec::new();
+    for flagfile in flagfiles {
+        let path = Path::new(current_directory).join(flagfile);
+        let contents = fs::read(&path)
+            .await
+            .err_tip(|| format!("Could not read flagfile {path:?}"))?;
+        let contents = String::from_utf8(contents)
+            .map_err(|e| make_err!(Code::InvalidArgument, "Flagfile {path:?} is not UTF-8: {e}"))?;
+        arguments.extend(contents.lines().map(str::to_string));
+    }
+    let mut inputs = Vec::new();
+    let mut directories = vec![(String::new(), input_root_digest)];
+    while let Some((path, digest)) = directories.pop() {
+        let directory = get_and_decode_digest::<ProtoDirectory>(cas_store, digest.into())
+            .await
+            .err_tip(|| "Converting digest to Directory")?;
+        for file in directory.files {
+            let digest = file
+                .digest
+                .err_tip(|| "Expected Digest to exist in Directory::file::digest")?;
+            inputs.push(Input {
+                path: format!("{path}{}", file.name),
+                // Bazel sends the hex hash too.
+                digest: digest.hash.into(),
+            });
+        }
+        for subdirectory in directory.directories {
+            let digest: DigestInfo = subdirectory
+                .digest
+                .err_tip(|| "Expected Digest to exist in Directory::directories::digest")?
+                .try_into()
+                .err_tip(|| "In Directory::directories::digest")?;
+            directories.push((format!("{path}{}/", subdirectory.name), digest));
+        }
+    }
+    Ok(WorkRequest {
+        arguments,
+        inputs,
+        ..Default::default()
+    })
+}
+
+async fn read_work_response(stdout: &mut BufReader<ChildStdout>) -> Result<WorkResponse, Error> {
+    let mut length = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = stdout
+            .read_u8()
+            .await
+            .err_tip(|| "Persistent worker closed its stdout")?;
+        length |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            error_if!(
+                length > MAX_WORK_RESPONSE_SIZE,
+                "WorkResponse of {length} bytes is larger than {MAX_WORK_RESPONSE_SIZE} bytes"
+            );
+            let mut message = vec![0; usize::try_from(length)?];
+            stdout
+                .read_exact(&mut message)
+                .await
+                .err_tip(|| "Persistent worker closed its stdout")?;
+            return WorkResponse::decode(message.as_slice())
+                .map_err(|e| make_err!(Code::Internal, "Could not decode WorkResponse: {e}"));
+        }
+    }
+    Err(make_err!(
+        Code::Internal,
+        "Invalid length of WorkResponse from persistent worker"
+    ))
+}
+
+/// Moves the entries of directory `from` into directory `to`.
+async fn move_entries(from: &str, to: &str) -> Result<(), Error> {
+    let mut names = Vec::new();
+    {
+        let (_permit, dir_handle) = fs::read_dir(from)
+            .await
+            .err_tip(|| format!("Error reading dir {from}"))?
+            .into_inner();
+        let mut dir_stream = ReadDirStream::new(dir_handle);
+        while let Some(entry) = dir_stream.next().await {
+            names.push(
+                entry
+                    .err_tip(|| "Error while iterating directory")?
+                    .file_name(),
+            );
+        }
+    }
+    for name in names {
+        let (from, to) = (Path::new(from).join(&name), Path::new(to).join(&name));
+        fs::rename(&from, &to)
+            .await
+            .err_tip(|| format!("Could not move {from:?} to {to:?}"))?;
+    }
+    Ok(())
+}
@@ -15,15 +15,17 @@
 use std::borrow::Cow;
 use std::cmp::min;
 use std::collections::vec_deque::VecDeque;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::convert::Into;
 use std::ffi::{OsStr, OsString};
 use std::fmt::Debug;
 #[cfg(target_family = "unix")]
 use std::fs::Permissions;
+#[cfg(target_os = "linux")]
+use std::os::fd::AsRawFd;
 #[cfg(target_family = "unix")]
 use std::os::unix::fs::{MetadataExt, PermissionsExt};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::process::Stdio;
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -34,13 +36,14 @@ use bytes::{Bytes, BytesMut};
 use filetime::{set_file_mtime, FileTime};
 use formatx::Template;
 use futures::future::{
-    try_join, try_join3, try_join_all, BoxFuture, Future, FutureExt, TryFutureExt,
+    try_join, try_join3, try_join_all, BoxFuture, Fuse, Future, FutureExt, TryFutureExt,
 };
 use futures::stream::{FuturesUnordered, StreamExt, TryStreamExt};
 use nativelink_config::cas_server::{
-    EnvironmentSource, UploadActionResultConfig, UploadCacheResultsStrategy,
+    CgroupConfig, ContainerConfig, EnvironmentSource, InputProvider, NetworkIsolationConfig,
+    PersistentWorkerConfig, SandboxConfig, UploadActionResultConfig, UploadCacheResultsStrategy,
 };
-use nativelink_error::{make_err, make_input_err, Code, Error, ResultExt};
+use nativelink_error::{error_if, make_err, make_input_err, Code, Error, ResultExt};
 use nativelink_metric::MetricsComponent;
 use nativelink_proto::build::bazel::remote::execution::v2::{
     Action, ActionResult as ProtoActionResult, Command as ProtoCommand,
@@ -54,8 +57,10 @@ use nativelink_store::ac_utils::{
     compute_buf_digest, get_and_decode_digest, serialize_and_upload_message, ESTIMATED_DIGEST_SIZE,
 };
 use nativelink_store::fast_slow_store::FastSlowStore;
-use nativelink_store::filesystem_store::{FileEntry, FilesystemStore};
+use nativelink_store::filesystem_store::{FileEntry, FileEntryImpl, FilesystemStore};
 use nativelink_store::grpc_store::GrpcStore;
+#[cfg(target_os = "linux")]
+use nativelink_util::action_messages::ResourceUsage;
 use nativelink_util::action_messages::{
     to_execute_response, ActionInfo, ActionResult, DirectoryInfo, ExecutionMetadata, FileInfo,
     NameOrPath, OperationId, SymlinkInfo,
@@ -64,21 +69,26 @@ use nativelink_util::common::{fs, DigestInfo};
 use nativelink_util::digest_hasher::{DigestHasher, DigestHasherFunc};
 use nativelink_util::metrics_utils::{AsyncCounterWrapper, CounterWithTime};
 use nativelink_util::shutdown_guard::ShutdownGuard;
-use nativelink_util::store_trait::{Store, StoreLike, UploadSizeInfo};
+use nativelink_util::store_trait::{Store, StoreKey, StoreLike, UploadSizeInfo};
+use nativelink_util::task::BlockingPoolKind;
 use nativelink_util::{background_spawn, spawn, spawn_blocking};
 use parking_lot::Mutex;
 use prost::Message;
 use relative_path::RelativePath;
 use scopeguard::{guard, ScopeGuard};
 use serde::Deserialize;
-use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::io::AsyncReadExt;
 use tokio::process;
-use tokio::sync::{oneshot, watch};
+use tokio::sync::{oneshot, watch, Semaphore, SemaphorePermit};
 use tokio_stream::wrappers::ReadDirStream;
 use tonic::Request;
 use tracing::{enabled, event, Level};
 use uuid::Uuid;
 
+#[cfg(target_os = "linux")]
+use crate::lazy_inputs::LazyInputs;
+use crate::persistent_workers::{make_work_request, PersistentWorkers, WorkerKey};
+
 /// For simplicity we use a fixed exit code for cases when our program is terminated
 /// due to a signal.
 const EXIT_CODE_FOR_SIGNAL: i32 = 9;
@@ -89,6 +99,46 @@ const EXIT_CODE_FOR_SIGNAL: i32 = 9;
 const DEFAULT_HISTORICAL_RESULTS_STRATEGY: UploadCacheResultsStrategy =
     UploadCacheResultsStrategy::failures_only;
 
+/// Default value for `LocalWorkerConfig::max_concurrent_output_uploads`.
+/// If this changes, remember to change the documentation in the config.
+const DEFAULT_MAX_CONCURRENT_OUTPUT_UPLOADS: usize = 64;
+
+/// Platform property that selects the image of the container an action
+/// runs in.
+const CONTAINER_IMAGE_PROPERTY: &str = "container-image";
+
+/// Default value for `ContainerConfig::podman_path`.
+/// If this changes, remember to change the documentation in the config.
+const DEFAULT_PODMAN_PATH: &str = "/usr/bin/podman";
+
+/// Platform properties that set the limits of the cgroup of an action.
+#[cfg(target_os = "linux")]
+const CPU_COUNT_PROPERTY: &str = "cpu_count";
+#[cfg(target_os = "linux")]
+const MEMORY_KB_PROPERTY: &str = "memory_kb";
+#[cfg(target_os = "linux")]
+const MAX_PIDS_PROPERTY: &str = "max_pids";
+
+/// Default value for `CgroupConfig::path`.
+/// If this changes, remember to change the documentation in the config.
+#[cfg(target_os = "linux")]
+const DEFAULT_CGROUP_PATH: &str = "/sys/fs/cgroup/nativelink";
+
+/// Period the CPU limit of a cgroup applies to, in microseconds.
+#[cfg(target_os = "linux")]
+const CGROUP_CPU_PERIOD_US: u64 = 100_000;
+
+/// Default value for `SandboxConfig::bwrap_path`.
+/// If this changes, remember to change the documentation in the config.
+#[cfg(target_os = "linux")]
+const DEFAULT_BWRAP_PATH: &str = "/usr/bin/bwrap";
+
+/// Default value for `SandboxConfig::read_only_paths`.
+/// If this changes, remember to change the documentation in the config.
+#[cfg(target_os = "linux")]
+const DEFAULT_SANDBOX_READ_ONLY_PATHS: [&str; 6] =
+    ["/bin", "/etc", "/lib", "/lib64", "/sbin", "/usr"];
+
 /// Valid string reasons for a failure.
 /// Note: If these change, the documentation should be updated.
 #[allow(non_camel_case_types)]
@@ -108,13 +158,76 @@ struct SideChannelInfo {
     failure: Option<SideChannelFailureReason>,
 }
 
+/// Number of times the file of an input is put in the `FilesystemStore`
+/// before giving up, if it keeps getting evicted before it can be staged.
+const MAX_INPUT_STAGING_ATTEMPTS: usize = 3;
+
+/// Puts the file of `digest` in the `FilesystemStore` and returns its entry.
+pub(crate) async fn get_file_entry_for_staging(
+    cas_store: &FastSlowStore,
+    filesystem_store: Pin<&FilesystemStore>,
+    digest: &DigestInfo,
+) -> Result<Arc<FileEntryImpl>, Error> {
+    let mut attempt = 1;
+    loop {
+        cas_store
+            .populate_fast_store((*digest).into())
+            .await
+            .err_tip(|| "During populate_fast_store in download_to_directory")?;
+        match filesystem_store.get_file_entry_for_digest(digest).await {
+            Ok(file_entry) => return Ok(file_entry),
+            // Inputs of large actions may evict each other from a small
+            // store before they are staged.
+            Err(err) if err.code == Code::NotFound && attempt < MAX_INPUT_STAGING_ATTEMPTS => {
+                event!(
+                    Level::WARN,
+                    ?digest,
+                    attempt,
+                    "Input was evicted from the filesystem store before it was staged, retrying"
+                );
+                attempt += 1;
+            }
+            Err(err) => return Err(err).err_tip(|| "During hard link"),
+        }
+    }
+}
+
+/// Hard links `src` to `dest`, so staging an input does not copy it. Falls
+/// back to copying if that is not possible, like when the `FilesystemStore`
+/// is on another device than the work directory or the file has as many
+/// links as the filesystem allows.
+async fn link_or_copy_file(src: OsString, dest: &str) -> Result<(), Error> {
+    let Err(link_err) = fs::hard_link(&src, dest).await else {
+        return Ok(());
+    };
+    event!(
+        Level::DEBUG,
+        ?src,
+        ?dest,
+        ?link_err,
+        "Could not hard link input, copying it"
+    );
+    let dest = dest.to_string();
+    spawn_blocking!(pool: BlockingPoolKind::Filesystem, "download_to_directory_copy_file", move || {
+        std::fs::copy(&src, &dest).map_err(|copy_err| {
+            make_err!(
+                Code::Internal,
+                "Could not make hardlink, {link_err:?}, or copy, {copy_err:?} : {dest}"
+            )
+        })
+    })
+    .await
+    .err_tip(|| "Failed to launch spawn_blocking in link_or_copy_file")??;
+    Ok(())
+}
+
 /// Aggressively download the digests of files and make a local folder from it. This function
 /// will spawn unbounded number of futures to try and get these downloaded. The store itself
 /// should be rate limited if spawning too many requests at once is an issue.
 /// We require the `FilesystemStore` to be the `fast` store of `FastSlowStore`. This is for
 /// efficiency reasons. We will request the `FastSlowStore` to populate the entry then we will
 /// assume the `FilesystemStore` has the file available immediately after and hardlink the file
-/// to a new location.
+/// to a new location, or copy it if it cannot be linked.
 // Sadly we cannot use `async fn` here because the rust compiler cannot determine the auto traits
 // of the future. So we need to force this function to return a dynamic future instead.
 // see: https://github.com/rust-lang/rust/issues/78649
@@ -146,48 +259,44 @@ pub fn download_to_directory<'a>(
                 unix_mode = Some(unix_mode.unwrap_or(0o444) | 0o111);
             }
             futures.push(
-                cas_store
-                    .populate_fast_store(digest.into())
-                    .and_then(move |()| async move {
-                        let file_entry = filesystem_store
-                            .get_file_entry_for_digest(&digest)
-                            .await
-                            .err_tip(|| "During hard link")?;
-                        file_entry
-                            .get_file_path_locked(|src| fs::hard_link(src, &dest))
+                async move {
+                    let file_entry =
+                        get_file_entry_for_staging(cas_store, filesystem_store, &digest)
+                            .await?;
+                    // The entry keeps the file alive until it is staged,
+                    // even if it gets evicted meanwhile.
+                    file_entry
+                        .get_file_path_locked(|src| link_or_copy_file(src, &dest))
+                        .await?;
+                    #[cfg(target_family = "unix")]
+                    if let Some(unix_mode) = unix_mode {
+                        fs::set_permissions(&dest, Permissions::from_mode(unix_mode))
                             .await
-                            .map_err(|e| {
-                                make_err!(Code::Internal, "Could not make hardlink, {e:?} : {dest}")
-                            })?;
-                        #[cfg(target_family = "unix")]
-                        if let Some(unix_mode) = unix_mode {
-                            fs::set_permissions(&dest, Permissions::from_mode(unix_mode))
-                                .await
-                                .err_tip(|| {
-                                    format!(
-                                        "Could not set unix mode in download_to_directory {dest}"
-                                    )
-                                })?;
-                        }
-                        if let Some(mtime) = mtime {
-                            spawn_blocking!("download_to_directory_set_mtime", move || {
-                                set_file_mtime(
-                                    &dest,
-                                    FileTime::from_unix_time(mtime.seconds, mtime.nanos as u32),
+                            .err_tip(|| {
+                                format!(
+                                    "Could not set unix mode in download_to_directory {dest}"
                                 )
-                                .err_tip(|| {
-                                    format!("Failed to set mtime in download_to_directory {dest}")
-                                })
-                            })
-                            .await
+                            })?;
+                    }
+                    if let Some(mtime) = mtime {
+                        spawn_blocking!(pool: BlockingPoolKind::Filesystem, "download_to_directory_set_mtime", move || {
+                            set_file_mtime(
+                                &dest,
+                                FileTime::from_unix_time(mtime.seconds, mtime.nanos as u32),
+                            )
                             .err_tip(|| {
-                                "Failed to launch spawn_blocking in download_to_directory"
-                            })??;
-                        }
-                        Ok(())
-                    })
-                    .map_err(move |e| e.append(format!("for digest {digest}")))
-                    .boxed(),
+                                format!("Failed to set mtime in download_to_directory {dest}")
+                            })
+                        })
+                        .await
+                        .err_tip(|| {
+                            "Failed to launch spawn_blocking in download_to_directory"
+                        })??;
+                    }
+                    Result::<(), Error>::Ok(())
+                }
+                .map_err(move |e| e.append(format!("for digest {digest}")))
+                .boxed(),
             );
         }
 
@@ -253,47 +362,33 @@ fn is_executable(metadata: &std::fs::Metadata, _full_path: &impl AsRef<Path>) ->
     (metadata.mode() & 0o111) != 0
 }
 
-async fn upload_file(
-    cas_store: Pin<&impl StoreLike>,
+async fn acquire_upload_permit(upload_permits: &Semaphore) -> Result<SemaphorePermit<'_>, Error> {
+    upload_permits
+        .acquire()
+        .await
+        .map_err(|e| make_err!(Code::Internal, "Output upload semaphore closed {e:?}"))
+}
+
+/// Hashes an output file without uploading it.
+async fn hash_output_file(
     full_path: impl AsRef<Path> + Debug,
     hasher: DigestHasherFunc,
     metadata: std::fs::Metadata,
+    upload_permits: &Semaphore,
 ) -> Result<FileInfo, Error> {
+    let _permit = acquire_upload_permit(upload_permits).await?;
     let is_executable = is_executable(&metadata, &full_path);
     let file_size = metadata.len();
     let resumeable_file = fs::open_file(&full_path, u64::MAX)
         .await
         .err_tip(|| format!("Could not open file {full_path:?}"))?;
 
-    let (digest, mut resumeable_file) = hasher
+    let (digest, _) = hasher
         .hasher()
         .digest_for_file(resumeable_file, Some(file_size))
         .await
         .err_tip(|| format!("Failed to hash file in digest_for_file failed for {full_path:?}"))?;
 
-    resumeable_file
-        .as_reader()
-        .await
-        .err_tip(|| "Could not get reader from file slot in RunningActionsManager::upload_file()")?
-        .get_mut()
-        .rewind()
-        .await
-        .err_tip(|| "Could not rewind file")?;
-
-    // Note: For unknown reasons we appear to be hitting:
-    // https://github.com/rust-lang/rust/issues/92096
-    // or a smiliar issue if we try to use the non-store driver function, so we
-    // are using the store driver function here.
-    cas_store
-        .as_store_driver_pin()
-        .update_with_whole_file(
-            digest.into(),
-            resumeable_file,
-            UploadSizeInfo::ExactSize(digest.size_bytes()),
-        )
-        .await
-        .err_tip(|| format!("for {full_path:?}"))?;
-
     let name = full_path
         .as_ref()
         .file_name()
@@ -315,6 +410,51 @@ async fn upload_file(
     })
 }
 
+/// Uploads the hashed output files the CAS does not have yet. A single
+/// `has_many` call, which is a `FindMissingBlobs` request for remote
+/// stores, finds the missing ones.
+async fn upload_missing_files(
+    cas_store: Pin<&impl StoreLike>,
+    files: &[(PathBuf, DigestInfo)],
+    upload_permits: &Semaphore,
+) -> Result<(), Error> {
+    if files.is_empty() {
+        return Ok(());
+    }
+    let keys: Vec<StoreKey<'_>> = files.iter().map(|(_, digest)| (*digest).into()).collect();
+    let sizes = cas_store
+        .has_many(&keys)
+        .await
+        .err_tip(|| "While checking which outputs are missing from the CAS")?;
+    files
+        .iter()
+        .zip(sizes)
+        .filter(|(_, size)| size.is_none())
+        .map(|((full_path, digest), _)| async move {
+            let _permit = acquire_upload_permit(upload_permits).await?;
+            let resumeable_file = fs::open_file(full_path, u64::MAX)
+                .await
+                .err_tip(|| format!("Could not open file {full_path:?}"))?;
+            // Note: For unknown reasons we appear to be hitting:
+            // https://github.com/rust-lang/rust/issues/92096
+            // or a smiliar issue if we try to use the non-store driver function, so we
+            // are using the store driver function here.
+            cas_store
+                .as_store_driver_pin()
+                .update_with_whole_file(
+                    (*digest).into(),
+                    resumeable_file,
+                    UploadSizeInfo::ExactSize(digest.size_bytes()),
+                )
+                .await
+                .err_tip(|| format!("for {full_path:?}"))?;
+            Result::<(), Error>::Ok(())
+        })
+        .collect::<FuturesUnordered<_>>()
+        .try_collect()
+        .await
+}
+
 async fn upload_symlink(
     full_path: impl AsRef<Path> + Debug,
     full_work_directory_path: impl AsRef<Path>,
@@ -366,11 +506,15 @@ async fn upload_symlink(
     })
 }
 
+/// Uploads an output directory. The `Directory` of each subdirectory is
+/// built as soon as its entries are done, while files of other directories
+/// are still being hashed and uploaded.
 fn upload_directory<'a, P: AsRef<Path> + Debug + Send + Sync + Clone + 'a>(
     cas_store: Pin<&'a impl StoreLike>,
     full_dir_path: P,
     full_work_directory: &'a str,
     hasher: DigestHasherFunc,
+    upload_permits: &'a Semaphore,
 ) -> BoxFuture<'a, Result<(Directory, VecDeque<ProtoDirectory>), Error>> {
     Box::pin(async move {
         let file_futures = FuturesUnordered::new();
@@ -396,49 +540,52 @@ fn upload_directory<'a, P: AsRef<Path> + Debug + Send + Sync + Clone + 'a>(
                 if file_type.is_dir() {
                     let full_dir_path = full_dir_path.clone();
                     dir_futures.push(
-                        upload_directory(cas_store, full_path.clone(), full_work_directory, hasher)
-                            .and_then(|(dir, all_dirs)| async move {
-                                let directory_name = full_path
-                                    .file_name()
-                                    .err_tip(|| {
-                                        format!("Expected file_name to exist on {full_dir_path:?}")
-                                    })?
-                                    .to_str()
-                                    .err_tip(|| {
-                                        make_err!(
-                                            Code::Internal,
-                                            "Could not convert {:?} to string",
-                                            full_dir_path
-                                        )
-                                    })?
-                                    .to_string();
-
-                                let digest = serialize_and_upload_message(
-                                    &dir,
-                                    cas_store,
-                                    &mut hasher.hasher(),
-                                )
-                                .await
-                                .err_tip(|| format!("for {full_path:?}"))?;
-
-                                Result::<(DirectoryNode, VecDeque<Directory>), Error>::Ok((
-                                    DirectoryNode {
-                                        name: directory_name,
-                                        digest: Some(digest.into()),
-                                    },
-                                    all_dirs,
-                                ))
-                            })
-                            .boxed(),
+                        upload_directory(
+                            cas_store,
+                            full_path.clone(),
+                            full_work_directory,
+                            hasher,
+                            upload_permits,
+                        )
+                        .and_then(|(dir, all_dirs)| async move {
+                            let directory_name = full_path
+                                .file_name()
+                                .err_tip(|| {
+                                    format!("Expected file_name to exist on {full_dir_path:?}")
+                                })?
+                                .to_str()
+                                .err_tip(|| {
+                                    make_err!(
+                                        Code::Internal,
+                                        "Could not convert {:?} to string",
+                                        full_dir_path
+                                    )
+                                })?
+                                .to_string();
+
+                            let digest =
+                                serialize_and_upload_message(&dir, cas_store, &mut hasher.hasher())
+                                    .await
+                                    .err_tip(|| format!("for {full_path:?}"))?;
+
+                            Result::<(DirectoryNode, VecDeque<Directory>), Error>::Ok((
+                                DirectoryNode {
+                                    name: directory_name,
+                                    digest: Some(digest.into()),
+                                },
+                                all_dirs,
+                            ))
+                        })
+                        .boxed(),
                     );
                 } else if file_type.is_file() {
                     file_futures.push(async move {
                         let metadata = fs::metadata(&full_path)
                             .await
                             .err_tip(|| format!("Could not open file {full_path:?}"))?;
-                        upload_file(cas_store, &full_path, hasher, metadata)
-                            .map_ok(Into::into)
-                            .await
+                        let file_info =
+                            hash_output_file(&full_path, hasher, metadata, upload_permits).await?;
+                        Result::<(PathBuf, FileInfo), Error>::Ok((full_path, file_info))
                     });
                 } else if file_type.is_symlink() {
                     symlink_futures
@@ -447,8 +594,26 @@ fn upload_directory<'a, P: AsRef<Path> + Debug + Send + Sync + Clone + 'a>(
             }
         }
 
+        // The files of this directory are uploaded while its subdirectories
+        // are still being processed.
+        let upload_files_fut = async {
+            let files = file_futures
+                .try_collect::<Vec<(PathBuf, FileInfo)>>()
+                .await?;
+            let to_upload: Vec<(PathBuf, DigestInfo)> = files
+                .iter()
+                .map(|(full_path, file_info)| (full_path.clone(), file_info.digest))
+                .collect();
+            upload_missing_files(cas_store, &to_upload, upload_permits).await?;
+            Result::<Vec<FileNode>, Error>::Ok(
+                files
+                    .into_iter()
+                    .map(|(_, file_info)| FileNode::from(file_info))
+                    .collect(),
+            )
+        };
         let (mut file_nodes, dir_entries, mut symlinks) = try_join3(
-            file_futures.try_collect::<Vec<FileNode>>(),
+            upload_files_fut,
             dir_futures.try_collect::<Vec<(DirectoryNode, VecDeque<Directory>)>>(),
             symlink_futures.try_collect::<Vec<SymlinkNode>>(),
         )
@@ -607,6 +772,9 @@ struct RunningActionImplState {
     // that prevented the action from running, upload failures, timeouts, exc...
     // but we have (or could have) the action results (like stderr/stdout).
     error: Option<Error>,
+    // Set while the input root is mounted at the work directory.
+    #[cfg(target_os = "linux")]
+    lazy_inputs: Option<LazyInputs>,
 }
 
 pub struct RunningActionImpl {
@@ -646,6 +814,8 @@ impl RunningActionImpl {
                 action_result: None,
                 execution_metadata,
                 error: None,
+                #[cfg(target_os = "linux")]
+                lazy_inputs: None,
             }),
             did_cleanup: AtomicBool::new(false),
         }
@@ -681,6 +851,25 @@ impl RunningActionImpl {
             let filesystem_store_pin =
                 Pin::new(self.running_actions_manager.filesystem_store.as_ref());
             let (command, ()) = try_join(command_fut, async {
+                #[cfg(target_os = "linux")]
+                if self
+                    .running_actions_manager
+                    .execution_configuration
+                    .input_provider
+                    == InputProvider::fuse
+                {
+                    let lazy_inputs = LazyInputs::mount(
+                        self.running_actions_manager.cas_store.clone(),
+                        self.running_actions_manager.filesystem_store.clone(),
+                        self.action_info.input_root_digest,
+                        &self.action_directory,
+                        &self.work_directory,
+                    )
+                    .await
+                    .err_tip(|| "While mounting lazy inputs")?;
+                    self.state.lock().lazy_inputs = Some(lazy_inputs);
+                    return Ok(());
+                }
                 fs::create_dir(&self.work_directory)
                     .await
                     .err_tip(|| format!("Error creating work directory {}", self.work_directory))?;
@@ -777,20 +966,6 @@ impl RunningActionImpl {
         } else {
             command_proto.arguments.iter().map(AsRef::as_ref).collect()
         };
-        event!(Level::INFO, ?args, "Executing command",);
-        let mut command_builder = process::Command::new(args[0]);
-        command_builder
-            .args(&args[1..])
-            .kill_on_drop(true)
-            .stdin(Stdio::null())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .current_dir(format!(
-                "{}/{}",
-                self.work_directory, command_proto.working_directory
-            ))
-            .env_clear();
-
         let requested_timeout = if self.action_info.timeout.is_zero() {
             self.running_actions_manager.max_action_timeout
         } else {
@@ -798,6 +973,7 @@ impl RunningActionImpl {
         };
 
         let mut maybe_side_channel_file: Option<Cow<'_, OsStr>> = None;
+        let mut environment: Vec<(&str, Cow<'_, str>)> = Vec::new();
         if let Some(additional_environment) = &self
             .running_actions_manager
             .execution_configuration
@@ -824,7 +1000,7 @@ impl RunningActionImpl {
                         Cow::Borrowed(self.action_directory.as_str())
                     }
                 };
-                command_builder.env(name, value.as_ref());
+                environment.push((name, value));
             }
         }
 
@@ -854,9 +1030,180 @@ impl RunningActionImpl {
             envs
         };
         for environment_variable in envs {
-            command_builder.env(&environment_variable.name, &environment_variable.value);
+            environment.push((
+                &environment_variable.name,
+                Cow::Borrowed(&environment_variable.value),
+            ));
+        }
+
+        let current_directory = format!(
+            "{}/{}",
+            self.work_directory, command_proto.working_directory
+        );
+        let maybe_worker_key = self
+            .running_actions_manager
+            .persistent_workers
+            .as_ref()
+            .and_then(|persistent_workers| {
+                persistent_workers.worker_key(
+                    &self.action_info.platform_properties,
+                    &args,
+                    &command_proto.working_directory,
+                    &environment,
+                )
+            });
+        if let Some((worker_key, flagfiles)) = maybe_worker_key {
+            return self
+                .execute_in_persistent_worker(
+                    worker_key,
+                    &flagfiles,
+                    &current_directory,
+                    command_proto,
+                    kill_channel_rx,
+                )
+                .await;
+        }
+        #[cfg(target_os = "linux")]
+        let sandbox_args;
+        #[cfg(target_os = "linux")]
+        let args =
+            if let Some(sandbox) = &self.running_actions_manager.execution_configuration.sandbox {
+                let allow_network = allows_network(
+                    &sandbox.allow_network_properties,
+                    &self.action_info.platform_properties,
+                );
+                if allow_network {
+                    event!(Level::INFO, "Action is allowed to use the network");
+                }
+                // The directories the outputs are written to were created
+                // while preparing the action.
+                let output_directories: BTreeSet<String> = command_proto
+                    .output_files
+                    .iter()
+                    .chain(&command_proto.output_directories)
+                    .chain(&command_proto.output_paths)
+                    .filter_map(|output_path| {
+                        Path::new(&format!("{current_directory}/{output_path}"))
+                            .parent()
+                            .map(|parent| parent.to_string_lossy().into_owned())
+                    })
+                    .collect();
+                sandbox_args = make_sandbox_args(
+                    sandbox,
+                    allow_network,
+                    &self.action_directory,
+                    &self.work_directory,
+                    &current_directory,
+                    &output_directories,
+                );
+                sandbox_args.iter().map(AsRef::as_ref).chain(args).collect()
+            } else {
+                args
+            };
+        let maybe_container_image = match (
+            &self.running_actions_manager.container_images,
+            self.action_info
+                .platform_properties
+                .get(CONTAINER_IMAGE_PROPERTY),
+        ) {
+            (Some(container_images), Some(image)) => Some((
+                container_images,
+                container_images
+                    .get_image_id(image)
+                    .await
+                    .err_tip(|| "In RunningActionImpl::execute")?,
+            )),
+            _ => None,
+        };
+        // Named, so it can be removed if the action is killed.
+        let maybe_container_name = maybe_container_image
+            .as_ref()
+            .map(|_| format!("nativelink-{}", Uuid::new_v4().simple()));
+        let container_args;
+        let args = if let (Some((container_images, image_id)), Some(container_name)) =
+            (&maybe_container_image, &maybe_container_name)
+        {
+            let allow_network = self
+                .running_actions_manager
+                .execution_configuration
+                .network_isolation
+                .as_ref()
+                .is_none_or(|network_isolation| {
+                    allows_network(
+                        &network_isolation.allow_network_properties,
+                        &self.action_info.platform_properties,
+                    )
+                });
+            container_args = make_container_args(
+                &container_images.podman_path,
+                image_id,
+                container_name,
+                allow_network,
+                &self.action_directory,
+                &current_directory,
+                &environment,
+            );
+            container_args
+                .iter()
+                .map(AsRef::as_ref)
+                .chain(args)
+                .collect()
+        } else {
+            args
+        };
+        event!(Level::INFO, ?args, "Executing command",);
+        let mut command_builder = process::Command::new(args[0]);
+        command_builder
+            .args(&args[1..])
+            .kill_on_drop(true)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .current_dir(&current_directory);
+        // The environment of the action is passed to the container in the
+        // arguments, podman itself runs in the environment of the worker.
+        if maybe_container_name.is_none() {
+            command_builder.env_clear().envs(
+                environment
+                    .iter()
+                    .map(|(name, value)| (name, value.as_ref())),
+            );
+        }
+
+        #[cfg(target_os = "linux")]
+        if let Some(network_isolation) = &self
+            .running_actions_manager
+            .execution_configuration
+            .network_isolation
+        {
+            if allows_network(
+                &network_isolation.allow_network_properties,
+                &self.action_info.platform_properties,
+            ) {
+                event!(Level::INFO, "Action is allowed to use the network");
+            } else if maybe_container_name.is_none() {
+                isolate_network(&mut command_builder);
+            }
         }
 
+        #[cfg(target_os = "linux")]
+        let maybe_cgroup = if let Some(cgroup_config) =
+            &self.running_actions_manager.execution_configuration.cgroup
+        {
+            let cgroup = ActionCgroup::new(
+                cgroup_config,
+                &self.operation_id,
+                &self.action_info.platform_properties,
+            )
+            .err_tip(|| "In RunningActionImpl::execute")?;
+            cgroup
+                .attach(&mut command_builder)
+                .err_tip(|| "In RunningActionImpl::execute")?;
+            Some(cgroup)
+        } else {
+            None
+        };
+
         let mut child_process = command_builder
             .spawn()
             .err_tip(|| format!("Could not execute command {args:?}"))?;
@@ -941,6 +1288,11 @@ impl RunningActionImpl {
                     // If we get killed before the stream is started, then these will lock up.
                     // TODO(allada) There is a significant bug here. If we kill the action and the action creates
                     // child processes, it can create zombies. See: https://github.com/tracemachina/nativelink/issues/225
+                    if let (true, Some((container_images, _)), Some(container_name)) =
+                        (killed_action, &maybe_container_image, maybe_container_name)
+                    {
+                        container_images.remove_container(container_name);
+                    }
                     let (stdout, stderr) = if killed_action {
                         drop(timer);
                         (Bytes::new(), Bytes::new())
@@ -952,6 +1304,13 @@ impl RunningActionImpl {
                             maybe_all_stderr.err_tip(|| "Internal error reading from stderr of worker task")??
                         )
                     };
+                    #[cfg(target_os = "linux")]
+                    let (maybe_resource_usage, maybe_out_of_memory_error) = maybe_cgroup
+                        .as_ref()
+                        .map_or((None, None), |cgroup| {
+                            let (resource_usage, maybe_out_of_memory_error) = cgroup.usage();
+                            (Some(resource_usage), maybe_out_of_memory_error)
+                        });
                     let exit_code = if let Some(exit_code) = exit_status.code() {
                         if exit_code == 0 {
                             self.metrics().child_process_success_error_code.inc();
@@ -972,6 +1331,11 @@ impl RunningActionImpl {
                     {
                         let mut state = self.state.lock();
                         state.error = Error::merge_option(state.error.take(), maybe_error_override);
+                        #[cfg(target_os = "linux")]
+                        {
+                            state.error = Error::merge_option(state.error.take(), maybe_out_of_memory_error);
+                            state.execution_metadata.resource_usage = maybe_resource_usage;
+                        }
 
                         state.command_proto = Some(command_proto);
                         state.execution_result = Some(RunningActionImplExecutionResult{
@@ -1015,10 +1379,103 @@ impl RunningActionImpl {
         // Unreachable.
     }
 
+    /// Runs the action in a persistent worker process, which is kept for
+    /// later actions with the same worker key.
+    async fn execute_in_persistent_worker(
+        self: Arc<Self>,
+        worker_key: WorkerKey,
+        flagfiles: &[OsString],
+        current_directory: &str,
+        command_proto: ProtoCommand,
+        kill_channel_rx: Fuse<oneshot::Receiver<()>>,
+    ) -> Result<Arc<Self>, Error> {
+        let persistent_workers = self
+            .running_actions_manager
+            .persistent_workers
+            .as_ref()
+            .err_tip(|| "Expected persistent_workers to be configured")?;
+        let work_request = make_work_request(
+            self.running_actions_manager.cas_store.as_ref(),
+            self.action_info.input_root_digest,
+            current_directory,
+            flagfiles,
+        )
+        .await
+        .err_tip(|| "In RunningActionImpl::execute_in_persistent_worker")?;
+        let command = worker_key
+            .arguments()
+            .join(OsStr::new(" "))
+            .to_string_lossy()
+            .into_owned();
+        event!(
+            Level::INFO,
+            ?worker_key,
+            "Executing command in persistent worker"
+        );
+
+        let timer = self.metrics().child_process.begin_timer();
+        let sleep_fut = (self.running_actions_manager.callbacks.sleep_fn)(self.timeout);
+        let cancelled = async {
+            let error = tokio::select! {
+                () = sleep_fut => {
+                    self.running_actions_manager.metrics.task_timeouts.inc();
+                    Error::new(
+                        Code::DeadlineExceeded,
+                        format!(
+                            "Command '{command}' timed out after {} seconds",
+                            self.action_info.timeout.as_secs_f32()
+                        ),
+                    )
+                },
+                _ = kill_channel_rx => Error::new(
+                    Code::Aborted,
+                    format!("Command '{command}' was killed by scheduler"),
+                ),
+            };
+            let mut state = self.state.lock();
+            state.error = Error::merge_option(state.error.take(), Some(error));
+        };
+        let maybe_work_response = persistent_workers
+            .execute(worker_key, &self.work_directory, &work_request, cancelled)
+            .await
+            .err_tip(|| "In RunningActionImpl::execute_in_persistent_worker")?;
+        let execution_result = if let Some(work_response) = maybe_work_response {
+            timer.measure();
+            if work_response.exit_code == 0 {
+                self.metrics().child_process_success_error_code.inc();
+            } else {
+                self.metrics().child_process_failure_error_code.inc();
+            }
+            // Bazel shows the output of a worker like the stderr of an
+            // action.
+            RunningActionImplExecutionResult {
+                stdout: Bytes::new(),
+                stderr: work_response.output.into(),
+                exit_code: work_response.exit_code,
+            }
+        } else {
+            drop(timer);
+            RunningActionImplExecutionResult {
+                stdout: Bytes::new(),
+                stderr: Bytes::new(),
+                exit_code: EXIT_CODE_FOR_SIGNAL,
+            }
+        };
+        {
+            let mut state = self.state.lock();
+            state.command_proto = Some(command_proto);
+            state.execution_result = Some(execution_result);
+            state.execution_metadata.execution_completed_timestamp =
+                (self.running_actions_manager.callbacks.now_fn)();
+        }
+        Ok(self)
+    }
+
     async fn inner_upload_results(self: Arc<Self>) -> Result<Arc<Self>, Error> {
         enum OutputType {
             None,
-            File(FileInfo),
+            // Hashed, but not yet uploaded.
+            File(PathBuf, FileInfo),
             Directory(DirectoryInfo),
             FileSymlink(SymlinkInfo),
             DirectorySymlink(SymlinkInfo),
@@ -1043,6 +1500,7 @@ impl RunningActionImpl {
         };
         let cas_store = self.running_actions_manager.cas_store.as_ref();
         let hasher = self.action_info.unique_qualifier.digest_function();
+        let upload_permits = &self.running_actions_manager.output_upload_permits;
 
         let mut output_path_futures = FuturesUnordered::new();
         let mut output_paths = command_proto.output_paths;
@@ -1052,15 +1510,37 @@ impl RunningActionImpl {
             output_paths.append(&mut command_proto.output_files);
             output_paths.append(&mut command_proto.output_directories);
         }
-        for entry in output_paths {
-            let full_path = OsString::from(if command_proto.working_directory.is_empty() {
-                format!("{}/{}", self.work_directory, entry)
-            } else {
-                format!(
-                    "{}/{}/{}",
-                    self.work_directory, command_proto.working_directory, entry
-                )
-            });
+        let full_output_paths: Vec<(String, String)> = output_paths
+            .into_iter()
+            .map(|entry| {
+                let full_path = if command_proto.working_directory.is_empty() {
+                    format!("{}/{}", self.work_directory, entry)
+                } else {
+                    format!(
+                        "{}/{}/{}",
+                        self.work_directory, command_proto.working_directory, entry
+                    )
+                };
+                (entry, full_path)
+            })
+            .collect();
+        #[cfg(target_os = "linux")]
+        {
+            let maybe_lazy_inputs = self.state.lock().lazy_inputs.take();
+            if let Some(lazy_inputs) = maybe_lazy_inputs {
+                lazy_inputs
+                    .finish(
+                        full_output_paths
+                            .iter()
+                            .map(|(_, full_path)| full_path.clone())
+                            .collect(),
+                    )
+                    .await
+                    .err_tip(|| "While unmounting lazy inputs")?;
+            }
+        }
+        for (entry, full_path) in full_output_paths {
+            let full_path = OsString::from(full_path);
             let work_directory = &self.work_directory;
             output_path_futures.push(async move {
                 let metadata = {
@@ -1077,40 +1557,46 @@ impl RunningActionImpl {
                     };
 
                     if metadata.is_file() {
-                        return Ok(OutputType::File(
-                            upload_file(cas_store.as_pin(), &full_path, hasher, metadata)
+                        let file_info =
+                            hash_output_file(&full_path, hasher, metadata, upload_permits)
                                 .await
                                 .map(|mut file_info| {
                                     file_info.name_or_path = NameOrPath::Path(entry);
                                     file_info
                                 })
-                                .err_tip(|| format!("Uploading file {full_path:?}"))?,
-                        ));
+                                .err_tip(|| format!("Hashing file {full_path:?}"))?;
+                        return Ok(OutputType::File(full_path.into(), file_info));
                     }
                     metadata
                 };
                 if metadata.is_dir() {
                     Ok(OutputType::Directory(
-                        upload_directory(cas_store.as_pin(), &full_path, work_directory, hasher)
-                            .and_then(|(root_dir, children)| async move {
-                                let tree = ProtoTree {
-                                    root: Some(root_dir),
-                                    children: children.into(),
-                                };
-                                let tree_digest = serialize_and_upload_message(
-                                    &tree,
-                                    cas_store.as_pin(),
-                                    &mut hasher.hasher(),
-                                )
-                                .await
-                                .err_tip(|| format!("While processing {entry}"))?;
-                                Ok(DirectoryInfo {
-                                    path: entry,
-                                    tree_digest,
-                                })
-                            })
+                        upload_directory(
+                            cas_store.as_pin(),
+                            &full_path,
+                            work_directory,
+                            hasher,
+                            upload_permits,
+                        )
+                        .and_then(|(root_dir, children)| async move {
+                            let tree = ProtoTree {
+                                root: Some(root_dir),
+                                children: children.into(),
+                            };
+                            let tree_digest = serialize_and_upload_message(
+                                &tree,
+                                cas_store.as_pin(),
+                                &mut hasher.hasher(),
+                            )
                             .await
-                            .err_tip(|| format!("Uploading directory {full_path:?}"))?,
+                            .err_tip(|| format!("While processing {entry}"))?;
+                            Ok(DirectoryInfo {
+                                path: entry,
+                                tree_digest,
+                            })
+                        })
+                        .await
+                        .err_tip(|| format!("Uploading directory {full_path:?}"))?,
                     ))
                 } else if metadata.is_symlink() {
                     let output_symlink = upload_symlink(&full_path, work_directory)
@@ -1189,9 +1675,13 @@ impl RunningActionImpl {
         });
 
         let upload_result = futures::try_join!(stdout_digest_fut, stderr_digest_fut, async {
+            let mut files_to_upload = vec![];
             while let Some(output_type) = output_path_futures.try_next().await? {
                 match output_type {
-                    OutputType::File(output_file) => output_files.push(output_file),
+                    OutputType::File(full_path, output_file) => {
+                        files_to_upload.push((full_path, output_file.digest));
+                        output_files.push(output_file);
+                    }
                     OutputType::Directory(output_folder) => output_folders.push(output_folder),
                     OutputType::FileSymlink(output_symlink) => {
                         output_file_symlinks.push(output_symlink);
@@ -1202,7 +1692,9 @@ impl RunningActionImpl {
                     OutputType::None => { /* Safe to ignore */ }
                 }
             }
-            Ok(())
+            upload_missing_files(cas_store.as_pin(), &files_to_upload, upload_permits)
+                .await
+                .err_tip(|| "Uploading output files")
         });
         drop(output_path_futures);
         let (stdout_digest, stderr_digest) = match upload_result {
@@ -1257,6 +1749,8 @@ impl Drop for RunningActionImpl {
             ?operation_id,
             "RunningActionImpl did not cleanup. This is a violation of the requirements, will attempt to do it in the background."
         );
+        #[cfg(target_os = "linux")]
+        drop(self.state.get_mut().lazy_inputs.take());
         let running_actions_manager = self.running_actions_manager.clone();
         let action_directory = self.action_directory.clone();
         background_spawn!("running_action_impl_drop", async move {
@@ -1310,6 +1804,10 @@ impl RunningAction for RunningActionImpl {
             .clone()
             .cleanup
             .wrap(async move {
+                // The input root must be unmounted before the action
+                // directory can be removed.
+                #[cfg(target_os = "linux")]
+                drop(self.state.lock().lazy_inputs.take());
                 let result = do_cleanup(
                     &self.running_actions_manager,
                     &self.operation_id,
@@ -1392,6 +1890,432 @@ pub struct ExecutionConfiguration {
     /// executes other than those in the `ActionInfo`.  On Windows, `SystemRoot`
     /// and PATH are also assigned (see `inner_execute`).
     pub additional_environment: Option<HashMap<String, EnvironmentSource>>,
+    /// If set, actions run without network access unless their platform
+    /// properties allow it (Linux only).
+    pub network_isolation: Option<NetworkIsolationConfig>,
+    /// If set, actions run in a bubblewrap sandbox (Linux only).
+    pub sandbox: Option<SandboxConfig>,
+    /// If set, actions with the `container-image` platform property run in
+    /// a container of the image.
+    pub container: Option<ContainerConfig>,
+    /// If set, each action runs in its own cgroup (Linux only).
+    pub cgroup: Option<CgroupConfig>,
+    /// How the input root of an action is made available to it.
+    pub input_provider: InputProvider,
+    /// Maximum number of output files hashed or uploaded at the same time,
+    /// over all actions. 0 means the default.
+    pub max_concurrent_output_uploads: usize,
+    /// If set, actions with a persistent worker key run in worker processes
+    /// that are kept between actions.
+    pub persistent_workers: Option<PersistentWorkerConfig>,
+}
+
+/// Returns true if the platform properties of an action give it network
+/// access under `allow_network_properties`.
+fn allows_network(
+    allow_network_properties: &HashMap<String, Vec<String>>,
+    platform_properties: &HashMap<String, String>,
+) -> bool {
+    allow_network_properties
+        .iter()
+        .any(|(name, allowed_values)| {
+            platform_properties
+                .get(name)
+                .is_some_and(|value| allowed_values.contains(value))
+        })
+}
+
+/// Makes `command_builder` start the process in a new network namespace.
+/// The namespace has no interfaces besides a loopback interface that is
+/// down, so the process can not reach any host, not even this one.
+#[cfg(target_os = "linux")]
+fn isolate_network(command_builder: &mut process::Command) {
+    fn write_proc_file(path: &std::ffi::CStr, contents: &[u8]) -> std::io::Result<()> {
+        // SAFETY: `path` is a valid C string and `contents` a valid buffer.
+        unsafe {
+            let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+            if fd < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let written = libc::write(fd, contents.as_ptr().cast(), contents.len());
+            let result = if written < 0 {
+                Err(std::io::Error::last_os_error())
+            } else {
+                Ok(())
+            };
+            libc::close(fd);
+            result
+        }
+    }
+
+    // Without CAP_SYS_ADMIN a network namespace can only be created along
+    // with a new user namespace. The user and group of the worker are
+    // mapped to themselves in it, so the action still sees them.
+    // SAFETY: getuid and getgid always succeed.
+    let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+    let uid_map = format!("{uid} {uid} 1");
+    let gid_map = format!("{gid} {gid} 1");
+    // SAFETY: The closure runs in the child between fork and exec, where
+    // only async-signal-safe functions may be called. It only makes
+    // syscalls and does not allocate.
+    unsafe {
+        command_builder.pre_exec(move || {
+            if libc::unshare(libc::CLONE_NEWNET) == 0 {
+                return Ok(());
+            }
+            if libc::unshare(libc::CLONE_NEWUSER | libc::CLONE_NEWNET) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            write_proc_file(c"/proc/self/setgroups", b"deny")?;
+            write_proc_file(c"/proc/self/uid_map", uid_map.as_bytes())?;
+            write_proc_file(c"/proc/self/gid_map", gid_map.as_bytes())
+        });
+    }
+}
+
+/// Returns the bubblewrap command line that runs a command in a sandbox.
+/// The arguments of the command follow it. Later mounts are put on top of
+/// earlier ones, so the action directory is writable, the inputs in it are
+/// not, and the directories of the outputs are writable again.
+#[cfg(target_os = "linux")]
+fn make_sandbox_args(
+    sandbox: &SandboxConfig,
+    allow_network: bool,
+    action_directory: &str,
+    work_directory: &str,
+    current_directory: &str,
+    output_directories: &BTreeSet<String>,
+) -> Vec<OsString> {
+    let bwrap_path = if sandbox.bwrap_path.is_empty() {
+        DEFAULT_BWRAP_PATH
+    } else {
+        sandbox.bwrap_path.as_str()
+    };
+    let mut args: Vec<OsString> = vec![
+        bwrap_path.into(),
+        "--unshare-all".into(),
+        "--die-with-parent".into(),
+    ];
+    if allow_network {
+        args.push("--share-net".into());
+    }
+    let mut push_args = |new_args: &[&str]| args.extend(new_args.iter().map(OsString::from));
+    push_args(&["--proc", "/proc", "--dev", "/dev", "--tmpfs", "/tmp"]);
+    if sandbox.read_only_paths.is_empty() {
+        for path in DEFAULT_SANDBOX_READ_ONLY_PATHS {
+            push_args(&["--ro-bind-try", path, path]);
+        }
+    } else {
+        for path in &sandbox.read_only_paths {
+            push_args(&["--ro-bind-try", path, path]);
+        }
+    }
+    push_args(&["--bind", action_directory, action_directory]);
+    push_args(&["--ro-bind", work_directory, work_directory]);
+    // Parents sort before their children, so they are mounted first.
+    for output_directory in output_directories {
+        push_args(&["--bind-try", output_directory, output_directory]);
+    }
+    push_args(&["--chdir", current_directory, "--"]);
+    args
+}
+
+/// The cgroup an action runs in. It is removed when dropped, along with
+/// any processes the action left behind.
+#[cfg(target_os = "linux")]
+struct ActionCgroup {
+    path: String,
+    memory_limit_bytes: u64,
+}
+
+// Files of the cgroup filesystem only live in memory, so they are read and
+// written without going through the blocking pool.
+#[cfg(target_os = "linux")]
+impl ActionCgroup {
+    /// Creates the cgroup of an action, limited by its platform properties
+    /// or the defaults of the config.
+    fn new(
+        config: &CgroupConfig,
+        operation_id: &OperationId,
+        platform_properties: &HashMap<String, String>,
+    ) -> Result<Self, Error> {
+        let get_limit = |property: &str, default_limit: u64| -> Result<u64, Error> {
+            platform_properties
+                .get(property)
+                .map_or(Ok(default_limit), |value| {
+                    value.parse().map_err(|e| {
+                        make_input_err!(
+                            "Could not parse platform property {property} with value '{value}' - {e:?}"
+                        )
+                    })
+                })
+        };
+        let cpu_count = get_limit(CPU_COUNT_PROPERTY, config.default_cpu_count)?;
+        let memory_limit_bytes =
+            get_limit(MEMORY_KB_PROPERTY, config.default_memory_kb)?.saturating_mul(1024);
+        let max_pids = get_limit(MAX_PIDS_PROPERTY, config.default_max_pids)?;
+
+        let parent_path = if config.path.is_empty() {
+            DEFAULT_CGROUP_PATH
+        } else {
+            config.path.as_str()
+        };
+        let path = format!("{parent_path}/{operation_id}");
+        std::fs::create_dir_all(&path)
+            .map_err(|e| make_err!(Code::Internal, "Could not create cgroup {path} - {e:?}"))?;
+        let cgroup = Self {
+            path,
+            memory_limit_bytes,
+        };
+        // If the action runs out of memory, all its processes are killed,
+        // not only the largest one.
+        cgroup.write("memory.oom.group", "1")?;
+        if cpu_count > 0 {
+            cgroup.write(
+                "cpu.max",
+                &format!(
+                    "{} {CGROUP_CPU_PERIOD_US}",
+                    cpu_count.saturating_mul(CGROUP_CPU_PERIOD_US)
+                ),
+            )?;
+        }
+        if memory_limit_bytes > 0 {
+            cgroup.write("memory.max", &memory_limit_bytes.to_string())?;
+        }
+        if max_pids > 0 {
+            cgroup.write("pids.max", &max_pids.to_string())?;
+        }
+        Ok(cgroup)
+    }
+
+    fn write(&self, file: &str, contents: &str) -> Result<(), Error> {
+        std::fs::write(format!("{}/{file}", self.path), contents).map_err(|e| {
+            make_err!(
+                Code::Internal,
+                "Could not write '{contents}' to {file} of cgroup {} - {e:?}",
+                self.path
+            )
+        })
+    }
+
+    /// Makes the command start in the cgroup.
+    fn attach(&self, command_builder: &mut process::Command) -> Result<(), Error> {
+        let procs_file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(format!("{}/cgroup.procs", self.path))
+            .map_err(|e| {
+                make_err!(
+                    Code::Internal,
+                    "Could not open cgroup.procs of cgroup {} - {e:?}",
+                    self.path
+                )
+            })?;
+        // SAFETY: The closure runs in the child between fork and exec, where
+        // only async-signal-safe functions may be called. It only makes a
+        // syscall and does not allocate.
+        unsafe {
+            command_builder.pre_exec(move || {
+                // Writing 0 moves the writing process.
+                let written = libc::write(procs_file.as_raw_fd(), b"0".as_ptr().cast(), 1);
+                if written < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns the resources the action used and an error if it was killed
+    /// because it ran out of memory. Values the kernel does not report are
+    /// zero.
+    fn usage(&self) -> (ResourceUsage, Option<Error>) {
+        let read_value = |file: &str, key: Option<&str>| -> u64 {
+            let Ok(contents) = std::fs::read_to_string(format!("{}/{file}", self.path)) else {
+                return 0;
+            };
+            let maybe_value = match key {
+                Some(key) => contents.lines().find_map(|line| {
+                    line.split_once(' ')
+                        .filter(|(name, _)| *name == key)
+                        .map(|(_, value)| value)
+                }),
+                None => Some(contents.as_str()),
+            };
+            maybe_value
+                .and_then(|value| value.trim().parse().ok())
+                .unwrap_or_default()
+        };
+        let resource_usage = ResourceUsage {
+            peak_memory_bytes: read_value("memory.peak", None),
+            cpu_time: Duration::from_micros(read_value("cpu.stat", Some("usage_usec"))),
+            peak_pids: read_value("pids.peak", None),
+        };
+        let maybe_out_of_memory_error = if read_value("memory.events", Some("oom_kill")) == 0 {
+            None
+        } else if self.memory_limit_bytes == 0 {
+            Some(make_err!(
+                Code::ResourceExhausted,
+                "Action ran out of memory and was killed"
+            ))
+        } else {
+            Some(make_err!(
+                Code::ResourceExhausted,
+                "Action used more than its memory limit of {} bytes and was killed",
+                self.memory_limit_bytes
+            ))
+        };
+        (resource_usage, maybe_out_of_memory_error)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for ActionCgroup {
+    fn drop(&mut self) {
+        const MAX_REMOVE_ATTEMPTS: usize = 10;
+        const REMOVE_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+        let path = std::mem::take(&mut self.path);
+        background_spawn!("running_actions_manager_remove_cgroup", async move {
+            // A cgroup can only be removed once all its processes exited.
+            if let Err(err) = std::fs::write(format!("{path}/cgroup.kill"), "1") {
+                event!(
+                    Level::WARN,
+                    ?err,
+                    ?path,
+                    "Could not kill processes of cgroup"
+                );
+            }
+            for _ in 0..MAX_REMOVE_ATTEMPTS {
+                match std::fs::remove_dir(&path) {
+                    Ok(()) => return,
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => return,
+                    Err(_) => tokio::time::sleep(REMOVE_RETRY_DELAY).await,
+                }
+            }
+            event!(Level::ERROR, ?path, "Could not remove cgroup of action");
+        });
+    }
+}
+
+/// Returns the podman command line that runs a command in a container of
+/// the image. The arguments of the command follow it.
+fn make_container_args(
+    podman_path: &str,
+    image_id: &str,
+    container_name: &str,
+    allow_network: bool,
+    action_directory: &str,
+    current_directory: &str,
+    environment: &[(&str, Cow<'_, str>)],
+) -> Vec<OsString> {
+    let mut args: Vec<OsString> = vec![
+        podman_path.into(),
+        "run".into(),
+        "--rm".into(),
+        "--pull=never".into(),
+        "--name".into(),
+        container_name.into(),
+        "--volume".into(),
+        format!("{action_directory}:{action_directory}").into(),
+        "--workdir".into(),
+        current_directory.into(),
+    ];
+    if !allow_network {
+        args.push("--network=none".into());
+    }
+    for (name, value) in environment {
+        args.push("--env".into());
+        args.push(format!("{name}={value}").into());
+    }
+    args.push(image_id.into());
+    args
+}
+
+/// Pulls the container images actions run in. The ID of the image a
+/// reference resolved to is kept, so all actions using the reference run in
+/// the same image, even if its tag moved since.
+struct ContainerImages {
+    podman_path: String,
+    require_digest: bool,
+    image_ids: Mutex<HashMap<String, String>>,
+}
+
+impl ContainerImages {
+    fn new(config: &ContainerConfig) -> Self {
+        Self {
+            podman_path: if config.podman_path.is_empty() {
+                DEFAULT_PODMAN_PATH.to_string()
+            } else {
+                config.podman_path.clone()
+            },
+            require_digest: config.require_digest,
+            image_ids: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the ID of the image, pulling it if no action used it before.
+    async fn get_image_id(&self, image: &str) -> Result<String, Error> {
+        let reference = image.strip_prefix("docker://").unwrap_or(image);
+        error_if!(
+            self.require_digest && !reference.contains("@sha256:"),
+            "Container image '{image}' is not pinned by digest"
+        );
+        if let Some(image_id) = self.image_ids.lock().get(reference) {
+            return Ok(image_id.clone());
+        }
+        // Actions that start with the same new image at the same time pull
+        // it each, podman only downloads it once.
+        let output = process::Command::new(&self.podman_path)
+            .args(["pull", "--quiet", reference])
+            .kill_on_drop(true)
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .err_tip(|| format!("Could not run {}", self.podman_path))?;
+        error_if!(
+            !output.status.success(),
+            "Could not pull container image '{image}': {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let image_id = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .last()
+            .map(str::trim)
+            .unwrap_or_default()
+            .to_string();
+        error_if!(
+            image_id.is_empty(),
+            "Pulling container image '{image}' did not return an image ID"
+        );
+        event!(Level::INFO, ?image, ?image_id, "Pulled container image");
+        self.image_ids
+            .lock()
+            .insert(reference.to_string(), image_id.clone());
+        Ok(image_id)
+    }
+
+    /// Removes the container of a killed action in the background. Killing
+    /// podman does not stop the container it started.
+    fn remove_container(&self, container_name: String) {
+        let mut command_builder = process::Command::new(&self.podman_path);
+        command_builder
+            .args(["rm", "--force", "--ignore"])
+            .arg(&container_name)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        background_spawn!("running_actions_manager_remove_container", async move {
+            if let Err(err) = command_builder.status().await {
+                event!(
+                    Level::ERROR,
+                    ?err,
+                    ?container_name,
+                    "Could not remove container of killed action",
+                );
+            }
+        });
+    }
 }
 
 struct UploadActionResults {
@@ -1653,12 +2577,16 @@ pub struct RunningActionsManagerArgs<'a> {
 pub struct RunningActionsManagerImpl {
     root_action_directory: String,
     execution_configuration: ExecutionConfiguration,
+    container_images: Option<ContainerImages>,
     cas_store: Arc<FastSlowStore>,
     filesystem_store: Arc<FilesystemStore>,
     upload_action_results: UploadActionResults,
     max_action_timeout: Duration,
     timeout_handled_externally: bool,
     running_actions: Mutex<HashMap<OperationId, Weak<RunningActionImpl>>>,
+    // Bounds the output files hashed or uploaded at the same time.
+    output_upload_permits: Semaphore,
+    persistent_workers: Option<PersistentWorkers>,
     // Note: We don't use Notify because we need to support a .wait_for()-like function, which
     // Notify does not support.
     action_done_tx: watch::Sender<()>,
@@ -1681,9 +2609,70 @@ impl RunningActionsManagerImpl {
             })?
             .get_arc()
             .err_tip(|| "FilesystemStore's internal Arc was lost")?;
+        error_if!(
+            cfg!(not(target_os = "linux"))
+                && args.execution_configuration.network_isolation.is_some(),
+            "network_isolation is only supported on Linux"
+        );
+        error_if!(
+            cfg!(not(target_os = "linux")) && args.execution_configuration.sandbox.is_some(),
+            "sandbox is only supported on Linux"
+        );
+        error_if!(
+            args.execution_configuration.sandbox.is_some()
+                && args.execution_configuration.network_isolation.is_some(),
+            "network_isolation can not be combined with sandbox, use allow_network_properties of sandbox instead"
+        );
+        error_if!(
+            args.execution_configuration.sandbox.is_some()
+                && args.execution_configuration.container.is_some(),
+            "container can not be combined with sandbox"
+        );
+        error_if!(
+            cfg!(not(target_os = "linux")) && args.execution_configuration.cgroup.is_some(),
+            "cgroup is only supported on Linux"
+        );
+        error_if!(
+            args.execution_configuration.cgroup.is_some()
+                && args.execution_configuration.container.is_some(),
+            "cgroup can not be combined with container"
+        );
+        error_if!(
+            cfg!(not(target_os = "linux"))
+                && args.execution_configuration.input_provider == InputProvider::fuse,
+            "The fuse input_provider is only supported on Linux"
+        );
+        if args.execution_configuration.persistent_workers.is_some() {
+            error_if!(
+                args.execution_configuration.sandbox.is_some()
+                    || args.execution_configuration.container.is_some()
+                    || args.execution_configuration.cgroup.is_some()
+                    || args.execution_configuration.network_isolation.is_some(),
+                "persistent_workers can not be combined with sandbox, container, cgroup or network_isolation"
+            );
+            error_if!(
+                args.execution_configuration.input_provider == InputProvider::fuse,
+                "persistent_workers can not be combined with the fuse input_provider"
+            );
+        }
+        let max_concurrent_output_uploads =
+            match args.execution_configuration.max_concurrent_output_uploads {
+                0 => DEFAULT_MAX_CONCURRENT_OUTPUT_UPLOADS,
+                max_concurrent_output_uploads => max_concurrent_output_uploads,
+            };
+        let persistent_workers = args
+            .execution_configuration
+            .persistent_workers
+            .as_ref()
+            .map(|config| PersistentWorkers::new(config, &args.root_action_directory));
         let (action_done_tx, _) = watch::channel(());
         Ok(Self {
             root_action_directory: args.root_action_directory,
+            container_images: args
+                .execution_configuration
+                .container
+                .as_ref()
+                .map(ContainerImages::new),
             execution_configuration: args.execution_configuration,
             cas_store: args.cas_store,
             filesystem_store,
@@ -1696,6 +2685,8 @@ impl RunningActionsManagerImpl {
             max_action_timeout: args.max_action_timeout,
             timeout_handled_externally: args.timeout_handled_externally,
             running_actions: Mutex::new(HashMap::new()),
+            output_upload_permits: Semaphore::new(max_concurrent_output_uploads),
+            persistent_workers,
             action_done_tx,
             callbacks,
             metrics: Arc::new(Metrics::default()),
@@ -1824,6 +2815,7 @@ impl RunningActionsManager for RunningActionsManagerImpl {
                     execution_completed_timestamp: SystemTime::UNIX_EPOCH,
                     output_upload_start_timestamp: SystemTime::UNIX_EPOCH,
                     output_upload_completed_timestamp: SystemTime::UNIX_EPOCH,
+                    resource_usage: None,
                 };
                 let timeout = if action_info.timeout.is_zero() || self.timeout_handled_externally {
                     self.max_action_timeout
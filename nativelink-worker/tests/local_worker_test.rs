@@ -30,8 +30,10 @@ mod utils {
 }
 
 use hyper::body::Frame;
-use nativelink_config::cas_server::{LocalWorkerConfig, WorkerProperty};
-use nativelink_config::stores::{FastSlowSpec, FilesystemSpec, MemorySpec, StoreSpec};
+use nativelink_config::cas_server::{LocalWorkerConfig, ReconnectBackoffConfig, WorkerProperty};
+use nativelink_config::stores::{
+    FastSlowPopulationSpec, FastSlowSpec, FilesystemSpec, MemorySpec, StoreSpec,
+};
 use nativelink_error::{make_err, make_input_err, Code, Error};
 use nativelink_macro::nativelink_test;
 use nativelink_proto::build::bazel::remote::execution::v2::platform::Property;
@@ -49,17 +51,20 @@ use nativelink_util::action_messages::{
 };
 use nativelink_util::common::{encode_stream_proto, fs, DigestInfo};
 use nativelink_util::digest_hasher::DigestHasherFunc;
+use nativelink_util::shutdown_guard::ShutdownGuard;
+use nativelink_util::spawn;
 use nativelink_util::store_trait::Store;
-use nativelink_worker::local_worker::new_local_worker;
+use nativelink_worker::local_worker::{new_local_worker, LocalWorker};
 use pretty_assertions::assert_eq;
 use prost::Message;
 use rand::{thread_rng, Rng};
 use tokio::io::AsyncWriteExt;
+use tokio::sync::{broadcast, mpsc};
 use tonic::Response;
 use utils::local_worker_test_utils::{
-    setup_grpc_stream, setup_local_worker, setup_local_worker_with_config,
+    setup_grpc_stream, setup_local_worker, setup_local_worker_with_config, MockWorkerApiClient,
 };
-use utils::mock_running_actions_manager::MockRunningAction;
+use utils::mock_running_actions_manager::{MockRunningAction, MockRunningActionsManager};
 
 const INSTANCE_NAME: &str = "foo";
 
@@ -159,6 +164,47 @@ async fn reconnect_on_server_disconnect_test() -> Result<(), Box<dyn std::error:
     Ok(())
 }
 
+#[nativelink_test]
+async fn reconnect_backoff_doubles_up_to_max_delay_test() -> Result<(), Box<dyn std::error::Error>>
+{
+    let (tx_delay, mut rx_delay) = mpsc::unbounded_channel();
+    let worker = LocalWorker::new_with_connection_factory_and_actions_manager(
+        Arc::new(LocalWorkerConfig {
+            reconnect_backoff: ReconnectBackoffConfig {
+                initial_delay_s: Some(1.),
+                max_delay_s: Some(4.),
+                jitter: Some(0.),
+            },
+            ..Default::default()
+        }),
+        Arc::new(MockRunningActionsManager::new()),
+        Box::new(|| {
+            Box::pin(async {
+                Err::<MockWorkerApiClient, _>(make_err!(Code::Unavailable, "Scheduler is down"))
+            })
+        }),
+        Box::new(move |delay| {
+            let _ = tx_delay.send(delay);
+            Box::pin(tokio::task::yield_now())
+        }),
+    );
+    let (shutdown_tx, _) = broadcast::channel::<ShutdownGuard>(1);
+    let _drop_guard = spawn!("local_worker_spawn", async move {
+        worker.run(shutdown_tx.subscribe()).await
+    });
+
+    // The scheduler can not be reached, so every attempt waits twice as long
+    // as the one before, until the maximum delay is reached.
+    for expected_delay_s in [1, 2, 4, 4] {
+        assert_eq!(
+            rx_delay.recv().await,
+            Some(Duration::from_secs(expected_delay_s))
+        );
+    }
+
+    Ok(())
+}
+
 #[nativelink_test]
 async fn kill_all_called_on_disconnect() -> Result<(), Box<dyn std::error::Error>> {
     let mut test_context = setup_local_worker(HashMap::new()).await;
@@ -180,6 +226,7 @@ async fn kill_all_called_on_disconnect() -> Result<(), Box<dyn std::error::Error
             .send(Frame::data(encode_stream_proto(&UpdateForWorker {
                 update: Some(Update::ConnectionResult(ConnectionResult {
                     worker_id: "foobar".to_string(),
+                    ..Default::default()
                 })),
             })?))
             .await
@@ -218,6 +265,7 @@ async fn blake3_digest_function_registerd_properly() -> Result<(), Box<dyn std::
             .send(Frame::data(encode_stream_proto(&UpdateForWorker {
                 update: Some(Update::ConnectionResult(ConnectionResult {
                     worker_id: expected_worker_id.clone(),
+                    ..Default::default()
                 })),
             })?))
             .await
@@ -238,6 +286,9 @@ async fn blake3_digest_function_registerd_properly() -> Result<(), Box<dyn std::
             digest_function: DigestHasherFunc::Blake3,
             digest: action_digest,
         }),
+        tool_invocation_id: None,
+        client_identity: None,
+        queue_priority: None,
     };
 
     {
@@ -300,6 +351,7 @@ async fn simple_worker_start_action_test() -> Result<(), Box<dyn std::error::Err
             .send(Frame::data(encode_stream_proto(&UpdateForWorker {
                 update: Some(Update::ConnectionResult(ConnectionResult {
                     worker_id: expected_worker_id.clone(),
+                    ..Default::default()
                 })),
             })?))
             .await
@@ -320,6 +372,9 @@ async fn simple_worker_start_action_test() -> Result<(), Box<dyn std::error::Err
             digest_function: DigestHasherFunc::Sha256,
             digest: action_digest,
         }),
+        tool_invocation_id: None,
+        client_identity: None,
+        queue_priority: None,
     };
 
     {
@@ -354,6 +409,7 @@ async fn simple_worker_start_action_test() -> Result<(), Box<dyn std::error::Err
             execution_completed_timestamp: SystemTime::UNIX_EPOCH,
             output_upload_start_timestamp: SystemTime::UNIX_EPOCH,
             output_upload_completed_timestamp: SystemTime::UNIX_EPOCH,
+            resource_usage: None,
         },
         server_logs: HashMap::new(),
         error: None,
@@ -411,6 +467,9 @@ async fn new_local_worker_creates_work_directory_test() -> Result<(), Box<dyn st
             // Note: These are not needed for this test, so we put dummy memory stores here.
             fast: StoreSpec::memory(MemorySpec::default()),
             slow: StoreSpec::memory(MemorySpec::default()),
+            popularity: None,
+            population: FastSlowPopulationSpec::default(),
+            write_back: false,
         },
         Store::new(
             <FilesystemStore>::new(&FilesystemSpec {
@@ -421,7 +480,7 @@ async fn new_local_worker_creates_work_directory_test() -> Result<(), Box<dyn st
             .await?,
         ),
         Store::new(MemoryStore::new(&MemorySpec::default())),
-    ));
+    )?);
     let ac_store = Store::new(MemoryStore::new(&MemorySpec::default()));
     let work_directory = make_temp_path("foo");
     new_local_worker(
@@ -451,6 +510,9 @@ async fn new_local_worker_removes_work_directory_before_start_test(
             // Note: These are not needed for this test, so we put dummy memory stores here.
             fast: StoreSpec::memory(MemorySpec::default()),
             slow: StoreSpec::memory(MemorySpec::default()),
+            popularity: None,
+            population: FastSlowPopulationSpec::default(),
+            write_back: false,
         },
         Store::new(
             <FilesystemStore>::new(&FilesystemSpec {
@@ -461,7 +523,7 @@ async fn new_local_worker_removes_work_directory_before_start_test(
             .await?,
         ),
         Store::new(MemoryStore::new(&MemorySpec::default())),
-    ));
+    )?);
     let ac_store = Store::new(MemoryStore::new(&MemorySpec::default()));
     let work_directory = make_temp_path("foo");
     fs::create_dir_all(format!("{}/{}", work_directory, "another_dir")).await?;
@@ -551,6 +613,7 @@ async fn experimental_precondition_script_fails() -> Result<(), Box<dyn std::err
             .send(Frame::data(encode_stream_proto(&UpdateForWorker {
                 update: Some(Update::ConnectionResult(ConnectionResult {
                     worker_id: expected_worker_id.clone(),
+                    ..Default::default()
                 })),
             })?))
             .await
@@ -571,6 +634,9 @@ async fn experimental_precondition_script_fails() -> Result<(), Box<dyn std::err
             digest_function: DigestHasherFunc::Sha256,
             digest: action_digest,
         }),
+        tool_invocation_id: None,
+        client_identity: None,
+        queue_priority: None,
     };
 
     {
@@ -631,6 +697,7 @@ async fn kill_action_request_kills_action() -> Result<(), Box<dyn std::error::Er
             .send(Frame::data(encode_stream_proto(&UpdateForWorker {
                 update: Some(Update::ConnectionResult(ConnectionResult {
                     worker_id: "foobar".to_string(),
+                    ..Default::default()
                 })),
             })?))
             .await
@@ -651,6 +718,9 @@ async fn kill_action_request_kills_action() -> Result<(), Box<dyn std::error::Er
             digest_function: DigestHasherFunc::Blake3,
             digest: action_digest,
         }),
+        tool_invocation_id: None,
+        client_identity: None,
+        queue_priority: None,
     };
 
     let operation_id = OperationId::default();
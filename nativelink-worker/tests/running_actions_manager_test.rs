@@ -26,8 +26,14 @@ use std::sync::{Arc, LazyLock, Mutex};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use futures::{FutureExt, StreamExt, TryFutureExt, TryStreamExt};
-use nativelink_config::cas_server::EnvironmentSource;
-use nativelink_config::stores::{FastSlowSpec, FilesystemSpec, MemorySpec, StoreSpec};
+#[cfg(target_os = "linux")]
+use nativelink_config::cas_server::{CgroupConfig, NetworkIsolationConfig, SandboxConfig};
+use nativelink_config::cas_server::{
+    ContainerConfig, EnvironmentSource, InputProvider, PersistentWorkerConfig,
+};
+use nativelink_config::stores::{
+    FastSlowPopulationSpec, FastSlowSpec, FilesystemSpec, MemorySpec, StoreSpec,
+};
 use nativelink_error::{make_input_err, Code, Error, ResultExt};
 use nativelink_macro::nativelink_test;
 use nativelink_proto::build::bazel::remote::execution::v2::command::EnvironmentVariable;
@@ -41,10 +47,14 @@ use nativelink_proto::com::github::trace_machina::nativelink::remote_execution::
     HistoricalExecuteResponse, StartExecute,
 };
 use nativelink_proto::google::rpc::Status;
-use nativelink_store::ac_utils::{get_and_decode_digest, serialize_and_upload_message};
+use nativelink_store::ac_utils::{
+    compute_buf_digest, get_and_decode_digest, serialize_and_upload_message,
+};
 use nativelink_store::fast_slow_store::FastSlowStore;
 use nativelink_store::filesystem_store::FilesystemStore;
 use nativelink_store::memory_store::MemoryStore;
+#[cfg(target_os = "linux")]
+use nativelink_util::action_messages::ResourceUsage;
 #[cfg_attr(target_family = "windows", allow(unused_imports))]
 use nativelink_util::action_messages::SymlinkInfo;
 use nativelink_util::action_messages::{
@@ -104,10 +114,13 @@ async fn setup_stores() -> Result<
         &FastSlowSpec {
             fast: StoreSpec::filesystem(fast_config),
             slow: StoreSpec::memory(slow_config),
+            popularity: None,
+            population: FastSlowPopulationSpec::default(),
+            write_back: false,
         },
         Store::new(fast_store.clone()),
         Store::new(slow_store.clone()),
-    );
+    )?;
     Ok((fast_store, slow_store, cas_store, ac_store))
 }
 
@@ -242,6 +255,71 @@ async fn download_to_directory_file_download_test() -> Result<(), Box<dyn std::e
     Ok(())
 }
 
+#[cfg(target_os = "linux")]
+#[nativelink_test]
+async fn download_to_directory_copies_across_devices_test() -> Result<(), Box<dyn std::error::Error>>
+{
+    const FILE_NAME: &str = "file.txt";
+    const FILE_CONTENT: &str = "HELLOFILE";
+    const OTHER_DEVICE_DIR: &str = "/dev/shm";
+
+    let (fast_store, slow_store, cas_store, _ac_store) = setup_stores().await?;
+    let linked_dir = make_temp_path("linked_dir");
+    fs::create_dir_all(&linked_dir).await?;
+    // Files can only be linked on the same device, so the test needs a
+    // directory on another device than the store.
+    let Ok(other_device_metadata) = fs::metadata(OTHER_DEVICE_DIR).await else {
+        return Ok(());
+    };
+    if other_device_metadata.dev() == fs::metadata(&linked_dir).await?.dev() {
+        return Ok(());
+    }
+    let copied_dir = format!("{OTHER_DEVICE_DIR}/{}", thread_rng().gen::<u64>());
+    fs::create_dir_all(&copied_dir).await?;
+
+    let root_directory_digest = {
+        let file_content_digest = DigestInfo::new([2u8; 32], 32);
+        slow_store
+            .as_ref()
+            .update_oneshot(file_content_digest, FILE_CONTENT.into())
+            .await?;
+        let root_directory_digest = DigestInfo::new([1u8; 32], 32);
+        let root_directory = Directory {
+            files: vec![FileNode {
+                name: FILE_NAME.to_string(),
+                digest: Some(file_content_digest.into()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        slow_store
+            .as_ref()
+            .update_oneshot(root_directory_digest, root_directory.encode_to_vec().into())
+            .await?;
+        root_directory_digest
+    };
+
+    for download_dir in [&linked_dir, &copied_dir] {
+        download_to_directory(
+            cas_store.as_ref(),
+            fast_store.as_pin(),
+            &root_directory_digest,
+            download_dir,
+        )
+        .await?;
+    }
+    let linked_file = format!("{linked_dir}/{FILE_NAME}");
+    let copied_file = format!("{copied_dir}/{FILE_NAME}");
+    for file in [&linked_file, &copied_file] {
+        assert_eq!(from_utf8(&fs::read(file).await?)?, FILE_CONTENT);
+    }
+    // The store and the linked file share the inode, the copy has its own.
+    assert_eq!(fs::metadata(&linked_file).await?.nlink(), 2);
+    assert_eq!(fs::metadata(&copied_file).await?.nlink(), 1);
+    fs::remove_dir_all(&copied_dir).await?;
+    Ok(())
+}
+
 #[nativelink_test]
 async fn download_to_directory_folder_download_test() -> Result<(), Box<dyn std::error::Error>> {
     const DIRECTORY1_NAME: &str = "folder1";
@@ -808,6 +886,7 @@ async fn blake3_upload_files() -> Result<(), Box<dyn std::error::Error>> {
                 execution_completed_timestamp: increment_clock(&mut clock_time),
                 output_upload_start_timestamp: increment_clock(&mut clock_time),
                 output_upload_completed_timestamp: increment_clock(&mut clock_time),
+                resource_usage: None,
                 worker_completed_timestamp: increment_clock(&mut clock_time),
             },
             error: None,
@@ -980,6 +1059,7 @@ async fn upload_files_from_above_cwd_test() -> Result<(), Box<dyn std::error::Er
                 execution_completed_timestamp: increment_clock(&mut clock_time),
                 output_upload_start_timestamp: increment_clock(&mut clock_time),
                 output_upload_completed_timestamp: increment_clock(&mut clock_time),
+                resource_usage: None,
                 worker_completed_timestamp: increment_clock(&mut clock_time),
             },
             error: None,
@@ -1187,6 +1267,7 @@ async fn upload_dir_and_symlink_test() -> Result<(), Box<dyn std::error::Error>>
                 execution_completed_timestamp: increment_clock(&mut clock_time),
                 output_upload_start_timestamp: increment_clock(&mut clock_time),
                 output_upload_completed_timestamp: increment_clock(&mut clock_time),
+                resource_usage: None,
                 worker_completed_timestamp: increment_clock(&mut clock_time),
             },
             error: None,
@@ -1314,6 +1395,7 @@ async fn cleanup_happens_on_job_failure() -> Result<(), Box<dyn std::error::Erro
                 execution_completed_timestamp: increment_clock(&mut clock_time),
                 output_upload_start_timestamp: increment_clock(&mut clock_time),
                 output_upload_completed_timestamp: increment_clock(&mut clock_time),
+                resource_usage: None,
                 worker_completed_timestamp: increment_clock(&mut clock_time),
             },
             error: None,
@@ -1500,6 +1582,13 @@ exit 0
             execution_configuration: ExecutionConfiguration {
                 entrypoint: Some(test_wrapper_script.into_string().unwrap()),
                 additional_environment: None,
+                network_isolation: None,
+                sandbox: None,
+                container: None,
+                cgroup: None,
+                input_provider: InputProvider::download,
+                max_concurrent_output_uploads: 0,
+                persistent_workers: None,
             },
             cas_store: cas_store.clone(),
             ac_store: Some(Store::new(ac_store.clone())),
@@ -1666,6 +1755,13 @@ exit 0
                         EnvironmentSource::value(std::env::var("PATH").unwrap()),
                     ),
                 ])),
+                network_isolation: None,
+                sandbox: None,
+                container: None,
+                cgroup: None,
+                input_provider: InputProvider::download,
+                max_concurrent_output_uploads: 0,
+                persistent_workers: None,
             },
             cas_store: cas_store.clone(),
             ac_store: Some(Store::new(ac_store.clone())),
@@ -1820,6 +1916,13 @@ exit 1
                     "SIDE_CHANNEL_FILE".to_string(),
                     EnvironmentSource::side_channel_file,
                 )])),
+                network_isolation: None,
+                sandbox: None,
+                container: None,
+                cgroup: None,
+                input_provider: InputProvider::download,
+                max_concurrent_output_uploads: 0,
+                persistent_workers: None,
             },
             cas_store: cas_store.clone(),
             ac_store: Some(Store::new(ac_store.clone())),
@@ -1889,6 +1992,1030 @@ exit 1
     Ok(())
 }
 
+#[cfg(target_os = "linux")]
+#[cfg_attr(feature = "nix", ignore)]
+#[nativelink_test]
+async fn network_isolation_only_lets_allowed_actions_use_network(
+) -> Result<(), Box<dyn std::error::Error>> {
+    const WORKER_ID: &str = "foo_worker_id";
+
+    /// Returns the names of the network interfaces in `/proc/net/dev`.
+    fn interface_names(proc_net_dev: &str) -> Vec<&str> {
+        proc_net_dev
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, _)| name.trim())
+            .collect()
+    }
+
+    let (_, _, cas_store, ac_store) = setup_stores().await?;
+    let root_action_directory = make_temp_path("root_action_directory");
+    fs::create_dir_all(&root_action_directory).await?;
+
+    let running_actions_manager =
+        Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
+            root_action_directory,
+            execution_configuration: ExecutionConfiguration {
+                network_isolation: Some(NetworkIsolationConfig {
+                    allow_network_properties: HashMap::from([(
+                        "network".to_string(),
+                        vec!["enabled".to_string()],
+                    )]),
+                }),
+                ..Default::default()
+            },
+            cas_store: cas_store.clone(),
+            ac_store: Some(Store::new(ac_store.clone())),
+            historical_store: Store::new(cas_store.clone()),
+            upload_action_result_config: &nativelink_config::cas_server::UploadActionResultConfig {
+                upload_ac_results_strategy:
+                    nativelink_config::cas_server::UploadCacheResultsStrategy::never,
+                ..Default::default()
+            },
+            max_action_timeout: Duration::MAX,
+            timeout_handled_externally: false,
+        })?);
+    let command = Command {
+        arguments: vec!["cat".to_string(), "/proc/net/dev".to_string()],
+        working_directory: ".".to_string(),
+        environment_variables: vec![EnvironmentVariable {
+            name: "PATH".to_string(),
+            value: std::env::var("PATH").unwrap(),
+        }],
+        ..Default::default()
+    };
+    let command_digest = serialize_and_upload_message(
+        &command,
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let input_root_digest = serialize_and_upload_message(
+        &Directory::default(),
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+
+    let worker_proc_net_dev = std::fs::read_to_string("/proc/net/dev")?;
+    for (network_property, expected_interfaces) in [
+        (None, vec!["lo"]),
+        (Some("disabled"), vec!["lo"]),
+        (Some("enabled"), interface_names(&worker_proc_net_dev)),
+    ] {
+        let action = Action {
+            command_digest: Some(command_digest.into()),
+            input_root_digest: Some(input_root_digest.into()),
+            platform: network_property.map(|value| Platform {
+                properties: vec![Property {
+                    name: "network".to_string(),
+                    value: value.to_string(),
+                }],
+            }),
+            ..Default::default()
+        };
+        let action_digest = serialize_and_upload_message(
+            &action,
+            cas_store.as_pin(),
+            &mut DigestHasherFunc::Sha256.hasher(),
+        )
+        .await?;
+        let running_action_impl = running_actions_manager
+            .clone()
+            .create_and_add_action(
+                WORKER_ID.to_string(),
+                StartExecute {
+                    execute_request: Some(ExecuteRequest {
+                        action_digest: Some(action_digest.into()),
+                        ..Default::default()
+                    }),
+                    operation_id: OperationId::default().to_string(),
+                    queued_timestamp: Some(make_system_time(1000).into()),
+                },
+            )
+            .await?;
+
+        let result = run_action(running_action_impl).await?;
+        assert_eq!(result.exit_code, 0, "Exit code should be 0");
+        let stdout = cas_store
+            .as_ref()
+            .get_part_unchunked(result.stdout_digest, 0, None)
+            .await?;
+        assert_eq!(
+            interface_names(from_utf8(&stdout)?),
+            expected_interfaces,
+            "Unexpected interfaces for network property {network_property:?}"
+        );
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[cfg_attr(feature = "nix", ignore)]
+#[nativelink_test]
+async fn sandbox_wraps_command_in_bwrap() -> Result<(), Box<dyn std::error::Error>> {
+    // Stands in for bwrap. It prints its arguments to stderr and runs the
+    // command after them without a sandbox.
+    const FAKE_BWRAP_SCRIPT_CONTENT: &str = "\
+#!/bin/sh
+>&2 printf '%s ' \"$@\"
+while [ \"$1\" != \"--\" ]; do shift; done
+shift
+exec \"$@\"
+";
+    const WORKER_ID: &str = "foo_worker_id";
+    const EXPECTED_STDOUT: &str = "Action did run";
+
+    let (_, _, cas_store, ac_store) = setup_stores().await?;
+    let root_action_directory = make_temp_path("root_action_directory");
+    fs::create_dir_all(&root_action_directory).await?;
+
+    let fake_bwrap_script = {
+        let fake_bwrap_dir = make_temp_path("fake_bwrap_dir");
+        fs::create_dir_all(&fake_bwrap_dir).await?;
+        let fake_bwrap_script = fake_bwrap_dir + "/bwrap";
+        let mut fake_bwrap_script_handle = std::fs::File::create(&fake_bwrap_script)?;
+        fake_bwrap_script_handle.write_all(FAKE_BWRAP_SCRIPT_CONTENT.as_bytes())?;
+        fake_bwrap_script_handle.set_permissions(Permissions::from_mode(0o777))?;
+        fake_bwrap_script_handle.sync_all()?;
+        drop(fake_bwrap_script_handle);
+        fake_bwrap_script
+    };
+
+    // TODO(#527) Sleep to reduce flakey chances.
+    tokio::time::sleep(Duration::from_millis(250)).await;
+
+    let running_actions_manager =
+        Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
+            root_action_directory,
+            execution_configuration: ExecutionConfiguration {
+                sandbox: Some(SandboxConfig {
+                    bwrap_path: fake_bwrap_script,
+                    allow_network_properties: HashMap::from([(
+                        "network".to_string(),
+                        vec!["enabled".to_string()],
+                    )]),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            cas_store: cas_store.clone(),
+            ac_store: Some(Store::new(ac_store.clone())),
+            historical_store: Store::new(cas_store.clone()),
+            upload_action_result_config: &nativelink_config::cas_server::UploadActionResultConfig {
+                upload_ac_results_strategy:
+                    nativelink_config::cas_server::UploadCacheResultsStrategy::never,
+                ..Default::default()
+            },
+            max_action_timeout: Duration::MAX,
+            timeout_handled_externally: false,
+        })?);
+    let command = Command {
+        arguments: vec!["printf".to_string(), EXPECTED_STDOUT.to_string()],
+        output_paths: vec!["out/dir/file".to_string()],
+        working_directory: ".".to_string(),
+        environment_variables: vec![EnvironmentVariable {
+            name: "PATH".to_string(),
+            value: std::env::var("PATH").unwrap(),
+        }],
+        ..Default::default()
+    };
+    let command_digest = serialize_and_upload_message(
+        &command,
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let input_root_digest = serialize_and_upload_message(
+        &Directory::default(),
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+
+    for (network_property, expect_network) in [(None, false), (Some("enabled"), true)] {
+        let action = Action {
+            command_digest: Some(command_digest.into()),
+            input_root_digest: Some(input_root_digest.into()),
+            platform: network_property.map(|value| Platform {
+                properties: vec![Property {
+                    name: "network".to_string(),
+                    value: value.to_string(),
+                }],
+            }),
+            ..Default::default()
+        };
+        let action_digest = serialize_and_upload_message(
+            &action,
+            cas_store.as_pin(),
+            &mut DigestHasherFunc::Sha256.hasher(),
+        )
+        .await?;
+        let running_action_impl = running_actions_manager
+            .clone()
+            .create_and_add_action(
+                WORKER_ID.to_string(),
+                StartExecute {
+                    execute_request: Some(ExecuteRequest {
+                        action_digest: Some(action_digest.into()),
+                        ..Default::default()
+                    }),
+                    operation_id: OperationId::default().to_string(),
+                    queued_timestamp: Some(make_system_time(1000).into()),
+                },
+            )
+            .await?;
+
+        let result = run_action(running_action_impl).await?;
+        assert_eq!(result.exit_code, 0, "Exit code should be 0");
+        let stdout = cas_store
+            .as_ref()
+            .get_part_unchunked(result.stdout_digest, 0, None)
+            .await?;
+        assert_eq!(from_utf8(&stdout)?, EXPECTED_STDOUT);
+        let stderr = cas_store
+            .as_ref()
+            .get_part_unchunked(result.stderr_digest, 0, None)
+            .await?;
+        let bwrap_args = from_utf8(&stderr)?;
+        assert!(bwrap_args.starts_with("--unshare-all "), "{bwrap_args}");
+        assert!(bwrap_args.contains(" --tmpfs /tmp "), "{bwrap_args}");
+        assert!(
+            bwrap_args.contains(" --ro-bind-try /usr /usr "),
+            "{bwrap_args}"
+        );
+        assert!(bwrap_args.contains("/out/dir "), "{bwrap_args}");
+        assert_eq!(
+            bwrap_args.contains(" --share-net "),
+            expect_network,
+            "Unexpected arguments for network property {network_property:?}: {bwrap_args}"
+        );
+    }
+    Ok(())
+}
+
+#[cfg(target_family = "unix")]
+#[cfg_attr(feature = "nix", ignore)]
+#[nativelink_test]
+async fn container_image_runs_action_in_container() -> Result<(), Box<dyn std::error::Error>> {
+    // Stands in for podman. It writes the images it pulls next to itself,
+    // prints the arguments of `run` to stderr and runs the command after
+    // the image without a container.
+    const FAKE_PODMAN_SCRIPT_CONTENT: &str = "\
+#!/bin/sh
+if [ \"$1\" = \"pull\" ]; then
+    echo \"$3\" >> \"$0.pulls\"
+    echo fake-image-id
+    exit 0
+fi
+>&2 printf '%s ' \"$@\"
+while [ \"$1\" != \"fake-image-id\" ]; do shift; done
+shift
+exec \"$@\"
+";
+    const WORKER_ID: &str = "foo_worker_id";
+    const EXPECTED_STDOUT: &str = "Action did run";
+
+    let (_, _, cas_store, ac_store) = setup_stores().await?;
+    let root_action_directory = make_temp_path("root_action_directory");
+    fs::create_dir_all(&root_action_directory).await?;
+
+    let fake_podman_script = {
+        let fake_podman_dir = make_temp_path("fake_podman_dir");
+        fs::create_dir_all(&fake_podman_dir).await?;
+        let fake_podman_script = fake_podman_dir + "/podman";
+        let mut fake_podman_script_handle = std::fs::File::create(&fake_podman_script)?;
+        fake_podman_script_handle.write_all(FAKE_PODMAN_SCRIPT_CONTENT.as_bytes())?;
+        fake_podman_script_handle.set_permissions(Permissions::from_mode(0o777))?;
+        fake_podman_script_handle.sync_all()?;
+        drop(fake_podman_script_handle);
+        fake_podman_script
+    };
+
+    // TODO(#527) Sleep to reduce flakey chances.
+    tokio::time::sleep(Duration::from_millis(250)).await;
+
+    let make_running_actions_manager = |require_digest| {
+        RunningActionsManagerImpl::new(RunningActionsManagerArgs {
+            root_action_directory: root_action_directory.clone(),
+            execution_configuration: ExecutionConfiguration {
+                container: Some(ContainerConfig {
+                    podman_path: fake_podman_script.clone(),
+                    require_digest,
+                }),
+                ..Default::default()
+            },
+            cas_store: cas_store.clone(),
+            ac_store: Some(Store::new(ac_store.clone())),
+            historical_store: Store::new(cas_store.clone()),
+            upload_action_result_config: &nativelink_config::cas_server::UploadActionResultConfig {
+                upload_ac_results_strategy:
+                    nativelink_config::cas_server::UploadCacheResultsStrategy::never,
+                ..Default::default()
+            },
+            max_action_timeout: Duration::MAX,
+            timeout_handled_externally: false,
+        })
+        .map(Arc::new)
+    };
+    let command = Command {
+        arguments: vec!["printf".to_string(), EXPECTED_STDOUT.to_string()],
+        working_directory: ".".to_string(),
+        environment_variables: vec![EnvironmentVariable {
+            name: "PATH".to_string(),
+            value: std::env::var("PATH").unwrap(),
+        }],
+        ..Default::default()
+    };
+    let command_digest = serialize_and_upload_message(
+        &command,
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let input_root_digest = serialize_and_upload_message(
+        &Directory::default(),
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let make_running_action =
+        |running_actions_manager: Arc<RunningActionsManagerImpl>,
+         container_image: Option<&'static str>| {
+            let cas_store = cas_store.clone();
+            async move {
+                let action = Action {
+                    command_digest: Some(command_digest.into()),
+                    input_root_digest: Some(input_root_digest.into()),
+                    platform: container_image.map(|value| Platform {
+                        properties: vec![Property {
+                            name: "container-image".to_string(),
+                            value: value.to_string(),
+                        }],
+                    }),
+                    ..Default::default()
+                };
+                let action_digest = serialize_and_upload_message(
+                    &action,
+                    cas_store.as_pin(),
+                    &mut DigestHasherFunc::Sha256.hasher(),
+                )
+                .await?;
+                running_actions_manager
+                    .create_and_add_action(
+                        WORKER_ID.to_string(),
+                        StartExecute {
+                            execute_request: Some(ExecuteRequest {
+                                action_digest: Some(action_digest.into()),
+                                ..Default::default()
+                            }),
+                            operation_id: OperationId::default().to_string(),
+                            queued_timestamp: Some(make_system_time(1000).into()),
+                        },
+                    )
+                    .await
+            }
+        };
+
+    let running_actions_manager = make_running_actions_manager(false)?;
+    for container_image in [None, Some("docker://ubuntu:24.04"), Some("ubuntu:24.04")] {
+        let running_action_impl =
+            make_running_action(running_actions_manager.clone(), container_image).await?;
+        let result = run_action(running_action_impl).await?;
+        assert_eq!(result.exit_code, 0, "Exit code should be 0");
+        let stdout = cas_store
+            .as_ref()
+            .get_part_unchunked(result.stdout_digest, 0, None)
+            .await?;
+        assert_eq!(from_utf8(&stdout)?, EXPECTED_STDOUT);
+        let stderr = cas_store
+            .as_ref()
+            .get_part_unchunked(result.stderr_digest, 0, None)
+            .await?;
+        let podman_args = from_utf8(&stderr)?;
+        if container_image.is_none() {
+            assert_eq!(podman_args, "");
+            continue;
+        }
+        assert!(
+            podman_args.starts_with("run --rm --pull=never --name nativelink-"),
+            "{podman_args}"
+        );
+        assert!(podman_args.contains(" --env PATH="), "{podman_args}");
+        assert!(!podman_args.contains("--network=none"), "{podman_args}");
+        assert!(
+            podman_args.ends_with(&format!(" fake-image-id printf {EXPECTED_STDOUT} ")),
+            "{podman_args}"
+        );
+    }
+    // The image was only pulled by the first action that used it.
+    assert_eq!(
+        std::fs::read_to_string(format!("{fake_podman_script}.pulls"))?,
+        "ubuntu:24.04\n"
+    );
+
+    let running_actions_manager = make_running_actions_manager(true)?;
+    let running_action_impl =
+        make_running_action(running_actions_manager, Some("docker://ubuntu:24.04")).await?;
+    let err = run_action(running_action_impl).await.unwrap_err();
+    assert_eq!(err.code, Code::InvalidArgument, "{err:?}");
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[cfg_attr(feature = "nix", ignore)]
+#[nativelink_test]
+async fn cgroup_limits_action_and_reports_usage() -> Result<(), Box<dyn std::error::Error>> {
+    const WORKER_ID: &str = "foo_worker_id";
+
+    let (_, _, cas_store, ac_store) = setup_stores().await?;
+    let root_action_directory = make_temp_path("root_action_directory");
+    fs::create_dir_all(&root_action_directory).await?;
+    // A real cgroup can not be created in tests, so the files the kernel
+    // would create in it are created up front.
+    let cgroup_path = make_temp_path("cgroup");
+    let make_cgroup = |operation_id: &str, oom_kills: u64| -> Result<String, Error> {
+        let action_cgroup_path = format!("{cgroup_path}/{operation_id}");
+        std::fs::create_dir_all(&action_cgroup_path)?;
+        for (file, contents) in [
+            ("cgroup.procs", String::new()),
+            ("memory.peak", "4096\n".to_string()),
+            (
+                "cpu.stat",
+                "usage_usec 1500000\nuser_usec 1000000\n".to_string(),
+            ),
+            ("pids.peak", "3\n".to_string()),
+            ("memory.events", format!("oom 0\noom_kill {oom_kills}\n")),
+        ] {
+            std::fs::write(format!("{action_cgroup_path}/{file}"), contents)?;
+        }
+        Ok(action_cgroup_path)
+    };
+
+    let running_actions_manager =
+        Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
+            root_action_directory,
+            execution_configuration: ExecutionConfiguration {
+                cgroup: Some(CgroupConfig {
+                    path: cgroup_path.clone(),
+                    default_cpu_count: 1,
+                    default_memory_kb: 0,
+                    default_max_pids: 64,
+                }),
+                ..Default::default()
+            },
+            cas_store: cas_store.clone(),
+            ac_store: Some(Store::new(ac_store.clone())),
+            historical_store: Store::new(cas_store.clone()),
+            upload_action_result_config: &nativelink_config::cas_server::UploadActionResultConfig {
+                upload_ac_results_strategy:
+                    nativelink_config::cas_server::UploadCacheResultsStrategy::never,
+                ..Default::default()
+            },
+            max_action_timeout: Duration::MAX,
+            timeout_handled_externally: false,
+        })?);
+    let command = Command {
+        arguments: vec!["true".to_string()],
+        working_directory: ".".to_string(),
+        environment_variables: vec![EnvironmentVariable {
+            name: "PATH".to_string(),
+            value: std::env::var("PATH").unwrap(),
+        }],
+        ..Default::default()
+    };
+    let command_digest = serialize_and_upload_message(
+        &command,
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let input_root_digest = serialize_and_upload_message(
+        &Directory::default(),
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let action = Action {
+        command_digest: Some(command_digest.into()),
+        input_root_digest: Some(input_root_digest.into()),
+        platform: Some(Platform {
+            properties: vec![
+                Property {
+                    name: "cpu_count".to_string(),
+                    value: "2".to_string(),
+                },
+                Property {
+                    name: "memory_kb".to_string(),
+                    value: "1024".to_string(),
+                },
+            ],
+        }),
+        ..Default::default()
+    };
+    let action_digest = serialize_and_upload_message(
+        &action,
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+
+    for (operation_id, oom_kills) in [("within_limits", 0), ("out_of_memory", 1)] {
+        let action_cgroup_path = make_cgroup(operation_id, oom_kills)?;
+        let running_action_impl = running_actions_manager
+            .clone()
+            .create_and_add_action(
+                WORKER_ID.to_string(),
+                StartExecute {
+                    execute_request: Some(ExecuteRequest {
+                        action_digest: Some(action_digest.into()),
+                        ..Default::default()
+                    }),
+                    operation_id: operation_id.to_string(),
+                    queued_timestamp: Some(make_system_time(1000).into()),
+                },
+            )
+            .await?;
+
+        let result = run_action(running_action_impl).await?;
+        assert_eq!(
+            result.execution_metadata.resource_usage,
+            Some(ResourceUsage {
+                peak_memory_bytes: 4096,
+                cpu_time: Duration::from_millis(1500),
+                peak_pids: 3,
+            })
+        );
+        for (file, expected_contents) in [
+            ("cgroup.procs", "0"),
+            ("memory.oom.group", "1"),
+            ("cpu.max", "200000 100000"),
+            ("memory.max", "1048576"),
+            ("pids.max", "64"),
+        ] {
+            assert_eq!(
+                std::fs::read_to_string(format!("{action_cgroup_path}/{file}"))?,
+                expected_contents,
+                "Unexpected contents of {file}"
+            );
+        }
+        if oom_kills == 0 {
+            assert_eq!(result.error, None);
+        } else {
+            let err = result.error.err_tip(|| "Error should exist")?;
+            assert_eq!(err.code, Code::ResourceExhausted, "{err:?}");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[cfg_attr(feature = "nix", ignore)]
+#[nativelink_test]
+async fn fuse_input_provider_only_fetches_read_inputs() -> Result<(), Box<dyn std::error::Error>> {
+    const WORKER_ID: &str = "foo_worker_id";
+    const INPUT_CONTENT: &str = "HELLO";
+    const NESTED_CONTENT: &str = "WORLD";
+
+    // Mounting needs /dev/fuse and the permission to mount filesystems.
+    if std::fs::File::options()
+        .read(true)
+        .write(true)
+        .open("/dev/fuse")
+        .is_err()
+    {
+        return Ok(());
+    }
+    let (_, slow_store, cas_store, ac_store) = setup_stores().await?;
+    let root_action_directory = make_temp_path("root_action_directory");
+    fs::create_dir_all(&root_action_directory).await?;
+
+    let running_actions_manager =
+        Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
+            root_action_directory,
+            execution_configuration: ExecutionConfiguration {
+                input_provider: InputProvider::fuse,
+                ..Default::default()
+            },
+            cas_store: cas_store.clone(),
+            ac_store: Some(Store::new(ac_store.clone())),
+            historical_store: Store::new(cas_store.clone()),
+            upload_action_result_config: &nativelink_config::cas_server::UploadActionResultConfig {
+                upload_ac_results_strategy:
+                    nativelink_config::cas_server::UploadCacheResultsStrategy::never,
+                ..Default::default()
+            },
+            max_action_timeout: Duration::MAX,
+            timeout_handled_externally: false,
+        })?);
+    let input_digest = DigestInfo::new([2u8; 32], INPUT_CONTENT.len() as u64);
+    let nested_digest = DigestInfo::new([3u8; 32], NESTED_CONTENT.len() as u64);
+    // Never uploaded, so staging the input root fails if it is read.
+    let unused_digest = DigestInfo::new([4u8; 32], 6);
+    for (digest, content) in [
+        (input_digest, INPUT_CONTENT),
+        (nested_digest, NESTED_CONTENT),
+    ] {
+        slow_store
+            .as_ref()
+            .update_oneshot(digest, content.into())
+            .await?;
+    }
+    let folder_digest = serialize_and_upload_message(
+        &Directory {
+            files: vec![FileNode {
+                name: "nested.txt".to_string(),
+                digest: Some(nested_digest.into()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        },
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let input_root_digest = serialize_and_upload_message(
+        &Directory {
+            files: vec![
+                FileNode {
+                    name: "input.txt".to_string(),
+                    digest: Some(input_digest.into()),
+                    ..Default::default()
+                },
+                FileNode {
+                    name: "unused.txt".to_string(),
+                    digest: Some(unused_digest.into()),
+                    ..Default::default()
+                },
+            ],
+            directories: vec![DirectoryNode {
+                name: "folder".to_string(),
+                digest: Some(folder_digest.into()),
+            }],
+            ..Default::default()
+        },
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let command = Command {
+        arguments: vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            "ls > /dev/null && cat input.txt folder/nested.txt > out/output.txt".to_string(),
+        ],
+        // An input that the action did not write is an output too.
+        output_paths: vec!["out/output.txt".to_string(), "input.txt".to_string()],
+        working_directory: ".".to_string(),
+        environment_variables: vec![EnvironmentVariable {
+            name: "PATH".to_string(),
+            value: std::env::var("PATH").unwrap(),
+        }],
+        ..Default::default()
+    };
+    let command_digest = serialize_and_upload_message(
+        &command,
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let action_digest = serialize_and_upload_message(
+        &Action {
+            command_digest: Some(command_digest.into()),
+            input_root_digest: Some(input_root_digest.into()),
+            ..Default::default()
+        },
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+
+    let running_action_impl = running_actions_manager
+        .clone()
+        .create_and_add_action(
+            WORKER_ID.to_string(),
+            StartExecute {
+                execute_request: Some(ExecuteRequest {
+                    action_digest: Some(action_digest.into()),
+                    ..Default::default()
+                }),
+                operation_id: "lazy_inputs".to_string(),
+                queued_timestamp: Some(make_system_time(1000).into()),
+            },
+        )
+        .await?;
+    let action_directory = running_action_impl
+        .get_work_directory()
+        .rsplit_once('/')
+        .unwrap()
+        .0
+        .to_string();
+    let result = match run_action(running_action_impl).await {
+        // Not allowed to mount filesystems.
+        Err(err) if err.code == Code::PermissionDenied => return Ok(()),
+        result => result?,
+    };
+    assert_eq!(result.error, None);
+    let stderr = cas_store
+        .as_ref()
+        .get_part_unchunked(result.stderr_digest, 0, None)
+        .await?;
+    assert_eq!(result.exit_code, 0, "{:?}", from_utf8(&stderr));
+    let mut outputs = Vec::with_capacity(result.output_files.len());
+    for output_file in result.output_files {
+        let content = cas_store
+            .as_ref()
+            .get_part_unchunked(output_file.digest, 0, None)
+            .await?;
+        outputs.push((output_file.name_or_path, from_utf8(&content)?.to_string()));
+    }
+    outputs.sort_by(|a, b| a.1.cmp(&b.1));
+    assert_eq!(
+        outputs,
+        vec![
+            (
+                NameOrPath::Path("input.txt".to_string()),
+                INPUT_CONTENT.to_string()
+            ),
+            (
+                NameOrPath::Path("out/output.txt".to_string()),
+                format!("{INPUT_CONTENT}{NESTED_CONTENT}")
+            ),
+        ]
+    );
+    // Everything was unmounted before the action directory was removed.
+    assert!(fs::metadata(&action_directory).await.is_err());
+    Ok(())
+}
+
+#[cfg(target_family = "unix")]
+#[nativelink_test]
+async fn upload_results_skips_outputs_the_cas_has() -> Result<(), Box<dyn std::error::Error>> {
+    const WORKER_ID: &str = "foo_worker_id";
+    const EXISTING_CONTENT: &str = "existing";
+    const NEW_CONTENT: &str = "new";
+
+    let (fast_store, slow_store, cas_store, ac_store) = setup_stores().await?;
+    let root_action_directory = make_temp_path("root_action_directory");
+    fs::create_dir_all(&root_action_directory).await?;
+
+    let running_actions_manager =
+        Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
+            root_action_directory,
+            execution_configuration: ExecutionConfiguration {
+                // Outputs are still uploaded one at a time.
+                max_concurrent_output_uploads: 1,
+                ..Default::default()
+            },
+            cas_store: cas_store.clone(),
+            ac_store: Some(Store::new(ac_store.clone())),
+            historical_store: Store::new(cas_store.clone()),
+            upload_action_result_config: &nativelink_config::cas_server::UploadActionResultConfig {
+                upload_ac_results_strategy:
+                    nativelink_config::cas_server::UploadCacheResultsStrategy::never,
+                ..Default::default()
+            },
+            max_action_timeout: Duration::MAX,
+            timeout_handled_externally: false,
+        })?);
+    let existing_digest = compute_buf_digest(
+        EXISTING_CONTENT.as_bytes(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    );
+    let new_digest = compute_buf_digest(
+        NEW_CONTENT.as_bytes(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    );
+    // Only in the slow store, so an upload through the fast store would
+    // put it there.
+    slow_store
+        .as_ref()
+        .update_oneshot(existing_digest, EXISTING_CONTENT.into())
+        .await?;
+
+    let command = Command {
+        arguments: vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            format!(
+                "printf {EXISTING_CONTENT} > existing.txt && mkdir dir && printf {EXISTING_CONTENT} > dir/existing.txt && printf {NEW_CONTENT} > dir/new.txt"
+            ),
+        ],
+        output_paths: vec!["existing.txt".to_string(), "dir".to_string()],
+        working_directory: ".".to_string(),
+        environment_variables: vec![EnvironmentVariable {
+            name: "PATH".to_string(),
+            value: std::env::var("PATH").unwrap(),
+        }],
+        ..Default::default()
+    };
+    let command_digest = serialize_and_upload_message(
+        &command,
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let input_root_digest = serialize_and_upload_message(
+        &Directory::default(),
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let action_digest = serialize_and_upload_message(
+        &Action {
+            command_digest: Some(command_digest.into()),
+            input_root_digest: Some(input_root_digest.into()),
+            ..Default::default()
+        },
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let running_action_impl = running_actions_manager
+        .clone()
+        .create_and_add_action(
+            WORKER_ID.to_string(),
+            StartExecute {
+                execute_request: Some(ExecuteRequest {
+                    action_digest: Some(action_digest.into()),
+                    ..Default::default()
+                }),
+                operation_id: OperationId::default().to_string(),
+                queued_timestamp: Some(make_system_time(1000).into()),
+            },
+        )
+        .await?;
+
+    let result = run_action(running_action_impl).await?;
+    assert_eq!(result.error, None);
+    assert_eq!(result.exit_code, 0);
+    assert_eq!(result.output_files.len(), 1);
+    assert_eq!(result.output_files[0].digest, existing_digest);
+    assert_eq!(result.output_folders.len(), 1);
+    assert_eq!(fast_store.has(existing_digest).await?, None);
+    assert!(fast_store.has(new_digest).await?.is_some());
+    assert_eq!(
+        slow_store.has(new_digest).await?,
+        Some(NEW_CONTENT.len() as u64)
+    );
+    Ok(())
+}
+
+#[cfg(target_family = "unix")]
+#[nativelink_test]
+async fn persistent_worker_is_reused_between_actions() -> Result<(), Box<dyn std::error::Error>> {
+    const WORKER_ID: &str = "foo_worker_id";
+    const INPUT_CONTENT: &str = "HELLO";
+    // Answers every WorkRequest with its pid and the number of requests it
+    // got, after copying input.txt to output.txt.
+    const WORKER_SCRIPT: &str = r#"
+n=0
+while :; do
+  len=0 scale=1
+  while :; do
+    byte=$(dd bs=1 count=1 2>/dev/null | od -An -tu1 | tr -d ' ')
+    [ -n "$byte" ] || exit 0
+    len=$((len + byte % 128 * scale)) scale=$((scale * 128))
+    [ "$byte" -lt 128 ] && break
+  done
+  dd bs=1 count=$len of=/dev/null 2>/dev/null
+  n=$((n + 1))
+  cp input.txt output.txt
+  out="$$ $n"
+  printf "\\$(printf %o $((${#out} + 2)))\\022\\$(printf %o ${#out})%s" "$out"
+done
+"#;
+
+    let (_, slow_store, cas_store, ac_store) = setup_stores().await?;
+    let root_action_directory = make_temp_path("root_action_directory");
+    fs::create_dir_all(&root_action_directory).await?;
+
+    let running_actions_manager =
+        Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
+            root_action_directory: root_action_directory.clone(),
+            execution_configuration: ExecutionConfiguration {
+                persistent_workers: Some(PersistentWorkerConfig {
+                    max_requests: 2,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            cas_store: cas_store.clone(),
+            ac_store: Some(Store::new(ac_store.clone())),
+            historical_store: Store::new(cas_store.clone()),
+            upload_action_result_config: &nativelink_config::cas_server::UploadActionResultConfig {
+                upload_ac_results_strategy:
+                    nativelink_config::cas_server::UploadCacheResultsStrategy::never,
+                ..Default::default()
+            },
+            max_action_timeout: Duration::MAX,
+            timeout_handled_externally: false,
+        })?);
+    let mut input_files = Vec::new();
+    for (name, content) in [("input.txt", INPUT_CONTENT), ("flags.txt", "--foo\n")] {
+        let digest = compute_buf_digest(content.as_bytes(), &mut DigestHasherFunc::Sha256.hasher());
+        slow_store
+            .as_ref()
+            .update_oneshot(digest, content.into())
+            .await?;
+        input_files.push(FileNode {
+            name: name.to_string(),
+            digest: Some(digest.into()),
+            ..Default::default()
+        });
+    }
+    // Sorted by name, like Bazel does.
+    input_files.reverse();
+    let input_root_digest = serialize_and_upload_message(
+        &Directory {
+            files: input_files,
+            ..Default::default()
+        },
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let command = Command {
+        arguments: vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            WORKER_SCRIPT.to_string(),
+            "@flags.txt".to_string(),
+        ],
+        output_paths: vec!["output.txt".to_string()],
+        working_directory: ".".to_string(),
+        environment_variables: vec![EnvironmentVariable {
+            name: "PATH".to_string(),
+            value: std::env::var("PATH").unwrap(),
+        }],
+        ..Default::default()
+    };
+    let command_digest = serialize_and_upload_message(
+        &command,
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let action_digest = serialize_and_upload_message(
+        &Action {
+            command_digest: Some(command_digest.into()),
+            input_root_digest: Some(input_root_digest.into()),
+            platform: Some(Platform {
+                properties: vec![Property {
+                    name: "persistentWorkerKey".to_string(),
+                    value: "sh".to_string(),
+                }],
+            }),
+            ..Default::default()
+        },
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+
+    let mut outputs = Vec::new();
+    for _ in 0..2 {
+        let running_action_impl = running_actions_manager
+            .clone()
+            .create_and_add_action(
+                WORKER_ID.to_string(),
+                StartExecute {
+                    execute_request: Some(ExecuteRequest {
+                        action_digest: Some(action_digest.into()),
+                        ..Default::default()
+                    }),
+                    operation_id: OperationId::default().to_string(),
+                    queued_timestamp: Some(make_system_time(1000).into()),
+                },
+            )
+            .await?;
+        let result = run_action(running_action_impl).await?;
+        assert_eq!(result.error, None);
+        assert_eq!(result.exit_code, 0);
+        assert_eq!(result.output_files.len(), 1);
+        let output = cas_store
+            .as_ref()
+            .get_part_unchunked(result.output_files[0].digest, 0, None)
+            .await?;
+        assert_eq!(from_utf8(&output)?, INPUT_CONTENT);
+        let stderr = cas_store
+            .as_ref()
+            .get_part_unchunked(result.stderr_digest, 0, None)
+            .await?;
+        outputs.push(from_utf8(&stderr)?.to_string());
+    }
+    let (pid, requests) = outputs[0].split_once(' ').err_tip(|| "Bad output")?;
+    assert_eq!(requests, "1");
+    assert_eq!(outputs[1], format!("{pid} 2"));
+    // The worker was stopped after `max_requests` and its directory removed.
+    assert!(
+        fs::metadata(format!("{root_action_directory}/persistent_workers/0"))
+            .await
+            .is_err()
+    );
+    Ok(())
+}
+
 #[nativelink_test]
 async fn caches_results_in_action_cache_store() -> Result<(), Box<dyn std::error::Error>> {
     let (_, _, cas_store, ac_store) = setup_stores().await?;
@@ -1942,6 +3069,7 @@ async fn caches_results_in_action_cache_store() -> Result<(), Box<dyn std::error
             execution_completed_timestamp: make_system_time(4),
             output_upload_start_timestamp: make_system_time(5),
             output_upload_completed_timestamp: make_system_time(6),
+            resource_usage: None,
             worker_completed_timestamp: make_system_time(7),
         },
         error: None,
@@ -2013,6 +3141,7 @@ async fn failed_action_does_not_cache_in_action_cache() -> Result<(), Box<dyn st
             execution_completed_timestamp: make_system_time(4),
             output_upload_start_timestamp: make_system_time(5),
             output_upload_completed_timestamp: make_system_time(6),
+            resource_usage: None,
             worker_completed_timestamp: make_system_time(7),
         },
         error: None,
@@ -2087,6 +3216,7 @@ async fn success_does_cache_in_historical_results() -> Result<(), Box<dyn std::e
             execution_completed_timestamp: make_system_time(4),
             output_upload_start_timestamp: make_system_time(5),
             output_upload_completed_timestamp: make_system_time(6),
+            resource_usage: None,
             worker_completed_timestamp: make_system_time(7),
         },
         error: None,
@@ -3244,6 +4374,7 @@ async fn upload_with_single_permit() -> Result<(), Box<dyn std::error::Error>> {
                 execution_completed_timestamp: increment_clock(&mut clock_time),
                 output_upload_start_timestamp: increment_clock(&mut clock_time),
                 output_upload_completed_timestamp: increment_clock(&mut clock_time),
+                resource_usage: None,
                 worker_completed_timestamp: increment_clock(&mut clock_time),
             },
             error: None,
@@ -19,7 +19,7 @@ use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use async_lock::Mutex as AsyncMutex;
 use axum::Router;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use futures::future::{try_join_all, BoxFuture, Either, OptionFuture, TryFutureExt};
 use futures::FutureExt;
 use hyper::{Response, StatusCode};
@@ -31,6 +31,7 @@ use nativelink_config::cas_server::{
     CasConfig, GlobalConfig, HttpCompressionAlgorithm, ListenerConfig, ServerConfig, WorkerConfig,
 };
 use nativelink_config::stores::ConfigDigestHashFunction;
+use nativelink_config::worker_bootstrap::WorkerBootstrapConfig;
 use nativelink_error::{make_err, make_input_err, Code, Error, ResultExt};
 use nativelink_metric::{
     MetricFieldData, MetricKind, MetricPublishKnownKindData, MetricsComponent, RootMetricsComponent,
@@ -42,25 +43,40 @@ use nativelink_service::bep_server::BepServer;
 use nativelink_service::bytestream_server::ByteStreamServer;
 use nativelink_service::capabilities_server::CapabilitiesServer;
 use nativelink_service::cas_server::CasServer;
+use nativelink_service::digest_subscription_server::DigestSubscriptionServer;
 use nativelink_service::execution_server::ExecutionServer;
+use nativelink_service::grpc_health_server::{GrpcHealthServer, DEFAULT_GRPC_CHECK_INTERVAL_S};
 use nativelink_service::health_server::HealthServer;
+use nativelink_service::instance_metrics::instance_metrics_registry;
+use nativelink_service::operation_admin_server::OperationAdminServer;
+use nativelink_service::reflection_server::ReflectionServer;
+use nativelink_service::worker_admin_server::WorkerAdminServer;
 use nativelink_service::worker_api_server::WorkerApiServer;
 use nativelink_store::default_store_factory::store_factory;
 use nativelink_store::store_manager::StoreManager;
 use nativelink_util::action_messages::WorkerId;
+use nativelink_util::audit_log::init_audit_log;
+use nativelink_util::auth_middleware::AuthMiddlewareLayer;
+use nativelink_util::authorization::{Authorizer, AuthorizerLayer};
 use nativelink_util::common::fs::{set_idle_file_descriptor_timeout, set_open_file_limit};
-use nativelink_util::digest_hasher::{set_default_digest_hasher_func, DigestHasherFunc};
+use nativelink_util::digest_hasher::{
+    set_default_digest_hasher_func, set_sha256_backend, sha256_backend, DigestHasherFunc,
+};
 use nativelink_util::health_utils::HealthRegistryBuilder;
 use nativelink_util::metrics_utils::{set_metrics_enabled_for_this_thread, Counter};
 use nativelink_util::operation_state_manager::ClientStateManager;
 use nativelink_util::origin_context::{ActiveOriginContext, OriginContext};
-use nativelink_util::origin_event_middleware::OriginEventMiddlewareLayer;
+use nativelink_util::origin_event_middleware::{OriginEventMiddlewareLayer, TlsClientIdentity};
 use nativelink_util::origin_event_publisher::OriginEventPublisher;
+use nativelink_util::popularity_tracker::get_popularity_tracker;
 use nativelink_util::shutdown_guard::{Priority, ShutdownGuard};
 use nativelink_util::store_trait::{
     set_default_digest_size_health_check, DEFAULT_DIGEST_SIZE_HEALTH_CHECK_CFG,
 };
-use nativelink_util::task::TaskExecutor;
+use nativelink_util::task::{
+    blocking_pools, set_blocking_pool_threads, TaskExecutor, DEFAULT_FILESYSTEM_POOL_THREADS,
+};
+use nativelink_util::tls_utils::certificate_common_name;
 use nativelink_util::{background_spawn, init_tracing, spawn, spawn_blocking};
 use nativelink_worker::local_worker::new_local_worker;
 use opentelemetry::metrics::MeterProvider;
@@ -79,7 +95,9 @@ use tokio_rustls::rustls::server::WebPkiClientVerifier;
 use tokio_rustls::rustls::{RootCertStore, ServerConfig as TlsServerConfig};
 use tokio_rustls::TlsAcceptor;
 use tonic::codec::CompressionEncoding;
+use tonic::service::Routes;
 use tonic::transport::Server as TonicServer;
+use tower::ServiceExt;
 use tracing::{error_span, event, trace_span, Level};
 use tracing_subscriber::layer::SubscriberExt;
 
@@ -114,10 +132,65 @@ const BROADCAST_CAPACITY: usize = 1;
     about,
     long_about = None
 )]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
     /// Config file to use.
-    #[clap(value_parser)]
-    config_file: String,
+    #[clap(value_parser, required = true)]
+    config_file: Option<String>,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run a single worker connected to a remote scheduler and CAS. It is
+    /// configured with a TOML file, then `NATIVELINK_WORKER_*` environment
+    /// variables, then flags, with later sources taking precedence.
+    Worker(WorkerArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct WorkerArgs {
+    /// TOML config file of the worker.
+    #[clap(long)]
+    config: Option<String>,
+
+    /// Name of the worker used for logging and metrics.
+    #[clap(long)]
+    name: Option<String>,
+
+    /// Endpoint of the scheduler's `WorkerApiService`.
+    #[clap(long)]
+    scheduler_endpoint: Option<String>,
+
+    /// Endpoint of the CAS the worker downloads from and uploads to.
+    #[clap(long)]
+    cas_endpoint: Option<String>,
+
+    /// Instance name used for requests to the CAS.
+    #[clap(long)]
+    instance_name: Option<String>,
+
+    /// Directory of the local CAS cache and the work directory.
+    #[clap(long)]
+    cache_dir: Option<String>,
+
+    /// Maximum number of bytes of the local CAS cache.
+    #[clap(long)]
+    max_cache_bytes: Option<usize>,
+
+    /// Number of actions to run at once.
+    #[clap(long)]
+    slots: Option<usize>,
+
+    /// Platform property of the worker as `key=value`. May be repeated.
+    #[clap(long = "platform-property")]
+    platform_properties: Vec<String>,
+
+    /// Print the effective worker config as TOML and exit.
+    #[clap(long)]
+    print_effective_config: bool,
 }
 
 /// The root metrics collector struct. All metrics will be
@@ -129,6 +202,10 @@ struct RootMetrics {
     stores: Arc<dyn RootMetricsComponent>,
     #[metric(group = "servers")]
     servers: HashMap<String, Arc<dyn RootMetricsComponent>>,
+    #[metric(group = "instances")]
+    instances: Arc<dyn RootMetricsComponent>,
+    #[metric(group = "blocking_pools")]
+    blocking_pools: Arc<dyn RootMetricsComponent>,
     #[metric(group = "workers")]
     workers: HashMap<String, Arc<dyn RootMetricsComponent>>,
     // TODO(allada) We cannot upcast these to RootMetricsComponent because
@@ -186,6 +263,9 @@ async fn inner_main(
     let health_registry_builder =
         Arc::new(AsyncMutex::new(HealthRegistryBuilder::new("nativelink")));
 
+    let store_tree =
+        StoreManager::validate_specs(&cfg.stores).err_tip(|| "Invalid store configuration")?;
+    event!(Level::INFO, "Configured stores:\n{store_tree}");
     let store_manager = Arc::new(StoreManager::new());
     {
         let mut health_registry_lock = health_registry_builder.lock().await;
@@ -204,10 +284,17 @@ async fn inner_main(
     let mut action_schedulers = HashMap::new();
     let mut worker_schedulers = HashMap::new();
     if let Some(schedulers_cfg) = cfg.schedulers {
+        let mut health_registry_lock = health_registry_builder.lock().await;
         for (name, scheduler_cfg) in schedulers_cfg {
-            let (maybe_action_scheduler, maybe_worker_scheduler) =
-                scheduler_factory(&scheduler_cfg, &store_manager)
-                    .err_tip(|| format!("Failed to create scheduler '{name}'"))?;
+            let mut health_register_scheduler =
+                health_registry_lock.sub_builder(&format!("schedulers/{name}"));
+            let (maybe_action_scheduler, maybe_worker_scheduler) = scheduler_factory(
+                &scheduler_cfg,
+                &store_manager,
+                Some(&mut health_register_scheduler),
+                Some(&shutdown_tx),
+            )
+            .err_tip(|| format!("Failed to create scheduler '{name}'"))?;
             if let Some(action_scheduler) = maybe_action_scheduler {
                 action_schedulers.insert(name.clone(), action_scheduler.clone());
             }
@@ -247,6 +334,8 @@ async fn inner_main(
     let root_metrics = Arc::new(RwLock::new(RootMetrics {
         stores: store_manager.clone(),
         servers: server_metrics,
+        instances: instance_metrics_registry(),
+        blocking_pools: blocking_pools(),
         workers: HashMap::new(), // Will be filled in later.
         schedulers: action_schedulers.clone(),
     }));
@@ -275,6 +364,10 @@ async fn inner_main(
         })
         .transpose()?;
 
+    if let Some(audit_log_cfg) = &cfg.audit_log {
+        init_audit_log(audit_log_cfg).err_tip(|| "Could not start the audit log")?;
+    }
+
     for (server_cfg, connected_clients_mux) in servers_and_clients {
         let services = server_cfg
             .services
@@ -283,6 +376,18 @@ async fn inner_main(
         // Currently we only support http as our socket type.
         let ListenerConfig::http(http_config) = server_cfg.listener;
 
+        // Created first, as it looks at the configuration of the other
+        // services.
+        let capabilities_server = OptionFuture::from(
+            services
+                .capabilities
+                .as_ref()
+                .map(|_| CapabilitiesServer::new(&services, &action_schedulers)),
+        )
+        .await
+        .transpose()
+        .err_tip(|| "Could not create Capabilities service")?;
+
         let tonic_services = TonicServer::builder()
             .add_optional_service(
                 services
@@ -316,6 +421,15 @@ async fn inner_main(
                     .map_or(Ok(None), |cfg| {
                         CasServer::new(&cfg, &store_manager).map(|v| {
                             let mut service = v.into_service();
+                            let limits = &http_config.message_limits.cas;
+                            if limits.max_decoding_message_size != 0 {
+                                service = service
+                                    .max_decoding_message_size(limits.max_decoding_message_size);
+                            }
+                            if limits.max_encoding_message_size != 0 {
+                                service = service
+                                    .max_encoding_message_size(limits.max_encoding_message_size);
+                            }
                             let send_algo = &http_config.compression.send_compression_algorithm;
                             if let Some(encoding) =
                                 into_encoding(send_algo.unwrap_or(HttpCompressionAlgorithm::none))
@@ -336,12 +450,47 @@ async fn inner_main(
                     })
                     .err_tip(|| "Could not create CAS service")?,
             )
+            .add_optional_service(
+                services
+                    .digest_subscription
+                    .map_or(Ok(None), |cfg| {
+                        DigestSubscriptionServer::new(&cfg, &store_manager).map(|v| {
+                            let mut service = v.into_service();
+                            let send_algo = &http_config.compression.send_compression_algorithm;
+                            if let Some(encoding) =
+                                into_encoding(send_algo.unwrap_or(HttpCompressionAlgorithm::none))
+                            {
+                                service = service.send_compressed(encoding);
+                            }
+                            for encoding in http_config
+                                .compression
+                                .accepted_compression_algorithms
+                                .iter()
+                                // Filter None values.
+                                .filter_map(|from: &HttpCompressionAlgorithm| into_encoding(*from))
+                            {
+                                service = service.accept_compressed(encoding);
+                            }
+                            Some(service)
+                        })
+                    })
+                    .err_tip(|| "Could not create DigestSubscription service")?,
+            )
             .add_optional_service(
                 services
                     .execution
                     .map_or(Ok(None), |cfg| {
                         ExecutionServer::new(&cfg, &action_schedulers, &store_manager).map(|v| {
                             let mut service = v.into_service();
+                            let limits = &http_config.message_limits.execution;
+                            if limits.max_decoding_message_size != 0 {
+                                service = service
+                                    .max_decoding_message_size(limits.max_decoding_message_size);
+                            }
+                            if limits.max_encoding_message_size != 0 {
+                                service = service
+                                    .max_encoding_message_size(limits.max_encoding_message_size);
+                            }
                             let send_algo = &http_config.compression.send_compression_algorithm;
                             if let Some(encoding) =
                                 into_encoding(send_algo.unwrap_or(HttpCompressionAlgorithm::none))
@@ -368,6 +517,15 @@ async fn inner_main(
                     .map_or(Ok(None), |cfg| {
                         ByteStreamServer::new(&cfg, &store_manager).map(|v| {
                             let mut service = v.into_service();
+                            let limits = &http_config.message_limits.bytestream;
+                            if limits.max_decoding_message_size != 0 {
+                                service = service
+                                    .max_decoding_message_size(limits.max_decoding_message_size);
+                            }
+                            if limits.max_encoding_message_size != 0 {
+                                service = service
+                                    .max_encoding_message_size(limits.max_encoding_message_size);
+                            }
                             let send_algo = &http_config.compression.send_compression_algorithm;
                             if let Some(encoding) =
                                 into_encoding(send_algo.unwrap_or(HttpCompressionAlgorithm::none))
@@ -388,49 +546,30 @@ async fn inner_main(
                     })
                     .err_tip(|| "Could not create ByteStream service")?,
             )
-            .add_optional_service(
-                OptionFuture::from(
-                    services
-                        .capabilities
-                        .as_ref()
-                        // Borrow checker fighting here...
-                        .map(|_| {
-                            CapabilitiesServer::new(
-                                services.capabilities.as_ref().unwrap(),
-                                &action_schedulers,
-                            )
-                        }),
-                )
-                .await
-                .map_or(Ok::<Option<CapabilitiesServer>, Error>(None), |server| {
-                    Ok(Some(server?))
-                })
-                .err_tip(|| "Could not create Capabilities service")?
-                .map(|v| {
-                    let mut service = v.into_service();
-                    let send_algo = &http_config.compression.send_compression_algorithm;
-                    if let Some(encoding) =
-                        into_encoding(send_algo.unwrap_or(HttpCompressionAlgorithm::none))
-                    {
-                        service = service.send_compressed(encoding);
-                    }
-                    for encoding in http_config
-                        .compression
-                        .accepted_compression_algorithms
-                        .iter()
-                        // Filter None values.
-                        .filter_map(|from: &HttpCompressionAlgorithm| into_encoding(*from))
-                    {
-                        service = service.accept_compressed(encoding);
-                    }
-                    service
-                }),
-            )
+            .add_optional_service(capabilities_server.map(|v| {
+                let mut service = v.into_service();
+                let send_algo = &http_config.compression.send_compression_algorithm;
+                if let Some(encoding) =
+                    into_encoding(send_algo.unwrap_or(HttpCompressionAlgorithm::none))
+                {
+                    service = service.send_compressed(encoding);
+                }
+                for encoding in http_config
+                    .compression
+                    .accepted_compression_algorithms
+                    .iter()
+                    // Filter None values.
+                    .filter_map(|from: &HttpCompressionAlgorithm| into_encoding(*from))
+                {
+                    service = service.accept_compressed(encoding);
+                }
+                service
+            }))
             .add_optional_service(
                 services
                     .worker_api
                     .map_or(Ok(None), |cfg| {
-                        WorkerApiServer::new(&cfg, &worker_schedulers).map(|v| {
+                        WorkerApiServer::new(&cfg, &worker_schedulers, &store_manager).map(|v| {
                             let mut service = v.into_service();
                             let send_algo = &http_config.compression.send_compression_algorithm;
                             if let Some(encoding) =
@@ -452,6 +591,16 @@ async fn inner_main(
                     })
                     .err_tip(|| "Could not create WorkerApi service")?,
             )
+            .add_optional_service(
+                services
+                    .worker_admin
+                    .map(|cfg| WorkerAdminServer::new(&cfg, &worker_schedulers).into_service()),
+            )
+            .add_optional_service(
+                services
+                    .operation_admin
+                    .map(|cfg| OperationAdminServer::new(&cfg, &action_schedulers).into_service()),
+            )
             .add_optional_service(
                 services
                     .experimental_bep
@@ -477,16 +626,38 @@ async fn inner_main(
                         })
                     })
                     .err_tip(|| "Could not create BEP service")?,
+            )
+            .add_optional_service(
+                services
+                    .reflection
+                    .map_or(Ok(None), |cfg| {
+                        ReflectionServer::new(&cfg).into_service().map(Some)
+                    })
+                    .err_tip(|| "Could not create reflection service")?,
             );
 
         let health_registry = health_registry_builder.lock().await.build();
 
-        let mut svc = Router::new().merge(tonic_services.into_service().into_axum_router().layer(
+        let mut grpc_router = tonic_services.into_service().into_axum_router().layer(
             OriginEventMiddlewareLayer::new(
                 maybe_origin_event_tx.clone(),
                 server_cfg.experimental_identity_header.clone(),
             ),
-        ));
+        );
+        // Added last so it runs before the origin event middleware, which
+        // takes the identity it attaches to the request.
+        if let Some(auth_cfg) = &server_cfg.auth {
+            grpc_router = grpc_router.layer(
+                AuthMiddlewareLayer::new(auth_cfg)
+                    .err_tip(|| "Could not create auth middleware")?,
+            );
+        } else {
+            // Requests without an authorizer are denied once any server
+            // authenticates its requests.
+            grpc_router =
+                grpc_router.layer(AuthorizerLayer::new(Arc::new(Authorizer::allow_all())));
+        }
+        let mut svc = Router::new().merge(grpc_router);
 
         if let Some(health_cfg) = services.health {
             let path = if health_cfg.path.is_empty() {
@@ -494,7 +665,22 @@ async fn inner_main(
             } else {
                 &health_cfg.path
             };
-            svc = svc.route_service(path, HealthServer::new(health_registry));
+            let (grpc_health_server, grpc_health_service) =
+                GrpcHealthServer::new(health_registry.clone()).await;
+            let check_interval_s = if health_cfg.grpc_check_interval_s == 0 {
+                DEFAULT_GRPC_CHECK_INTERVAL_S
+            } else {
+                health_cfg.grpc_check_interval_s
+            };
+            background_spawn!(
+                "grpc_health_server",
+                grpc_health_server.run(Duration::from_secs(check_interval_s))
+            );
+            // Merged outside of the auth middleware, so probes don't need
+            // credentials.
+            svc = svc
+                .merge(Routes::new(grpc_health_service).into_axum_router())
+                .route_service(path, HealthServer::new(health_registry));
         }
 
         if let Some(prometheus_cfg) = services.experimental_prometheus {
@@ -636,6 +822,31 @@ async fn inner_main(
                 &admin_config.path
             };
             let worker_schedulers = Arc::new(worker_schedulers.clone());
+            let drain_worker_schedulers = worker_schedulers.clone();
+            let mut log_filter_route = axum::routing::get(|| async move {
+                nativelink_util::log_filter().map_err(|e| {
+                    (
+                        axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("Error: {e:?}"),
+                    )
+                })
+            });
+            // The admin API is not behind the auth middleware, so changing
+            // the log filter must be enabled explicitly.
+            if admin_config.allow_log_filter_changes {
+                log_filter_route = log_filter_route.post(|directives: String| async move {
+                    nativelink_util::set_log_filter(directives.trim())
+                        .map(|()| format!("Log filter set to '{}'", directives.trim()))
+                        .map_err(|e| {
+                            let status_code = if e.code == Code::InvalidArgument {
+                                axum::http::StatusCode::BAD_REQUEST
+                            } else {
+                                axum::http::StatusCode::INTERNAL_SERVER_ERROR
+                            };
+                            (status_code, format!("Error: {e:?}"))
+                        })
+                });
+            }
             svc = svc.nest_service(
                 path,
                 Router::new().route(
@@ -680,6 +891,71 @@ async fn inner_main(
                             })
                         },
                     ),
+                )
+                .route(
+                    "/scheduler/:instance_name/drain_worker/:worker_id/:timeout_s",
+                    axum::routing::post(
+                        move |params: axum::extract::Path<(String, String, u64)>| async move {
+                            let (instance_name, worker_id, timeout_s) = params.0;
+                            (async move {
+                                drain_worker_schedulers
+                                    .get(&instance_name)
+                                    .err_tip(|| {
+                                        format!(
+                                            "Can not get an instance with the name of '{}'",
+                                            &instance_name
+                                        )
+                                    })?
+                                    .clone()
+                                    .drain_worker(
+                                        &WorkerId::try_from(worker_id.clone())?,
+                                        Duration::from_secs(timeout_s),
+                                    )
+                                    .await?;
+                                Ok::<_, Error>(format!("Drained worker {worker_id}"))
+                            })
+                            .await
+                            .map_err(|e| {
+                                let status_code = if e.code == Code::DeadlineExceeded {
+                                    axum::http::StatusCode::GATEWAY_TIMEOUT
+                                } else {
+                                    axum::http::StatusCode::INTERNAL_SERVER_ERROR
+                                };
+                                (status_code, format!("Error: {e:?}"))
+                            })
+                        },
+                    ),
+                )
+                .route("/log_filter", log_filter_route)
+                .route(
+                    "/popularity/:tracker_name/hottest/:count",
+                    axum::routing::get(
+                        |params: axum::extract::Path<(String, usize)>| async move {
+                            let (tracker_name, count) = params.0;
+                            let tracker = get_popularity_tracker(&tracker_name).ok_or_else(|| {
+                                (
+                                    axum::http::StatusCode::NOT_FOUND,
+                                    format!("No popularity tracker named '{tracker_name}'"),
+                                )
+                            })?;
+                            let hottest: Vec<_> = tracker
+                                .hottest(count)
+                                .into_iter()
+                                .map(|(key, reads)| {
+                                    serde_json::json!({
+                                        "key": key.as_str(),
+                                        "estimated_reads": reads,
+                                    })
+                                })
+                                .collect();
+                            serde_json::to_string_pretty(&hottest).map_err(|e| {
+                                (
+                                    axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                                    format!("Error: {e:?}"),
+                                )
+                            })
+                        },
+                    ),
                 ),
             );
         }
@@ -744,16 +1020,25 @@ async fn inner_main(
                 } else {
                     Vec::new()
                 };
-                WebPkiClientVerifier::builder(Arc::new(client_auth_roots))
-                    .with_crls(crls)
-                    .build()
-                    .map_err(|e| {
-                        make_err!(
-                            Code::Internal,
-                            "Could not create WebPkiClientVerifier: {e:?}"
-                        )
-                    })?
+                let builder =
+                    WebPkiClientVerifier::builder(Arc::new(client_auth_roots)).with_crls(crls);
+                let builder = if tls_config.require_client_cert.unwrap_or(true) {
+                    builder
+                } else {
+                    builder.allow_unauthenticated()
+                };
+                builder.build().map_err(|e| {
+                    make_err!(
+                        Code::Internal,
+                        "Could not create WebPkiClientVerifier: {e:?}"
+                    )
+                })?
             } else {
+                if tls_config.require_client_cert == Some(true) {
+                    return Err(make_input_err!(
+                        "'require_client_cert' requires 'client_ca_file' to be set"
+                    ));
+                }
                 WebPkiClientVerifier::no_client_auth()
             };
             let mut config = TlsServerConfig::builder()
@@ -877,10 +1162,30 @@ async fn inner_main(
                                         let _guard = scope_guard;
                                         let serve_connection = if let Some(tls_acceptor) = maybe_tls_acceptor {
                                             match tls_acceptor.accept(tcp_stream).await {
-                                                Ok(tls_stream) => Either::Left(http.serve_connection(
-                                                    TokioIo::new(tls_stream),
-                                                    TowerToHyperService::new(svc),
-                                                )),
+                                                Ok(tls_stream) => {
+                                                    // The certificate was verified during the
+                                                    // handshake, so it identifies the client of
+                                                    // every request of this connection.
+                                                    let maybe_identity = tls_stream
+                                                        .get_ref()
+                                                        .1
+                                                        .peer_certificates()
+                                                        .and_then(|certs| certs.first())
+                                                        .and_then(|cert| certificate_common_name(cert));
+                                                    let svc = svc.map_request(
+                                                        move |mut req: hyper::Request<hyper::body::Incoming>| {
+                                                            if let Some(identity) = &maybe_identity {
+                                                                req.extensions_mut()
+                                                                    .insert(TlsClientIdentity(identity.clone()));
+                                                            }
+                                                            req
+                                                        },
+                                                    );
+                                                    Either::Left(http.serve_connection(
+                                                        TokioIo::new(tls_stream),
+                                                        TowerToHyperService::new(svc),
+                                                    ))
+                                                }
                                                 Err(err) => {
                                                     event!(Level::ERROR, ?err, "Failed to accept tls stream");
                                                     return;
@@ -1002,13 +1307,62 @@ async fn inner_main(
 
 async fn get_config() -> Result<CasConfig, Box<dyn std::error::Error>> {
     let args = Args::parse();
+    if let Some(Command::Worker(worker_args)) = args.command {
+        return get_worker_config(worker_args);
+    }
+    let config_file = args
+        .config_file
+        .err_tip(|| "Expected a config file to be set")?;
     let json_contents = String::from_utf8(
-        std::fs::read(&args.config_file)
-            .err_tip(|| format!("Could not open config file {}", args.config_file))?,
+        std::fs::read(&config_file)
+            .err_tip(|| format!("Could not open config file {config_file}"))?,
     )?;
     Ok(serde_json5::from_str(&json_contents)?)
 }
 
+fn get_worker_config(args: WorkerArgs) -> Result<CasConfig, Box<dyn std::error::Error>> {
+    let mut worker_cfg = match &args.config {
+        Some(config_file) => toml::from_str(&String::from_utf8(
+            std::fs::read(config_file)
+                .err_tip(|| format!("Could not open worker config file {config_file}"))?,
+        )?)?,
+        None => WorkerBootstrapConfig::default(),
+    };
+    worker_cfg.apply_env(std::env::vars())?;
+    if let Some(name) = args.name {
+        worker_cfg.name = name;
+    }
+    if let Some(scheduler_endpoint) = args.scheduler_endpoint {
+        worker_cfg.scheduler_endpoint = scheduler_endpoint;
+    }
+    if let Some(cas_endpoint) = args.cas_endpoint {
+        worker_cfg.cas_endpoint = cas_endpoint;
+    }
+    if let Some(instance_name) = args.instance_name {
+        worker_cfg.instance_name = instance_name;
+    }
+    if let Some(cache_dir) = args.cache_dir {
+        worker_cfg.cache_dir = cache_dir;
+    }
+    if let Some(max_cache_bytes) = args.max_cache_bytes {
+        worker_cfg.max_cache_bytes = max_cache_bytes;
+    }
+    if let Some(slots) = args.slots {
+        worker_cfg.slots = slots;
+    }
+    for platform_property in &args.platform_properties {
+        worker_cfg.set_field("platform_properties", platform_property)?;
+    }
+    worker_cfg.resolve_defaults();
+    worker_cfg.validate()?;
+
+    if args.print_effective_config {
+        print!("{}", toml::to_string_pretty(&worker_cfg)?);
+        std::process::exit(0);
+    }
+    Ok(worker_cfg.to_cas_config())
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     init_tracing()?;
 
@@ -1021,6 +1375,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         // Note: If the default changes make sure you update the documentation in
         // `config/cas_server.rs`.
         const DEFAULT_IDLE_FILE_DESCRIPTOR_TIMEOUT_MILLIS: u64 = 1000;
+        let default_blocking_cpu_threads =
+            std::thread::available_parallelism().map_or(1, usize::from);
         let global_cfg = if let Some(global_cfg) = &mut cfg.global {
             if global_cfg.max_open_files == 0 {
                 global_cfg.max_open_files = DEFAULT_MAX_OPEN_FILES;
@@ -1032,6 +1388,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             if global_cfg.default_digest_size_health_check == 0 {
                 global_cfg.default_digest_size_health_check = DEFAULT_DIGEST_SIZE_HEALTH_CHECK_CFG;
             }
+            if global_cfg.blocking_filesystem_threads == 0 {
+                global_cfg.blocking_filesystem_threads = DEFAULT_FILESYSTEM_POOL_THREADS;
+            }
+            if global_cfg.blocking_cpu_threads == 0 {
+                global_cfg.blocking_cpu_threads = default_blocking_cpu_threads;
+            }
 
             *global_cfg
         } else {
@@ -1045,7 +1407,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     service.experimental_prometheus.is_none()
                 }),
                 default_digest_hash_function: None,
+                sha256_backend: None,
                 default_digest_size_health_check: DEFAULT_DIGEST_SIZE_HEALTH_CHECK_CFG,
+                blocking_filesystem_threads: DEFAULT_FILESYSTEM_POOL_THREADS,
+                blocking_cpu_threads: default_blocking_cpu_threads,
             }
         };
         set_open_file_limit(global_cfg.max_open_files);
@@ -1057,7 +1422,15 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .default_digest_hash_function
                 .unwrap_or(ConfigDigestHashFunction::sha256),
         ))?;
+        if let Some(backend) = global_cfg.sha256_backend {
+            set_sha256_backend(backend.into())?;
+        }
+        event!(Level::INFO, sha256_backend = ?sha256_backend(), "Selected sha256 backend");
         set_default_digest_size_health_check(global_cfg.default_digest_size_health_check)?;
+        set_blocking_pool_threads(
+            global_cfg.blocking_filesystem_threads,
+            global_cfg.blocking_cpu_threads,
+        )?;
         // TODO (#513): prevent deadlocks by assigning max blocking threads number of open files * ten
         (!global_cfg.disable_metrics, global_cfg.max_open_files * 10)
     };